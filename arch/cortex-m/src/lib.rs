@@ -918,6 +918,25 @@ pub unsafe fn print_cortexm_state(writer: &mut dyn Write) {
     }
 }
 
+/// Make an ARM semihosting call, with the `bkpt 0xab` used on M-profile
+/// cores.
+///
+/// The operation goes in r0 and `arg0`, usually a pointer to the
+/// operation's parameters, in r1. `arg1` is unused, and only there to match
+/// `rv32i::semihost_command`.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub unsafe fn semihost_command(command: usize, arg0: usize, _arg1: usize) -> usize {
+    use core::arch::asm;
+    let res;
+    asm!(
+        "bkpt 0xab",
+        inout("r0") command => res,
+        in("r1") arg0,
+        options(nostack),
+    );
+    res
+}
+
 // Table 2.5
 // http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dui0553a/CHDBIBGJ.html
 pub fn ipsr_isr_number_to_str(isr_number: usize) -> &'static str {
@@ -984,3 +1003,8 @@ pub unsafe extern "C" fn switch_to_user_arm_v7m(
 pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
     unimplemented!()
 }
+
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+pub unsafe fn semihost_command(_command: usize, _arg0: usize, _arg1: usize) -> usize {
+    unimplemented!()
+}
@@ -16,7 +16,7 @@ use riscv_csr::csr::{
     PMPADDR57, PMPADDR58, PMPADDR59, PMPADDR6, PMPADDR60, PMPADDR61, PMPADDR62, PMPADDR63,
     PMPADDR7, PMPADDR8, PMPADDR9, PMPCFG0, PMPCFG1, PMPCFG10, PMPCFG11, PMPCFG12, PMPCFG13,
     PMPCFG14, PMPCFG15, PMPCFG2, PMPCFG3, PMPCFG4, PMPCFG5, PMPCFG6, PMPCFG7, PMPCFG8, PMPCFG9,
-    STVEC, UTVEC,
+    STVEC, TDATA1, TDATA2, TSELECT, UTVEC,
 };
 use tock_registers::fields::FieldValue;
 use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
@@ -35,6 +35,8 @@ pub mod mtvec;
 pub mod pmpaddr;
 pub mod pmpconfig;
 pub mod stvec;
+pub mod tdata;
+pub mod tselect;
 pub mod utvec;
 
 // NOTE! We default to 32 bit if this is being compiled for debug/testing. We do
@@ -157,6 +159,10 @@ pub struct CSR {
 
     pub utvec: ReadWriteRiscvCsr<usize, utvec::utvec::Register, UTVEC>,
     pub stvec: ReadWriteRiscvCsr<usize, stvec::stvec::Register, STVEC>,
+
+    pub tselect: ReadWriteRiscvCsr<usize, tselect::tselect::Register, TSELECT>,
+    pub tdata1: ReadWriteRiscvCsr<usize, tdata::mcontrol::Register, TDATA1>,
+    pub tdata2: ReadWriteRiscvCsr<usize, tdata::tdata2::Register, TDATA2>,
 }
 
 // Define the "addresses" of each CSR register.
@@ -274,6 +280,10 @@ pub const CSR: &CSR = &CSR {
 
     utvec: ReadWriteRiscvCsr::new(),
     stvec: ReadWriteRiscvCsr::new(),
+
+    tselect: ReadWriteRiscvCsr::new(),
+    tdata1: ReadWriteRiscvCsr::new(),
+    tdata2: ReadWriteRiscvCsr::new(),
 };
 
 impl CSR {
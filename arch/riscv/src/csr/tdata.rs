@@ -0,0 +1,44 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+use kernel::utilities::registers::register_bitfields;
+
+// tdata1 of an address/data match trigger, from the RISC-V debug
+// specification 0.13
+register_bitfields![usize,
+    pub mcontrol [
+        type_ OFFSET(crate::XLEN - 4) NUMBITS(4) [
+            None = 0,
+            AddressData = 2
+        ],
+        dmode OFFSET(crate::XLEN - 5) NUMBITS(1) [],
+        maskmax OFFSET(crate::XLEN - 11) NUMBITS(6) [],
+        hit OFFSET(20) NUMBITS(1) [],
+        select OFFSET(19) NUMBITS(1) [],
+        timing OFFSET(18) NUMBITS(1) [],
+        sizelo OFFSET(16) NUMBITS(2) [],
+        action OFFSET(12) NUMBITS(4) [
+            Breakpoint = 0,
+            DebugMode = 1
+        ],
+        chain OFFSET(11) NUMBITS(1) [],
+        match_ OFFSET(7) NUMBITS(4) [
+            Equal = 0,
+            Napot = 1
+        ],
+        m OFFSET(6) NUMBITS(1) [],
+        s OFFSET(4) NUMBITS(1) [],
+        u OFFSET(3) NUMBITS(1) [],
+        execute OFFSET(2) NUMBITS(1) [],
+        store OFFSET(1) NUMBITS(1) [],
+        load OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+// tdata2 holds the address a trigger matches
+register_bitfields![usize,
+    pub tdata2 [
+        value OFFSET(0) NUMBITS(crate::XLEN) []
+    ]
+];
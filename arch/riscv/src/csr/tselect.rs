@@ -0,0 +1,12 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+use kernel::utilities::registers::register_bitfields;
+
+// tselect selects the trigger accessed through the tdata registers
+register_bitfields![usize,
+    pub tselect [
+        index OFFSET(0) NUMBITS(crate::XLEN) []
+    ]
+];
@@ -68,6 +68,9 @@ pub struct PMP<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> {
     /// This will be between 0 and MAX_AVAILABLE_REGIONS_OVER_TWO * 2 depending
     /// on the hardware and previous boot stages.
     num_regions: usize,
+    /// Size of the guard region below each process's stack, 0 if processes
+    /// have no stack guard region.
+    stack_guard_size: Cell<usize>,
 }
 
 impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> PMP<MAX_AVAILABLE_REGIONS_OVER_TWO> {
@@ -148,8 +151,20 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> PMP<MAX_AVAILABLE_REGIONS_OVER
             last_configured_for: MapCell::empty(),
             num_regions,
             locked_region_mask: Cell::new(locked_region_mask),
+            stack_guard_size: Cell::new(0),
         }
     }
+
+    /// Place a guard region of `size` bytes below the stack of processes
+    /// loaded from now on, so that a stack overflow faults in it and is
+    /// reported as such. The size is rounded up to a multiple of 4 bytes, and
+    /// 0 disables the guard region.
+    ///
+    /// Each guard region uses one of the app regions, and the memory it
+    /// covers is taken from the memory given to processes.
+    pub fn set_stack_guard_size(&self, size: usize) {
+        self.stack_guard_size.set((size + 3) & !3);
+    }
 }
 
 impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> fmt::Display
@@ -293,6 +308,18 @@ impl PMPRegion {
         })
     }
 
+    /// Create a new PMPRegion the app can't access, to guard its stack
+    fn new_guard(start: *const u8, size: usize) -> PMPRegion {
+        PMPRegion {
+            location: (start, size),
+            cfg: pmpcfg::l::CLEAR
+                + pmpcfg::r::CLEAR
+                + pmpcfg::w::CLEAR
+                + pmpcfg::x::CLEAR
+                + pmpcfg::a::TOR,
+        }
+    }
+
     /// Create a new PMPRegion for use by the kernel
     fn new_kernel(
         start: *const u8,
@@ -745,6 +772,41 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::platform::mpu::MPU
         Ok(())
     }
 
+    fn stack_guard_size(&self) -> usize {
+        self.stack_guard_size.get()
+    }
+
+    fn allocate_stack_guard_region(
+        &self,
+        stack_bottom: *const u8,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let size = self.stack_guard_size.get();
+        // Regions must be at least 8 bytes, and the stack bottom is the start
+        // of app memory, which is aligned to 4 bytes.
+        if size < 8 || stack_bottom as usize % 4 != 0 {
+            return None;
+        }
+        let start = (stack_bottom as usize).checked_sub(size)? as *const u8;
+
+        for region in config.regions.iter() {
+            if region.is_some() {
+                if region.unwrap().overlaps(start, size) {
+                    return None;
+                }
+            }
+        }
+
+        // The guard region overlaps no other app region, and kernel regions
+        // come after all app regions, so it always takes precedence.
+        let region_num = config.unused_region_number(self.locked_region_mask.get())?;
+
+        config.regions[region_num] = Some(PMPRegion::new_guard(start, size));
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start, size))
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, processid: &ProcessId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
@@ -990,3 +1052,99 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::platform::mpu::KernelM
         csr::CSR.mseccfg.modify(csr::mseccfg::mseccfg::mml::SET);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::platform::mpu::MPU;
+
+    const APP_MEMORY: usize = 0x8000_2000;
+
+    fn pmp(stack_guard_size: usize) -> PMP<8> {
+        // `PMP::new` probes the hardware, so build a PMP with all regions
+        // usable instead.
+        let pmp = PMP {
+            last_configured_for: MapCell::empty(),
+            locked_region_mask: Cell::new(0),
+            num_regions: 16,
+            stack_guard_size: Cell::new(0),
+        };
+        pmp.set_stack_guard_size(stack_guard_size);
+        pmp
+    }
+
+    /// A configuration with app memory at `APP_MEMORY`.
+    fn config(pmp: &PMP<8>) -> PMPConfig<8> {
+        let mut config = PMPConfig::default();
+        pmp.allocate_app_memory_region(
+            APP_MEMORY as *const u8,
+            0x1000,
+            0x800,
+            0x400,
+            0x100,
+            mpu::Permissions::ReadWriteOnly,
+            &mut config,
+        )
+        .unwrap();
+        config
+    }
+
+    #[test]
+    fn stack_guard_size_is_rounded_up_to_words() {
+        assert_eq!(pmp(0).stack_guard_size(), 0);
+        assert_eq!(pmp(30).stack_guard_size(), 32);
+        assert_eq!(pmp(64).stack_guard_size(), 64);
+    }
+
+    #[test]
+    fn stack_guard_region_ends_at_the_stack_bottom() {
+        let pmp = pmp(30);
+        let mut config = config(&pmp);
+
+        let guard = pmp
+            .allocate_stack_guard_region(APP_MEMORY as *const u8, &mut config)
+            .unwrap();
+        assert_eq!(guard.start_address() as usize, APP_MEMORY - 32);
+        assert_eq!(guard.size(), 32);
+
+        // The guard region is in the configuration, and inaccessible to the
+        // process.
+        let region = config
+            .regions
+            .iter()
+            .flatten()
+            .find(|region| **region == guard)
+            .unwrap();
+        let access = pmpcfg::r::SET.value | pmpcfg::w::SET.value | pmpcfg::x::SET.value;
+        assert_eq!(region.cfg.value & access, 0);
+
+        // App memory is unchanged.
+        let app_region = config.app_memory_region.unwrap_or(0);
+        assert_eq!(
+            config.regions[app_region].unwrap().location.0,
+            APP_MEMORY as *const u8
+        );
+    }
+
+    #[test]
+    fn no_stack_guard_region_where_it_cannot_go() {
+        // Stack guard regions are disabled.
+        let without_guard = pmp(0);
+        let mut app_config = config(&without_guard);
+        assert!(without_guard
+            .allocate_stack_guard_region(APP_MEMORY as *const u8, &mut app_config)
+            .is_none());
+
+        let pmp = pmp(32);
+        let mut config = config(&pmp);
+        // The stack bottom is not aligned.
+        assert!(pmp
+            .allocate_stack_guard_region((APP_MEMORY + 2) as *const u8, &mut config)
+            .is_none());
+        // The guard region would overlap app memory.
+        assert!(pmp
+            .allocate_stack_guard_region((APP_MEMORY + 16) as *const u8, &mut config)
+            .is_none());
+        assert_eq!(config.regions.iter().flatten().count(), 1);
+    }
+}
@@ -19,6 +19,7 @@ pub mod machine_timer;
 pub mod pmp;
 pub mod support;
 pub mod syscall;
+pub mod triggers;
 
 // Re-export the shared CSR library so that dependent crates do not have to have
 // both rv32i and riscv as dependencies.
@@ -543,4 +543,17 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    fn fault_address(&self, state: &Riscv32iStoredState) -> Option<*const u8> {
+        // For access faults, mtval holds the address that was accessed.
+        match mcause::Trap::from(state.mcause as usize) {
+            mcause::Trap::Exception(
+                mcause::Exception::LoadFault
+                | mcause::Exception::StoreFault
+                | mcause::Exception::LoadPageFault
+                | mcause::Exception::StorePageFault,
+            ) => Some(state.mtval as *const u8),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,235 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Hardware triggers, for watchpoints without a debugger.
+//!
+//! The trigger module of the RISC-V debug specification compares the
+//! addresses of loads, stores and executed instructions against a few
+//! trigger slots, selected with `tselect` and programmed through `tdata1` and
+//! `tdata2`. This is unrelated to the PMP: a trigger raises a breakpoint
+//! exception before the access happens instead of denying it.
+//!
+//! A watchpoint covers a naturally aligned, power of two sized range, and
+//! only fires on accesses by the kernel. When it fires, the chip's trap
+//! handler calls [`handle_breakpoint`], which disables the watchpoint, so
+//! that the access can go ahead when the trap returns, and reports it to the
+//! handler set with [`set_watchpoint_handler`].
+//!
+//! Slots that a debugger is using, or that cannot raise exceptions on this
+//! hart, are refused.
+//!
+//! ```rust,ignore
+//! fn watchpoint_hit(watchpoint: rv32i::triggers::Watchpoint) {
+//!     kernel::debug!("{:#x} written at {:#x}", watchpoint.address, watchpoint.pc);
+//! }
+//!
+//! unsafe { rv32i::triggers::set_watchpoint_handler(watchpoint_hit) };
+//! rv32i::triggers::set_watchpoint(0, address, 4, rv32i::triggers::Access::WRITE)?;
+//! ```
+
+use crate::csr::{tdata::mcontrol, CSR};
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::LocalRegisterCopy;
+use kernel::ErrorCode;
+
+/// Triggers probed by [`trigger_count`]. The debug specification allows up
+/// to 2^XLEN, but harts have a handful.
+const MAX_TRIGGERS: usize = 32;
+
+/// The accesses a watchpoint fires on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Access {
+    pub const READ: Access = Access {
+        read: true,
+        write: false,
+        execute: false,
+    };
+    pub const WRITE: Access = Access {
+        read: false,
+        write: true,
+        execute: false,
+    };
+    pub const READ_WRITE: Access = Access {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    pub const EXECUTE: Access = Access {
+        read: false,
+        write: false,
+        execute: true,
+    };
+}
+
+/// A watchpoint that fired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// The trigger slot of the watchpoint.
+    pub slot: usize,
+    /// The address accessed.
+    pub address: usize,
+    /// The instruction that accessed it.
+    pub pc: usize,
+}
+
+pub type WatchpointHandler = fn(Watchpoint);
+
+static mut HANDLER: Option<WatchpointHandler> = None;
+
+/// Set the function called when a watchpoint fires. It runs in the trap
+/// handler, with interrupts disabled.
+///
+/// # Safety
+///
+/// Must not be called while a watchpoint is set.
+pub unsafe fn set_watchpoint_handler(handler: WatchpointHandler) {
+    HANDLER = Some(handler);
+}
+
+/// The number of trigger slots of the hart.
+///
+/// Slots are selected in turn until `tselect` does not keep the index, or
+/// the slot has no trigger.
+pub fn trigger_count() -> usize {
+    let selected = CSR.tselect.get();
+    let mut count = 0;
+    while count < MAX_TRIGGERS {
+        CSR.tselect.set(count);
+        if CSR.tselect.get() != count || CSR.tdata1.matches_all(mcontrol::type_::None) {
+            break;
+        }
+        count += 1;
+    }
+    CSR.tselect.set(selected);
+    count
+}
+
+/// Disable the selected trigger. It keeps its type, as a trigger of type 0
+/// does not exist.
+fn disable() {
+    CSR.tdata1.write(mcontrol::type_::AddressData);
+}
+
+/// Select `slot`, if it is free for the kernel to use.
+fn select(slot: usize) -> Result<(), ErrorCode> {
+    if slot >= trigger_count() {
+        return Err(ErrorCode::INVAL);
+    }
+    CSR.tselect.set(slot);
+    if !CSR.tdata1.matches_all(mcontrol::type_::AddressData) {
+        return Err(ErrorCode::NOSUPPORT);
+    }
+    if CSR.tdata1.is_set(mcontrol::dmode) {
+        // The slot belongs to the debugger.
+        return Err(ErrorCode::BUSY);
+    }
+    Ok(())
+}
+
+/// Fire on `access` to the `size` bytes at `address` by the kernel. `size`
+/// must be a power of two, and `address` a multiple of it.
+///
+/// Returns `INVAL` if the slot does not exist or the range is not aligned,
+/// `BUSY` if a debugger uses the slot, and `NOSUPPORT` if the slot cannot
+/// raise a breakpoint exception for this range and access.
+pub fn set_watchpoint(
+    slot: usize,
+    address: usize,
+    size: usize,
+    access: Access,
+) -> Result<(), ErrorCode> {
+    if !size.is_power_of_two()
+        || address % size != 0
+        || !(access.read || access.write || access.execute)
+    {
+        return Err(ErrorCode::INVAL);
+    }
+    select(slot)?;
+
+    // Disable the trigger while it is changed.
+    disable();
+    let (match_, tdata2) = if size == 1 {
+        (mcontrol::match_::Equal, address)
+    } else {
+        (mcontrol::match_::Napot, address | (size / 2 - 1))
+    };
+    CSR.tdata2.set(tdata2);
+    let tdata1 = mcontrol::type_::AddressData
+        + mcontrol::action::Breakpoint
+        + match_
+        + mcontrol::m::SET
+        + mcontrol::load.val(access.read as usize)
+        + mcontrol::store.val(access.write as usize)
+        + mcontrol::execute.val(access.execute as usize);
+    CSR.tdata1.write(tdata1);
+
+    // The fields are WARL: the hart keeps what it does not support at a
+    // legal value, which would be a different trigger.
+    let mask = tdata1.mask();
+    if CSR.tdata1.get() & mask != tdata1.value || CSR.tdata2.get() != tdata2 {
+        disable();
+        return Err(ErrorCode::NOSUPPORT);
+    }
+    Ok(())
+}
+
+/// Remove the watchpoint in `slot`.
+pub fn clear_watchpoint(slot: usize) -> Result<(), ErrorCode> {
+    select(slot)?;
+    disable();
+    Ok(())
+}
+
+/// Whether the watchpoint programmed with `tdata1` and `tdata2` covers
+/// `address`.
+fn covers(
+    tdata1: LocalRegisterCopy<usize, mcontrol::Register>,
+    tdata2: usize,
+    address: usize,
+) -> bool {
+    match tdata1.read_as_enum(mcontrol::match_) {
+        Some(mcontrol::match_::Value::Equal) => address == tdata2,
+        Some(mcontrol::match_::Value::Napot) => {
+            let size = 2usize << tdata2.trailing_ones();
+            address & !(size - 1) == tdata2 & !(size - 1)
+        }
+        None => false,
+    }
+}
+
+/// Handle a breakpoint exception taken by the kernel. Returns `true` if it
+/// was raised by a watchpoint, which is then disabled and reported.
+pub fn handle_breakpoint() -> bool {
+    let address = CSR.mtval.get();
+    let pc = CSR.mepc.get();
+    for slot in 0..trigger_count() {
+        CSR.tselect.set(slot);
+        let tdata1 = LocalRegisterCopy::<usize, mcontrol::Register>::new(CSR.tdata1.get());
+        if !tdata1.matches_all(mcontrol::type_::AddressData + mcontrol::action::Breakpoint)
+            || tdata1.is_set(mcontrol::dmode)
+            || !tdata1.is_set(mcontrol::m)
+        {
+            continue;
+        }
+        // Harts need not implement the hit bit, so fall back to the address
+        // in mtval.
+        if tdata1.is_set(mcontrol::hit) || covers(tdata1, CSR.tdata2.get(), address) {
+            disable();
+            let watchpoint = Watchpoint { slot, address, pc };
+            unsafe {
+                if let Some(handler) = HANDLER {
+                    handler(watchpoint);
+                }
+            }
+            return true;
+        }
+    }
+    false
+}
@@ -141,6 +141,7 @@ impl KernelResources<nrf52832::chip::NRF52<'static, Nrf52832DefaultPeripherals<'
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -166,6 +167,9 @@ impl KernelResources<nrf52832::chip::NRF52<'static, Nrf52832DefaultPeripherals<'
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// This is in a separate, inline(never) function so that its stack frame is
@@ -97,6 +97,26 @@ static mut CCS811: Option<&'static capsules_extra::ccs811::Ccs811<'static>> = No
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
 
+// Addresses of the sensors the kernel drives on the Qwiic bus.
+const BME280_ADDRESS: u8 = 0x77;
+const CCS811_ADDRESS: u8 = 0x5B;
+
+/// Addresses processes may access on the Qwiic bus: every 7-bit address
+/// except the reserved ones and those of the kernel's sensors.
+static PROCESS_I2C_ADDRESSES: [u8; 110] = {
+    let mut addresses = [0; 110];
+    let mut index = 0;
+    let mut address = 0x08;
+    while address <= 0x77 {
+        if address != BME280_ADDRESS && address != CCS811_ADDRESS {
+            addresses[index] = address;
+            index += 1;
+        }
+        address += 1;
+    }
+    addresses
+};
+
 const LORA_SPI_DRIVER_NUM: usize = capsules_core::driver::NUM::LoRaPhySPI as usize;
 const LORA_GPIO_DRIVER_NUM: usize = capsules_core::driver::NUM::LoRaPhyGPIO as usize;
 
@@ -176,6 +196,7 @@ impl KernelResources<apollo3::chip::Apollo3<Apollo3DefaultPeripherals>> for LoRa
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -201,6 +222,9 @@ impl KernelResources<apollo3::chip::Apollo3<Apollo3DefaultPeripherals>> for LoRa
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 unsafe fn setup() -> (
@@ -327,7 +351,8 @@ unsafe fn setup() -> (
             board_kernel.create_grant(
                 capsules_core::i2c_master::DRIVER_NUM,
                 &memory_allocation_cap
-            )
+            ),
+            Some(&PROCESS_I2C_ADDRESSES),
         )
     );
 
@@ -338,7 +363,7 @@ unsafe fn setup() -> (
         components::i2c_mux_component_static!(apollo3::iom::Iom<'static>),
     );
 
-    let bme280 = Bme280Component::new(mux_i2c, 0x77).finalize(
+    let bme280 = Bme280Component::new(mux_i2c, BME280_ADDRESS).finalize(
         components::bme280_component_static!(apollo3::iom::Iom<'static>),
     );
     let temperature = components::temperature::TemperatureComponent::new(
@@ -355,7 +380,7 @@ unsafe fn setup() -> (
     .finalize(components::humidity_component_static!());
     BME280 = Some(bme280);
 
-    let ccs811 = Ccs811Component::new(mux_i2c, 0x5B).finalize(
+    let ccs811 = Ccs811Component::new(mux_i2c, CCS811_ADDRESS).finalize(
         components::ccs811_component_static!(apollo3::iom::Iom<'static>),
     );
     let air_quality = components::air_quality::AirQualityComponent::new(
@@ -138,6 +138,7 @@ impl KernelResources<apollo3::chip::Apollo3<Apollo3DefaultPeripherals>> for Redb
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -163,6 +164,9 @@ impl KernelResources<apollo3::chip::Apollo3<Apollo3DefaultPeripherals>> for Redb
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 unsafe fn setup() -> (
@@ -284,7 +288,8 @@ unsafe fn setup() -> (
             board_kernel.create_grant(
                 capsules_core::i2c_master::DRIVER_NUM,
                 &memory_allocation_cap
-            )
+            ),
+            None,
         )
     );
 
@@ -95,6 +95,7 @@ impl KernelResources<arty_e21_chip::chip::ArtyExx<'static, ArtyExxDefaultPeriphe
     type SchedulerTimer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -120,6 +121,9 @@ impl KernelResources<arty_e21_chip::chip::ArtyExx<'static, ArtyExxDefaultPeriphe
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// Main function.
@@ -233,6 +233,7 @@ impl KernelResources<nrf52::chip::NRF52<'static, Nrf52840DefaultPeripherals<'sta
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -258,6 +259,9 @@ impl KernelResources<nrf52::chip::NRF52<'static, Nrf52840DefaultPeripherals<'sta
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// This is in a separate, inline(never) function so that its stack frame is
@@ -687,6 +691,8 @@ pub unsafe fn main() {
         capsules_extra::screen::DRIVER_NUM,
         tft,
         Some(tft),
+        kernel::hil::screen::ScreenRotation::Normal,
+        false,
     )
     .finalize(components::screen_component_static!(57600));
 
@@ -0,0 +1,74 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for capturing waveforms with an ADC's high-speed mode.
+//!
+//! The capture takes over the ADC, which must not be shared.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let capture = components::adc_capture::AdcCaptureComponent::new(
+//!     &peripherals.adc,
+//!     channel,
+//!     44100,
+//! )
+//! .finalize(components::adc_capture_component_static!(sam4l::adc::Adc));
+//! ```
+
+use capsules_extra::adc_capture::{AdcCapture, SPARE_SAMPLES};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::adc;
+
+#[macro_export]
+macro_rules! adc_capture_component_static {
+    ($A:ty $(,)?) => {{
+        let spare = kernel::static_buf!([u16; capsules_extra::adc_capture::SPARE_SAMPLES]);
+        let capture = kernel::static_buf!(capsules_extra::adc_capture::AdcCapture<'static, $A>);
+
+        (spare, capture)
+    };};
+}
+
+pub struct AdcCaptureComponent<A: 'static + adc::AdcHighSpeed<'static>> {
+    adc: &'static A,
+    channel: &'static A::Channel,
+    frequency: u32,
+}
+
+impl<A: 'static + adc::AdcHighSpeed<'static>> AdcCaptureComponent<A> {
+    pub fn new(
+        adc: &'static A,
+        channel: &'static A::Channel,
+        frequency: u32,
+    ) -> AdcCaptureComponent<A> {
+        AdcCaptureComponent {
+            adc,
+            channel,
+            frequency,
+        }
+    }
+}
+
+impl<A: 'static + adc::AdcHighSpeed<'static>> Component for AdcCaptureComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u16; SPARE_SAMPLES]>,
+        &'static mut MaybeUninit<AdcCapture<'static, A>>,
+    );
+    type Output = &'static AdcCapture<'static, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spare = s.0.write([0; SPARE_SAMPLES]);
+        let capture = s.1.write(AdcCapture::new(
+            self.adc,
+            self.channel,
+            self.frequency,
+            spare,
+        ));
+        self.adc.set_highspeed_client(capture);
+        capture
+    }
+}
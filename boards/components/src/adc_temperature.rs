@@ -0,0 +1,97 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for NTC thermistors read by an ADC.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let thermistor = components::adc_temperature::NtcThermistorComponent::new(
+//!     adc_mux,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput4),
+//!     capsules_extra::adc_temperature::Divider::ThermistorLow,
+//!     10_000, // reference_ohms
+//!     capsules_extra::adc_temperature::SteinhartHart {
+//!         a: 1_009_249_522_000,
+//!         b: 237_840_544_400,
+//!         c: 201_920_270,
+//!     },
+//! )
+//! .finalize(components::ntc_thermistor_component_static!(
+//!     nrf52840::adc::Adc
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_adc::{AdcDevice, MuxAdc};
+use capsules_extra::adc_temperature::{Divider, NtcThermistor, SteinhartHart};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::adc;
+use kernel::hil::adc::AdcChannel;
+
+#[macro_export]
+macro_rules! ntc_thermistor_component_static {
+    ($A:ty $(,)?) => {{
+        let adc_device = components::adc_component_static!($A);
+        let thermistor = kernel::static_buf!(
+            capsules_extra::adc_temperature::NtcThermistor<
+                'static,
+                capsules_core::virtualizers::virtual_adc::AdcDevice<'static, $A>,
+            >
+        );
+
+        (adc_device, thermistor)
+    };};
+}
+
+pub struct NtcThermistorComponent<A: 'static + adc::Adc<'static>> {
+    adc_mux: &'static MuxAdc<'static, A>,
+    adc_channel: A::Channel,
+    divider: Divider,
+    reference_ohms: u32,
+    coefficients: SteinhartHart,
+}
+
+impl<A: 'static + adc::Adc<'static>> NtcThermistorComponent<A> {
+    pub fn new(
+        adc_mux: &'static MuxAdc<'static, A>,
+        adc_channel: A::Channel,
+        divider: Divider,
+        reference_ohms: u32,
+        coefficients: SteinhartHart,
+    ) -> NtcThermistorComponent<A> {
+        NtcThermistorComponent {
+            adc_mux,
+            adc_channel,
+            divider,
+            reference_ohms,
+            coefficients,
+        }
+    }
+}
+
+impl<A: 'static + adc::Adc<'static>> Component for NtcThermistorComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<AdcDevice<'static, A>>,
+        &'static mut MaybeUninit<NtcThermistor<'static, AdcDevice<'static, A>>>,
+    );
+    type Output = &'static NtcThermistor<'static, AdcDevice<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let adc_device =
+            crate::adc::AdcComponent::new(self.adc_mux, self.adc_channel).finalize(s.0);
+
+        let thermistor = s.1.write(NtcThermistor::new(
+            adc_device,
+            self.divider,
+            self.reference_ohms,
+            self.coefficients,
+        ));
+
+        adc_device.set_client(thermistor);
+
+        thermistor
+    }
+}
@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the ADS1115 I2C ADC.
+//!
+//! Uses the ALERT/RDY pin, which is open drain and gets the pin's pull-up,
+//! as a conversion-ready interrupt.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ads1115 = components::ads1115::Ads1115Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ads1115::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[ADS1115_ALERT_RDY],
+//! )
+//! .finalize(components::ads1115_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::ads1115::{Ads1115, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! ads1115_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::ads1115::BUFFER_SIZE]);
+        let ads1115 = kernel::static_buf!(
+            capsules_extra::ads1115::Ads1115<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, ads1115)
+    };};
+}
+
+pub type Ads1115ComponentType<I, G> = Ads1115<'static, I2CDevice<'static, I>, G>;
+
+pub struct Ads1115Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    ready_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Ads1115Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        ready_pin: &'static G,
+    ) -> Ads1115Component<I, G> {
+        Ads1115Component {
+            i2c_mux,
+            i2c_address,
+            ready_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Ads1115Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Ads1115ComponentType<I, G>>,
+    );
+    type Output = &'static Ads1115ComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let ads1115 = s.2.write(Ads1115::new(i2c_device, self.ready_pin, buffer));
+        i2c_device.set_client(ads1115);
+
+        self.ready_pin.make_input();
+        self.ready_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.ready_pin.set_client(ads1115);
+
+        ads1115
+    }
+}
@@ -0,0 +1,99 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the AHT20 sensor.
+//!
+//! I2C Interface
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let aht20 = components::aht20::Aht20Component::new(sensors_i2c_bus, capsules_extra::aht20::BASE_ADDR, mux_alarm).finalize(
+//!         components::aht20_component_static!(nrf52::rtc::Rtc<'static>, nrf52::i2c::TWI),
+//!     );
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::aht20::{Aht20, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! aht20_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::aht20::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let aht20_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let aht20 = kernel::static_buf!(
+            capsules_extra::aht20::Aht20<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (aht20_alarm, i2c_device, aht20, buffer)
+    };};
+}
+
+pub struct Aht20Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Aht20Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Aht20Component<A, I> {
+        Aht20Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Aht20Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<
+            Aht20<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>,
+        >,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Aht20<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let aht20_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let aht20_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        aht20_alarm.setup();
+
+        let aht20 = static_buffer
+            .2
+            .write(Aht20::new(aht20_i2c, buffer, aht20_alarm));
+        aht20_i2c.set_client(aht20);
+        aht20_alarm.set_alarm_client(aht20);
+
+        aht20
+    }
+}
@@ -4,9 +4,10 @@
 
 //! Components for hardware timer Alarms.
 //!
-//! This provides two components, `AlarmMuxComponent`, which provides a
-//! multiplexed interface to a hardware alarm, and `AlarmDriverComponent`,
-//! which provides an alarm system call interface.
+//! This provides three components, `AlarmMuxComponent`, which provides a
+//! multiplexed interface to a hardware alarm, `AlarmDriverComponent`,
+//! which provides an alarm system call interface, and `AlarmTimerComponent`,
+//! which provides one-shot and repeating timers to userspace.
 //!
 //! Usage
 //! -----
@@ -17,6 +18,12 @@
 //! ast.configure(mux_alarm);
 //! let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
 //!     .finalize(components::alarm_component_static!(sam4l::ast::Ast));
+//! let alarm_timer = components::alarm::AlarmTimerComponent::new(
+//!     board_kernel,
+//!     capsules_core::alarm_timer::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::alarm_timer_component_static!(sam4l::ast::Ast));
 //! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
@@ -25,6 +32,7 @@
 use core::mem::MaybeUninit;
 
 use capsules_core::alarm::AlarmDriver;
+use capsules_core::alarm_timer::AlarmTimer;
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::component::Component;
@@ -57,6 +65,24 @@ macro_rules! alarm_component_static {
     };};
 }
 
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! alarm_timer_component_static {
+    ($A:ty $(,)?) => {{
+        let mux_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let alarm_timer = kernel::static_buf!(
+            capsules_core::alarm_timer::AlarmTimer<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (mux_alarm, alarm_timer)
+    };};
+}
+
 pub struct AlarmMuxComponent<A: 'static + time::Alarm<'static>> {
     alarm: &'static A,
 }
@@ -121,3 +147,46 @@ impl<A: 'static + time::Alarm<'static>> Component for AlarmDriverComponent<A> {
         alarm
     }
 }
+
+pub struct AlarmTimerComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>> AlarmTimerComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux: &'static MuxAlarm<'static, A>,
+    ) -> AlarmTimerComponent<A> {
+        AlarmTimerComponent {
+            board_kernel,
+            driver_num,
+            alarm_mux: mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for AlarmTimerComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<AlarmTimer<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static AlarmTimer<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        virtual_alarm.setup();
+
+        let alarm_timer = static_buffer.1.write(AlarmTimer::new(
+            virtual_alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        virtual_alarm.set_alarm_client(alarm_timer);
+        alarm_timer
+    }
+}
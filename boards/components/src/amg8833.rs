@@ -0,0 +1,108 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the AMG8833 8x8 thermal sensor.
+//!
+//! The INT pin is optional, and is only needed for the threshold interrupt.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let amg8833 = components::amg8833::Amg8833Component::new(
+//!     mux_i2c,
+//!     capsules_extra::amg8833::BASE_ADDR,
+//!     Some(&nrf52840_peripherals.gpio_port[AMG8833_INT]),
+//! )
+//! .finalize(components::amg8833_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::amg8833::{Amg8833, BUFFER_SIZE, PIXELS};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! amg8833_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::amg8833::BUFFER_SIZE]);
+        let frame = kernel::static_buf!([i32; capsules_extra::amg8833::PIXELS]);
+        let amg8833 = kernel::static_buf!(
+            capsules_extra::amg8833::Amg8833<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, buffer, frame, amg8833)
+    };};
+}
+
+pub struct Amg8833Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: Option<&'static G>,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Amg8833Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: Option<&'static G>,
+    ) -> Amg8833Component<I, G> {
+        Amg8833Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Amg8833Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[i32; PIXELS]>,
+        &'static mut MaybeUninit<Amg8833<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static Amg8833<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+        let frame = s.2.write([0; PIXELS]);
+
+        let amg8833 = s.3.write(Amg8833::new(
+            i2c_device,
+            self.interrupt_pin
+                .map(|pin| pin as &dyn gpio::InterruptPin<'static>),
+            buffer,
+            frame,
+        ));
+        i2c_device.set_client(amg8833);
+
+        // INT is an open drain output, active low.
+        if let Some(pin) = self.interrupt_pin {
+            pin.make_input();
+            pin.set_floating_state(gpio::FloatingState::PullUp);
+            pin.set_client(amg8833);
+        }
+
+        amg8833
+    }
+}
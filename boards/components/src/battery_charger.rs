@@ -0,0 +1,65 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for any battery charger.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let charger = BatteryChargerComponent::new(board_kernel, capsules_extra::battery_charger::DRIVER_NUM, bq24195)
+//!     .finalize(components::battery_charger_component_static!());
+//! ```
+
+use capsules_extra::battery_charger::BatteryChargerDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! battery_charger_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::battery_charger::BatteryChargerDriver<'static>)
+    };};
+}
+
+pub struct BatteryChargerComponent<T: 'static + hil::battery_charger::BatteryCharger<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    charger: &'static T,
+}
+
+impl<T: 'static + hil::battery_charger::BatteryCharger<'static>> BatteryChargerComponent<T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        charger: &'static T,
+    ) -> BatteryChargerComponent<T> {
+        BatteryChargerComponent {
+            board_kernel,
+            driver_num,
+            charger,
+        }
+    }
+}
+
+impl<T: 'static + hil::battery_charger::BatteryCharger<'static>> Component
+    for BatteryChargerComponent<T>
+{
+    type StaticInput = &'static mut MaybeUninit<BatteryChargerDriver<'static>>;
+    type Output = &'static BatteryChargerDriver<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.write(BatteryChargerDriver::new(
+            self.charger,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::battery_charger::BatteryCharger::set_client(self.charger, driver);
+        driver
+    }
+}
@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the BME680 and BME688 environmental sensors.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bme680 = components::bme680::Bme680Component::new(
+//!     mux_i2c,
+//!     capsules_extra::bme680::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::bme680_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! let temperature = components::temperature::TemperatureComponent::new(
+//!     board_kernel,
+//!     capsules_extra::temperature::DRIVER_NUM,
+//!     bme680,
+//! )
+//! .finalize(components::temperature_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::bme680::{Bme680, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! bme680_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::bme680::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let bme680 = kernel::static_buf!(
+            capsules_extra::bme680::Bme680<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, bme680, buffer)
+    };};
+}
+
+pub type Bme680ComponentType<A, I> =
+    Bme680<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct Bme680Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Bme680Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Bme680Component<A, I> {
+        Bme680Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Bme680Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Bme680ComponentType<A, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Bme680ComponentType<A, I>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let bme680_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let bme680_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        bme680_alarm.setup();
+
+        let bme680 = static_buffer
+            .2
+            .write(Bme680::new(bme680_i2c, bme680_alarm, buffer));
+        bme680_i2c.set_client(bme680);
+        bme680_alarm.set_alarm_client(bme680);
+
+        let _ = bme680.start();
+        bme680
+    }
+}
@@ -0,0 +1,250 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for the BMI270 IMU.
+//!
+//! The BMI270 is connected over I2C or SPI. Create the bus device with
+//! `Bmi270I2CTransportComponent` or `Bmi270SpiTransportComponent`, and pass
+//! it to `Bmi270Component` with the output data rates and ranges to use.
+//!
+//! The BMI270 needs the configuration file of its feature engine, which
+//! Bosch publishes as `bmi270_config_file` in the BMI270 Sensor API. The
+//! board stores it in a static and passes it to the component, for example
+//! with `static BMI270_CONFIG_FILE: [u8; 8192] = *include_bytes!("bmi270.bin");`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bmi270_transport = components::bmi270::Bmi270I2CTransportComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::bmi270::I2C_ADDRESS,
+//! )
+//! .finalize(components::bmi270_i2c_transport_component_static!(
+//!     nrf52840::i2c::TWI
+//! ));
+//! let bmi270 = components::bmi270::Bmi270Component::new(
+//!     bmi270_transport,
+//!     mux_alarm,
+//!     &BMI270_CONFIG_FILE,
+//!     capsules_extra::bmi270::OutputDataRate::Hz100,
+//!     capsules_extra::bmi270::AccelRange::G4,
+//!     capsules_extra::bmi270::OutputDataRate::Hz200,
+//!     capsules_extra::bmi270::GyroRange::Dps2000,
+//! )
+//! .finalize(components::bmi270_component_static!(
+//!     capsules_extra::bmi270::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::bmi270::{
+    AccelRange, Bmi270, GyroRange, I2CTransport, OutputDataRate, SpiTransport, Transport,
+    BUFFER_LEN,
+};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! bmi270_i2c_transport_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let transport = kernel::static_buf!(
+            capsules_extra::bmi270::I2CTransport<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! bmi270_spi_transport_component_static {
+    ($S:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::bmi270::BUFFER_LEN]);
+        let transport = kernel::static_buf!(
+            capsules_extra::bmi270::SpiTransport<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi_device, tx_buffer, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! bmi270_component_static {
+    ($T:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::bmi270::BUFFER_LEN]);
+        let bmi270 = kernel::static_buf!(
+            capsules_extra::bmi270::Bmi270<
+                'static,
+                $T,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, buffer, bmi270)
+    };};
+}
+
+pub struct Bmi270I2CTransportComponent<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Bmi270I2CTransportComponent<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+    ) -> Bmi270I2CTransportComponent<I> {
+        Bmi270I2CTransportComponent {
+            i2c_mux,
+            i2c_address,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Bmi270I2CTransportComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<I2CTransport<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static I2CTransport<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let transport = s.1.write(I2CTransport::new(i2c_device));
+        i2c_device.set_client(transport);
+
+        transport
+    }
+}
+
+pub struct Bmi270SpiTransportComponent<S: 'static + spi::SpiMaster<'static>> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    baud_rate: u32,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Bmi270SpiTransportComponent<S> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        baud_rate: u32,
+    ) -> Bmi270SpiTransportComponent<S> {
+        Bmi270SpiTransportComponent {
+            spi_mux,
+            chip_select,
+            baud_rate,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Component for Bmi270SpiTransportComponent<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>>,
+    );
+    type Output = &'static SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let tx_buffer = s.1.write([0; BUFFER_LEN]);
+        let transport = s.2.write(SpiTransport::new(spi_device, tx_buffer));
+        spi_device.set_client(transport);
+
+        if let Err(error) = transport.configure(self.baud_rate) {
+            panic!("Failed to setup BMI270 SPI ({:?})", error);
+        }
+
+        transport
+    }
+}
+
+pub struct Bmi270Component<T: 'static + Transport<'static>, A: 'static + Alarm<'static>> {
+    transport: &'static T,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    config: &'static [u8],
+    accel_rate: OutputDataRate,
+    accel_range: AccelRange,
+    gyro_rate: OutputDataRate,
+    gyro_range: GyroRange,
+}
+
+impl<T: 'static + Transport<'static>, A: 'static + Alarm<'static>> Bmi270Component<T, A> {
+    pub fn new(
+        transport: &'static T,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        config: &'static [u8],
+        accel_rate: OutputDataRate,
+        accel_range: AccelRange,
+        gyro_rate: OutputDataRate,
+        gyro_range: GyroRange,
+    ) -> Bmi270Component<T, A> {
+        Bmi270Component {
+            transport,
+            alarm_mux,
+            config,
+            accel_rate,
+            accel_range,
+            gyro_rate,
+            gyro_range,
+        }
+    }
+}
+
+impl<T: 'static + Transport<'static>, A: 'static + Alarm<'static>> Component
+    for Bmi270Component<T, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<Bmi270<'static, T, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Bmi270<'static, T, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = s.1.write([0; BUFFER_LEN]);
+        let bmi270 =
+            s.2.write(Bmi270::new(self.transport, alarm, self.config, buffer));
+        self.transport.set_client(bmi270);
+        alarm.set_alarm_client(bmi270);
+
+        let _ = bmi270.configure(
+            self.accel_rate,
+            self.accel_range,
+            self.gyro_rate,
+            self.gyro_range,
+        );
+
+        bmi270
+    }
+}
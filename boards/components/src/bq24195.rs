@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the BQ24195 battery charger.
+//!
+//! I2C Interface, with the INT pin connected to a GPIO.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bq24195 = components::bq24195::Bq24195Component::new(
+//!     i2c_mux,
+//!     capsules_extra::bq24195::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[CHARGER_INT_PIN],
+//! )
+//! .finalize(components::bq24195_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::bq24195::{Bq24195, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! bq24195_component_static {
+    ($I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::bq24195::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let bq24195 = kernel::static_buf!(
+            capsules_extra::bq24195::Bq24195<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, bq24195, buffer)
+    };};
+}
+
+pub struct Bq24195Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static P,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, P: 'static + gpio::InterruptPin<'static>>
+    Bq24195Component<I, P>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static P,
+    ) -> Bq24195Component<I, P> {
+        Bq24195Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, P: 'static + gpio::InterruptPin<'static>> Component
+    for Bq24195Component<I, P>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Bq24195<'static, I2CDevice<'static, I>>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Bq24195<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let bq24195_i2c = static_buffer
+            .0
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.2.write([0; BUFFER_SIZE]);
+
+        let bq24195 = static_buffer.1.write(Bq24195::new(bq24195_i2c, buffer));
+        bq24195_i2c.set_client(bq24195);
+
+        // INT is open drain and pulses low.
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.interrupt_pin.set_client(bq24195);
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+
+        bq24195
+    }
+}
@@ -0,0 +1,48 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for reporting the state of peripheral clocks to userspace.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let clocks = static_init!(
+//!     [&'static dyn kernel::platform::chip::ClockInterface; 2],
+//!     [&peripherals.spi_host0_clock, &peripherals.spi_host1_clock]
+//! );
+//! let clk_stats = components::clk_stats::ClkStatsComponent::new(clocks)
+//!     .finalize(components::clk_stats_component_static!());
+//! ```
+
+use capsules_extra::clk_stats::ClkStats;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::platform::chip::ClockInterface;
+
+#[macro_export]
+macro_rules! clk_stats_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::clk_stats::ClkStats<'static>)
+    };};
+}
+
+pub struct ClkStatsComponent {
+    clocks: &'static [&'static dyn ClockInterface],
+}
+
+impl ClkStatsComponent {
+    pub fn new(clocks: &'static [&'static dyn ClockInterface]) -> ClkStatsComponent {
+        ClkStatsComponent { clocks }
+    }
+}
+
+impl Component for ClkStatsComponent {
+    type StaticInput = &'static mut MaybeUninit<ClkStats<'static>>;
+    type Output = &'static ClkStats<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        s.write(ClkStats::new(self.clocks))
+    }
+}
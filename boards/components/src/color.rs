@@ -0,0 +1,63 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for any color sensor.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let color = ColorComponent::new(board_kernel, capsules_extra::color::DRIVER_NUM, tcs34725)
+//!     .finalize(components::color_component_static!());
+//! ```
+
+use capsules_extra::color::ColorSensor;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! color_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::color::ColorSensor<'static>)
+    };};
+}
+
+pub struct ColorComponent<T: 'static + hil::sensors::Color<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sensor: &'static T,
+}
+
+impl<T: 'static + hil::sensors::Color<'static>> ColorComponent<T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sensor: &'static T,
+    ) -> ColorComponent<T> {
+        ColorComponent {
+            board_kernel,
+            driver_num,
+            sensor,
+        }
+    }
+}
+
+impl<T: 'static + hil::sensors::Color<'static>> Component for ColorComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<ColorSensor<'static>>;
+    type Output = &'static ColorSensor<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let color = s.write(ColorSensor::new(
+            self.sensor,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::sensors::Color::set_client(self.sensor, color);
+        color
+    }
+}
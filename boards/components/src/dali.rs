@@ -0,0 +1,132 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a DALI lighting control bus master.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dali = components::dali::DaliMasterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::dali::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[DALI_TX],
+//!     &nrf52840_peripherals.gpio_port[DALI_RX],
+//!     mux_alarm,
+//! )
+//! .finalize(components::dali_master_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::dali::{DaliDriver, DaliMaster};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::gpio;
+use kernel::hil::lighting::DaliMaster as _;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! dali_master_component_static {
+    ($TX:ty, $RX:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let dali = kernel::static_buf!(
+            capsules_extra::dali::DaliMaster<
+                'static,
+                $TX,
+                $RX,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::dali::DaliDriver<
+                'static,
+                capsules_extra::dali::DaliMaster<
+                    'static,
+                    $TX,
+                    $RX,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+
+        (alarm, dali, driver)
+    };};
+}
+
+pub type DaliMasterComponentType<TX, RX, A> =
+    DaliDriver<'static, DaliMaster<'static, TX, RX, VirtualMuxAlarm<'static, A>>>;
+
+pub struct DaliMasterComponent<
+    TX: 'static + gpio::Pin,
+    RX: 'static + gpio::Pin,
+    A: 'static + Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    tx: &'static TX,
+    rx: &'static RX,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<TX: 'static + gpio::Pin, RX: 'static + gpio::Pin, A: 'static + Alarm<'static>>
+    DaliMasterComponent<TX, RX, A>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        tx: &'static TX,
+        rx: &'static RX,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> DaliMasterComponent<TX, RX, A> {
+        DaliMasterComponent {
+            board_kernel,
+            driver_num,
+            tx,
+            rx,
+            alarm_mux,
+        }
+    }
+}
+
+impl<TX: 'static + gpio::Pin, RX: 'static + gpio::Pin, A: 'static + Alarm<'static>> Component
+    for DaliMasterComponent<TX, RX, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<DaliMaster<'static, TX, RX, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<DaliMasterComponentType<TX, RX, A>>,
+    );
+    type Output = &'static DaliMasterComponentType<TX, RX, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        // Release the bus until the first frame.
+        self.tx.make_output();
+        self.tx.set();
+        self.rx.make_input();
+
+        let dali = s.1.write(DaliMaster::new(self.tx, self.rx, alarm));
+        alarm.set_alarm_client(dali);
+
+        let driver = s.2.write(DaliDriver::new(
+            dali,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        dali.set_client(driver);
+
+        driver
+    }
+}
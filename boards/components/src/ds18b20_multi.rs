@@ -0,0 +1,95 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for DS18B20 temperature sensors on a 1-Wire bus.
+//!
+//! The bus is scanned for sensors when the component is finalized.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ds18b20 = components::ds18b20_multi::Ds18b20MultiComponent::new(
+//!     onewire_bus,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ds18b20_multi_component_static!(
+//!     OneWireBus,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     4
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ds18b20_multi::{Ds18b20Multi, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::onewire::OneWire;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! ds18b20_multi_component_static {
+    ($B:ty, $A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::ds18b20_multi::BUFFER_SIZE]);
+        let ds18b20 = kernel::static_buf!(
+            capsules_extra::ds18b20_multi::Ds18b20Multi<
+                'static,
+                $B,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $N,
+            >
+        );
+
+        (alarm, buffer, ds18b20)
+    };};
+}
+
+pub struct Ds18b20MultiComponent<
+    B: 'static + OneWire<'static>,
+    A: 'static + Alarm<'static>,
+    const N: usize,
+> {
+    bus: &'static B,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<B: 'static + OneWire<'static>, A: 'static + Alarm<'static>, const N: usize>
+    Ds18b20MultiComponent<B, A, N>
+{
+    pub fn new(
+        bus: &'static B,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Ds18b20MultiComponent<B, A, N> {
+        Ds18b20MultiComponent { bus, alarm_mux }
+    }
+}
+
+impl<B: 'static + OneWire<'static>, A: 'static + Alarm<'static>, const N: usize> Component
+    for Ds18b20MultiComponent<B, A, N>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Ds18b20Multi<'static, B, VirtualMuxAlarm<'static, A>, N>>,
+    );
+    type Output = &'static Ds18b20Multi<'static, B, VirtualMuxAlarm<'static, A>, N>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let ds18b20 = s.2.write(Ds18b20Multi::new(self.bus, alarm, buffer));
+        self.bus.set_client(ds18b20);
+        alarm.set_alarm_client(ds18b20);
+
+        // If the bus is busy, the board can scan again later.
+        let _ = ds18b20.scan();
+
+        ds18b20
+    }
+}
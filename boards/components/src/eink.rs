@@ -0,0 +1,173 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for SSD1680 and UC8151 e-paper displays.
+//!
+//! Uses a SPI interface, the DC, reset and busy pins and an alarm. The width
+//! and height given to the macro are the panel's geometry, and the frame
+//! buffer is allocated for it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let eink = components::eink::EinkComponent::new(
+//!     mux_spi,
+//!     &nrf52840::gpio::PORT[GPIO_D4],
+//!     &nrf52840::gpio::PORT[GPIO_D3],
+//!     &nrf52840::gpio::PORT[GPIO_D2],
+//!     &nrf52840::gpio::PORT[GPIO_D5],
+//!     mux_alarm,
+//!     &capsules_extra::eink::SSD1680,
+//! )
+//! .finalize(components::eink_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::gpio::GPIOPin<'static>,
+//!     128,
+//!     296,
+//! ));
+//! let _ = eink.init();
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::eink::{Controller, Eink, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! eink_component_static {
+    ($S:ty, $A:ty, $P:ty, $W:expr, $H:expr $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::eink::BUFFER_SIZE]);
+        let framebuffer = kernel::static_buf!([u8; capsules_extra::eink::framebuffer_len($W, $H)]);
+        let spi = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let eink = kernel::static_buf!(
+            capsules_extra::eink::Eink<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                $P,
+                { $W },
+                { $H },
+            >
+        );
+
+        (spi, alarm, eink, buffer, framebuffer)
+    };};
+}
+
+pub type EinkComponentType<S, A, P, const WIDTH: usize, const HEIGHT: usize> = Eink<
+    'static,
+    VirtualMuxAlarm<'static, A>,
+    VirtualSpiMasterDevice<'static, S>,
+    P,
+    WIDTH,
+    HEIGHT,
+>;
+
+pub struct EinkComponent<
+    S: 'static + spi::SpiMaster<'static>,
+    A: 'static + Alarm<'static>,
+    P: 'static + gpio::Pin,
+    const WIDTH: usize,
+    const HEIGHT: usize,
+    const FRAMEBUFFER_LEN: usize,
+> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    dc: &'static P,
+    reset: &'static P,
+    busy: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    controller: &'static Controller,
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        A: 'static + Alarm<'static>,
+        P: 'static + gpio::Pin,
+        const WIDTH: usize,
+        const HEIGHT: usize,
+        const FRAMEBUFFER_LEN: usize,
+    > EinkComponent<S, A, P, WIDTH, HEIGHT, FRAMEBUFFER_LEN>
+{
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        dc: &'static P,
+        reset: &'static P,
+        busy: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        controller: &'static Controller,
+    ) -> EinkComponent<S, A, P, WIDTH, HEIGHT, FRAMEBUFFER_LEN> {
+        EinkComponent {
+            spi_mux,
+            chip_select,
+            dc,
+            reset,
+            busy,
+            alarm_mux,
+            controller,
+        }
+    }
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        A: 'static + Alarm<'static>,
+        P: 'static + gpio::Pin,
+        const WIDTH: usize,
+        const HEIGHT: usize,
+        const FRAMEBUFFER_LEN: usize,
+    > Component for EinkComponent<S, A, P, WIDTH, HEIGHT, FRAMEBUFFER_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<EinkComponentType<S, A, P, WIDTH, HEIGHT>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[u8; FRAMEBUFFER_LEN]>,
+    );
+    type Output = &'static EinkComponentType<S, A, P, WIDTH, HEIGHT>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let spi_device = static_buffer
+            .0
+            .write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let alarm = static_buffer.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+        let framebuffer = static_buffer.4.write([0; FRAMEBUFFER_LEN]);
+
+        let eink = static_buffer.2.write(Eink::new(
+            spi_device,
+            alarm,
+            self.dc,
+            self.reset,
+            self.busy,
+            buffer,
+            framebuffer,
+            self.controller,
+        ));
+        spi_device.set_client(eink);
+        alarm.set_alarm_client(eink);
+        eink.register();
+
+        eink
+    }
+}
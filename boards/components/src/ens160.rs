@@ -0,0 +1,124 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the ENS160 air quality sensor.
+//!
+//! The readings can be compensated with the temperature and humidity of
+//! another sensor. This component takes over the temperature and humidity
+//! clients of that sensor, so they must not have another client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ens160 = components::ens160::Ens160Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ens160::BASE_ADDR,
+//!     mux_alarm,
+//!     Some((hts221, hts221)),
+//! )
+//! .finalize(components::ens160_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::ens160::{Ens160, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::sensors::{HumidityDriver, TemperatureDriver};
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! ens160_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::ens160::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let ens160 = kernel::static_buf!(
+            capsules_extra::ens160::Ens160<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, ens160, buffer)
+    };};
+}
+
+pub type Ens160ComponentType<A, I> =
+    Ens160<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct Ens160Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    compensation: Option<(
+        &'static dyn TemperatureDriver<'static>,
+        &'static dyn HumidityDriver<'static>,
+    )>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Ens160Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        compensation: Option<(
+            &'static dyn TemperatureDriver<'static>,
+            &'static dyn HumidityDriver<'static>,
+        )>,
+    ) -> Ens160Component<A, I> {
+        Ens160Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            compensation,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Ens160Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Ens160ComponentType<A, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Ens160ComponentType<A, I>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ens160_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let ens160_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        ens160_alarm.setup();
+
+        let ens160 = static_buffer
+            .2
+            .write(Ens160::new(ens160_i2c, ens160_alarm, buffer));
+        ens160_i2c.set_client(ens160);
+        ens160_alarm.set_alarm_client(ens160);
+
+        if let Some((temperature, humidity)) = self.compensation {
+            temperature.set_client(ens160);
+            humidity.set_client(ens160);
+            ens160.set_compensation_source(temperature, humidity);
+        }
+
+        let _ = ens160.start();
+        ens160
+    }
+}
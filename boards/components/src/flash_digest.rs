@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for hashing a range of flash with a digest engine.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let flash_digest = components::flash_digest::FlashDigestComponent::new(flash_user, digest)
+//!     .finalize(components::flash_digest_component_static!(
+//!         capsules_core::virtualizers::virtual_flash::FlashUser<
+//!             'static,
+//!             lowrisc::flash_ctrl::FlashCtrl<'static>,
+//!         >,
+//!         capsules_core::virtualizers::virtual_digest::VirtualMuxDigest<
+//!             'static,
+//!             lowrisc::hmac::Hmac<'static>,
+//!             32,
+//!         >,
+//!         32,
+//!         lowrisc::flash_ctrl::PAGE_SIZE,
+//!     ));
+//! ```
+
+use capsules_extra::flash_digest::FlashDigest;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::digest;
+use kernel::hil::flash;
+
+#[macro_export]
+macro_rules! flash_digest_component_static {
+    ($F:ty, $D:ty, $L:expr, $PAGE_SIZE:expr $(,)?) => {{
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let data = kernel::static_buf!([u8; $PAGE_SIZE]);
+        let flash_digest =
+            kernel::static_buf!(capsules_extra::flash_digest::FlashDigest<'static, $F, $D, $L>);
+
+        (page, data, flash_digest)
+    };};
+}
+
+pub struct FlashDigestComponent<
+    F: 'static + flash::Flash + flash::HasClient<'static, FlashDigest<'static, F, D, L>>,
+    D: 'static + digest::Digest<'static, L>,
+    const L: usize,
+    const PAGE_SIZE: usize,
+> {
+    flash: &'static F,
+    digest: &'static D,
+}
+
+impl<
+        F: 'static + flash::Flash + flash::HasClient<'static, FlashDigest<'static, F, D, L>>,
+        D: 'static + digest::Digest<'static, L>,
+        const L: usize,
+        const PAGE_SIZE: usize,
+    > FlashDigestComponent<F, D, L, PAGE_SIZE>
+{
+    pub fn new(flash: &'static F, digest: &'static D) -> Self {
+        Self { flash, digest }
+    }
+}
+
+impl<
+        F: 'static + flash::Flash + flash::HasClient<'static, FlashDigest<'static, F, D, L>>,
+        D: 'static + digest::Digest<'static, L>,
+        const L: usize,
+        const PAGE_SIZE: usize,
+    > Component for FlashDigestComponent<F, D, L, PAGE_SIZE>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<F::Page>,
+        &'static mut MaybeUninit<[u8; PAGE_SIZE]>,
+        &'static mut MaybeUninit<FlashDigest<'static, F, D, L>>,
+    );
+    type Output = &'static FlashDigest<'static, F, D, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let page = s.0.write(F::Page::default());
+        let data = s.1.write([0; PAGE_SIZE]);
+
+        let flash_digest =
+            s.2.write(FlashDigest::new(self.flash, self.digest, page, data));
+
+        flash::HasClient::set_client(self.flash, flash_digest);
+        digest::Digest::set_client(self.digest, flash_digest);
+
+        flash_digest
+    }
+}
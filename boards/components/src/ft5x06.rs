@@ -0,0 +1,105 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the FT5x06 touch panel controller.
+//!
+//! The positions of touches are scaled to the screen resolution passed to
+//! the component.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ft5x06 = components::ft5x06::Ft5x06Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ft5x06::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[FT5X06_INT],
+//!     (800, 480),
+//! )
+//! .finalize(components::ft5x06_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::ft5x06::{Ft5x06, BUFFER_SIZE, MAX_TOUCHES, NO_TOUCH};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::touch::TouchEvent;
+
+#[macro_export]
+macro_rules! ft5x06_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::ft5x06::BUFFER_SIZE]);
+        let events = kernel::static_buf!(
+            [kernel::hil::touch::TouchEvent; capsules_extra::ft5x06::MAX_TOUCHES]
+        );
+        let ft5x06 = kernel::static_buf!(
+            capsules_extra::ft5x06::Ft5x06<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, buffer, events, ft5x06)
+    };};
+}
+
+pub type Ft5x06ComponentType<I> = Ft5x06<'static, I2CDevice<'static, I>>;
+
+pub struct Ft5x06Component<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+    screen_resolution: (u16, u16),
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Ft5x06Component<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+        screen_resolution: (u16, u16),
+    ) -> Ft5x06Component<I> {
+        Ft5x06Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+            screen_resolution,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Ft5x06Component<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[TouchEvent; MAX_TOUCHES]>,
+        &'static mut MaybeUninit<Ft5x06ComponentType<I>>,
+    );
+    type Output = &'static Ft5x06ComponentType<I>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+        let events = s.2.write([NO_TOUCH; MAX_TOUCHES]);
+
+        let ft5x06 = s.3.write(Ft5x06::new(
+            i2c_device,
+            self.interrupt_pin,
+            buffer,
+            events,
+            self.screen_resolution,
+        ));
+        i2c_device.set_client(ft5x06);
+
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.interrupt_pin.set_client(ft5x06);
+
+        ft5x06
+    }
+}
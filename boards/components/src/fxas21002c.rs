@@ -0,0 +1,97 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the FXAS21002C gyroscope.
+//!
+//! I2C Interface, with INT2 on an interrupt pin for data-ready events.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fxas21002c = components::fxas21002c::Fxas21002cComponent::new(
+//!     mux_i2c,
+//!     capsules_extra::fxas21002c::I2C_ADDRESS,
+//!     &peripherals.ports.pin(PinId::PTA01),
+//! )
+//! .finalize(components::fxas21002c_component_static!(imxrt1060::lpi2c::Lpi2c));
+//! fxas21002c
+//!     .configure(
+//!         capsules_extra::fxas21002c::FullScale::Dps500,
+//!         capsules_extra::fxas21002c::DataRate::Hz200,
+//!         capsules_extra::fxas21002c::LowPassFilter::Medium,
+//!     )
+//!     .unwrap();
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::fxas21002c::{Fxas21002c, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! fxas21002c_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::fxas21002c::BUF_LEN]);
+        let fxas21002c = kernel::static_buf!(
+            capsules_extra::fxas21002c::Fxas21002c<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, buffer, fxas21002c)
+    };};
+}
+
+pub type Fxas21002cComponentType<I> = Fxas21002c<'static, I2CDevice<'static, I>>;
+
+pub struct Fxas21002cComponent<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Fxas21002cComponent<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static dyn gpio::InterruptPin<'static>,
+    ) -> Fxas21002cComponent<I> {
+        Fxas21002cComponent {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Fxas21002cComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<Fxas21002c<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static Fxas21002cComponentType<I>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUF_LEN]);
+        let fxas21002c =
+            s.2.write(Fxas21002c::new(i2c_device, self.interrupt_pin, buffer));
+        i2c_device.set_client(fxas21002c);
+
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullNone);
+        self.interrupt_pin.set_client(fxas21002c);
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+
+        fxas21002c
+    }
+}
@@ -0,0 +1,99 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for rate limiting the interrupts of a GPIO pin.
+//!
+//! The returned pin is used in place of the original one, for example as
+//! the pin of a button or of the GPIO driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let button_pin = components::gpio_rate_limit::GpioRateLimitComponent::new(
+//!     &nrf52840_peripherals.gpio_port[BUTTON_PIN],
+//!     mux_alarm,
+//!     Some(capsules_extra::gpio_rate_limit::RateLimit {
+//!         max_interrupts: 10,
+//!         window_ms: 100,
+//!     }),
+//! )
+//! .finalize(components::gpio_rate_limit_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::gpio_rate_limit::{RateLimit, RateLimitedPin};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! gpio_rate_limit_component_static {
+    ($P:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let pin = kernel::static_buf!(
+            capsules_extra::gpio_rate_limit::RateLimitedPin<
+                'static,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, pin)
+    };};
+}
+
+pub struct GpioRateLimitComponent<
+    P: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+> {
+    pin: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    limit: Option<RateLimit>,
+}
+
+impl<P: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>>
+    GpioRateLimitComponent<P, A>
+{
+    pub fn new(
+        pin: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        limit: Option<RateLimit>,
+    ) -> GpioRateLimitComponent<P, A> {
+        GpioRateLimitComponent {
+            pin,
+            alarm_mux,
+            limit,
+        }
+    }
+}
+
+impl<P: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>> Component
+    for GpioRateLimitComponent<P, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<RateLimitedPin<'static, P, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static RateLimitedPin<'static, P, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let pin = s.1.write(RateLimitedPin::new(self.pin, alarm));
+        pin.set_rate_limit(self.limit);
+
+        self.pin.set_client(pin);
+        alarm.set_alarm_client(pin);
+
+        pin
+    }
+}
@@ -0,0 +1,110 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the HX711 load cell ADC.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hx711 = components::hx711::Hx711Component::new(
+//!     board_kernel,
+//!     capsules_extra::hx711::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[HX711_DATA],
+//!     &nrf52840_peripherals.gpio_port[HX711_CLK],
+//!     mux_alarm,
+//! )
+//! .finalize(components::hx711_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::hx711::{Hx711, Hx711Driver};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! hx711_component_static {
+    ($P:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let hx711 = kernel::static_buf!(
+            capsules_extra::hx711::Hx711<
+                'static,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::hx711::Hx711Driver<
+                'static,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, hx711, driver)
+    };};
+}
+
+pub struct Hx711Component<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    data: &'static P,
+    clock: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> Hx711Component<P, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        data: &'static P,
+        clock: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Hx711Component<P, A> {
+        Hx711Component {
+            board_kernel,
+            driver_num,
+            data,
+            clock,
+            alarm_mux,
+        }
+    }
+}
+
+impl<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> Component for Hx711Component<P, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Hx711<'static, P, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<Hx711Driver<'static, P, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Hx711Driver<'static, P, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let hx711 = s.1.write(Hx711::new(self.data, self.clock, alarm));
+        hx711.setup();
+        alarm.set_alarm_client(hx711);
+
+        let driver = s.2.write(Hx711Driver::new(
+            hx711,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        hx711.set_client(driver);
+
+        driver
+    }
+}
@@ -4,12 +4,21 @@
 
 //! Components for I2C.
 //!
-//! This provides two components.
+//! This provides five components.
 //!
 //! 1. `I2CMuxComponent` provides a virtualization layer for a I2C bus.
 //!
 //! 2. `I2CComponent` provides a virtualized client to the I2C bus.
 //!
+//! 3. `I2CRetryComponent` provides a virtualized client to the I2C bus that
+//!    retries the transactions the device does not acknowledge.
+//!
+//! 4. `I2CMasterDriverComponent` provides the I2C master syscall driver,
+//!    optionally restricted to a set of device addresses.
+//!
+//! 5. `I2CMasterSlaveDriverComponent` provides the I2C master/slave syscall
+//!    driver.
+//!
 //! Usage
 //! -----
 //! ```rust
@@ -17,16 +26,35 @@
 //!     .finalize(components::i2c_mux_component_static!());
 //! let client_i2c = components::i2c::I2CComponent::new(mux_i2c, 0x19)
 //!     .finalize(components::i2c_component_static!());
+//!
+//! // Retry up to 3 times, after 1, 2 and 4 ms.
+//! let retry_i2c = components::i2c::I2CRetryComponent::new(mux_i2c, 0x40, mux_alarm, 3, 1)
+//!     .finalize(components::i2c_retry_component_static!(
+//!         stm32f3xx::i2c::I2C<'static>,
+//!         stm32f3xx::tim2::Tim2<'static>
+//!     ));
+//!
+//! // Processes may only access the devices at 0x29 and 0x38.
+//! let i2c_master = components::i2c::I2CMasterDriverComponent::new(
+//!     board_kernel,
+//!     capsules_core::i2c_master::DRIVER_NUM,
+//!     &peripherals.i2c0,
+//!     Some(&[0x29, 0x38]),
+//! )
+//! .finalize(components::i2c_master_component_static!(lowrisc::i2c::I2c<'static>));
 //! ```
 
 // Author: Alexandru Radovici <msg4alex@gmail.com>
 
-use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_core::i2c_master::I2CMasterDriver;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C, RetryI2CDevice};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil::i2c::{self, NoSMBus};
+use kernel::hil::time::Alarm;
 
 // Setup static space for the objects.
 #[macro_export]
@@ -46,6 +74,36 @@ macro_rules! i2c_component_static {
     };};
 }
 
+#[macro_export]
+macro_rules! i2c_retry_component_static {
+    ($I:ty, $A:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let retry = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_i2c::RetryI2CDevice<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, alarm, retry)
+    };};
+}
+
+#[macro_export]
+macro_rules! i2c_master_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_master_buffer = kernel::static_buf!([u8; capsules_core::i2c_master::BUFFER_LENGTH]);
+        let driver = kernel::static_buf!(capsules_core::i2c_master::I2CMasterDriver<'static, $I>);
+
+        (driver, i2c_master_buffer)
+    };};
+}
+
 #[macro_export]
 macro_rules! i2c_master_slave_component_static {
     ($I:ty $(,)?) => {{
@@ -123,6 +181,119 @@ impl<I: 'static + i2c::I2CMaster<'static>> Component for I2CComponent<I> {
     }
 }
 
+pub type I2CRetryComponentType<I, A> =
+    RetryI2CDevice<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct I2CRetryComponent<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    retries: usize,
+    delay_ms: u32,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> I2CRetryComponent<I, A> {
+    /// A transaction is retried up to `retries` times, first after
+    /// `delay_ms`, with the delay doubling for every retry.
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        retries: usize,
+        delay_ms: u32,
+    ) -> Self {
+        I2CRetryComponent {
+            i2c_mux,
+            address,
+            alarm_mux,
+            retries,
+            delay_ms,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> Component
+    for I2CRetryComponent<I, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CRetryComponentType<I, A>>,
+    );
+    type Output = &'static I2CRetryComponentType<I, A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let i2c_device = static_buffer
+            .0
+            .write(I2CDevice::new(self.i2c_mux, self.address));
+        let alarm = static_buffer.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let retry = static_buffer.2.write(RetryI2CDevice::new(
+            i2c_device,
+            alarm,
+            self.retries,
+            self.delay_ms,
+        ));
+        i2c_device.set_client(retry);
+        alarm.set_alarm_client(retry);
+
+        retry
+    }
+}
+
+pub struct I2CMasterDriverComponent<I: 'static + i2c::I2CMaster<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    i2c: &'static I,
+    allowed_addresses: Option<&'static [u8]>,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> I2CMasterDriverComponent<I> {
+    /// With `allowed_addresses` set, processes can only access the devices
+    /// at those addresses.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        i2c: &'static I,
+        allowed_addresses: Option<&'static [u8]>,
+    ) -> Self {
+        I2CMasterDriverComponent {
+            board_kernel,
+            driver_num,
+            i2c,
+            allowed_addresses,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for I2CMasterDriverComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CMasterDriver<'static, I>>,
+        &'static mut MaybeUninit<[u8; capsules_core::i2c_master::BUFFER_LENGTH]>,
+    );
+    type Output = &'static I2CMasterDriver<'static, I>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let i2c_master_buffer = static_buffer
+            .1
+            .write([0; capsules_core::i2c_master::BUFFER_LENGTH]);
+
+        let i2c_master = static_buffer.0.write(I2CMasterDriver::new(
+            self.i2c,
+            i2c_master_buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.allowed_addresses,
+        ));
+
+        self.i2c.set_master_client(i2c_master);
+
+        i2c_master
+    }
+}
+
 pub struct I2CMasterSlaveDriverComponent<I: 'static + i2c::I2CMasterSlave<'static>> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a software (bit-banged) I2C master.
+//!
+//! The resulting I2C master can be virtualized with `I2CMuxComponent` like
+//! a hardware controller.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let i2c_bitbang = components::i2c_bitbang::I2CBitBangComponent::new(
+//!     &nrf52840_peripherals.gpio_port[SDA_PIN],
+//!     &nrf52840_peripherals.gpio_port[SCL_PIN],
+//!     mux_alarm,
+//!     capsules_extra::i2c_bitbang::DEFAULT_SPEED_HZ,
+//! )
+//! .finalize(components::i2c_bitbang_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::i2c_bitbang::I2CBitBang;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! i2c_bitbang_component_static {
+    ($P:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let i2c = kernel::static_buf!(
+            capsules_extra::i2c_bitbang::I2CBitBang<
+                'static,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, i2c)
+    };};
+}
+
+pub struct I2CBitBangComponent<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> {
+    sda: &'static P,
+    scl: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    speed_hz: u32,
+}
+
+impl<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> I2CBitBangComponent<P, A> {
+    pub fn new(
+        sda: &'static P,
+        scl: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        speed_hz: u32,
+    ) -> I2CBitBangComponent<P, A> {
+        I2CBitBangComponent {
+            sda,
+            scl,
+            alarm_mux,
+            speed_hz,
+        }
+    }
+}
+
+impl<P: 'static + gpio::Pin, A: 'static + Alarm<'static>> Component for I2CBitBangComponent<P, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CBitBang<'static, P, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static I2CBitBang<'static, P, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let i2c = s.1.write(I2CBitBang::new(self.sda, self.scl, alarm));
+        i2c.set_speed_hz(self.speed_hz);
+        alarm.set_alarm_client(i2c);
+
+        i2c
+    }
+}
@@ -0,0 +1,107 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the ICM-20649 high-g accelerometer and gyroscope.
+//!
+//! Uses a SPI Interface. The interrupt pin is only needed for shock
+//! detection.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let icm20649 = components::icm20649::Icm20649Component::new(
+//!     mux_spi,
+//!     nrf52840::gpio::Pin::P1_08,
+//!     Some(&nrf52840_peripherals.gpio_port[ICM20649_INT]),
+//! )
+//! .finalize(components::icm20649_component_static!(nrf52840::spi::SPIM));
+//! ```
+
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::icm20649::{Icm20649, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::spi::SpiMasterDevice;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! icm20649_component_static {
+    ($S:ty $(,)?) => {{
+        let txbuffer = kernel::static_buf!([u8; capsules_extra::icm20649::BUF_LEN]);
+        let rxbuffer = kernel::static_buf!([u8; capsules_extra::icm20649::BUF_LEN]);
+
+        let spi = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let icm20649 = kernel::static_buf!(
+            capsules_extra::icm20649::Icm20649<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi, icm20649, txbuffer, rxbuffer)
+    };};
+}
+
+pub type Icm20649ComponentType<S> = Icm20649<'static, VirtualSpiMasterDevice<'static, S>>;
+
+pub struct Icm20649Component<S: 'static + spi::SpiMaster<'static>> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    interrupt_pin: Option<&'static dyn gpio::InterruptPin<'static>>,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Icm20649Component<S> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        interrupt_pin: Option<&'static dyn gpio::InterruptPin<'static>>,
+    ) -> Icm20649Component<S> {
+        Icm20649Component {
+            spi_mux,
+            chip_select,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Component for Icm20649Component<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<Icm20649ComponentType<S>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static Icm20649ComponentType<S>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let spi_device = static_buffer
+            .0
+            .write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let txbuffer = static_buffer.2.write([0; BUF_LEN]);
+        let rxbuffer = static_buffer.3.write([0; BUF_LEN]);
+
+        let icm20649 = static_buffer.1.write(Icm20649::new(
+            spi_device,
+            self.interrupt_pin,
+            txbuffer,
+            rxbuffer,
+        ));
+        spi_device.set_client(icm20649);
+        if let Some(pin) = self.interrupt_pin {
+            pin.set_client(icm20649);
+        }
+
+        // TODO verify SPI return value
+        let _ = icm20649.configure();
+        let _ = icm20649.start_streaming();
+
+        icm20649
+    }
+}
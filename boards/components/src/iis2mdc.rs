@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the IIS2MDC magnetometer.
+//!
+//! Uses the INT/DRDY pin, which is push-pull and active high, as a
+//! data-ready interrupt.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let iis2mdc = components::iis2mdc::Iis2mdcComponent::new(
+//!     mux_i2c,
+//!     capsules_extra::iis2mdc::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[IIS2MDC_DRDY],
+//! )
+//! .finalize(components::iis2mdc_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::iis2mdc::{Iis2mdc, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! iis2mdc_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::iis2mdc::BUFFER_SIZE]);
+        let iis2mdc = kernel::static_buf!(
+            capsules_extra::iis2mdc::Iis2mdc<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, iis2mdc)
+    };};
+}
+
+pub type Iis2mdcComponentType<I, G> = Iis2mdc<'static, I2CDevice<'static, I>, G>;
+
+pub struct Iis2mdcComponent<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    drdy_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Iis2mdcComponent<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        drdy_pin: &'static G,
+    ) -> Iis2mdcComponent<I, G> {
+        Iis2mdcComponent {
+            i2c_mux,
+            i2c_address,
+            drdy_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Iis2mdcComponent<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Iis2mdcComponentType<I, G>>,
+    );
+    type Output = &'static Iis2mdcComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let iis2mdc = s.2.write(Iis2mdc::new(i2c_device, self.drdy_pin, buffer));
+        i2c_device.set_client(iis2mdc);
+
+        self.drdy_pin.make_input();
+        self.drdy_pin
+            .set_floating_state(gpio::FloatingState::PullNone);
+        self.drdy_pin.set_client(iis2mdc);
+
+        iis2mdc
+    }
+}
@@ -0,0 +1,172 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for the INA3221 three-channel power monitor.
+//!
+//! I2C Interface
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ina3221 = components::ina3221::Ina3221Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ina3221::BASE_ADDR,
+//!     capsules_extra::ina3221::Config {
+//!         shunt_mohm: [100, 100, 0],
+//!         averaging: capsules_extra::ina3221::Averaging::Samples16,
+//!         bus_conversion: capsules_extra::ina3221::ConversionTime::Us1100,
+//!         shunt_conversion: capsules_extra::ina3221::ConversionTime::Us1100,
+//!     },
+//!     capsules_extra::ina3221::AlertPins {
+//!         critical: Some(critical_pin),
+//!         ..Default::default()
+//!     },
+//! )
+//! .finalize(components::ina3221_component_static!(nrf52840::i2c::TWI));
+//! let ina3221_driver = components::ina3221::Ina3221DriverComponent::new(
+//!     ina3221,
+//!     board_kernel,
+//!     capsules_extra::ina3221::DRIVER_NUM,
+//! )
+//! .finalize(components::ina3221_driver_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::ina3221::{AlertPins, Config, Ina3221, Ina3221Driver, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::i2c;
+use kernel::hil::sensors::MultiChannelPowerMonitor;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ina3221_component_static {
+    ($I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::ina3221::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let ina3221 = kernel::static_buf!(
+            capsules_extra::ina3221::Ina3221<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, ina3221, buffer)
+    };};
+}
+
+#[macro_export]
+macro_rules! ina3221_driver_component_static {
+    ($I:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::ina3221::Ina3221Driver<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        )
+    };};
+}
+
+pub struct Ina3221Component<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    config: Config,
+    alert_pins: AlertPins<'static>,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Ina3221Component<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        config: Config,
+        alert_pins: AlertPins<'static>,
+    ) -> Self {
+        Ina3221Component {
+            i2c_mux,
+            i2c_address,
+            config,
+            alert_pins,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Ina3221Component<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Ina3221<'static, I2CDevice<'static, I>>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Ina3221<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ina3221_i2c = static_buffer
+            .0
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.2.write([0; BUFFER_SIZE]);
+
+        let pins = [
+            self.alert_pins.warning,
+            self.alert_pins.critical,
+            self.alert_pins.timing_control,
+            self.alert_pins.power_valid,
+        ];
+        let ina3221 = static_buffer.1.write(Ina3221::new(
+            ina3221_i2c,
+            self.config,
+            self.alert_pins,
+            buffer,
+        ));
+        ina3221_i2c.set_client(ina3221);
+        for pin in pins.into_iter().flatten() {
+            pin.set_client(ina3221);
+        }
+
+        if let Err(error) = ina3221.configure() {
+            panic!("Failed to configure INA3221 ({:?})", error);
+        }
+
+        ina3221
+    }
+}
+
+pub struct Ina3221DriverComponent<I: 'static + i2c::I2CMaster<'static>> {
+    ina3221: &'static Ina3221<'static, I2CDevice<'static, I>>,
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Ina3221DriverComponent<I> {
+    pub fn new(
+        ina3221: &'static Ina3221<'static, I2CDevice<'static, I>>,
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+    ) -> Self {
+        Ina3221DriverComponent {
+            ina3221,
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Ina3221DriverComponent<I> {
+    type StaticInput = &'static mut MaybeUninit<Ina3221Driver<'static, I2CDevice<'static, I>>>;
+    type Output = &'static Ina3221Driver<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        let ina3221_driver = s.write(Ina3221Driver::new(self.ina3221, grant));
+        MultiChannelPowerMonitor::set_client(self.ina3221, ina3221_driver);
+        self.ina3221.set_client(ina3221_driver);
+
+        ina3221_driver
+    }
+}
@@ -0,0 +1,71 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the indoor air quality syscall driver, for any
+//! `hil::sensors::IndoorAirQuality` device.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let iaq = components::indoor_air_quality::IndoorAirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::indoor_air_quality::DRIVER_NUM,
+//!     bme680,
+//! )
+//! .finalize(components::indoor_air_quality_component_static!());
+//! ```
+
+use capsules_extra::indoor_air_quality::IndoorAirQualityDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! indoor_air_quality_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::indoor_air_quality::IndoorAirQualityDriver<'static>)
+    };};
+}
+
+pub struct IndoorAirQualityComponent<S: 'static + hil::sensors::IndoorAirQuality<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sensor: &'static S,
+}
+
+impl<S: 'static + hil::sensors::IndoorAirQuality<'static>> IndoorAirQualityComponent<S> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sensor: &'static S,
+    ) -> IndoorAirQualityComponent<S> {
+        IndoorAirQualityComponent {
+            board_kernel,
+            driver_num,
+            sensor,
+        }
+    }
+}
+
+impl<S: 'static + hil::sensors::IndoorAirQuality<'static>> Component
+    for IndoorAirQualityComponent<S>
+{
+    type StaticInput = &'static mut MaybeUninit<IndoorAirQualityDriver<'static>>;
+    type Output = &'static IndoorAirQualityDriver<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.write(IndoorAirQualityDriver::new(
+            self.sensor,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::sensors::IndoorAirQuality::set_client(self.sensor, driver);
+        driver
+    }
+}
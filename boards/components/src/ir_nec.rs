@@ -0,0 +1,145 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for sending and receiving NEC infrared remote control codes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ir_nec = components::ir_nec::IrNecComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ir_nec::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[IR_RX],
+//!     ir_led_pwm_pin,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ir_nec_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     capsules_core::virtualizers::virtual_pwm::PwmPinUser<'static, nrf52840::pwm::Pwm>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ir_nec::{IrNecDriver, IrNecRx, IrNecTx};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::gpio;
+use kernel::hil::pwm;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! ir_nec_component_static {
+    ($G:ty, $P:ty, $A:ty $(,)?) => {{
+        let rx_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let tx_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let rx = kernel::static_buf!(
+            capsules_extra::ir_nec::IrNecRx<
+                'static,
+                $G,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let tx = kernel::static_buf!(
+            capsules_extra::ir_nec::IrNecTx<
+                'static,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::ir_nec::IrNecDriver<
+                'static,
+                $G,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (rx_alarm, tx_alarm, rx, tx, driver)
+    };};
+}
+
+pub struct IrNecComponent<
+    G: 'static + gpio::InterruptPin<'static>,
+    P: 'static + pwm::PwmPin,
+    A: 'static + Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    rx_pin: &'static G,
+    tx_pin: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+        G: 'static + gpio::InterruptPin<'static>,
+        P: 'static + pwm::PwmPin,
+        A: 'static + Alarm<'static>,
+    > IrNecComponent<G, P, A>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        rx_pin: &'static G,
+        tx_pin: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> IrNecComponent<G, P, A> {
+        IrNecComponent {
+            board_kernel,
+            driver_num,
+            rx_pin,
+            tx_pin,
+            alarm_mux,
+        }
+    }
+}
+
+impl<
+        G: 'static + gpio::InterruptPin<'static>,
+        P: 'static + pwm::PwmPin,
+        A: 'static + Alarm<'static>,
+    > Component for IrNecComponent<G, P, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IrNecRx<'static, G, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<IrNecTx<'static, P, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<IrNecDriver<'static, G, P, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static IrNecDriver<'static, G, P, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let rx_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        rx_alarm.setup();
+        let tx_alarm = s.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        tx_alarm.setup();
+
+        let rx = s.2.write(IrNecRx::new(self.rx_pin, rx_alarm));
+        self.rx_pin.set_client(rx);
+
+        let tx = s.3.write(IrNecTx::new(self.tx_pin, tx_alarm));
+        tx_alarm.set_alarm_client(tx);
+
+        let driver = s.4.write(IrNecDriver::new(
+            rx,
+            tx,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        rx.set_client(driver);
+        tx.set_client(driver);
+
+        driver
+    }
+}
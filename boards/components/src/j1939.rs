@@ -0,0 +1,152 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the J1939 stack and its syscall interface.
+//!
+//! Uses the CAN peripheral exclusively and an alarm. The stack is started
+//! when the component is finalized, and claims its address in the
+//! background.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let j1939 = components::j1939::J1939StackComponent::new(
+//!     board_kernel,
+//!     capsules_extra::j1939::driver::DRIVER_NUM,
+//!     &peripherals.can1,
+//!     mux_alarm,
+//!     0x8000_0000_0012_3456,
+//!     0x80,
+//!     250_000,
+//! )
+//! .finalize(components::j1939_stack_component_static!(
+//!     stm32f429zi::can::Can<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::j1939::driver::J1939Driver;
+use capsules_extra::j1939::J1939Stack;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::can;
+use kernel::hil::can::j1939::MAX_MESSAGE_LEN;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! j1939_stack_component_static {
+    ($C:ty, $A:ty $(,)?) => {{
+        use kernel::hil::can;
+        use kernel::hil::can::j1939::MAX_MESSAGE_LEN;
+        use kernel::static_buf;
+
+        let alarm =
+            static_buf!(capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>);
+        let stack = static_buf!(
+            capsules_extra::j1939::J1939Stack<
+                'static,
+                $C,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = static_buf!(
+            capsules_extra::j1939::driver::J1939Driver<
+                'static,
+                $C,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let can_tx = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let can_rx = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let rx_buffer = static_buf!([u8; MAX_MESSAGE_LEN]);
+        let tx_buffer = static_buf!([u8; MAX_MESSAGE_LEN]);
+
+        (alarm, stack, driver, can_tx, can_rx, rx_buffer, tx_buffer)
+    };};
+}
+
+pub type J1939StackComponentType<C, A> = J1939Stack<'static, C, VirtualMuxAlarm<'static, A>>;
+
+pub struct J1939StackComponent<C: 'static + can::Can, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    can: &'static C,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    name: u64,
+    preferred_address: u8,
+    bitrate: u32,
+}
+
+impl<C: 'static + can::Can, A: 'static + Alarm<'static>> J1939StackComponent<C, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        can: &'static C,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        name: u64,
+        preferred_address: u8,
+        bitrate: u32,
+    ) -> J1939StackComponent<C, A> {
+        J1939StackComponent {
+            board_kernel,
+            driver_num,
+            can,
+            alarm_mux,
+            name,
+            preferred_address,
+            bitrate,
+        }
+    }
+}
+
+impl<C: 'static + can::Can, A: 'static + Alarm<'static>> Component for J1939StackComponent<C, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<J1939StackComponentType<C, A>>,
+        &'static mut MaybeUninit<J1939Driver<'static, C, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; can::STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; can::STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; MAX_MESSAGE_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_MESSAGE_LEN]>,
+    );
+    type Output = &'static J1939Driver<'static, C, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let stack = static_buffer.1.write(J1939Stack::new(
+            self.can,
+            alarm,
+            self.name,
+            self.preferred_address,
+            self.bitrate,
+            static_buffer.3.write([0; can::STANDARD_CAN_PACKET_SIZE]),
+            static_buffer.4.write([0; can::STANDARD_CAN_PACKET_SIZE]),
+            static_buffer.5.write([0; MAX_MESSAGE_LEN]),
+        ));
+        alarm.set_alarm_client(stack);
+        can::Controller::set_client(self.can, Some(stack));
+        can::Transmit::set_client(self.can, Some(stack));
+        can::Receive::set_client(self.can, Some(stack));
+
+        let driver = static_buffer.2.write(J1939Driver::new(
+            stack,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            static_buffer.6.write([0; MAX_MESSAGE_LEN]),
+        ));
+        stack.set_client(driver);
+
+        if let Err(error) = stack.start() {
+            panic!("Failed to start J1939 stack ({:?})", error);
+        }
+
+        driver
+    }
+}
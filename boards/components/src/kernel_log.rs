@@ -0,0 +1,56 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for the kernel log, the ring buffer behind `klog!`.
+//!
+//! This provides one `Component`, `KernelLogComponent`, which creates a kernel
+//! log holding the last `N` messages and registers it with the kernel. Which
+//! levels are kept is chosen at compile time with the `kernel_log_*` features
+//! of the kernel crate; without any of them `klog!` compiles to nothing. The
+//! log can be dumped and cleared with the `klog` process console command.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! KernelLogComponent::new().finalize(components::kernel_log_component_static!(32));
+//! ```
+
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::debug::{KernelLog, LogEntry};
+
+#[macro_export]
+macro_rules! kernel_log_component_static {
+    ($N:expr $(,)?) => {{
+        let entries = kernel::static_buf!([kernel::debug::LogEntry; $N]);
+        let log = kernel::static_buf!(kernel::debug::KernelLog);
+
+        (entries, log)
+    };};
+}
+
+pub struct KernelLogComponent<const N: usize> {}
+
+impl<const N: usize> KernelLogComponent<N> {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<const N: usize> Component for KernelLogComponent<N> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[LogEntry; N]>,
+        &'static mut MaybeUninit<KernelLog>,
+    );
+    type Output = &'static KernelLog;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let entries = s.0.write(core::array::from_fn(|_| LogEntry::new()));
+        let log = s.1.write(KernelLog::new(entries));
+        unsafe {
+            kernel::debug::set_kernel_log(log);
+        }
+        log
+    }
+}
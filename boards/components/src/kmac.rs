@@ -0,0 +1,82 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the SHA-3/KMAC syscall driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let kmac = components::kmac::KmacComponent::new(
+//!     board_kernel,
+//!     capsules_extra::kmac::DRIVER_NUM,
+//!     &peripherals.kmac,
+//! )
+//! .finalize(components::kmac_component_static!(earlgrey::kmac::Kmac));
+//! ```
+
+use capsules_extra::kmac::KmacDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::hash::Sha3;
+use kernel::hil::mac::Kmac;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! kmac_component_static {
+    ($H:ty $(,)?) => {{
+        let kmac_driver = kernel::static_buf!(capsules_extra::kmac::KmacDriver<'static, $H>);
+        let data_buffer = kernel::static_buf!([u8; 64]);
+        let output_buffer = kernel::static_buf!([u8; 64]);
+
+        (kmac_driver, data_buffer, output_buffer)
+    };};
+}
+
+pub struct KmacComponent<H: 'static + Sha3<'static> + Kmac<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    kmac: &'static H,
+}
+
+impl<H: 'static + Sha3<'static> + Kmac<'static>> KmacComponent<H> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        kmac: &'static H,
+    ) -> KmacComponent<H> {
+        KmacComponent {
+            board_kernel,
+            driver_num,
+            kmac,
+        }
+    }
+}
+
+impl<H: 'static + Sha3<'static> + Kmac<'static>> Component for KmacComponent<H> {
+    type StaticInput = (
+        &'static mut MaybeUninit<KmacDriver<'static, H>>,
+        &'static mut MaybeUninit<[u8; 64]>,
+        &'static mut MaybeUninit<[u8; 64]>,
+    );
+    type Output = &'static KmacDriver<'static, H>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let data_buffer = s.1.write([0; 64]);
+        let output_buffer = s.2.write([0; 64]);
+
+        let kmac = s.0.write(KmacDriver::new(
+            self.kmac,
+            data_buffer,
+            output_buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.kmac.set_client(kmac);
+
+        kmac
+    }
+}
@@ -5,80 +5,149 @@
 #![no_std]
 
 pub mod adc;
+pub mod adc_capture;
 pub mod adc_microphone;
+pub mod adc_temperature;
+pub mod ads1115;
 pub mod aes;
+pub mod aht20;
 pub mod air_quality;
 pub mod alarm;
+pub mod amg8833;
 pub mod analog_comparator;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod battery_charger;
 pub mod ble;
 pub mod bme280;
+pub mod bme680;
+pub mod bmi270;
 pub mod bmp280;
+pub mod bq24195;
 pub mod bus;
 pub mod button;
 pub mod can;
 pub mod ccs811;
 pub mod cdc;
+pub mod clk_stats;
+pub mod color;
 pub mod console;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
+pub mod dali;
 pub mod debug_queue;
 pub mod debug_writer;
 pub mod digest;
+pub mod ds18b20_multi;
+pub mod eink;
+pub mod ens160;
 pub mod flash;
+pub mod flash_digest;
 pub mod fm25cl;
+pub mod ft5x06;
 pub mod ft6x06;
+pub mod fxas21002c;
 pub mod fxos8700;
 pub mod gpio;
+pub mod gpio_rate_limit;
 pub mod hd44780;
 pub mod hmac;
 pub mod hts221;
 pub mod humidity;
+pub mod hx711;
 pub mod i2c;
+pub mod i2c_bitbang;
+pub mod icm20649;
 pub mod ieee802154;
+pub mod iis2mdc;
+pub mod ina3221;
+pub mod indoor_air_quality;
+pub mod ir_nec;
 pub mod isl29035;
+pub mod j1939;
+pub mod kernel_log;
 pub mod keyboard_hid;
+pub mod kmac;
 pub mod kv_system;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
+pub mod lis3dh;
 pub mod lldb;
+pub mod lorawan_mac;
 pub mod lpm013m126;
 pub mod lps25hb;
 pub mod lsm303agr;
 pub mod lsm303dlhc;
 pub mod lsm6dsox;
 pub mod ltc294x;
+pub mod max31865;
+pub mod mcp23017;
+pub mod mcp4725;
+pub mod mlx90393;
 pub mod mlx90614;
+pub mod mmc5983;
+pub mod modbus_rtu;
+pub mod motion_detector;
+pub mod mpr121;
+pub mod mpu6886;
+pub mod mpu9250;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
+pub mod ntc_pid_heater;
+pub mod opt3001;
 pub mod panic_button;
+pub mod pca9555;
+pub mod pca9685;
+pub mod pir_motion;
+pub mod pn532;
 pub mod process_console;
+pub mod process_fault_policy;
 pub mod process_printer;
 pub mod proximity;
 pub mod pwm;
+pub mod resistive_adc_buttons;
 pub mod rf233;
 pub mod rng;
+pub mod rotary_encoder;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sensor_batch;
+pub mod sgp30;
+pub mod sgp41;
 pub mod sha;
 pub mod sht3x;
+pub mod si1145;
 pub mod si7021;
+pub mod soil_moisture;
+pub mod sound_level;
 pub mod sound_pressure;
 pub mod spi;
+pub mod spi_nor;
+pub mod st7789;
 pub mod st77xx;
+pub mod sx1276;
+pub mod tca9548a;
+pub mod tcs34725;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
 pub mod test;
 pub mod text_screen;
 pub mod tickv;
+pub mod timestamped_sensor;
 pub mod touch;
+pub mod tps65987d;
+pub mod tsl2591;
+pub mod uair;
 pub mod udp_driver;
 pub mod udp_mux;
 pub mod usb;
+pub mod usb_pd;
+pub mod veml6075;
+pub mod ws2812b_animation;
+pub mod ws2812b_dma;
@@ -0,0 +1,233 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for the LIS3DH accelerometer.
+//!
+//! The LIS3DH is connected over I2C or SPI. Create the bus device with
+//! `Lis3dhI2CTransportComponent` or `Lis3dhSpiTransportComponent`, and pass
+//! it to `Lis3dhComponent` with the INT1 pin, the output data rate and range,
+//! and optionally the tap thresholds and the FIFO watermark.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lis3dh_transport = components::lis3dh::Lis3dhI2CTransportComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::lis3dh::I2C_ADDRESS,
+//! )
+//! .finalize(components::lis3dh_i2c_transport_component_static!(
+//!     nrf52840::i2c::TWI
+//! ));
+//! let lis3dh = components::lis3dh::Lis3dhComponent::new(
+//!     lis3dh_transport,
+//!     &nrf52840_peripherals.gpio_port[LIS3DH_INT1],
+//!     capsules_extra::lis3dh::DataRate::Hz100,
+//!     capsules_extra::lis3dh::Range::G4,
+//!     None,
+//!     None,
+//! )
+//! .finalize(components::lis3dh_component_static!(
+//!     capsules_extra::lis3dh::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! lis3dh.start().unwrap();
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::lis3dh::{
+    DataRate, I2CTransport, Lis3dh, Range, SpiTransport, TapThresholds, Transport, BUFFER_LEN,
+};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::spi::{self, SpiMasterDevice};
+
+#[macro_export]
+macro_rules! lis3dh_i2c_transport_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let transport = kernel::static_buf!(
+            capsules_extra::lis3dh::I2CTransport<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! lis3dh_spi_transport_component_static {
+    ($S:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::lis3dh::BUFFER_LEN]);
+        let transport = kernel::static_buf!(
+            capsules_extra::lis3dh::SpiTransport<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi_device, tx_buffer, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! lis3dh_component_static {
+    ($T:ty, $G:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::lis3dh::BUFFER_LEN]);
+        let lis3dh = kernel::static_buf!(capsules_extra::lis3dh::Lis3dh<'static, $T, $G>);
+
+        (buffer, lis3dh)
+    };};
+}
+
+pub struct Lis3dhI2CTransportComponent<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Lis3dhI2CTransportComponent<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+    ) -> Lis3dhI2CTransportComponent<I> {
+        Lis3dhI2CTransportComponent {
+            i2c_mux,
+            i2c_address,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Lis3dhI2CTransportComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<I2CTransport<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static I2CTransport<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let transport = s.1.write(I2CTransport::new(i2c_device));
+        i2c_device.set_client(transport);
+
+        transport
+    }
+}
+
+pub struct Lis3dhSpiTransportComponent<S: 'static + spi::SpiMaster<'static>> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    baud_rate: u32,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Lis3dhSpiTransportComponent<S> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        baud_rate: u32,
+    ) -> Lis3dhSpiTransportComponent<S> {
+        Lis3dhSpiTransportComponent {
+            spi_mux,
+            chip_select,
+            baud_rate,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Component for Lis3dhSpiTransportComponent<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>>,
+    );
+    type Output = &'static SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let tx_buffer = s.1.write([0; BUFFER_LEN]);
+        let transport = s.2.write(SpiTransport::new(spi_device, tx_buffer));
+        spi_device.set_client(transport);
+
+        if let Err(error) = transport.configure(self.baud_rate) {
+            panic!("Failed to setup LIS3DH SPI ({:?})", error);
+        }
+
+        transport
+    }
+}
+
+pub struct Lis3dhComponent<
+    T: 'static + Transport<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    transport: &'static T,
+    int1: &'static G,
+    data_rate: DataRate,
+    range: Range,
+    tap: Option<TapThresholds>,
+    fifo_watermark: Option<u8>,
+}
+
+impl<T: 'static + Transport<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Lis3dhComponent<T, G>
+{
+    pub fn new(
+        transport: &'static T,
+        int1: &'static G,
+        data_rate: DataRate,
+        range: Range,
+        tap: Option<TapThresholds>,
+        fifo_watermark: Option<u8>,
+    ) -> Lis3dhComponent<T, G> {
+        Lis3dhComponent {
+            transport,
+            int1,
+            data_rate,
+            range,
+            tap,
+            fifo_watermark,
+        }
+    }
+}
+
+impl<T: 'static + Transport<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Lis3dhComponent<T, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<Lis3dh<'static, T, G>>,
+    );
+    type Output = &'static Lis3dh<'static, T, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.0.write([0; BUFFER_LEN]);
+        let lis3dh = s.1.write(Lis3dh::new(self.transport, self.int1, buffer));
+        self.transport.set_client(lis3dh);
+
+        self.int1.make_input();
+        self.int1.set_client(lis3dh);
+
+        if let Err(error) =
+            lis3dh.configure(self.data_rate, self.range, self.tap, self.fifo_watermark)
+        {
+            panic!("Invalid LIS3DH configuration ({:?})", error);
+        }
+
+        lis3dh
+    }
+}
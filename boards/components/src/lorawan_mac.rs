@@ -0,0 +1,185 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a LoRaWAN end device on an SX1276 transceiver.
+//!
+//! The transceiver is on an SPI bus, with DIO0 and DIO1 on interrupt pins,
+//! and is set up with `components::sx1276::Sx1276Component`.
+//! The AES engine must support ECB and CBC, such as a virtual AES device.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lorawan = components::lorawan_mac::LorawanMacComponent::new(
+//!     spi_mux,
+//!     nrf52840::gpio::Pin::P0_12,
+//!     &nrf52840_peripherals.gpio_port[DIO0_PIN],
+//!     &nrf52840_peripherals.gpio_port[DIO1_PIN],
+//!     mux_alarm,
+//!     aes,
+//!     capsules_extra::lorawan_mac::EU868,
+//!     DEV_EUI,
+//!     JOIN_EUI,
+//!     APP_KEY,
+//! )
+//! .finalize(components::lorawan_mac_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc,
+//!     capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<
+//!         'static,
+//!         nrf52840::aes::AesECB<'static>,
+//!     >
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::lorawan_mac::{LorawanMac, Region, CRYPTO_SIZE, FRAME_SIZE};
+use capsules_extra::sx1276::BUFFER_SIZE;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::symmetric_encryption::{AES128, AES128CBC, AES128ECB};
+use kernel::hil::time::Alarm;
+
+use crate::sx1276::{Sx1276Component, Sx1276ComponentType};
+
+#[macro_export]
+macro_rules! lorawan_mac_component_static {
+    ($S:ty, $A:ty, $E:ty $(,)?) => {{
+        let radio = components::sx1276_component_static!($S, $A);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let frame = kernel::static_buf!([u8; capsules_extra::lorawan_mac::FRAME_SIZE]);
+        let crypto = kernel::static_buf!([u8; capsules_extra::lorawan_mac::CRYPTO_SIZE]);
+        let mac = kernel::static_buf!(
+            capsules_extra::lorawan_mac::LorawanMac<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $E,
+            >
+        );
+
+        (radio, alarm, frame, crypto, mac)
+    };};
+}
+
+pub type LorawanMacComponentType<S, A, E> =
+    LorawanMac<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>, E>;
+
+pub struct LorawanMacComponent<
+    S: 'static + spi::SpiMaster<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+    E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    dio0: &'static P,
+    dio1: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    aes: &'static E,
+    region: Region,
+    dev_eui: [u8; 8],
+    join_eui: [u8; 8],
+    app_key: [u8; 16],
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+        E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+    > LorawanMacComponent<S, P, A, E>
+{
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        dio0: &'static P,
+        dio1: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        aes: &'static E,
+        region: Region,
+        dev_eui: [u8; 8],
+        join_eui: [u8; 8],
+        app_key: [u8; 16],
+    ) -> LorawanMacComponent<S, P, A, E> {
+        LorawanMacComponent {
+            spi_mux,
+            chip_select,
+            dio0,
+            dio1,
+            alarm_mux,
+            aes,
+            region,
+            dev_eui,
+            join_eui,
+            app_key,
+        }
+    }
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+        E: 'static + AES128<'static> + AES128ECB + AES128CBC,
+    > Component for LorawanMacComponent<S, P, A, E>
+{
+    type StaticInput = (
+        (
+            &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+            &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+            &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+            &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+            &'static mut MaybeUninit<Sx1276ComponentType<S, A>>,
+        ),
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; FRAME_SIZE]>,
+        &'static mut MaybeUninit<[u8; CRYPTO_SIZE]>,
+        &'static mut MaybeUninit<LorawanMacComponentType<S, A, E>>,
+    );
+    type Output = &'static LorawanMacComponentType<S, A, E>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let radio = Sx1276Component::new(
+            self.spi_mux,
+            self.chip_select,
+            self.dio0,
+            self.dio1,
+            None,
+            self.alarm_mux,
+        )
+        .finalize(s.0);
+
+        let alarm = s.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let frame = s.2.write([0; FRAME_SIZE]);
+        let crypto = s.3.write([0; CRYPTO_SIZE]);
+        let mac = s.4.write(LorawanMac::new(
+            radio,
+            alarm,
+            self.aes,
+            self.region,
+            self.dev_eui,
+            self.join_eui,
+            self.app_key,
+            frame,
+            crypto,
+        ));
+        radio.set_client(mac);
+        alarm.set_alarm_client(mac);
+        self.aes.enable();
+        self.aes.set_client(mac);
+
+        let _ = radio.init();
+
+        mac
+    }
+}
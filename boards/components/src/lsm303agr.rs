@@ -2,24 +2,26 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
-//! Components for the LSM303DLHC sensor.
+//! Components for the LSM303AGR sensor.
 //!
 //! I2C Interface
 //!
+//! The component mirrors [Lsm303dlhcI2CComponent](crate::lsm303dlhc::Lsm303dlhcI2CComponent)
+//! so a board can switch between the two sensors by changing the component
+//! and the driver type.
+//!
 //! Usage
 //! -----
 //! ```rust
 //! let lsm303agr = components::lsm303agr::Lsm303agrI2CComponent::new(mux_i2c, None, None, board_kernel, DRIVER_NUM)
-//!    .finalize(components::lsm303agr_component_static!());
+//!    .finalize(components::lsm303agr_component_static!(nrf52833::i2c::TWI<'static>));
 //!
 //! lsm303agr.configure(
-//!    lsm303agr::Lsm303dlhcAccelDataRate::DataRate25Hz,
+//!    lsm303xx::Lsm303AccelDataRate::DataRate25Hz,
 //!    false,
-//!    lsm303agr::Lsm303dlhcScale::Scale2G,
+//!    lsm303xx::Lsm303Scale::Scale2G,
 //!    false,
-//!    true,
-//!    lsm303agr::Lsm303dlhcMagnetoDataRate::DataRate3_0Hz,
-//!    lsm303agr::Lsm303dlhcRange::Range4_7G,
+//!    lsm303agr::Lsm303agrMagnetoDataRate::DataRate10Hz,
 //! );
 //! ```
 
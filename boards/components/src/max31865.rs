@@ -0,0 +1,151 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MAX31865 RTD-to-digital converter.
+//!
+//! Uses a SPI interface, the DRDY pin and an alarm.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let max31865 = components::max31865::Max31865Component::new(
+//!     mux_spi,
+//!     nrf52840::gpio::Pin::P1_08,
+//!     &nrf52840_peripherals.gpio_port[MAX31865_DRDY],
+//!     mux_alarm,
+//!     capsules_extra::max31865::Rtd::Pt100,
+//!     430,
+//!     capsules_extra::max31865::Wires::Three,
+//!     capsules_extra::max31865::Bias::DuringConversion,
+//!     capsules_extra::max31865::FaultDetection::Automatic,
+//! )
+//! .finalize(components::max31865_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::max31865::{Bias, FaultDetection, Max31865, Rtd, Wires, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! max31865_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let txbuffer = kernel::static_buf!([u8; capsules_extra::max31865::BUF_LEN]);
+        let rxbuffer = kernel::static_buf!([u8; capsules_extra::max31865::BUF_LEN]);
+
+        let spi = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let max31865 = kernel::static_buf!(
+            capsules_extra::max31865::Max31865<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (spi, alarm, max31865, txbuffer, rxbuffer)
+    };};
+}
+
+pub type Max31865ComponentType<S, A> =
+    Max31865<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+pub struct Max31865Component<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    drdy: &'static dyn gpio::InterruptPin<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rtd: Rtd,
+    reference_ohms: u32,
+    wires: Wires,
+    bias: Bias,
+    fault_detection: FaultDetection,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> Max31865Component<S, A> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        drdy: &'static dyn gpio::InterruptPin<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rtd: Rtd,
+        reference_ohms: u32,
+        wires: Wires,
+        bias: Bias,
+        fault_detection: FaultDetection,
+    ) -> Max31865Component<S, A> {
+        Max31865Component {
+            spi_mux,
+            chip_select,
+            drdy,
+            alarm_mux,
+            rtd,
+            reference_ohms,
+            wires,
+            bias,
+            fault_detection,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> Component
+    for Max31865Component<S, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Max31865ComponentType<S, A>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static Max31865ComponentType<S, A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let spi_device = static_buffer
+            .0
+            .write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let alarm = static_buffer.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let txbuffer = static_buffer.3.write([0; BUF_LEN]);
+        let rxbuffer = static_buffer.4.write([0; BUF_LEN]);
+
+        let max31865 = static_buffer.2.write(Max31865::new(
+            spi_device,
+            self.drdy,
+            alarm,
+            self.rtd,
+            self.reference_ohms,
+            txbuffer,
+            rxbuffer,
+        ));
+        spi_device.set_client(max31865);
+        alarm.set_alarm_client(max31865);
+
+        self.drdy.make_input();
+        self.drdy.set_floating_state(gpio::FloatingState::PullUp);
+        self.drdy.set_client(max31865);
+
+        if let Err(error) = max31865.configure(self.wires, self.bias, self.fault_detection) {
+            panic!("Failed to configure MAX31865 ({:?})", error);
+        }
+
+        max31865
+    }
+}
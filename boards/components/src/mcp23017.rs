@@ -0,0 +1,107 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MCP23017 I2C GPIO expander.
+//!
+//! The component starts the expander and returns its 16 pins, which can be
+//! passed to any capsule expecting a `gpio::Pin` or `gpio::InterruptPin`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mcp23017_pins = components::mcp23017::Mcp23017Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mcp23017::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MCP23017_INT],
+//! )
+//! .finalize(components::mcp23017_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mcp23017::{Mcp23017, Mcp23017Pins, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! mcp23017_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::mcp23017::BUFFER_SIZE]);
+        let mcp23017 = kernel::static_buf!(
+            capsules_extra::mcp23017::Mcp23017<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+        let pins = kernel::static_buf!(
+            capsules_extra::mcp23017::Mcp23017Pins<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, mcp23017, pins)
+    };};
+}
+
+pub type Mcp23017ComponentType<I, G> = Mcp23017Pins<'static, I2CDevice<'static, I>, G>;
+
+pub struct Mcp23017Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Mcp23017Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static G,
+    ) -> Mcp23017Component<I, G> {
+        Mcp23017Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Mcp23017Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Mcp23017<'static, I2CDevice<'static, I>, G>>,
+        &'static mut MaybeUninit<Mcp23017ComponentType<I, G>>,
+    );
+    type Output = &'static Mcp23017ComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let mcp23017 =
+            s.2.write(Mcp23017::new(i2c_device, self.interrupt_pin, buffer));
+        i2c_device.set_client(mcp23017);
+        self.interrupt_pin.set_client(mcp23017);
+        let _ = mcp23017.start();
+
+        s.3.write(Mcp23017Pins::new(mcp23017))
+    }
+}
@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MCP4725 I2C DAC.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mcp4725 = components::mcp4725::Mcp4725Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mcp4725::BASE_ADDR,
+//!     mux_alarm,
+//!     3300, // supply_mv
+//! )
+//! .finalize(components::mcp4725_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mcp4725::{Mcp4725, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! mcp4725_component_static {
+    ($I:ty, $A:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::mcp4725::BUFFER_SIZE]);
+        let mcp4725 = kernel::static_buf!(
+            capsules_extra::mcp4725::Mcp4725<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (i2c_device, alarm, buffer, mcp4725)
+    };};
+}
+
+type Mcp4725Device<I, A> = Mcp4725<'static, I2CDevice<'static, I>, VirtualMuxAlarm<'static, A>>;
+
+pub struct Mcp4725Component<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    supply_mv: u16,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> Mcp4725Component<I, A> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        supply_mv: u16,
+    ) -> Self {
+        Mcp4725Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            supply_mv,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> Component
+    for Mcp4725Component<I, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Mcp4725Device<I, A>>,
+    );
+    type Output = &'static Mcp4725Device<I, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let alarm = s.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+        let buffer = s.2.write([0; BUFFER_SIZE]);
+
+        let mcp4725 =
+            s.3.write(Mcp4725::new(i2c_device, alarm, self.supply_mv, buffer));
+        i2c_device.set_client(mcp4725);
+        alarm.set_alarm_client(mcp4725);
+
+        mcp4725
+    }
+}
@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MLX90393 magnetometer.
+//!
+//! Uses the INT pin, which is push-pull and active high, as a data-ready
+//! interrupt.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mlx90393 = components::mlx90393::Mlx90393Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mlx90393::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MLX90393_INT],
+//! )
+//! .finalize(components::mlx90393_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mlx90393::{Mlx90393, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! mlx90393_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::mlx90393::BUFFER_SIZE]);
+        let mlx90393 = kernel::static_buf!(
+            capsules_extra::mlx90393::Mlx90393<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, mlx90393)
+    };};
+}
+
+pub type Mlx90393ComponentType<I, G> = Mlx90393<'static, I2CDevice<'static, I>, G>;
+
+pub struct Mlx90393Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    ready_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Mlx90393Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        ready_pin: &'static G,
+    ) -> Mlx90393Component<I, G> {
+        Mlx90393Component {
+            i2c_mux,
+            i2c_address,
+            ready_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Mlx90393Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Mlx90393ComponentType<I, G>>,
+    );
+    type Output = &'static Mlx90393ComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let mlx90393 = s.2.write(Mlx90393::new(i2c_device, self.ready_pin, buffer));
+        i2c_device.set_client(mlx90393);
+
+        self.ready_pin.make_input();
+        self.ready_pin
+            .set_floating_state(gpio::FloatingState::PullNone);
+        self.ready_pin.set_client(mlx90393);
+
+        mlx90393
+    }
+}
@@ -0,0 +1,105 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MMC5983MA magnetometer.
+//!
+//! I2C Interface
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mmc5983 = components::mmc5983::Mmc5983Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mmc5983::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mmc5983_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mmc5983::{Mmc5983, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! mmc5983_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::mmc5983::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let mmc5983 = kernel::static_buf!(
+            capsules_extra::mmc5983::Mmc5983<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, mmc5983, buffer)
+    };};
+}
+
+pub struct Mmc5983Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Mmc5983Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Mmc5983Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Mmc5983Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<
+            Mmc5983<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>,
+        >,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Mmc5983<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mmc5983_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let mmc5983_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        mmc5983_alarm.setup();
+
+        let mmc5983 = static_buffer
+            .2
+            .write(Mmc5983::new(mmc5983_i2c, mmc5983_alarm, buffer));
+        mmc5983_i2c.set_client(mmc5983);
+        mmc5983_alarm.set_alarm_client(mmc5983);
+
+        mmc5983
+    }
+}
@@ -0,0 +1,142 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a Modbus RTU master and its syscall interface.
+//!
+//! Uses the UART exclusively and an alarm. The UART is configured for the
+//! serial line when the component is finalized.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let modbus = components::modbus_rtu::ModbusMasterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::modbus_rtu::driver::DRIVER_NUM,
+//!     &peripherals.uarte1,
+//!     mux_alarm,
+//!     19200,
+//!     kernel::hil::uart::Parity::Even,
+//! )
+//! .finalize(components::modbus_master_component_static!(
+//!     nrf52840::uart::Uarte<'static>,
+//!     nrf52840::rtc::Rtc<'static>,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::modbus_rtu::driver::ModbusDriver;
+use capsules_extra::modbus_rtu::{ModbusMaster, MAX_FRAME_LEN, REQUEST_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart;
+use kernel::{capabilities, create_capability};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! modbus_master_component_static {
+    ($U:ty, $A:ty $(,)?) => {{
+        use capsules_extra::modbus_rtu::{MAX_FRAME_LEN, REQUEST_LEN};
+        use kernel::static_buf;
+
+        let alarm =
+            static_buf!(capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>);
+        let master = static_buf!(
+            capsules_extra::modbus_rtu::ModbusMaster<
+                'static,
+                $U,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = static_buf!(
+            capsules_extra::modbus_rtu::driver::ModbusDriver<
+                'static,
+                $U,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let tx_buffer = static_buf!([u8; REQUEST_LEN]);
+        let rx_buffer = static_buf!([u8; MAX_FRAME_LEN]);
+        let rx_byte = static_buf!([u8; 1]);
+
+        (alarm, master, driver, tx_buffer, rx_buffer, rx_byte)
+    };};
+}
+
+pub type ModbusMasterComponentType<U, A> = ModbusMaster<'static, U, VirtualMuxAlarm<'static, A>>;
+
+pub struct ModbusMasterComponent<U: 'static + uart::Uart<'static>, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    uart: &'static U,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    baud_rate: u32,
+    parity: uart::Parity,
+}
+
+impl<U: 'static + uart::Uart<'static>, A: 'static + Alarm<'static>> ModbusMasterComponent<U, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        uart: &'static U,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        baud_rate: u32,
+        parity: uart::Parity,
+    ) -> ModbusMasterComponent<U, A> {
+        ModbusMasterComponent {
+            board_kernel,
+            driver_num,
+            uart,
+            alarm_mux,
+            baud_rate,
+            parity,
+        }
+    }
+}
+
+impl<U: 'static + uart::Uart<'static>, A: 'static + Alarm<'static>> Component
+    for ModbusMasterComponent<U, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<ModbusMasterComponentType<U, A>>,
+        &'static mut MaybeUninit<ModbusDriver<'static, U, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; REQUEST_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<[u8; 1]>,
+    );
+    type Output = &'static ModbusDriver<'static, U, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let master = static_buffer.1.write(ModbusMaster::new(
+            self.uart,
+            alarm,
+            self.baud_rate,
+            self.parity,
+            static_buffer.3.write([0; REQUEST_LEN]),
+            static_buffer.4.write([0; MAX_FRAME_LEN]),
+            static_buffer.5.write([0; 1]),
+        ));
+        alarm.set_alarm_client(master);
+        self.uart.set_transmit_client(master);
+        self.uart.set_receive_client(master);
+
+        let driver = static_buffer.2.write(ModbusDriver::new(
+            master,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        master.set_client(driver);
+
+        if let Err(error) = master.start() {
+            panic!("Failed to configure Modbus UART ({:?})", error);
+        }
+
+        driver
+    }
+}
@@ -0,0 +1,67 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for any motion detector.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let motion = MotionDetectorComponent::new(
+//!     board_kernel,
+//!     capsules_extra::motion_detector::DRIVER_NUM,
+//!     pir,
+//! )
+//! .finalize(components::motion_detector_component_static!());
+//! ```
+
+use capsules_extra::motion_detector::MotionDetectorDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! motion_detector_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::motion_detector::MotionDetectorDriver<'static>)
+    };};
+}
+
+pub struct MotionDetectorComponent<M: 'static + hil::sensors::MotionDetector<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    detector: &'static M,
+}
+
+impl<M: 'static + hil::sensors::MotionDetector<'static>> MotionDetectorComponent<M> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        detector: &'static M,
+    ) -> MotionDetectorComponent<M> {
+        MotionDetectorComponent {
+            board_kernel,
+            driver_num,
+            detector,
+        }
+    }
+}
+
+impl<M: 'static + hil::sensors::MotionDetector<'static>> Component for MotionDetectorComponent<M> {
+    type StaticInput = &'static mut MaybeUninit<MotionDetectorDriver<'static>>;
+    type Output = &'static MotionDetectorDriver<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let motion = s.write(MotionDetectorDriver::new(
+            self.detector,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::sensors::MotionDetector::set_client(self.detector, motion);
+        motion
+    }
+}
@@ -0,0 +1,106 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MPR121 capacitive touch sensor.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mpr121 = components::mpr121::Mpr121Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mpr121::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MPR121_IRQ],
+//!     capsules_extra::mpr121::DEFAULT_TOUCH_THRESHOLD,
+//!     capsules_extra::mpr121::DEFAULT_RELEASE_THRESHOLD,
+//! )
+//! .finalize(components::mpr121_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mpr121::{Mpr121, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! mpr121_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::mpr121::BUFFER_SIZE]);
+        let mpr121 = kernel::static_buf!(
+            capsules_extra::mpr121::Mpr121<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, mpr121)
+    };};
+}
+
+pub struct Mpr121Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    irq: &'static G,
+    touch_threshold: u8,
+    release_threshold: u8,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Mpr121Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        irq: &'static G,
+        touch_threshold: u8,
+        release_threshold: u8,
+    ) -> Mpr121Component<I, G> {
+        Mpr121Component {
+            i2c_mux,
+            i2c_address,
+            irq,
+            touch_threshold,
+            release_threshold,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Mpr121Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Mpr121<'static, I2CDevice<'static, I>, G>>,
+    );
+    type Output = &'static Mpr121<'static, I2CDevice<'static, I>, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let mpr121 = s.2.write(Mpr121::new(
+            i2c_device,
+            self.irq,
+            buffer,
+            self.touch_threshold,
+            self.release_threshold,
+        ));
+        i2c_device.set_client(mpr121);
+        self.irq.set_client(mpr121);
+
+        mpr121
+    }
+}
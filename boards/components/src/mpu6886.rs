@@ -0,0 +1,114 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the MPU-6886 6-axis IMU.
+//!
+//! I2C Interface
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mpu6886 = components::mpu6886::Mpu6886Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mpu6886::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mpu6886_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(mpu6886));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::mpu6886::{Mpu6886, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! mpu6886_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::mpu6886::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let mpu6886 = kernel::static_buf!(
+            capsules_extra::mpu6886::Mpu6886<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, mpu6886, buffer)
+    };};
+}
+
+pub struct Mpu6886Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Mpu6886Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Mpu6886Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Mpu6886Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<
+            Mpu6886<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>,
+        >,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Mpu6886<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mpu6886_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let mpu6886_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        mpu6886_alarm.setup();
+
+        let mpu6886 = static_buffer
+            .2
+            .write(Mpu6886::new(mpu6886_i2c, mpu6886_alarm, buffer));
+        mpu6886_i2c.set_client(mpu6886);
+        mpu6886_alarm.set_alarm_client(mpu6886);
+
+        if let Err(error) = mpu6886.configure() {
+            panic!("Failed to configure MPU-6886 ({:?})", error);
+        }
+
+        mpu6886
+    }
+}
@@ -0,0 +1,150 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a heater controlled from a temperature sensor.
+//!
+//! The heater pin is made an output and turned off, and the controller
+//! starts reading the temperature. The heater stays off until userspace
+//! sets a setpoint.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let heater = components::ntc_pid_heater::PidHeaterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ntc_pid_heater::DRIVER_NUM,
+//!     thermistor,
+//!     &nrf52840_peripherals.gpio_port[HEATER_PIN],
+//!     mux_alarm,
+//!     capsules_extra::ntc_pid_heater::PidGains {
+//!         kp: 40 << 8,
+//!         ki: 1 << 8,
+//!         kd: 200 << 8,
+//!     },
+//!     capsules_extra::ntc_pid_heater::HeaterMode::Pwm,
+//!     1000,
+//!     1200,
+//! )
+//! .finalize(components::pid_heater_component_static!(
+//!     capsules_extra::adc_temperature::AdcTemperature<'static, nrf52840::adc::Adc>,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ntc_pid_heater::{HeaterMode, PidGains, PidHeater};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::gpio;
+use kernel::hil::sensors::TemperatureDriver;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! pid_heater_component_static {
+    ($T:ty, $H:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let heater = kernel::static_buf!(
+            capsules_extra::ntc_pid_heater::PidHeater<
+                'static,
+                $T,
+                $H,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, heater)
+    };};
+}
+
+pub type PidHeaterComponentType<T, H, A> = PidHeater<'static, T, H, VirtualMuxAlarm<'static, A>>;
+
+pub struct PidHeaterComponent<
+    T: 'static + TemperatureDriver<'static>,
+    H: 'static + gpio::Pin,
+    A: 'static + Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sensor: &'static T,
+    heater: &'static H,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    gains: PidGains,
+    mode: HeaterMode,
+    period_ms: u32,
+    max_setpoint: i32,
+}
+
+impl<
+        T: 'static + TemperatureDriver<'static>,
+        H: 'static + gpio::Pin,
+        A: 'static + Alarm<'static>,
+    > PidHeaterComponent<T, H, A>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sensor: &'static T,
+        heater: &'static H,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        gains: PidGains,
+        mode: HeaterMode,
+        period_ms: u32,
+        max_setpoint: i32,
+    ) -> Self {
+        PidHeaterComponent {
+            board_kernel,
+            driver_num,
+            sensor,
+            heater,
+            alarm_mux,
+            gains,
+            mode,
+            period_ms,
+            max_setpoint,
+        }
+    }
+}
+
+impl<
+        T: 'static + TemperatureDriver<'static>,
+        H: 'static + gpio::Pin,
+        A: 'static + Alarm<'static>,
+    > Component for PidHeaterComponent<T, H, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<PidHeaterComponentType<T, H, A>>,
+    );
+    type Output = &'static PidHeaterComponentType<T, H, A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        self.heater.make_output();
+        let heater = static_buffer.1.write(PidHeater::new(
+            self.sensor,
+            self.heater,
+            alarm,
+            self.gains,
+            self.mode,
+            self.period_ms,
+            self.max_setpoint,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.sensor.set_client(heater);
+        alarm.set_alarm_client(heater);
+        heater.start();
+
+        heater
+    }
+}
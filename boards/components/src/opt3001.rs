@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the OPT3001 ambient light sensor.
+//!
+//! Uses the INT pin, which is open-drain and active low, as an
+//! end-of-conversion interrupt.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let opt3001 = components::opt3001::Opt3001Component::new(
+//!     mux_i2c,
+//!     capsules_extra::opt3001::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[OPT3001_INT],
+//! )
+//! .finalize(components::opt3001_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::opt3001::{Opt3001, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! opt3001_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::opt3001::BUFFER_SIZE]);
+        let opt3001 = kernel::static_buf!(
+            capsules_extra::opt3001::Opt3001<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, opt3001)
+    };};
+}
+
+pub type Opt3001ComponentType<I, G> = Opt3001<'static, I2CDevice<'static, I>, G>;
+
+pub struct Opt3001Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Opt3001Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static G,
+    ) -> Opt3001Component<I, G> {
+        Opt3001Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Opt3001Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Opt3001ComponentType<I, G>>,
+    );
+    type Output = &'static Opt3001ComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let opt3001 =
+            s.2.write(Opt3001::new(i2c_device, self.interrupt_pin, buffer));
+        i2c_device.set_client(opt3001);
+
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.interrupt_pin.set_client(opt3001);
+
+        opt3001
+    }
+}
@@ -0,0 +1,107 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the PCA9555 I2C GPIO expander.
+//!
+//! The component starts the expander and returns its 16 pins, which can be
+//! passed to any capsule expecting a `gpio::Pin` or `gpio::InterruptPin`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pca9555_pins = components::pca9555::Pca9555Component::new(
+//!     mux_i2c,
+//!     capsules_extra::pca9555::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[PCA9555_INT],
+//! )
+//! .finalize(components::pca9555_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::pca9555::{Pca9555, Pca9555Pins, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! pca9555_component_static {
+    ($I:ty, $G:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::pca9555::BUFFER_SIZE]);
+        let pca9555 = kernel::static_buf!(
+            capsules_extra::pca9555::Pca9555<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+        let pins = kernel::static_buf!(
+            capsules_extra::pca9555::Pca9555Pins<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $G,
+            >
+        );
+
+        (i2c_device, buffer, pca9555, pins)
+    };};
+}
+
+pub type Pca9555ComponentType<I, G> = Pca9555Pins<'static, I2CDevice<'static, I>, G>;
+
+pub struct Pca9555Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static G,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>>
+    Pca9555Component<I, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static G,
+    ) -> Pca9555Component<I, G> {
+        Pca9555Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, G: 'static + gpio::InterruptPin<'static>> Component
+    for Pca9555Component<I, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Pca9555<'static, I2CDevice<'static, I>, G>>,
+        &'static mut MaybeUninit<Pca9555ComponentType<I, G>>,
+    );
+    type Output = &'static Pca9555ComponentType<I, G>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+
+        let pca9555 =
+            s.2.write(Pca9555::new(i2c_device, self.interrupt_pin, buffer));
+        i2c_device.set_client(pca9555);
+        self.interrupt_pin.set_client(pca9555);
+        let _ = pca9555.start();
+
+        s.3.write(Pca9555Pins::new(pca9555))
+    }
+}
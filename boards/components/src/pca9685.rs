@@ -0,0 +1,122 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the PCA9685 16-channel PWM controller.
+//!
+//! The component sets the PWM frequency of the controller and returns it and
+//! a PWM pin for each of its channels.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (pca9685, pins) = components::pca9685::Pca9685Component::new(
+//!     mux_i2c,
+//!     capsules_extra::pca9685::BASE_ADDR,
+//!     mux_alarm,
+//!     50,
+//! )
+//! .finalize(components::pca9685_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::pca9685::{Pca9685, Pca9685Pin, BUFFER_SIZE, NUM_CHANNELS};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! pca9685_component_static {
+    ($I:ty, $A:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::pca9685::BUFFER_SIZE]);
+        let pca9685 = kernel::static_buf!(
+            capsules_extra::pca9685::Pca9685<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let pins = kernel::static_buf!(
+            [capsules_extra::pca9685::Pca9685Pin<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >; capsules_extra::pca9685::NUM_CHANNELS]
+        );
+
+        (i2c_device, alarm, buffer, pca9685, pins)
+    };};
+}
+
+type Pca9685Device<I, A> = Pca9685<'static, I2CDevice<'static, I>, VirtualMuxAlarm<'static, A>>;
+type Pca9685DevicePin<I, A> =
+    Pca9685Pin<'static, I2CDevice<'static, I>, VirtualMuxAlarm<'static, A>>;
+
+pub struct Pca9685Component<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    frequency_hz: u16,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> Pca9685Component<I, A> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        frequency_hz: u16,
+    ) -> Self {
+        Pca9685Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            frequency_hz,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, A: 'static + Alarm<'static>> Component
+    for Pca9685Component<I, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Pca9685Device<I, A>>,
+        &'static mut MaybeUninit<[Pca9685DevicePin<I, A>; NUM_CHANNELS]>,
+    );
+    type Output = (
+        &'static Pca9685Device<I, A>,
+        &'static [Pca9685DevicePin<I, A>; NUM_CHANNELS],
+    );
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let alarm = s.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+        let buffer = s.2.write([0; BUFFER_SIZE]);
+
+        let pca9685 = s.3.write(Pca9685::new(i2c_device, alarm, buffer));
+        i2c_device.set_client(pca9685);
+        alarm.set_alarm_client(pca9685);
+        if let Err(error) = pca9685.configure(self.frequency_hz) {
+            panic!("Failed to configure PCA9685 ({:?})", error);
+        }
+
+        let pins = s.4.write(core::array::from_fn(|channel| {
+            Pca9685Pin::new(pca9685, channel as u8)
+        }));
+
+        (pca9685, pins)
+    }
+}
@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for PIR motion sensors.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pir = components::pir_motion::PirMotionComponent::new(
+//!     &nrf52840_peripherals.gpio_port[PIR_PIN],
+//!     mux_alarm,
+//!     capsules_extra::pir_motion::DEFAULT_HOLD_TIME_MS,
+//! )
+//! .finalize(components::pir_motion_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::pir_motion::PirMotion;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! pir_motion_component_static {
+    ($G:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let pir_motion = kernel::static_buf!(
+            capsules_extra::pir_motion::PirMotion<
+                'static,
+                $G,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, pir_motion)
+    };};
+}
+
+pub struct PirMotionComponent<G: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>>
+{
+    pin: &'static G,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    hold_time_ms: u32,
+}
+
+impl<G: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>>
+    PirMotionComponent<G, A>
+{
+    pub fn new(
+        pin: &'static G,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        hold_time_ms: u32,
+    ) -> PirMotionComponent<G, A> {
+        PirMotionComponent {
+            pin,
+            alarm_mux,
+            hold_time_ms,
+        }
+    }
+}
+
+impl<G: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>> Component
+    for PirMotionComponent<G, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<PirMotion<'static, G, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static PirMotion<'static, G, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let pir_motion = s.1.write(PirMotion::new(self.pin, alarm));
+        pir_motion.set_hold_time_ms(self.hold_time_ms);
+
+        self.pin.set_client(pir_motion);
+        alarm.set_alarm_client(pir_motion);
+
+        pir_motion
+    }
+}
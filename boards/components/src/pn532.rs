@@ -0,0 +1,252 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for the PN532 NFC controller.
+//!
+//! The PN532 is connected over I2C or SPI. Create the bus device with
+//! `Pn532I2CTransportComponent` or `Pn532SpiTransportComponent`, and pass it
+//! to `Pn532Component` with the GPIO pin connected to the PN532 IRQ output.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pn532_transport = components::pn532::Pn532I2CTransportComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::pn532::I2C_ADDRESS,
+//! )
+//! .finalize(components::pn532_i2c_transport_component_static!(
+//!     nrf52840::i2c::TWI
+//! ));
+//! let pn532 = components::pn532::Pn532Component::new(
+//!     board_kernel,
+//!     capsules_extra::pn532::DRIVER_NUM,
+//!     pn532_transport,
+//!     &nrf52840_peripherals.gpio_port[PN532_IRQ_PIN],
+//!     mux_alarm,
+//! )
+//! .finalize(components::pn532_component_static!(
+//!     capsules_extra::pn532::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::pn532::{I2CTransport, Pn532, SpiTransport, Transport, BUFFER_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! pn532_i2c_transport_component_static {
+    ($I:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let transport = kernel::static_buf!(
+            capsules_extra::pn532::I2CTransport<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (i2c_device, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! pn532_spi_transport_component_static {
+    ($S:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let tx_buffer = kernel::static_buf!([u8; capsules_extra::pn532::BUFFER_LEN]);
+        let transport = kernel::static_buf!(
+            capsules_extra::pn532::SpiTransport<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi_device, tx_buffer, transport)
+    };};
+}
+
+#[macro_export]
+macro_rules! pn532_component_static {
+    ($T:ty, $P:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::pn532::BUFFER_LEN]);
+        let pn532 = kernel::static_buf!(
+            capsules_extra::pn532::Pn532<
+                'static,
+                $T,
+                $P,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, buffer, pn532)
+    };};
+}
+
+pub struct Pn532I2CTransportComponent<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Pn532I2CTransportComponent<I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+    ) -> Pn532I2CTransportComponent<I> {
+        Pn532I2CTransportComponent {
+            i2c_mux,
+            i2c_address,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Pn532I2CTransportComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<I2CTransport<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static I2CTransport<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let transport = s.1.write(I2CTransport::new(i2c_device));
+        i2c_device.set_client(transport);
+
+        transport
+    }
+}
+
+pub struct Pn532SpiTransportComponent<S: 'static + spi::SpiMaster<'static>> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    baud_rate: u32,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Pn532SpiTransportComponent<S> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        baud_rate: u32,
+    ) -> Pn532SpiTransportComponent<S> {
+        Pn532SpiTransportComponent {
+            spi_mux,
+            chip_select,
+            baud_rate,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>> Component for Pn532SpiTransportComponent<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>>,
+    );
+    type Output = &'static SpiTransport<'static, VirtualSpiMasterDevice<'static, S>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let tx_buffer = s.1.write([0; BUFFER_LEN]);
+        let transport = s.2.write(SpiTransport::new(spi_device, tx_buffer));
+        spi_device.set_client(transport);
+
+        if let Err(error) = transport.configure(self.baud_rate) {
+            panic!("Failed to setup PN532 SPI ({:?})", error);
+        }
+
+        transport
+    }
+}
+
+pub struct Pn532Component<
+    T: 'static + Transport<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    transport: &'static T,
+    irq: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+        T: 'static + Transport<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Pn532Component<T, P, A>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        transport: &'static T,
+        irq: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Pn532Component<T, P, A> {
+        Pn532Component {
+            board_kernel,
+            driver_num,
+            transport,
+            irq,
+            alarm_mux,
+        }
+    }
+}
+
+impl<
+        T: 'static + Transport<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Component for Pn532Component<T, P, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<Pn532<'static, T, P, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Pn532<'static, T, P, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = s.1.write([0; BUFFER_LEN]);
+        let pn532 = s.2.write(Pn532::new(
+            self.transport,
+            self.irq,
+            alarm,
+            buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.transport.set_client(pn532);
+        self.irq.set_client(pn532);
+        alarm.set_alarm_client(pn532);
+
+        pn532
+    }
+}
@@ -0,0 +1,125 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for a fault policy that takes a different action for each
+//! process.
+//!
+//! Processes are matched by their package name or fixed `ShortID`. A process
+//! that is restarted is stopped first, and restarted with an alarm once a
+//! delay has passed. The policy is given to the kernel when loading the
+//! processes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fault_policy = components::process_fault_policy::ProcessFaultPolicyComponent::new(
+//!     mux_alarm,
+//!     &[
+//!         (ProcessMatch::Name("sensor_daemon"), FaultAction::Panic),
+//!         (ProcessMatch::ShortId(0x1234), FaultAction::Restart),
+//!     ],
+//!     FaultAction::Stop,
+//!     RestartBackoff {
+//!         max_restarts: 5,
+//!         initial_ms: 100,
+//!         max_ms: 10_000,
+//!     },
+//! )
+//! .finalize(components::process_fault_policy_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     NUM_PROCS
+//! ));
+//!
+//! kernel::process::load_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     fault_policy,
+//!     &process_management_capability,
+//! )
+//! .unwrap_or_else(|err| {
+//!     debug!("Error loading processes!");
+//!     debug!("{:?}", err);
+//! });
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::process::{FaultAction, PerProcessFaultPolicy, ProcessMatch, RestartBackoff};
+
+#[macro_export]
+macro_rules! process_fault_policy_component_static {
+    ($A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let policy = kernel::static_buf!(
+            kernel::process::PerProcessFaultPolicy<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $N,
+            >
+        );
+
+        (alarm, policy)
+    };};
+}
+
+pub type ProcessFaultPolicyComponentType<A, const NUM_PROCS: usize> =
+    PerProcessFaultPolicy<'static, VirtualMuxAlarm<'static, A>, NUM_PROCS>;
+
+pub struct ProcessFaultPolicyComponent<A: 'static + Alarm<'static>, const NUM_PROCS: usize> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rules: &'static [(ProcessMatch, FaultAction)],
+    default: FaultAction,
+    backoff: RestartBackoff,
+}
+
+impl<A: 'static + Alarm<'static>, const NUM_PROCS: usize>
+    ProcessFaultPolicyComponent<A, NUM_PROCS>
+{
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rules: &'static [(ProcessMatch, FaultAction)],
+        default: FaultAction,
+        backoff: RestartBackoff,
+    ) -> ProcessFaultPolicyComponent<A, NUM_PROCS> {
+        ProcessFaultPolicyComponent {
+            alarm_mux,
+            rules,
+            default,
+            backoff,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, const NUM_PROCS: usize> Component
+    for ProcessFaultPolicyComponent<A, NUM_PROCS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<ProcessFaultPolicyComponentType<A, NUM_PROCS>>,
+    );
+    type Output = &'static ProcessFaultPolicyComponentType<A, NUM_PROCS>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let policy = static_buffer.1.write(PerProcessFaultPolicy::new(
+            alarm,
+            self.rules,
+            self.default,
+            self.backoff,
+        ));
+        alarm.set_alarm_client(policy);
+
+        policy
+    }
+}
@@ -0,0 +1,144 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for buttons on a resistor ladder read through an ADC channel.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! const BUTTON_LEVELS_MV: [u16; 4] = [0, 600, 1300, 2000];
+//!
+//! let adc_channel = components::adc::AdcComponent::new(adc_mux, nrf52840::adc::AdcChannelSetup::new(AIN2))
+//!     .finalize(components::adc_component_static!(nrf52840::adc::Adc));
+//! let buttons = components::resistive_adc_buttons::ResistiveButtonsComponent::new(
+//!     board_kernel,
+//!     capsules_core::button::DRIVER_NUM,
+//!     adc_channel,
+//!     mux_alarm,
+//!     &BUTTON_LEVELS_MV,
+//!     150,
+//! )
+//! .finalize(components::resistive_adc_buttons_component_static!(
+//!     capsules_core::virtualizers::virtual_adc::AdcDevice<'static, nrf52840::adc::Adc>,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     4
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::resistive_adc_buttons::{ButtonDriver, ResistiveButtons};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::adc;
+use kernel::hil::input::Buttons;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! resistive_adc_buttons_component_static {
+    ($A:ty, $T:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $T>
+        );
+        let buttons = kernel::static_buf!(
+            capsules_extra::resistive_adc_buttons::ResistiveButtons<
+                'static,
+                $A,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $T>,
+                $N,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::resistive_adc_buttons::ButtonDriver<
+                'static,
+                capsules_extra::resistive_adc_buttons::ResistiveButtons<
+                    'static,
+                    $A,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $T>,
+                    $N,
+                >,
+            >
+        );
+
+        (alarm, buttons, driver)
+    };};
+}
+
+pub type ResistiveButtonsComponentType<A, T, const N: usize> =
+    ButtonDriver<'static, ResistiveButtons<'static, A, VirtualMuxAlarm<'static, T>, N>>;
+
+pub struct ResistiveButtonsComponent<
+    A: 'static + adc::AdcChannel<'static>,
+    T: 'static + Alarm<'static>,
+    const N: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    adc: &'static A,
+    alarm_mux: &'static MuxAlarm<'static, T>,
+    levels_mv: &'static [u16; N],
+    tolerance_mv: u16,
+}
+
+impl<A: 'static + adc::AdcChannel<'static>, T: 'static + Alarm<'static>, const N: usize>
+    ResistiveButtonsComponent<A, T, N>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        adc: &'static A,
+        alarm_mux: &'static MuxAlarm<'static, T>,
+        levels_mv: &'static [u16; N],
+        tolerance_mv: u16,
+    ) -> ResistiveButtonsComponent<A, T, N> {
+        ResistiveButtonsComponent {
+            board_kernel,
+            driver_num,
+            adc,
+            alarm_mux,
+            levels_mv,
+            tolerance_mv,
+        }
+    }
+}
+
+impl<A: 'static + adc::AdcChannel<'static>, T: 'static + Alarm<'static>, const N: usize> Component
+    for ResistiveButtonsComponent<A, T, N>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, T>>,
+        &'static mut MaybeUninit<ResistiveButtons<'static, A, VirtualMuxAlarm<'static, T>, N>>,
+        &'static mut MaybeUninit<ResistiveButtonsComponentType<A, T, N>>,
+    );
+    type Output = &'static ResistiveButtonsComponentType<A, T, N>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buttons = s.1.write(ResistiveButtons::new(
+            self.adc,
+            alarm,
+            self.levels_mv,
+            self.tolerance_mv,
+        ));
+        self.adc.set_client(buttons);
+        alarm.set_alarm_client(buttons);
+
+        let driver = s.2.write(ButtonDriver::new(
+            buttons,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        buttons.set_client(driver);
+
+        // Keep sampling so that the button state can be read at any time.
+        let _ = buttons.enable();
+
+        driver
+    }
+}
@@ -0,0 +1,69 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for quadrature rotary encoders.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let encoder = components::rotary_encoder::RotaryEncoderComponent::new(
+//!     &nrf52840_peripherals.gpio_port[ENCODER_A],
+//!     &nrf52840_peripherals.gpio_port[ENCODER_B],
+//!     Some(&nrf52840_peripherals.gpio_port[ENCODER_BUTTON]),
+//! )
+//! .finalize(components::rotary_encoder_component_static!(
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_extra::rotary_encoder::RotaryEncoder;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+
+#[macro_export]
+macro_rules! rotary_encoder_component_static {
+    ($P:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::rotary_encoder::RotaryEncoder<'static, $P>)
+    };};
+}
+
+pub struct RotaryEncoderComponent<P: 'static + gpio::InterruptPin<'static>> {
+    pin_a: &'static P,
+    pin_b: &'static P,
+    button: Option<&'static P>,
+}
+
+impl<P: 'static + gpio::InterruptPin<'static>> RotaryEncoderComponent<P> {
+    pub fn new(
+        pin_a: &'static P,
+        pin_b: &'static P,
+        button: Option<&'static P>,
+    ) -> RotaryEncoderComponent<P> {
+        RotaryEncoderComponent {
+            pin_a,
+            pin_b,
+            button,
+        }
+    }
+}
+
+impl<P: 'static + gpio::InterruptPin<'static>> Component for RotaryEncoderComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<RotaryEncoder<'static, P>>;
+    type Output = &'static RotaryEncoder<'static, P>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let encoder = s.write(RotaryEncoder::new(self.pin_a, self.pin_b, self.button));
+
+        self.pin_a.set_client(encoder);
+        self.pin_b.set_client(encoder);
+        if let Some(button) = self.button {
+            button.set_client(encoder);
+        }
+        let _ = encoder.enable();
+
+        encoder
+    }
+}
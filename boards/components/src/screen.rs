@@ -18,23 +18,40 @@
 //!
 //! // Screen
 //! ```rust
-//! let screen =
-//!     components::screen::ScreenComponent::new(board_kernel, tft, None)
-//!         .finalize(components::screen_component_static!(40960));
+//! let screen = components::screen::ScreenComponent::new(
+//!     board_kernel,
+//!     capsules_extra::screen::DRIVER_NUM,
+//!     tft,
+//!     None,
+//!     ScreenRotation::Normal,
+//!     false,
+//! )
+//! .finalize(components::screen_component_static!(40960));
 //! ```
 //!
 //! // Screen with Setup
 //! ```rust
-//! let screen =
-//!     components::screen::ScreenComponent::new(board_kernel, tft, Some(tft))
-//!         .finalize(components::screen_component_static!(40960));
+//! let screen = components::screen::ScreenComponent::new(
+//!     board_kernel,
+//!     capsules_extra::screen::DRIVER_NUM,
+//!     tft,
+//!     Some(tft),
+//!     ScreenRotation::Normal,
+//!     false,
+//! )
+//! .finalize(components::screen_component_static!(40960));
 //! ```
+//!
+//! The last two arguments set the software rotation and horizontal mirroring
+//! applied to everything applications draw, for displays that are mounted
+//! sideways or upside down.
 
 use capsules_extra::screen::Screen;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
+use kernel::hil::screen::ScreenRotation;
 
 #[macro_export]
 macro_rules! screen_component_static {
@@ -51,6 +68,8 @@ pub struct ScreenComponent<const SCREEN_BUF_LEN: usize> {
     driver_num: usize,
     screen: &'static dyn kernel::hil::screen::Screen<'static>,
     screen_setup: Option<&'static dyn kernel::hil::screen::ScreenSetup<'static>>,
+    rotation: ScreenRotation,
+    mirrored: bool,
 }
 
 impl<const SCREEN_BUF_LEN: usize> ScreenComponent<SCREEN_BUF_LEN> {
@@ -59,12 +78,16 @@ impl<const SCREEN_BUF_LEN: usize> ScreenComponent<SCREEN_BUF_LEN> {
         driver_num: usize,
         screen: &'static dyn kernel::hil::screen::Screen,
         screen_setup: Option<&'static dyn kernel::hil::screen::ScreenSetup>,
+        rotation: ScreenRotation,
+        mirrored: bool,
     ) -> ScreenComponent<SCREEN_BUF_LEN> {
         ScreenComponent {
             board_kernel: board_kernel,
             driver_num: driver_num,
             screen: screen,
             screen_setup: screen_setup,
+            rotation: rotation,
+            mirrored: mirrored,
         }
     }
 }
@@ -87,6 +110,8 @@ impl<const SCREEN_BUF_LEN: usize> Component for ScreenComponent<SCREEN_BUF_LEN>
             self.screen_setup,
             buffer,
             grant_screen,
+            self.rotation,
+            self.mirrored,
         ));
 
         kernel::hil::screen::Screen::set_client(self.screen, Some(screen));
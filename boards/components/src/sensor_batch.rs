@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for reading a group of sensors on a common period.
+//!
+//! The batch starts with the first readings one period after `finalize`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sensors = static_init!(
+//!     [capsules_extra::sensor_batch::Sensor<'static>; 2],
+//!     [
+//!         capsules_extra::sensor_batch::Sensor::Temperature(sht3x),
+//!         capsules_extra::sensor_batch::Sensor::Humidity(sht3x),
+//!     ]
+//! );
+//! let batch = components::sensor_batch::SensorBatchComponent::new(mux_alarm, sensors, 10_000)
+//!     .finalize(components::sensor_batch_component_static!(
+//!         nrf52840::rtc::Rtc<'static>
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::sensor_batch::{Sensor, SensorBatch};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! sensor_batch_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let batch = kernel::static_buf!(
+            capsules_extra::sensor_batch::SensorBatch<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, batch)
+    };};
+}
+
+pub type SensorBatchComponentType<A> = SensorBatch<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct SensorBatchComponent<A: 'static + Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    sensors: &'static [Sensor<'static>],
+    period_ms: u32,
+}
+
+impl<A: 'static + Alarm<'static>> SensorBatchComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        sensors: &'static [Sensor<'static>],
+        period_ms: u32,
+    ) -> Self {
+        SensorBatchComponent {
+            alarm_mux,
+            sensors,
+            period_ms,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for SensorBatchComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SensorBatchComponentType<A>>,
+    );
+    type Output = &'static SensorBatchComponentType<A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let batch = static_buffer
+            .1
+            .write(SensorBatch::new(alarm, self.sensors, self.period_ms));
+        alarm.set_alarm_client(batch);
+
+        let _ = batch.start();
+
+        batch
+    }
+}
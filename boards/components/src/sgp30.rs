@@ -0,0 +1,130 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the SGP30 air quality sensor.
+//!
+//! The baseline of the sensor is kept in nonvolatile storage if the board
+//! provides some, at `baseline_address`. The storage must not be shared with
+//! another client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sgp30 = components::sgp30::Sgp30Component::new(
+//!     mux_i2c,
+//!     capsules_extra::sgp30::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(baseline_storage),
+//!     0,
+//! )
+//! .finalize(components::sgp30_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! let air_quality = components::air_quality::AirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::air_quality::DRIVER_NUM,
+//!     sgp30,
+//! )
+//! .finalize(components::air_quality_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::sgp30::{Sgp30, BASELINE_SIZE, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! sgp30_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::sgp30::BUFFER_SIZE]);
+        let baseline_buffer = kernel::static_buf!([u8; capsules_extra::sgp30::BASELINE_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let sgp30 = kernel::static_buf!(
+            capsules_extra::sgp30::Sgp30<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, sgp30, buffer, baseline_buffer)
+    };};
+}
+
+pub type Sgp30ComponentType<A, I> =
+    Sgp30<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct Sgp30Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    baseline_storage: Option<&'static dyn NonvolatileStorage<'static>>,
+    baseline_address: usize,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Sgp30Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        baseline_storage: Option<&'static dyn NonvolatileStorage<'static>>,
+        baseline_address: usize,
+    ) -> Sgp30Component<A, I> {
+        Sgp30Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            baseline_storage,
+            baseline_address,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Sgp30Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Sgp30ComponentType<A, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[u8; BASELINE_SIZE]>,
+    );
+    type Output = &'static Sgp30ComponentType<A, I>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let sgp30_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let sgp30_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        sgp30_alarm.setup();
+
+        let sgp30 = static_buffer
+            .2
+            .write(Sgp30::new(sgp30_i2c, sgp30_alarm, buffer));
+        sgp30_i2c.set_client(sgp30);
+        sgp30_alarm.set_alarm_client(sgp30);
+
+        if let Some(storage) = self.baseline_storage {
+            let baseline_buffer = static_buffer.4.write([0; BASELINE_SIZE]);
+            storage.set_client(sgp30);
+            sgp30.set_baseline_storage(storage, self.baseline_address, baseline_buffer);
+        }
+
+        let _ = sgp30.start();
+        sgp30
+    }
+}
@@ -0,0 +1,170 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the SGP41 VOC and NOx sensor.
+//!
+//! The seconds of conditioning done are kept in the key-value store if the
+//! board provides one. The store must not have another client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sgp41 = components::sgp41::Sgp41Component::new(
+//!     mux_i2c,
+//!     capsules_extra::sgp41::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(kv_store),
+//! )
+//! .finalize(components::sgp41_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//!     capsules_extra::tickv::TicKVStore<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52840::nvmc::Nvmc>,
+//!         capsules_extra::sip_hash::SipHasher24<'static>,
+//!         2048,
+//!     >,
+//!     [u8; 8]
+//! ));
+//! let air_quality = components::air_quality::AirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::air_quality::DRIVER_NUM,
+//!     sgp41,
+//! )
+//! .finalize(components::air_quality_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::kv_store::KVStore;
+use capsules_extra::sgp41::{Sgp41, BUFFER_SIZE, CONDITIONING_KEY, STORAGE_BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::i2c;
+use kernel::hil::kv_system::{KVSystem, KeyType};
+use kernel::hil::time::Alarm;
+use kernel::storage_permissions::StoragePermissions;
+
+#[macro_export]
+macro_rules! sgp41_component_static {
+    ($A:ty, $I:ty, $K:ty, $T:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::sgp41::BUFFER_SIZE]);
+        let storage_key = kernel::static_buf!([u8; capsules_extra::sgp41::CONDITIONING_KEY.len()]);
+        let storage_buffer = kernel::static_buf!([u8; capsules_extra::sgp41::STORAGE_BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let sgp41 = kernel::static_buf!(
+            capsules_extra::sgp41::Sgp41<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $K,
+                $T,
+            >
+        );
+
+        (
+            alarm,
+            i2c_device,
+            sgp41,
+            buffer,
+            storage_key,
+            storage_buffer,
+        )
+    };};
+}
+
+pub type Sgp41ComponentType<A, I, K, T> =
+    Sgp41<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>, K, T>;
+
+pub struct Sgp41Component<
+    A: 'static + Alarm<'static>,
+    I: 'static + i2c::I2CMaster<'static>,
+    K: 'static + KVSystem<'static, K = T>,
+    T: 'static + KeyType,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    kv_store: Option<&'static KVStore<'static, K, T>>,
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        I: 'static + i2c::I2CMaster<'static>,
+        K: 'static + KVSystem<'static, K = T>,
+        T: 'static + KeyType,
+    > Sgp41Component<A, I, K, T>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        kv_store: Option<&'static KVStore<'static, K, T>>,
+    ) -> Sgp41Component<A, I, K, T> {
+        Sgp41Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            kv_store,
+        }
+    }
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        I: 'static + i2c::I2CMaster<'static>,
+        K: 'static + KVSystem<'static, K = T>,
+        T: 'static + KeyType,
+    > Component for Sgp41Component<A, I, K, T>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Sgp41ComponentType<A, I, K, T>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[u8; CONDITIONING_KEY.len()]>,
+        &'static mut MaybeUninit<[u8; STORAGE_BUFFER_SIZE]>,
+    );
+    type Output = &'static Sgp41ComponentType<A, I, K, T>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let sgp41_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let sgp41_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        sgp41_alarm.setup();
+
+        let sgp41 = static_buffer
+            .2
+            .write(Sgp41::new(sgp41_i2c, sgp41_alarm, buffer));
+        sgp41_i2c.set_client(sgp41);
+        sgp41_alarm.set_alarm_client(sgp41);
+
+        if let Some(kv_store) = self.kv_store {
+            let storage_cap = create_capability!(capabilities::KerneluserStorageCapability);
+            let key = static_buffer.4.write([0; CONDITIONING_KEY.len()]);
+            key.copy_from_slice(CONDITIONING_KEY);
+            let storage_buffer = static_buffer.5.write([0; STORAGE_BUFFER_SIZE]);
+            kv_store.set_client(sgp41);
+            sgp41.set_conditioning_storage(
+                kv_store,
+                key,
+                storage_buffer,
+                StoragePermissions::new_kernel_permissions(&storage_cap),
+            );
+        }
+
+        let _ = sgp41.start();
+        sgp41
+    }
+}
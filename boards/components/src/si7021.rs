@@ -21,7 +21,7 @@ use core::mem::MaybeUninit;
 
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
-use capsules_extra::si7021::SI7021;
+use capsules_extra::si7021::{BUFFER_SIZE, SI7021};
 use kernel::component::Component;
 use kernel::hil::i2c;
 use kernel::hil::time::{self, Alarm};
@@ -42,7 +42,7 @@ macro_rules! si7021_component_static {
                 capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
             >
         );
-        let buffer = kernel::static_buf!([u8; 14]);
+        let buffer = kernel::static_buf!([u8; capsules_extra::si7021::BUFFER_SIZE]);
 
         (alarm, i2c_device, si7021, buffer)
     };};
@@ -80,7 +80,7 @@ impl<A: 'static + time::Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Co
         &'static mut MaybeUninit<
             SI7021<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>,
         >,
-        &'static mut MaybeUninit<[u8; 14]>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
     );
     type Output = &'static SI7021<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
 
@@ -92,7 +92,7 @@ impl<A: 'static + time::Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Co
         let si7021_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
         si7021_alarm.setup();
 
-        let buffer = static_buffer.3.write([0; 14]);
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
 
         let si7021 = static_buffer
             .2
@@ -0,0 +1,180 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for analog and frequency output soil moisture sensors.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let soil_moisture = components::soil_moisture::CapacitiveSoilMoistureComponent::new(
+//!     adc_mux,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput2),
+//!     2800, // dry_mv
+//!     1200, // wet_mv
+//! )
+//! .finalize(components::capacitive_soil_moisture_component_static!(
+//!     nrf52840::adc::Adc
+//! ));
+//!
+//! let soil_moisture = components::soil_moisture::FrequencySoilMoistureComponent::new(
+//!     &nrf52840_peripherals.gpio_port[SOIL_MOISTURE_PIN],
+//!     mux_alarm,
+//!     1000, // window_ms
+//!     1500, // dry_hz
+//!     400,  // wet_hz
+//! )
+//! .finalize(components::frequency_soil_moisture_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_adc::{AdcDevice, MuxAdc};
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::soil_moisture::{CapacitiveSoilMoisture, FrequencySoilMoisture};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::adc;
+use kernel::hil::adc::AdcChannel;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! capacitive_soil_moisture_component_static {
+    ($A:ty $(,)?) => {{
+        let adc_device = components::adc_component_static!($A);
+        let soil_moisture = kernel::static_buf!(
+            capsules_extra::soil_moisture::CapacitiveSoilMoisture<
+                'static,
+                capsules_core::virtualizers::virtual_adc::AdcDevice<'static, $A>,
+            >
+        );
+
+        (adc_device, soil_moisture)
+    };};
+}
+
+#[macro_export]
+macro_rules! frequency_soil_moisture_component_static {
+    ($G:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let soil_moisture = kernel::static_buf!(
+            capsules_extra::soil_moisture::FrequencySoilMoisture<
+                'static,
+                $G,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, soil_moisture)
+    };};
+}
+
+pub struct CapacitiveSoilMoistureComponent<A: 'static + adc::Adc<'static>> {
+    adc_mux: &'static MuxAdc<'static, A>,
+    adc_channel: A::Channel,
+    dry_mv: u32,
+    wet_mv: u32,
+}
+
+impl<A: 'static + adc::Adc<'static>> CapacitiveSoilMoistureComponent<A> {
+    pub fn new(
+        adc_mux: &'static MuxAdc<'static, A>,
+        adc_channel: A::Channel,
+        dry_mv: u32,
+        wet_mv: u32,
+    ) -> CapacitiveSoilMoistureComponent<A> {
+        CapacitiveSoilMoistureComponent {
+            adc_mux,
+            adc_channel,
+            dry_mv,
+            wet_mv,
+        }
+    }
+}
+
+impl<A: 'static + adc::Adc<'static>> Component for CapacitiveSoilMoistureComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<AdcDevice<'static, A>>,
+        &'static mut MaybeUninit<CapacitiveSoilMoisture<'static, AdcDevice<'static, A>>>,
+    );
+    type Output = &'static CapacitiveSoilMoisture<'static, AdcDevice<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let adc_device =
+            crate::adc::AdcComponent::new(self.adc_mux, self.adc_channel).finalize(s.0);
+
+        let soil_moisture = s.1.write(CapacitiveSoilMoisture::new(
+            adc_device,
+            self.dry_mv,
+            self.wet_mv,
+        ));
+
+        adc_device.set_client(soil_moisture);
+
+        soil_moisture
+    }
+}
+
+pub struct FrequencySoilMoistureComponent<
+    G: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+> {
+    pin: &'static G,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    window_ms: u32,
+    dry_hz: u32,
+    wet_hz: u32,
+}
+
+impl<G: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>>
+    FrequencySoilMoistureComponent<G, A>
+{
+    pub fn new(
+        pin: &'static G,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        window_ms: u32,
+        dry_hz: u32,
+        wet_hz: u32,
+    ) -> FrequencySoilMoistureComponent<G, A> {
+        FrequencySoilMoistureComponent {
+            pin,
+            alarm_mux,
+            window_ms,
+            dry_hz,
+            wet_hz,
+        }
+    }
+}
+
+impl<G: 'static + gpio::InterruptPin<'static>, A: 'static + Alarm<'static>> Component
+    for FrequencySoilMoistureComponent<G, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<FrequencySoilMoisture<'static, G, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static FrequencySoilMoisture<'static, G, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let soil_moisture = s.1.write(FrequencySoilMoisture::new(
+            self.pin,
+            alarm,
+            self.window_ms,
+            self.dry_hz,
+            self.wet_hz,
+        ));
+
+        self.pin.set_client(soil_moisture);
+        alarm.set_alarm_client(soil_moisture);
+
+        soil_moisture
+    }
+}
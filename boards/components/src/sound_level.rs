@@ -0,0 +1,183 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Components for measuring sound pressure level with an ADC microphone.
+//!
+//! This provides two components.
+//!
+//! 1. `SoundLevelComponent` samples the microphone and computes the sound
+//!    level. It takes over the ADC, which must not be shared.
+//!
+//! 2. `SoundLevelDriverComponent` provides the sound level syscall driver
+//!    for any `hil::sensors::SoundLevel` device.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let microphone_channel = static_init!(
+//!     nrf52840::adc::AdcChannelSetup,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput4)
+//! );
+//! let sound_level = components::sound_level::SoundLevelComponent::new(
+//!     &base_peripherals.adc,
+//!     microphone_channel,
+//!     mux_alarm,
+//!     8000,
+//!     12000,
+//! )
+//! .finalize(components::sound_level_component_static!(
+//!     nrf52840::adc::Adc<'static>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//!
+//! let sound_level_driver = components::sound_level::SoundLevelDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::sound_level_driver::DRIVER_NUM,
+//!     sound_level,
+//! )
+//! .finalize(components::sound_level_driver_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::sound_level::{SoundLevel, BUFFER_SAMPLES};
+use capsules_extra::sound_level_driver::SoundLevelDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::adc;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! sound_level_component_static {
+    ($A:ty, $T:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $T>
+        );
+        let buffer1 = kernel::static_buf!([u16; capsules_extra::sound_level::BUFFER_SAMPLES]);
+        let buffer2 = kernel::static_buf!([u16; capsules_extra::sound_level::BUFFER_SAMPLES]);
+        let sound_level = kernel::static_buf!(
+            capsules_extra::sound_level::SoundLevel<
+                'static,
+                $A,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $T>,
+            >
+        );
+
+        (alarm, buffer1, buffer2, sound_level)
+    };};
+}
+
+#[macro_export]
+macro_rules! sound_level_driver_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::sound_level_driver::SoundLevelDriver<'static>)
+    };};
+}
+
+pub struct SoundLevelComponent<A: 'static + adc::AdcHighSpeed<'static>, T: 'static + Alarm<'static>>
+{
+    adc: &'static A,
+    channel: &'static A::Channel,
+    alarm_mux: &'static MuxAlarm<'static, T>,
+    sample_rate_hz: u32,
+    calibration_cdb: u16,
+}
+
+impl<A: 'static + adc::AdcHighSpeed<'static>, T: 'static + Alarm<'static>>
+    SoundLevelComponent<A, T>
+{
+    /// `calibration_cdb` is the level, in hundredths of a dB SPL, of a
+    /// signal with an RMS amplitude of 32768 ADC counts.
+    pub fn new(
+        adc: &'static A,
+        channel: &'static A::Channel,
+        alarm_mux: &'static MuxAlarm<'static, T>,
+        sample_rate_hz: u32,
+        calibration_cdb: u16,
+    ) -> SoundLevelComponent<A, T> {
+        SoundLevelComponent {
+            adc,
+            channel,
+            alarm_mux,
+            sample_rate_hz,
+            calibration_cdb,
+        }
+    }
+}
+
+impl<A: 'static + adc::AdcHighSpeed<'static>, T: 'static + Alarm<'static>> Component
+    for SoundLevelComponent<A, T>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, T>>,
+        &'static mut MaybeUninit<[u16; BUFFER_SAMPLES]>,
+        &'static mut MaybeUninit<[u16; BUFFER_SAMPLES]>,
+        &'static mut MaybeUninit<SoundLevel<'static, A, VirtualMuxAlarm<'static, T>>>,
+    );
+    type Output = &'static SoundLevel<'static, A, VirtualMuxAlarm<'static, T>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer1 = s.1.write([0; BUFFER_SAMPLES]);
+        let buffer2 = s.2.write([0; BUFFER_SAMPLES]);
+
+        let sound_level = s.3.write(SoundLevel::new(
+            self.adc,
+            self.channel,
+            alarm,
+            self.sample_rate_hz,
+            self.calibration_cdb,
+            buffer1,
+            buffer2,
+        ));
+
+        alarm.set_alarm_client(sound_level);
+        self.adc.set_client(sound_level);
+        self.adc.set_highspeed_client(sound_level);
+
+        sound_level
+    }
+}
+
+pub struct SoundLevelDriverComponent<S: 'static + hil::sensors::SoundLevel<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sensor: &'static S,
+}
+
+impl<S: 'static + hil::sensors::SoundLevel<'static>> SoundLevelDriverComponent<S> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sensor: &'static S,
+    ) -> SoundLevelDriverComponent<S> {
+        SoundLevelDriverComponent {
+            board_kernel,
+            driver_num,
+            sensor,
+        }
+    }
+}
+
+impl<S: 'static + hil::sensors::SoundLevel<'static>> Component for SoundLevelDriverComponent<S> {
+    type StaticInput = &'static mut MaybeUninit<SoundLevelDriver<'static>>;
+    type Output = &'static SoundLevelDriver<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.write(SoundLevelDriver::new(
+            self.sensor,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::sensors::SoundLevel::set_client(self.sensor, driver);
+        driver
+    }
+}
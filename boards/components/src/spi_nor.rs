@@ -0,0 +1,126 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for SPI NOR flash chips.
+//!
+//! The chip is on a SPI device, such as one from the `SpiComponent`, and its
+//! geometry is given by the board.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let spi_nor_spi = components::spi::SpiComponent::new(mux_spi, nrf52840::gpio::Pin::P0_17)
+//!     .finalize(components::spi_component_static!(nrf52840::spi::SPIM));
+//! let spi_nor = components::spi_nor::SpiNorComponent::new(
+//!     spi_nor_spi,
+//!     mux_alarm,
+//!     capsules_extra::spi_nor::Geometry {
+//!         sectors: 2048,
+//!         program_size: 256,
+//!     },
+//!     capsules_extra::spi_nor::ReadCommand::FastRead,
+//! )
+//! .finalize(components::spi_nor_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice;
+use capsules_extra::spi_nor::{Geometry, ReadCommand, SpiNor, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::Alarm;
+
+/// The SPI clock of the chip. Most chips support at least 50 MHz for fast
+/// reads, and 33 MHz for reads.
+const SPI_RATE: u32 = 8_000_000;
+
+#[macro_export]
+macro_rules! spi_nor_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let tx_buf = kernel::static_buf!([u8; capsules_extra::spi_nor::BUF_LEN]);
+        let rx_buf = kernel::static_buf!([u8; capsules_extra::spi_nor::BUF_LEN]);
+        let spi_nor = kernel::static_buf!(
+            capsules_extra::spi_nor::SpiNor<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, tx_buf, rx_buf, spi_nor)
+    };};
+}
+
+pub type SpiNorComponentType<S, A> =
+    SpiNor<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+pub struct SpiNorComponent<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> {
+    spi_device: &'static VirtualSpiMasterDevice<'static, S>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    geometry: Geometry,
+    read_command: ReadCommand,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> SpiNorComponent<S, A> {
+    pub fn new(
+        spi_device: &'static VirtualSpiMasterDevice<'static, S>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        geometry: Geometry,
+        read_command: ReadCommand,
+    ) -> SpiNorComponent<S, A> {
+        SpiNorComponent {
+            spi_device,
+            alarm_mux,
+            geometry,
+            read_command,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>> Component
+    for SpiNorComponent<S, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<SpiNorComponentType<S, A>>,
+    );
+    type Output = &'static SpiNorComponentType<S, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        if let Err(error) = self.spi_device.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_RATE,
+        ) {
+            panic!("Failed to setup SPI NOR flash SPI ({:?})", error);
+        }
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let tx_buf = s.1.write([0; BUF_LEN]);
+        let rx_buf = s.2.write([0; BUF_LEN]);
+        let spi_nor = s.3.write(SpiNor::new(
+            self.spi_device,
+            alarm,
+            self.geometry,
+            self.read_command,
+            tx_buf,
+            rx_buf,
+        ));
+        self.spi_device.set_client(spi_nor);
+        alarm.set_alarm_client(spi_nor);
+
+        spi_nor
+    }
+}
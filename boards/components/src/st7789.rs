@@ -0,0 +1,140 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for ST7789 and ILI9341 TFT screens.
+//!
+//! Uses a SPI interface, the DC pin, an optional reset pin and an alarm.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let tft = components::st7789::St7789Component::new(
+//!     mux_spi,
+//!     &nrf52840::gpio::PORT[GPIO_D4],
+//!     &nrf52840::gpio::PORT[GPIO_D3],
+//!     Some(&nrf52840::gpio::PORT[GPIO_D2]),
+//!     mux_alarm,
+//!     &capsules_extra::st7789::ST7789,
+//!     (240, 320),
+//! )
+//! .finalize(components::st7789_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::gpio::GPIOPin<'static>,
+//! ));
+//! let _ = tft.init();
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::st7789::{Panel, St7789, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! st7789_component_static {
+    ($S:ty, $A:ty, $P:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::st7789::BUFFER_SIZE]);
+        let spi = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let st7789 = kernel::static_buf!(
+            capsules_extra::st7789::St7789<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                $P,
+            >
+        );
+
+        (spi, alarm, st7789, buffer)
+    };};
+}
+
+pub type St7789ComponentType<S, A, P> =
+    St7789<'static, VirtualMuxAlarm<'static, A>, VirtualSpiMasterDevice<'static, S>, P>;
+
+pub struct St7789Component<
+    S: 'static + spi::SpiMaster<'static>,
+    A: 'static + Alarm<'static>,
+    P: 'static + gpio::Pin,
+> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    dc: &'static P,
+    reset: Option<&'static P>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    panel: &'static Panel,
+    resolution: (usize, usize),
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>, P: 'static + gpio::Pin>
+    St7789Component<S, A, P>
+{
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        dc: &'static P,
+        reset: Option<&'static P>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        panel: &'static Panel,
+        resolution: (usize, usize),
+    ) -> St7789Component<S, A, P> {
+        St7789Component {
+            spi_mux,
+            chip_select,
+            dc,
+            reset,
+            alarm_mux,
+            panel,
+            resolution,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, A: 'static + Alarm<'static>, P: 'static + gpio::Pin>
+    Component for St7789Component<S, A, P>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<St7789ComponentType<S, A, P>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static St7789ComponentType<S, A, P>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let spi_device = static_buffer
+            .0
+            .write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let alarm = static_buffer.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let st7789 = static_buffer.2.write(St7789::new(
+            spi_device,
+            alarm,
+            self.dc,
+            self.reset,
+            buffer,
+            self.panel,
+            self.resolution,
+        ));
+        spi_device.set_client(st7789);
+        alarm.set_alarm_client(st7789);
+
+        st7789
+    }
+}
@@ -0,0 +1,154 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the SX1276 LoRa transceiver.
+//!
+//! The transceiver is on an SPI bus, with DIO0 and DIO1 on interrupt pins.
+//! DIO3 can also be connected, to an interrupt pin, for channel activity
+//! detection.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let radio = components::sx1276::Sx1276Component::new(
+//!     spi_mux,
+//!     nrf52840::gpio::Pin::P0_12,
+//!     &nrf52840_peripherals.gpio_port[DIO0_PIN],
+//!     &nrf52840_peripherals.gpio_port[DIO1_PIN],
+//!     Some(&nrf52840_peripherals.gpio_port[DIO3_PIN]),
+//!     mux_alarm,
+//! )
+//! .finalize(components::sx1276_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::sx1276::{Sx1276, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::spi::{self, SpiMasterDevice};
+use kernel::hil::time::Alarm;
+
+/// The SPI clock of the transceiver, which supports up to 10 MHz.
+const SPI_RATE: u32 = 4_000_000;
+
+#[macro_export]
+macro_rules! sx1276_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let write_buffer = kernel::static_buf!([u8; capsules_extra::sx1276::BUFFER_SIZE]);
+        let read_buffer = kernel::static_buf!([u8; capsules_extra::sx1276::BUFFER_SIZE]);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let radio = kernel::static_buf!(
+            capsules_extra::sx1276::Sx1276<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (spi_device, write_buffer, read_buffer, alarm, radio)
+    };};
+}
+
+pub type Sx1276ComponentType<S, A> =
+    Sx1276<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+pub struct Sx1276Component<
+    S: 'static + spi::SpiMaster<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    dio0: &'static P,
+    dio1: &'static P,
+    dio3: Option<&'static P>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Sx1276Component<S, P, A>
+{
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        dio0: &'static P,
+        dio1: &'static P,
+        dio3: Option<&'static P>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Sx1276Component<S, P, A> {
+        Sx1276Component {
+            spi_mux,
+            chip_select,
+            dio0,
+            dio1,
+            dio3,
+            alarm_mux,
+        }
+    }
+}
+
+impl<
+        S: 'static + spi::SpiMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Component for Sx1276Component<S, P, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Sx1276ComponentType<S, A>>,
+    );
+    type Output = &'static Sx1276ComponentType<S, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+        if let Err(error) = spi_device.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_RATE,
+        ) {
+            panic!("Failed to setup SX1276 SPI ({:?})", error);
+        }
+
+        let write_buffer = s.1.write([0; BUFFER_SIZE]);
+        let read_buffer = s.2.write([0; BUFFER_SIZE]);
+        let alarm = s.3.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let radio =
+            s.4.write(Sx1276::new(spi_device, alarm, write_buffer, read_buffer));
+        spi_device.set_client(radio);
+        alarm.set_alarm_client(radio);
+
+        for dio in [Some(self.dio0), Some(self.dio1), self.dio3]
+            .into_iter()
+            .flatten()
+        {
+            dio.make_input();
+            dio.set_floating_state(gpio::FloatingState::PullNone);
+            dio.set_client(radio);
+            dio.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        }
+
+        radio
+    }
+}
@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the TCA9548A I2C multiplexer.
+//!
+//! The component creates a device at the same address on each of the eight
+//! downstream channels, for boards with several identical sensors.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let channels = components::tca9548a::Tca9548aComponent::new(mux_i2c, 0x70, 0x44)
+//!     .finalize(components::tca9548a_component_static!(
+//!         nrf52840::i2c::TWI
+//!     ));
+//! let sensor0 = &channels[0];
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::tca9548a::{MuxedI2cDevice, Tca9548a, BUFFER_SIZE, NUM_CHANNELS};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+
+#[macro_export]
+macro_rules! tca9548a_component_static {
+    ($I:ty $(,)?) => {{
+        let control =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::tca9548a::BUFFER_SIZE]);
+        let tca9548a = kernel::static_buf!(
+            capsules_extra::tca9548a::Tca9548a<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+        let devices = kernel::static_buf!(
+            [capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>;
+                capsules_extra::tca9548a::NUM_CHANNELS]
+        );
+        let channels = kernel::static_buf!(
+            [capsules_extra::tca9548a::MuxedI2cDevice<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >; capsules_extra::tca9548a::NUM_CHANNELS]
+        );
+
+        (control, buffer, tca9548a, devices, channels)
+    };};
+}
+
+pub struct Tca9548aComponent<I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    mux_address: u8,
+    device_address: u8,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Tca9548aComponent<I> {
+    /// `device_address` is the address of the devices on the downstream
+    /// channels.
+    pub fn new(i2c_mux: &'static MuxI2C<'static, I>, mux_address: u8, device_address: u8) -> Self {
+        Tca9548aComponent {
+            i2c_mux,
+            mux_address,
+            device_address,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>> Component for Tca9548aComponent<I> {
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Tca9548a<'static, I2CDevice<'static, I>>>,
+        &'static mut MaybeUninit<[I2CDevice<'static, I>; NUM_CHANNELS]>,
+        &'static mut MaybeUninit<[MuxedI2cDevice<'static, I2CDevice<'static, I>>; NUM_CHANNELS]>,
+    );
+    type Output = &'static [MuxedI2cDevice<'static, I2CDevice<'static, I>>; NUM_CHANNELS];
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let control = s.0.write(I2CDevice::new(self.i2c_mux, self.mux_address));
+        let buffer = s.1.write([0; BUFFER_SIZE]);
+        let tca9548a = s.2.write(Tca9548a::new(control, buffer));
+        control.set_client(tca9548a);
+        kernel::deferred_call::DeferredCallClient::register(tca9548a);
+
+        let devices = s.3.write(core::array::from_fn(|_| {
+            I2CDevice::new(self.i2c_mux, self.device_address)
+        }));
+        let devices: &'static [I2CDevice<'static, I>; NUM_CHANNELS] = devices;
+        let channels = s.4.write(core::array::from_fn(|channel| {
+            MuxedI2cDevice::new(tca9548a, channel as u8, &devices[channel])
+        }));
+        for (device, channel) in devices.iter().zip(channels.iter()) {
+            device.set_client(channel);
+        }
+
+        channels
+    }
+}
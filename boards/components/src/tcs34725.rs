@@ -0,0 +1,133 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the TCS34725 color sensor.
+//!
+//! I2C Interface, with the INT pin connected to a GPIO.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcs34725 = components::tcs34725::Tcs34725Component::new(
+//!     sensors_i2c_bus,
+//!     capsules_extra::tcs34725::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[TCS34725_INT_PIN],
+//!     mux_alarm,
+//!     capsules_extra::tcs34725::DEFAULT_ATIME,
+//! )
+//! .finalize(components::tcs34725_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::tcs34725::{Tcs34725, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcs34725_component_static {
+    ($A:ty, $I:ty, $P:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::tcs34725::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let tcs34725_alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let tcs34725 = kernel::static_buf!(
+            capsules_extra::tcs34725::Tcs34725<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                $P,
+            >
+        );
+
+        (tcs34725_alarm, i2c_device, tcs34725, buffer)
+    };};
+}
+
+pub struct Tcs34725Component<
+    A: 'static + Alarm<'static>,
+    I: 'static + i2c::I2CMaster<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    atime: u8,
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        I: 'static + i2c::I2CMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+    > Tcs34725Component<A, I, P>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        atime: u8,
+    ) -> Tcs34725Component<A, I, P> {
+        Tcs34725Component {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+            alarm_mux,
+            atime,
+        }
+    }
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        I: 'static + i2c::I2CMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+    > Component for Tcs34725Component<A, I, P>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<
+            Tcs34725<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>, P>,
+        >,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Tcs34725<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>, P>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let tcs34725_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let tcs34725_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        tcs34725_alarm.setup();
+
+        let tcs34725 = static_buffer.2.write(Tcs34725::new(
+            tcs34725_i2c,
+            self.interrupt_pin,
+            buffer,
+            tcs34725_alarm,
+            self.atime,
+        ));
+        tcs34725_i2c.set_client(tcs34725);
+        tcs34725_alarm.set_alarm_client(tcs34725);
+        self.interrupt_pin.set_client(tcs34725);
+
+        tcs34725
+    }
+}
@@ -0,0 +1,60 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for tagging the readings of a temperature sensor with the
+//! time they were delivered.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timestamped = components::timestamped_sensor::TimestampedTemperatureComponent::new(
+//!     temperature_sensor,
+//!     rv_timer,
+//! )
+//! .finalize(components::timestamped_temperature_component_static!(
+//!     earlgrey::timer::RvTimer<'static>
+//! ));
+//! ```
+
+use capsules_extra::timestamped_sensor::{TimestampedSensor, TimestampedTemperature};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::sensors::TemperatureDriver;
+use kernel::hil::time::Time;
+
+#[macro_export]
+macro_rules! timestamped_temperature_component_static {
+    ($T:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::timestamped_sensor::TimestampedTemperature<'static, $T>)
+    };};
+}
+
+pub struct TimestampedTemperatureComponent<T: 'static + Time> {
+    temperature_sensor: &'static dyn TemperatureDriver<'static>,
+    time: &'static T,
+}
+
+impl<T: 'static + Time> TimestampedTemperatureComponent<T> {
+    pub fn new(
+        temperature_sensor: &'static dyn TemperatureDriver<'static>,
+        time: &'static T,
+    ) -> TimestampedTemperatureComponent<T> {
+        TimestampedTemperatureComponent {
+            temperature_sensor,
+            time,
+        }
+    }
+}
+
+impl<T: 'static + Time> Component for TimestampedTemperatureComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<TimestampedTemperature<'static, T>>;
+    type Output = &'static TimestampedTemperature<'static, T>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let timestamped = s.write(TimestampedSensor::new(self.temperature_sensor, self.time));
+        self.temperature_sensor.set_client(timestamped);
+        timestamped
+    }
+}
@@ -0,0 +1,133 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the TPS65987D USB Type-C and Power Delivery controller.
+//!
+//! I2C Interface, with the INT pin connected to a GPIO.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tps65987d = components::tps65987d::Tps65987dComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::tps65987d::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[PD_INT_PIN],
+//!     mux_alarm,
+//! )
+//! .finalize(components::tps65987d_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::tps65987d::{Tps65987d, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::time::{self, Alarm};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tps65987d_component_static {
+    ($I:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::tps65987d::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let tps65987d = kernel::static_buf!(
+            capsules_extra::tps65987d::Tps65987d<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, tps65987d, buffer)
+    };};
+}
+
+pub type Tps65987dComponentType<I, A> =
+    Tps65987d<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct Tps65987dComponent<
+    I: 'static + i2c::I2CMaster<'static>,
+    P: 'static + gpio::InterruptPin<'static>,
+    A: 'static + Alarm<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    interrupt_pin: &'static P,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+        I: 'static + i2c::I2CMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Tps65987dComponent<I, P, A>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        interrupt_pin: &'static P,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Tps65987dComponent<I, P, A> {
+        Tps65987dComponent {
+            i2c_mux,
+            i2c_address,
+            interrupt_pin,
+            alarm_mux,
+        }
+    }
+}
+
+impl<
+        I: 'static + i2c::I2CMaster<'static>,
+        P: 'static + gpio::InterruptPin<'static>,
+        A: 'static + Alarm<'static>,
+    > Component for Tps65987dComponent<I, P, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Tps65987dComponentType<I, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Tps65987dComponentType<I, A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let tps65987d_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let tps65987d = static_buffer
+            .2
+            .write(Tps65987d::new(tps65987d_i2c, alarm, buffer));
+        tps65987d_i2c.set_client(tps65987d);
+        time::Alarm::set_alarm_client(alarm, tps65987d);
+
+        // INT is open drain and stays low while an event is set.
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.interrupt_pin.set_client(tps65987d);
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+
+        let _ = tps65987d.start();
+
+        tps65987d
+    }
+}
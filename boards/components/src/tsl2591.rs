@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the TSL2591 light sensor.
+//!
+//! The INT pin is optional, and is only needed for the threshold interrupt.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tsl2591 = components::tsl2591::Tsl2591Component::new(
+//!     mux_i2c,
+//!     capsules_extra::tsl2591::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(&nrf52840_peripherals.gpio_port[TSL2591_INT]),
+//! )
+//! .finalize(components::tsl2591_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::tsl2591::{Tsl2591, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! tsl2591_component_static {
+    ($I:ty, $A:ty $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::tsl2591::BUFFER_SIZE]);
+        let tsl2591 = kernel::static_buf!(
+            capsules_extra::tsl2591::Tsl2591<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (i2c_device, alarm, buffer, tsl2591)
+    };};
+}
+
+type Tsl2591Device<I, A> = Tsl2591<'static, I2CDevice<'static, I>, VirtualMuxAlarm<'static, A>>;
+
+pub struct Tsl2591Component<
+    I: 'static + i2c::I2CMaster<'static>,
+    A: 'static + Alarm<'static>,
+    G: 'static + gpio::InterruptPin<'static>,
+> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interrupt_pin: Option<&'static G>,
+}
+
+impl<
+        I: 'static + i2c::I2CMaster<'static>,
+        A: 'static + Alarm<'static>,
+        G: 'static + gpio::InterruptPin<'static>,
+    > Tsl2591Component<I, A, G>
+{
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interrupt_pin: Option<&'static G>,
+    ) -> Tsl2591Component<I, A, G> {
+        Tsl2591Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+            interrupt_pin,
+        }
+    }
+}
+
+impl<
+        I: 'static + i2c::I2CMaster<'static>,
+        A: 'static + Alarm<'static>,
+        G: 'static + gpio::InterruptPin<'static>,
+    > Component for Tsl2591Component<I, A, G>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+        &'static mut MaybeUninit<Tsl2591Device<I, A>>,
+    );
+    type Output = &'static Tsl2591Device<I, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let i2c_device = s.0.write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let alarm = s.1.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+        let buffer = s.2.write([0; BUFFER_SIZE]);
+
+        let tsl2591 = s.3.write(Tsl2591::new(
+            i2c_device,
+            alarm,
+            self.interrupt_pin
+                .map(|pin| pin as &dyn gpio::InterruptPin<'static>),
+            buffer,
+        ));
+        i2c_device.set_client(tsl2591);
+        alarm.set_alarm_client(tsl2591);
+
+        // INT is an open drain output, active low.
+        if let Some(pin) = self.interrupt_pin {
+            pin.make_input();
+            pin.set_floating_state(gpio::FloatingState::PullUp);
+            pin.set_client(tsl2591);
+        }
+
+        tsl2591
+    }
+}
@@ -0,0 +1,120 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the universal air quality reporting hub.
+//!
+//! The hub becomes the client of every sensor in `sources`, and starts
+//! polling them.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sources = static_init!(
+//!     [capsules_extra::uair::AirQualitySource<'static>; 2],
+//!     [
+//!         AirQualitySource::new(Gas::Co2, ccs811),
+//!         AirQualitySource::new(Gas::Tvoc, ccs811),
+//!     ]
+//! );
+//! let uair = components::uair::AirQualityHubComponent::new(
+//!     board_kernel,
+//!     capsules_extra::uair::DRIVER_NUM,
+//!     sources,
+//!     mux_alarm,
+//!     60_000,
+//! )
+//! .finalize(components::air_quality_hub_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::uair::{AirQualityHub, AirQualityHubDriver, AirQualitySource};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! air_quality_hub_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let hub = kernel::static_buf!(
+            capsules_extra::uair::AirQualityHub<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let driver = kernel::static_buf!(
+            capsules_extra::uair::AirQualityHubDriver<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, hub, driver)
+    };};
+}
+
+pub struct AirQualityHubComponent<A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sources: &'static [AirQualitySource<'static>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    interval_ms: u32,
+}
+
+impl<A: 'static + Alarm<'static>> AirQualityHubComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sources: &'static [AirQualitySource<'static>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        interval_ms: u32,
+    ) -> AirQualityHubComponent<A> {
+        AirQualityHubComponent {
+            board_kernel,
+            driver_num,
+            sources,
+            alarm_mux,
+            interval_ms,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for AirQualityHubComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<AirQualityHub<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<AirQualityHubDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static AirQualityHubDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let hub =
+            s.1.write(AirQualityHub::new(self.sources, alarm, self.interval_ms));
+        alarm.set_alarm_client(hub);
+        for source in self.sources {
+            source.sensor.set_client(hub);
+        }
+
+        let driver = s.2.write(AirQualityHubDriver::new(
+            hub,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        hub.set_client(driver);
+        hub.start();
+
+        driver
+    }
+}
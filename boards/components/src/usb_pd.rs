@@ -0,0 +1,63 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for any USB Type-C Power Delivery controller.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let usb_pd = UsbPdComponent::new(board_kernel, capsules_extra::usb_pd::DRIVER_NUM, tps65987d)
+//!     .finalize(components::usb_pd_component_static!());
+//! ```
+
+use capsules_extra::usb_pd::UsbPdDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! usb_pd_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::usb_pd::UsbPdDriver<'static>)
+    };};
+}
+
+pub struct UsbPdComponent<T: 'static + hil::usb_pd::UsbPdController<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    controller: &'static T,
+}
+
+impl<T: 'static + hil::usb_pd::UsbPdController<'static>> UsbPdComponent<T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        controller: &'static T,
+    ) -> UsbPdComponent<T> {
+        UsbPdComponent {
+            board_kernel,
+            driver_num,
+            controller,
+        }
+    }
+}
+
+impl<T: 'static + hil::usb_pd::UsbPdController<'static>> Component for UsbPdComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<UsbPdDriver<'static>>;
+    type Output = &'static UsbPdDriver<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.write(UsbPdDriver::new(
+            self.controller,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        hil::usb_pd::UsbPdController::set_client(self.controller, driver);
+        driver
+    }
+}
@@ -0,0 +1,102 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the VEML6075 UV-A and UV-B light sensor.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let veml6075 = components::veml6075::Veml6075Component::new(
+//!     mux_i2c,
+//!     capsules_extra::veml6075::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::veml6075_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::veml6075::{Veml6075, BUFFER_SIZE};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! veml6075_component_static {
+    ($A:ty, $I:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::veml6075::BUFFER_SIZE]);
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let veml6075 = kernel::static_buf!(
+            capsules_extra::veml6075::Veml6075<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, $I>,
+            >
+        );
+
+        (alarm, i2c_device, veml6075, buffer)
+    };};
+}
+
+pub type Veml6075ComponentType<A, I> =
+    Veml6075<'static, VirtualMuxAlarm<'static, A>, I2CDevice<'static, I>>;
+
+pub struct Veml6075Component<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Veml6075Component<A, I> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Veml6075Component<A, I> {
+        Veml6075Component {
+            i2c_mux,
+            i2c_address,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, I: 'static + i2c::I2CMaster<'static>> Component
+    for Veml6075Component<A, I>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<Veml6075ComponentType<A, I>>,
+        &'static mut MaybeUninit<[u8; BUFFER_SIZE]>,
+    );
+    type Output = &'static Veml6075ComponentType<A, I>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let veml6075_i2c = static_buffer
+            .1
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.3.write([0; BUFFER_SIZE]);
+
+        let veml6075_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        veml6075_alarm.setup();
+
+        let veml6075 = static_buffer
+            .2
+            .write(Veml6075::new(veml6075_i2c, veml6075_alarm, buffer));
+        veml6075_i2c.set_client(veml6075);
+        veml6075_alarm.set_alarm_client(veml6075);
+
+        veml6075
+    }
+}
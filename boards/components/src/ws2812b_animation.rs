@@ -0,0 +1,103 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for animations on addressable RGB LED strips.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let animation = components::ws2812b_animation::LedAnimationComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ws2812b_animation::DRIVER_NUM,
+//!     strip,
+//!     mux_alarm,
+//!     &capsules_extra::ws2812b_animation::RAINBOW,
+//! )
+//! .finalize(components::led_animation_component_static!(
+//!     Ws2812b<'static>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ws2812b_animation::{AnimationFrame, LedAnimation, LedStrip};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! led_animation_component_static {
+    ($W:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let led_animation = kernel::static_buf!(
+            capsules_extra::ws2812b_animation::LedAnimation<
+                'static,
+                $W,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, led_animation)
+    };};
+}
+
+pub struct LedAnimationComponent<W: 'static + LedStrip<'static>, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    strip: &'static W,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    frames: &'static [AnimationFrame],
+}
+
+impl<W: 'static + LedStrip<'static>, A: 'static + Alarm<'static>> LedAnimationComponent<W, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        strip: &'static W,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        frames: &'static [AnimationFrame],
+    ) -> LedAnimationComponent<W, A> {
+        LedAnimationComponent {
+            board_kernel,
+            driver_num,
+            strip,
+            alarm_mux,
+            frames,
+        }
+    }
+}
+
+impl<W: 'static + LedStrip<'static>, A: 'static + Alarm<'static>> Component
+    for LedAnimationComponent<W, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<LedAnimation<'static, W, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static LedAnimation<'static, W, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let led_animation = s.1.write(LedAnimation::new(
+            self.strip,
+            alarm,
+            self.frames,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        self.strip.set_client(led_animation);
+        alarm.set_alarm_client(led_animation);
+
+        led_animation
+    }
+}
@@ -0,0 +1,96 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for WS2812B LED strips driven over SPI.
+//!
+//! The frame buffer is allocated statically for the number of LEDs on the
+//! strip, aligned to 32 bits for DMA.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let strip = components::ws2812b_dma::Ws2812bDmaComponent::new(
+//!     spi_mux,
+//!     &nrf52840_peripherals.gpio_port[LED_STRIP_CS],
+//! )
+//! .finalize(components::ws2812b_dma_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     144
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::ws2812b_dma::{DmaBuffer, Ws2812bDma};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::spi::{self, SpiMasterDevice};
+
+#[macro_export]
+macro_rules! ws2812b_dma_component_static {
+    ($S:ty, $NUM_LEDS:expr $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let buffer = kernel::static_buf!(
+            capsules_extra::ws2812b_dma::DmaBuffer<
+                { capsules_extra::ws2812b_dma::buffer_len($NUM_LEDS) },
+            >
+        );
+        let strip = kernel::static_buf!(
+            capsules_extra::ws2812b_dma::Ws2812bDma<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi_device, buffer, strip)
+    };};
+}
+
+pub struct Ws2812bDmaComponent<S: 'static + spi::SpiMaster<'static>, const BUFFER_LEN: usize> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, const BUFFER_LEN: usize>
+    Ws2812bDmaComponent<S, BUFFER_LEN>
+{
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+    ) -> Ws2812bDmaComponent<S, BUFFER_LEN> {
+        Ws2812bDmaComponent {
+            spi_mux,
+            chip_select,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, const BUFFER_LEN: usize> Component
+    for Ws2812bDmaComponent<S, BUFFER_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<DmaBuffer<BUFFER_LEN>>,
+        &'static mut MaybeUninit<Ws2812bDma<'static, VirtualSpiMasterDevice<'static, S>>>,
+    );
+    type Output = &'static Ws2812bDma<'static, VirtualSpiMasterDevice<'static, S>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let buffer = s.1.write(DmaBuffer([0; BUFFER_LEN]));
+        let strip = s.2.write(Ws2812bDma::new(spi_device, &mut buffer.0));
+        spi_device.set_client(strip);
+
+        if let Err(error) = strip.configure() {
+            panic!("Failed to setup WS2812B SPI ({:?})", error);
+        }
+
+        strip
+    }
+}
@@ -189,6 +189,7 @@ impl KernelResources<nrf52833::chip::NRF52<'static, Nrf52833DefaultPeripherals<'
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -214,6 +215,9 @@ impl KernelResources<nrf52833::chip::NRF52<'static, Nrf52833DefaultPeripherals<'
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// This is in a separate, inline(never) function so that its stack frame is
@@ -481,9 +485,7 @@ pub unsafe fn main() {
         false,
         capsules_extra::lsm303xx::Lsm303Scale::Scale2G,
         false,
-        true,
-        capsules_extra::lsm303xx::Lsm303MagnetoDataRate::DataRate3_0Hz,
-        capsules_extra::lsm303xx::Lsm303Range::Range1_9G,
+        capsules_extra::lsm303agr::Lsm303agrMagnetoDataRate::DataRate10Hz,
     ) {
         debug!("Failed to configure LSM303AGR sensor ({:?})", error);
     }
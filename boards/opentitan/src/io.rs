@@ -2,39 +2,42 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+#[cfg(test)]
 use core::fmt::Write;
 use core::panic::PanicInfo;
-use core::str;
+#[cfg(not(feature = "semihosting"))]
+use earlgrey::uart::PanicWriter;
 use kernel::debug;
-use kernel::debug::IoWrite;
+#[cfg(not(feature = "semihosting"))]
+use kernel::utilities::StaticRef;
+#[cfg(not(feature = "semihosting"))]
+use lowrisc::uart::UartRegisters;
 
 use crate::CHIP;
 use crate::PROCESSES;
 use crate::PROCESS_PRINTER;
 
-struct Writer {}
-
-static mut WRITER: Writer = Writer {};
+/// UART the panic dump is written to. Any of `UART0_BASE` to `UART3_BASE`
+/// can be used, as long as its TX pad is connected.
+#[cfg(not(feature = "semihosting"))]
+const PANIC_UART: StaticRef<UartRegisters> = earlgrey::uart::UART0_BASE;
+/// Baud rate of the panic dump.
+#[cfg(not(feature = "semihosting"))]
+const PANIC_BAUDRATE: u32 = earlgrey::uart::UART0_BAUDRATE;
 
-impl Write for Writer {
-    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
-        self.write(s.as_bytes());
-        Ok(())
-    }
-}
+/// Writes by polling the UART, so output still goes out with interrupts
+/// disabled.
+#[cfg(not(feature = "semihosting"))]
+static mut WRITER: PanicWriter = PanicWriter::new(
+    PANIC_UART,
+    earlgrey::chip_config::CONFIG.peripheral_freq,
+    PANIC_BAUDRATE,
+);
 
-impl IoWrite for Writer {
-    fn write(&mut self, buf: &[u8]) -> usize {
-        // This creates a second instance of the UART peripheral, and should only be used
-        // during panic.
-        earlgrey::uart::Uart::new(
-            earlgrey::uart::UART0_BASE,
-            earlgrey::chip_config::CONFIG.peripheral_freq,
-        )
-        .transmit_sync(buf);
-        buf.len()
-    }
-}
+/// Writes to the simulator's console, for simulators with no UART.
+#[cfg(feature = "semihosting")]
+static mut WRITER: debug::SemihostingDebugWriter =
+    debug::SemihostingDebugWriter::new(rv32i::semihost_command);
 
 #[cfg(not(test))]
 use kernel::hil::gpio::Configure;
@@ -22,10 +22,12 @@ use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_core::virtualizers::virtual_hmac::VirtualMuxHmac;
 use capsules_core::virtualizers::virtual_sha::VirtualMuxSha;
 use earlgrey::chip::EarlGreyDefaultPeripherals;
+use earlgrey::pinmux::{MioPad, PeripheralInput, PeripheralOutput};
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::hil;
 use kernel::hil::digest::Digest;
+#[cfg(not(feature = "test_rng"))]
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::hasher::Hasher;
 use kernel::hil::i2c::I2CMaster;
@@ -45,11 +47,17 @@ use rv32i::csr;
 
 pub mod io;
 mod otbn;
+mod pinmux;
+mod plic;
 #[cfg(test)]
 mod tests;
 
 const NUM_PROCS: usize = 4;
 
+/// Interrupts that do not use the default PLIC priority, as pairs of an
+/// interrupt and its priority.
+const PLIC_PRIORITIES: &[(u32, u8)] = &[];
+
 //
 // Actual memory for holding the active process structures. Need an empty list
 // at least.
@@ -103,6 +111,10 @@ static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText>
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
 
+// Size of the no-access guard region below each process's stack, so that a
+// stack overflow is reported as such. Set to 0 to disable the guard regions.
+const STACK_GUARD_SIZE: usize = 256;
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
@@ -162,6 +174,7 @@ struct EarlGrey {
         >,
     >,
     rng: &'static capsules_core::rng::RngDriver<'static>,
+    kmac: &'static capsules_extra::kmac::KmacDriver<'static, earlgrey::kmac::Kmac<'static>>,
     aes: &'static capsules_extra::symmetric_encryption::aes::AesDriver<
         'static,
         aes_gcm::Aes128Gcm<
@@ -206,6 +219,7 @@ impl SyscallDriverLookup for EarlGrey {
             capsules_core::i2c_master::DRIVER_NUM => f(Some(self.i2c_master)),
             capsules_core::spi_controller::DRIVER_NUM => f(Some(self.spi_controller)),
             capsules_core::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules_extra::kmac::DRIVER_NUM => f(Some(self.kmac)),
             capsules_extra::symmetric_encryption::aes::DRIVER_NUM => f(Some(self.aes)),
             capsules_extra::kv_driver::DRIVER_NUM => f(Some(self.kv_driver)),
             _ => f(None),
@@ -225,6 +239,7 @@ impl KernelResources<earlgrey::chip::EarlGrey<'static, EarlGreyDefaultPeripheral
         VirtualSchedulerTimer<VirtualMuxAlarm<'static, earlgrey::timer::RvTimer<'static>>>;
     type WatchDog = lowrisc::aon_timer::AonTimer;
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -250,6 +265,9 @@ impl KernelResources<earlgrey::chip::EarlGrey<'static, EarlGreyDefaultPeripheral
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 unsafe fn setup() -> (
@@ -267,9 +285,13 @@ unsafe fn setup() -> (
 
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
+    let clkmgr = static_init!(
+        earlgrey::clkmgr::ClockManager,
+        earlgrey::clkmgr::ClockManager::new(earlgrey::clkmgr::CLKMGR_BASE)
+    );
     let peripherals = static_init!(
         EarlGreyDefaultPeripherals,
-        EarlGreyDefaultPeripherals::new()
+        EarlGreyDefaultPeripherals::new(clkmgr)
     );
     peripherals.init();
 
@@ -280,6 +302,15 @@ unsafe fn setup() -> (
         None,
     );
 
+    // Connect the console UART to the same pads the ROM uses.
+    crate::pinmux::PinmuxComponent::new(
+        &peripherals.pinmux,
+        &[(MioPad::Ioc3, PeripheralInput::Uart0Rx)],
+        &[(PeripheralOutput::Uart0Tx, MioPad::Ioc4)],
+    )
+    .finalize(())
+    .unwrap();
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = components::console::UartMuxComponent::new(
         &peripherals.uart0,
@@ -371,6 +402,8 @@ unsafe fn setup() -> (
 
     // Need to enable all interrupts for Tock Kernel
     chip.enable_plic_interrupts();
+    crate::plic::PlicPriorityInitComponent::new(&earlgrey::plic::PLIC, PLIC_PRIORITIES)
+        .finalize(());
     // enable interrupts globally
     csr::CSR.mie.modify(
         csr::mie::mie::msoft::SET + csr::mie::mie::mtimer::CLEAR + csr::mie::mie::mext::SET,
@@ -434,6 +467,13 @@ unsafe fn setup() -> (
 
     digest.set_sha_client(sha);
 
+    let kmac = components::kmac::KmacComponent::new(
+        board_kernel,
+        capsules_extra::kmac::DRIVER_NUM,
+        &peripherals.kmac,
+    )
+    .finalize(components::kmac_component_static!(earlgrey::kmac::Kmac));
+
     let i2c_master_buffer = static_init!(
         [u8; capsules_core::i2c_master::BUFFER_LENGTH],
         [0; capsules_core::i2c_master::BUFFER_LENGTH]
@@ -446,7 +486,8 @@ unsafe fn setup() -> (
             board_kernel.create_grant(
                 capsules_core::i2c_master::DRIVER_NUM,
                 &memory_allocation_cap
-            )
+            ),
+            None,
         )
     );
 
@@ -633,11 +674,26 @@ unsafe fn setup() -> (
     }
 
     // Convert hardware RNG to the Random interface.
-    let entropy_to_random = static_init!(
-        capsules_core::rng::Entropy32ToRandom<'static>,
-        capsules_core::rng::Entropy32ToRandom::new(&peripherals.rng)
-    );
-    peripherals.rng.set_client(entropy_to_random);
+    #[cfg(not(feature = "test_rng"))]
+    let entropy_to_random = {
+        let entropy_to_random: &'static _ = static_init!(
+            capsules_core::rng::Entropy32ToRandom<'static>,
+            capsules_core::rng::Entropy32ToRandom::new(&peripherals.rng)
+        );
+        peripherals.rng.set_client(entropy_to_random);
+        entropy_to_random
+    };
+    // Deterministic RNG for reproducible tests, in place of the CSRNG.
+    #[cfg(feature = "test_rng")]
+    let entropy_to_random = {
+        debug!("WARNING: using the deterministic test RNG, not for production.");
+        let test_rng: &'static _ = static_init!(
+            capsules_extra::test_rng::TestRng<'static>,
+            capsules_extra::test_rng::TestRng::new(capsules_extra::test_rng::DEFAULT_SEED)
+        );
+        kernel::deferred_call::DeferredCallClient::register(test_rng);
+        test_rng
+    };
     // Setup RNG for userspace
     let rng = static_init!(
         capsules_core::rng::RngDriver<'static>,
@@ -745,6 +801,7 @@ unsafe fn setup() -> (
             hmac,
             sha,
             rng,
+            kmac,
             lldb: lldb,
             i2c_master,
             spi_controller,
@@ -802,6 +859,7 @@ unsafe fn setup() -> (
         .unwrap();
 
     chip.pmp.enable_kernel_mpu(&mut mpu_config);
+    chip.pmp.set_stack_guard_size(STACK_GUARD_SIZE);
 
     kernel::process::load_processes(
         board_kernel,
@@ -0,0 +1,60 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the EarlGrey pin multiplexer.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!     crate::pinmux::PinmuxComponent::new(
+//!         &peripherals.pinmux,
+//!         &[(MioPad::Ioc3, PeripheralInput::Uart0Rx)],
+//!         &[(PeripheralOutput::Uart0Tx, MioPad::Ioc4)],
+//!     )
+//!     .finalize(())?;
+//! ```
+
+use earlgrey::pinmux::{MioPad, PeripheralInput, PeripheralOutput, Pinmux, PinmuxCapability};
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::ErrorCode;
+
+pub struct PinmuxComponent {
+    pinmux: &'static Pinmux,
+    inputs: &'static [(MioPad, PeripheralInput)],
+    outputs: &'static [(PeripheralOutput, MioPad)],
+}
+
+impl PinmuxComponent {
+    pub fn new(
+        pinmux: &'static Pinmux,
+        inputs: &'static [(MioPad, PeripheralInput)],
+        outputs: &'static [(PeripheralOutput, MioPad)],
+    ) -> PinmuxComponent {
+        PinmuxComponent {
+            pinmux,
+            inputs,
+            outputs,
+        }
+    }
+}
+
+impl Component for PinmuxComponent {
+    type StaticInput = ();
+    type Output = Result<(), ErrorCode>;
+
+    fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let pinmux_cap = create_capability!(PinmuxCapability);
+
+        for &(mio_pad, peripheral_input) in self.inputs {
+            self.pinmux
+                .configure_input(mio_pad, peripheral_input, &pinmux_cap)?;
+        }
+        for &(peripheral_output, mio_pad) in self.outputs {
+            self.pinmux
+                .configure_output(peripheral_output, mio_pad, &pinmux_cap)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Component for the priorities of EarlGrey interrupts.
+//!
+//! The priorities are applied after `enable_plic_interrupts`, which sets
+//! every interrupt to the same priority.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!     crate::plic::PlicPriorityInitComponent::new(
+//!         &earlgrey::plic::PLIC,
+//!         &[(earlgrey::interrupts::RVTIMERTIMEREXPIRED0_0, 5)],
+//!     )
+//!     .finalize(());
+//! ```
+
+use earlgrey::plic::{Plic, PlicConfigCapability, PlicPriority};
+use kernel::component::Component;
+use kernel::create_capability;
+
+pub struct PlicPriorityInitComponent {
+    plic: &'static Plic,
+    priorities: &'static [(u32, u8)],
+}
+
+impl PlicPriorityInitComponent {
+    /// `priorities` are pairs of an interrupt and its priority.
+    pub fn new(plic: &'static Plic, priorities: &'static [(u32, u8)]) -> PlicPriorityInitComponent {
+        PlicPriorityInitComponent { plic, priorities }
+    }
+}
+
+impl Component for PlicPriorityInitComponent {
+    type StaticInput = ();
+    type Output = ();
+
+    fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let plic_cap = create_capability!(PlicConfigCapability);
+
+        for &(interrupt_id, priority) in self.priorities {
+            self.plic
+                .set_priority(interrupt_id, PlicPriority::new(priority), &plic_cap);
+        }
+    }
+}
@@ -12,6 +12,7 @@ use capsules_extra::test::aes::{TestAes128Cbc, TestAes128Ctr, TestAes128Ecb};
 use capsules_extra::test::aes_ccm;
 use capsules_extra::test::aes_gcm;
 use earlgrey::aes::Aes;
+use earlgrey::aes_ccm::AesCcm;
 use kernel::debug;
 use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE};
 use kernel::static_init;
@@ -51,6 +52,34 @@ unsafe fn static_init_test_ccm(
     )
 }
 
+#[test_case]
+fn run_aes128_ccm_hardware() {
+    debug!("check run AES128 CCM on hardware... ");
+    run_kernel_op(100);
+
+    unsafe {
+        let perf = PERIPHERALS.unwrap();
+        let aes_ccm = static_init!(AesCcm<'static>, AesCcm::new(&perf.aes));
+        kernel::deferred_call::DeferredCallClient::register(aes_ccm);
+
+        let buf = static_init!([u8; 4 * AES128_BLOCK_SIZE], [0; 4 * AES128_BLOCK_SIZE]);
+        let t = static_init!(
+            aes_ccm::Test<'static, AesCcm<'static>>,
+            aes_ccm::Test::new(aes_ccm, buf)
+        );
+        kernel::hil::symmetric_encryption::AES128CCM::set_client(aes_ccm, t);
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            while !perf.aes.idle() {}
+            t.run();
+        }
+    }
+    run_kernel_op(10000);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
 #[test_case]
 fn run_aes128_gcm() {
     debug!("check run AES128 GCM... ");
@@ -0,0 +1,207 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Test the KMAC block against the examples published by NIST for SHA-3
+//! (FIPS 202) and KMAC (SP 800-185).
+
+use crate::tests::run_kernel_op;
+use crate::PERIPHERALS;
+use core::cell::Cell;
+#[allow(unused_imports)] // Can be unused if software only test
+use kernel::hil::hash::{Sha3, Sha3Mode, SpongeClient};
+#[allow(unused_imports)] // Can be unused if software only test
+use kernel::hil::mac::{Kmac, KmacMode};
+use kernel::static_init;
+use kernel::utilities::cells::TakeCell;
+use kernel::{debug, ErrorCode};
+
+/// The key of the SP 800-185 KMAC samples.
+#[allow(dead_code)] // Can be unused if software only test
+static KMAC_KEY: [u8; 32] = [
+    0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x4B, 0x4C, 0x4D, 0x4E, 0x4F,
+    0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x5B, 0x5C, 0x5D, 0x5E, 0x5F,
+];
+
+struct KmacTestCallback {
+    absorb_done: Cell<bool>,
+    squeeze_done: Cell<bool>,
+    input_buffer: TakeCell<'static, [u8]>,
+    output_buffer: TakeCell<'static, [u8]>,
+}
+
+unsafe impl Sync for KmacTestCallback {}
+
+impl KmacTestCallback {
+    fn new(input_buffer: &'static mut [u8], output_buffer: &'static mut [u8]) -> Self {
+        KmacTestCallback {
+            absorb_done: Cell::new(false),
+            squeeze_done: Cell::new(false),
+            input_buffer: TakeCell::new(input_buffer),
+            output_buffer: TakeCell::new(output_buffer),
+        }
+    }
+
+    fn reset(&self) {
+        self.absorb_done.set(false);
+        self.squeeze_done.set(false);
+    }
+
+    /// Absorb `input` into the operation already started and check the
+    /// first bytes of output against `expected`.
+    #[allow(unused_variables)] // Can be unused if software only test
+    fn check(&self, kmac: &earlgrey::kmac::Kmac, input: &[u8], expected: &[u8]) {
+        self.reset();
+
+        #[cfg(feature = "hardware_tests")]
+        {
+            let input_buffer = self.input_buffer.take().unwrap();
+            input_buffer[..input.len()].copy_from_slice(input);
+            assert_eq!(
+                kmac.absorb(input_buffer, input.len()).map_err(|e| e.0),
+                Ok(())
+            );
+            run_kernel_op(1000);
+            assert_eq!(self.absorb_done.get(), true);
+
+            let output_buffer = self.output_buffer.take().unwrap();
+            assert_eq!(
+                kmac.squeeze(output_buffer, expected.len()).map_err(|e| e.0),
+                Ok(())
+            );
+            run_kernel_op(1000);
+            assert_eq!(self.squeeze_done.get(), true);
+            self.output_buffer
+                .map(|output| assert_eq!(&output[..expected.len()], expected));
+            kmac.finish();
+        }
+    }
+}
+
+impl SpongeClient for KmacTestCallback {
+    fn absorb_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.input_buffer.replace(data);
+        self.absorb_done.set(true);
+        assert_eq!(result, Ok(()));
+    }
+
+    fn squeeze_done(&self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+        self.output_buffer.replace(output);
+        self.squeeze_done.set(true);
+        assert_eq!(result, Ok(()));
+    }
+}
+
+macro_rules! static_init_test_cb {
+    () => {{
+        let input_data = static_init!([u8; 32], [0; 32]);
+        let output_data = static_init!([u8; 64], [0; 64]);
+
+        static_init!(
+            KmacTestCallback,
+            KmacTestCallback::new(input_data, output_data)
+        )
+    }};
+}
+
+#[test_case]
+fn kmac_sha3() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let kmac = &perf.kmac;
+
+    let callback = unsafe { static_init_test_cb!() };
+    kmac.set_client(callback);
+
+    debug!("check kmac SHA3-256, SHA3-512 and SHAKE128... ");
+    run_kernel_op(100);
+
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(kmac.start_sha3(Sha3Mode::Sha3_256), Ok(()));
+    callback.check(
+        kmac,
+        b"abc",
+        &[
+            0x3A, 0x98, 0x5D, 0xA7, 0x4F, 0xE2, 0x25, 0xB2, 0x04, 0x5C, 0x17, 0x2D, 0x6B, 0xD3,
+            0x90, 0xBD, 0x85, 0x5F, 0x08, 0x6E, 0x3E, 0x9D, 0x52, 0x5B, 0x46, 0xBF, 0xE2, 0x45,
+            0x11, 0x43, 0x15, 0x32,
+        ],
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(kmac.start_sha3(Sha3Mode::Sha3_512), Ok(()));
+    callback.check(
+        kmac,
+        b"abc",
+        &[
+            0xB7, 0x51, 0x85, 0x0B, 0x1A, 0x57, 0x16, 0x8A, 0x56, 0x93, 0xCD, 0x92, 0x4B, 0x6B,
+            0x09, 0x6E, 0x08, 0xF6, 0x21, 0x82, 0x74, 0x44, 0xF7, 0x0D, 0x88, 0x4F, 0x5D, 0x02,
+            0x40, 0xD2, 0x71, 0x2E, 0x10, 0xE1, 0x16, 0xE9, 0x19, 0x2A, 0xF3, 0xC9, 0x1A, 0x7E,
+            0xC5, 0x76, 0x47, 0xE3, 0x93, 0x40, 0x57, 0x34, 0x0B, 0x4C, 0xF4, 0x08, 0xD5, 0xA5,
+            0x65, 0x92, 0xF8, 0x27, 0x4E, 0xEC, 0x53, 0xF0,
+        ],
+    );
+
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(kmac.start_sha3(Sha3Mode::Shake128), Ok(()));
+    callback.check(
+        kmac,
+        b"",
+        &[
+            0x7F, 0x9C, 0x2B, 0xA4, 0xE8, 0x8F, 0x82, 0x7D, 0x61, 0x60, 0x45, 0x50, 0x76, 0x05,
+            0x85, 0x3E, 0xD7, 0x3B, 0x80, 0x93, 0xF6, 0xEF, 0xBC, 0x88, 0xEB, 0x1A, 0x6E, 0xAC,
+            0xFA, 0x66, 0xEF, 0x26,
+        ],
+    );
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
+
+#[test_case]
+fn kmac_kmac128() {
+    let perf = unsafe { PERIPHERALS.unwrap() };
+    let kmac = &perf.kmac;
+
+    let callback = unsafe { static_init_test_cb!() };
+    kmac.set_client(callback);
+
+    debug!("check kmac KMAC128... ");
+    run_kernel_op(100);
+
+    // SP 800-185 KMAC sample #1.
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(
+        kmac.start_kmac(KmacMode::Kmac128, &KMAC_KEY, b"", 32),
+        Ok(())
+    );
+    callback.check(
+        kmac,
+        &[0x00, 0x01, 0x02, 0x03],
+        &[
+            0xE5, 0x78, 0x0B, 0x0D, 0x3E, 0xA6, 0xF7, 0xD3, 0xA4, 0x29, 0xC5, 0x70, 0x6A, 0xA4,
+            0x3A, 0x00, 0xFA, 0xDB, 0xD7, 0xD4, 0x96, 0x28, 0x83, 0x9E, 0x31, 0x87, 0x24, 0x3F,
+            0x45, 0x6E, 0xE1, 0x4E,
+        ],
+    );
+
+    // SP 800-185 KMAC sample #2.
+    #[cfg(feature = "hardware_tests")]
+    assert_eq!(
+        kmac.start_kmac(KmacMode::Kmac128, &KMAC_KEY, b"My Tagged Application", 32),
+        Ok(())
+    );
+    callback.check(
+        kmac,
+        &[0x00, 0x01, 0x02, 0x03],
+        &[
+            0x3B, 0x1F, 0xBA, 0x96, 0x3C, 0xD8, 0xB0, 0xB5, 0x9E, 0x8C, 0x1A, 0x6D, 0x71, 0x88,
+            0x8B, 0x71, 0x43, 0x65, 0x1A, 0xF8, 0xBA, 0x0A, 0x70, 0x70, 0xC0, 0x97, 0x9E, 0x28,
+            0x11, 0x32, 0x4A, 0xA5,
+        ],
+    );
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
@@ -56,14 +56,18 @@ fn trivial_assertion() {
 mod aes_test;
 mod csrng;
 mod hmac;
+mod kmac;
 mod multi_alarm;
 mod otbn;
 mod rsa;
 mod rsa_4096;
+#[cfg(feature = "semihosting")]
+mod semihosting;
 mod sha256soft_test; // Test software SHA capsule
 mod sip_hash;
 mod spi_host;
 mod tickv_test;
+mod watchpoint;
 /// Only run the flash_ctrl tests last, as testing memory protection
 /// may deny access to flash pages for other tests depending
 /// on flash (i.e tickV).
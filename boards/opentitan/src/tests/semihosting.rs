@@ -0,0 +1,17 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Test that output written with semihosting reaches the simulator's
+//! console. `make test-semihosting` checks for the message.
+
+use core::fmt::Write;
+use kernel::debug;
+
+#[test_case]
+fn semihosting_output() {
+    debug!("check semihosting output... ");
+    let mut writer = debug::SemihostingDebugWriter::new(rv32i::semihost_command);
+    let _ = writeln!(writer, "semihosting: hello from tock");
+    debug!("    [ok]");
+}
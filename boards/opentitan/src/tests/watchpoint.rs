@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Test that a write watchpoint set with the hardware triggers fires on a
+//! write to the watched variable, and not on a read.
+
+use crate::tests::run_kernel_op;
+use core::ptr::{addr_of, addr_of_mut};
+use kernel::debug;
+use rv32i::triggers::{self, Access, Watchpoint};
+
+static mut WATCHED: u32 = 0;
+static mut HIT: Option<Watchpoint> = None;
+
+fn watchpoint_hit(watchpoint: Watchpoint) {
+    unsafe { HIT = Some(watchpoint) };
+}
+
+#[test_case]
+fn write_watchpoint() {
+    debug!("check write watchpoint... ");
+    run_kernel_op(100);
+
+    let count = triggers::trigger_count();
+    debug!("    {} trigger slots", count);
+    assert!(count > 0);
+
+    unsafe {
+        triggers::set_watchpoint_handler(watchpoint_hit);
+        let address = addr_of!(WATCHED) as usize;
+        assert_eq!(
+            triggers::set_watchpoint(0, address, 4, Access::WRITE),
+            Ok(())
+        );
+
+        assert_eq!(core::ptr::read_volatile(addr_of!(WATCHED)), 0);
+        assert_eq!(HIT, None);
+
+        core::ptr::write_volatile(addr_of_mut!(WATCHED), 42);
+        let hit = HIT.take().unwrap();
+        assert_eq!(hit.slot, 0);
+        assert_eq!(hit.address, address);
+        // The write went ahead once the watchpoint fired.
+        assert_eq!(core::ptr::read_volatile(addr_of!(WATCHED)), 42);
+
+        // The watchpoint only fires once.
+        core::ptr::write_volatile(addr_of_mut!(WATCHED), 43);
+        assert_eq!(HIT, None);
+    }
+
+    run_kernel_op(100);
+    debug!("    [ok]");
+    run_kernel_op(100);
+}
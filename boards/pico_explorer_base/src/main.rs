@@ -129,6 +129,7 @@ impl KernelResources<Rp2040<'static, Rp2040DefaultPeripherals<'static>>> for Pic
     type SchedulerTimer = cortexm0p::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -154,6 +155,9 @@ impl KernelResources<Rp2040<'static, Rp2040DefaultPeripherals<'static>>> for Pic
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// Entry point used for debuger
@@ -518,6 +522,8 @@ pub unsafe fn main() {
         capsules_extra::screen::DRIVER_NUM,
         tft,
         Some(tft),
+        kernel::hil::screen::ScreenRotation::Normal,
+        false,
     )
     .finalize(components::screen_component_static!(57600));
 
@@ -120,6 +120,7 @@ impl
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
     type ContextSwitchCallback = ();
+    type YieldSpinPolicy = ();
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -145,6 +146,9 @@ impl
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
         &()
     }
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy {
+        &()
+    }
 }
 
 /// Helper function called during bring-up that configures DMA.
@@ -658,6 +662,8 @@ pub unsafe fn main() {
         capsules_extra::screen::DRIVER_NUM,
         tft,
         Some(tft),
+        ScreenRotation::Normal,
+        false,
     )
     .finalize(components::screen_component_static!(57600));
 
@@ -0,0 +1,284 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Tock syscall driver capsule for one-shot and repeating countdown timers.
+//!
+//! Each process has `TIMERS_PER_PROCESS` timers, which it refers to by index.
+//! A repeating timer is re-armed in the kernel when it fires, without waiting
+//! for the process. Its next expiration is computed from when it was due to
+//! fire, not from when the alarm callback ran, so late callbacks do not make
+//! the period drift. If the callback is more than a period late, the missed
+//! expirations are counted and reported together in a single upcall.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a timer expires, with the timer's index and the number
+//!   of times it expired since the last upcall.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: start timer `arg1` as a one-shot timer, expiring in `arg2` ms
+//! * `2`: start timer `arg1` as a repeating timer, expiring every `arg2` ms
+//! * `3`: stop timer `arg1`
+//!
+//! Starting a timer that is already running restarts it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let alarm_timer = components::alarm::AlarmTimerComponent::new(
+//!     board_kernel,
+//!     capsules_core::alarm_timer::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::alarm_timer_component_static!(sam4l::ast::Ast));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks, Ticks32};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AlarmTimer as usize;
+
+/// Number of timers each process can have running at the same time.
+pub const TIMERS_PER_PROCESS: usize = 4;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const EXPIRED: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Timer {
+    Disabled,
+    /// Expires `dt` ticks after `reference`. A repeating timer is then
+    /// re-armed `period` ticks later; a one-shot timer has a period of 0.
+    Enabled {
+        reference: u32,
+        dt: u32,
+        period: u32,
+    },
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::Disabled
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    timers: [Timer; TIMERS_PER_PROCESS],
+}
+
+/// Returns how many ticks after `now` a timer expires, or 0 if it already
+/// has.
+fn remaining(now: u32, reference: u32, dt: u32) -> u32 {
+    let end = reference.wrapping_add(dt);
+    if Ticks32::from(now).within_range(Ticks32::from(reference), Ticks32::from(end)) {
+        end.wrapping_sub(now)
+    } else {
+        0
+    }
+}
+
+/// Re-arms a repeating timer that was due at `due`, given that it is now
+/// `now`. Returns the reference of the next expiration, which is a whole
+/// number of periods after `due`, and how many expirations have passed.
+fn next_period(now: u32, due: u32, period: u32) -> (u32, u32) {
+    let missed = now.wrapping_sub(due) / period;
+    (due.wrapping_add(missed.wrapping_mul(period)), missed + 1)
+}
+
+pub struct AlarmTimer<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    num_armed: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> AlarmTimer<'a, A> {
+    pub fn new(
+        alarm: &'a A,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> AlarmTimer<'a, A> {
+        AlarmTimer {
+            alarm,
+            apps: grant,
+            num_armed: Cell::new(0),
+        }
+    }
+
+    /// Sets the underlying alarm for the timer that expires first.
+    fn reset_active_alarm(&self) {
+        if self.num_armed.get() == 0 {
+            let _ = self.alarm.disarm();
+            return;
+        }
+
+        let now = self.alarm.now();
+        let mut earliest = u32::MAX;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                for timer in app.timers.iter() {
+                    if let Timer::Enabled { reference, dt, .. } = *timer {
+                        earliest = earliest.min(remaining(now.into_u32(), reference, dt));
+                    }
+                }
+            });
+        }
+        self.alarm.set_alarm(now, A::Ticks::from(earliest));
+    }
+
+    fn start(&self, index: usize, ms: usize, repeat: bool, processid: ProcessId) -> CommandReturn {
+        if index >= TIMERS_PER_PROCESS {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        let dt = self.alarm.ticks_from_ms(ms as u32).into_u32();
+        if repeat && dt == 0 {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+
+        let result = self.apps.enter(processid, |app, _| {
+            if app.timers[index] == Timer::Disabled {
+                self.num_armed.set(self.num_armed.get() + 1);
+            }
+            app.timers[index] = Timer::Enabled {
+                reference: self.alarm.now().into_u32(),
+                dt,
+                period: if repeat { dt } else { 0 },
+            };
+        });
+        match result {
+            Ok(()) => {
+                self.reset_active_alarm();
+                CommandReturn::success()
+            }
+            Err(err) => CommandReturn::failure(err.into()),
+        }
+    }
+
+    fn stop(&self, index: usize, processid: ProcessId) -> CommandReturn {
+        if index >= TIMERS_PER_PROCESS {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+
+        let result = self.apps.enter(processid, |app, _| {
+            if app.timers[index] == Timer::Disabled {
+                Err(ErrorCode::ALREADY)
+            } else {
+                app.timers[index] = Timer::Disabled;
+                self.num_armed.set(self.num_armed.get() - 1);
+                Ok(())
+            }
+        });
+        match result {
+            Ok(Ok(())) => {
+                self.reset_active_alarm();
+                CommandReturn::success()
+            }
+            Ok(Err(e)) => CommandReturn::failure(e),
+            Err(err) => CommandReturn::failure(err.into()),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for AlarmTimer<'a, A> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start timer `arg1` as a one-shot timer expiring in `arg2` ms.
+    /// - `2`: Start timer `arg1` as a repeating timer with a period of
+    ///   `arg2` ms.
+    /// - `3`: Stop timer `arg1`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start(arg1, arg2, false, processid),
+            2 => self.start(arg1, arg2, true, processid),
+            3 => self.stop(arg1, processid),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmTimer<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now().into_u32();
+        self.apps.each(|_, app, kernel_data| {
+            for (index, timer) in app.timers.iter_mut().enumerate() {
+                if let Timer::Enabled {
+                    reference,
+                    dt,
+                    period,
+                } = *timer
+                {
+                    if remaining(now, reference, dt) != 0 {
+                        continue;
+                    }
+
+                    let due = reference.wrapping_add(dt);
+                    let expirations = if period == 0 {
+                        *timer = Timer::Disabled;
+                        self.num_armed.set(self.num_armed.get() - 1);
+                        1
+                    } else {
+                        let (reference, expirations) = next_period(now, due, period);
+                        *timer = Timer::Enabled {
+                            reference,
+                            dt: period,
+                            period,
+                        };
+                        expirations
+                    };
+                    kernel_data
+                        .schedule_upcall(upcall::EXPIRED, (index, expirations as usize, 0))
+                        .ok();
+                }
+            }
+        });
+
+        self.reset_active_alarm();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_does_not_drift() {
+        // Handled on time: the next period starts when this one was due.
+        assert_eq!(next_period(1000, 1000, 100), (1000, 1));
+        // Handled late: still aligned to when it was due.
+        assert_eq!(next_period(1030, 1000, 100), (1000, 1));
+        // More than a period late: the missed expirations are skipped.
+        assert_eq!(next_period(1250, 1000, 100), (1200, 3));
+        // Across the 32-bit tick counter wrapping around.
+        assert_eq!(next_period(20, u32::MAX - 9, 100), (u32::MAX - 9, 1));
+
+        let (reference, _) = next_period(1250, 1000, 100);
+        assert_eq!(remaining(1250, reference, 100), 50);
+    }
+}
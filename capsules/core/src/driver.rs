@@ -22,6 +22,8 @@ pub enum NUM {
     AnalogComparator      = 0x00007,
     LowLevelDebug         = 0x00008,
     ReadOnlyState         = 0x00009,
+    AlarmTimer            = 0x0000A,
+    ClkStats              = 0x0000B,
     Pwm                   = 0x00010,
 
     // Kernel
@@ -34,6 +36,8 @@ pub enum NUM {
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
     Can                   = 0x20007,
+    J1939                 = 0x20008,
+    ModbusRtu             = 0x20009,
 
     // Radio
     BleAdvertising        = 0x30000,
@@ -49,6 +53,7 @@ pub enum NUM {
     CtapHid               = 0x40004,
     Sha                   = 0x40005,
     Aes                   = 0x40006,
+    Kmac                  = 0x40007,
 
     // Storage
     AppFlash              = 0x50000,
@@ -64,6 +69,11 @@ pub enum NUM {
     Proximity             = 0x60005,
     SoundPressure         = 0x60006,
     AirQuality            = 0x60007,
+    MotionDetector        = 0x60008,
+    Color                 = 0x60009,
+    SoundLevel            = 0x6000A,
+    Uair                  = 0x6000B,
+    IndoorAirQuality      = 0x6000C,
 
     // Sensor ICs
     Tsl2561               = 0x70000,
@@ -73,6 +83,8 @@ pub enum NUM {
     Lsm303dlch            = 0x70006,
     Mlx90614              = 0x70007,
     Lsm6dsoxtr            = 0x70008,
+    Si1145                = 0x70009,
+    Hx711                 = 0x7000A,
 
     // Other ICs
     Ltc294x               = 0x80000,
@@ -80,6 +92,10 @@ pub enum NUM {
     Pca9544a              = 0x80002,
     GpioAsync             = 0x80003,
     Nrf51822Serialization = 0x80004,
+    Pn532                 = 0x80005,
+    BatteryCharger        = 0x80006,
+    UsbPd                 = 0x80007,
+    Ina3221               = 0x80008,
 
     // Misc
     Buzzer                = 0x90000,
@@ -88,5 +104,9 @@ pub enum NUM {
     TextScreen            = 0x90003,
     SevenSegment          = 0x90004,
     KeyboardHid           = 0x90005,
+    LedAnimation          = 0x90006,
+    IrNec                 = 0x90007,
+    Dali                  = 0x90008,
+    PidHeater             = 0x90009,
 }
 }
@@ -3,6 +3,12 @@
 // Copyright Tock Contributors 2022.
 
 //! SyscallDriver for an I2C Master interface.
+//!
+//! A board can restrict which devices processes may talk to by giving the
+//! driver a list of allowed addresses. Transfers to any other address fail
+//! with `RESERVE`, so that, for example, a sensor app cannot reconfigure the
+//! power management chip on the same bus. With no list every address is
+//! allowed.
 
 use enum_primitive::enum_from_primitive;
 
@@ -29,6 +35,11 @@ pub struct App;
 
 pub const BUFFER_LENGTH: usize = 64;
 
+/// Whether processes may access the device at `addr`.
+fn address_allowed(allowed_addresses: Option<&[u8]>, addr: u8) -> bool {
+    allowed_addresses.map_or(true, |allowed| allowed.contains(&addr))
+}
+
 struct Transaction {
     /// The buffer containing the bytes to transmit as it should be returned to
     /// the client
@@ -42,6 +53,7 @@ pub struct I2CMasterDriver<'a, I: i2c::I2CMaster<'a>> {
     buf: TakeCell<'static, [u8]>,
     tx: MapCell<Transaction>,
     apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    allowed_addresses: Option<&'a [u8]>,
 }
 
 impl<'a, I: i2c::I2CMaster<'a>> I2CMasterDriver<'a, I> {
@@ -49,12 +61,14 @@ impl<'a, I: i2c::I2CMaster<'a>> I2CMasterDriver<'a, I> {
         i2c: &'a I,
         buf: &'static mut [u8],
         apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+        allowed_addresses: Option<&'a [u8]>,
     ) -> I2CMasterDriver<'a, I> {
         I2CMasterDriver {
             i2c,
             buf: TakeCell::new(buf),
             tx: MapCell::empty(),
             apps,
+            allowed_addresses,
         }
     }
 
@@ -67,6 +81,9 @@ impl<'a, I: i2c::I2CMaster<'a>> I2CMasterDriver<'a, I> {
         wlen: usize,
         rlen: usize,
     ) -> Result<(), ErrorCode> {
+        if !address_allowed(self.allowed_addresses, addr) {
+            return Err(ErrorCode::RESERVE);
+        }
         kernel_data
             .get_readwrite_processbuffer(rw_allow::BUFFER)
             .and_then(|buffer| {
@@ -214,3 +231,17 @@ impl<'a, I: i2c::I2CMaster<'a>> i2c::I2CHwMasterClient for I2CMasterDriver<'a, I
         self.buf.put(Some(buffer));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_addresses() {
+        let allowed = [0x29, 0x38];
+        assert!(address_allowed(Some(&allowed), 0x38));
+        assert!(!address_allowed(Some(&allowed), 0x6B));
+        assert!(!address_allowed(Some(&[]), 0x38));
+        assert!(address_allowed(None, 0x6B));
+    }
+}
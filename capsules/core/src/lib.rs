@@ -12,6 +12,7 @@ pub mod stream;
 
 pub mod adc;
 pub mod alarm;
+pub mod alarm_timer;
 pub mod button;
 pub mod console;
 pub mod console_ordered;
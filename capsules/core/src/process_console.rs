@@ -14,6 +14,7 @@ use core::str;
 use kernel::capabilities::ProcessManagementCapability;
 use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::ProcessId;
 
@@ -39,11 +40,13 @@ pub const READ_BUF_LEN: usize = 4;
 pub const COMMAND_BUF_LEN: usize = 32;
 /// Default size for the history command.
 pub const DEFAULT_COMMAND_HISTORY_LEN: usize = 10;
+/// How many commands other capsules can add to the console.
+pub const MAX_CUSTOM_COMMANDS: usize = 8;
 
 /// List of valid commands for printing help. Consolidated as these are
 /// displayed in a few different cases.
 const VALID_COMMANDS_STR: &[u8] =
-    b"help status list stop start fault boot terminate process kernel reset panic\r\n";
+    b"help status list syscalls stop start fault boot terminate process kernel klog reset panic\r\n";
 
 /// Escape character for ANSI escape sequences.
 const ESC: u8 = '\x1B' as u8;
@@ -89,6 +92,16 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    Syscalls {
+        index: isize,
+        total: isize,
+    },
+    /// Dumping the kernel log. Each state prints the message just before
+    /// `sequence`.
+    KernelLog {
+        sequence: usize,
+        end: usize,
+    },
 }
 
 impl Default for WriterState {
@@ -187,6 +200,75 @@ impl EscState {
     }
 }
 
+/// A command added to the console by another capsule, with
+/// [`ProcessConsole::register_command`].
+pub trait ConsoleCommand {
+    /// The word that runs the command.
+    fn name(&self) -> &'static str;
+
+    /// A line describing the command, printed by `help <name>`.
+    fn help(&self) -> &'static str;
+
+    /// Run the command. `arguments` is the rest of the command line, without
+    /// surrounding whitespace. Output written to `out` is printed on the
+    /// console once the command returns, up to the size of its buffer.
+    fn execute(&self, arguments: &str, out: &mut dyn fmt::Write);
+}
+
+/// The commands added to the console, which are looked up by the first word
+/// of a command line that is not a built-in command.
+struct CustomCommands<'a> {
+    commands: [OptionalCell<&'a dyn ConsoleCommand>; MAX_CUSTOM_COMMANDS],
+}
+
+impl<'a> CustomCommands<'a> {
+    fn new() -> Self {
+        CustomCommands {
+            commands: core::array::from_fn(|_| OptionalCell::empty()),
+        }
+    }
+
+    fn register(&self, command: &'a dyn ConsoleCommand) -> Result<(), ErrorCode> {
+        let name = command.name();
+        // Built-in commands are matched by prefix, so a name starting with
+        // one could never be run.
+        let builtins = str::from_utf8(VALID_COMMANDS_STR).unwrap_or("");
+        if name.is_empty()
+            || name.contains(char::is_whitespace)
+            || builtins.split_whitespace().any(|b| name.starts_with(b))
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.find(name).is_some() {
+            return Err(ErrorCode::ALREADY);
+        }
+        let slot = self
+            .commands
+            .iter()
+            .find(|slot| slot.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        slot.set(command);
+        Ok(())
+    }
+
+    /// The command named by the first word of `line`, and its arguments.
+    fn find<'l>(&self, line: &'l str) -> Option<(&'a dyn ConsoleCommand, &'l str)> {
+        let line = line.trim();
+        let name = line.split_whitespace().next()?;
+        self.commands
+            .iter()
+            .filter_map(|slot| slot.extract())
+            .find(|command| command.name() == name)
+            .map(|command| (command, line[name.len()..].trim()))
+    }
+
+    fn each(&self, mut f: impl FnMut(&'a dyn ConsoleCommand)) {
+        for command in self.commands.iter().filter_map(|slot| slot.extract()) {
+            f(command);
+        }
+    }
+}
+
 /// Data structure to hold addresses about how the kernel is stored in memory on
 /// the chip.
 ///
@@ -232,6 +314,10 @@ pub struct ProcessConsole<
     /// Cursor index in the current typing command
     cursor: Cell<usize>,
 
+    /// Whether typed characters are echoed and escape sequences (arrow keys,
+    /// history) are handled. Disabled for scripted use.
+    line_editing: Cell<bool>,
+
     /// Keep the previously read byte to consider \r\n sequences
     /// as a single \n.
     previous_byte: Cell<u8>,
@@ -253,6 +339,9 @@ pub struct ProcessConsole<
     /// Function used to reset the device in bootloader mode
     reset_function: Option<fn() -> !>,
 
+    /// Commands added by other capsules.
+    custom_commands: CustomCommands<'a>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -324,6 +413,20 @@ impl PartialEq<[u8; COMMAND_BUF_LEN]> for Command {
     }
 }
 
+/// Insert `byte` at `cursor` in the `len` byte command in `command`, moving
+/// the bytes after it (and the terminating EOL) one position to the right.
+fn insert_at_cursor(command: &mut [u8], len: usize, cursor: usize, byte: u8) {
+    command.copy_within(cursor..=len, cursor + 1);
+    command[cursor] = byte;
+}
+
+/// Remove the byte preceding `cursor` from the `len` byte command in
+/// `command`, moving the bytes after it (and the terminating EOL) one
+/// position to the left.
+fn remove_before_cursor(command: &mut [u8], len: usize, cursor: usize) {
+    command.copy_within(cursor..=len, cursor - 1);
+}
+
 struct CommandHistory<'a, const COMMAND_HISTORY_LEN: usize> {
     cmds: &'a mut [Command; COMMAND_HISTORY_LEN],
     cmd_idx: usize,
@@ -417,10 +520,15 @@ impl ConsoleWriter {
 }
 impl fmt::Write for ConsoleWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let curr = (s).as_bytes().len();
-        self.buf[self.size..self.size + curr].copy_from_slice(&(s).as_bytes()[..]);
+        // Output that does not fit is dropped.
+        let curr = cmp::min(s.len(), self.buf.len() - self.size);
+        self.buf[self.size..self.size + curr].copy_from_slice(&s.as_bytes()[..curr]);
         self.size += curr;
-        Ok(())
+        if curr < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -472,6 +580,8 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
 
             cursor: Cell::new(0),
 
+            line_editing: Cell::new(true),
+
             previous_byte: Cell::new(EOL),
 
             running: Cell::new(false),
@@ -479,10 +589,34 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
             kernel: kernel,
             kernel_addresses: kernel_addresses,
             reset_function: reset_function,
+            custom_commands: CustomCommands::new(),
             capability: capability,
         }
     }
 
+    /// Add a command to the console, which runs when the first word of a
+    /// command line is its name. Up to `MAX_CUSTOM_COMMANDS` commands can be
+    /// added.
+    ///
+    /// Returns `INVAL` if the name is empty, contains whitespace or would be
+    /// taken for a built-in command, `ALREADY` if a command with the same
+    /// name was added, and `NOMEM` if there is no room for another command.
+    pub fn register_command(&self, command: &'a dyn ConsoleCommand) -> Result<(), ErrorCode> {
+        self.custom_commands.register(command)
+    }
+
+    /// Print the built-in and added commands.
+    fn write_valid_commands(&self) {
+        let _ = self.write_bytes(b"Valid commands are: ");
+        let builtins = &VALID_COMMANDS_STR[..VALID_COMMANDS_STR.len() - 2];
+        let _ = self.write_bytes(builtins);
+        self.custom_commands.each(|command| {
+            let _ = self.write_bytes(b" ");
+            let _ = self.write_bytes(command.name().as_bytes());
+        });
+        let _ = self.write_bytes(b"\r\n");
+    }
+
     /// Start the process console listening for user commands.
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.running.get() == false {
@@ -493,6 +627,17 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         Ok(())
     }
 
+    /// Enable or disable line editing. It is enabled by default.
+    ///
+    /// With line editing disabled, received characters are not echoed and
+    /// escape sequences are not interpreted, so a script driving the console
+    /// only sees the prompt and command output. Backspace still removes the
+    /// last character.
+    pub fn set_line_editing(&self, enabled: bool) {
+        self.line_editing.set(enabled);
+        self.esc_state.set(EscState::Bypass);
+    }
+
     /// Print base information about the kernel version installed and the help
     /// message.
     pub fn display_welcome(&self) {
@@ -519,8 +664,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
 
         let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-        let _ = self.write_bytes(b"Valid commands are: ");
-        let _ = self.write_bytes(VALID_COMMANDS_STR);
+        self.write_valid_commands();
         self.prompt();
     }
 
@@ -553,6 +697,26 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                     }
                 }
             }
+            WriterState::Syscalls { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Syscalls {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::KernelLog { sequence, end } => {
+                if sequence == end {
+                    WriterState::Empty
+                } else {
+                    WriterState::KernelLog {
+                        sequence: sequence + 1,
+                        end,
+                    }
+                }
+            }
             WriterState::Empty => WriterState::Empty,
         }
     }
@@ -719,6 +883,66 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         }
                     });
             }
+            WriterState::Syscalls { index, total: _ } => {
+                let mut local_index = -1;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        local_index += 1;
+                        if local_index == index {
+                            let mut console_writer = ConsoleWriter::new();
+                            match process.debug_syscall_counters() {
+                                Some(counters) => {
+                                    let _ = write(
+                                        &mut console_writer,
+                                        format_args!(
+                                            " {:<7?}{:<20}{:8}{:7}{:11}{:8}{:7}{:6}\r\n",
+                                            process.processid(),
+                                            process.get_process_name(),
+                                            counters.command,
+                                            counters.allow,
+                                            counters.subscribe,
+                                            counters.yield_,
+                                            counters.memop,
+                                            counters.exit,
+                                        ),
+                                    );
+                                }
+                                None => {
+                                    let _ = write(
+                                        &mut console_writer,
+                                        format_args!(
+                                            " {:<7?}{:<20}  (not counted)\r\n",
+                                            process.processid(),
+                                            process.get_process_name(),
+                                        ),
+                                    );
+                                }
+                            }
+
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                        }
+                    });
+            }
+            WriterState::KernelLog { sequence, end: _ } => {
+                let sequence = sequence - 1;
+                let mut console_writer = ConsoleWriter::new();
+                let _ = match kernel::debug::kernel_log().and_then(|log| log.record(sequence)) {
+                    Some(record) => write(
+                        &mut console_writer,
+                        format_args!(
+                            " {:<8}{}  {}\r\n",
+                            sequence,
+                            record.level().as_str(),
+                            record.message()
+                        ),
+                    ),
+                    None => write(
+                        &mut console_writer,
+                        format_args!(" {:<8}(overwritten)\r\n", sequence),
+                    ),
+                };
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
             WriterState::Empty => {
                 self.prompt();
             }
@@ -759,9 +983,23 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         }
 
                         if clean_str.starts_with("help") {
-                            let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
+                            let argument = clean_str.split_whitespace().nth(1);
+                            match argument.and_then(|name| self.custom_commands.find(name)) {
+                                Some((command, _)) => {
+                                    let mut console_writer = ConsoleWriter::new();
+                                    let _ = write(
+                                        &mut console_writer,
+                                        format_args!("{}: {}\r\n", command.name(), command.help()),
+                                    );
+                                    let _ = self
+                                        .write_bytes(&(console_writer.buf)[..console_writer.size]);
+                                }
+                                None => {
+                                    let _ =
+                                        self.write_bytes(b"Welcome to the process console.\r\n");
+                                    self.write_valid_commands();
+                                }
+                            }
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -875,6 +1113,57 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                     total: count,
                                 });
                             }
+                        } else if clean_str.starts_with("syscalls") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            if argument == Some("reset") {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        proc.debug_reset_syscall_counters();
+                                    });
+                                let _ = self.write_bytes(b"Syscall counters reset.\r\n");
+                            } else {
+                                let _ = self.write_bytes(b" PID    Name                 ");
+                                let _ = self.write_bytes(
+                                    b"Command  Allow  Subscribe   Yield  Memop  Exit\r\n",
+                                );
+
+                                let mut count = 0;
+                                self.kernel.process_each_capability(&self.capability, |_| {
+                                    count += 1;
+                                });
+
+                                if count > 0 {
+                                    self.write_state(WriterState::Syscalls {
+                                        index: -1,
+                                        total: count,
+                                    });
+                                }
+                            }
+                        } else if clean_str.starts_with("klog") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            match kernel::debug::kernel_log() {
+                                None => {
+                                    let _ = self.write_bytes(b"Kernel log is not enabled.\r\n");
+                                }
+                                Some(log) if argument == Some("clear") => {
+                                    log.clear();
+                                    let _ = self.write_bytes(b"Kernel log cleared.\r\n");
+                                }
+                                Some(log) => {
+                                    let sequences = log.sequences();
+                                    if sequences.is_empty() {
+                                        let _ = self.write_bytes(b"Kernel log is empty.\r\n");
+                                    } else {
+                                        let _ = self.write_bytes(b" Seq     Level  Message\r\n");
+                                        // Start the state machine to print
+                                        // each message separately.
+                                        self.writer_state.replace(WriterState::KernelLog {
+                                            sequence: sequences.start,
+                                            end: sequences.end,
+                                        });
+                                    }
+                                }
+                            }
                         } else if clean_str.starts_with("status") {
                             let info: KernelInfo = KernelInfo::new(self.kernel);
                             let mut console_writer = ConsoleWriter::new();
@@ -970,9 +1259,14 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             );
                         } else if clean_str.starts_with("panic") {
                             panic!("Process Console forced a kernel panic.");
+                        } else if let Some((command, arguments)) =
+                            self.custom_commands.find(clean_str)
+                        {
+                            let mut console_writer = ConsoleWriter::new();
+                            command.execute(arguments, &mut console_writer);
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
                         } else {
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
+                            self.write_valid_commands();
                         }
                     }
                     Err(_e) => {
@@ -999,6 +1293,13 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         let _ = self.write_bytes(b"tock$ ");
     }
 
+    /// Echo received input back to the terminal, if line editing is enabled.
+    fn echo(&self, bytes: &[u8]) {
+        if self.line_editing.get() {
+            let _ = self.write_bytes(bytes);
+        }
+    }
+
     /// Start or iterate the state machine for an asynchronous write operation
     /// spread across multiple callback cycles.
     fn write_state(&self, state: WriterState) {
@@ -1153,7 +1454,11 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                 0 => debug!("ProcessConsole had read of 0 bytes"),
                 1 => {
                     self.command_buffer.map(|command| {
-                        let esc_state = self.esc_state.get().next_state(read_buf[0]);
+                        let esc_state = if self.line_editing.get() {
+                            self.esc_state.get().next_state(read_buf[0])
+                        } else {
+                            EscState::Bypass
+                        };
                         self.esc_state.set(esc_state);
 
                         let previous_byte = self.previous_byte.get();
@@ -1257,7 +1562,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                 self.cursor.set(0);
                                 self.execute.set(true);
 
-                                let _ = self.write_bytes(&[CR, NLINE]);
+                                self.echo(&[CR, NLINE]);
 
                                 if COMMAND_HISTORY_LEN > 1 {
                                     // Clear the unfinished command
@@ -1273,20 +1578,18 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                 // Backspace, echo and remove the byte
                                 // preceding the cursor
                                 // Note echo is '\b \b' to erase
-                                let _ = self.write_bytes(&[BS, SPACE, BS]);
+                                self.echo(&[BS, SPACE, BS]);
 
                                 // Move the bytes one position to left
-                                for i in (cursor - 1)..index {
-                                    command[i] = command[i + 1];
-                                    let _ = self.write_byte(command[i]);
-                                }
+                                remove_before_cursor(command, index, cursor);
+                                self.echo(&command[(cursor - 1)..index]);
 
                                 // Remove the EOL character at the end of the command
-                                let _ = self.write_bytes(&[BS, SPACE, BS]);
+                                self.echo(&[BS, SPACE, BS]);
 
                                 // Move the cursor to last position
                                 for _ in cursor..index {
-                                    let _ = self.write_byte(BS);
+                                    self.echo(&[BS]);
                                 }
 
                                 self.command_index.set(index - 1);
@@ -1311,24 +1614,17 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             // which causes utf-8 decoding failure, so check byte is < 128. -pal
 
                             // Echo the typed byte
-                            let _ = self.write_byte(read_buf[0]);
+                            self.echo(&[read_buf[0]]);
 
                             // Echo the rest of the bytes from the command
-                            for i in cursor..index {
-                                let _ = self.write_byte(command[i]);
-                            }
-
-                            // Make space for the newest byte
-                            for i in (cursor..(index + 1)).rev() {
-                                command[i + 1] = command[i];
-                            }
+                            self.echo(&command[cursor..index]);
 
                             // Move the cursor to the last position
                             for _ in cursor..index {
-                                let _ = self.write_byte(BS);
+                                self.echo(&[BS]);
                             }
 
-                            command[cursor] = read_buf[0];
+                            insert_at_cursor(command, index, cursor, read_buf[0]);
                             self.cursor.set(cursor + 1);
                             self.command_index.set(index + 1);
 
@@ -1347,6 +1643,13 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             }
                         }
                     });
+
+                    // Commands normally run once the newline has been echoed.
+                    // Without line editing nothing is echoed, so run it now.
+                    if self.execute.get() && !self.tx_in_progress.get() {
+                        self.execute.set(false);
+                        self.read_command();
+                    }
                 }
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
@@ -1358,3 +1661,73 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         let _ = self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo(&'static str);
+
+    impl ConsoleCommand for Echo {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn help(&self) -> &'static str {
+            "print the arguments"
+        }
+
+        fn execute(&self, arguments: &str, out: &mut dyn fmt::Write) {
+            let _ = write(out, format_args!("{}: [{}]\r\n", self.0, arguments));
+        }
+    }
+
+    #[test]
+    fn custom_command_dispatch() {
+        let led = Echo("led");
+        let blink = Echo("blink");
+        let commands = CustomCommands::new();
+        assert_eq!(commands.register(&led), Ok(()));
+        assert_eq!(commands.register(&blink), Ok(()));
+
+        let (command, arguments) = commands.find("  blink  3 fast ").unwrap();
+        assert_eq!(command.name(), "blink");
+        assert_eq!(arguments, "3 fast");
+        let mut console_writer = ConsoleWriter::new();
+        command.execute(arguments, &mut console_writer);
+        assert_eq!(
+            &console_writer.buf[..console_writer.size],
+            b"blink: [3 fast]\r\n"
+        );
+
+        assert_eq!(commands.find("led").unwrap().1, "");
+        assert!(commands.find("leds on").is_none());
+        assert!(commands.find("").is_none());
+    }
+
+    #[test]
+    fn custom_command_registration() {
+        let commands = CustomCommands::new();
+        assert_eq!(commands.register(&Echo("stopwatch")), Err(ErrorCode::INVAL));
+        assert_eq!(commands.register(&Echo("two words")), Err(ErrorCode::INVAL));
+        assert_eq!(commands.register(&Echo("")), Err(ErrorCode::INVAL));
+
+        const NAMES: [&str; MAX_CUSTOM_COMMANDS] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let echoes = NAMES.map(Echo);
+        for echo in echoes.iter() {
+            assert_eq!(commands.register(echo), Ok(()));
+        }
+        assert_eq!(commands.register(&Echo("a")), Err(ErrorCode::ALREADY));
+        assert_eq!(commands.register(&Echo("i")), Err(ErrorCode::NOMEM));
+    }
+
+    #[test]
+    fn console_writer_truncates() {
+        let mut console_writer = ConsoleWriter::new();
+        let long = [b'x'; 300];
+        let long = str::from_utf8(&long).unwrap();
+        assert!(fmt::Write::write_str(&mut console_writer, long).is_ok());
+        assert!(fmt::Write::write_str(&mut console_writer, long).is_err());
+        assert_eq!(console_writer.size, console_writer.buf.len());
+    }
+}
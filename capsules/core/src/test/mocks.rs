@@ -0,0 +1,1065 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Mock alarms, I2C devices and masters, SPI devices, GPIO pins, AES engines and UARTs
+//! for host tests of drivers.
+//!
+//! This module is only built for the tests of this crate, or with the
+//! `test_mocks` feature, which other capsule crates enable in their
+//! `[dev-dependencies]`:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! capsules-core = { path = "../core", features = ["test_mocks"] }
+//! ```
+//!
+//! Transfers started by a driver stay in progress until the test completes
+//! them, so the test decides what the device answers and when. The mocks
+//! record what the driver wrote for the test to check.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+use std::vec::Vec;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::symmetric_encryption::{self, AES128, AES128ECB, AES128_BLOCK_SIZE};
+use kernel::hil::time::{Alarm, AlarmClient, Freq1KHz, Frequency, Ticks, Ticks32, Time};
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// An alarm whose time only moves when the test moves it.
+///
+/// The frequency defaults to 1 kHz, so ticks are milliseconds.
+pub struct MockAlarm<'a, F: Frequency = Freq1KHz> {
+    now: Cell<u32>,
+    /// The reference and dt of the alarm most recently set.
+    alarm: Cell<(u32, u32)>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+    _frequency: PhantomData<F>,
+}
+
+impl<F: Frequency> MockAlarm<'_, F> {
+    pub fn new() -> Self {
+        MockAlarm {
+            now: Cell::new(0),
+            alarm: Cell::new((0, 0)),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+
+    pub fn set_now(&self, now: u32) {
+        self.now.set(now);
+    }
+
+    /// The dt the alarm is armed with.
+    pub fn dt(&self) -> Option<u32> {
+        self.armed.get().then(|| self.alarm.get().1)
+    }
+
+    /// Move time on by `ticks`, and fire the alarm if it expires. Returns
+    /// whether it did.
+    pub fn advance(&self, ticks: u32) -> bool {
+        let now = self.now.get().wrapping_add(ticks);
+        self.now.set(now);
+        let (reference, dt) = self.alarm.get();
+        let expired = self.armed.get() && now.wrapping_sub(reference) >= dt;
+        if expired {
+            self.fire();
+        }
+        expired
+    }
+
+    /// Disarm the alarm as if it had expired, without moving time or
+    /// calling the client, and return the dt it was armed with.
+    pub fn expire(&self) -> Option<u32> {
+        let dt = self.dt();
+        self.armed.set(false);
+        dt
+    }
+
+    /// Move time on to when the alarm, which must be armed, expires if it
+    /// has not yet, then disarm it and call its client.
+    pub fn fire(&self) {
+        assert!(self.armed.replace(false), "alarm fired while not armed");
+        let (reference, dt) = self.alarm.get();
+        if self.now.get().wrapping_sub(reference) < dt {
+            self.now.set(reference.wrapping_add(dt));
+        }
+        self.client.map(|client| client.alarm());
+    }
+}
+
+impl<F: Frequency> Default for MockAlarm<'_, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Frequency> Time for MockAlarm<'_, F> {
+    type Ticks = Ticks32;
+    type Frequency = F;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get().into()
+    }
+}
+
+impl<'a, F: Frequency> Alarm<'a> for MockAlarm<'a, F> {
+    fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.alarm.set((reference.into_u32(), dt.into_u32()));
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        let (reference, dt) = self.alarm.get();
+        reference.wrapping_add(dt).into()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+/// An I2C transfer: the bytes written, then the number of bytes read.
+#[derive(Clone, PartialEq, Debug)]
+pub struct I2cTransfer {
+    pub write: Vec<u8>,
+    pub read_len: usize,
+}
+
+/// An I2C device that holds each transfer until the test completes it.
+pub struct MockI2c {
+    transfers: RefCell<Vec<I2cTransfer>>,
+    in_flight: RefCell<Option<I2cTransfer>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        MockI2c {
+            transfers: RefCell::new(Vec::new()),
+            in_flight: RefCell::new(None),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Whether a transfer is in progress.
+    pub fn busy(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    /// The transfer in progress.
+    pub fn transfer(&self) -> Option<I2cTransfer> {
+        self.in_flight.borrow().clone()
+    }
+
+    /// Every transfer started so far.
+    pub fn transfers(&self) -> Vec<I2cTransfer> {
+        self.transfers.borrow().clone()
+    }
+
+    /// Every transfer started since the last call.
+    pub fn take_transfers(&self) -> Vec<I2cTransfer> {
+        self.transfers.take()
+    }
+
+    /// The bytes written by every transfer started so far.
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.transfers
+            .borrow()
+            .iter()
+            .map(|transfer| transfer.write.clone())
+            .collect()
+    }
+
+    /// The bytes written by every transfer started since the last call to
+    /// this or [`MockI2c::take_transfers`].
+    pub fn take_writes(&self) -> Vec<Vec<u8>> {
+        let writes = self.writes();
+        self.transfers.borrow_mut().clear();
+        writes
+    }
+
+    /// Complete the transfer in progress, which reads `read`, and return the
+    /// bytes it wrote.
+    pub fn complete(&self, client: &dyn i2c::I2CClient, read: &[u8]) -> Vec<u8> {
+        self.complete_with(client, read, Ok(()))
+    }
+
+    /// Fail the transfer in progress with `error`, and return the bytes it
+    /// wrote.
+    pub fn fail(&self, client: &dyn i2c::I2CClient, error: i2c::Error) -> Vec<u8> {
+        self.complete_with(client, &[], Err(error))
+    }
+
+    fn complete_with(
+        &self,
+        client: &dyn i2c::I2CClient,
+        read: &[u8],
+        status: Result<(), i2c::Error>,
+    ) -> Vec<u8> {
+        let buffer = self.buffer.take().expect("no I2C transfer in progress");
+        let transfer = self.in_flight.take().unwrap();
+        assert!(read.len() <= transfer.read_len, "read more than requested");
+        buffer[..read.len()].copy_from_slice(read);
+        client.command_complete(buffer, status);
+        transfer.write
+    }
+
+    fn start(
+        &self,
+        buffer: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        assert!(
+            !self.busy(),
+            "I2C transfer started while another is in progress"
+        );
+        let transfer = I2cTransfer {
+            write: buffer[..write_len].to_vec(),
+            read_len,
+        };
+        self.transfers.borrow_mut().push(transfer.clone());
+        *self.in_flight.borrow_mut() = Some(transfer);
+        self.buffer.replace(buffer);
+        Ok(())
+    }
+}
+
+impl Default for MockI2c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl i2c::I2CDevice for MockI2c {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn write_read(
+        &self,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(data, len, 0)
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(buffer, 0, len)
+    }
+}
+
+/// An I2C master that holds each transfer until the test completes it.
+pub struct MockI2cMaster<'a> {
+    client: OptionalCell<&'a dyn i2c::I2CHwMasterClient>,
+    transfers: RefCell<Vec<(u8, I2cTransfer)>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl MockI2cMaster<'_> {
+    pub fn new() -> Self {
+        MockI2cMaster {
+            client: OptionalCell::empty(),
+            transfers: RefCell::new(Vec::new()),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Every transfer started so far, with the address it went to.
+    pub fn transfers(&self) -> Vec<(u8, I2cTransfer)> {
+        self.transfers.borrow().clone()
+    }
+
+    /// Complete the transfer in progress, which reads `read`.
+    pub fn complete(&self, read: &[u8]) {
+        let buffer = self.buffer.take().expect("no I2C transfer in progress");
+        buffer[..read.len()].copy_from_slice(read);
+        self.client
+            .map(move |client| client.command_complete(buffer, Ok(())));
+    }
+
+    fn start(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        assert!(
+            self.buffer.is_none(),
+            "I2C transfer started while another is in progress"
+        );
+        let transfer = I2cTransfer {
+            write: buffer[..write_len].to_vec(),
+            read_len,
+        };
+        self.transfers.borrow_mut().push((addr, transfer));
+        self.buffer.replace(buffer);
+        Ok(())
+    }
+}
+
+impl Default for MockI2cMaster<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> i2c::I2CMaster<'a> for MockI2cMaster<'a> {
+    fn set_master_client(&self, master_client: &'a dyn i2c::I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(addr, data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(addr, data, len, 0)
+    }
+
+    fn read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (i2c::Error, &'static mut [u8])> {
+        self.start(addr, buffer, 0, len)
+    }
+}
+
+/// A device that a test models behind mock pins.
+pub trait PinModel {
+    /// Pin `id` was made an output or input, or driven to another level.
+    /// `level` is the level it drives while it is an output.
+    fn driven(&self, _id: usize, _output: bool, _level: bool) {}
+
+    /// The level read from pin `id`.
+    fn read(&self, id: usize) -> bool;
+}
+
+/// A GPIO pin. Without a model, it reads the level it was last set to by
+/// the driver or the test.
+pub struct MockPin<'a> {
+    level: Cell<bool>,
+    output: Cell<bool>,
+    input: Cell<bool>,
+    floating_state: Cell<gpio::FloatingState>,
+    interrupt_edge: Cell<Option<gpio::InterruptEdge>>,
+    client: OptionalCell<&'a dyn gpio::Client>,
+    model: OptionalCell<(&'a dyn PinModel, usize)>,
+}
+
+impl<'a> MockPin<'a> {
+    pub fn new(level: bool) -> Self {
+        MockPin {
+            level: Cell::new(level),
+            output: Cell::new(false),
+            input: Cell::new(false),
+            floating_state: Cell::new(gpio::FloatingState::PullNone),
+            interrupt_edge: Cell::new(None),
+            client: OptionalCell::empty(),
+            model: OptionalCell::empty(),
+        }
+    }
+
+    /// Connect the pin to `model`, which knows it as pin `id`.
+    pub fn attach(&self, model: &'a dyn PinModel, id: usize) {
+        self.model.set((model, id));
+    }
+
+    /// The level the pin was last set to.
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Whether interrupts are enabled.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupt_edge.get().is_some()
+    }
+
+    /// The edge interrupts are enabled on.
+    pub fn interrupt_edge(&self) -> Option<gpio::InterruptEdge> {
+        self.interrupt_edge.get()
+    }
+
+    /// Drive the pin to `level` from outside, and call the client if this
+    /// is an edge that interrupts are enabled on.
+    pub fn set_level(&self, level: bool) {
+        let previous = self.level.replace(level);
+        let fire = match self.interrupt_edge.get() {
+            _ if previous == level => false,
+            Some(gpio::InterruptEdge::RisingEdge) => level,
+            Some(gpio::InterruptEdge::FallingEdge) => !level,
+            Some(gpio::InterruptEdge::EitherEdge) => true,
+            None => false,
+        };
+        if fire {
+            self.client.map(|client| client.fired());
+        }
+    }
+
+    fn configuration_of(&self) -> gpio::Configuration {
+        match (self.input.get(), self.output.get()) {
+            (false, false) => gpio::Configuration::LowPower,
+            (true, false) => gpio::Configuration::Input,
+            (false, true) => gpio::Configuration::Output,
+            (true, true) => gpio::Configuration::InputOutput,
+        }
+    }
+
+    fn drive(&self, level: bool) {
+        self.level.set(level);
+        self.driven();
+    }
+
+    fn driven(&self) {
+        self.model
+            .map(|(model, id)| model.driven(*id, self.output.get(), self.level.get()));
+    }
+}
+
+impl Default for MockPin<'_> {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl gpio::Configure for MockPin<'_> {
+    fn configuration(&self) -> gpio::Configuration {
+        self.configuration_of()
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.output.set(true);
+        self.driven();
+        self.configuration_of()
+    }
+
+    fn disable_output(&self) -> gpio::Configuration {
+        self.output.set(false);
+        self.driven();
+        self.configuration_of()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.input.set(true);
+        self.configuration_of()
+    }
+
+    fn disable_input(&self) -> gpio::Configuration {
+        self.input.set(false);
+        self.configuration_of()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.input.set(false);
+        self.output.set(false);
+        self.driven();
+    }
+
+    fn set_floating_state(&self, state: gpio::FloatingState) {
+        self.floating_state.set(state);
+    }
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        self.floating_state.get()
+    }
+}
+
+impl gpio::Output for MockPin<'_> {
+    fn set(&self) {
+        self.drive(true);
+    }
+
+    fn clear(&self) {
+        self.drive(false);
+    }
+
+    fn toggle(&self) -> bool {
+        self.drive(!self.level.get());
+        self.level.get()
+    }
+}
+
+impl gpio::Input for MockPin<'_> {
+    fn read(&self) -> bool {
+        self.model
+            .map_or(self.level.get(), |(model, id)| model.read(*id))
+    }
+}
+
+impl<'a> gpio::Interrupt<'a> for MockPin<'a> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.interrupt_edge.set(Some(mode));
+    }
+
+    fn disable_interrupts(&self) {
+        self.interrupt_edge.set(None);
+    }
+
+    fn is_pending(&self) -> bool {
+        false
+    }
+}
+
+/// An SPI transfer in progress.
+type SpiTransfer = (&'static mut [u8], Option<&'static mut [u8]>, usize);
+
+/// An SPI device that holds each transfer until the test completes it.
+///
+/// A display's data/command pin can be attached with [`MockSpi::tag_with`],
+/// and then each transfer is recorded with the level of the pin when it
+/// started.
+pub struct MockSpi<'a> {
+    transfer: RefCell<Option<SpiTransfer>>,
+    transfers: RefCell<Vec<(bool, Vec<u8>)>>,
+    tag: OptionalCell<&'a MockPin<'a>>,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+}
+
+impl<'a> MockSpi<'a> {
+    pub fn new() -> Self {
+        MockSpi {
+            transfer: RefCell::new(None),
+            transfers: RefCell::new(Vec::new()),
+            tag: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            rate: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+        }
+    }
+
+    /// Record the level of `pin` with each transfer.
+    pub fn tag_with(&self, pin: &'a MockPin<'a>) {
+        self.tag.set(pin);
+    }
+
+    /// Whether a transfer is in progress.
+    pub fn busy(&self) -> bool {
+        self.transfer.borrow().is_some()
+    }
+
+    /// The bytes written by every transfer started so far.
+    pub fn transfers(&self) -> Vec<Vec<u8>> {
+        self.transfers
+            .borrow()
+            .iter()
+            .map(|(_, write)| write.clone())
+            .collect()
+    }
+
+    /// The bytes written by every transfer started so far, with the level
+    /// of the tag pin when it started.
+    pub fn tagged_transfers(&self) -> Vec<(bool, Vec<u8>)> {
+        self.transfers.borrow().clone()
+    }
+
+    /// The bytes written by every transfer since the last call.
+    pub fn take_transfers(&self) -> Vec<Vec<u8>> {
+        self.take_tagged_transfers()
+            .into_iter()
+            .map(|(_, write)| write)
+            .collect()
+    }
+
+    /// The bytes written by every transfer since the last call, with the
+    /// level of the tag pin when it started.
+    pub fn take_tagged_transfers(&self) -> Vec<(bool, Vec<u8>)> {
+        self.transfers.take()
+    }
+
+    /// Complete the transfer in progress, which reads `read` from its
+    /// start, and return the bytes it wrote.
+    pub fn complete(&self, read: &[u8]) -> Vec<u8> {
+        self.complete_with(|_, rx| rx[..read.len()].copy_from_slice(read))
+    }
+
+    /// Complete the transfer in progress, with `respond` filling in the
+    /// bytes read from the bytes written, and return the bytes it wrote.
+    /// Without a read buffer, `respond` is given no bytes to fill in.
+    pub fn complete_with(&self, respond: impl FnOnce(&[u8], &mut [u8])) -> Vec<u8> {
+        let (tx, mut rx, len) = self
+            .transfer
+            .borrow_mut()
+            .take()
+            .expect("no SPI transfer in progress");
+        match rx.as_deref_mut() {
+            Some(rx) => {
+                let read_len = len.min(rx.len());
+                respond(&tx[..len], &mut rx[..read_len]);
+            }
+            None => respond(&tx[..len], &mut []),
+        }
+        let written = tx[..len].to_vec();
+        self.client
+            .map(|client| client.read_write_done(tx, rx, len, Ok(())));
+        written
+    }
+}
+
+impl Default for MockSpi<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> SpiMasterDevice<'a> for MockSpi<'a> {
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) -> Result<(), ErrorCode> {
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
+        Ok(())
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+        assert!(
+            !self.busy(),
+            "SPI transfer started while another is in progress"
+        );
+        let len = len.min(write_buffer.len());
+        let tag = self.tag.map_or(false, |pin| pin.level());
+        self.transfers
+            .borrow_mut()
+            .push((tag, write_buffer[..len].to_vec()));
+        *self.transfer.borrow_mut() = Some((write_buffer, read_buffer, len));
+        Ok(())
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.rate.set(rate);
+        Ok(())
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.polarity.set(polarity);
+        Ok(())
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.phase.set(phase);
+        Ok(())
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+}
+
+/// The AES S-box.
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// Encrypts `block` in place with AES-128 under `key`, in software.
+pub fn aes128_encrypt(key: &[u8; 16], block: &mut [u8; AES128_BLOCK_SIZE]) {
+    let xtime = |b: u8| (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 };
+
+    let mut round_key = *key;
+    let mut rcon = 1;
+    block.iter_mut().zip(&round_key).for_each(|(b, k)| *b ^= k);
+    for round in 1..=10 {
+        // The next round key.
+        let mut word = [round_key[13], round_key[14], round_key[15], round_key[12]];
+        word.iter_mut().for_each(|b| *b = SBOX[*b as usize]);
+        word[0] ^= rcon;
+        rcon = xtime(rcon);
+        for i in 0..16 {
+            round_key[i] ^= if i < 4 { word[i] } else { round_key[i - 4] };
+        }
+
+        // SubBytes and ShiftRows, with the state in column order.
+        let state = *block;
+        for i in 0..16 {
+            block[i] = SBOX[state[(i + 4 * (i % 4)) % 16] as usize];
+        }
+        if round < 10 {
+            for column in block.chunks_mut(4) {
+                let all = column.iter().fold(0, |all, b| all ^ b);
+                let first = column[0];
+                for i in 0..4 {
+                    let next = if i < 3 { column[i + 1] } else { first };
+                    column[i] ^= all ^ xtime(column[i] ^ next);
+                }
+            }
+        }
+        block.iter_mut().zip(&round_key).for_each(|(b, k)| *b ^= k);
+    }
+}
+
+/// A `crypt` request held by [`MockAes128`].
+type Crypt = (Option<&'static mut [u8]>, &'static mut [u8], usize, usize);
+
+/// An AES-128 engine that encrypts in ECB mode in software, and holds each
+/// `crypt` until the test completes it.
+pub struct MockAes128<'a> {
+    key: Cell<[u8; 16]>,
+    crypt: RefCell<Option<Crypt>>,
+    blocks: Cell<usize>,
+    client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+}
+
+impl<'a> MockAes128<'a> {
+    pub fn new() -> Self {
+        MockAes128 {
+            key: Cell::new([0; 16]),
+            crypt: RefCell::new(None),
+            blocks: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Whether a `crypt` is in progress.
+    pub fn busy(&self) -> bool {
+        self.crypt.borrow().is_some()
+    }
+
+    /// The number of blocks encrypted so far.
+    pub fn blocks(&self) -> usize {
+        self.blocks.get()
+    }
+
+    /// Encrypt the blocks of the `crypt` in progress, and call the client.
+    pub fn complete(&self) {
+        let (source, dest, start, stop) = self.crypt.take().expect("no AES operation in progress");
+        if let Some(source) = source.as_deref() {
+            dest[start..stop].copy_from_slice(source);
+        }
+        for block in dest[start..stop].chunks_mut(AES128_BLOCK_SIZE) {
+            let mut b = [0; AES128_BLOCK_SIZE];
+            b.copy_from_slice(block);
+            aes128_encrypt(&self.key.get(), &mut b);
+            block.copy_from_slice(&b);
+            self.blocks.set(self.blocks.get() + 1);
+        }
+        self.client.map(|client| client.crypt_done(source, dest));
+    }
+
+    /// Complete every `crypt` until the client stops starting new ones.
+    pub fn run(&self) {
+        while self.busy() {
+            self.complete();
+        }
+    }
+}
+
+impl Default for MockAes128<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> AES128<'a> for MockAes128<'a> {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let key = key.try_into().map_err(|_| ErrorCode::INVAL)?;
+        self.key.set(key);
+        Ok(())
+    }
+
+    fn set_iv(&self, _iv: &[u8]) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn start_message(&self) {}
+
+    fn crypt(
+        &self,
+        source: Option<&'static mut [u8]>,
+        dest: &'static mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(
+        Result<(), ErrorCode>,
+        Option<&'static mut [u8]>,
+        &'static mut [u8],
+    )> {
+        if self.busy() {
+            return Some((Err(ErrorCode::BUSY), source, dest));
+        }
+        let len = stop_index.wrapping_sub(start_index);
+        if stop_index > dest.len()
+            || start_index > stop_index
+            || len % AES128_BLOCK_SIZE != 0
+            || source.as_ref().map_or(false, |source| source.len() != len)
+        {
+            return Some((Err(ErrorCode::INVAL), source, dest));
+        }
+        *self.crypt.borrow_mut() = Some((source, dest, start_index, stop_index));
+        None
+    }
+}
+
+impl AES128ECB for MockAes128<'_> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        if encrypting {
+            Ok(())
+        } else {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+}
+
+/// A UART that holds each transmission and reception until the test
+/// completes it.
+///
+/// Transmitted bytes are recorded when the transmission starts. Received
+/// bytes are given by the test to the reception in progress.
+pub struct MockUart<'a> {
+    transmission: RefCell<Option<(&'static mut [u8], usize)>>,
+    reception: RefCell<Option<(&'static mut [u8], usize)>>,
+    output: RefCell<Vec<u8>>,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+}
+
+impl MockUart<'_> {
+    pub fn new() -> Self {
+        MockUart {
+            transmission: RefCell::new(None),
+            reception: RefCell::new(None),
+            output: RefCell::new(Vec::new()),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Whether a transmission is in progress.
+    pub fn transmitting(&self) -> bool {
+        self.transmission.borrow().is_some()
+    }
+
+    /// Whether a reception is in progress.
+    pub fn receiving(&self) -> bool {
+        self.reception.borrow().is_some()
+    }
+
+    /// The bytes transmitted since the last call.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.output.take()
+    }
+
+    /// Complete the transmission in progress.
+    pub fn transmit_complete(&self) {
+        let (buffer, len) = self
+            .transmission
+            .borrow_mut()
+            .take()
+            .expect("no UART transmission in progress");
+        self.tx_client
+            .map(|client| client.transmitted_buffer(buffer, len, Ok(())));
+    }
+
+    /// Complete transmissions until the client starts no new one.
+    pub fn flush(&self) {
+        while self.transmitting() {
+            self.transmit_complete();
+        }
+    }
+
+    /// Complete the reception in progress with `bytes`, which must fit in
+    /// what it asked for.
+    pub fn receive(&self, bytes: &[u8]) {
+        let (buffer, len) = self
+            .reception
+            .borrow_mut()
+            .take()
+            .expect("no UART reception in progress");
+        assert!(bytes.len() <= len, "received more than asked for");
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        self.rx_client
+            .map(|client| client.received_buffer(buffer, bytes.len(), Ok(()), uart::Error::None));
+    }
+}
+
+impl Default for MockUart<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> uart::Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.transmitting() {
+            return Err((ErrorCode::BUSY, tx_buffer));
+        }
+        if tx_len > tx_buffer.len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        self.output
+            .borrow_mut()
+            .extend_from_slice(&tx_buffer[..tx_len]);
+        self.transmission.replace(Some((tx_buffer, tx_len)));
+        Ok(())
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+}
+
+impl<'a> uart::Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.receiving() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
+        if rx_len > rx_buffer.len() {
+            return Err((ErrorCode::SIZE, rx_buffer));
+        }
+        self.reception.replace(Some((rx_buffer, rx_len)));
+        Ok(())
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aes128_fips197_example() {
+        // FIPS-197, Appendix C.1.
+        let key = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let mut block = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        aes128_encrypt(&key, &mut block);
+        assert_eq!(
+            block,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+    }
+}
@@ -5,6 +5,8 @@
 pub mod alarm;
 pub mod alarm_edge_cases;
 pub mod double_grant_entry;
+#[cfg(any(test, feature = "test_mocks"))]
+pub mod mocks;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
@@ -6,12 +6,18 @@
 //!
 //! `MuxI2C` provides shared access to a single I2C Master Bus for multiple
 //! users. `I2CDevice` provides access to a specific I2C address.
+//!
+//! `RetryI2CDevice` can wrap a device that does not acknowledge transactions
+//! while it is busy, for example during a conversion. It retries the
+//! transactions that were not acknowledged, after a delay that doubles with
+//! every attempt.
 
 use core::cell::Cell;
 
 use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::i2c::{self, Error, I2CClient, I2CHwMasterClient, NoSMBus};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 // `NoSMBus` provides a placeholder for `SMBusMaster` in case the board doesn't have a SMBus
 pub struct MuxI2C<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a> = NoSMBus> {
@@ -463,3 +469,227 @@ impl<'a, I: i2c::I2CMaster<'a>, S: i2c::SMBusMaster<'a>> i2c::SMBusDevice
         }
     }
 }
+
+/// An I2C device that retries the transactions `device` did not acknowledge.
+pub struct RetryI2CDevice<'a, A: Alarm<'a>, D: i2c::I2CDevice> {
+    device: &'a D,
+    alarm: &'a A,
+    /// Retries of a transaction before giving up.
+    retries: usize,
+    /// Delay before the first retry.
+    delay_ms: u32,
+    /// Retries of the current transaction so far.
+    attempt: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Op>,
+    client: OptionalCell<&'a dyn I2CClient>,
+}
+
+impl<'a, A: Alarm<'a>, D: i2c::I2CDevice> RetryI2CDevice<'a, A, D> {
+    pub fn new(
+        device: &'a D,
+        alarm: &'a A,
+        retries: usize,
+        delay_ms: u32,
+    ) -> RetryI2CDevice<'a, A, D> {
+        RetryI2CDevice {
+            device,
+            alarm,
+            retries,
+            delay_ms,
+            attempt: Cell::new(0),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn I2CClient) {
+        self.client.set(client);
+    }
+
+    fn start(
+        &self,
+        operation: Op,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.operation.get() != Op::Idle {
+            return Err((Error::Busy, buffer));
+        }
+        self.attempt.set(0);
+        self.operation.set(operation);
+        self.issue(buffer).map_err(|e| {
+            self.operation.set(Op::Idle);
+            e
+        })
+    }
+
+    fn issue(&self, buffer: &'static mut [u8]) -> Result<(), (Error, &'static mut [u8])> {
+        match self.operation.get() {
+            Op::Write(len) => self.device.write(buffer, len),
+            Op::Read(len) => self.device.read(buffer, len),
+            Op::WriteRead(write_len, read_len) => {
+                self.device.write_read(buffer, write_len, read_len)
+            }
+            _ => Err((Error::NotSupported, buffer)),
+        }
+    }
+
+    fn complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        self.operation.set(Op::Idle);
+        self.client.map(move |client| {
+            client.command_complete(buffer, status);
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, D: i2c::I2CDevice> I2CClient for RetryI2CDevice<'a, A, D> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        let attempt = self.attempt.get();
+        match status {
+            Err(Error::AddressNak) | Err(Error::DataNak) if attempt < self.retries => {
+                self.attempt.set(attempt + 1);
+                self.buffer.replace(buffer);
+                let delay_ms = self.delay_ms.saturating_mul(1 << attempt.min(16));
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay_ms));
+            }
+            _ => self.complete(buffer, status),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, D: i2c::I2CDevice> AlarmClient for RetryI2CDevice<'a, A, D> {
+    fn alarm(&self) {
+        self.buffer.take().map(|buffer| {
+            if let Err((e, buffer)) = self.issue(buffer) {
+                self.complete(buffer, Err(e));
+            }
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, D: i2c::I2CDevice> i2c::I2CDevice for RetryI2CDevice<'a, A, D> {
+    fn enable(&self) {
+        self.device.enable();
+    }
+
+    fn disable(&self) {
+        self.device.disable();
+    }
+
+    fn write_read(
+        &self,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(Op::WriteRead(write_len, read_len), data)
+    }
+
+    fn write(&self, data: &'static mut [u8], len: usize) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(Op::Write(len), data)
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(Op::Read(len), buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::i2c::I2CDevice as _;
+    use kernel::hil::time::Freq1MHz;
+    use kernel::ErrorCode;
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct MockClient {
+        status: Cell<Option<Result<(), Error>>>,
+    }
+
+    impl I2CClient for MockClient {
+        fn command_complete(&self, _buffer: &'static mut [u8], status: Result<(), Error>) {
+            self.status.set(Some(status));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestDevice = RetryI2CDevice<'static, TestAlarm, MockI2c>;
+
+    fn setup() -> (
+        &'static TestDevice,
+        &'static MockI2c,
+        &'static TestAlarm,
+        &'static MockClient,
+    ) {
+        let device = Box::leak(Box::new(MockI2c::new()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let retry = Box::leak(Box::new(RetryI2CDevice::new(device, alarm, 2, 5)));
+        retry.set_client(client);
+        (retry, device, alarm, client)
+    }
+
+    /// Complete the transfer in progress with `status`.
+    fn complete(retry: &TestDevice, device: &MockI2c, status: Result<(), Error>) {
+        match status {
+            Ok(()) => device.complete(retry, &[]),
+            Err(error) => device.fail(retry, error),
+        };
+    }
+
+    /// Fire the backoff alarm.
+    fn backoff(retry: &TestDevice, alarm: &TestAlarm) {
+        assert!(alarm.expire().is_some());
+        retry.alarm();
+    }
+
+    #[test]
+    fn retries_nacks_with_backoff() {
+        let (retry, device, alarm, client) = setup();
+        assert!(retry.write(Box::leak(Box::new([0u8; 4])), 4).is_ok());
+        complete(retry, device, Err(Error::AddressNak));
+        assert_eq!(alarm.dt(), Some(5_000));
+        backoff(retry, alarm);
+        complete(retry, device, Err(Error::DataNak));
+        assert_eq!(alarm.dt(), Some(10_000));
+        assert_eq!(client.status.get(), None);
+        backoff(retry, alarm);
+        complete(retry, device, Ok(()));
+        assert_eq!(device.writes().len(), 3);
+        assert_eq!(client.status.get(), Some(Ok(())));
+
+        // Too many NACKs.
+        assert!(retry.write(Box::leak(Box::new([0u8; 4])), 4).is_ok());
+        for _ in 0..2 {
+            complete(retry, device, Err(Error::AddressNak));
+            backoff(retry, alarm);
+        }
+        complete(retry, device, Err(Error::AddressNak));
+        let status = client.status.get().unwrap();
+        assert_eq!(
+            status.map_err(Into::<ErrorCode>::into),
+            Err(ErrorCode::NOACK)
+        );
+        assert_eq!(device.writes().len(), 6);
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let (retry, device, alarm, client) = setup();
+        assert!(retry.write(Box::leak(Box::new([0u8; 4])), 4).is_ok());
+        complete(retry, device, Err(Error::ArbitrationLost));
+        assert_eq!(alarm.dt(), None);
+        assert_eq!(client.status.get(), Some(Err(Error::ArbitrationLost)));
+        assert_eq!(device.writes().len(), 1);
+    }
+}
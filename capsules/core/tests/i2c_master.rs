@@ -0,0 +1,129 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A process using the I2C master driver with an allow-list of addresses.
+//!
+//! This is an integration test because the driver needs a grant, which this
+//! crate cannot create.
+
+use capsules_core::i2c_master::{I2CMasterDriver, DRIVER_NUM};
+use capsules_core::test::mocks::{I2cTransfer, MockI2cMaster};
+use kernel::capabilities::MemoryAllocationCapability;
+use kernel::hil::i2c::I2CMaster;
+use kernel::platform::SyscallDriverLookup;
+use kernel::process::ShortID;
+use kernel::scheduler::round_robin::{RoundRobinProcessNode, RoundRobinSched};
+use kernel::syscall::{Syscall, SyscallDriver, SyscallReturn};
+use kernel::test::mocks::{self, MockChip, MockResources};
+use kernel::ErrorCode;
+
+const ALLOWED: u8 = 0x29;
+const RESERVED: u8 = 0x6B;
+/// The driver's read command.
+const READ: usize = 2;
+
+struct Capability;
+unsafe impl MemoryAllocationCapability for Capability {}
+
+struct Drivers {
+    i2c_master: &'static I2CMasterDriver<'static, MockI2cMaster<'static>>,
+}
+
+impl SyscallDriverLookup for Drivers {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn SyscallDriver>) -> R,
+    {
+        match driver_num {
+            DRIVER_NUM => f(Some(self.i2c_master)),
+            _ => f(None),
+        }
+    }
+}
+
+fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+#[test]
+fn only_allowed_addresses_reach_the_bus() {
+    let chip = MockChip::new();
+    let (kernel, procs) = mocks::kernel(1);
+    let grant = kernel.create_grant(DRIVER_NUM, &Capability);
+    let processes =
+        mocks::create_processes(chip, kernel, procs, &[("sensor", ShortID::LocallyUnique)]);
+
+    let i2c = leak(MockI2cMaster::new());
+    let i2c_master = leak(I2CMasterDriver::new(
+        i2c,
+        leak([0; capsules_core::i2c_master::BUFFER_LENGTH]),
+        grant,
+        Some(&[ALLOWED]),
+    ));
+    i2c.set_master_client(i2c_master);
+
+    let scheduler = RoundRobinSched::new();
+    let slot = leak(Some(processes[0]));
+    scheduler
+        .processes
+        .push_tail(leak(RoundRobinProcessNode::new(slot)));
+    let resources = MockResources {
+        scheduler,
+        yield_spin_policy: (),
+        drivers: Drivers { i2c_master },
+    };
+
+    // The process shares the start of its memory for the read, then reads
+    // four bytes from each address.
+    let buffer = processes[0].get_addresses().sram_start as *mut u8;
+    chip.syscall(Syscall::ReadWriteAllow {
+        driver_number: DRIVER_NUM,
+        subdriver_number: 1,
+        allow_address: buffer,
+        allow_size: 4,
+    });
+    for addr in [RESERVED, ALLOWED] {
+        chip.syscall(Syscall::Command {
+            driver_number: DRIVER_NUM,
+            subdriver_number: READ,
+            arg0: addr as usize,
+            arg1: 4,
+        });
+    }
+    chip.syscall(Syscall::Yield {
+        which: 1,
+        address: core::ptr::null_mut(),
+    });
+    resources.run_once(kernel, chip);
+    assert_eq!(chip.switches_left(), 0);
+
+    let returns = chip.take_syscall_returns();
+    assert!(
+        matches!(
+            returns[..],
+            [
+                SyscallReturn::AllowReadWriteSuccess(..),
+                SyscallReturn::Failure(ErrorCode::RESERVE),
+                SyscallReturn::Success,
+            ]
+        ),
+        "{:?}",
+        returns
+    );
+    assert_eq!(
+        i2c.transfers(),
+        [(
+            ALLOWED,
+            I2cTransfer {
+                write: Vec::new(),
+                read_len: 4,
+            }
+        )]
+    );
+
+    // The bytes read end up in the process's buffer.
+    i2c.complete(&[1, 2, 3, 4]);
+    let read = unsafe { core::slice::from_raw_parts(buffer, 4) };
+    assert_eq!(read, [1, 2, 3, 4]);
+}
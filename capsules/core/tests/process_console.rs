@@ -0,0 +1,197 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Typing into the process console over a mock UART.
+//!
+//! This is an integration test because the console needs a
+//! `ProcessManagementCapability`, which this crate cannot create.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use capsules_core::process_console::{
+    Command, ConsoleCommand, KernelAddresses, ProcessConsole, COMMAND_BUF_LEN,
+    DEFAULT_COMMAND_HISTORY_LEN, QUEUE_BUF_LEN, READ_BUF_LEN, WRITE_BUF_LEN,
+};
+use capsules_core::test::mocks::{MockAlarm, MockUart};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::process::ProcessPrinterText;
+use kernel::Kernel;
+
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7F;
+const LEFT: &[u8] = b"\x1b[D";
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+type TestConsole =
+    ProcessConsole<'static, DEFAULT_COMMAND_HISTORY_LEN, MockAlarm<'static>, Capability>;
+
+/// A command that records the arguments it is run with.
+struct Record {
+    arguments: RefCell<Vec<String>>,
+}
+
+impl ConsoleCommand for Record {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn help(&self) -> &'static str {
+        "record the arguments"
+    }
+
+    fn execute(&self, arguments: &str, _out: &mut dyn fmt::Write) {
+        self.arguments.borrow_mut().push(arguments.to_string());
+    }
+}
+
+struct Terminal {
+    uart: &'static MockUart<'static>,
+    record: &'static Record,
+    console: &'static TestConsole,
+}
+
+fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+fn buffer(len: usize) -> &'static mut [u8] {
+    Box::leak(vec![0; len].into_boxed_slice())
+}
+
+/// A started console, which has printed its prompt, and the UART to type
+/// into it.
+fn terminal() -> Terminal {
+    let uart: &'static MockUart = leak(MockUart::new());
+    let alarm: &'static MockAlarm = leak(MockAlarm::new());
+    let kernel: &'static Kernel = leak(Kernel::new(&[]));
+    let printer = leak(ProcessPrinterText::new());
+    let addresses = KernelAddresses {
+        stack_start: std::ptr::null(),
+        stack_end: std::ptr::null(),
+        text_start: std::ptr::null(),
+        text_end: std::ptr::null(),
+        read_only_data_start: std::ptr::null(),
+        relocations_start: std::ptr::null(),
+        relocations_end: std::ptr::null(),
+        bss_start: std::ptr::null(),
+        bss_end: std::ptr::null(),
+    };
+    let console: &'static TestConsole = leak(ProcessConsole::new(
+        uart,
+        alarm,
+        printer,
+        buffer(WRITE_BUF_LEN),
+        buffer(READ_BUF_LEN),
+        buffer(QUEUE_BUF_LEN),
+        buffer(COMMAND_BUF_LEN),
+        leak([Command::default(); DEFAULT_COMMAND_HISTORY_LEN]),
+        kernel,
+        addresses,
+        None,
+        Capability,
+    ));
+    uart.set_transmit_client(console);
+    uart.set_receive_client(console);
+    alarm.set_alarm_client(console);
+    let record = leak(Record {
+        arguments: RefCell::new(Vec::new()),
+    });
+    assert_eq!(console.register_command(record), Ok(()));
+
+    assert_eq!(console.start(), Ok(()));
+    alarm.fire();
+    uart.flush();
+    assert_eq!(uart.take_output(), b"tock$ ");
+    Terminal {
+        uart,
+        record,
+        console,
+    }
+}
+
+impl Terminal {
+    /// Type `bytes` one at a time, and return what the console printed.
+    fn type_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        for &byte in bytes {
+            self.uart.receive(&[byte]);
+            self.uart.flush();
+        }
+        assert!(self.uart.receiving());
+        self.uart.take_output()
+    }
+
+    /// The arguments `echo` ran with since the last call.
+    fn arguments(&self) -> Vec<String> {
+        self.record.arguments.take()
+    }
+}
+
+#[test]
+fn backspace_edits_command_line() {
+    let terminal = terminal();
+    terminal.type_bytes(b"echo stopp");
+    terminal.type_bytes(&[BS]);
+    terminal.type_bytes(b" blinl");
+    terminal.type_bytes(&[DEL]);
+    terminal.type_bytes(b"k\r");
+    assert_eq!(terminal.arguments(), ["stop blink"]);
+
+    // Backspace with the cursor at the start of the line does nothing.
+    assert_eq!(terminal.type_bytes(&[BS, BS]), b"");
+    terminal.type_bytes(b"echo list\r");
+    assert_eq!(terminal.arguments(), ["list"]);
+}
+
+#[test]
+fn edits_at_cursor() {
+    let terminal = terminal();
+    // Type "sop", move the cursor after the "s", then insert a "t".
+    terminal.type_bytes(b"echo sop");
+    terminal.type_bytes(LEFT);
+    terminal.type_bytes(LEFT);
+    terminal.type_bytes(b"t\r");
+    assert_eq!(terminal.arguments(), ["stop"]);
+
+    // Type "stxop", move the cursor before the "p", erase "xo" and type
+    // "o".
+    terminal.type_bytes(b"echo stxop");
+    terminal.type_bytes(LEFT);
+    terminal.type_bytes(&[BS, BS]);
+    terminal.type_bytes(b"o\r");
+    assert_eq!(terminal.arguments(), ["stop"]);
+}
+
+#[test]
+fn full_command_line_is_not_overrun() {
+    let terminal = terminal();
+    terminal.type_bytes(b"echo ");
+    terminal.type_bytes(&[b'a'; 2 * COMMAND_BUF_LEN]);
+    terminal.type_bytes(b"\r");
+    let arguments = terminal.arguments();
+    assert_eq!(arguments.len(), 1);
+    assert_eq!(
+        arguments[0],
+        "a".repeat(COMMAND_BUF_LEN - 1 - "echo ".len())
+    );
+}
+
+#[test]
+fn without_line_editing_nothing_is_echoed() {
+    let terminal = terminal();
+    assert_eq!(terminal.type_bytes(b"echo on"), b"echo on");
+
+    terminal.console.set_line_editing(false);
+    terminal.type_bytes(b"\r");
+    assert_eq!(terminal.type_bytes(b"echo offf"), b"");
+    assert_eq!(terminal.type_bytes(&[BS]), b"");
+    // Escape sequences are typed like other bytes.
+    assert_eq!(terminal.type_bytes(LEFT), b"");
+    terminal.type_bytes(b"\r");
+    assert_eq!(terminal.arguments(), ["on", "off\x1b[D"]);
+}
@@ -0,0 +1,422 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Waveform capture with an ADC's high-speed mode.
+//!
+//! A capture collects a fixed number of samples at a fixed rate and hands
+//! the whole buffer to the client in one callback. The ADC fills buffers in
+//! the background, with DMA on chips that have it, so the sample rate is
+//! not limited by interrupt latency.
+//!
+//! The ADC's high-speed mode always needs two buffers, so that it can move
+//! to the second as soon as the first is full. This capsule owns one spare
+//! buffer for that:
+//!
+//! - `capture` fills the client's buffer once, and the spare is only there
+//!   to keep the ADC busy until sampling is stopped.
+//! - `capture_continuous` captures back to back. While the client handles
+//!   one buffer the ADC fills the other, and no samples are dropped as long
+//!   as the client gives each buffer back with `provide_buffer` before the
+//!   other one is full.
+//!
+//! This capsule uses the ADC directly, and so needs an ADC that is not
+//! shared with other capsules.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let channel = static_init!(
+//!     sam4l::adc::AdcChannel,
+//!     sam4l::adc::AdcChannel::new(sam4l::adc::Channel::AD1)
+//! );
+//! let capture = components::adc_capture::AdcCaptureComponent::new(
+//!     &peripherals.adc,
+//!     channel,
+//!     44100,
+//! )
+//! .finalize(components::adc_capture_component_static!(sam4l::adc::Adc));
+//! capture.set_client(recorder);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::adc;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the spare buffer. Captures can not be longer than this.
+pub const SPARE_SAMPLES: usize = 512;
+
+pub trait AdcCaptureClient {
+    /// A capture is complete, and `buffer` holds `length` consecutive
+    /// samples.
+    ///
+    /// In continuous mode the next capture is already going, and the buffer
+    /// must be given back with `provide_buffer` before it is done.
+    fn capture_done(&self, buffer: &'static mut [u16], length: usize);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Single,
+    Continuous,
+}
+
+pub struct AdcCapture<'a, A: adc::AdcHighSpeed<'a>> {
+    adc: &'a A,
+    channel: &'a A::Channel,
+    frequency: u32,
+    client: OptionalCell<&'a dyn AdcCaptureClient>,
+    state: Cell<State>,
+    /// Number of samples in each capture.
+    length: Cell<usize>,
+    spare: TakeCell<'static, [u16]>,
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>> AdcCapture<'a, A> {
+    pub fn new(
+        adc: &'a A,
+        channel: &'a A::Channel,
+        frequency: u32,
+        spare: &'static mut [u16],
+    ) -> AdcCapture<'a, A> {
+        AdcCapture {
+            adc,
+            channel,
+            frequency,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            length: Cell::new(0),
+            spare: TakeCell::new(spare),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn AdcCaptureClient) {
+        self.client.set(client);
+    }
+
+    /// Capture `length` samples into `buffer`.
+    pub fn capture(
+        &self,
+        buffer: &'static mut [u16],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u16])> {
+        self.start(State::Single, buffer, length)
+    }
+
+    /// Capture `length` samples at a time until `stop` is called, starting
+    /// with `buffer`.
+    pub fn capture_continuous(
+        &self,
+        buffer: &'static mut [u16],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u16])> {
+        self.start(State::Continuous, buffer, length)
+    }
+
+    /// Give back a buffer from `capture_done` to carry on capturing.
+    pub fn provide_buffer(
+        &self,
+        buffer: &'static mut [u16],
+    ) -> Result<(), (ErrorCode, &'static mut [u16])> {
+        if self.state.get() != State::Continuous {
+            return Err((ErrorCode::OFF, buffer));
+        }
+        self.adc.provide_buffer(buffer, self.length.get())
+    }
+
+    /// Stop capturing. Returns the client's buffer, if the capsule was
+    /// holding it.
+    pub fn stop(&self) -> Result<Option<&'static mut [u16]>, ErrorCode> {
+        if self.state.get() == State::Idle {
+            return Err(ErrorCode::OFF);
+        }
+        self.state.set(State::Idle);
+        Ok(self.stop_sampling())
+    }
+
+    fn start(
+        &self,
+        state: State,
+        buffer: &'static mut [u16],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u16])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if length == 0 || length > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        let spare = match self.spare.take() {
+            Some(spare) if spare.len() >= length => spare,
+            Some(spare) => {
+                self.spare.replace(spare);
+                return Err((ErrorCode::SIZE, buffer));
+            }
+            None => return Err((ErrorCode::NOMEM, buffer)),
+        };
+
+        self.length.set(length);
+        match self
+            .adc
+            .sample_highspeed(self.channel, self.frequency, buffer, length, spare, length)
+        {
+            Ok(()) => {
+                self.state.set(state);
+                Ok(())
+            }
+            Err((e, buffer, spare)) => {
+                self.spare.replace(spare);
+                Err((e, buffer))
+            }
+        }
+    }
+
+    /// Stop the ADC and take back the buffers it holds. The spare buffer is
+    /// kept, and the other one returned.
+    fn stop_sampling(&self) -> Option<&'static mut [u16]> {
+        let _ = self.adc.stop_sampling();
+        let (buffer1, buffer2) = self.adc.retrieve_buffers().unwrap_or((None, None));
+        let mut client_buffer = None;
+        for buffer in [buffer1, buffer2].into_iter().flatten() {
+            if self.spare.is_none() {
+                self.spare.replace(buffer);
+            } else {
+                client_buffer = Some(buffer);
+            }
+        }
+        client_buffer
+    }
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>> adc::HighSpeedClient for AdcCapture<'a, A> {
+    fn samples_ready(&self, buffer: &'static mut [u16], length: usize) {
+        match self.state.get() {
+            State::Idle => {
+                // Sampling was stopped while this buffer was being filled.
+                if self.spare.is_none() {
+                    self.spare.replace(buffer);
+                } else {
+                    self.client
+                        .map(|client| client.capture_done(buffer, length));
+                }
+            }
+            State::Single => {
+                self.state.set(State::Idle);
+                // The ADC only holds the spare by now, which is kept.
+                self.stop_sampling();
+                self.client
+                    .map(|client| client.capture_done(buffer, length));
+            }
+            State::Continuous => {
+                self.client
+                    .map(|client| client.capture_done(buffer, length));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Double-buffered ADC producing a ramp, one sample per `tick`.
+    struct RampAdc<'a> {
+        client: OptionalCell<&'a dyn adc::HighSpeedClient>,
+        running: Cell<bool>,
+        next_sample: Cell<u16>,
+        position: Cell<usize>,
+        current: TakeCell<'static, [u16]>,
+        current_length: Cell<usize>,
+        queued: TakeCell<'static, [u16]>,
+        queued_length: Cell<usize>,
+        dropped: Cell<usize>,
+    }
+
+    impl<'a> RampAdc<'a> {
+        fn new() -> Self {
+            RampAdc {
+                client: OptionalCell::empty(),
+                running: Cell::new(false),
+                next_sample: Cell::new(0),
+                position: Cell::new(0),
+                current: TakeCell::empty(),
+                current_length: Cell::new(0),
+                queued: TakeCell::empty(),
+                queued_length: Cell::new(0),
+                dropped: Cell::new(0),
+            }
+        }
+
+        fn tick(&self) {
+            if !self.running.get() {
+                return;
+            }
+            let sample = self.next_sample.get();
+            self.next_sample.set(sample + 1);
+            if self.current.is_none() {
+                self.dropped.set(self.dropped.get() + 1);
+                return;
+            }
+            let position = self.position.get();
+            self.current.map(|buffer| buffer[position] = sample);
+            self.position.set(position + 1);
+            if position + 1 == self.current_length.get() {
+                let full = self.current.take().unwrap();
+                let length = self.current_length.get();
+                // Move on to the queued buffer before reporting this one.
+                self.position.set(0);
+                self.queued
+                    .take()
+                    .map(|buffer| self.current.replace(buffer));
+                self.current_length.set(self.queued_length.get());
+                self.client.map(|client| client.samples_ready(full, length));
+            }
+        }
+    }
+
+    impl<'a> adc::Adc<'a> for RampAdc<'a> {
+        type Channel = ();
+
+        fn sample(&self, _channel: &()) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn sample_continuous(&self, _channel: &(), _frequency: u32) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn stop_sampling(&self) -> Result<(), ErrorCode> {
+            self.running.set(false);
+            Ok(())
+        }
+
+        fn get_resolution_bits(&self) -> usize {
+            16
+        }
+
+        fn get_voltage_reference_mv(&self) -> Option<usize> {
+            None
+        }
+
+        fn set_client(&self, _client: &'a dyn adc::Client) {}
+    }
+
+    impl<'a> adc::AdcHighSpeed<'a> for RampAdc<'a> {
+        fn sample_highspeed(
+            &self,
+            _channel: &(),
+            _frequency: u32,
+            buffer1: &'static mut [u16],
+            length1: usize,
+            buffer2: &'static mut [u16],
+            length2: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u16], &'static mut [u16])> {
+            self.current.replace(buffer1);
+            self.current_length.set(length1);
+            self.queued.replace(buffer2);
+            self.queued_length.set(length2);
+            self.position.set(0);
+            self.running.set(true);
+            Ok(())
+        }
+
+        fn provide_buffer(
+            &self,
+            buffer: &'static mut [u16],
+            length: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u16])> {
+            if self.current.is_none() {
+                self.current.replace(buffer);
+                self.current_length.set(length);
+            } else {
+                self.queued.replace(buffer);
+                self.queued_length.set(length);
+            }
+            Ok(())
+        }
+
+        fn retrieve_buffers(
+            &self,
+        ) -> Result<(Option<&'static mut [u16]>, Option<&'static mut [u16]>), ErrorCode> {
+            Ok((self.current.take(), self.queued.take()))
+        }
+
+        fn set_highspeed_client(&self, client: &'a dyn adc::HighSpeedClient) {
+            self.client.set(client);
+        }
+    }
+
+    struct Recorder {
+        samples: core::cell::RefCell<Vec<u16>>,
+        buffer: TakeCell<'static, [u16]>,
+    }
+
+    impl AdcCaptureClient for Recorder {
+        fn capture_done(&self, buffer: &'static mut [u16], length: usize) {
+            self.samples
+                .borrow_mut()
+                .extend_from_slice(&buffer[..length]);
+            self.buffer.replace(buffer);
+        }
+    }
+
+    fn setup() -> (
+        &'static RampAdc<'static>,
+        &'static AdcCapture<'static, RampAdc<'static>>,
+        &'static Recorder,
+    ) {
+        let adc = Box::leak(Box::new(RampAdc::new()));
+        let spare = Box::leak(Box::new([0u16; 8]));
+        let capture = Box::leak(Box::new(AdcCapture::new(adc, &(), 1000, spare)));
+        let recorder = Box::leak(Box::new(Recorder {
+            samples: Default::default(),
+            buffer: TakeCell::empty(),
+        }));
+        adc::AdcHighSpeed::set_highspeed_client(adc, capture);
+        capture.set_client(recorder);
+        (adc, capture, recorder)
+    }
+
+    #[test]
+    fn single_capture_returns_the_whole_buffer() {
+        let (adc, capture, recorder) = setup();
+        let buffer = Box::leak(Box::new([0u16; 8]));
+
+        assert!(capture.capture(buffer, 6).is_ok());
+        for _ in 0..10 {
+            adc.tick();
+        }
+        assert_eq!(*recorder.samples.borrow(), [0, 1, 2, 3, 4, 5]);
+        assert!(!adc.running.get());
+        // The spare is back, so another capture can start.
+        let buffer = recorder.buffer.take().unwrap();
+        assert!(capture.capture(buffer, 4).is_ok());
+    }
+
+    #[test]
+    fn back_to_back_captures_drop_no_samples() {
+        let (adc, capture, recorder) = setup();
+        let buffer = Box::leak(Box::new([0u16; 8]));
+
+        assert!(capture.capture_continuous(buffer, 4).is_ok());
+        for _ in 0..20 {
+            adc.tick();
+            if let Some(buffer) = recorder.buffer.take() {
+                assert!(capture.provide_buffer(buffer).is_ok());
+            }
+        }
+        let expected: Vec<u16> = (0..20).collect();
+        assert_eq!(*recorder.samples.borrow(), expected);
+        assert_eq!(adc.dropped.get(), 0);
+
+        assert!(capture.stop().is_ok());
+        assert_eq!(capture.stop().err(), Some(ErrorCode::OFF));
+    }
+}
@@ -0,0 +1,242 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for NTC thermistors read by an ADC.
+//!
+//! The thermistor forms a voltage divider with a reference resistor, with
+//! the ADC channel at their junction and the divider powered from the ADC
+//! voltage reference. The driver computes the resistance of the thermistor
+//! from the ADC sample, and then the temperature with the Steinhart-Hart
+//! equation:
+//!
+//! ```text
+//! 1/T = A + B ln(R) + C ln(R)^3
+//! ```
+//!
+//! The computation only uses integer arithmetic. The coefficients are given
+//! in units of 1e-15, see [SteinhartHart], and the temperature is resolved
+//! to tenths of degrees. It is reported through the `TemperatureDriver` HIL,
+//! in hundredths of degrees as the HIL requires. The resistance of the last
+//! reading is available to userspace through the temperature driver, to
+//! diagnose the wiring or the coefficients of a thermistor.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let thermistor = components::adc_temperature::NtcThermistorComponent::new(
+//!     adc_mux,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput4),
+//!     capsules_extra::adc_temperature::Divider::ThermistorLow,
+//!     10_000,
+//!     capsules_extra::adc_temperature::SteinhartHart {
+//!         a: 1_009_249_522_000,
+//!         b: 237_840_544_400,
+//!         c: 201_920_270,
+//!     },
+//! )
+//! .finalize(components::ntc_thermistor_component_static!(
+//!     nrf52840::adc::Adc
+//! ));
+//! let temperature = components::temperature::TemperatureComponent::new(
+//!     board_kernel,
+//!     capsules_extra::temperature::DRIVER_NUM,
+//!     thermistor,
+//! )
+//! .finalize(components::temperature_component_static!());
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::adc;
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The scale of the Steinhart-Hart coefficients.
+pub const COEFFICIENT_SCALE: i64 = 1_000_000_000_000_000;
+
+/// The scale of the fixed-point logarithms.
+const LN_SCALE: i64 = 1_000_000;
+
+/// The Steinhart-Hart coefficients of a thermistor, in units of 1e-15, for
+/// resistances in ohms and temperatures in kelvin. A typical 10 kΩ
+/// thermistor has A = 1.009249522e-3, B = 2.378405444e-4 and
+/// C = 2.019202697e-7, which are 1_009_249_522_000, 237_840_544_400 and
+/// 201_920_270.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SteinhartHart {
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
+}
+
+/// Where the thermistor is in the voltage divider.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Divider {
+    /// The thermistor is between the ADC channel and ground.
+    ThermistorLow,
+    /// The thermistor is between the voltage reference and the ADC channel.
+    ThermistorHigh,
+}
+
+/// The resistance of the thermistor for an ADC `sample`, or `None` if the
+/// thermistor is open or shorted.
+fn resistance_ohms(sample: u16, divider: Divider, reference_ohms: u32) -> Option<u32> {
+    // Samples are left-justified in the u16, so full scale is 65536.
+    let low = sample as u64;
+    let high = 65536 - low;
+    let (numerator, denominator) = match divider {
+        Divider::ThermistorLow => (low, high),
+        Divider::ThermistorHigh => (high, low),
+    };
+    if numerator == 0 || denominator == 0 {
+        return None;
+    }
+    u32::try_from(reference_ohms as u64 * numerator / denominator).ok()
+}
+
+/// The natural logarithm of `value`, in units of 1e-6.
+fn ln_micro(value: u32) -> i64 {
+    let exponent = 31 - value.leading_zeros();
+    // The mantissa is in [1, 2), with 30 fractional bits. Each squaring
+    // yields the next bit of its logarithm.
+    let mut mantissa = ((value as u64) << 30) >> exponent;
+    let mut log2 = (exponent as u64) << 30;
+    for bit in (0..30).rev() {
+        mantissa = (mantissa * mantissa) >> 30;
+        if mantissa >= 2 << 30 {
+            mantissa >>= 1;
+            log2 |= 1 << bit;
+        }
+    }
+    // ln(x) = log2(x) * ln(2), with ln(2) in units of 1e-8.
+    ((log2 * 69_314_718) >> 30) as i64 / 100
+}
+
+/// The temperature of a thermistor of `resistance_ohms`, in tenths of
+/// degrees Celsius, or `None` if it is out of range of the coefficients.
+fn temperature_decidegrees(resistance_ohms: u32, coefficients: SteinhartHart) -> Option<i32> {
+    if resistance_ohms == 0 {
+        return None;
+    }
+    let ln = ln_micro(resistance_ohms);
+    let ln3 = ln * ln / LN_SCALE * ln / LN_SCALE;
+    let inverse = coefficients
+        .a
+        .checked_add(coefficients.b.checked_mul(ln)? / LN_SCALE)?
+        .checked_add(coefficients.c.checked_mul(ln3)? / LN_SCALE)?;
+    if inverse <= 0 {
+        return None;
+    }
+    let centikelvin = 100 * COEFFICIENT_SCALE / inverse;
+    i32::try_from((centikelvin - 27_315 + 5).div_euclid(10)).ok()
+}
+
+pub struct NtcThermistor<'a, A: adc::AdcChannel<'a>> {
+    adc: &'a A,
+    divider: Divider,
+    reference_ohms: u32,
+    coefficients: SteinhartHart,
+    busy: Cell<bool>,
+    resistance: OptionalCell<u32>,
+    client: OptionalCell<&'a dyn TemperatureClient>,
+}
+
+impl<'a, A: adc::AdcChannel<'a>> NtcThermistor<'a, A> {
+    /// `reference_ohms` is the resistance of the other resistor of the
+    /// divider.
+    pub fn new(
+        adc: &'a A,
+        divider: Divider,
+        reference_ohms: u32,
+        coefficients: SteinhartHart,
+    ) -> NtcThermistor<'a, A> {
+        NtcThermistor {
+            adc,
+            divider,
+            reference_ohms,
+            coefficients,
+            busy: Cell::new(false),
+            resistance: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>> TemperatureDriver<'a> for NtcThermistor<'a, A> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.adc.sample()?;
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn resistance_ohms(&self) -> Result<u32, ErrorCode> {
+        self.resistance.extract().ok_or(ErrorCode::FAIL)
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>> adc::Client for NtcThermistor<'a, A> {
+    fn sample_ready(&self, sample: u16) {
+        self.busy.set(false);
+        let resistance = resistance_ohms(sample, self.divider, self.reference_ohms);
+        self.resistance.insert(resistance);
+        let result = resistance
+            .and_then(|resistance| temperature_decidegrees(resistance, self.coefficients))
+            .map(|decidegrees| decidegrees * 10)
+            .ok_or(ErrorCode::FAIL);
+        self.client.map(|client| client.callback(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTC_10K: SteinhartHart = SteinhartHart {
+        a: 1_009_249_522_000,
+        b: 237_840_544_400,
+        c: 201_920_270,
+    };
+
+    #[test]
+    fn resistance_from_the_divider() {
+        assert_eq!(
+            resistance_ohms(32768, Divider::ThermistorLow, 10_000),
+            Some(10_000)
+        );
+        assert_eq!(
+            resistance_ohms(49152, Divider::ThermistorLow, 10_000),
+            Some(30_000)
+        );
+        assert_eq!(
+            resistance_ohms(49152, Divider::ThermistorHigh, 30_000),
+            Some(10_000)
+        );
+        // An open or shorted thermistor has no resistance.
+        assert_eq!(resistance_ohms(0, Divider::ThermistorLow, 10_000), None);
+        assert_eq!(resistance_ohms(0, Divider::ThermistorHigh, 10_000), None);
+    }
+
+    #[test]
+    fn steinhart_hart_in_fixed_point() {
+        assert_eq!(ln_micro(1), 0);
+        assert_eq!(ln_micro(2), 693_147);
+        assert_eq!(ln_micro(10_000), 9_210_340);
+        assert_eq!(ln_micro(u32::MAX), 22_180_709);
+
+        // 24.68, -34.62, 94.67 and -48.54 °C in floating point.
+        assert_eq!(temperature_decidegrees(10_000, NTC_10K), Some(247));
+        assert_eq!(temperature_decidegrees(32_650, NTC_10K), Some(-35));
+        assert_eq!(temperature_decidegrees(1_000, NTC_10K), Some(947));
+        assert_eq!(temperature_decidegrees(336_450, NTC_10K), Some(-485));
+        assert_eq!(temperature_decidegrees(0, NTC_10K), None);
+    }
+}
@@ -0,0 +1,501 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Texas Instruments ADS1115 16-bit, 4-channel I2C ADC.
+//!
+//! <https://www.ti.com/lit/ds/symlink/ads1115.pdf>
+//!
+//! The ADS1115 implements `hil::adc::AdcChannel`, so it can be used by any
+//! capsule written for an on-chip ADC channel. The input, the programmable
+//! gain amplifier's full-scale range and the data rate are chosen with
+//! [`Ads1115::configure`].
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! `sample` starts a single-shot conversion, after which the device powers
+//! down. `sample_continuous` puts the device in continuous conversion mode at
+//! the configured data rate until `stop_sampling`.
+//!
+//! The ALERT/RDY pin is used as a conversion-ready signal: with the MSB of
+//! the Hi_thresh register set and the MSB of the Lo_thresh register cleared,
+//! the device pulls the pin low at the end of every conversion. The driver
+//! writes these thresholds before its first conversion and reads the result
+//! on each falling edge, so the comparator cannot be used at the same time.
+//!
+//! The device returns signed results. As `hil::adc` samples are unsigned and
+//! left-justified, a single-ended input gives 15-bit samples where 0 is 0 V
+//! and 0xFFFE the full-scale voltage, and a differential input gives 16-bit
+//! offset binary samples where 0x8000 is 0 V. `get_voltage_reference_mv`
+//! returns the full-scale voltage.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ads1115 = components::ads1115::Ads1115Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ads1115::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[ADS1115_ALERT_RDY],
+//! )
+//! .finalize(components::ads1115_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ads1115.configure(
+//!     capsules_extra::ads1115::Input::Ain0,
+//!     capsules_extra::ads1115::FullScale::Mv4096,
+//!     capsules_extra::ads1115::DataRate::Sps128,
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::adc;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the ADS1115 with ADDR connected to ground.
+pub const BASE_ADDR: u8 = 0x48;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 3;
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+const REG_LO_THRESH: u8 = 0x02;
+const REG_HI_THRESH: u8 = 0x03;
+
+// Config register fields. The comparator is left in its default traditional,
+// active low, non-latching mode and asserts after one conversion.
+const CONFIG_OS: u16 = 1 << 15;
+const CONFIG_MUX_SHIFT: u16 = 12;
+const CONFIG_PGA_SHIFT: u16 = 9;
+const CONFIG_MODE_SINGLE_SHOT: u16 = 1 << 8;
+const CONFIG_DR_SHIFT: u16 = 5;
+
+/// The input multiplexer configurations: the four inputs against ground and
+/// four differential pairs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    /// AIN0 - AIN1
+    Ain0Ain1 = 0,
+    /// AIN0 - AIN3
+    Ain0Ain3 = 1,
+    /// AIN1 - AIN3
+    Ain1Ain3 = 2,
+    /// AIN2 - AIN3
+    Ain2Ain3 = 3,
+    Ain0 = 4,
+    Ain1 = 5,
+    Ain2 = 6,
+    Ain3 = 7,
+}
+
+impl Input {
+    fn is_differential(self) -> bool {
+        (self as u16) < 4
+    }
+}
+
+/// The full-scale range of the programmable gain amplifier, which should be
+/// above the largest input voltage. Inputs are still limited to the supply
+/// voltage.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FullScale {
+    Mv6144 = 0,
+    Mv4096 = 1,
+    Mv2048 = 2,
+    Mv1024 = 3,
+    Mv512 = 4,
+    Mv256 = 5,
+}
+
+impl FullScale {
+    pub fn millivolts(self) -> usize {
+        match self {
+            FullScale::Mv6144 => 6144,
+            FullScale::Mv4096 => 4096,
+            FullScale::Mv2048 => 2048,
+            FullScale::Mv1024 => 1024,
+            FullScale::Mv512 => 512,
+            FullScale::Mv256 => 256,
+        }
+    }
+}
+
+/// Conversions per second.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DataRate {
+    Sps8 = 0,
+    Sps16 = 1,
+    Sps32 = 2,
+    Sps64 = 3,
+    Sps128 = 4,
+    Sps250 = 5,
+    Sps475 = 6,
+    Sps860 = 7,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    WriteLoThresh,
+    WriteHiThresh,
+    WriteConfig,
+    /// Waiting for the ALERT/RDY pin.
+    Converting,
+    ReadConversion,
+    PowerDown,
+}
+
+pub struct Ads1115<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    ready_pin: &'a G,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn adc::Client>,
+    state: Cell<State>,
+    input: Cell<Input>,
+    full_scale: Cell<FullScale>,
+    data_rate: Cell<DataRate>,
+    continuous: Cell<bool>,
+    /// `stop_sampling` was called during a transfer.
+    stopping: Cell<bool>,
+    thresholds_written: Cell<bool>,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Ads1115<'a, I, G> {
+    pub fn new(i2c: &'a I, ready_pin: &'a G, buffer: &'static mut [u8]) -> Ads1115<'a, I, G> {
+        Ads1115 {
+            i2c,
+            ready_pin,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            input: Cell::new(Input::Ain0),
+            full_scale: Cell::new(FullScale::Mv2048),
+            data_rate: Cell::new(DataRate::Sps128),
+            continuous: Cell::new(false),
+            stopping: Cell::new(false),
+            thresholds_written: Cell::new(false),
+        }
+    }
+
+    /// Choose the input, full-scale range and data rate of the following
+    /// conversions. Defaults to AIN0, 2.048 V and 128 samples per second.
+    pub fn configure(
+        &self,
+        input: Input,
+        full_scale: FullScale,
+        data_rate: DataRate,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.input.set(input);
+        self.full_scale.set(full_scale);
+        self.data_rate.set(data_rate);
+        Ok(())
+    }
+
+    fn config(&self, continuous: bool) -> u16 {
+        let mode = if continuous {
+            0
+        } else {
+            CONFIG_OS | CONFIG_MODE_SINGLE_SHOT
+        };
+        mode | (self.input.get() as u16) << CONFIG_MUX_SHIFT
+            | (self.full_scale.get() as u16) << CONFIG_PGA_SHIFT
+            | (self.data_rate.get() as u16) << CONFIG_DR_SHIFT
+    }
+
+    fn write_register(&self, register: u8, value: u16, next: State) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            buffer[1..3].copy_from_slice(&value.to_be_bytes());
+            self.state.set(next);
+            self.i2c.write(buffer, 3).map_err(|(error, buffer)| {
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+                error.into()
+            })
+        })
+    }
+
+    fn start(&self, continuous: bool) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.continuous.set(continuous);
+        self.stopping.set(false);
+        self.i2c.enable();
+        self.ready_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        let result = if self.thresholds_written.get() {
+            self.write_register(REG_CONFIG, self.config(continuous), State::WriteConfig)
+        } else {
+            self.write_register(REG_LO_THRESH, 0x0000, State::WriteLoThresh)
+        };
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    /// Return to idle, leaving the device powered down.
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.ready_pin.disable_interrupts();
+        self.i2c.disable();
+    }
+
+    /// Stop after a transfer completes, powering down the device if it is
+    /// converting continuously.
+    fn stop(&self) {
+        self.stopping.set(false);
+        if !self.continuous.get()
+            || self
+                .write_register(
+                    REG_CONFIG,
+                    self.config(false) & !CONFIG_OS,
+                    State::PowerDown,
+                )
+                .is_err()
+        {
+            self.finish();
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Ads1115<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let sample = i16::from_be_bytes([buffer[0], buffer[1]]);
+        self.buffer.replace(buffer);
+        let state = self.state.get();
+
+        if status.is_err() {
+            // Leave the device powered down if possible, but report nothing:
+            // `hil::adc` has no way to report a failed sample.
+            if state == State::PowerDown {
+                self.finish();
+            } else {
+                self.stop();
+            }
+            return;
+        }
+        if self.stopping.get() && state != State::PowerDown {
+            self.stop();
+            return;
+        }
+
+        let result = match state {
+            State::WriteLoThresh => {
+                self.write_register(REG_HI_THRESH, 0x8000, State::WriteHiThresh)
+            }
+            State::WriteHiThresh => {
+                self.thresholds_written.set(true);
+                let config = self.config(self.continuous.get());
+                self.write_register(REG_CONFIG, config, State::WriteConfig)
+            }
+            State::WriteConfig => {
+                self.state.set(State::Converting);
+                Ok(())
+            }
+            State::ReadConversion => {
+                if self.continuous.get() {
+                    self.state.set(State::Converting);
+                } else {
+                    self.finish();
+                }
+                let sample = if self.input.get().is_differential() {
+                    sample as u16 ^ 0x8000
+                } else {
+                    (sample.max(0) as u16) << 1
+                };
+                self.client.map(|client| client.sample_ready(sample));
+                Ok(())
+            }
+            State::PowerDown => {
+                self.finish();
+                Ok(())
+            }
+            State::Idle | State::Converting => Ok(()),
+        };
+        if result.is_err() {
+            self.finish();
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Ads1115<'a, I, G> {
+    fn fired(&self) {
+        // A single-shot conversion may finish before the config write is
+        // acknowledged at the highest data rates.
+        if !matches!(self.state.get(), State::Converting | State::WriteConfig) {
+            return;
+        }
+        if let Some(buffer) = self.buffer.take() {
+            buffer[0] = REG_CONVERSION;
+            self.state.set(State::ReadConversion);
+            if let Err((_error, buffer)) = self.i2c.write_read(buffer, 1, 2) {
+                self.buffer.replace(buffer);
+                self.stop();
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> adc::AdcChannel<'a> for Ads1115<'a, I, G> {
+    fn sample(&self) -> Result<(), ErrorCode> {
+        self.start(false)
+    }
+
+    fn sample_continuous(&self) -> Result<(), ErrorCode> {
+        self.start(true)
+    }
+
+    fn stop_sampling(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle | State::PowerDown => {}
+            State::Converting => {
+                self.ready_pin.disable_interrupts();
+                self.stop();
+            }
+            _ => self.stopping.set(true),
+        }
+        Ok(())
+    }
+
+    fn get_resolution_bits(&self) -> usize {
+        if self.input.get().is_differential() {
+            16
+        } else {
+            15
+        }
+    }
+
+    fn get_voltage_reference_mv(&self) -> Option<usize> {
+        Some(self.full_scale.get().millivolts())
+    }
+
+    fn set_client(&self, client: &'a dyn adc::Client) {
+        self.client.set(client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use core::cell::RefCell;
+    use kernel::hil::adc::AdcChannel;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        samples: RefCell<Vec<u16>>,
+    }
+
+    impl adc::Client for Client {
+        fn sample_ready(&self, sample: u16) {
+            self.samples.borrow_mut().push(sample);
+        }
+    }
+
+    type Device = Ads1115<'static, MockI2c, MockPin<'static>>;
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockPin<'static>,
+        &'static Device,
+        &'static Client,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let ready: &'static MockPin<'static> = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let ads1115 = Box::leak(Box::new(Ads1115::new(
+            i2c,
+            ready,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        ads1115.set_client(client);
+        (i2c, ready, ads1115, client)
+    }
+
+    #[test]
+    fn single_shot_waits_for_ready() {
+        let (i2c, ready, ads1115, client) = setup();
+        assert_eq!(
+            ads1115.configure(Input::Ain1, FullScale::Mv4096, DataRate::Sps860),
+            Ok(())
+        );
+        assert_eq!(ads1115.get_resolution_bits(), 15);
+        assert_eq!(ads1115.get_voltage_reference_mv(), Some(4096));
+
+        assert_eq!(ads1115.sample(), Ok(()));
+        assert_eq!(ads1115.sample(), Err(ErrorCode::BUSY));
+        assert!(matches!(
+            ready.interrupt_edge(),
+            Some(gpio::InterruptEdge::FallingEdge)
+        ));
+        // The first conversion sets up ALERT/RDY as a ready signal.
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_LO_THRESH, 0x00, 0x00]);
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_HI_THRESH, 0x80, 0x00]);
+        // OS, AIN1, +-4.096 V, single-shot, 860 SPS.
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_CONFIG, 0xD3, 0xE0]);
+        assert!(!i2c.busy());
+
+        gpio::Client::fired(ads1115);
+        assert_eq!(i2c.complete(ads1115, &[0x12, 0x34]), [REG_CONVERSION]);
+        assert_eq!(*client.samples.borrow(), [0x2468]);
+        assert!(!ready.interrupts_enabled());
+
+        // Negative single-ended readings are clipped to 0 V, and the
+        // thresholds are only written once.
+        assert_eq!(ads1115.sample(), Ok(()));
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_CONFIG, 0xD3, 0xE0]);
+        gpio::Client::fired(ads1115);
+        assert_eq!(i2c.complete(ads1115, &[0xFF, 0xF0]), [REG_CONVERSION]);
+        assert_eq!(*client.samples.borrow(), [0x2468, 0]);
+    }
+
+    #[test]
+    fn continuous_differential_until_stopped() {
+        let (i2c, ready, ads1115, client) = setup();
+        assert_eq!(
+            ads1115.configure(Input::Ain2Ain3, FullScale::Mv256, DataRate::Sps8),
+            Ok(())
+        );
+        assert_eq!(ads1115.get_resolution_bits(), 16);
+
+        assert_eq!(ads1115.sample_continuous(), Ok(()));
+        i2c.complete(ads1115, &[]);
+        i2c.complete(ads1115, &[]);
+        // AIN2 - AIN3, +-0.256 V, continuous, 8 SPS.
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_CONFIG, 0x3A, 0x00]);
+
+        for reading in [[0x00, 0x10], [0xFF, 0xF0]] {
+            gpio::Client::fired(ads1115);
+            assert_eq!(i2c.complete(ads1115, &reading), [REG_CONVERSION]);
+        }
+        assert_eq!(*client.samples.borrow(), [0x8010, 0x7FF0]);
+
+        // Stopping mid-read drops the sample and powers the device down.
+        gpio::Client::fired(ads1115);
+        assert_eq!(ads1115.stop_sampling(), Ok(()));
+        assert_eq!(i2c.complete(ads1115, &[0x12, 0x34]), [REG_CONVERSION]);
+        assert_eq!(i2c.complete(ads1115, &[]), [REG_CONFIG, 0x3B, 0x00]);
+        assert_eq!(client.samples.borrow().len(), 2);
+        assert!(!ready.interrupts_enabled());
+        assert_eq!(
+            ads1115.configure(Input::Ain0, FullScale::Mv2048, DataRate::Sps128),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,326 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the AHT20 temperature and humidity sensor.
+//!
+//! <https://asairsensors.com/wp-content/uploads/2021/09/Data-Sheet-AHT20-Humidity-and-Temperature-Sensor-ASAIR-V1.0.03.pdf>
+//!
+//! The sensor is calibrated with the initialization command the first time
+//! a reading is requested. Each measurement then takes 80 ms, and returns
+//! the temperature and humidity together, so a temperature and a humidity
+//! request made at the same time share a measurement.
+//!
+//! The AHT20 sends a CRC after the measurement, which is optional on the
+//! AHT20 but expected by drivers of the AHT21 and AHT25. Enable the
+//! `aht20_crc` feature of this crate from the board to read and check it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let aht20 = components::aht20::Aht20Component::new(
+//!     sensors_i2c_bus,
+//!     capsules_extra::aht20::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::aht20_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c;
+use kernel::hil::sensors::{HumidityClient, HumidityDriver, TemperatureClient, TemperatureDriver};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const BASE_ADDR: u8 = 0x38;
+
+/// Whether to read and check the CRC after each measurement.
+const CHECK_CRC: bool = cfg!(feature = "aht20_crc");
+
+/// Length of a measurement, including the CRC.
+pub const BUFFER_SIZE: usize = 7;
+
+const CMD_INITIALIZE: [u8; 3] = [0xBE, 0x08, 0x00];
+const CMD_MEASURE: [u8; 3] = [0xAC, 0x33, 0x00];
+
+const STATUS_BUSY: u8 = 1 << 7;
+const STATUS_CALIBRATED: u8 = 1 << 3;
+
+const INITIALIZE_TIME_MS: u32 = 10;
+const MEASUREMENT_TIME_MS: u32 = 80;
+/// Extra time to wait if the sensor is still busy after the measurement
+/// time.
+const BUSY_RETRY_MS: u32 = 10;
+
+/// How many times to retry calibration, or to wait for a busy sensor,
+/// before giving up.
+const MAX_RETRIES: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Initialize,
+    InitializeWait,
+    ReadStatus,
+    Measure,
+    MeasureWait,
+    ReadData,
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let polynomial = 0x31;
+    let mut crc = 0xff;
+
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if (crc & 0x80) != 0 {
+                crc = crc << 1 ^ polynomial;
+            } else {
+                crc = crc << 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Converts a measurement to the temperature in hundredths of degrees
+/// centigrade and the humidity in hundredths of percent.
+fn parse_measurement(data: &[u8]) -> (i32, usize) {
+    let humidity = ((data[1] as u32) << 12) | ((data[2] as u32) << 4) | ((data[3] as u32) >> 4);
+    let temperature = (((data[3] & 0x0F) as u32) << 16) | ((data[4] as u32) << 8) | data[5] as u32;
+
+    // RH = S / 2^20 * 100%, T = S / 2^20 * 200 - 50
+    let humidity = ((humidity as u64 * 10000) >> 20) as usize;
+    let temperature = ((temperature as u64 * 20000) >> 20) as i32 - 5000;
+    (temperature, humidity)
+}
+
+pub struct Aht20<'a, A: Alarm<'a>, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+    humidity_client: OptionalCell<&'a dyn HumidityClient>,
+    state: Cell<State>,
+    calibrated: Cell<bool>,
+    retries: Cell<u8>,
+    buffer: TakeCell<'static, [u8]>,
+    read_temp: Cell<bool>,
+    read_hum: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> Aht20<'a, A, I> {
+    pub fn new(i2c: &'a I, buffer: &'static mut [u8], alarm: &'a A) -> Aht20<'a, A, I> {
+        Aht20 {
+            i2c,
+            alarm,
+            temperature_client: OptionalCell::empty(),
+            humidity_client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            calibrated: Cell::new(false),
+            retries: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            read_temp: Cell::new(false),
+            read_hum: Cell::new(false),
+        }
+    }
+
+    fn start_reading(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            // The pending measurement will serve this request as well.
+            return Ok(());
+        }
+        self.retries.set(0);
+        self.i2c.enable();
+        if self.calibrated.get() {
+            self.send(State::Measure, &CMD_MEASURE)
+        } else {
+            self.send(State::Initialize, &CMD_INITIALIZE)
+        }
+    }
+
+    fn send(&self, state: State, command: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[..command.len()].copy_from_slice(command);
+            match self.i2c.write(buffer, command.len()) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn receive(&self, state: State, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            match self.i2c.read(buffer, len) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn wait(&self, state: State, ms: u32) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Counts a retry, and returns false if there are none left.
+    fn retry(&self) -> bool {
+        let retries = self.retries.get() + 1;
+        self.retries.set(retries);
+        retries <= MAX_RETRIES
+    }
+
+    fn finish(&self, result: Result<(i32, usize), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        if self.read_temp.take() {
+            self.temperature_client
+                .map(|client| client.callback(result.map(|(temperature, _)| temperature)));
+        }
+        if self.read_hum.take() {
+            let humidity = result.map_or(usize::MAX, |(_, humidity)| humidity);
+            self.humidity_client.map(|client| client.callback(humidity));
+        }
+    }
+
+    /// Finishes the reading if `result` is an error.
+    fn check(&self, result: Result<(), ErrorCode>) {
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> time::AlarmClient for Aht20<'a, A, I> {
+    fn alarm(&self) {
+        let result = match self.state.get() {
+            State::InitializeWait => self.receive(State::ReadStatus, 1),
+            State::MeasureWait => {
+                let len = if CHECK_CRC { 7 } else { 6 };
+                self.receive(State::ReadData, len)
+            }
+            _ => Ok(()),
+        };
+        self.check(result);
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> i2c::I2CClient for Aht20<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let status_byte = buffer[0];
+        let measurement = {
+            let mut data = [0; BUFFER_SIZE];
+            data.copy_from_slice(&buffer[..BUFFER_SIZE]);
+            data
+        };
+        self.buffer.replace(buffer);
+
+        if let Err(e) = status {
+            self.finish(Err(e.into()));
+            return;
+        }
+
+        match state {
+            State::Initialize => self.wait(State::InitializeWait, INITIALIZE_TIME_MS),
+            State::ReadStatus => {
+                if status_byte & STATUS_CALIBRATED != 0 {
+                    self.calibrated.set(true);
+                    self.retries.set(0);
+                    self.check(self.send(State::Measure, &CMD_MEASURE));
+                } else if self.retry() {
+                    self.check(self.send(State::Initialize, &CMD_INITIALIZE));
+                } else {
+                    self.finish(Err(ErrorCode::FAIL));
+                }
+            }
+            State::Measure => self.wait(State::MeasureWait, MEASUREMENT_TIME_MS),
+            State::ReadData => {
+                if status_byte & STATUS_BUSY != 0 {
+                    if self.retry() {
+                        self.wait(State::MeasureWait, BUSY_RETRY_MS);
+                    } else {
+                        self.finish(Err(ErrorCode::BUSY));
+                    }
+                } else if CHECK_CRC && crc8(&measurement[..6]) != measurement[6] {
+                    self.finish(Err(ErrorCode::FAIL));
+                } else {
+                    self.finish(Ok(parse_measurement(&measurement)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> TemperatureDriver<'a> for Aht20<'a, A, I> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        if self.read_temp.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.start_reading()?;
+        self.read_temp.set(true);
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> HumidityDriver<'a> for Aht20<'a, A, I> {
+    fn set_client(&self, client: &'a dyn HumidityClient) {
+        self.humidity_client.set(client);
+    }
+
+    fn read_humidity(&self) -> Result<(), ErrorCode> {
+        if self.read_hum.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.start_reading()?;
+        self.read_hum.set(true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measurement_conversion() {
+        // Humidity 0x80000 (50%), temperature 0x60000 (25 C).
+        let data = [0x1C, 0x80, 0x00, 0x06, 0x00, 0x00];
+        assert_eq!(parse_measurement(&data), (2500, 5000));
+
+        // The extremes of the 20-bit range.
+        let data = [0x1C, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(parse_measurement(&data), (-5000, 0));
+        let data = [0x1C, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(parse_measurement(&data), (14999, 9999));
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+        assert_eq!(crc8(&[]), 0xFF);
+    }
+}
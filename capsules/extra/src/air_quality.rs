@@ -39,6 +39,9 @@ enum Operation {
     None,
     CO2,
     TVOC,
+    VocIndex,
+    NoxIndex,
+    Aqi,
 }
 
 impl Default for Operation {
@@ -81,6 +84,9 @@ impl<'a> AirQualitySensor<'a> {
                         Operation::None => Err(ErrorCode::FAIL),
                         Operation::CO2 => self.driver.read_co2(),
                         Operation::TVOC => self.driver.read_tvoc(),
+                        Operation::VocIndex => self.driver.read_voc_index(),
+                        Operation::NoxIndex => self.driver.read_nox_index(),
+                        Operation::Aqi => self.driver.read_aqi(),
                     };
                     let eres = ErrorCode::try_from(rcode);
 
@@ -132,6 +138,54 @@ impl hil::sensors::AirQualityClient for AirQualitySensor<'_> {
             });
         }
     }
+
+    fn voc_index_available(&self, value: Result<u32, ErrorCode>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, upcalls| {
+                if app.operation == Operation::VocIndex {
+                    value
+                        .map(|index| {
+                            self.busy.set(false);
+                            app.operation = Operation::None;
+                            upcalls.schedule_upcall(0, (index as usize, 0, 0)).ok();
+                        })
+                        .ok();
+                }
+            });
+        }
+    }
+
+    fn nox_index_available(&self, value: Result<u32, ErrorCode>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, upcalls| {
+                if app.operation == Operation::NoxIndex {
+                    value
+                        .map(|index| {
+                            self.busy.set(false);
+                            app.operation = Operation::None;
+                            upcalls.schedule_upcall(0, (index as usize, 0, 0)).ok();
+                        })
+                        .ok();
+                }
+            });
+        }
+    }
+
+    fn aqi_available(&self, value: Result<u32, ErrorCode>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, upcalls| {
+                if app.operation == Operation::Aqi {
+                    value
+                        .map(|aqi| {
+                            self.busy.set(false);
+                            app.operation = Operation::None;
+                            upcalls.schedule_upcall(0, (aqi as usize, 0, 0)).ok();
+                        })
+                        .ok();
+                }
+            });
+        }
+    }
 }
 
 impl SyscallDriver for AirQualitySensor<'_> {
@@ -155,6 +209,15 @@ impl SyscallDriver for AirQualitySensor<'_> {
             // read TVOC
             3 => self.enqueue_command(processid, Operation::TVOC),
 
+            // read VOC index
+            4 => self.enqueue_command(processid, Operation::VocIndex),
+
+            // read NOx index
+            5 => self.enqueue_command(processid, Operation::NoxIndex),
+
+            // read AQI
+            6 => self.enqueue_command(processid, Operation::Aqi),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
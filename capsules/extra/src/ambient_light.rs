@@ -31,22 +31,28 @@ pub const DRIVER_NUM: usize = driver::NUM::AmbientLight as usize;
 #[derive(Default)]
 pub struct App {
     pending: bool,
+    raw_pending: bool,
+    millilux_pending: bool,
 }
 
 pub struct AmbientLight<'a> {
     sensor: &'a dyn hil::sensors::AmbientLight<'a>,
     command_pending: Cell<bool>,
-    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    raw_command_pending: Cell<bool>,
+    millilux_command_pending: Cell<bool>,
+    apps: Grant<App, UpcallCount<3>, AllowRoCount<0>, AllowRwCount<0>>,
 }
 
 impl<'a> AmbientLight<'a> {
     pub fn new(
         sensor: &'a dyn hil::sensors::AmbientLight<'a>,
-        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+        grant: Grant<App, UpcallCount<3>, AllowRoCount<0>, AllowRwCount<0>>,
     ) -> AmbientLight {
         AmbientLight {
             sensor: sensor,
             command_pending: Cell::new(false),
+            raw_command_pending: Cell::new(false),
+            millilux_command_pending: Cell::new(false),
             apps: grant,
         }
     }
@@ -67,6 +73,38 @@ impl<'a> AmbientLight<'a> {
             })
             .unwrap_or_else(|err| err.into())
     }
+
+    fn enqueue_raw_reading(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.raw_pending {
+                    return Err(ErrorCode::BUSY);
+                }
+                if !self.raw_command_pending.get() {
+                    self.sensor.read_raw_channels()?;
+                    self.raw_command_pending.set(true);
+                }
+                app.raw_pending = true;
+                Ok(())
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn enqueue_millilux_reading(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.millilux_pending {
+                    return Err(ErrorCode::BUSY);
+                }
+                if !self.millilux_command_pending.get() {
+                    self.sensor.read_light_intensity_millilux()?;
+                    self.millilux_command_pending.set(true);
+                }
+                app.millilux_pending = true;
+                Ok(())
+            })
+            .unwrap_or_else(|err| err.into())
+    }
 }
 
 impl SyscallDriver for AmbientLight<'_> {
@@ -76,6 +114,11 @@ impl SyscallDriver for AmbientLight<'_> {
     //
     // - `0`: Subscribe to light intensity readings. The callback signature is
     // `fn(lux: usize)`, where `lux` is the light intensity in lux (lx).
+    // - `1`: Subscribe to raw readings. The callback signature is
+    // `fn(broadband: usize, infrared: usize)`, the counts of the two
+    // channels of the sensor.
+    // - `2`: Subscribe to readings in millilux. The callback signature is
+    // `fn(millilux: usize)`.
 
     /// Initiate light intensity readings
     ///
@@ -88,11 +131,16 @@ impl SyscallDriver for AmbientLight<'_> {
     ///
     /// - `0`: Check driver presence
     /// - `1`: Start a light sensor reading
+    /// - `2`: Start a raw reading of the broadband and infrared channels, if
+    ///   the sensor has them
+    /// - `3`: Start a reading in millilux, if the sensor supports it
+    /// - `4`: Select single-shot (`0`) or continuous (`1`) conversions, and
+    ///   the integration time in milliseconds
     fn command(
         &self,
         command_num: usize,
-        _: usize,
-        _: usize,
+        arg1: usize,
+        arg2: usize,
         processid: ProcessId,
     ) -> CommandReturn {
         match command_num {
@@ -101,6 +149,12 @@ impl SyscallDriver for AmbientLight<'_> {
                 let _ = self.enqueue_sensor_reading(processid);
                 CommandReturn::success()
             }
+            2 => CommandReturn::from(self.enqueue_raw_reading(processid)),
+            3 => CommandReturn::from(self.enqueue_millilux_reading(processid)),
+            4 => match arg1 {
+                0 | 1 => CommandReturn::from(self.sensor.set_conversion(arg1 == 1, arg2 as u32)),
+                _ => CommandReturn::failure(ErrorCode::INVAL),
+            },
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -120,4 +174,24 @@ impl hil::sensors::AmbientLightClient for AmbientLight<'_> {
             }
         });
     }
+
+    fn raw_callback(&self, broadband: usize, infrared: usize) {
+        self.raw_command_pending.set(false);
+        self.apps.each(|_, app, upcalls| {
+            if app.raw_pending {
+                app.raw_pending = false;
+                upcalls.schedule_upcall(1, (broadband, infrared, 0)).ok();
+            }
+        });
+    }
+
+    fn millilux_callback(&self, millilux: u32) {
+        self.millilux_command_pending.set(false);
+        self.apps.each(|_, app, upcalls| {
+            if app.millilux_pending {
+                app.millilux_pending = false;
+                upcalls.schedule_upcall(2, (millilux as usize, 0, 0)).ok();
+            }
+        });
+    }
 }
@@ -0,0 +1,405 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Panasonic AMG8833 (Grid-EYE) 8x8 thermal sensor, over I2C.
+//!
+//! <https://industrial.panasonic.com/cdbs/www-data/pdf/ADI8000/ADI8000C66.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! [Amg8833::init] resets the sensor and sets its frame rate to 10 fps.
+//! [Amg8833::read_frame] then reads the 64 pixel temperatures, row by row,
+//! into the frame buffer of the driver, and lends it to the client. Pixels
+//! are 12-bit two's complement values of 0.25 °C, and are reported in
+//! hundredths of degrees Celsius.
+//!
+//! The thermistor of the sensor is read through `hil::sensors::
+//! TemperatureDriver`. It is a 12-bit sign and magnitude value of 0.0625 °C.
+//!
+//! If the INT pin of the sensor is connected, [Amg8833::set_thresholds]
+//! enables the interrupt for pixels above or below absolute thresholds.
+//! When the sensor pulls INT low, the driver reads which pixels are beyond
+//! the thresholds, clears the interrupt and reports them to the client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let amg8833 = components::amg8833::Amg8833Component::new(
+//!     mux_i2c,
+//!     capsules_extra::amg8833::BASE_ADDR,
+//!     Some(&nrf52840_peripherals.gpio_port[AMG8833_INT]),
+//! )
+//! .finalize(components::amg8833_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! amg8833.set_client(thermal_camera);
+//! amg8833.init().unwrap();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the AMG8833, with AD_SELECT connected to VDD. It
+/// is 0x68 with AD_SELECT connected to ground.
+pub const BASE_ADDR: u8 = 0x69;
+
+/// Number of pixels of a frame.
+pub const PIXELS: usize = 64;
+
+/// Size of the buffer the driver needs, for the two bytes of every pixel.
+pub const BUFFER_SIZE: usize = 2 * PIXELS;
+
+const REG_PCTL: u8 = 0x00;
+const REG_RST: u8 = 0x01;
+const REG_FPSC: u8 = 0x02;
+const REG_INTC: u8 = 0x03;
+const REG_SCLR: u8 = 0x05;
+const REG_INTHL: u8 = 0x08;
+const REG_TTHL: u8 = 0x0E;
+const REG_INT0: u8 = 0x10;
+const REG_T01L: u8 = 0x80;
+
+const PCTL_NORMAL: u8 = 0x00;
+const RST_INITIAL: u8 = 0x3F;
+const FPSC_10FPS: u8 = 0x00;
+/// `INTC` with the interrupt enabled, in absolute value mode.
+const INTC_ABSOLUTE: u8 = 0x03;
+/// `SCLR` bit that clears the interrupt flag.
+const SCLR_INTCLR: u8 = 0x02;
+
+/// Receives frames and interrupts from the AMG8833.
+pub trait Amg8833Client {
+    /// [Amg8833::init], [Amg8833::set_thresholds] or
+    /// [Amg8833::disable_thresholds] completed.
+    fn configured(&self, result: Result<(), ErrorCode>);
+
+    /// A frame was read, in hundredths of degrees Celsius, row by row.
+    fn frame_ready(&self, frame: Result<&[i32; PIXELS], ErrorCode>);
+
+    /// Pixels went beyond the interrupt thresholds, bit `n` for pixel `n`.
+    fn threshold_interrupt(&self, pixels: u64);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    SetPowerMode,
+    Reset,
+    SetFrameRate,
+    ReadFrame,
+    ReadThermistor,
+    /// Writing the thresholds, then `INTC`.
+    WriteThresholds,
+    /// Writing `INTC`, to enable the interrupt or not.
+    WriteInterruptControl(bool),
+    ReadInterruptTable,
+    ClearInterrupt(u64),
+}
+
+/// Decode a pixel or an interrupt threshold, a 12-bit two's complement
+/// value of 0.25 °C, to hundredths of degrees.
+fn pixel_centidegrees(low: u8, high: u8) -> i32 {
+    // Shift the sign bit to the top of an i16 and back to extend it.
+    let raw = ((u16::from_le_bytes([low, high]) << 4) as i16) >> 4;
+    raw as i32 * 25
+}
+
+/// Encode hundredths of degrees as a pixel value, clamped to the range of
+/// the sensor.
+fn pixel_raw(centidegrees: i32) -> [u8; 2] {
+    let raw = (centidegrees / 25).clamp(-2048, 2047) as i16 as u16 & 0x0FFF;
+    raw.to_le_bytes()
+}
+
+/// Decode the thermistor, a 12-bit sign and magnitude value of 0.0625 °C,
+/// to hundredths of degrees.
+fn thermistor_centidegrees(low: u8, high: u8) -> i32 {
+    let raw = u16::from_le_bytes([low, high]);
+    let magnitude = (raw & 0x07FF) as i32 * 625 / 100;
+    if raw & 0x0800 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Decode the pixel registers in `bytes` into `frame`.
+fn decode_frame(bytes: &[u8], frame: &mut [i32; PIXELS]) {
+    for (pixel, value) in bytes.chunks_exact(2).zip(frame.iter_mut()) {
+        *value = pixel_centidegrees(pixel[0], pixel[1]);
+    }
+}
+
+pub struct Amg8833<'a, I: I2CDevice> {
+    i2c: &'a I,
+    interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+    state: Cell<State>,
+    /// The INT line fell while the driver was busy.
+    interrupt_pending: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    frame: TakeCell<'static, [i32; PIXELS]>,
+    client: OptionalCell<&'a dyn Amg8833Client>,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+}
+
+impl<'a, I: I2CDevice> Amg8833<'a, I> {
+    pub fn new(
+        i2c: &'a I,
+        interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+        buffer: &'static mut [u8; BUFFER_SIZE],
+        frame: &'static mut [i32; PIXELS],
+    ) -> Amg8833<'a, I> {
+        Amg8833 {
+            i2c,
+            interrupt_pin,
+            state: Cell::new(State::Idle),
+            interrupt_pending: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            frame: TakeCell::new(frame),
+            client: OptionalCell::empty(),
+            temperature_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Amg8833Client) {
+        self.client.set(client);
+    }
+
+    /// Wake the sensor, reset it and set its frame rate to 10 fps.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        self.idle()?;
+        self.write(State::SetPowerMode, &[REG_PCTL, PCTL_NORMAL])
+    }
+
+    /// Read a frame of the 64 pixels.
+    pub fn read_frame(&self) -> Result<(), ErrorCode> {
+        self.idle()?;
+        self.write_read(State::ReadFrame, REG_T01L, BUFFER_SIZE)
+    }
+
+    /// Interrupt on pixels above `high` or below `low`, in hundredths of
+    /// degrees. A pixel that went beyond a threshold must come back by
+    /// `hysteresis` to clear.
+    pub fn set_thresholds(&self, high: i32, low: i32, hysteresis: i32) -> Result<(), ErrorCode> {
+        if self.interrupt_pin.is_none() {
+            return Err(ErrorCode::NODEVICE);
+        }
+        self.idle()?;
+        let [high_low, high_high] = pixel_raw(high);
+        let [low_low, low_high] = pixel_raw(low);
+        let [hysteresis_low, hysteresis_high] = pixel_raw(hysteresis);
+        self.write(
+            State::WriteThresholds,
+            &[
+                REG_INTHL,
+                high_low,
+                high_high,
+                low_low,
+                low_high,
+                hysteresis_low,
+                hysteresis_high,
+            ],
+        )
+    }
+
+    /// Stop interrupting on the thresholds.
+    pub fn disable_thresholds(&self) -> Result<(), ErrorCode> {
+        self.idle()?;
+        self.interrupt_pin.map(|pin| pin.disable_interrupts());
+        self.write(State::WriteInterruptControl(false), &[REG_INTC, 0])
+    }
+
+    fn idle(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Idle {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write(buffer, bytes.len()) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    fn write_read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = register;
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write_read(buffer, 1, len) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    /// Report the end of the operation in `state` with `result`, with the
+    /// data read in the buffer.
+    fn finish(&self, state: State, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        match state {
+            State::ReadFrame => {
+                if result.is_ok() {
+                    self.buffer
+                        .map(|buffer| self.frame.map(|frame| decode_frame(buffer, frame)));
+                }
+                self.frame.map(|frame| {
+                    self.client
+                        .map(|client| client.frame_ready(result.map(|()| &*frame)));
+                });
+            }
+            State::ReadThermistor => {
+                let temperature = result.and_then(|()| {
+                    self.buffer
+                        .map(|buffer| thermistor_centidegrees(buffer[0], buffer[1]))
+                        .ok_or(ErrorCode::FAIL)
+                });
+                self.temperature_client
+                    .map(|client| client.callback(temperature));
+            }
+            State::ClearInterrupt(pixels) => {
+                self.client.map(|client| client.threshold_interrupt(pixels));
+            }
+            State::ReadInterruptTable => {}
+            _ => {
+                self.client.map(|client| client.configured(result));
+            }
+        }
+    }
+
+    /// Read the pixels beyond the thresholds, if the sensor interrupted.
+    fn service_interrupt(&self) {
+        if self.interrupt_pending.get() && self.state.get() == State::Idle {
+            self.interrupt_pending.set(false);
+            let _ = self.write_read(State::ReadInterruptTable, REG_INT0, 8);
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> I2CClient for Amg8833<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let next = match status {
+            Err(error) => Err(error.into()),
+            Ok(()) => match state {
+                State::SetPowerMode => Ok(Some((State::Reset, [REG_RST, RST_INITIAL]))),
+                State::Reset => Ok(Some((State::SetFrameRate, [REG_FPSC, FPSC_10FPS]))),
+                State::WriteThresholds => Ok(Some((
+                    State::WriteInterruptControl(true),
+                    [REG_INTC, INTC_ABSOLUTE],
+                ))),
+                State::ReadInterruptTable => {
+                    let mut table = [0; 8];
+                    table.copy_from_slice(&buffer[..8]);
+                    Ok(Some((
+                        State::ClearInterrupt(u64::from_le_bytes(table)),
+                        [REG_SCLR, SCLR_INTCLR],
+                    )))
+                }
+                _ => Ok(None),
+            },
+        };
+        match next {
+            Ok(Some((next_state, bytes))) => {
+                self.buffer.replace(buffer);
+                if let Err(error) = self.write(next_state, &bytes) {
+                    self.i2c.disable();
+                    self.finish(state, Err(error));
+                }
+            }
+            result => {
+                self.i2c.disable();
+                self.buffer.replace(buffer);
+                if state == State::WriteInterruptControl(true) && result.is_ok() {
+                    self.interrupt_pin
+                        .map(|pin| pin.enable_interrupts(gpio::InterruptEdge::FallingEdge));
+                }
+                self.finish(state, result.map(|_| ()));
+            }
+        }
+        self.service_interrupt();
+    }
+}
+
+impl<'a, I: I2CDevice> gpio::Client for Amg8833<'a, I> {
+    fn fired(&self) {
+        self.interrupt_pending.set(true);
+        self.service_interrupt();
+    }
+}
+
+impl<'a, I: I2CDevice> TemperatureDriver<'a> for Amg8833<'a, I> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.idle()?;
+        self.write_read(State::ReadThermistor, REG_TTHL, 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame with the extremes of the pixel range, zero, and values on
+    /// either side of zero.
+    const FRAME: [u8; BUFFER_SIZE] = [
+        0x00, 0x08, 0xFF, 0x07, 0xFF, 0x0F, 0x00, 0x00, 0x58, 0x00, 0xDD, 0x0F, 0x02, 0x00, 0x27,
+        0x00, 0x4C, 0x00, 0xD1, 0x0F, 0xF6, 0x0F, 0x1B, 0x00, 0x40, 0x00, 0xC5, 0x0F, 0xEA, 0x0F,
+        0x0F, 0x00, 0x34, 0x00, 0x59, 0x00, 0xDE, 0x0F, 0x03, 0x00, 0x28, 0x00, 0x4D, 0x00, 0xD2,
+        0x0F, 0xF7, 0x0F, 0x1C, 0x00, 0x41, 0x00, 0xC6, 0x0F, 0xEB, 0x0F, 0x10, 0x00, 0x35, 0x00,
+        0x5A, 0x00, 0xDF, 0x0F, 0x04, 0x00, 0x29, 0x00, 0x4E, 0x00, 0xD3, 0x0F, 0xF8, 0x0F, 0x1D,
+        0x00, 0x42, 0x00, 0xC7, 0x0F, 0xEC, 0x0F, 0x11, 0x00, 0x36, 0x00, 0x5B, 0x00, 0xE0, 0x0F,
+        0x05, 0x00, 0x2A, 0x00, 0x4F, 0x00, 0xD4, 0x0F, 0xF9, 0x0F, 0x1E, 0x00, 0x43, 0x00, 0xC8,
+        0x0F, 0xED, 0x0F, 0x12, 0x00, 0x37, 0x00, 0x5C, 0x00, 0xE1, 0x0F, 0x06, 0x00, 0x2B, 0x00,
+        0x50, 0x00, 0xD5, 0x0F, 0xFA, 0x0F, 0x1F, 0x00,
+    ];
+
+    #[test]
+    fn decodes_signed_pixels() {
+        let mut frame = [0; PIXELS];
+        decode_frame(&FRAME, &mut frame);
+        assert_eq!(
+            frame,
+            [
+                -51200, 51175, -25, 0, 2200, -875, 50, 975, 1900, -1175, -250, 675, 1600, -1475,
+                -550, 375, 1300, 2225, -850, 75, 1000, 1925, -1150, -225, 700, 1625, -1450, -525,
+                400, 1325, 2250, -825, 100, 1025, 1950, -1125, -200, 725, 1650, -1425, -500, 425,
+                1350, 2275, -800, 125, 1050, 1975, -1100, -175, 750, 1675, -1400, -475, 450, 1375,
+                2300, -775, 150, 1075, 2000, -1075, -150, 775,
+            ]
+        );
+
+        // Thresholds are encoded like pixels, clamped to the range.
+        assert_eq!(pixel_raw(-25), [0xFF, 0x0F]);
+        assert_eq!(pixel_raw(3000), [0x78, 0x00]);
+        assert_eq!(pixel_raw(-100_000), [0x00, 0x08]);
+
+        // The thermistor is sign and magnitude.
+        assert_eq!(thermistor_centidegrees(0x90, 0x01), 2500);
+        assert_eq!(thermistor_centidegrees(0x10, 0x08), -100);
+    }
+}
@@ -5,7 +5,9 @@
 //! This allows multiple apps to write their own flash region.
 //!
 //! All write requests from userland are checked to ensure that they are only
-//! trying to write their own flash space, and not the TBF header either.
+//! trying to write their own flash space, and not the TBF header either. The
+//! check is repeated when a queued write starts, as the process may have
+//! shared a different buffer in the meantime.
 //!
 //! This driver can handle non page aligned writes.
 //!
@@ -28,7 +30,8 @@
 
 use core::cmp;
 
-use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
 use kernel::hil;
 use kernel::processbuffer::ReadableProcessBuffer;
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -46,6 +49,20 @@ mod ro_allow {
     pub const COUNT: u8 = 1;
 }
 
+/// Check that writing `length` bytes at `address` stays within the editable
+/// flash region from `start` to `end` of a process.
+fn check_write_range(
+    address: usize,
+    length: usize,
+    (start, end): (usize, usize),
+) -> Result<(), ErrorCode> {
+    let write_end = address.checked_add(length).ok_or(ErrorCode::INVAL)?;
+    if address < start || address >= end || write_end > end {
+        return Err(ErrorCode::INVAL);
+    }
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct App {
     pending_command: bool,
@@ -73,6 +90,44 @@ impl<'a> AppFlash<'a> {
         }
     }
 
+    // Copy the allowed buffer of the process into the internal buffer and
+    // write it to `flash_address`, once the range that is actually written is
+    // checked.
+    fn start_write(
+        &self,
+        kernel_data: &GrantKernelData,
+        flash_address: usize,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::BUFFER)
+            .and_then(|buffer| {
+                buffer.enter(|app_buffer| {
+                    self.buffer
+                        .take()
+                        .map_or(Err(ErrorCode::RESERVE), |buffer| {
+                            let length = cmp::min(buffer.len(), app_buffer.len());
+                            if let Err(e) = check_write_range(
+                                flash_address,
+                                length,
+                                processid.get_editable_flash_range(),
+                            ) {
+                                self.buffer.replace(buffer);
+                                return Err(e);
+                            }
+
+                            let d = &app_buffer[0..length];
+                            for (i, c) in buffer.as_mut()[0..length].iter_mut().enumerate() {
+                                *c = d[i].get();
+                            }
+
+                            self.driver.write(buffer, flash_address, length)
+                        })
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
     // Check to see if we are doing something. If not, go ahead and do this
     // command. If so, this is queued and will be run when the pending command
     // completes.
@@ -83,38 +138,20 @@ impl<'a> AppFlash<'a> {
                 let flash_length = kernel_data
                     .get_readonly_processbuffer(ro_allow::BUFFER)
                     .map_or(0, |buffer| buffer.len());
-                let (app_flash_start, app_flash_end) = processid.get_editable_flash_range();
-                if flash_address < app_flash_start
-                    || flash_address >= app_flash_end
-                    || flash_address + flash_length >= app_flash_end
-                {
-                    return Err(ErrorCode::INVAL);
-                }
+                check_write_range(
+                    flash_address,
+                    flash_length,
+                    processid.get_editable_flash_range(),
+                )?;
 
                 if self.current_app.is_none() {
                     self.current_app.set(processid);
 
-                    kernel_data
-                        .get_readonly_processbuffer(ro_allow::BUFFER)
-                        .and_then(|buffer| {
-                            buffer.enter(|app_buffer| {
-                                // Copy contents to internal buffer and write it.
-                                self.buffer
-                                    .take()
-                                    .map_or(Err(ErrorCode::RESERVE), |buffer| {
-                                        let length = cmp::min(buffer.len(), app_buffer.len());
-                                        let d = &app_buffer[0..length];
-                                        for (i, c) in
-                                            buffer.as_mut()[0..length].iter_mut().enumerate()
-                                        {
-                                            *c = d[i].get();
-                                        }
-
-                                        self.driver.write(buffer, flash_address, length)
-                                    })
-                            })
-                        })
-                        .unwrap_or(Err(ErrorCode::RESERVE))
+                    let res = self.start_write(kernel_data, flash_address, processid);
+                    if res.is_err() {
+                        self.current_app.clear();
+                    }
+                    res
                 } else {
                     // Queue this request for later.
                     if app.pending_command == true {
@@ -151,37 +188,16 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient for AppFlash<'_> {
                 if app.pending_command {
                     app.pending_command = false;
                     self.current_app.set(processid);
-                    let flash_address = app.flash_address;
-
-                    kernel_data
-                        .get_readonly_processbuffer(ro_allow::BUFFER)
-                        .and_then(|buffer| {
-                            buffer.enter(|app_buffer| {
-                                self.buffer.take().map_or(false, |buffer| {
-                                    if app_buffer.len() != 512 {
-                                        false
-                                    } else {
-                                        // Copy contents to internal buffer and write it.
-                                        let length = cmp::min(buffer.len(), app_buffer.len());
-                                        let d = &app_buffer[0..length];
-                                        for (i, c) in
-                                            buffer.as_mut()[0..length].iter_mut().enumerate()
-                                        {
-                                            *c = d[i].get();
-                                        }
-
-                                        if let Ok(()) =
-                                            self.driver.write(buffer, flash_address, length)
-                                        {
-                                            true
-                                        } else {
-                                            false
-                                        }
-                                    }
-                                })
-                            })
-                        })
-                        .unwrap_or(false)
+
+                    if let Err(e) = self.start_write(kernel_data, app.flash_address, processid) {
+                        self.current_app.clear();
+                        kernel_data
+                            .schedule_upcall(0, (into_statuscode(Err(e)), 0, 0))
+                            .ok();
+                        false
+                    } else {
+                        true
+                    }
                 } else {
                     false
                 }
@@ -242,3 +258,55 @@ impl SyscallDriver for AppFlash<'_> {
         self.apps.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGION: (usize, usize) = (0x4_0000, 0x4_1000);
+
+    #[test]
+    fn writes_within_the_region_are_allowed() {
+        assert_eq!(check_write_range(0x4_0000, 512, REGION), Ok(()));
+        assert_eq!(check_write_range(0x4_0800, 0, REGION), Ok(()));
+        // A write may end exactly at the end of the region.
+        assert_eq!(check_write_range(0x4_0E00, 512, REGION), Ok(()));
+        assert_eq!(check_write_range(0x4_0FFF, 1, REGION), Ok(()));
+    }
+
+    #[test]
+    fn writes_outside_the_region_are_rejected() {
+        assert_eq!(
+            check_write_range(0x3_FFFF, 512, REGION),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            check_write_range(0x4_0E01, 512, REGION),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            check_write_range(0x4_1000, 0, REGION),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            check_write_range(0x4_2000, 1, REGION),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn overflowing_writes_are_rejected() {
+        assert_eq!(
+            check_write_range(0x4_0800, usize::MAX, REGION),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            check_write_range(usize::MAX, 2, REGION),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            check_write_range(0x4_0000, usize::MAX - 0x3_FFFF, REGION),
+            Err(ErrorCode::INVAL)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with control of a battery charger.
+//!
+//! Changing how a battery is charged can damage it, so boards should only
+//! give this driver to a trusted process. This is done with the board's
+//! `SyscallFilter`, for example `kernel::platform::TbfHeaderFilterDefaultAllow`
+//! together with a TBF permissions header that grants the process access to
+//! this driver number.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a command completes, with the status code.
+//! * `1`: called when charging stops, with the reason:
+//!   * `0`: the battery finished charging
+//!   * `1`: input fault
+//!   * `2`: thermal shutdown
+//!   * `3`: safety timer expired
+//!   * `4`: battery fault
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: enable charging
+//! * `2`: disable charging
+//! * `3`: set the input current limit to argument 1, in mA
+//! * `4`: set the charge current to argument 1, in mA
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::battery_charger::BatteryCharger`
+//! trait.
+//!
+//! ```rust
+//! let charger = components::battery_charger::BatteryChargerComponent::new(
+//!     board_kernel,
+//!     capsules_extra::battery_charger::DRIVER_NUM,
+//!     bq24195,
+//! )
+//! .finalize(components::battery_charger_component_static!());
+//! ```
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::battery_charger::{BatteryCharger, BatteryChargerClient, ChargerFault};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BatteryCharger as usize;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const COMMAND_COMPLETE: usize = 0;
+    pub const FAULT: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+fn fault_code(fault: ChargerFault) -> usize {
+    match fault {
+        ChargerFault::NormalTermination => 0,
+        ChargerFault::InputFault => 1,
+        ChargerFault::ThermalShutdown => 2,
+        ChargerFault::SafetyTimer => 3,
+        ChargerFault::BatteryFault => 4,
+    }
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct BatteryChargerDriver<'a> {
+    charger: &'a dyn BatteryCharger<'a>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process whose command is in progress.
+    processid: OptionalCell<ProcessId>,
+}
+
+impl<'a> BatteryChargerDriver<'a> {
+    pub fn new(
+        charger: &'a dyn BatteryCharger<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> BatteryChargerDriver<'a> {
+        BatteryChargerDriver {
+            charger,
+            apps: grant,
+            processid: OptionalCell::empty(),
+        }
+    }
+}
+
+impl SyscallDriver for BatteryChargerDriver<'_> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Enable charging.
+    /// - `2`: Disable charging.
+    /// - `3`: Set the input current limit, in mA.
+    /// - `4`: Set the charge current, in mA.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if self.processid.is_some() {
+            return CommandReturn::failure(ErrorCode::BUSY);
+        }
+        let ma = u16::try_from(data).unwrap_or(u16::MAX);
+        let result = match command_num {
+            1 => self.charger.enable_charging(),
+            2 => self.charger.disable_charging(),
+            3 => self.charger.set_input_current_limit_ma(ma),
+            4 => self.charger.set_charge_current_ma(ma),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        if result.is_ok() {
+            self.processid.set(processid);
+        }
+        result.into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl BatteryChargerClient for BatteryChargerDriver<'_> {
+    fn command_complete(&self, result: Result<(), ErrorCode>) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::COMMAND_COMPLETE, (into_statuscode(result), 0, 0))
+                    .ok();
+            });
+        });
+    }
+
+    fn fault(&self, fault: ChargerFault) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::FAULT, (fault_code(fault), 0, 0))
+                .ok();
+        });
+    }
+}
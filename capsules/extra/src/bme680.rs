@@ -0,0 +1,913 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Bosch BME680 and BME688 environmental sensors, which
+//! measure temperature, pressure, humidity and the resistance of a heated
+//! metal-oxide gas sensor.
+//!
+//! <https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme680-ds001.pdf>
+//! <https://www.bosch-sensortec.com/media/boschsensortec/downloads/datasheets/bst-bme688-ds000.pdf>
+//!
+//! The driver runs a measurement in forced mode every three seconds, and
+//! requests for a reading are answered with the next measurement. The
+//! readings are compensated with the integer formulas of Bosch's BME68x
+//! API, with the calibration coefficients of the sensor.
+//!
+//! Before each measurement, the gas sensor is heated to the temperature of
+//! the next set point of the heater profile, for its duration. There are up
+//! to [`MAX_HEATER_STEPS`] set points, and a single one at 320 °C for
+//! 150 ms by default. The gas resistance is only used once the sensor
+//! reports it valid and the heater stable.
+//!
+//! Indoor air quality
+//! ------------------
+//!
+//! Bosch computes its IAQ index with the BSEC library, which is only
+//! distributed as a binary, so this driver estimates it in the same range
+//! instead. For each set point, the resistance of clean air is learnt as
+//! the average of the first [`BURN_IN_SAMPLES`] measurements, and then as
+//! the highest resistance seen, slowly decaying to follow the drift of the
+//! sensor. Three quarters of the score come from how far the resistance is
+//! below that baseline, and a quarter from how far the humidity is from
+//! 40 %RH. Requests for the index wait for the end of the burn-in.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bme680 = components::bme680::Bme680Component::new(
+//!     mux_i2c,
+//!     capsules_extra::bme680::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::bme680_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! let iaq = components::indoor_air_quality::IndoorAirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::indoor_air_quality::DRIVER_NUM,
+//!     bme680,
+//! )
+//! .finalize(components::indoor_air_quality_component_static!());
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{
+    HumidityClient, HumidityDriver, IndoorAirQuality, IndoorAirQualityClient, TemperatureClient,
+    TemperatureDriver,
+};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The address with SDO low, and with SDO high.
+pub const BASE_ADDR: u8 = 0x76;
+pub const ALTERNATE_ADDR: u8 = 0x77;
+
+/// Size of the buffer for the sensor: the first block of calibration
+/// coefficients.
+pub const BUFFER_SIZE: usize = 23;
+
+/// Most set points of the heater profile.
+pub const MAX_HEATER_STEPS: usize = 10;
+
+/// Measurements of each set point averaged into the first baseline of the
+/// air quality estimate.
+pub const BURN_IN_SAMPLES: u32 = 20;
+
+const REG_COEFFICIENTS_3: u8 = 0x00;
+const REG_FIELD_0: u8 = 0x1D;
+const REG_RES_HEAT_0: u8 = 0x5A;
+const REG_GAS_WAIT_0: u8 = 0x64;
+const REG_CTRL_GAS_1: u8 = 0x71;
+const REG_CTRL_HUM: u8 = 0x72;
+const REG_CTRL_MEAS: u8 = 0x74;
+const REG_COEFFICIENTS_1: u8 = 0x8A;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_COEFFICIENTS_2: u8 = 0xE1;
+
+const CHIP_ID: u8 = 0x61;
+/// In RegVariantId, read after the second block of coefficients.
+const VARIANT_BME688: u8 = 0x01;
+
+const COEFFICIENTS_1_LEN: usize = 23;
+const COEFFICIENTS_2_LEN: usize = 14;
+const COEFFICIENTS_3_LEN: usize = 5;
+const COEFFICIENTS_LEN: usize = COEFFICIENTS_1_LEN + COEFFICIENTS_2_LEN + COEFFICIENTS_3_LEN;
+
+/// From meas_status_0 to gas_r_lsb of the BME688.
+const FIELD_LEN: usize = 17;
+const NEW_DATA: u8 = 0x80;
+const GAS_VALID: u8 = 0x20;
+const HEAT_STAB: u8 = 0x10;
+
+/// run_gas, which moved in the BME688, with heater set point 0.
+const RUN_GAS_BME680: u8 = 0x10;
+const RUN_GAS_BME688: u8 = 0x20;
+/// Oversampling of 1 for each measurement, in forced mode.
+const OSRS_H_1X: u8 = 0x01;
+const CTRL_MEAS_FORCED: u8 = 0x01 << 5 | 0x01 << 2 | 0x01;
+
+/// The measurement of temperature, pressure and humidity at oversampling
+/// 1, with the wake up, before the gas measurement.
+const TPH_DURATION_MS: u32 = 11;
+/// Time between polls of a measurement that is not done yet.
+const POLL_MS: u32 = 5;
+const MAX_POLLS: u32 = 10;
+const SAMPLE_INTERVAL_MS: u32 = 3000;
+
+const MIN_HEATER_TEMPERATURE_C: u16 = 200;
+const MAX_HEATER_TEMPERATURE_C: u16 = 400;
+/// The longest gas_wait: 63 ms multiplied by 64.
+const MAX_HEATER_DURATION_MS: u16 = 4032;
+const DEFAULT_HEATER_STEP: (u16, u16) = (320, 150);
+const DEFAULT_AMBIENT_C: i32 = 25;
+
+/// The baseline loses this fraction of its distance to lower resistances
+/// at each measurement.
+const BASELINE_DECAY: u32 = 4096;
+const HUMIDITY_OPTIMUM_MILLI: u32 = 40_000;
+const GAS_WEIGHT: u32 = 75;
+const HUMIDITY_WEIGHT: u32 = 25;
+
+/// The gas range tables of the BME680.
+const GAS_RANGE_1: [u32; 16] = [
+    2147483647, 2147483647, 2147483647, 2147483647, 2147483647, 2126008810, 2147483647, 2130303777,
+    2147483647, 2147483647, 2143188679, 2136746228, 2147483647, 2126008810, 2147483647, 2147483647,
+];
+const GAS_RANGE_2: [u32; 16] = [
+    4096000000, 2048000000, 1024000000, 512000000, 255744255, 127110228, 64000000, 32258064,
+    16016016, 8000000, 4000000, 2000000, 1000000, 500000, 250000, 125000,
+];
+
+/// A compensated measurement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// In hundredths of °C.
+    pub temperature: i32,
+    /// In Pa.
+    pub pressure: u32,
+    /// In thousandths of %RH.
+    pub humidity: u32,
+    /// In ohms, if the gas sensor was heated to a stable temperature.
+    pub gas_resistance: Option<u32>,
+}
+
+/// The calibration coefficients of a sensor.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i8,
+    p1: u16,
+    p2: i16,
+    p3: i8,
+    p4: i16,
+    p5: i16,
+    p6: i8,
+    p7: i8,
+    p8: i16,
+    p9: i16,
+    p10: u8,
+    h1: u16,
+    h2: u16,
+    h3: i8,
+    h4: i8,
+    h5: i8,
+    h6: u8,
+    h7: i8,
+    gh1: i8,
+    gh2: i16,
+    gh3: i8,
+    res_heat_range: u8,
+    res_heat_val: i8,
+    range_sw_err: i8,
+}
+
+impl Calibration {
+    /// Parse the three blocks of coefficients, one after the other.
+    fn new(c: &[u8; COEFFICIENTS_LEN]) -> Calibration {
+        let u16_at = |i: usize| u16::from_le_bytes([c[i], c[i + 1]]);
+        Calibration {
+            t1: u16_at(31),
+            t2: u16_at(0) as i16,
+            t3: c[2] as i8,
+            p1: u16_at(4),
+            p2: u16_at(6) as i16,
+            p3: c[8] as i8,
+            p4: u16_at(10) as i16,
+            p5: u16_at(12) as i16,
+            p6: c[15] as i8,
+            p7: c[14] as i8,
+            p8: u16_at(18) as i16,
+            p9: u16_at(20) as i16,
+            p10: c[22],
+            h1: (c[25] as u16) << 4 | (c[24] & 0x0F) as u16,
+            h2: (c[23] as u16) << 4 | (c[24] >> 4) as u16,
+            h3: c[26] as i8,
+            h4: c[27] as i8,
+            h5: c[28] as i8,
+            h6: c[29],
+            h7: c[30] as i8,
+            gh1: c[35] as i8,
+            gh2: u16_at(33) as i16,
+            gh3: c[36] as i8,
+            res_heat_range: (c[39] & 0x30) >> 4,
+            res_heat_val: c[37] as i8,
+            range_sw_err: (c[41] & 0xF0) as i8 / 16,
+        }
+    }
+
+    /// The fine temperature used by the other compensations, and the
+    /// temperature in hundredths of °C.
+    fn temperature(&self, adc: u32) -> (i32, i32) {
+        let var1 = (adc as i32 >> 3) - ((self.t1 as i32) << 1);
+        let var2 = (var1 * self.t2 as i32) >> 11;
+        let var3 = ((var1 >> 1) * (var1 >> 1)) >> 12;
+        let var3 = (var3 * ((self.t3 as i32) << 4)) >> 14;
+        let t_fine = var2 + var3;
+        (t_fine, (t_fine * 5 + 128) >> 8)
+    }
+
+    /// The pressure in Pa.
+    fn pressure(&self, adc: u32, t_fine: i32) -> u32 {
+        let var1 = (t_fine as i64 >> 1) - 64000;
+        let var2 = ((((var1 >> 2) * (var1 >> 2)) >> 11) * self.p6 as i64) >> 2;
+        let var2 = var2 + ((var1 * self.p5 as i64) << 1);
+        let var2 = (var2 >> 2) + ((self.p4 as i64) << 16);
+        let var1 = (((((var1 >> 2) * (var1 >> 2)) >> 13) * ((self.p3 as i64) << 5)) >> 3)
+            + ((self.p2 as i64 * var1) >> 1);
+        let var1 = var1 >> 18;
+        let var1 = ((32768 + var1) * self.p1 as i64) >> 15;
+        if var1 == 0 {
+            return 0;
+        }
+        let pressure = ((1_048_576 - adc as i64) - (var2 >> 12)) * 3125;
+        let pressure = if pressure >= 1 << 30 {
+            (pressure / var1) << 1
+        } else {
+            (pressure << 1) / var1
+        };
+        let var1 = (self.p9 as i64 * (((pressure >> 3) * (pressure >> 3)) >> 13)) >> 12;
+        let var2 = ((pressure >> 2) * self.p8 as i64) >> 13;
+        let var3 = ((pressure >> 8) * (pressure >> 8) * (pressure >> 8) * self.p10 as i64) >> 17;
+        (pressure + ((var1 + var2 + var3 + ((self.p7 as i64) << 7)) >> 4)) as u32
+    }
+
+    /// The relative humidity in thousandths of %.
+    fn humidity(&self, adc: u32, t_fine: i32) -> u32 {
+        let temp_scaled = (t_fine * 5 + 128) >> 8;
+        let var1 =
+            (adc as i32 - self.h1 as i32 * 16) - (((temp_scaled * self.h3 as i32) / 100) >> 1);
+        let var2 = (self.h2 as i32
+            * (((temp_scaled * self.h4 as i32) / 100)
+                + (((temp_scaled * ((temp_scaled * self.h5 as i32) / 100)) >> 6) / 100)
+                + (1 << 14)))
+            >> 10;
+        let var3 = var1 * var2;
+        let var4 = ((self.h6 as i32) << 7) + ((temp_scaled * self.h7 as i32) / 100) >> 4;
+        let var5 = ((var3 >> 14) * (var3 >> 14)) >> 10;
+        let var6 = (var4 * var5) >> 1;
+        let humidity = (((var3 + var6) >> 10) * 1000) >> 12;
+        humidity.clamp(0, 100_000) as u32
+    }
+
+    /// The gas resistance measured by a BME680, in ohms.
+    fn gas_resistance(&self, adc: u16, range: u8) -> u32 {
+        let range = range as usize;
+        let var1 = ((1340 + 5 * self.range_sw_err as i64) * GAS_RANGE_1[range] as i64) >> 16;
+        let var2 = ((adc as i64) << 15) - 16_777_216 + var1;
+        let var3 = (GAS_RANGE_2[range] as i64 * var1) >> 9;
+        ((var3 + (var2 >> 1)) / var2) as u32
+    }
+
+    /// res_heat for a heater temperature, at an ambient temperature in °C.
+    fn heater_resistance(&self, temperature_c: u16, ambient_c: i32) -> u8 {
+        let temperature = temperature_c.min(MAX_HEATER_TEMPERATURE_C) as i32;
+        let var1 = ((ambient_c * self.gh3 as i32) / 1000) * 256;
+        let var2 = (self.gh1 as i32 + 784)
+            * (((((self.gh2 as i32 + 154_009) * temperature * 5) / 100) + 3_276_800) / 10);
+        let var3 = var1 + var2 / 2;
+        let var4 = var3 / (self.res_heat_range as i32 + 4);
+        let var5 = 131 * self.res_heat_val as i32 + 65_536;
+        let resistance_x100 = ((var4 / var5) - 250) * 34;
+        ((resistance_x100 + 50) / 100) as u8
+    }
+}
+
+/// The gas resistance measured by a BME688, in ohms.
+fn gas_resistance_bme688(adc: u16, range: u8) -> u32 {
+    let var1 = 262_144u32 >> range;
+    let var2 = 4096 + (adc as i32 - 512) * 3;
+    10_000 * var1 / var2 as u32 * 100
+}
+
+/// gas_wait for a heating duration: 6 bits of duration, multiplied by a
+/// power of 4.
+fn gas_wait(duration_ms: u16) -> u8 {
+    if duration_ms >= MAX_HEATER_DURATION_MS {
+        return 0xFF;
+    }
+    let mut duration = duration_ms;
+    let mut factor = 0;
+    while duration > 0x3F {
+        duration /= 4;
+        factor += 1;
+    }
+    (duration + factor * 64) as u8
+}
+
+/// The clean air resistance of a set point.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Baseline {
+    ohms: u32,
+    samples: u32,
+}
+
+/// Estimates the air quality from the gas resistance and the humidity.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct IaqEstimate {
+    baselines: [Baseline; MAX_HEATER_STEPS],
+}
+
+impl IaqEstimate {
+    /// The index for a measurement at set point `step`, once its burn-in is
+    /// over.
+    fn process(&mut self, step: usize, gas_ohms: u32, humidity: u32) -> Option<u16> {
+        let baseline = &mut self.baselines[step];
+        baseline.samples = baseline.samples.saturating_add(1);
+        if baseline.samples <= BURN_IN_SAMPLES {
+            // The running average.
+            let sum = baseline.ohms as u64 * (baseline.samples - 1) as u64 + gas_ohms as u64;
+            baseline.ohms = (sum / baseline.samples as u64) as u32;
+            return None;
+        }
+        if gas_ohms > baseline.ohms {
+            baseline.ohms = gas_ohms;
+        } else {
+            baseline.ohms -= (baseline.ohms - gas_ohms) / BASELINE_DECAY;
+        }
+
+        let gas_score = match baseline.ohms {
+            0 => 0,
+            ohms => (GAS_WEIGHT as u64 * gas_ohms as u64 / ohms as u64) as u32,
+        };
+        let humidity_score = if humidity < HUMIDITY_OPTIMUM_MILLI {
+            HUMIDITY_WEIGHT * humidity / HUMIDITY_OPTIMUM_MILLI
+        } else {
+            HUMIDITY_WEIGHT * (100_000 - humidity) / (100_000 - HUMIDITY_OPTIMUM_MILLI)
+        };
+        // 100 is the best air quality, which is an index of 0.
+        let quality = gas_score + humidity_score;
+        Some((100 - quality.min(100)) as u16 * 5)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Off,
+    Identify,
+    /// Reading a block of calibration coefficients.
+    Coefficients(u8),
+    /// Waiting for the next measurement.
+    Idle,
+    /// Heating and starting a measurement.
+    Starting,
+    /// Waiting for the measurement, which has been polled this many times.
+    Measuring(u32),
+    Reading(u32),
+}
+
+pub struct Bme680<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    coefficients: Cell<[u8; COEFFICIENTS_LEN]>,
+    calibration: Cell<Calibration>,
+    bme688: Cell<bool>,
+    /// When the last measurement was started.
+    measured_at: Cell<A::Ticks>,
+    measurement: OptionalCell<Measurement>,
+    /// Set points of the heater: a temperature in °C and a duration in ms.
+    heater_profile: Cell<[(u16, u16); MAX_HEATER_STEPS]>,
+    heater_steps: Cell<usize>,
+    /// The set point of the next measurement.
+    step: Cell<usize>,
+    iaq: Cell<IaqEstimate>,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+    humidity_client: OptionalCell<&'a dyn HumidityClient>,
+    iaq_client: OptionalCell<&'a dyn IndoorAirQualityClient>,
+    temperature_pending: Cell<bool>,
+    humidity_pending: Cell<bool>,
+    iaq_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Bme680<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Bme680<'a, A, I> {
+        Bme680 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Off),
+            coefficients: Cell::new([0; COEFFICIENTS_LEN]),
+            calibration: Cell::new(Calibration::default()),
+            bme688: Cell::new(false),
+            measured_at: Cell::new(alarm.now()),
+            measurement: OptionalCell::empty(),
+            heater_profile: Cell::new([DEFAULT_HEATER_STEP; MAX_HEATER_STEPS]),
+            heater_steps: Cell::new(1),
+            step: Cell::new(0),
+            iaq: Cell::new(IaqEstimate::default()),
+            temperature_client: OptionalCell::empty(),
+            humidity_client: OptionalCell::empty(),
+            iaq_client: OptionalCell::empty(),
+            temperature_pending: Cell::new(false),
+            humidity_pending: Cell::new(false),
+            iaq_pending: Cell::new(false),
+        }
+    }
+
+    /// Identify the sensor, read its calibration and start measuring.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Off {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.read(State::Identify, REG_CHIP_ID, 1)
+    }
+
+    /// The last measurement, if there was one.
+    pub fn last_measurement(&self) -> Option<Measurement> {
+        self.measurement.extract()
+    }
+
+    /// Read `len` bytes from `register`.
+    fn read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = register;
+        self.state.set(state);
+        self.i2c.write_read(buffer, 1, len).map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            e.into()
+        })
+    }
+
+    /// Heat the sensor to the next set point, and start a measurement.
+    fn sample(&self) {
+        self.measured_at.set(self.alarm.now());
+        let result = self
+            .buffer
+            .take()
+            .ok_or(ErrorCode::BUSY)
+            .and_then(|buffer| {
+                let (temperature, duration) = self.heater_profile.get()[self.step.get()];
+                let ambient = self
+                    .measurement
+                    .map_or(DEFAULT_AMBIENT_C, |m| m.temperature / 100);
+                let run_gas = if self.bme688.get() {
+                    RUN_GAS_BME688
+                } else {
+                    RUN_GAS_BME680
+                };
+                // Pairs of registers and values, with the mode last.
+                let writes = [
+                    REG_RES_HEAT_0,
+                    self.calibration
+                        .get()
+                        .heater_resistance(temperature, ambient),
+                    REG_GAS_WAIT_0,
+                    gas_wait(duration),
+                    REG_CTRL_GAS_1,
+                    run_gas,
+                    REG_CTRL_HUM,
+                    OSRS_H_1X,
+                    REG_CTRL_MEAS,
+                    CTRL_MEAS_FORCED,
+                ];
+                buffer[..writes.len()].copy_from_slice(&writes);
+                self.state.set(State::Starting);
+                self.i2c.write(buffer, writes.len()).map_err(|(e, buffer)| {
+                    self.buffer.replace(buffer);
+                    e.into()
+                })
+            });
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+
+    /// Compensate the field data in `buffer`.
+    fn compensate(&self, buffer: &[u8]) -> Measurement {
+        let calibration = self.calibration.get();
+        let adc_20 = |i: usize| {
+            (buffer[i] as u32) << 12 | (buffer[i + 1] as u32) << 4 | (buffer[i + 2] as u32) >> 4
+        };
+        let (t_fine, temperature) = calibration.temperature(adc_20(5));
+        let humidity_adc = (buffer[8] as u32) << 8 | buffer[9] as u32;
+
+        // The gas registers moved in the BME688.
+        let gas = if self.bme688.get() { 15 } else { 13 };
+        let gas_adc = (buffer[gas] as u16) << 2 | (buffer[gas + 1] >> 6) as u16;
+        let gas_range = buffer[gas + 1] & 0x0F;
+        let gas_resistance = if buffer[gas + 1] & (GAS_VALID | HEAT_STAB) == GAS_VALID | HEAT_STAB {
+            if self.bme688.get() {
+                Some(gas_resistance_bme688(gas_adc, gas_range))
+            } else {
+                Some(calibration.gas_resistance(gas_adc, gas_range))
+            }
+        } else {
+            None
+        };
+
+        Measurement {
+            temperature,
+            pressure: calibration.pressure(adc_20(2), t_fine),
+            humidity: calibration.humidity(humidity_adc, t_fine),
+            gas_resistance,
+        }
+    }
+
+    /// Answer the requests with a measurement, and wait for the next one.
+    fn finish(&self, result: Result<Measurement, ErrorCode>) {
+        self.state.set(State::Idle);
+        match result {
+            Ok(measurement) => {
+                self.measurement.set(measurement);
+                let step = self.step.get();
+                let score = measurement.gas_resistance.and_then(|ohms| {
+                    let mut iaq = self.iaq.get();
+                    let score = iaq.process(step, ohms, measurement.humidity);
+                    self.iaq.set(iaq);
+                    score
+                });
+                self.step.set((step + 1) % self.heater_steps.get());
+
+                if self.temperature_pending.take() {
+                    self.temperature_client
+                        .map(|client| client.callback(Ok(measurement.temperature)));
+                }
+                if self.humidity_pending.take() {
+                    self.humidity_client
+                        .map(|client| client.callback(measurement.humidity as usize / 10));
+                }
+                if let Some(score) = score {
+                    if self.iaq_pending.take() {
+                        self.iaq_client.map(|client| client.iaq_score(Ok(score)));
+                    }
+                }
+            }
+            // Humidity readings cannot fail, so they wait for the next
+            // measurement.
+            Err(e) => {
+                if self.temperature_pending.take() {
+                    self.temperature_client
+                        .map(|client| client.callback(Err(e)));
+                }
+                if self.iaq_pending.take() {
+                    self.iaq_client.map(|client| client.iaq_score(Err(e)));
+                }
+            }
+        }
+        self.alarm.set_alarm(
+            self.measured_at.get(),
+            self.alarm.ticks_from_ms(SAMPLE_INTERVAL_MS),
+        );
+    }
+
+    /// Stop starting up if it failed, or else fail the requests.
+    fn fail(&self, e: ErrorCode) {
+        match self.state.get() {
+            State::Identify | State::Coefficients(_) => {
+                kernel::debug!("bme680: failed to start: {:?}", e);
+                self.state.set(State::Off);
+            }
+            _ => self.finish(Err(e)),
+        }
+    }
+
+    fn request(&self, pending: &Cell<bool>) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> TemperatureDriver<'a> for Bme680<'a, A, I> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.request(&self.temperature_pending)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> HumidityDriver<'a> for Bme680<'a, A, I> {
+    fn set_client(&self, client: &'a dyn HumidityClient) {
+        self.humidity_client.set(client);
+    }
+
+    fn read_humidity(&self) -> Result<(), ErrorCode> {
+        self.request(&self.humidity_pending)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> IndoorAirQuality<'a> for Bme680<'a, A, I> {
+    fn set_client(&self, client: &'a dyn IndoorAirQualityClient) {
+        self.iaq_client.set(client);
+    }
+
+    fn read_iaq_score(&self) -> Result<(), ErrorCode> {
+        self.request(&self.iaq_pending)
+    }
+
+    /// The temperature is from 200 to 400 °C, and the duration from 1 to
+    /// 4032 ms.
+    fn set_heater_step(
+        &self,
+        step: usize,
+        temperature_c: u16,
+        duration_ms: u16,
+    ) -> Result<(), ErrorCode> {
+        if step >= MAX_HEATER_STEPS
+            || !(MIN_HEATER_TEMPERATURE_C..=MAX_HEATER_TEMPERATURE_C).contains(&temperature_c)
+            || !(1..=MAX_HEATER_DURATION_MS).contains(&duration_ms)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        let mut profile = self.heater_profile.get();
+        profile[step] = (temperature_c, duration_ms);
+        self.heater_profile.set(profile);
+        self.step.set(0);
+        self.iaq.set(IaqEstimate::default());
+        Ok(())
+    }
+
+    fn set_heater_steps(&self, steps: usize) -> Result<(), ErrorCode> {
+        if !(1..=MAX_HEATER_STEPS).contains(&steps) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.heater_steps.set(steps);
+        self.step.set(0);
+        self.iaq.set(IaqEstimate::default());
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AlarmClient for Bme680<'a, A, I> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => self.sample(),
+            State::Measuring(polls) => {
+                if let Err(e) = self.read(State::Reading(polls), REG_FIELD_0, FIELD_LEN) {
+                    self.finish(Err(e));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Bme680<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let mut coefficients = self.coefficients.get();
+        let (id, new_data) = (buffer[0], buffer[0] & NEW_DATA != 0);
+        let measurement = match state {
+            State::Coefficients(1) => {
+                coefficients[..COEFFICIENTS_1_LEN].copy_from_slice(&buffer[..COEFFICIENTS_1_LEN]);
+                None
+            }
+            State::Coefficients(2) => {
+                coefficients[COEFFICIENTS_1_LEN..COEFFICIENTS_1_LEN + COEFFICIENTS_2_LEN]
+                    .copy_from_slice(&buffer[..COEFFICIENTS_2_LEN]);
+                // RegVariantId follows the coefficients.
+                self.bme688
+                    .set(buffer[COEFFICIENTS_2_LEN + 1] == VARIANT_BME688);
+                None
+            }
+            State::Coefficients(_) => {
+                coefficients[COEFFICIENTS_1_LEN + COEFFICIENTS_2_LEN..]
+                    .copy_from_slice(&buffer[..COEFFICIENTS_3_LEN]);
+                None
+            }
+            State::Reading(_) if new_data && status.is_ok() => Some(self.compensate(buffer)),
+            _ => None,
+        };
+        self.coefficients.set(coefficients);
+        self.buffer.replace(buffer);
+
+        if let Err(e) = status {
+            self.fail(e.into());
+            return;
+        }
+
+        let result = match state {
+            State::Identify if id == CHIP_ID => self.read(
+                State::Coefficients(1),
+                REG_COEFFICIENTS_1,
+                COEFFICIENTS_1_LEN,
+            ),
+            State::Identify => Err(ErrorCode::NODEVICE),
+            // Read up to RegVariantId.
+            State::Coefficients(1) => self.read(
+                State::Coefficients(2),
+                REG_COEFFICIENTS_2,
+                COEFFICIENTS_2_LEN + 2,
+            ),
+            State::Coefficients(2) => self.read(
+                State::Coefficients(3),
+                REG_COEFFICIENTS_3,
+                COEFFICIENTS_3_LEN,
+            ),
+            State::Coefficients(_) => {
+                self.calibration.set(Calibration::new(&coefficients));
+                self.state.set(State::Idle);
+                self.sample();
+                Ok(())
+            }
+            State::Starting => {
+                let (_, duration) = self.heater_profile.get()[self.step.get()];
+                self.state.set(State::Measuring(0));
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(TPH_DURATION_MS + duration as u32),
+                );
+                Ok(())
+            }
+            State::Reading(polls) => match measurement {
+                Some(measurement) => {
+                    self.finish(Ok(measurement));
+                    Ok(())
+                }
+                None if polls < MAX_POLLS => {
+                    self.state.set(State::Measuring(polls + 1));
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(POLL_MS));
+                    Ok(())
+                }
+                None => Err(ErrorCode::FAIL),
+            },
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use std::boxed::Box;
+
+    /// Coefficients of a sensor at 29.42 °C, 1020.24 hPa and 41.348 %RH
+    /// with the readings of `field`.
+    const COEFFICIENTS: [u8; COEFFICIENTS_LEN] = [
+        111, 102, 3, 0, 33, 142, 228, 214, 88, 0, 64, 28, 115, 255, 41, 30, 0, 0, 253, 255, 123,
+        246, 30, 63, 53, 50, 0, 45, 20, 120, 156, 70, 102, 44, 202, 226, 18, 43, 0, 16, 0, 0,
+    ];
+
+    /// Field data of a BME680 with a valid gas reading.
+    fn field() -> [u8; FIELD_LEN] {
+        let mut field = [0; FIELD_LEN];
+        let put_20 = |field: &mut [u8], adc: u32| {
+            field[0] = (adc >> 12) as u8;
+            field[1] = (adc >> 4) as u8;
+            field[2] = (adc << 4) as u8;
+        };
+        field[0] = NEW_DATA;
+        put_20(&mut field[2..], 346000);
+        put_20(&mut field[5..], 513000);
+        field[8..10].copy_from_slice(&21000u16.to_be_bytes());
+        field[13] = (600 >> 2) as u8;
+        field[14] = ((600 & 0x03) << 6) as u8 | GAS_VALID | HEAT_STAB | 5;
+        field
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        temperature: Cell<Option<Result<i32, ErrorCode>>>,
+        humidity: Cell<Option<usize>>,
+        iaq: Cell<Option<Result<u16, ErrorCode>>>,
+    }
+
+    impl TemperatureClient for MockClient {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.temperature.set(Some(value));
+        }
+    }
+
+    impl HumidityClient for MockClient {
+        fn callback(&self, value: usize) {
+            self.humidity.set(Some(value));
+        }
+    }
+
+    impl IndoorAirQualityClient for MockClient {
+        fn iaq_score(&self, score: Result<u16, ErrorCode>) {
+            self.iaq.set(Some(score));
+        }
+    }
+
+    type TestBme680 = Bme680<'static, MockAlarm<'static>, MockI2c>;
+
+    #[test]
+    fn compensates_with_calibration() {
+        let calibration = Calibration::new(&COEFFICIENTS);
+        assert_eq!((calibration.h1, calibration.h2), (805, 1011));
+        assert_eq!(calibration.p9, -2437);
+        assert_eq!(calibration.temperature(513000), (150614, 2942));
+        assert_eq!(calibration.pressure(346000, 150614), 102024);
+        assert_eq!(calibration.humidity(21000, 150614), 41348);
+        assert_eq!(calibration.gas_resistance(600, 5), 232818);
+        assert_eq!(gas_resistance_bme688(600, 5), 1878800);
+        assert_eq!(calibration.heater_resistance(320, 25), 114);
+        assert_eq!(gas_wait(150), 0x65);
+        assert_eq!(gas_wait(MAX_HEATER_DURATION_MS), 0xFF);
+    }
+
+    #[test]
+    fn measures_and_estimates_iaq() {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let alarm = Box::leak(Box::new(MockAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let bme680: &TestBme680 = Box::leak(Box::new(Bme680::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(bme680);
+        TemperatureDriver::set_client(bme680, client);
+        HumidityDriver::set_client(bme680, client);
+        IndoorAirQuality::set_client(bme680, client);
+        assert_eq!(bme680.read_temperature(), Err(ErrorCode::OFF));
+        assert_eq!(bme680.set_heater_step(0, 450, 100), Err(ErrorCode::INVAL));
+        assert_eq!(bme680.set_heater_steps(11), Err(ErrorCode::INVAL));
+
+        // The chip ID, and the three blocks of coefficients with the variant.
+        assert_eq!(bme680.start(), Ok(()));
+        i2c.complete(bme680, &[CHIP_ID]);
+        i2c.complete(bme680, &COEFFICIENTS[..23]);
+        i2c.complete(bme680, &COEFFICIENTS[23..37]);
+        i2c.complete(bme680, &COEFFICIENTS[37..]);
+        assert_eq!(
+            i2c.writes(),
+            [
+                [REG_CHIP_ID].to_vec(),
+                [REG_COEFFICIENTS_1].to_vec(),
+                [REG_COEFFICIENTS_2].to_vec(),
+                [REG_COEFFICIENTS_3].to_vec(),
+                [0x5A, 114, 0x64, 0x65, 0x71, 0x10, 0x72, 0x01, 0x74, 0x25].to_vec(),
+            ]
+        );
+
+        assert_eq!(bme680.read_temperature(), Ok(()));
+        assert_eq!(bme680.read_humidity(), Ok(()));
+        assert_eq!(bme680.read_iaq_score(), Ok(()));
+        assert_eq!(bme680.read_iaq_score(), Err(ErrorCode::BUSY));
+        i2c.complete(bme680, &[]);
+        assert_eq!(alarm.dt(), Some(TPH_DURATION_MS + 150));
+
+        // A measurement that is not done yet is polled again.
+        alarm.fire();
+        i2c.complete(bme680, &[0; FIELD_LEN]);
+        assert_eq!(alarm.dt(), Some(POLL_MS));
+        alarm.fire();
+        assert_eq!(i2c.writes().last(), Some(&[REG_FIELD_0].to_vec()));
+        i2c.complete(bme680, &field());
+        assert_eq!(client.temperature.get(), Some(Ok(2942)));
+        assert_eq!(client.humidity.get(), Some(4134));
+        assert_eq!(
+            bme680.last_measurement(),
+            Some(Measurement {
+                temperature: 2942,
+                pressure: 102024,
+                humidity: 41348,
+                gas_resistance: Some(232818),
+            })
+        );
+        assert_eq!(alarm.dt(), Some(SAMPLE_INTERVAL_MS));
+
+        // The air quality comes after the burn-in, with the gas resistance
+        // at the baseline and the humidity close to the optimum.
+        for _ in 1..=BURN_IN_SAMPLES {
+            assert_eq!(client.iaq.get(), None);
+            alarm.fire();
+            i2c.complete(bme680, &[]);
+            alarm.fire();
+            i2c.complete(bme680, &field());
+        }
+        assert_eq!(client.iaq.get(), Some(Ok(5)));
+    }
+}
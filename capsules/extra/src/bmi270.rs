@@ -0,0 +1,866 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Bosch BMI270 6-axis IMU.
+//!
+//! <https://www.bosch-sensortec.com/products/motion-sensors/imus/bmi270/>
+//!
+//! The BMI270 is connected over I2C or SPI, and provides accelerometer and
+//! gyroscope readings through the `NineDof` HIL.
+//!
+//! Before the BMI270 produces valid data, the configuration file for its
+//! feature engine has to be uploaded. This file, about 8 kB, is published by
+//! Bosch as part of the BMI270 Sensor API and is passed to the driver by the
+//! board. The driver initializes the sensor when it is first read: it
+//! streams the file to the sensor in bursts, then polls the internal status
+//! register until the sensor reports that initialization is complete, and
+//! only then enables the accelerometer and the gyroscope.
+//!
+//! Accelerometer readings are in milli-g, and gyroscope readings in
+//! millidegrees per second.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bmi270 = components::bmi270::Bmi270Component::new(
+//!     bmi270_transport,
+//!     mux_alarm,
+//!     &BMI270_CONFIG_FILE,
+//!     capsules_extra::bmi270::OutputDataRate::Hz100,
+//!     capsules_extra::bmi270::AccelRange::G4,
+//!     capsules_extra::bmi270::OutputDataRate::Hz200,
+//!     capsules_extra::bmi270::GyroRange::Dps2000,
+//! )
+//! .finalize(components::bmi270_component_static!(
+//!     capsules_extra::bmi270::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(bmi270));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c;
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::spi;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address when SDO is pulled low. It is `0x69` when SDO is high.
+pub const I2C_ADDRESS: u8 = 0x68;
+
+/// Number of bytes of the configuration file written in one transfer.
+pub const BURST_LEN: usize = 32;
+
+/// Length of the buffers used for transfers: a register address followed by
+/// a burst of data.
+pub const BUFFER_LEN: usize = BURST_LEN + 1;
+
+const CHIP_ID: u8 = 0x24;
+
+mod register {
+    pub const CHIP_ID: u8 = 0x00;
+    pub const DATA_ACC: u8 = 0x0C;
+    pub const DATA_GYR: u8 = 0x12;
+    pub const INTERNAL_STATUS: u8 = 0x21;
+    pub const ACC_CONF: u8 = 0x40;
+    pub const INIT_CTRL: u8 = 0x59;
+    pub const INIT_ADDR_0: u8 = 0x5B;
+    pub const INIT_DATA: u8 = 0x5E;
+    pub const PWR_CONF: u8 = 0x7C;
+    pub const PWR_CTRL: u8 = 0x7D;
+}
+
+/// `INTERNAL_STATUS` message once the configuration file is loaded.
+const STATUS_INIT_OK: u8 = 0x01;
+const STATUS_MESSAGE_MASK: u8 = 0x0F;
+
+/// Enable the accelerometer, the gyroscope and the temperature sensor.
+const PWR_CTRL_ENABLE: u8 = 0x0E;
+/// Keep the fast power up of the feature engine, without advanced power
+/// save.
+const PWR_CONF_PERFORMANCE: u8 = 0x02;
+/// Filter performance mode, normal bandwidth.
+const ACC_CONF_PERFORMANCE: u8 = 0xA0;
+/// Filter and noise performance modes, normal bandwidth.
+const GYR_CONF_PERFORMANCE: u8 = 0xE0;
+
+/// Time to wait after disabling advanced power save, which must be at least
+/// 450 us.
+const POWER_UP_DELAY_MS: u32 = 1;
+/// Time between checks of the internal status after the upload.
+const INIT_POLL_INTERVAL_MS: u32 = 20;
+const INIT_POLL_ATTEMPTS: u8 = 10;
+
+/// Output data rate of the accelerometer or the gyroscope.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputDataRate {
+    Hz25 = 0x06,
+    Hz50 = 0x07,
+    Hz100 = 0x08,
+    Hz200 = 0x09,
+    Hz400 = 0x0A,
+    Hz800 = 0x0B,
+    Hz1600 = 0x0C,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccelRange {
+    G2 = 0,
+    G4 = 1,
+    G8 = 2,
+    G16 = 3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GyroRange {
+    Dps2000 = 0,
+    Dps1000 = 1,
+    Dps500 = 2,
+    Dps250 = 3,
+    Dps125 = 4,
+}
+
+/// A bus over which the BMI270 is connected.
+///
+/// Byte 0 of the buffers passed to the transport holds the address of the
+/// first register to access.
+pub trait Transport<'a> {
+    fn set_client(&self, client: &'a dyn TransportClient);
+
+    /// Write the `len` bytes starting at `buffer[1]` to consecutive
+    /// registers.
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Read `len` consecutive registers into the start of `buffer`.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait TransportClient {
+    /// Called when a write or a read finishes.
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>);
+}
+
+/// BMI270 connected over I2C.
+pub struct I2CTransport<'a, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, I: i2c::I2CDevice> I2CTransport<'a, I> {
+    pub fn new(i2c: &'a I) -> I2CTransport<'a, I> {
+        I2CTransport {
+            i2c,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> Transport<'a> for I2CTransport<'a, I> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.i2c.enable();
+        self.i2c.write(buffer, len + 1).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len == 0 || len > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.i2c.enable();
+        self.i2c.write_read(buffer, 1, len).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> i2c::I2CClient for I2CTransport<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        self.i2c.disable();
+        self.client
+            .map(|client| client.transfer_done(buffer, status.map_err(|e| e.into())));
+    }
+}
+
+/// Set in the register address to read over SPI.
+const SPI_READ: u8 = 0x80;
+
+/// BMI270 connected over SPI.
+///
+/// Over SPI, the BMI270 sends a dummy byte before the register contents,
+/// which is removed before the buffer is returned.
+pub struct SpiTransport<'a, S: spi::SpiMasterDevice<'a>> {
+    spi: &'a S,
+    /// Write buffer used while reading.
+    tx_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> SpiTransport<'a, S> {
+    pub fn new(spi: &'a S, tx_buffer: &'static mut [u8]) -> SpiTransport<'a, S> {
+        SpiTransport {
+            spi,
+            tx_buffer: TakeCell::new(tx_buffer),
+            len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn configure(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            rate,
+        )
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> Transport<'a> for SpiTransport<'a, S> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        buffer[0] &= !SPI_READ;
+        self.len.set(0);
+        self.spi
+            .read_write_bytes(buffer, None, len + 1)
+            .map_err(|(e, buffer, _)| (e, buffer))
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let tx_buffer = match self.tx_buffer.take() {
+            Some(tx_buffer) => tx_buffer,
+            None => return Err((ErrorCode::BUSY, buffer)),
+        };
+        if len == 0 || len + 2 > buffer.len() || len + 2 > tx_buffer.len() {
+            self.tx_buffer.replace(tx_buffer);
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        tx_buffer[..len + 2].fill(0);
+        tx_buffer[0] = buffer[0] | SPI_READ;
+        self.len.set(len);
+        self.spi
+            .read_write_bytes(tx_buffer, Some(buffer), len + 2)
+            .map_err(|(e, tx_buffer, buffer)| {
+                self.tx_buffer.replace(tx_buffer);
+                (e, buffer.unwrap())
+            })
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> spi::SpiMasterClient for SpiTransport<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let buffer = match read_buffer {
+            Some(read_buffer) => {
+                self.tx_buffer.replace(write_buffer);
+                // Skip the address byte and the dummy byte.
+                read_buffer.copy_within(2..self.len.get() + 2, 0);
+                read_buffer
+            }
+            None => write_buffer,
+        };
+        self.client
+            .map(|client| client.transfer_done(buffer, status));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Sensor {
+    Accelerometer,
+    Gyroscope,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    /// The configuration file has not been uploaded yet.
+    Uninitialized,
+    /// Initialization failed, the sensor cannot be used.
+    Failed,
+    Idle,
+    /// Reading the chip ID for the first time, which over SPI only switches
+    /// the sensor to SPI mode.
+    DummyRead,
+    ReadChipId,
+    DisableAdvancedPowerSave,
+    PowerUpDelay,
+    PrepareUpload,
+    /// Setting the address at which to write the configuration file from
+    /// this offset.
+    SetUploadAddress(usize),
+    /// Writing a burst of the configuration file from this offset.
+    Upload(usize),
+    CompleteUpload,
+    /// Waiting before this check of the internal status.
+    WaitInit(u8),
+    ReadStatus(u8),
+    EnableSensors,
+    ConfigureSensors,
+    SetPowerMode,
+    Read(Sensor),
+}
+
+pub struct Bmi270<'a, T: Transport<'a>, A: Alarm<'a>> {
+    transport: &'a T,
+    alarm: &'a A,
+    config: &'static [u8],
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// Reading started once initialization completes.
+    pending: OptionalCell<Sensor>,
+    accel_rate: Cell<OutputDataRate>,
+    accel_range: Cell<AccelRange>,
+    gyro_rate: Cell<OutputDataRate>,
+    gyro_range: Cell<GyroRange>,
+    client: OptionalCell<&'a dyn NineDofClient>,
+}
+
+impl<'a, T: Transport<'a>, A: Alarm<'a>> Bmi270<'a, T, A> {
+    /// `config` is the configuration file of the BMI270 feature engine.
+    pub fn new(
+        transport: &'a T,
+        alarm: &'a A,
+        config: &'static [u8],
+        buffer: &'static mut [u8],
+    ) -> Bmi270<'a, T, A> {
+        Bmi270 {
+            transport,
+            alarm,
+            config,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Uninitialized),
+            pending: OptionalCell::empty(),
+            accel_rate: Cell::new(OutputDataRate::Hz100),
+            accel_range: Cell::new(AccelRange::G2),
+            gyro_rate: Cell::new(OutputDataRate::Hz200),
+            gyro_range: Cell::new(GyroRange::Dps2000),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the output data rates and ranges. They are applied when the
+    /// sensor is initialized, so this has to be called before the first
+    /// reading.
+    pub fn configure(
+        &self,
+        accel_rate: OutputDataRate,
+        accel_range: AccelRange,
+        gyro_rate: OutputDataRate,
+        gyro_range: GyroRange,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Uninitialized {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.accel_rate.set(accel_rate);
+        self.accel_range.set(accel_range);
+        self.gyro_rate.set(gyro_rate);
+        self.gyro_range.set(gyro_range);
+        Ok(())
+    }
+
+    fn start_reading(&self, sensor: Sensor) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => self.read(State::Read(sensor), Self::data_register(sensor), 6),
+            State::Uninitialized => {
+                // The configuration file is written two bytes at a time.
+                if self.config.is_empty() || self.config.len() % 2 != 0 {
+                    return Err(ErrorCode::INVAL);
+                }
+                self.read(State::DummyRead, register::CHIP_ID, 1)?;
+                self.pending.set(sensor);
+                Ok(())
+            }
+            State::Failed => Err(ErrorCode::NODEVICE),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    fn data_register(sensor: Sensor) -> u8 {
+        match sensor {
+            Sensor::Accelerometer => register::DATA_ACC,
+            Sensor::Gyroscope => register::DATA_GYR,
+        }
+    }
+
+    fn write(&self, state: State, register: u8, data: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            buffer[1..data.len() + 1].copy_from_slice(data);
+            self.state.set(state);
+            self.transport
+                .write(buffer, data.len())
+                .map_err(|(e, buffer)| self.transfer_failed(e, buffer))
+        })
+    }
+
+    fn read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            self.state.set(state);
+            self.transport
+                .read(buffer, len)
+                .map_err(|(e, buffer)| self.transfer_failed(e, buffer))
+        })
+    }
+
+    fn transfer_failed(&self, error: ErrorCode, buffer: &'static mut [u8]) -> ErrorCode {
+        self.buffer.replace(buffer);
+        self.state.set(match self.state.get() {
+            State::Read(_) => State::Idle,
+            _ => State::Uninitialized,
+        });
+        error
+    }
+
+    fn wait(&self, state: State, ms: u32) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Writes the next burst of the configuration file, or completes the
+    /// upload once the whole file has been written.
+    fn upload(&self, offset: usize) -> Result<(), ErrorCode> {
+        if offset < self.config.len() {
+            // The address is in 16-bit words, split into 4 and 8 bits.
+            let word = offset / 2;
+            self.write(
+                State::SetUploadAddress(offset),
+                register::INIT_ADDR_0,
+                &[(word & 0x0F) as u8, (word >> 4) as u8],
+            )
+        } else {
+            self.write(State::CompleteUpload, register::INIT_CTRL, &[0x01])
+        }
+    }
+
+    /// Stops initialization. The pending reading reports zeros.
+    fn init_failed(&self) {
+        self.state.set(State::Failed);
+        if self.pending.take().is_some() {
+            self.client.map(|client| client.callback(0, 0, 0));
+        }
+    }
+
+    fn init_done(&self) {
+        self.state.set(State::Idle);
+        if let Some(sensor) = self.pending.take() {
+            if self.start_reading(sensor).is_err() {
+                self.client.map(|client| client.callback(0, 0, 0));
+            }
+        }
+    }
+
+    /// Runs the step after `state` has finished.
+    fn next(&self, state: State, data: &[u8]) -> Result<(), ErrorCode> {
+        match state {
+            State::DummyRead => self.read(State::ReadChipId, register::CHIP_ID, 1),
+            State::ReadChipId => {
+                if data[0] != CHIP_ID {
+                    return Err(ErrorCode::NODEVICE);
+                }
+                self.write(State::DisableAdvancedPowerSave, register::PWR_CONF, &[0x00])
+            }
+            State::DisableAdvancedPowerSave => {
+                self.wait(State::PowerUpDelay, POWER_UP_DELAY_MS);
+                Ok(())
+            }
+            State::PowerUpDelay => self.write(State::PrepareUpload, register::INIT_CTRL, &[0x00]),
+            State::PrepareUpload => self.upload(0),
+            State::SetUploadAddress(offset) => {
+                let end = (offset + BURST_LEN).min(self.config.len());
+                self.write(
+                    State::Upload(offset),
+                    register::INIT_DATA,
+                    &self.config[offset..end],
+                )
+            }
+            State::Upload(offset) => self.upload(offset + BURST_LEN),
+            State::CompleteUpload => {
+                self.wait(State::WaitInit(0), INIT_POLL_INTERVAL_MS);
+                Ok(())
+            }
+            State::WaitInit(attempt) => {
+                self.read(State::ReadStatus(attempt), register::INTERNAL_STATUS, 1)
+            }
+            State::ReadStatus(attempt) => {
+                if data[0] & STATUS_MESSAGE_MASK == STATUS_INIT_OK {
+                    self.write(State::EnableSensors, register::PWR_CTRL, &[PWR_CTRL_ENABLE])
+                } else if attempt + 1 < INIT_POLL_ATTEMPTS {
+                    self.wait(State::WaitInit(attempt + 1), INIT_POLL_INTERVAL_MS);
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                }
+            }
+            State::EnableSensors => self.write(
+                State::ConfigureSensors,
+                register::ACC_CONF,
+                &[
+                    ACC_CONF_PERFORMANCE | self.accel_rate.get() as u8,
+                    self.accel_range.get() as u8,
+                    GYR_CONF_PERFORMANCE | self.gyro_rate.get() as u8,
+                    self.gyro_range.get() as u8,
+                ],
+            ),
+            State::ConfigureSensors => self.write(
+                State::SetPowerMode,
+                register::PWR_CONF,
+                &[PWR_CONF_PERFORMANCE],
+            ),
+            State::SetPowerMode => {
+                self.init_done();
+                Ok(())
+            }
+            State::Uninitialized | State::Failed | State::Idle | State::Read(_) => Ok(()),
+        }
+    }
+
+    fn scale(&self, sensor: Sensor, raw: i16) -> usize {
+        // Full scale in milli-g or millidegrees per second.
+        let full_scale: i64 = match sensor {
+            Sensor::Accelerometer => 2_000 << self.accel_range.get() as u8,
+            Sensor::Gyroscope => 2_000_000 >> self.gyro_range.get() as u8,
+        };
+        (raw as i64 * full_scale / 32768) as isize as usize
+    }
+
+    fn report(&self, sensor: Sensor, data: &[u8]) {
+        let axis = |i: usize| self.scale(sensor, i16::from_le_bytes([data[i], data[i + 1]]));
+        self.client
+            .map(|client| client.callback(axis(0), axis(2), axis(4)));
+    }
+}
+
+impl<'a, T: Transport<'a>, A: Alarm<'a>> TransportClient for Bmi270<'a, T, A> {
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>) {
+        let state = self.state.get();
+        if let State::Read(sensor) = state {
+            self.state.set(State::Idle);
+            match status {
+                Ok(()) => self.report(sensor, buffer),
+                Err(_) => {
+                    self.client.map(|client| client.callback(0, 0, 0));
+                }
+            }
+            self.buffer.replace(buffer);
+            return;
+        }
+
+        let data = [buffer[0]];
+        self.buffer.replace(buffer);
+        if status.and_then(|()| self.next(state, &data)).is_err() {
+            self.init_failed();
+        }
+    }
+}
+
+impl<'a, T: Transport<'a>, A: Alarm<'a>> AlarmClient for Bmi270<'a, T, A> {
+    fn alarm(&self) {
+        if self.next(self.state.get(), &[]).is_err() {
+            self.init_failed();
+        }
+    }
+}
+
+impl<'a, T: Transport<'a>, A: Alarm<'a>> NineDof<'a> for Bmi270<'a, T, A> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        self.start_reading(Sensor::Accelerometer)
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        self.start_reading(Sensor::Gyroscope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Access {
+        Read(u8),
+        Write(u8, u8),
+    }
+
+    /// A BMI270 whose internal status reports that initialization is
+    /// complete after a number of checks.
+    struct MockTransport {
+        buffer: TakeCell<'static, [u8]>,
+        len: Cell<usize>,
+        read: Cell<bool>,
+        accesses: RefCell<Vec<Access>>,
+        uploaded: RefCell<Vec<u8>>,
+        status_reads: Cell<usize>,
+        status_ready_after: usize,
+        client: OptionalCell<&'static dyn TransportClient>,
+    }
+
+    impl MockTransport {
+        /// Completes the transfer in progress, if any.
+        fn complete(&self) -> bool {
+            let buffer = match self.buffer.take() {
+                Some(buffer) => buffer,
+                None => return false,
+            };
+            let register = buffer[0];
+            let len = self.len.get();
+            if self.read.get() {
+                self.accesses.borrow_mut().push(Access::Read(register));
+                match register {
+                    register::CHIP_ID => buffer[0] = CHIP_ID,
+                    register::INTERNAL_STATUS => {
+                        let reads = self.status_reads.get() + 1;
+                        self.status_reads.set(reads);
+                        buffer[0] = if reads >= self.status_ready_after {
+                            STATUS_INIT_OK
+                        } else {
+                            0x00
+                        };
+                    }
+                    _ => {
+                        // X = 0.5, Y = -0.25 and Z = 1 of the full scale.
+                        buffer[..len].copy_from_slice(&[0x00, 0x40, 0x00, 0xE0, 0xFF, 0x7F]);
+                    }
+                }
+            } else if register == register::INIT_DATA {
+                self.uploaded
+                    .borrow_mut()
+                    .extend_from_slice(&buffer[1..len + 1]);
+            } else {
+                for (i, &value) in buffer[1..len + 1].iter().enumerate() {
+                    self.accesses
+                        .borrow_mut()
+                        .push(Access::Write(register + i as u8, value));
+                }
+            }
+            self.client
+                .map(|client| client.transfer_done(buffer, Ok(())));
+            true
+        }
+    }
+
+    impl Transport<'static> for MockTransport {
+        fn set_client(&self, client: &'static dyn TransportClient) {
+            self.client.set(client);
+        }
+
+        fn write(
+            &self,
+            buffer: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.read.set(false);
+            self.len.set(len);
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+
+        fn read(
+            &self,
+            buffer: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.read.set(true);
+            self.len.set(len);
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+    }
+
+    struct Client {
+        readings: RefCell<Vec<(usize, usize, usize)>>,
+    }
+
+    impl NineDofClient for Client {
+        fn callback(&self, arg1: usize, arg2: usize, arg3: usize) {
+            self.readings.borrow_mut().push((arg1, arg2, arg3));
+        }
+    }
+
+    struct Setup {
+        bmi270: &'static Bmi270<'static, MockTransport, MockAlarm<'static>>,
+        transport: &'static MockTransport,
+        alarm: &'static MockAlarm<'static>,
+        client: &'static Client,
+    }
+
+    fn setup(config: &'static [u8], status_ready_after: usize) -> Setup {
+        let transport = Box::leak(Box::new(MockTransport {
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            read: Cell::new(false),
+            accesses: RefCell::new(Vec::new()),
+            uploaded: RefCell::new(Vec::new()),
+            status_reads: Cell::new(0),
+            status_ready_after,
+            client: OptionalCell::empty(),
+        }));
+        let alarm = Box::leak(Box::new(MockAlarm::new()));
+        let buffer = Box::leak(Box::new([0; BUFFER_LEN]));
+        let bmi270 = Box::leak(Box::new(Bmi270::new(transport, alarm, config, buffer)));
+        let client = Box::leak(Box::new(Client {
+            readings: RefCell::new(Vec::new()),
+        }));
+        transport.set_client(bmi270);
+        alarm.set_alarm_client(bmi270);
+        bmi270.set_client(client);
+        Setup {
+            bmi270,
+            transport,
+            alarm,
+            client,
+        }
+    }
+
+    /// Completes transfers and fires alarms until the driver stops.
+    fn run(setup: &Setup) {
+        loop {
+            if setup.transport.complete() {
+                continue;
+            }
+            if !setup.alarm.is_armed() {
+                break;
+            }
+            setup.alarm.fire();
+        }
+    }
+
+    fn config() -> &'static [u8] {
+        let config: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        Box::leak(config.into_boxed_slice())
+    }
+
+    #[test]
+    fn init_waits_for_status_before_reading() {
+        let setup = setup(config(), 3);
+        setup
+            .bmi270
+            .configure(
+                OutputDataRate::Hz100,
+                AccelRange::G4,
+                OutputDataRate::Hz200,
+                GyroRange::Dps2000,
+            )
+            .unwrap();
+        assert_eq!(setup.bmi270.read_accelerometer(), Ok(()));
+        run(&setup);
+
+        assert_eq!(&setup.transport.uploaded.borrow()[..], config());
+        assert_eq!(setup.transport.status_reads.get(), 3);
+
+        let accesses = setup.transport.accesses.borrow();
+        let position = |access| accesses.iter().position(|&a| a == access).unwrap();
+        // The upload is started and completed around the whole file.
+        assert!(position(Access::Write(register::INIT_CTRL, 0x00)) < 4);
+        let complete = position(Access::Write(register::INIT_CTRL, 0x01));
+        assert_eq!(
+            accesses[..complete]
+                .iter()
+                .filter(|a| matches!(a, Access::Write(register::INIT_ADDR_0, _)))
+                .count(),
+            4
+        );
+        // The sensors are enabled, and read, only after the sensor reports
+        // that initialization is complete.
+        let statuses: Vec<usize> = accesses
+            .iter()
+            .enumerate()
+            .filter(|(_, &a)| a == Access::Read(register::INTERNAL_STATUS))
+            .map(|(i, _)| i)
+            .collect();
+        assert!(statuses.iter().all(|&i| i > complete));
+        let enable = position(Access::Write(register::PWR_CTRL, PWR_CTRL_ENABLE));
+        assert!(enable > statuses[2]);
+        assert!(position(Access::Read(register::DATA_ACC)) > enable);
+        assert!(accesses.contains(&Access::Write(register::ACC_CONF, 0xA8)));
+
+        assert_eq!(
+            &setup.client.readings.borrow()[..],
+            &[(2000, -1000isize as usize, 3999)]
+        );
+        assert_eq!(setup.bmi270.read_gyroscope(), Ok(()));
+    }
+
+    #[test]
+    fn init_fails_if_status_never_ready() {
+        let setup = setup(config(), usize::MAX);
+        assert_eq!(setup.bmi270.read_gyroscope(), Ok(()));
+        run(&setup);
+
+        assert_eq!(
+            setup.transport.status_reads.get(),
+            INIT_POLL_ATTEMPTS as usize
+        );
+        assert!(!setup
+            .transport
+            .accesses
+            .borrow()
+            .contains(&Access::Read(register::DATA_GYR)));
+        assert_eq!(&setup.client.readings.borrow()[..], &[(0, 0, 0)]);
+        assert_eq!(setup.bmi270.read_gyroscope(), Err(ErrorCode::NODEVICE));
+    }
+}
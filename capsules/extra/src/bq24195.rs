@@ -0,0 +1,394 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the BQ24195 single cell USB battery charger.
+//!
+//! <https://www.ti.com/lit/ds/symlink/bq24195.pdf>
+//!
+//! The charger is controlled over I2C. Its INT pin pulses low when the
+//! charge status changes or a fault occurs. The pin is set up by the
+//! component and delivers its interrupts to this driver, which then reads the
+//! system status (REG08) and fault (REG09) registers and reports why
+//! charging stopped, if it did.
+//!
+//! In host mode the charger resets all of its registers if the I2C
+//! watchdog is not kicked every 40 seconds. The driver disables the
+//! watchdog before its first write so that the settings it makes persist.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let bq24195 = components::bq24195::Bq24195Component::new(
+//!     i2c_mux,
+//!     capsules_extra::bq24195::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[CHARGER_INT_PIN],
+//! )
+//! .finalize(components::bq24195_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::battery_charger::{BatteryCharger, BatteryChargerClient, ChargerFault};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const BASE_ADDR: u8 = 0x6B;
+
+pub const BUFFER_SIZE: usize = 2;
+
+#[allow(dead_code)]
+enum Registers {
+    InputSourceControl = 0x00,
+    PowerOnConfiguration = 0x01,
+    ChargeCurrentControl = 0x02,
+    PrechargeTerminationCurrentControl = 0x03,
+    ChargeVoltageControl = 0x04,
+    ChargeTerminationTimerControl = 0x05,
+    ThermalRegulationControl = 0x06,
+    MiscOperationControl = 0x07,
+    SystemStatus = 0x08,
+    Fault = 0x09,
+    VendorPartRevision = 0x0A,
+}
+
+/// IINLIM field of REG00.
+const INPUT_LIMIT_MASK: u8 = 0x07;
+/// Input current limits selected by each IINLIM value, in mA.
+const INPUT_LIMITS_MA: [u16; 8] = [100, 150, 500, 900, 1200, 1500, 2000, 3000];
+
+/// CHG_CONFIG field of REG01.
+const CHARGE_CONFIG_MASK: u8 = 0x30;
+const CHARGE_CONFIG_DISABLE: u8 = 0x00;
+const CHARGE_CONFIG_CHARGE: u8 = 0x10;
+
+/// ICHG field of REG02.
+const CHARGE_CURRENT_MASK: u8 = 0xFC;
+const CHARGE_CURRENT_SHIFT: u8 = 2;
+const CHARGE_CURRENT_OFFSET_MA: u16 = 512;
+const CHARGE_CURRENT_STEP_MA: u16 = 64;
+const CHARGE_CURRENT_MAX_MA: u16 = 4544;
+
+/// WATCHDOG field of REG05.
+const WATCHDOG_MASK: u8 = 0x30;
+
+/// CHRG_STAT field of REG08.
+const CHARGE_STATUS_MASK: u8 = 0x30;
+const CHARGE_STATUS_DONE: u8 = 0x30;
+
+/// CHRG_FAULT field of REG09.
+const CHARGE_FAULT_MASK: u8 = 0x30;
+const CHARGE_FAULT_INPUT: u8 = 0x10;
+const CHARGE_FAULT_THERMAL: u8 = 0x20;
+const CHARGE_FAULT_TIMER: u8 = 0x30;
+/// BAT_FAULT bit of REG09.
+const BATTERY_FAULT: u8 = 0x08;
+
+/// IINLIM value for the largest input limit not above `ma`.
+fn input_limit_bits(ma: u16) -> Option<u8> {
+    INPUT_LIMITS_MA
+        .iter()
+        .rposition(|&limit| limit <= ma)
+        .map(|i| i as u8)
+}
+
+/// ICHG field for the largest charge current not above `ma`.
+fn charge_current_bits(ma: u16) -> Option<u8> {
+    if !(CHARGE_CURRENT_OFFSET_MA..=CHARGE_CURRENT_MAX_MA).contains(&ma) {
+        return None;
+    }
+    let steps = (ma - CHARGE_CURRENT_OFFSET_MA) / CHARGE_CURRENT_STEP_MA;
+    Some((steps as u8) << CHARGE_CURRENT_SHIFT)
+}
+
+/// Reasons charging stopped, from the system status and fault registers.
+fn decode_faults(status: u8, fault: u8) -> [Option<ChargerFault>; 2] {
+    let charge = match fault & CHARGE_FAULT_MASK {
+        CHARGE_FAULT_INPUT => Some(ChargerFault::InputFault),
+        CHARGE_FAULT_THERMAL => Some(ChargerFault::ThermalShutdown),
+        CHARGE_FAULT_TIMER => Some(ChargerFault::SafetyTimer),
+        _ if status & CHARGE_STATUS_MASK == CHARGE_STATUS_DONE => {
+            Some(ChargerFault::NormalTermination)
+        }
+        _ => None,
+    };
+    let battery = if fault & BATTERY_FAULT != 0 {
+        Some(ChargerFault::BatteryFault)
+    } else {
+        None
+    };
+    [charge, battery]
+}
+
+/// A read-modify-write of one register field.
+#[derive(Clone, Copy, PartialEq)]
+struct Update {
+    register: u8,
+    mask: u8,
+    value: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ReadWatchdog,
+    WriteWatchdog,
+    ReadRegister,
+    WriteRegister,
+    ReadFaults,
+}
+
+pub struct Bq24195<'a, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    client: OptionalCell<&'a dyn BatteryChargerClient>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    update: OptionalCell<Update>,
+    watchdog_disabled: Cell<bool>,
+    /// The INT pin fired while a request was in progress.
+    faults_pending: Cell<bool>,
+}
+
+impl<'a, I: i2c::I2CDevice> Bq24195<'a, I> {
+    pub fn new(i2c: &'a I, buffer: &'static mut [u8]) -> Bq24195<'a, I> {
+        Bq24195 {
+            i2c,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            update: OptionalCell::empty(),
+            watchdog_disabled: Cell::new(false),
+            faults_pending: Cell::new(false),
+        }
+    }
+
+    fn start_update(&self, update: Update) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.i2c.enable();
+        self.update.set(update);
+        let result = if self.watchdog_disabled.get() {
+            self.read_register(State::ReadRegister, update.register, 1)
+        } else {
+            self.read_register(
+                State::ReadWatchdog,
+                Registers::ChargeTerminationTimerControl as u8,
+                1,
+            )
+        };
+        if result.is_err() {
+            self.update.clear();
+            self.i2c.disable();
+        }
+        result
+    }
+
+    fn read_register(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            match self.i2c.write_read(buffer, 1, len) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn write_register(&self, state: State, register: u8, value: u8) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            buffer[1] = value;
+            match self.i2c.write(buffer, 2) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn read_faults(&self) {
+        self.faults_pending.set(false);
+        self.i2c.enable();
+        if self
+            .read_register(State::ReadFaults, Registers::SystemStatus as u8, 2)
+            .is_err()
+        {
+            self.i2c.disable();
+        }
+    }
+
+    /// Finish the current request, and then read faults if the INT pin
+    /// fired while it was in progress.
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.update.clear();
+        self.i2c.disable();
+        self.client.map(|client| client.command_complete(result));
+        if self.faults_pending.get() && self.state.get() == State::Idle {
+            self.read_faults();
+        }
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> BatteryCharger<'a> for Bq24195<'a, I> {
+    fn set_client(&self, client: &'a dyn BatteryChargerClient) {
+        self.client.set(client);
+    }
+
+    fn set_input_current_limit_ma(&self, ma: u16) -> Result<(), ErrorCode> {
+        let value = input_limit_bits(ma).ok_or(ErrorCode::INVAL)?;
+        self.start_update(Update {
+            register: Registers::InputSourceControl as u8,
+            mask: INPUT_LIMIT_MASK,
+            value,
+        })
+    }
+
+    fn set_charge_current_ma(&self, ma: u16) -> Result<(), ErrorCode> {
+        let value = charge_current_bits(ma).ok_or(ErrorCode::INVAL)?;
+        self.start_update(Update {
+            register: Registers::ChargeCurrentControl as u8,
+            mask: CHARGE_CURRENT_MASK,
+            value,
+        })
+    }
+
+    fn enable_charging(&self) -> Result<(), ErrorCode> {
+        self.start_update(Update {
+            register: Registers::PowerOnConfiguration as u8,
+            mask: CHARGE_CONFIG_MASK,
+            value: CHARGE_CONFIG_CHARGE,
+        })
+    }
+
+    fn disable_charging(&self) -> Result<(), ErrorCode> {
+        self.start_update(Update {
+            register: Registers::PowerOnConfiguration as u8,
+            mask: CHARGE_CONFIG_MASK,
+            value: CHARGE_CONFIG_DISABLE,
+        })
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> i2c::I2CClient for Bq24195<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let (first, second) = (buffer[0], buffer[1]);
+        self.buffer.replace(buffer);
+
+        if state == State::ReadFaults {
+            self.state.set(State::Idle);
+            self.i2c.disable();
+            if status.is_ok() {
+                for fault in decode_faults(first, second).iter().flatten() {
+                    self.client.map(|client| client.fault(*fault));
+                }
+            }
+            return;
+        }
+
+        if let Err(e) = status {
+            self.update_done(Err(e.into()));
+            return;
+        }
+
+        let result = match state {
+            State::ReadWatchdog => self.write_register(
+                State::WriteWatchdog,
+                Registers::ChargeTerminationTimerControl as u8,
+                first & !WATCHDOG_MASK,
+            ),
+            State::WriteWatchdog => {
+                self.watchdog_disabled.set(true);
+                self.update.map_or(Err(ErrorCode::FAIL), |update| {
+                    self.read_register(State::ReadRegister, update.register, 1)
+                })
+            }
+            State::ReadRegister => self.update.map_or(Err(ErrorCode::FAIL), |update| {
+                let value = (first & !update.mask) | (update.value & update.mask);
+                self.write_register(State::WriteRegister, update.register, value)
+            }),
+            State::WriteRegister => {
+                self.update_done(Ok(()));
+                Ok(())
+            }
+            State::Idle | State::ReadFaults => Ok(()),
+        };
+        if let Err(e) = result {
+            self.update_done(Err(e));
+        }
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> gpio::Client for Bq24195<'a, I> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => self.read_faults(),
+            // REG09 latches faults until it is read, so nothing is lost
+            // by reading it once the current request is done.
+            _ => self.faults_pending.set(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_settings() {
+        assert_eq!(input_limit_bits(50), None);
+        assert_eq!(input_limit_bits(100), Some(0));
+        assert_eq!(input_limit_bits(499), Some(1));
+        assert_eq!(input_limit_bits(500), Some(2));
+        assert_eq!(input_limit_bits(5000), Some(7));
+
+        assert_eq!(charge_current_bits(511), None);
+        assert_eq!(charge_current_bits(512), Some(0));
+        assert_eq!(charge_current_bits(2048), Some(0x60));
+        assert_eq!(charge_current_bits(2100), Some(0x60));
+        assert_eq!(charge_current_bits(4544), Some(0xFC));
+        assert_eq!(charge_current_bits(4545), None);
+    }
+
+    #[test]
+    fn fault_decoding() {
+        // Charging, no fault.
+        assert_eq!(decode_faults(0x24, 0x00), [None, None]);
+        // Charge termination done.
+        assert_eq!(
+            decode_faults(0x34, 0x00),
+            [Some(ChargerFault::NormalTermination), None]
+        );
+        assert_eq!(
+            decode_faults(0x04, 0x10),
+            [Some(ChargerFault::InputFault), None]
+        );
+        assert_eq!(
+            decode_faults(0x04, 0x20),
+            [Some(ChargerFault::ThermalShutdown), None]
+        );
+        assert_eq!(
+            decode_faults(0x04, 0x38),
+            [
+                Some(ChargerFault::SafetyTimer),
+                Some(ChargerFault::BatteryFault)
+            ]
+        );
+        // NTC faults on their own are not reported.
+        assert_eq!(decode_faults(0x04, 0x05), [None, None]);
+    }
+}
@@ -0,0 +1,112 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with the state of peripheral clocks.
+//!
+//! On chips that turn off the clocks of idle peripherals, this shows which
+//! clocks are running. The board chooses the clocks, and userspace refers
+//! to them by their index.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let clocks = static_init!(
+//!     [&'static dyn kernel::platform::chip::ClockInterface; 2],
+//!     [&peripherals.spi_host0_clock, &peripherals.spi_host1_clock]
+//! );
+//! let clk_stats = components::clk_stats::ClkStatsComponent::new(clocks)
+//!     .finalize(components::clk_stats_component_static!());
+//! ```
+
+use kernel::platform::chip::ClockInterface;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ClkStats as usize;
+
+pub struct ClkStats<'a> {
+    clocks: &'a [&'a dyn ClockInterface],
+}
+
+impl<'a> ClkStats<'a> {
+    pub fn new(clocks: &'a [&'a dyn ClockInterface]) -> ClkStats<'a> {
+        ClkStats { clocks }
+    }
+
+    /// Bit `i` is set if clock `i` is running, for the first 32 clocks.
+    fn enabled_mask(&self) -> u32 {
+        self.clocks
+            .iter()
+            .take(32)
+            .enumerate()
+            .filter(|(_, clock)| clock.is_enabled())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+}
+
+impl SyscallDriver for ClkStats<'_> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Return the number of clocks.
+    /// - `2`: Return 1 if clock `data1` is running, 0 if it is not.
+    /// - `3`: Return a mask of the running clocks among the first 32.
+    fn command(&self, command_num: usize, data1: usize, _: usize, _: ProcessId) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => CommandReturn::success_u32(self.clocks.len() as u32),
+            2 => match self.clocks.get(data1) {
+                Some(clock) => CommandReturn::success_u32(clock.is_enabled() as u32),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            3 => CommandReturn::success_u32(self.enabled_mask()),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    struct Clock(Cell<bool>);
+
+    impl ClockInterface for Clock {
+        fn is_enabled(&self) -> bool {
+            self.0.get()
+        }
+        fn enable(&self) {
+            self.0.set(true);
+        }
+        fn disable(&self) {
+            self.0.set(false);
+        }
+    }
+
+    #[test]
+    fn mask_has_running_clocks() {
+        let clocks = [
+            Clock(Cell::new(true)),
+            Clock(Cell::new(false)),
+            Clock(Cell::new(true)),
+        ];
+        let refs: [&dyn ClockInterface; 3] = [&clocks[0], &clocks[1], &clocks[2]];
+        let stats = ClkStats::new(&refs);
+        assert_eq!(stats.enabled_mask(), 0b101);
+        clocks[2].disable();
+        assert_eq!(stats.enabled_mask(), 0b001);
+    }
+}
@@ -0,0 +1,188 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Shared userland driver for color sensors.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a reading completes. On success the upcall receives
+//!   `(red | green << 16, blue | clear << 16, gain)` with the raw channel
+//!   counts. On failure `gain` is 0 and the first argument is the error code.
+//!
+//! ### `allow_readwrite` System Call
+//!
+//! * `0`: if set, filled on every successful reading with the red, green,
+//!   blue and clear channels normalized to a gain of 1x, as four
+//!   little-endian `u32` values in thousandths of a count.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: start a color reading
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::Color` trait.
+//!
+//! ```rust
+//! let color = components::color::ColorComponent::new(
+//!     board_kernel,
+//!     capsules_extra::color::DRIVER_NUM,
+//!     tcs34725,
+//! )
+//! .finalize(components::color_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::hil::sensors::ColorReading;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Color as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Channels normalized to 1x gain.
+    pub const NORMALIZED: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Channels normalized to 1x gain, in thousandths of a count.
+fn normalize(reading: &ColorReading) -> [u32; 4] {
+    let gain = reading.gain.max(1) as u32;
+    [reading.red, reading.green, reading.blue, reading.clear].map(|c| c as u32 * 1000 / gain)
+}
+
+/// Per-process metadata
+#[derive(Default)]
+pub struct App {
+    pending: bool,
+}
+
+pub struct ColorSensor<'a> {
+    sensor: &'a dyn hil::sensors::Color<'a>,
+    command_pending: Cell<bool>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+}
+
+impl<'a> ColorSensor<'a> {
+    pub fn new(
+        sensor: &'a dyn hil::sensors::Color<'a>,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> ColorSensor<'a> {
+        ColorSensor {
+            sensor,
+            command_pending: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn enqueue_sensor_reading(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.pending {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    if !self.command_pending.get() {
+                        self.sensor.read_color()?;
+                        self.command_pending.set(true);
+                    }
+                    app.pending = true;
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl SyscallDriver for ColorSensor<'_> {
+    /// Initiate color readings
+    ///
+    /// Sensor readings are coalesced if processes request them concurrently.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Check driver presence
+    /// - `1`: Start a color reading
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.enqueue_sensor_reading(processid).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl hil::sensors::ColorClient for ColorSensor<'_> {
+    fn callback(&self, reading: Result<ColorReading, ErrorCode>) {
+        self.command_pending.set(false);
+        self.apps.each(|_, app, kernel_data| {
+            if !app.pending {
+                return;
+            }
+            app.pending = false;
+            let upcall = match reading {
+                Ok(reading) => {
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::NORMALIZED)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                for (chunk, value) in dest.chunks(4).zip(normalize(&reading).iter())
+                                {
+                                    if chunk.len() == 4 {
+                                        chunk.copy_from_slice(&value.to_le_bytes());
+                                    }
+                                }
+                            })
+                        });
+                    (
+                        reading.red as usize | (reading.green as usize) << 16,
+                        reading.blue as usize | (reading.clear as usize) << 16,
+                        reading.gain as usize,
+                    )
+                }
+                Err(e) => (usize::from(e), 0, 0),
+            };
+            kernel_data.schedule_upcall(0, upcall).ok();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_channels() {
+        let reading = ColorReading {
+            red: 600,
+            green: 120,
+            blue: 6,
+            clear: 65535,
+            gain: 60,
+        };
+        assert_eq!(normalize(&reading), [10000, 2000, 100, 1092250]);
+    }
+}
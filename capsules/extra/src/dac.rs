@@ -41,6 +41,8 @@ impl SyscallDriver for Dac<'_> {
     /// - `0`: Driver check.
     /// - `1`: Initialize and enable the DAC.
     /// - `2`: Set the output to `data1`, a scaled output value.
+    /// - `3`: Set the output to `data1`, and store it as the output at power
+    ///   up. Returns `NOSUPPORT` if the DAC has no non-volatile memory.
     fn command(&self, command_num: usize, data: usize, _: usize, _: ProcessId) -> CommandReturn {
         match command_num {
             0 => CommandReturn::success(),
@@ -51,6 +53,9 @@ impl SyscallDriver for Dac<'_> {
             // set the dac output
             2 => CommandReturn::from(self.dac.set_value(data)),
 
+            // set the dac output at power up
+            3 => CommandReturn::from(self.dac.set_power_on_value(data)),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,606 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! DALI (IEC 62386) lighting control bus master.
+//!
+//! DALI runs at 1200 baud with Manchester encoding: every bit is split in
+//! two halves of 417 us (Te), and a one is low then high, a zero high then
+//! low. The bus is high when idle. A forward frame, from the master, is a
+//! start bit (a one), the address byte and the command byte, most
+//! significant bit first, followed by two bit times of idle bus. Control
+//! gear answers queries with a backward frame, a start bit and one byte,
+//! starting 7 to 22 Te after the forward frame. A query that is not answered
+//! within that time means "no". The bus must then be idle for 22 Te before
+//! the next forward frame.
+//!
+//! [`DaliMaster`] bit-bangs the bus with two pins and an alarm. The pins are
+//! at the level of the bus: a bus interface that inverts the bus, like most
+//! optocoupled ones, needs pins configured to match. The forward frame is
+//! clocked from the previous alarm, so the half-bits do not drift. The
+//! receive pin is polled every half of Te for the start of an answer, and
+//! both halves of every bit of the answer are sampled.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! Addresses are a short address, `0` to `63`, a group, `64` plus `0` to
+//! `15`, or `255` to broadcast.
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when an operation completes, with its status, and for a
+//!   query `1` and the answer, or `0` if there was no answer.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: set the arc power level of the control gear at address `arg1` to
+//!   `arg2`
+//! * `2`: send the command with opcode `arg2` to the control gear at address
+//!   `arg1`: off (`0`), up (`1`), down (`2`), recall max level (`5`) or
+//!   recall min level (`6`)
+//! * `3`: send the query with opcode `arg2` to the control gear at address
+//!   `arg1`: status (`0x90`), control gear present (`0x91`), lamp failure
+//!   (`0x92`), actual level (`0xA0`), max level (`0xA1`) or min level
+//!   (`0xA2`)
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dali = components::dali::DaliMasterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::dali::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[DALI_TX],
+//!     &nrf52840_peripherals.gpio_port[DALI_RX],
+//!     mux_alarm,
+//! )
+//! .finalize(components::dali_master_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::lighting::{self, DaliAddress, DaliClient, DaliCommand, DaliQuery};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Dali as usize;
+
+/// Half of a bit time, Te.
+const HALF_BIT_US: u32 = 417;
+
+/// Half-bits in a forward frame, with the start bit.
+const FORWARD_HALF_BITS: u8 = 34;
+/// Samples of a backward frame, two for each bit with the start bit.
+const BACKWARD_SAMPLES: u8 = 18;
+
+/// Idle bus ending a frame, in Te.
+const STOP_TE: u32 = 4;
+/// Idle bus required before the next forward frame, in Te.
+const SETTLING_TE: u32 = 22;
+/// Polls for the start of an answer, every half of Te, before concluding
+/// there is none.
+const ANSWER_POLLS: u8 = 2 * 22;
+
+/// The level of half-bit `half` of the forward frame `frame`, or `None`
+/// after the last one.
+fn forward_level(frame: u16, half: u8) -> Option<bool> {
+    if half >= FORWARD_HALF_BITS {
+        return None;
+    }
+    let bit = match half / 2 {
+        0 => true,
+        n => frame >> (16 - n) & 1 == 1,
+    };
+    // A one is low, then high.
+    Some(if half % 2 == 0 { !bit } else { bit })
+}
+
+/// Decodes the samples of a backward frame, the first in the lowest bit.
+fn decode_backward(samples: u32) -> Option<u8> {
+    let mut byte = 0;
+    for bit in 0..BACKWARD_SAMPLES / 2 {
+        let first = samples >> (2 * bit) & 1 == 1;
+        let second = samples >> (2 * bit + 1) & 1 == 1;
+        if first == second {
+            // No transition in the middle of the bit.
+            return None;
+        }
+        if bit == 0 {
+            if !second {
+                return None;
+            }
+        } else {
+            byte = byte << 1 | second as u8;
+        }
+    }
+    Some(byte)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Sent half-bit `half` of the forward frame.
+    Sending {
+        half: u8,
+    },
+    /// Waiting for the bus to settle after a command.
+    Settling,
+    /// Polled `polls` times for the start of the answer to a query.
+    Listening {
+        polls: u8,
+    },
+    /// Took `count` samples of the answer.
+    Receiving {
+        samples: u32,
+        count: u8,
+    },
+    /// Waiting for the bus to settle after the answer to a query.
+    Answered {
+        answer: Option<u8>,
+    },
+}
+
+pub struct DaliMaster<'a, TX: gpio::Pin, RX: gpio::Pin, A: Alarm<'a>> {
+    tx: &'a TX,
+    rx: &'a RX,
+    alarm: &'a A,
+    state: Cell<State>,
+    frame: Cell<u16>,
+    query: Cell<bool>,
+    client: OptionalCell<&'a dyn DaliClient>,
+}
+
+impl<'a, TX: gpio::Pin, RX: gpio::Pin, A: Alarm<'a>> DaliMaster<'a, TX, RX, A> {
+    pub fn new(tx: &'a TX, rx: &'a RX, alarm: &'a A) -> DaliMaster<'a, TX, RX, A> {
+        DaliMaster {
+            tx,
+            rx,
+            alarm,
+            state: Cell::new(State::Idle),
+            frame: Cell::new(0),
+            query: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn start(&self, frame: u16, query: bool) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.frame.set(frame);
+        self.query.set(query);
+        self.rx.make_input();
+        self.tx.make_output();
+        self.set_bus(forward_level(frame, 0) == Some(true));
+        self.state.set(State::Sending { half: 0 });
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(HALF_BIT_US));
+        Ok(())
+    }
+
+    fn set_bus(&self, high: bool) {
+        if high {
+            self.tx.set();
+        } else {
+            self.tx.clear();
+        }
+    }
+
+    /// Set the next alarm `us` after the last one.
+    fn wait(&self, us: u32) {
+        self.alarm
+            .set_alarm(self.alarm.get_alarm(), self.alarm.ticks_from_us(us));
+    }
+
+    fn finish(&self, answer: Result<Option<u8>, ErrorCode>) {
+        self.state.set(State::Idle);
+        if self.query.get() {
+            self.client.map(|client| client.query_done(answer));
+        } else {
+            self.client
+                .map(|client| client.command_done(answer.map(|_| ())));
+        }
+    }
+}
+
+impl<'a, TX: gpio::Pin, RX: gpio::Pin, A: Alarm<'a>> lighting::DaliMaster<'a>
+    for DaliMaster<'a, TX, RX, A>
+{
+    fn set_client(&self, client: &'a dyn DaliClient) {
+        self.client.set(client);
+    }
+
+    fn send_command(&self, address: DaliAddress, command: DaliCommand) -> Result<(), ErrorCode> {
+        let (opcode, is_command) = command.opcode();
+        let address = address.address_byte(is_command).ok_or(ErrorCode::INVAL)?;
+        self.start(u16::from_be_bytes([address, opcode]), false)
+    }
+
+    fn query(&self, address: DaliAddress, query: DaliQuery) -> Result<(), ErrorCode> {
+        let address = address.address_byte(true).ok_or(ErrorCode::INVAL)?;
+        self.start(u16::from_be_bytes([address, query.opcode()]), true)
+    }
+}
+
+impl<'a, TX: gpio::Pin, RX: gpio::Pin, A: Alarm<'a>> time::AlarmClient
+    for DaliMaster<'a, TX, RX, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::Sending { half } => match forward_level(self.frame.get(), half + 1) {
+                Some(level) => {
+                    self.set_bus(level);
+                    self.state.set(State::Sending { half: half + 1 });
+                    self.wait(HALF_BIT_US);
+                }
+                None => {
+                    self.tx.set();
+                    if self.query.get() {
+                        self.state.set(State::Listening { polls: 0 });
+                        self.wait(HALF_BIT_US / 2);
+                    } else {
+                        self.state.set(State::Settling);
+                        self.wait((STOP_TE + SETTLING_TE) * HALF_BIT_US);
+                    }
+                }
+            },
+            State::Settling => self.finish(Ok(None)),
+            State::Listening { polls } => {
+                if !self.rx.read() {
+                    // The start bit began in the last half of Te. Sample a
+                    // quarter of Te later, which is within the first half of
+                    // the start bit, and every Te from there.
+                    self.state.set(State::Receiving {
+                        samples: 0,
+                        count: 0,
+                    });
+                    self.wait(HALF_BIT_US / 4);
+                } else if polls + 1 >= ANSWER_POLLS {
+                    // The bus has been idle long enough for the next frame.
+                    self.finish(Ok(None));
+                } else {
+                    self.state.set(State::Listening { polls: polls + 1 });
+                    self.wait(HALF_BIT_US / 2);
+                }
+            }
+            State::Receiving { samples, count } => {
+                let samples = samples | (self.rx.read() as u32) << count;
+                if count + 1 < BACKWARD_SAMPLES {
+                    self.state.set(State::Receiving {
+                        samples,
+                        count: count + 1,
+                    });
+                    self.wait(HALF_BIT_US);
+                } else {
+                    self.state.set(State::Answered {
+                        answer: decode_backward(samples),
+                    });
+                    self.wait((STOP_TE + SETTLING_TE) * HALF_BIT_US);
+                }
+            }
+            State::Answered { answer } => {
+                self.finish(answer.map(Some).ok_or(ErrorCode::FAIL));
+            }
+        }
+    }
+}
+
+/// The address `arg` from userspace.
+fn address_from_arg(arg: usize) -> Option<DaliAddress> {
+    match arg {
+        0..=63 => Some(DaliAddress::Short(arg as u8)),
+        64..=79 => Some(DaliAddress::Group((arg - 64) as u8)),
+        255 => Some(DaliAddress::Broadcast),
+        _ => None,
+    }
+}
+
+/// The command with opcode `arg` from userspace.
+fn command_from_arg(arg: usize) -> Option<DaliCommand> {
+    match arg {
+        0x00 => Some(DaliCommand::Off),
+        0x01 => Some(DaliCommand::Up),
+        0x02 => Some(DaliCommand::Down),
+        0x05 => Some(DaliCommand::RecallMaxLevel),
+        0x06 => Some(DaliCommand::RecallMinLevel),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct DaliDriver<'a, D: lighting::DaliMaster<'a>> {
+    dali: &'a D,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    current: OptionalCell<ProcessId>,
+}
+
+impl<'a, D: lighting::DaliMaster<'a>> DaliDriver<'a, D> {
+    pub fn new(
+        dali: &'a D,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> DaliDriver<'a, D> {
+        DaliDriver {
+            dali,
+            apps: grant,
+            current: OptionalCell::empty(),
+        }
+    }
+
+    fn run(
+        &self,
+        processid: ProcessId,
+        operation: impl FnOnce() -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |_, _| {
+                operation()?;
+                self.current.set(processid);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn done(&self, result: Result<Option<u8>, ErrorCode>) {
+        self.current.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, upcalls| {
+                let (answered, value) = match result {
+                    Ok(Some(value)) => (1, value as usize),
+                    _ => (0, 0),
+                };
+                upcalls
+                    .schedule_upcall(0, (into_statuscode(result.map(|_| ())), answered, value))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, D: lighting::DaliMaster<'a>> DaliClient for DaliDriver<'a, D> {
+    fn command_done(&self, result: Result<(), ErrorCode>) {
+        self.done(result.map(|()| None));
+    }
+
+    fn query_done(&self, result: Result<Option<u8>, ErrorCode>) {
+        self.done(result);
+    }
+}
+
+impl<'a, D: lighting::DaliMaster<'a>> SyscallDriver for DaliDriver<'a, D> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Set the arc power level of the control gear at address `data1`
+    ///   to `data2`.
+    /// - `2`: Send the command with opcode `data2` to the control gear at
+    ///   address `data1`.
+    /// - `3`: Send the query with opcode `data2` to the control gear at
+    ///   address `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        let address = match address_from_arg(data1) {
+            Some(address) => address,
+            None => return CommandReturn::failure(ErrorCode::INVAL),
+        };
+        match command_num {
+            1 => match u8::try_from(data2) {
+                Ok(level) => self
+                    .run(processid, || {
+                        self.dali.send_command(address, DaliCommand::Dapc(level))
+                    })
+                    .into(),
+                Err(_) => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            2 => match command_from_arg(data2) {
+                Some(command) => self
+                    .run(processid, || self.dali.send_command(address, command))
+                    .into(),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            3 => match u8::try_from(data2).ok().and_then(DaliQuery::from_opcode) {
+                Some(query) => self
+                    .run(processid, || self.dali.query(address, query))
+                    .into(),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin};
+    use kernel::hil::lighting::DaliMaster as _;
+    use kernel::hil::time::{Freq1MHz, Ticks, Time};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct MockClient {
+        answer: Cell<Option<Result<Option<u8>, ErrorCode>>>,
+    }
+
+    impl DaliClient for MockClient {
+        fn command_done(&self, result: Result<(), ErrorCode>) {
+            self.answer.set(Some(result.map(|()| None)));
+        }
+        fn query_done(&self, result: Result<Option<u8>, ErrorCode>) {
+            self.answer.set(Some(result));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestDali = DaliMaster<'static, MockPin<'static>, MockPin<'static>, TestAlarm>;
+
+    /// The bus with control gear answering `answer` at `start` us, as a
+    /// function of the time in us.
+    fn answering(answer: Option<u8>, start: u32) -> impl Fn(u32) -> bool {
+        move |t| match answer {
+            Some(byte) if t >= start => {
+                let half = (t - start) / HALF_BIT_US;
+                if half >= BACKWARD_SAMPLES as u32 {
+                    return true;
+                }
+                let bit = match half / 2 {
+                    0 => true,
+                    n => byte >> (8 - n) & 1 == 1,
+                };
+                if half % 2 == 0 {
+                    !bit
+                } else {
+                    bit
+                }
+            }
+            _ => true,
+        }
+    }
+
+    /// Runs the alarms until the operation completes, with the bus driven
+    /// by `bus` as well. Returns the levels sent at every half-bit of the
+    /// forward frame.
+    fn run(
+        dali: &TestDali,
+        tx: &MockPin,
+        rx: &MockPin,
+        alarm: &TestAlarm,
+        bus: impl Fn(u32) -> bool,
+    ) -> Vec<bool> {
+        let mut sent = std::vec![tx.level()];
+        let start = alarm.now().into_u32();
+        while alarm.is_armed() {
+            rx.set_level(bus(alarm.get_alarm().into_u32() - start));
+            alarm.fire();
+            if let State::Sending { .. } = dali.state.get() {
+                sent.push(tx.level());
+            }
+        }
+        sent
+    }
+
+    fn manchester(bits: &[bool]) -> Vec<bool> {
+        bits.iter().flat_map(|&bit| [!bit, bit]).collect()
+    }
+
+    fn frame_bits(bytes: [u8; 2]) -> Vec<bool> {
+        let mut bits = std::vec![true];
+        bits.extend((0..16).map(|i| u16::from_be_bytes(bytes) >> (15 - i) & 1 == 1));
+        bits
+    }
+
+    #[test]
+    fn command_sends_forward_frame() {
+        let tx = Box::leak(Box::new(MockPin::new(true)));
+        let rx = Box::leak(Box::new(MockPin::new(true)));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let dali: &TestDali = Box::leak(Box::new(DaliMaster::new(tx, rx, alarm)));
+        alarm.set_alarm_client(dali);
+        let client = Box::leak(Box::new(MockClient::default()));
+        dali.set_client(client);
+
+        // DAPC to short address 5, then RECALL MAX LEVEL to group 3.
+        assert_eq!(
+            dali.send_command(DaliAddress::Short(5), DaliCommand::Dapc(128)),
+            Ok(())
+        );
+        assert_eq!(
+            dali.send_command(DaliAddress::Broadcast, DaliCommand::Off),
+            Err(ErrorCode::BUSY)
+        );
+        let sent = run(dali, tx, rx, alarm, |_| true);
+        assert_eq!(sent, manchester(&frame_bits([0x0A, 0x80])));
+        assert_eq!(client.answer.take(), Some(Ok(None)));
+        assert!(tx.level());
+
+        assert_eq!(
+            dali.send_command(DaliAddress::Group(3), DaliCommand::RecallMaxLevel),
+            Ok(())
+        );
+        let sent = run(dali, tx, rx, alarm, |_| true);
+        assert_eq!(sent, manchester(&frame_bits([0x87, 0x05])));
+        assert_eq!(client.answer.take(), Some(Ok(None)));
+
+        assert_eq!(
+            dali.send_command(DaliAddress::Short(64), DaliCommand::Off),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn query_receives_backward_frame() {
+        let tx = Box::leak(Box::new(MockPin::new(true)));
+        let rx = Box::leak(Box::new(MockPin::new(true)));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let dali: &TestDali = Box::leak(Box::new(DaliMaster::new(tx, rx, alarm)));
+        alarm.set_alarm_client(dali);
+        let client = Box::leak(Box::new(MockClient::default()));
+        dali.set_client(client);
+        let frame_end = FORWARD_HALF_BITS as u32 * HALF_BIT_US;
+
+        // Control gear answers the actual level 10 Te after the query, a
+        // little off the half-bit grid.
+        assert_eq!(
+            dali.query(DaliAddress::Broadcast, DaliQuery::ActualLevel),
+            Ok(())
+        );
+        let sent = run(
+            dali,
+            tx,
+            rx,
+            alarm,
+            answering(Some(0xA5), frame_end + 10 * HALF_BIT_US + 150),
+        );
+        assert_eq!(sent, manchester(&frame_bits([0xFF, 0xA0])));
+        assert_eq!(client.answer.take(), Some(Ok(Some(0xA5))));
+
+        // No answer.
+        assert_eq!(
+            dali.query(DaliAddress::Short(1), DaliQuery::ControlGearPresent),
+            Ok(())
+        );
+        run(dali, tx, rx, alarm, answering(None, 0));
+        assert_eq!(client.answer.take(), Some(Ok(None)));
+
+        // Two control gear answering at once garble the answer.
+        assert_eq!(
+            dali.query(DaliAddress::Broadcast, DaliQuery::Status),
+            Ok(())
+        );
+        let start = frame_end + 8 * HALF_BIT_US;
+        let one = answering(Some(0x0F), start);
+        let other = answering(Some(0xF0), start);
+        run(dali, tx, rx, alarm, move |t| one(t) && other(t));
+        assert_eq!(client.answer.take(), Some(Err(ErrorCode::FAIL)));
+    }
+}
@@ -0,0 +1,371 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! AES-CMAC (RFC 4493) over an AES-128 ECB engine.
+//!
+//! <https://www.rfc-editor.org/rfc/rfc4493>
+//!
+//! The message is MACed one block per `crypt` call, chaining the blocks in
+//! software, so any engine with ECB mode works. The subkeys are derived from
+//! the key once, and kept until the key changes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cmac = static_init!(
+//!     capsules_extra::desfire::cmac::AesCmac<'static, earlgrey::aes::Aes>,
+//!     capsules_extra::desfire::cmac::AesCmac::new(
+//!         &peripherals.aes,
+//!         static_init!([u8; 16], [0; 16])
+//!     )
+//! );
+//! kernel::hil::symmetric_encryption::AES128::set_client(&peripherals.aes, cmac);
+//! cmac.set_client(desfire_auth);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::symmetric_encryption::{self, AES128, AES128ECB, AES128_BLOCK_SIZE};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+type Block = [u8; AES128_BLOCK_SIZE];
+
+/// The constant of the subkey doubling in GF(2^128).
+const R_128: u8 = 0x87;
+
+pub trait CmacClient {
+    /// The MAC of the message given to `compute` is done.
+    fn cmac_done(&self, message: &'static mut [u8], result: Result<Block, ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Encrypting the zero block for the subkeys.
+    Subkeys,
+    /// Encrypting a block of the message.
+    Message,
+    /// Encrypting the last block of the message.
+    Last,
+}
+
+/// Multiplies `block` by x in GF(2^128).
+fn double(block: &Block) -> Block {
+    let mut doubled = [0; AES128_BLOCK_SIZE];
+    for (i, byte) in doubled.iter_mut().enumerate() {
+        let carry = block.get(i + 1).map_or(0, |next| next >> 7);
+        *byte = block[i] << 1 | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        doubled[AES128_BLOCK_SIZE - 1] ^= R_128;
+    }
+    doubled
+}
+
+pub struct AesCmac<'a, A: AES128<'a> + AES128ECB> {
+    aes: &'a A,
+    block: TakeCell<'static, [u8]>,
+    message: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    /// Bytes of the message chained so far.
+    offset: Cell<usize>,
+    /// The encryption of the zero block, from which the subkeys derive.
+    l: Cell<Option<Block>>,
+    /// The CBC-MAC of the blocks so far.
+    mac: Cell<Block>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn CmacClient>,
+}
+
+impl<'a, A: AES128<'a> + AES128ECB> AesCmac<'a, A> {
+    pub fn new(aes: &'a A, block: &'static mut Block) -> AesCmac<'a, A> {
+        AesCmac {
+            aes,
+            block: TakeCell::new(block),
+            message: TakeCell::empty(),
+            len: Cell::new(0),
+            offset: Cell::new(0),
+            l: Cell::new(None),
+            mac: Cell::new([0; AES128_BLOCK_SIZE]),
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn CmacClient) {
+        self.client.set(client);
+    }
+
+    pub fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.aes.set_key(key)?;
+        self.l.set(None);
+        Ok(())
+    }
+
+    /// Computes the MAC of the first `len` bytes of `message`.
+    pub fn compute(
+        &self,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, message));
+        }
+        if len > message.len() {
+            return Err((ErrorCode::SIZE, message));
+        }
+        if let Err(e) = self.aes.set_mode_aes128ecb(true) {
+            return Err((e, message));
+        }
+        self.aes.enable();
+        self.aes.start_message();
+        self.message.replace(message);
+        self.len.set(len);
+        self.offset.set(0);
+        self.mac.set([0; AES128_BLOCK_SIZE]);
+
+        let started = match self.l.get() {
+            Some(l) => self.next_block(&l),
+            None => {
+                self.state.set(State::Subkeys);
+                self.encrypt(&[0; AES128_BLOCK_SIZE])
+            }
+        };
+        started.map_err(|e| {
+            self.state.set(State::Idle);
+            self.aes.disable();
+            (e, self.message.take().unwrap())
+        })
+    }
+
+    /// Encrypts the next block of the message chained with the MAC so far.
+    /// The last block is XORed with a subkey first, and padded if short.
+    fn next_block(&self, l: &Block) -> Result<(), ErrorCode> {
+        let offset = self.offset.get();
+        let remaining = self.len.get() - offset;
+        let mut block = self.mac.get();
+        self.message.map(|message| {
+            let chunk = remaining.min(AES128_BLOCK_SIZE);
+            for (b, m) in block.iter_mut().zip(&message[offset..offset + chunk]) {
+                *b ^= m;
+            }
+            if remaining <= AES128_BLOCK_SIZE {
+                let k1 = double(l);
+                let subkey = if remaining == AES128_BLOCK_SIZE {
+                    k1
+                } else {
+                    block[chunk] ^= 0x80;
+                    double(&k1)
+                };
+                for (b, k) in block.iter_mut().zip(&subkey) {
+                    *b ^= k;
+                }
+            }
+            self.offset.set(offset + chunk);
+        });
+        self.state.set(if remaining <= AES128_BLOCK_SIZE {
+            State::Last
+        } else {
+            State::Message
+        });
+        self.encrypt(&block)
+    }
+
+    fn encrypt(&self, input: &Block) -> Result<(), ErrorCode> {
+        let block = self.block.take().ok_or(ErrorCode::BUSY)?;
+        block[..AES128_BLOCK_SIZE].copy_from_slice(input);
+        match self.aes.crypt(None, block, 0, AES128_BLOCK_SIZE) {
+            None => Ok(()),
+            Some((result, _, block)) => {
+                self.block.replace(block);
+                Err(result.err().unwrap_or(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    fn finish(&self, result: Result<Block, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.aes.disable();
+        if let Some(message) = self.message.take() {
+            self.client.map(|client| client.cmac_done(message, result));
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128ECB> symmetric_encryption::Client<'a> for AesCmac<'a, A> {
+    fn crypt_done(&'a self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        let mut output = [0; AES128_BLOCK_SIZE];
+        output.copy_from_slice(&dest[..AES128_BLOCK_SIZE]);
+        self.block.replace(dest);
+
+        let next = match self.state.get() {
+            State::Idle => return,
+            State::Subkeys => {
+                self.l.set(Some(output));
+                self.next_block(&output)
+            }
+            State::Message => {
+                self.mac.set(output);
+                self.l
+                    .get()
+                    .map_or(Err(ErrorCode::FAIL), |l| self.next_block(&l))
+            }
+            State::Last => {
+                self.finish(Ok(output));
+                return;
+            }
+        };
+        if let Err(e) = next {
+            self.finish(Err(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAes128;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const KEY: Block = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+
+    const MESSAGE: [u8; 64] = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf,
+        0x8e, 0x51, 0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11, 0xe5, 0xfb, 0xc1, 0x19, 0x1a,
+        0x0a, 0x52, 0xef, 0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17, 0xad, 0x2b, 0x41, 0x7b,
+        0xe6, 0x6c, 0x37, 0x10,
+    ];
+
+    #[derive(Default)]
+    struct Client {
+        macs: RefCell<Vec<Result<Block, ErrorCode>>>,
+    }
+
+    impl CmacClient for Client {
+        fn cmac_done(&self, _message: &'static mut [u8], result: Result<Block, ErrorCode>) {
+            self.macs.borrow_mut().push(result);
+        }
+    }
+
+    fn setup() -> (
+        &'static MockAes128<'static>,
+        &'static AesCmac<'static, MockAes128<'static>>,
+        &'static Client,
+    ) {
+        let aes: &'static MockAes128 = Box::leak(Box::default());
+        let cmac = Box::leak(Box::new(AesCmac::new(
+            aes,
+            Box::leak(Box::new([0; AES128_BLOCK_SIZE])),
+        )));
+        let client: &'static Client = Box::leak(Box::default());
+        aes.set_client(cmac);
+        cmac.set_client(client);
+        assert_eq!(cmac.set_key(&KEY), Ok(()));
+        (aes, cmac, client)
+    }
+
+    fn mac(len: usize) -> Block {
+        let (aes, cmac, client) = setup();
+        let message = Box::leak(Box::new(MESSAGE));
+        assert!(cmac.compute(message, len).is_ok());
+        aes.run();
+        client.macs.borrow()[0].unwrap()
+    }
+
+    #[test]
+    fn subkeys() {
+        // L = AES-128(K, 0) from RFC 4493, section 4.
+        let l = [
+            0x7d, 0xf7, 0x6b, 0x0c, 0x1a, 0xb8, 0x99, 0xb3, 0x3e, 0x42, 0xf0, 0x47, 0xb9, 0x1b,
+            0x54, 0x6f,
+        ];
+        let k1 = double(&l);
+        assert_eq!(
+            k1,
+            [
+                0xfb, 0xee, 0xd6, 0x18, 0x35, 0x71, 0x33, 0x66, 0x7c, 0x85, 0xe0, 0x8f, 0x72, 0x36,
+                0xa8, 0xde
+            ]
+        );
+        assert_eq!(
+            double(&k1),
+            [
+                0xf7, 0xdd, 0xac, 0x30, 0x6a, 0xe2, 0x66, 0xcc, 0xf9, 0x0b, 0xc1, 0x1e, 0xe4, 0x6d,
+                0x51, 0x3b
+            ]
+        );
+    }
+
+    #[test]
+    fn rfc4493_examples() {
+        assert_eq!(
+            mac(0),
+            [
+                0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+                0x67, 0x46
+            ]
+        );
+        assert_eq!(
+            mac(16),
+            [
+                0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+                0x28, 0x7c
+            ]
+        );
+        assert_eq!(
+            mac(40),
+            [
+                0xdf, 0xa6, 0x67, 0x47, 0xde, 0x9a, 0xe6, 0x30, 0x30, 0xca, 0x32, 0x61, 0x14, 0x97,
+                0xc8, 0x27
+            ]
+        );
+        assert_eq!(
+            mac(64),
+            [
+                0x51, 0xf0, 0xbe, 0xbf, 0x7e, 0x3b, 0x9d, 0x92, 0xfc, 0x49, 0x74, 0x17, 0x79, 0x36,
+                0x3c, 0xfe
+            ]
+        );
+    }
+
+    #[test]
+    fn subkeys_are_kept_until_the_key_changes() {
+        let (aes, cmac, client) = setup();
+        assert!(cmac.compute(Box::leak(Box::new(MESSAGE)), 16).is_ok());
+        assert!(cmac.compute(Box::leak(Box::new(MESSAGE)), 16).is_err());
+        aes.run();
+        assert_eq!(aes.blocks(), 2);
+
+        assert!(cmac.compute(Box::leak(Box::new(MESSAGE)), 16).is_ok());
+        aes.run();
+        assert_eq!(aes.blocks(), 3);
+
+        assert_eq!(cmac.set_key(&KEY), Ok(()));
+        assert!(cmac.compute(Box::leak(Box::new(MESSAGE)), 16).is_ok());
+        aes.run();
+        assert_eq!(aes.blocks(), 5);
+
+        let macs = client.macs.borrow();
+        assert_eq!(macs.len(), 3);
+        assert!(macs.iter().all(|mac| *mac == macs[0]));
+        assert_eq!(
+            cmac.compute(Box::leak(Box::new(MESSAGE)), 65)
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+    }
+}
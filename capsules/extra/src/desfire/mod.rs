@@ -0,0 +1,645 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! MIFARE DESFire EV2 commands over an ISO 14443-4 contactless interface.
+//!
+//! <https://www.nxp.com/docs/en/data-sheet/MF1P(H)x2_SDS.pdf>
+//!
+//! DESFire native commands are sent wrapped in ISO 7816-4 APDUs:
+//!
+//! ```text
+//! 90 <command> 00 00 [Lc <data>] 00
+//! ```
+//!
+//! and the card answers with its data followed by the status word `91`
+//! `<status>`. A status of `AF` means the card has more to send, or expects
+//! more of the command: the exchange continues with `AF` frames until the
+//! card answers `00`. [`Desfire`] implements this chaining for
+//! `SelectApplication`, `GetFileIDs`, `ReadData` and `WriteData`, in plain
+//! communication mode.
+//!
+//! The APDUs are exchanged through the [`Transceive`] interface. The PN532
+//! implements it, and does the ISO 14443-4 (T=CL) block framing itself. For
+//! a reader that only exchanges ISO 14443-3 frames, [`tcl::Tcl`] adds the
+//! block framing and chaining in software.
+//!
+//! Files in MACed communication mode are authenticated with AES-CMAC, which
+//! [`cmac::AesCmac`] computes with a hardware AES engine, such as the one of
+//! EarlGrey. The session key comes from authenticating to the application,
+//! which this layer does not implement.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let desfire = static_init!(
+//!     capsules_extra::desfire::Desfire<'static, Pn532Device>,
+//!     capsules_extra::desfire::Desfire::new(
+//!         pn532,
+//!         static_init!([u8; capsules_extra::desfire::BUFFER_LEN], [0; capsules_extra::desfire::BUFFER_LEN]),
+//!     )
+//! );
+//! capsules_extra::desfire::Transceive::set_client(pn532, desfire);
+//! desfire.set_client(badge_reader);
+//! ```
+
+pub mod cmac;
+pub mod tcl;
+
+use core::cell::Cell;
+
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// An interface to a contactless card that exchanges ISO 7816-4 APDUs.
+pub trait Transceive<'a> {
+    fn set_client(&self, client: &'a dyn TransceiveClient);
+
+    /// Sends the first `len` bytes of `buffer` to the card, and reads its
+    /// answer into `buffer`.
+    fn transceive(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait TransceiveClient {
+    /// The exchange is done, and the answer is the first `len` bytes of
+    /// `buffer` if `result` is `Ok(len)`.
+    fn transceive_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+}
+
+/// Why a command failed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DesfireError {
+    /// The exchange with the card failed.
+    Transceive(ErrorCode),
+    /// The card answered with this status code.
+    Card(u8),
+    /// The answer of the card does not fit the command.
+    Malformed,
+}
+
+pub trait DesfireClient {
+    fn application_selected(&self, result: Result<(), DesfireError>);
+
+    /// The file numbers of the selected application are the first `len`
+    /// bytes of `buffer` if `result` is `Ok(len)`.
+    fn file_ids(&self, buffer: &'static mut [u8], result: Result<usize, DesfireError>);
+
+    /// The data read is the first `len` bytes of `buffer` if `result` is
+    /// `Ok(len)`.
+    fn read_done(&self, buffer: &'static mut [u8], result: Result<usize, DesfireError>);
+
+    fn write_done(&self, buffer: &'static mut [u8], result: Result<(), DesfireError>);
+}
+
+const CLA: u8 = 0x90;
+const SW1: u8 = 0x91;
+
+const CMD_SELECT_APPLICATION: u8 = 0x5A;
+const CMD_GET_FILE_IDS: u8 = 0x6F;
+const CMD_READ_DATA: u8 = 0xBD;
+const CMD_WRITE_DATA: u8 = 0x3D;
+const CMD_ADDITIONAL_FRAME: u8 = 0xAF;
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_ADDITIONAL_FRAME: u8 = 0xAF;
+
+/// CLA, INS, P1, P2 and Lc.
+const HEADER_LEN: usize = 5;
+/// Largest data field of a command frame.
+pub const MAX_FRAME_DATA: usize = 52;
+/// Size of the frame buffer, which holds a command frame, or an answer of
+/// up to 59 bytes of data and the status word.
+pub const BUFFER_LEN: usize = 64;
+/// File number, offset and length of `ReadData` and `WriteData`.
+const FILE_ACCESS_LEN: usize = 7;
+/// Offsets and lengths are 3 bytes long.
+const MAX_FILE_OFFSET: usize = 1 << 24;
+
+/// Wraps a native command whose data is at `frame[HEADER_LEN..]` into an
+/// APDU, and returns the length of the APDU.
+fn wrap(frame: &mut [u8], command: u8, data_len: usize) -> usize {
+    frame[..4].copy_from_slice(&[CLA, command, 0x00, 0x00]);
+    if data_len == 0 {
+        // Only Le.
+        frame[4] = 0x00;
+        HEADER_LEN
+    } else {
+        frame[4] = data_len as u8;
+        frame[HEADER_LEN + data_len] = 0x00;
+        HEADER_LEN + data_len + 1
+    }
+}
+
+/// Splits the answer of the card into its data and status code.
+fn unwrap(response: &[u8]) -> Result<(&[u8], u8), DesfireError> {
+    match response {
+        [data @ .., SW1, status] => Ok((data, *status)),
+        _ => Err(DesfireError::Malformed),
+    }
+}
+
+/// The parameters of `ReadData` and `WriteData`.
+fn file_access(file: u8, offset: usize, len: usize) -> [u8; FILE_ACCESS_LEN] {
+    let offset = (offset as u32).to_le_bytes();
+    let len = (len as u32).to_le_bytes();
+    [
+        file, offset[0], offset[1], offset[2], len[0], len[1], len[2],
+    ]
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Command {
+    SelectApplication,
+    GetFileIds,
+    ReadData,
+    WriteData,
+}
+
+impl Command {
+    fn code(&self) -> u8 {
+        match self {
+            Command::SelectApplication => CMD_SELECT_APPLICATION,
+            Command::GetFileIds => CMD_GET_FILE_IDS,
+            Command::ReadData => CMD_READ_DATA,
+            Command::WriteData => CMD_WRITE_DATA,
+        }
+    }
+}
+
+pub struct Desfire<'a, T: Transceive<'a>> {
+    transceiver: &'a T,
+    frame: TakeCell<'static, [u8]>,
+    /// The buffer read into or written from.
+    data: TakeCell<'static, [u8]>,
+    command: OptionalCell<Command>,
+    /// Bytes of `data` received or sent so far.
+    offset: Cell<usize>,
+    /// Bytes of `data` to receive or send.
+    len: Cell<usize>,
+    client: OptionalCell<&'a dyn DesfireClient>,
+}
+
+impl<'a, T: Transceive<'a>> Desfire<'a, T> {
+    pub fn new(transceiver: &'a T, frame: &'static mut [u8; BUFFER_LEN]) -> Desfire<'a, T> {
+        Desfire {
+            transceiver,
+            frame: TakeCell::new(frame),
+            data: TakeCell::empty(),
+            command: OptionalCell::empty(),
+            offset: Cell::new(0),
+            len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn DesfireClient) {
+        self.client.set(client);
+    }
+
+    /// Selects the application `aid`, or the card itself if `aid` is 0.
+    pub fn select_application(&self, aid: u32) -> Result<(), ErrorCode> {
+        if aid as usize >= MAX_FILE_OFFSET {
+            return Err(ErrorCode::INVAL);
+        }
+        self.start(Command::SelectApplication, None, 0, &aid.to_le_bytes()[..3])
+            .map_err(|(e, _)| e)
+    }
+
+    /// Reads the file numbers of the selected application into `buffer`.
+    pub fn get_file_ids(
+        &self,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let len = buffer.len();
+        self.start(Command::GetFileIds, Some(buffer), len, &[])
+            .map_err(|(e, buffer)| (e, buffer.unwrap()))
+    }
+
+    /// Reads `len` bytes of file `file` from `offset` into `buffer`.
+    pub fn read_data(
+        &self,
+        file: u8,
+        offset: usize,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        // A length of 0 would read the whole file, whatever its size.
+        if len == 0 || len > buffer.len() || len >= MAX_FILE_OFFSET || offset >= MAX_FILE_OFFSET {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+        self.start(
+            Command::ReadData,
+            Some(buffer),
+            len,
+            &file_access(file, offset, len),
+        )
+        .map_err(|(e, buffer)| (e, buffer.unwrap()))
+    }
+
+    /// Writes the first `len` bytes of `buffer` to file `file` at `offset`.
+    pub fn write_data(
+        &self,
+        file: u8,
+        offset: usize,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len == 0 || len > buffer.len() || len >= MAX_FILE_OFFSET || offset >= MAX_FILE_OFFSET {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+        self.start(
+            Command::WriteData,
+            Some(buffer),
+            len,
+            &file_access(file, offset, len),
+        )
+        .map_err(|(e, buffer)| (e, buffer.unwrap()))
+    }
+
+    /// Sends the first frame of `command` with `parameters`, followed by as
+    /// much of `data` as fits if writing.
+    fn start(
+        &self,
+        command: Command,
+        data: Option<&'static mut [u8]>,
+        len: usize,
+        parameters: &[u8],
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
+        if self.command.is_some() {
+            return Err((ErrorCode::BUSY, data));
+        }
+        let frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => return Err((ErrorCode::BUSY, data)),
+        };
+        let fields = &mut frame[HEADER_LEN..];
+        fields[..parameters.len()].copy_from_slice(parameters);
+        let mut data_len = parameters.len();
+        let mut offset = 0;
+        if let (Command::WriteData, Some(data)) = (command, data.as_deref()) {
+            offset = len.min(MAX_FRAME_DATA - data_len);
+            fields[data_len..data_len + offset].copy_from_slice(&data[..offset]);
+            data_len += offset;
+        }
+        let frame_len = wrap(frame, command.code(), data_len);
+
+        match self.transceiver.transceive(frame, frame_len) {
+            Ok(()) => {
+                self.command.set(command);
+                self.offset.set(offset);
+                self.len.set(len);
+                if let Some(data) = data {
+                    self.data.replace(data);
+                }
+                Ok(())
+            }
+            Err((e, frame)) => {
+                self.frame.replace(frame);
+                Err((e, data))
+            }
+        }
+    }
+
+    /// Handles the answer of the card, and returns whether the command
+    /// continues with another frame.
+    fn receive(&self, command: Command, response: &[u8]) -> Result<bool, DesfireError> {
+        let (data, status) = unwrap(response)?;
+        let more = match status {
+            STATUS_OK => false,
+            STATUS_ADDITIONAL_FRAME => true,
+            status => return Err(DesfireError::Card(status)),
+        };
+        let offset = self.offset.get();
+        let len = self.len.get();
+        match command {
+            Command::SelectApplication => {
+                if more || !data.is_empty() {
+                    return Err(DesfireError::Malformed);
+                }
+            }
+            Command::GetFileIds | Command::ReadData => {
+                let end = offset + data.len();
+                if end > len {
+                    return Err(DesfireError::Malformed);
+                }
+                self.data
+                    .map(|buffer| buffer[offset..end].copy_from_slice(data));
+                self.offset.set(end);
+                if command == Command::ReadData && !more && end != len {
+                    return Err(DesfireError::Malformed);
+                }
+            }
+            Command::WriteData => {
+                // The card asks for the rest of the data, and only for it.
+                if !data.is_empty() || more != (offset < len) {
+                    return Err(DesfireError::Malformed);
+                }
+            }
+        }
+        Ok(more)
+    }
+
+    /// Sends the next frame of `command`.
+    fn continue_command(
+        &self,
+        command: Command,
+        frame: &'static mut [u8],
+    ) -> Result<(), ErrorCode> {
+        let mut data_len = 0;
+        if command == Command::WriteData {
+            let offset = self.offset.get();
+            data_len = (self.len.get() - offset).min(MAX_FRAME_DATA);
+            self.data.map(|data| {
+                frame[HEADER_LEN..HEADER_LEN + data_len]
+                    .copy_from_slice(&data[offset..offset + data_len])
+            });
+            self.offset.set(offset + data_len);
+        }
+        let frame_len = wrap(frame, CMD_ADDITIONAL_FRAME, data_len);
+        self.transceiver
+            .transceive(frame, frame_len)
+            .map_err(|(e, frame)| {
+                self.frame.replace(frame);
+                e
+            })
+    }
+
+    fn finish(&self, command: Command, result: Result<usize, DesfireError>) {
+        self.command.clear();
+        let data = self.data.take();
+        self.client.map(|client| match (command, data) {
+            (Command::SelectApplication, _) => client.application_selected(result.map(|_| ())),
+            (Command::GetFileIds, Some(data)) => client.file_ids(data, result),
+            (Command::ReadData, Some(data)) => client.read_done(data, result),
+            (Command::WriteData, Some(data)) => client.write_done(data, result.map(|_| ())),
+            (_, None) => {}
+        });
+    }
+}
+
+impl<'a, T: Transceive<'a>> TransceiveClient for Desfire<'a, T> {
+    fn transceive_done(&self, frame: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        let command = match self.command.extract() {
+            Some(command) => command,
+            None => {
+                self.frame.replace(frame);
+                return;
+            }
+        };
+        let more = result.map_err(DesfireError::Transceive).and_then(|len| {
+            let response = frame.get(..len).ok_or(DesfireError::Malformed)?;
+            self.receive(command, response)
+        });
+        let result = match more {
+            Ok(true) => match self.continue_command(command, frame) {
+                Ok(()) => return,
+                Err(e) => Err(DesfireError::Transceive(e)),
+            },
+            Ok(false) => {
+                self.frame.replace(frame);
+                Ok(self.offset.get())
+            }
+            Err(e) => {
+                self.frame.replace(frame);
+                Err(e)
+            }
+        };
+        self.finish(command, result);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A card, or a reader's frames, answered by the test.
+    pub(crate) struct MockTransceiver {
+        sent: RefCell<Vec<Vec<u8>>>,
+        buffer: TakeCell<'static, [u8]>,
+        client: OptionalCell<&'static dyn TransceiveClient>,
+    }
+
+    impl MockTransceiver {
+        pub(crate) fn new() -> &'static MockTransceiver {
+            Box::leak(Box::new(MockTransceiver {
+                sent: RefCell::new(Vec::new()),
+                buffer: TakeCell::empty(),
+                client: OptionalCell::empty(),
+            }))
+        }
+
+        pub(crate) fn busy(&self) -> bool {
+            self.buffer.is_some()
+        }
+
+        /// Answer the exchange in progress with `response`, and return what
+        /// was sent.
+        pub(crate) fn answer(&self, response: &[u8]) -> Vec<u8> {
+            let buffer = self.buffer.take().expect("no exchange in progress");
+            buffer[..response.len()].copy_from_slice(response);
+            let sent = self.sent.borrow().last().unwrap().clone();
+            self.client
+                .map(|client| client.transceive_done(buffer, Ok(response.len())));
+            sent
+        }
+
+        pub(crate) fn fail(&self, error: ErrorCode) {
+            let buffer = self.buffer.take().expect("no exchange in progress");
+            self.client
+                .map(|client| client.transceive_done(buffer, Err(error)));
+        }
+    }
+
+    impl Transceive<'static> for MockTransceiver {
+        fn set_client(&self, client: &'static dyn TransceiveClient) {
+            self.client.set(client);
+        }
+
+        fn transceive(
+            &self,
+            buffer: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            assert!(!self.busy());
+            self.sent.borrow_mut().push(buffer[..len].to_vec());
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Client {
+        selected: RefCell<Vec<Result<(), DesfireError>>>,
+        data: RefCell<Vec<(Vec<u8>, Result<usize, DesfireError>)>>,
+        written: RefCell<Vec<Result<(), DesfireError>>>,
+    }
+
+    impl DesfireClient for Client {
+        fn application_selected(&self, result: Result<(), DesfireError>) {
+            self.selected.borrow_mut().push(result);
+        }
+        fn file_ids(&self, buffer: &'static mut [u8], result: Result<usize, DesfireError>) {
+            let len = *result.as_ref().unwrap_or(&0);
+            self.data
+                .borrow_mut()
+                .push((buffer[..len].to_vec(), result));
+        }
+        fn read_done(&self, buffer: &'static mut [u8], result: Result<usize, DesfireError>) {
+            let len = *result.as_ref().unwrap_or(&0);
+            self.data
+                .borrow_mut()
+                .push((buffer[..len].to_vec(), result));
+        }
+        fn write_done(&self, _buffer: &'static mut [u8], result: Result<(), DesfireError>) {
+            self.written.borrow_mut().push(result);
+        }
+    }
+
+    type TestDesfire = Desfire<'static, MockTransceiver>;
+
+    fn setup() -> (
+        &'static MockTransceiver,
+        &'static TestDesfire,
+        &'static Client,
+    ) {
+        let card = MockTransceiver::new();
+        let desfire = Box::leak(Box::new(Desfire::new(
+            card,
+            Box::leak(Box::new([0; BUFFER_LEN])),
+        )));
+        let client: &'static Client = Box::leak(Box::default());
+        card.set_client(desfire);
+        desfire.set_client(client);
+        (card, desfire, client)
+    }
+
+    #[test]
+    fn wrapped_apdu_framing() {
+        let mut frame = [0; 16];
+        assert_eq!(wrap(&mut frame, CMD_GET_FILE_IDS, 0), 5);
+        assert_eq!(frame[..5], [0x90, 0x6F, 0x00, 0x00, 0x00]);
+
+        frame[HEADER_LEN..HEADER_LEN + 3].copy_from_slice(&[0x01, 0x02, 0x03]);
+        assert_eq!(wrap(&mut frame, CMD_SELECT_APPLICATION, 3), 9);
+        assert_eq!(
+            frame[..9],
+            [0x90, 0x5A, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03, 0x00]
+        );
+
+        assert_eq!(unwrap(&[0x01, 0x02, 0x91, 0xAF]), Ok((&[1u8, 2][..], 0xAF)));
+        assert_eq!(unwrap(&[0x91, 0x00]), Ok((&[][..], 0x00)));
+        assert_eq!(unwrap(&[0x90, 0x00]), Err(DesfireError::Malformed));
+        assert_eq!(unwrap(&[0x00]), Err(DesfireError::Malformed));
+    }
+
+    #[test]
+    fn select_application_and_card_errors() {
+        let (card, desfire, client) = setup();
+        assert_eq!(desfire.select_application(1 << 24), Err(ErrorCode::INVAL));
+        assert_eq!(desfire.select_application(0x123456), Ok(()));
+        assert_eq!(desfire.select_application(0), Err(ErrorCode::BUSY));
+        assert_eq!(
+            card.answer(&[0x91, 0x00]),
+            [0x90, 0x5A, 0x00, 0x00, 0x03, 0x56, 0x34, 0x12, 0x00]
+        );
+
+        // Application not found.
+        assert_eq!(desfire.select_application(0x010203), Ok(()));
+        card.answer(&[0x91, 0xA0]);
+        assert_eq!(desfire.select_application(0x010203), Ok(()));
+        card.fail(ErrorCode::NOACK);
+        assert_eq!(
+            *client.selected.borrow(),
+            [
+                Ok(()),
+                Err(DesfireError::Card(0xA0)),
+                Err(DesfireError::Transceive(ErrorCode::NOACK))
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_follow_additional_frames() {
+        let (card, desfire, client) = setup();
+        assert!(desfire.get_file_ids(Box::leak(Box::new([0; 32]))).is_ok());
+        assert_eq!(card.answer(&[0, 1, 2, 0x91, 0x00]), [0x90, 0x6F, 0, 0, 0]);
+
+        // 70 bytes from offset 10 of file 2, in two frames.
+        let contents: Vec<u8> = (0..70).collect();
+        assert!(desfire
+            .read_data(2, 10, Box::leak(Box::new([0; 80])), 70)
+            .is_ok());
+        let mut first = contents[..59].to_vec();
+        first.extend([0x91, 0xAF]);
+        assert_eq!(
+            card.answer(&first),
+            [0x90, 0xBD, 0, 0, 7, 2, 10, 0, 0, 70, 0, 0, 0]
+        );
+        let mut last = contents[59..].to_vec();
+        last.extend([0x91, 0x00]);
+        assert_eq!(card.answer(&last), [0x90, 0xAF, 0, 0, 0]);
+        assert!(!card.busy());
+
+        // A card that sends more than asked for.
+        assert!(desfire
+            .read_data(2, 0, Box::leak(Box::new([0; 80])), 4)
+            .is_ok());
+        card.answer(&[1, 2, 3, 4, 5, 0x91, 0x00]);
+
+        assert_eq!(
+            *client.data.borrow(),
+            [
+                (std::vec![0, 1, 2], Ok(3)),
+                (contents, Ok(70)),
+                (Vec::new(), Err(DesfireError::Malformed)),
+            ]
+        );
+        assert_eq!(
+            desfire
+                .read_data(2, 0, Box::leak(Box::new([0; 4])), 5)
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn writes_are_sent_in_additional_frames() {
+        let (card, desfire, client) = setup();
+        let data: Vec<u8> = (0..100).collect();
+        let buffer = Box::leak(data.clone().into_boxed_slice());
+        assert!(desfire.write_data(1, 0x0100, buffer, 100).is_ok());
+
+        // The parameters and the first 45 bytes, then the rest in frames
+        // of up to 52 bytes.
+        let mut first = std::vec![0x90, 0x3D, 0, 0, 52, 1, 0x00, 0x01, 0, 100, 0, 0];
+        first.extend(&data[..45]);
+        first.push(0x00);
+        assert_eq!(card.answer(&[0x91, 0xAF]), first);
+        let mut second = std::vec![0x90, 0xAF, 0, 0, 52];
+        second.extend(&data[45..97]);
+        second.push(0x00);
+        assert_eq!(card.answer(&[0x91, 0xAF]), second);
+        let mut last = std::vec![0x90, 0xAF, 0, 0, 3];
+        last.extend(&data[97..]);
+        last.push(0x00);
+        assert_eq!(card.answer(&[0x91, 0x00]), last);
+        assert_eq!(*client.written.borrow(), [Ok(())]);
+
+        // A card that stops asking for data early.
+        let buffer = Box::leak(data.into_boxed_slice());
+        assert!(desfire.write_data(1, 0, buffer, 100).is_ok());
+        card.answer(&[0x91, 0x00]);
+        assert_eq!(client.written.borrow()[1], Err(DesfireError::Malformed));
+        assert!(!card.busy());
+    }
+}
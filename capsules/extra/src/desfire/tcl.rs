@@ -0,0 +1,334 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! ISO 14443-4 (T=CL) block transmission over ISO 14443-3 frames.
+//!
+//! An APDU is sent in I-blocks of at most the size of the frame buffer. If
+//! it does not fit in one, the blocks are chained, and the card acknowledges
+//! each one with an R(ACK) block before the next is sent. The answer of the
+//! card is received the same way, with the reader acknowledging chained
+//! I-blocks. Waiting time extension requests (S(WTX)) are echoed back.
+//!
+//! The blocks have no CID nor NAD. The lower [`Transceive`] exchanges
+//! frames, with the reader adding and checking the CRC.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcl = static_init!(
+//!     capsules_extra::desfire::tcl::Tcl<'static, Reader>,
+//!     capsules_extra::desfire::tcl::Tcl::new(reader, static_init!([u8; 32], [0; 32]))
+//! );
+//! capsules_extra::desfire::Transceive::set_client(reader, tcl);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::{Transceive, TransceiveClient};
+
+const PCB_I_BLOCK: u8 = 0x02;
+const PCB_R_ACK: u8 = 0xA2;
+const PCB_S_WTX: u8 = 0xF2;
+const PCB_CHAINING: u8 = 0x10;
+const PCB_BLOCK_NUMBER: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Sending the I-block of the APDU from `sent`.
+    Sending,
+    /// Receiving the chained I-blocks of the answer.
+    Receiving,
+}
+
+pub struct Tcl<'a, T: Transceive<'a>> {
+    frames: &'a T,
+    block: TakeCell<'static, [u8]>,
+    apdu: TakeCell<'static, [u8]>,
+    apdu_len: Cell<usize>,
+    /// Bytes of the APDU acknowledged by the card.
+    sent: Cell<usize>,
+    /// Bytes of the APDU in the I-block in flight.
+    chunk: Cell<usize>,
+    /// Bytes of the answer received.
+    received: Cell<usize>,
+    block_number: Cell<u8>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn TransceiveClient>,
+}
+
+impl<'a, T: Transceive<'a>> Tcl<'a, T> {
+    /// `block` holds a frame of up to the frame size the card accepts,
+    /// without the CRC.
+    pub fn new(frames: &'a T, block: &'static mut [u8]) -> Tcl<'a, T> {
+        Tcl {
+            frames,
+            block: TakeCell::new(block),
+            apdu: TakeCell::empty(),
+            apdu_len: Cell::new(0),
+            sent: Cell::new(0),
+            chunk: Cell::new(0),
+            received: Cell::new(0),
+            block_number: Cell::new(0),
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts over with block number 0, after a card is activated.
+    pub fn reset(&self) {
+        self.block_number.set(0);
+    }
+
+    fn send(&self, block: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        self.frames.transceive(block, len).map_err(|(e, block)| {
+            self.block.replace(block);
+            e
+        })
+    }
+
+    fn send_i_block(&self, block: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let sent = self.sent.get();
+        let remaining = self.apdu_len.get() - sent;
+        let chunk = remaining.min(block.len() - 1);
+        let mut pcb = PCB_I_BLOCK | self.block_number.get();
+        if chunk < remaining {
+            pcb |= PCB_CHAINING;
+        }
+        block[0] = pcb;
+        self.apdu
+            .map(|apdu| block[1..=chunk].copy_from_slice(&apdu[sent..sent + chunk]));
+        self.chunk.set(chunk);
+        self.state.set(State::Sending);
+        self.send(block, chunk + 1)
+    }
+
+    fn toggle_block_number(&self) {
+        self.block_number
+            .set(self.block_number.get() ^ PCB_BLOCK_NUMBER);
+    }
+
+    /// Handles a block from the card, and sends the next block if any.
+    fn receive(&self, block: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        let pcb = match block.get(..len).and_then(|frame| frame.first()) {
+            Some(pcb) => *pcb,
+            None => {
+                self.block.replace(block);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+        let state = self.state.get();
+        let last_block = self.sent.get() + self.chunk.get() == self.apdu_len.get();
+
+        if pcb == PCB_S_WTX && len >= 2 {
+            // Keep the multiplier, drop the power level bits.
+            block[1] &= 0x3F;
+            self.send(block, 2)
+        } else if pcb == PCB_R_ACK | self.block_number.get()
+            && state == State::Sending
+            && !last_block
+        {
+            self.sent.set(self.sent.get() + self.chunk.get());
+            self.toggle_block_number();
+            self.send_i_block(block)
+        } else if pcb & !(PCB_CHAINING | PCB_BLOCK_NUMBER) == PCB_I_BLOCK
+            && pcb & PCB_BLOCK_NUMBER == self.block_number.get()
+            && (state == State::Receiving || last_block)
+        {
+            self.toggle_block_number();
+            let received = self.received.get();
+            let end = received + len - 1;
+            let fits = self
+                .apdu
+                .map_or(false, |apdu| match apdu.get_mut(received..end) {
+                    Some(answer) => {
+                        answer.copy_from_slice(&block[1..len]);
+                        true
+                    }
+                    None => false,
+                });
+            if !fits {
+                self.block.replace(block);
+                return Err(ErrorCode::SIZE);
+            }
+            self.received.set(end);
+            if pcb & PCB_CHAINING != 0 {
+                block[0] = PCB_R_ACK | self.block_number.get();
+                self.state.set(State::Receiving);
+                self.send(block, 1)
+            } else {
+                self.block.replace(block);
+                self.finish(Ok(end));
+                Ok(())
+            }
+        } else {
+            self.block.replace(block);
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    fn finish(&self, result: Result<usize, ErrorCode>) {
+        self.state.set(State::Idle);
+        if let Some(apdu) = self.apdu.take() {
+            self.client
+                .map(|client| client.transceive_done(apdu, result));
+        }
+    }
+}
+
+impl<'a, T: Transceive<'a>> Transceive<'a> for Tcl<'a, T> {
+    fn set_client(&self, client: &'a dyn TransceiveClient) {
+        self.client.set(client);
+    }
+
+    fn transceive(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if len == 0 || len > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        let block = match self.block.take() {
+            Some(block) => block,
+            None => return Err((ErrorCode::BUSY, buffer)),
+        };
+        self.apdu.replace(buffer);
+        self.apdu_len.set(len);
+        self.sent.set(0);
+        self.received.set(0);
+        self.send_i_block(block).map_err(|e| {
+            self.state.set(State::Idle);
+            (e, self.apdu.take().unwrap())
+        })
+    }
+}
+
+impl<'a, T: Transceive<'a>> TransceiveClient for Tcl<'a, T> {
+    fn transceive_done(&self, block: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        if self.state.get() == State::Idle {
+            self.block.replace(block);
+            return;
+        }
+        let result = match result {
+            Ok(len) => self.receive(block, len),
+            Err(e) => {
+                self.block.replace(block);
+                Err(e)
+            }
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::tests::MockTransceiver;
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        answers: RefCell<Vec<Result<Vec<u8>, ErrorCode>>>,
+    }
+
+    impl TransceiveClient for Client {
+        fn transceive_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+            self.answers
+                .borrow_mut()
+                .push(result.map(|len| buffer[..len].to_vec()));
+        }
+    }
+
+    fn setup(
+        block_len: usize,
+    ) -> (
+        &'static MockTransceiver,
+        &'static Tcl<'static, MockTransceiver>,
+        &'static Client,
+    ) {
+        let reader = MockTransceiver::new();
+        let block = Box::leak(std::vec![0; block_len].into_boxed_slice());
+        let tcl = Box::leak(Box::new(Tcl::new(reader, block)));
+        let client: &'static Client = Box::leak(Box::default());
+        reader.set_client(tcl);
+        tcl.set_client(client);
+        (reader, tcl, client)
+    }
+
+    fn apdu(bytes: &[u8]) -> &'static mut [u8] {
+        let mut buffer = std::vec![0; 32];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        Box::leak(buffer.into_boxed_slice())
+    }
+
+    #[test]
+    fn single_blocks_toggle_block_number() {
+        let (reader, tcl, client) = setup(16);
+        assert!(tcl.transceive(apdu(&[0x90, 0x6F, 0, 0, 0]), 5).is_ok());
+        assert!(tcl.transceive(apdu(&[]), 1).is_err());
+        assert_eq!(
+            reader.answer(&[0x02, 0x01, 0x91, 0x00]),
+            [0x02, 0x90, 0x6F, 0, 0, 0]
+        );
+
+        assert!(tcl.transceive(apdu(&[0x90, 0x6F, 0, 0, 0]), 5).is_ok());
+        // The card asks for more time before answering.
+        assert_eq!(reader.answer(&[0xF2, 0x41]), [0x03, 0x90, 0x6F, 0, 0, 0]);
+        assert_eq!(reader.answer(&[0x03, 0x91, 0x00]), [0xF2, 0x01]);
+        assert!(!reader.busy());
+
+        assert_eq!(
+            *client.answers.borrow(),
+            [Ok(std::vec![0x01, 0x91, 0x00]), Ok(std::vec![0x91, 0x00])]
+        );
+    }
+
+    #[test]
+    fn chained_blocks() {
+        let (reader, tcl, client) = setup(4);
+        let command = [0x90, 0x3D, 0, 0, 2, 0xAA, 0xBB, 0];
+        assert!(tcl.transceive(apdu(&command), 8).is_ok());
+
+        // Sent three bytes at a time, each chained block acknowledged.
+        assert_eq!(reader.answer(&[0xA2]), [0x12, 0x90, 0x3D, 0]);
+        assert_eq!(reader.answer(&[0xA3]), [0x13, 0, 2, 0xAA]);
+        // The answer comes back chained too.
+        assert_eq!(reader.answer(&[0x12, 1, 2, 3]), [0x02, 0xBB, 0]);
+        assert_eq!(reader.answer(&[0x03, 0x91, 0x00]), [0xA3]);
+        assert!(!reader.busy());
+
+        // An acknowledgement with the wrong block number.
+        assert!(tcl.transceive(apdu(&command), 8).is_ok());
+        assert_eq!(reader.answer(&[0xA3]), [0x12, 0x90, 0x3D, 0]);
+
+        assert_eq!(
+            *client.answers.borrow(),
+            [Ok(std::vec![1, 2, 3, 0x91, 0x00]), Err(ErrorCode::FAIL)]
+        );
+    }
+
+    #[test]
+    fn answer_too_long() {
+        let (reader, tcl, client) = setup(64);
+        let buffer = Box::leak(Box::new([0x90, 0x6F, 0, 0, 0]));
+        assert!(tcl.transceive(buffer, 5).is_ok());
+        reader.answer(&[0x02, 1, 2, 3, 4, 5, 0x91, 0x00]);
+        assert!(!reader.busy());
+        assert_eq!(*client.answers.borrow(), [Err(ErrorCode::SIZE)]);
+    }
+}
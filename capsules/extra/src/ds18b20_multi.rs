@@ -0,0 +1,504 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for several Maxim DS18B20 temperature sensors on one 1-Wire bus.
+//!
+//! <https://www.analog.com/media/en/technical-documentation/data-sheets/DS18B20.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! [Ds18b20Multi::scan] finds the sensors on the bus with the ROM search
+//! algorithm, and keeps the ROM codes of up to `N` of them. Devices that are
+//! not DS18B20s, and ROM codes with a bad CRC, are skipped.
+//!
+//! [Ds18b20Multi::read] starts a conversion on every sensor at once by
+//! broadcasting `CONVERT T` after `SKIP ROM`, waits the 750 ms a 12-bit
+//! conversion takes, and then reads the scratchpad of each sensor addressed
+//! by its ROM code with `MATCH ROM`. Temperatures are in hundredths of a
+//! degree Celsius. A slot is `None` if no sensor was found for it, or if the
+//! sensor did not answer: a sensor removed after the scan leaves the bus
+//! high, which reads as a scratchpad of all `0xFF`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ds18b20 = components::ds18b20_multi::Ds18b20MultiComponent::new(
+//!     onewire_bus,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ds18b20_multi_component_static!(
+//!     OneWireBus,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     4
+//! ));
+//! ds18b20.set_client(thermostat);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::onewire::{self, OneWire, Triplet};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 10;
+
+const SEARCH_ROM: u8 = 0xF0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+
+/// Family code of the DS18B20, the first byte of its ROM code.
+const FAMILY_DS18B20: u8 = 0x28;
+
+const SCRATCHPAD_LEN: usize = 9;
+const ROM_BITS: u8 = 64;
+
+/// Time a 12-bit conversion takes.
+const CONVERSION_MS: u32 = 750;
+
+pub trait Ds18b20MultiClient<const N: usize> {
+    /// The bus was scanned, and `sensors` were found.
+    fn scan_done(&self, result: Result<usize, ErrorCode>);
+
+    /// The temperatures of the sensors, in hundredths of a degree Celsius,
+    /// in the order they were found.
+    fn readings_done(&self, result: Result<[Option<i16>; N], ErrorCode>);
+}
+
+/// Dallas/Maxim CRC-8, used by ROM codes and scratchpads.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 1 == 1 {
+                crc >> 1 ^ 0x8C
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// The temperature in a scratchpad, or `None` if it is not valid.
+fn scratchpad_temperature(scratchpad: &[u8]) -> Option<i16> {
+    if scratchpad.iter().all(|&byte| byte == 0xFF)
+        || crc8(&scratchpad[..SCRATCHPAD_LEN - 1]) != scratchpad[SCRATCHPAD_LEN - 1]
+    {
+        return None;
+    }
+    // In sixteenths of a degree.
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as i32;
+    Some((raw * 100 / 16) as i16)
+}
+
+/// State of the ROM search algorithm (Maxim application note 187) across
+/// the passes that each find one device.
+#[derive(Clone, Copy)]
+struct RomSearch {
+    rom: u64,
+    /// The last bit, counted from 1, where the pass took the 0 branch with
+    /// devices left on the 1 branch. The next pass takes the 1 branch there.
+    last_discrepancy: u8,
+    last_zero: u8,
+    /// The last pass found the last device.
+    done: bool,
+}
+
+impl RomSearch {
+    fn new() -> RomSearch {
+        RomSearch {
+            rom: 0,
+            last_discrepancy: 0,
+            last_zero: 0,
+            done: false,
+        }
+    }
+
+    /// The branch to take where devices disagree on `bit`.
+    fn direction(&self, bit: u8) -> bool {
+        if bit + 1 < self.last_discrepancy {
+            self.rom >> bit & 1 == 1
+        } else {
+            bit + 1 == self.last_discrepancy
+        }
+    }
+
+    /// Record the result of the triplet for `bit`. Returns false if no
+    /// device answered.
+    fn record(&mut self, bit: u8, triplet: Triplet) -> bool {
+        if triplet.id_bit && triplet.complement_bit {
+            return false;
+        }
+        if !triplet.id_bit && !triplet.complement_bit && !triplet.direction {
+            self.last_zero = bit + 1;
+        }
+        if triplet.direction {
+            self.rom |= 1 << bit;
+        } else {
+            self.rom &= !(1 << bit);
+        }
+        true
+    }
+
+    /// End a pass, returning the ROM code found.
+    fn finish(&mut self) -> u64 {
+        self.last_discrepancy = self.last_zero;
+        self.last_zero = 0;
+        self.done = self.last_discrepancy == 0;
+        self.rom
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    SearchReset,
+    SearchCommand,
+    SearchTriplet(u8),
+    ConvertReset,
+    ConvertCommand,
+    Converting,
+    ReadReset(usize),
+    ReadCommand(usize),
+    ReadScratchpad(usize),
+}
+
+pub struct Ds18b20Multi<'a, B: OneWire<'a>, A: Alarm<'a>, const N: usize> {
+    bus: &'a B,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn Ds18b20MultiClient<N>>,
+    state: Cell<State>,
+    search: Cell<RomSearch>,
+    roms: Cell<[u64; N]>,
+    found: Cell<usize>,
+    temperatures: Cell<[Option<i16>; N]>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, B: OneWire<'a>, A: Alarm<'a>, const N: usize> Ds18b20Multi<'a, B, A, N> {
+    pub fn new(bus: &'a B, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        Ds18b20Multi {
+            bus,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            search: Cell::new(RomSearch::new()),
+            roms: Cell::new([0; N]),
+            found: Cell::new(0),
+            temperatures: Cell::new([None; N]),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Ds18b20MultiClient<N>) {
+        self.client.set(client);
+    }
+
+    /// The number of sensors found by the last scan.
+    pub fn sensors(&self) -> usize {
+        self.found.get()
+    }
+
+    /// Find the sensors on the bus, replacing those found before.
+    pub fn scan(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.search.set(RomSearch::new());
+        self.found.set(0);
+        self.reset(State::SearchReset)
+    }
+
+    /// Read the temperature of every sensor found.
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.found.get() == 0 {
+            return Err(ErrorCode::NODEVICE);
+        }
+        self.temperatures.set([None; N]);
+        self.reset(State::ConvertReset)
+    }
+
+    fn reset(&self, state: State) -> Result<(), ErrorCode> {
+        self.bus.reset()?;
+        self.state.set(state);
+        Ok(())
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        match self.bus.write(buffer, bytes.len()) {
+            Ok(()) => {
+                self.state.set(state);
+                Ok(())
+            }
+            Err((error, buffer)) => {
+                self.buffer.replace(buffer);
+                Err(error)
+            }
+        }
+    }
+
+    fn triplet(&self, bit: u8) -> Result<(), ErrorCode> {
+        self.bus.triplet(self.search.get().direction(bit))?;
+        self.state.set(State::SearchTriplet(bit));
+        Ok(())
+    }
+
+    /// Address sensor `index` and ask for its scratchpad.
+    fn write_read_command(&self, index: usize) -> Result<(), ErrorCode> {
+        let mut command = [0; BUFFER_SIZE];
+        command[0] = MATCH_ROM;
+        command[1..9].copy_from_slice(&self.roms.get()[index].to_le_bytes());
+        command[9] = READ_SCRATCHPAD;
+        self.write(State::ReadCommand(index), &command)
+    }
+
+    /// Keep the ROM code found by a search pass, and start the next pass.
+    fn search_pass_done(&self) -> Result<(), ErrorCode> {
+        let mut search = self.search.get();
+        let rom = search.finish();
+        self.search.set(search);
+
+        let bytes = rom.to_le_bytes();
+        if bytes[0] == FAMILY_DS18B20 && crc8(&bytes[..7]) == bytes[7] {
+            let mut roms = self.roms.get();
+            roms[self.found.get()] = rom;
+            self.roms.set(roms);
+            self.found.set(self.found.get() + 1);
+        }
+
+        if search.done || self.found.get() == N {
+            self.scan_done(Ok(self.found.get()));
+            Ok(())
+        } else {
+            self.reset(State::SearchReset)
+        }
+    }
+
+    /// Move on to the sensor after `index`, or report the readings.
+    fn next_sensor(&self, index: usize) -> Result<(), ErrorCode> {
+        if index + 1 < self.found.get() {
+            self.reset(State::ReadReset(index + 1))
+        } else {
+            self.state.set(State::Idle);
+            self.client
+                .map(|client| client.readings_done(Ok(self.temperatures.get())));
+            Ok(())
+        }
+    }
+
+    fn scan_done(&self, result: Result<usize, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client.map(|client| client.scan_done(result));
+    }
+
+    /// End the operation in progress with `error`.
+    fn fail(&self, error: ErrorCode) {
+        match self.state.get() {
+            State::SearchReset | State::SearchCommand | State::SearchTriplet(_) => {
+                self.scan_done(Err(error))
+            }
+            State::Idle => {}
+            _ => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.readings_done(Err(error)));
+            }
+        }
+    }
+}
+
+impl<'a, B: OneWire<'a>, A: Alarm<'a>, const N: usize> onewire::Client
+    for Ds18b20Multi<'a, B, A, N>
+{
+    fn reset_done(&self, result: Result<bool, ErrorCode>) {
+        let result = result.and_then(|presence| match self.state.get() {
+            State::SearchReset if presence => self.write(State::SearchCommand, &[SEARCH_ROM]),
+            State::SearchReset => {
+                self.scan_done(Ok(self.found.get()));
+                Ok(())
+            }
+            State::ConvertReset if presence => {
+                self.write(State::ConvertCommand, &[SKIP_ROM, CONVERT_T])
+            }
+            State::ConvertReset => Err(ErrorCode::NODEVICE),
+            State::ReadReset(index) if presence => self.write_read_command(index),
+            // Every sensor was removed.
+            State::ReadReset(index) => self.next_sensor(index),
+            _ => Ok(()),
+        });
+        if let Err(error) = result {
+            self.fail(error);
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.buffer.replace(buffer);
+        let result = result.and_then(|()| match self.state.get() {
+            State::SearchCommand => self.triplet(0),
+            State::ConvertCommand => {
+                self.state.set(State::Converting);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(CONVERSION_MS));
+                Ok(())
+            }
+            State::ReadCommand(index) => match self.buffer.take() {
+                Some(buffer) => match self.bus.read(buffer, SCRATCHPAD_LEN) {
+                    Ok(()) => {
+                        self.state.set(State::ReadScratchpad(index));
+                        Ok(())
+                    }
+                    Err((error, buffer)) => {
+                        self.buffer.replace(buffer);
+                        Err(error)
+                    }
+                },
+                None => Err(ErrorCode::NOMEM),
+            },
+            _ => Ok(()),
+        });
+        if let Err(error) = result {
+            self.fail(error);
+        }
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        let temperature = scratchpad_temperature(&buffer[..SCRATCHPAD_LEN]);
+        self.buffer.replace(buffer);
+        let result = result.and_then(|()| match self.state.get() {
+            State::ReadScratchpad(index) => {
+                let mut temperatures = self.temperatures.get();
+                temperatures[index] = temperature;
+                self.temperatures.set(temperatures);
+                self.next_sensor(index)
+            }
+            _ => Ok(()),
+        });
+        if let Err(error) = result {
+            self.fail(error);
+        }
+    }
+
+    fn triplet_done(&self, result: Result<Triplet, ErrorCode>) {
+        let result = result.and_then(|triplet| match self.state.get() {
+            State::SearchTriplet(bit) => {
+                let mut search = self.search.get();
+                let answered = search.record(bit, triplet);
+                self.search.set(search);
+                if !answered {
+                    // The devices left during the search.
+                    self.scan_done(Ok(self.found.get()));
+                    Ok(())
+                } else if bit + 1 < ROM_BITS {
+                    self.triplet(bit + 1)
+                } else {
+                    self.search_pass_done()
+                }
+            }
+            _ => Ok(()),
+        });
+        if let Err(error) = result {
+            self.fail(error);
+        }
+    }
+}
+
+impl<'a, B: OneWire<'a>, A: Alarm<'a>, const N: usize> time::AlarmClient
+    for Ds18b20Multi<'a, B, A, N>
+{
+    fn alarm(&self) {
+        if self.state.get() == State::Converting {
+            if let Err(error) = self.reset(State::ReadReset(0)) {
+                self.fail(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// A ROM code with a valid CRC.
+    fn rom(family: u8, serial: u64) -> u64 {
+        let mut bytes = (serial << 8 | family as u64).to_le_bytes();
+        bytes[7] = crc8(&bytes[..7]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Run the ROM search over devices on a wired-AND bus.
+    fn search_all(devices: &[u64]) -> Vec<u64> {
+        let mut search = RomSearch::new();
+        let mut found = Vec::new();
+        while !search.done {
+            let mut taking_part: Vec<u64> = devices.to_vec();
+            for bit in 0..ROM_BITS {
+                let id_bit = taking_part.iter().all(|rom| rom >> bit & 1 == 1);
+                let complement_bit = taking_part.iter().all(|rom| rom >> bit & 1 == 0);
+                let direction = if id_bit != complement_bit {
+                    id_bit
+                } else {
+                    search.direction(bit)
+                };
+                let triplet = Triplet {
+                    id_bit,
+                    complement_bit,
+                    direction,
+                };
+                if !search.record(bit, triplet) {
+                    return found;
+                }
+                taking_part.retain(|rom| (rom >> bit & 1 == 1) == direction);
+            }
+            found.push(search.finish());
+        }
+        found
+    }
+
+    #[test]
+    fn search_finds_every_device() {
+        let devices = [
+            rom(FAMILY_DS18B20, 0x0000_0000_1234),
+            rom(FAMILY_DS18B20, 0x8000_0000_1234),
+            rom(0x10, 0x0000_0000_1234),
+            rom(FAMILY_DS18B20, 0x0000_0000_1235),
+        ];
+        let mut found = search_all(&devices);
+        found.sort();
+        let mut expected = devices.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        assert_eq!(search_all(&devices[..1]), [devices[0]]);
+    }
+
+    #[test]
+    fn scratchpad_temperatures() {
+        // +25.0625 C, the example in the datasheet.
+        let mut scratchpad = [0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0x00];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        assert_eq!(scratchpad_temperature(&scratchpad), Some(2506));
+
+        // -10.125 C.
+        let mut scratchpad = [0x5E, 0xFF, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0x00];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        assert_eq!(scratchpad_temperature(&scratchpad), Some(-1012));
+
+        // A sensor that was removed.
+        assert_eq!(scratchpad_temperature(&[0xFF; SCRATCHPAD_LEN]), None);
+
+        scratchpad[1] = 0x00;
+        assert_eq!(scratchpad_temperature(&scratchpad), None);
+    }
+}
@@ -0,0 +1,1067 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for SSD1680 and UC8151 e-paper displays on a 4-wire SPI interface.
+//!
+//! Both controllers take a command byte with the DC pin low, followed by its
+//! parameters with the DC pin high, and signal with the busy pin while they
+//! are working. Two panels are supported, with their geometry as the const
+//! generics of [Eink]:
+//!
+//! - [Waveshare2_9]: Waveshare 2.9" module, SSD1680, 296×128.
+//! - [WavetailJelly]: Wavetail Jelly, UC8151, 212×104.
+//!
+//! The geometry is that of the controller's memory, in which both panels are
+//! taller than they are wide. Rotation is not supported.
+//!
+//! Pixels are in the `Mono` format, 8 to a byte with the leftmost in the most
+//! significant bit, and a set bit is a white pixel. Writes go to a frame
+//! buffer of the whole panel, so the write frame must start and end on a
+//! column that is a multiple of 8. The panel is refreshed once the last
+//! pixel of the write frame has been written, and the write completes when
+//! the refresh has finished; writes that leave part of the frame to
+//! `write_continue` complete straight away.
+//!
+//! A refresh is either a full refresh, which flashes the panel with the
+//! controller's own waveform and takes around 2 s (up to 10 s when cold), or
+//! a partial refresh with the fast waveform of this driver, which takes under
+//! 0.5 s but leaves ghosting behind. The first refresh after the panel is
+//! powered on is always a full one, because a partial refresh needs the
+//! previous image in the controller's memory.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let eink = components::eink::EinkComponent::new(
+//!     mux_spi,
+//!     &nrf52840::gpio::PORT[GPIO_D4],
+//!     &nrf52840::gpio::PORT[GPIO_D3],
+//!     &nrf52840::gpio::PORT[GPIO_D2],
+//!     &nrf52840::gpio::PORT[GPIO_D5],
+//!     mux_alarm,
+//!     &capsules_extra::eink::SSD1680,
+//! )
+//! .finalize(components::eink_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::gpio::GPIOPin<'static>,
+//!     128,
+//!     296,
+//! ));
+//! eink.set_refresh_mode(capsules_extra::eink::RefreshMode::Partial);
+//! let _ = eink.init();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::gpio::Pin;
+use kernel::hil::screen::{Screen, ScreenClient, ScreenPixelFormat, ScreenRotation};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the command buffer, which holds the longest parameter list: the
+/// SSD1680 waveform.
+pub const BUFFER_SIZE: usize = 160;
+
+/// Length of the frame buffer of a `width` × `height` panel.
+pub const fn framebuffer_len(width: usize, height: usize) -> usize {
+    width / 8 * height
+}
+
+/// Waveshare 2.9" e-paper module, to be used with [SSD1680].
+pub type Waveshare2_9<'a, A, S, P> = Eink<'a, A, S, P, 128, 296>;
+
+/// Wavetail Jelly e-paper display, to be used with [UC8151].
+pub type WavetailJelly<'a, A, S, P> = Eink<'a, A, S, P, 104, 212>;
+
+/// Time the reset pin is held low.
+const RESET_LOW_MS: u32 = 10;
+/// Time the controller needs after a hardware reset.
+const RESET_WAIT_MS: u32 = 10;
+/// Interval at which the busy pin is polled.
+const BUSY_POLL_MS: u32 = 20;
+/// Time after which the controller is given up on. A full refresh can keep
+/// it busy for 10 s at low temperatures.
+const BUSY_TIMEOUT_MS: u32 = 12_000;
+
+/// A step of a command sequence.
+#[derive(Clone, Copy)]
+enum Op {
+    /// Send a command with fixed parameters.
+    Command(u8, &'static [u8]),
+    /// Send a command with parameters that depend on the panel's geometry,
+    /// written to the buffer by the function from the width and height.
+    Geometry(u8, fn(usize, usize, &mut [u8]) -> usize),
+    /// Send a command followed by the frame buffer.
+    Frame(u8),
+    /// Wait for the controller to release the busy pin.
+    WaitBusy,
+}
+
+/// A controller supported by this driver.
+pub struct Controller {
+    /// Sent after a hardware reset.
+    init_sequence: &'static [Op],
+    full_refresh: &'static [Op],
+    partial_refresh: &'static [Op],
+    /// Puts the controller in deep sleep, from which only a hardware reset
+    /// wakes it.
+    sleep_sequence: &'static [Op],
+    /// Level of the busy pin while the controller is busy.
+    busy_level: bool,
+    /// SPI clock rate in Hz.
+    rate: u32,
+}
+
+mod ssd1680 {
+    pub const DRIVER_OUTPUT: u8 = 0x01;
+    pub const GATE_VOLTAGE: u8 = 0x03;
+    pub const SOURCE_VOLTAGE: u8 = 0x04;
+    pub const DEEP_SLEEP: u8 = 0x10;
+    pub const DATA_ENTRY_MODE: u8 = 0x11;
+    pub const SW_RESET: u8 = 0x12;
+    pub const TEMPERATURE_SENSOR: u8 = 0x18;
+    pub const MASTER_ACTIVATION: u8 = 0x20;
+    pub const UPDATE_CONTROL_1: u8 = 0x21;
+    pub const UPDATE_CONTROL_2: u8 = 0x22;
+    pub const WRITE_RAM_BW: u8 = 0x24;
+    /// The previous image, against which a partial refresh is made.
+    pub const WRITE_RAM_PREVIOUS: u8 = 0x26;
+    pub const VCOM: u8 = 0x2C;
+    pub const WRITE_LUT: u8 = 0x32;
+    pub const DISPLAY_OPTION: u8 = 0x37;
+    pub const BORDER_WAVEFORM: u8 = 0x3C;
+    pub const END_OPTION: u8 = 0x3F;
+    pub const RAM_X_RANGE: u8 = 0x44;
+    pub const RAM_Y_RANGE: u8 = 0x45;
+    pub const RAM_X_COUNTER: u8 = 0x4E;
+    pub const RAM_Y_COUNTER: u8 = 0x4F;
+
+    /// Load the waveform from OTP, then refresh with display mode 1.
+    pub const UPDATE_FULL: u8 = 0xF7;
+    /// Enable the clock and the analog circuits.
+    pub const UPDATE_ENABLE: u8 = 0xC0;
+    /// Refresh with display mode 2 and the waveform in the registers.
+    pub const UPDATE_PARTIAL: u8 = 0x0F;
+
+    /// Fast waveform: the voltage selection of the five LUTs, then the
+    /// timing of the twelve groups, then frame rate and gate scan selection.
+    pub const PARTIAL_LUT: [u8; 153] = [
+        // LUT0 to LUT4 voltage selection.
+        0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x80, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x40, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        // Group timing and repeat count.
+        0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, //
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        // Frame rate.
+        0x22, 0x22, 0x22, 0x22, 0x22, 0x22, //
+        // Gate scan selection.
+        0x00, 0x00, 0x00,
+    ];
+
+    /// Gates 0 to `height - 1`, scanned from gate 0.
+    pub fn driver_output(_width: usize, height: usize, buffer: &mut [u8]) -> usize {
+        let [low, high] = (height as u16 - 1).to_le_bytes();
+        buffer[..3].copy_from_slice(&[low, high, 0x00]);
+        3
+    }
+
+    /// Columns of the whole panel, in bytes.
+    pub fn ram_x_range(width: usize, _height: usize, buffer: &mut [u8]) -> usize {
+        buffer[..2].copy_from_slice(&[0x00, (width / 8 - 1) as u8]);
+        2
+    }
+
+    /// Rows of the whole panel.
+    pub fn ram_y_range(_width: usize, height: usize, buffer: &mut [u8]) -> usize {
+        let [low, high] = (height as u16 - 1).to_le_bytes();
+        buffer[..4].copy_from_slice(&[0x00, 0x00, low, high]);
+        4
+    }
+}
+
+mod uc8151 {
+    pub const PANEL_SETTING: u8 = 0x00;
+    pub const POWER_SETTING: u8 = 0x01;
+    pub const POWER_OFF: u8 = 0x02;
+    pub const POWER_ON: u8 = 0x04;
+    pub const BOOSTER_SOFT_START: u8 = 0x06;
+    pub const DEEP_SLEEP: u8 = 0x07;
+    /// The previous image, against which a partial refresh is made.
+    pub const DATA_START_1: u8 = 0x10;
+    pub const DISPLAY_REFRESH: u8 = 0x12;
+    pub const DATA_START_2: u8 = 0x13;
+    pub const LUT_VCOM: u8 = 0x20;
+    pub const LUT_WW: u8 = 0x21;
+    pub const LUT_KW: u8 = 0x22;
+    pub const LUT_WK: u8 = 0x23;
+    pub const LUT_KK: u8 = 0x24;
+    pub const LUT_BORDER: u8 = 0x25;
+    pub const PLL: u8 = 0x30;
+    pub const VCOM_DATA_INTERVAL: u8 = 0x50;
+    pub const RESOLUTION: u8 = 0x61;
+    pub const VCOM_DC: u8 = 0x82;
+
+    /// Black and white, waveform from OTP, scanning up and to the right,
+    /// booster on, no soft reset.
+    pub const PANEL_OTP_LUT: u8 = 0x1F;
+    /// As `PANEL_OTP_LUT`, with the waveform in the registers.
+    pub const PANEL_REGISTER_LUT: u8 = 0x3F;
+    /// Border from the border LUT, a set bit is white, default interval.
+    pub const VCOM_DATA_WHITE: u8 = 0x97;
+    /// Check code of `DEEP_SLEEP`.
+    pub const DEEP_SLEEP_CHECK: u8 = 0xA5;
+
+    /// A waveform made of one group, with the rest of its `N` bytes unused.
+    const fn lut<const N: usize>(group: [u8; 6]) -> [u8; N] {
+        let mut lut = [0; N];
+        let mut i = 0;
+        while i < group.len() {
+            lut[i] = group[i];
+            i += 1;
+        }
+        lut
+    }
+
+    // Each group is the level of its four phases (two bits each: ground,
+    // VDH, VDL or floating), their lengths in frames and its repeat count.
+    // Pixels that change are driven for 8 frames; the others, the border and
+    // VCOM are held at ground for as long.
+    pub const VCOM_LUT: [u8; 44] = lut([0x00, 0x08, 0x00, 0x00, 0x00, 0x01]);
+    pub const HOLD_LUT: [u8; 42] = lut([0x00, 0x08, 0x00, 0x00, 0x00, 0x01]);
+    pub const TO_WHITE_LUT: [u8; 42] = lut([0x80, 0x08, 0x00, 0x00, 0x00, 0x01]);
+    pub const TO_BLACK_LUT: [u8; 42] = lut([0x40, 0x08, 0x00, 0x00, 0x00, 0x01]);
+
+    /// Width, then height.
+    pub fn resolution(width: usize, height: usize, buffer: &mut [u8]) -> usize {
+        let [high, low] = (height as u16).to_be_bytes();
+        buffer[..3].copy_from_slice(&[width as u8, high, low]);
+        3
+    }
+}
+
+pub const SSD1680: Controller = Controller {
+    init_sequence: &[
+        Op::WaitBusy,
+        Op::Command(ssd1680::SW_RESET, &[]),
+        Op::WaitBusy,
+        Op::Geometry(ssd1680::DRIVER_OUTPUT, ssd1680::driver_output),
+        // X and Y increment, so the frame buffer is sent row by row.
+        Op::Command(ssd1680::DATA_ENTRY_MODE, &[0x03]),
+        Op::Geometry(ssd1680::RAM_X_RANGE, ssd1680::ram_x_range),
+        Op::Geometry(ssd1680::RAM_Y_RANGE, ssd1680::ram_y_range),
+        Op::Command(ssd1680::UPDATE_CONTROL_1, &[0x00, 0x80]),
+        Op::Command(ssd1680::TEMPERATURE_SENSOR, &[0x80]),
+        Op::WaitBusy,
+    ],
+    full_refresh: &[
+        Op::Command(ssd1680::BORDER_WAVEFORM, &[0x05]),
+        Op::Command(ssd1680::RAM_X_COUNTER, &[0x00]),
+        Op::Command(ssd1680::RAM_Y_COUNTER, &[0x00, 0x00]),
+        Op::Frame(ssd1680::WRITE_RAM_BW),
+        Op::Command(ssd1680::RAM_X_COUNTER, &[0x00]),
+        Op::Command(ssd1680::RAM_Y_COUNTER, &[0x00, 0x00]),
+        Op::Frame(ssd1680::WRITE_RAM_PREVIOUS),
+        Op::Command(ssd1680::UPDATE_CONTROL_2, &[ssd1680::UPDATE_FULL]),
+        Op::Command(ssd1680::MASTER_ACTIVATION, &[]),
+        Op::WaitBusy,
+    ],
+    partial_refresh: &[
+        Op::Command(ssd1680::WRITE_LUT, &ssd1680::PARTIAL_LUT),
+        Op::WaitBusy,
+        Op::Command(ssd1680::END_OPTION, &[0x22]),
+        Op::Command(ssd1680::GATE_VOLTAGE, &[0x17]),
+        Op::Command(ssd1680::SOURCE_VOLTAGE, &[0x41, 0xB0, 0x32]),
+        Op::Command(ssd1680::VCOM, &[0x36]),
+        // Ping-pong off, so the previous image stays in its RAM.
+        Op::Command(
+            ssd1680::DISPLAY_OPTION,
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00],
+        ),
+        // The border follows the waveform, so it is not flashed.
+        Op::Command(ssd1680::BORDER_WAVEFORM, &[0x80]),
+        Op::Command(ssd1680::UPDATE_CONTROL_2, &[ssd1680::UPDATE_ENABLE]),
+        Op::Command(ssd1680::MASTER_ACTIVATION, &[]),
+        Op::WaitBusy,
+        Op::Command(ssd1680::RAM_X_COUNTER, &[0x00]),
+        Op::Command(ssd1680::RAM_Y_COUNTER, &[0x00, 0x00]),
+        Op::Frame(ssd1680::WRITE_RAM_BW),
+        Op::Command(ssd1680::UPDATE_CONTROL_2, &[ssd1680::UPDATE_PARTIAL]),
+        Op::Command(ssd1680::MASTER_ACTIVATION, &[]),
+        Op::WaitBusy,
+        Op::Command(ssd1680::RAM_X_COUNTER, &[0x00]),
+        Op::Command(ssd1680::RAM_Y_COUNTER, &[0x00, 0x00]),
+        Op::Frame(ssd1680::WRITE_RAM_PREVIOUS),
+    ],
+    sleep_sequence: &[Op::Command(ssd1680::DEEP_SLEEP, &[0x01])],
+    busy_level: true,
+    rate: 10_000_000,
+};
+
+pub const UC8151: Controller = Controller {
+    init_sequence: &[
+        Op::WaitBusy,
+        Op::Command(uc8151::POWER_SETTING, &[0x03, 0x00, 0x2B, 0x2B, 0x03]),
+        Op::Command(uc8151::BOOSTER_SOFT_START, &[0x17, 0x17, 0x17]),
+        Op::Command(uc8151::POWER_ON, &[]),
+        Op::WaitBusy,
+        Op::Command(uc8151::PANEL_SETTING, &[uc8151::PANEL_OTP_LUT]),
+        // 50 Hz frame rate.
+        Op::Command(uc8151::PLL, &[0x3C]),
+        Op::Geometry(uc8151::RESOLUTION, uc8151::resolution),
+        Op::Command(uc8151::VCOM_DC, &[0x12]),
+        Op::Command(uc8151::VCOM_DATA_INTERVAL, &[uc8151::VCOM_DATA_WHITE]),
+    ],
+    full_refresh: &[
+        Op::Command(uc8151::PANEL_SETTING, &[uc8151::PANEL_OTP_LUT]),
+        Op::Frame(uc8151::DATA_START_2),
+        Op::Command(uc8151::DISPLAY_REFRESH, &[]),
+        Op::WaitBusy,
+        Op::Frame(uc8151::DATA_START_1),
+    ],
+    partial_refresh: &[
+        Op::Command(uc8151::PANEL_SETTING, &[uc8151::PANEL_REGISTER_LUT]),
+        Op::Command(uc8151::LUT_VCOM, &uc8151::VCOM_LUT),
+        Op::Command(uc8151::LUT_WW, &uc8151::HOLD_LUT),
+        Op::Command(uc8151::LUT_KW, &uc8151::TO_WHITE_LUT),
+        Op::Command(uc8151::LUT_WK, &uc8151::TO_BLACK_LUT),
+        Op::Command(uc8151::LUT_KK, &uc8151::HOLD_LUT),
+        Op::Command(uc8151::LUT_BORDER, &uc8151::HOLD_LUT),
+        Op::Frame(uc8151::DATA_START_2),
+        Op::Command(uc8151::DISPLAY_REFRESH, &[]),
+        Op::WaitBusy,
+        Op::Frame(uc8151::DATA_START_1),
+    ],
+    sleep_sequence: &[
+        Op::Command(uc8151::POWER_OFF, &[]),
+        Op::WaitBusy,
+        Op::Command(uc8151::DEEP_SLEEP, &[uc8151::DEEP_SLEEP_CHECK]),
+    ],
+    // BUSY_N is low while the controller is busy.
+    busy_level: false,
+    rate: 4_000_000,
+};
+
+/// How the panel is refreshed after a write.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RefreshMode {
+    /// Flash the whole panel to clear any ghosting.
+    Full,
+    /// Change only the pixels that differ from the previous image.
+    Partial,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Sequence {
+    Init,
+    FullRefresh,
+    PartialRefresh,
+    Sleep,
+}
+
+/// The part of an op in flight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Command,
+    Parameters,
+    /// Sending the frame buffer.
+    Frame,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Uninitialized,
+    Idle,
+    /// Holding the reset pin low.
+    Reset,
+    /// Waiting for the controller to come out of reset.
+    ResetWait,
+    /// Running an op of a sequence.
+    Op(Sequence, usize, Step),
+    /// Waiting for the busy pin after an op of a sequence, with the time
+    /// waited so far in ms.
+    Busy(Sequence, usize, u32),
+}
+
+/// A callback to be made from the deferred call.
+#[derive(Clone, Copy)]
+enum Deferred {
+    CommandComplete,
+    WriteComplete,
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+pub struct Eink<
+    'a,
+    A: Alarm<'a>,
+    S: SpiMasterDevice<'a>,
+    P: Pin,
+    const WIDTH: usize,
+    const HEIGHT: usize,
+> {
+    spi: &'a S,
+    alarm: &'a A,
+    dc: &'a P,
+    reset: &'a P,
+    busy: &'a P,
+    controller: &'static Controller,
+    state: Cell<State>,
+    refresh_mode: Cell<RefreshMode>,
+    /// Whether the controller has no previous image to make a partial
+    /// refresh against.
+    needs_full_refresh: Cell<bool>,
+    inverted: Cell<bool>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    framebuffer: TakeCell<'static, [u8]>,
+    frame: Cell<Frame>,
+    /// Bytes of the write frame written so far.
+    write_position: Cell<usize>,
+    write_buffer: TakeCell<'static, [u8]>,
+    deferred: OptionalCell<Deferred>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'a dyn ScreenClient>,
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin, const WIDTH: usize, const HEIGHT: usize>
+    Eink<'a, A, S, P, WIDTH, HEIGHT>
+{
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        dc: &'a P,
+        reset: &'a P,
+        busy: &'a P,
+        tx_buffer: &'static mut [u8],
+        framebuffer: &'static mut [u8],
+        controller: &'static Controller,
+    ) -> Self {
+        assert!(WIDTH % 8 == 0 && framebuffer.len() == framebuffer_len(WIDTH, HEIGHT));
+        dc.make_output();
+        reset.make_output();
+        reset.set();
+        busy.make_input();
+        Eink {
+            spi,
+            alarm,
+            dc,
+            reset,
+            busy,
+            controller,
+            state: Cell::new(State::Uninitialized),
+            refresh_mode: Cell::new(RefreshMode::Full),
+            needs_full_refresh: Cell::new(true),
+            inverted: Cell::new(false),
+            tx_buffer: TakeCell::new(tx_buffer),
+            framebuffer: TakeCell::new(framebuffer),
+            frame: Cell::new(Frame {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT,
+            }),
+            write_position: Cell::new(0),
+            write_buffer: TakeCell::empty(),
+            deferred: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Reset and initialise the controller. The client's `screen_is_ready`
+    /// is called once it is ready for writes.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Uninitialized | State::Idle => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        self.spi.configure(
+            ClockPolarity::IdleLow,
+            ClockPhase::SampleLeading,
+            self.controller.rate,
+        )?;
+        self.reset.clear();
+        self.set_delay(RESET_LOW_MS, State::Reset);
+        Ok(())
+    }
+
+    /// Set how the panel is refreshed after later writes.
+    pub fn set_refresh_mode(&self, mode: RefreshMode) {
+        self.refresh_mode.set(mode);
+    }
+
+    fn set_delay(&self, ms: u32, state: State) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Fail with `BUSY` or `OFF` unless the driver is idle.
+    fn check_idle(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle if self.deferred.is_none() => Ok(()),
+            State::Uninitialized => Err(ErrorCode::OFF),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    fn defer(&self, callback: Deferred) {
+        self.deferred.set(callback);
+        self.deferred_call.set();
+    }
+
+    fn sequence(&self, sequence: Sequence) -> &'static [Op] {
+        match sequence {
+            Sequence::Init => self.controller.init_sequence,
+            Sequence::FullRefresh => self.controller.full_refresh,
+            Sequence::PartialRefresh => self.controller.partial_refresh,
+            Sequence::Sleep => self.controller.sleep_sequence,
+        }
+    }
+
+    /// Run the ops of `sequence` from `index`.
+    fn run(&self, sequence: Sequence, index: usize) -> Result<(), ErrorCode> {
+        let command = match self.sequence(sequence).get(index) {
+            None => {
+                self.finish(sequence);
+                return Ok(());
+            }
+            // The busy pin is only checked after a poll interval, as the
+            // controller may not have raised it yet.
+            Some(Op::WaitBusy) => {
+                self.set_delay(BUSY_POLL_MS, State::Busy(sequence, index, 0));
+                return Ok(());
+            }
+            Some(Op::Command(command, _))
+            | Some(Op::Geometry(command, _))
+            | Some(Op::Frame(command)) => *command,
+        };
+        self.dc.clear();
+        self.tx_buffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                buffer[0] = command;
+                self.transfer(buffer, 1, State::Op(sequence, index, Step::Command))
+            })
+    }
+
+    /// Send the parameters of the op at `index` once its command has been
+    /// sent.
+    fn parameters(&self, sequence: Sequence, index: usize) -> Result<(), ErrorCode> {
+        let state = State::Op(sequence, index, Step::Parameters);
+        self.dc.set();
+        match self.sequence(sequence)[index] {
+            Op::Command(_, parameters) if parameters.is_empty() => self.run(sequence, index + 1),
+            Op::Command(_, parameters) => {
+                self.tx_buffer
+                    .take()
+                    .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                        buffer[..parameters.len()].copy_from_slice(parameters);
+                        self.transfer(buffer, parameters.len(), state)
+                    })
+            }
+            Op::Geometry(_, parameters) => {
+                self.tx_buffer
+                    .take()
+                    .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                        let len = parameters(WIDTH, HEIGHT, buffer);
+                        self.transfer(buffer, len, state)
+                    })
+            }
+            Op::Frame(_) => self
+                .framebuffer
+                .take()
+                .map_or(Err(ErrorCode::NOMEM), |framebuffer| {
+                    let len = framebuffer.len();
+                    self.transfer(framebuffer, len, State::Op(sequence, index, Step::Frame))
+                }),
+            Op::WaitBusy => Ok(()),
+        }
+    }
+
+    fn transfer(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+        state: State,
+    ) -> Result<(), ErrorCode> {
+        let previous = self.state.replace(state);
+        self.spi
+            .read_write_bytes(buffer, None, len)
+            .map_err(|(error, buffer, _)| {
+                self.return_buffer(state, buffer);
+                self.state.set(previous);
+                error
+            })
+    }
+
+    /// Put back the buffer of the transfer made in `state`.
+    fn return_buffer(&self, state: State, buffer: &'static mut [u8]) {
+        match state {
+            State::Op(_, _, Step::Frame) => self.framebuffer.replace(buffer),
+            _ => self.tx_buffer.replace(buffer),
+        };
+    }
+
+    fn finish(&self, sequence: Sequence) {
+        match sequence {
+            Sequence::Init => {
+                self.state.set(State::Idle);
+                self.needs_full_refresh.set(true);
+                self.client.map(|client| client.screen_is_ready());
+            }
+            Sequence::FullRefresh | Sequence::PartialRefresh => {
+                self.state.set(State::Idle);
+                self.needs_full_refresh.set(false);
+                self.write_buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, Ok(())));
+                });
+            }
+            Sequence::Sleep => {
+                self.state.set(State::Uninitialized);
+                self.client.map(|client| client.screen_is_ready());
+            }
+        }
+    }
+
+    /// Report `error` for the operation that was in progress in `state`.
+    fn fail(&self, state: State, error: ErrorCode) {
+        let sequence = match state {
+            State::Op(sequence, _, _) | State::Busy(sequence, _, _) => sequence,
+            _ => Sequence::Init,
+        };
+        match sequence {
+            Sequence::FullRefresh | Sequence::PartialRefresh => {
+                // The controller may hold part of the new image.
+                self.state.set(State::Idle);
+                self.needs_full_refresh.set(true);
+                self.write_buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, Err(error)));
+                });
+            }
+            // The controller is in an unknown state until it is
+            // initialised again.
+            Sequence::Init | Sequence::Sleep => self.state.set(State::Uninitialized),
+        }
+    }
+
+    /// Copy pixels into the write frame, after those already written.
+    fn copy_to_framebuffer(&self, pixels: &[u8]) {
+        let frame = self.frame.get();
+        let row_len = frame.width / 8;
+        let position = self.write_position.get();
+        let count = cmp::min(pixels.len(), row_len * frame.height - position);
+        let mask = if self.inverted.get() { 0xFF } else { 0x00 };
+        self.framebuffer.map(|framebuffer| {
+            for (offset, pixel) in pixels[..count].iter().enumerate() {
+                let row = frame.y + (position + offset) / row_len;
+                let column = frame.x / 8 + (position + offset) % row_len;
+                framebuffer[row * WIDTH / 8 + column] = pixel ^ mask;
+            }
+        });
+        self.write_position.set(position + count);
+    }
+
+    fn start_write(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        if len == 0 || len > buffer.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.copy_to_framebuffer(&buffer[..len]);
+        self.write_buffer.replace(buffer);
+        let frame = self.frame.get();
+        if self.write_position.get() < frame.width / 8 * frame.height {
+            self.defer(Deferred::WriteComplete);
+            return Ok(());
+        }
+        let sequence = match self.refresh_mode.get() {
+            RefreshMode::Partial if !self.needs_full_refresh.get() => Sequence::PartialRefresh,
+            _ => Sequence::FullRefresh,
+        };
+        self.run(sequence, 0)
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin, const WIDTH: usize, const HEIGHT: usize>
+    Screen<'a> for Eink<'a, A, S, P, WIDTH, HEIGHT>
+{
+    fn get_resolution(&self) -> (usize, usize) {
+        (WIDTH, HEIGHT)
+    }
+
+    fn get_pixel_format(&self) -> ScreenPixelFormat {
+        ScreenPixelFormat::Mono
+    }
+
+    fn get_rotation(&self) -> ScreenRotation {
+        ScreenRotation::Normal
+    }
+
+    fn set_write_frame(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        if width == 0
+            || height == 0
+            || x % 8 != 0
+            || width % 8 != 0
+            || x + width > WIDTH
+            || y + height > HEIGHT
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        self.frame.set(Frame {
+            x,
+            y,
+            width,
+            height,
+        });
+        self.write_position.set(0);
+        self.defer(Deferred::CommandComplete);
+        Ok(())
+    }
+
+    fn write(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        self.write_position.set(0);
+        self.start_write(buffer, len)
+    }
+
+    fn write_continue(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        self.start_write(buffer, len)
+    }
+
+    fn set_client(&self, client: Option<&'a dyn ScreenClient>) {
+        match client {
+            Some(client) => self.client.set(client),
+            None => self.client.clear(),
+        }
+    }
+
+    fn set_brightness(&self, _brightness: usize) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn set_power(&self, enabled: bool) -> Result<(), ErrorCode> {
+        match (self.state.get(), enabled) {
+            (State::Uninitialized, true) => self.init(),
+            (State::Uninitialized, false) => Err(ErrorCode::OFF),
+            (State::Idle, true) => {
+                self.check_idle()?;
+                self.client.map(|client| client.screen_is_ready());
+                Ok(())
+            }
+            (State::Idle, false) => {
+                self.check_idle()?;
+                self.run(Sequence::Sleep, 0)
+            }
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Inverts the frame buffer, which is shown by the next refresh.
+    fn set_invert(&self, enabled: bool) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        if enabled != self.inverted.replace(enabled) {
+            self.framebuffer.map(|framebuffer| {
+                for byte in framebuffer.iter_mut() {
+                    *byte = !*byte;
+                }
+            });
+        }
+        self.defer(Deferred::CommandComplete);
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin, const WIDTH: usize, const HEIGHT: usize>
+    AlarmClient for Eink<'a, A, S, P, WIDTH, HEIGHT>
+{
+    fn alarm(&self) {
+        let state = self.state.get();
+        let result = match state {
+            State::Reset => {
+                self.reset.set();
+                self.set_delay(RESET_WAIT_MS, State::ResetWait);
+                Ok(())
+            }
+            State::ResetWait => self.run(Sequence::Init, 0),
+            State::Busy(sequence, index, waited) => {
+                let waited = waited + BUSY_POLL_MS;
+                if self.busy.read() != self.controller.busy_level {
+                    self.run(sequence, index + 1)
+                } else if waited >= BUSY_TIMEOUT_MS {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    self.set_delay(BUSY_POLL_MS, State::Busy(sequence, index, waited));
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            self.fail(state, error);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin, const WIDTH: usize, const HEIGHT: usize>
+    SpiMasterClient for Eink<'a, A, S, P, WIDTH, HEIGHT>
+{
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        _read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let state = self.state.get();
+        self.return_buffer(state, write_buffer);
+        let result = status.and_then(|()| match state {
+            State::Op(sequence, index, Step::Command) => self.parameters(sequence, index),
+            State::Op(sequence, index, _) => self.run(sequence, index + 1),
+            _ => Ok(()),
+        });
+        if let Err(error) = result {
+            self.fail(state, error);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin, const WIDTH: usize, const HEIGHT: usize>
+    DeferredCallClient for Eink<'a, A, S, P, WIDTH, HEIGHT>
+{
+    fn handle_deferred_call(&self) {
+        match self.deferred.take() {
+            Some(Deferred::CommandComplete) => {
+                self.client.map(|client| client.command_complete(Ok(())));
+            }
+            Some(Deferred::WriteComplete) => {
+                self.write_buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, Ok(())));
+                });
+            }
+            None => {}
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin, MockSpi};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        ready: Cell<usize>,
+        commands: Cell<usize>,
+        writes: RefCell<Vec<Result<(), ErrorCode>>>,
+    }
+
+    impl ScreenClient for Client {
+        fn command_complete(&self, r: Result<(), ErrorCode>) {
+            assert_eq!(r, Ok(()));
+            self.commands.set(self.commands.get() + 1);
+        }
+        fn write_complete(&self, _buffer: &'static mut [u8], r: Result<(), ErrorCode>) {
+            self.writes.borrow_mut().push(r);
+        }
+        fn screen_is_ready(&self) {
+            self.ready.set(self.ready.get() + 1);
+        }
+    }
+
+    /// A small SSD1680 panel: two bytes by four rows.
+    type Device = Eink<'static, MockAlarm<'static>, MockSpi<'static>, MockPin<'static>, 16, 4>;
+
+    struct Test {
+        spi: &'static MockSpi<'static>,
+        alarm: &'static MockAlarm<'static>,
+        busy: &'static MockPin<'static>,
+        eink: &'static Device,
+        client: &'static Client,
+    }
+
+    impl Test {
+        /// A panel that has been initialised.
+        fn new() -> Test {
+            let dc: &'static MockPin = Box::leak(Box::default());
+            let spi: &'static MockSpi = Box::leak(Box::default());
+            spi.tag_with(dc);
+            let alarm: &'static MockAlarm = Box::leak(Box::default());
+            let busy: &'static MockPin = Box::leak(Box::default());
+            let client: &'static Client = Box::leak(Box::default());
+            let eink = Box::leak(Box::new(Eink::new(
+                spi,
+                alarm,
+                dc,
+                Box::leak(Box::default()),
+                busy,
+                Box::leak(Box::new([0; BUFFER_SIZE])),
+                Box::leak(Box::new([0; framebuffer_len(16, 4)])),
+                &SSD1680,
+            )));
+            spi.set_client(eink);
+            alarm.set_alarm_client(eink);
+            eink.set_client(Some(client));
+            let test = Test {
+                spi,
+                alarm,
+                busy,
+                eink,
+                client,
+            };
+            assert_eq!(test.eink.set_power(true), Ok(()));
+            test.run();
+            assert_eq!(test.client.ready.get(), 1);
+            test
+        }
+
+        /// Complete transfers, fire alarms and run deferred calls until the
+        /// driver is idle.
+        fn run(&self) {
+            loop {
+                if self.spi.busy() {
+                    self.spi.complete(&[]);
+                    continue;
+                }
+                if self.alarm.is_armed() {
+                    self.alarm.fire();
+                    continue;
+                }
+                if self.eink.deferred.is_some() {
+                    self.eink.handle_deferred_call();
+                    continue;
+                }
+                break;
+            }
+        }
+
+        fn take_transfers(&self) -> Vec<(bool, Vec<u8>)> {
+            self.spi.take_tagged_transfers()
+        }
+    }
+
+    #[test]
+    fn init_sends_geometry() {
+        let test = Test::new();
+        let transfers = test.take_transfers();
+        assert_eq!(transfers[0], (false, [ssd1680::SW_RESET].to_vec()));
+        // Gates and RAM ranges of a panel 16 pixels wide and 4 tall.
+        assert_eq!(
+            transfers[1..3],
+            [
+                (false, [ssd1680::DRIVER_OUTPUT].to_vec()),
+                (true, [0x03, 0x00, 0x00].to_vec()),
+            ]
+        );
+        assert!(transfers.contains(&(true, [0x00, 0x01].to_vec())));
+        assert!(transfers.contains(&(true, [0x00, 0x00, 0x03, 0x00].to_vec())));
+
+        let mut buffer = [0; 3];
+        assert_eq!(uc8151::resolution(104, 212, &mut buffer), 3);
+        assert_eq!(buffer, [104, 0x00, 212]);
+    }
+
+    #[test]
+    fn refresh_once_frame_is_written() {
+        let test = Test::new();
+        test.take_transfers();
+        test.eink.set_refresh_mode(RefreshMode::Partial);
+
+        assert_eq!(test.eink.set_write_frame(8, 1, 8, 2), Ok(()));
+        test.run();
+        assert_eq!(test.client.commands.get(), 1);
+        assert_eq!(test.eink.set_write_frame(4, 0, 8, 1), Err(ErrorCode::INVAL));
+
+        // Half the frame: nothing is sent.
+        assert_eq!(test.eink.write(Box::leak(Box::new([0xAA])), 1), Ok(()));
+        test.run();
+        assert_eq!(*test.client.writes.borrow(), [Ok(())]);
+        assert!(test.take_transfers().is_empty());
+
+        // The rest of it: the first refresh is a full one, even in partial
+        // mode.
+        assert_eq!(
+            test.eink.write_continue(Box::leak(Box::new([0x55])), 1),
+            Ok(())
+        );
+        test.run();
+        assert_eq!(*test.client.writes.borrow(), [Ok(()), Ok(())]);
+        let transfers = test.take_transfers();
+        let frame = (true, [0, 0, 0, 0xAA, 0, 0x55, 0, 0].to_vec());
+        assert_eq!(transfers.iter().filter(|t| **t == frame).count(), 2);
+        assert!(transfers.contains(&(true, [ssd1680::UPDATE_FULL].to_vec())));
+        assert!(!transfers.contains(&(true, ssd1680::PARTIAL_LUT.to_vec())));
+
+        // Later refreshes load the fast waveform.
+        assert_eq!(test.eink.write(Box::leak(Box::new([0xFF; 2])), 2), Ok(()));
+        test.run();
+        let transfers = test.take_transfers();
+        assert!(transfers.contains(&(true, ssd1680::PARTIAL_LUT.to_vec())));
+        assert!(transfers.contains(&(true, [0, 0, 0, 0xFF, 0, 0xFF, 0, 0].to_vec())));
+        assert!(!transfers.contains(&(true, [ssd1680::UPDATE_FULL].to_vec())));
+    }
+
+    #[test]
+    fn busy_timeout_fails_refresh() {
+        let test = Test::new();
+        test.take_transfers();
+        test.eink.set_refresh_mode(RefreshMode::Partial);
+
+        test.busy.set_level(true);
+        assert_eq!(test.eink.write(Box::leak(Box::new([0; 8])), 8), Ok(()));
+        test.run();
+        assert_eq!(*test.client.writes.borrow(), [Err(ErrorCode::FAIL)]);
+
+        // The driver can be used again, with a full refresh.
+        test.busy.set_level(false);
+        test.take_transfers();
+        assert_eq!(test.eink.write(Box::leak(Box::new([0; 8])), 8), Ok(()));
+        test.run();
+        assert_eq!(*test.client.writes.borrow(), [Err(ErrorCode::FAIL), Ok(())]);
+        assert!(test
+            .take_transfers()
+            .contains(&(true, [ssd1680::UPDATE_FULL].to_vec())));
+    }
+}
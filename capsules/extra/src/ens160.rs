@@ -0,0 +1,570 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the ScioSense ENS160 digital metal-oxide multi-gas sensor,
+//! which reports an air quality index (AQI), equivalent CO2 (eCO2) and
+//! total volatile organic compounds (TVOC).
+//!
+//! <https://www.sciosense.com/wp-content/uploads/2023/12/ENS160-Datasheet.pdf>
+//!
+//! The driver puts the sensor in standard operating mode, in which it
+//! produces a new result every second. Before each reading, the ambient
+//! temperature and humidity are written to TEMP_IN and RH_IN to compensate
+//! the result. They come from another sensor if the board gives one, or
+//! else from `specify_environment`, and are 25 °C and 50 %RH until then.
+//! The result is then read once the status register flags new data.
+//!
+//! For the first three minutes after power on the sensor warms up, and
+//! requests fail with `BUSY`. Results are reported during the first hour
+//! of operation of a new sensor, its initial start-up, even though they
+//! are less accurate. Requests fail with `FAIL` if the sensor reports an
+//! invalid output or an error.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ens160 = components::ens160::Ens160Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ens160::BASE_ADDR,
+//!     mux_alarm,
+//!     Some((hts221, hts221)),
+//! )
+//! .finalize(components::ens160_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! let air_quality = components::air_quality::AirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::air_quality::DRIVER_NUM,
+//!     ens160,
+//! )
+//! .finalize(components::air_quality_component_static!());
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{
+    AirQualityClient, AirQualityDriver, HumidityClient, HumidityDriver, TemperatureClient,
+    TemperatureDriver,
+};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The address with ADDR low, and with ADDR high.
+pub const BASE_ADDR: u8 = 0x52;
+pub const ALTERNATE_ADDR: u8 = 0x53;
+
+/// Size of the buffer for the sensor: the status and data registers.
+pub const BUFFER_SIZE: usize = 6;
+
+const REG_PART_ID: u8 = 0x00;
+const REG_OPMODE: u8 = 0x10;
+const REG_TEMP_IN: u8 = 0x13;
+const REG_DEVICE_STATUS: u8 = 0x20;
+
+const PART_ID: u16 = 0x0160;
+const OPMODE_STANDARD: u8 = 0x02;
+
+/// From DEVICE_STATUS to DATA_ECO2.
+const DATA_LEN: usize = 6;
+const STATER: u8 = 0x40;
+const VALIDITY_SHIFT: u8 = 2;
+const VALIDITY_MASK: u8 = 0x03;
+const VALIDITY_WARM_UP: u8 = 1;
+const VALIDITY_INVALID: u8 = 3;
+const NEWDAT: u8 = 0x02;
+
+/// Time between polls of the status, and the most polls for a result. A
+/// result is produced every second.
+const POLL_MS: u32 = 100;
+const MAX_POLLS: u32 = 15;
+
+/// In hundredths of °C, and of %RH.
+const DEFAULT_TEMPERATURE: i32 = 2500;
+const DEFAULT_HUMIDITY: u32 = 5000;
+
+/// A result of the sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// The air quality index of the German Federal Environmental Agency
+    /// (UBA), from 1 (excellent) to 5 (unhealthy).
+    pub aqi: u8,
+    /// In ppb.
+    pub tvoc: u16,
+    /// In ppm.
+    pub eco2: u16,
+}
+
+/// Decode the registers from DEVICE_STATUS to DATA_ECO2.
+fn decode(data: &[u8]) -> Result<Reading, ErrorCode> {
+    let status = data[0];
+    let validity = (status >> VALIDITY_SHIFT) & VALIDITY_MASK;
+    if status & STATER != 0 || validity == VALIDITY_INVALID {
+        return Err(ErrorCode::FAIL);
+    }
+    if validity == VALIDITY_WARM_UP {
+        return Err(ErrorCode::BUSY);
+    }
+    Ok(Reading {
+        aqi: data[1] & 0x07,
+        tvoc: u16::from_le_bytes([data[2], data[3]]),
+        eco2: u16::from_le_bytes([data[4], data[5]]),
+    })
+}
+
+/// TEMP_IN is in kelvin multiplied by 64.
+fn temp_in(temperature: i32) -> u16 {
+    ((temperature + 27315).max(0) as u32 * 64 / 100) as u16
+}
+
+/// RH_IN is in %RH multiplied by 512.
+fn rh_in(humidity: u32) -> u16 {
+    (humidity.min(10000) * 512 / 100) as u16
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Off,
+    Identify,
+    /// Writing the operating mode.
+    Starting,
+    Idle,
+    /// Writing the environment given by `specify_environment`.
+    Environment,
+    /// Reading the temperature of the compensation source.
+    Temperature,
+    /// Reading the humidity of the compensation source.
+    Humidity,
+    /// Writing the compensation before a reading.
+    Compensating,
+    /// Waiting to poll the status, which has been polled this many times.
+    Waiting(u32),
+    Reading(u32),
+}
+
+pub struct Ens160<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// The sensor that gives the temperature and humidity to compensate
+    /// for, if any.
+    temperature_source: OptionalCell<&'a dyn TemperatureDriver<'a>>,
+    humidity_source: OptionalCell<&'a dyn HumidityDriver<'a>>,
+    /// In hundredths of °C, and of %RH.
+    temperature: Cell<i32>,
+    humidity: Cell<u32>,
+    client: OptionalCell<&'a dyn AirQualityClient>,
+    co2_pending: Cell<bool>,
+    tvoc_pending: Cell<bool>,
+    aqi_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Ens160<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Ens160<'a, A, I> {
+        Ens160 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Off),
+            temperature_source: OptionalCell::empty(),
+            humidity_source: OptionalCell::empty(),
+            temperature: Cell::new(DEFAULT_TEMPERATURE),
+            humidity: Cell::new(DEFAULT_HUMIDITY),
+            client: OptionalCell::empty(),
+            co2_pending: Cell::new(false),
+            tvoc_pending: Cell::new(false),
+            aqi_pending: Cell::new(false),
+        }
+    }
+
+    /// Compensate with the readings of another sensor, which must call
+    /// this driver back as its temperature and humidity client.
+    pub fn set_compensation_source(
+        &self,
+        temperature: &'a dyn TemperatureDriver<'a>,
+        humidity: &'a dyn HumidityDriver<'a>,
+    ) {
+        self.temperature_source.set(temperature);
+        self.humidity_source.set(humidity);
+    }
+
+    /// Identify the sensor and put it in standard operating mode.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Off {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.transfer(State::Identify, &[REG_PART_ID], 2)
+    }
+
+    /// Write `data`, and then read `read_len` bytes if it is not zero.
+    fn transfer(&self, state: State, data: &[u8], read_len: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..data.len()].copy_from_slice(data);
+        self.state.set(state);
+        let result = if read_len == 0 {
+            self.i2c.write(buffer, data.len())
+        } else {
+            self.i2c.write_read(buffer, data.len(), read_len)
+        };
+        result.map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            e.into()
+        })
+    }
+
+    /// Write the temperature and humidity to TEMP_IN and RH_IN.
+    fn write_environment(&self, state: State) -> Result<(), ErrorCode> {
+        let [t_lo, t_hi] = temp_in(self.temperature.get()).to_le_bytes();
+        let [rh_lo, rh_hi] = rh_in(self.humidity.get()).to_le_bytes();
+        self.transfer(state, &[REG_TEMP_IN, t_lo, t_hi, rh_lo, rh_hi], 0)
+    }
+
+    /// Start a reading, with the compensation from the source if there is
+    /// one.
+    fn measure(&self) {
+        let result = match self.temperature_source.extract() {
+            Some(source) => {
+                self.state.set(State::Temperature);
+                source
+                    .read_temperature()
+                    .or_else(|_| self.read_humidity_source())
+            }
+            None => self.write_environment(State::Compensating),
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+
+    /// Read the humidity of the source, or else compensate without it.
+    fn read_humidity_source(&self) -> Result<(), ErrorCode> {
+        self.state.set(State::Humidity);
+        self.humidity_source
+            .extract()
+            .ok_or(ErrorCode::NODEVICE)
+            .and_then(|source| source.read_humidity())
+            .or_else(|_| self.write_environment(State::Compensating))
+    }
+
+    /// Answer the requests with a reading.
+    fn finish(&self, result: Result<Reading, ErrorCode>) {
+        self.state.set(State::Idle);
+        if self.co2_pending.take() {
+            self.client
+                .map(|client| client.co2_data_available(result.map(|r| r.eco2 as u32)));
+        }
+        if self.tvoc_pending.take() {
+            self.client
+                .map(|client| client.tvoc_data_available(result.map(|r| r.tvoc as u32)));
+        }
+        if self.aqi_pending.take() {
+            self.client
+                .map(|client| client.aqi_available(result.map(|r| r.aqi as u32)));
+        }
+    }
+
+    fn request(&self, pending: &Cell<bool>) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.state.get() == State::Idle {
+            self.measure();
+        }
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AirQualityDriver<'a> for Ens160<'a, A, I> {
+    fn set_client(&self, client: &'a dyn AirQualityClient) {
+        self.client.set(client);
+    }
+
+    /// The temperature is in °C and the humidity in %RH. They are only
+    /// used if there is no compensation source.
+    fn specify_environment(
+        &self,
+        temp: Option<i32>,
+        humidity: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if let Some(temp) = temp {
+            self.temperature.set(temp * 100);
+        }
+        if let Some(humidity) = humidity {
+            self.humidity.set(humidity * 100);
+        }
+        self.write_environment(State::Environment)
+    }
+
+    fn read_co2(&self) -> Result<(), ErrorCode> {
+        self.request(&self.co2_pending)
+    }
+
+    fn read_tvoc(&self) -> Result<(), ErrorCode> {
+        self.request(&self.tvoc_pending)
+    }
+
+    fn read_aqi(&self) -> Result<(), ErrorCode> {
+        self.request(&self.aqi_pending)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> TemperatureClient for Ens160<'a, A, I> {
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        if self.state.get() != State::Temperature {
+            return;
+        }
+        if let Ok(temperature) = value {
+            self.temperature.set(temperature);
+        }
+        if let Err(e) = self.read_humidity_source() {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> HumidityClient for Ens160<'a, A, I> {
+    fn callback(&self, value: usize) {
+        if self.state.get() != State::Humidity {
+            return;
+        }
+        self.humidity.set(value as u32);
+        if let Err(e) = self.write_environment(State::Compensating) {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AlarmClient for Ens160<'a, A, I> {
+    fn alarm(&self) {
+        if let State::Waiting(polls) = self.state.get() {
+            if let Err(e) = self.transfer(State::Reading(polls), &[REG_DEVICE_STATUS], DATA_LEN) {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Ens160<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let part_id = u16::from_le_bytes([buffer[0], buffer[1]]);
+        let new_data = buffer[0] & NEWDAT != 0;
+        let reading = decode(&buffer[..DATA_LEN]);
+        self.buffer.replace(buffer);
+
+        let result = status.map_err(|e| e.into()).and_then(|()| match state {
+            State::Identify if part_id == PART_ID => {
+                self.transfer(State::Starting, &[REG_OPMODE, OPMODE_STANDARD], 0)
+            }
+            State::Identify => Err(ErrorCode::NODEVICE),
+            State::Starting => {
+                self.state.set(State::Idle);
+                if self.co2_pending.get() || self.tvoc_pending.get() || self.aqi_pending.get() {
+                    self.measure();
+                }
+                Ok(())
+            }
+            State::Environment => {
+                self.state.set(State::Idle);
+                self.client
+                    .map(|client| client.environment_specified(Ok(())));
+                Ok(())
+            }
+            State::Compensating => self.transfer(State::Reading(0), &[REG_DEVICE_STATUS], DATA_LEN),
+            State::Reading(polls) => match reading {
+                Ok(reading) if new_data => {
+                    self.finish(Ok(reading));
+                    Ok(())
+                }
+                Ok(_) if polls < MAX_POLLS => {
+                    self.state.set(State::Waiting(polls + 1));
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(POLL_MS));
+                    Ok(())
+                }
+                Ok(_) => Err(ErrorCode::FAIL),
+                Err(e) => Err(e),
+            },
+            _ => Ok(()),
+        });
+
+        if let Err(e) = result {
+            match state {
+                State::Identify | State::Starting => {
+                    kernel::debug!("ens160: failed to start: {:?}", e);
+                    self.state.set(State::Off);
+                }
+                State::Environment => {
+                    self.state.set(State::Idle);
+                    self.client
+                        .map(|client| client.environment_specified(Err(e)));
+                }
+                _ => self.finish(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use std::boxed::Box;
+
+    /// A temperature and humidity sensor that answers at once.
+    struct MockSource {
+        client: OptionalCell<&'static Ens160<'static, MockAlarm<'static>, MockI2c>>,
+    }
+
+    impl<'a> TemperatureDriver<'a> for MockSource {
+        fn set_client(&self, _client: &'a dyn TemperatureClient) {}
+        fn read_temperature(&self) -> Result<(), ErrorCode> {
+            self.client
+                .map(|client| TemperatureClient::callback(*client, Ok(3000)));
+            Ok(())
+        }
+    }
+
+    impl<'a> HumidityDriver<'a> for MockSource {
+        fn set_client(&self, _client: &'a dyn HumidityClient) {}
+        fn read_humidity(&self) -> Result<(), ErrorCode> {
+            self.client
+                .map(|client| HumidityClient::callback(*client, 4000));
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        environment: Cell<Option<Result<(), ErrorCode>>>,
+        co2: Cell<Option<Result<u32, ErrorCode>>>,
+        tvoc: Cell<Option<Result<u32, ErrorCode>>>,
+        aqi: Cell<Option<Result<u32, ErrorCode>>>,
+    }
+
+    impl AirQualityClient for MockClient {
+        fn environment_specified(&self, result: Result<(), ErrorCode>) {
+            self.environment.set(Some(result));
+        }
+        fn co2_data_available(&self, value: Result<u32, ErrorCode>) {
+            self.co2.set(Some(value));
+        }
+        fn tvoc_data_available(&self, value: Result<u32, ErrorCode>) {
+            self.tvoc.set(Some(value));
+        }
+        fn aqi_available(&self, value: Result<u32, ErrorCode>) {
+            self.aqi.set(Some(value));
+        }
+    }
+
+    type TestEns160 = Ens160<'static, MockAlarm<'static>, MockI2c>;
+
+    #[test]
+    fn decodes_data_registers() {
+        // Normal operation with new data: AQI 3, 450 ppb and 900 ppm.
+        assert_eq!(
+            decode(&[0x82, 0x03, 0xC2, 0x01, 0x84, 0x03]),
+            Ok(Reading {
+                aqi: 3,
+                tvoc: 450,
+                eco2: 900,
+            })
+        );
+        // The initial start-up still reports data.
+        assert_eq!(
+            decode(&[0x8A, 0x01, 0x00, 0x00, 0x90, 0x01]).map(|r| r.eco2),
+            Ok(400)
+        );
+        assert_eq!(
+            decode(&[0x86, 0x01, 0x00, 0x00, 0x90, 0x01]),
+            Err(ErrorCode::BUSY)
+        );
+        assert_eq!(
+            decode(&[0x8E, 0x01, 0x00, 0x00, 0x90, 0x01]),
+            Err(ErrorCode::FAIL)
+        );
+        assert_eq!(
+            decode(&[0xC2, 0x01, 0x00, 0x00, 0x90, 0x01]),
+            Err(ErrorCode::FAIL)
+        );
+        assert_eq!((temp_in(2500), rh_in(5000)), (19081, 25600));
+    }
+
+    #[test]
+    fn reads_with_compensation() {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let alarm = Box::leak(Box::new(MockAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let source = Box::leak(Box::new(MockSource {
+            client: OptionalCell::empty(),
+        }));
+        let ens160: &TestEns160 = Box::leak(Box::new(Ens160::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(ens160);
+        ens160.set_client(client);
+        assert_eq!(ens160.read_co2(), Err(ErrorCode::OFF));
+
+        assert_eq!(ens160.start(), Ok(()));
+        i2c.complete(ens160, &[0x60, 0x01]);
+        i2c.complete(ens160, &[]);
+        assert_eq!(ens160.specify_environment(Some(20), Some(60)), Ok(()));
+        i2c.complete(ens160, &[]);
+        assert_eq!(client.environment.get(), Some(Ok(())));
+        assert_eq!(
+            i2c.take_writes(),
+            [
+                [REG_PART_ID].to_vec(),
+                [REG_OPMODE, OPMODE_STANDARD].to_vec(),
+                // 293.15 K and 60 %RH.
+                [REG_TEMP_IN, 0x49, 0x49, 0x00, 0x78].to_vec(),
+            ]
+        );
+
+        // While warming up, the requests are busy.
+        assert_eq!(ens160.read_co2(), Ok(()));
+        assert_eq!(ens160.read_aqi(), Ok(()));
+        assert_eq!(ens160.read_aqi(), Err(ErrorCode::BUSY));
+        i2c.complete(ens160, &[]);
+        i2c.complete(ens160, &[0x86, 0x01, 0, 0, 0x90, 0x01]);
+        assert_eq!(client.co2.get(), Some(Err(ErrorCode::BUSY)));
+        assert_eq!(client.aqi.get(), Some(Err(ErrorCode::BUSY)));
+
+        // The compensation comes from the source, and the status is polled
+        // until there is new data.
+        ens160.set_compensation_source(source, source);
+        source.client.set(ens160);
+        i2c.take_writes();
+        assert_eq!(ens160.read_tvoc(), Ok(()));
+        i2c.complete(ens160, &[]);
+        i2c.complete(ens160, &[0x80, 0x01, 0, 0, 0x90, 0x01]);
+        assert_eq!(alarm.dt(), Some(POLL_MS));
+        assert_eq!(client.tvoc.get(), None);
+        alarm.fire();
+        i2c.complete(ens160, &[0x82, 0x02, 0xC2, 0x01, 0x84, 0x03]);
+        assert_eq!(client.tvoc.get(), Some(Ok(450)));
+        assert_eq!(
+            i2c.take_writes(),
+            [
+                // 303.15 K and 40 %RH.
+                [REG_TEMP_IN, 0xC9, 0x4B, 0x00, 0x50].to_vec(),
+                [REG_DEVICE_STATUS].to_vec(),
+                [REG_DEVICE_STATUS].to_vec(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,590 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Compute a digest over a range of flash without reading it into RAM first.
+//!
+//! This reads the range a page at a time and feeds each page to the digest
+//! engine, completing with the digest of the whole range. It needs one flash
+//! page and one page-sized buffer of RAM, however large the range is.
+//!
+//! The flash HIL reads into its own page type, which cannot be lent to the
+//! digest engine, so each page is copied into the data buffer before it is
+//! hashed. This frees the page for the next flash read, which then runs while
+//! the previous page is being hashed. Chips with no DMA path from flash to
+//! their digest engine, such as earlgrey, get the same overlap this way.
+//!
+//! ```plain
+//!  hil::flash::Flash      hil::digest::Digest
+//!          │                       ▲
+//!          ▼                       │
+//!     ┌─────────┐   copy    ┌─────────────┐
+//!     │  page   │ ────────► │ data buffer │
+//!     └─────────┘           └─────────────┘
+//! ```
+//!
+//! The digest is computed with whatever mode the digest engine is in, so the
+//! caller must select the mode (for example with `set_mode_sha256()`) before
+//! starting. If a flash read fails part way through the range, the partial
+//! digest is cleared and the client gets the error.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let flash_user = components::flash::FlashUserComponent::new(mux_flash).finalize(
+//!     components::flash_user_component_static!(lowrisc::flash_ctrl::FlashCtrl),
+//! );
+//! let digest = components::digest::DigestComponent::new(&mux_digest).finalize(
+//!     components::digest_component_static!(lowrisc::hmac::Hmac, 32),
+//! );
+//!
+//! let flash_digest = components::flash_digest::FlashDigestComponent::new(flash_user, digest)
+//!     .finalize(components::flash_digest_component_static!(
+//!         capsules_core::virtualizers::virtual_flash::FlashUser<
+//!             'static,
+//!             lowrisc::flash_ctrl::FlashCtrl<'static>,
+//!         >,
+//!         capsules_core::virtualizers::virtual_digest::VirtualMuxDigest<
+//!             'static,
+//!             lowrisc::hmac::Hmac<'static>,
+//!             32,
+//!         >,
+//!         32,
+//!         lowrisc::flash_ctrl::PAGE_SIZE,
+//!     ));
+//!
+//! hil::digest::Sha256::set_mode_sha256(digest).unwrap();
+//! flash_digest.set_client(verifier);
+//! flash_digest.digest_range(SLOT_OFFSET, SLOT_LENGTH, digest_buffer);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil;
+use kernel::hil::digest;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::ErrorCode;
+
+pub struct FlashDigest<'a, F: hil::flash::Flash + 'static, D: digest::Digest<'a, L>, const L: usize>
+{
+    flash: &'a F,
+    digest: &'a D,
+    client: OptionalCell<&'a dyn digest::ClientHash<L>>,
+    page_size: usize,
+    /// Buffer for flash reads, if no read is in progress.
+    page: TakeCell<'static, F::Page>,
+    /// Buffer the digest engine hashes from, if it is not hashing.
+    data: TakeCell<'static, [u8]>,
+    /// Where the digest goes once the whole range has been hashed.
+    output: TakeCell<'static, [u8; L]>,
+    /// Range of addresses being hashed.
+    start: Cell<usize>,
+    end: Cell<usize>,
+    /// Next page to read.
+    next_page: Cell<usize>,
+    /// Bytes of `page` that still have to be hashed.
+    pending_start: Cell<usize>,
+    pending_end: Cell<usize>,
+    busy: Cell<bool>,
+    reading: Cell<bool>,
+    hashing: Cell<bool>,
+    /// Why the operation failed. It is reported once nothing is in flight.
+    error: OptionalCell<ErrorCode>,
+}
+
+impl<'a, F: hil::flash::Flash, D: digest::Digest<'a, L>, const L: usize> FlashDigest<'a, F, D, L> {
+    /// `data` should be at least as long as a flash page. A shorter buffer
+    /// works, but each page is then hashed in several pieces and only the
+    /// last one overlaps with the next read.
+    pub fn new(
+        flash: &'a F,
+        digest: &'a D,
+        page: &'static mut F::Page,
+        data: &'static mut [u8],
+    ) -> FlashDigest<'a, F, D, L> {
+        FlashDigest {
+            flash,
+            digest,
+            client: OptionalCell::empty(),
+            page_size: page.as_mut().len(),
+            page: TakeCell::new(page),
+            data: TakeCell::new(data),
+            output: TakeCell::empty(),
+            start: Cell::new(0),
+            end: Cell::new(0),
+            next_page: Cell::new(0),
+            pending_start: Cell::new(0),
+            pending_end: Cell::new(0),
+            busy: Cell::new(false),
+            reading: Cell::new(false),
+            hashing: Cell::new(false),
+            error: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn digest::ClientHash<L>) {
+        self.client.set(client);
+    }
+
+    /// Hash `length` bytes of flash starting `address` bytes from the start
+    /// of flash, storing the result in `digest`. The client's `hash_done` is
+    /// called once the digest is ready or the operation failed.
+    pub fn digest_range(
+        &self,
+        address: usize,
+        length: usize,
+        digest: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, digest));
+        }
+        let end = match address.checked_add(length) {
+            Some(end) => end,
+            None => return Err((ErrorCode::INVAL, digest)),
+        };
+        if self.page.is_none() || self.data.is_none() {
+            return Err((ErrorCode::RESERVE, digest));
+        }
+
+        self.busy.set(true);
+        self.output.replace(digest);
+        self.start.set(address);
+        self.end.set(end);
+        self.next_page.set(address / self.page_size);
+        self.pending_start.set(0);
+        self.pending_end.set(0);
+        self.error.clear();
+        self.advance();
+        Ok(())
+    }
+
+    /// Start whatever can run next: hashing a read page, reading the next
+    /// page, or, once the whole range has been hashed, computing the digest.
+    fn advance(&self) {
+        let pending = self.pending_start.get() < self.pending_end.get();
+        let more_to_read = self.next_page.get() * self.page_size < self.end.get();
+
+        if self.error.is_none() && !self.hashing.get() && pending {
+            self.hash_pending();
+        }
+        let pending = self.pending_start.get() < self.pending_end.get();
+        if self.error.is_none() && !self.reading.get() && !pending && more_to_read {
+            self.read_next_page();
+        }
+
+        if self.reading.get() || self.hashing.get() {
+            return;
+        }
+        if let Some(error) = self.error.take() {
+            self.digest.clear_data();
+            self.finish(Err(error));
+        } else if !pending && !more_to_read {
+            self.output.take().map(|output| {
+                if let Err((error, output)) = self.digest.run(output) {
+                    self.output.replace(output);
+                    self.digest.clear_data();
+                    self.finish(Err(error));
+                }
+            });
+        }
+    }
+
+    /// Copy as much of the read page as fits into the data buffer and start
+    /// hashing it.
+    fn hash_pending(&self) {
+        let (page, data) = match (self.page.take(), self.data.take()) {
+            (Some(page), Some(data)) => (page, data),
+            (page, data) => {
+                page.map(|page| self.page.replace(page));
+                data.map(|data| self.data.replace(data));
+                return;
+            }
+        };
+        let start = self.pending_start.get();
+        let length = cmp::min(data.len(), self.pending_end.get() - start);
+        data[..length].copy_from_slice(&page.as_mut()[start..start + length]);
+        self.pending_start.set(start + length);
+        self.page.replace(page);
+
+        let mut buffer = LeasableMutableBuffer::new(data);
+        buffer.slice(..length);
+        match self.digest.add_mut_data(buffer) {
+            Ok(()) => self.hashing.set(true),
+            Err((error, buffer)) => {
+                self.data.replace(buffer.take());
+                self.error.set(error);
+            }
+        }
+    }
+
+    fn read_next_page(&self) {
+        self.page.take().map(
+            |page| match self.flash.read_page(self.next_page.get(), page) {
+                Ok(()) => self.reading.set(true),
+                Err((error, page)) => {
+                    self.page.replace(page);
+                    self.error.set(error);
+                }
+            },
+        );
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.busy.set(false);
+        self.output.take().map(|output| {
+            self.client.map(|client| client.hash_done(result, output));
+        });
+    }
+}
+
+impl<'a, F: hil::flash::Flash, D: digest::Digest<'a, L>, const L: usize> hil::flash::Client<F>
+    for FlashDigest<'a, F, D, L>
+{
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(page);
+        self.reading.set(false);
+
+        if error != hil::flash::Error::CommandComplete {
+            self.error.set(ErrorCode::FAIL);
+        } else {
+            // Only the first page can start part way through, and only the
+            // last can end part way through.
+            let page_address = self.next_page.get() * self.page_size;
+            self.pending_start
+                .set(self.start.get().saturating_sub(page_address));
+            self.pending_end
+                .set(cmp::min(self.end.get() - page_address, self.page_size));
+            self.next_page.set(self.next_page.get() + 1);
+        }
+        self.advance();
+    }
+
+    fn write_complete(&self, _write_buffer: &'static mut F::Page, _error: hil::flash::Error) {}
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<'a, F: hil::flash::Flash, D: digest::Digest<'a, L>, const L: usize> digest::ClientData<L>
+    for FlashDigest<'a, F, D, L>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: LeasableBuffer<'static, u8>) {}
+
+    fn add_mut_data_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        data: LeasableMutableBuffer<'static, u8>,
+    ) {
+        self.data.replace(data.take());
+        self.hashing.set(false);
+        if let Err(error) = result {
+            self.error.set(error);
+        }
+        self.advance();
+    }
+}
+
+impl<'a, F: hil::flash::Flash, D: digest::Digest<'a, L>, const L: usize> digest::ClientHash<L>
+    for FlashDigest<'a, F, D, L>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; L]) {
+        self.busy.set(false);
+        self.client.map(|client| client.hash_done(result, digest));
+    }
+}
+
+impl<'a, F: hil::flash::Flash, D: digest::Digest<'a, L>, const L: usize> digest::ClientVerify<L>
+    for FlashDigest<'a, F, D, L>
+{
+    fn verification_done(&self, _result: Result<bool, ErrorCode>, _compare: &'static mut [u8; L]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::hil::flash::{Flash, HasClient};
+    use std::boxed::Box;
+
+    const PAGE_SIZE: usize = 16;
+    const PAGES: usize = 5;
+
+    const MESSAGE: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    /// Where the message is stored, so that it starts and ends part way
+    /// through a page.
+    const MESSAGE_ADDRESS: usize = 5;
+
+    struct Page([u8; PAGE_SIZE]);
+
+    impl Default for Page {
+        fn default() -> Self {
+            Page([0; PAGE_SIZE])
+        }
+    }
+
+    impl AsMut<[u8]> for Page {
+        fn as_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+    }
+
+    /// Flash whose reads complete when `complete_read` is called.
+    struct MockFlash {
+        contents: [u8; PAGE_SIZE * PAGES],
+        failing_page: Option<usize>,
+        client: OptionalCell<&'static dyn hil::flash::Client<MockFlash>>,
+        read: TakeCell<'static, Page>,
+        read_page: Cell<usize>,
+        reads: Cell<usize>,
+    }
+
+    impl MockFlash {
+        fn new(failing_page: Option<usize>) -> MockFlash {
+            let mut contents = [0xFF; PAGE_SIZE * PAGES];
+            contents[MESSAGE_ADDRESS..MESSAGE_ADDRESS + MESSAGE.len()].copy_from_slice(MESSAGE);
+            MockFlash {
+                contents,
+                failing_page,
+                client: OptionalCell::empty(),
+                read: TakeCell::empty(),
+                read_page: Cell::new(0),
+                reads: Cell::new(0),
+            }
+        }
+
+        /// Completes the read in progress, if there is one.
+        fn complete_read(&self) -> bool {
+            self.read
+                .take()
+                .map(|page| {
+                    let error = if self.failing_page == Some(self.read_page.get()) {
+                        hil::flash::Error::FlashError
+                    } else {
+                        hil::flash::Error::CommandComplete
+                    };
+                    self.client.map(|client| client.read_complete(page, error));
+                })
+                .is_some()
+        }
+    }
+
+    impl Flash for MockFlash {
+        type Page = Page;
+
+        fn read_page(
+            &self,
+            page_number: usize,
+            buf: &'static mut Page,
+        ) -> Result<(), (ErrorCode, &'static mut Page)> {
+            if self.read.is_some() {
+                return Err((ErrorCode::BUSY, buf));
+            }
+            let start = page_number * PAGE_SIZE;
+            buf.0
+                .copy_from_slice(&self.contents[start..start + PAGE_SIZE]);
+            self.read.replace(buf);
+            self.read_page.set(page_number);
+            self.reads.set(self.reads.get() + 1);
+            Ok(())
+        }
+
+        fn write_page(
+            &self,
+            _page_number: usize,
+            buf: &'static mut Page,
+        ) -> Result<(), (ErrorCode, &'static mut Page)> {
+            Err((ErrorCode::NOSUPPORT, buf))
+        }
+
+        fn erase_page(&self, _page_number: usize) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+
+    impl<C: hil::flash::Client<MockFlash> + 'static> HasClient<'static, C> for MockFlash {
+        fn set_client(&'static self, client: &'static C) {
+            self.client.set(client);
+        }
+    }
+
+    /// FNV-1a, which stands in for the digest engine's hash function.
+    fn fnv1a(mut hash: u32, data: &[u8]) -> u32 {
+        for byte in data {
+            hash = (hash ^ *byte as u32).wrapping_mul(0x0100_0193);
+        }
+        hash
+    }
+
+    const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+
+    /// Digest engine whose operations complete when `complete` is called.
+    struct MockDigest {
+        client: OptionalCell<&'static dyn digest::Client<32>>,
+        hash: Cell<u32>,
+        data: OptionalCell<LeasableMutableBuffer<'static, u8>>,
+        output: TakeCell<'static, [u8; 32]>,
+    }
+
+    impl MockDigest {
+        fn new() -> MockDigest {
+            MockDigest {
+                client: OptionalCell::empty(),
+                hash: Cell::new(FNV_OFFSET_BASIS),
+                data: OptionalCell::empty(),
+                output: TakeCell::empty(),
+            }
+        }
+
+        fn busy(&self) -> bool {
+            self.data.is_some() || self.output.is_some()
+        }
+
+        /// Completes the operation in progress, if there is one.
+        fn complete(&self) -> bool {
+            if let Some(data) = self.data.take() {
+                self.hash.set(fnv1a(self.hash.get(), &data[..]));
+                self.client
+                    .map(|client| client.add_mut_data_done(Ok(()), data));
+                true
+            } else if let Some(output) = self.output.take() {
+                output.fill(0);
+                output[..4].copy_from_slice(&self.hash.get().to_be_bytes());
+                self.hash.set(FNV_OFFSET_BASIS);
+                self.client.map(|client| client.hash_done(Ok(()), output));
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    impl digest::DigestData<'static, 32> for MockDigest {
+        fn set_data_client(&'static self, _client: &'static dyn digest::ClientData<32>) {}
+
+        fn add_data(
+            &self,
+            data: LeasableBuffer<'static, u8>,
+        ) -> Result<(), (ErrorCode, LeasableBuffer<'static, u8>)> {
+            Err((ErrorCode::NOSUPPORT, data))
+        }
+
+        fn add_mut_data(
+            &self,
+            data: LeasableMutableBuffer<'static, u8>,
+        ) -> Result<(), (ErrorCode, LeasableMutableBuffer<'static, u8>)> {
+            if self.busy() {
+                return Err((ErrorCode::BUSY, data));
+            }
+            self.data.set(data);
+            Ok(())
+        }
+
+        fn clear_data(&self) {
+            self.hash.set(FNV_OFFSET_BASIS);
+        }
+    }
+
+    impl digest::DigestHash<'static, 32> for MockDigest {
+        fn set_hash_client(&'static self, _client: &'static dyn digest::ClientHash<32>) {}
+
+        fn run(
+            &'static self,
+            digest: &'static mut [u8; 32],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+            if self.busy() {
+                return Err((ErrorCode::BUSY, digest));
+            }
+            self.output.replace(digest);
+            Ok(())
+        }
+    }
+
+    impl digest::DigestVerify<'static, 32> for MockDigest {
+        fn set_verify_client(&'static self, _client: &'static dyn digest::ClientVerify<32>) {}
+
+        fn verify(
+            &'static self,
+            compare: &'static mut [u8; 32],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+            Err((ErrorCode::NOSUPPORT, compare))
+        }
+    }
+
+    impl digest::Digest<'static, 32> for MockDigest {
+        fn set_client(&'static self, client: &'static dyn digest::Client<32>) {
+            self.client.set(client);
+        }
+    }
+
+    struct TestClient {
+        result: Cell<Option<Result<(), ErrorCode>>>,
+        digest: TakeCell<'static, [u8; 32]>,
+    }
+
+    impl digest::ClientHash<32> for TestClient {
+        fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+            self.result.set(Some(result));
+            self.digest.replace(digest);
+        }
+    }
+
+    /// Hashes `length` bytes from `address`, running the flash and the
+    /// digest engine until neither has anything in flight.
+    fn digest_flash(
+        flash: &'static MockFlash,
+        address: usize,
+        length: usize,
+    ) -> &'static TestClient {
+        let engine: &'static MockDigest = Box::leak(Box::new(MockDigest::new()));
+        let flash_digest = Box::leak(Box::new(FlashDigest::new(
+            flash,
+            engine,
+            Box::leak(Box::new(Page::default())),
+            Box::leak(Box::new([0; PAGE_SIZE])),
+        )));
+        let client = Box::leak(Box::new(TestClient {
+            result: Cell::new(None),
+            digest: TakeCell::empty(),
+        }));
+        flash.set_client(flash_digest);
+        digest::Digest::set_client(engine, flash_digest);
+        flash_digest.set_client(client);
+
+        assert!(flash_digest
+            .digest_range(address, length, Box::leak(Box::new([0; 32])))
+            .is_ok());
+        loop {
+            let read = flash.complete_read();
+            let hashed = engine.complete();
+            if !read && !hashed {
+                break;
+            }
+        }
+        client
+    }
+
+    #[test]
+    fn digest_matches_reference() {
+        let flash = Box::leak(Box::new(MockFlash::new(None)));
+        let client = digest_flash(flash, MESSAGE_ADDRESS, MESSAGE.len());
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        let mut reference = [0; 32];
+        reference[..4].copy_from_slice(&fnv1a(FNV_OFFSET_BASIS, MESSAGE).to_be_bytes());
+        assert_eq!(client.digest.take().map(|d| *d), Some(reference));
+        // Only the pages holding the message are read.
+        assert_eq!(flash.reads.get(), 4);
+    }
+
+    #[test]
+    fn read_error_aborts() {
+        let flash = Box::leak(Box::new(MockFlash::new(Some(2))));
+        let client = digest_flash(flash, MESSAGE_ADDRESS, MESSAGE.len());
+
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+        assert!(client.digest.is_some());
+        assert_eq!(flash.reads.get(), 3);
+    }
+}
@@ -0,0 +1,404 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the FocalTech FT5x06 capacitive touch panel controller, over
+//! I2C.
+//!
+//! <https://www.buydisplay.com/download/ic/FT5206.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The controller tracks up to five touches and pulls its INT line low when
+//! the touches change. The driver then reads the gesture ID, the number of
+//! touches and every touch point in one transfer, and hands the touches to
+//! the [touch::MultiTouchClient] together, the first touch to the
+//! [touch::TouchClient], and any gesture to the [touch::GestureClient].
+//!
+//! Each touch point has an event flag, a 12-bit position, the ID the
+//! controller tracks the touch with, a weight and an area. The position is
+//! scaled from the resolution the controller reports in, which is the
+//! screen resolution unless set with [Ft5x06::set_touch_resolution], to the
+//! screen resolution. The weight and area are scaled to the 0 to 65535
+//! range of the pressure and size of a [TouchEvent].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ft5x06 = components::ft5x06::Ft5x06Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ft5x06::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[FT5X06_INT],
+//!     (800, 480),
+//! )
+//! .finalize(components::ft5x06_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::touch::{self, GestureEvent, TouchEvent, TouchStatus};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the FT5x06.
+pub const BASE_ADDR: u8 = 0x38;
+
+/// The most touches the controller tracks.
+pub const MAX_TOUCHES: usize = 5;
+
+/// Bytes of each touch point.
+const POINT_SIZE: usize = 6;
+
+/// Size of the buffer the driver needs, for the gesture ID, the number of
+/// touches and all touch points.
+pub const BUFFER_SIZE: usize = 2 + MAX_TOUCHES * POINT_SIZE;
+
+const REG_GEST_ID: u8 = 0x01;
+
+const EVENT_PRESS_DOWN: u8 = 0b00;
+const EVENT_LIFT_UP: u8 = 0b01;
+const EVENT_CONTACT: u8 = 0b10;
+
+pub static NO_TOUCH: TouchEvent = TouchEvent {
+    id: 0,
+    x: 0,
+    y: 0,
+    status: TouchStatus::Released,
+    size: None,
+    pressure: None,
+};
+
+fn gesture(gesture_id: u8) -> Option<GestureEvent> {
+    match gesture_id {
+        0x10 => Some(GestureEvent::SwipeUp),
+        0x14 => Some(GestureEvent::SwipeRight),
+        0x18 => Some(GestureEvent::SwipeDown),
+        0x1C => Some(GestureEvent::SwipeLeft),
+        0x48 => Some(GestureEvent::ZoomIn),
+        0x49 => Some(GestureEvent::ZoomOut),
+        _ => None,
+    }
+}
+
+/// Scale a position from `from` steps to `to` steps.
+fn scale(position: u16, from: u16, to: u16) -> u16 {
+    if from == 0 || to == 0 {
+        return position;
+    }
+    let scaled = position as u32 * to as u32 / from as u32;
+    scaled.min(to as u32 - 1) as u16
+}
+
+/// Decode a touch point, or `None` if its slot has no event.
+fn decode_point(
+    point: &[u8],
+    touch_resolution: (u16, u16),
+    screen_resolution: (u16, u16),
+) -> Option<TouchEvent> {
+    let status = match point[0] >> 6 {
+        EVENT_PRESS_DOWN => TouchStatus::Pressed,
+        EVENT_LIFT_UP => TouchStatus::Released,
+        EVENT_CONTACT => TouchStatus::Moved,
+        _ => return None,
+    };
+    let x = u16::from_be_bytes([point[0] & 0x0F, point[1]]);
+    let y = u16::from_be_bytes([point[2] & 0x0F, point[3]]);
+    Some(TouchEvent {
+        status,
+        x: scale(x, touch_resolution.0, screen_resolution.0),
+        y: scale(y, touch_resolution.1, screen_resolution.1),
+        id: (point[2] >> 4) as usize,
+        // The weight is 8 bits and the area 4 bits.
+        pressure: Some(point[4] as u16 * 0x0101),
+        size: Some((point[5] >> 4) as u16 * 0x1111),
+    })
+}
+
+pub struct Ft5x06<'a, I: I2CDevice> {
+    i2c: &'a I,
+    interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
+    touch_client: OptionalCell<&'a dyn touch::TouchClient>,
+    gesture_client: OptionalCell<&'a dyn touch::GestureClient>,
+    multi_touch_client: OptionalCell<&'a dyn touch::MultiTouchClient>,
+    screen_resolution: (u16, u16),
+    touch_resolution: Cell<(u16, u16)>,
+    enabled: Cell<bool>,
+    num_touches: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    events: TakeCell<'static, [TouchEvent; MAX_TOUCHES]>,
+}
+
+impl<'a, I: I2CDevice> Ft5x06<'a, I> {
+    pub fn new(
+        i2c: &'a I,
+        interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
+        buffer: &'static mut [u8; BUFFER_SIZE],
+        events: &'static mut [TouchEvent; MAX_TOUCHES],
+        screen_resolution: (u16, u16),
+    ) -> Ft5x06<'a, I> {
+        Ft5x06 {
+            i2c,
+            interrupt_pin,
+            touch_client: OptionalCell::empty(),
+            gesture_client: OptionalCell::empty(),
+            multi_touch_client: OptionalCell::empty(),
+            screen_resolution,
+            touch_resolution: Cell::new(screen_resolution),
+            enabled: Cell::new(false),
+            num_touches: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            events: TakeCell::new(events),
+        }
+    }
+
+    /// Set the resolution the controller reports positions in, when it
+    /// differs from the screen resolution.
+    pub fn set_touch_resolution(&self, width: u16, height: u16) {
+        self.touch_resolution.set((width, height));
+    }
+
+    fn enable_touches(&self) -> Result<(), ErrorCode> {
+        self.enabled.set(true);
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        Ok(())
+    }
+
+    fn disable_touches(&self) -> Result<(), ErrorCode> {
+        self.enabled.set(false);
+        self.interrupt_pin.disable_interrupts();
+        Ok(())
+    }
+
+    /// Decode the touches of a report, and hand them to the clients.
+    fn report(&self, report: &[u8]) {
+        if let Some(gesture) = gesture(report[0]) {
+            self.gesture_client
+                .map(|client| client.gesture_event(gesture));
+        }
+
+        let reported = ((report[1] & 0x0F) as usize).min(MAX_TOUCHES);
+        self.events.map(|events| {
+            let mut num_touches = 0;
+            for point in report[2..].chunks_exact(POINT_SIZE).take(reported) {
+                if let Some(event) =
+                    decode_point(point, self.touch_resolution.get(), self.screen_resolution)
+                {
+                    events[num_touches] = event;
+                    num_touches += 1;
+                }
+            }
+            self.num_touches.set(num_touches);
+
+            if num_touches > 0 {
+                self.touch_client
+                    .map(|client| client.touch_event(events[0]));
+            }
+            self.multi_touch_client
+                .map(|client| client.touch_events(&events[..], num_touches));
+        });
+    }
+}
+
+impl<'a, I: I2CDevice> I2CClient for Ft5x06<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        self.i2c.disable();
+        if status.is_ok() {
+            self.report(buffer);
+        }
+        self.buffer.replace(buffer);
+        if self.enabled.get() {
+            self.interrupt_pin
+                .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> gpio::Client for Ft5x06<'a, I> {
+    fn fired(&self) {
+        self.buffer.take().map(|buffer| {
+            self.interrupt_pin.disable_interrupts();
+            buffer[0] = REG_GEST_ID;
+            self.i2c.enable();
+            if let Err((_error, buffer)) = self.i2c.write_read(buffer, 1, BUFFER_SIZE) {
+                self.buffer.replace(buffer);
+                self.i2c.disable();
+                self.interrupt_pin
+                    .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+            }
+        });
+    }
+}
+
+impl<'a, I: I2CDevice> touch::Touch<'a> for Ft5x06<'a, I> {
+    fn enable(&self) -> Result<(), ErrorCode> {
+        self.enable_touches()
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        self.disable_touches()
+    }
+
+    fn set_client(&self, client: &'a dyn touch::TouchClient) {
+        self.touch_client.replace(client);
+    }
+}
+
+impl<'a, I: I2CDevice> touch::Gesture<'a> for Ft5x06<'a, I> {
+    fn set_client(&self, client: &'a dyn touch::GestureClient) {
+        self.gesture_client.replace(client);
+    }
+}
+
+impl<'a, I: I2CDevice> touch::MultiTouch<'a> for Ft5x06<'a, I> {
+    fn enable(&self) -> Result<(), ErrorCode> {
+        self.enable_touches()
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        self.disable_touches()
+    }
+
+    fn get_num_touches(&self) -> usize {
+        MAX_TOUCHES
+    }
+
+    fn get_touch(&self, index: usize) -> Option<TouchEvent> {
+        if index < self.num_touches.get() {
+            self.events.map(|events| events[index])
+        } else {
+            None
+        }
+    }
+
+    fn set_client(&self, client: &'a dyn touch::MultiTouchClient) {
+        self.multi_touch_client.replace(client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use core::cell::RefCell;
+    use kernel::hil::touch::MultiTouch;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct MockClient {
+        statuses: RefCell<Vec<TouchStatus>>,
+        touches: RefCell<Vec<(u16, u16, usize, Option<u16>, Option<u16>)>>,
+        gestures: RefCell<Vec<GestureEvent>>,
+    }
+
+    impl touch::MultiTouchClient for MockClient {
+        fn touch_events(&self, touch_events: &[TouchEvent], len: usize) {
+            for e in &touch_events[..len] {
+                self.statuses.borrow_mut().push(e.status);
+                self.touches
+                    .borrow_mut()
+                    .push((e.x, e.y, e.id, e.pressure, e.size));
+            }
+        }
+    }
+
+    impl touch::GestureClient for MockClient {
+        fn gesture_event(&self, gesture_event: GestureEvent) {
+            self.gestures.borrow_mut().push(gesture_event);
+        }
+    }
+
+    fn setup(
+        screen_resolution: (u16, u16),
+    ) -> (
+        &'static MockI2c,
+        &'static MockPin<'static>,
+        &'static Ft5x06<'static, MockI2c>,
+        &'static MockClient,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let int: &'static MockPin = Box::leak(Box::default());
+        let client: &'static MockClient = Box::leak(Box::default());
+        let ft5x06 = Box::leak(Box::new(Ft5x06::new(
+            i2c,
+            int,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+            Box::leak(Box::new([NO_TOUCH; MAX_TOUCHES])),
+            screen_resolution,
+        )));
+        MultiTouch::set_client(ft5x06, client);
+        touch::Gesture::set_client(ft5x06, client);
+        (i2c, int, ft5x06, client)
+    }
+
+    /// A report of two touches: one pressed at (300, 200) and one moving at
+    /// (1000, 700).
+    fn two_finger_report(gesture_id: u8) -> [u8; BUFFER_SIZE] {
+        let mut report = [0xFF; BUFFER_SIZE];
+        report[0] = gesture_id;
+        report[1] = 2;
+        report[2..8].copy_from_slice(&[0x01, 0x2C, 0x00, 0xC8, 0x80, 0x30]);
+        report[8..14].copy_from_slice(&[0x83, 0xE8, 0x12, 0xBC, 0xFF, 0xF0]);
+        report
+    }
+
+    #[test]
+    fn two_finger_touch() {
+        let (i2c, int, ft5x06, client) = setup((1024, 768));
+        assert_eq!(MultiTouch::enable(ft5x06), Ok(()));
+        assert!(matches!(
+            int.interrupt_edge(),
+            Some(gpio::InterruptEdge::FallingEdge)
+        ));
+
+        gpio::Client::fired(ft5x06);
+        assert!(!int.interrupts_enabled());
+        assert_eq!(
+            i2c.transfer()
+                .map(|transfer| (transfer.write, transfer.read_len)),
+            Some(([REG_GEST_ID].to_vec(), BUFFER_SIZE))
+        );
+        i2c.complete(ft5x06, &two_finger_report(0));
+        assert!(int.interrupts_enabled());
+
+        assert!(matches!(
+            client.statuses.borrow()[..],
+            [TouchStatus::Pressed, TouchStatus::Moved]
+        ));
+        assert_eq!(
+            client.touches.borrow()[..],
+            [
+                (300, 200, 0, Some(0x8080), Some(0x3333)),
+                (1000, 700, 1, Some(0xFFFF), Some(0xFFFF)),
+            ]
+        );
+        assert_eq!(ft5x06.get_touch(1).map(|e| (e.x, e.y)), Some((1000, 700)));
+        assert!(ft5x06.get_touch(2).is_none());
+        assert!(client.gestures.borrow().is_empty());
+    }
+
+    #[test]
+    fn scaling_and_gestures() {
+        let (i2c, int, ft5x06, client) = setup((512, 384));
+        ft5x06.set_touch_resolution(1024, 768);
+        gpio::Client::fired(ft5x06);
+        i2c.complete(ft5x06, &two_finger_report(0x48));
+
+        let touches = client.touches.borrow();
+        assert_eq!((touches[0].0, touches[0].1), (150, 100));
+        assert_eq!((touches[1].0, touches[1].1), (500, 350));
+        assert!(matches!(
+            client.gestures.borrow()[..],
+            [GestureEvent::ZoomIn]
+        ));
+        // Interrupts stay off until the touches are enabled.
+        assert!(!int.interrupts_enabled());
+    }
+}
@@ -0,0 +1,346 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the NXP FXAS21002C 3-axis gyroscope, over I2C.
+//!
+//! <https://www.nxp.com/docs/en/data-sheet/FXAS21002.pdf>
+//!
+//! The FXAS21002C is paired with the FXOS8700CQ accelerometer on many
+//! Freedom boards. It only accepts changes of its configuration in standby,
+//! so the driver keeps it in standby between readings. A reading activates
+//! the gyroscope, waits for the data-ready interrupt on INT2, reads the
+//! sample and puts the gyroscope back in standby. The configuration is
+//! written before the next activation whenever it changed.
+//!
+//! The driver implements the gyroscope of `hil::sensors::NineDof`, with the
+//! rotation rates in mdps. Rates below the dead band set with
+//! `set_gyroscope_dead_band` are reported as 0, which hides the noise of
+//! the zero-rate level at rest.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fxas21002c = components::fxas21002c::Fxas21002cComponent::new(
+//!     mux_i2c,
+//!     capsules_extra::fxas21002c::I2C_ADDRESS,
+//!     &peripherals.ports.pin(PinId::PTA01),
+//! )
+//! .finalize(components::fxas21002c_component_static!(imxrt1060::lpi2c::Lpi2c));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(fxos8700, fxas21002c));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The address with SA0 low. It is 0x21 with SA0 high.
+pub const I2C_ADDRESS: u8 = 0x20;
+
+pub const BUF_LEN: usize = 6;
+
+#[allow(dead_code)]
+enum Registers {
+    Status = 0x00,
+    OutXMsb = 0x01,
+    DrStatus = 0x07,
+    WhoAmI = 0x0C,
+    CtrlReg0 = 0x0D,
+    Temp = 0x12,
+    CtrlReg1 = 0x13,
+    CtrlReg2 = 0x14,
+    CtrlReg3 = 0x15,
+}
+
+/// ACTIVE of CTRL_REG1. The gyroscope is in standby when it and READY are
+/// clear.
+const CTRL_REG1_ACTIVE: u8 = 0x02;
+
+/// INT_EN_DRDY of CTRL_REG2, with INT_CFG_DRDY clear to route the
+/// interrupt to INT2, and IPOL set for an active high, push-pull output.
+const CTRL_REG2_DRDY_INT2: u8 = 0x06;
+
+/// The range of the rotation rates, the FS field of CTRL_REG0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FullScale {
+    Dps2000 = 0,
+    Dps1000 = 1,
+    Dps500 = 2,
+    Dps250 = 3,
+}
+
+/// The output data rate, the DR field of CTRL_REG1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataRate {
+    Hz800 = 0,
+    Hz400 = 1,
+    Hz200 = 2,
+    Hz100 = 3,
+    Hz50 = 4,
+    Hz25 = 5,
+    Hz12_5 = 6,
+}
+
+/// The cutoff of the low pass filter, the BW field of CTRL_REG0. The
+/// cutoffs are 256, 128 and 64 Hz at 800 Hz, and scale with the data rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowPassFilter {
+    Widest = 0,
+    Medium = 1,
+    Narrowest = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Going to standby, to write the configuration.
+    Standby,
+    WriteCtrlReg0,
+    WriteCtrlReg2,
+    Activating,
+    /// Waiting for the data-ready interrupt.
+    Waiting,
+    Reading,
+    Deactivating(i32, i32, i32),
+}
+
+pub struct Fxas21002c<'a, I: I2CDevice> {
+    i2c: &'a I,
+    interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    full_scale: Cell<FullScale>,
+    data_rate: Cell<DataRate>,
+    low_pass: Cell<LowPassFilter>,
+    /// Whether the configuration was written since it last changed.
+    configured: Cell<bool>,
+    dead_band: Cell<i32>,
+    nine_dof_client: OptionalCell<&'a dyn NineDofClient>,
+}
+
+impl<'a, I: I2CDevice> Fxas21002c<'a, I> {
+    pub fn new(
+        i2c: &'a I,
+        interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
+        buffer: &'static mut [u8; BUF_LEN],
+    ) -> Fxas21002c<'a, I> {
+        Fxas21002c {
+            i2c,
+            interrupt_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            full_scale: Cell::new(FullScale::Dps250),
+            data_rate: Cell::new(DataRate::Hz100),
+            low_pass: Cell::new(LowPassFilter::Widest),
+            configured: Cell::new(false),
+            dead_band: Cell::new(0),
+            nine_dof_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Set the configuration, which is written before the next reading.
+    pub fn configure(
+        &self,
+        full_scale: FullScale,
+        data_rate: DataRate,
+        low_pass: LowPassFilter,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.full_scale.set(full_scale);
+        self.data_rate.set(data_rate);
+        self.low_pass.set(low_pass);
+        self.configured.set(false);
+        Ok(())
+    }
+
+    fn ctrl_reg1(&self, active: bool) -> u8 {
+        let active = if active { CTRL_REG1_ACTIVE } else { 0 };
+        (self.data_rate.get() as u8) << 2 | active
+    }
+
+    fn write_register(
+        &self,
+        state: State,
+        register: Registers,
+        value: u8,
+    ) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+            buf[0] = register as u8;
+            buf[1] = value;
+            self.i2c.enable();
+            if let Err((error, buf)) = self.i2c.write(buf, 2) {
+                self.buffer.replace(buf);
+                self.i2c.disable();
+                Err(error.into())
+            } else {
+                self.state.set(state);
+                Ok(())
+            }
+        })
+    }
+
+    fn read_sample(&self) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+            buf[0] = Registers::OutXMsb as u8;
+            self.i2c.enable();
+            if let Err((error, buf)) = self.i2c.write_read(buf, 1, 6) {
+                self.buffer.replace(buf);
+                self.i2c.disable();
+                Err(error.into())
+            } else {
+                self.state.set(State::Reading);
+                Ok(())
+            }
+        })
+    }
+
+    /// End the reading in progress with a zero sample, as the NineDof
+    /// client has no error.
+    fn fail(&self) {
+        self.state.set(State::Idle);
+        self.nine_dof_client.map(|client| client.callback(0, 0, 0));
+    }
+}
+
+/// Convert the `raw` sample of an axis to mdps, with rates below
+/// `dead_band` reported as 0.
+fn rate_mdps(raw: i16, full_scale: FullScale, dead_band: i32) -> i32 {
+    // The sensitivity is 62.5 mdps/LSB at ±2000 dps, and halves with each
+    // smaller range.
+    let mdps = raw as i32 * 62_500 / (1000 << full_scale as u8);
+    if mdps.abs() < dead_band {
+        0
+    } else {
+        mdps
+    }
+}
+
+impl<'a, I: I2CDevice> I2CClient for Fxas21002c<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let result = match (status, self.state.get()) {
+            (Err(error), _) => {
+                self.buffer.replace(buffer);
+                Err(error.into())
+            }
+            (Ok(()), State::Reading) => {
+                let axis = |i: usize| {
+                    rate_mdps(
+                        i16::from_be_bytes([buffer[i], buffer[i + 1]]),
+                        self.full_scale.get(),
+                        self.dead_band.get(),
+                    )
+                };
+                let (x, y, z) = (axis(0), axis(2), axis(4));
+                self.buffer.replace(buffer);
+                self.write_register(
+                    State::Deactivating(x, y, z),
+                    Registers::CtrlReg1,
+                    self.ctrl_reg1(false),
+                )
+            }
+            (Ok(()), state) => {
+                self.buffer.replace(buffer);
+                match state {
+                    State::Standby => self.write_register(
+                        State::WriteCtrlReg0,
+                        Registers::CtrlReg0,
+                        (self.low_pass.get() as u8) << 6 | self.full_scale.get() as u8,
+                    ),
+                    State::WriteCtrlReg0 => self.write_register(
+                        State::WriteCtrlReg2,
+                        Registers::CtrlReg2,
+                        CTRL_REG2_DRDY_INT2,
+                    ),
+                    State::WriteCtrlReg2 => {
+                        self.configured.set(true);
+                        self.write_register(
+                            State::Activating,
+                            Registers::CtrlReg1,
+                            self.ctrl_reg1(true),
+                        )
+                    }
+                    State::Activating => {
+                        self.i2c.disable();
+                        if self.interrupt_pin.read() {
+                            // A sample is already ready.
+                            self.read_sample()
+                        } else {
+                            self.state.set(State::Waiting);
+                            Ok(())
+                        }
+                    }
+                    State::Deactivating(x, y, z) => {
+                        self.i2c.disable();
+                        self.state.set(State::Idle);
+                        self.nine_dof_client
+                            .map(|client| client.callback(x as usize, y as usize, z as usize));
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                }
+            }
+        };
+        if result.is_err() {
+            self.i2c.disable();
+            self.fail();
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> gpio::Client for Fxas21002c<'a, I> {
+    fn fired(&self) {
+        if self.state.get() == State::Waiting && self.read_sample().is_err() {
+            self.fail();
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> NineDof<'a> for Fxas21002c<'a, I> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.nine_dof_client.set(client);
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.configured.get() {
+            self.write_register(State::Activating, Registers::CtrlReg1, self.ctrl_reg1(true))
+        } else {
+            self.write_register(State::Standby, Registers::CtrlReg1, self.ctrl_reg1(false))
+        }
+    }
+
+    fn set_gyroscope_dead_band(&self, threshold: usize) -> Result<(), ErrorCode> {
+        self.dead_band.set(threshold.min(i32::MAX as usize) as i32);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_scale_with_the_range_and_dead_band() {
+        assert_eq!(rate_mdps(16, FullScale::Dps2000, 0), 1000);
+        assert_eq!(rate_mdps(-16, FullScale::Dps2000, 0), -1000);
+        assert_eq!(rate_mdps(128, FullScale::Dps250, 0), 1000);
+        assert_eq!(rate_mdps(i16::MAX, FullScale::Dps2000, 0), 2_047_937);
+
+        // Rates below the dead band are zeroed, on their own axis.
+        assert_eq!(rate_mdps(16, FullScale::Dps2000, 1001), 0);
+        assert_eq!(rate_mdps(-16, FullScale::Dps2000, 1001), 0);
+        assert_eq!(rate_mdps(16, FullScale::Dps2000, 1000), 1000);
+    }
+}
@@ -0,0 +1,322 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interrupt rate limiting for GPIO pins.
+//!
+//! A noisy or stuck input can fire interrupts fast enough to starve the rest
+//! of the kernel. `RateLimitedPin` wraps an interrupt pin and can be used
+//! anywhere the pin itself would be. Once a rate limit is set, at most
+//! `max_interrupts` interrupts are passed on to the client within a window
+//! of `window_ms` milliseconds, with the window starting at the first
+//! interrupt. The next interrupt in the window masks the pin until the
+//! window ends, and when the pin is unmasked the client receives a single
+//! `fired()` callback for all of the interrupts it missed.
+//!
+//! No rate limit is set by default, in which case interrupts are passed
+//! through unchanged.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let button_pin = components::gpio_rate_limit::GpioRateLimitComponent::new(
+//!     &nrf52840_peripherals.gpio_port[BUTTON_PIN],
+//!     mux_alarm,
+//!     Some(capsules_extra::gpio_rate_limit::RateLimit {
+//!         max_interrupts: 10,
+//!         window_ms: 100,
+//!     }),
+//! )
+//! .finalize(components::gpio_rate_limit_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RateLimit {
+    /// Number of interrupts delivered within a window before the pin is
+    /// masked.
+    pub max_interrupts: usize,
+    /// Length of the window, in milliseconds.
+    pub window_ms: u32,
+}
+
+pub struct RateLimitedPin<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> {
+    pin: &'a P,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn gpio::Client>,
+    limit: Cell<Option<RateLimit>>,
+    /// Edge configured by the client, if interrupts are enabled.
+    mode: OptionalCell<gpio::InterruptEdge>,
+    /// Interrupts seen in the current window.
+    count: Cell<usize>,
+    masked: Cell<bool>,
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> RateLimitedPin<'a, P, A> {
+    pub fn new(pin: &'a P, alarm: &'a A) -> RateLimitedPin<'a, P, A> {
+        RateLimitedPin {
+            pin,
+            alarm,
+            client: OptionalCell::empty(),
+            limit: Cell::new(None),
+            mode: OptionalCell::empty(),
+            count: Cell::new(0),
+            masked: Cell::new(false),
+        }
+    }
+
+    /// Set the rate limit, or pass `None` to deliver every interrupt. Any
+    /// window in progress is ended and a masked pin is unmasked.
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) {
+        self.limit.set(limit);
+        self.end_window();
+    }
+
+    /// Ends the current window, unmasking the pin if needed. Returns
+    /// whether the pin was masked.
+    fn end_window(&self) -> bool {
+        let _ = self.alarm.disarm();
+        self.count.set(0);
+        let masked = self.masked.replace(false);
+        if masked {
+            self.mode.map(|mode| self.pin.enable_interrupts(*mode));
+        }
+        masked
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Client for RateLimitedPin<'a, P, A> {
+    fn fired(&self) {
+        let limit = match self.limit.get() {
+            Some(limit) => limit,
+            None => {
+                self.client.map(|client| client.fired());
+                return;
+            }
+        };
+        if self.masked.get() {
+            // An interrupt that was already pending when the pin was masked.
+            return;
+        }
+
+        let count = self.count.get() + 1;
+        self.count.set(count);
+        if count == 1 {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(limit.window_ms));
+        }
+        if count <= limit.max_interrupts {
+            self.client.map(|client| client.fired());
+        } else {
+            // Hold this interrupt, and any that follow, until the window
+            // ends.
+            self.masked.set(true);
+            self.pin.disable_interrupts();
+        }
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> time::AlarmClient for RateLimitedPin<'a, P, A> {
+    fn alarm(&self) {
+        if self.end_window() {
+            self.client.map(|client| client.fired());
+        }
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Configure for RateLimitedPin<'a, P, A> {
+    fn configuration(&self) -> gpio::Configuration {
+        self.pin.configuration()
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.pin.make_output()
+    }
+
+    fn disable_output(&self) -> gpio::Configuration {
+        self.pin.disable_output()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.pin.make_input()
+    }
+
+    fn disable_input(&self) -> gpio::Configuration {
+        self.pin.disable_input()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.pin.deactivate_to_low_power()
+    }
+
+    fn set_floating_state(&self, state: gpio::FloatingState) {
+        self.pin.set_floating_state(state)
+    }
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        self.pin.floating_state()
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Output for RateLimitedPin<'a, P, A> {
+    fn set(&self) {
+        self.pin.set()
+    }
+
+    fn clear(&self) {
+        self.pin.clear()
+    }
+
+    fn toggle(&self) -> bool {
+        self.pin.toggle()
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Input for RateLimitedPin<'a, P, A> {
+    fn read(&self) -> bool {
+        self.pin.read()
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Interrupt<'a> for RateLimitedPin<'a, P, A> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.mode.set(mode);
+        if !self.masked.get() {
+            self.pin.enable_interrupts(mode);
+        }
+    }
+
+    fn disable_interrupts(&self) {
+        self.mode.clear();
+        self.pin.disable_interrupts();
+        self.end_window();
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pin.is_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin};
+    use kernel::hil::gpio::{Client, Interrupt, InterruptEdge};
+
+    #[derive(Default)]
+    struct Callbacks {
+        fired: Cell<usize>,
+    }
+
+    impl Client for Callbacks {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+        }
+    }
+
+    const LIMIT: RateLimit = RateLimit {
+        max_interrupts: 3,
+        window_ms: 100,
+    };
+
+    /// Fire `n` interrupts on the pin, dropping those that arrive while
+    /// the pin has interrupts disabled.
+    fn flood<'a>(
+        limited: &RateLimitedPin<'a, MockPin<'a>, MockAlarm<'a>>,
+        pin: &MockPin,
+        n: usize,
+    ) {
+        for _ in 0..n {
+            if pin.interrupts_enabled() {
+                limited.fired();
+            }
+        }
+    }
+
+    #[test]
+    fn flood_is_capped_and_pin_reenabled() {
+        let pin = MockPin::default();
+        let alarm = MockAlarm::new();
+        let callbacks = Callbacks::default();
+        let limited = RateLimitedPin::new(&pin, &alarm);
+        alarm.set_alarm_client(&limited);
+        limited.set_client(&callbacks);
+        limited.set_rate_limit(Some(LIMIT));
+        limited.enable_interrupts(InterruptEdge::EitherEdge);
+
+        flood(&limited, &pin, 1000);
+        assert_eq!(callbacks.fired.get(), 3);
+        assert!(!pin.interrupts_enabled());
+
+        // The pin stays masked for the rest of the window.
+        alarm.advance(50);
+        flood(&limited, &pin, 1000);
+        assert_eq!(callbacks.fired.get(), 3);
+
+        // The window ends with one coalesced callback and the pin unmasked.
+        alarm.advance(50);
+        assert_eq!(callbacks.fired.get(), 4);
+        assert!(pin.interrupts_enabled());
+
+        // The next window starts afresh.
+        flood(&limited, &pin, 2);
+        assert_eq!(callbacks.fired.get(), 6);
+        alarm.advance(100);
+        assert_eq!(callbacks.fired.get(), 6);
+        assert!(pin.interrupts_enabled());
+        flood(&limited, &pin, 1000);
+        assert_eq!(callbacks.fired.get(), 9);
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let pin = MockPin::default();
+        let alarm = MockAlarm::new();
+        let callbacks = Callbacks::default();
+        let limited = RateLimitedPin::new(&pin, &alarm);
+        alarm.set_alarm_client(&limited);
+        limited.set_client(&callbacks);
+        limited.enable_interrupts(InterruptEdge::RisingEdge);
+
+        flood(&limited, &pin, 1000);
+        assert_eq!(callbacks.fired.get(), 1000);
+        assert!(pin.interrupts_enabled());
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn disable_while_masked() {
+        let pin = MockPin::default();
+        let alarm = MockAlarm::new();
+        let callbacks = Callbacks::default();
+        let limited = RateLimitedPin::new(&pin, &alarm);
+        alarm.set_alarm_client(&limited);
+        limited.set_client(&callbacks);
+        limited.set_rate_limit(Some(LIMIT));
+        limited.enable_interrupts(InterruptEdge::FallingEdge);
+
+        flood(&limited, &pin, 10);
+        limited.disable_interrupts();
+        assert!(!alarm.is_armed());
+        alarm.advance(100);
+        assert_eq!(callbacks.fired.get(), 3);
+        assert!(!pin.interrupts_enabled());
+
+        // Re-enabling starts with a fresh window.
+        limited.enable_interrupts(InterruptEdge::FallingEdge);
+        flood(&limited, &pin, 10);
+        assert_eq!(callbacks.fired.get(), 6);
+    }
+}
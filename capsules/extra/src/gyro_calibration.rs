@@ -0,0 +1,248 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Zero-rate offset calibration shared by gyroscope drivers.
+//!
+//! A gyroscope at rest should report zero angular velocity on every axis,
+//! but real parts have a per-axis bias. This helper averages a number of raw
+//! samples taken while the device is at rest and stores the result so it can
+//! be subtracted from subsequent readings.
+//!
+//! Usage
+//! -----
+//!
+//! A driver holds a `GyroCalibration`, calls [GyroCalibration::start] when a
+//! calibration is requested, feeds every raw sample it reads to
+//! [GyroCalibration::add_sample] until it returns `true`, and passes every
+//! raw reading through [GyroCalibration::correct] before scaling it.
+//!
+//! The InvenSense MPU and ICM parts share a self-test procedure: the
+//! gyroscope is read with and without the self-test stimulus, and the
+//! difference is compared with the response measured at the factory.
+//! [invensense_self_test_passed] applies the pass criteria of that
+//! procedure.
+
+use core::cell::Cell;
+use kernel::ErrorCode;
+
+/// Factory self-test response of an InvenSense gyroscope with a self-test
+/// code of 1, in µdps. Every step of the code adds 1%.
+const INVENSENSE_FACTORY_RESPONSE_UDPS: i32 = 20_000_000;
+/// Smallest self-test response of an axis without a factory code, in mdps.
+const INVENSENSE_MIN_RESPONSE_MDPS: i32 = 60_000;
+/// Largest zero-rate output during the self-test, in mdps.
+const INVENSENSE_MAX_OFFSET_MDPS: i32 = 20_000;
+
+pub struct GyroCalibration {
+    bias: Cell<[i16; 3]>,
+    sum: Cell<[i32; 3]>,
+    collected: Cell<usize>,
+    requested: Cell<usize>,
+}
+
+impl GyroCalibration {
+    pub const fn new() -> GyroCalibration {
+        GyroCalibration {
+            bias: Cell::new([0; 3]),
+            sum: Cell::new([0; 3]),
+            collected: Cell::new(0),
+            requested: Cell::new(0),
+        }
+    }
+
+    /// Begin collecting `samples` raw readings.
+    ///
+    /// The current bias is left in place until the new one has been
+    /// computed.
+    pub fn start(&self, samples: usize) -> Result<(), ErrorCode> {
+        if samples == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.is_running() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.sum.set([0; 3]);
+        self.collected.set(0);
+        self.requested.set(samples);
+        Ok(())
+    }
+
+    /// Whether a calibration is in progress.
+    pub fn is_running(&self) -> bool {
+        self.requested.get() != 0
+    }
+
+    /// Add a raw sample to an in-progress calibration.
+    ///
+    /// Returns `true` once the requested number of samples has been
+    /// collected and the new bias is in effect.
+    pub fn add_sample(&self, raw: [i16; 3]) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        let mut sum = self.sum.get();
+        for axis in 0..3 {
+            sum[axis] += raw[axis] as i32;
+        }
+        self.sum.set(sum);
+        self.collected.set(self.collected.get() + 1);
+
+        if self.collected.get() < self.requested.get() {
+            return false;
+        }
+
+        let count = self.collected.get() as i32;
+        self.bias.set([
+            (sum[0] / count) as i16,
+            (sum[1] / count) as i16,
+            (sum[2] / count) as i16,
+        ]);
+        self.requested.set(0);
+        true
+    }
+
+    /// Abandon an in-progress calibration, keeping the previous bias.
+    pub fn cancel(&self) {
+        self.requested.set(0);
+    }
+
+    /// The per-axis bias in raw counts.
+    pub fn bias(&self) -> [i16; 3] {
+        self.bias.get()
+    }
+
+    /// Remove the stored bias from a raw sample.
+    pub fn correct(&self, raw: [i16; 3]) -> [i16; 3] {
+        let bias = self.bias.get();
+        [
+            raw[0].saturating_sub(bias[0]),
+            raw[1].saturating_sub(bias[1]),
+            raw[2].saturating_sub(bias[2]),
+        ]
+    }
+}
+
+/// The factory self-test response in mdps for a code of the InvenSense
+/// `SELF_TEST_*_GYRO` registers, or `None` for a code of 0, which means the
+/// response was not recorded.
+fn invensense_factory_response_mdps(code: u8) -> Option<i32> {
+    if code == 0 {
+        return None;
+    }
+    let response = (1..code).fold(INVENSENSE_FACTORY_RESPONSE_UDPS, |response, _| {
+        response / 100 * 101
+    });
+    Some(response / 1000)
+}
+
+/// Whether an InvenSense gyroscope passed its self-test.
+///
+/// `baseline` and `stimulated` are the average raw readings without and
+/// with the self-test stimulus at a sensitivity of `lsb_per_10dps`, and
+/// `codes` are the factory self-test codes of the X, Y and Z axes. Every
+/// axis must respond with more than half of its factory response, or with
+/// at least 60 dps if it has no factory code, and read within 20 dps of zero
+/// without the stimulus.
+pub fn invensense_self_test_passed(
+    baseline: [i32; 3],
+    stimulated: [i32; 3],
+    codes: [u8; 3],
+    lsb_per_10dps: i32,
+) -> bool {
+    let mdps = |raw: i32| raw * 10_000 / lsb_per_10dps;
+    (0..3).all(|axis| {
+        let response = mdps(stimulated[axis] - baseline[axis]);
+        let responds = match invensense_factory_response_mdps(codes[axis]) {
+            Some(factory) => response * 2 > factory,
+            None => response >= INVENSENSE_MIN_RESPONSE_MDPS,
+        };
+        responds && mdps(baseline[axis]).abs() <= INVENSENSE_MAX_OFFSET_MDPS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biased_stream_is_corrected() {
+        let calibration = GyroCalibration::new();
+        let bias = [12, -7, 3];
+        // Noise around the bias that averages to zero.
+        let noise = [[1, -1, 2], [-1, 1, -2], [2, 0, 1], [-2, 0, -1]];
+
+        assert_eq!(calibration.start(noise.len()), Ok(()));
+        assert_eq!(calibration.start(noise.len()), Err(ErrorCode::BUSY));
+        for (i, n) in noise.iter().enumerate() {
+            let sample = [bias[0] + n[0], bias[1] + n[1], bias[2] + n[2]];
+            assert_eq!(calibration.add_sample(sample), i == noise.len() - 1);
+        }
+        assert!(!calibration.is_running());
+        assert_eq!(calibration.bias(), bias);
+
+        assert_eq!(calibration.correct(bias), [0, 0, 0]);
+        assert_eq!(calibration.correct([112, 93, -97]), [100, 100, -100]);
+    }
+
+    #[test]
+    fn uncalibrated_readings_pass_through() {
+        let calibration = GyroCalibration::new();
+        assert_eq!(calibration.start(0), Err(ErrorCode::INVAL));
+        assert!(!calibration.add_sample([5, 5, 5]));
+        assert_eq!(calibration.correct([1, -2, 3]), [1, -2, 3]);
+    }
+
+    #[test]
+    fn invensense_self_test_criteria() {
+        // 1% more per step of the code.
+        assert_eq!(invensense_factory_response_mdps(0), None);
+        assert_eq!(invensense_factory_response_mdps(1), Some(20_000));
+        assert_eq!(invensense_factory_response_mdps(2), Some(20_200));
+        assert_eq!(invensense_factory_response_mdps(71), Some(40_131));
+
+        // At 131 LSB/dps, code 71 needs a response of more than 20 dps.
+        let baseline = [131, -262, 0];
+        let respond = |dps: i32| baseline.map(|raw| raw + dps * 131);
+        assert!(invensense_self_test_passed(
+            baseline,
+            respond(21),
+            [71; 3],
+            1310
+        ));
+        assert!(!invensense_self_test_passed(
+            baseline,
+            respond(19),
+            [71; 3],
+            1310
+        ));
+        // Without a factory code, 60 dps is needed.
+        assert!(!invensense_self_test_passed(
+            baseline,
+            respond(21),
+            [71, 0, 71],
+            1310
+        ));
+        assert!(invensense_self_test_passed(
+            baseline,
+            respond(60),
+            [71, 0, 71],
+            1310
+        ));
+        // The same readings are half the rate at twice the sensitivity.
+        assert!(!invensense_self_test_passed(
+            baseline,
+            respond(21),
+            [71; 3],
+            2620
+        ));
+        // A zero-rate output over 20 dps fails.
+        let offset = [131 * 21, 0, 0];
+        assert!(!invensense_self_test_passed(
+            offset,
+            offset.map(|raw| raw + 100 * 131),
+            [71; 3],
+            1310
+        ));
+    }
+}
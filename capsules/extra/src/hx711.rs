@@ -0,0 +1,608 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Avia Semiconductor HX711 24-bit ADC for load cells.
+//!
+//! <https://cdn.sparkfun.com/datasheets/Sensors/ForceFlex/hx711_english.pdf>
+//!
+//! The HX711 is read over two GPIO pins. DATA goes low when a conversion is
+//! ready. The host then sends 24 pulses on CLK, and the HX711 shifts out
+//! one bit of the two's complement result, most significant bit first, on
+//! each rising edge. One to three more pulses select the channel and gain
+//! of the next conversion.
+//!
+//! The driver checks DATA every millisecond, and fails a reading with
+//! `NODEVICE` if the HX711 is not ready within 500 ms. The pulses are timed
+//! with an alarm. CLK is raised and lowered in the same alarm callback, as
+//! the HX711 powers down if CLK stays high for more than 60 us, and each bit
+//! is sampled just before the next pulse. When the gain is changed, the
+//! conversion that was already started with the old gain is discarded.
+//!
+//! Readings are reported both as the raw ADC value and as a weight. The
+//! weight is calibrated with a tare, which records the raw value with
+//! nothing on the load cell, and a known weight, which sets the scale. Its
+//! units are those of the known weight. Until the scale is set, the weight
+//! is the raw value minus the tare.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a reading completes, with its status, the raw value
+//!   and the weight.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: start a reading
+//! * `2`: tare: set the zero of the weight from a new reading
+//! * `3`: set the scale from a new reading of the known weight `arg1`
+//! * `4`: select the channel and gain (`1`: A 128, `2`: B 32, `3`: A 64)
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hx711 = components::hx711::Hx711Component::new(
+//!     board_kernel,
+//!     capsules_extra::hx711::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[HX711_DATA],
+//!     &nrf52840_peripherals.gpio_port[HX711_CLK],
+//!     mux_alarm,
+//! )
+//! .finalize(components::hx711_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Hx711 as usize;
+
+/// Bits in a conversion result.
+const DATA_BITS: u8 = 24;
+
+/// Time between checks of DATA while waiting for a conversion.
+const READY_POLL_MS: u32 = 1;
+/// Longest time to wait for a conversion. At 10 samples per second one is
+/// ready every 100 ms.
+const READY_TIMEOUT_MS: u32 = 500;
+
+/// Time CLK is held low between pulses.
+const CLOCK_LOW_US: u32 = 1;
+
+/// Input channel and gain, selected by the number of pulses after the data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gain {
+    ChannelA128 = 1,
+    ChannelB32 = 2,
+    ChannelA64 = 3,
+}
+
+impl Gain {
+    fn from_usize(gain: usize) -> Option<Gain> {
+        match gain {
+            1 => Some(Gain::ChannelA128),
+            2 => Some(Gain::ChannelB32),
+            3 => Some(Gain::ChannelA64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Reading {
+    pub raw: i32,
+    pub weight: i32,
+}
+
+pub trait Hx711Client {
+    fn reading_done(&self, result: Result<Reading, ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Read,
+    Tare,
+    /// Set the scale from a reading of this weight.
+    Calibrate(i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Waiting for DATA to go low, for this many milliseconds so far.
+    WaitReady(u32),
+    /// Sending this pulse on CLK, counting from 0.
+    Clock(u8),
+}
+
+/// Sign-extends a 24-bit two's complement value.
+fn sign_extend(value: u32) -> i32 {
+    ((value << 8) as i32) >> 8
+}
+
+/// Returns the weight of `raw` given the raw value with no weight, `offset`,
+/// and a scale of `counts` per `weight`.
+fn weight(raw: i32, offset: i32, counts: i32, weight: i32) -> i32 {
+    let net = raw as i64 - offset as i64;
+    if counts == 0 {
+        net as i32
+    } else {
+        (net * weight as i64 / counts as i64) as i32
+    }
+}
+
+pub struct Hx711<'a, P: gpio::Pin, A: Alarm<'a>> {
+    data: &'a P,
+    clock: &'a P,
+    alarm: &'a A,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    value: Cell<u32>,
+    gain: Cell<Gain>,
+    /// Gain of the conversion in progress, which was selected by the
+    /// previous reading. The HX711 starts with channel A at 128.
+    conversion_gain: Cell<Gain>,
+    offset: Cell<i32>,
+    scale_counts: Cell<i32>,
+    scale_weight: Cell<i32>,
+    client: OptionalCell<&'a dyn Hx711Client>,
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> Hx711<'a, P, A> {
+    pub fn new(data: &'a P, clock: &'a P, alarm: &'a A) -> Hx711<'a, P, A> {
+        Hx711 {
+            data,
+            clock,
+            alarm,
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Read),
+            value: Cell::new(0),
+            gain: Cell::new(Gain::ChannelA128),
+            conversion_gain: Cell::new(Gain::ChannelA128),
+            offset: Cell::new(0),
+            scale_counts: Cell::new(0),
+            scale_weight: Cell::new(1),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Hx711Client) {
+        self.client.set(client);
+    }
+
+    /// Configures the pins. CLK is driven low, which keeps the HX711
+    /// powered up.
+    pub fn setup(&self) {
+        self.clock.make_output();
+        self.clock.clear();
+        self.data.make_input();
+    }
+
+    /// Sets the calibration: the raw value with no weight, and the number of
+    /// counts for `weight`.
+    pub fn set_calibration(&self, offset: i32, counts: i32, weight: i32) {
+        self.offset.set(offset);
+        self.scale_counts.set(counts);
+        self.scale_weight.set(weight);
+    }
+
+    pub fn set_gain(&self, gain: Gain) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.gain.set(gain);
+        Ok(())
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.state.get() != State::Idle
+    }
+
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Read)
+    }
+
+    /// Takes a reading with nothing on the load cell as the zero weight.
+    pub fn tare(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Tare)
+    }
+
+    /// Takes a reading of `known_weight` to set the scale. The load cell
+    /// must have been tared first.
+    pub fn calibrate(&self, known_weight: i32) -> Result<(), ErrorCode> {
+        if known_weight == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.start(Operation::Calibrate(known_weight))
+    }
+
+    fn start(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(operation);
+        self.wait_ready(0);
+        Ok(())
+    }
+
+    /// Starts the readout if a conversion is ready, or checks again later.
+    fn wait_ready(&self, waited_ms: u32) {
+        if !self.data.read() {
+            self.value.set(0);
+            self.clock_pulse(0);
+        } else if waited_ms >= READY_TIMEOUT_MS {
+            self.finish(Err(ErrorCode::NODEVICE));
+        } else {
+            self.state.set(State::WaitReady(waited_ms + READY_POLL_MS));
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(READY_POLL_MS));
+        }
+    }
+
+    /// Samples the bit shifted out by the previous pulse, then sends pulse
+    /// `pulse`.
+    fn clock_pulse(&self, pulse: u8) {
+        if pulse > 0 && pulse <= DATA_BITS {
+            // The bit stays on DATA until the next rising edge.
+            self.value
+                .set(self.value.get() << 1 | self.data.read() as u32);
+        }
+        if pulse == DATA_BITS + self.gain.get() as u8 {
+            self.readout_done();
+            return;
+        }
+
+        self.clock.set();
+        self.clock.clear();
+        self.state.set(State::Clock(pulse + 1));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(CLOCK_LOW_US));
+    }
+
+    fn readout_done(&self) {
+        let converted_with = self.conversion_gain.get();
+        self.conversion_gain.set(self.gain.get());
+        if converted_with != self.gain.get() {
+            // The conversion used the previous gain, use the next one.
+            self.wait_ready(0);
+            return;
+        }
+
+        let raw = sign_extend(self.value.get());
+        let result = match self.operation.get() {
+            Operation::Read => Ok(()),
+            Operation::Tare => {
+                self.offset.set(raw);
+                Ok(())
+            }
+            Operation::Calibrate(known_weight) => {
+                let counts = raw.wrapping_sub(self.offset.get());
+                if counts == 0 {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    self.scale_counts.set(counts);
+                    self.scale_weight.set(known_weight);
+                    Ok(())
+                }
+            }
+        };
+        self.finish(result.map(|()| Reading {
+            raw,
+            weight: weight(
+                raw,
+                self.offset.get(),
+                self.scale_counts.get(),
+                self.scale_weight.get(),
+            ),
+        }));
+    }
+
+    fn finish(&self, result: Result<Reading, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client.map(|client| client.reading_done(result));
+    }
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> time::AlarmClient for Hx711<'a, P, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::WaitReady(waited_ms) => self.wait_ready(waited_ms),
+            State::Clock(pulse) => self.clock_pulse(pulse),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: bool,
+}
+
+/// System call interface to an HX711.
+pub struct Hx711Driver<'a, P: gpio::Pin, A: Alarm<'a>> {
+    hx711: &'a Hx711<'a, P, A>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> Hx711Driver<'a, P, A> {
+    pub fn new(
+        hx711: &'a Hx711<'a, P, A>,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Hx711Driver<'a, P, A> {
+        Hx711Driver { hx711, apps: grant }
+    }
+
+    fn start(&self, operation: Operation, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.pending {
+                    return Err(ErrorCode::BUSY);
+                }
+                match operation {
+                    // Join a reading that is already in progress.
+                    Operation::Read if self.hx711.is_busy() => {}
+                    Operation::Read => self.hx711.read()?,
+                    Operation::Tare => self.hx711.tare()?,
+                    Operation::Calibrate(known_weight) => self.hx711.calibrate(known_weight)?,
+                }
+                app.pending = true;
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> Hx711Client for Hx711Driver<'a, P, A> {
+    fn reading_done(&self, result: Result<Reading, ErrorCode>) {
+        let status = into_statuscode(result.map(|_| ()));
+        let reading = result.unwrap_or(Reading { raw: 0, weight: 0 });
+        self.apps.each(|_, app, upcalls| {
+            if app.pending {
+                app.pending = false;
+                upcalls
+                    .schedule_upcall(0, (status, reading.raw as usize, reading.weight as usize))
+                    .ok();
+            }
+        });
+    }
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> SyscallDriver for Hx711Driver<'a, P, A> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start a reading.
+    /// - `2`: Tare from a new reading.
+    /// - `3`: Set the scale from a new reading of the known weight `data1`.
+    /// - `4`: Select the channel and gain `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start(Operation::Read, processid).into(),
+            2 => self.start(Operation::Tare, processid).into(),
+            3 => self
+                .start(Operation::Calibrate(data1 as i32), processid)
+                .into(),
+            4 => match Gain::from_usize(data1) {
+                Some(gain) => self.hx711.set_gain(gain).into(),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin, PinModel};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+
+    /// An HX711 with a conversion result that is ready after some checks of
+    /// DATA.
+    struct Chip {
+        result: Cell<u32>,
+        /// Checks of DATA before the conversion is ready.
+        ready_after: Cell<usize>,
+        ready: Cell<bool>,
+        clock_high: Cell<bool>,
+        pulses: Cell<u8>,
+        /// Pulses in each completed readout.
+        readouts: Cell<[u8; 2]>,
+        num_readouts: Cell<usize>,
+        dout: Cell<bool>,
+    }
+
+    impl Chip {
+        fn new(result: u32, ready_after: usize) -> Chip {
+            Chip {
+                result: Cell::new(result),
+                ready_after: Cell::new(ready_after),
+                ready: Cell::new(false),
+                clock_high: Cell::new(false),
+                pulses: Cell::new(0),
+                readouts: Cell::new([0; 2]),
+                num_readouts: Cell::new(0),
+                dout: Cell::new(true),
+            }
+        }
+
+        fn read_data(&self) -> bool {
+            if self.pulses.get() > DATA_BITS {
+                // The readout is over, the next conversion starts.
+                let mut readouts = self.readouts.get();
+                readouts[self.num_readouts.get()] = self.pulses.get();
+                self.readouts.set(readouts);
+                self.num_readouts.set(self.num_readouts.get() + 1);
+                self.pulses.set(0);
+                self.ready.set(false);
+            } else if !self.ready.get() {
+                match self.ready_after.get() {
+                    0 => {
+                        self.ready.set(true);
+                        self.dout.set(false);
+                    }
+                    n => self.ready_after.set(n - 1),
+                }
+            }
+            self.dout.get()
+        }
+
+        fn set_clock(&self, high: bool) {
+            let rising = high && !self.clock_high.get();
+            self.clock_high.set(high);
+            if !rising {
+                return;
+            }
+            assert!(self.ready.get(), "clocked before the conversion was ready");
+            let pulse = self.pulses.get() + 1;
+            self.pulses.set(pulse);
+            if pulse <= DATA_BITS {
+                self.dout
+                    .set(self.result.get() >> (DATA_BITS - pulse) & 1 == 1);
+            } else {
+                // The extra pulses select the gain and pull DATA high until
+                // the next conversion.
+                self.dout.set(true);
+            }
+        }
+    }
+
+    const DATA: usize = 0;
+    const CLOCK: usize = 1;
+
+    impl PinModel for Chip {
+        fn driven(&self, id: usize, _output: bool, level: bool) {
+            assert_eq!(id, CLOCK);
+            self.set_clock(level);
+        }
+
+        fn read(&self, id: usize) -> bool {
+            assert_eq!(id, DATA);
+            // CLK must never be left high, or the HX711 powers down.
+            assert!(!self.clock_high.get());
+            self.read_data()
+        }
+    }
+
+    struct Client {
+        result: Cell<Option<Result<Reading, ErrorCode>>>,
+    }
+
+    impl Hx711Client for Client {
+        fn reading_done(&self, result: Result<Reading, ErrorCode>) {
+            self.result.set(Some(result));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestHx711 = Hx711<'static, MockPin<'static>, TestAlarm>;
+
+    fn setup(chip: Chip) -> (&'static TestHx711, &'static Chip, &'static TestAlarm) {
+        let chip = Box::leak(Box::new(chip));
+        let data: &'static MockPin = Box::leak(Box::default());
+        data.attach(chip, DATA);
+        let clock: &'static MockPin = Box::leak(Box::default());
+        clock.attach(chip, CLOCK);
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let hx711 = Box::leak(Box::new(Hx711::new(data, clock, alarm)));
+        alarm.set_alarm_client(hx711);
+        hx711.setup();
+        (hx711, chip, alarm)
+    }
+
+    /// Delivers alarms until the reading completes.
+    fn run(hx711: &TestHx711, alarm: &TestAlarm) -> Result<Reading, ErrorCode> {
+        let client = Box::leak(Box::new(Client {
+            result: Cell::new(None),
+        }));
+        hx711.set_client(client);
+        for _ in 0..10_000 {
+            if let Some(result) = client.result.take() {
+                return result;
+            }
+            alarm.fire();
+        }
+        panic!("reading did not complete");
+    }
+
+    #[test]
+    fn assembles_24_bit_value() {
+        let (hx711, chip, alarm) = setup(Chip::new(0x12_3456, 3));
+        assert_eq!(hx711.read(), Ok(()));
+        assert_eq!(hx711.read(), Err(ErrorCode::BUSY));
+        let reading = run(hx711, alarm).unwrap();
+        assert_eq!(reading.raw, 0x12_3456);
+        // 24 data pulses and one to keep channel A at a gain of 128.
+        assert_eq!(chip.pulses.get(), 25);
+
+        // Negative values are sign-extended.
+        chip.result.set(0xFF_FFFE);
+        chip.ready_after.set(0);
+        assert_eq!(hx711.read(), Ok(()));
+        assert_eq!(run(hx711, alarm).unwrap().raw, -2);
+    }
+
+    #[test]
+    fn gain_change_discards_old_conversion() {
+        let (hx711, chip, alarm) = setup(Chip::new(0x80_0000, 0));
+        assert_eq!(hx711.set_gain(Gain::ChannelA64), Ok(()));
+        assert_eq!(hx711.read(), Ok(()));
+        let reading = run(hx711, alarm).unwrap();
+        assert_eq!(reading.raw, -0x80_0000);
+        // The first conversion was made at the old gain.
+        assert_eq!(chip.num_readouts.get(), 1);
+        assert_eq!(chip.readouts.get()[0], 27);
+        assert_eq!(chip.pulses.get(), 27);
+    }
+
+    #[test]
+    fn not_ready_times_out() {
+        let (hx711, chip, alarm) = setup(Chip::new(0, usize::MAX));
+        assert_eq!(hx711.read(), Ok(()));
+        assert_eq!(run(hx711, alarm), Err(ErrorCode::NODEVICE));
+        assert_eq!(chip.pulses.get(), 0);
+        assert!(!hx711.is_busy());
+    }
+
+    #[test]
+    fn weight_calibration() {
+        // Tared at 1000 counts, with 5000 counts for 250 g.
+        assert_eq!(weight(1000, 1000, 5000, 250), 0);
+        assert_eq!(weight(6000, 1000, 5000, 250), 250);
+        assert_eq!(weight(-1000, 1000, 5000, 250), -100);
+        // Without a scale, the weight is in counts.
+        assert_eq!(weight(1500, 1000, 0, 1), 500);
+        assert_eq!(sign_extend(0x7F_FFFF), 0x7F_FFFF);
+        assert_eq!(sign_extend(0x80_0000), -0x80_0000);
+    }
+}
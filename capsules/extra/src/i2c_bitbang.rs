@@ -0,0 +1,779 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Software (bit-banged) I2C master over two GPIO pins.
+//!
+//! This provides [I2CMaster] on pins that are not connected to an I2C
+//! controller, so existing sensor drivers can be used through the usual I2C
+//! mux and device virtualizers.
+//!
+//! The SDA and SCL pins are driven as open-drain outputs: a line is pulled
+//! low by configuring its pin as an output driving low, and released by
+//! disabling the output. Both lines need pull-up resistors; the pins'
+//! internal pull-ups are enabled, but are usually too weak for anything but
+//! slow buses.
+//!
+//! Every half period of SCL is timed with an alarm, so the bus never runs
+//! faster than the configured speed. It may run slower if the alarm cannot
+//! resolve the half period.
+//!
+//! After releasing SCL the driver waits for the line to actually go high, so
+//! slaves can stretch the clock. A transfer fails with [Error::Busy] if a
+//! slave holds SCL low for longer than 25 ms. Whenever the driver releases
+//! SDA it checks that the line is high when sampled; if another master is
+//! pulling it low the transfer stops with [Error::ArbitrationLost] and both
+//! lines are released.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let i2c_bitbang = components::i2c_bitbang::I2CBitBangComponent::new(
+//!     &nrf52840_peripherals.gpio_port[SDA_PIN],
+//!     &nrf52840_peripherals.gpio_port[SCL_PIN],
+//!     mux_alarm,
+//!     100_000,
+//! )
+//! .finalize(components::i2c_bitbang_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//!
+//! let mux_i2c = components::i2c::I2CMuxComponent::new(i2c_bitbang, None)
+//!     .finalize(components::i2c_mux_component_static!(
+//!         capsules_extra::i2c_bitbang::I2CBitBang<
+//!             'static,
+//!             nrf52840::gpio::GPIOPin,
+//!             capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!                 'static,
+//!                 nrf52840::rtc::Rtc<'static>,
+//!             >,
+//!         >
+//!     ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{Error, I2CHwMasterClient, I2CMaster};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Bus speed used unless the board configures another one.
+pub const DEFAULT_SPEED_HZ: u32 = 100_000;
+
+/// Longest time a slave may stretch the clock.
+const STRETCH_TIMEOUT_US: u32 = 25_000;
+
+/// The half period of SCL that the driver performs next.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Idle,
+    /// Repeated start: pull SCL low and release SDA.
+    StartLow,
+    /// Release SCL.
+    StartHigh,
+    /// Wait for SCL high, then pull SDA low.
+    StartCondition,
+    /// Pull SCL low and put the next bit on SDA.
+    BitLow,
+    /// Release SCL.
+    BitHigh,
+    /// Wait for SCL high, then sample SDA.
+    BitSample,
+    /// Pull SCL and SDA low.
+    StopLow,
+    /// Release SCL.
+    StopHigh,
+    /// Wait for SCL high, then release SDA.
+    StopCondition,
+    /// The bus free time after the stop condition has passed.
+    Done,
+}
+
+/// The byte being transferred.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Address { read: bool },
+    Write,
+    Read,
+}
+
+pub struct I2CBitBang<'a, P: gpio::Pin, A: Alarm<'a>> {
+    sda: &'a P,
+    scl: &'a P,
+    alarm: &'a A,
+    half_period_us: Cell<u32>,
+    step: Cell<Step>,
+    stage: Cell<Stage>,
+    stretched_us: Cell<u32>,
+    addr: Cell<u8>,
+    buffer: TakeCell<'static, [u8]>,
+    write_len: Cell<usize>,
+    read_len: Cell<usize>,
+    index: Cell<usize>,
+    bit: Cell<u8>,
+    shift: Cell<u8>,
+    status: Cell<Result<(), Error>>,
+    client: OptionalCell<&'a dyn I2CHwMasterClient>,
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> I2CBitBang<'a, P, A> {
+    pub fn new(sda: &'a P, scl: &'a P, alarm: &'a A) -> I2CBitBang<'a, P, A> {
+        let i2c = I2CBitBang {
+            sda,
+            scl,
+            alarm,
+            half_period_us: Cell::new(0),
+            step: Cell::new(Step::Idle),
+            stage: Cell::new(Stage::Address { read: false }),
+            stretched_us: Cell::new(0),
+            addr: Cell::new(0),
+            buffer: TakeCell::empty(),
+            write_len: Cell::new(0),
+            read_len: Cell::new(0),
+            index: Cell::new(0),
+            bit: Cell::new(0),
+            shift: Cell::new(0),
+            status: Cell::new(Ok(())),
+            client: OptionalCell::empty(),
+        };
+        i2c.set_speed_hz(DEFAULT_SPEED_HZ);
+        i2c
+    }
+
+    /// Set the SCL frequency. The bus never runs faster than `speed_hz`.
+    pub fn set_speed_hz(&self, speed_hz: u32) {
+        let speed_hz = speed_hz.max(1);
+        self.half_period_us
+            .set(((500_000 + speed_hz - 1) / speed_hz).max(1));
+    }
+
+    fn release(pin: &P) {
+        pin.disable_output();
+    }
+
+    fn pull_low(pin: &P) {
+        pin.clear();
+        pin.make_output();
+    }
+
+    fn start_transfer(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.step.get() != Step::Idle {
+            return Err((Error::Busy, data));
+        }
+        if write_len > data.len() || read_len > data.len() {
+            return Err((Error::NotSupported, data));
+        }
+        self.buffer.replace(data);
+        self.addr.set(addr);
+        self.write_len.set(write_len);
+        self.read_len.set(read_len);
+        self.stage.set(Stage::Address {
+            read: write_len == 0 && read_len > 0,
+        });
+        self.status.set(Ok(()));
+        // The bus is idle with both lines released, so the start condition
+        // begins with SCL already high.
+        self.step.set(Step::StartHigh);
+        self.stretched_us.set(0);
+        self.run();
+        Ok(())
+    }
+
+    /// Move to `step` after the next half period.
+    fn wait(&self, step: Step) {
+        self.step.set(step);
+        self.stretched_us.set(0);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_us(self.half_period_us.get()),
+        );
+    }
+
+    /// A slave is holding SCL low, check it again after a half period.
+    fn stretch(&self) {
+        let stretched_us = self.stretched_us.get() + self.half_period_us.get();
+        if stretched_us > STRETCH_TIMEOUT_US {
+            self.abort(Error::Busy);
+            return;
+        }
+        self.stretched_us.set(stretched_us);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_us(self.half_period_us.get()),
+        );
+    }
+
+    /// Whether the current bit leaves SDA released.
+    fn sda_released(&self) -> bool {
+        let bit = self.bit.get();
+        match self.stage.get() {
+            Stage::Address { .. } | Stage::Write if bit < 8 => {
+                self.shift.get() & (0x80 >> bit) != 0
+            }
+            // The slave acknowledges bytes we send.
+            Stage::Address { .. } | Stage::Write => true,
+            Stage::Read if bit < 8 => true,
+            // Acknowledge every byte we read except the last.
+            Stage::Read => self.index.get() + 1 >= self.read_len.get(),
+        }
+    }
+
+    fn load_byte(&self, byte: u8) {
+        self.shift.set(byte);
+        self.bit.set(0);
+        self.step.set(Step::BitLow);
+        self.run();
+    }
+
+    fn stop(&self) {
+        self.step.set(Step::StopLow);
+        self.run();
+    }
+
+    /// Give up the bus without a stop condition and report `error`.
+    fn abort(&self, error: Error) {
+        Self::release(self.sda);
+        Self::release(self.scl);
+        self.status.set(Err(error));
+        self.complete();
+    }
+
+    fn complete(&self) {
+        self.step.set(Step::Idle);
+        let status = self.status.get();
+        self.buffer.take().map(|buffer| {
+            self.client
+                .map(move |client| client.command_complete(buffer, status));
+        });
+    }
+
+    fn sample(&self, sda: bool) {
+        let bit = self.bit.get();
+        let stage = self.stage.get();
+        if bit < 8 {
+            if stage == Stage::Read {
+                self.shift.set(self.shift.get() << 1 | sda as u8);
+            } else if self.sda_released() && !sda {
+                self.abort(Error::ArbitrationLost);
+                return;
+            }
+            self.bit.set(bit + 1);
+            self.step.set(Step::BitLow);
+            self.run();
+            return;
+        }
+
+        match stage {
+            Stage::Address { .. } | Stage::Write if sda => {
+                self.status.set(Err(if stage == Stage::Write {
+                    Error::DataNak
+                } else {
+                    Error::AddressNak
+                }));
+                self.stop();
+                return;
+            }
+            Stage::Read => {
+                let index = self.index.get();
+                let byte = self.shift.get();
+                self.buffer.map(|buffer| buffer[index] = byte);
+                self.index.set(index + 1);
+            }
+            _ => {}
+        }
+        self.next_byte();
+    }
+
+    fn next_byte(&self) {
+        match self.stage.get() {
+            Stage::Address { read: false } => {
+                self.index.set(0);
+                self.stage.set(Stage::Write);
+                self.write_next();
+            }
+            Stage::Write => {
+                self.index.set(self.index.get() + 1);
+                self.write_next();
+            }
+            Stage::Address { read: true } => {
+                self.index.set(0);
+                self.stage.set(Stage::Read);
+                self.read_next();
+            }
+            Stage::Read => self.read_next(),
+        }
+    }
+
+    fn write_next(&self) {
+        let index = self.index.get();
+        if index < self.write_len.get() {
+            let byte = self.buffer.map_or(0, |buffer| buffer[index]);
+            self.load_byte(byte);
+        } else if self.read_len.get() > 0 {
+            self.stage.set(Stage::Address { read: true });
+            self.step.set(Step::StartLow);
+            self.run();
+        } else {
+            self.stop();
+        }
+    }
+
+    fn read_next(&self) {
+        if self.index.get() < self.read_len.get() {
+            self.load_byte(0);
+        } else {
+            self.stop();
+        }
+    }
+
+    /// Perform the current step.
+    fn run(&self) {
+        match self.step.get() {
+            Step::Idle => {}
+            Step::StartLow => {
+                Self::pull_low(self.scl);
+                Self::release(self.sda);
+                self.wait(Step::StartHigh);
+            }
+            Step::StartHigh => {
+                Self::release(self.scl);
+                self.wait(Step::StartCondition);
+            }
+            Step::StartCondition => {
+                if !self.scl.read() {
+                    self.stretch();
+                } else if !self.sda.read() {
+                    // Another master is using the bus.
+                    self.abort(Error::ArbitrationLost);
+                } else {
+                    Self::pull_low(self.sda);
+                    let read = self.stage.get() == Stage::Address { read: true };
+                    self.shift.set(self.addr.get() << 1 | read as u8);
+                    self.bit.set(0);
+                    self.wait(Step::BitLow);
+                }
+            }
+            Step::BitLow => {
+                Self::pull_low(self.scl);
+                if self.sda_released() {
+                    Self::release(self.sda);
+                } else {
+                    Self::pull_low(self.sda);
+                }
+                self.wait(Step::BitHigh);
+            }
+            Step::BitHigh => {
+                Self::release(self.scl);
+                self.wait(Step::BitSample);
+            }
+            Step::BitSample => {
+                if !self.scl.read() {
+                    self.stretch();
+                } else {
+                    self.sample(self.sda.read());
+                }
+            }
+            Step::StopLow => {
+                Self::pull_low(self.scl);
+                Self::pull_low(self.sda);
+                self.wait(Step::StopHigh);
+            }
+            Step::StopHigh => {
+                Self::release(self.scl);
+                self.wait(Step::StopCondition);
+            }
+            Step::StopCondition => {
+                if !self.scl.read() {
+                    self.stretch();
+                } else {
+                    Self::release(self.sda);
+                    self.wait(Step::Done);
+                }
+            }
+            Step::Done => self.complete(),
+        }
+    }
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> time::AlarmClient for I2CBitBang<'a, P, A> {
+    fn alarm(&self) {
+        self.run();
+    }
+}
+
+impl<'a, P: gpio::Pin, A: Alarm<'a>> I2CMaster<'a> for I2CBitBang<'a, P, A> {
+    fn set_master_client(&self, master_client: &'a dyn I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        for pin in [self.sda, self.scl] {
+            pin.set_floating_state(gpio::FloatingState::PullUp);
+            pin.make_input();
+            Self::release(pin);
+        }
+    }
+
+    fn disable(&self) {
+        for pin in [self.sda, self.scl] {
+            Self::release(pin);
+            pin.disable_input();
+        }
+    }
+
+    fn write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start_transfer(addr, data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start_transfer(addr, data, len, 0)
+    }
+
+    fn read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start_transfer(addr, buffer, 0, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin, PinModel};
+    use core::cell::RefCell;
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const SLAVE_ADDR: u8 = 0x42;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Event {
+        Start,
+        Stop,
+        /// A byte received and acknowledged by the slave.
+        Received(u8),
+        /// A byte sent by the slave, with whether the master acknowledged it.
+        Sent(u8, bool),
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum SlaveState {
+        Idle,
+        Address,
+        Write,
+        Read,
+    }
+
+    /// A register based I2C slave, with the lines as a wired-AND between it
+    /// and the master pins. The first byte written sets the register
+    /// pointer; following writes and reads access consecutive registers.
+    struct Bus {
+        master_sda_low: Cell<bool>,
+        master_scl_low: Cell<bool>,
+        slave_sda_low: Cell<bool>,
+        /// Half periods the slave holds SCL low for after each byte it
+        /// receives.
+        stretch: Cell<u32>,
+        stretching: Cell<u32>,
+        sda: Cell<bool>,
+        scl: Cell<bool>,
+        state: Cell<SlaveState>,
+        just_started: Cell<bool>,
+        bit: Cell<u8>,
+        shift: Cell<u8>,
+        first_write: Cell<bool>,
+        master_ack: Cell<bool>,
+        pointer: Cell<usize>,
+        registers: RefCell<[u8; 32]>,
+        events: RefCell<Vec<Event>>,
+    }
+
+    impl Bus {
+        fn new() -> Bus {
+            Bus {
+                master_sda_low: Cell::new(false),
+                master_scl_low: Cell::new(false),
+                slave_sda_low: Cell::new(false),
+                stretch: Cell::new(0),
+                stretching: Cell::new(0),
+                sda: Cell::new(true),
+                scl: Cell::new(true),
+                state: Cell::new(SlaveState::Idle),
+                just_started: Cell::new(false),
+                bit: Cell::new(0),
+                shift: Cell::new(0),
+                first_write: Cell::new(false),
+                master_ack: Cell::new(false),
+                pointer: Cell::new(0),
+                registers: RefCell::new([0; 32]),
+                events: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn sda(&self) -> bool {
+            !self.master_sda_low.get() && !self.slave_sda_low.get()
+        }
+
+        fn scl(&self) -> bool {
+            !self.master_scl_low.get() && self.stretching.get() == 0
+        }
+
+        /// One half period passes.
+        fn tick(&self) {
+            if self.stretching.get() > 0 {
+                self.stretching.set(self.stretching.get() - 1);
+                self.update();
+            }
+        }
+
+        fn tx_byte(&self) -> u8 {
+            self.registers.borrow()[self.pointer.get()]
+        }
+
+        fn update(&self) {
+            let (sda, scl) = (self.sda(), self.scl());
+            let (prev_sda, prev_scl) = (self.sda.get(), self.scl.get());
+            self.sda.set(sda);
+            self.scl.set(scl);
+
+            if scl && prev_scl && sda != prev_sda {
+                self.slave_sda_low.set(false);
+                if sda {
+                    self.events.borrow_mut().push(Event::Stop);
+                    self.state.set(SlaveState::Idle);
+                } else {
+                    self.events.borrow_mut().push(Event::Start);
+                    self.state.set(SlaveState::Address);
+                    self.just_started.set(true);
+                    self.bit.set(0);
+                }
+            } else if scl && !prev_scl {
+                self.rising_edge(sda);
+            } else if !scl && prev_scl {
+                self.falling_edge();
+            }
+            self.sda.set(self.sda());
+        }
+
+        fn rising_edge(&self, sda: bool) {
+            let bit = self.bit.get();
+            match self.state.get() {
+                SlaveState::Address | SlaveState::Write if bit < 8 => {
+                    self.shift.set(self.shift.get() << 1 | sda as u8)
+                }
+                SlaveState::Read if bit == 8 => self.master_ack.set(!sda),
+                _ => {}
+            }
+        }
+
+        fn falling_edge(&self) {
+            if self.just_started.get() {
+                self.just_started.set(false);
+                return;
+            }
+            let bit = self.bit.get() + 1;
+            self.bit.set(bit);
+            let byte = self.shift.get();
+            match self.state.get() {
+                SlaveState::Idle => {}
+                SlaveState::Address if bit == 8 => {
+                    if byte >> 1 == SLAVE_ADDR {
+                        self.events.borrow_mut().push(Event::Received(byte));
+                        self.slave_sda_low.set(true);
+                        self.stretching.set(self.stretch.get());
+                    } else {
+                        self.state.set(SlaveState::Idle);
+                    }
+                }
+                SlaveState::Address if bit == 9 => {
+                    self.slave_sda_low.set(false);
+                    self.bit.set(0);
+                    if byte & 1 == 1 {
+                        self.state.set(SlaveState::Read);
+                        self.slave_sda_low.set(self.tx_byte() & 0x80 == 0);
+                    } else {
+                        self.state.set(SlaveState::Write);
+                        self.first_write.set(true);
+                    }
+                }
+                SlaveState::Write if bit == 8 => {
+                    self.events.borrow_mut().push(Event::Received(byte));
+                    if self.first_write.get() {
+                        self.first_write.set(false);
+                        self.pointer.set(byte as usize);
+                    } else {
+                        self.registers.borrow_mut()[self.pointer.get()] = byte;
+                        self.pointer.set(self.pointer.get() + 1);
+                    }
+                    self.slave_sda_low.set(true);
+                    self.stretching.set(self.stretch.get());
+                }
+                SlaveState::Write if bit == 9 => {
+                    self.slave_sda_low.set(false);
+                    self.bit.set(0);
+                }
+                SlaveState::Read if bit < 8 => {
+                    self.slave_sda_low.set(self.tx_byte() & (0x80 >> bit) == 0);
+                }
+                SlaveState::Read if bit == 8 => self.slave_sda_low.set(false),
+                SlaveState::Read => {
+                    let ack = self.master_ack.get();
+                    self.events
+                        .borrow_mut()
+                        .push(Event::Sent(self.tx_byte(), ack));
+                    self.pointer.set(self.pointer.get() + 1);
+                    self.bit.set(0);
+                    if ack {
+                        self.slave_sda_low.set(self.tx_byte() & 0x80 == 0);
+                    } else {
+                        self.state.set(SlaveState::Idle);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    const SDA: usize = 0;
+    const SCL: usize = 1;
+
+    impl PinModel for Bus {
+        fn driven(&self, id: usize, output: bool, level: bool) {
+            let low = output && !level;
+            match id {
+                SCL => self.master_scl_low.set(low),
+                _ => self.master_sda_low.set(low),
+            }
+            self.update();
+        }
+
+        fn read(&self, id: usize) -> bool {
+            match id {
+                SCL => self.scl(),
+                _ => self.sda(),
+            }
+        }
+    }
+
+    struct Client {
+        result: Cell<Option<Result<(), Error>>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl I2CHwMasterClient for Client {
+        fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+            self.result.set(Some(status));
+            self.buffer.replace(buffer);
+        }
+    }
+
+    /// Deliver alarms until the transfer completes.
+    fn run(bus: &Bus, alarm: &MockAlarm<Freq1MHz>) {
+        for _ in 0..10_000 {
+            if !alarm.is_armed() {
+                return;
+            }
+            bus.tick();
+            alarm.fire();
+        }
+        panic!("transfer did not complete");
+    }
+
+    fn transfer(stretch: u32, addr: u8) -> (Bus, Option<Result<(), Error>>, [u8; 2]) {
+        let bus = Bus::new();
+        bus.stretch.set(stretch);
+        bus.registers.borrow_mut()[0x10] = 0xA5;
+        bus.registers.borrow_mut()[0x11] = 0x3C;
+        let result;
+        let mut read = [0; 2];
+        {
+            let sda = MockPin::new(true);
+            sda.attach(&bus, SDA);
+            let scl = MockPin::new(true);
+            scl.attach(&bus, SCL);
+            let alarm = MockAlarm::<Freq1MHz>::new();
+            let client = Client {
+                result: Cell::new(None),
+                buffer: TakeCell::empty(),
+            };
+            let i2c = I2CBitBang::new(&sda, &scl, &alarm);
+            alarm.set_alarm_client(&i2c);
+            i2c.set_master_client(&client);
+            i2c.enable();
+
+            let buffer: &'static mut [u8] = Box::leak(Box::new([0x10, 0]));
+            assert!(i2c.write_read(addr, buffer, 1, 2).is_ok());
+            run(&bus, &alarm);
+
+            result = client.result.get();
+            client.buffer.map(|buffer| read.copy_from_slice(buffer));
+            // The master releases both lines after the transfer.
+            assert!(!bus.master_sda_low.get() && !bus.master_scl_low.get());
+        }
+        (bus, result, read)
+    }
+
+    #[test]
+    fn write_then_read() {
+        let (bus, result, read) = transfer(0, SLAVE_ADDR);
+        assert_eq!(result, Some(Ok(())));
+        assert_eq!(read, [0xA5, 0x3C]);
+        assert_eq!(
+            *bus.events.borrow(),
+            [
+                Event::Start,
+                Event::Received(SLAVE_ADDR << 1),
+                Event::Received(0x10),
+                Event::Start,
+                Event::Received(SLAVE_ADDR << 1 | 1),
+                Event::Sent(0xA5, true),
+                Event::Sent(0x3C, false),
+                Event::Stop,
+            ]
+        );
+    }
+
+    #[test]
+    fn clock_stretching() {
+        let (bus, result, read) = transfer(5, SLAVE_ADDR);
+        assert_eq!(result, Some(Ok(())));
+        assert_eq!(read, [0xA5, 0x3C]);
+        assert_eq!(bus.events.borrow().len(), 8);
+
+        // A slave that never releases SCL times the transfer out.
+        let (_, result, _) = transfer(u32::MAX, SLAVE_ADDR);
+        assert_eq!(result, Some(Err(Error::Busy)));
+    }
+
+    #[test]
+    fn address_nak() {
+        let (bus, result, _) = transfer(0, SLAVE_ADDR + 1);
+        assert_eq!(result, Some(Err(Error::AddressNak)));
+        assert_eq!(*bus.events.borrow(), [Event::Start, Event::Stop]);
+    }
+}
@@ -0,0 +1,1047 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TDK InvenSense ICM-20649 high-g accelerometer and
+//! gyroscope, over SPI.
+//!
+//! <https://invensense.tdk.com/wp-content/uploads/2021/10/DS-000192-ICM-20649-v1.6.pdf>
+//!
+//! The ICM-20649 measures up to ±30g and ±4000 dps, for vibration monitoring
+//! in power tools and industrial equipment. The driver uses the widest
+//! ranges, with samples written to the 512 byte FIFO at the highest rate in
+//! streaming mode, where new samples replace the oldest. Every sample in the
+//! FIFO is the accelerometer X, Y and Z followed by the gyroscope X, Y and Z,
+//! the same layout as the output data registers, and is read in one burst.
+//! A NineDof reading drains the FIFO and reports the latest sample, in mg for
+//! the accelerometer and mdps for the gyroscope. If the FIFO may have
+//! wrapped and lost the sample boundaries, it is reset and the output data
+//! registers are read instead.
+//!
+//! `NineDof::calibrate_gyroscope` averages gyroscope samples from the FIFO
+//! taken at rest, and subtracts the result from later readings.
+//! `NineDof::run_gyroscope_self_test` switches the gyroscope to ±500 dps
+//! and averages [SELF_TEST_SAMPLES] samples from the FIFO without and with
+//! the self-test stimulus, after letting it settle for
+//! [SELF_TEST_DISCARD_SAMPLES] samples. The response is compared with the
+//! factory codes in `SELF_TEST_X_GYRO` to `SELF_TEST_Z_GYRO`, scaled to
+//! ±500 dps. Streaming then carries on as before. If the self-test fails
+//! part way through, the configuration is lost and readings fail with `OFF`
+//! until [Icm20649::start_streaming] is called again.
+//!
+//! The registers are split in four banks, selected with `REG_BANK_SEL` at
+//! the same address in every bank. The driver remembers the selected bank
+//! and only switches when a register is in another one.
+//!
+//! Shock detection puts the sensor to sleep instead, with the gyroscope off
+//! and the accelerometer waking at 0.27 Hz to compare against the previous
+//! sample. A change larger than the threshold raises the wake-on-motion
+//! interrupt, which can wake the system, and is reported to the
+//! [ShockClient].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icm20649 = components::icm20649::Icm20649Component::new(
+//!     mux_spi,
+//!     nrf52840::gpio::Pin::P1_08,
+//!     Some(&nrf52840_peripherals.gpio_port[ICM20649_INT]),
+//! )
+//! .finalize(components::icm20649_component_static!(nrf52840::spi::SPIM));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(icm20649));
+//! ```
+
+use crate::gyro_calibration::{invensense_self_test_passed, GyroCalibration};
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::spi::{self, SpiMasterClient, SpiMasterDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Registers in every bank.
+const REG_BANK_SEL: u8 = 0x7F;
+
+/// Registers in bank 0.
+const REG_WHO_AM_I: u8 = 0x00;
+const REG_USER_CTRL: u8 = 0x03;
+const REG_LP_CONFIG: u8 = 0x05;
+const REG_PWR_MGMT_1: u8 = 0x06;
+const REG_PWR_MGMT_2: u8 = 0x07;
+const REG_INT_ENABLE: u8 = 0x10;
+const REG_INT_STATUS: u8 = 0x19;
+const REG_ACCEL_XOUT_H: u8 = 0x2D;
+const REG_FIFO_EN_2: u8 = 0x67;
+const REG_FIFO_RST: u8 = 0x68;
+const REG_FIFO_MODE: u8 = 0x69;
+const REG_FIFO_COUNTH: u8 = 0x70;
+const REG_FIFO_R_W: u8 = 0x72;
+
+/// Registers in bank 1. `SELF_TEST_X_GYRO` is followed by `SELF_TEST_Y_GYRO`
+/// and `SELF_TEST_Z_GYRO`.
+const REG_SELF_TEST_X_GYRO: u8 = 0x02;
+
+/// Registers in bank 2.
+const REG_GYRO_SMPLRT_DIV: u8 = 0x00;
+const REG_GYRO_CONFIG_1: u8 = 0x01;
+const REG_GYRO_CONFIG_2: u8 = 0x02;
+const REG_ACCEL_SMPLRT_DIV_1: u8 = 0x10;
+const REG_ACCEL_SMPLRT_DIV_2: u8 = 0x11;
+const REG_ACCEL_INTEL_CTRL: u8 = 0x12;
+const REG_ACCEL_WOM_THR: u8 = 0x13;
+const REG_ACCEL_CONFIG: u8 = 0x14;
+
+const WHO_AM_I: u8 = 0xE1;
+
+const USER_CTRL_FIFO_EN: u8 = 1 << 6;
+/// The I2C interface has to be disabled when using SPI.
+const USER_CTRL_I2C_IF_DIS: u8 = 1 << 4;
+const LP_CONFIG_ACCEL_CYCLE: u8 = 1 << 5;
+const PWR_MGMT_1_LP_EN: u8 = 1 << 5;
+const PWR_MGMT_1_CLKSEL_AUTO: u8 = 0x01;
+const PWR_MGMT_2_DISABLE_GYRO: u8 = 0x07;
+const INT_WOM: u8 = 1 << 3;
+/// Accelerometer and gyroscope X, Y and Z in the FIFO.
+const FIFO_EN_2_ACCEL_GYRO: u8 = 0x1E;
+const FIFO_RST_ALL: u8 = 0x1F;
+const FIFO_MODE_STREAM: u8 = 0x00;
+/// Wake-on-motion compares each sample with the previous one.
+const ACCEL_INTEL_EN_COMPARE_PREVIOUS: u8 = 0x03;
+/// ±4000 dps, with the low pass filter.
+const GYRO_CONFIG_4000DPS: u8 = 3 << 1 | 1;
+/// ±500 dps, with the low pass filter, for the self-test.
+const GYRO_CONFIG_500DPS: u8 = 1;
+/// The self-test stimulus on every axis.
+const GYRO_CONFIG_2_SELF_TEST: u8 = 0x38;
+/// ±30g, with the low pass filter.
+const ACCEL_CONFIG_30G: u8 = 3 << 1 | 1;
+/// The slowest accelerometer rate, 1125 Hz / 4096 = 0.27 Hz.
+const ACCEL_SMPLRT_DIV_SLOWEST: u16 = 4095;
+
+/// The sensitivity of the accelerometer at ±30g.
+const ACCEL_LSB_PER_G: i32 = 1024;
+/// The sensitivity of the gyroscope at ±4000 dps, 8.2 LSB/dps.
+const GYRO_LSB_PER_10DPS: i32 = 82;
+/// The sensitivity of the gyroscope at ±500 dps, 65.5 LSB/dps.
+const SELF_TEST_LSB_PER_10DPS: i32 = 655;
+/// The wake-on-motion threshold is in steps of 4 mg.
+const WOM_THRESHOLD_MG_PER_LSB: u32 = 4;
+
+/// Bytes in the FIFO.
+const FIFO_SIZE: usize = 512;
+/// Bytes in a sample: the accelerometer then the gyroscope, each X, Y and Z.
+pub const SAMPLE_SIZE: usize = 12;
+/// Size of the transfer buffers: a register address and a sample.
+pub const BUF_LEN: usize = 1 + SAMPLE_SIZE;
+
+const READ: u8 = 0x80;
+
+/// Gyroscope samples averaged with and without the self-test stimulus.
+pub const SELF_TEST_SAMPLES: usize = 200;
+/// Gyroscope samples skipped while the self-test stimulus settles, about
+/// 20 ms at the highest rate.
+pub const SELF_TEST_DISCARD_SAMPLES: usize = 20;
+
+/// The register writes to stream samples into the FIFO, as bank, register
+/// and value.
+const STREAMING: [(u8, u8, u8); 16] = [
+    (0, REG_PWR_MGMT_1, PWR_MGMT_1_CLKSEL_AUTO),
+    (0, REG_USER_CTRL, USER_CTRL_I2C_IF_DIS),
+    (0, REG_PWR_MGMT_2, 0),
+    (0, REG_LP_CONFIG, 0),
+    (0, REG_INT_ENABLE, 0),
+    (2, REG_GYRO_SMPLRT_DIV, 0),
+    (2, REG_GYRO_CONFIG_1, GYRO_CONFIG_4000DPS),
+    (2, REG_ACCEL_SMPLRT_DIV_1, 0),
+    (2, REG_ACCEL_SMPLRT_DIV_2, 0),
+    (2, REG_ACCEL_CONFIG, ACCEL_CONFIG_30G),
+    (2, REG_ACCEL_INTEL_CTRL, 0),
+    (0, REG_FIFO_EN_2, FIFO_EN_2_ACCEL_GYRO),
+    (0, REG_FIFO_MODE, FIFO_MODE_STREAM),
+    (0, REG_FIFO_RST, FIFO_RST_ALL),
+    (0, REG_FIFO_RST, 0),
+    (0, REG_USER_CTRL, USER_CTRL_FIFO_EN | USER_CTRL_I2C_IF_DIS),
+];
+
+/// The register writes to reset the FIFO.
+const FIFO_RESET: [(u8, u8, u8); 2] = [(0, REG_FIFO_RST, FIFO_RST_ALL), (0, REG_FIFO_RST, 0)];
+
+/// A gyroscope calibration or self-test.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Procedure {
+    Calibrate,
+    SelfTest,
+}
+
+/// A step of a gyroscope calibration or self-test.
+#[derive(Clone, Copy)]
+enum ProcedureStep {
+    /// Read the factory self-test codes.
+    ReadSelfTestCodes,
+    /// Write a register, as bank, register and value.
+    Write(u8, u8, u8),
+    /// Collect gyroscope samples from the FIFO until the procedure has
+    /// enough.
+    Collect,
+}
+
+const CALIBRATE: [ProcedureStep; 3] = [
+    ProcedureStep::Write(0, REG_FIFO_RST, FIFO_RST_ALL),
+    ProcedureStep::Write(0, REG_FIFO_RST, 0),
+    ProcedureStep::Collect,
+];
+
+/// The FIFO is reset after every change, so the samples collected are all
+/// taken with the new configuration.
+const SELF_TEST: [ProcedureStep; 13] = [
+    ProcedureStep::ReadSelfTestCodes,
+    ProcedureStep::Write(2, REG_GYRO_CONFIG_1, GYRO_CONFIG_500DPS),
+    ProcedureStep::Write(0, REG_FIFO_RST, FIFO_RST_ALL),
+    ProcedureStep::Write(0, REG_FIFO_RST, 0),
+    ProcedureStep::Collect,
+    ProcedureStep::Write(2, REG_GYRO_CONFIG_2, GYRO_CONFIG_2_SELF_TEST),
+    ProcedureStep::Write(0, REG_FIFO_RST, FIFO_RST_ALL),
+    ProcedureStep::Write(0, REG_FIFO_RST, 0),
+    ProcedureStep::Collect,
+    ProcedureStep::Write(2, REG_GYRO_CONFIG_2, 0),
+    ProcedureStep::Write(2, REG_GYRO_CONFIG_1, GYRO_CONFIG_4000DPS),
+    ProcedureStep::Write(0, REG_FIFO_RST, FIFO_RST_ALL),
+    ProcedureStep::Write(0, REG_FIFO_RST, 0),
+];
+
+impl Procedure {
+    fn steps(&self) -> &'static [ProcedureStep] {
+        match self {
+            Procedure::Calibrate => &CALIBRATE,
+            Procedure::SelfTest => &SELF_TEST,
+        }
+    }
+}
+
+/// The register writes to sleep until a shock larger than `threshold_mg`.
+fn shock_detect_writes(threshold_mg: u32) -> [(u8, u8, u8); 11] {
+    let threshold = (threshold_mg / WOM_THRESHOLD_MG_PER_LSB).min(u8::MAX as u32) as u8;
+    [
+        (0, REG_USER_CTRL, USER_CTRL_I2C_IF_DIS),
+        (0, REG_FIFO_EN_2, 0),
+        (0, REG_PWR_MGMT_2, PWR_MGMT_2_DISABLE_GYRO),
+        (2, REG_ACCEL_CONFIG, ACCEL_CONFIG_30G),
+        (
+            2,
+            REG_ACCEL_SMPLRT_DIV_1,
+            (ACCEL_SMPLRT_DIV_SLOWEST >> 8) as u8,
+        ),
+        (2, REG_ACCEL_SMPLRT_DIV_2, ACCEL_SMPLRT_DIV_SLOWEST as u8),
+        (2, REG_ACCEL_INTEL_CTRL, ACCEL_INTEL_EN_COMPARE_PREVIOUS),
+        (2, REG_ACCEL_WOM_THR, threshold),
+        (0, REG_INT_ENABLE, INT_WOM),
+        (0, REG_LP_CONFIG, LP_CONFIG_ACCEL_CYCLE),
+        (0, REG_PWR_MGMT_1, PWR_MGMT_1_LP_EN | PWR_MGMT_1_CLKSEL_AUTO),
+    ]
+}
+
+pub trait ShockClient {
+    /// The sensor woke on a shock.
+    fn shock(&self);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    Off,
+    Streaming,
+    ShockDetect { threshold_mg: u32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Sensor {
+    Accelerometer,
+    Gyroscope,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    CheckIdentity,
+    /// Writing the registers of the mode, up to `step`.
+    Configure {
+        step: usize,
+    },
+    ReadFifoCount(Sensor),
+    /// Draining `samples` samples from the FIFO, keeping the last.
+    ReadFifo {
+        sensor: Sensor,
+        samples: usize,
+    },
+    /// Resetting the FIFO after it wrapped, up to `step`.
+    ResetFifo {
+        sensor: Sensor,
+        step: usize,
+    },
+    ReadDataRegisters(Sensor),
+    ReadInterruptStatus,
+    /// Running `step` of a gyroscope calibration or self-test.
+    Procedure {
+        procedure: Procedure,
+        step: usize,
+    },
+    /// Reading `samples` samples from the FIFO in `step` of a gyroscope
+    /// calibration or self-test.
+    ProcedureFifo {
+        procedure: Procedure,
+        step: usize,
+        samples: usize,
+    },
+}
+
+/// The X, Y and Z readings at `offset` in a sample.
+fn raw_axes(data: &[u8], offset: usize) -> [i16; 3] {
+    let axis = |i: usize| i16::from_be_bytes([data[offset + 2 * i], data[offset + 2 * i + 1]]);
+    [axis(0), axis(1), axis(2)]
+}
+
+/// Convert raw gyroscope output at ±4000 dps to mdps.
+fn gyroscope_mdps(raw: [i16; 3]) -> [i32; 3] {
+    raw.map(|raw| raw as i32 * 10_000 / GYRO_LSB_PER_10DPS)
+}
+
+/// A transfer with the sensor.
+enum Transfer {
+    Write(u8, u8, u8),
+    Read(u8, u8, usize),
+}
+
+pub struct Icm20649<'a, S: SpiMasterDevice<'a>> {
+    spi: &'a S,
+    interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+    txbuffer: TakeCell<'static, [u8]>,
+    rxbuffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    mode: Cell<Mode>,
+    /// The selected register bank, if known.
+    bank: OptionalCell<u8>,
+    /// Whether the transfer in progress selects the bank.
+    selecting_bank: Cell<bool>,
+    interrupt_pending: Cell<bool>,
+    calibration: GyroCalibration,
+    self_test_codes: Cell<[u8; 3]>,
+    self_test_count: Cell<usize>,
+    self_test_sum: Cell<[i32; 3]>,
+    /// Average without the self-test stimulus, once it has been measured.
+    self_test_baseline: OptionalCell<[i32; 3]>,
+    self_test_passed: Cell<bool>,
+    nine_dof_client: OptionalCell<&'a dyn NineDofClient>,
+    shock_client: OptionalCell<&'a dyn ShockClient>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>> Icm20649<'a, S> {
+    pub fn new(
+        spi: &'a S,
+        interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+        txbuffer: &'static mut [u8; BUF_LEN],
+        rxbuffer: &'static mut [u8; BUF_LEN],
+    ) -> Icm20649<'a, S> {
+        Icm20649 {
+            spi,
+            interrupt_pin,
+            txbuffer: TakeCell::new(txbuffer),
+            rxbuffer: TakeCell::new(rxbuffer),
+            state: Cell::new(State::Idle),
+            mode: Cell::new(Mode::Off),
+            bank: OptionalCell::empty(),
+            selecting_bank: Cell::new(false),
+            interrupt_pending: Cell::new(false),
+            calibration: GyroCalibration::new(),
+            self_test_codes: Cell::new([0; 3]),
+            self_test_count: Cell::new(0),
+            self_test_sum: Cell::new([0; 3]),
+            self_test_baseline: OptionalCell::empty(),
+            self_test_passed: Cell::new(false),
+            nine_dof_client: OptionalCell::empty(),
+            shock_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_shock_client(&self, client: &'a dyn ShockClient) {
+        self.shock_client.set(client);
+    }
+
+    pub fn configure(&self) -> Result<(), ErrorCode> {
+        self.spi.configure(
+            spi::ClockPolarity::IdleHigh,
+            spi::ClockPhase::SampleTrailing,
+            7_000_000,
+        )
+    }
+
+    /// Check the identity of the sensor and stream samples into the FIFO.
+    pub fn start_streaming(&self) -> Result<(), ErrorCode> {
+        self.set_mode(Mode::Streaming)
+    }
+
+    /// Sleep until the acceleration changes by more than `threshold_mg`,
+    /// up to 1020 mg, between two samples.
+    pub fn shock_detect(&self, threshold_mg: u32) -> Result<(), ErrorCode> {
+        if self.interrupt_pin.is_none() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.set_mode(Mode::ShockDetect { threshold_mg })
+    }
+
+    fn set_mode(&self, mode: Mode) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.interrupt_pin.map(|pin| pin.disable_interrupts());
+        let state = if self.mode.get() == Mode::Off {
+            State::CheckIdentity
+        } else {
+            State::Configure { step: 0 }
+        };
+        self.mode.set(mode);
+        self.start(state).map_err(|e| {
+            self.mode.set(Mode::Off);
+            e
+        })
+    }
+
+    fn read(&self, sensor: Sensor) -> Result<(), ErrorCode> {
+        if self.mode.get() != Mode::Streaming {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.start(State::ReadFifoCount(sensor))
+    }
+
+    /// Start the gyroscope self-test. Completion is reported through the
+    /// NineDof client with `1` if the test passed and `0` otherwise.
+    pub fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        self.start_procedure(Procedure::SelfTest)
+    }
+
+    /// Start a zero-rate offset calibration over `samples` gyroscope
+    /// readings. The device must be kept still until the NineDof client is
+    /// called with the measured offset.
+    pub fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        self.calibration.start(samples)?;
+        self.start_procedure(Procedure::Calibrate).map_err(|e| {
+            self.calibration.cancel();
+            e
+        })
+    }
+
+    fn start_procedure(&self, procedure: Procedure) -> Result<(), ErrorCode> {
+        if self.mode.get() != Mode::Streaming {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.start(State::Procedure { procedure, step: 0 })
+    }
+
+    /// Add a gyroscope sample to `procedure`. Returns whether it has enough.
+    fn add_procedure_sample(&self, procedure: Procedure, raw: [i16; 3]) -> bool {
+        if procedure == Procedure::Calibrate {
+            return self.calibration.add_sample(raw);
+        }
+
+        let count = self.self_test_count.get() + 1;
+        self.self_test_count.set(count);
+        if count > SELF_TEST_DISCARD_SAMPLES {
+            let mut sum = self.self_test_sum.get();
+            for axis in 0..3 {
+                sum[axis] += raw[axis] as i32;
+            }
+            self.self_test_sum.set(sum);
+        }
+        if count < SELF_TEST_DISCARD_SAMPLES + SELF_TEST_SAMPLES {
+            return false;
+        }
+
+        let average = self
+            .self_test_sum
+            .get()
+            .map(|sum| sum / SELF_TEST_SAMPLES as i32);
+        self.self_test_count.set(0);
+        self.self_test_sum.set([0; 3]);
+        match self.self_test_baseline.take() {
+            None => self.self_test_baseline.set(average),
+            Some(baseline) => self.self_test_passed.set(invensense_self_test_passed(
+                baseline,
+                average,
+                self.self_test_codes.get(),
+                SELF_TEST_LSB_PER_10DPS,
+            )),
+        }
+        true
+    }
+
+    /// Move to `state` and start its transfer, or finish if it has none.
+    fn start(&self, state: State) -> Result<(), ErrorCode> {
+        self.state.set(state);
+        let transfer = match self.transfer() {
+            Some(transfer) => transfer,
+            None => {
+                self.done(Ok(()));
+                return Ok(());
+            }
+        };
+        let bank = match transfer {
+            Transfer::Write(bank, _, _) | Transfer::Read(bank, _, _) => bank,
+        };
+        let (tx, rx) = match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(tx), Some(rx)) => (tx, rx),
+            (tx, rx) => {
+                tx.map(|tx| self.txbuffer.replace(tx));
+                rx.map(|rx| self.rxbuffer.replace(rx));
+                self.state.set(State::Idle);
+                return Err(ErrorCode::BUSY);
+            }
+        };
+        let len = if !self.bank.contains(&bank) {
+            self.selecting_bank.set(true);
+            self.bank.set(bank);
+            tx[0] = REG_BANK_SEL;
+            tx[1] = bank << 4;
+            2
+        } else {
+            match transfer {
+                Transfer::Write(_, register, value) => {
+                    tx[0] = register;
+                    tx[1] = value;
+                    2
+                }
+                Transfer::Read(_, register, len) => {
+                    tx[0] = register | READ;
+                    tx[1..=len].fill(0);
+                    1 + len
+                }
+            }
+        };
+        self.spi
+            .read_write_bytes(tx, Some(rx), len)
+            .map_err(|(e, tx, rx)| {
+                self.txbuffer.replace(tx);
+                rx.map(|rx| self.rxbuffer.replace(rx));
+                // The bank may or may not have been selected.
+                self.bank.clear();
+                self.selecting_bank.set(false);
+                self.state.set(State::Idle);
+                e
+            })
+    }
+
+    /// The transfer for the current state.
+    fn transfer(&self) -> Option<Transfer> {
+        match self.state.get() {
+            State::Idle => None,
+            State::CheckIdentity => Some(Transfer::Read(0, REG_WHO_AM_I, 1)),
+            State::Configure { step } => {
+                let (bank, register, value) = match self.mode.get() {
+                    Mode::Off => return None,
+                    Mode::Streaming => *STREAMING.get(step)?,
+                    Mode::ShockDetect { threshold_mg } => {
+                        *shock_detect_writes(threshold_mg).get(step)?
+                    }
+                };
+                Some(Transfer::Write(bank, register, value))
+            }
+            State::ReadFifoCount(_) => Some(Transfer::Read(0, REG_FIFO_COUNTH, 2)),
+            State::ReadFifo { .. } => Some(Transfer::Read(0, REG_FIFO_R_W, SAMPLE_SIZE)),
+            State::ResetFifo { step, .. } => {
+                let (bank, register, value) = *FIFO_RESET.get(step)?;
+                Some(Transfer::Write(bank, register, value))
+            }
+            State::ReadDataRegisters(_) => Some(Transfer::Read(0, REG_ACCEL_XOUT_H, SAMPLE_SIZE)),
+            State::ReadInterruptStatus => Some(Transfer::Read(0, REG_INT_STATUS, 1)),
+            State::Procedure { procedure, step } => match *procedure.steps().get(step)? {
+                ProcedureStep::ReadSelfTestCodes => {
+                    Some(Transfer::Read(1, REG_SELF_TEST_X_GYRO, 3))
+                }
+                ProcedureStep::Write(bank, register, value) => {
+                    Some(Transfer::Write(bank, register, value))
+                }
+                ProcedureStep::Collect => Some(Transfer::Read(0, REG_FIFO_COUNTH, 2)),
+            },
+            State::ProcedureFifo { .. } => Some(Transfer::Read(0, REG_FIFO_R_W, SAMPLE_SIZE)),
+        }
+    }
+
+    /// Complete the operation in progress.
+    fn done(&self, result: Result<(), ErrorCode>) {
+        let state = self.state.replace(State::Idle);
+        match state {
+            State::CheckIdentity | State::Configure { .. } => {
+                if result.is_err() {
+                    self.mode.set(Mode::Off);
+                } else if let Mode::ShockDetect { .. } = self.mode.get() {
+                    self.interrupt_pin.map(|pin| {
+                        pin.make_input();
+                        pin.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+                    });
+                }
+            }
+            State::ReadFifoCount(_)
+            | State::ReadFifo { .. }
+            | State::ResetFifo { .. }
+            | State::ReadDataRegisters(_) => {
+                if result.is_err() {
+                    self.nine_dof_client.map(|client| client.callback(0, 0, 0));
+                }
+            }
+            State::Procedure { procedure, .. } | State::ProcedureFifo { procedure, .. } => {
+                let (x, y, z) = match (procedure, result) {
+                    (Procedure::Calibrate, Ok(())) => {
+                        let [x, y, z] = gyroscope_mdps(self.calibration.bias());
+                        (x as usize, y as usize, z as usize)
+                    }
+                    (Procedure::SelfTest, Ok(())) => (self.self_test_passed.get() as usize, 0, 0),
+                    (Procedure::Calibrate, Err(_)) => {
+                        self.calibration.cancel();
+                        (0, 0, 0)
+                    }
+                    (Procedure::SelfTest, Err(_)) => {
+                        // The gyroscope may still be configured for the
+                        // self-test.
+                        self.mode.set(Mode::Off);
+                        (0, 0, 0)
+                    }
+                };
+                self.nine_dof_client.map(|client| client.callback(x, y, z));
+            }
+            State::ReadInterruptStatus | State::Idle => {}
+        }
+        if self.state.get() == State::Idle && self.interrupt_pending.take() {
+            let _ = self.start(State::ReadInterruptStatus);
+        }
+    }
+
+    /// Report the sample at the start of `data` to the NineDof client.
+    fn report(&self, sensor: Sensor, data: &[u8]) {
+        let [x, y, z] = match sensor {
+            Sensor::Accelerometer => {
+                raw_axes(data, 0).map(|raw| raw as i32 * 1000 / ACCEL_LSB_PER_G)
+            }
+            Sensor::Gyroscope => gyroscope_mdps(self.calibration.correct(raw_axes(data, 6))),
+        };
+        self.nine_dof_client
+            .map(|client| client.callback(x as usize, y as usize, z as usize));
+    }
+
+    /// Handle the data read in the current state. Returns the next state.
+    fn received(&self, data: &[u8]) -> Result<State, ErrorCode> {
+        Ok(match self.state.get() {
+            State::CheckIdentity => {
+                if data[0] != WHO_AM_I {
+                    return Err(ErrorCode::NODEVICE);
+                }
+                State::Configure { step: 0 }
+            }
+            State::Configure { step } => State::Configure { step: step + 1 },
+            State::ReadFifoCount(sensor) => {
+                let count = u16::from_be_bytes([data[0] & 0x1F, data[1]]) as usize;
+                if count > FIFO_SIZE - SAMPLE_SIZE {
+                    // New samples may have overwritten part of the oldest.
+                    State::ResetFifo { sensor, step: 0 }
+                } else if count < SAMPLE_SIZE {
+                    State::ReadDataRegisters(sensor)
+                } else {
+                    State::ReadFifo {
+                        sensor,
+                        samples: count / SAMPLE_SIZE,
+                    }
+                }
+            }
+            State::ReadFifo { sensor, samples } => {
+                if samples > 1 {
+                    State::ReadFifo {
+                        sensor,
+                        samples: samples - 1,
+                    }
+                } else {
+                    self.report(sensor, data);
+                    State::Idle
+                }
+            }
+            State::ResetFifo { sensor, step } => {
+                if step + 1 < FIFO_RESET.len() {
+                    State::ResetFifo {
+                        sensor,
+                        step: step + 1,
+                    }
+                } else {
+                    State::ReadDataRegisters(sensor)
+                }
+            }
+            State::ReadDataRegisters(sensor) => {
+                self.report(sensor, data);
+                State::Idle
+            }
+            State::ReadInterruptStatus => {
+                if data[0] & INT_WOM != 0 {
+                    self.shock_client.map(|client| client.shock());
+                }
+                State::Idle
+            }
+            State::Procedure { procedure, step } => {
+                let next = State::Procedure {
+                    procedure,
+                    step: step + 1,
+                };
+                match procedure.steps()[step] {
+                    ProcedureStep::ReadSelfTestCodes => {
+                        self.self_test_codes.set([data[0], data[1], data[2]]);
+                        self.self_test_count.set(0);
+                        self.self_test_sum.set([0; 3]);
+                        self.self_test_baseline.clear();
+                        next
+                    }
+                    ProcedureStep::Write(..) => next,
+                    ProcedureStep::Collect => {
+                        let count = u16::from_be_bytes([data[0] & 0x1F, data[1]]) as usize;
+                        if count > FIFO_SIZE - SAMPLE_SIZE {
+                            // The samples can no longer be told apart.
+                            return Err(ErrorCode::FAIL);
+                        } else if count < SAMPLE_SIZE {
+                            // Wait for the next sample.
+                            State::Procedure { procedure, step }
+                        } else {
+                            State::ProcedureFifo {
+                                procedure,
+                                step,
+                                samples: count / SAMPLE_SIZE,
+                            }
+                        }
+                    }
+                }
+            }
+            State::ProcedureFifo {
+                procedure,
+                step,
+                samples,
+            } => {
+                if self.add_procedure_sample(procedure, raw_axes(data, 6)) {
+                    State::Procedure {
+                        procedure,
+                        step: step + 1,
+                    }
+                } else if samples > 1 {
+                    State::ProcedureFifo {
+                        procedure,
+                        step,
+                        samples: samples - 1,
+                    }
+                } else {
+                    State::Procedure { procedure, step }
+                }
+            }
+            State::Idle => State::Idle,
+        })
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> SpiMasterClient for Icm20649<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let next = if self.selecting_bank.take() {
+            status.map(|()| self.state.get())
+        } else {
+            status.and_then(|()| {
+                read_buffer
+                    .as_ref()
+                    .map_or(Err(ErrorCode::FAIL), |rx| self.received(&rx[1..]))
+            })
+        };
+        self.txbuffer.replace(write_buffer);
+        read_buffer.map(|rx| self.rxbuffer.replace(rx));
+
+        match next {
+            Ok(State::Idle) => self.done(Ok(())),
+            Ok(state) => {
+                if let Err(e) = self.start(state) {
+                    self.state.set(state);
+                    self.done(Err(e));
+                }
+            }
+            Err(e) => {
+                if status.is_err() {
+                    self.bank.clear();
+                }
+                self.done(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> gpio::Client for Icm20649<'a, S> {
+    fn fired(&self) {
+        if self.state.get() == State::Idle {
+            let _ = self.start(State::ReadInterruptStatus);
+        } else {
+            self.interrupt_pending.set(true);
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> NineDof<'a> for Icm20649<'a, S> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.nine_dof_client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        self.read(Sensor::Accelerometer)
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        self.read(Sensor::Gyroscope)
+    }
+
+    fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        self.run_gyroscope_self_test()
+    }
+
+    fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        self.calibrate_gyroscope(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockSpi;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Complete the transfer in progress, reading `data` after the register
+    /// address.
+    fn complete(spi: &MockSpi, data: &[u8]) {
+        spi.complete_with(|_, rx| rx[1..1 + data.len()].copy_from_slice(data));
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        values: Cell<Option<(isize, isize, isize)>>,
+    }
+
+    impl NineDofClient for MockClient {
+        fn callback(&self, x: usize, y: usize, z: usize) {
+            self.values.set(Some((x as isize, y as isize, z as isize)));
+        }
+    }
+
+    /// A sample with the accelerometer and gyroscope readings.
+    fn sample(accel: [i16; 3], gyro: [i16; 3]) -> Vec<u8> {
+        accel
+            .iter()
+            .chain(gyro.iter())
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    type Device = Icm20649<'static, MockSpi<'static>>;
+
+    fn setup() -> (
+        &'static MockSpi<'static>,
+        &'static Device,
+        &'static MockClient,
+    ) {
+        let spi: &'static MockSpi = Box::leak(Box::default());
+        let client = Box::leak(Box::new(MockClient::default()));
+        let icm20649 = Box::leak(Box::new(Icm20649::new(
+            spi,
+            None,
+            Box::leak(Box::new([0; BUF_LEN])),
+            Box::leak(Box::new([0; BUF_LEN])),
+        )));
+        spi.set_client(icm20649);
+        icm20649.set_client(client);
+        (spi, icm20649, client)
+    }
+
+    fn start_streaming(spi: &MockSpi, icm20649: &Device) {
+        assert_eq!(icm20649.start_streaming(), Ok(()));
+        complete(spi, &[]);
+        complete(spi, &[WHO_AM_I]);
+        while spi.busy() {
+            spi.complete(&[]);
+        }
+    }
+
+    /// Answer transfers until the sensor is idle, with the factory
+    /// self-test `codes`, ten samples in the FIFO at a time, and gyroscope
+    /// readings of `gyro` with the self-test stimulus off or on. Returns the
+    /// registers written, other than the bank.
+    fn run_procedure(
+        spi: &MockSpi,
+        codes: [u8; 3],
+        gyro: impl Fn(bool) -> [i16; 3],
+    ) -> Vec<(u8, u8)> {
+        let stimulus = Cell::new(false);
+        let mut writes = Vec::new();
+        while spi.busy() {
+            spi.complete_with(|tx, rx| match tx[0] {
+                REG_BANK_SEL => {}
+                register if register & READ == 0 => {
+                    if register == REG_GYRO_CONFIG_2 {
+                        stimulus.set(tx[1] == GYRO_CONFIG_2_SELF_TEST);
+                    }
+                    writes.push((register, tx[1]));
+                }
+                register => {
+                    let data = match register & !READ {
+                        REG_SELF_TEST_X_GYRO => codes.to_vec(),
+                        REG_FIFO_COUNTH => (10 * SAMPLE_SIZE as u16).to_be_bytes().to_vec(),
+                        REG_FIFO_R_W => sample([0; 3], gyro(stimulus.get())),
+                        _ => panic!("unexpected read of {:#x}", register),
+                    };
+                    rx[1..1 + data.len()].copy_from_slice(&data);
+                }
+            });
+        }
+        writes
+    }
+
+    #[test]
+    fn switches_banks_and_scales_fifo_samples() {
+        let (spi, icm20649, client) = setup();
+
+        assert_eq!(icm20649.read_accelerometer(), Err(ErrorCode::OFF));
+        start_streaming(spi, icm20649);
+        {
+            let transfers = spi.transfers();
+            // The bank is selected before the first register, and only
+            // switched for the bank 2 registers and back.
+            assert_eq!(transfers[0], [REG_BANK_SEL, 0x00]);
+            assert_eq!(transfers[1], [REG_WHO_AM_I | READ, 0]);
+            assert_eq!(transfers[7], [REG_BANK_SEL, 0x20]);
+            assert_eq!(transfers[8], [REG_GYRO_SMPLRT_DIV, 0]);
+            assert_eq!(transfers[12], [REG_ACCEL_CONFIG, ACCEL_CONFIG_30G]);
+            assert_eq!(transfers[14], [REG_BANK_SEL, 0x00]);
+            assert_eq!(transfers.len(), 2 + STREAMING.len() + 2);
+        }
+        spi.take_transfers();
+
+        // Two samples in the FIFO: the latest is reported, in mg.
+        assert_eq!(icm20649.read_accelerometer(), Ok(()));
+        assert_eq!(icm20649.read_gyroscope(), Err(ErrorCode::BUSY));
+        complete(spi, &[0, 2 * SAMPLE_SIZE as u8]);
+        complete(spi, &sample([1, 2, 3], [4, 5, 6]));
+        assert_eq!(client.values.get(), None);
+        complete(spi, &sample([1024, -2048, 30 * 1024], [82, 0, -32768]));
+        assert_eq!(client.values.take(), Some((1000, -2000, 30000)));
+        {
+            let transfers = spi.transfers();
+            assert_eq!(transfers[0], [REG_FIFO_COUNTH | READ, 0, 0]);
+            assert_eq!(transfers[1].len(), BUF_LEN);
+            assert_eq!(transfers[1][0], REG_FIFO_R_W | READ);
+            assert_eq!(transfers.len(), 3);
+        }
+        spi.take_transfers();
+
+        // A full FIFO may have lost the sample boundaries, so it is reset
+        // and the output data registers are read instead, in mdps.
+        assert_eq!(icm20649.read_gyroscope(), Ok(()));
+        complete(spi, &[0x02, 0x00]);
+        complete(spi, &[]);
+        complete(spi, &[]);
+        complete(spi, &sample([0, 0, 0], [82, 0, -32768]));
+        assert_eq!(client.values.take(), Some((10_000, 0, -3_996_097)));
+        let transfers = spi.transfers();
+        assert_eq!(transfers[1], [REG_FIFO_RST, FIFO_RST_ALL]);
+        assert_eq!(transfers[2], [REG_FIFO_RST, 0]);
+        assert_eq!(transfers[3][0], REG_ACCEL_XOUT_H | READ);
+    }
+
+    #[test]
+    fn gyroscope_calibration_corrects_readings() {
+        let (spi, icm20649, client) = setup();
+        assert_eq!(icm20649.calibrate_gyroscope(20), Err(ErrorCode::OFF));
+        start_streaming(spi, icm20649);
+        spi.take_transfers();
+
+        assert_eq!(icm20649.calibrate_gyroscope(0), Err(ErrorCode::INVAL));
+        assert_eq!(icm20649.calibrate_gyroscope(20), Ok(()));
+        assert_eq!(icm20649.read_gyroscope(), Err(ErrorCode::BUSY));
+        // Noise around a bias of 10, -20 and 1 dps.
+        let bias = [82, -164, 8];
+        let noise = Cell::new(1);
+        let writes = run_procedure(spi, [0; 3], |_| {
+            noise.set(-noise.get());
+            bias.map(|raw| raw + noise.get())
+        });
+        // Samples are only taken after the FIFO is reset.
+        assert_eq!(writes, [(REG_FIFO_RST, FIFO_RST_ALL), (REG_FIFO_RST, 0)]);
+        assert_eq!(client.values.take(), Some((10_000, -20_000, 975)));
+
+        // Later readings have the bias removed.
+        assert_eq!(icm20649.read_gyroscope(), Ok(()));
+        complete(spi, &[0, SAMPLE_SIZE as u8]);
+        complete(spi, &sample([0; 3], [82 + 820, -164, 8]));
+        assert_eq!(client.values.take(), Some((100_000, 0, 0)));
+    }
+
+    #[test]
+    fn gyroscope_self_test() {
+        let (spi, icm20649, client) = setup();
+        assert_eq!(icm20649.run_gyroscope_self_test(), Err(ErrorCode::OFF));
+        start_streaming(spi, icm20649);
+
+        // At ±500 dps, code 71 is a factory response of 40 dps, so 21 dps
+        // passes and 19 dps fails. The samples taken while the stimulus
+        // settles are skipped.
+        let baseline = [65, -131, 0];
+        for (dps, passed) in [(21, 1), (19, 0)] {
+            assert_eq!(icm20649.run_gyroscope_self_test(), Ok(()));
+            assert_eq!(icm20649.read_gyroscope(), Err(ErrorCode::BUSY));
+            let settled = Cell::new((false, 0));
+            let writes = run_procedure(spi, [71; 3], |stimulus| {
+                let (previous, count) = settled.get();
+                let count = if stimulus == previous { count + 1 } else { 1 };
+                settled.set((stimulus, count));
+                if count <= SELF_TEST_DISCARD_SAMPLES {
+                    [i16::MAX; 3]
+                } else if stimulus {
+                    baseline.map(|raw| raw + dps * 655 / 10)
+                } else {
+                    baseline
+                }
+            });
+            assert_eq!(
+                writes,
+                [
+                    (REG_GYRO_CONFIG_1, GYRO_CONFIG_500DPS),
+                    (REG_FIFO_RST, FIFO_RST_ALL),
+                    (REG_FIFO_RST, 0),
+                    (REG_GYRO_CONFIG_2, GYRO_CONFIG_2_SELF_TEST),
+                    (REG_FIFO_RST, FIFO_RST_ALL),
+                    (REG_FIFO_RST, 0),
+                    (REG_GYRO_CONFIG_2, 0),
+                    (REG_GYRO_CONFIG_1, GYRO_CONFIG_4000DPS),
+                    (REG_FIFO_RST, FIFO_RST_ALL),
+                    (REG_FIFO_RST, 0),
+                ]
+            );
+            assert_eq!(client.values.take(), Some((passed, 0, 0)));
+        }
+
+        // Streaming carries on.
+        assert_eq!(icm20649.read_gyroscope(), Ok(()));
+        complete(spi, &[0, SAMPLE_SIZE as u8]);
+        complete(spi, &sample([0; 3], [82, 0, 0]));
+        assert_eq!(client.values.take(), Some((10_000, 0, 0)));
+    }
+}
@@ -0,0 +1,427 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the ST IIS2MDC 3-axis magnetometer.
+//!
+//! <https://www.st.com/resource/en/datasheet/iis2mdc.pdf>
+//!
+//! > The IIS2MDC is a high-accuracy, ultra-low-power 3-axis digital
+//! > magnetic sensor. The IIS2MDC has a magnetic field dynamic range up to
+//! > ±50 gauss.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The field is exposed through the [Magnetometer] HIL in nanotesla, with
+//! a sensitivity of 1.5 mgauss (150 nT) per count. The output data rate,
+//! the measurement mode and the temperature compensation are set by
+//! [Iis2mdc::configure]. Rates of 20 Hz and below run the sensor in its
+//! low-power mode, with fewer averaged samples per measurement.
+//!
+//! In single mode every reading starts a measurement, after which the
+//! sensor goes back to idle. In continuous mode the sensor measures at the
+//! output data rate, and a reading returns the next measurement. Either
+//! way the measurement is read once the sensor raises its DRDY line.
+//!
+//! The sensor subtracts a hard-iron offset from every measurement before
+//! it is output, which is set with [Iis2mdc::set_hard_iron_offset].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let iis2mdc = components::iis2mdc::Iis2mdcComponent::new(
+//!     mux_i2c,
+//!     capsules_extra::iis2mdc::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[IIS2MDC_DRDY],
+//! )
+//! .finalize(components::iis2mdc_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{Magnetometer, MagnetometerClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the IIS2MDC.
+pub const BASE_ADDR: u8 = 0x1E;
+
+/// Size of the buffer the driver needs, for a register address and the
+/// three axes.
+pub const BUFFER_SIZE: usize = 7;
+
+/// Hard-iron offset of the X axis, followed by Y and Z.
+const REG_OFFSET_X_L: u8 = 0x45;
+/// `CFG_REG_A`, followed by `CFG_REG_B` and `CFG_REG_C`.
+const REG_CFG_A: u8 = 0x60;
+/// X axis output, followed by Y and Z.
+const REG_OUTX_L: u8 = 0x68;
+
+const CFG_A_COMP_TEMP_EN: u8 = 1 << 7;
+const CFG_A_LP: u8 = 1 << 4;
+const CFG_A_ODR_SHIFT: u8 = 2;
+const CFG_A_MD_CONTINUOUS: u8 = 0b00;
+const CFG_A_MD_SINGLE: u8 = 0b01;
+const CFG_A_MD_IDLE: u8 = 0b11;
+
+/// Offset cancellation, and in single mode.
+const CFG_B_OFF_CANC: u8 = 1 << 1;
+const CFG_B_OFF_CANC_ONE_SHOT: u8 = 1 << 4;
+
+/// Block data update, and data ready on the DRDY pin.
+const CFG_C_BDU: u8 = 1 << 4;
+const CFG_C_DRDY_ON_PIN: u8 = 1 << 0;
+
+/// Nanotesla per count.
+const SENSITIVITY_NT: i32 = 150;
+
+/// Output data rate, in continuous mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DataRate {
+    Hz10 = 0,
+    Hz20 = 1,
+    Hz50 = 2,
+    Hz100 = 3,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// Measure at the output data rate.
+    Continuous,
+    /// Measure once for every reading.
+    Single,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Configure,
+    WriteOffset,
+    StartSingle,
+    Measure,
+    Read,
+}
+
+/// Convert the X, Y and Z outputs to nanotesla.
+fn field_to_nt(buf: &[u8]) -> (i32, i32, i32) {
+    let axis = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]) as i32 * SENSITIVITY_NT;
+    (axis(0), axis(2), axis(4))
+}
+
+/// Convert an offset in nanotesla to counts, if it fits the offset
+/// registers.
+fn offset_counts(offset_nt: i32) -> Result<i16, ErrorCode> {
+    i16::try_from(offset_nt / SENSITIVITY_NT).map_err(|_| ErrorCode::INVAL)
+}
+
+pub struct Iis2mdc<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    drdy_pin: &'a G,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    data_rate: Cell<DataRate>,
+    mode: Cell<Mode>,
+    temperature_compensation: Cell<bool>,
+    /// Continuous mode was written to the sensor.
+    running: Cell<bool>,
+    client: OptionalCell<&'a dyn MagnetometerClient>,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Iis2mdc<'a, I, G> {
+    pub fn new(i2c: &'a I, drdy_pin: &'a G, buffer: &'static mut [u8]) -> Self {
+        Iis2mdc {
+            i2c,
+            drdy_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            data_rate: Cell::new(DataRate::Hz10),
+            mode: Cell::new(Mode::Single),
+            temperature_compensation: Cell::new(true),
+            running: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Configure the output data rate, the measurement mode and whether
+    /// the sensor compensates the measurements for its temperature. In
+    /// continuous mode the sensor starts measuring right away.
+    ///
+    /// Until this is called the sensor is used in single mode, with
+    /// temperature compensation.
+    pub fn configure(
+        &self,
+        data_rate: DataRate,
+        mode: Mode,
+        temperature_compensation: bool,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.data_rate.set(data_rate);
+        self.mode.set(mode);
+        self.temperature_compensation.set(temperature_compensation);
+        let md = match mode {
+            Mode::Continuous => CFG_A_MD_CONTINUOUS,
+            Mode::Single => CFG_A_MD_IDLE,
+        };
+        self.running.set(false);
+        let result = self.write_config(State::Configure, md);
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    /// Set the hard-iron offset the sensor subtracts from the X, Y and Z
+    /// axes, in nanotesla.
+    pub fn set_hard_iron_offset(&self, x: i32, y: i32, z: i32) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let [x_l, x_h] = offset_counts(x)?.to_le_bytes();
+        let [y_l, y_h] = offset_counts(y)?.to_le_bytes();
+        let [z_l, z_h] = offset_counts(z)?.to_le_bytes();
+        let result = self.write(
+            State::WriteOffset,
+            &[REG_OFFSET_X_L, x_l, x_h, y_l, y_h, z_l, z_h],
+        );
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            self.state.set(state);
+            self.i2c.enable();
+            self.i2c
+                .write(buffer, bytes.len())
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    /// Write the three configuration registers, with the mode bits `md`.
+    fn write_config(&self, state: State, md: u8) -> Result<(), ErrorCode> {
+        let data_rate = self.data_rate.get();
+        let mut cfg_a = (data_rate as u8) << CFG_A_ODR_SHIFT | md;
+        if data_rate == DataRate::Hz10 || data_rate == DataRate::Hz20 {
+            cfg_a |= CFG_A_LP;
+        }
+        if self.temperature_compensation.get() {
+            cfg_a |= CFG_A_COMP_TEMP_EN;
+        }
+        let cfg_b = match self.mode.get() {
+            Mode::Continuous => CFG_B_OFF_CANC,
+            Mode::Single => CFG_B_OFF_CANC | CFG_B_OFF_CANC_ONE_SHOT,
+        };
+        let cfg_c = CFG_C_BDU | CFG_C_DRDY_ON_PIN;
+        self.write(state, &[REG_CFG_A, cfg_a, cfg_b, cfg_c])
+    }
+
+    /// Wait for DRDY, which may already be high if the measurement
+    /// finished before the driver started waiting.
+    fn wait_ready(&self) {
+        self.state.set(State::Measure);
+        self.drdy_pin
+            .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        if self.drdy_pin.read() {
+            gpio::Client::fired(self);
+        }
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.drdy_pin.disable_interrupts();
+        self.i2c.disable();
+    }
+
+    fn finish_field(&self, value: Result<(i32, i32, i32), ErrorCode>) {
+        self.finish();
+        self.client.map(|client| client.callback(value));
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Iis2mdc<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let mut data = [0; BUFFER_SIZE];
+        data.copy_from_slice(&buffer[..BUFFER_SIZE]);
+        self.buffer.replace(buffer);
+
+        if let Err(error) = status {
+            match state {
+                State::StartSingle | State::Read => self.finish_field(Err(error.into())),
+                _ => self.finish(),
+            }
+            return;
+        }
+
+        match state {
+            State::Configure => {
+                self.running.set(self.mode.get() == Mode::Continuous);
+                self.finish();
+            }
+            State::StartSingle => {
+                self.i2c.disable();
+                self.wait_ready();
+            }
+            State::Read => self.finish_field(Ok(field_to_nt(&data))),
+            State::WriteOffset | State::Idle | State::Measure => self.finish(),
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Iis2mdc<'a, I, G> {
+    fn fired(&self) {
+        if self.state.get() != State::Measure {
+            return;
+        }
+        self.drdy_pin.disable_interrupts();
+        let result = self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = REG_OUTX_L;
+            self.state.set(State::Read);
+            self.i2c.enable();
+            self.i2c
+                .write_read(buffer, 1, 6)
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        });
+        if let Err(error) = result {
+            self.finish_field(Err(error));
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Magnetometer<'a> for Iis2mdc<'a, I, G> {
+    fn set_client(&self, client: &'a dyn MagnetometerClient) {
+        self.client.set(client);
+    }
+
+    fn read_field_nt(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.running.get() {
+            self.wait_ready();
+            return Ok(());
+        }
+        let result = self.write_config(State::StartSingle, CFG_A_MD_SINGLE);
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        fields: RefCell<Vec<Result<(i32, i32, i32), ErrorCode>>>,
+    }
+
+    impl MagnetometerClient for Client {
+        fn callback(&self, value: Result<(i32, i32, i32), ErrorCode>) {
+            self.fields.borrow_mut().push(value);
+        }
+    }
+
+    type Device = Iis2mdc<'static, MockI2c, MockPin<'static>>;
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockPin<'static>,
+        &'static Device,
+        &'static Client,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let ready: &'static MockPin = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let iis2mdc = Box::leak(Box::new(Iis2mdc::new(
+            i2c,
+            ready,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        Magnetometer::set_client(iis2mdc, client);
+        (i2c, ready, iis2mdc, client)
+    }
+
+    #[test]
+    fn sensitivity_and_offsets() {
+        let field = [0x64, 0x00, 0x9C, 0xFF, 0xFF, 0x7F];
+        assert_eq!(field_to_nt(&field), (15_000, -15_000, 4_915_050));
+        assert_eq!(offset_counts(-15_000), Ok(-100));
+        assert_eq!(offset_counts(4_915_050), Ok(32767));
+        assert_eq!(offset_counts(5_000_000), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn single_mode_reading() {
+        let (i2c, ready, iis2mdc, client) = setup();
+
+        assert_eq!(iis2mdc.read_field_nt(), Ok(()));
+        assert_eq!(iis2mdc.read_field_nt(), Err(ErrorCode::BUSY));
+        // Low-power 10 Hz, temperature compensation, single measurement.
+        assert_eq!(i2c.complete(iis2mdc, &[]), [REG_CFG_A, 0x91, 0x12, 0x11]);
+        assert!(matches!(
+            ready.interrupt_edge(),
+            Some(gpio::InterruptEdge::RisingEdge)
+        ));
+        assert!(!i2c.busy());
+
+        gpio::Client::fired(iis2mdc);
+        assert!(!ready.interrupts_enabled());
+        let reading = [0x64, 0x00, 0x9C, 0xFF, 0x0A, 0x00];
+        assert_eq!(i2c.complete(iis2mdc, &reading), [REG_OUTX_L]);
+        assert_eq!(*client.fields.borrow(), [Ok((15_000, -15_000, 1_500))]);
+    }
+
+    #[test]
+    fn continuous_mode_and_hard_iron_offset() {
+        let (i2c, ready, iis2mdc, client) = setup();
+
+        assert_eq!(iis2mdc.set_hard_iron_offset(15_000, -15_000, 0), Ok(()));
+        assert_eq!(
+            i2c.complete(iis2mdc, &[]),
+            [REG_OFFSET_X_L, 0x64, 0x00, 0x9C, 0xFF, 0x00, 0x00]
+        );
+
+        // 50 Hz is above the low-power rates.
+        assert_eq!(
+            iis2mdc.configure(DataRate::Hz50, Mode::Continuous, false),
+            Ok(())
+        );
+        assert_eq!(i2c.complete(iis2mdc, &[]), [REG_CFG_A, 0x08, 0x02, 0x11]);
+
+        // A measurement is already waiting, so no measurement is started.
+        ready.set_level(true);
+        assert_eq!(iis2mdc.read_field_nt(), Ok(()));
+        assert_eq!(
+            i2c.complete(iis2mdc, &[0, 0, 0, 0, 0x01, 0x00]),
+            [REG_OUTX_L]
+        );
+        assert_eq!(*client.fields.borrow(), [Ok((0, 0, 150))]);
+        assert!(!i2c.busy());
+    }
+}
@@ -0,0 +1,808 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TI INA3221 triple-channel shunt and bus voltage monitor.
+//!
+//! <https://www.ti.com/lit/ds/symlink/ina3221.pdf>
+//!
+//! The INA3221 measures the voltage across a shunt resistor and the bus
+//! voltage of three independent rails. The board gives the resistance of
+//! each shunt, the number of samples to average and the conversion times in
+//! a [Config]; a channel without a shunt is disabled.
+//!
+//! [Ina3221::configure] checks the manufacturer ID, resets the device and
+//! starts continuous conversions on the enabled channels. Readings fail with
+//! `OFF` until this has finished. Every reading fetches the shunt and bus
+//! voltages of all three channels, registers 0x01 to 0x06, in a single
+//! 12-byte read.
+//!
+//! Alerts
+//! ------
+//!
+//! The WARNING and CRITICAL pins fall when the averaged or, respectively,
+//! a single shunt voltage is above the channel's limit. The limits are set in
+//! µA and start at their maximum, 163.8 mV across the shunt. The flags are
+//! latched until read, so the pins only fall once for each alert. The PV pin
+//! is high while every bus voltage is valid, and the TC pin falls when the
+//! channel 1 bus voltage does not come up in time after power up.
+//!
+//! The connected pins share one interrupt handler, which reads the
+//! Mask/Enable register and reports its flags to the [Ina3221Client] as
+//! [Alerts].
+//!
+//! Structure
+//! ---------
+//!
+//! As with the LTC294X driver, `Ina3221` drives the chip and provides the
+//! `MultiChannelPowerMonitor` HIL, and `Ina3221Driver` provides it to
+//! userspace.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ina3221 = components::ina3221::Ina3221Component::new(
+//!     mux_i2c,
+//!     capsules_extra::ina3221::BASE_ADDR,
+//!     capsules_extra::ina3221::Config {
+//!         shunt_mohm: [100, 100, 0],
+//!         averaging: capsules_extra::ina3221::Averaging::Samples16,
+//!         bus_conversion: capsules_extra::ina3221::ConversionTime::Us1100,
+//!         shunt_conversion: capsules_extra::ina3221::ConversionTime::Us1100,
+//!     },
+//!     capsules_extra::ina3221::AlertPins {
+//!         critical: Some(critical_pin),
+//!         ..Default::default()
+//!     },
+//! )
+//! .finalize(components::ina3221_component_static!(nrf52840::i2c::TWI));
+//! let ina3221_driver = components::ina3221::Ina3221DriverComponent::new(
+//!     ina3221,
+//!     board_kernel,
+//!     capsules_extra::ina3221::DRIVER_NUM,
+//! )
+//! .finalize(components::ina3221_driver_component_static!(nrf52840::i2c::TWI));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{MultiChannelPowerMonitor, MultiChannelPowerMonitorClient};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ina3221 as usize;
+
+/// I2C address of the INA3221 with A0 tied to GND.
+pub const BASE_ADDR: u8 = 0x40;
+
+/// Number of channels.
+pub const CHANNELS: u8 = 3;
+
+/// Size of the buffer the driver needs, for the six channel registers.
+pub const BUFFER_SIZE: usize = 12;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_CH1_SHUNT: u8 = 0x01;
+const REG_CH1_CRITICAL: u8 = 0x07;
+const REG_CH1_WARNING: u8 = 0x08;
+const REG_MASK_ENABLE: u8 = 0x0F;
+const REG_MANUFACTURER_ID: u8 = 0xFE;
+
+const MANUFACTURER_ID_TI: u16 = 0x5449;
+
+const CONFIG_RST: u16 = 1 << 15;
+const CONFIG_CH1_EN: u16 = 1 << 14;
+/// Continuous shunt and bus voltage conversions.
+const CONFIG_MODE_CONTINUOUS: u16 = 0b111;
+/// Latch the warning and critical flags until Mask/Enable is read.
+const MASK_ENABLE_LATCH: u16 = 1 << 11 | 1 << 10;
+
+/// 40 µV/LSB.
+const SHUNT_UV_PER_LSB: u64 = 40;
+/// 8 mV/LSB.
+const BUS_MV_PER_LSB: i32 = 8;
+/// The limits are 13-bit two's complement values in bits 15 to 3.
+const LIMIT_MAX_LSB: u64 = 0xFFF;
+const LIMIT_MAX: u16 = 0x7FF8;
+
+/// Number of samples averaged for each reading.
+#[derive(Clone, Copy, Debug)]
+pub enum Averaging {
+    Samples1 = 0,
+    Samples4 = 1,
+    Samples16 = 2,
+    Samples64 = 3,
+    Samples128 = 4,
+    Samples256 = 5,
+    Samples512 = 6,
+    Samples1024 = 7,
+}
+
+/// Time taken by each conversion.
+#[derive(Clone, Copy, Debug)]
+pub enum ConversionTime {
+    Us140 = 0,
+    Us204 = 1,
+    Us332 = 2,
+    Us588 = 3,
+    Us1100 = 4,
+    Us2116 = 5,
+    Us4156 = 6,
+    Us8244 = 7,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Resistance of the shunt of each channel, in mΩ, or 0 to disable the
+    /// channel.
+    pub shunt_mohm: [u32; CHANNELS as usize],
+    pub averaging: Averaging,
+    pub bus_conversion: ConversionTime,
+    pub shunt_conversion: ConversionTime,
+}
+
+impl Config {
+    fn register(&self) -> u16 {
+        let enabled = (0..CHANNELS)
+            .filter(|&ch| self.shunt_mohm[ch as usize] != 0)
+            .fold(0, |enabled, ch| enabled | CONFIG_CH1_EN >> ch);
+        enabled
+            | (self.averaging as u16) << 9
+            | (self.bus_conversion as u16) << 6
+            | (self.shunt_conversion as u16) << 3
+            | CONFIG_MODE_CONTINUOUS
+    }
+}
+
+/// The alert pins connected to the board. All are open drain.
+#[derive(Default)]
+pub struct AlertPins<'a> {
+    pub warning: Option<&'a dyn gpio::InterruptPin<'a>>,
+    pub critical: Option<&'a dyn gpio::InterruptPin<'a>>,
+    pub timing_control: Option<&'a dyn gpio::InterruptPin<'a>>,
+    pub power_valid: Option<&'a dyn gpio::InterruptPin<'a>>,
+}
+
+/// The flags of the Mask/Enable register.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Alerts(u16);
+
+impl Alerts {
+    /// A conversion on channel `ch` was above its critical limit.
+    pub fn critical(&self, ch: u8) -> bool {
+        self.0 & 1 << (9 - ch) != 0
+    }
+
+    /// The averaged shunt voltage of channel `ch` was above its warning
+    /// limit.
+    pub fn warning(&self, ch: u8) -> bool {
+        self.0 & 1 << (5 - ch) != 0
+    }
+
+    /// Every bus voltage is above the power-valid limit.
+    pub fn power_valid(&self) -> bool {
+        self.0 & 1 << 2 != 0
+    }
+
+    /// The channel 1 bus voltage did not come up in time after power up.
+    pub fn timing_control(&self) -> bool {
+        self.0 & 1 << 1 == 0
+    }
+
+    /// The flags as passed to userspace: critical alerts in bits 0 to 2,
+    /// warnings in bits 3 to 5, power valid in bit 6 and timing control in
+    /// bit 7.
+    fn bits(&self) -> usize {
+        (0..CHANNELS).fold(0, |bits, ch| {
+            bits | (self.critical(ch) as usize) << ch | (self.warning(ch) as usize) << (3 + ch)
+        }) | (self.power_valid() as usize) << 6
+            | (self.timing_control() as usize) << 7
+    }
+}
+
+pub trait Ina3221Client {
+    /// Called when an alert pin changes, with the flags read from the
+    /// device.
+    fn alert(&self, alerts: Alerts);
+
+    /// Called when a limit has been written.
+    fn limit_set(&self, result: Result<(), ErrorCode>);
+}
+
+/// Convert the big-endian channel registers to the bus voltage in mV and the
+/// current in µA of channel `ch`.
+fn channel_reading(data: &[u8], ch: usize, shunt_mohm: u32) -> (u32, i32) {
+    let register = |i: usize| (i16::from_be_bytes([data[2 * i], data[2 * i + 1]]) >> 3) as i32;
+    let shunt_uv = register(2 * ch) as i64 * SHUNT_UV_PER_LSB as i64;
+    let bus_mv = register(2 * ch + 1) * BUS_MV_PER_LSB;
+    (
+        bus_mv.max(0) as u32,
+        (shunt_uv * 1000 / shunt_mohm as i64) as i32,
+    )
+}
+
+/// Convert a current in µA to a limit register value.
+fn limit_register(current_ua: u32, shunt_mohm: u32) -> u16 {
+    let shunt_uv = current_ua as u64 * shunt_mohm as u64 / 1000;
+    ((shunt_uv / SHUNT_UV_PER_LSB).min(LIMIT_MAX_LSB) as u16) << 3
+}
+
+/// Convert a limit register value to a current in µA.
+fn limit_current(register: u16, shunt_mohm: u32) -> u32 {
+    ((register >> 3) as u64 * SHUNT_UV_PER_LSB * 1000 / shunt_mohm as u64) as u32
+}
+
+/// A step of the configuration.
+#[derive(Clone, Copy)]
+enum Step {
+    /// Check the manufacturer ID.
+    CheckIdentity,
+    /// Write a register.
+    Write(u8, u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Configure(usize),
+    ReadChannel(u8),
+    WriteLimit,
+    ReadAlerts,
+}
+
+pub struct Ina3221<'a, I: I2CDevice> {
+    i2c: &'a I,
+    config: Config,
+    alert_pins: AlertPins<'a>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    configured: Cell<bool>,
+    /// An alert pin changed while a transfer was in progress.
+    alert_pending: Cell<bool>,
+    critical_limits: Cell<[u16; CHANNELS as usize]>,
+    warning_limits: Cell<[u16; CHANNELS as usize]>,
+    monitor_client: OptionalCell<&'a dyn MultiChannelPowerMonitorClient>,
+    client: OptionalCell<&'a dyn Ina3221Client>,
+}
+
+impl<'a, I: I2CDevice> Ina3221<'a, I> {
+    pub fn new(
+        i2c: &'a I,
+        config: Config,
+        alert_pins: AlertPins<'a>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Ina3221 {
+            i2c,
+            config,
+            alert_pins,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            configured: Cell::new(false),
+            alert_pending: Cell::new(false),
+            critical_limits: Cell::new([LIMIT_MAX; CHANNELS as usize]),
+            warning_limits: Cell::new([LIMIT_MAX; CHANNELS as usize]),
+            monitor_client: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Ina3221Client) {
+        self.client.set(client);
+    }
+
+    /// Call `f` on each connected alert pin.
+    fn each_alert_pin(&self, f: impl Fn(&'a dyn gpio::InterruptPin<'a>)) {
+        let pins = &self.alert_pins;
+        [
+            pins.warning,
+            pins.critical,
+            pins.timing_control,
+            pins.power_valid,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(f);
+    }
+
+    fn steps(&self) -> [Step; 4] {
+        [
+            Step::CheckIdentity,
+            Step::Write(REG_CONFIG, CONFIG_RST),
+            Step::Write(REG_CONFIG, self.config.register()),
+            Step::Write(REG_MASK_ENABLE, MASK_ENABLE_LATCH),
+        ]
+    }
+
+    /// Check, reset and configure the INA3221.
+    pub fn configure(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.configured.set(false);
+        self.each_alert_pin(|pin| pin.disable_interrupts());
+        self.i2c.enable();
+        self.configure_step(0).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn configure_step(&self, step: usize) -> Result<(), ErrorCode> {
+        self.state.set(State::Configure(step));
+        match self.steps()[step] {
+            Step::CheckIdentity => self.read(REG_MANUFACTURER_ID, 2),
+            Step::Write(register, value) => self.write(register, value),
+        }
+    }
+
+    /// Move on from configuration step `step`, which read `data`.
+    fn configure_done(&self, step: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if let Step::CheckIdentity = self.steps()[step] {
+            if u16::from_be_bytes([data[0], data[1]]) != MANUFACTURER_ID_TI {
+                return Err(ErrorCode::NODEVICE);
+            }
+        }
+        if step + 1 < self.steps().len() {
+            return self.configure_step(step + 1);
+        }
+        // The reset restored the limits to their maximum.
+        self.critical_limits.set([LIMIT_MAX; CHANNELS as usize]);
+        self.warning_limits.set([LIMIT_MAX; CHANNELS as usize]);
+        self.configured.set(true);
+        self.finish();
+        self.each_alert_pin(|pin| {
+            pin.make_input();
+            pin.enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        });
+        // Report when the rails become valid again as well.
+        self.alert_pins
+            .power_valid
+            .map(|pin| pin.enable_interrupts(gpio::InterruptEdge::EitherEdge));
+        Ok(())
+    }
+
+    /// The critical limit of channel `ch`, in µA.
+    pub fn critical_limit(&self, ch: u8) -> Result<u32, ErrorCode> {
+        let shunt_mohm = self.shunt_mohm(ch)?;
+        Ok(limit_current(
+            self.critical_limits.get()[ch as usize],
+            shunt_mohm,
+        ))
+    }
+
+    /// The warning limit of channel `ch`, in µA.
+    pub fn warning_limit(&self, ch: u8) -> Result<u32, ErrorCode> {
+        let shunt_mohm = self.shunt_mohm(ch)?;
+        Ok(limit_current(
+            self.warning_limits.get()[ch as usize],
+            shunt_mohm,
+        ))
+    }
+
+    /// Raise the critical alert when a single conversion of channel `ch` is
+    /// above `current_ua`.
+    pub fn set_critical_limit(&self, ch: u8, current_ua: u32) -> Result<(), ErrorCode> {
+        self.set_limit(&self.critical_limits, REG_CH1_CRITICAL, ch, current_ua)
+    }
+
+    /// Raise the warning alert when the average of channel `ch` is above
+    /// `current_ua`.
+    pub fn set_warning_limit(&self, ch: u8, current_ua: u32) -> Result<(), ErrorCode> {
+        self.set_limit(&self.warning_limits, REG_CH1_WARNING, ch, current_ua)
+    }
+
+    fn set_limit(
+        &self,
+        limits: &Cell<[u16; CHANNELS as usize]>,
+        ch1_register: u8,
+        ch: u8,
+        current_ua: u32,
+    ) -> Result<(), ErrorCode> {
+        let shunt_mohm = self.shunt_mohm(ch)?;
+        self.start(State::WriteLimit)?;
+        let value = limit_register(current_ua, shunt_mohm);
+        self.write(ch1_register + 2 * ch, value)
+            .map(|()| {
+                let mut updated = limits.get();
+                updated[ch as usize] = value;
+                limits.set(updated);
+            })
+            .map_err(|error| {
+                self.finish();
+                error
+            })
+    }
+
+    /// The shunt resistance of enabled channel `ch`.
+    fn shunt_mohm(&self, ch: u8) -> Result<u32, ErrorCode> {
+        match self.config.shunt_mohm.get(ch as usize) {
+            Some(&shunt_mohm) if shunt_mohm != 0 => Ok(shunt_mohm),
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+
+    fn write(&self, register: u8, value: u16) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            buffer[1..3].copy_from_slice(&value.to_be_bytes());
+            self.i2c.write(buffer, 3).map_err(|(error, buffer)| {
+                self.buffer.replace(buffer);
+                error.into()
+            })
+        })
+    }
+
+    fn read(&self, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            self.i2c
+                .write_read(buffer, 1, len)
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn start(&self, state: State) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(state);
+        self.i2c.enable();
+        Ok(())
+    }
+
+    fn read_alerts(&self) -> Result<(), ErrorCode> {
+        self.start(State::ReadAlerts)?;
+        self.read(REG_MASK_ENABLE, 2).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+    }
+}
+
+impl<I: I2CDevice> I2CClient for Ina3221<'_, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let mut data = [0; BUFFER_SIZE];
+        data.copy_from_slice(&buffer[..BUFFER_SIZE]);
+        self.buffer.replace(buffer);
+
+        match (self.state.get(), status) {
+            (State::Configure(step), Ok(())) => {
+                if self.configure_done(step, &data).is_err() {
+                    self.finish();
+                }
+            }
+            (State::Configure(_), Err(_)) => self.finish(),
+            (State::ReadChannel(ch), status) => {
+                self.finish();
+                let reading = status.map_err(|error| error.into()).and_then(|()| {
+                    let shunt_mohm = self.shunt_mohm(ch)?;
+                    Ok(channel_reading(&data, ch as usize, shunt_mohm))
+                });
+                self.monitor_client
+                    .map(|client| client.channel_read(ch, reading));
+            }
+            (State::WriteLimit, status) => {
+                self.finish();
+                self.client
+                    .map(|client| client.limit_set(status.map_err(Into::into)));
+            }
+            (State::ReadAlerts, status) => {
+                self.finish();
+                if status.is_ok() {
+                    let alerts = Alerts(u16::from_be_bytes([data[0], data[1]]));
+                    self.client.map(|client| client.alert(alerts));
+                }
+            }
+            (State::Idle, _) => {}
+        }
+
+        if self.state.get() == State::Idle && self.alert_pending.take() {
+            let _ = self.read_alerts();
+        }
+    }
+}
+
+impl<I: I2CDevice> gpio::Client for Ina3221<'_, I> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => {
+                let _ = self.read_alerts();
+            }
+            _ => self.alert_pending.set(true),
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> MultiChannelPowerMonitor<'a> for Ina3221<'a, I> {
+    fn set_client(&self, client: &'a dyn MultiChannelPowerMonitorClient) {
+        self.monitor_client.set(client);
+    }
+
+    fn channels(&self) -> u8 {
+        CHANNELS
+    }
+
+    fn read_channel(&self, ch: u8) -> Result<(), ErrorCode> {
+        self.shunt_mohm(ch)?;
+        self.start(State::ReadChannel(ch))?;
+        self.read(REG_CH1_SHUNT, BUFFER_SIZE).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+}
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const CHANNEL_READ: usize = 0;
+    pub const LIMIT_SET: usize = 1;
+    pub const ALERT: usize = 2;
+    pub const COUNT: u8 = 3;
+}
+
+fn limit_return(limit: Result<u32, ErrorCode>) -> CommandReturn {
+    match limit {
+        Ok(current_ua) => CommandReturn::success_u32(current_ua),
+        Err(error) => CommandReturn::failure(error),
+    }
+}
+
+#[derive(Default)]
+pub struct App {}
+
+/// Provides the INA3221 to userspace.
+pub struct Ina3221Driver<'a, I: I2CDevice> {
+    ina3221: &'a Ina3221<'a, I>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process whose command is in progress.
+    processid: OptionalCell<ProcessId>,
+}
+
+impl<'a, I: I2CDevice> Ina3221Driver<'a, I> {
+    pub fn new(
+        ina3221: &'a Ina3221<'a, I>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Ina3221Driver<'a, I> {
+        Ina3221Driver {
+            ina3221,
+            apps: grant,
+            processid: OptionalCell::empty(),
+        }
+    }
+
+    fn schedule_upcall(&self, upcall_num: usize, args: (usize, usize, usize)) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data.schedule_upcall(upcall_num, args).ok();
+            });
+        });
+    }
+}
+
+impl<I: I2CDevice> SyscallDriver for Ina3221Driver<'_, I> {
+    // ### `subscribe_num`
+    //
+    // - `0`: A channel has been read, with the status code, the bus voltage
+    //   in mV and the current in µA.
+    // - `1`: A limit has been set, with the status code.
+    // - `2`: An alert pin changed, with the flags: critical alerts on
+    //   channels 0 to 2 in bits 0 to 2, warnings in bits 3 to 5, power valid
+    //   in bit 6 and the timing control alert in bit 7.
+
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Get the number of channels.
+    /// - `2`: Read channel `data`.
+    /// - `3`: Set the critical limit of channel `data` to `data2` µA.
+    /// - `4`: Set the warning limit of channel `data` to `data2` µA.
+    /// - `5`: Get the critical limit of channel `data`, in µA.
+    /// - `6`: Get the warning limit of channel `data`, in µA.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let ch = u8::try_from(data).unwrap_or(u8::MAX);
+        let current_ua = u32::try_from(data2).unwrap_or(u32::MAX);
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 => return CommandReturn::success_u32(CHANNELS as u32),
+            5 => return limit_return(self.ina3221.critical_limit(ch)),
+            6 => return limit_return(self.ina3221.warning_limit(ch)),
+            _ if self.processid.is_some() => Err(ErrorCode::BUSY),
+            2 => self.ina3221.read_channel(ch),
+            3 => self.ina3221.set_critical_limit(ch, current_ua),
+            4 => self.ina3221.set_warning_limit(ch, current_ua),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        if result.is_ok() {
+            self.processid.set(processid);
+        }
+        result.into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<I: I2CDevice> MultiChannelPowerMonitorClient for Ina3221Driver<'_, I> {
+    fn channel_read(&self, _ch: u8, reading: Result<(u32, i32), ErrorCode>) {
+        let (voltage_mv, current_ua) = reading.unwrap_or((0, 0));
+        self.schedule_upcall(
+            upcall::CHANNEL_READ,
+            (
+                into_statuscode(reading.map(|_| ())),
+                voltage_mv as usize,
+                current_ua as usize,
+            ),
+        );
+    }
+}
+
+impl<I: I2CDevice> Ina3221Client for Ina3221Driver<'_, I> {
+    fn alert(&self, alerts: Alerts) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::ALERT, (alerts.bits(), 0, 0))
+                .ok();
+        });
+    }
+
+    fn limit_set(&self, result: Result<(), ErrorCode>) {
+        self.schedule_upcall(upcall::LIMIT_SET, (into_statuscode(result), 0, 0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockI2c;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        readings: RefCell<Vec<(u8, Result<(u32, i32), ErrorCode>)>>,
+        alerts: RefCell<Vec<Alerts>>,
+        limits: RefCell<Vec<Result<(), ErrorCode>>>,
+    }
+
+    impl MultiChannelPowerMonitorClient for Client {
+        fn channel_read(&self, ch: u8, reading: Result<(u32, i32), ErrorCode>) {
+            self.readings.borrow_mut().push((ch, reading));
+        }
+    }
+
+    impl Ina3221Client for Client {
+        fn alert(&self, alerts: Alerts) {
+            self.alerts.borrow_mut().push(alerts);
+        }
+
+        fn limit_set(&self, result: Result<(), ErrorCode>) {
+            self.limits.borrow_mut().push(result);
+        }
+    }
+
+    type Device = Ina3221<'static, MockI2c>;
+
+    fn setup() -> (&'static MockI2c, &'static Device, &'static Client) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let config = Config {
+            shunt_mohm: [100, 10, 0],
+            averaging: Averaging::Samples16,
+            bus_conversion: ConversionTime::Us1100,
+            shunt_conversion: ConversionTime::Us588,
+        };
+        let ina3221 = Box::leak(Box::new(Ina3221::new(
+            i2c,
+            config,
+            AlertPins::default(),
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        MultiChannelPowerMonitor::set_client(ina3221, client);
+        Ina3221::set_client(ina3221, client);
+        (i2c, ina3221, client)
+    }
+
+    fn configure(i2c: &MockI2c, ina3221: &Device) {
+        assert_eq!(ina3221.configure(), Ok(()));
+        assert_eq!(i2c.complete(ina3221, &[0x54, 0x49]), [0xFE]);
+        assert_eq!(i2c.complete(ina3221, &[]), [0x00, 0x80, 0x00]);
+        // Channels 1 and 2, 16 samples, 1.1 ms bus and 588 µs shunt
+        // conversions, continuous.
+        assert_eq!(i2c.complete(ina3221, &[]), [0x00, 0x65, 0x1F]);
+        assert_eq!(i2c.complete(ina3221, &[]), [0x0F, 0x0C, 0x00]);
+        assert!(!i2c.busy());
+    }
+
+    #[test]
+    fn conversions() {
+        // 12 V and 10 mV on channel 1, 3.3 V and -1 mV on channel 2.
+        let data = [
+            0x07, 0xD0, 0x2E, 0xE0, 0xFF, 0x38, 0x0C, 0xE0, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(channel_reading(&data, 0, 100), (12000, 100_000));
+        assert_eq!(channel_reading(&data, 1, 10), (3296, -100_000));
+
+        // 1 A across 100 mΩ is 100 mV, or 2500 LSB.
+        assert_eq!(limit_register(1_000_000, 100), 2500 << 3);
+        assert_eq!(limit_current(2500 << 3, 100), 1_000_000);
+        // Limits saturate at 163.8 mV.
+        assert_eq!(limit_register(u32::MAX, 100), LIMIT_MAX);
+
+        let alerts = Alerts(1 << 9 | 1 << 4 | 1 << 2 | 1 << 1);
+        assert!(alerts.critical(0) && !alerts.critical(1));
+        assert!(alerts.warning(1) && !alerts.warning(0));
+        assert!(alerts.power_valid() && !alerts.timing_control());
+        assert_eq!(alerts.bits(), 1 << 0 | 1 << 4 | 1 << 6);
+    }
+
+    #[test]
+    fn reads_all_channels_at_once() {
+        let (i2c, ina3221, client) = setup();
+        assert_eq!(ina3221.read_channel(0), Err(ErrorCode::OFF));
+        configure(i2c, ina3221);
+
+        // Channel 3 has no shunt.
+        assert_eq!(ina3221.read_channel(2), Err(ErrorCode::INVAL));
+        assert_eq!(ina3221.read_channel(1), Ok(()));
+        assert_eq!(ina3221.read_channel(0), Err(ErrorCode::BUSY));
+        let data = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x0C, 0xE0, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(i2c.complete(ina3221, &data), [0x01]);
+        assert_eq!(*client.readings.borrow(), [(1, Ok((3296, 40_000)))]);
+
+        assert_eq!(ina3221.critical_limit(1), Ok(16_380_000));
+        assert_eq!(ina3221.set_critical_limit(1, 2_000_000), Ok(()));
+        assert_eq!(i2c.complete(ina3221, &[]), [0x09, 0x0F, 0xA0]);
+        assert_eq!(*client.limits.borrow(), [Ok(())]);
+        assert_eq!(ina3221.critical_limit(1), Ok(2_000_000));
+        assert_eq!(ina3221.warning_limit(1), Ok(16_380_000));
+    }
+
+    #[test]
+    fn alert_waits_for_transfer() {
+        let (i2c, ina3221, client) = setup();
+        configure(i2c, ina3221);
+
+        assert_eq!(ina3221.read_channel(0), Ok(()));
+        gpio::Client::fired(ina3221);
+        assert_eq!(i2c.complete(ina3221, &[0; 12]), [0x01]);
+        assert_eq!(client.readings.borrow().len(), 1);
+
+        // The flags are read once the reading has finished.
+        assert_eq!(i2c.complete(ina3221, &[0x02, 0x06]), [0x0F]);
+        assert_eq!(*client.alerts.borrow(), [Alerts(0x0206)]);
+        assert!(client.alerts.borrow()[0].critical(0));
+        assert!(!i2c.busy());
+    }
+}
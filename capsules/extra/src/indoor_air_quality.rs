@@ -0,0 +1,137 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with indoor air quality estimates, and control of the
+//! heater profile of the gas sensor they come from.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when an estimate completes, with the status code and the
+//!   IAQ index, from 0 to 500.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: estimate the indoor air quality
+//! * `2`: set heater set point argument 1 to the temperature in °C in the
+//!   low 16 bits of argument 2, for the duration in ms in its high 16 bits
+//! * `3`: use the first argument 1 set points of the heater profile
+//!
+//! Only one estimate can be in progress at a time. The heater profile is
+//! shared by all processes.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::IndoorAirQuality`
+//! trait.
+//!
+//! ```rust
+//! let iaq = components::indoor_air_quality::IndoorAirQualityComponent::new(
+//!     board_kernel,
+//!     capsules_extra::indoor_air_quality::DRIVER_NUM,
+//!     bme680,
+//! )
+//! .finalize(components::indoor_air_quality_component_static!());
+//! ```
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::IndoorAirQuality as usize;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const IAQ_SCORE: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct IndoorAirQualityDriver<'a> {
+    sensor: &'a dyn hil::sensors::IndoorAirQuality<'a>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process whose estimate is in progress.
+    processid: OptionalCell<ProcessId>,
+}
+
+impl<'a> IndoorAirQualityDriver<'a> {
+    pub fn new(
+        sensor: &'a dyn hil::sensors::IndoorAirQuality<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> IndoorAirQualityDriver<'a> {
+        IndoorAirQualityDriver {
+            sensor,
+            apps: grant,
+            processid: OptionalCell::empty(),
+        }
+    }
+
+    fn start_estimate(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.processid.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.sensor.read_iaq_score()?;
+        self.processid.set(processid);
+        Ok(())
+    }
+}
+
+impl SyscallDriver for IndoorAirQualityDriver<'_> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Estimate the indoor air quality.
+    /// - `2`: Set heater set point `arg1` to the temperature and duration
+    ///   packed in `arg2`.
+    /// - `3`: Use the first `arg1` set points of the heater profile.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start_estimate(processid).into(),
+            2 => self
+                .sensor
+                .set_heater_step(arg1, arg2 as u16, (arg2 >> 16) as u16)
+                .into(),
+            3 => self.sensor.set_heater_steps(arg1).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl hil::sensors::IndoorAirQualityClient for IndoorAirQualityDriver<'_> {
+    fn iaq_score(&self, score: Result<u16, ErrorCode>) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let (status, score) = match score {
+                    Ok(score) => (0, score as usize),
+                    Err(e) => (into_statuscode(Err(e)), 0),
+                };
+                kernel_data
+                    .schedule_upcall(upcall::IAQ_SCORE, (status, score, 0))
+                    .ok();
+            });
+        });
+    }
+}
@@ -0,0 +1,625 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Infrared remote control codes using the NEC protocol.
+//!
+//! An NEC frame is a 9 ms burst of the 38 kHz carrier and a 4.5 ms space,
+//! followed by 32 bits, least significant first: the address, the inverted
+//! address, the command and the inverted command. Each bit is a 562.5 us
+//! burst, then a 562.5 us space for a zero or a 1687.5 us space for a one.
+//! A final 562.5 us burst ends the frame. While a button is held, the
+//! remote sends repeat codes: a 9 ms burst, a 2.25 ms space and a 562.5 us
+//! burst.
+//!
+//! [`IrNecRx`] decodes frames from a demodulating IR receiver, such as a
+//! TSOP38238, whose output is low during a burst. It timestamps every edge
+//! of the output and decodes the lengths of the bursts and spaces, which
+//! may be a quarter off. Frames whose inverted bytes do not match are
+//! dropped. A repeat code is reported as the last frame received.
+//!
+//! [`IrNecTx`] sends frames by driving an IR LED with a 38 kHz PWM carrier
+//! at a third of the duty cycle, turned on and off with an alarm.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a frame is received, with the address, the command,
+//!   and `1` if it was a repeat code.
+//! * `1`: called when a frame has been sent, with its status.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: start receiving frames
+//! * `2`: stop receiving frames
+//! * `3`: send the frame with address `arg1` and command `arg2`
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ir_nec = components::ir_nec::IrNecComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ir_nec::DRIVER_NUM,
+//!     &nrf52840_peripherals.gpio_port[IR_RX],
+//!     ir_led_pwm_pin,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ir_nec_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     capsules_core::virtualizers::virtual_pwm::PwmPinUser<'static, nrf52840::pwm::Pwm>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::pwm;
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::IrNec as usize;
+
+/// Frequency of the carrier.
+pub const CARRIER_HZ: usize = 38_000;
+
+const LEADER_MARK_US: u32 = 9000;
+const LEADER_SPACE_US: u32 = 4500;
+const REPEAT_SPACE_US: u32 = 2250;
+const BIT_MARK_US: u32 = 562;
+const ZERO_SPACE_US: u32 = 562;
+const ONE_SPACE_US: u32 = 1687;
+
+const FRAME_BITS: u8 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrNecFrame {
+    pub address: u8,
+    pub command: u8,
+}
+
+impl IrNecFrame {
+    /// The 32 bits sent for the frame, in the order they are sent.
+    fn to_bits(self) -> u32 {
+        u32::from_le_bytes([self.address, !self.address, self.command, !self.command])
+    }
+
+    /// Checks the inverted bytes of a received frame.
+    fn from_bits(bits: u32) -> Option<IrNecFrame> {
+        let [address, not_address, command, not_command] = bits.to_le_bytes();
+        if address != !not_address || command != !not_command {
+            return None;
+        }
+        Some(IrNecFrame { address, command })
+    }
+}
+
+/// Whether `us` is within a quarter of `expected`.
+fn near(us: u32, expected: u32) -> bool {
+    us.abs_diff(expected) <= expected / 4
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RxState {
+    Idle,
+    /// Received the leader burst.
+    Leader,
+    /// Received the space of a repeat code.
+    Repeat,
+    /// Waiting for the burst starting bit `count`, or ending the frame.
+    Mark {
+        bits: u32,
+        count: u8,
+    },
+    /// Waiting for the space of bit `count`.
+    Space {
+        bits: u32,
+        count: u8,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Received {
+    Frame(IrNecFrame),
+    Repeat,
+}
+
+/// Decodes a burst (`mark`) or a space lasting `us`.
+fn decode(state: RxState, mark: bool, us: u32) -> (RxState, Option<Received>) {
+    match (state, mark) {
+        (RxState::Leader, false) if near(us, LEADER_SPACE_US) => {
+            (RxState::Mark { bits: 0, count: 0 }, None)
+        }
+        (RxState::Leader, false) if near(us, REPEAT_SPACE_US) => (RxState::Repeat, None),
+        (RxState::Repeat, true) if near(us, BIT_MARK_US) => (RxState::Idle, Some(Received::Repeat)),
+        (RxState::Mark { bits, count }, true) if near(us, BIT_MARK_US) => {
+            if count == FRAME_BITS {
+                (
+                    RxState::Idle,
+                    IrNecFrame::from_bits(bits).map(Received::Frame),
+                )
+            } else {
+                (RxState::Space { bits, count }, None)
+            }
+        }
+        (RxState::Space { bits, count }, false) if near(us, ZERO_SPACE_US) => (
+            RxState::Mark {
+                bits,
+                count: count + 1,
+            },
+            None,
+        ),
+        (RxState::Space { bits, count }, false) if near(us, ONE_SPACE_US) => (
+            RxState::Mark {
+                bits: bits | 1 << count,
+                count: count + 1,
+            },
+            None,
+        ),
+        // Anything else ends the frame, but may start the next one.
+        (_, true) if near(us, LEADER_MARK_US) => (RxState::Leader, None),
+        _ => (RxState::Idle, None),
+    }
+}
+
+/// The burst (`true`) or space of step `step` of sending `bits`, and its
+/// length.
+fn segment(bits: u32, step: usize) -> Option<(bool, u32)> {
+    let bit = |i: usize| bits >> i & 1 == 1;
+    match step {
+        0 => Some((true, LEADER_MARK_US)),
+        1 => Some((false, LEADER_SPACE_US)),
+        // The burst of each bit, and the one ending the frame.
+        s if s % 2 == 0 && s <= 2 + 2 * FRAME_BITS as usize => Some((true, BIT_MARK_US)),
+        s if s % 2 == 1 && s < 2 + 2 * FRAME_BITS as usize => {
+            let space = if bit((s - 3) / 2) {
+                ONE_SPACE_US
+            } else {
+                ZERO_SPACE_US
+            };
+            Some((false, space))
+        }
+        _ => None,
+    }
+}
+
+pub trait IrNecRxClient {
+    /// Called when a frame is received. `repeat` is set if the remote sent
+    /// a repeat code, and `frame` is then the last frame received.
+    fn frame_received(&self, frame: IrNecFrame, repeat: bool);
+}
+
+pub trait IrNecTxClient {
+    /// Called when a frame has been sent.
+    fn send_done(&self, result: Result<(), ErrorCode>);
+}
+
+pub struct IrNecRx<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> {
+    pin: &'a G,
+    alarm: &'a A,
+    last_edge: Cell<A::Ticks>,
+    state: Cell<RxState>,
+    last_frame: OptionalCell<IrNecFrame>,
+    client: OptionalCell<&'a dyn IrNecRxClient>,
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> IrNecRx<'a, G, A> {
+    pub fn new(pin: &'a G, alarm: &'a A) -> IrNecRx<'a, G, A> {
+        IrNecRx {
+            pin,
+            alarm,
+            last_edge: Cell::new(A::Ticks::from(0)),
+            state: Cell::new(RxState::Idle),
+            last_frame: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn IrNecRxClient) {
+        self.client.set(client);
+    }
+
+    pub fn start(&self) {
+        self.pin.make_input();
+        self.state.set(RxState::Idle);
+        self.last_edge.set(self.alarm.now());
+        self.pin.enable_interrupts(gpio::InterruptEdge::EitherEdge);
+    }
+
+    pub fn stop(&self) {
+        self.pin.disable_interrupts();
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Client for IrNecRx<'a, G, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        let us = self
+            .alarm
+            .ticks_to_us(now.wrapping_sub(self.last_edge.get()));
+        self.last_edge.set(now);
+
+        // The output is low during a burst, so a burst just ended if it is
+        // now high.
+        let mark = self.pin.read();
+        let (state, received) = decode(self.state.get(), mark, us);
+        self.state.set(state);
+
+        let (frame, repeat) = match received {
+            Some(Received::Frame(frame)) => {
+                self.last_frame.set(frame);
+                (frame, false)
+            }
+            Some(Received::Repeat) => match self.last_frame.extract() {
+                Some(frame) => (frame, true),
+                None => return,
+            },
+            None => return,
+        };
+        self.client
+            .map(|client| client.frame_received(frame, repeat));
+    }
+}
+
+pub struct IrNecTx<'a, P: pwm::PwmPin, A: Alarm<'a>> {
+    pin: &'a P,
+    alarm: &'a A,
+    bits: Cell<u32>,
+    /// The next step of the frame, while sending.
+    step: OptionalCell<usize>,
+    client: OptionalCell<&'a dyn IrNecTxClient>,
+}
+
+impl<'a, P: pwm::PwmPin, A: Alarm<'a>> IrNecTx<'a, P, A> {
+    pub fn new(pin: &'a P, alarm: &'a A) -> IrNecTx<'a, P, A> {
+        IrNecTx {
+            pin,
+            alarm,
+            bits: Cell::new(0),
+            step: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn IrNecTxClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.step.is_some()
+    }
+
+    pub fn send(&self, frame: IrNecFrame) -> Result<(), ErrorCode> {
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.bits.set(frame.to_bits());
+        self.next_segment(0).map_err(|e| {
+            self.step.clear();
+            e
+        })
+    }
+
+    /// Turns the carrier on or off for step `step`, or ends the frame.
+    fn next_segment(&self, step: usize) -> Result<(), ErrorCode> {
+        match segment(self.bits.get(), step) {
+            Some((mark, us)) => {
+                if mark {
+                    self.pin
+                        .start(CARRIER_HZ, self.pin.get_maximum_duty_cycle() / 3)?;
+                } else {
+                    self.pin.stop()?;
+                }
+                self.step.set(step + 1);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(us));
+                Ok(())
+            }
+            None => {
+                self.step.clear();
+                let result = self.pin.stop();
+                self.client.map(|client| client.send_done(result));
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'a, P: pwm::PwmPin, A: Alarm<'a>> time::AlarmClient for IrNecTx<'a, P, A> {
+    fn alarm(&self) {
+        if let Some(step) = self.step.extract() {
+            if let Err(e) = self.next_segment(step) {
+                self.step.clear();
+                let _ = self.pin.stop();
+                self.client.map(|client| client.send_done(Err(e)));
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    receiving: bool,
+}
+
+pub struct IrNecDriver<'a, G: gpio::InterruptPin<'a>, P: pwm::PwmPin, A: Alarm<'a>> {
+    rx: &'a IrNecRx<'a, G, A>,
+    tx: &'a IrNecTx<'a, P, A>,
+    apps: Grant<App, UpcallCount<2>, AllowRoCount<0>, AllowRwCount<0>>,
+    sending: OptionalCell<ProcessId>,
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, P: pwm::PwmPin, A: Alarm<'a>> IrNecDriver<'a, G, P, A> {
+    pub fn new(
+        rx: &'a IrNecRx<'a, G, A>,
+        tx: &'a IrNecTx<'a, P, A>,
+        grant: Grant<App, UpcallCount<2>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> IrNecDriver<'a, G, P, A> {
+        IrNecDriver {
+            rx,
+            tx,
+            apps: grant,
+            sending: OptionalCell::empty(),
+        }
+    }
+
+    fn set_receiving(&self, receiving: bool, processid: ProcessId) -> Result<(), ErrorCode> {
+        let was_receiving = self.any_receiving();
+        self.apps
+            .enter(processid, |app, _| app.receiving = receiving)
+            .map_err(ErrorCode::from)?;
+        match (was_receiving, self.any_receiving()) {
+            (false, true) => self.rx.start(),
+            (true, false) => self.rx.stop(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn any_receiving(&self) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.enter(|app, _| app.receiving))
+    }
+
+    fn send(&self, frame: IrNecFrame, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |_, _| {
+                self.tx.send(frame)?;
+                self.sending.set(processid);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, P: pwm::PwmPin, A: Alarm<'a>> IrNecRxClient
+    for IrNecDriver<'a, G, P, A>
+{
+    fn frame_received(&self, frame: IrNecFrame, repeat: bool) {
+        self.apps.each(|_, app, upcalls| {
+            if app.receiving {
+                upcalls
+                    .schedule_upcall(
+                        0,
+                        (
+                            frame.address as usize,
+                            frame.command as usize,
+                            repeat as usize,
+                        ),
+                    )
+                    .ok();
+            }
+        });
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, P: pwm::PwmPin, A: Alarm<'a>> IrNecTxClient
+    for IrNecDriver<'a, G, P, A>
+{
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        self.sending.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, upcalls| {
+                upcalls
+                    .schedule_upcall(1, (into_statuscode(result), 0, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, P: pwm::PwmPin, A: Alarm<'a>> SyscallDriver
+    for IrNecDriver<'a, G, P, A>
+{
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start receiving frames.
+    /// - `2`: Stop receiving frames.
+    /// - `3`: Send the frame with address `data1` and command `data2`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.set_receiving(true, processid).into(),
+            2 => self.set_receiving(false, processid).into(),
+            3 => {
+                if data1 > u8::MAX as usize || data2 > u8::MAX as usize {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let frame = IrNecFrame {
+                    address: data1 as u8,
+                    command: data2 as u8,
+                };
+                self.send(frame, processid).into()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Decodes bursts and spaces, starting with a burst.
+    fn decode_all(lengths: &[u32]) -> Vec<Received> {
+        let mut state = RxState::Idle;
+        let mut received = Vec::new();
+        for (i, &us) in lengths.iter().enumerate() {
+            let (next, r) = decode(state, i % 2 == 0, us);
+            state = next;
+            received.extend(r);
+        }
+        received
+    }
+
+    /// The burst and space lengths of a frame, measured by a receiver
+    /// whose bursts are 50 us longer than sent.
+    fn measured(bytes: [u8; 4]) -> Vec<u32> {
+        let mut lengths = std::vec![9050, 4450];
+        for bit in 0..32 {
+            let one = bytes[bit / 8] >> (bit % 8) & 1 == 1;
+            lengths.push(612);
+            lengths.push(if one { 1637 } else { 512 });
+        }
+        lengths.push(612);
+        lengths
+    }
+
+    #[test]
+    fn decodes_frame() {
+        // The power button of a common remote.
+        let frame = IrNecFrame {
+            address: 0x00,
+            command: 0x45,
+        };
+        assert_eq!(
+            decode_all(&measured([0x00, 0xFF, 0x45, 0xBA])),
+            [Received::Frame(frame)]
+        );
+
+        // Followed by a repeat code.
+        let mut lengths = measured([0x00, 0xFF, 0x45, 0xBA]);
+        lengths.extend([40_000, 9000, 2250, 562]);
+        assert_eq!(
+            decode_all(&lengths),
+            [Received::Frame(frame), Received::Repeat]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        // The inverted command does not match.
+        assert_eq!(decode_all(&measured([0x00, 0xFF, 0x45, 0xBB])), []);
+
+        // A space that is neither a zero nor a one.
+        let mut lengths = measured([0x00, 0xFF, 0x45, 0xBA]);
+        lengths[5] = 1100;
+        assert_eq!(decode_all(&lengths), []);
+
+        // A new leader restarts decoding.
+        let mut lengths = std::vec![9000, 4500, 562, 562];
+        lengths.extend(measured([0x12, 0xED, 0x34, 0xCB]));
+        assert_eq!(
+            decode_all(&lengths),
+            [Received::Frame(IrNecFrame {
+                address: 0x12,
+                command: 0x34
+            })]
+        );
+    }
+
+    struct MockPwm {
+        on: Cell<bool>,
+    }
+
+    impl pwm::PwmPin for MockPwm {
+        fn start(&self, frequency_hz: usize, duty_cycle: usize) -> Result<(), ErrorCode> {
+            assert_eq!(frequency_hz, CARRIER_HZ);
+            assert_eq!(duty_cycle, 100);
+            self.on.set(true);
+            Ok(())
+        }
+        fn stop(&self) -> Result<(), ErrorCode> {
+            self.on.set(false);
+            Ok(())
+        }
+        fn get_maximum_frequency_hz(&self) -> usize {
+            1_000_000
+        }
+        fn get_maximum_duty_cycle(&self) -> usize {
+            300
+        }
+    }
+
+    struct Client {
+        done: Cell<bool>,
+    }
+
+    impl IrNecTxClient for Client {
+        fn send_done(&self, result: Result<(), ErrorCode>) {
+            assert_eq!(result, Ok(()));
+            self.done.set(true);
+        }
+    }
+
+    #[test]
+    fn sent_frame_decodes() {
+        let pin = Box::leak(Box::new(MockPwm {
+            on: Cell::new(false),
+        }));
+        let alarm = Box::leak(Box::new(MockAlarm::<Freq1MHz>::new()));
+        let tx = Box::leak(Box::new(IrNecTx::new(pin, alarm)));
+        let client = Box::leak(Box::new(Client {
+            done: Cell::new(false),
+        }));
+        alarm.set_alarm_client(tx);
+        tx.set_client(client);
+
+        let frame = IrNecFrame {
+            address: 0xA5,
+            command: 0x3C,
+        };
+        assert_eq!(tx.send(frame), Ok(()));
+        assert_eq!(tx.send(frame), Err(ErrorCode::BUSY));
+
+        // Record the carrier as the receiver would see it.
+        let mut lengths = Vec::new();
+        while let Some(dt) = alarm.dt() {
+            assert_eq!(pin.on.get(), lengths.len() % 2 == 0);
+            lengths.push(dt);
+            alarm.fire();
+        }
+        assert!(client.done.get());
+        assert!(!pin.on.get());
+        assert_eq!(lengths.len(), 2 + 2 * 32 + 1);
+        assert_eq!(decode_all(&lengths), [Received::Frame(frame)]);
+    }
+}
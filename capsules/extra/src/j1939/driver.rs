@@ -0,0 +1,321 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Syscall driver for the J1939 stack.
+//!
+//! Processes register the PGNs they want to receive and are sent every
+//! message of those PGNs addressed to this node or broadcast. Any process
+//! can send messages; sends are queued and done one at a time.
+//!
+//! Commands
+//! --------
+//!
+//! - 0: Driver existence check.
+//! - 1: Receive messages of PGN `arg1`, whose low byte is ignored for
+//!   addressed (PDU1) PGNs. Fails with `NOMEM` once a process
+//!   has registered `MAX_PGNS` PGNs.
+//! - 2: Stop receiving messages of PGN `arg1`.
+//! - 3: Send the first `arg2 >> 8` bytes of the read-only allow buffer to
+//!   address `arg2 & 0xFF` as a message of PGN `arg1 & 0x3FFFF` with
+//!   priority `arg1 >> 24`. The buffer is read when the message is started,
+//!   which may be after other processes' messages.
+//! - 4: Get the claimed address.
+//!
+//! Upcalls
+//! -------
+//!
+//! - 0: A message was received into the read-write allow buffer, truncated
+//!   to its length: `(pgn, length, source | destination << 8 | priority << 16)`.
+//! - 1: A send finished: `(statuscode, pgn, 0)`.
+//! - 2: Address claiming finished: `(statuscode, address, 0)`.
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::can;
+use kernel::hil::can::j1939::{self, J1939Frame};
+use kernel::hil::time::Alarm;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use super::{J1939Client, J1939Stack};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::J1939 as usize;
+
+/// The PGNs a process can receive.
+pub const MAX_PGNS: usize = 8;
+
+mod up_calls {
+    pub const UPCALL_MESSAGE_RECEIVED: usize = 0;
+    pub const UPCALL_SEND_DONE: usize = 1;
+    pub const UPCALL_ADDRESS_CLAIMED: usize = 2;
+    pub const COUNT: u8 = 3;
+}
+
+mod ro_allow {
+    pub const SEND: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+mod rw_allow {
+    pub const RECEIVE: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    pgns: [Option<u32>; MAX_PGNS],
+    /// A message waiting for the stack, and its length.
+    pending_send: Option<(J1939Frame, usize)>,
+}
+
+pub struct J1939Driver<'a, C: can::Can, A: Alarm<'a>> {
+    stack: &'a J1939Stack<'a, C, A>,
+    apps: Grant<
+        App,
+        UpcallCount<{ up_calls::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    buffer: TakeCell<'static, [u8]>,
+    /// The process whose message the stack is sending.
+    sending: OptionalCell<(ProcessId, u32)>,
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> J1939Driver<'a, C, A> {
+    pub fn new(
+        stack: &'a J1939Stack<'a, C, A>,
+        apps: Grant<
+            App,
+            UpcallCount<{ up_calls::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        buffer: &'static mut [u8],
+    ) -> J1939Driver<'a, C, A> {
+        J1939Driver {
+            stack,
+            apps,
+            buffer: TakeCell::new(buffer),
+            sending: OptionalCell::empty(),
+        }
+    }
+
+    /// Start the next queued message if the stack is free.
+    fn send_next(&self) {
+        if self.sending.is_some() {
+            return;
+        }
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let started = app.enter(|app, kernel_data| {
+                let (frame, len) = match app.pending_send.take() {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                let copied = kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEND)
+                    .and_then(|send| {
+                        send.enter(|data| {
+                            if data.len() < len || buffer.len() < len {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data[..len].copy_to_slice(&mut buffer[..len]);
+                                Ok(())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE));
+                if let Err(error) = copied {
+                    kernel_data
+                        .schedule_upcall(
+                            up_calls::UPCALL_SEND_DONE,
+                            (into_statuscode(Err(error)), frame.pgn as usize, 0),
+                        )
+                        .ok();
+                    return None;
+                }
+                Some((frame, len))
+            });
+            if let Some((frame, len)) = started {
+                match self.stack.send(frame, buffer, len) {
+                    Ok(()) => {
+                        self.sending.set((processid, frame.pgn));
+                        return;
+                    }
+                    Err((error, returned)) => {
+                        self.buffer.replace(returned);
+                        let _ = self.apps.enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .schedule_upcall(
+                                    up_calls::UPCALL_SEND_DONE,
+                                    (into_statuscode(Err(error)), frame.pgn as usize, 0),
+                                )
+                                .ok();
+                        });
+                        // Try the next process with the buffer back.
+                        return self.send_next();
+                    }
+                }
+            }
+        }
+        self.buffer.replace(buffer);
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> SyscallDriver for J1939Driver<'a, C, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Register a PGN
+            1 => {
+                let pgn = J1939Frame::new(0, arg1 as u32, 0, 0).pgn;
+                self.apps
+                    .enter(processid, |app, _| {
+                        if app.pgns.contains(&Some(pgn)) {
+                            CommandReturn::failure(ErrorCode::ALREADY)
+                        } else if let Some(slot) = app.pgns.iter_mut().find(|p| p.is_none()) {
+                            *slot = Some(pgn);
+                            CommandReturn::success()
+                        } else {
+                            CommandReturn::failure(ErrorCode::NOMEM)
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            // Unregister a PGN
+            2 => {
+                let pgn = J1939Frame::new(0, arg1 as u32, 0, 0).pgn;
+                self.apps
+                    .enter(processid, |app, _| {
+                        match app.pgns.iter_mut().find(|p| **p == Some(pgn)) {
+                            Some(slot) => {
+                                *slot = None;
+                                CommandReturn::success()
+                            }
+                            None => CommandReturn::failure(ErrorCode::INVAL),
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            // Send a message
+            3 => {
+                let len = arg2 >> 8;
+                if len > j1939::MAX_MESSAGE_LEN {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                if let Err(error) = self.stack.address() {
+                    return CommandReturn::failure(error);
+                }
+                let frame = J1939Frame::new((arg1 >> 24) as u8, arg1 as u32, 0, arg2 as u8);
+                let queued = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if app.pending_send.is_some() {
+                            Err(ErrorCode::BUSY)
+                        } else {
+                            app.pending_send = Some((frame, len));
+                            Ok(())
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match queued {
+                    Ok(()) => {
+                        self.send_next();
+                        CommandReturn::success()
+                    }
+                    Err(error) => CommandReturn::failure(error),
+                }
+            }
+
+            // Get the claimed address
+            4 => match self.stack.address() {
+                Ok(address) => CommandReturn::success_u32(address as u32),
+                Err(error) => CommandReturn::failure(error),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> J1939Client for J1939Driver<'a, C, A> {
+    fn message_received(&self, frame: J1939Frame, data: &[u8]) {
+        let header = frame.source as usize
+            | (frame.destination as usize) << 8
+            | (frame.priority as usize) << 16;
+        for app in self.apps.iter() {
+            app.enter(|app, kernel_data| {
+                if !app.pgns.contains(&Some(frame.pgn)) {
+                    return;
+                }
+                let _ = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .and_then(|receive| {
+                        receive.mut_enter(|buffer| {
+                            let len = core::cmp::min(buffer.len(), data.len());
+                            buffer[..len].copy_from_slice(&data[..len]);
+                        })
+                    });
+                kernel_data
+                    .schedule_upcall(
+                        up_calls::UPCALL_MESSAGE_RECEIVED,
+                        (frame.pgn as usize, data.len(), header),
+                    )
+                    .ok();
+            });
+        }
+    }
+
+    fn send_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.buffer.replace(buffer);
+        if let Some((processid, pgn)) = self.sending.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        up_calls::UPCALL_SEND_DONE,
+                        (into_statuscode(result), pgn as usize, 0),
+                    )
+                    .ok();
+            });
+        }
+        self.send_next();
+    }
+
+    fn address_claimed(&self, result: Result<u8, ErrorCode>) {
+        let (status, address) = match result {
+            Ok(address) => (Ok(()), address as usize),
+            Err(error) => (Err(error), 0),
+        };
+        for app in self.apps.iter() {
+            app.enter(|_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        up_calls::UPCALL_ADDRESS_CLAIMED,
+                        (into_statuscode(status), address, 0),
+                    )
+                    .ok();
+            });
+        }
+    }
+}
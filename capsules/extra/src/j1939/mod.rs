@@ -0,0 +1,1148 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! SAE J1939 protocol layer over the CAN HIL.
+//!
+//! J1939 is the higher layer protocol used on the CAN bus of commercial
+//! vehicles. [`J1939Stack`] implements the parts of it every node needs:
+//!
+//! - Address claiming (J1939-81). After the controller is enabled the stack
+//!   broadcasts an Address Claimed message with its 64-bit NAME and waits
+//!   250 ms. If another node claims the same address with a lower NAME, the
+//!   stack tries the next address in the self-configurable range 128 to 247
+//!   if its NAME says it is arbitrary address capable, and otherwise sends
+//!   Cannot Claim. A claim with a higher NAME is answered by claiming the
+//!   address again, as is a request for the Address Claimed PGN.
+//! - The transport protocol (J1939-21) for messages of 9 to 1785 bytes. A
+//!   broadcast message is announced with TP.CM_BAM and its TP.DT packets
+//!   sent 50 ms apart. A message to a single node is announced with
+//!   TP.CM_RTS and sent in the windows the destination grants with
+//!   TP.CM_CTS, until it acknowledges it with TP.CM_EndOfMsgAck. The stack
+//!   receives both kinds, one message at a time, and aborts a second RTS
+//!   while it is receiving.
+//!
+//! Complete messages addressed to the claimed address or broadcast are
+//! passed to the [`J1939Client`]; [`driver::J1939Driver`] routes them to
+//! the processes that registered for their PGN.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let j1939 = components::j1939::J1939StackComponent::new(
+//!     board_kernel,
+//!     capsules_extra::j1939::driver::DRIVER_NUM,
+//!     &peripherals.can1,
+//!     mux_alarm,
+//!     NAME,
+//!     0x80,
+//!     250_000,
+//! )
+//! .finalize(components::j1939_stack_component_static!(
+//!     stm32f429zi::can::Can<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>,
+//! ));
+//! ```
+
+pub mod driver;
+
+use core::cell::Cell;
+
+use kernel::hil::can::j1939::{self, J1939Frame, GLOBAL_ADDRESS, NULL_ADDRESS};
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const PGN_REQUEST: u32 = 0xEA00;
+pub const PGN_ADDRESS_CLAIMED: u32 = 0xEE00;
+pub const PGN_TP_CM: u32 = 0xEC00;
+pub const PGN_TP_DT: u32 = 0xEB00;
+
+/// Priority of address claims.
+const CLAIM_PRIORITY: u8 = 6;
+/// Priority of transport protocol frames.
+const TP_PRIORITY: u8 = 7;
+
+// TP.CM control bytes.
+const TP_CM_RTS: u8 = 16;
+const TP_CM_CTS: u8 = 17;
+const TP_CM_EOMA: u8 = 19;
+const TP_CM_BAM: u8 = 32;
+const TP_CM_ABORT: u8 = 255;
+
+// Reasons for TP.CM_Abort.
+const ABORT_BUSY: u8 = 1;
+const ABORT_RESOURCES: u8 = 2;
+const ABORT_TIMEOUT: u8 = 3;
+
+/// Bytes of a message in one TP.DT packet.
+const PACKET_DATA_LEN: usize = 7;
+
+/// Time for other nodes to contest an address claim.
+const CLAIM_WINDOW_MS: u32 = 250;
+/// Time between the packets of a broadcast message.
+const BAM_PACKET_GAP_MS: u32 = 50;
+/// T1: longest gap between received packets.
+const T1_MS: u32 = 750;
+/// T2: longest wait for data after sending a CTS.
+const T2_MS: u32 = 1250;
+/// T3: longest wait for a CTS or an acknowledgment after sending data.
+const T3_MS: u32 = 1250;
+
+/// The self-configurable addresses tried by an arbitrary address capable
+/// node.
+const ARBITRARY_ADDRESSES: core::ops::RangeInclusive<u8> = 128..=247;
+
+// Frames waiting to be sent, in order of precedence.
+const PENDING_CLAIM: u8 = 1 << 0;
+const PENDING_RX_ABORT: u8 = 1 << 1;
+const PENDING_TX_ABORT: u8 = 1 << 2;
+const PENDING_RX_CTS: u8 = 1 << 3;
+const PENDING_RX_EOMA: u8 = 1 << 4;
+const PENDING_TX: u8 = 1 << 5;
+
+pub trait J1939Client {
+    /// A message for this node or for all nodes was received.
+    fn message_received(&self, frame: J1939Frame, data: &[u8]);
+
+    /// The message passed to `J1939Stack::send` was sent, and acknowledged
+    /// if it used the transport protocol to a single node.
+    fn send_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// Address claiming finished with the claimed address, or `RESERVE` if
+    /// every address it could use is taken.
+    fn address_claimed(&self, result: Result<u8, ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ClaimState {
+    Stopped,
+    /// Waiting for the controller to be enabled.
+    Enabling,
+    /// Claimed the address, other nodes may still contest it.
+    Claiming(u8),
+    Claimed(u8),
+    CannotClaim,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TxState {
+    Idle,
+    /// A message of up to 8 bytes.
+    Single,
+    /// The TP.CM_BAM or TP.CM_RTS of a longer message.
+    Announce,
+    WaitCts,
+    /// Sending the TP.DT packets from `next` to `last`.
+    Data {
+        next: u8,
+        last: u8,
+    },
+    WaitAck,
+}
+
+/// A message being received with the transport protocol.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct RxSession {
+    /// Whether the message is broadcast rather than sent with RTS/CTS.
+    broadcast: bool,
+    source: u8,
+    pgn: u32,
+    len: usize,
+    packets: u8,
+    /// The packets the sender may send per CTS.
+    window: u8,
+    next: u8,
+    /// The last packet of the current CTS window.
+    last: u8,
+}
+
+pub struct J1939Stack<'a, C: can::Can, A: Alarm<'a>> {
+    can: &'a C,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn J1939Client>,
+    name: u64,
+    preferred_address: u8,
+    bitrate: u32,
+    claim: Cell<ClaimState>,
+    /// Addresses tried since the last successful claim.
+    claim_attempts: Cell<u8>,
+
+    can_tx: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    can_rx: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    pending: Cell<u8>,
+    in_flight: Cell<u8>,
+
+    tx: Cell<TxState>,
+    tx_frame: Cell<J1939Frame>,
+    tx_len: Cell<usize>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// The destination and PGN of an abort of the message being sent.
+    tx_abort: Cell<(u8, u32)>,
+
+    rx: Cell<Option<RxSession>>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    /// The destination, PGN and reason of an abort to a sender.
+    rx_abort: Cell<(u8, u32, u8)>,
+    /// The destination, PGN, length and packet count of an acknowledgment
+    /// of a received message.
+    rx_ack: Cell<(u8, u32, u16, u8)>,
+
+    // Deadlines, as the alarm reference and interval.
+    claim_deadline: Cell<Option<(A::Ticks, A::Ticks)>>,
+    tx_deadline: Cell<Option<(A::Ticks, A::Ticks)>>,
+    rx_deadline: Cell<Option<(A::Ticks, A::Ticks)>>,
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> J1939Stack<'a, C, A> {
+    pub fn new(
+        can: &'a C,
+        alarm: &'a A,
+        name: u64,
+        preferred_address: u8,
+        bitrate: u32,
+        can_tx: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        can_rx: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        rx_buffer: &'static mut [u8],
+    ) -> J1939Stack<'a, C, A> {
+        J1939Stack {
+            can,
+            alarm,
+            client: OptionalCell::empty(),
+            name,
+            preferred_address,
+            bitrate,
+            claim: Cell::new(ClaimState::Stopped),
+            claim_attempts: Cell::new(0),
+            can_tx: TakeCell::new(can_tx),
+            can_rx: TakeCell::new(can_rx),
+            pending: Cell::new(0),
+            in_flight: Cell::new(0),
+            tx: Cell::new(TxState::Idle),
+            tx_frame: Cell::new(J1939Frame::new(0, 0, NULL_ADDRESS, GLOBAL_ADDRESS)),
+            tx_len: Cell::new(0),
+            tx_buffer: TakeCell::empty(),
+            tx_abort: Cell::new((GLOBAL_ADDRESS, 0)),
+            rx: Cell::new(None),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_abort: Cell::new((GLOBAL_ADDRESS, 0, 0)),
+            rx_ack: Cell::new((GLOBAL_ADDRESS, 0, 0, 0)),
+            claim_deadline: Cell::new(None),
+            tx_deadline: Cell::new(None),
+            rx_deadline: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn J1939Client) {
+        self.client.set(client);
+    }
+
+    /// Configure and enable the CAN controller, then claim an address.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.claim.get() != ClaimState::Stopped {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.can.set_bitrate(self.bitrate)?;
+        self.can.set_operation_mode(can::OperationMode::Normal)?;
+        self.can.enable()?;
+        self.claim.set(ClaimState::Enabling);
+        Ok(())
+    }
+
+    /// The claimed address, `BUSY` while claiming and `RESERVE` if no
+    /// address could be claimed.
+    pub fn address(&self) -> Result<u8, ErrorCode> {
+        match self.claim.get() {
+            ClaimState::Claimed(address) => Ok(address),
+            ClaimState::Stopped => Err(ErrorCode::OFF),
+            ClaimState::Enabling | ClaimState::Claiming(_) => Err(ErrorCode::BUSY),
+            ClaimState::CannotClaim => Err(ErrorCode::RESERVE),
+        }
+    }
+
+    /// Send the first `len` bytes of `buffer` as a message of the PGN,
+    /// priority and destination in `frame`, from the claimed address.
+    /// Messages longer than 8 bytes use the transport protocol.
+    pub fn send(
+        &self,
+        frame: J1939Frame,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let source = match self.address() {
+            Ok(address) => address,
+            Err(error) => return Err((error, buffer)),
+        };
+        if self.tx.get() != TxState::Idle {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if len > buffer.len() || len > j1939::MAX_MESSAGE_LEN {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if matches!(frame.pgn, PGN_TP_CM | PGN_TP_DT | PGN_ADDRESS_CLAIMED) {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+
+        self.tx_frame.set(J1939Frame::new(
+            frame.priority,
+            frame.pgn,
+            source,
+            frame.destination,
+        ));
+        self.tx_len.set(len);
+        self.tx_buffer.replace(buffer);
+        self.tx.set(if len <= STANDARD_CAN_PACKET_SIZE {
+            TxState::Single
+        } else {
+            TxState::Announce
+        });
+        self.pending.set(self.pending.get() | PENDING_TX);
+        self.send_next().or_else(|error| {
+            self.tx.set(TxState::Idle);
+            self.tx_buffer
+                .take()
+                .map_or(Ok(()), |buffer| Err((error, buffer)))
+        })
+    }
+
+    fn schedule(&self, pending: u8) {
+        self.pending.set(self.pending.get() | pending);
+        self.service();
+    }
+
+    /// Send pending frames from a callback, where a refused frame of the
+    /// message being sent ends it.
+    fn service(&self) {
+        if self.send_next().is_err() {
+            self.finish_tx(Err(ErrorCode::FAIL));
+        }
+    }
+
+    fn own_address(&self) -> Option<u8> {
+        match self.claim.get() {
+            ClaimState::Claiming(address) | ClaimState::Claimed(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    fn tx_packets(&self) -> u8 {
+        ((self.tx_len.get() + PACKET_DATA_LEN - 1) / PACKET_DATA_LEN) as u8
+    }
+
+    /// Send pending frames while the controller is free. Fails if the
+    /// controller refused a frame of the message being sent.
+    fn send_next(&self) -> Result<(), ErrorCode> {
+        let mut result = Ok(());
+        while self.pending.get() != 0 {
+            let buffer = match self.can_tx.take() {
+                Some(buffer) => buffer,
+                None => break,
+            };
+            let pending = self.pending.get();
+            let work = pending & pending.wrapping_neg();
+            self.pending.set(pending & !work);
+
+            buffer.fill(0xFF);
+            let (frame, len) = match self.build(work, buffer) {
+                Some(built) => built,
+                None => {
+                    self.can_tx.replace(buffer);
+                    continue;
+                }
+            };
+            self.in_flight.set(work);
+            if let Err((error, buffer)) = self.can.send(can::Id::Extended(frame.id()), buffer, len)
+            {
+                self.can_tx.replace(buffer);
+                self.in_flight.set(0);
+                if work == PENDING_TX {
+                    result = Err(error);
+                }
+            }
+        }
+        result
+    }
+
+    /// Fill `buffer` with the frame for `work`, if it is still needed.
+    fn build(
+        &self,
+        work: u8,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Option<(J1939Frame, usize)> {
+        let source = self.own_address().unwrap_or(NULL_ADDRESS);
+        let control = |destination: u8, pgn: u32, bytes: [u8; 5], buffer: &mut [u8; 8]| {
+            buffer[..5].copy_from_slice(&bytes);
+            buffer[5..8].copy_from_slice(&pgn.to_le_bytes()[..3]);
+            Some((
+                J1939Frame::new(TP_PRIORITY, PGN_TP_CM, source, destination),
+                STANDARD_CAN_PACKET_SIZE,
+            ))
+        };
+        match work {
+            PENDING_CLAIM => {
+                buffer.copy_from_slice(&self.name.to_le_bytes());
+                let frame =
+                    J1939Frame::new(CLAIM_PRIORITY, PGN_ADDRESS_CLAIMED, source, GLOBAL_ADDRESS);
+                Some((frame, STANDARD_CAN_PACKET_SIZE))
+            }
+            PENDING_RX_ABORT => {
+                let (destination, pgn, reason) = self.rx_abort.get();
+                control(
+                    destination,
+                    pgn,
+                    [TP_CM_ABORT, reason, 0xFF, 0xFF, 0xFF],
+                    buffer,
+                )
+            }
+            PENDING_TX_ABORT => {
+                let (destination, pgn) = self.tx_abort.get();
+                control(
+                    destination,
+                    pgn,
+                    [TP_CM_ABORT, ABORT_TIMEOUT, 0xFF, 0xFF, 0xFF],
+                    buffer,
+                )
+            }
+            PENDING_RX_CTS => {
+                // The sender may have aborted in the meantime.
+                let session = self.rx.get()?;
+                let count = session.last - session.next + 1;
+                control(
+                    session.source,
+                    session.pgn,
+                    [TP_CM_CTS, count, session.next, 0xFF, 0xFF],
+                    buffer,
+                )
+            }
+            PENDING_RX_EOMA => {
+                let (destination, pgn, len, packets) = self.rx_ack.get();
+                let len = len.to_le_bytes();
+                control(
+                    destination,
+                    pgn,
+                    [TP_CM_EOMA, len[0], len[1], packets, 0xFF],
+                    buffer,
+                )
+            }
+            _ => self.build_tx(buffer),
+        }
+    }
+
+    /// Fill `buffer` with the next frame of the message being sent.
+    fn build_tx(&self, buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE]) -> Option<(J1939Frame, usize)> {
+        let frame = self.tx_frame.get();
+        let len = self.tx_len.get();
+        let tp = |pgn| J1939Frame::new(TP_PRIORITY, pgn, frame.source, frame.destination);
+        self.tx_buffer.map_or(None, |data| match self.tx.get() {
+            TxState::Single => {
+                buffer[..len].copy_from_slice(&data[..len]);
+                Some((frame, len))
+            }
+            TxState::Announce => {
+                let control = if frame.destination == GLOBAL_ADDRESS {
+                    TP_CM_BAM
+                } else {
+                    TP_CM_RTS
+                };
+                let len_bytes = (len as u16).to_le_bytes();
+                buffer[..5].copy_from_slice(&[
+                    control,
+                    len_bytes[0],
+                    len_bytes[1],
+                    self.tx_packets(),
+                    0xFF,
+                ]);
+                buffer[5..8].copy_from_slice(&frame.pgn.to_le_bytes()[..3]);
+                Some((tp(PGN_TP_CM), STANDARD_CAN_PACKET_SIZE))
+            }
+            TxState::Data { next, .. } => {
+                let start = (next as usize - 1) * PACKET_DATA_LEN;
+                let end = core::cmp::min(start + PACKET_DATA_LEN, len);
+                buffer[0] = next;
+                buffer[1..1 + end - start].copy_from_slice(&data[start..end]);
+                Some((tp(PGN_TP_DT), STANDARD_CAN_PACKET_SIZE))
+            }
+            TxState::Idle | TxState::WaitCts | TxState::WaitAck => None,
+        })
+    }
+
+    /// A frame of the message being sent was transmitted.
+    fn tx_frame_sent(&self) {
+        let broadcast = self.tx_frame.get().destination == GLOBAL_ADDRESS;
+        match self.tx.get() {
+            TxState::Single => self.finish_tx(Ok(())),
+            TxState::Announce if broadcast => {
+                self.tx.set(TxState::Data {
+                    next: 1,
+                    last: self.tx_packets(),
+                });
+                self.set_deadline(&self.tx_deadline, BAM_PACKET_GAP_MS);
+            }
+            TxState::Announce => {
+                self.tx.set(TxState::WaitCts);
+                self.set_deadline(&self.tx_deadline, T3_MS);
+            }
+            TxState::Data { next, last } if next < last => {
+                self.tx.set(TxState::Data {
+                    next: next + 1,
+                    last,
+                });
+                if broadcast {
+                    self.set_deadline(&self.tx_deadline, BAM_PACKET_GAP_MS);
+                } else {
+                    self.pending.set(self.pending.get() | PENDING_TX);
+                }
+            }
+            TxState::Data { .. } if broadcast => self.finish_tx(Ok(())),
+            TxState::Data { last, .. } => {
+                self.tx.set(if last == self.tx_packets() {
+                    TxState::WaitAck
+                } else {
+                    TxState::WaitCts
+                });
+                self.set_deadline(&self.tx_deadline, T3_MS);
+            }
+            TxState::Idle | TxState::WaitCts | TxState::WaitAck => {}
+        }
+    }
+
+    fn finish_tx(&self, result: Result<(), ErrorCode>) {
+        self.tx.set(TxState::Idle);
+        self.tx_deadline.set(None);
+        self.pending.set(self.pending.get() & !PENDING_TX);
+        if let Some(buffer) = self.tx_buffer.take() {
+            self.client
+                .map(move |client| client.send_done(buffer, result));
+        }
+    }
+
+    fn set_deadline(&self, deadline: &Cell<Option<(A::Ticks, A::Ticks)>>, ms: u32) {
+        deadline.set(Some((self.alarm.now(), self.alarm.ticks_from_ms(ms))));
+    }
+
+    /// Arm the alarm for the earliest deadline.
+    fn rearm(&self) {
+        let now = self.alarm.now();
+        let earliest = [&self.claim_deadline, &self.tx_deadline, &self.rx_deadline]
+            .iter()
+            .filter_map(|deadline| deadline.get())
+            .map(|(reference, dt)| {
+                let elapsed = now.wrapping_sub(reference);
+                if elapsed >= dt {
+                    A::Ticks::from(0)
+                } else {
+                    dt.wrapping_sub(elapsed)
+                }
+            })
+            .min();
+        match earliest {
+            Some(remaining) => self.alarm.set_alarm(now, remaining),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Take `deadline` if it has passed.
+    fn expired(&self, deadline: &Cell<Option<(A::Ticks, A::Ticks)>>, now: A::Ticks) -> bool {
+        match deadline.get() {
+            Some((reference, dt)) if now.wrapping_sub(reference) >= dt => {
+                deadline.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn begin_claim(&self, address: u8) {
+        self.claim.set(ClaimState::Claiming(address));
+        self.set_deadline(&self.claim_deadline, CLAIM_WINDOW_MS);
+        self.schedule(PENDING_CLAIM);
+    }
+
+    /// Another node claimed `address` with `name`.
+    fn address_contested(&self, address: u8, name: u64) {
+        let own = match self.own_address() {
+            Some(own) if own == address && name != self.name => own,
+            _ => return,
+        };
+        if self.name < name {
+            // Our NAME has priority, the other node has to give way.
+            self.schedule(PENDING_CLAIM);
+            return;
+        }
+
+        let arbitrary_capable = self.name >> 63 != 0;
+        let attempts = self.claim_attempts.get() + 1;
+        self.claim_attempts.set(attempts);
+        if arbitrary_capable && (attempts as usize) < ARBITRARY_ADDRESSES.len() {
+            let next = if ARBITRARY_ADDRESSES.contains(&own) && own < *ARBITRARY_ADDRESSES.end() {
+                own + 1
+            } else {
+                *ARBITRARY_ADDRESSES.start()
+            };
+            self.begin_claim(next);
+        } else {
+            self.claim.set(ClaimState::CannotClaim);
+            self.claim_deadline.set(None);
+            self.schedule(PENDING_CLAIM);
+            self.client
+                .map(|client| client.address_claimed(Err(ErrorCode::RESERVE)));
+        }
+    }
+
+    fn receive(&self, frame: J1939Frame, data: &[u8]) {
+        let own = self.own_address();
+        if frame.destination != GLOBAL_ADDRESS && Some(frame.destination) != own {
+            return;
+        }
+        match frame.pgn {
+            PGN_ADDRESS_CLAIMED if data.len() == 8 => {
+                let mut name = [0; 8];
+                name.copy_from_slice(data);
+                self.address_contested(frame.source, u64::from_le_bytes(name));
+            }
+            PGN_REQUEST
+                if data.len() >= 3
+                    && u32::from_le_bytes([data[0], data[1], data[2], 0])
+                        == PGN_ADDRESS_CLAIMED =>
+            {
+                if !matches!(self.claim.get(), ClaimState::Stopped | ClaimState::Enabling) {
+                    self.schedule(PENDING_CLAIM);
+                }
+            }
+            PGN_TP_CM if data.len() == 8 => self.receive_tp_cm(frame, data),
+            PGN_TP_DT if data.len() == 8 => self.receive_tp_dt(frame, data),
+            _ => {
+                // Messages for a specific node only reach us once we have an
+                // address.
+                if frame.destination == GLOBAL_ADDRESS
+                    || matches!(self.claim.get(), ClaimState::Claimed(_))
+                {
+                    self.client
+                        .map(|client| client.message_received(frame, data));
+                }
+            }
+        }
+    }
+
+    fn receive_tp_cm(&self, frame: J1939Frame, data: &[u8]) {
+        let pgn = u32::from_le_bytes([data[5], data[6], data[7], 0]);
+        let len = u16::from_le_bytes([data[1], data[2]]) as usize;
+        match data[0] {
+            TP_CM_BAM | TP_CM_RTS => {
+                let broadcast = data[0] == TP_CM_BAM;
+                if broadcast != (frame.destination == GLOBAL_ADDRESS) {
+                    return;
+                }
+                if !broadcast && !matches!(self.claim.get(), ClaimState::Claimed(_)) {
+                    return;
+                }
+                let packets = data[3];
+                let valid = len > STANDARD_CAN_PACKET_SIZE
+                    && len <= j1939::MAX_MESSAGE_LEN
+                    && packets as usize == (len + PACKET_DATA_LEN - 1) / PACKET_DATA_LEN;
+                let busy = self
+                    .rx
+                    .get()
+                    .map_or(false, |session| session.source != frame.source);
+                let fits = self.rx_buffer.map_or(false, |buffer| buffer.len() >= len);
+                if busy || !valid || !fits {
+                    // Broadcasts cannot be refused.
+                    if !broadcast {
+                        let reason = if busy { ABORT_BUSY } else { ABORT_RESOURCES };
+                        self.rx_abort.set((frame.source, pgn, reason));
+                        self.schedule(PENDING_RX_ABORT);
+                    }
+                    return;
+                }
+                let window = if broadcast || data[4] == 0 {
+                    packets
+                } else {
+                    core::cmp::min(data[4], packets)
+                };
+                self.rx.set(Some(RxSession {
+                    broadcast,
+                    source: frame.source,
+                    pgn,
+                    len,
+                    packets,
+                    window,
+                    next: 1,
+                    last: window,
+                }));
+                if broadcast {
+                    self.set_deadline(&self.rx_deadline, T1_MS);
+                } else {
+                    self.set_deadline(&self.rx_deadline, T2_MS);
+                    self.schedule(PENDING_RX_CTS);
+                }
+            }
+            TP_CM_CTS | TP_CM_EOMA | TP_CM_ABORT => {
+                let tx_frame = self.tx_frame.get();
+                if self.tx.get() == TxState::Idle
+                    || frame.source != tx_frame.destination
+                    || pgn != tx_frame.pgn
+                {
+                    if data[0] == TP_CM_ABORT
+                        && self
+                            .rx
+                            .get()
+                            .map_or(false, |session| session.source == frame.source)
+                    {
+                        self.rx.set(None);
+                        self.rx_deadline.set(None);
+                    }
+                    return;
+                }
+                match (data[0], self.tx.get()) {
+                    (TP_CM_CTS, TxState::WaitCts) => {
+                        let (count, next) = (data[1], data[2]);
+                        if count == 0 {
+                            // Hold the connection open.
+                            self.set_deadline(&self.tx_deadline, T3_MS);
+                        } else if next >= 1 && next <= self.tx_packets() {
+                            let last = core::cmp::min(
+                                next as usize + count as usize - 1,
+                                self.tx_packets() as usize,
+                            ) as u8;
+                            self.tx.set(TxState::Data { next, last });
+                            self.tx_deadline.set(None);
+                            self.schedule(PENDING_TX);
+                        }
+                    }
+                    (TP_CM_EOMA, TxState::WaitAck) => self.finish_tx(Ok(())),
+                    (TP_CM_ABORT, _) => self.finish_tx(Err(ErrorCode::CANCEL)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_tp_dt(&self, frame: J1939Frame, data: &[u8]) {
+        let mut session = match self.rx.get() {
+            Some(session) if session.source == frame.source => session,
+            _ => return,
+        };
+        if session.broadcast != (frame.destination == GLOBAL_ADDRESS) {
+            return;
+        }
+        let sequence = data[0];
+        if sequence != session.next || sequence > session.last {
+            // A lost packet: give up on the message.
+            self.rx.set(None);
+            self.rx_deadline.set(None);
+            if !session.broadcast {
+                self.rx_abort
+                    .set((session.source, session.pgn, ABORT_RESOURCES));
+                self.schedule(PENDING_RX_ABORT);
+            }
+            return;
+        }
+
+        let start = (sequence as usize - 1) * PACKET_DATA_LEN;
+        let end = core::cmp::min(start + PACKET_DATA_LEN, session.len);
+        self.rx_buffer.map(|buffer| {
+            buffer[start..end].copy_from_slice(&data[1..1 + end - start]);
+        });
+
+        session.next += 1;
+        if sequence == session.packets {
+            self.rx.set(None);
+            self.rx_deadline.set(None);
+            if !session.broadcast {
+                self.rx_ack.set((
+                    session.source,
+                    session.pgn,
+                    session.len as u16,
+                    session.packets,
+                ));
+                self.schedule(PENDING_RX_EOMA);
+            }
+            let own = self.own_address().unwrap_or(NULL_ADDRESS);
+            let destination = if session.broadcast {
+                GLOBAL_ADDRESS
+            } else {
+                own
+            };
+            let frame = J1939Frame::new(TP_PRIORITY, session.pgn, session.source, destination);
+            self.rx_buffer.map(|buffer| {
+                self.client
+                    .map(|client| client.message_received(frame, &buffer[..session.len]));
+            });
+        } else if sequence == session.last {
+            session.last = core::cmp::min(
+                session.last as usize + session.window as usize,
+                session.packets as usize,
+            ) as u8;
+            self.rx.set(Some(session));
+            self.set_deadline(&self.rx_deadline, T2_MS);
+            self.schedule(PENDING_RX_CTS);
+        } else {
+            self.rx.set(Some(session));
+            self.set_deadline(&self.rx_deadline, T1_MS);
+        }
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> AlarmClient for J1939Stack<'a, C, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        if self.expired(&self.claim_deadline, now) {
+            if let ClaimState::Claiming(address) = self.claim.get() {
+                self.claim.set(ClaimState::Claimed(address));
+                self.claim_attempts.set(0);
+                self.client
+                    .map(|client| client.address_claimed(Ok(address)));
+            }
+        }
+        if self.expired(&self.tx_deadline, now) {
+            match self.tx.get() {
+                TxState::Data { .. } => self.schedule(PENDING_TX),
+                TxState::WaitCts | TxState::WaitAck => {
+                    let frame = self.tx_frame.get();
+                    self.tx_abort.set((frame.destination, frame.pgn));
+                    self.pending.set(self.pending.get() | PENDING_TX_ABORT);
+                    self.finish_tx(Err(ErrorCode::FAIL));
+                }
+                _ => {}
+            }
+        }
+        if self.expired(&self.rx_deadline, now) {
+            if let Some(session) = self.rx.take() {
+                if !session.broadcast {
+                    self.rx_abort
+                        .set((session.source, session.pgn, ABORT_TIMEOUT));
+                    self.pending.set(self.pending.get() | PENDING_RX_ABORT);
+                }
+            }
+        }
+        self.service();
+        self.rearm();
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::ControllerClient for J1939Stack<'a, C, A> {
+    fn state_changed(&self, _state: can::State) {}
+
+    fn enabled(&self, status: Result<(), ErrorCode>) {
+        if self.claim.get() != ClaimState::Enabling {
+            return;
+        }
+        let status = status.and_then(|()| {
+            self.can_rx.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+                self.can
+                    .start_receive_process(buffer)
+                    .map_err(|(error, buffer)| {
+                        self.can_rx.replace(buffer);
+                        error
+                    })
+            })
+        });
+        match status {
+            Ok(()) => {
+                self.begin_claim(self.preferred_address);
+                self.rearm();
+            }
+            Err(error) => {
+                self.claim.set(ClaimState::Stopped);
+                self.client.map(|client| client.address_claimed(Err(error)));
+            }
+        }
+    }
+
+    fn disabled(&self, _status: Result<(), ErrorCode>) {}
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::TransmitClient<STANDARD_CAN_PACKET_SIZE>
+    for J1939Stack<'a, C, A>
+{
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        self.can_tx.replace(buffer);
+        match self.in_flight.replace(0) {
+            PENDING_TX if status.is_ok() => self.tx_frame_sent(),
+            PENDING_TX => self.finish_tx(Err(ErrorCode::FAIL)),
+            _ => {}
+        }
+        self.service();
+        self.rearm();
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::ReceiveClient<STANDARD_CAN_PACKET_SIZE>
+    for J1939Stack<'a, C, A>
+{
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        if let (can::Id::Extended(id), Ok(())) = (id, status) {
+            let len = core::cmp::min(len, STANDARD_CAN_PACKET_SIZE);
+            self.receive(J1939Frame::from_id(id), &buffer[..len]);
+            self.rearm();
+        }
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.can_rx.replace(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use core::cell::RefCell;
+    use kernel::hil::can::{BitTiming, ControllerClient, ReceiveClient, TransmitClient};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const NAME: u64 = 0x8000_0000_0012_3456;
+
+    const TIMING: BitTiming = BitTiming {
+        segment1: 1,
+        segment2: 1,
+        propagation: 0,
+        sync_jump_width: 1,
+        baud_rate_prescaler: 1,
+    };
+
+    struct MockCan {
+        in_flight: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+        sent: RefCell<Vec<(u32, Vec<u8>)>>,
+    }
+
+    impl MockCan {
+        fn take_sent(&self) -> Vec<(u32, Vec<u8>)> {
+            self.sent.take()
+        }
+    }
+
+    impl can::Configure for MockCan {
+        const MIN_BIT_TIMINGS: BitTiming = TIMING;
+        const MAX_BIT_TIMINGS: BitTiming = TIMING;
+
+        fn set_bitrate(&self, _bitrate: u32) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn set_bit_timing(&self, _bit_timing: BitTiming) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn set_operation_mode(&self, _mode: can::OperationMode) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn get_bit_timing(&self) -> Result<BitTiming, ErrorCode> {
+            Ok(TIMING)
+        }
+        fn get_operation_mode(&self) -> Result<can::OperationMode, ErrorCode> {
+            Ok(can::OperationMode::Normal)
+        }
+        fn set_automatic_retransmission(&self, _automatic: bool) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn set_wake_up(&self, _wake_up: bool) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn get_automatic_retransmission(&self) -> Result<bool, ErrorCode> {
+            Ok(true)
+        }
+        fn get_wake_up(&self) -> Result<bool, ErrorCode> {
+            Ok(false)
+        }
+        fn receive_fifo_count(&self) -> usize {
+            1
+        }
+    }
+
+    impl can::Controller for MockCan {
+        fn set_client(&self, _client: Option<&'static dyn can::ControllerClient>) {}
+        fn enable(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn disable(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn get_state(&self) -> Result<can::State, ErrorCode> {
+            Ok(can::State::Running)
+        }
+    }
+
+    impl can::Transmit<STANDARD_CAN_PACKET_SIZE> for MockCan {
+        fn set_client(
+            &self,
+            _client: Option<&'static dyn can::TransmitClient<STANDARD_CAN_PACKET_SIZE>>,
+        ) {
+        }
+        fn send(
+            &self,
+            id: can::Id,
+            buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+            assert!(self.in_flight.is_none());
+            match id {
+                can::Id::Extended(id) => self.sent.borrow_mut().push((id, buffer[..len].to_vec())),
+                can::Id::Standard(_) => panic!("J1939 uses extended identifiers"),
+            }
+            self.in_flight.replace(buffer);
+            Ok(())
+        }
+    }
+
+    impl can::Receive<STANDARD_CAN_PACKET_SIZE> for MockCan {
+        fn set_client(
+            &self,
+            _client: Option<&'static dyn can::ReceiveClient<STANDARD_CAN_PACKET_SIZE>>,
+        ) {
+        }
+        fn start_receive_process(
+            &self,
+            _buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+            Ok(())
+        }
+        fn stop_receive(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Client {
+        claims: RefCell<Vec<Result<u8, ErrorCode>>>,
+        messages: RefCell<Vec<(J1939Frame, Vec<u8>)>>,
+    }
+
+    impl J1939Client for Client {
+        fn message_received(&self, frame: J1939Frame, data: &[u8]) {
+            self.messages.borrow_mut().push((frame, data.to_vec()));
+        }
+        fn send_done(&self, _buffer: &'static mut [u8], _result: Result<(), ErrorCode>) {}
+        fn address_claimed(&self, result: Result<u8, ErrorCode>) {
+            self.claims.borrow_mut().push(result);
+        }
+    }
+
+    type Stack = J1939Stack<'static, MockCan, MockAlarm<'static>>;
+
+    fn setup() -> (
+        &'static MockCan,
+        &'static MockAlarm<'static>,
+        &'static Stack,
+        &'static Client,
+    ) {
+        let can = Box::leak(Box::new(MockCan {
+            in_flight: TakeCell::empty(),
+            sent: RefCell::new(Vec::new()),
+        }));
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let stack = Box::leak(Box::new(J1939Stack::new(
+            can,
+            alarm,
+            NAME,
+            0x80,
+            250_000,
+            Box::leak(Box::new([0; STANDARD_CAN_PACKET_SIZE])),
+            Box::leak(Box::new([0; STANDARD_CAN_PACKET_SIZE])),
+            Box::leak(std::vec![0; j1939::MAX_MESSAGE_LEN].into_boxed_slice()),
+        )));
+        alarm.set_alarm_client(stack);
+        stack.set_client(client);
+        (can, alarm, stack, client)
+    }
+
+    /// Complete the frame being transmitted.
+    fn transmitted(can: &MockCan, stack: &Stack) {
+        let buffer = can.in_flight.take().expect("no frame in flight");
+        stack.transmit_complete(Ok(()), buffer);
+    }
+
+    fn receive(stack: &Stack, id: u32, data: [u8; 8]) {
+        let mut buffer = data;
+        stack.message_received(can::Id::Extended(id), &mut buffer, 8, Ok(()));
+    }
+
+    fn fire_after(alarm: &MockAlarm, ms: u32) {
+        assert!(alarm.is_armed());
+        alarm.advance(ms);
+    }
+
+    #[test]
+    fn address_claim_gives_way_to_lower_name() {
+        let (can, alarm, stack, client) = setup();
+        assert_eq!(stack.start(), Ok(()));
+        stack.enabled(Ok(()));
+        assert_eq!(can.take_sent(), [(0x18EEFF80, NAME.to_le_bytes().to_vec())]);
+        transmitted(can, stack);
+        assert_eq!(stack.address(), Err(ErrorCode::BUSY));
+
+        // A node with a lower NAME claims the same address: move on to the
+        // next one and restart the claim window.
+        alarm.set_now(100);
+        receive(stack, 0x18EEFF80, 0x0000_0000_0000_0001u64.to_le_bytes());
+        assert_eq!(can.take_sent(), [(0x18EEFF81, NAME.to_le_bytes().to_vec())]);
+        transmitted(can, stack);
+
+        // A higher NAME has to give way to us.
+        receive(stack, 0x18EEFF81, u64::MAX.to_le_bytes());
+        assert_eq!(can.take_sent(), [(0x18EEFF81, NAME.to_le_bytes().to_vec())]);
+        transmitted(can, stack);
+
+        fire_after(alarm, 249);
+        assert!(client.claims.borrow().is_empty());
+        fire_after(alarm, 1);
+        assert_eq!(*client.claims.borrow(), [Ok(0x81)]);
+        assert_eq!(stack.address(), Ok(0x81));
+    }
+
+    #[test]
+    fn rts_cts_message_is_acknowledged() {
+        let (can, alarm, stack, client) = setup();
+        assert_eq!(stack.start(), Ok(()));
+        stack.enabled(Ok(()));
+        transmitted(can, stack);
+        fire_after(alarm, 250);
+        assert_eq!(stack.address(), Ok(0x80));
+        can.take_sent();
+
+        // 0x20 sends 20 bytes of PGN 0xFECA in three packets.
+        let message: Vec<u8> = (0..20).collect();
+        receive(stack, 0x1CEC8020, [16, 20, 0, 3, 0xFF, 0xCA, 0xFE, 0x00]);
+        assert_eq!(
+            can.take_sent(),
+            [(
+                0x1CEC2080,
+                std::vec![17, 3, 1, 0xFF, 0xFF, 0xCA, 0xFE, 0x00]
+            )]
+        );
+        transmitted(can, stack);
+
+        for (sequence, chunk) in message.chunks(PACKET_DATA_LEN).enumerate() {
+            let mut data = [0xFF; 8];
+            data[0] = sequence as u8 + 1;
+            data[1..1 + chunk.len()].copy_from_slice(chunk);
+            receive(stack, 0x1CEB8020, data);
+        }
+        assert_eq!(
+            can.take_sent(),
+            [(0x1CEC2080, std::vec![19, 20, 0, 3, 0xFF, 0xCA, 0xFE, 0x00])]
+        );
+        transmitted(can, stack);
+        assert_eq!(
+            *client.messages.borrow(),
+            [(J1939Frame::new(7, 0xFECA, 0x20, 0x80), message)]
+        );
+    }
+}
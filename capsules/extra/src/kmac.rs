@@ -0,0 +1,474 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! SHA-3 and KMAC.
+//!
+//! Computes SHA3-256, SHA3-512, SHAKE128, SHAKE256, KMAC128 and KMAC256 over
+//! a buffer shared by the process. The KMAC key is copied into the kernel
+//! when the algorithm is selected and kept in the process's grant, so the
+//! process can erase its own copy; the kernel wipes the grant when the
+//! process terminates.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kmac = static_init!(
+//!     capsules_extra::kmac::KmacDriver<'static, earlgrey::kmac::Kmac>,
+//!     capsules_extra::kmac::KmacDriver::new(
+//!         &peripherals.kmac,
+//!         data_buffer,
+//!         output_buffer,
+//!         board_kernel.create_grant(capsules_extra::kmac::DRIVER_NUM, &memory_allocation_cap),
+//!     )
+//! );
+//! kernel::hil::hash::Sha3::set_client(&peripherals.kmac, kmac);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use capsules_core::driver;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::hash::{Sha3, Sha3Mode, SpongeClient};
+use kernel::hil::mac::{Kmac, KmacMode};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Kmac as usize;
+
+/// The longest KMAC key a process can set, in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// The longest customization string a process can use, in bytes.
+pub const MAX_CUSTOMIZATION_LEN: usize = 64;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DATA: usize = 0;
+    pub const KEY: usize = 1;
+    pub const CUSTOMIZATION: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const OUTPUT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Sha3(Sha3Mode),
+    Kmac(KmacMode),
+}
+
+pub struct App {
+    algorithm: Option<Algorithm>,
+    key: [u8; MAX_KEY_LEN],
+    key_len: usize,
+    /// The output length of a `run` waiting for the hardware.
+    pending_run: Option<usize>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            algorithm: None,
+            key: [0; MAX_KEY_LEN],
+            key_len: 0,
+            pending_run: None,
+        }
+    }
+}
+
+impl App {
+    fn clear_key(&mut self) {
+        self.key.iter_mut().for_each(|b| *b = 0);
+        self.key_len = 0;
+    }
+}
+
+pub struct KmacDriver<'a, H: Sha3<'a> + Kmac<'a>> {
+    hash: &'a H,
+
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    processid: OptionalCell<ProcessId>,
+
+    data_buffer: TakeCell<'static, [u8]>,
+    data_copied: Cell<usize>,
+    output_buffer: TakeCell<'static, [u8]>,
+    output_len: Cell<usize>,
+    output_copied: Cell<usize>,
+}
+
+impl<'a, H: Sha3<'a> + Kmac<'a>> KmacDriver<'a, H> {
+    pub fn new(
+        hash: &'a H,
+        data_buffer: &'static mut [u8],
+        output_buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> KmacDriver<'a, H> {
+        KmacDriver {
+            hash,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            data_copied: Cell::new(0),
+            output_buffer: TakeCell::new(output_buffer),
+            output_len: Cell::new(0),
+            output_copied: Cell::new(0),
+        }
+    }
+
+    /// Select the algorithm for `app`, copying the key of a KMAC from the
+    /// key allow buffer.
+    fn set_algorithm(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        algorithm: Algorithm,
+    ) -> Result<(), ErrorCode> {
+        app.clear_key();
+        app.algorithm = None;
+        if let Algorithm::Kmac(_) = algorithm {
+            let key = &mut app.key;
+            app.key_len = kernel_data
+                .get_readonly_processbuffer(ro_allow::KEY)
+                .and_then(|k| {
+                    k.enter(|k| {
+                        if k.len() > MAX_KEY_LEN {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        k.copy_to_slice(&mut key[..k.len()]);
+                        Ok(k.len())
+                    })
+                })
+                .unwrap_or(Err(ErrorCode::RESERVE))?;
+        }
+        app.algorithm = Some(algorithm);
+        Ok(())
+    }
+
+    /// Start the hardware on the pending `run` of `processid`.
+    fn start(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let output_len = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                let output_len = app.pending_run.take().ok_or(ErrorCode::FAIL)?;
+                let output_fits = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::OUTPUT)
+                    .map_or(false, |output| output.len() >= output_len);
+                if !output_fits {
+                    return Err(ErrorCode::SIZE);
+                }
+
+                match app.algorithm.ok_or(ErrorCode::INVAL)? {
+                    Algorithm::Sha3(mode) => {
+                        if mode.digest_len().map_or(false, |len| output_len > len) {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        self.hash.start_sha3(mode)?;
+                    }
+                    Algorithm::Kmac(mode) => {
+                        let mut customization = [0; MAX_CUSTOMIZATION_LEN];
+                        let customization_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::CUSTOMIZATION)
+                            .and_then(|c| {
+                                c.enter(|c| {
+                                    if c.len() > MAX_CUSTOMIZATION_LEN {
+                                        return Err(ErrorCode::SIZE);
+                                    }
+                                    c.copy_to_slice(&mut customization[..c.len()]);
+                                    Ok(c.len())
+                                })
+                            })
+                            .unwrap_or(Ok(0))?;
+                        self.hash.start_kmac(
+                            mode,
+                            &app.key[..app.key_len],
+                            &customization[..customization_len],
+                            output_len,
+                        )?;
+                    }
+                }
+                Ok(output_len)
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.processid.set(processid);
+        self.data_copied.set(0);
+        self.output_len.set(output_len);
+        self.output_copied.set(0);
+        self.absorb_next()
+    }
+
+    /// Absorb the next part of the process's data, or start squeezing once
+    /// all of it is absorbed.
+    fn absorb_next(&self) -> Result<(), ErrorCode> {
+        let processid = self.processid.extract().ok_or(ErrorCode::RESERVE)?;
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let copied = self.data_copied.get();
+
+        let len = self.apps.enter(processid, |_, kernel_data| {
+            kernel_data
+                .get_readonly_processbuffer(ro_allow::DATA)
+                .and_then(|data| {
+                    data.enter(|data| {
+                        if copied >= data.len() {
+                            return 0;
+                        }
+                        let len = cmp::min(data.len() - copied, buffer.len());
+                        data[copied..copied + len].copy_to_slice(&mut buffer[..len]);
+                        len
+                    })
+                })
+                .unwrap_or(0)
+        });
+
+        match len {
+            Err(err) => {
+                self.data_buffer.replace(buffer);
+                Err(err.into())
+            }
+            Ok(0) => {
+                self.data_buffer.replace(buffer);
+                self.squeeze_next()
+            }
+            Ok(len) => {
+                self.data_copied.set(copied + len);
+                self.hash.absorb(buffer, len).map_err(|(err, buffer)| {
+                    self.data_buffer.replace(buffer);
+                    err
+                })
+            }
+        }
+    }
+
+    /// The length of the next part of the output to squeeze.
+    fn squeeze_len(&self, buffer: &[u8]) -> usize {
+        cmp::min(
+            self.output_len.get() - self.output_copied.get(),
+            buffer.len(),
+        )
+    }
+
+    fn squeeze_next(&self) -> Result<(), ErrorCode> {
+        let buffer = self.output_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let len = self.squeeze_len(buffer);
+        self.hash.squeeze(buffer, len).map_err(|(err, buffer)| {
+            self.output_buffer.replace(buffer);
+            err
+        })
+    }
+
+    /// End the operation and report `result` to the process.
+    fn complete(&self, result: Result<(), ErrorCode>) {
+        self.hash.finish();
+        let output_len = result.map_or(0, |()| self.output_len.get());
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(0, (into_statuscode(result), output_len, 0))
+                    .ok();
+            });
+        });
+    }
+
+    /// Start the next pending `run`, if the hardware is free.
+    fn check_queue(&self) {
+        while self.processid.is_none() {
+            let next = self.apps.iter().find_map(|appiter| {
+                let processid = appiter.processid();
+                appiter.enter(|app, _| app.pending_run.map(|_| processid))
+            });
+            match next {
+                Some(processid) => {
+                    if let Err(e) = self.start(processid) {
+                        // `start` has taken the pending `run`, so this
+                        // process is not picked again.
+                        self.processid.set(processid);
+                        self.complete(Err(e));
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl<'a, H: Sha3<'a> + Kmac<'a>> SpongeClient for KmacDriver<'a, H> {
+    fn absorb_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+        if let Err(e) = result.and_then(|()| self.absorb_next()) {
+            self.complete(Err(e));
+            self.check_queue();
+        }
+    }
+
+    fn squeeze_done(&self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+        let len = self.squeeze_len(output);
+        let copied = self.output_copied.get();
+        self.output_buffer.replace(output);
+        if let Err(e) = result {
+            self.complete(Err(e));
+            self.check_queue();
+            return;
+        }
+
+        let written = self
+            .processid
+            .extract()
+            .map_or(Err(ErrorCode::RESERVE), |processid| {
+                self.apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::OUTPUT)
+                            .and_then(|dest| {
+                                dest.mut_enter(|dest| {
+                                    if dest.len() < copied + len {
+                                        return Err(ErrorCode::SIZE);
+                                    }
+                                    self.output_buffer.map(|buffer| {
+                                        dest[copied..copied + len].copy_from_slice(&buffer[..len]);
+                                    });
+                                    Ok(())
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            });
+        self.output_copied.set(copied + len);
+
+        let result = written.and_then(|()| {
+            if self.output_copied.get() < self.output_len.get() {
+                self.squeeze_next().map(|()| false)
+            } else {
+                Ok(true)
+            }
+        });
+        match result {
+            Ok(false) => return,
+            Ok(true) => self.complete(Ok(())),
+            Err(e) => self.complete(Err(e)),
+        }
+        self.check_queue();
+    }
+}
+
+/// Specify memory regions to be used.
+///
+/// ### `allow_num`
+///
+/// Read-only:
+/// - `0`: The data to hash. This should not be changed until the `run`
+///        has completed.
+/// - `1`: The KMAC key, copied into the kernel when a KMAC algorithm is
+///        selected.
+/// - `2`: The KMAC customization string, read when a `run` starts.
+///
+/// Read-write:
+/// - `0`: The output, filled in before the upcall.
+impl<'a, H: Sha3<'a> + Kmac<'a>> SyscallDriver for KmacDriver<'a, H> {
+    // Subscribe to KmacDriver events.
+    //
+    // ### `subscribe_num`
+    //
+    // - `0`: A `run` has completed.
+    //        The callback signature is `fn(result: u32, output_len: u32)`
+
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Select the algorithm. `data1` is one of SHA3-256 (0),
+    ///        SHA3-512 (1), SHAKE128 (2), SHAKE256 (3), KMAC128 (4) and
+    ///        KMAC256 (5). For KMAC the key is copied from the key allow
+    ///        buffer; selecting any algorithm erases the previous key.
+    /// - `2`: Run the algorithm over the data, producing `data1` bytes of
+    ///        output. Queued if another process is using the hardware.
+    /// - `3`: Erase the key and deselect the algorithm.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let res = self
+            .apps
+            .enter(processid, |app, kernel_data| match command_num {
+                0 => Ok(()),
+
+                1 => {
+                    if self.processid.contains(&processid) || app.pending_run.is_some() {
+                        return Err(ErrorCode::BUSY);
+                    }
+                    let algorithm = match data1 {
+                        0 => Algorithm::Sha3(Sha3Mode::Sha3_256),
+                        1 => Algorithm::Sha3(Sha3Mode::Sha3_512),
+                        2 => Algorithm::Sha3(Sha3Mode::Shake128),
+                        3 => Algorithm::Sha3(Sha3Mode::Shake256),
+                        4 => Algorithm::Kmac(KmacMode::Kmac128),
+                        5 => Algorithm::Kmac(KmacMode::Kmac256),
+                        _ => return Err(ErrorCode::NOSUPPORT),
+                    };
+                    self.set_algorithm(app, kernel_data, algorithm)
+                }
+
+                2 => {
+                    if self.processid.contains(&processid) || app.pending_run.is_some() {
+                        return Err(ErrorCode::BUSY);
+                    }
+                    if app.algorithm.is_none() {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    app.pending_run = Some(data1);
+                    Ok(())
+                }
+
+                3 => {
+                    if self.processid.contains(&processid) || app.pending_run.is_some() {
+                        return Err(ErrorCode::BUSY);
+                    }
+                    app.clear_key();
+                    app.algorithm = None;
+                    Ok(())
+                }
+
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => {
+                if command_num == 2 && self.processid.is_none() {
+                    self.check_queue();
+                }
+                CommandReturn::success()
+            }
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
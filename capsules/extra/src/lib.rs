@@ -10,39 +10,71 @@ pub mod test;
 #[macro_use]
 pub mod net;
 
+pub mod adc_capture;
 pub mod adc_microphone;
+pub mod adc_temperature;
+pub mod ads1115;
+pub mod aht20;
 pub mod air_quality;
 pub mod ambient_light;
+pub mod amg8833;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod battery_charger;
 pub mod ble_advertising_driver;
 pub mod bme280;
+pub mod bme680;
+pub mod bmi270;
 pub mod bmp280;
+pub mod bq24195;
 pub mod bus;
 pub mod buzzer_driver;
 pub mod buzzer_pwm;
 pub mod can;
 pub mod ccs811;
+pub mod clk_stats;
+pub mod color;
 pub mod crc;
 pub mod dac;
+pub mod dali;
 pub mod debug_process_restart;
+pub mod desfire;
+pub mod ds18b20_multi;
+pub mod eink;
+pub mod ens160;
+pub mod flash_digest;
 pub mod fm25cl;
+pub mod ft5x06;
 pub mod ft6x06;
+pub mod fxas21002c;
 pub mod fxos8700cq;
 pub mod gpio_async;
+pub mod gpio_rate_limit;
+pub mod gyro_calibration;
 pub mod hd44780;
 pub mod hmac;
 pub mod hts221;
 pub mod humidity;
+pub mod hx711;
+pub mod i2c_bitbang;
+pub mod icm20649;
 pub mod ieee802154;
+pub mod iis2mdc;
+pub mod ina3221;
+pub mod indoor_air_quality;
+pub mod ir_nec;
 pub mod isl29035;
+pub mod j1939;
+pub mod kmac;
 pub mod kv_driver;
 pub mod kv_store;
 pub mod l3gd20;
 pub mod led_matrix;
+pub mod lis3dh;
 pub mod log;
+pub mod lorawan_mac;
 pub mod lpm013m126;
 pub mod lps25hb;
 pub mod lsm303agr;
@@ -51,39 +83,78 @@ pub mod lsm303xx;
 pub mod lsm6dsoxtr;
 pub mod ltc294x;
 pub mod max17205;
+pub mod max31865;
+pub mod mcp23017;
 pub mod mcp230xx;
+pub mod mcp4725;
+pub mod mlx90393;
 pub mod mlx90614;
+pub mod mmc5983;
+pub mod modbus_rtu;
+pub mod motion_detector;
+pub mod mpr121;
+pub mod mpu6886;
+pub mod mpu9250;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
+pub mod ntc_pid_heater;
+pub mod opt3001;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod pca9555;
+pub mod pca9685;
+pub mod pir_motion;
+pub mod pn532;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod read_only_state;
+pub mod resistive_adc_buttons;
 pub mod rf233;
 pub mod rf233_const;
+pub mod rotary_encoder;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sensor_batch;
 pub mod seven_segment;
+pub mod sgp30;
+pub mod sgp41;
 pub mod sha;
 pub mod sha256;
 pub mod sht3x;
+pub mod si1145;
 pub mod si7021;
 pub mod sip_hash;
+pub mod soil_moisture;
+pub mod sound_level;
+pub mod sound_level_driver;
 pub mod sound_pressure;
+pub mod spi_nor;
+pub mod st7789;
 pub mod st77xx;
+pub mod sx1276;
 pub mod symmetric_encryption;
+pub mod tca9548a;
+pub mod tcs34725;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
+pub mod test_rng;
 pub mod text_screen;
 pub mod tickv;
+pub mod timestamped_sensor;
 pub mod touch;
+pub mod tps65987d;
 pub mod tsl2561;
+pub mod tsl2591;
+pub mod uair;
 pub mod usb;
 pub mod usb_hid_driver;
+pub mod usb_pd;
+pub mod veml6075;
+pub mod ws2812b_animation;
+pub mod ws2812b_dma;
@@ -0,0 +1,1029 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the ST LIS3DH 3-axis accelerometer.
+//!
+//! <https://www.st.com/resource/en/datasheet/lis3dh.pdf>
+//!
+//! The LIS3DH is connected over I2C or SPI, and provides accelerometer
+//! readings through the `NineDof` HIL, in milli-g. The sensor runs in
+//! high-resolution mode, in which samples are 12 bits, left-justified in
+//! 16-bit registers.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! [Lis3dh::start] checks the identity of the sensor and configures it: the
+//! output data rate and full-scale range, the FIFO, and the detection of
+//! taps and free fall. All events are routed to the INT1 pin, which is
+//! latched until the driver reads the sources of the interrupt.
+//!
+//! When a FIFO watermark is configured, the FIFO runs in stream mode and the
+//! driver reads the samples it holds once it reaches the watermark. The
+//! whole burst is passed to [Lis3dhClient::burst], and a pending
+//! `read_accelerometer` is answered with the newest sample of the burst.
+//! Without the FIFO, `read_accelerometer` reads the output registers.
+//!
+//! Single and double taps are detected on all axes when tap thresholds are
+//! configured, and a free fall when the acceleration on all axes stays
+//! below 350 mg for 30 ms. Both are passed to [Lis3dhClient::event].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lis3dh_transport = components::lis3dh::Lis3dhI2CTransportComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::lis3dh::I2C_ADDRESS,
+//! )
+//! .finalize(components::lis3dh_i2c_transport_component_static!(
+//!     nrf52840::i2c::TWI
+//! ));
+//! let lis3dh = components::lis3dh::Lis3dhComponent::new(
+//!     lis3dh_transport,
+//!     &nrf52840_peripherals.gpio_port[LIS3DH_INT1],
+//!     capsules_extra::lis3dh::DataRate::Hz100,
+//!     capsules_extra::lis3dh::Range::G4,
+//!     Some(capsules_extra::lis3dh::TapThresholds {
+//!         threshold_mg: 1000,
+//!         time_limit_ms: 20,
+//!         latency_ms: 50,
+//!         window_ms: 200,
+//!     }),
+//!     Some(16),
+//! )
+//! .finalize(components::lis3dh_component_static!(
+//!     capsules_extra::lis3dh::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! lis3dh.set_client(motion);
+//! lis3dh.start().unwrap();
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::spi;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address when SDO is pulled low. It is `0x19` when SDO is high.
+pub const I2C_ADDRESS: u8 = 0x18;
+
+/// Number of samples the FIFO holds.
+pub const FIFO_DEPTH: usize = 32;
+
+/// Length of the buffers used for transfers: a register address followed by
+/// a full FIFO.
+pub const BUFFER_LEN: usize = 1 + 6 * FIFO_DEPTH;
+
+const WHO_AM_I: u8 = 0x33;
+
+mod register {
+    pub const WHO_AM_I: u8 = 0x0F;
+    pub const CTRL_REG1: u8 = 0x20;
+    pub const OUT_X_L: u8 = 0x28;
+    pub const FIFO_CTRL_REG: u8 = 0x2E;
+    pub const FIFO_SRC_REG: u8 = 0x2F;
+    pub const INT1_CFG: u8 = 0x30;
+    pub const INT1_THS: u8 = 0x32;
+    pub const CLICK_CFG: u8 = 0x38;
+    pub const CLICK_THS: u8 = 0x3A;
+}
+
+/// Enable the X, Y and Z axes.
+const CTRL_REG1_XYZ: u8 = 0x07;
+/// Filter the data used for tap detection with the high-pass filter, so
+/// gravity does not count as a tap.
+const CTRL_REG2_HPCLICK: u8 = 0x04;
+const CTRL_REG3_I1_CLICK: u8 = 0x80;
+const CTRL_REG3_I1_IA1: u8 = 0x40;
+const CTRL_REG3_I1_WTM: u8 = 0x04;
+/// Block data update, so the bytes of a sample belong together.
+const CTRL_REG4_BDU: u8 = 0x80;
+const CTRL_REG4_HR: u8 = 0x08;
+const CTRL_REG5_FIFO_EN: u8 = 0x40;
+const CTRL_REG5_LIR_INT1: u8 = 0x08;
+const FIFO_CTRL_STREAM: u8 = 0x80;
+const FIFO_SRC_WTM: u8 = 0x80;
+const FIFO_SRC_OVRN: u8 = 0x40;
+const FIFO_SRC_FSS_MASK: u8 = 0x1F;
+/// Free fall: all of X, Y and Z below the threshold.
+const INT1_CFG_FREE_FALL: u8 = 0x95;
+const INT_SRC_IA: u8 = 0x40;
+/// Single and double taps on all axes.
+const CLICK_CFG_ALL: u8 = 0x3F;
+const CLICK_SRC_DCLICK: u8 = 0x20;
+const CLICK_SRC_SCLICK: u8 = 0x10;
+const CLICK_THS_LIR: u8 = 0x80;
+
+const FREE_FALL_THRESHOLD_MG: u32 = 350;
+const FREE_FALL_DURATION_MS: u32 = 30;
+
+/// Number of register writes in the init sequence.
+const INIT_STEPS: usize = 6;
+
+/// Number of registers read when INT1 fires, from `FIFO_SRC_REG` to
+/// `CLICK_SRC`.
+const STATUS_LEN: usize = 11;
+
+/// Output data rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DataRate {
+    Hz1 = 1,
+    Hz10 = 2,
+    Hz25 = 3,
+    Hz50 = 4,
+    Hz100 = 5,
+    Hz200 = 6,
+    Hz400 = 7,
+}
+
+impl DataRate {
+    fn hz(self) -> u32 {
+        match self {
+            DataRate::Hz1 => 1,
+            DataRate::Hz10 => 10,
+            DataRate::Hz25 => 25,
+            DataRate::Hz50 => 50,
+            DataRate::Hz100 => 100,
+            DataRate::Hz200 => 200,
+            DataRate::Hz400 => 400,
+        }
+    }
+
+    /// `ms` in periods of the output data rate, as the durations of the
+    /// interrupt and tap detection are.
+    fn periods(self, ms: u32) -> u8 {
+        (ms.saturating_mul(self.hz()).saturating_add(999) / 1000).min(u8::MAX as u32) as u8
+    }
+}
+
+/// Full-scale range.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Range {
+    G2 = 0,
+    G4 = 1,
+    G8 = 2,
+    G16 = 3,
+}
+
+impl Range {
+    /// Sensitivity of the 12-bit samples in high-resolution mode.
+    fn mg_per_digit(self) -> i32 {
+        match self {
+            Range::G2 => 1,
+            Range::G4 => 2,
+            Range::G8 => 4,
+            Range::G16 => 12,
+        }
+    }
+
+    /// `mg` in steps of the 7-bit interrupt and tap thresholds.
+    fn threshold(self, mg: u32) -> u8 {
+        let step = match self {
+            Range::G2 => 16,
+            Range::G4 => 32,
+            Range::G8 => 62,
+            Range::G16 => 186,
+        };
+        (mg / step).min(0x7F) as u8
+    }
+}
+
+/// Configuration of the tap detection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TapThresholds {
+    /// Acceleration above which a tap starts.
+    pub threshold_mg: u32,
+    /// Longest time the acceleration can stay above the threshold for a tap.
+    pub time_limit_ms: u32,
+    /// Time after a tap before the second tap of a double tap can start.
+    pub latency_ms: u32,
+    /// Time after the latency in which the second tap of a double tap has to
+    /// start.
+    pub window_ms: u32,
+}
+
+/// An acceleration, in milli-g.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Acceleration {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// An event detected by the sensor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    SingleTap,
+    DoubleTap,
+    FreeFall,
+}
+
+pub trait Lis3dhClient {
+    /// Called when [Lis3dh::start] finishes.
+    fn configured(&self, result: Result<(), ErrorCode>);
+
+    /// Called with the samples of the FIFO once it reaches the watermark,
+    /// oldest first.
+    fn burst(&self, samples: &[Acceleration]);
+
+    /// Called when a tap or a free fall is detected.
+    fn event(&self, event: Event);
+}
+
+/// A bus over which the LIS3DH is connected.
+///
+/// Byte 0 of the buffers passed to the transport holds the address of the
+/// first register to access. The transport sets the bits that make the
+/// sensor increment the address when more than one register is accessed.
+pub trait Transport<'a> {
+    fn set_client(&self, client: &'a dyn TransportClient);
+
+    /// Write the `len` bytes starting at `buffer[1]` to consecutive
+    /// registers.
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Read `len` consecutive registers into the start of `buffer`.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait TransportClient {
+    /// Called when a write or a read finishes.
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>);
+}
+
+/// Set in the register address to increment it over I2C.
+const I2C_AUTO_INCREMENT: u8 = 0x80;
+
+/// LIS3DH connected over I2C.
+pub struct I2CTransport<'a, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, I: i2c::I2CDevice> I2CTransport<'a, I> {
+    pub fn new(i2c: &'a I) -> I2CTransport<'a, I> {
+        I2CTransport {
+            i2c,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> Transport<'a> for I2CTransport<'a, I> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len == 0 || len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if len > 1 {
+            buffer[0] |= I2C_AUTO_INCREMENT;
+        }
+        self.i2c.enable();
+        self.i2c.write(buffer, len + 1).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len == 0 || len > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if len > 1 {
+            buffer[0] |= I2C_AUTO_INCREMENT;
+        }
+        self.i2c.enable();
+        self.i2c.write_read(buffer, 1, len).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> i2c::I2CClient for I2CTransport<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        self.i2c.disable();
+        self.client
+            .map(|client| client.transfer_done(buffer, status.map_err(|e| e.into())));
+    }
+}
+
+/// Set in the register address to read over SPI.
+const SPI_READ: u8 = 0x80;
+/// Set in the register address to increment it over SPI.
+const SPI_AUTO_INCREMENT: u8 = 0x40;
+
+/// LIS3DH connected over SPI.
+pub struct SpiTransport<'a, S: spi::SpiMasterDevice<'a>> {
+    spi: &'a S,
+    /// Write buffer used while reading.
+    tx_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> SpiTransport<'a, S> {
+    pub fn new(spi: &'a S, tx_buffer: &'static mut [u8]) -> SpiTransport<'a, S> {
+        SpiTransport {
+            spi,
+            tx_buffer: TakeCell::new(tx_buffer),
+            len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn configure(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.spi.configure(
+            spi::ClockPolarity::IdleHigh,
+            spi::ClockPhase::SampleTrailing,
+            rate,
+        )
+    }
+
+    fn address(register: u8, len: usize) -> u8 {
+        if len > 1 {
+            register | SPI_AUTO_INCREMENT
+        } else {
+            register
+        }
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> Transport<'a> for SpiTransport<'a, S> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len == 0 || len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        buffer[0] = Self::address(buffer[0] & !(SPI_READ | SPI_AUTO_INCREMENT), len);
+        self.len.set(0);
+        self.spi
+            .read_write_bytes(buffer, None, len + 1)
+            .map_err(|(e, buffer, _)| (e, buffer))
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let tx_buffer = match self.tx_buffer.take() {
+            Some(tx_buffer) => tx_buffer,
+            None => return Err((ErrorCode::BUSY, buffer)),
+        };
+        if len == 0 || len + 1 > buffer.len() || len + 1 > tx_buffer.len() {
+            self.tx_buffer.replace(tx_buffer);
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        tx_buffer[..len + 1].fill(0);
+        tx_buffer[0] = Self::address(buffer[0] | SPI_READ, len);
+        self.len.set(len);
+        self.spi
+            .read_write_bytes(tx_buffer, Some(buffer), len + 1)
+            .map_err(|(e, tx_buffer, buffer)| {
+                self.tx_buffer.replace(tx_buffer);
+                (e, buffer.unwrap())
+            })
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> spi::SpiMasterClient for SpiTransport<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let buffer = match read_buffer {
+            Some(read_buffer) => {
+                self.tx_buffer.replace(write_buffer);
+                // Skip the byte clocked in with the address.
+                read_buffer.copy_within(1..self.len.get() + 1, 0);
+                read_buffer
+            }
+            None => write_buffer,
+        };
+        self.client
+            .map(|client| client.transfer_done(buffer, status));
+    }
+}
+
+/// Decode a sample of the output registers or the FIFO, X, Y and Z in
+/// little-endian.
+fn decode(data: &[u8], range: Range) -> Acceleration {
+    // Samples are 12 bits, left-justified.
+    let axis = |i: usize| (i16::from_le_bytes([data[i], data[i + 1]]) >> 4) as i32;
+    let mg_per_digit = range.mg_per_digit();
+    Acceleration {
+        x: axis(0) * mg_per_digit,
+        y: axis(2) * mg_per_digit,
+        z: axis(4) * mg_per_digit,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Stopped,
+    ReadWhoAmI,
+    /// Writing the init sequence, the step that is being written.
+    Init(usize),
+    Idle,
+    ReadSample,
+    ReadStatus,
+    /// Reading this number of samples from the FIFO.
+    ReadFifo(usize),
+}
+
+pub struct Lis3dh<'a, T: Transport<'a>, G: gpio::InterruptPin<'a>> {
+    transport: &'a T,
+    int1: &'a G,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// INT1 rose while the driver was busy.
+    int_pending: Cell<bool>,
+    /// A reading waits for the next burst of the FIFO.
+    read_pending: Cell<bool>,
+    data_rate: Cell<DataRate>,
+    range: Cell<Range>,
+    tap: Cell<Option<TapThresholds>>,
+    fifo_watermark: Cell<Option<u8>>,
+    client: OptionalCell<&'a dyn Lis3dhClient>,
+    ninedof_client: OptionalCell<&'a dyn NineDofClient>,
+}
+
+impl<'a, T: Transport<'a>, G: gpio::InterruptPin<'a>> Lis3dh<'a, T, G> {
+    pub fn new(transport: &'a T, int1: &'a G, buffer: &'static mut [u8]) -> Lis3dh<'a, T, G> {
+        Lis3dh {
+            transport,
+            int1,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Stopped),
+            int_pending: Cell::new(false),
+            read_pending: Cell::new(false),
+            data_rate: Cell::new(DataRate::Hz100),
+            range: Cell::new(Range::G2),
+            tap: Cell::new(None),
+            fifo_watermark: Cell::new(None),
+            client: OptionalCell::empty(),
+            ninedof_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Lis3dhClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the output data rate and range, the tap detection, and the
+    /// number of samples, 1 to 31, at which the FIFO delivers a burst.
+    /// Taps are not detected without `tap`, and the FIFO is not used
+    /// without `fifo_watermark`. Takes effect at the next [Lis3dh::start].
+    pub fn configure(
+        &self,
+        data_rate: DataRate,
+        range: Range,
+        tap: Option<TapThresholds>,
+        fifo_watermark: Option<u8>,
+    ) -> Result<(), ErrorCode> {
+        if fifo_watermark
+            .is_some_and(|watermark| watermark == 0 || watermark as usize >= FIFO_DEPTH)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        self.data_rate.set(data_rate);
+        self.range.set(range);
+        self.tap.set(tap);
+        self.fifo_watermark.set(fifo_watermark);
+        Ok(())
+    }
+
+    /// Check the identity of the sensor and configure it. The client is
+    /// called once the sensor is configured.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Stopped => {}
+            State::Idle => return Err(ErrorCode::ALREADY),
+            _ => return Err(ErrorCode::BUSY),
+        }
+        self.int1.disable_interrupts();
+        self.read(State::ReadWhoAmI, register::WHO_AM_I, 1)
+    }
+
+    fn write(&self, state: State, register: u8, data: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            buffer[1..data.len() + 1].copy_from_slice(data);
+            self.state.set(state);
+            self.transport
+                .write(buffer, data.len())
+                .map_err(|(e, buffer)| self.transfer_failed(e, buffer))
+        })
+    }
+
+    fn read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register;
+            self.state.set(state);
+            self.transport
+                .read(buffer, len)
+                .map_err(|(e, buffer)| self.transfer_failed(e, buffer))
+        })
+    }
+
+    fn transfer_failed(&self, error: ErrorCode, buffer: &'static mut [u8]) -> ErrorCode {
+        self.buffer.replace(buffer);
+        self.state.set(match self.state.get() {
+            State::ReadWhoAmI | State::Init(_) => State::Stopped,
+            _ => State::Idle,
+        });
+        error
+    }
+
+    /// Write the registers of one step of the init sequence.
+    fn write_init_step(&self, step: usize) -> Result<(), ErrorCode> {
+        let data_rate = self.data_rate.get();
+        let range = self.range.get();
+        let tap = self.tap.get();
+        let watermark = self.fifo_watermark.get();
+        let state = State::Init(step);
+        match step {
+            0 => {
+                let (mut ctrl2, mut ctrl3, mut ctrl5) = (0, CTRL_REG3_I1_IA1, CTRL_REG5_LIR_INT1);
+                if tap.is_some() {
+                    ctrl2 |= CTRL_REG2_HPCLICK;
+                    ctrl3 |= CTRL_REG3_I1_CLICK;
+                }
+                if watermark.is_some() {
+                    ctrl3 |= CTRL_REG3_I1_WTM;
+                    ctrl5 |= CTRL_REG5_FIFO_EN;
+                }
+                self.write(
+                    state,
+                    register::CTRL_REG1,
+                    &[
+                        (data_rate as u8) << 4 | CTRL_REG1_XYZ,
+                        ctrl2,
+                        ctrl3,
+                        CTRL_REG4_BDU | (range as u8) << 4 | CTRL_REG4_HR,
+                        ctrl5,
+                        0x00,
+                    ],
+                )
+            }
+            1 => {
+                // Switching to bypass mode first empties the FIFO.
+                let fifo_ctrl = watermark.map_or(0x00, |watermark| FIFO_CTRL_STREAM | watermark);
+                self.write(state, register::FIFO_CTRL_REG, &[fifo_ctrl])
+            }
+            2 => self.write(
+                state,
+                register::INT1_THS,
+                &[
+                    range.threshold(FREE_FALL_THRESHOLD_MG),
+                    data_rate.periods(FREE_FALL_DURATION_MS),
+                ],
+            ),
+            3 => self.write(state, register::INT1_CFG, &[INT1_CFG_FREE_FALL]),
+            4 => self.write(
+                state,
+                register::CLICK_CFG,
+                &[tap.map_or(0x00, |_| CLICK_CFG_ALL)],
+            ),
+            _ => {
+                let tap = tap.unwrap_or(TapThresholds {
+                    threshold_mg: 0,
+                    time_limit_ms: 0,
+                    latency_ms: 0,
+                    window_ms: 0,
+                });
+                self.write(
+                    state,
+                    register::CLICK_THS,
+                    &[
+                        CLICK_THS_LIR | range.threshold(tap.threshold_mg),
+                        data_rate.periods(tap.time_limit_ms),
+                        data_rate.periods(tap.latency_ms),
+                        data_rate.periods(tap.window_ms),
+                    ],
+                )
+            }
+        }
+    }
+
+    fn configured(&self, result: Result<(), ErrorCode>) {
+        if result.is_ok() {
+            self.state.set(State::Idle);
+            self.int1.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+            // Events detected during the init sequence are not lost.
+            self.int_pending.set(self.int1.read());
+        } else {
+            self.state.set(State::Stopped);
+        }
+        self.client.map(|client| client.configured(result));
+    }
+
+    /// Reads the sources of INT1 if it fired while the driver was busy, or
+    /// if it is still high because of events that were latched since.
+    fn service_interrupt(&self) {
+        if self.state.get() == State::Idle && (self.int_pending.get() || self.int1.read()) {
+            self.int_pending.set(false);
+            let _ = self.read(State::ReadStatus, register::FIFO_SRC_REG, STATUS_LEN);
+        }
+    }
+
+    /// Runs the step after `state` has finished.
+    fn next(&self, state: State, data: &[u8]) -> Result<(), ErrorCode> {
+        match state {
+            State::ReadWhoAmI => {
+                if data[0] != WHO_AM_I {
+                    return Err(ErrorCode::NODEVICE);
+                }
+                self.write_init_step(0)
+            }
+            State::Init(step) if step + 1 < INIT_STEPS => self.write_init_step(step + 1),
+            State::Init(_) => {
+                self.configured(Ok(()));
+                Ok(())
+            }
+            State::ReadStatus => {
+                let (fifo_src, int1_src, click_src) = (data[0], data[2], data[10]);
+                self.state.set(State::Idle);
+                self.client.map(|client| {
+                    if click_src & INT_SRC_IA != 0 {
+                        if click_src & CLICK_SRC_SCLICK != 0 {
+                            client.event(Event::SingleTap);
+                        }
+                        if click_src & CLICK_SRC_DCLICK != 0 {
+                            client.event(Event::DoubleTap);
+                        }
+                    }
+                    if int1_src & INT_SRC_IA != 0 {
+                        client.event(Event::FreeFall);
+                    }
+                });
+                if self.fifo_watermark.get().is_some() && fifo_src & FIFO_SRC_WTM != 0 {
+                    let samples = if fifo_src & FIFO_SRC_OVRN != 0 {
+                        FIFO_DEPTH
+                    } else {
+                        (fifo_src & FIFO_SRC_FSS_MASK) as usize
+                    };
+                    // In FIFO mode, the address wraps around to `OUT_X_L`
+                    // after `OUT_Z_H`, so the samples are read in one go.
+                    self.read(State::ReadFifo(samples), register::OUT_X_L, 6 * samples)?;
+                }
+                Ok(())
+            }
+            State::Stopped | State::Idle | State::ReadSample | State::ReadFifo(_) => Ok(()),
+        }
+    }
+
+    fn deliver_burst(&self, data: &[u8]) {
+        let range = self.range.get();
+        let mut samples = [Acceleration::default(); FIFO_DEPTH];
+        let len = data.len() / 6;
+        for (sample, data) in samples.iter_mut().zip(data.chunks_exact(6)) {
+            *sample = decode(data, range);
+        }
+        self.client.map(|client| client.burst(&samples[..len]));
+        if len > 0 && self.read_pending.take() {
+            self.report(samples[len - 1]);
+        }
+    }
+
+    fn report(&self, acceleration: Acceleration) {
+        self.ninedof_client.map(|client| {
+            client.callback(
+                acceleration.x as isize as usize,
+                acceleration.y as isize as usize,
+                acceleration.z as isize as usize,
+            )
+        });
+    }
+}
+
+impl<'a, T: Transport<'a>, G: gpio::InterruptPin<'a>> TransportClient for Lis3dh<'a, T, G> {
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>) {
+        let state = self.state.get();
+        match state {
+            State::ReadSample | State::ReadFifo(_) => {
+                self.state.set(State::Idle);
+                match (state, status) {
+                    (State::ReadFifo(len), Ok(())) => self.deliver_burst(&buffer[..6 * len]),
+                    (State::ReadSample, Ok(())) => self.report(decode(buffer, self.range.get())),
+                    (State::ReadSample, Err(_)) => {
+                        self.ninedof_client.map(|client| client.callback(0, 0, 0));
+                    }
+                    _ => {}
+                }
+                self.buffer.replace(buffer);
+            }
+            _ => {
+                // The next step may reuse the buffer.
+                let mut data = [0; STATUS_LEN];
+                data.copy_from_slice(&buffer[..STATUS_LEN]);
+                self.buffer.replace(buffer);
+                if let Err(error) = status.and_then(|()| self.next(state, &data)) {
+                    match state {
+                        State::ReadWhoAmI | State::Init(_) => self.configured(Err(error)),
+                        _ => self.state.set(State::Idle),
+                    }
+                }
+            }
+        }
+        self.service_interrupt();
+    }
+}
+
+impl<'a, T: Transport<'a>, G: gpio::InterruptPin<'a>> gpio::Client for Lis3dh<'a, T, G> {
+    fn fired(&self) {
+        self.int_pending.set(true);
+        self.service_interrupt();
+    }
+}
+
+impl<'a, T: Transport<'a>, G: gpio::InterruptPin<'a>> NineDof<'a> for Lis3dh<'a, T, G> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.ninedof_client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Stopped => Err(ErrorCode::OFF),
+            State::ReadWhoAmI | State::Init(_) => Err(ErrorCode::BUSY),
+            _ if self.fifo_watermark.get().is_some() => {
+                // Reading the output registers would take a sample from the
+                // FIFO, so the reading waits for the next burst.
+                if self.read_pending.replace(true) {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    Ok(())
+                }
+            }
+            State::Idle => self.read(State::ReadSample, register::OUT_X_L, 6),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockPin;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A LIS3DH whose interrupt sources and FIFO contents are set by the
+    /// test.
+    struct MockTransport {
+        buffer: TakeCell<'static, [u8]>,
+        len: Cell<usize>,
+        read: Cell<bool>,
+        writes: RefCell<Vec<Vec<u8>>>,
+        status: Cell<[u8; STATUS_LEN]>,
+        fifo: RefCell<Vec<u8>>,
+        client: OptionalCell<&'static dyn TransportClient>,
+    }
+
+    impl MockTransport {
+        /// Completes the transfer in progress, if any.
+        fn complete(&self) -> bool {
+            let buffer = match self.buffer.take() {
+                Some(buffer) => buffer,
+                None => return false,
+            };
+            let len = self.len.get();
+            if self.read.get() {
+                match buffer[0] {
+                    register::WHO_AM_I => buffer[0] = WHO_AM_I,
+                    register::FIFO_SRC_REG => {
+                        buffer[..len].copy_from_slice(&self.status.take());
+                    }
+                    register::OUT_X_L => {
+                        let fifo: Vec<u8> = self.fifo.borrow_mut().drain(..len).collect();
+                        buffer[..len].copy_from_slice(&fifo);
+                    }
+                    _ => {}
+                }
+            } else {
+                self.writes.borrow_mut().push(buffer[..len + 1].to_vec());
+            }
+            self.client
+                .map(|client| client.transfer_done(buffer, Ok(())));
+            true
+        }
+
+        fn run(&self) {
+            while self.complete() {}
+        }
+    }
+
+    impl Transport<'static> for MockTransport {
+        fn set_client(&self, client: &'static dyn TransportClient) {
+            self.client.set(client);
+        }
+
+        fn write(
+            &self,
+            buffer: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.read.set(false);
+            self.len.set(len);
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+
+        fn read(
+            &self,
+            buffer: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.read.set(true);
+            self.len.set(len);
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Client {
+        configured: RefCell<Vec<Result<(), ErrorCode>>>,
+        bursts: RefCell<Vec<Vec<Acceleration>>>,
+        events: RefCell<Vec<Event>>,
+        readings: RefCell<Vec<(usize, usize, usize)>>,
+    }
+
+    impl Lis3dhClient for Client {
+        fn configured(&self, result: Result<(), ErrorCode>) {
+            self.configured.borrow_mut().push(result);
+        }
+        fn burst(&self, samples: &[Acceleration]) {
+            self.bursts.borrow_mut().push(samples.to_vec());
+        }
+        fn event(&self, event: Event) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    impl NineDofClient for Client {
+        fn callback(&self, arg1: usize, arg2: usize, arg3: usize) {
+            self.readings.borrow_mut().push((arg1, arg2, arg3));
+        }
+    }
+
+    fn setup() -> (
+        &'static Lis3dh<'static, MockTransport, MockPin<'static>>,
+        &'static MockTransport,
+        &'static Client,
+    ) {
+        let transport = Box::leak(Box::new(MockTransport {
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            read: Cell::new(false),
+            writes: RefCell::new(Vec::new()),
+            status: Cell::new([0; STATUS_LEN]),
+            fifo: RefCell::new(Vec::new()),
+            client: OptionalCell::empty(),
+        }));
+        let buffer = Box::leak(Box::new([0; BUFFER_LEN]));
+        let int1: &'static MockPin = Box::leak(Box::default());
+        let lis3dh = Box::leak(Box::new(Lis3dh::new(transport, int1, buffer)));
+        let client = Box::leak(Box::new(Client::default()));
+        transport.set_client(lis3dh);
+        lis3dh.set_client(client);
+        NineDof::set_client(lis3dh, client);
+        (lis3dh, transport, client)
+    }
+
+    #[test]
+    fn fifo_burst_is_decoded_per_range() {
+        let (lis3dh, transport, client) = setup();
+        lis3dh
+            .configure(DataRate::Hz100, Range::G4, None, Some(3))
+            .unwrap();
+        assert_eq!(lis3dh.start(), Ok(()));
+        transport.run();
+        assert_eq!(*client.configured.borrow(), [Ok(())]);
+        {
+            let writes = transport.writes.borrow();
+            assert_eq!(writes.len(), INIT_STEPS);
+            assert_eq!(
+                writes[0],
+                [register::CTRL_REG1, 0x57, 0x00, 0x44, 0x98, 0x48, 0x00]
+            );
+            assert_eq!(writes[1], [register::FIFO_CTRL_REG, 0x83]);
+            // 350 mg in steps of 32 mg, and 30 ms at 100 Hz.
+            assert_eq!(writes[2], [register::INT1_THS, 10, 3]);
+        }
+
+        // Answered with the newest sample of the next burst.
+        assert_eq!(lis3dh.read_accelerometer(), Ok(()));
+        assert!(client.readings.borrow().is_empty());
+
+        // Three samples of 12 bits left-justified, at 2 mg per digit. The
+        // low bits of Z in the second sample are not part of it.
+        transport.fifo.borrow_mut().extend_from_slice(&[
+            0x40, 0x1F, 0x60, 0xF0, 0xF0, 0x7F, // 500, -250, 2047
+            0x00, 0x80, 0x00, 0x00, 0x1F, 0x00, // -2048, 0, 1
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x20, // 0, 0, 512
+        ]);
+        let mut status = [0; STATUS_LEN];
+        status[0] = FIFO_SRC_WTM | 3;
+        transport.status.set(status);
+        gpio::Client::fired(lis3dh);
+        transport.run();
+
+        assert!(transport.fifo.borrow().is_empty());
+        let acceleration = |x, y, z| Acceleration { x, y, z };
+        assert_eq!(
+            *client.bursts.borrow(),
+            [[
+                acceleration(1000, -500, 4094),
+                acceleration(-4096, 0, 2),
+                acceleration(0, 0, 1024),
+            ]]
+        );
+        assert_eq!(*client.readings.borrow(), [(0, 0, 1024)]);
+        assert!(client.events.borrow().is_empty());
+    }
+
+    #[test]
+    fn tap_event_fires_on_interrupt() {
+        let (lis3dh, transport, client) = setup();
+        let tap = TapThresholds {
+            threshold_mg: 480,
+            time_limit_ms: 20,
+            latency_ms: 50,
+            window_ms: 200,
+        };
+        lis3dh
+            .configure(DataRate::Hz400, Range::G2, Some(tap), None)
+            .unwrap();
+        assert_eq!(lis3dh.start(), Ok(()));
+        transport.run();
+        {
+            let writes = transport.writes.borrow();
+            assert_eq!(writes[0][1..5], [0x77, 0x04, 0xC0, 0x88]);
+            assert_eq!(writes[1], [register::FIFO_CTRL_REG, 0x00]);
+            assert_eq!(writes[4], [register::CLICK_CFG, 0x3F]);
+            assert_eq!(writes[5], [register::CLICK_THS, 0x80 | 30, 8, 20, 80]);
+        }
+
+        // A single tap on Z.
+        let mut status = [0; STATUS_LEN];
+        status[10] = INT_SRC_IA | CLICK_SRC_SCLICK | 0x04;
+        transport.status.set(status);
+        gpio::Client::fired(lis3dh);
+        transport.run();
+        assert_eq!(*client.events.borrow(), [Event::SingleTap]);
+
+        // A double tap, and a free fall.
+        status[10] = INT_SRC_IA | CLICK_SRC_DCLICK;
+        status[2] = INT_SRC_IA;
+        transport.status.set(status);
+        gpio::Client::fired(lis3dh);
+        transport.run();
+        assert_eq!(
+            *client.events.borrow(),
+            [Event::SingleTap, Event::DoubleTap, Event::FreeFall]
+        );
+        assert!(client.bursts.borrow().is_empty());
+    }
+}
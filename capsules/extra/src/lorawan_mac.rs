@@ -0,0 +1,933 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! LoRaWAN 1.0 Class A end device, on an SX1276 transceiver.
+//!
+//! The MAC joins a network with over-the-air activation, and then sends
+//! unconfirmed uplinks. After each uplink, it opens the two receive windows
+//! of Class A, and delivers the application payload of a downlink received
+//! in them.
+//!
+//! Frames are encrypted and authenticated with an AES engine that supports
+//! ECB and CBC. The message integrity codes are AES-CMAC, computed as a
+//! CBC-MAC over the padded message, with the subkeys of an ECB block.
+//!
+//! The MAC is a foundation, with limits:
+//!
+//! - Uplinks use the fixed data rate and channels of a [`Region`], and the
+//!   receive windows the fixed data rates of the region. The data rate
+//!   settings of the join accept, the channels of its CFList and MAC
+//!   commands are ignored, and there is no adaptive data rate.
+//! - Confirmed downlinks are delivered, but not acknowledged.
+//! - The session and the DevNonce are kept in RAM. A board that keeps the
+//!   DevNonce across resets sets it with `set_dev_nonce`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lorawan = components::lorawan_mac::LorawanMacComponent::new(
+//!     spi_mux,
+//!     nrf52840::gpio::Pin::P0_12,
+//!     &nrf52840_peripherals.gpio_port[DIO0_PIN],
+//!     &nrf52840_peripherals.gpio_port[DIO1_PIN],
+//!     mux_alarm,
+//!     aes,
+//!     capsules_extra::lorawan_mac::EU868,
+//!     DEV_EUI,
+//!     JOIN_EUI,
+//!     APP_KEY,
+//! )
+//! .finalize(components::lorawan_mac_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc,
+//!     capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<
+//!         'static,
+//!         nrf52840::aes::AesECB<'static>,
+//!     >
+//! ));
+//! lorawan.set_client(app);
+//! lorawan.join();
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::lora::{LoraWan, LoraWanClient};
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::symmetric_encryption::{self, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::sx1276::{self, Bandwidth, CodingRate, LoraConfig, Sx1276, Sx1276Client};
+
+/// Size of the frame buffer.
+pub const FRAME_SIZE: usize = sx1276::MAX_PACKET_SIZE;
+
+/// Size of the buffer the AES engine works on: a block B0 and the largest
+/// frame.
+pub const CRYPTO_SIZE: usize = AES128_BLOCK_SIZE + FRAME_SIZE;
+
+/// The largest application payload of an uplink: MHDR, DevAddr, FCtrl,
+/// FCnt, FPort and the MIC take 13 bytes.
+pub const MAX_PAYLOAD: usize = FRAME_SIZE - 13;
+
+const MHDR_JOIN_REQUEST: u8 = 0x00;
+const MHDR_JOIN_ACCEPT: u8 = 0x20;
+const MHDR_UNCONFIRMED_UP: u8 = 0x40;
+const MHDR_UNCONFIRMED_DOWN: u8 = 0x60;
+const MHDR_CONFIRMED_DOWN: u8 = 0xA0;
+
+const JOIN_REQUEST_LEN: usize = 19;
+const MIC_LEN: usize = 4;
+
+/// Delays from the end of a transmission to the receive windows, in ms.
+const JOIN_ACCEPT_DELAY: u32 = 5000;
+const RECEIVE_DELAY: u32 = 1000;
+/// The second window opens this long after the first.
+const RX2_DELAY: u32 = 1000;
+/// The receiver is started this early, in ms, for the timing errors of the
+/// alarm and the transceiver.
+const RX_EARLY: u32 = 20;
+/// Symbols of the preamble the receiver needs to lock on.
+const PREAMBLE_SYMBOLS: u32 = 8;
+
+/// A LoRa data rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataRate {
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+}
+
+/// The channels and data rates of a regional plan.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    /// Uplink frequencies in Hz, used in turn.
+    pub uplink: &'static [u32],
+    pub uplink_rate: DataRate,
+    /// Frequencies of the first receive window, for the uplink channel of
+    /// the same index, modulo their count.
+    pub rx1: &'static [u32],
+    pub rx1_rate: DataRate,
+    pub rx2_frequency: u32,
+    pub rx2_rate: DataRate,
+}
+
+const EU868_CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+
+/// The three default channels of EU863-870, at DR5.
+pub const EU868: Region = Region {
+    uplink: &EU868_CHANNELS,
+    uplink_rate: DataRate {
+        spreading_factor: 7,
+        bandwidth: Bandwidth::Khz125,
+    },
+    rx1: &EU868_CHANNELS,
+    rx1_rate: DataRate {
+        spreading_factor: 7,
+        bandwidth: Bandwidth::Khz125,
+    },
+    rx2_frequency: 869_525_000,
+    rx2_rate: DataRate {
+        spreading_factor: 12,
+        bandwidth: Bandwidth::Khz125,
+    },
+};
+
+/// Sub-band 2 of US902-928 (channels 8 to 15), at DR3, which most networks
+/// use.
+pub const US915_SUB_BAND_2: Region = Region {
+    uplink: &[
+        903_900_000,
+        904_100_000,
+        904_300_000,
+        904_500_000,
+        904_700_000,
+        904_900_000,
+        905_100_000,
+        905_300_000,
+    ],
+    uplink_rate: DataRate {
+        spreading_factor: 7,
+        bandwidth: Bandwidth::Khz125,
+    },
+    rx1: &[
+        923_300_000,
+        923_900_000,
+        924_500_000,
+        925_100_000,
+        925_700_000,
+        926_300_000,
+        926_900_000,
+        927_500_000,
+    ],
+    rx1_rate: DataRate {
+        spreading_factor: 7,
+        bandwidth: Bandwidth::Khz500,
+    },
+    rx2_frequency: 923_300_000,
+    rx2_rate: DataRate {
+        spreading_factor: 12,
+        bandwidth: Bandwidth::Khz500,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Session {
+    dev_addr: [u8; 4],
+    nwk_s_key: [u8; 16],
+    app_s_key: [u8; 16],
+    fcnt_up: u32,
+    /// The next downlink counter accepted.
+    fcnt_down: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exchange {
+    Join,
+    Uplink,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Window {
+    Rx1,
+    Rx2,
+}
+
+/// The frames the MIC is computed for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mic {
+    JoinRequest,
+    JoinAccept(Window),
+    Uplink,
+    Downlink(Window),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Encrypting a zero block, for the CMAC subkeys.
+    Subkey(Mic),
+    Mic(Mic),
+    EncryptingUplink,
+    Transmitting,
+    Waiting(Window),
+    Receiving(Window),
+    DecryptingJoinAccept(Window),
+    DerivingKeys,
+    DecryptingDownlink,
+}
+
+pub struct LorawanMac<
+    'a,
+    S: SpiMasterDevice<'a>,
+    A: Alarm<'a>,
+    E: AES128<'a> + AES128ECB + AES128CBC,
+> {
+    radio: &'a Sx1276<'a, S, A>,
+    alarm: &'a A,
+    aes: &'a E,
+    region: Region,
+    dev_eui: [u8; 8],
+    join_eui: [u8; 8],
+    app_key: [u8; 16],
+    client: OptionalCell<&'a dyn LoraWanClient>,
+    state: Cell<State>,
+    exchange: Cell<Exchange>,
+    radio_ready: Cell<bool>,
+    frame: TakeCell<'static, [u8]>,
+    frame_len: Cell<usize>,
+    crypto: TakeCell<'static, [u8]>,
+    /// The blocks of the AES operation in progress.
+    crypto_blocks: Cell<usize>,
+    /// The range of the application payload in the frame.
+    payload_range: Cell<(usize, usize)>,
+    /// The payload of the uplink in progress.
+    payload: TakeCell<'static, [u8]>,
+    /// The buffer for the next downlink.
+    downlink: TakeCell<'static, [u8]>,
+    downlink_fcnt: Cell<u32>,
+    session: OptionalCell<Session>,
+    dev_nonce: Cell<u16>,
+    channel: Cell<usize>,
+    tx_done_at: Cell<A::Ticks>,
+    rx_delay: Cell<u32>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, E: AES128<'a> + AES128ECB + AES128CBC>
+    LorawanMac<'a, S, A, E>
+{
+    /// `dev_eui` and `join_eui` are in the order they are written in, most
+    /// significant byte first.
+    pub fn new(
+        radio: &'a Sx1276<'a, S, A>,
+        alarm: &'a A,
+        aes: &'a E,
+        region: Region,
+        dev_eui: [u8; 8],
+        join_eui: [u8; 8],
+        app_key: [u8; 16],
+        frame: &'static mut [u8; FRAME_SIZE],
+        crypto: &'static mut [u8; CRYPTO_SIZE],
+    ) -> LorawanMac<'a, S, A, E> {
+        LorawanMac {
+            radio,
+            alarm,
+            aes,
+            region,
+            dev_eui,
+            join_eui,
+            app_key,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            exchange: Cell::new(Exchange::Join),
+            radio_ready: Cell::new(false),
+            frame: TakeCell::new(frame),
+            frame_len: Cell::new(0),
+            crypto: TakeCell::new(crypto),
+            crypto_blocks: Cell::new(0),
+            payload_range: Cell::new((0, 0)),
+            payload: TakeCell::empty(),
+            downlink: TakeCell::empty(),
+            downlink_fcnt: Cell::new(0),
+            session: OptionalCell::empty(),
+            dev_nonce: Cell::new(0),
+            channel: Cell::new(0),
+            tx_done_at: Cell::new(A::Ticks::from(0)),
+            rx_delay: Cell::new(RECEIVE_DELAY),
+        }
+    }
+
+    /// Set the DevNonce of the next join, which the network expects to
+    /// never repeat.
+    pub fn set_dev_nonce(&self, dev_nonce: u16) {
+        self.dev_nonce.set(dev_nonce);
+    }
+
+    /// The key the MIC of `mic` is computed with.
+    fn mic_key(&self, mic: Mic) -> [u8; 16] {
+        match mic {
+            Mic::JoinRequest | Mic::JoinAccept(_) => self.app_key,
+            Mic::Uplink | Mic::Downlink(_) => {
+                self.session.map_or([0; 16], |session| session.nwk_s_key)
+            }
+        }
+    }
+
+    /// Run the AES engine on the first `blocks` blocks of the crypto
+    /// buffer, and continue in `state`.
+    fn crypt(
+        &self,
+        key: &[u8; 16],
+        blocks: usize,
+        cbc: bool,
+        state: State,
+    ) -> Result<(), ErrorCode> {
+        if cbc {
+            self.aes.set_mode_aes128cbc(true)?;
+            self.aes.set_iv(&[0; AES128_BLOCK_SIZE])?;
+        } else {
+            self.aes.set_mode_aes128ecb(true)?;
+        }
+        self.aes.set_key(key)?;
+        self.aes.start_message();
+        let crypto = self.crypto.take().ok_or(ErrorCode::BUSY)?;
+        match self.aes.crypt(None, crypto, 0, blocks * AES128_BLOCK_SIZE) {
+            None => {
+                self.crypto_blocks.set(blocks);
+                self.state.set(state);
+                Ok(())
+            }
+            Some((result, _, crypto)) => {
+                self.crypto.replace(crypto);
+                result.and(Err(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// Start the MIC of `mic` by computing the CMAC subkeys of its key.
+    fn start_mic(&self, mic: Mic) -> Result<(), ErrorCode> {
+        self.crypto
+            .map(|crypto| crypto[..AES128_BLOCK_SIZE].fill(0));
+        self.crypt(&self.mic_key(mic), 1, false, State::Subkey(mic))
+    }
+
+    /// Write the message of the MIC of `mic` to `crypto`, and return its
+    /// length.
+    fn mic_message(&self, mic: Mic, frame: &[u8], crypto: &mut [u8]) -> usize {
+        let len = self.frame_len.get();
+        let (message, b0) = match mic {
+            Mic::JoinRequest => (&frame[..JOIN_REQUEST_LEN], None),
+            Mic::JoinAccept(_) => (&frame[..len - MIC_LEN], None),
+            Mic::Uplink => (
+                &frame[..len],
+                Some((0, self.session.map_or(0, |session| session.fcnt_up))),
+            ),
+            Mic::Downlink(_) => (&frame[..len - MIC_LEN], Some((1, self.downlink_fcnt.get()))),
+        };
+        match b0 {
+            Some((direction, fcnt)) => {
+                crypto[..AES128_BLOCK_SIZE].copy_from_slice(&self.block(0x49, direction, fcnt));
+                crypto[AES128_BLOCK_SIZE - 1] = message.len() as u8;
+                crypto[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + message.len()]
+                    .copy_from_slice(message);
+                AES128_BLOCK_SIZE + message.len()
+            }
+            None => {
+                crypto[..message.len()].copy_from_slice(message);
+                message.len()
+            }
+        }
+    }
+
+    /// The B0 and A blocks of a data frame, with the last byte left 0.
+    fn block(&self, first: u8, direction: u8, fcnt: u32) -> [u8; AES128_BLOCK_SIZE] {
+        let mut block = [0; AES128_BLOCK_SIZE];
+        block[0] = first;
+        block[5] = direction;
+        block[6..10].copy_from_slice(&self.session.map_or([0; 4], |session| session.dev_addr));
+        block[10..14].copy_from_slice(&fcnt.to_le_bytes());
+        block
+    }
+
+    /// Compute the MIC of `mic`, once the encrypted zero block the subkeys
+    /// are derived from is in the crypto buffer.
+    fn compute_mic(&self, mic: Mic) -> Result<(), ErrorCode> {
+        let blocks = self
+            .crypto
+            .map(|crypto| {
+                let (k1, k2) = subkeys(crypto[..AES128_BLOCK_SIZE].try_into().unwrap_or([0; 16]));
+                let len = self
+                    .frame
+                    .map_or(0, |frame| self.mic_message(mic, frame, crypto));
+                let (blocks, subkey) = if len > 0 && len % AES128_BLOCK_SIZE == 0 {
+                    (len / AES128_BLOCK_SIZE, k1)
+                } else {
+                    let blocks = len / AES128_BLOCK_SIZE + 1;
+                    crypto[len] = 0x80;
+                    crypto[len + 1..blocks * AES128_BLOCK_SIZE].fill(0);
+                    (blocks, k2)
+                };
+                let last = (blocks - 1) * AES128_BLOCK_SIZE;
+                crypto[last..last + AES128_BLOCK_SIZE]
+                    .iter_mut()
+                    .zip(subkey.iter())
+                    .for_each(|(byte, key)| *byte ^= key);
+                blocks
+            })
+            .ok_or(ErrorCode::BUSY)?;
+        self.crypt(&self.mic_key(mic), blocks, true, State::Mic(mic))
+    }
+
+    /// Continue with the MIC of `mic`, the first bytes of `cmac`.
+    fn mic_done(&self, mic: Mic, cmac: [u8; MIC_LEN]) -> Result<(), ErrorCode> {
+        let len = self.frame_len.get();
+        match mic {
+            Mic::JoinRequest | Mic::Uplink => {
+                self.frame
+                    .map(|frame| frame[len..len + MIC_LEN].copy_from_slice(&cmac));
+                self.frame_len.set(len + MIC_LEN);
+                self.transmit()
+            }
+            Mic::JoinAccept(window) | Mic::Downlink(window) => {
+                if self
+                    .frame
+                    .map_or(false, |frame| frame[len - MIC_LEN..len] == cmac)
+                {
+                    self.frame_valid(mic)
+                } else {
+                    self.missed(window)
+                }
+            }
+        }
+    }
+
+    /// Continue with a received frame whose MIC is valid.
+    fn frame_valid(&self, mic: Mic) -> Result<(), ErrorCode> {
+        if let Mic::Downlink(_) = mic {
+            let fcnt = self.downlink_fcnt.get();
+            self.session
+                .map(|session| session.fcnt_down = fcnt.wrapping_add(1));
+            let (start, end) = self.payload_range.get();
+            let port = self.frame.map_or(0, |frame| frame[start - 1]);
+            if start == end || port == 0 || self.downlink.is_none() {
+                // Nothing for the application, or nowhere to deliver it.
+                self.finish(Ok(()));
+                return Ok(());
+            }
+            let key = self.session.map_or([0; 16], |session| session.app_s_key);
+            return self.keystream(&key, 1, fcnt, end - start, State::DecryptingDownlink);
+        }
+        // The keys are derived from the AppNonce, the NetID and the DevNonce
+        // of the join request.
+        let nonce = self.dev_nonce.get().wrapping_sub(1).to_le_bytes();
+        self.frame
+            .map(|frame| {
+                self.crypto.map(|crypto| {
+                    for (block, first) in [(0, 0x01), (AES128_BLOCK_SIZE, 0x02)] {
+                        crypto[block..block + AES128_BLOCK_SIZE].fill(0);
+                        crypto[block] = first;
+                        crypto[block + 1..block + 7].copy_from_slice(&frame[1..7]);
+                        crypto[block + 7..block + 9].copy_from_slice(&nonce);
+                    }
+                })
+            })
+            .ok_or(ErrorCode::BUSY)?;
+        self.crypt(&self.app_key, 2, false, State::DerivingKeys)
+    }
+
+    /// Compute the keystream for `len` bytes of a data frame in `direction`
+    /// with counter `fcnt`.
+    fn keystream(
+        &self,
+        key: &[u8; 16],
+        direction: u8,
+        fcnt: u32,
+        len: usize,
+        state: State,
+    ) -> Result<(), ErrorCode> {
+        let blocks = (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE;
+        self.crypto
+            .map(|crypto| {
+                for i in 0..blocks {
+                    let mut block = self.block(0x01, direction, fcnt);
+                    block[AES128_BLOCK_SIZE - 1] = i as u8 + 1;
+                    crypto[i * AES128_BLOCK_SIZE..(i + 1) * AES128_BLOCK_SIZE]
+                        .copy_from_slice(&block);
+                }
+            })
+            .ok_or(ErrorCode::BUSY)?;
+        self.crypt(key, blocks, false, state)
+    }
+
+    /// XOR the payload of the frame with the keystream.
+    fn apply_keystream(&self) {
+        let (start, end) = self.payload_range.get();
+        self.frame.map(|frame| {
+            self.crypto.map(|crypto| {
+                frame[start..end]
+                    .iter_mut()
+                    .zip(crypto.iter())
+                    .for_each(|(byte, key)| *byte ^= key);
+            })
+        });
+    }
+
+    fn transmit(&self) -> Result<(), ErrorCode> {
+        let channel = (self.channel.get() + 1) % self.region.uplink.len();
+        self.channel.set(channel);
+        let config = LoraConfig {
+            frequency_hz: self.region.uplink[channel],
+            spreading_factor: self.region.uplink_rate.spreading_factor,
+            bandwidth: self.region.uplink_rate.bandwidth,
+            coding_rate: CodingRate::Cr4_5,
+            invert_iq: false,
+        };
+        let frame = self.frame.take().ok_or(ErrorCode::BUSY)?;
+        self.radio
+            .transmit(config, frame, self.frame_len.get())
+            .map_err(|(e, frame)| {
+                self.frame.replace(frame);
+                e
+            })?;
+        self.state.set(State::Transmitting);
+        Ok(())
+    }
+
+    /// The delay from the end of the transmission to `window`, in ms.
+    fn window_delay(&self, window: Window) -> u32 {
+        let rx1 = match self.exchange.get() {
+            Exchange::Join => JOIN_ACCEPT_DELAY,
+            Exchange::Uplink => self.rx_delay.get(),
+        };
+        match window {
+            Window::Rx1 => rx1,
+            Window::Rx2 => rx1 + RX2_DELAY,
+        }
+    }
+
+    fn wait(&self, window: Window) {
+        self.state.set(State::Waiting(window));
+        self.alarm.set_alarm(
+            self.tx_done_at.get(),
+            self.alarm
+                .ticks_from_ms(self.window_delay(window) - RX_EARLY),
+        );
+    }
+
+    fn open_window(&self, window: Window) -> Result<(), ErrorCode> {
+        let (frequency_hz, rate) = match window {
+            Window::Rx1 => (
+                self.region.rx1[self.channel.get() % self.region.rx1.len()],
+                self.region.rx1_rate,
+            ),
+            Window::Rx2 => (self.region.rx2_frequency, self.region.rx2_rate),
+        };
+        let config = LoraConfig {
+            frequency_hz,
+            spreading_factor: rate.spreading_factor,
+            bandwidth: rate.bandwidth,
+            coding_rate: CodingRate::Cr4_5,
+            invert_iq: true,
+        };
+        // Listen from early before the window to the end of the preamble
+        // of a downlink that starts late by as much.
+        let timeout = PREAMBLE_SYMBOLS + 2 * RX_EARLY * 1000 / config.symbol_us();
+        let frame = self.frame.take().ok_or(ErrorCode::BUSY)?;
+        self.radio
+            .receive(config, timeout.min(u16::MAX as u32) as u16, frame)
+            .map_err(|(e, frame)| {
+                self.frame.replace(frame);
+                e
+            })?;
+        self.state.set(State::Receiving(window));
+        Ok(())
+    }
+
+    /// Nothing valid was received in `window`.
+    fn missed(&self, window: Window) -> Result<(), ErrorCode> {
+        match (window, self.exchange.get()) {
+            (Window::Rx1, _) => self.wait(Window::Rx2),
+            (Window::Rx2, Exchange::Join) => self.finish(Err(ErrorCode::NOACK)),
+            (Window::Rx2, Exchange::Uplink) => self.finish(Ok(())),
+        }
+        Ok(())
+    }
+
+    /// Check the frame of `len` bytes received in `window`.
+    fn received_frame(&self, window: Window, len: usize) -> Result<(), ErrorCode> {
+        self.frame_len.set(len);
+        match self.exchange.get() {
+            Exchange::Join => {
+                let join_accept = self
+                    .frame
+                    .map_or(false, |frame| frame[0] == MHDR_JOIN_ACCEPT);
+                if !join_accept || (len != 17 && len != 33) {
+                    return self.missed(window);
+                }
+                // Join accepts are encrypted with an AES decryption, so they
+                // are decrypted with an encryption.
+                self.frame
+                    .map(|frame| {
+                        self.crypto
+                            .map(|crypto| crypto[..len - 1].copy_from_slice(&frame[1..len]))
+                    })
+                    .ok_or(ErrorCode::BUSY)?;
+                self.crypt(
+                    &self.app_key,
+                    (len - 1) / AES128_BLOCK_SIZE,
+                    false,
+                    State::DecryptingJoinAccept(window),
+                )
+            }
+            Exchange::Uplink => match self.parse_downlink(len) {
+                Some(fcnt) => {
+                    self.downlink_fcnt.set(fcnt);
+                    self.start_mic(Mic::Downlink(window))
+                }
+                None => self.missed(window),
+            },
+        }
+    }
+
+    /// Check the header of a downlink of `len` bytes, and return its full
+    /// frame counter.
+    fn parse_downlink(&self, len: usize) -> Option<u32> {
+        let session = self.session.extract()?;
+        self.frame.map_or(None, |frame| {
+            // MHDR, DevAddr, FCtrl, FCnt and the MIC.
+            if len < 12
+                || (frame[0] != MHDR_UNCONFIRMED_DOWN && frame[0] != MHDR_CONFIRMED_DOWN)
+                || frame[1..5] != session.dev_addr
+            {
+                return None;
+            }
+            let options_end = 8 + (frame[5] & 0x0F) as usize;
+            let payload_end = len - MIC_LEN;
+            if options_end > payload_end {
+                return None;
+            }
+            // The FPort follows the options if there is a payload.
+            let start = if options_end < payload_end {
+                options_end + 1
+            } else {
+                payload_end
+            };
+            self.payload_range.set((start, payload_end));
+
+            let low = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+            let mut fcnt = session.fcnt_down & 0xFFFF_0000 | low;
+            if fcnt < session.fcnt_down {
+                fcnt = fcnt.wrapping_add(0x1_0000);
+            }
+            Some(fcnt)
+        })
+    }
+
+    /// End the join or uplink in progress.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        match self.exchange.get() {
+            Exchange::Join => {
+                self.client.map(|client| client.join_done(result));
+            }
+            Exchange::Uplink => {
+                self.payload.take().map(|payload| {
+                    self.client
+                        .map(move |client| client.send_done(payload, result))
+                });
+            }
+        }
+    }
+
+    /// Continue once the AES engine finished the operation of `state`.
+    fn crypt_step(&self, state: State) -> Result<(), ErrorCode> {
+        match state {
+            State::Subkey(mic) => self.compute_mic(mic),
+            State::Mic(mic) => {
+                let last = (self.crypto_blocks.get() - 1) * AES128_BLOCK_SIZE;
+                let cmac = self.crypto.map_or([0; MIC_LEN], |crypto| {
+                    crypto[last..last + MIC_LEN]
+                        .try_into()
+                        .unwrap_or([0; MIC_LEN])
+                });
+                self.mic_done(mic, cmac)
+            }
+            State::EncryptingUplink => {
+                self.apply_keystream();
+                self.start_mic(Mic::Uplink)
+            }
+            State::DecryptingJoinAccept(window) => {
+                let len = self.frame_len.get();
+                self.frame.map(|frame| {
+                    self.crypto
+                        .map(|crypto| frame[1..len].copy_from_slice(&crypto[..len - 1]))
+                });
+                self.start_mic(Mic::JoinAccept(window))
+            }
+            State::DerivingKeys => {
+                let session = self
+                    .frame
+                    .map(|frame| {
+                        // RxDelay is in seconds, where 0 means 1.
+                        self.rx_delay.set((frame[12] & 0x0F).max(1) as u32 * 1000);
+                        self.crypto.map(|crypto| Session {
+                            dev_addr: frame[7..11].try_into().unwrap_or([0; 4]),
+                            nwk_s_key: crypto[..16].try_into().unwrap_or([0; 16]),
+                            app_s_key: crypto[16..32].try_into().unwrap_or([0; 16]),
+                            fcnt_up: 0,
+                            fcnt_down: 0,
+                        })
+                    })
+                    .flatten()
+                    .ok_or(ErrorCode::BUSY)?;
+                self.session.set(session);
+                self.finish(Ok(()));
+                Ok(())
+            }
+            State::DecryptingDownlink => {
+                self.apply_keystream();
+                let (start, end) = self.payload_range.get();
+                let port = self.frame.map_or(0, |frame| frame[start - 1]);
+                self.frame.map(|frame| {
+                    self.downlink.take().map(|buffer| {
+                        let len = (end - start).min(buffer.len());
+                        buffer[..len].copy_from_slice(&frame[start..start + len]);
+                        self.client
+                            .map(move |client| client.received(port, buffer, len));
+                    })
+                });
+                self.finish(Ok(()));
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The CMAC subkeys K1 and K2 of the encrypted zero block `l`.
+fn subkeys(l: [u8; 16]) -> ([u8; 16], [u8; 16]) {
+    let double = |block: [u8; 16]| {
+        let mut doubled = (u128::from_be_bytes(block) << 1).to_be_bytes();
+        if block[0] & 0x80 != 0 {
+            doubled[15] ^= 0x87;
+        }
+        doubled
+    };
+    let k1 = double(l);
+    (k1, double(k1))
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, E: AES128<'a> + AES128ECB + AES128CBC> LoraWan<'a>
+    for LorawanMac<'a, S, A, E>
+{
+    fn set_client(&self, client: &'a dyn LoraWanClient) {
+        self.client.set(client);
+    }
+
+    fn join(&self) -> Result<(), ErrorCode> {
+        if !self.radio_ready.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let dev_nonce = self.dev_nonce.get();
+        self.dev_nonce.set(dev_nonce.wrapping_add(1));
+        self.frame
+            .map(|frame| {
+                frame[0] = MHDR_JOIN_REQUEST;
+                for (i, byte) in self.join_eui.iter().rev().enumerate() {
+                    frame[1 + i] = *byte;
+                }
+                for (i, byte) in self.dev_eui.iter().rev().enumerate() {
+                    frame[9 + i] = *byte;
+                }
+                frame[17..19].copy_from_slice(&dev_nonce.to_le_bytes());
+            })
+            .ok_or(ErrorCode::BUSY)?;
+        self.frame_len.set(JOIN_REQUEST_LEN);
+        self.exchange.set(Exchange::Join);
+        self.start_mic(Mic::JoinRequest)
+    }
+
+    fn send(
+        &self,
+        port: u8,
+        payload: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let session = match self.session.extract() {
+            Some(session) => session,
+            None => return Err((ErrorCode::OFF, payload)),
+        };
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, payload));
+        }
+        if port == 0 || port > 223 {
+            return Err((ErrorCode::INVAL, payload));
+        }
+        if len > MAX_PAYLOAD || len > payload.len() {
+            return Err((ErrorCode::SIZE, payload));
+        }
+        let written = self.frame.map(|frame| {
+            frame[0] = MHDR_UNCONFIRMED_UP;
+            frame[1..5].copy_from_slice(&session.dev_addr);
+            frame[5] = 0;
+            frame[6..8].copy_from_slice(&(session.fcnt_up as u16).to_le_bytes());
+            frame[8] = port;
+            frame[9..9 + len].copy_from_slice(&payload[..len]);
+        });
+        if written.is_none() {
+            return Err((ErrorCode::BUSY, payload));
+        }
+        self.frame_len.set(9 + len);
+        self.payload_range.set((9, 9 + len));
+        self.exchange.set(Exchange::Uplink);
+        let started = if len == 0 {
+            self.start_mic(Mic::Uplink)
+        } else {
+            self.keystream(
+                &session.app_s_key,
+                0,
+                session.fcnt_up,
+                len,
+                State::EncryptingUplink,
+            )
+        };
+        match started {
+            Ok(()) => {
+                self.payload.replace(payload);
+                Ok(())
+            }
+            Err(e) => Err((e, payload)),
+        }
+    }
+
+    fn receive(&self, buffer: &'static mut [u8]) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.downlink.is_some() {
+            return Err((ErrorCode::ALREADY, buffer));
+        }
+        self.downlink.replace(buffer);
+        Ok(())
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, E: AES128<'a> + AES128ECB + AES128CBC>
+    symmetric_encryption::Client<'a> for LorawanMac<'a, S, A, E>
+{
+    fn crypt_done(&'a self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        self.crypto.replace(dest);
+        if let Err(e) = self.crypt_step(self.state.get()) {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, E: AES128<'a> + AES128ECB + AES128CBC> Sx1276Client
+    for LorawanMac<'a, S, A, E>
+{
+    fn init_done(&self, result: Result<(), ErrorCode>) {
+        self.radio_ready.set(result.is_ok());
+    }
+
+    fn transmit_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.frame.replace(buffer);
+        if let Err(e) = result {
+            self.finish(Err(e));
+            return;
+        }
+        self.tx_done_at.set(self.alarm.now());
+        if self.exchange.get() == Exchange::Uplink {
+            self.session
+                .map(|session| session.fcnt_up = session.fcnt_up.wrapping_add(1));
+        }
+        self.wait(Window::Rx1);
+    }
+
+    fn receive_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        self.frame.replace(buffer);
+        if let State::Receiving(window) = self.state.get() {
+            let result = match result {
+                Ok(len) => self.received_frame(window, len),
+                Err(_) => self.missed(window),
+            };
+            if let Err(e) = result {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, E: AES128<'a> + AES128ECB + AES128CBC> AlarmClient
+    for LorawanMac<'a, S, A, E>
+{
+    fn alarm(&self) {
+        if let State::Waiting(window) = self.state.get() {
+            if let Err(e) = self.open_window(window) {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> [u8; 16] {
+        let mut block = [0; 16];
+        for (i, byte) in block.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        block
+    }
+
+    #[test]
+    fn cmac_subkeys() {
+        // The subkeys of RFC 4493, section 4.
+        let (k1, k2) = subkeys(hex("7df76b0c1ab899b33e42f047b91b546f"));
+        assert_eq!(k1, hex("fbeed618357133667c85e08f7236a8de"));
+        assert_eq!(k2, hex("f7ddac306ae266ccf90bc11ee46d513b"));
+    }
+}
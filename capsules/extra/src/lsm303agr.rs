@@ -13,69 +13,63 @@
 //! The syscall interface is described in
 //! [lsm303dlhc.md](https://github.com/tock/tock/tree/master/doc/syscalls/70006_lsm303dlhc.md)
 //!
+//! Differences from the LSM303DLHC
+//! -------------------------------
+//!
+//! The AGR shares the accelerometer data rate and scale encodings with the
+//! DLHC, which live in [lsm303xx](crate::lsm303xx), but its magnetometer is
+//! a different block:
+//!
+//! - the magnetometer registers use the `CFG_REG_x_M` layout, with a 2-bit
+//!   output data rate ([Lsm303agrMagnetoDataRate]) and the output registers
+//!   ordered X, Y, Z, low byte first,
+//! - the magnetometer has a fixed +/-50 gauss range with a sensitivity of
+//!   1.5 mgauss/LSB, so there is no range setting,
+//! - the temperature sensor is part of the accelerometer block rather than
+//!   the magnetometer. The driver turns it on as part of [Lsm303agrI2C::configure],
+//!   so no separate enable flag is needed.
+//!
 //! Usage
 //! -----
 //!
 //! ```rust
-//! let mux_i2c = components::i2c::I2CMuxComponent::new(&stm32f3xx::i2c::I2C1)
-//!     .finalize(components::i2c_mux_component_helper!());
+//! let lsm303agr = components::lsm303agr::Lsm303agrI2CComponent::new(
+//!     mux_i2c,
+//!     None,
+//!     None,
+//!     board_kernel,
+//!     capsules_extra::lsm303agr::DRIVER_NUM,
+//! )
+//! .finalize(components::lsm303agr_component_static!(nrf52833::i2c::TWI<'static>));
 //!
-//! let lsm303dlhc = components::lsm303dlhc::Lsm303agrI2CComponent::new()
-//!    .finalize(components::lsm303dlhc_i2c_component_helper!(mux_i2c));
-//!
-//! lsm303dlhc.configure(
-//!    lsm303dlhc::Lsm303AccelDataRate::DataRate25Hz,
+//! lsm303agr.configure(
+//!    lsm303xx::Lsm303AccelDataRate::DataRate25Hz,
 //!    false,
-//!    lsm303dlhc::Lsm303Scale::Scale2G,
+//!    lsm303xx::Lsm303Scale::Scale2G,
 //!    false,
-//!    true,
-//!    lsm303dlhc::Lsm303MagnetoDataRate::DataRate3_0Hz,
-//!    lsm303dlhc::Lsm303Range::Range4_7G,
+//!    lsm303agr::Lsm303agrMagnetoDataRate::DataRate10Hz,
 //!);
 //! ```
 //!
 //! NideDof Example
 //!
 //! ```rust
-//! let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
-//! let grant_ninedof = board_kernel.create_grant(&grant_cap);
-//!
-//! // use as primary NineDof Sensor
-//! let ninedof = static_init!(
-//!    capsules::ninedof::NineDof<'static>,
-//!    capsules::ninedof::NineDof::new(lsm303dlhc, grant_ninedof)
-//! );
-//!
-//! hil::sensors::NineDof::set_client(lsm303dlhc, ninedof);
-//!
-//! // use as secondary NineDof Sensor
-//! let lsm303dlhc_secondary = static_init!(
-//!    capsules::ninedof::NineDofNode<'static, &'static dyn hil::sensors::NineDof>,
-//!    capsules::ninedof::NineDofNode::new(lsm303dlhc)
-//! );
-//! ninedof.add_secondary_driver(lsm303dlhc_secondary);
-//! hil::sensors::NineDof::set_client(lsm303dlhc, ninedof);
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(lsm303agr));
 //! ```
 //!
 //! Temperature Example
 //!
 //! ```rust
-//! let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
-//! let grant_temp = board_kernel.create_grant(&grant_cap);
-//!
-//! lsm303dlhc.configure(
-//!    lsm303dlhc::Lsm303AccelDataRate::DataRate25Hz,
-//!    false,
-//!    lsm303dlhc::Lsm303Scale::Scale2G,
-//!    false,
-//!    true,
-//!    lsm303dlhc::Lsm303MagnetoDataRate::DataRate3_0Hz,
-//!    lsm303dlhc::Lsm303Range::Range4_7G,
-//!);
-//! let temp = static_init!(
-//! capsules::temperature::TemperatureSensor<'static>,
-//!     capsules::temperature::TemperatureSensor::new(lsm303dlhc, grant_temperature));
-//! kernel::hil::sensors::TemperatureDriver::set_client(lsm303dlhc, temp);
+//! let temperature = components::temperature::TemperatureComponent::new(
+//!     board_kernel,
+//!     capsules_extra::temperature::DRIVER_NUM,
+//!     lsm303agr,
+//! )
+//! .finalize(components::temperature_component_static!());
 //! ```
 //!
 //! Author: Alexandru Radovici <msg4alex@gmail.com>
@@ -95,9 +89,10 @@ use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
 
+use kernel::utilities::registers::register_bitfields;
+
 use crate::lsm303xx::{
-    AccelerometerRegisters, Lsm303AccelDataRate, Lsm303MagnetoDataRate, Lsm303Range, Lsm303Scale,
-    CTRL_REG1, CTRL_REG4, RANGE_FACTOR_X_Y, RANGE_FACTOR_Z, SCALE_FACTOR,
+    AccelerometerRegisters, Lsm303AccelDataRate, Lsm303Scale, CTRL_REG1, SCALE_FACTOR,
 };
 use capsules_core::driver;
 
@@ -107,26 +102,88 @@ pub const DRIVER_NUM: usize = driver::NUM::Lsm303dlch as usize;
 /// Register values
 const REGISTER_AUTO_INCREMENT: u8 = 0x80;
 
+/// Value of `WHO_AM_I_A`.
+const ACCELEROMETER_ID: u8 = 0x33;
+
+/// Magnetometer sensitivity in tenths of a milligauss per LSB.
+const MAGNETOMETER_SENSITIVITY: i32 = 15;
+
 enum_from_primitive! {
     pub enum AgrAccelerometerRegisters {
-        TEMP_OUT_H_A = 0x0C,
-        TEMP_OUT_L_A = 0x0D
+        OUT_TEMP_L_A = 0x0C,
+        OUT_TEMP_H_A = 0x0D,
+        WHO_AM_I_A = 0x0F,
+        TEMP_CFG_REG_A = 0x1F,
     }
 }
 
 enum_from_primitive! {
     enum MagnetometerRegisters {
-        CRA_REG_M = 0x60,
-        CRB_REG_M = 0x61,
-        OUT_X_H_M = 0x68,
-        OUT_X_L_M = 0x69,
-        OUT_Z_H_M = 0x6A,
-        OUT_Z_L_M = 0x6B,
-        OUT_Y_H_M = 0x6C,
-        OUT_Y_L_M = 0x6D,
+        CFG_REG_A_M = 0x60,
+        CFG_REG_B_M = 0x61,
+        CFG_REG_C_M = 0x62,
+        OUTX_L_REG_M = 0x68,
+        OUTX_H_REG_M = 0x69,
+        OUTY_L_REG_M = 0x6A,
+        OUTY_H_REG_M = 0x6B,
+        OUTZ_L_REG_M = 0x6C,
+        OUTZ_H_REG_M = 0x6D,
+    }
+}
+
+// Manual table 93, page 67
+enum_from_primitive! {
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Lsm303agrMagnetoDataRate {
+        DataRate10Hz = 0,
+        DataRate20Hz = 1,
+        DataRate50Hz = 2,
+        DataRate100Hz = 3,
     }
 }
 
+register_bitfields![u8,
+    CTRL_REG4_A [
+        /// Block data update
+        BDU OFFSET(7) NUMBITS(1) [],
+        /// Big/little endian data selection
+        BLE OFFSET(6) NUMBITS(1) [],
+        /// Full scale selection
+        FS OFFSET(4) NUMBITS(2) [],
+        /// High resolution output mode
+        HR OFFSET(3) NUMBITS(1) [],
+        /// Self-test enable
+        ST OFFSET(1) NUMBITS(2) [],
+        /// 3-wire SPI interface enable
+        SPI_ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+    TEMP_CFG_REG_A [
+        /// Temperature sensor enable
+        TEMP_EN OFFSET(6) NUMBITS(2) [
+            Disabled = 0b00,
+            Enabled = 0b11
+        ]
+    ],
+    CFG_REG_A_M [
+        /// Magnetometer temperature compensation
+        COMP_TEMP_EN OFFSET(7) NUMBITS(1) [],
+        /// Low power mode
+        LP OFFSET(4) NUMBITS(1) [],
+        /// Output data rate
+        ODR OFFSET(2) NUMBITS(2) [],
+        /// Mode select
+        MD OFFSET(0) NUMBITS(2) [
+            Continuous = 0b00,
+            Single = 0b01,
+            Idle = 0b11
+        ]
+    ],
+    CFG_REG_C_M [
+        /// Block data update
+        BDU OFFSET(4) NUMBITS(1) []
+    ]
+];
+
 #[derive(Clone, Copy, PartialEq)]
 enum State {
     Idle,
@@ -134,9 +191,9 @@ enum State {
     SetPowerMode,
     SetScaleAndResolution,
     ReadAccelerationXYZ,
+    EnableTemperature,
     SetDataRate,
-    // SetTemperature,
-    SetRange,
+    SetMagnetometerBlockUpdate,
     ReadTemperature,
     ReadMagnetometerXYZ,
 }
@@ -150,12 +207,10 @@ pub struct Lsm303agrI2C<'a, I: i2c::I2CDevice> {
     i2c_magnetometer: &'a I,
     state: Cell<State>,
     accel_scale: Cell<Lsm303Scale>,
-    mag_range: Cell<Lsm303Range>,
     accel_high_resolution: Cell<bool>,
-    mag_data_rate: Cell<Lsm303MagnetoDataRate>,
+    mag_data_rate: Cell<Lsm303agrMagnetoDataRate>,
     accel_data_rate: Cell<Lsm303AccelDataRate>,
     low_power: Cell<bool>,
-    temperature: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     nine_dof_client: OptionalCell<&'a dyn sensors::NineDofClient>,
     temperature_client: OptionalCell<&'a dyn sensors::TemperatureClient>,
@@ -177,12 +232,10 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
             i2c_magnetometer: i2c_magnetometer,
             state: Cell::new(State::Idle),
             accel_scale: Cell::new(Lsm303Scale::Scale2G),
-            mag_range: Cell::new(Lsm303Range::Range1G),
             accel_high_resolution: Cell::new(false),
-            mag_data_rate: Cell::new(Lsm303MagnetoDataRate::DataRate0_75Hz),
+            mag_data_rate: Cell::new(Lsm303agrMagnetoDataRate::DataRate10Hz),
             accel_data_rate: Cell::new(Lsm303AccelDataRate::DataRate1Hz),
             low_power: Cell::new(false),
-            temperature: Cell::new(false),
             buffer: TakeCell::new(buffer),
             nine_dof_client: OptionalCell::empty(),
             temperature_client: OptionalCell::empty(),
@@ -191,24 +244,26 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
         }
     }
 
+    /// Configure the accelerometer, temperature sensor and magnetometer.
+    ///
+    /// The configuration is applied as a sequence of I2C transactions:
+    /// accelerometer power mode, accelerometer scale and resolution,
+    /// temperature sensor enable, magnetometer data rate and magnetometer
+    /// block data update.
     pub fn configure(
         &self,
         accel_data_rate: Lsm303AccelDataRate,
         low_power: bool,
         accel_scale: Lsm303Scale,
         accel_high_resolution: bool,
-        temperature: bool,
-        mag_data_rate: Lsm303MagnetoDataRate,
-        mag_range: Lsm303Range,
+        mag_data_rate: Lsm303agrMagnetoDataRate,
     ) -> Result<(), ErrorCode> {
         if self.state.get() == State::Idle {
             self.config_in_progress.set(true);
 
             self.accel_scale.set(accel_scale);
             self.accel_high_resolution.set(accel_high_resolution);
-            self.temperature.set(temperature);
             self.mag_data_rate.set(mag_data_rate);
-            self.mag_range.set(mag_range);
             self.accel_data_rate.set(accel_data_rate);
             self.low_power.set(low_power);
 
@@ -219,16 +274,16 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
     }
 
     fn is_present(&self) -> Result<(), ErrorCode> {
-        if self.state.get() != State::Idle {
+        if self.state.get() == State::Idle {
             self.state.set(State::IsPresent);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
                 // turn on i2c to send commands
-                buf[0] = 0x0F;
-                self.i2c_magnetometer.enable();
-                if let Err((error, buf)) = self.i2c_magnetometer.write_read(buf, 1, 1) {
+                buf[0] = AgrAccelerometerRegisters::WHO_AM_I_A as u8;
+                self.i2c_accelerometer.enable();
+                if let Err((error, buf)) = self.i2c_accelerometer.write_read(buf, 1, 1) {
                     self.state.set(State::Idle);
                     self.buffer.replace(buf);
-                    self.i2c_magnetometer.disable();
+                    self.i2c_accelerometer.disable();
                     Err(error.into())
                 } else {
                     Ok(())
@@ -281,9 +336,9 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
             self.accel_high_resolution.set(high_resolution);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
                 buf[0] = AccelerometerRegisters::CTRL_REG4 as u8;
-                buf[1] = (CTRL_REG4::FS.val(scale as u8)
-                    + CTRL_REG4::HR.val(high_resolution as u8)
-                    + CTRL_REG4::BDU::SET)
+                buf[1] = (CTRL_REG4_A::FS.val(scale as u8)
+                    + CTRL_REG4_A::HR.val(high_resolution as u8)
+                    + CTRL_REG4_A::BDU::SET)
                     .value;
                 self.i2c_accelerometer.enable();
                 if let Err((error, buf)) = self.i2c_accelerometer.write(buf, 2) {
@@ -320,12 +375,36 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
         }
     }
 
-    fn set_magneto_data_rate(&self, data_rate: Lsm303MagnetoDataRate) -> Result<(), ErrorCode> {
+    fn enable_temperature(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Idle {
+            self.state.set(State::EnableTemperature);
+            self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+                buf[0] = AgrAccelerometerRegisters::TEMP_CFG_REG_A as u8;
+                buf[1] = TEMP_CFG_REG_A::TEMP_EN::Enabled.value;
+                self.i2c_accelerometer.enable();
+                if let Err((error, buf)) = self.i2c_accelerometer.write(buf, 2) {
+                    self.state.set(State::Idle);
+                    self.i2c_accelerometer.disable();
+                    self.buffer.replace(buf);
+                    Err(error.into())
+                } else {
+                    Ok(())
+                }
+            })
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    fn set_magneto_data_rate(&self, data_rate: Lsm303agrMagnetoDataRate) -> Result<(), ErrorCode> {
         if self.state.get() == State::Idle {
             self.state.set(State::SetDataRate);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
-                buf[0] = MagnetometerRegisters::CRA_REG_M as u8;
-                buf[1] = ((data_rate as u8) << 2) | 1 << 7;
+                buf[0] = MagnetometerRegisters::CFG_REG_A_M as u8;
+                buf[1] = (CFG_REG_A_M::COMP_TEMP_EN::SET
+                    + CFG_REG_A_M::ODR.val(data_rate as u8)
+                    + CFG_REG_A_M::MD::Continuous)
+                    .value;
                 self.i2c_magnetometer.enable();
                 if let Err((error, buf)) = self.i2c_magnetometer.write(buf, 2) {
                     self.state.set(State::Idle);
@@ -341,16 +420,14 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
         }
     }
 
-    fn set_range(&self, range: Lsm303Range) -> Result<(), ErrorCode> {
+    fn set_magneto_block_update(&self) -> Result<(), ErrorCode> {
         if self.state.get() == State::Idle {
-            self.state.set(State::SetRange);
-            self.mag_range.set(range);
+            self.state.set(State::SetMagnetometerBlockUpdate);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
-                buf[0] = MagnetometerRegisters::CRB_REG_M as u8;
-                buf[1] = (range as u8) << 5;
-                buf[2] = 0;
+                buf[0] = MagnetometerRegisters::CFG_REG_C_M as u8;
+                buf[1] = CFG_REG_C_M::BDU::SET.value;
                 self.i2c_magnetometer.enable();
-                if let Err((error, buf)) = self.i2c_magnetometer.write(buf, 3) {
+                if let Err((error, buf)) = self.i2c_magnetometer.write(buf, 2) {
                     self.state.set(State::Idle);
                     self.i2c_magnetometer.disable();
                     self.buffer.replace(buf);
@@ -368,7 +445,7 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
         if self.state.get() == State::Idle {
             self.state.set(State::ReadTemperature);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
-                buf[0] = AgrAccelerometerRegisters::TEMP_OUT_H_A as u8;
+                buf[0] = AgrAccelerometerRegisters::OUT_TEMP_L_A as u8 | REGISTER_AUTO_INCREMENT;
                 self.i2c_accelerometer.enable();
                 if let Err((error, buf)) = self.i2c_accelerometer.write_read(buf, 1, 2) {
                     self.state.set(State::Idle);
@@ -388,7 +465,7 @@ impl<'a, I: i2c::I2CDevice> Lsm303agrI2C<'a, I> {
         if self.state.get() == State::Idle {
             self.state.set(State::ReadMagnetometerXYZ);
             self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
-                buf[0] = MagnetometerRegisters::OUT_X_H_M as u8;
+                buf[0] = MagnetometerRegisters::OUTX_L_REG_M as u8;
                 self.i2c_magnetometer.enable();
                 if let Err((error, buf)) = self.i2c_magnetometer.write_read(buf, 1, 6) {
                     self.state.set(State::Idle);
@@ -409,11 +486,7 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
         match self.state.get() {
             State::IsPresent => {
-                let present = if status == Ok(()) && buffer[0] == 60 {
-                    true
-                } else {
-                    false
-                };
+                let present = status == Ok(()) && buffer[0] == ACCELEROMETER_ID;
                 self.owning_process.map(|pid| {
                     let _res = self.apps.enter(*pid, |_app, upcalls| {
                         upcalls
@@ -422,7 +495,7 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
                     });
                 });
                 self.buffer.replace(buffer);
-                self.i2c_magnetometer.disable();
+                self.i2c_accelerometer.disable();
                 self.state.set(State::Idle);
             }
             State::SetPowerMode => {
@@ -461,6 +534,24 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
                 self.buffer.replace(buffer);
                 self.i2c_accelerometer.disable();
                 self.state.set(State::Idle);
+                if self.config_in_progress.get() {
+                    if let Err(_error) = self.enable_temperature() {
+                        self.config_in_progress.set(false);
+                    }
+                }
+            }
+            State::EnableTemperature => {
+                let enable_temperature = status == Ok(());
+                self.owning_process.map(|pid| {
+                    let _res = self.apps.enter(*pid, |_app, upcalls| {
+                        upcalls
+                            .schedule_upcall(0, (if enable_temperature { 1 } else { 0 }, 0, 0))
+                            .ok();
+                    });
+                });
+                self.buffer.replace(buffer);
+                self.i2c_accelerometer.disable();
+                self.state.set(State::Idle);
                 if self.config_in_progress.get() {
                     if let Err(_error) = self.set_magneto_data_rate(self.mag_data_rate.get()) {
                         self.config_in_progress.set(false);
@@ -526,17 +617,17 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
                 self.i2c_magnetometer.disable();
                 self.state.set(State::Idle);
                 if self.config_in_progress.get() {
-                    if let Err(_error) = self.set_range(self.mag_range.get()) {
+                    if let Err(_error) = self.set_magneto_block_update() {
                         self.config_in_progress.set(false);
                     }
                 }
             }
-            State::SetRange => {
-                let set_range = status == Ok(());
+            State::SetMagnetometerBlockUpdate => {
+                let set_block_update = status == Ok(());
                 self.owning_process.map(|pid| {
                     let _res = self.apps.enter(*pid, |_app, upcalls| {
                         upcalls
-                            .schedule_upcall(0, (if set_range { 1 } else { 0 }, 0, 0))
+                            .schedule_upcall(0, (if set_block_update { 1 } else { 0 }, 0, 0))
                             .ok();
                     });
                 });
@@ -549,7 +640,11 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
             }
             State::ReadTemperature => {
                 let values = match status {
-                    Ok(()) => Ok((buffer[1] as u16 as i16 | ((buffer[0] as i16) << 8)) as i32 / 8),
+                    // Left-justified 10-bit value, 0.25 C/LSB, 0 is 25 C
+                    Ok(()) => Ok(
+                        ((buffer[0] as u16 | ((buffer[1] as u16) << 8)) as i16 as i32 >> 6) * 25
+                            + 2500,
+                    ),
                     Err(i2c_err) => Err(i2c_err.into()),
                 };
                 self.temperature_client.map(|client| {
@@ -573,21 +668,17 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm303agrI2C<'_, I> {
                 let mut y: usize = 0;
                 let mut z: usize = 0;
                 let values = if status == Ok(()) {
+                    x = ((buffer[0] as u16 | ((buffer[1] as u16) << 8)) as i16) as usize;
+                    y = ((buffer[2] as u16 | ((buffer[3] as u16) << 8)) as i16) as usize;
+                    z = ((buffer[4] as u16 | ((buffer[5] as u16) << 8)) as i16) as usize;
                     self.nine_dof_client.map(|client| {
-                        // compute using only integers
-                        let range = self.mag_range.get() as usize;
-                        x = (((buffer[1] as i16 | ((buffer[0] as i16) << 8)) as i32) * 100
-                            / RANGE_FACTOR_X_Y[range] as i32) as usize;
-                        z = (((buffer[3] as i16 | ((buffer[2] as i16) << 8)) as i32) * 100
-                            / RANGE_FACTOR_X_Y[range] as i32) as usize;
-                        y = (((buffer[5] as i16 | ((buffer[4] as i16) << 8)) as i32) * 100
-                            / RANGE_FACTOR_Z[range] as i32) as usize;
-                        client.callback(x, y, z);
+                        // compute using only integers, in hundredths of a gauss
+                        client.callback(
+                            (x as i16 as i32 * MAGNETOMETER_SENSITIVITY / 100) as usize,
+                            (y as i16 as i32 * MAGNETOMETER_SENSITIVITY / 100) as usize,
+                            (z as i16 as i32 * MAGNETOMETER_SENSITIVITY / 100) as usize,
+                        );
                     });
-
-                    x = ((buffer[1] as u16 | ((buffer[0] as u16) << 8)) as i16) as usize;
-                    z = ((buffer[3] as u16 | ((buffer[2] as u16) << 8)) as i16) as usize;
-                    y = ((buffer[5] as u16 | ((buffer[4] as u16) << 8)) as i16) as usize;
                     true
                 } else {
                     self.nine_dof_client.map(|client| {
@@ -687,10 +778,10 @@ impl<I: i2c::I2CDevice> SyscallDriver for Lsm303agrI2C<'_, I> {
                     CommandReturn::failure(ErrorCode::BUSY)
                 }
             }
-            // Set Magnetometer Temperature Enable and Data Rate
+            // Set Magnetometer Data Rate
             4 => {
                 if self.state.get() == State::Idle {
-                    if let Some(data_rate) = Lsm303MagnetoDataRate::from_usize(data1) {
+                    if let Some(data_rate) = Lsm303agrMagnetoDataRate::from_usize(data1) {
                         match self.set_magneto_data_rate(data_rate) {
                             Ok(()) => CommandReturn::success(),
                             Err(error) => CommandReturn::failure(error),
@@ -702,21 +793,8 @@ impl<I: i2c::I2CDevice> SyscallDriver for Lsm303agrI2C<'_, I> {
                     CommandReturn::failure(ErrorCode::BUSY)
                 }
             }
-            // Set Magnetometer Range
-            5 => {
-                if self.state.get() == State::Idle {
-                    if let Some(range) = Lsm303Range::from_usize(data1) {
-                        match self.set_range(range) {
-                            Ok(()) => CommandReturn::success(),
-                            Err(error) => CommandReturn::failure(error),
-                        }
-                    } else {
-                        CommandReturn::failure(ErrorCode::INVAL)
-                    }
-                } else {
-                    CommandReturn::failure(ErrorCode::BUSY)
-                }
-            }
+            // Set Magnetometer Range: the AGR has a fixed range
+            5 => CommandReturn::failure(ErrorCode::NOSUPPORT),
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
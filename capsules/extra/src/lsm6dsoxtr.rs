@@ -10,6 +10,14 @@
 //!
 //! I2C Interface
 //!
+//! The gyroscope supports the built-in self-test and a zero-rate offset
+//! calibration through the NineDof HIL. The self-test follows the procedure
+//! of the datasheet: it averages readings with and without the self-test
+//! stimulus applied at the 2000 dps full scale and checks that the
+//! difference lies within the specified limits. The calibration averages
+//! readings taken at rest and subtracts the resulting bias from every
+//! subsequent gyroscope reading.
+//!
 //! Datasheet: <https://www.digikey.sg/product-detail/en/stmicroelectronics/LSM6DSOXTR/497-18367-1-ND/9841887>
 //!
 //! Author: Cristiana Andrei <cristiana.andrei05@gmail.com>
@@ -17,6 +25,7 @@
 #![allow(non_camel_case_types)]
 use capsules_core::driver;
 
+use crate::gyro_calibration::GyroCalibration;
 use core::cell::Cell;
 use enum_primitive::cast::FromPrimitive;
 use enum_primitive::enum_from_primitive;
@@ -95,7 +104,9 @@ enum_from_primitive! {
     #[derive(Clone, Copy, PartialEq)]
     pub enum LSM6DSOXTRGyroRegisters {
         CTRL2_G = 0x11,
+        CTRL5_C = 0x14,
         CTRL7_G = 0x16,
+        STATUS_REG = 0x1E,
         OUT_X_L_G = 0x22,
         OUT_X_H_G = 0x23,
         OUT_Y_L_G = 0x24,
@@ -117,6 +128,20 @@ pub const SCALE_FACTOR_ACCEL: [u16; 4] = [61, 488, 122, 244];
 pub const SCALE_FACTOR_GYRO: [u16; 4] = [875, 1750, 3500, 7000];
 pub const TEMP_SENSITIVITY_FACTOR: u16 = 256;
 
+/// Gyroscope data available bit of `STATUS_REG`.
+const STATUS_GDA: u8 = 1 << 1;
+/// Positive sign gyroscope self-test in `CTRL5_C`.
+const CTRL5_C_ST_G_POSITIVE: u8 = 0b01 << 2;
+/// Gyroscope samples discarded after changing the self-test stimulus to let
+/// the output settle.
+const SELF_TEST_DISCARD_SAMPLES: usize = 3;
+/// Gyroscope samples averaged for each half of the self-test.
+const SELF_TEST_SAMPLES: usize = 5;
+/// Self-test output change limits at 2000 dps (70 mdps/LSB), corresponding to
+/// 150 dps and 700 dps.
+const SELF_TEST_MIN_LSB: i32 = 2142;
+const SELF_TEST_MAX_LSB: i32 = 10000;
+
 enum_from_primitive! {
     #[derive(Clone, Copy, PartialEq)]
     pub enum LSM6DSOXTRAccelRegisters {
@@ -165,6 +190,14 @@ enum State {
     ReadTemperature,
     SetPowerModeAccel,
     SetPowerModeGyro,
+    CalibrateStatus,
+    CalibrateSample,
+    SelfTestConfigure,
+    SelfTestStatus,
+    SelfTestSample,
+    SelfTestEnable,
+    SelfTestDisable,
+    SelfTestRestore,
 }
 #[derive(Default)]
 pub struct App {}
@@ -182,6 +215,12 @@ pub struct Lsm6dsoxtrI2C<'a, I: i2c::I2CDevice> {
     nine_dof_client: OptionalCell<&'a dyn sensors::NineDofClient>,
     temperature_client: OptionalCell<&'a dyn sensors::TemperatureClient>,
     is_present: Cell<bool>,
+    calibration: GyroCalibration,
+    self_test_stimulus: Cell<bool>,
+    self_test_count: Cell<usize>,
+    self_test_sum: Cell<[i32; 3]>,
+    self_test_baseline: Cell<[i32; 3]>,
+    self_test_passed: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
     syscall_process: OptionalCell<ProcessId>,
@@ -206,6 +245,12 @@ impl<'a, I: i2c::I2CDevice> Lsm6dsoxtrI2C<'a, I> {
             nine_dof_client: OptionalCell::empty(),
             temperature_client: OptionalCell::empty(),
             is_present: Cell::new(false),
+            calibration: GyroCalibration::new(),
+            self_test_stimulus: Cell::new(false),
+            self_test_count: Cell::new(0),
+            self_test_sum: Cell::new([0; 3]),
+            self_test_baseline: Cell::new([0; 3]),
+            self_test_passed: Cell::new(false),
             buffer: TakeCell::new(buffer),
             apps: grant,
             syscall_process: OptionalCell::empty(),
@@ -378,6 +423,220 @@ impl<'a, I: i2c::I2CDevice> Lsm6dsoxtrI2C<'a, I> {
             Err(ErrorCode::BUSY)
         }
     }
+
+    /// Start the gyroscope self-test. Completion is reported through the
+    /// NineDof client with `1` if the test passed and `0` otherwise.
+    pub fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut reg: LocalRegisterCopy<u8, CTRL2_G::Register> = LocalRegisterCopy::new(0);
+        reg.modify(CTRL2_G::ODR.val(LSM6DSOXGyroDataRate::LSM6DSOX_GYRO_RATE_208_HZ as u8));
+        reg.modify(CTRL2_G::FS.val(LSM6DSOXTRGyroRange::LSM6DSOX_GYRO_RANGE_2000_DPS as u8));
+        self.write_register(
+            State::SelfTestConfigure,
+            LSM6DSOXTRGyroRegisters::CTRL2_G as u8,
+            reg.get(),
+        )
+    }
+
+    /// Start a zero-rate offset calibration over `samples` gyroscope
+    /// readings. The device must be kept still until the NineDof client is
+    /// called with the measured offset.
+    pub fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.calibration.start(samples)?;
+        self.read_registers(
+            State::CalibrateStatus,
+            LSM6DSOXTRGyroRegisters::STATUS_REG as u8,
+            1,
+        )
+        .map_err(|error| {
+            self.calibration.cancel();
+            error
+        })
+    }
+
+    fn write_register(&self, state: State, register: u8, value: u8) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+            self.state.set(state);
+            buf[0] = register;
+            buf[1] = value;
+            self.i2c.enable();
+            if let Err((error, buf)) = self.i2c.write(buf, 2) {
+                self.state.set(State::Idle);
+                self.i2c.disable();
+                self.buffer.replace(buf);
+                Err(error.into())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn read_registers(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+            self.state.set(state);
+            buf[0] = register;
+            self.i2c.enable();
+            if let Err((error, buf)) = self.i2c.write_read(buf, 1, len) {
+                self.state.set(State::Idle);
+                self.i2c.disable();
+                self.buffer.replace(buf);
+                Err(error.into())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn raw_gyroscope(buffer: &[u8]) -> [i16; 3] {
+        [
+            (buffer[0] as u16 + ((buffer[1] as u16) << 8)) as i16,
+            (buffer[2] as u16 + ((buffer[3] as u16) << 8)) as i16,
+            (buffer[4] as u16 + ((buffer[5] as u16) << 8)) as i16,
+        ]
+    }
+
+    fn scale_gyroscope(&self, raw: i16) -> usize {
+        let scale_factor = self.gyro_range.get() as usize;
+        (raw as isize * (SCALE_FACTOR_GYRO[scale_factor] as isize) / 100) as usize
+    }
+
+    /// Poll the status register until the next gyroscope sample is ready.
+    fn wait_for_gyroscope_sample(&self, state: State) -> Result<(), ErrorCode> {
+        self.read_registers(state, LSM6DSOXTRGyroRegisters::STATUS_REG as u8, 1)
+    }
+
+    /// Abort a calibration or self-test and report the failure.
+    fn gyroscope_procedure_failed(&self) {
+        self.calibration.cancel();
+        self.state.set(State::Idle);
+        self.nine_dof_client.map(|client| client.callback(0, 0, 0));
+    }
+
+    /// Account for one self-test sample and move on to the next step once
+    /// enough samples have been collected.
+    fn self_test_sample(&self, raw: [i16; 3]) -> Result<(), ErrorCode> {
+        let count = self.self_test_count.get() + 1;
+        self.self_test_count.set(count);
+        if count > SELF_TEST_DISCARD_SAMPLES {
+            let mut sum = self.self_test_sum.get();
+            for axis in 0..3 {
+                sum[axis] += raw[axis] as i32;
+            }
+            self.self_test_sum.set(sum);
+        }
+
+        if count < SELF_TEST_DISCARD_SAMPLES + SELF_TEST_SAMPLES {
+            return self.wait_for_gyroscope_sample(State::SelfTestStatus);
+        }
+
+        let average = self
+            .self_test_sum
+            .get()
+            .map(|sum| sum / SELF_TEST_SAMPLES as i32);
+        self.self_test_count.set(0);
+        self.self_test_sum.set([0; 3]);
+        if !self.self_test_stimulus.get() {
+            self.self_test_baseline.set(average);
+            self.write_register(
+                State::SelfTestEnable,
+                LSM6DSOXTRGyroRegisters::CTRL5_C as u8,
+                CTRL5_C_ST_G_POSITIVE,
+            )
+        } else {
+            let baseline = self.self_test_baseline.get();
+            self.self_test_passed.set((0..3).all(|axis| {
+                let delta = (average[axis] - baseline[axis]).abs();
+                (SELF_TEST_MIN_LSB..=SELF_TEST_MAX_LSB).contains(&delta)
+            }));
+            self.write_register(
+                State::SelfTestDisable,
+                LSM6DSOXTRGyroRegisters::CTRL5_C as u8,
+                0,
+            )
+        }
+    }
+
+    /// Advance the calibration or self-test state machine after a
+    /// successful I2C transaction.
+    fn gyroscope_procedure_step(&self, state: State, buffer: &[u8]) -> Result<(), ErrorCode> {
+        match state {
+            State::CalibrateStatus => {
+                if buffer[0] & STATUS_GDA != 0 {
+                    self.read_registers(
+                        State::CalibrateSample,
+                        LSM6DSOXTRGyroRegisters::OUT_X_L_G as u8,
+                        6,
+                    )
+                } else {
+                    self.wait_for_gyroscope_sample(State::CalibrateStatus)
+                }
+            }
+            State::CalibrateSample => {
+                if self.calibration.add_sample(Self::raw_gyroscope(buffer)) {
+                    self.i2c.disable();
+                    self.state.set(State::Idle);
+                    let bias = self.calibration.bias();
+                    self.nine_dof_client.map(|client| {
+                        client.callback(
+                            self.scale_gyroscope(bias[0]),
+                            self.scale_gyroscope(bias[1]),
+                            self.scale_gyroscope(bias[2]),
+                        )
+                    });
+                    Ok(())
+                } else {
+                    self.wait_for_gyroscope_sample(State::CalibrateStatus)
+                }
+            }
+            State::SelfTestConfigure => {
+                self.self_test_stimulus.set(false);
+                self.self_test_count.set(0);
+                self.self_test_sum.set([0; 3]);
+                self.wait_for_gyroscope_sample(State::SelfTestStatus)
+            }
+            State::SelfTestStatus => {
+                if buffer[0] & STATUS_GDA != 0 {
+                    self.read_registers(
+                        State::SelfTestSample,
+                        LSM6DSOXTRGyroRegisters::OUT_X_L_G as u8,
+                        6,
+                    )
+                } else {
+                    self.wait_for_gyroscope_sample(State::SelfTestStatus)
+                }
+            }
+            State::SelfTestSample => self.self_test_sample(Self::raw_gyroscope(buffer)),
+            State::SelfTestEnable => {
+                self.self_test_stimulus.set(true);
+                self.wait_for_gyroscope_sample(State::SelfTestStatus)
+            }
+            State::SelfTestDisable => {
+                let mut reg: LocalRegisterCopy<u8, CTRL2_G::Register> = LocalRegisterCopy::new(0);
+                reg.modify(CTRL2_G::ODR.val(self.gyro_data_rate.get() as u8));
+                reg.modify(CTRL2_G::LPF.val(self.low_power.get() as u8));
+                reg.modify(CTRL2_G::FS.val(0));
+                self.write_register(
+                    State::SelfTestRestore,
+                    LSM6DSOXTRGyroRegisters::CTRL2_G as u8,
+                    reg.get(),
+                )
+            }
+            State::SelfTestRestore => {
+                self.i2c.disable();
+                self.state.set(State::Idle);
+                let passed = self.self_test_passed.get();
+                self.nine_dof_client
+                    .map(|client| client.callback(passed as usize, 0, 0));
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm6dsoxtrI2C<'_, I> {
@@ -452,23 +711,14 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm6dsoxtrI2C<'_, I> {
             }
 
             State::ReadGyroscopeXYZ => {
-                let mut x: usize = 0;
-                let mut y: usize = 0;
-                let mut z: usize = 0;
                 if status == Ok(()) {
+                    let raw = self.calibration.correct(Self::raw_gyroscope(buffer));
                     self.nine_dof_client.map(|nine_dof_client| {
-                        let scale_factor = self.gyro_range.get() as usize;
-                        x = (((buffer[0] as u16 + ((buffer[1] as u16) << 8)) as i16) as isize
-                            * (SCALE_FACTOR_GYRO[scale_factor] as isize)
-                            / 100) as usize;
-                        y = (((buffer[2] as u16 + ((buffer[3] as u16) << 8)) as i16) as isize
-                            * (SCALE_FACTOR_GYRO[scale_factor] as isize)
-                            / 100) as usize;
-
-                        z = (((buffer[4] as u16 + ((buffer[5] as u16) << 8)) as i16) as isize
-                            * (SCALE_FACTOR_GYRO[scale_factor] as isize)
-                            / 100) as usize;
-                        nine_dof_client.callback(x, y, z)
+                        nine_dof_client.callback(
+                            self.scale_gyroscope(raw[0]),
+                            self.scale_gyroscope(raw[1]),
+                            self.scale_gyroscope(raw[2]),
+                        )
                     });
                 } else {
                     self.nine_dof_client.map(|client| {
@@ -529,6 +779,29 @@ impl<I: i2c::I2CDevice> i2c::I2CClient for Lsm6dsoxtrI2C<'_, I> {
                 });
             }
 
+            State::CalibrateStatus
+            | State::CalibrateSample
+            | State::SelfTestConfigure
+            | State::SelfTestStatus
+            | State::SelfTestSample
+            | State::SelfTestEnable
+            | State::SelfTestDisable
+            | State::SelfTestRestore => {
+                let state = self.state.get();
+                // Copy out the data so the buffer is available for the next
+                // step of the procedure.
+                let mut data = [0; 6];
+                data.copy_from_slice(&buffer[0..6]);
+                self.buffer.replace(buffer);
+                let result = status
+                    .map_err(|i2c_error| i2c_error.into())
+                    .and_then(|()| self.gyroscope_procedure_step(state, &data));
+                if result.is_err() {
+                    self.i2c.disable();
+                    self.gyroscope_procedure_failed();
+                }
+            }
+
             State::SetPowerModeGyro => {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
@@ -645,6 +918,14 @@ impl<'a, I: i2c::I2CDevice> NineDof<'a> for Lsm6dsoxtrI2C<'a, I> {
     fn read_gyroscope(&self) -> Result<(), ErrorCode> {
         self.read_gyroscope_xyz()
     }
+
+    fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        self.run_gyroscope_self_test()
+    }
+
+    fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        self.calibrate_gyroscope(samples)
+    }
 }
 
 impl<'a, I: i2c::I2CDevice> sensors::TemperatureDriver<'a> for Lsm6dsoxtrI2C<'a, I> {
@@ -0,0 +1,657 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Maxim MAX31865 RTD-to-digital converter.
+//!
+//! <https://www.analog.com/media/en/technical-documentation/data-sheets/MAX31865.pdf>
+//!
+//! The MAX31865 measures the resistance of a platinum RTD, such as a PT100
+//! or a PT1000, as a 15-bit ratio to a reference resistor, over SPI. The
+//! driver converts the resistance to a temperature with the Callendar-Van
+//! Dusen equation:
+//!
+//! ```text
+//! R(T) = R0 (1 + A T + B T^2 + C (T - 100) T^3)
+//! ```
+//!
+//! where the `C` term only applies below 0 °C. The equation is solved for
+//! `T` with Newton's method, in integer arithmetic, to a thousandth of a
+//! degree.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! [Max31865::configure] sets the wiring of the RTD, whether the bias
+//! voltage is on all the time or only during readings, and whether the
+//! wiring is checked for faults before every reading. The bias voltage
+//! needs 10 ms to settle when it is only turned on for a reading.
+//!
+//! A reading starts a one-shot conversion, and the driver reads the result
+//! when the converter pulls DRDY low. If the converter flags a fault, the
+//! reading fails with an error for the faults in the fault status
+//! register, which is then cleared:
+//!
+//! - `NODEVICE`: the RTD is out of the fault thresholds, or RTDIN- is below
+//!   0.85 of the bias voltage. The RTD is open or shorted.
+//! - `INVAL`: REFIN- is above or below 0.85 of the bias voltage. The
+//!   reference resistor or the FORCE- wire is open or shorted.
+//! - `FAIL`: an input is over or under voltage.
+//!
+//! The fault status register of the last fault is available with
+//! [Max31865::fault_status].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let max31865 = components::max31865::Max31865Component::new(
+//!     mux_spi,
+//!     nrf52840::gpio::Pin::P1_08,
+//!     &nrf52840_peripherals.gpio_port[MAX31865_DRDY],
+//!     mux_alarm,
+//!     capsules_extra::max31865::Rtd::Pt100,
+//!     430,
+//!     capsules_extra::max31865::Wires::Three,
+//!     capsules_extra::max31865::Bias::DuringConversion,
+//!     capsules_extra::max31865::FaultDetection::Automatic,
+//! )
+//! .finalize(components::max31865_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let temperature = components::temperature::TemperatureComponent::new(
+//!     board_kernel,
+//!     capsules_extra::temperature::DRIVER_NUM,
+//!     max31865,
+//! )
+//! .finalize(components::temperature_component_static!());
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::hil::spi::{self, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Length of the SPI buffers: a register address and two bytes of data.
+pub const BUF_LEN: usize = 3;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_RTD_MSB: u8 = 0x01;
+const REG_FAULT_STATUS: u8 = 0x07;
+/// Set in the register address to write it.
+const WRITE: u8 = 0x80;
+
+const CONFIG_VBIAS: u8 = 0x80;
+const CONFIG_ONE_SHOT: u8 = 0x20;
+const CONFIG_3_WIRE: u8 = 0x10;
+const CONFIG_FAULT_AUTOMATIC: u8 = 0x04;
+const CONFIG_FAULT_CYCLE_MASK: u8 = 0x0C;
+const CONFIG_FAULT_CLEAR: u8 = 0x02;
+
+/// Set in the RTD registers when a fault was detected.
+const RTD_FAULT: u16 = 0x0001;
+
+const FAULT_RTD_HIGH: u8 = 0x80;
+const FAULT_RTD_LOW: u8 = 0x40;
+const FAULT_REFIN_HIGH: u8 = 0x20;
+const FAULT_REFIN_LOW: u8 = 0x10;
+const FAULT_RTDIN_LOW: u8 = 0x08;
+const FAULT_OVUV: u8 = 0x04;
+
+/// Time for the RTD input filter to settle once the bias voltage is on.
+const BIAS_SETTLE_MS: u32 = 10;
+/// Time between checks of the automatic fault detection cycle, which takes
+/// about 550 us.
+const FAULT_CYCLE_POLL_MS: u32 = 1;
+const FAULT_CYCLE_POLL_ATTEMPTS: u8 = 5;
+
+/// Callendar-Van Dusen coefficients of IEC 60751 platinum RTDs: A in units
+/// of 1e-9, B in units of 1e-10, and C in units of 1e-15.
+const CVD_A: i128 = 3_908_300;
+const CVD_B: i128 = -5_775;
+const CVD_C: i128 = -4_183;
+
+/// `R/R0` in units of 1e-9 at `t` millidegrees.
+fn cvd_ratio(t: i128) -> i128 {
+    let mut ratio = 1_000_000_000 + CVD_A * t / 1_000 + CVD_B * t * t / 10_000_000;
+    if t < 0 {
+        ratio += CVD_C * (t - 100_000) * t * t * t / 1_000_000_000_000_000_000;
+    }
+    ratio
+}
+
+/// The derivative of [cvd_ratio], in units of 1e-9 per degree.
+fn cvd_slope(t: i128) -> i128 {
+    let mut slope = CVD_A + 2 * CVD_B * t / 10_000;
+    if t < 0 {
+        slope += CVD_C * (4 * t * t * t - 300_000 * t * t) / 1_000_000_000_000_000;
+    }
+    slope
+}
+
+/// The temperature in hundredths of degrees of an RTD measured as `code`,
+/// the 15-bit ratio of the RTD to the reference resistor.
+fn temperature_centidegrees(code: u16, rtd: Rtd, reference_ohms: u32) -> i32 {
+    let ratio =
+        code as i128 * reference_ohms as i128 * 1_000_000_000 / (32_768 * rtd.ohms() as i128);
+    // Start from the linear approximation. The equation is smooth enough
+    // that Newton's method converges in a few steps over the whole range.
+    let mut t = (ratio - 1_000_000_000) * 1_000 / CVD_A;
+    for _ in 0..8 {
+        let step = (ratio - cvd_ratio(t)) * 1_000 / cvd_slope(t);
+        t += step;
+        if step == 0 {
+            break;
+        }
+    }
+    ((t + 5).div_euclid(10)) as i32
+}
+
+/// The error for the faults in the fault status register.
+fn fault_error(status: u8) -> ErrorCode {
+    if status & FAULT_OVUV != 0 {
+        ErrorCode::FAIL
+    } else if status & (FAULT_REFIN_HIGH | FAULT_REFIN_LOW) != 0 {
+        ErrorCode::INVAL
+    } else if status & (FAULT_RTD_HIGH | FAULT_RTD_LOW | FAULT_RTDIN_LOW) != 0 {
+        ErrorCode::NODEVICE
+    } else {
+        ErrorCode::FAIL
+    }
+}
+
+/// Nominal resistance of the RTD at 0 °C.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rtd {
+    Pt100,
+    Pt1000,
+}
+
+impl Rtd {
+    fn ohms(self) -> u32 {
+        match self {
+            Rtd::Pt100 => 100,
+            Rtd::Pt1000 => 1_000,
+        }
+    }
+}
+
+/// Number of wires the RTD is connected with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wires {
+    Two,
+    Three,
+    Four,
+}
+
+/// When the bias voltage of the RTD is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Bias {
+    /// All the time, so readings start right away.
+    Continuous,
+    /// Only during readings, which saves power and avoids self-heating of
+    /// the RTD.
+    DuringConversion,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultDetection {
+    /// Faults are only detected by the conversions.
+    Off,
+    /// Run the automatic fault detection cycle before every reading.
+    Automatic,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Unconfigured,
+    Configuring,
+    Idle,
+    /// Turning on the bias voltage for a reading.
+    BiasOn,
+    /// Waiting for the RTD input to settle.
+    BiasSettle,
+    StartFaultCycle,
+    /// Waiting for the fault detection cycle, before this check of whether
+    /// it has finished.
+    FaultCycleWait(u8),
+    ReadFaultCycle(u8),
+    StartConversion,
+    /// Waiting for DRDY.
+    Converting,
+    ReadRtd,
+    /// Reading the fault status after the fault detection cycle, or after
+    /// the conversion flagged a fault if true.
+    ReadFaultStatus(bool),
+    /// Clearing the fault status and turning off the bias voltage if needed,
+    /// before reporting the reading.
+    Finish(Result<i32, ErrorCode>),
+}
+
+pub struct Max31865<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> {
+    spi: &'a S,
+    drdy: &'a dyn gpio::InterruptPin<'a>,
+    alarm: &'a A,
+    rtd: Rtd,
+    reference_ohms: u32,
+    txbuffer: TakeCell<'static, [u8]>,
+    rxbuffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    wires: Cell<Wires>,
+    bias: Cell<Bias>,
+    fault_detection: Cell<FaultDetection>,
+    resistance: OptionalCell<u32>,
+    fault_status: Cell<u8>,
+    client: OptionalCell<&'a dyn TemperatureClient>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> Max31865<'a, S, A> {
+    /// `reference_ohms` is the resistance of the reference resistor,
+    /// usually 430 Ω for a PT100 and 4300 Ω for a PT1000.
+    pub fn new(
+        spi: &'a S,
+        drdy: &'a dyn gpio::InterruptPin<'a>,
+        alarm: &'a A,
+        rtd: Rtd,
+        reference_ohms: u32,
+        txbuffer: &'static mut [u8; BUF_LEN],
+        rxbuffer: &'static mut [u8; BUF_LEN],
+    ) -> Max31865<'a, S, A> {
+        Max31865 {
+            spi,
+            drdy,
+            alarm,
+            rtd,
+            reference_ohms,
+            txbuffer: TakeCell::new(txbuffer),
+            rxbuffer: TakeCell::new(rxbuffer),
+            state: Cell::new(State::Unconfigured),
+            wires: Cell::new(Wires::Two),
+            bias: Cell::new(Bias::Continuous),
+            fault_detection: Cell::new(FaultDetection::Off),
+            resistance: OptionalCell::empty(),
+            fault_status: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Configure the SPI bus and the converter.
+    pub fn configure(
+        &self,
+        wires: Wires,
+        bias: Bias,
+        fault_detection: FaultDetection,
+    ) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Unconfigured | State::Idle => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleTrailing,
+            5_000_000,
+        )?;
+        self.wires.set(wires);
+        self.bias.set(bias);
+        self.fault_detection.set(fault_detection);
+        self.drdy
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        self.write(State::Configuring, self.config(false) | CONFIG_FAULT_CLEAR)
+    }
+
+    /// The fault status register when the last fault was detected.
+    pub fn fault_status(&self) -> u8 {
+        self.fault_status.get()
+    }
+
+    /// The configuration register, with the bias voltage on if `reading` or
+    /// if it is always on.
+    fn config(&self, reading: bool) -> u8 {
+        let mut config = 0;
+        if reading || self.bias.get() == Bias::Continuous {
+            config |= CONFIG_VBIAS;
+        }
+        if self.wires.get() == Wires::Three {
+            config |= CONFIG_3_WIRE;
+        }
+        config
+    }
+
+    fn transfer(&self, state: State, tx_data: &[u8]) -> Result<(), ErrorCode> {
+        let (tx, rx) = match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(tx), Some(rx)) => (tx, rx),
+            (tx, rx) => {
+                tx.map(|tx| self.txbuffer.replace(tx));
+                rx.map(|rx| self.rxbuffer.replace(rx));
+                return Err(ErrorCode::BUSY);
+            }
+        };
+        tx[..tx_data.len()].copy_from_slice(tx_data);
+        self.state.set(state);
+        self.spi
+            .read_write_bytes(tx, Some(rx), tx_data.len())
+            .map_err(|(e, tx, rx)| {
+                self.txbuffer.replace(tx);
+                rx.map(|rx| self.rxbuffer.replace(rx));
+                e
+            })
+    }
+
+    fn write(&self, state: State, config: u8) -> Result<(), ErrorCode> {
+        self.transfer(state, &[REG_CONFIG | WRITE, config])
+    }
+
+    fn read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        let mut tx = [0; BUF_LEN];
+        tx[0] = register;
+        self.transfer(state, &tx[..len + 1])
+    }
+
+    fn wait(&self, state: State, ms: u32) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Checks for faults, if enabled, then starts the conversion.
+    fn start_checks(&self) -> Result<(), ErrorCode> {
+        match self.fault_detection.get() {
+            FaultDetection::Off => {
+                self.write(State::StartConversion, self.config(true) | CONFIG_ONE_SHOT)
+            }
+            FaultDetection::Automatic => self.write(
+                State::StartFaultCycle,
+                self.config(true) | CONFIG_FAULT_AUTOMATIC,
+            ),
+        }
+    }
+
+    /// Clears the faults and restores the bias voltage, then reports
+    /// `result`.
+    fn finish(&self, result: Result<i32, ErrorCode>) {
+        if self
+            .write(
+                State::Finish(result),
+                self.config(false) | CONFIG_FAULT_CLEAR,
+            )
+            .is_err()
+        {
+            self.done(result);
+        }
+    }
+
+    fn done(&self, result: Result<i32, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client.map(|client| client.callback(result));
+    }
+
+    /// Runs the step after `state` has finished, with the bytes read after
+    /// the register address.
+    fn next(&self, state: State, data: &[u8]) -> Result<(), ErrorCode> {
+        match state {
+            State::Configuring => {
+                self.state.set(State::Idle);
+                Ok(())
+            }
+            State::BiasOn => {
+                self.wait(State::BiasSettle, BIAS_SETTLE_MS);
+                Ok(())
+            }
+            State::BiasSettle => self.start_checks(),
+            State::StartFaultCycle => {
+                self.wait(State::FaultCycleWait(0), FAULT_CYCLE_POLL_MS);
+                Ok(())
+            }
+            State::FaultCycleWait(attempt) => {
+                self.read(State::ReadFaultCycle(attempt), REG_CONFIG, 1)
+            }
+            State::ReadFaultCycle(attempt) => {
+                if data[0] & CONFIG_FAULT_CYCLE_MASK == 0 {
+                    self.read(State::ReadFaultStatus(false), REG_FAULT_STATUS, 1)
+                } else if attempt + 1 < FAULT_CYCLE_POLL_ATTEMPTS {
+                    self.wait(State::FaultCycleWait(attempt + 1), FAULT_CYCLE_POLL_MS);
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                }
+            }
+            State::StartConversion => {
+                self.state.set(State::Converting);
+                Ok(())
+            }
+            State::ReadRtd => {
+                let rtd = u16::from_be_bytes([data[0], data[1]]);
+                if rtd & RTD_FAULT != 0 {
+                    return self.read(State::ReadFaultStatus(true), REG_FAULT_STATUS, 1);
+                }
+                let code = rtd >> 1;
+                self.resistance
+                    .set(((code as u64 * self.reference_ohms as u64 + 16_384) / 32_768) as u32);
+                self.finish(Ok(temperature_centidegrees(
+                    code,
+                    self.rtd,
+                    self.reference_ohms,
+                )));
+                Ok(())
+            }
+            State::ReadFaultStatus(conversion) => {
+                let status = data[0];
+                if status == 0 && !conversion {
+                    // The fault detection cycle found nothing.
+                    return self.write(State::StartConversion, self.config(true) | CONFIG_ONE_SHOT);
+                }
+                self.fault_status.set(status);
+                self.finish(Err(fault_error(status)));
+                Ok(())
+            }
+            State::Finish(result) => {
+                self.done(result);
+                Ok(())
+            }
+            State::Unconfigured | State::Idle | State::Converting => Ok(()),
+        }
+    }
+
+    fn step(&self, state: State, data: &[u8], status: Result<(), ErrorCode>) {
+        if let Err(error) = status.and_then(|()| self.next(state, data)) {
+            match state {
+                State::Configuring => self.state.set(State::Unconfigured),
+                State::Finish(result) => self.done(result),
+                _ => self.finish(Err(error)),
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> SpiMasterClient for Max31865<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let mut data = [0; BUF_LEN - 1];
+        read_buffer.map(|rx| {
+            data.copy_from_slice(&rx[1..]);
+            self.rxbuffer.replace(rx);
+        });
+        self.txbuffer.replace(write_buffer);
+
+        self.step(self.state.get(), &data, status);
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> AlarmClient for Max31865<'a, S, A> {
+    fn alarm(&self) {
+        self.step(self.state.get(), &[], Ok(()));
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> gpio::Client for Max31865<'a, S, A> {
+    fn fired(&self) {
+        if self.state.get() == State::Converting {
+            if self.read(State::ReadRtd, REG_RTD_MSB, 2).is_err() {
+                self.finish(Err(ErrorCode::FAIL));
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> TemperatureDriver<'a> for Max31865<'a, S, A> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Unconfigured => Err(ErrorCode::OFF),
+            State::Idle => match self.bias.get() {
+                Bias::Continuous => self.start_checks(),
+                Bias::DuringConversion => self.write(State::BiasOn, self.config(true)),
+            },
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    fn resistance_ohms(&self) -> Result<u32, ErrorCode> {
+        self.resistance.extract().ok_or(ErrorCode::FAIL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin, MockSpi};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[test]
+    fn callendar_van_dusen_in_fixed_point() {
+        // 0.018, 100.008, -100.025, -200.010, 399.979 and -212.418 °C in
+        // floating point.
+        assert_eq!(temperature_centidegrees(7621, Rtd::Pt100, 430), 2);
+        assert_eq!(temperature_centidegrees(10555, Rtd::Pt100, 430), 10001);
+        assert_eq!(temperature_centidegrees(4591, Rtd::Pt100, 430), -10003);
+        assert_eq!(temperature_centidegrees(1411, Rtd::Pt100, 430), -20001);
+        assert_eq!(temperature_centidegrees(18829, Rtd::Pt1000, 4300), 39998);
+        assert_eq!(temperature_centidegrees(1000, Rtd::Pt1000, 4300), -21242);
+
+        assert_eq!(fault_error(FAULT_RTD_HIGH), ErrorCode::NODEVICE);
+        assert_eq!(fault_error(FAULT_REFIN_LOW), ErrorCode::INVAL);
+        assert_eq!(fault_error(FAULT_OVUV | FAULT_RTDIN_LOW), ErrorCode::FAIL);
+    }
+
+    /// Complete the transfer in progress, reading `data` after the register
+    /// address.
+    fn complete(spi: &MockSpi, data: &[u8]) {
+        spi.complete_with(|_, rx| rx[1..1 + data.len()].copy_from_slice(data));
+    }
+
+    #[derive(Default)]
+    struct Client {
+        readings: RefCell<Vec<Result<i32, ErrorCode>>>,
+    }
+
+    impl TemperatureClient for Client {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.readings.borrow_mut().push(value);
+        }
+    }
+
+    #[test]
+    fn readings_check_for_faults() {
+        let spi: &'static MockSpi = Box::leak(Box::default());
+        let drdy: &'static MockPin = Box::leak(Box::new(MockPin::new(true)));
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let max31865 = Box::leak(Box::new(Max31865::new(
+            spi,
+            drdy,
+            alarm,
+            Rtd::Pt100,
+            430,
+            Box::leak(Box::new([0; BUF_LEN])),
+            Box::leak(Box::new([0; BUF_LEN])),
+        )));
+        let client = Box::leak(Box::new(Client::default()));
+        spi.set_client(max31865);
+        alarm.set_alarm_client(max31865);
+        max31865.set_client(client);
+
+        assert_eq!(max31865.read_temperature(), Err(ErrorCode::OFF));
+        assert_eq!(
+            max31865.configure(
+                Wires::Three,
+                Bias::DuringConversion,
+                FaultDetection::Automatic
+            ),
+            Ok(())
+        );
+        complete(spi, &[]);
+
+        // The bias voltage settles, the fault detection cycle finishes on
+        // the second check and finds no fault, then the conversion runs.
+        assert_eq!(max31865.read_temperature(), Ok(()));
+        complete(spi, &[]);
+        alarm.fire();
+        complete(spi, &[]);
+        alarm.fire();
+        complete(spi, &[CONFIG_VBIAS | CONFIG_FAULT_AUTOMATIC]);
+        alarm.fire();
+        complete(spi, &[CONFIG_VBIAS]);
+        complete(spi, &[0x00]);
+        complete(spi, &[]);
+        assert_eq!(max31865.read_temperature(), Err(ErrorCode::BUSY));
+        gpio::Client::fired(max31865);
+        complete(spi, &(10555u16 << 1).to_be_bytes());
+        complete(spi, &[]);
+        assert_eq!(*client.readings.borrow(), [Ok(10001)]);
+        assert_eq!(max31865.resistance_ohms(), Ok(139));
+        assert_eq!(
+            spi.take_transfers(),
+            [
+                [REG_CONFIG | WRITE, 0x12].to_vec(),
+                [REG_CONFIG | WRITE, 0x90].to_vec(),
+                [REG_CONFIG | WRITE, 0x94].to_vec(),
+                [REG_CONFIG, 0].to_vec(),
+                [REG_CONFIG, 0].to_vec(),
+                [REG_FAULT_STATUS, 0].to_vec(),
+                [REG_CONFIG | WRITE, 0xB0].to_vec(),
+                [REG_RTD_MSB, 0, 0].to_vec(),
+                [REG_CONFIG | WRITE, 0x12].to_vec(),
+            ]
+        );
+
+        // The conversion flags a fault: the RTD is open.
+        assert_eq!(
+            max31865.configure(Wires::Four, Bias::Continuous, FaultDetection::Off),
+            Ok(())
+        );
+        complete(spi, &[]);
+        assert_eq!(max31865.read_temperature(), Ok(()));
+        complete(spi, &[]);
+        gpio::Client::fired(max31865);
+        complete(spi, &[0xFF, 0xFF]);
+        complete(spi, &[FAULT_RTD_HIGH | FAULT_RTDIN_LOW]);
+        complete(spi, &[]);
+        assert_eq!(client.readings.borrow()[1], Err(ErrorCode::NODEVICE));
+        assert_eq!(max31865.fault_status(), 0x88);
+        assert_eq!(
+            spi.take_transfers(),
+            [
+                [REG_CONFIG | WRITE, 0x82].to_vec(),
+                [REG_CONFIG | WRITE, 0xA0].to_vec(),
+                [REG_RTD_MSB, 0, 0].to_vec(),
+                [REG_FAULT_STATUS, 0].to_vec(),
+                [REG_CONFIG | WRITE, 0x82].to_vec(),
+            ]
+        );
+    }
+}
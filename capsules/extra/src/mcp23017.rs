@@ -0,0 +1,592 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Microchip MCP23017 16-bit I2C GPIO expander, exposing its
+//! pins through the synchronous `hil::gpio` traits.
+//!
+//! <https://ww1.microchip.com/downloads/en/devicedoc/20001952c.pdf>
+//!
+//! Unlike [`crate::mcp230xx`], which implements `hil::gpio_async`, every
+//! expander pin here is a [`Mcp23017Pin`] implementing `gpio::Pin` and
+//! `gpio::InterruptPin`, so capsules written for on-chip GPIO can use them
+//! unchanged.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The driver keeps a copy of the direction, pull-up, output latch and
+//! interrupt enable registers. The `hil::gpio` calls update the copy and
+//! return immediately; the changed registers are then written over I2C in the
+//! background. The output latch is written before the directions, so a pin
+//! made an output after being set drives the new level straight away.
+//!
+//! Reading an input returns the level last sampled from the expander. The
+//! inputs are sampled whenever the expander raises its INT line, so pins
+//! with interrupts enabled are always current; other inputs can be sampled
+//! with [`Mcp23017::refresh_inputs`]. Reading an output returns the level it
+//! was set to.
+//!
+//! The expander is configured with its two INT outputs mirrored and flags
+//! any change on an enabled pin. On the falling edge of INT the driver reads
+//! which pins changed and their level at that time, and calls the client of
+//! every pin whose edge matches the one it enabled.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mcp23017_pins = components::mcp23017::Mcp23017Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mcp23017::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MCP23017_INT],
+//! )
+//! .finalize(components::mcp23017_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! let row0 = mcp23017_pins.gpa0();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the MCP23017, with A0 to A2 connected to ground.
+pub const BASE_ADDR: u8 = 0x20;
+
+/// Number of GPIO pins, GPA0 to GPA7 followed by GPB0 to GPB7.
+pub const NUM_PINS: usize = 16;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 6;
+
+// Register addresses of port A with `IOCON.BANK` cleared. The port B
+// register follows each of them.
+const REG_IODIRA: u8 = 0x00;
+const REG_GPINTENA: u8 = 0x04;
+const REG_IOCON: u8 = 0x0A;
+const REG_GPPUA: u8 = 0x0C;
+const REG_INTFA: u8 = 0x0E;
+const REG_GPIOA: u8 = 0x12;
+const REG_OLATA: u8 = 0x14;
+
+/// `IOCON` value: INTA and INTB mirrored, active low push-pull outputs.
+const IOCON_MIRROR: u8 = 0x40;
+
+// Work waiting to be done over I2C, in the order it is done.
+const PENDING_IOCON: u8 = 1 << 0;
+const PENDING_OLAT: u8 = 1 << 1;
+const PENDING_GPPU: u8 = 1 << 2;
+const PENDING_IODIR: u8 = 1 << 3;
+const PENDING_GPINTEN: u8 = 1 << 4;
+const PENDING_INTERRUPT: u8 = 1 << 5;
+const PENDING_INPUTS: u8 = 1 << 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Stopped,
+    Idle,
+    Writing,
+    /// Reading `INTF`, `INTCAP` and `GPIO` of both ports.
+    ReadingInterrupt,
+    /// Reading `GPIO` of both ports.
+    ReadingInputs,
+}
+
+pub struct Mcp23017<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    interrupt_pin: &'a G,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    pending: Cell<u8>,
+    /// Set bits are inputs, as in `IODIR`.
+    inputs_mask: Cell<u16>,
+    pull_ups: Cell<u16>,
+    outputs: Cell<u16>,
+    rising_edges: Cell<u16>,
+    falling_edges: Cell<u16>,
+    /// The levels last read from `GPIO`.
+    levels: Cell<u16>,
+    clients: [OptionalCell<&'a dyn gpio::Client>; NUM_PINS],
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Mcp23017<'a, I, G> {
+    pub fn new(i2c: &'a I, interrupt_pin: &'a G, buffer: &'static mut [u8]) -> Mcp23017<'a, I, G> {
+        Mcp23017 {
+            i2c,
+            interrupt_pin,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Stopped),
+            pending: Cell::new(0),
+            // All pins are inputs after reset.
+            inputs_mask: Cell::new(0xFFFF),
+            pull_ups: Cell::new(0),
+            outputs: Cell::new(0),
+            rising_edges: Cell::new(0),
+            falling_edges: Cell::new(0),
+            levels: Cell::new(0),
+            clients: core::array::from_fn(|_| OptionalCell::empty()),
+        }
+    }
+
+    /// Configure the expander and write the pin configuration made so far.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Stopped {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+
+        // Reading the interrupt captures clears any interrupt left over from
+        // before the driver started.
+        self.state.set(State::Idle);
+        self.schedule(
+            PENDING_IOCON
+                | PENDING_OLAT
+                | PENDING_GPPU
+                | PENDING_IODIR
+                | PENDING_GPINTEN
+                | PENDING_INTERRUPT,
+        );
+        Ok(())
+    }
+
+    pub fn configure_output(&self, pin: usize) {
+        self.update(&self.inputs_mask, pin, false, PENDING_IODIR);
+    }
+
+    pub fn configure_input(&self, pin: usize) {
+        self.update(&self.inputs_mask, pin, true, PENDING_IODIR);
+    }
+
+    pub fn is_input(&self, pin: usize) -> bool {
+        self.inputs_mask.get() & (1 << pin) != 0
+    }
+
+    /// Enable or disable the 100 kOhm pull-up of `pin`.
+    pub fn set_pull_up(&self, pin: usize, enabled: bool) {
+        self.update(&self.pull_ups, pin, enabled, PENDING_GPPU);
+    }
+
+    pub fn pull_up(&self, pin: usize) -> bool {
+        self.pull_ups.get() & (1 << pin) != 0
+    }
+
+    /// Drive `pin` high or low once it is an output.
+    pub fn write(&self, pin: usize, high: bool) {
+        self.update(&self.outputs, pin, high, PENDING_OLAT);
+    }
+
+    /// The level of `pin`: the last sampled level for an input, the driven
+    /// level for an output.
+    pub fn read(&self, pin: usize) -> bool {
+        let levels = if self.is_input(pin) {
+            self.levels.get()
+        } else {
+            self.outputs.get()
+        };
+        levels & (1 << pin) != 0
+    }
+
+    /// Sample all inputs, for inputs without interrupts enabled.
+    pub fn refresh_inputs(&self) {
+        self.schedule(PENDING_INPUTS);
+    }
+
+    pub fn set_pin_client(&self, pin: usize, client: &'a dyn gpio::Client) {
+        self.clients[pin].set(client);
+    }
+
+    pub fn enable_interrupt(&self, pin: usize, edge: gpio::InterruptEdge) {
+        let (rising, falling) = match edge {
+            gpio::InterruptEdge::RisingEdge => (true, false),
+            gpio::InterruptEdge::FallingEdge => (false, true),
+            gpio::InterruptEdge::EitherEdge => (true, true),
+        };
+        let bit = 1 << pin;
+        self.rising_edges
+            .set(self.rising_edges.get() & !bit | if rising { bit } else { 0 });
+        self.falling_edges
+            .set(self.falling_edges.get() & !bit | if falling { bit } else { 0 });
+        self.schedule(PENDING_GPINTEN);
+    }
+
+    pub fn disable_interrupt(&self, pin: usize) {
+        let bit = 1 << pin;
+        self.rising_edges.set(self.rising_edges.get() & !bit);
+        self.falling_edges.set(self.falling_edges.get() & !bit);
+        self.schedule(PENDING_GPINTEN);
+    }
+
+    fn update(&self, register: &Cell<u16>, pin: usize, set: bool, pending: u8) {
+        let bit = 1 << pin;
+        if set {
+            register.set(register.get() | bit);
+        } else {
+            register.set(register.get() & !bit);
+        }
+        self.schedule(pending);
+    }
+
+    fn schedule(&self, pending: u8) {
+        self.pending.set(self.pending.get() | pending);
+        self.run();
+    }
+
+    /// Start the next pending I2C transfer if the driver is idle.
+    fn run(&self) {
+        while self.state.get() == State::Idle && self.pending.get() != 0 {
+            let pending = self.pending.get();
+            // Lowest set bit first.
+            let work = pending & pending.wrapping_neg();
+            self.pending.set(pending & !work);
+
+            let buffer = match self.buffer.take() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let result = match work {
+                PENDING_IOCON => {
+                    buffer[0] = REG_IOCON;
+                    buffer[1] = IOCON_MIRROR;
+                    self.state.set(State::Writing);
+                    self.i2c.write(buffer, 2)
+                }
+                PENDING_INTERRUPT => {
+                    buffer[0] = REG_INTFA;
+                    self.state.set(State::ReadingInterrupt);
+                    self.i2c.write_read(buffer, 1, 6)
+                }
+                PENDING_INPUTS => {
+                    buffer[0] = REG_GPIOA;
+                    self.state.set(State::ReadingInputs);
+                    self.i2c.write_read(buffer, 1, 2)
+                }
+                _ => {
+                    let (register, value) = match work {
+                        PENDING_IODIR => (REG_IODIRA, self.inputs_mask.get()),
+                        PENDING_GPPU => (REG_GPPUA, self.pull_ups.get()),
+                        PENDING_OLAT => (REG_OLATA, self.outputs.get()),
+                        _ => (
+                            REG_GPINTENA,
+                            self.rising_edges.get() | self.falling_edges.get(),
+                        ),
+                    };
+                    buffer[0] = register;
+                    buffer[1..3].copy_from_slice(&value.to_le_bytes());
+                    self.state.set(State::Writing);
+                    self.i2c.write(buffer, 3)
+                }
+            };
+            if let Err((_error, buffer)) = result {
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+        }
+    }
+
+    /// Call the clients of the pins in `flagged` whose enabled edge matches
+    /// the level `captured` when the interrupt was raised.
+    fn dispatch(&self, flagged: u16, captured: u16) {
+        let fired =
+            flagged & (captured & self.rising_edges.get() | !captured & self.falling_edges.get());
+        for (pin, client) in self.clients.iter().enumerate() {
+            if fired & (1 << pin) != 0 {
+                client.map(|client| client.fired());
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Mcp23017<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let word = |index: usize| u16::from_le_bytes([buffer[index], buffer[index + 1]]);
+        let mut interrupt = None;
+        if status.is_ok() {
+            match state {
+                State::ReadingInterrupt => {
+                    self.levels.set(word(4));
+                    interrupt = Some((word(0), word(2)));
+                }
+                State::ReadingInputs => self.levels.set(word(0)),
+                _ => {}
+            }
+        }
+        self.buffer.replace(buffer);
+        self.state.set(State::Idle);
+
+        if let Some((flagged, captured)) = interrupt {
+            self.dispatch(flagged, captured);
+        }
+        self.run();
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Mcp23017<'a, I, G> {
+    fn fired(&self) {
+        self.schedule(PENDING_INTERRUPT);
+    }
+}
+
+/// One pin of an MCP23017, usable wherever an on-chip GPIO pin is.
+pub struct Mcp23017Pin<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    mcp23017: &'a Mcp23017<'a, I, G>,
+    pin: usize,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Mcp23017Pin<'a, I, G> {
+    pub fn new(mcp23017: &'a Mcp23017<'a, I, G>, pin: usize) -> Mcp23017Pin<'a, I, G> {
+        Mcp23017Pin { mcp23017, pin }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Configure for Mcp23017Pin<'a, I, G> {
+    fn configuration(&self) -> gpio::Configuration {
+        if self.mcp23017.is_input(self.pin) {
+            gpio::Configuration::Input
+        } else {
+            gpio::Configuration::Output
+        }
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.mcp23017.configure_output(self.pin);
+        gpio::Configuration::Output
+    }
+
+    /// A pin is always an input or an output, so this makes it an input.
+    fn disable_output(&self) -> gpio::Configuration {
+        self.make_input()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.mcp23017.configure_input(self.pin);
+        gpio::Configuration::Input
+    }
+
+    /// A pin is always an input or an output, so this leaves an output as it
+    /// is.
+    fn disable_input(&self) -> gpio::Configuration {
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.mcp23017.configure_input(self.pin);
+        self.mcp23017.set_pull_up(self.pin, false);
+    }
+
+    /// The MCP23017 has no pull-downs, `PullDown` leaves the pin floating.
+    fn set_floating_state(&self, state: gpio::FloatingState) {
+        self.mcp23017
+            .set_pull_up(self.pin, matches!(state, gpio::FloatingState::PullUp));
+    }
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        if self.mcp23017.pull_up(self.pin) {
+            gpio::FloatingState::PullUp
+        } else {
+            gpio::FloatingState::PullNone
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Output for Mcp23017Pin<'a, I, G> {
+    fn set(&self) {
+        self.mcp23017.write(self.pin, true);
+    }
+
+    fn clear(&self) {
+        self.mcp23017.write(self.pin, false);
+    }
+
+    fn toggle(&self) -> bool {
+        let high = self.mcp23017.outputs.get() & (1 << self.pin) == 0;
+        self.mcp23017.write(self.pin, high);
+        high
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Input for Mcp23017Pin<'a, I, G> {
+    fn read(&self) -> bool {
+        self.mcp23017.read(self.pin)
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Interrupt<'a> for Mcp23017Pin<'a, I, G> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.mcp23017.set_pin_client(self.pin, client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.mcp23017.enable_interrupt(self.pin, mode);
+    }
+
+    fn disable_interrupts(&self) {
+        self.mcp23017.disable_interrupt(self.pin);
+    }
+
+    /// Interrupts are dispatched as soon as they are read from the expander.
+    fn is_pending(&self) -> bool {
+        false
+    }
+}
+
+/// The 16 pins of an MCP23017.
+pub struct Mcp23017Pins<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    pins: [Mcp23017Pin<'a, I, G>; NUM_PINS],
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Mcp23017Pins<'a, I, G> {
+    pub fn new(mcp23017: &'a Mcp23017<'a, I, G>) -> Mcp23017Pins<'a, I, G> {
+        Mcp23017Pins {
+            pins: core::array::from_fn(|pin| Mcp23017Pin::new(mcp23017, pin)),
+        }
+    }
+
+    /// The expander the pins belong to.
+    pub fn device(&self) -> &'a Mcp23017<'a, I, G> {
+        self.pins[0].mcp23017
+    }
+
+    /// Pin `index`, where 0 to 7 are GPA0 to GPA7 and 8 to 15 are GPB0 to
+    /// GPB7.
+    pub fn pin(&self, index: usize) -> Option<&Mcp23017Pin<'a, I, G>> {
+        self.pins.get(index)
+    }
+
+    pub fn gpa0(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[0]
+    }
+    pub fn gpa1(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[1]
+    }
+    pub fn gpa2(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[2]
+    }
+    pub fn gpa3(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[3]
+    }
+    pub fn gpa4(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[4]
+    }
+    pub fn gpa5(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[5]
+    }
+    pub fn gpa6(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[6]
+    }
+    pub fn gpa7(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[7]
+    }
+    pub fn gpb0(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[8]
+    }
+    pub fn gpb1(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[9]
+    }
+    pub fn gpb2(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[10]
+    }
+    pub fn gpb3(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[11]
+    }
+    pub fn gpb4(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[12]
+    }
+    pub fn gpb5(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[13]
+    }
+    pub fn gpb6(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[14]
+    }
+    pub fn gpb7(&self) -> &Mcp23017Pin<'a, I, G> {
+        &self.pins[15]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use kernel::hil::gpio::{Configure, Input, Interrupt, Output};
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct PinClient {
+        fired: Cell<usize>,
+    }
+
+    impl gpio::Client for PinClient {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+        }
+    }
+
+    type Device = Mcp23017<'static, MockI2c, MockPin<'static>>;
+
+    #[test]
+    fn virtual_pins_behave_like_gpio() {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let mcp23017: &Device = Box::leak(Box::new(Mcp23017::new(
+            i2c,
+            Box::leak(Box::new(MockPin::default())),
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        let pins = Box::leak(Box::new(Mcp23017Pins::new(mcp23017)));
+        let client = Box::leak(Box::new(PinClient::default()));
+
+        // A keypad column on GPB1 and a row on GPA2, configured before the
+        // driver starts.
+        let column = pins.gpb1();
+        column.make_output();
+        column.set();
+        let row = pins.gpa2();
+        row.make_input();
+        row.set_floating_state(gpio::FloatingState::PullUp);
+        row.set_client(client);
+        row.enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        assert!(!i2c.busy());
+
+        assert_eq!(mcp23017.start(), Ok(()));
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_IOCON, IOCON_MIRROR]);
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_OLATA, 0x00, 0x02]);
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_GPPUA, 0x04, 0x00]);
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_IODIRA, 0xFF, 0xFD]);
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_GPINTENA, 0x04, 0x00]);
+        assert_eq!(
+            i2c.complete(mcp23017, &[0, 0, 0, 0, 0xFF, 0x00]),
+            [REG_INTFA]
+        );
+        assert!(!i2c.busy());
+        assert!(column.read());
+        assert!(row.read());
+
+        // Toggling an output is written in the background.
+        assert!(!column.toggle());
+        assert!(!column.read());
+        assert_eq!(i2c.complete(mcp23017, &[]), [REG_OLATA, 0x00, 0x00]);
+
+        // GPA2 falls: the client is called and the new level is visible.
+        gpio::Client::fired(mcp23017);
+        assert_eq!(
+            i2c.complete(mcp23017, &[0x04, 0x00, 0xFB, 0x00, 0xFB, 0x00]),
+            [REG_INTFA]
+        );
+        assert_eq!(client.fired.get(), 1);
+        assert!(!row.read());
+
+        // GPA2 rises again, which the client did not ask for.
+        gpio::Client::fired(mcp23017);
+        i2c.complete(mcp23017, &[0x04, 0x00, 0xFF, 0x00, 0xFF, 0x00]);
+        assert_eq!(client.fired.get(), 1);
+        assert!(row.read());
+    }
+}
@@ -0,0 +1,361 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Microchip MCP4725 12-bit I2C DAC.
+//!
+//! <https://ww1.microchip.com/downloads/en/DeviceDoc/22039d.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The output is set with two-byte fast writes, which only change the DAC
+//! register. A value set while a write is in progress is written once it
+//! completes, and replaces any value that was already waiting, so the chip
+//! always ends up at the last value set.
+//!
+//! [Mcp4725::set_power_on_value] also writes the value to the EEPROM, which
+//! the chip loads at power up. The EEPROM write takes up to 50 ms, during
+//! which the chip ignores other writes: the driver polls the ready bit with
+//! an alarm, and returns `BUSY` for any output change until it is done.
+//!
+//! The MCP4725 implements `hil::dac::DacChannel`, with the power up value
+//! set through `set_power_on_value`, and [Mcp4725::set_vout_mv] sets the
+//! output in millivolts from the supply voltage, which is the reference of
+//! the DAC.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mcp4725 = components::mcp4725::Mcp4725Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mcp4725::BASE_ADDR,
+//!     mux_alarm,
+//!     3300, // supply_mv
+//! )
+//! .finalize(components::mcp4725_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let dac = components::dac::DacComponent::new(mcp4725)
+//!     .finalize(components::dac_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::dac;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the MCP4725A0, with A0 connected to ground.
+pub const BASE_ADDR: u8 = 0x60;
+
+/// Size of the buffer the driver needs, for an EEPROM write.
+pub const BUFFER_SIZE: usize = 3;
+
+/// Largest output value.
+pub const MAX_VALUE: u16 = 4095;
+
+/// First byte of a write of the DAC register and the EEPROM.
+const CMD_WRITE_DAC_EEPROM: u8 = 0x60;
+
+/// Bit of the status byte that is clear during an EEPROM write.
+const STATUS_READY: u8 = 0x80;
+
+/// Interval between polls of the ready bit during an EEPROM write.
+const EEPROM_POLL_MS: u32 = 10;
+/// Number of polls before an EEPROM write fails, twice its maximum time.
+const EEPROM_MAX_POLLS: u8 = 10;
+
+pub trait Mcp4725Client {
+    /// The value of `set_power_on_value` was written to the EEPROM.
+    fn power_on_value_written(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Write,
+    EepromWrite,
+    /// Waiting before the next poll of the ready bit.
+    EepromWait,
+    EepromPoll,
+}
+
+pub struct Mcp4725<'a, I: I2CDevice, A: Alarm<'a>> {
+    i2c: &'a I,
+    alarm: &'a A,
+    supply_mv: u16,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn Mcp4725Client>,
+    state: Cell<State>,
+    /// Output value waiting for the current write to complete.
+    pending: OptionalCell<u16>,
+    polls: Cell<u8>,
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> Mcp4725<'a, I, A> {
+    /// `supply_mv` is the supply voltage of the chip, which is also the
+    /// full scale of its output.
+    pub fn new(i2c: &'a I, alarm: &'a A, supply_mv: u16, buffer: &'static mut [u8]) -> Self {
+        Mcp4725 {
+            i2c,
+            alarm,
+            supply_mv,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            pending: OptionalCell::empty(),
+            polls: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Mcp4725Client) {
+        self.client.set(client);
+    }
+
+    /// Set the output to `value`, from 0 to [MAX_VALUE].
+    pub fn set_output(&self, value: u16) -> Result<(), ErrorCode> {
+        if value > MAX_VALUE {
+            return Err(ErrorCode::INVAL);
+        }
+        match self.state.get() {
+            State::Idle => self.write(State::Write, &fast_write(value)),
+            State::Write => {
+                self.pending.set(value);
+                Ok(())
+            }
+            State::EepromWrite | State::EepromWait | State::EepromPoll => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Set the output to `mv` millivolts, up to the supply voltage.
+    pub fn set_vout_mv(&self, mv: u16) -> Result<(), ErrorCode> {
+        let value = counts(mv, self.supply_mv).ok_or(ErrorCode::INVAL)?;
+        self.set_output(value)
+    }
+
+    /// Set the output to `value`, and store it in the EEPROM as the output
+    /// at power up.
+    pub fn set_power_on_value(&self, value: u16) -> Result<(), ErrorCode> {
+        if value > MAX_VALUE {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.write(State::EepromWrite, &eeprom_write(value))
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        match self.i2c.write(buffer, bytes.len()) {
+            Ok(()) => {
+                self.state.set(state);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.buffer.replace(buffer);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn poll_ready(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        match self.i2c.read(buffer, 1) {
+            Ok(()) => {
+                self.state.set(State::EepromPoll);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.buffer.replace(buffer);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn wait_ready(&self) {
+        self.state.set(State::EepromWait);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(EEPROM_POLL_MS));
+    }
+
+    /// Finish an EEPROM write, successful or not.
+    fn eeprom_done(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client
+            .map(|client| client.power_on_value_written(result));
+    }
+}
+
+/// The fast write of `value`, with the chip powered up.
+fn fast_write(value: u16) -> [u8; 2] {
+    [(value >> 8) as u8 & 0x0F, value as u8]
+}
+
+/// The write of `value` to the DAC register and the EEPROM.
+fn eeprom_write(value: u16) -> [u8; BUFFER_SIZE] {
+    [CMD_WRITE_DAC_EEPROM, (value >> 4) as u8, (value << 4) as u8]
+}
+
+/// The output value closest to `mv`, or `None` above the supply voltage.
+fn counts(mv: u16, supply_mv: u16) -> Option<u16> {
+    if supply_mv == 0 || mv > supply_mv {
+        return None;
+    }
+    let full_scale = MAX_VALUE as u32 + 1;
+    let value = (mv as u32 * full_scale + supply_mv as u32 / 2) / supply_mv as u32;
+    Some(core::cmp::min(value, MAX_VALUE as u32) as u16)
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> I2CClient for Mcp4725<'a, I, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let ready = buffer[0] & STATUS_READY != 0;
+        self.buffer.replace(buffer);
+        let status = status.map_err(|e| e.into());
+        match self.state.get() {
+            State::Write => {
+                // The HIL has no completion callback, so a failed write is
+                // only fixed by the next value.
+                self.state.set(State::Idle);
+                if let Some(value) = self.pending.take() {
+                    let _ = self.write(State::Write, &fast_write(value));
+                }
+            }
+            State::EepromWrite => match status {
+                Ok(()) => {
+                    self.polls.set(0);
+                    self.wait_ready();
+                }
+                Err(e) => self.eeprom_done(Err(e)),
+            },
+            State::EepromPoll => match status {
+                Ok(()) if ready => self.eeprom_done(Ok(())),
+                Ok(()) if self.polls.get() + 1 < EEPROM_MAX_POLLS => {
+                    self.polls.set(self.polls.get() + 1);
+                    self.wait_ready();
+                }
+                Ok(()) => self.eeprom_done(Err(ErrorCode::FAIL)),
+                Err(e) => self.eeprom_done(Err(e)),
+            },
+            State::Idle | State::EepromWait => {}
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> time::AlarmClient for Mcp4725<'a, I, A> {
+    fn alarm(&self) {
+        if self.state.get() == State::EepromWait {
+            if let Err(e) = self.poll_ready() {
+                self.eeprom_done(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> dac::DacChannel for Mcp4725<'a, I, A> {
+    fn set_value(&self, value: usize) -> Result<(), ErrorCode> {
+        let value = u16::try_from(value).map_err(|_| ErrorCode::INVAL)?;
+        self.set_output(value)
+    }
+
+    fn set_power_on_value(&self, value: usize) -> Result<(), ErrorCode> {
+        let value = u16::try_from(value).map_err(|_| ErrorCode::INVAL)?;
+        Mcp4725::set_power_on_value(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct MockClient {
+        written: Cell<Option<Result<(), ErrorCode>>>,
+    }
+
+    impl Mcp4725Client for MockClient {
+        fn power_on_value_written(&self, result: Result<(), ErrorCode>) {
+            self.written.set(Some(result));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type Device = Mcp4725<'static, MockI2c, TestAlarm>;
+
+    /// Complete the transfer in progress, reading `status` if it reads.
+    fn complete(i2c: &MockI2c, mcp4725: &Device, status: u8) {
+        let read_len = i2c.transfer().unwrap().read_len;
+        i2c.complete(mcp4725, &[status][..read_len.min(1)]);
+    }
+
+    #[test]
+    fn millivolts_to_counts() {
+        assert_eq!(counts(0, 3300), Some(0));
+        assert_eq!(counts(1650, 3300), Some(2048));
+        assert_eq!(counts(1000, 3300), Some(1241));
+        assert_eq!(counts(3300, 3300), Some(MAX_VALUE));
+        assert_eq!(counts(3301, 3300), None);
+    }
+
+    #[test]
+    fn volatile_writes_coalesce_and_eeprom_write_polls() {
+        let i2c = Box::leak(Box::new(MockI2c::new()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let mcp4725: &Device = Box::leak(Box::new(Mcp4725::new(
+            i2c,
+            alarm,
+            3300,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        let client = Box::leak(Box::new(MockClient {
+            written: Cell::new(None),
+        }));
+        alarm.set_alarm_client(mcp4725);
+        mcp4725.set_client(client);
+
+        // Values set during a write are coalesced into one more write.
+        assert_eq!(mcp4725.set_output(0xABC), Ok(()));
+        assert_eq!(mcp4725.set_output(1), Ok(()));
+        assert_eq!(mcp4725.set_vout_mv(1650), Ok(()));
+        assert_eq!(mcp4725.set_output(MAX_VALUE + 1), Err(ErrorCode::INVAL));
+        complete(i2c, mcp4725, 0);
+        complete(i2c, mcp4725, 0);
+
+        // The EEPROM write is polled until the chip is ready, and blocks
+        // output changes until then.
+        assert_eq!(mcp4725.set_power_on_value(0x123), Ok(()));
+        assert_eq!(mcp4725.set_output(0), Err(ErrorCode::BUSY));
+        complete(i2c, mcp4725, 0);
+        assert_eq!(alarm.dt(), Some(EEPROM_POLL_MS * 1000));
+        alarm.fire();
+        complete(i2c, mcp4725, 0x00);
+        assert_eq!(client.written.get(), None);
+        alarm.fire();
+        complete(i2c, mcp4725, STATUS_READY);
+        assert_eq!(client.written.get(), Some(Ok(())));
+        assert_eq!(mcp4725.set_output(0), Ok(()));
+
+        let (reads, writes): (Vec<_>, Vec<_>) = i2c
+            .transfers()
+            .into_iter()
+            .partition(|transfer| transfer.read_len > 0);
+        assert_eq!(reads.len(), 2);
+        let writes: Vec<_> = writes.into_iter().map(|transfer| transfer.write).collect();
+        assert_eq!(writes[0], [0x0A, 0xBC]);
+        assert_eq!(writes[1], [0x08, 0x00]);
+        assert_eq!(writes[2], [CMD_WRITE_DAC_EEPROM, 0x12, 0x30]);
+        assert_eq!(writes[3], [0x00, 0x00]);
+        assert_eq!(writes.len(), 4);
+    }
+}
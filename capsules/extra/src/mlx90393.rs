@@ -0,0 +1,745 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Melexis MLX90393 3-axis magnetometer.
+//!
+//! <https://www.melexis.com/en/product/MLX90393/Triaxis-Micropower-Magnetometer>
+//!
+//! > The MLX90393 is a magnetic field sensor designed for micropower
+//! > applications, with programmable duty cycles in the range 0.1% to 100%.
+//! > The device's sensitivity, resolution, oversampling ratio and digital
+//! > filtering can be tuned to the application.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The sensor is driven with commands: an opcode, with the measured axes or
+//! register address and data following, answered by a status byte and any
+//! data. The gain (`GAIN_SEL`), Hall plate configuration (`HALLCONF`),
+//! resolution, oversampling ratio and digital filter are written to the
+//! sensor's registers by [Mlx90393::configure].
+//!
+//! The field is exposed through the [Magnetometer] HIL in nanotesla and the
+//! temperature sensor through [TemperatureDriver]. Every reading starts a
+//! single measurement and reads it back once the sensor raises its INT
+//! (DRDY) line.
+//!
+//! In burst mode, started with [Mlx90393::start_burst], the sensor measures
+//! the field periodically on its own and raises DRDY after each
+//! measurement. The driver collects up to [MAX_BURST_SAMPLES] samples and
+//! hands them to the [BurstClient] together.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mlx90393 = components::mlx90393::Mlx90393Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mlx90393::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MLX90393_INT],
+//! )
+//! .finalize(components::mlx90393_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{
+    Magnetometer, MagnetometerClient, TemperatureClient, TemperatureDriver,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the MLX90393 with A1 and A0 low.
+pub const BASE_ADDR: u8 = 0x0C;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 7;
+
+/// The most field samples a burst hands to the client at once.
+pub const MAX_BURST_SAMPLES: usize = 16;
+
+const CMD_START_BURST: u8 = 0x10;
+const CMD_START_MEASUREMENT: u8 = 0x30;
+const CMD_READ_MEASUREMENT: u8 = 0x40;
+const CMD_READ_REGISTER: u8 = 0x50;
+const CMD_WRITE_REGISTER: u8 = 0x60;
+const CMD_EXIT: u8 = 0x80;
+
+/// Axis selection in the low nibble of the measurement commands.
+const AXIS_T: u8 = 1 << 0;
+const AXES_XYZ: u8 = 0b1110;
+
+const STATUS_ERROR: u8 = 1 << 4;
+
+/// `GAIN_SEL` and `HALLCONF`.
+const REG_GAIN: u8 = 0x00;
+/// `BURST_DATA_RATE` and the burst and interface options.
+const REG_BURST: u8 = 0x01;
+/// `OSR`, `DIG_FILT` and `RES_XYZ`.
+const REG_RESOLUTION: u8 = 0x02;
+
+const GAIN_SEL_SHIFT: u16 = 4;
+const BURST_DATA_RATE_MASK: u16 = 0x3F;
+const DIG_FILT_SHIFT: u16 = 2;
+const RES_X_SHIFT: u16 = 5;
+const RES_Y_SHIFT: u16 = 7;
+const RES_Z_SHIFT: u16 = 9;
+
+/// Temperature output at 25 C.
+const TEMPERATURE_REFERENCE: i32 = 46244;
+
+/// Analog gain, as a multiple of the lowest gain.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gain {
+    X5 = 0,
+    X4 = 1,
+    X3 = 2,
+    X2_5 = 3,
+    X2 = 4,
+    X1_67 = 5,
+    X1_33 = 6,
+    X1 = 7,
+}
+
+/// Hall plate spinning configuration.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HallConf {
+    /// Two phases, for the shortest conversions.
+    Hallconf0 = 0x0,
+    /// Four phases, the reset default.
+    HallconfC = 0xC,
+}
+
+/// Which 16 bits of the 19-bit measurement are output. Each step halves
+/// the sensitivity and doubles the range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resolution {
+    Res16 = 0,
+    Res17 = 1,
+    Res18 = 2,
+    Res19 = 3,
+}
+
+/// Number of ADC conversions averaged per measurement.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Oversampling {
+    X1 = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+}
+
+pub trait BurstClient {
+    /// Field samples in nanotesla, oldest first, or the error that ended
+    /// the burst.
+    fn samples_ready(&self, samples: Result<&[(i32, i32, i32)], ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    WriteGain,
+    WriteResolution,
+    StartField,
+    MeasureField,
+    ReadField,
+    StartTemperature,
+    MeasureTemperature,
+    ReadTemperature,
+    ReadBurstRate,
+    WriteBurstRate,
+    StartBurst,
+    Burst,
+    ReadBurst,
+    ExitBurst,
+}
+
+/// Sensitivity with `Resolution::Res16`, in nanotesla per count, of the X
+/// and Y axes and of the Z axis.
+fn base_sensitivity_nt(hallconf: HallConf, gain: Gain) -> (i32, i32) {
+    const HALLCONF_0: [(i32, i32); 8] = [
+        (787, 1267),
+        (629, 1014),
+        (472, 760),
+        (393, 634),
+        (315, 507),
+        (262, 422),
+        (210, 338),
+        (157, 253),
+    ];
+    const HALLCONF_C: [(i32, i32); 8] = [
+        (751, 1210),
+        (601, 968),
+        (451, 726),
+        (376, 605),
+        (300, 484),
+        (250, 403),
+        (200, 323),
+        (150, 242),
+    ];
+    match hallconf {
+        HallConf::Hallconf0 => HALLCONF_0[gain as usize],
+        HallConf::HallconfC => HALLCONF_C[gain as usize],
+    }
+}
+
+/// Convert an axis output to signed counts centered on zero field.
+///
+/// The two lowest resolutions output a two's complement value, the others
+/// an unsigned value with an offset.
+fn to_signed(raw: u16, resolution: Resolution) -> i32 {
+    match resolution {
+        Resolution::Res16 | Resolution::Res17 => raw as i16 as i32,
+        Resolution::Res18 => raw as i32 - 32768,
+        Resolution::Res19 => raw as i32 - 16384,
+    }
+}
+
+/// Convert the X, Y and Z outputs of a measurement to nanotesla.
+fn field_to_nt(
+    buf: &[u8],
+    hallconf: HallConf,
+    gain: Gain,
+    resolution: Resolution,
+) -> (i32, i32, i32) {
+    let (xy, z) = base_sensitivity_nt(hallconf, gain);
+    let axis = |i: usize, sensitivity: i32| {
+        let raw = u16::from_be_bytes([buf[i], buf[i + 1]]);
+        (to_signed(raw, resolution) * sensitivity) << resolution as u32
+    };
+    (axis(0, xy), axis(2, xy), axis(4, z))
+}
+
+/// Convert the temperature output to hundredths of degrees Celsius.
+///
+/// The output is 46244 at 25 C with 45.2 counts per degree.
+fn temperature_to_centi_celsius(raw: u16) -> i32 {
+    2500 + (raw as i32 - TEMPERATURE_REFERENCE) * 1000 / 452
+}
+
+pub struct Mlx90393<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    ready_pin: &'a G,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    gain: Cell<Gain>,
+    hallconf: Cell<HallConf>,
+    resolution: Cell<Resolution>,
+    oversampling: Cell<Oversampling>,
+    digital_filter: Cell<u8>,
+    burst_rate: Cell<u8>,
+    burst_len: Cell<usize>,
+    burst_count: Cell<usize>,
+    burst_samples: Cell<[(i32, i32, i32); MAX_BURST_SAMPLES]>,
+    /// `stop_burst` was called during a transfer.
+    stopping: Cell<bool>,
+    magnetometer_client: OptionalCell<&'a dyn MagnetometerClient>,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+    burst_client: OptionalCell<&'a dyn BurstClient>,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Mlx90393<'a, I, G> {
+    pub fn new(i2c: &'a I, ready_pin: &'a G, buffer: &'static mut [u8]) -> Self {
+        Mlx90393 {
+            i2c,
+            ready_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            gain: Cell::new(Gain::X1),
+            hallconf: Cell::new(HallConf::HallconfC),
+            resolution: Cell::new(Resolution::Res16),
+            oversampling: Cell::new(Oversampling::X1),
+            digital_filter: Cell::new(0),
+            burst_rate: Cell::new(0),
+            burst_len: Cell::new(0),
+            burst_count: Cell::new(0),
+            burst_samples: Cell::new([(0, 0, 0); MAX_BURST_SAMPLES]),
+            stopping: Cell::new(false),
+            magnetometer_client: OptionalCell::empty(),
+            temperature_client: OptionalCell::empty(),
+            burst_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_burst_client(&self, client: &'a dyn BurstClient) {
+        self.burst_client.set(client);
+    }
+
+    /// Configure the gain, Hall plate configuration, resolution of all
+    /// three axes, oversampling ratio and digital filter (0 to 7) of the
+    /// field measurements. Higher oversampling and filtering lower the noise
+    /// but lengthen each measurement.
+    ///
+    /// Until this is called the sensor is used with its reset configuration:
+    /// `Gain::X1`, `HallConf::HallconfC`, `Resolution::Res16`,
+    /// `Oversampling::X1` and no filter.
+    pub fn configure(
+        &self,
+        gain: Gain,
+        hallconf: HallConf,
+        resolution: Resolution,
+        oversampling: Oversampling,
+        digital_filter: u8,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if digital_filter > 7 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.gain.set(gain);
+        self.hallconf.set(hallconf);
+        self.resolution.set(resolution);
+        self.oversampling.set(oversampling);
+        self.digital_filter.set(digital_filter);
+        let value = (gain as u16) << GAIN_SEL_SHIFT | hallconf as u16;
+        let result = self.write_register(State::WriteGain, REG_GAIN, value);
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    /// Start burst mode, measuring the field every `data_rate` * 20 ms, or
+    /// back to back if `data_rate` is 0, up to 63. Every `samples` samples,
+    /// at most [MAX_BURST_SAMPLES], are passed to the [BurstClient] until
+    /// [Mlx90393::stop_burst] is called.
+    pub fn start_burst(&self, data_rate: u8, samples: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if data_rate as u16 > BURST_DATA_RATE_MASK || samples == 0 || samples > MAX_BURST_SAMPLES {
+            return Err(ErrorCode::INVAL);
+        }
+        self.burst_rate.set(data_rate);
+        self.burst_len.set(samples);
+        self.burst_count.set(0);
+        self.stopping.set(false);
+        // Only the data rate bits of the register are changed.
+        let result = self.command(
+            State::ReadBurstRate,
+            &[CMD_READ_REGISTER, REG_BURST << 2],
+            3,
+        );
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    /// Leave burst mode. Samples not yet passed to the client are dropped.
+    pub fn stop_burst(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Burst => {
+                self.ready_pin.disable_interrupts();
+                self.exit_burst();
+                Ok(())
+            }
+            State::ReadBurstRate | State::WriteBurstRate | State::StartBurst | State::ReadBurst => {
+                self.stopping.set(true);
+                Ok(())
+            }
+            State::ExitBurst => Ok(()),
+            _ => Err(ErrorCode::OFF),
+        }
+    }
+
+    /// Send a command, reading the status byte and `read_len` - 1 bytes of
+    /// data in response.
+    fn command(&self, state: State, command: &[u8], read_len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[..command.len()].copy_from_slice(command);
+            self.state.set(state);
+            self.i2c.enable();
+            self.i2c
+                .write_read(buffer, command.len(), read_len)
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn write_register(&self, state: State, register: u8, value: u16) -> Result<(), ErrorCode> {
+        let [high, low] = value.to_be_bytes();
+        self.command(state, &[CMD_WRITE_REGISTER, high, low, register << 2], 1)
+    }
+
+    fn start_measurement(&self, state: State, axes: u8) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.ready_pin
+            .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        let result = self.command(state, &[CMD_START_MEASUREMENT | axes], 1);
+        if result.is_err() {
+            self.finish();
+        }
+        result
+    }
+
+    /// Wait for DRDY in `state`, which may already be high if the
+    /// measurement finished before the command was acknowledged.
+    fn wait_ready(&self, state: State) {
+        self.state.set(state);
+        if self.ready_pin.read() {
+            gpio::Client::fired(self);
+        }
+    }
+
+    fn exit_burst(&self) {
+        self.stopping.set(false);
+        if self.command(State::ExitBurst, &[CMD_EXIT], 1).is_err() {
+            self.finish();
+        }
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.ready_pin.disable_interrupts();
+        self.i2c.disable();
+    }
+
+    fn finish_field(&self, value: Result<(i32, i32, i32), ErrorCode>) {
+        self.finish();
+        self.magnetometer_client
+            .map(|client| client.callback(value));
+    }
+
+    fn finish_temperature(&self, value: Result<i32, ErrorCode>) {
+        self.finish();
+        self.temperature_client.map(|client| client.callback(value));
+    }
+
+    /// End the burst on an error. The sensor is told to leave burst mode,
+    /// which may fail as well.
+    fn fail_burst(&self, error: ErrorCode, started: bool) {
+        if started {
+            self.exit_burst();
+        } else {
+            self.finish();
+        }
+        self.burst_client
+            .map(|client| client.samples_ready(Err(error)));
+    }
+
+    fn add_burst_sample(&self, sample: (i32, i32, i32)) {
+        let mut samples = self.burst_samples.get();
+        let count = self.burst_count.get();
+        samples[count] = sample;
+        self.burst_samples.set(samples);
+        if count + 1 < self.burst_len.get() {
+            self.burst_count.set(count + 1);
+        } else {
+            self.burst_count.set(0);
+            self.burst_client
+                .map(|client| client.samples_ready(Ok(&samples[..count + 1])));
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Mlx90393<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let result = match status {
+            Err(error) => Err(error.into()),
+            Ok(()) if buffer[0] & STATUS_ERROR != 0 => Err(ErrorCode::FAIL),
+            Ok(()) => Ok(()),
+        };
+        let mut data = [0; BUFFER_SIZE - 1];
+        data.copy_from_slice(&buffer[1..BUFFER_SIZE]);
+        self.buffer.replace(buffer);
+
+        if let Err(error) = result {
+            match state {
+                State::StartField | State::ReadField => self.finish_field(Err(error)),
+                State::StartTemperature | State::ReadTemperature => {
+                    self.finish_temperature(Err(error))
+                }
+                State::ReadBurstRate | State::WriteBurstRate => self.fail_burst(error, false),
+                State::StartBurst | State::ReadBurst => self.fail_burst(error, true),
+                _ => self.finish(),
+            }
+            return;
+        }
+
+        match state {
+            State::WriteGain => {
+                let value = self.oversampling.get() as u16
+                    | (self.digital_filter.get() as u16) << DIG_FILT_SHIFT
+                    | (self.resolution.get() as u16) << RES_X_SHIFT
+                    | (self.resolution.get() as u16) << RES_Y_SHIFT
+                    | (self.resolution.get() as u16) << RES_Z_SHIFT;
+                if self
+                    .write_register(State::WriteResolution, REG_RESOLUTION, value)
+                    .is_err()
+                {
+                    self.finish();
+                }
+            }
+            State::StartField => self.wait_ready(State::MeasureField),
+            State::ReadField => {
+                let field = field_to_nt(
+                    &data,
+                    self.hallconf.get(),
+                    self.gain.get(),
+                    self.resolution.get(),
+                );
+                self.finish_field(Ok(field));
+            }
+            State::StartTemperature => self.wait_ready(State::MeasureTemperature),
+            State::ReadTemperature => {
+                let raw = u16::from_be_bytes([data[0], data[1]]);
+                self.finish_temperature(Ok(temperature_to_centi_celsius(raw)));
+            }
+            State::ReadBurstRate | State::WriteBurstRate if self.stopping.get() => {
+                self.stopping.set(false);
+                self.finish();
+            }
+            State::ReadBurstRate => {
+                let value = u16::from_be_bytes([data[0], data[1]]) & !BURST_DATA_RATE_MASK
+                    | self.burst_rate.get() as u16;
+                if let Err(error) = self.write_register(State::WriteBurstRate, REG_BURST, value) {
+                    self.fail_burst(error, false);
+                }
+            }
+            State::WriteBurstRate => {
+                self.ready_pin
+                    .enable_interrupts(gpio::InterruptEdge::RisingEdge);
+                if let Err(error) =
+                    self.command(State::StartBurst, &[CMD_START_BURST | AXES_XYZ], 1)
+                {
+                    self.fail_burst(error, false);
+                }
+            }
+            State::StartBurst | State::ReadBurst => {
+                if state == State::ReadBurst {
+                    let sample = field_to_nt(
+                        &data,
+                        self.hallconf.get(),
+                        self.gain.get(),
+                        self.resolution.get(),
+                    );
+                    if !self.stopping.get() {
+                        self.add_burst_sample(sample);
+                    }
+                }
+                if self.stopping.get() {
+                    self.ready_pin.disable_interrupts();
+                    self.exit_burst();
+                } else {
+                    self.wait_ready(State::Burst);
+                }
+            }
+            State::WriteResolution
+            | State::ExitBurst
+            | State::Idle
+            | State::MeasureField
+            | State::MeasureTemperature
+            | State::Burst => self.finish(),
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Mlx90393<'a, I, G> {
+    fn fired(&self) {
+        let result = match self.state.get() {
+            State::MeasureField => self.command(
+                State::ReadField,
+                &[CMD_READ_MEASUREMENT | AXES_XYZ],
+                BUFFER_SIZE,
+            ),
+            State::MeasureTemperature => {
+                if let Err(error) =
+                    self.command(State::ReadTemperature, &[CMD_READ_MEASUREMENT | AXIS_T], 3)
+                {
+                    self.finish_temperature(Err(error));
+                }
+                return;
+            }
+            State::Burst => {
+                if let Err(error) = self.command(
+                    State::ReadBurst,
+                    &[CMD_READ_MEASUREMENT | AXES_XYZ],
+                    BUFFER_SIZE,
+                ) {
+                    self.fail_burst(error, true);
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Err(error) = result {
+            self.finish_field(Err(error));
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Magnetometer<'a> for Mlx90393<'a, I, G> {
+    fn set_client(&self, client: &'a dyn MagnetometerClient) {
+        self.magnetometer_client.set(client);
+    }
+
+    fn read_field_nt(&self) -> Result<(), ErrorCode> {
+        self.start_measurement(State::StartField, AXES_XYZ)
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> TemperatureDriver<'a> for Mlx90393<'a, I, G> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.start_measurement(State::StartTemperature, AXIS_T)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        fields: RefCell<Vec<Result<(i32, i32, i32), ErrorCode>>>,
+        bursts: RefCell<Vec<Vec<(i32, i32, i32)>>>,
+    }
+
+    impl MagnetometerClient for Client {
+        fn callback(&self, value: Result<(i32, i32, i32), ErrorCode>) {
+            self.fields.borrow_mut().push(value);
+        }
+    }
+
+    impl BurstClient for Client {
+        fn samples_ready(&self, samples: Result<&[(i32, i32, i32)], ErrorCode>) {
+            self.bursts.borrow_mut().push(samples.unwrap().to_vec());
+        }
+    }
+
+    type Device = Mlx90393<'static, MockI2c, MockPin<'static>>;
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockPin<'static>,
+        &'static Device,
+        &'static Client,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let ready: &'static MockPin = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let mlx90393 = Box::leak(Box::new(Mlx90393::new(
+            i2c,
+            ready,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        Magnetometer::set_client(mlx90393, client);
+        mlx90393.set_burst_client(client);
+        (i2c, ready, mlx90393, client)
+    }
+
+    #[test]
+    fn sensitivity() {
+        let field = [0x00, 0x64, 0xFF, 0x9C, 0x00, 0x64];
+        assert_eq!(
+            field_to_nt(&field, HallConf::HallconfC, Gain::X1, Resolution::Res16),
+            (15_000, -15_000, 24_200)
+        );
+        assert_eq!(
+            field_to_nt(&field, HallConf::Hallconf0, Gain::X5, Resolution::Res17),
+            (157_400, -157_400, 253_400)
+        );
+        // The two highest resolutions are offset rather than signed.
+        let field = [0x80, 0x64, 0x7F, 0x9C, 0x80, 0x64];
+        assert_eq!(
+            field_to_nt(&field, HallConf::HallconfC, Gain::X1, Resolution::Res18),
+            (60_000, -60_000, 96_800)
+        );
+        assert_eq!(to_signed(0x4064, Resolution::Res19), 100);
+    }
+
+    #[test]
+    fn temperature() {
+        assert_eq!(temperature_to_centi_celsius(46244), 2500);
+        assert_eq!(temperature_to_centi_celsius(46244 + 452), 3500);
+    }
+
+    #[test]
+    fn configure_and_read_field() {
+        let (i2c, ready, mlx90393, client) = setup();
+        assert_eq!(
+            mlx90393.configure(
+                Gain::X2,
+                HallConf::HallconfC,
+                Resolution::Res17,
+                Oversampling::X8,
+                5
+            ),
+            Ok(())
+        );
+        assert_eq!(i2c.complete(mlx90393, &[0x00]), [0x60, 0x00, 0x4C, 0x00]);
+        // OSR 3, DIG_FILT 5 and RES 1 on all three axes.
+        assert_eq!(i2c.complete(mlx90393, &[0x00]), [0x60, 0x02, 0xB7, 0x08]);
+
+        assert_eq!(mlx90393.read_field_nt(), Ok(()));
+        assert_eq!(mlx90393.read_field_nt(), Err(ErrorCode::BUSY));
+        assert!(matches!(
+            ready.interrupt_edge(),
+            Some(gpio::InterruptEdge::RisingEdge)
+        ));
+        assert_eq!(i2c.complete(mlx90393, &[0x20]), [0x3E]);
+        assert!(!i2c.busy());
+
+        gpio::Client::fired(mlx90393);
+        let reading = [0x02, 0x00, 0x0A, 0xFF, 0xF6, 0x00, 0x01];
+        assert_eq!(i2c.complete(mlx90393, &reading), [0x4E]);
+        assert_eq!(*client.fields.borrow(), [Ok((6_000, -6_000, 968))]);
+        assert!(!ready.interrupts_enabled());
+
+        // The sensor rejects the command.
+        assert_eq!(mlx90393.read_field_nt(), Ok(()));
+        i2c.complete(mlx90393, &[STATUS_ERROR]);
+        assert_eq!(client.fields.borrow()[1], Err(ErrorCode::FAIL));
+    }
+
+    #[test]
+    fn burst_batches_samples() {
+        let (i2c, ready, mlx90393, client) = setup();
+        assert_eq!(mlx90393.start_burst(5, 17), Err(ErrorCode::INVAL));
+        assert_eq!(mlx90393.start_burst(5, 2), Ok(()));
+        assert_eq!(i2c.complete(mlx90393, &[0x00, 0x80, 0x40]), [0x50, 0x04]);
+        assert_eq!(i2c.complete(mlx90393, &[0x00]), [0x60, 0x80, 0x45, 0x04]);
+        // DRDY is already high when the burst starts.
+        ready.set_level(true);
+        assert_eq!(i2c.complete(mlx90393, &[0x80]), [0x1E]);
+        ready.set_level(false);
+
+        for z in 1..=4 {
+            assert_eq!(i2c.complete(mlx90393, &[0x82, 0, 0, 0, 0, 0, z]), [0x4E]);
+            gpio::Client::fired(mlx90393);
+        }
+        assert_eq!(
+            *client.bursts.borrow(),
+            [[(0, 0, 242), (0, 0, 484)], [(0, 0, 726), (0, 0, 968)]]
+        );
+
+        // Stopping mid-read drops the sample and leaves burst mode.
+        assert_eq!(mlx90393.stop_burst(), Ok(()));
+        i2c.complete(mlx90393, &[0x82, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(i2c.complete(mlx90393, &[0x00]), [0x80]);
+        assert_eq!(client.bursts.borrow().len(), 2);
+        assert!(!ready.interrupts_enabled());
+        assert_eq!(mlx90393.stop_burst(), Err(ErrorCode::OFF));
+    }
+}
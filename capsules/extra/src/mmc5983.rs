@@ -0,0 +1,497 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the MEMSIC MMC5983MA 3-axis magnetometer.
+//!
+//! <https://www.memsic.com/magnetometer-5>
+//!
+//! > The MMC5983MA is a monolithic complete 3-axis AMR magnetic sensor with
+//! > on-chip signal processing and integrated digital bus (I2C fast mode and
+//! > SPI bus interface). It can measure magnetic fields within the full scale
+//! > range of +/-8 Gauss (G), with 0.25mG/0.0625mG per LSB resolution at
+//! > 16bits/18bits operation mode.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The driver exposes the magnetic field through the [Magnetometer] HIL in
+//! nanotesla and the on-die temperature sensor through [TemperatureDriver].
+//!
+//! When the device is in one-shot mode (the default), every field reading
+//! is composed of two measurements: one taken after a SET pulse and one
+//! taken after a RESET pulse. The SET and RESET pulses magnetize the sensing
+//! elements in opposite directions, so the field component of the output
+//! flips sign while the bridge offset does not. Averaging the SET reading
+//! with the negated RESET reading cancels the offset. If the RESET pulse is
+//! disabled with [Mmc5983::configure], only the SET measurement is used.
+//!
+//! When a continuous output data rate is configured, the sensor performs
+//! its own periodic SET/RESET and the driver simply reads the latest output
+//! registers.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mmc5983 = components::mmc5983::Mmc5983Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mmc5983::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mmc5983_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{
+    Magnetometer, MagnetometerClient, TemperatureClient, TemperatureDriver,
+};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the MMC5983MA.
+pub const BASE_ADDR: u8 = 0x30;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 7;
+
+const REG_XOUT0: u8 = 0x00;
+const REG_TOUT: u8 = 0x07;
+const REG_CONTROL0: u8 = 0x09;
+const REG_CONTROL1: u8 = 0x0A;
+const REG_CONTROL2: u8 = 0x0B;
+
+const CONTROL0_TM_M: u8 = 1 << 0;
+const CONTROL0_TM_T: u8 = 1 << 1;
+const CONTROL0_SET: u8 = 1 << 3;
+const CONTROL0_RESET: u8 = 1 << 4;
+const CONTROL0_AUTO_SR_EN: u8 = 1 << 5;
+
+const CONTROL2_CMM_EN: u8 = 1 << 3;
+
+/// Output value with no field applied in 18-bit mode.
+const NULL_FIELD_OUTPUT: i32 = 1 << 17;
+
+/// Time needed for a temperature measurement to complete.
+const TEMPERATURE_MEASUREMENT_MS: u32 = 2;
+
+/// Measurement bandwidth. A lower bandwidth has less noise but takes
+/// longer to complete a measurement.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bandwidth {
+    Bw100Hz = 0,
+    Bw200Hz = 1,
+    Bw400Hz = 2,
+    Bw800Hz = 3,
+}
+
+impl Bandwidth {
+    /// Time needed for a magnetic measurement to complete, in microseconds.
+    fn measurement_time_us(&self) -> u32 {
+        match self {
+            Bandwidth::Bw100Hz => 8000,
+            Bandwidth::Bw200Hz => 4000,
+            Bandwidth::Bw400Hz => 2000,
+            Bandwidth::Bw800Hz => 500,
+        }
+    }
+}
+
+/// Output data rate of the continuous measurement mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputDataRate {
+    /// Continuous mode off, every reading triggers a measurement.
+    OneShot = 0,
+    Odr1Hz = 1,
+    Odr10Hz = 2,
+    Odr20Hz = 3,
+    Odr50Hz = 4,
+    Odr100Hz = 5,
+    Odr200Hz = 6,
+    Odr1000Hz = 7,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ConfigureBandwidth,
+    ConfigureDataRate,
+    ConfigureAutoSetReset,
+    SetPulse,
+    MeasureSet,
+    ReadSet,
+    ResetPulse,
+    MeasureReset,
+    ReadReset,
+    ReadContinuous,
+    MeasureTemperature,
+    ReadTemperature,
+}
+
+/// Extract the three 18-bit field outputs from the `Xout0` to `XYZout2`
+/// registers.
+fn decode_output(buf: &[u8]) -> (u32, u32, u32) {
+    let x = (buf[0] as u32) << 10 | (buf[1] as u32) << 2 | (buf[6] as u32 >> 6) & 0x3;
+    let y = (buf[2] as u32) << 10 | (buf[3] as u32) << 2 | (buf[6] as u32 >> 4) & 0x3;
+    let z = (buf[4] as u32) << 10 | (buf[5] as u32) << 2 | (buf[6] as u32 >> 2) & 0x3;
+    (x, y, z)
+}
+
+/// Convert an 18-bit output to a signed count centered on zero field.
+fn to_signed(raw: u32) -> i32 {
+    raw as i32 - NULL_FIELD_OUTPUT
+}
+
+/// Convert signed counts to nanotesla.
+///
+/// The sensitivity in 18-bit mode is 16384 counts/G and 1 G is 100000 nT, so
+/// one count is 100000 / 16384 = 3125 / 512 nT.
+fn counts_to_nt(counts: i32) -> i32 {
+    counts * 3125 / 512
+}
+
+/// Convert the `Tout` register to hundredths of degrees Celsius.
+///
+/// The output is -75 C at 0 with a resolution of about 0.8 C per count.
+fn temperature_to_centi_celsius(raw: u8) -> i32 {
+    raw as i32 * 80 - 7500
+}
+
+pub struct Mmc5983<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    bandwidth: Cell<Bandwidth>,
+    data_rate: Cell<OutputDataRate>,
+    use_reset: Cell<bool>,
+    set_reading: Cell<(i32, i32, i32)>,
+    magnetometer_client: OptionalCell<&'a dyn MagnetometerClient>,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Mmc5983<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        Mmc5983 {
+            i2c,
+            alarm,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            bandwidth: Cell::new(Bandwidth::Bw100Hz),
+            data_rate: Cell::new(OutputDataRate::OneShot),
+            use_reset: Cell::new(true),
+            set_reading: Cell::new((0, 0, 0)),
+            magnetometer_client: OptionalCell::empty(),
+            temperature_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Configure the measurement bandwidth and output data rate.
+    ///
+    /// `use_reset` selects whether one-shot readings use both a SET and a
+    /// RESET measurement to cancel the bridge offset, or only a SET
+    /// measurement.
+    pub fn configure(
+        &self,
+        bandwidth: Bandwidth,
+        data_rate: OutputDataRate,
+        use_reset: bool,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.bandwidth.set(bandwidth);
+        self.data_rate.set(data_rate);
+        self.use_reset.set(use_reset);
+        self.write_register(State::ConfigureBandwidth, REG_CONTROL1, bandwidth as u8)
+    }
+
+    fn write_register(&self, state: State, register: u8, value: u8) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            buffer[1] = value;
+            self.i2c.enable();
+            match self.i2c.write(buffer, 2) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((error, buffer)) => {
+                    self.buffer.replace(buffer);
+                    self.i2c.disable();
+                    self.state.set(State::Idle);
+                    Err(error.into())
+                }
+            }
+        })
+    }
+
+    fn read_registers(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            self.i2c.enable();
+            match self.i2c.write_read(buffer, 1, len) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((error, buffer)) => {
+                    self.buffer.replace(buffer);
+                    self.i2c.disable();
+                    self.state.set(State::Idle);
+                    Err(error.into())
+                }
+            }
+        })
+    }
+
+    fn start_field_reading(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.data_rate.get() == OutputDataRate::OneShot {
+            self.write_register(State::SetPulse, REG_CONTROL0, CONTROL0_SET)
+        } else {
+            self.read_registers(State::ReadContinuous, REG_XOUT0, BUFFER_SIZE)
+        }
+    }
+
+    fn start_temperature_reading(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.write_register(State::MeasureTemperature, REG_CONTROL0, CONTROL0_TM_T)
+    }
+
+    fn wait_us(&self, us: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(us));
+    }
+
+    fn finish_field(&self, value: Result<(i32, i32, i32), ErrorCode>) {
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        self.magnetometer_client
+            .map(|client| client.callback(value));
+    }
+
+    fn finish_temperature(&self, value: Result<i32, ErrorCode>) {
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        self.temperature_client.map(|client| client.callback(value));
+    }
+
+    fn signed_reading(buffer: &[u8]) -> (i32, i32, i32) {
+        let (x, y, z) = decode_output(buffer);
+        (to_signed(x), to_signed(y), to_signed(z))
+    }
+
+    fn report_field(&self, counts: (i32, i32, i32)) {
+        self.finish_field(Ok((
+            counts_to_nt(counts.0),
+            counts_to_nt(counts.1),
+            counts_to_nt(counts.2),
+        )));
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Mmc5983<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        if let Err(i2c_err) = status {
+            self.buffer.replace(buffer);
+            match state {
+                State::ConfigureBandwidth
+                | State::ConfigureDataRate
+                | State::ConfigureAutoSetReset => {
+                    self.i2c.disable();
+                    self.state.set(State::Idle);
+                }
+                State::MeasureTemperature | State::ReadTemperature => {
+                    self.finish_temperature(Err(i2c_err.into()))
+                }
+                _ => self.finish_field(Err(i2c_err.into())),
+            }
+            return;
+        }
+
+        match state {
+            State::ConfigureBandwidth => {
+                self.buffer.replace(buffer);
+                let data_rate = self.data_rate.get();
+                let control2 = if data_rate == OutputDataRate::OneShot {
+                    0
+                } else {
+                    CONTROL2_CMM_EN | data_rate as u8
+                };
+                if self
+                    .write_register(State::ConfigureDataRate, REG_CONTROL2, control2)
+                    .is_err()
+                {
+                    self.i2c.disable();
+                }
+            }
+            State::ConfigureDataRate => {
+                self.buffer.replace(buffer);
+                if self.data_rate.get() != OutputDataRate::OneShot {
+                    // Let the sensor handle SET/RESET in continuous mode.
+                    if self
+                        .write_register(
+                            State::ConfigureAutoSetReset,
+                            REG_CONTROL0,
+                            CONTROL0_AUTO_SR_EN,
+                        )
+                        .is_err()
+                    {
+                        self.i2c.disable();
+                    }
+                } else {
+                    self.i2c.disable();
+                    self.state.set(State::Idle);
+                }
+            }
+            State::ConfigureAutoSetReset | State::Idle => {
+                self.buffer.replace(buffer);
+                self.i2c.disable();
+                self.state.set(State::Idle);
+            }
+            State::SetPulse => {
+                self.buffer.replace(buffer);
+                if let Err(e) = self.write_register(State::MeasureSet, REG_CONTROL0, CONTROL0_TM_M)
+                {
+                    self.finish_field(Err(e));
+                }
+            }
+            State::MeasureSet | State::MeasureReset => {
+                self.buffer.replace(buffer);
+                self.wait_us(self.bandwidth.get().measurement_time_us());
+            }
+            State::ReadSet => {
+                let set = Self::signed_reading(buffer);
+                self.buffer.replace(buffer);
+                if self.use_reset.get() {
+                    self.set_reading.set(set);
+                    if let Err(e) =
+                        self.write_register(State::ResetPulse, REG_CONTROL0, CONTROL0_RESET)
+                    {
+                        self.finish_field(Err(e));
+                    }
+                } else {
+                    self.report_field(set);
+                }
+            }
+            State::ResetPulse => {
+                self.buffer.replace(buffer);
+                if let Err(e) =
+                    self.write_register(State::MeasureReset, REG_CONTROL0, CONTROL0_TM_M)
+                {
+                    self.finish_field(Err(e));
+                }
+            }
+            State::ReadReset => {
+                let reset = Self::signed_reading(buffer);
+                self.buffer.replace(buffer);
+                let set = self.set_reading.get();
+                // The field flips sign between SET and RESET while the offset
+                // does not, so half the difference is the offset-free field.
+                self.report_field((
+                    (set.0 - reset.0) / 2,
+                    (set.1 - reset.1) / 2,
+                    (set.2 - reset.2) / 2,
+                ));
+            }
+            State::ReadContinuous => {
+                let reading = Self::signed_reading(buffer);
+                self.buffer.replace(buffer);
+                self.report_field(reading);
+            }
+            State::MeasureTemperature => {
+                self.buffer.replace(buffer);
+                self.wait_us(TEMPERATURE_MEASUREMENT_MS * 1000);
+            }
+            State::ReadTemperature => {
+                let temperature = temperature_to_centi_celsius(buffer[0]);
+                self.buffer.replace(buffer);
+                self.finish_temperature(Ok(temperature));
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> time::AlarmClient for Mmc5983<'a, A, I> {
+    fn alarm(&self) {
+        let result = match self.state.get() {
+            State::MeasureSet => self.read_registers(State::ReadSet, REG_XOUT0, BUFFER_SIZE),
+            State::MeasureReset => self.read_registers(State::ReadReset, REG_XOUT0, BUFFER_SIZE),
+            State::MeasureTemperature => {
+                if let Err(e) = self.read_registers(State::ReadTemperature, REG_TOUT, 1) {
+                    self.finish_temperature(Err(e));
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Err(e) = result {
+            self.finish_field(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Magnetometer<'a> for Mmc5983<'a, A, I> {
+    fn set_client(&self, client: &'a dyn MagnetometerClient) {
+        self.magnetometer_client.set(client);
+    }
+
+    fn read_field_nt(&self) -> Result<(), ErrorCode> {
+        self.start_field_reading()
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> TemperatureDriver<'a> for Mmc5983<'a, A, I> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.start_temperature_reading()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_18_bit_output() {
+        // X at null field, Y at full positive scale, Z at full negative scale.
+        let registers = [0x80, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0b0011_0000];
+        let (x, y, z) = decode_output(&registers);
+        assert_eq!(x, 0x20000);
+        assert_eq!(y, 0x3FFFF);
+        assert_eq!(z, 0);
+
+        assert_eq!(to_signed(x), 0);
+        assert_eq!(to_signed(y), 131071);
+        assert_eq!(to_signed(z), -131072);
+    }
+
+    #[test]
+    fn counts_to_nanotesla() {
+        // 16384 counts is 1 G, which is 100 uT.
+        assert_eq!(counts_to_nt(16384), 100_000);
+        assert_eq!(counts_to_nt(-16384), -100_000);
+        // Full scale is 8 G.
+        assert_eq!(counts_to_nt(-131072), -800_000);
+    }
+
+    #[test]
+    fn temperature() {
+        assert_eq!(temperature_to_centi_celsius(0), -7500);
+        assert_eq!(temperature_to_centi_celsius(125), 2500);
+    }
+}
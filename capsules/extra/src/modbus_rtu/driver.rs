@@ -0,0 +1,220 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Syscall driver for the Modbus RTU master.
+//!
+//! Each process can have one request in progress. Requests of different
+//! processes are queued and sent one at a time.
+//!
+//! Commands
+//! --------
+//!
+//! - 0: Driver existence check.
+//! - 1: Send a request to slave `arg1 & 0xFF` with function code
+//!   `(arg1 >> 8) & 0xFF` for address `arg1 >> 16`. `arg2` is the number of
+//!   coils, inputs or registers to read, or the value to write. Function
+//!   codes 1 to 4 read coils, discrete inputs, holding registers and input
+//!   registers, and 5 and 6 write a single coil or register. Writes to slave
+//!   0 are broadcast.
+//!
+//! Upcalls
+//! -------
+//!
+//! - 0: A request finished: `(statuscode, length, exception)`. The data read
+//!   is in the read-write allow buffer, truncated to its size, and `length`
+//!   is the length of the data of the response: coils and inputs are eight
+//!   to a byte, the first in the lowest bit, and registers are big-endian
+//!   pairs of bytes. If the slave answered with an exception, the status is
+//!   `FAIL` and `exception` is its code. A slave that does not answer gives
+//!   `NOACK`, and a response that is corrupted or does not match gives
+//!   `FAIL` with an exception of 0.
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::Alarm;
+use kernel::hil::uart;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use super::{check_request, FunctionCode, ModbusClient, ModbusError, ModbusMaster};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ModbusRtu as usize;
+
+mod up_calls {
+    pub const UPCALL_REQUEST_DONE: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+mod rw_allow {
+    pub const RESPONSE: usize = 0;
+    pub const COUNT: u8 = 1;
+}
+
+/// A request: the slave, the function, the address and the count or value.
+#[derive(Clone, Copy)]
+struct Request {
+    slave: u8,
+    function: FunctionCode,
+    address: u16,
+    value: u16,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// A request waiting for the master.
+    pending: Option<Request>,
+}
+
+pub struct ModbusDriver<'a, U: uart::Uart<'a>, A: Alarm<'a>> {
+    master: &'a ModbusMaster<'a, U, A>,
+    apps: Grant<
+        App,
+        UpcallCount<{ up_calls::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process whose request the master is sending.
+    current: OptionalCell<ProcessId>,
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> ModbusDriver<'a, U, A> {
+    pub fn new(
+        master: &'a ModbusMaster<'a, U, A>,
+        apps: Grant<
+            App,
+            UpcallCount<{ up_calls::COUNT }>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> ModbusDriver<'a, U, A> {
+        ModbusDriver {
+            master,
+            apps,
+            current: OptionalCell::empty(),
+        }
+    }
+
+    fn request_done(&self, processid: ProcessId, result: Result<&[u8], ModbusError>) {
+        let _ = self.apps.enter(processid, |_, kernel_data| {
+            let (status, length, exception) = match result {
+                Ok(data) => {
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::RESPONSE)
+                        .and_then(|response| {
+                            response.mut_enter(|buffer| {
+                                let len = core::cmp::min(buffer.len(), data.len());
+                                buffer[..len].copy_from_slice(&data[..len]);
+                            })
+                        });
+                    (Ok(()), data.len(), 0)
+                }
+                Err(ModbusError::Exception(code)) => (Err(ErrorCode::FAIL), 0, code as usize),
+                Err(ModbusError::Timeout) => (Err(ErrorCode::NOACK), 0, 0),
+                Err(ModbusError::InvalidResponse) => (Err(ErrorCode::FAIL), 0, 0),
+                Err(ModbusError::Uart(error)) => (Err(error), 0, 0),
+            };
+            kernel_data
+                .schedule_upcall(
+                    up_calls::UPCALL_REQUEST_DONE,
+                    (into_statuscode(status), length, exception),
+                )
+                .ok();
+        });
+    }
+
+    /// Send the next queued request if the master is free.
+    fn send_next(&self) {
+        if self.current.is_some() {
+            return;
+        }
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let request = match app.enter(|app, _| app.pending.take()) {
+                Some(request) => request,
+                None => continue,
+            };
+            match self.master.request(
+                request.slave,
+                request.function,
+                request.address,
+                request.value,
+            ) {
+                Ok(()) => {
+                    self.current.set(processid);
+                    return;
+                }
+                Err(error) => self.request_done(processid, Err(ModbusError::Uart(error))),
+            }
+        }
+    }
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> SyscallDriver for ModbusDriver<'a, U, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Send a request
+            1 => {
+                let function = match FunctionCode::from_u8((arg1 >> 8) as u8) {
+                    Some(function) => function,
+                    None => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+                };
+                let slave = arg1 as u8;
+                let value = match check_request(slave, function, arg2 as u16) {
+                    Ok(value) => value,
+                    Err(error) => return CommandReturn::failure(error),
+                };
+                let request = Request {
+                    slave,
+                    function,
+                    address: (arg1 >> 16) as u16,
+                    value,
+                };
+                let queued = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if app.pending.is_some() || self.current.contains(&processid) {
+                            Err(ErrorCode::BUSY)
+                        } else {
+                            app.pending = Some(request);
+                            Ok(())
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match queued {
+                    Ok(()) => {
+                        self.send_next();
+                        CommandReturn::success()
+                    }
+                    Err(error) => CommandReturn::failure(error),
+                }
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> ModbusClient for ModbusDriver<'a, U, A> {
+    fn request_done(&self, result: Result<&[u8], ModbusError>) {
+        if let Some(processid) = self.current.take() {
+            self.request_done(processid, result);
+        }
+        self.send_next();
+    }
+}
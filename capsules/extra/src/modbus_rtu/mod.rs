@@ -0,0 +1,774 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Modbus RTU master over a UART.
+//!
+//! <https://modbus.org/docs/Modbus_over_serial_line_V1_02.pdf>
+//!
+//! Modbus RTU frames are a slave address, a function code, the data and a
+//! CRC-16/Modbus, the low byte first. Frames are not delimited by any
+//! character: a frame ends when the line is silent for 3.5 character
+//! times. A character is 11 bits, with the start bit, a parity bit or a
+//! second stop bit and the stop bit, so the silence is 38.5 bit times, or
+//! 1750 us above 19200 baud where the standard fixes it.
+//!
+//! [`ModbusMaster`] sends one request at a time and then receives the
+//! response a byte at a time. Every byte restarts the alarm for the
+//! silence, from the time it was received, and the alarm is rounded up by a
+//! tick so it never ends a frame early. When the alarm fires, the frame is
+//! only ended if the silence has passed since the last byte, so a byte that
+//! arrived as the alarm fired does not split the frame. A frame with a byte
+//! received with a parity or framing error is discarded, as is one with a
+//! wrong CRC or that does not answer the request.
+//!
+//! The supported functions read coils (FC01), discrete inputs (FC02),
+//! holding registers (FC03) and input registers (FC04), and write a single
+//! coil (FC05) or register (FC06). Writes can be broadcast to address 0,
+//! which slaves do not answer, so the master waits for the turnaround delay
+//! instead.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let modbus = components::modbus_rtu::ModbusMasterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::modbus_rtu::driver::DRIVER_NUM,
+//!     &peripherals.uarte1,
+//!     mux_alarm,
+//!     19200,
+//!     kernel::hil::uart::Parity::Even,
+//! )
+//! .finalize(components::modbus_master_component_static!(
+//!     nrf52840::uart::Uarte<'static>,
+//!     nrf52840::rtc::Rtc<'static>,
+//! ));
+//! ```
+
+pub mod driver;
+
+use core::cell::Cell;
+use core::ops::Range;
+
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The length of every supported request.
+pub const REQUEST_LEN: usize = 8;
+/// The longest frame.
+pub const MAX_FRAME_LEN: usize = 256;
+
+/// The address writes are broadcast to.
+pub const BROADCAST_ADDRESS: u8 = 0;
+/// The highest slave address.
+pub const MAX_SLAVE_ADDRESS: u8 = 247;
+/// The most coils or discrete inputs a request can read.
+pub const MAX_BITS: u16 = 2000;
+/// The most registers a request can read.
+pub const MAX_REGISTERS: u16 = 125;
+
+/// The value of a coil that is written on.
+const COIL_ON: u16 = 0xFF00;
+/// Set in the function code of an exception response.
+const EXCEPTION: u8 = 0x80;
+/// Bits of a character, and the characters of silence ending a frame.
+const CHARACTER_BITS: u32 = 11;
+const FRAME_SILENCE_CHARACTERS_X10: u32 = 35;
+/// The silence above 19200 baud.
+const FAST_BAUD_RATE: u32 = 19200;
+const FAST_FRAME_SILENCE_US: u32 = 1750;
+
+const DEFAULT_RESPONSE_TIMEOUT_MS: u32 = 1000;
+const TURNAROUND_DELAY_MS: u32 = 100;
+
+/// The supported function codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionCode {
+    ReadCoils = 0x01,
+    ReadDiscreteInputs = 0x02,
+    ReadHoldingRegisters = 0x03,
+    ReadInputRegisters = 0x04,
+    WriteSingleCoil = 0x05,
+    WriteSingleRegister = 0x06,
+}
+
+impl FunctionCode {
+    pub fn from_u8(code: u8) -> Option<FunctionCode> {
+        match code {
+            0x01 => Some(FunctionCode::ReadCoils),
+            0x02 => Some(FunctionCode::ReadDiscreteInputs),
+            0x03 => Some(FunctionCode::ReadHoldingRegisters),
+            0x04 => Some(FunctionCode::ReadInputRegisters),
+            0x05 => Some(FunctionCode::WriteSingleCoil),
+            0x06 => Some(FunctionCode::WriteSingleRegister),
+            _ => None,
+        }
+    }
+
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            FunctionCode::WriteSingleCoil | FunctionCode::WriteSingleRegister
+        )
+    }
+
+    /// The length of the data of the response to a read of `count` items.
+    fn response_data_len(self, count: u16) -> usize {
+        match self {
+            FunctionCode::ReadCoils | FunctionCode::ReadDiscreteInputs => (count as usize + 7) / 8,
+            _ => count as usize * 2,
+        }
+    }
+}
+
+/// Why a request failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModbusError {
+    /// The slave answered with this exception code.
+    Exception(u8),
+    /// The slave did not answer within the response timeout.
+    Timeout,
+    /// The response had a wrong CRC, a character error, or did not answer
+    /// the request.
+    InvalidResponse,
+    /// The UART failed.
+    Uart(ErrorCode),
+}
+
+pub trait ModbusClient {
+    /// A request completed. Reads give the data of the response: coils and
+    /// inputs eight to a byte with the first in the lowest bit, or
+    /// registers as big-endian pairs of bytes. Writes give no data.
+    fn request_done(&self, result: Result<&[u8], ModbusError>);
+}
+
+/// Check that a request can be sent, and return the value to send: the
+/// count of items to read, or the value to write.
+pub fn check_request(slave: u8, function: FunctionCode, value: u16) -> Result<u16, ErrorCode> {
+    if slave > MAX_SLAVE_ADDRESS || (slave == BROADCAST_ADDRESS && !function.is_write()) {
+        return Err(ErrorCode::INVAL);
+    }
+    match function {
+        FunctionCode::ReadCoils | FunctionCode::ReadDiscreteInputs
+            if !(1..=MAX_BITS).contains(&value) =>
+        {
+            Err(ErrorCode::INVAL)
+        }
+        FunctionCode::ReadHoldingRegisters | FunctionCode::ReadInputRegisters
+            if !(1..=MAX_REGISTERS).contains(&value) =>
+        {
+            Err(ErrorCode::INVAL)
+        }
+        FunctionCode::WriteSingleCoil if value != 0 => Ok(COIL_ON),
+        _ => Ok(value),
+    }
+}
+
+/// The CRC-16/Modbus of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// The silence that ends a frame, in us, rounded up.
+fn frame_silence_us(baud_rate: u32) -> u32 {
+    if baud_rate > FAST_BAUD_RATE {
+        FAST_FRAME_SILENCE_US
+    } else {
+        let bits_x10 = (CHARACTER_BITS * FRAME_SILENCE_CHARACTERS_X10) as u64;
+        ((bits_x10 * 100_000 + baud_rate as u64 - 1) / baud_rate as u64) as u32
+    }
+}
+
+/// Check that `frame` answers `request`, and return where its data is.
+fn parse_response(
+    request: &[u8; REQUEST_LEN],
+    frame: &[u8],
+    count: u16,
+) -> Result<Range<usize>, ModbusError> {
+    let len = frame.len();
+    if len < 5 {
+        return Err(ModbusError::InvalidResponse);
+    }
+    if crc16(&frame[..len - 2]) != u16::from_le_bytes([frame[len - 2], frame[len - 1]]) {
+        return Err(ModbusError::InvalidResponse);
+    }
+    if frame[0] != request[0] {
+        return Err(ModbusError::InvalidResponse);
+    }
+    if frame[1] == request[1] | EXCEPTION && len == 5 {
+        return Err(ModbusError::Exception(frame[2]));
+    }
+    if frame[1] != request[1] {
+        return Err(ModbusError::InvalidResponse);
+    }
+    match FunctionCode::from_u8(request[1]) {
+        // Writes echo the request.
+        Some(function) if function.is_write() => {
+            if len == REQUEST_LEN && frame[..REQUEST_LEN - 2] == request[..REQUEST_LEN - 2] {
+                Ok(0..0)
+            } else {
+                Err(ModbusError::InvalidResponse)
+            }
+        }
+        Some(function) => {
+            let data_len = function.response_data_len(count);
+            if frame[2] as usize == data_len && len == 5 + data_len {
+                Ok(3..3 + data_len)
+            } else {
+                Err(ModbusError::InvalidResponse)
+            }
+        }
+        None => Err(ModbusError::InvalidResponse),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Transmitting,
+    /// Waiting for the first byte of the response.
+    Waiting,
+    /// Receiving the response, with this many bytes so far.
+    Receiving(usize),
+    /// Waiting for slaves to process a broadcast.
+    Turnaround,
+}
+
+pub struct ModbusMaster<'a, U: uart::Uart<'a>, A: Alarm<'a>> {
+    uart: &'a U,
+    alarm: &'a A,
+    baud_rate: u32,
+    parity: uart::Parity,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    /// The byte being received.
+    rx_byte: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// The request in progress, and the items it reads.
+    request: Cell<[u8; REQUEST_LEN]>,
+    count: Cell<u16>,
+    /// When the last byte of the response was received.
+    last_byte_at: Cell<A::Ticks>,
+    /// Whether a byte of the response had a character error.
+    character_error: Cell<bool>,
+    response_timeout_ms: Cell<u32>,
+    client: OptionalCell<&'a dyn ModbusClient>,
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> ModbusMaster<'a, U, A> {
+    pub fn new(
+        uart: &'a U,
+        alarm: &'a A,
+        baud_rate: u32,
+        parity: uart::Parity,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        rx_byte: &'static mut [u8],
+    ) -> ModbusMaster<'a, U, A> {
+        ModbusMaster {
+            uart,
+            alarm,
+            baud_rate,
+            parity,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_byte: TakeCell::new(rx_byte),
+            state: Cell::new(State::Idle),
+            request: Cell::new([0; REQUEST_LEN]),
+            count: Cell::new(0),
+            last_byte_at: Cell::new(alarm.now()),
+            character_error: Cell::new(false),
+            response_timeout_ms: Cell::new(DEFAULT_RESPONSE_TIMEOUT_MS),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ModbusClient) {
+        self.client.set(client);
+    }
+
+    /// Configure the UART: 8 data bits with the parity, and two stop bits
+    /// without parity, as the standard requires.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let stop_bits = match self.parity {
+            uart::Parity::None => uart::StopBits::Two,
+            _ => uart::StopBits::One,
+        };
+        self.uart.configure(uart::Parameters {
+            baud_rate: self.baud_rate,
+            width: uart::Width::Eight,
+            parity: self.parity,
+            stop_bits,
+            hw_flow_control: false,
+        })
+    }
+
+    /// How long to wait for the start of a response.
+    pub fn set_response_timeout(&self, ms: u32) {
+        self.response_timeout_ms.set(ms);
+    }
+
+    /// Send a request to `slave`, reading `value` items from `address` or
+    /// writing `value` to it. A coil is written on if `value` is not zero.
+    pub fn request(
+        &self,
+        slave: u8,
+        function: FunctionCode,
+        address: u16,
+        value: u16,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let value = check_request(slave, function, value)?;
+
+        let mut request = [0; REQUEST_LEN];
+        request[0] = slave;
+        request[1] = function as u8;
+        request[2..4].copy_from_slice(&address.to_be_bytes());
+        request[4..6].copy_from_slice(&value.to_be_bytes());
+        let crc = crc16(&request[..REQUEST_LEN - 2]);
+        request[REQUEST_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+
+        let buffer = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..REQUEST_LEN].copy_from_slice(&request);
+        self.request.set(request);
+        self.count.set(value);
+        self.state.set(State::Transmitting);
+        self.uart
+            .transmit_buffer(buffer, REQUEST_LEN)
+            .map_err(|(e, buffer)| {
+                self.tx_buffer.replace(buffer);
+                self.state.set(State::Idle);
+                e
+            })
+    }
+
+    fn frame_silence(&self) -> A::Ticks {
+        self.alarm
+            .ticks_from_us(frame_silence_us(self.baud_rate))
+            .wrapping_add(A::Ticks::from(1))
+    }
+
+    /// Receive the next byte of the response.
+    fn receive_byte(&self) -> Result<(), ErrorCode> {
+        let byte = self.rx_byte.take().ok_or(ErrorCode::BUSY)?;
+        self.uart.receive_buffer(byte, 1).map_err(|(e, byte)| {
+            self.rx_byte.replace(byte);
+            e
+        })
+    }
+
+    /// End the frame of `len` bytes, and answer the request with it.
+    fn end_frame(&self, len: usize) {
+        let _ = self.uart.receive_abort();
+        self.state.set(State::Idle);
+        let request = self.request.get();
+        let character_error = self.character_error.get();
+        self.rx_buffer.map(|buffer| {
+            let frame = &buffer[..len.min(buffer.len())];
+            let result = if character_error || len > buffer.len() {
+                Err(ModbusError::InvalidResponse)
+            } else {
+                parse_response(&request, frame, self.count.get())
+            };
+            self.client
+                .map(|client| client.request_done(result.map(|data| &frame[data])));
+        });
+    }
+
+    fn fail(&self, error: ModbusError) {
+        let _ = self.alarm.disarm();
+        if matches!(self.state.get(), State::Waiting | State::Receiving(_)) {
+            let _ = self.uart.receive_abort();
+        }
+        self.state.set(State::Idle);
+        self.client.map(|client| client.request_done(Err(error)));
+    }
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> uart::TransmitClient for ModbusMaster<'a, U, A> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(tx_buffer);
+        if self.state.get() != State::Transmitting {
+            return;
+        }
+        if let Err(e) = rval {
+            self.fail(ModbusError::Uart(e));
+            return;
+        }
+        if self.request.get()[0] == BROADCAST_ADDRESS {
+            self.state.set(State::Turnaround);
+            self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(TURNAROUND_DELAY_MS),
+            );
+            return;
+        }
+        self.character_error.set(false);
+        self.state.set(State::Waiting);
+        match self.receive_byte() {
+            Ok(()) => self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(self.response_timeout_ms.get()),
+            ),
+            Err(e) => self.fail(ModbusError::Uart(e)),
+        }
+    }
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> uart::ReceiveClient for ModbusMaster<'a, U, A> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        let byte = rx_buffer[0];
+        self.rx_byte.replace(rx_buffer);
+        let len = match self.state.get() {
+            State::Waiting => 0,
+            State::Receiving(len) => len,
+            _ => return,
+        };
+        match rval {
+            // Aborted when the frame ended.
+            Err(ErrorCode::CANCEL) if rx_len == 0 => return,
+            Ok(()) if rx_len == 1 => {
+                self.rx_buffer.map(|buffer| {
+                    if let Some(slot) = buffer.get_mut(len) {
+                        *slot = byte;
+                    }
+                });
+            }
+            // A character with an error still delays the end of the frame.
+            _ => {
+                if error == uart::Error::None {
+                    self.fail(ModbusError::Uart(rval.err().unwrap_or(ErrorCode::FAIL)));
+                    return;
+                }
+                self.character_error.set(true);
+            }
+        }
+        let now = self.alarm.now();
+        self.last_byte_at.set(now);
+        self.state.set(State::Receiving(len + 1));
+        self.alarm.set_alarm(now, self.frame_silence());
+        if let Err(e) = self.receive_byte() {
+            self.fail(ModbusError::Uart(e));
+        }
+    }
+}
+
+impl<'a, U: uart::Uart<'a>, A: Alarm<'a>> AlarmClient for ModbusMaster<'a, U, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Waiting => self.fail(ModbusError::Timeout),
+            State::Receiving(len) => {
+                let silence = self.frame_silence();
+                let last_byte_at = self.last_byte_at.get();
+                if self.alarm.now().wrapping_sub(last_byte_at) < silence {
+                    // A byte arrived since the alarm was set.
+                    self.alarm.set_alarm(last_byte_at, silence);
+                } else {
+                    self.end_frame(len);
+                }
+            }
+            State::Turnaround => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.request_done(Ok(&[])));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use core::cell::RefCell;
+    use kernel::hil::time::{Freq1MHz, Time};
+    use kernel::hil::uart::{Configure, Receive, ReceiveClient, Transmit, TransmitClient};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct MockUart {
+        transmitted: RefCell<Vec<u8>>,
+        tx_buffer: TakeCell<'static, [u8]>,
+        rx_buffer: TakeCell<'static, [u8]>,
+        client: OptionalCell<&'static dyn ReceiveClient>,
+    }
+
+    impl Configure for MockUart {
+        fn configure(&self, _params: uart::Parameters) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+    }
+
+    impl<'a> Transmit<'a> for MockUart {
+        fn set_transmit_client(&self, _client: &'a dyn TransmitClient) {}
+        fn transmit_buffer(
+            &self,
+            tx_buffer: &'static mut [u8],
+            tx_len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            *self.transmitted.borrow_mut() = tx_buffer[..tx_len].to_vec();
+            self.tx_buffer.replace(tx_buffer);
+            Ok(())
+        }
+        fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+        fn transmit_abort(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+    }
+
+    impl Receive<'static> for MockUart {
+        fn set_receive_client(&self, client: &'static dyn ReceiveClient) {
+            self.client.set(client);
+        }
+        fn receive_buffer(
+            &self,
+            rx_buffer: &'static mut [u8],
+            _rx_len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.rx_buffer.replace(rx_buffer);
+            Ok(())
+        }
+        fn receive_word(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+        fn receive_abort(&self) -> Result<(), ErrorCode> {
+            if let Some(buffer) = self.rx_buffer.take() {
+                self.client.map(|client| {
+                    client.received_buffer(buffer, 0, Err(ErrorCode::CANCEL), uart::Error::Aborted)
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        result: RefCell<Option<Result<Vec<u8>, ModbusError>>>,
+    }
+
+    impl ModbusClient for MockClient {
+        fn request_done(&self, result: Result<&[u8], ModbusError>) {
+            *self.result.borrow_mut() = Some(result.map(|data| data.to_vec()));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestMaster = ModbusMaster<'static, MockUart, TestAlarm>;
+
+    struct Test {
+        uart: &'static MockUart,
+        alarm: &'static TestAlarm,
+        client: &'static MockClient,
+        master: &'static TestMaster,
+    }
+
+    fn setup() -> Test {
+        let uart = Box::leak(Box::new(MockUart {
+            transmitted: RefCell::new(Vec::new()),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        }));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let master: &TestMaster = Box::leak(Box::new(ModbusMaster::new(
+            uart,
+            alarm,
+            9600,
+            uart::Parity::Even,
+            Box::leak(Box::new([0; REQUEST_LEN])),
+            Box::leak(Box::new([0; MAX_FRAME_LEN])),
+            Box::leak(Box::new([0; 1])),
+        )));
+        master.set_client(client);
+        alarm.set_alarm_client(master);
+        uart.set_receive_client(master);
+        Test {
+            uart,
+            alarm,
+            client,
+            master,
+        }
+    }
+
+    impl Test {
+        /// Finish transmitting the request.
+        fn transmitted(&self) {
+            let buffer = self.uart.tx_buffer.take().unwrap();
+            self.master.transmitted_buffer(buffer, REQUEST_LEN, Ok(()));
+        }
+
+        /// Receive `bytes` `gap_us` apart.
+        fn receive(&self, bytes: &[u8], gap_us: u32) {
+            for byte in bytes {
+                self.alarm
+                    .set_now(self.alarm.now().into_u32().wrapping_add(gap_us));
+                let buffer = self.uart.rx_buffer.take().unwrap();
+                buffer[0] = *byte;
+                self.master
+                    .received_buffer(buffer, 1, Ok(()), uart::Error::None);
+            }
+        }
+
+        /// When the alarm expires, if it is armed.
+        fn expiry(&self) -> Option<u32> {
+            self.alarm
+                .is_armed()
+                .then(|| self.alarm.get_alarm().into_u32())
+        }
+    }
+
+    #[test]
+    fn computes_crc_and_silence() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]), 0x0A84);
+        assert_eq!(frame_silence_us(9600), 4011);
+        assert_eq!(frame_silence_us(19200), 2006);
+        assert_eq!(frame_silence_us(115200), 1750);
+    }
+
+    #[test]
+    fn reads_holding_registers() {
+        let test = setup();
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::ReadHoldingRegisters, 0x006B, 2),
+            Ok(())
+        );
+        assert_eq!(
+            *test.uart.transmitted.borrow(),
+            [0x11, 0x03, 0x00, 0x6B, 0x00, 0x02, 0xB7, 0x47]
+        );
+        test.transmitted();
+        assert_eq!(test.expiry(), Some(1_000_000));
+
+        // A gap shorter than the silence does not end the frame, even when
+        // the alarm set for the previous byte fires late.
+        test.receive(&[0x11, 0x03, 0x04, 0x00, 0x2A], 1000);
+        let stale = test.expiry().unwrap();
+        test.receive(&[0x01], 3000);
+        test.alarm.set_now(stale);
+        test.master.alarm();
+        assert_eq!(test.expiry(), Some(stale + 3000));
+        assert!(test.client.result.borrow().is_none());
+
+        test.receive(&[0x2C, 0xCA, 0x77], 1000);
+        test.alarm.fire();
+        assert_eq!(
+            test.client.result.take(),
+            Some(Ok([0x00, 0x2A, 0x01, 0x2C].to_vec()))
+        );
+    }
+
+    #[test]
+    fn reports_exceptions_and_bad_frames() {
+        let test = setup();
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::ReadHoldingRegisters, 0x006B, 2),
+            Ok(())
+        );
+        test.transmitted();
+        test.receive(&[0x11, 0x83, 0x02, 0xC1, 0x34], 1000);
+        test.alarm.fire();
+        assert_eq!(
+            test.client.result.take(),
+            Some(Err(ModbusError::Exception(2)))
+        );
+
+        // A wrong CRC.
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::ReadHoldingRegisters, 0x006B, 2),
+            Ok(())
+        );
+        test.transmitted();
+        test.receive(&[0x11, 0x83, 0x02, 0xC1, 0x35], 1000);
+        test.alarm.fire();
+        assert_eq!(
+            test.client.result.take(),
+            Some(Err(ModbusError::InvalidResponse))
+        );
+
+        // No answer.
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::ReadCoils, 0x0013, 10),
+            Ok(())
+        );
+        test.transmitted();
+        test.alarm.fire();
+        assert_eq!(test.client.result.take(), Some(Err(ModbusError::Timeout)));
+    }
+
+    #[test]
+    fn writes_and_broadcasts() {
+        let test = setup();
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::ReadInputRegisters, 0, 126),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            test.master.request(0, FunctionCode::ReadCoils, 0, 1),
+            Err(ErrorCode::INVAL)
+        );
+
+        // The slave echoes the write.
+        assert_eq!(
+            test.master
+                .request(0x11, FunctionCode::WriteSingleRegister, 0x0001, 0x0003),
+            Ok(())
+        );
+        test.transmitted();
+        test.receive(&[0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9A, 0x9B], 1000);
+        test.alarm.fire();
+        assert_eq!(test.client.result.take(), Some(Ok(Vec::new())));
+
+        // Nobody answers a broadcast.
+        assert_eq!(
+            test.master
+                .request(BROADCAST_ADDRESS, FunctionCode::WriteSingleCoil, 0x00AC, 1),
+            Ok(())
+        );
+        assert_eq!(
+            &test.uart.transmitted.borrow()[..6],
+            [0, 5, 0, 0xAC, 0xFF, 0]
+        );
+        test.transmitted();
+        assert!(test.uart.rx_buffer.is_none());
+        test.alarm.fire();
+        assert_eq!(test.client.result.take(), Some(Ok(Vec::new())));
+    }
+}
@@ -0,0 +1,155 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with motion start and stop events from a motion
+//! detector.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when motion is detected.
+//! * `1`: called when motion stops.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: start receiving motion events
+//! * `2`: stop receiving motion events
+//!
+//! The motion detector is enabled while at least one process is receiving
+//! events.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::MotionDetector` trait.
+//!
+//! ```rust
+//! let motion = components::motion_detector::MotionDetectorComponent::new(
+//!     board_kernel,
+//!     capsules_extra::motion_detector::DRIVER_NUM,
+//!     pir,
+//! )
+//! .finalize(components::motion_detector_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::MotionDetector as usize;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const MOTION_DETECTED: usize = 0;
+    pub const MOTION_STOPPED: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    listening: bool,
+}
+
+pub struct MotionDetectorDriver<'a> {
+    driver: &'a dyn hil::sensors::MotionDetector<'a>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    listeners: Cell<usize>,
+}
+
+impl<'a> MotionDetectorDriver<'a> {
+    pub fn new(
+        driver: &'a dyn hil::sensors::MotionDetector<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> MotionDetectorDriver<'a> {
+        MotionDetectorDriver {
+            driver,
+            apps: grant,
+            listeners: Cell::new(0),
+        }
+    }
+
+    fn set_listening(&self, processid: ProcessId, listening: bool) -> Result<(), ErrorCode> {
+        let changed = self
+            .apps
+            .enter(processid, |app, _| {
+                let changed = app.listening != listening;
+                app.listening = listening;
+                changed
+            })
+            .map_err(ErrorCode::from)?;
+        if !changed {
+            return Ok(());
+        }
+
+        if listening {
+            if self.listeners.get() == 0 {
+                if let Err(error) = self.driver.enable() {
+                    let _ = self.apps.enter(processid, |app, _| app.listening = false);
+                    return Err(error);
+                }
+            }
+            self.listeners.set(self.listeners.get() + 1);
+        } else {
+            self.listeners.set(self.listeners.get() - 1);
+            if self.listeners.get() == 0 {
+                let _ = self.driver.disable();
+            }
+        }
+        Ok(())
+    }
+
+    fn notify(&self, upcall_id: usize) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, upcalls| {
+                if app.listening {
+                    upcalls.schedule_upcall(upcall_id, (0, 0, 0)).ok();
+                }
+            });
+        }
+    }
+}
+
+impl hil::sensors::MotionDetectorClient for MotionDetectorDriver<'_> {
+    fn motion_detected(&self) {
+        self.notify(upcall::MOTION_DETECTED);
+    }
+
+    fn motion_stopped(&self) {
+        self.notify(upcall::MOTION_STOPPED);
+    }
+}
+
+impl SyscallDriver for MotionDetectorDriver<'_> {
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Start receiving motion events.
+            1 => self.set_listening(processid, true).into(),
+
+            // Stop receiving motion events.
+            2 => self.set_listening(processid, false).into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,364 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the NXP (Freescale) MPR121 12-channel capacitive touch sensor.
+//!
+//! <https://www.nxp.com/docs/en/data-sheet/MPR121.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! [Mpr121::start] resets the sensor and configures it while it is in stop
+//! mode: the baseline filters, the touch and release thresholds of every
+//! electrode, the debounce, and the auto-configuration of the charge
+//! current and time. It then enables the 12 electrodes, which puts the
+//! sensor in run mode.
+//!
+//! The sensor pulls its IRQ line low when an electrode is touched or
+//! released. The driver then reads the 12-bit touch status, and calls the
+//! client once for every electrode whose state changed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mpr121 = components::mpr121::Mpr121Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mpr121::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[MPR121_IRQ],
+//!     capsules_extra::mpr121::DEFAULT_TOUCH_THRESHOLD,
+//!     capsules_extra::mpr121::DEFAULT_RELEASE_THRESHOLD,
+//! )
+//! .finalize(components::mpr121_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! mpr121.set_client(keypad);
+//! mpr121.start().unwrap();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the MPR121, with ADDR connected to ground.
+pub const BASE_ADDR: u8 = 0x5A;
+
+/// Number of electrodes.
+pub const NUM_ELECTRODES: usize = 12;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 1 + 2 * NUM_ELECTRODES;
+
+/// Touch threshold recommended by the datasheet.
+pub const DEFAULT_TOUCH_THRESHOLD: u8 = 12;
+/// Release threshold recommended by the datasheet.
+pub const DEFAULT_RELEASE_THRESHOLD: u8 = 6;
+
+const REG_TOUCH_STATUS: u8 = 0x00;
+const REG_MHDR: u8 = 0x2B;
+const REG_E0TTH: u8 = 0x41;
+const REG_DEBOUNCE: u8 = 0x5B;
+const REG_ECR: u8 = 0x5E;
+const REG_AUTOCONFIG0: u8 = 0x7B;
+const REG_SOFT_RESET: u8 = 0x80;
+
+const SOFT_RESET: u8 = 0x63;
+
+/// Baseline filter settings for rising, falling and touched data, from
+/// `MHDR` to `FDLT`, as recommended by the datasheet.
+const BASELINE_FILTERS: [u8; 11] = [
+    0x01, 0x01, 0x0E, 0x00, // rising
+    0x01, 0x05, 0x01, 0x00, // falling
+    0x00, 0x00, 0x00, // touched
+];
+
+/// `CONFIG1` and `CONFIG2` after the debounce register: 16 uA charge
+/// current, 0.5 us charge time and a 1 ms sample period. Auto-configuration
+/// replaces the charge current and time.
+const AFE_CONFIG: [u8; 2] = [0x10, 0x20];
+
+/// `AUTOCONFIG0`, `AUTOCONFIG1` and the limits for a 3.3 V supply: enable
+/// auto-configuration and reconfiguration with the baseline set to the
+/// measured value.
+const AUTO_CONFIG: [u8; 5] = [0x0B, 0x00, 200, 130, 180];
+
+/// `ECR` value that starts run mode: baseline tracking enabled with the
+/// first 10 bits of the initial values, and all electrodes enabled.
+const ECR_RUN: u8 = 0x80 | NUM_ELECTRODES as u8;
+
+/// Bits of the touch status that are electrodes.
+const TOUCH_STATUS_MASK: u16 = (1 << NUM_ELECTRODES) - 1;
+
+/// Receives touch and release events from the MPR121.
+pub trait Mpr121Client {
+    /// `electrode` was touched if `touched` is true, otherwise released.
+    fn electrode_event(&self, electrode: usize, touched: bool);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Stopped,
+    /// Writing the init sequence, the step that is being written.
+    Init(usize),
+    Idle,
+    ReadingStatus,
+}
+
+/// Number of register writes in the init sequence.
+const INIT_STEPS: usize = 7;
+
+pub struct Mpr121<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    irq: &'a G,
+    client: OptionalCell<&'a dyn Mpr121Client>,
+    state: Cell<State>,
+    /// The IRQ line fell while the driver was busy.
+    irq_pending: Cell<bool>,
+    touch_threshold: u8,
+    release_threshold: u8,
+    debounce: Cell<u8>,
+    touched: Cell<u16>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Mpr121<'a, I, G> {
+    pub fn new(
+        i2c: &'a I,
+        irq: &'a G,
+        buffer: &'static mut [u8],
+        touch_threshold: u8,
+        release_threshold: u8,
+    ) -> Mpr121<'a, I, G> {
+        Mpr121 {
+            i2c,
+            irq,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Stopped),
+            irq_pending: Cell::new(false),
+            touch_threshold,
+            release_threshold,
+            debounce: Cell::new(0),
+            touched: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Mpr121Client) {
+        self.client.set(client);
+    }
+
+    /// Set the number of consecutive samples, 0 to 7, needed to detect a
+    /// touch and a release. Takes effect at the next [Mpr121::start].
+    pub fn set_debounce(&self, touch: u8, release: u8) {
+        self.debounce.set((release & 0x7) << 4 | (touch & 0x7));
+    }
+
+    /// Configure the sensor and start reporting touches.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Stopped => {}
+            State::Idle => return Err(ErrorCode::ALREADY),
+            State::Init(_) | State::ReadingStatus => return Err(ErrorCode::BUSY),
+        }
+        self.irq.disable_interrupts();
+        self.touched.set(0);
+        self.i2c.enable();
+        self.write_init_step(0)
+    }
+
+    /// Stop reporting touches. The sensor keeps running.
+    pub fn stop(&self) {
+        self.irq.disable_interrupts();
+        self.state.set(State::Stopped);
+    }
+
+    /// Write the registers of one step of the init sequence.
+    fn write_init_step(&self, step: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let len = match step {
+            0 => {
+                buffer[..2].copy_from_slice(&[REG_SOFT_RESET, SOFT_RESET]);
+                2
+            }
+            1 => {
+                // Registers can only be written in stop mode.
+                buffer[..2].copy_from_slice(&[REG_ECR, 0x00]);
+                2
+            }
+            2 => {
+                buffer[0] = REG_MHDR;
+                buffer[1..12].copy_from_slice(&BASELINE_FILTERS);
+                12
+            }
+            3 => {
+                buffer[0] = REG_E0TTH;
+                for electrode in 0..NUM_ELECTRODES {
+                    buffer[1 + 2 * electrode] = self.touch_threshold;
+                    buffer[2 + 2 * electrode] = self.release_threshold;
+                }
+                BUFFER_SIZE
+            }
+            4 => {
+                buffer[..2].copy_from_slice(&[REG_DEBOUNCE, self.debounce.get()]);
+                buffer[2..4].copy_from_slice(&AFE_CONFIG);
+                4
+            }
+            5 => {
+                buffer[0] = REG_AUTOCONFIG0;
+                buffer[1..6].copy_from_slice(&AUTO_CONFIG);
+                6
+            }
+            _ => {
+                buffer[..2].copy_from_slice(&[REG_ECR, ECR_RUN]);
+                2
+            }
+        };
+        match self.i2c.write(buffer, len) {
+            Ok(()) => {
+                self.state.set(State::Init(step));
+                Ok(())
+            }
+            Err((error, buffer)) => {
+                self.buffer.replace(buffer);
+                self.state.set(State::Stopped);
+                Err(error.into())
+            }
+        }
+    }
+
+    fn read_touch_status(&self) {
+        self.irq_pending.set(false);
+        self.buffer.take().map(|buffer| {
+            buffer[0] = REG_TOUCH_STATUS;
+            match self.i2c.write_read(buffer, 1, 2) {
+                Ok(()) => self.state.set(State::ReadingStatus),
+                Err((_, buffer)) => {
+                    self.buffer.replace(buffer);
+                }
+            }
+        });
+    }
+
+    /// Report the electrodes whose state changed.
+    fn update_touched(&self, status: u16) {
+        let touched = status & TOUCH_STATUS_MASK;
+        let changed = touched ^ self.touched.get();
+        self.touched.set(touched);
+        self.client.map(|client| {
+            for electrode in (0..NUM_ELECTRODES).filter(|e| changed & 1 << e != 0) {
+                client.electrode_event(electrode, touched & 1 << electrode != 0);
+            }
+        });
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Mpr121<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let touch_status = u16::from_le_bytes([buffer[0], buffer[1]]);
+        self.buffer.replace(buffer);
+
+        match self.state.get() {
+            State::Init(_) if status.is_err() => self.state.set(State::Stopped),
+            State::Init(step) if step + 1 < INIT_STEPS => {
+                let _ = self.write_init_step(step + 1);
+            }
+            State::Init(_) => {
+                self.state.set(State::Idle);
+                self.irq.enable_interrupts(gpio::InterruptEdge::FallingEdge);
+                // Touches during the init sequence are not lost.
+                if !self.irq.read() {
+                    self.read_touch_status();
+                }
+            }
+            State::ReadingStatus => {
+                self.state.set(State::Idle);
+                if status.is_ok() {
+                    self.update_touched(touch_status);
+                }
+                if self.irq_pending.get() || !self.irq.read() {
+                    self.read_touch_status();
+                }
+            }
+            State::Idle | State::Stopped => {}
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Mpr121<'a, I, G> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => self.read_touch_status(),
+            State::ReadingStatus => self.irq_pending.set(true),
+            State::Init(_) | State::Stopped => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{I2cTransfer, MockI2c, MockPin};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        events: RefCell<Vec<(usize, bool)>>,
+    }
+
+    impl Mpr121Client for Client {
+        fn electrode_event(&self, electrode: usize, touched: bool) {
+            self.events.borrow_mut().push((electrode, touched));
+        }
+    }
+
+    #[test]
+    fn touch_status_changes_are_reported() {
+        let i2c = Box::leak(Box::new(MockI2c::new()));
+        let client = Box::leak(Box::new(Client::default()));
+        let mpr121 = Box::leak(Box::new(Mpr121::new(
+            i2c,
+            Box::leak(Box::new(MockPin::new(true))),
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+            DEFAULT_TOUCH_THRESHOLD,
+            DEFAULT_RELEASE_THRESHOLD,
+        )));
+        mpr121.set_client(client);
+
+        assert_eq!(mpr121.start(), Ok(()));
+        for _ in 0..INIT_STEPS {
+            i2c.complete(mpr121, &[]);
+        }
+        let writes = i2c.writes();
+        assert_eq!(writes.len(), INIT_STEPS);
+        assert_eq!(writes[0], [REG_SOFT_RESET, SOFT_RESET]);
+        assert_eq!(writes[3][..3], [REG_E0TTH, 12, 6]);
+        assert_eq!(writes[INIT_STEPS - 1], [REG_ECR, 0x8C]);
+
+        // Electrodes 0 and 11 are touched.
+        gpio::Client::fired(mpr121);
+        assert_eq!(
+            i2c.transfer(),
+            Some(I2cTransfer {
+                write: [REG_TOUCH_STATUS].to_vec(),
+                read_len: 2,
+            })
+        );
+        i2c.complete(mpr121, &[0x01, 0x08]);
+        assert_eq!(*client.events.borrow(), [(0, true), (11, true)]);
+
+        // Electrode 0 is released and 2 touched.
+        client.events.borrow_mut().clear();
+        gpio::Client::fired(mpr121);
+        i2c.complete(mpr121, &[0x04, 0x08]);
+        assert_eq!(*client.events.borrow(), [(0, false), (2, true)]);
+    }
+}
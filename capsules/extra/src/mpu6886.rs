@@ -0,0 +1,786 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TDK InvenSense MPU-6886 6-axis motion sensor, found in
+//! M5Stack and TTGO boards.
+//!
+//! <https://m5stack.oss-cn-shenzhen.aliyuncs.com/resource/docs/datasheet/core/MPU-6886-000193%2Bv1.1_GHIC_en.pdf>
+//!
+//! The MPU-6886 keeps most of the MPU-6050 register map, but answers
+//! `WHO_AM_I` with 0x19 and has a different temperature sensor, with
+//! 326.8 LSB/°C and an offset of 25 °C at 0 LSB.
+//!
+//! The factory accelerometer offsets are kept in OTP and loaded into
+//! `XA_OFFSET`, `YA_OFFSET` and `ZA_OFFSET` when the device resets, so
+//! [Mpu6886::configure] resets the device before anything else and then
+//! leaves them alone. The self-test registers at 0x0D to 0x0F hold the
+//! factory self-test responses of the accelerometer, not offsets, and are
+//! not used.
+//!
+//! [Mpu6886::configure] checks `WHO_AM_I`, resets the device and samples at
+//! 100 Hz with the accelerometer at ±4 g and the gyroscope at ±500 dps.
+//! Readings fail with `OFF` until this has finished. The NineDof HIL reports
+//! the accelerometer in mg and the gyroscope in mdps; there is no
+//! magnetometer. Temperature is reported in hundredths of a degree.
+//!
+//! `NineDof::calibrate_gyroscope` averages gyroscope samples taken at rest,
+//! one per sample period, and subtracts the result from later readings.
+//! `NineDof::run_gyroscope_self_test` switches to 1 kHz and ±250 dps,
+//! averages [SELF_TEST_SAMPLES] samples without and with the self-test
+//! stimulus, and compares the response with the factory codes in
+//! `SELF_TEST_X_GYRO` to `SELF_TEST_Z_GYRO`. It then restores the
+//! configuration. If the self-test fails part way through, the configuration
+//! is lost and readings fail with `OFF` until [Mpu6886::configure] runs
+//! again.
+//!
+//! Usage
+//! -----
+//!
+//! The component takes the same arguments as the MPU-9250 one:
+//!
+//! ```rust
+//! let mpu6886 = components::mpu6886::Mpu6886Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mpu6886::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mpu6886_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(mpu6886));
+//! ```
+
+use crate::gyro_calibration::{invensense_self_test_passed, GyroCalibration};
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{NineDof, NineDofClient, TemperatureClient, TemperatureDriver};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the MPU-6886 with AD0 low.
+pub const BASE_ADDR: u8 = 0x68;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 6;
+
+/// Gyroscope samples averaged with and without the self-test stimulus.
+pub const SELF_TEST_SAMPLES: usize = 200;
+
+const REG_SMPLRT_DIV: u8 = 0x19;
+const REG_CONFIG: u8 = 0x1A;
+const REG_GYRO_CONFIG: u8 = 0x1B;
+const REG_ACCEL_CONFIG: u8 = 0x1C;
+const REG_ACCEL_CONFIG2: u8 = 0x1D;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+const REG_TEMP_OUT_H: u8 = 0x41;
+const REG_GYRO_XOUT_H: u8 = 0x43;
+/// Followed by `SELF_TEST_Y_GYRO` and `SELF_TEST_Z_GYRO`.
+const REG_SELF_TEST_X_GYRO: u8 = 0x50;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_PWR_MGMT_2: u8 = 0x6C;
+const REG_WHO_AM_I: u8 = 0x75;
+
+const WHO_AM_I_MPU6886: u8 = 0x19;
+
+const PWR_MGMT_1_DEVICE_RESET: u8 = 1 << 7;
+const PWR_MGMT_1_CLKSEL_AUTO: u8 = 0x01;
+/// 41 Hz low pass filter on the gyroscope and temperature sensor.
+const CONFIG_DLPF_41HZ: u8 = 0x03;
+/// 92 Hz low pass filter on the gyroscope and temperature sensor.
+const CONFIG_DLPF_92HZ: u8 = 0x02;
+/// 1 kHz / (1 + 9) = 100 Hz.
+const SMPLRT_DIV_100HZ: u8 = 9;
+const SMPLRT_DIV_1KHZ: u8 = 0;
+const GYRO_CONFIG_250DPS: u8 = 0;
+const GYRO_CONFIG_500DPS: u8 = 1 << 3;
+/// The self-test stimulus on every axis, at ±250 dps.
+const GYRO_CONFIG_SELF_TEST: u8 = 0xE0 | GYRO_CONFIG_250DPS;
+const ACCEL_CONFIG_4G: u8 = 1 << 3;
+/// 44.8 Hz low pass filter on the accelerometer.
+const ACCEL_CONFIG2_DLPF_45HZ: u8 = 0x03;
+
+const ACCEL_LSB_PER_G: i32 = 8192;
+/// 65.5 LSB/dps at ±500 dps.
+const GYRO_LSB_PER_10DPS: i32 = 655;
+/// 131 LSB/dps at ±250 dps, for the self-test.
+const SELF_TEST_LSB_PER_10DPS: i32 = 1310;
+/// 326.8 LSB/°C.
+const TEMP_LSB_PER_10C: i32 = 3268;
+const TEMP_OFFSET_CENTI_C: i32 = 2500;
+
+/// Time for the device to reset and reload its OTP, and for the clock to
+/// settle.
+const RESET_WAIT_MS: u32 = 10;
+/// One sample period at 100 Hz, between calibration samples.
+const SAMPLE_PERIOD_MS: u32 = 10;
+/// One sample period at 1 kHz, between self-test samples.
+const SELF_TEST_SAMPLE_PERIOD_MS: u32 = 1;
+/// Time for the gyroscope to settle after a change of configuration.
+const SELF_TEST_SETTLE_MS: u32 = 20;
+
+/// A step of the configuration.
+#[derive(Clone, Copy)]
+enum Step {
+    /// Check `WHO_AM_I`.
+    CheckIdentity,
+    /// Write a register.
+    Write(u8, u8),
+    /// Wait for the device.
+    Wait(u32),
+    /// Read the factory self-test codes.
+    ReadSelfTestCodes,
+    /// Average [SELF_TEST_SAMPLES] gyroscope samples.
+    SampleGyroscope,
+}
+
+const CONFIGURE: [Step; 11] = [
+    Step::CheckIdentity,
+    Step::Write(REG_PWR_MGMT_1, PWR_MGMT_1_DEVICE_RESET),
+    Step::Wait(RESET_WAIT_MS),
+    Step::Write(REG_PWR_MGMT_1, PWR_MGMT_1_CLKSEL_AUTO),
+    Step::Wait(RESET_WAIT_MS),
+    Step::Write(REG_PWR_MGMT_2, 0),
+    Step::Write(REG_CONFIG, CONFIG_DLPF_41HZ),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_100HZ),
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_500DPS),
+    Step::Write(REG_ACCEL_CONFIG, ACCEL_CONFIG_4G),
+    Step::Write(REG_ACCEL_CONFIG2, ACCEL_CONFIG2_DLPF_45HZ),
+];
+
+const SELF_TEST: [Step; 12] = [
+    Step::ReadSelfTestCodes,
+    Step::Write(REG_CONFIG, CONFIG_DLPF_92HZ),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_1KHZ),
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_250DPS),
+    Step::Wait(SELF_TEST_SETTLE_MS),
+    Step::SampleGyroscope,
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_SELF_TEST),
+    Step::Wait(SELF_TEST_SETTLE_MS),
+    Step::SampleGyroscope,
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_500DPS),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_100HZ),
+    Step::Write(REG_CONFIG, CONFIG_DLPF_41HZ),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Configure(usize),
+    ReadAccelerometer,
+    ReadGyroscope,
+    ReadTemperature,
+    CalibrateGyroscopeWait,
+    CalibrateGyroscopeRead,
+    SelfTest(usize),
+}
+
+/// Convert big-endian accelerometer output to mg.
+fn accelerometer_mg(data: &[u8]) -> [i32; 3] {
+    let axis = |i: usize| i16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as i32;
+    [axis(0), axis(1), axis(2)].map(|raw| raw * 1000 / ACCEL_LSB_PER_G)
+}
+
+/// Read big-endian gyroscope output.
+fn raw_gyroscope(data: &[u8]) -> [i16; 3] {
+    let axis = |i: usize| i16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+    [axis(0), axis(1), axis(2)]
+}
+
+/// Convert raw gyroscope output to mdps.
+fn gyroscope_mdps(raw: [i16; 3]) -> [i32; 3] {
+    raw.map(|raw| raw as i32 * 10_000 / GYRO_LSB_PER_10DPS)
+}
+
+/// Convert big-endian temperature output to hundredths of a degree.
+fn temperature_centi_c(data: &[u8]) -> i32 {
+    let raw = i16::from_be_bytes([data[0], data[1]]) as i32;
+    raw * 1000 / TEMP_LSB_PER_10C + TEMP_OFFSET_CENTI_C
+}
+
+pub struct Mpu6886<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    configured: Cell<bool>,
+    calibration: GyroCalibration,
+    self_test_codes: Cell<[u8; 3]>,
+    self_test_count: Cell<usize>,
+    self_test_sum: Cell<[i32; 3]>,
+    /// Average without the self-test stimulus, once it has been measured.
+    self_test_baseline: OptionalCell<[i32; 3]>,
+    self_test_passed: Cell<bool>,
+    nine_dof_client: OptionalCell<&'a dyn NineDofClient>,
+    temperature_client: OptionalCell<&'a dyn TemperatureClient>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Mpu6886<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        Mpu6886 {
+            i2c,
+            alarm,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            configured: Cell::new(false),
+            calibration: GyroCalibration::new(),
+            self_test_codes: Cell::new([0; 3]),
+            self_test_count: Cell::new(0),
+            self_test_sum: Cell::new([0; 3]),
+            self_test_baseline: OptionalCell::empty(),
+            self_test_passed: Cell::new(false),
+            nine_dof_client: OptionalCell::empty(),
+            temperature_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Check, reset and configure the MPU-6886.
+    pub fn configure(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.configured.set(false);
+        self.i2c.enable();
+        self.configure_step(0).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn configure_step(&self, step: usize) -> Result<(), ErrorCode> {
+        self.state.set(State::Configure(step));
+        self.start_step(CONFIGURE[step])
+    }
+
+    fn start_step(&self, step: Step) -> Result<(), ErrorCode> {
+        match step {
+            Step::CheckIdentity => self.read(REG_WHO_AM_I, 1),
+            Step::Write(register, value) => self.write(&[register, value]),
+            Step::Wait(ms) => {
+                self.wait_ms(ms);
+                Ok(())
+            }
+            Step::ReadSelfTestCodes => self.read(REG_SELF_TEST_X_GYRO, 3),
+            Step::SampleGyroscope => self.read(REG_GYRO_XOUT_H, 6),
+        }
+    }
+
+    /// Move on from configuration step `step`, which read `data`.
+    fn configure_done(&self, step: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if let Step::CheckIdentity = CONFIGURE[step] {
+            if data[0] != WHO_AM_I_MPU6886 {
+                return Err(ErrorCode::NODEVICE);
+            }
+        }
+        if step + 1 < CONFIGURE.len() {
+            self.configure_step(step + 1)
+        } else {
+            self.configured.set(true);
+            self.finish();
+            Ok(())
+        }
+    }
+
+    /// Start the gyroscope self-test. Completion is reported through the
+    /// NineDof client with `1` if the test passed and `0` otherwise.
+    pub fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.self_test_count.set(0);
+        self.self_test_sum.set([0; 3]);
+        self.self_test_baseline.clear();
+        self.i2c.enable();
+        self.self_test_step(0).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn self_test_step(&self, step: usize) -> Result<(), ErrorCode> {
+        self.state.set(State::SelfTest(step));
+        self.start_step(SELF_TEST[step])
+    }
+
+    /// Move on from self-test step `step`, which read `data`.
+    fn self_test_done(&self, step: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        match SELF_TEST[step] {
+            Step::ReadSelfTestCodes => self.self_test_codes.set([data[0], data[1], data[2]]),
+            Step::SampleGyroscope => {
+                let raw = raw_gyroscope(data);
+                let mut sum = self.self_test_sum.get();
+                for axis in 0..3 {
+                    sum[axis] += raw[axis] as i32;
+                }
+                self.self_test_sum.set(sum);
+                let count = self.self_test_count.get() + 1;
+                self.self_test_count.set(count);
+                if count < SELF_TEST_SAMPLES {
+                    self.wait_ms(SELF_TEST_SAMPLE_PERIOD_MS);
+                    return Ok(());
+                }
+
+                let average = sum.map(|sum| sum / SELF_TEST_SAMPLES as i32);
+                self.self_test_count.set(0);
+                self.self_test_sum.set([0; 3]);
+                match self.self_test_baseline.take() {
+                    None => self.self_test_baseline.set(average),
+                    Some(baseline) => self.self_test_passed.set(invensense_self_test_passed(
+                        baseline,
+                        average,
+                        self.self_test_codes.get(),
+                        SELF_TEST_LSB_PER_10DPS,
+                    )),
+                }
+            }
+            _ => {}
+        }
+        if step + 1 < SELF_TEST.len() {
+            self.self_test_step(step + 1)
+        } else {
+            let passed = self.self_test_passed.get();
+            self.finish();
+            self.nine_dof_client
+                .map(|client| client.callback(passed as usize, 0, 0));
+            Ok(())
+        }
+    }
+
+    /// Abort the self-test, which may have left the device configured for
+    /// it, and report the failure.
+    fn self_test_failed(&self) {
+        self.configured.set(false);
+        self.report(None);
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            self.i2c
+                .write(buffer, bytes.len())
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn read(&self, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            self.i2c
+                .write_read(buffer, 1, len)
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn wait_ms(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn start_read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(state);
+        self.i2c.enable();
+        self.read(register, len).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+    }
+
+    fn report(&self, value: Option<[i32; 3]>) {
+        self.finish();
+        let [x, y, z] = value.unwrap_or([0; 3]);
+        self.nine_dof_client
+            .map(|client| client.callback(x as usize, y as usize, z as usize));
+    }
+
+    fn report_temperature(&self, value: Result<i32, ErrorCode>) {
+        self.finish();
+        self.temperature_client.map(|client| client.callback(value));
+    }
+
+    /// Start a zero-rate offset calibration over `samples` gyroscope
+    /// readings. The device must be kept still until the NineDof client is
+    /// called with the measured offset.
+    pub fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.calibration.start(samples)?;
+        self.start_read(State::CalibrateGyroscopeRead, REG_GYRO_XOUT_H, 6)
+            .map_err(|error| {
+                self.calibration.cancel();
+                error
+            })
+    }
+
+    /// Add a calibration sample, and finish the calibration once enough have
+    /// been taken.
+    fn add_calibration_sample(&self, data: &[u8]) {
+        if self.calibration.add_sample(raw_gyroscope(data)) {
+            self.report(Some(gyroscope_mdps(self.calibration.bias())));
+        } else {
+            self.state.set(State::CalibrateGyroscopeWait);
+            self.wait_ms(SAMPLE_PERIOD_MS);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Mpu6886<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let mut data = [0; BUFFER_SIZE];
+        data.copy_from_slice(&buffer[..BUFFER_SIZE]);
+        self.buffer.replace(buffer);
+
+        match (self.state.get(), status) {
+            (State::Configure(step), Ok(())) => {
+                if self.configure_done(step, &data).is_err() {
+                    self.finish();
+                }
+            }
+            (State::Configure(_), Err(_)) => self.finish(),
+            (State::ReadAccelerometer, Ok(())) => self.report(Some(accelerometer_mg(&data))),
+            (State::ReadGyroscope, Ok(())) => {
+                let raw = self.calibration.correct(raw_gyroscope(&data));
+                self.report(Some(gyroscope_mdps(raw)))
+            }
+            (State::ReadAccelerometer | State::ReadGyroscope, Err(_)) => self.report(None),
+            (State::CalibrateGyroscopeRead, Ok(())) => self.add_calibration_sample(&data),
+            (State::CalibrateGyroscopeRead, Err(_)) => {
+                self.calibration.cancel();
+                self.report(None);
+            }
+            (State::SelfTest(step), Ok(())) => {
+                if self.self_test_done(step, &data).is_err() {
+                    self.self_test_failed();
+                }
+            }
+            (State::SelfTest(_), Err(_)) => self.self_test_failed(),
+            (State::ReadTemperature, Ok(())) => {
+                self.report_temperature(Ok(temperature_centi_c(&data)))
+            }
+            (State::ReadTemperature, Err(error)) => self.report_temperature(Err(error.into())),
+            (State::Idle | State::CalibrateGyroscopeWait, _) => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> time::AlarmClient for Mpu6886<'a, A, I> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Configure(step) => {
+                if self.configure_done(step, &[]).is_err() {
+                    self.finish();
+                }
+            }
+            State::CalibrateGyroscopeWait => {
+                self.state.set(State::CalibrateGyroscopeRead);
+                if self.read(REG_GYRO_XOUT_H, 6).is_err() {
+                    self.calibration.cancel();
+                    self.report(None);
+                }
+            }
+            State::SelfTest(step) => {
+                // Sampling waits between samples, and the other steps wait
+                // for the gyroscope to settle.
+                let result = match SELF_TEST[step] {
+                    Step::SampleGyroscope => self.start_step(Step::SampleGyroscope),
+                    _ => self.self_test_done(step, &[]),
+                };
+                if result.is_err() {
+                    self.self_test_failed();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> NineDof<'a> for Mpu6886<'a, A, I> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.nine_dof_client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadAccelerometer, REG_ACCEL_XOUT_H, 6)
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadGyroscope, REG_GYRO_XOUT_H, 6)
+    }
+
+    fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        self.run_gyroscope_self_test()
+    }
+
+    fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        self.calibrate_gyroscope(samples)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> TemperatureDriver<'a> for Mpu6886<'a, A, I> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.temperature_client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadTemperature, REG_TEMP_OUT_H, 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        readings: RefCell<Vec<(usize, usize, usize)>>,
+        temperatures: RefCell<Vec<Result<i32, ErrorCode>>>,
+    }
+
+    impl NineDofClient for Client {
+        fn callback(&self, x: usize, y: usize, z: usize) {
+            self.readings.borrow_mut().push((x, y, z));
+        }
+    }
+
+    impl TemperatureClient for Client {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.temperatures.borrow_mut().push(value);
+        }
+    }
+
+    type Device = Mpu6886<'static, MockAlarm<'static>, MockI2c>;
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockAlarm<'static>,
+        &'static Device,
+        &'static Client,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let mpu6886 = Box::leak(Box::new(Mpu6886::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(mpu6886);
+        NineDof::set_client(mpu6886, client);
+        TemperatureDriver::set_client(mpu6886, client);
+        (i2c, alarm, mpu6886, client)
+    }
+
+    fn configure(i2c: &MockI2c, alarm: &MockAlarm, mpu6886: &Device) {
+        assert_eq!(mpu6886.configure(), Ok(()));
+        i2c.complete(mpu6886, &[WHO_AM_I_MPU6886]);
+        while i2c.busy() || alarm.dt().is_some() {
+            if alarm.dt().is_some() {
+                alarm.fire();
+            } else {
+                i2c.complete(mpu6886, &[]);
+            }
+        }
+    }
+
+    /// Gyroscope output, big-endian.
+    fn gyroscope(raw: [i16; 3]) -> Vec<u8> {
+        raw.iter().flat_map(|axis| axis.to_be_bytes()).collect()
+    }
+
+    /// Average [SELF_TEST_SAMPLES] samples of `raw`, one per sample period.
+    fn self_test_samples(i2c: &MockI2c, alarm: &MockAlarm, mpu6886: &Device, raw: [i16; 3]) {
+        for sample in 0..SELF_TEST_SAMPLES {
+            if sample > 0 {
+                assert_eq!(alarm.dt(), Some(SELF_TEST_SAMPLE_PERIOD_MS));
+                alarm.fire();
+            }
+            assert_eq!(i2c.complete(mpu6886, &gyroscope(raw)), [0x43]);
+        }
+    }
+
+    /// Run the self-test with the given factory codes and readings without
+    /// and with the stimulus.
+    fn self_test(
+        i2c: &MockI2c,
+        alarm: &MockAlarm,
+        mpu6886: &Device,
+        codes: [u8; 3],
+        baseline: [i16; 3],
+        stimulated: [i16; 3],
+    ) {
+        assert_eq!(mpu6886.run_gyroscope_self_test(), Ok(()));
+        assert_eq!(mpu6886.read_gyroscope(), Err(ErrorCode::BUSY));
+        assert_eq!(i2c.complete(mpu6886, &codes), [0x50]);
+        // 1 kHz and ±250 dps.
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x1A, 0x02]);
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x19, 0x00]);
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x1B, 0x00]);
+        alarm.fire();
+        self_test_samples(i2c, alarm, mpu6886, baseline);
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x1B, 0xE0]);
+        alarm.fire();
+        self_test_samples(i2c, alarm, mpu6886, stimulated);
+        // The configuration is restored.
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x1B, 0x08]);
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x19, 0x09]);
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x1A, 0x03]);
+        assert!(!i2c.busy());
+        assert_eq!(alarm.dt(), None);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            accelerometer_mg(&[0x20, 0x00, 0xE0, 0x00, 0x40, 0x00]),
+            [1000, -1000, 2000]
+        );
+        assert_eq!(
+            gyroscope_mdps(raw_gyroscope(&[0x00, 0x83, 0xFF, 0x7D, 0x00, 0x00])),
+            [2000, -2000, 0]
+        );
+        // 25 °C at 0, and 326.8 LSB/°C either side.
+        assert_eq!(temperature_centi_c(&[0x00, 0x00]), 2500);
+        assert_eq!(temperature_centi_c(&3268i16.to_be_bytes()), 3500);
+        assert_eq!(temperature_centi_c(&(-3268i16).to_be_bytes()), 1500);
+    }
+
+    #[test]
+    fn resets_before_configuring() {
+        let (i2c, alarm, mpu6886, client) = setup();
+        assert_eq!(mpu6886.read_accelerometer(), Err(ErrorCode::OFF));
+
+        assert_eq!(mpu6886.configure(), Ok(()));
+        assert_eq!(i2c.complete(mpu6886, &[WHO_AM_I_MPU6886]), [0x75]);
+        // The reset reloads the factory offsets from OTP.
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x6B, 0x80]);
+        alarm.fire();
+        assert_eq!(i2c.complete(mpu6886, &[]), [0x6B, 0x01]);
+        alarm.fire();
+        for _ in 0..6 {
+            i2c.complete(mpu6886, &[]);
+        }
+        assert!(!i2c.busy());
+
+        assert_eq!(mpu6886.read_temperature(), Ok(()));
+        assert_eq!(mpu6886.read_gyroscope(), Err(ErrorCode::BUSY));
+        assert_eq!(i2c.complete(mpu6886, &[0x00, 0x00]), [0x41]);
+        assert_eq!(*client.temperatures.borrow(), [Ok(2500)]);
+
+        assert_eq!(mpu6886.read_accelerometer(), Ok(()));
+        i2c.complete(mpu6886, &[0x20, 0x00, 0x00, 0x00, 0xE0, 0x00]);
+        assert_eq!(*client.readings.borrow(), [(1000, 0, -1000i32 as usize)]);
+    }
+
+    #[test]
+    fn rejects_other_devices() {
+        let (i2c, _alarm, mpu6886, _client) = setup();
+        assert_eq!(mpu6886.configure(), Ok(()));
+        // An MPU-6050 answers 0x68.
+        i2c.complete(mpu6886, &[0x68]);
+        assert!(!i2c.busy());
+        assert_eq!(mpu6886.read_accelerometer(), Err(ErrorCode::OFF));
+    }
+
+    #[test]
+    fn gyroscope_calibration_corrects_readings() {
+        let (i2c, alarm, mpu6886, client) = setup();
+        assert_eq!(mpu6886.calibrate_gyroscope(4), Err(ErrorCode::OFF));
+        configure(i2c, alarm, mpu6886);
+
+        assert_eq!(mpu6886.calibrate_gyroscope(0), Err(ErrorCode::INVAL));
+        assert_eq!(mpu6886.calibrate_gyroscope(4), Ok(()));
+        assert_eq!(mpu6886.read_gyroscope(), Err(ErrorCode::BUSY));
+        // Noise around a bias of 10, -20 and 2 dps.
+        let bias = [655, -1310, 131];
+        let noise = [[3, -3, 1], [-3, 3, -1], [5, 0, 2], [-5, 0, -2]];
+        for (sample, noise) in noise.iter().enumerate() {
+            if sample > 0 {
+                assert_eq!(alarm.dt(), Some(SAMPLE_PERIOD_MS));
+                alarm.fire();
+            }
+            let raw = [0, 1, 2].map(|axis| bias[axis] + noise[axis]);
+            assert_eq!(i2c.complete(mpu6886, &gyroscope(raw)), [0x43]);
+        }
+        assert!(!i2c.busy());
+        assert_eq!(alarm.dt(), None);
+        assert_eq!(
+            *client.readings.borrow(),
+            [(10_000, -20_000i32 as usize, 2000)]
+        );
+
+        // Later readings have the bias removed.
+        assert_eq!(mpu6886.read_gyroscope(), Ok(()));
+        i2c.complete(mpu6886, &gyroscope([655 + 6550, -1310, 131 - 655]));
+        assert_eq!(
+            client.readings.borrow()[1],
+            (100_000, 0, -10_000i32 as usize)
+        );
+    }
+
+    #[test]
+    fn gyroscope_self_test() {
+        let (i2c, alarm, mpu6886, client) = setup();
+        assert_eq!(mpu6886.run_gyroscope_self_test(), Err(ErrorCode::OFF));
+        configure(i2c, alarm, mpu6886);
+
+        // Code 71 is a factory response of 40 dps, so 21 dps passes.
+        let baseline = [131, -262, 0];
+        let respond = |dps: i16| baseline.map(|raw| raw + dps * 131);
+        self_test(i2c, alarm, mpu6886, [71; 3], baseline, respond(21));
+        self_test(i2c, alarm, mpu6886, [71; 3], baseline, respond(19));
+        assert_eq!(*client.readings.borrow(), [(1, 0, 0), (0, 0, 0)]);
+
+        // Readings carry on at ±500 dps.
+        assert_eq!(mpu6886.read_gyroscope(), Ok(()));
+        i2c.complete(mpu6886, &gyroscope([655, 0, 0]));
+        assert_eq!(client.readings.borrow()[2], (10_000, 0, 0));
+    }
+
+    #[test]
+    fn failed_self_test_needs_configuring_again() {
+        let (i2c, alarm, mpu6886, client) = setup();
+        configure(i2c, alarm, mpu6886);
+
+        assert_eq!(mpu6886.run_gyroscope_self_test(), Ok(()));
+        i2c.complete(mpu6886, &[71; 3]);
+        i2c.fail(mpu6886, i2c::Error::DataNak);
+        assert!(!i2c.busy());
+        assert_eq!(*client.readings.borrow(), [(0, 0, 0)]);
+        assert_eq!(mpu6886.read_gyroscope(), Err(ErrorCode::OFF));
+    }
+}
@@ -0,0 +1,954 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TDK InvenSense MPU-9250 9-axis motion sensor.
+//!
+//! <https://invensense.tdk.com/wp-content/uploads/2015/02/PS-MPU-9250A-01-v1.1.pdf>
+//!
+//! The MPU-9250 is an MPU-6500 accelerometer and gyroscope with an AK8963
+//! magnetometer in the same package. The AK8963 sits on the auxiliary I2C
+//! bus of the MPU-6500, whose I2C master the driver uses to reach it rather
+//! than the bypass mode, so the MPU-9250 stays the only device on the host
+//! bus. An AK8963 register is written by loading `I2C_SLV0_DO` and pointing
+//! `I2C_SLV0_ADDR`, `I2C_SLV0_REG` and `I2C_SLV0_CTRL` at it, and read by
+//! pointing slave 0 at it in read mode and reading the bytes back from
+//! `EXT_SENS_DATA`. Slave 0 transactions run once per sample, so the driver
+//! waits two sample periods after setting one up.
+//!
+//! [Mpu9250::configure] samples at 100 Hz with the accelerometer at ±4 g and
+//! the gyroscope at ±500 dps, reads the AK8963 sensitivity adjustment values
+//! from its Fuse ROM, and then puts the AK8963 in 16-bit continuous
+//! measurement mode with slave 0 copying every measurement to
+//! `EXT_SENS_DATA`. Readings fail with `OFF` until this has finished.
+//!
+//! The NineDof HIL reports the accelerometer in mg, the gyroscope in mdps and
+//! the magnetometer in nT. Magnetometer readings are scaled by the
+//! sensitivity adjustment, turned to the accelerometer's axes (the AK8963 X
+//! and Y axes are swapped and its Z axis points the other way), and have the
+//! hard-iron offset removed. The offset is measured by
+//! `NineDof::calibrate_magnetometer`, which takes [CALIBRATION_SAMPLES]
+//! samples while the device is turned through every orientation and uses the
+//! middle of the range seen on each axis.
+//!
+//! `NineDof::calibrate_gyroscope` averages gyroscope samples taken at rest,
+//! one per sample period, and subtracts the result from later readings.
+//! `NineDof::run_gyroscope_self_test` switches to 1 kHz and ±250 dps,
+//! averages [SELF_TEST_SAMPLES] samples without and with the self-test
+//! stimulus, and compares the response with the factory codes in
+//! `SELF_TEST_X_GYRO` to `SELF_TEST_Z_GYRO`. It then restores the
+//! configuration. If the self-test fails part way through, the configuration
+//! is lost and readings fail with `OFF` until [Mpu9250::configure] runs
+//! again.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mpu9250 = components::mpu9250::Mpu9250Component::new(
+//!     mux_i2c,
+//!     capsules_extra::mpu9250::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mpu9250_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! let ninedof = components::ninedof::NineDofComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ninedof::DRIVER_NUM,
+//! )
+//! .finalize(components::ninedof_component_static!(mpu9250));
+//! ```
+
+use crate::gyro_calibration::{invensense_self_test_passed, GyroCalibration};
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the MPU-9250 with AD0 low.
+pub const BASE_ADDR: u8 = 0x68;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 8;
+
+/// Magnetometer samples taken by a hard-iron calibration.
+pub const CALIBRATION_SAMPLES: usize = 100;
+
+/// Gyroscope samples averaged with and without the self-test stimulus.
+pub const SELF_TEST_SAMPLES: usize = 200;
+
+/// Followed by `SELF_TEST_Y_GYRO` and `SELF_TEST_Z_GYRO`.
+const REG_SELF_TEST_X_GYRO: u8 = 0x00;
+const REG_SMPLRT_DIV: u8 = 0x19;
+const REG_CONFIG: u8 = 0x1A;
+const REG_GYRO_CONFIG: u8 = 0x1B;
+const REG_ACCEL_CONFIG: u8 = 0x1C;
+const REG_I2C_MST_CTRL: u8 = 0x24;
+/// Followed by `I2C_SLV0_REG` and `I2C_SLV0_CTRL`.
+const REG_I2C_SLV0_ADDR: u8 = 0x25;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+const REG_GYRO_XOUT_H: u8 = 0x43;
+const REG_EXT_SENS_DATA_00: u8 = 0x49;
+const REG_I2C_SLV0_DO: u8 = 0x63;
+const REG_USER_CTRL: u8 = 0x6A;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_PWR_MGMT_2: u8 = 0x6C;
+const REG_WHO_AM_I: u8 = 0x75;
+
+const WHO_AM_I_MPU9250: u8 = 0x71;
+const WHO_AM_I_MPU9255: u8 = 0x73;
+
+const PWR_MGMT_1_CLKSEL_AUTO: u8 = 0x01;
+/// 41 Hz low pass filter on the gyroscope and temperature sensor.
+const CONFIG_DLPF_41HZ: u8 = 0x03;
+/// 92 Hz low pass filter on the gyroscope and temperature sensor.
+const CONFIG_DLPF_92HZ: u8 = 0x02;
+/// 1 kHz / (1 + 9) = 100 Hz.
+const SMPLRT_DIV_100HZ: u8 = 9;
+const SMPLRT_DIV_1KHZ: u8 = 0;
+const GYRO_CONFIG_250DPS: u8 = 0;
+const GYRO_CONFIG_500DPS: u8 = 1 << 3;
+/// The self-test stimulus on every axis, at ±250 dps.
+const GYRO_CONFIG_SELF_TEST: u8 = 0xE0 | GYRO_CONFIG_250DPS;
+const ACCEL_CONFIG_4G: u8 = 1 << 3;
+const USER_CTRL_I2C_MST_EN: u8 = 1 << 5;
+const I2C_MST_CTRL_400KHZ: u8 = 0x0D;
+const I2C_SLV_READ: u8 = 1 << 7;
+const I2C_SLV_EN: u8 = 1 << 7;
+
+const AK8963_ADDR: u8 = 0x0C;
+const AK_WIA: u8 = 0x00;
+const AK_HXL: u8 = 0x03;
+const AK_CNTL1: u8 = 0x0A;
+const AK_ASAX: u8 = 0x10;
+
+const AK_WIA_AK8963: u8 = 0x48;
+const CNTL1_POWER_DOWN: u8 = 0x00;
+const CNTL1_FUSE_ROM: u8 = 0x0F;
+/// 16-bit output, continuous measurement at 100 Hz.
+const CNTL1_CONTINUOUS_100HZ_16BIT: u8 = 0x16;
+const ST2_HOFL: u8 = 1 << 3;
+/// `HXL` to `HZH`, and `ST2`, which has to be read to release the next
+/// measurement.
+const AK_DATA_LEN: usize = 7;
+
+const ACCEL_LSB_PER_G: i32 = 8192;
+/// 65.5 LSB/dps at ±500 dps.
+const GYRO_LSB_PER_10DPS: i32 = 655;
+/// 131 LSB/dps at ±250 dps, for the self-test.
+const SELF_TEST_LSB_PER_10DPS: i32 = 1310;
+/// 0.15 uT/LSB in 16-bit mode.
+const MAG_NT_PER_LSB: i32 = 150;
+
+/// Two sample periods, for slave 0 to run once.
+const AUX_WAIT_MS: u32 = 20;
+/// Time between calibration samples, so the calibration takes 5 seconds.
+const CALIBRATION_INTERVAL_MS: u32 = 50;
+/// One sample period at 100 Hz, between gyroscope calibration samples.
+const SAMPLE_PERIOD_MS: u32 = 10;
+/// One sample period at 1 kHz, between self-test samples.
+const SELF_TEST_SAMPLE_PERIOD_MS: u32 = 1;
+/// Time for the gyroscope to settle after a change of configuration.
+const SELF_TEST_SETTLE_MS: u32 = 20;
+
+/// A step of the configuration.
+#[derive(Clone, Copy)]
+enum Step {
+    /// Check `WHO_AM_I`.
+    CheckIdentity,
+    /// Write an MPU-9250 register.
+    Write(u8, u8),
+    /// Write an AK8963 register through slave 0: load the data, start the
+    /// write and wait for it to run.
+    AkWrite(u8, u8),
+    /// Read AK8963 registers through slave 0: start the read, wait for it
+    /// to run and read the data back.
+    AkRead(u8, usize),
+    /// Leave slave 0 reading every AK8963 measurement.
+    AkPoll,
+    /// Wait for the device.
+    Wait(u32),
+    /// Read the factory self-test codes of the gyroscope.
+    ReadSelfTestCodes,
+    /// Average [SELF_TEST_SAMPLES] gyroscope samples.
+    SampleGyroscope,
+}
+
+impl Step {
+    fn phases(&self) -> u8 {
+        match self {
+            Step::CheckIdentity
+            | Step::Write(..)
+            | Step::AkPoll
+            | Step::Wait(_)
+            | Step::ReadSelfTestCodes
+            | Step::SampleGyroscope => 1,
+            Step::AkWrite(..) | Step::AkRead(..) => 3,
+        }
+    }
+}
+
+const CONFIGURE: [Step; 16] = [
+    Step::CheckIdentity,
+    Step::Write(REG_PWR_MGMT_1, PWR_MGMT_1_CLKSEL_AUTO),
+    Step::Write(REG_PWR_MGMT_2, 0),
+    Step::Write(REG_CONFIG, CONFIG_DLPF_41HZ),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_100HZ),
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_500DPS),
+    Step::Write(REG_ACCEL_CONFIG, ACCEL_CONFIG_4G),
+    Step::Write(REG_USER_CTRL, USER_CTRL_I2C_MST_EN),
+    Step::Write(REG_I2C_MST_CTRL, I2C_MST_CTRL_400KHZ),
+    Step::AkRead(AK_WIA, 1),
+    Step::AkWrite(AK_CNTL1, CNTL1_POWER_DOWN),
+    Step::AkWrite(AK_CNTL1, CNTL1_FUSE_ROM),
+    Step::AkRead(AK_ASAX, 3),
+    Step::AkWrite(AK_CNTL1, CNTL1_POWER_DOWN),
+    Step::AkWrite(AK_CNTL1, CNTL1_CONTINUOUS_100HZ_16BIT),
+    Step::AkPoll,
+];
+
+const SELF_TEST: [Step; 12] = [
+    Step::ReadSelfTestCodes,
+    Step::Write(REG_CONFIG, CONFIG_DLPF_92HZ),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_1KHZ),
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_250DPS),
+    Step::Wait(SELF_TEST_SETTLE_MS),
+    Step::SampleGyroscope,
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_SELF_TEST),
+    Step::Wait(SELF_TEST_SETTLE_MS),
+    Step::SampleGyroscope,
+    Step::Write(REG_GYRO_CONFIG, GYRO_CONFIG_500DPS),
+    Step::Write(REG_SMPLRT_DIV, SMPLRT_DIV_100HZ),
+    Step::Write(REG_CONFIG, CONFIG_DLPF_41HZ),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Configure { step: usize, phase: u8 },
+    ReadAccelerometer,
+    ReadGyroscope,
+    ReadMagnetometer,
+    CalibrateWait,
+    CalibrateRead,
+    CalibrateGyroscopeWait,
+    CalibrateGyroscopeRead,
+    SelfTest(usize),
+}
+
+/// Convert big-endian accelerometer output to mg.
+fn accelerometer_mg(data: &[u8]) -> [i32; 3] {
+    let axis = |i: usize| i16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as i32;
+    [axis(0), axis(1), axis(2)].map(|raw| raw * 1000 / ACCEL_LSB_PER_G)
+}
+
+/// Read big-endian gyroscope output.
+fn raw_gyroscope(data: &[u8]) -> [i16; 3] {
+    let axis = |i: usize| i16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+    [axis(0), axis(1), axis(2)]
+}
+
+/// Convert raw gyroscope output to mdps.
+fn gyroscope_mdps(raw: [i16; 3]) -> [i32; 3] {
+    raw.map(|raw| raw as i32 * 10_000 / GYRO_LSB_PER_10DPS)
+}
+
+/// Convert the AK8963 `HXL` to `ST2` registers to nT on the accelerometer's
+/// axes, applying the sensitivity adjustment `asa`. Returns `None` if the
+/// measurement overflowed.
+fn magnetometer_nt(data: &[u8], asa: [u8; 3]) -> Option<[i32; 3]> {
+    if data[6] & ST2_HOFL != 0 {
+        return None;
+    }
+    let axis = |i: usize| {
+        let raw = i16::from_le_bytes([data[2 * i], data[2 * i + 1]]) as i32;
+        // Hadj = H * ((ASA - 128) / 256 + 1)
+        raw * (asa[i] as i32 + 128) * MAG_NT_PER_LSB / 256
+    };
+    Some([axis(1), axis(0), -axis(2)])
+}
+
+pub struct Mpu9250<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    configured: Cell<bool>,
+    /// AK8963 sensitivity adjustment values.
+    asa: Cell<[u8; 3]>,
+    /// Magnetometer hard-iron offset in nT.
+    hard_iron: Cell<[i32; 3]>,
+    calibration_count: Cell<usize>,
+    calibration_min: Cell<[i32; 3]>,
+    calibration_max: Cell<[i32; 3]>,
+    gyro_calibration: GyroCalibration,
+    self_test_codes: Cell<[u8; 3]>,
+    self_test_count: Cell<usize>,
+    self_test_sum: Cell<[i32; 3]>,
+    /// Average without the self-test stimulus, once it has been measured.
+    self_test_baseline: OptionalCell<[i32; 3]>,
+    self_test_passed: Cell<bool>,
+    nine_dof_client: OptionalCell<&'a dyn NineDofClient>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Mpu9250<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        Mpu9250 {
+            i2c,
+            alarm,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            configured: Cell::new(false),
+            asa: Cell::new([128; 3]),
+            hard_iron: Cell::new([0; 3]),
+            calibration_count: Cell::new(0),
+            calibration_min: Cell::new([0; 3]),
+            calibration_max: Cell::new([0; 3]),
+            gyro_calibration: GyroCalibration::new(),
+            self_test_codes: Cell::new([0; 3]),
+            self_test_count: Cell::new(0),
+            self_test_sum: Cell::new([0; 3]),
+            self_test_baseline: OptionalCell::empty(),
+            self_test_passed: Cell::new(false),
+            nine_dof_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Configure the MPU-9250 and the AK8963 behind it.
+    pub fn configure(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.configured.set(false);
+        self.i2c.enable();
+        self.configure_step(0, 0).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    /// Start `phase` of configuration step `step`.
+    fn configure_step(&self, step: usize, phase: u8) -> Result<(), ErrorCode> {
+        self.state.set(State::Configure { step, phase });
+        self.start_step(CONFIGURE[step], phase)
+    }
+
+    /// Start `phase` of `step`.
+    fn start_step(&self, step: Step, phase: u8) -> Result<(), ErrorCode> {
+        match (step, phase) {
+            (Step::CheckIdentity, _) => self.read(REG_WHO_AM_I, 1),
+            (Step::Write(register, value), _) => self.write(&[register, value]),
+            (Step::Wait(ms), _) => {
+                self.wait_ms(ms);
+                Ok(())
+            }
+            (Step::ReadSelfTestCodes, _) => self.read(REG_SELF_TEST_X_GYRO, 3),
+            (Step::SampleGyroscope, _) => self.read(REG_GYRO_XOUT_H, 6),
+            (Step::AkWrite(_, value), 0) => self.write(&[REG_I2C_SLV0_DO, value]),
+            (Step::AkWrite(register, _), 1) => {
+                self.write(&[REG_I2C_SLV0_ADDR, AK8963_ADDR, register, I2C_SLV_EN | 1])
+            }
+            (Step::AkRead(register, len), 0) => self.write(&[
+                REG_I2C_SLV0_ADDR,
+                I2C_SLV_READ | AK8963_ADDR,
+                register,
+                I2C_SLV_EN | len as u8,
+            ]),
+            (Step::AkRead(_, len), 2) => self.read(REG_EXT_SENS_DATA_00, len),
+            (Step::AkPoll, _) => self.write(&[
+                REG_I2C_SLV0_ADDR,
+                I2C_SLV_READ | AK8963_ADDR,
+                AK_HXL,
+                I2C_SLV_EN | AK_DATA_LEN as u8,
+            ]),
+            // The remaining phases wait for slave 0.
+            _ => {
+                self.wait_ms(AUX_WAIT_MS);
+                Ok(())
+            }
+        }
+    }
+
+    /// Move on from `phase` of configuration step `step`, which read `data`.
+    fn configure_done(&self, step: usize, phase: u8, data: &[u8]) -> Result<(), ErrorCode> {
+        match CONFIGURE[step] {
+            Step::CheckIdentity => {
+                if data[0] != WHO_AM_I_MPU9250 && data[0] != WHO_AM_I_MPU9255 {
+                    return Err(ErrorCode::NODEVICE);
+                }
+            }
+            Step::AkRead(AK_WIA, _) if phase == 2 => {
+                if data[0] != AK_WIA_AK8963 {
+                    return Err(ErrorCode::NODEVICE);
+                }
+            }
+            Step::AkRead(AK_ASAX, _) if phase == 2 => {
+                self.asa.set([data[0], data[1], data[2]]);
+            }
+            _ => {}
+        }
+        if phase + 1 < CONFIGURE[step].phases() {
+            self.configure_step(step, phase + 1)
+        } else if step + 1 < CONFIGURE.len() {
+            self.configure_step(step + 1, 0)
+        } else {
+            self.configured.set(true);
+            self.finish();
+            Ok(())
+        }
+    }
+
+    /// Start the gyroscope self-test. Completion is reported through the
+    /// NineDof client with `1` if the test passed and `0` otherwise.
+    pub fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.self_test_count.set(0);
+        self.self_test_sum.set([0; 3]);
+        self.self_test_baseline.clear();
+        self.i2c.enable();
+        self.self_test_step(0).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn self_test_step(&self, step: usize) -> Result<(), ErrorCode> {
+        self.state.set(State::SelfTest(step));
+        self.start_step(SELF_TEST[step], 0)
+    }
+
+    /// Move on from self-test step `step`, which read `data`.
+    fn self_test_done(&self, step: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        match SELF_TEST[step] {
+            Step::ReadSelfTestCodes => self.self_test_codes.set([data[0], data[1], data[2]]),
+            Step::SampleGyroscope => {
+                let raw = raw_gyroscope(data);
+                let mut sum = self.self_test_sum.get();
+                for axis in 0..3 {
+                    sum[axis] += raw[axis] as i32;
+                }
+                self.self_test_sum.set(sum);
+                let count = self.self_test_count.get() + 1;
+                self.self_test_count.set(count);
+                if count < SELF_TEST_SAMPLES {
+                    self.wait_ms(SELF_TEST_SAMPLE_PERIOD_MS);
+                    return Ok(());
+                }
+
+                let average = sum.map(|sum| sum / SELF_TEST_SAMPLES as i32);
+                self.self_test_count.set(0);
+                self.self_test_sum.set([0; 3]);
+                match self.self_test_baseline.take() {
+                    None => self.self_test_baseline.set(average),
+                    Some(baseline) => self.self_test_passed.set(invensense_self_test_passed(
+                        baseline,
+                        average,
+                        self.self_test_codes.get(),
+                        SELF_TEST_LSB_PER_10DPS,
+                    )),
+                }
+            }
+            _ => {}
+        }
+        if step + 1 < SELF_TEST.len() {
+            self.self_test_step(step + 1)
+        } else {
+            let passed = self.self_test_passed.get();
+            self.finish();
+            self.nine_dof_client
+                .map(|client| client.callback(passed as usize, 0, 0));
+            Ok(())
+        }
+    }
+
+    /// Abort the self-test, which may have left the device configured for
+    /// it, and report the failure.
+    fn self_test_failed(&self) {
+        self.configured.set(false);
+        self.report(None);
+    }
+
+    /// Start a zero-rate offset calibration over `samples` gyroscope
+    /// readings. The device must be kept still until the NineDof client is
+    /// called with the measured offset.
+    pub fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.gyro_calibration.start(samples)?;
+        self.start_read(State::CalibrateGyroscopeRead, REG_GYRO_XOUT_H, 6)
+            .map_err(|error| {
+                self.gyro_calibration.cancel();
+                error
+            })
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            self.i2c
+                .write(buffer, bytes.len())
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn read(&self, register: u8, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = register;
+            self.i2c
+                .write_read(buffer, 1, len)
+                .map_err(|(error, buffer)| {
+                    self.buffer.replace(buffer);
+                    error.into()
+                })
+        })
+    }
+
+    fn wait_ms(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn start_read(&self, state: State, register: u8, len: usize) -> Result<(), ErrorCode> {
+        if !self.configured.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(state);
+        self.i2c.enable();
+        self.read(register, len).map_err(|error| {
+            self.finish();
+            error
+        })
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+    }
+
+    fn report(&self, value: Option<[i32; 3]>) {
+        self.finish();
+        let [x, y, z] = value.unwrap_or([0; 3]);
+        self.nine_dof_client
+            .map(|client| client.callback(x as usize, y as usize, z as usize));
+    }
+
+    /// Add a calibration sample, and finish the calibration once enough have
+    /// been taken.
+    fn add_calibration_sample(&self, sample: [i32; 3]) {
+        let count = self.calibration_count.get();
+        let mut min = self.calibration_min.get();
+        let mut max = self.calibration_max.get();
+        for axis in 0..3 {
+            if count == 0 || sample[axis] < min[axis] {
+                min[axis] = sample[axis];
+            }
+            if count == 0 || sample[axis] > max[axis] {
+                max[axis] = sample[axis];
+            }
+        }
+        self.calibration_min.set(min);
+        self.calibration_max.set(max);
+        self.calibration_count.set(count + 1);
+    }
+
+    /// Add a gyroscope calibration sample, and finish the calibration once
+    /// enough have been taken.
+    fn add_gyroscope_calibration_sample(&self, data: &[u8]) {
+        if self.gyro_calibration.add_sample(raw_gyroscope(data)) {
+            self.report(Some(gyroscope_mdps(self.gyro_calibration.bias())));
+        } else {
+            self.state.set(State::CalibrateGyroscopeWait);
+            self.wait_ms(SAMPLE_PERIOD_MS);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Mpu9250<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let mut data = [0; BUFFER_SIZE];
+        data.copy_from_slice(&buffer[..BUFFER_SIZE]);
+        self.buffer.replace(buffer);
+        let state = self.state.get();
+
+        if status.is_err() {
+            match state {
+                State::Configure { .. } => self.finish(),
+                State::SelfTest(_) => self.self_test_failed(),
+                State::CalibrateGyroscopeRead => {
+                    self.gyro_calibration.cancel();
+                    self.report(None);
+                }
+                _ => self.report(None),
+            }
+            return;
+        }
+
+        match state {
+            State::Configure { step, phase } => {
+                if self.configure_done(step, phase, &data).is_err() {
+                    self.finish();
+                }
+            }
+            State::ReadAccelerometer => self.report(Some(accelerometer_mg(&data))),
+            State::ReadGyroscope => {
+                let raw = self.gyro_calibration.correct(raw_gyroscope(&data));
+                self.report(Some(gyroscope_mdps(raw)));
+            }
+            State::ReadMagnetometer => {
+                let hard_iron = self.hard_iron.get();
+                let field = magnetometer_nt(&data, self.asa.get())
+                    .map(|field| [0, 1, 2].map(|axis| field[axis] - hard_iron[axis]));
+                self.report(field);
+            }
+            State::CalibrateRead => {
+                // Overflowed samples are skipped.
+                if let Some(sample) = magnetometer_nt(&data, self.asa.get()) {
+                    self.add_calibration_sample(sample);
+                }
+                if self.calibration_count.get() < CALIBRATION_SAMPLES {
+                    self.state.set(State::CalibrateWait);
+                    self.wait_ms(CALIBRATION_INTERVAL_MS);
+                } else {
+                    let min = self.calibration_min.get();
+                    let max = self.calibration_max.get();
+                    let offset = [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2);
+                    self.hard_iron.set(offset);
+                    self.report(Some(offset));
+                }
+            }
+            State::CalibrateGyroscopeRead => self.add_gyroscope_calibration_sample(&data),
+            State::SelfTest(step) => {
+                if self.self_test_done(step, &data).is_err() {
+                    self.self_test_failed();
+                }
+            }
+            State::Idle | State::CalibrateWait | State::CalibrateGyroscopeWait => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> time::AlarmClient for Mpu9250<'a, A, I> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Configure { step, phase } => {
+                if self.configure_done(step, phase, &[]).is_err() {
+                    self.finish();
+                }
+            }
+            State::CalibrateWait => {
+                self.state.set(State::CalibrateRead);
+                if self.read(REG_EXT_SENS_DATA_00, AK_DATA_LEN).is_err() {
+                    self.report(None);
+                }
+            }
+            State::CalibrateGyroscopeWait => {
+                self.state.set(State::CalibrateGyroscopeRead);
+                if self.read(REG_GYRO_XOUT_H, 6).is_err() {
+                    self.gyro_calibration.cancel();
+                    self.report(None);
+                }
+            }
+            State::SelfTest(step) => {
+                // Sampling waits between samples, and the other steps wait
+                // for the gyroscope to settle.
+                let result = match SELF_TEST[step] {
+                    Step::SampleGyroscope => self.start_step(Step::SampleGyroscope, 0),
+                    _ => self.self_test_done(step, &[]),
+                };
+                if result.is_err() {
+                    self.self_test_failed();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> NineDof<'a> for Mpu9250<'a, A, I> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.nine_dof_client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadAccelerometer, REG_ACCEL_XOUT_H, 6)
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadGyroscope, REG_GYRO_XOUT_H, 6)
+    }
+
+    fn read_magnetometer(&self) -> Result<(), ErrorCode> {
+        self.start_read(State::ReadMagnetometer, REG_EXT_SENS_DATA_00, AK_DATA_LEN)
+    }
+
+    fn calibrate_magnetometer(&self) -> Result<(), ErrorCode> {
+        self.calibration_count.set(0);
+        self.start_read(State::CalibrateRead, REG_EXT_SENS_DATA_00, AK_DATA_LEN)
+    }
+
+    fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        self.run_gyroscope_self_test()
+    }
+
+    fn calibrate_gyroscope(&self, samples: usize) -> Result<(), ErrorCode> {
+        self.calibrate_gyroscope(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        readings: RefCell<Vec<(usize, usize, usize)>>,
+    }
+
+    impl NineDofClient for Client {
+        fn callback(&self, x: usize, y: usize, z: usize) {
+            self.readings.borrow_mut().push((x, y, z));
+        }
+    }
+
+    type Device = Mpu9250<'static, MockAlarm<'static>, MockI2c>;
+
+    /// A measurement in the AK8963 output format.
+    fn measurement(x: i16, y: i16, z: i16) -> Vec<u8> {
+        let mut data = Vec::new();
+        for axis in [x, y, z] {
+            data.extend_from_slice(&axis.to_le_bytes());
+        }
+        data.push(0x10);
+        data
+    }
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockAlarm<'static>,
+        &'static Device,
+        &'static Client,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let client: &'static Client = Box::leak(Box::default());
+        let mpu9250 = Box::leak(Box::new(Mpu9250::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(mpu9250);
+        mpu9250.set_client(client);
+        (i2c, alarm, mpu9250, client)
+    }
+
+    /// Gyroscope output, big-endian.
+    fn gyroscope(raw: [i16; 3]) -> Vec<u8> {
+        raw.iter().flat_map(|axis| axis.to_be_bytes()).collect()
+    }
+
+    /// Run the configuration, with the given AK8963 sensitivity adjustment
+    /// values.
+    fn configure(i2c: &MockI2c, alarm: &MockAlarm, mpu9250: &Device, asa: [u8; 3]) {
+        assert_eq!(mpu9250.configure(), Ok(()));
+        assert_eq!(i2c.complete(mpu9250, &[WHO_AM_I_MPU9250]), [REG_WHO_AM_I]);
+        for _ in 0..8 {
+            i2c.complete(mpu9250, &[]);
+        }
+        // The AK8963 is identified through slave 0.
+        assert_eq!(i2c.complete(mpu9250, &[]), [0x25, 0x8C, 0x00, 0x81]);
+        alarm.fire();
+        assert_eq!(i2c.complete(mpu9250, &[AK_WIA_AK8963]), [0x49]);
+        // Power down, then switch to Fuse ROM access.
+        for mode in [CNTL1_POWER_DOWN, CNTL1_FUSE_ROM] {
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x63, mode]);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x25, 0x0C, 0x0A, 0x81]);
+            alarm.fire();
+        }
+        assert_eq!(i2c.complete(mpu9250, &[]), [0x25, 0x8C, 0x10, 0x83]);
+        alarm.fire();
+        assert_eq!(i2c.complete(mpu9250, &asa), [0x49]);
+        for mode in [CNTL1_POWER_DOWN, CNTL1_CONTINUOUS_100HZ_16BIT] {
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x63, mode]);
+            i2c.complete(mpu9250, &[]);
+            alarm.fire();
+        }
+        // Slave 0 is left copying every measurement.
+        assert_eq!(i2c.complete(mpu9250, &[]), [0x25, 0x8C, 0x03, 0x87]);
+        assert!(!i2c.busy());
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            accelerometer_mg(&[0x20, 0x00, 0xE0, 0x00, 0x40, 0x00]),
+            [1000, -1000, 2000]
+        );
+        assert_eq!(
+            gyroscope_mdps(raw_gyroscope(&[0x00, 0x83, 0xFF, 0x7D, 0x00, 0x00])),
+            [2000, -2000, 0]
+        );
+        // The axes are turned to the accelerometer's, and adjustment values
+        // of 0, 128 and 255 are factors of 0.5, 1 and about 1.5.
+        let field = magnetometer_nt(&measurement(100, 200, 300), [128, 0, 255]).unwrap();
+        assert_eq!(field, [15_000, 15_000, -67_324]);
+        let mut overflow = measurement(0, 0, 0);
+        overflow[6] |= ST2_HOFL;
+        assert_eq!(magnetometer_nt(&overflow, [128; 3]), None);
+    }
+
+    #[test]
+    fn configures_ak8963_through_slave_0() {
+        let (i2c, alarm, mpu9250, client) = setup();
+        assert_eq!(mpu9250.read_magnetometer(), Err(ErrorCode::OFF));
+        configure(i2c, alarm, mpu9250, [0, 0, 0]);
+
+        assert_eq!(mpu9250.read_magnetometer(), Ok(()));
+        assert_eq!(mpu9250.read_accelerometer(), Err(ErrorCode::BUSY));
+        // Half sensitivity, in the accelerometer's axes.
+        assert_eq!(i2c.complete(mpu9250, &measurement(-100, 100, 200)), [0x49]);
+        assert_eq!(
+            *client.readings.borrow(),
+            [(7500, -7500i32 as usize, -15000i32 as usize)]
+        );
+    }
+
+    #[test]
+    fn hard_iron_calibration() {
+        let (i2c, alarm, mpu9250, client) = setup();
+        configure(i2c, alarm, mpu9250, [128; 3]);
+
+        assert_eq!(mpu9250.calibrate_magnetometer(), Ok(()));
+        for sample in 0..CALIBRATION_SAMPLES as i16 {
+            if sample > 0 {
+                alarm.fire();
+            }
+            // Turning the device sweeps each axis around the offset.
+            let swing = sample % 21 - 10;
+            i2c.complete(mpu9250, &measurement(40 + swing, -20 + swing, 60 - swing));
+        }
+        assert!(!alarm.is_armed());
+        // In the accelerometer's axes and in nT, at 150 nT per count.
+        let offset = (-3000i32 as usize, 6000, -9000i32 as usize);
+        assert_eq!(*client.readings.borrow(), [offset]);
+
+        // The offset is removed from readings.
+        assert_eq!(mpu9250.read_magnetometer(), Ok(()));
+        i2c.complete(mpu9250, &measurement(40, -20, 60));
+        assert_eq!(client.readings.borrow()[1], (0, 0, 0));
+    }
+
+    #[test]
+    fn gyroscope_calibration_corrects_readings() {
+        let (i2c, alarm, mpu9250, client) = setup();
+        assert_eq!(mpu9250.calibrate_gyroscope(4), Err(ErrorCode::OFF));
+        configure(i2c, alarm, mpu9250, [128; 3]);
+
+        assert_eq!(mpu9250.calibrate_gyroscope(0), Err(ErrorCode::INVAL));
+        assert_eq!(mpu9250.calibrate_gyroscope(4), Ok(()));
+        assert_eq!(mpu9250.read_gyroscope(), Err(ErrorCode::BUSY));
+        // Noise around a bias of 10, -20 and 2 dps.
+        let bias = [655, -1310, 131];
+        let noise = [[3, -3, 1], [-3, 3, -1], [5, 0, 2], [-5, 0, -2]];
+        for (sample, noise) in noise.iter().enumerate() {
+            if sample > 0 {
+                assert_eq!(alarm.dt(), Some(SAMPLE_PERIOD_MS));
+                alarm.fire();
+            }
+            let raw = [0, 1, 2].map(|axis| bias[axis] + noise[axis]);
+            assert_eq!(i2c.complete(mpu9250, &gyroscope(raw)), [0x43]);
+        }
+        assert!(!i2c.busy());
+        assert_eq!(alarm.dt(), None);
+        assert_eq!(
+            *client.readings.borrow(),
+            [(10_000, -20_000i32 as usize, 2000)]
+        );
+
+        // Later readings have the bias removed.
+        assert_eq!(mpu9250.read_gyroscope(), Ok(()));
+        i2c.complete(mpu9250, &gyroscope([655 + 6550, -1310, 131 - 655]));
+        assert_eq!(
+            client.readings.borrow()[1],
+            (100_000, 0, -10_000i32 as usize)
+        );
+    }
+
+    #[test]
+    fn gyroscope_self_test() {
+        let (i2c, alarm, mpu9250, client) = setup();
+        assert_eq!(mpu9250.run_gyroscope_self_test(), Err(ErrorCode::OFF));
+        configure(i2c, alarm, mpu9250, [128; 3]);
+
+        // Code 71 is a factory response of 40 dps, so 21 dps passes and 19
+        // dps fails.
+        let baseline = [131, -262, 0];
+        let samples = |raw: [i16; 3]| {
+            for sample in 0..SELF_TEST_SAMPLES {
+                if sample > 0 {
+                    assert_eq!(alarm.dt(), Some(SELF_TEST_SAMPLE_PERIOD_MS));
+                    alarm.fire();
+                }
+                assert_eq!(i2c.complete(mpu9250, &gyroscope(raw)), [0x43]);
+            }
+        };
+        for dps in [21, 19] {
+            assert_eq!(mpu9250.run_gyroscope_self_test(), Ok(()));
+            assert_eq!(mpu9250.read_gyroscope(), Err(ErrorCode::BUSY));
+            assert_eq!(i2c.complete(mpu9250, &[71; 3]), [0x00]);
+            // 1 kHz and ±250 dps.
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x1A, 0x02]);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x19, 0x00]);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x1B, 0x00]);
+            alarm.fire();
+            samples(baseline);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x1B, 0xE0]);
+            alarm.fire();
+            samples(baseline.map(|raw| raw + dps * 131));
+            // The configuration is restored.
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x1B, 0x08]);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x19, 0x09]);
+            assert_eq!(i2c.complete(mpu9250, &[]), [0x1A, 0x03]);
+            assert!(!i2c.busy());
+            assert_eq!(alarm.dt(), None);
+        }
+        assert_eq!(*client.readings.borrow(), [(1, 0, 0), (0, 0, 0)]);
+    }
+
+    #[test]
+    fn failed_self_test_needs_configuring_again() {
+        let (i2c, alarm, mpu9250, client) = setup();
+        configure(i2c, alarm, mpu9250, [128; 3]);
+
+        assert_eq!(mpu9250.run_gyroscope_self_test(), Ok(()));
+        i2c.complete(mpu9250, &[71; 3]);
+        i2c.fail(mpu9250, i2c::Error::DataNak);
+        assert!(!i2c.busy());
+        assert_eq!(*client.readings.borrow(), [(0, 0, 0)]);
+        assert_eq!(mpu9250.read_gyroscope(), Err(ErrorCode::OFF));
+    }
+}
@@ -4,6 +4,9 @@
 
 //! Provides userspace with virtualized access to 9DOF sensors.
 //!
+//! The syscall interface is described in [ninedof.md](https://github.com/tock/tock/tree/master/doc/syscalls/60004_ninedof.md),
+//! including which sensors support the calibration and self-test commands.
+//!
 //! Usage
 //! -----
 //!
@@ -37,7 +40,10 @@ pub enum NineDofCommand {
     Exists,
     ReadAccelerometer,
     ReadMagnetometer,
+    CalibrateMagnetometer,
     ReadGyroscope,
+    GyroscopeSelfTest,
+    CalibrateGyroscope,
 }
 
 pub struct App {
@@ -109,7 +115,7 @@ impl<'a> NineDof<'a> {
             })
     }
 
-    fn call_driver(&self, command: NineDofCommand, _: usize) -> Result<(), ErrorCode> {
+    fn call_driver(&self, command: NineDofCommand, arg1: usize) -> Result<(), ErrorCode> {
         match command {
             NineDofCommand::ReadAccelerometer => {
                 let mut data = Err(ErrorCode::NODEVICE);
@@ -141,6 +147,36 @@ impl<'a> NineDof<'a> {
                 }
                 data
             }
+            NineDofCommand::CalibrateMagnetometer => {
+                let mut data = Err(ErrorCode::NODEVICE);
+                for driver in self.drivers.iter() {
+                    data = driver.calibrate_magnetometer();
+                    if data == Ok(()) {
+                        break;
+                    }
+                }
+                data
+            }
+            NineDofCommand::GyroscopeSelfTest => {
+                let mut data = Err(ErrorCode::NODEVICE);
+                for driver in self.drivers.iter() {
+                    data = driver.run_gyroscope_self_test();
+                    if data == Ok(()) {
+                        break;
+                    }
+                }
+                data
+            }
+            NineDofCommand::CalibrateGyroscope => {
+                let mut data = Err(ErrorCode::NODEVICE);
+                for driver in self.drivers.iter() {
+                    data = driver.calibrate_gyroscope(arg1);
+                    if data == Ok(()) {
+                        break;
+                    }
+                }
+                data
+            }
             _ => Err(ErrorCode::NOSUPPORT),
         }
     }
@@ -206,9 +242,33 @@ impl SyscallDriver for NineDof<'_> {
             // Single magnetometer reading.
             100 => self.enqueue_command(NineDofCommand::ReadMagnetometer, arg1, processid),
 
+            // Magnetometer hard-iron calibration. The device should be turned
+            // through every orientation until the upcall reports the offset.
+            101 => self.enqueue_command(NineDofCommand::CalibrateMagnetometer, arg1, processid),
+
             // Single gyroscope reading.
             200 => self.enqueue_command(NineDofCommand::ReadGyroscope, arg1, processid),
 
+            // Gyroscope self-test. The upcall reports 1 on pass, 0 on fail.
+            // Only some sensors support this and the calibration below.
+            201 => self.enqueue_command(NineDofCommand::GyroscopeSelfTest, arg1, processid),
+
+            // Gyroscope zero-rate calibration over `arg1` samples. The upcall
+            // reports the measured per-axis offset.
+            202 => self.enqueue_command(NineDofCommand::CalibrateGyroscope, arg1, processid),
+
+            // Gyroscope dead band of `arg1`, in the units of gyroscope
+            // readings. This takes effect immediately, without an upcall.
+            203 => {
+                let mut result = Err(ErrorCode::NODEVICE);
+                for driver in self.drivers.iter() {
+                    if driver.set_gyroscope_dead_band(arg1) == Ok(()) {
+                        result = Ok(());
+                    }
+                }
+                CommandReturn::from(result)
+            }
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,430 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Closed-loop heater control from a temperature sensor, for 3D printer
+//! beds, incubators or sous-vide cookers.
+//!
+//! Every control period the temperature is read, usually from an NTC
+//! thermistor through `adc_temperature`, and a PID controller turns the
+//! error from the setpoint into a duty cycle. With [HeaterMode::Pwm] the
+//! heater GPIO is on for that fraction of the next period, which suits the
+//! slow response of heaters and the relays or MOSFETs driving them. With
+//! [HeaterMode::BangBang] the heater is fully on below the setpoint and off
+//! at or above it, and the gains are not used.
+//!
+//! The controller works in integers, with temperatures in tenths of a
+//! degree, duty cycles in tenths of a percent (0 to 1000) and time in
+//! milliseconds. The gains are Q8.8 fixed point:
+//!
+//! - `kp`: duty per tenth of a degree of error.
+//! - `ki`: duty per tenth of a degree of error and second.
+//! - `kd`: duty per tenth of a degree per second of change. It acts on the
+//!   measurement rather than the error, so a setpoint step does not kick
+//!   the output.
+//!
+//! The integral term is clamped to the duty range, so it cannot wind up
+//! while the heater is saturated, for example while heating to a new
+//! setpoint, and overshoot once the setpoint is reached.
+//!
+//! The heater is turned off while no setpoint is set, and whenever the
+//! temperature cannot be read.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: set the setpoint to argument 1, a signed number of tenths of a
+//!   degree Celsius, and start controlling the heater. Setpoints above the
+//!   maximum the board allows fail with `INVAL`.
+//! * `2`: stop controlling the heater, and turn it off
+//! * `3`: the last temperature read, in tenths of a degree Celsius. Fails
+//!   with `FAIL` if the last reading failed, or none has been made yet.
+//! * `4`: the current duty cycle, in tenths of a percent
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let heater = components::ntc_pid_heater::PidHeaterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ntc_pid_heater::DRIVER_NUM,
+//!     thermistor,
+//!     &nrf52840_peripherals.gpio_port[HEATER_PIN],
+//!     mux_alarm,
+//!     capsules_extra::ntc_pid_heater::PidGains {
+//!         kp: 40 << 8,
+//!         ki: 1 << 8,
+//!         kd: 200 << 8,
+//!     },
+//!     capsules_extra::ntc_pid_heater::HeaterMode::Pwm,
+//!     1000,
+//!     1200,
+//! )
+//! .finalize(components::pid_heater_component_static!(
+//!     capsules_extra::adc_temperature::AdcTemperature<'static, nrf52840::adc::Adc>,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::PidHeater as usize;
+
+/// Full duty cycle, in tenths of a percent.
+pub const DUTY_MAX: u16 = 1000;
+
+/// Gains of the PID controller, in Q8.8 fixed point.
+#[derive(Clone, Copy, Debug)]
+pub struct PidGains {
+    pub kp: i32,
+    pub ki: i32,
+    pub kd: i32,
+}
+
+/// How the heater output is driven.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeaterMode {
+    /// On below the setpoint, off at or above it.
+    BangBang,
+    /// On for the duty cycle's fraction of each control period.
+    Pwm,
+}
+
+/// The state of a discrete PID controller.
+#[derive(Clone, Copy)]
+struct Pid {
+    gains: PidGains,
+    /// The integral term, in Q8.8 duty.
+    integral: i64,
+    /// The previous measurement, for the derivative term.
+    previous: Option<i32>,
+}
+
+impl Pid {
+    fn new(gains: PidGains) -> Pid {
+        Pid {
+            gains,
+            integral: 0,
+            previous: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.integral = 0;
+        self.previous = None;
+    }
+
+    /// The duty cycle for a measurement `dt_ms` after the previous one.
+    fn update(&mut self, setpoint: i32, measured: i32, dt_ms: u32) -> u16 {
+        let max = (DUTY_MAX as i64) << 8;
+        let error = (setpoint - measured) as i64;
+        let dt_ms = dt_ms.max(1) as i64;
+
+        let proportional = self.gains.kp as i64 * error;
+        self.integral += self.gains.ki as i64 * error * dt_ms / 1000;
+        self.integral = self.integral.clamp(0, max);
+        let derivative = match self.previous {
+            Some(previous) => -self.gains.kd as i64 * (measured - previous) as i64 * 1000 / dt_ms,
+            None => 0,
+        };
+        self.previous = Some(measured);
+
+        ((proportional + self.integral + derivative).clamp(0, max) >> 8) as u16
+    }
+}
+
+/// What the next alarm does.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Phase {
+    /// Turn the heater off, part way through the period.
+    HeaterOff,
+    /// Start the next period with a reading.
+    Sample,
+}
+
+pub struct PidHeater<'a, T: TemperatureDriver<'a>, H: gpio::Output, A: Alarm<'a>> {
+    sensor: &'a T,
+    heater: &'a H,
+    alarm: &'a A,
+    mode: HeaterMode,
+    period_ms: u32,
+    /// The highest setpoint userspace can ask for, in tenths of a degree.
+    max_setpoint: i32,
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+
+    pid: Cell<Pid>,
+    setpoint: OptionalCell<i32>,
+    /// The last temperature read, in tenths of a degree.
+    temperature: OptionalCell<i32>,
+    duty: Cell<u16>,
+    /// When the current period started.
+    period_start: Cell<A::Ticks>,
+    phase: Cell<Phase>,
+    running: Cell<bool>,
+}
+
+impl<'a, T: TemperatureDriver<'a>, H: gpio::Output, A: Alarm<'a>> PidHeater<'a, T, H, A> {
+    pub fn new(
+        sensor: &'a T,
+        heater: &'a H,
+        alarm: &'a A,
+        gains: PidGains,
+        mode: HeaterMode,
+        period_ms: u32,
+        max_setpoint: i32,
+        apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Self {
+        PidHeater {
+            sensor,
+            heater,
+            alarm,
+            mode,
+            period_ms,
+            max_setpoint,
+            apps,
+            pid: Cell::new(Pid::new(gains)),
+            setpoint: OptionalCell::empty(),
+            temperature: OptionalCell::empty(),
+            duty: Cell::new(0),
+            period_start: Cell::new(A::Ticks::from(0)),
+            phase: Cell::new(Phase::Sample),
+            running: Cell::new(false),
+        }
+    }
+
+    /// Start reading the temperature every control period. The heater
+    /// stays off until a setpoint is set.
+    pub fn start(&self) {
+        self.heater.clear();
+        if !self.running.replace(true) {
+            self.period_start.set(self.alarm.now());
+            self.sample();
+        }
+    }
+
+    /// Set the setpoint, in tenths of a degree, or turn the heater off with
+    /// `None`.
+    pub fn set_setpoint(&self, setpoint: Option<i32>) -> Result<(), ErrorCode> {
+        match setpoint {
+            Some(setpoint) if setpoint > self.max_setpoint => return Err(ErrorCode::INVAL),
+            Some(setpoint) => self.setpoint.set(setpoint),
+            None => {
+                self.setpoint.clear();
+                self.heater_off();
+            }
+        }
+        Ok(())
+    }
+
+    /// The last temperature read, in tenths of a degree.
+    pub fn temperature(&self) -> Option<i32> {
+        self.temperature.extract()
+    }
+
+    /// The current duty cycle, in tenths of a percent.
+    pub fn duty(&self) -> u16 {
+        self.duty.get()
+    }
+
+    fn heater_off(&self) {
+        self.duty.set(0);
+        self.heater.clear();
+        let mut pid = self.pid.get();
+        pid.reset();
+        self.pid.set(pid);
+    }
+
+    fn sample(&self) {
+        if self.sensor.read_temperature().is_err() {
+            self.temperature.clear();
+            self.heater_off();
+            self.wait_until(Phase::Sample, self.period_ms);
+        }
+    }
+
+    /// Set the alarm for `phase`, `ms` after the start of the period.
+    fn wait_until(&self, phase: Phase, ms: u32) {
+        self.phase.set(phase);
+        self.alarm
+            .set_alarm(self.period_start.get(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Drive the heater for the period that started with `temperature`.
+    fn control(&self, temperature: i32) {
+        let setpoint = match self.setpoint.extract() {
+            Some(setpoint) => setpoint,
+            None => {
+                self.heater_off();
+                self.wait_until(Phase::Sample, self.period_ms);
+                return;
+            }
+        };
+
+        let duty = match self.mode {
+            HeaterMode::BangBang if temperature < setpoint => DUTY_MAX,
+            HeaterMode::BangBang => 0,
+            HeaterMode::Pwm => {
+                let mut pid = self.pid.get();
+                let duty = pid.update(setpoint, temperature, self.period_ms);
+                self.pid.set(pid);
+                duty
+            }
+        };
+        self.duty.set(duty);
+
+        let on_ms = (self.period_ms as u64 * duty as u64 / DUTY_MAX as u64) as u32;
+        if on_ms == 0 {
+            self.heater.clear();
+        } else {
+            self.heater.set();
+        }
+        if on_ms > 0 && on_ms < self.period_ms {
+            self.wait_until(Phase::HeaterOff, on_ms);
+        } else {
+            self.wait_until(Phase::Sample, self.period_ms);
+        }
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>, H: gpio::Output, A: Alarm<'a>> TemperatureClient
+    for PidHeater<'a, T, H, A>
+{
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        match value {
+            Ok(centi_c) => {
+                let temperature = centi_c / 10;
+                self.temperature.set(temperature);
+                self.control(temperature);
+            }
+            Err(_) => {
+                self.temperature.clear();
+                self.heater_off();
+                self.wait_until(Phase::Sample, self.period_ms);
+            }
+        }
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>, H: gpio::Output, A: Alarm<'a>> time::AlarmClient
+    for PidHeater<'a, T, H, A>
+{
+    fn alarm(&self) {
+        match self.phase.get() {
+            Phase::HeaterOff => {
+                self.heater.clear();
+                self.wait_until(Phase::Sample, self.period_ms);
+            }
+            Phase::Sample => {
+                let start = self.period_start.get();
+                self.period_start
+                    .set(start.wrapping_add(self.alarm.ticks_from_ms(self.period_ms)));
+                self.sample();
+            }
+        }
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>, H: gpio::Output, A: Alarm<'a>> SyscallDriver
+    for PidHeater<'a, T, H, A>
+{
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        _: usize,
+        _processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.set_setpoint(Some(data as i32)).into(),
+            2 => self.set_setpoint(None).into(),
+            3 => match self.temperature() {
+                Some(temperature) => CommandReturn::success_u32(temperature as u32),
+                None => CommandReturn::failure(ErrorCode::FAIL),
+            },
+            4 => CommandReturn::success_u32(self.duty() as u32),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAINS: PidGains = PidGains {
+        kp: 40 << 8,
+        ki: 1 << 8,
+        kd: 200 << 8,
+    };
+
+    #[test]
+    fn pid_terms() {
+        // Proportional only: 40 per tenth of a degree.
+        let mut pid = Pid::new(PidGains {
+            ki: 0,
+            kd: 0,
+            ..GAINS
+        });
+        assert_eq!(pid.update(500, 495, 1000), 200);
+        assert_eq!(pid.update(500, 400, 1000), DUTY_MAX);
+        assert_eq!(pid.update(500, 510, 1000), 0);
+
+        // The derivative term opposes the measurement rising by a tenth of
+        // a degree per second, and ignores the setpoint changing.
+        let mut pid = Pid::new(PidGains { ki: 0, ..GAINS });
+        assert_eq!(pid.update(500, 490, 1000), 400);
+        assert_eq!(pid.update(500, 491, 1000), 160);
+        assert_eq!(pid.update(600, 491, 1000), DUTY_MAX);
+
+        // The integral term grows by 1 per tenth of a degree and second,
+        // at half a second per update.
+        let mut pid = Pid::new(PidGains {
+            kp: 0,
+            ki: 2 << 8,
+            kd: 0,
+        });
+        assert_eq!(pid.update(500, 490, 500), 10);
+        assert_eq!(pid.update(500, 490, 500), 20);
+    }
+
+    #[test]
+    fn integral_does_not_wind_up() {
+        let mut pid = Pid::new(GAINS);
+        // A long heat up from cold after a setpoint step saturates the
+        // output the whole time.
+        for _ in 0..600 {
+            assert_eq!(pid.update(600, 200, 1000), DUTY_MAX);
+        }
+        assert_eq!(pid.integral, (DUTY_MAX as i64) << 8);
+
+        // At the setpoint the integral alone holds the output at full, but
+        // it is no larger than that, so it unwinds as soon as the
+        // temperature overshoots.
+        pid.update(600, 600, 1000);
+        assert_eq!(pid.update(600, 600, 1000), DUTY_MAX);
+        assert!(pid.update(600, 605, 1000) < DUTY_MAX);
+        let mut duty = DUTY_MAX;
+        for _ in 0..10 {
+            duty = pid.update(600, 605, 1000);
+        }
+        assert!(duty < 900, "{}", duty);
+    }
+}
@@ -0,0 +1,529 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TI OPT3001 ambient light sensor, over I2C.
+//!
+//! <https://www.ti.com/lit/ds/symlink/opt3001.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The sensor measures from 0.01 lux to 83,000 lux. Its result register
+//! holds a 4-bit exponent and a 12-bit mantissa, the reading being
+//! 0.01 lux * 2^exponent * mantissa, which the driver converts to millilux
+//! in integer arithmetic. The full-scale range is picked by the sensor for
+//! every conversion with [Range::Automatic], or fixed with
+//! [Range::Manual].
+//!
+//! The INT pin is put in end-of-conversion mode: it falls when a
+//! conversion completes and stays low until the configuration register is
+//! read, which tells whether the conversion is ready. In single-shot mode,
+//! each reading starts a conversion of 100 ms or 800 ms and reads the
+//! result once INT falls; the sensor then shuts down on its own. In
+//! continuous mode the sensor keeps converting, and a reading returns the
+//! last conversion completed since the previous reading, or waits for the
+//! next one. The mode and integration time are selected with
+//! [AmbientLight::set_conversion].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let opt3001 = components::opt3001::Opt3001Component::new(
+//!     mux_i2c,
+//!     capsules_extra::opt3001::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[OPT3001_INT],
+//! )
+//! .finalize(components::opt3001_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! let ambient_light = components::isl29035::AmbientLightComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ambient_light::DRIVER_NUM,
+//!     opt3001,
+//! )
+//! .finalize(components::ambient_light_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{AmbientLight, AmbientLightClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the OPT3001 with ADDR tied to GND.
+pub const BASE_ADDR: u8 = 0x44;
+
+/// Size of the buffer the driver needs, for a register write.
+pub const BUFFER_SIZE: usize = 3;
+
+const REG_RESULT: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+const REG_LOW_LIMIT: u8 = 0x02;
+
+/// Automatic full-scale range, in `RN`.
+const CONFIG_RN_AUTOMATIC: u16 = 0xC << 12;
+/// 800 ms conversions, rather than 100 ms.
+const CONFIG_CT_800MS: u16 = 1 << 11;
+const CONFIG_M_SHUTDOWN: u16 = 0b00 << 9;
+const CONFIG_M_SINGLE_SHOT: u16 = 0b01 << 9;
+const CONFIG_M_CONTINUOUS: u16 = 0b10 << 9;
+/// Conversion ready.
+const CONFIG_CRF: u16 = 1 << 7;
+/// Latched interrupt, which holds INT low until the configuration register
+/// is read.
+const CONFIG_L: u16 = 1 << 4;
+
+/// The two most significant bits of the low limit exponent set put INT in
+/// end-of-conversion mode.
+const LOW_LIMIT_END_OF_CONVERSION: u16 = 0xC000;
+
+/// Highest valid result exponent, and highest manual range.
+const MAX_EXPONENT: u8 = 11;
+
+/// Full-scale range of the conversions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Range {
+    /// The sensor picks the range of every conversion.
+    Automatic,
+    /// A full scale of 40.95 lux * 2^n, with n up to 11.
+    Manual(u8),
+}
+
+/// Convert a result register to millilux.
+///
+/// The result is 0.01 lux * 2^exponent * mantissa, up to 83,865.6 lux,
+/// which fits a `u32` in millilux.
+fn millilux(result: u16) -> Option<u32> {
+    let exponent = (result >> 12) as u8;
+    let mantissa = (result & 0x0FFF) as u32;
+    if exponent > MAX_EXPONENT {
+        None
+    } else {
+        Some((10 * mantissa) << exponent)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Putting INT in end-of-conversion mode.
+    WriteLowLimit,
+    /// Starting single-shot or continuous conversions.
+    WriteConfig,
+    /// Waiting for INT to fall at the end of a conversion.
+    Converting,
+    ReadConfig,
+    ReadResult,
+    /// Stopping continuous conversions.
+    Shutdown,
+}
+
+pub struct Opt3001<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    interrupt_pin: &'a G,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    range: Cell<Range>,
+    continuous: Cell<bool>,
+    integration_800ms: Cell<bool>,
+    /// The low limit register puts INT in end-of-conversion mode.
+    end_of_conversion: Cell<bool>,
+    /// The sensor is converting continuously with the current settings.
+    running: Cell<bool>,
+    /// INT fell while no reading waited for it.
+    conversion_ready: Cell<bool>,
+    lux_requested: Cell<bool>,
+    millilux_requested: Cell<bool>,
+    client: OptionalCell<&'a dyn AmbientLightClient>,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Opt3001<'a, I, G> {
+    pub fn new(
+        i2c: &'a I,
+        interrupt_pin: &'a G,
+        buffer: &'static mut [u8; BUFFER_SIZE],
+    ) -> Opt3001<'a, I, G> {
+        Opt3001 {
+            i2c,
+            interrupt_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            range: Cell::new(Range::Automatic),
+            continuous: Cell::new(false),
+            integration_800ms: Cell::new(false),
+            end_of_conversion: Cell::new(false),
+            running: Cell::new(false),
+            conversion_ready: Cell::new(false),
+            lux_requested: Cell::new(false),
+            millilux_requested: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Select the full-scale range, from the next conversion the driver
+    /// starts.
+    pub fn set_range(&self, range: Range) -> Result<(), ErrorCode> {
+        if let Range::Manual(n) = range {
+            if n > MAX_EXPONENT {
+                return Err(ErrorCode::INVAL);
+            }
+        }
+        self.range.set(range);
+        // Continuous conversions are restarted with the new range by the
+        // next reading.
+        self.running.set(false);
+        Ok(())
+    }
+
+    fn idle(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Idle {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    fn write(&self, state: State, register: u8, value: u16) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let [high, low] = value.to_be_bytes();
+        buffer[..3].copy_from_slice(&[register, high, low]);
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write(buffer, 3) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    fn read(&self, state: State, register: u8) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = register;
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write_read(buffer, 1, 2) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    fn config(&self, mode: u16) -> u16 {
+        let range = match self.range.get() {
+            Range::Automatic => CONFIG_RN_AUTOMATIC,
+            Range::Manual(n) => (n as u16) << 12,
+        };
+        let integration = if self.integration_800ms.get() {
+            CONFIG_CT_800MS
+        } else {
+            0
+        };
+        range | integration | mode | CONFIG_L
+    }
+
+    /// Start conversions in the current mode, once INT is in
+    /// end-of-conversion mode.
+    fn configure(&self) -> Result<(), ErrorCode> {
+        if !self.end_of_conversion.get() {
+            return self.write(
+                State::WriteLowLimit,
+                REG_LOW_LIMIT,
+                LOW_LIMIT_END_OF_CONVERSION,
+            );
+        }
+        let mode = if self.continuous.get() {
+            CONFIG_M_CONTINUOUS
+        } else {
+            CONFIG_M_SINGLE_SHOT
+        };
+        self.write(State::WriteConfig, REG_CONFIG, self.config(mode))
+    }
+
+    /// Read the configuration register if a conversion completed, or wait
+    /// for INT.
+    fn wait_conversion(&self) -> Result<(), ErrorCode> {
+        if self.conversion_ready.replace(false) {
+            self.read(State::ReadConfig, REG_CONFIG)
+        } else {
+            self.state.set(State::Converting);
+            Ok(())
+        }
+    }
+
+    fn start_reading(&self) -> Result<(), ErrorCode> {
+        if self.continuous.get() && self.running.get() {
+            self.wait_conversion()
+        } else {
+            self.configure()
+        }
+    }
+
+    fn requested(&self) -> bool {
+        self.lux_requested.get() || self.millilux_requested.get()
+    }
+
+    /// Ask for a reading, which starts now unless the driver is busy.
+    fn request(&self, requested: &Cell<bool>) -> Result<(), ErrorCode> {
+        requested.set(true);
+        if self.state.get() != State::Idle {
+            return Ok(());
+        }
+        self.start_reading().map_err(|e| {
+            requested.set(false);
+            e
+        })
+    }
+
+    /// Return a reading to the clients that asked for it.
+    fn report(&self, millilux: Option<u32>) {
+        self.state.set(State::Idle);
+        let millilux = millilux.unwrap_or(0);
+        if self.lux_requested.replace(false) {
+            self.client
+                .map(|client| client.callback((millilux / 1000) as usize));
+        }
+        if self.millilux_requested.replace(false) {
+            self.client.map(|client| client.millilux_callback(millilux));
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Opt3001<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let value = u16::from_be_bytes([buffer[0], buffer[1]]);
+        self.buffer.replace(buffer);
+        self.i2c.disable();
+        self.state.set(State::Idle);
+
+        let status: Result<(), ErrorCode> = status.map_err(|e| e.into());
+        let result = status.and_then(|()| match state {
+            State::WriteLowLimit => {
+                self.end_of_conversion.set(true);
+                self.interrupt_pin
+                    .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+                self.configure()
+            }
+            State::WriteConfig => {
+                self.running.set(self.continuous.get());
+                // Writing the configuration register releases INT.
+                self.conversion_ready.set(false);
+                if self.requested() {
+                    self.wait_conversion()
+                } else {
+                    Ok(())
+                }
+            }
+            State::ReadConfig => {
+                if value & CONFIG_CRF != 0 {
+                    self.read(State::ReadResult, REG_RESULT)
+                } else {
+                    self.wait_conversion()
+                }
+            }
+            State::ReadResult => {
+                self.report(millilux(value));
+                Ok(())
+            }
+            State::Shutdown => {
+                self.running.set(false);
+                Ok(())
+            }
+            State::Idle | State::Converting => Ok(()),
+        });
+        if result.is_err() && self.requested() {
+            self.report(None);
+        }
+
+        if self.state.get() == State::Idle && self.requested() {
+            if self.start_reading().is_err() {
+                self.report(None);
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Opt3001<'a, I, G> {
+    fn fired(&self) {
+        if self.state.get() == State::Converting {
+            if self.read(State::ReadConfig, REG_CONFIG).is_err() {
+                self.report(None);
+            }
+        } else {
+            self.conversion_ready.set(true);
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> AmbientLight<'a> for Opt3001<'a, I, G> {
+    fn set_client(&self, client: &'a dyn AmbientLightClient) {
+        self.client.set(client);
+    }
+
+    fn read_light_intensity(&self) -> Result<(), ErrorCode> {
+        self.request(&self.lux_requested)
+    }
+
+    fn read_light_intensity_millilux(&self) -> Result<(), ErrorCode> {
+        self.request(&self.millilux_requested)
+    }
+
+    fn set_conversion(&self, continuous: bool, integration_ms: u32) -> Result<(), ErrorCode> {
+        let integration_800ms = match integration_ms {
+            100 => false,
+            800 => true,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        self.idle()?;
+        self.continuous.set(continuous);
+        self.integration_800ms.set(integration_800ms);
+        if continuous {
+            self.running.set(false);
+            self.configure()
+        } else if self.running.get() {
+            self.write(State::Shutdown, REG_CONFIG, self.config(CONFIG_M_SHUTDOWN))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct MockClient {
+        lux: Cell<Option<usize>>,
+        millilux: Cell<Option<u32>>,
+    }
+
+    impl AmbientLightClient for MockClient {
+        fn callback(&self, lux: usize) {
+            self.lux.set(Some(lux));
+        }
+
+        fn millilux_callback(&self, millilux: u32) {
+            self.millilux.set(Some(millilux));
+        }
+    }
+
+    type Sensor = Opt3001<'static, MockI2c, MockPin<'static>>;
+
+    fn setup() -> (
+        &'static MockI2c,
+        &'static MockPin<'static>,
+        &'static Sensor,
+        &'static MockClient,
+    ) {
+        let i2c: &'static MockI2c = Box::leak(Box::default());
+        let int: &'static MockPin = Box::leak(Box::default());
+        let client: &'static MockClient = Box::leak(Box::default());
+        let opt3001 = Box::leak(Box::new(Opt3001::new(
+            i2c,
+            int,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        AmbientLight::set_client(opt3001, client);
+        (i2c, int, opt3001, client)
+    }
+
+    /// Complete the transfer in progress, reading `value` if it reads.
+    fn complete(i2c: &MockI2c, opt3001: &Sensor, value: u16) {
+        let read_len = i2c.transfer().unwrap().read_len;
+        i2c.complete(opt3001, &value.to_be_bytes()[..read_len]);
+    }
+
+    #[test]
+    fn result_to_millilux() {
+        assert_eq!(millilux(0x0000), Some(0));
+        // The lowest step, 0.01 lux.
+        assert_eq!(millilux(0x0001), Some(10));
+        // 0.01 lux * 2^6 * 1346.
+        assert_eq!(millilux(0x6542), Some(861_440));
+        // Full scale, 83,865.6 lux.
+        assert_eq!(millilux(0xBFFF), Some(83_865_600));
+        assert_eq!(millilux(0xC000), None);
+    }
+
+    #[test]
+    fn single_shot_reading() {
+        let (i2c, int, opt3001, client) = setup();
+
+        assert_eq!(opt3001.read_light_intensity(), Ok(()));
+        assert_eq!(opt3001.read_light_intensity_millilux(), Ok(()));
+        // Low limit, then a single-shot conversion.
+        complete(i2c, opt3001, 0);
+        assert!(matches!(
+            int.interrupt_edge(),
+            Some(gpio::InterruptEdge::FallingEdge)
+        ));
+        complete(i2c, opt3001, 0);
+        gpio::Client::fired(opt3001);
+        complete(i2c, opt3001, 0xC290);
+        complete(i2c, opt3001, 0x6542);
+        assert_eq!(client.lux.get(), Some(861));
+        assert_eq!(client.millilux.get(), Some(861_440));
+
+        let writes = i2c.writes();
+        assert_eq!(writes[0], [REG_LOW_LIMIT, 0xC0, 0x00]);
+        assert_eq!(writes[1], [REG_CONFIG, 0xC2, 0x10]);
+        assert_eq!(writes[2], [REG_CONFIG]);
+        assert_eq!(writes[3], [REG_RESULT]);
+        assert_eq!(writes.len(), 4);
+    }
+
+    #[test]
+    fn continuous_conversions() {
+        let (i2c, _int, opt3001, client) = setup();
+
+        assert_eq!(opt3001.set_conversion(true, 50), Err(ErrorCode::INVAL));
+        assert_eq!(opt3001.set_range(Range::Manual(12)), Err(ErrorCode::INVAL));
+        assert_eq!(opt3001.set_range(Range::Manual(3)), Ok(()));
+        assert_eq!(opt3001.set_conversion(true, 800), Ok(()));
+        complete(i2c, opt3001, 0);
+        complete(i2c, opt3001, 0);
+
+        // A conversion completed before the reading, which reads it
+        // directly.
+        gpio::Client::fired(opt3001);
+        assert_eq!(opt3001.read_light_intensity_millilux(), Ok(()));
+        complete(i2c, opt3001, 0x3C90);
+        complete(i2c, opt3001, 0x3100);
+        assert_eq!(client.millilux.get(), Some(20_480));
+
+        // The next reading waits for the next conversion, without
+        // restarting conversions.
+        assert_eq!(opt3001.read_light_intensity_millilux(), Ok(()));
+        gpio::Client::fired(opt3001);
+        complete(i2c, opt3001, 0x3C90);
+        complete(i2c, opt3001, 0x3200);
+        assert_eq!(client.millilux.get(), Some(40_960));
+
+        // Back to single-shot mode, which shuts the sensor down.
+        assert_eq!(opt3001.set_conversion(false, 100), Ok(()));
+        complete(i2c, opt3001, 0);
+
+        let writes = i2c.writes();
+        assert_eq!(writes[1], [REG_CONFIG, 0x3C, 0x10]);
+        assert_eq!(writes[2], [REG_CONFIG]);
+        assert_eq!(writes[3], [REG_RESULT]);
+        assert_eq!(writes[4], [REG_CONFIG]);
+        assert_eq!(writes[5], [REG_RESULT]);
+        assert_eq!(writes[6], [REG_CONFIG, 0x30, 0x10]);
+        assert_eq!(writes.len(), 7);
+    }
+}
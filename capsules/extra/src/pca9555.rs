@@ -0,0 +1,547 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TI PCA9555 16-bit I2C GPIO expander, exposing its pins
+//! through the synchronous `hil::gpio` traits.
+//!
+//! <https://www.ti.com/lit/ds/symlink/pca9555.pdf>
+//!
+//! Like [`crate::mcp23017`], every expander pin is a [`Pca9555Pin`]
+//! implementing `gpio::Pin` and `gpio::InterruptPin`, so capsules written for
+//! on-chip GPIO can use them unchanged.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The PCA9555 has an input, output, polarity inversion and configuration
+//! register for each of its two 8-bit ports. The driver keeps a copy of the
+//! output, polarity inversion and configuration registers. The `hil::gpio`
+//! calls update the copy and return immediately; the changed registers are
+//! then written over I2C in the background. The outputs are written before
+//! the configuration, so a pin made an output after being set drives the
+//! new level straight away.
+//!
+//! The expander has no interrupt configuration: it pulls its open-drain INT
+//! line low whenever an input differs from when the inputs were last read,
+//! and releases it once they are read again. On the falling edge of INT the
+//! driver reads both input registers, compares them with the levels it read
+//! last, and calls the client of every changed input whose edge matches the
+//! one it enabled. Reading an input returns the level last read, which is
+//! current as long as INT is connected. Reading an output returns the level
+//! it was set to.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pca9555_pins = components::pca9555::Pca9555Component::new(
+//!     mux_i2c,
+//!     capsules_extra::pca9555::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[PCA9555_INT],
+//! )
+//! .finalize(components::pca9555_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! let button = pca9555_pins.io0_3();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the PCA9555, with A0 to A2 connected to ground.
+pub const BASE_ADDR: u8 = 0x20;
+
+/// Number of GPIO pins, IO0_0 to IO0_7 followed by IO1_0 to IO1_7.
+pub const NUM_PINS: usize = 16;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 3;
+
+// Register addresses of port 0. The port 1 register follows each of them,
+// and the expander moves from one to the other within a transfer.
+const REG_INPUT0: u8 = 0x00;
+const REG_OUTPUT0: u8 = 0x02;
+const REG_POLARITY0: u8 = 0x04;
+const REG_CONFIG0: u8 = 0x06;
+
+// Work waiting to be done over I2C, in the order it is done.
+const PENDING_OUTPUT: u8 = 1 << 0;
+const PENDING_POLARITY: u8 = 1 << 1;
+const PENDING_CONFIG: u8 = 1 << 2;
+const PENDING_INPUTS: u8 = 1 << 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Stopped,
+    Idle,
+    Writing,
+    /// Reading the input registers of both ports.
+    ReadingInputs,
+}
+
+pub struct Pca9555<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    interrupt_pin: &'a G,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    pending: Cell<u8>,
+    /// Set bits are inputs, as in the configuration registers.
+    inputs_mask: Cell<u16>,
+    outputs: Cell<u16>,
+    inverted: Cell<u16>,
+    rising_edges: Cell<u16>,
+    falling_edges: Cell<u16>,
+    /// The levels last read from the input registers, if they were read.
+    levels: Cell<Option<u16>>,
+    clients: [OptionalCell<&'a dyn gpio::Client>; NUM_PINS],
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Pca9555<'a, I, G> {
+    pub fn new(i2c: &'a I, interrupt_pin: &'a G, buffer: &'static mut [u8]) -> Pca9555<'a, I, G> {
+        Pca9555 {
+            i2c,
+            interrupt_pin,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Stopped),
+            pending: Cell::new(0),
+            // All pins are inputs driving high after reset.
+            inputs_mask: Cell::new(0xFFFF),
+            outputs: Cell::new(0xFFFF),
+            inverted: Cell::new(0),
+            rising_edges: Cell::new(0),
+            falling_edges: Cell::new(0),
+            levels: Cell::new(None),
+            clients: core::array::from_fn(|_| OptionalCell::empty()),
+        }
+    }
+
+    /// Write the pin configuration made so far and read the inputs.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Stopped {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.interrupt_pin.make_input();
+        self.interrupt_pin
+            .set_floating_state(gpio::FloatingState::PullUp);
+        self.interrupt_pin
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+
+        // Reading the inputs releases INT if it was left low from before the
+        // driver started.
+        self.state.set(State::Idle);
+        self.schedule(PENDING_OUTPUT | PENDING_POLARITY | PENDING_CONFIG | PENDING_INPUTS);
+        Ok(())
+    }
+
+    pub fn configure_output(&self, pin: usize) {
+        self.update(&self.inputs_mask, pin, false, PENDING_CONFIG);
+    }
+
+    /// Make `pin` an input, and read its level.
+    pub fn configure_input(&self, pin: usize) {
+        self.update(
+            &self.inputs_mask,
+            pin,
+            true,
+            PENDING_CONFIG | PENDING_INPUTS,
+        );
+    }
+
+    pub fn is_input(&self, pin: usize) -> bool {
+        self.inputs_mask.get() & (1 << pin) != 0
+    }
+
+    /// Invert the level read from `pin`, for active low inputs. Interrupt
+    /// edges are those of the inverted level.
+    pub fn set_polarity_inversion(&self, pin: usize, inverted: bool) {
+        self.update(
+            &self.inverted,
+            pin,
+            inverted,
+            PENDING_POLARITY | PENDING_INPUTS,
+        );
+    }
+
+    /// Drive `pin` high or low once it is an output.
+    pub fn write(&self, pin: usize, high: bool) {
+        self.update(&self.outputs, pin, high, PENDING_OUTPUT);
+    }
+
+    /// The level of `pin`: the last read level for an input, the driven
+    /// level for an output.
+    pub fn read(&self, pin: usize) -> bool {
+        let levels = if self.is_input(pin) {
+            self.levels.get().unwrap_or(0)
+        } else {
+            self.outputs.get()
+        };
+        levels & (1 << pin) != 0
+    }
+
+    pub fn set_pin_client(&self, pin: usize, client: &'a dyn gpio::Client) {
+        self.clients[pin].set(client);
+    }
+
+    /// Call the client of `pin` on `edge`. The expander signals every change
+    /// of an input, so this needs no I2C transfer.
+    pub fn enable_interrupt(&self, pin: usize, edge: gpio::InterruptEdge) {
+        let (rising, falling) = match edge {
+            gpio::InterruptEdge::RisingEdge => (true, false),
+            gpio::InterruptEdge::FallingEdge => (false, true),
+            gpio::InterruptEdge::EitherEdge => (true, true),
+        };
+        let bit = 1 << pin;
+        self.rising_edges
+            .set(self.rising_edges.get() & !bit | if rising { bit } else { 0 });
+        self.falling_edges
+            .set(self.falling_edges.get() & !bit | if falling { bit } else { 0 });
+    }
+
+    pub fn disable_interrupt(&self, pin: usize) {
+        let bit = 1 << pin;
+        self.rising_edges.set(self.rising_edges.get() & !bit);
+        self.falling_edges.set(self.falling_edges.get() & !bit);
+    }
+
+    fn update(&self, register: &Cell<u16>, pin: usize, set: bool, pending: u8) {
+        let bit = 1 << pin;
+        if set {
+            register.set(register.get() | bit);
+        } else {
+            register.set(register.get() & !bit);
+        }
+        self.schedule(pending);
+    }
+
+    fn schedule(&self, pending: u8) {
+        self.pending.set(self.pending.get() | pending);
+        self.run();
+    }
+
+    /// Start the next pending I2C transfer if the driver is idle.
+    fn run(&self) {
+        while self.state.get() == State::Idle && self.pending.get() != 0 {
+            let pending = self.pending.get();
+            // Lowest set bit first.
+            let work = pending & pending.wrapping_neg();
+            self.pending.set(pending & !work);
+
+            let buffer = match self.buffer.take() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let result = if work == PENDING_INPUTS {
+                buffer[0] = REG_INPUT0;
+                self.state.set(State::ReadingInputs);
+                self.i2c.write_read(buffer, 1, 2)
+            } else {
+                let (register, value) = match work {
+                    PENDING_OUTPUT => (REG_OUTPUT0, self.outputs.get()),
+                    PENDING_POLARITY => (REG_POLARITY0, self.inverted.get()),
+                    _ => (REG_CONFIG0, self.inputs_mask.get()),
+                };
+                buffer[0] = register;
+                buffer[1..3].copy_from_slice(&value.to_le_bytes());
+                self.state.set(State::Writing);
+                self.i2c.write(buffer, 3)
+            };
+            if let Err((_error, buffer)) = result {
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+        }
+    }
+
+    /// Call the clients of the inputs that changed from `previous` to
+    /// `levels` on an edge they enabled.
+    fn dispatch(&self, previous: u16, levels: u16) {
+        let changed = (previous ^ levels) & self.inputs_mask.get();
+        let fired =
+            changed & (levels & self.rising_edges.get() | !levels & self.falling_edges.get());
+        for (pin, client) in self.clients.iter().enumerate() {
+            if fired & (1 << pin) != 0 {
+                client.map(|client| client.fired());
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> I2CClient for Pca9555<'a, I, G> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let mut levels = None;
+        if status.is_ok() && self.state.get() == State::ReadingInputs {
+            levels = Some(u16::from_le_bytes([buffer[0], buffer[1]]));
+        }
+        self.buffer.replace(buffer);
+        self.state.set(State::Idle);
+
+        if let Some(levels) = levels {
+            // The first read only gives the levels to compare with.
+            if let Some(previous) = self.levels.replace(Some(levels)) {
+                self.dispatch(previous, levels);
+            }
+        }
+        self.run();
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Client for Pca9555<'a, I, G> {
+    fn fired(&self) {
+        self.schedule(PENDING_INPUTS);
+    }
+}
+
+/// One pin of a PCA9555, usable wherever an on-chip GPIO pin is.
+pub struct Pca9555Pin<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    pca9555: &'a Pca9555<'a, I, G>,
+    pin: usize,
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Pca9555Pin<'a, I, G> {
+    pub fn new(pca9555: &'a Pca9555<'a, I, G>, pin: usize) -> Pca9555Pin<'a, I, G> {
+        Pca9555Pin { pca9555, pin }
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Configure for Pca9555Pin<'a, I, G> {
+    fn configuration(&self) -> gpio::Configuration {
+        if self.pca9555.is_input(self.pin) {
+            gpio::Configuration::Input
+        } else {
+            gpio::Configuration::Output
+        }
+    }
+
+    fn make_output(&self) -> gpio::Configuration {
+        self.pca9555.configure_output(self.pin);
+        gpio::Configuration::Output
+    }
+
+    /// A pin is always an input or an output, so this makes it an input.
+    fn disable_output(&self) -> gpio::Configuration {
+        self.make_input()
+    }
+
+    fn make_input(&self) -> gpio::Configuration {
+        self.pca9555.configure_input(self.pin);
+        gpio::Configuration::Input
+    }
+
+    /// A pin is always an input or an output, so this leaves an output as it
+    /// is.
+    fn disable_input(&self) -> gpio::Configuration {
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.pca9555.configure_input(self.pin);
+    }
+
+    /// The PCA9555 has fixed 100 kOhm pull-ups, so this does nothing.
+    fn set_floating_state(&self, _state: gpio::FloatingState) {}
+
+    fn floating_state(&self) -> gpio::FloatingState {
+        gpio::FloatingState::PullUp
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Output for Pca9555Pin<'a, I, G> {
+    fn set(&self) {
+        self.pca9555.write(self.pin, true);
+    }
+
+    fn clear(&self) {
+        self.pca9555.write(self.pin, false);
+    }
+
+    fn toggle(&self) -> bool {
+        let high = self.pca9555.outputs.get() & (1 << self.pin) == 0;
+        self.pca9555.write(self.pin, high);
+        high
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Input for Pca9555Pin<'a, I, G> {
+    fn read(&self) -> bool {
+        self.pca9555.read(self.pin)
+    }
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> gpio::Interrupt<'a> for Pca9555Pin<'a, I, G> {
+    fn set_client(&self, client: &'a dyn gpio::Client) {
+        self.pca9555.set_pin_client(self.pin, client);
+    }
+
+    fn enable_interrupts(&self, mode: gpio::InterruptEdge) {
+        self.pca9555.enable_interrupt(self.pin, mode);
+    }
+
+    fn disable_interrupts(&self) {
+        self.pca9555.disable_interrupt(self.pin);
+    }
+
+    /// Interrupts are dispatched as soon as the inputs are read.
+    fn is_pending(&self) -> bool {
+        false
+    }
+}
+
+/// The 16 pins of a PCA9555.
+pub struct Pca9555Pins<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> {
+    pins: [Pca9555Pin<'a, I, G>; NUM_PINS],
+}
+
+impl<'a, I: I2CDevice, G: gpio::InterruptPin<'a>> Pca9555Pins<'a, I, G> {
+    pub fn new(pca9555: &'a Pca9555<'a, I, G>) -> Pca9555Pins<'a, I, G> {
+        Pca9555Pins {
+            pins: core::array::from_fn(|pin| Pca9555Pin::new(pca9555, pin)),
+        }
+    }
+
+    /// The expander the pins belong to.
+    pub fn device(&self) -> &'a Pca9555<'a, I, G> {
+        self.pins[0].pca9555
+    }
+
+    /// Pin `index`, where 0 to 7 are IO0_0 to IO0_7 and 8 to 15 are IO1_0 to
+    /// IO1_7.
+    pub fn pin(&self, index: usize) -> Option<&Pca9555Pin<'a, I, G>> {
+        self.pins.get(index)
+    }
+
+    pub fn io0_0(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[0]
+    }
+    pub fn io0_1(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[1]
+    }
+    pub fn io0_2(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[2]
+    }
+    pub fn io0_3(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[3]
+    }
+    pub fn io0_4(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[4]
+    }
+    pub fn io0_5(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[5]
+    }
+    pub fn io0_6(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[6]
+    }
+    pub fn io0_7(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[7]
+    }
+    pub fn io1_0(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[8]
+    }
+    pub fn io1_1(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[9]
+    }
+    pub fn io1_2(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[10]
+    }
+    pub fn io1_3(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[11]
+    }
+    pub fn io1_4(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[12]
+    }
+    pub fn io1_5(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[13]
+    }
+    pub fn io1_6(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[14]
+    }
+    pub fn io1_7(&self) -> &Pca9555Pin<'a, I, G> {
+        &self.pins[15]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockI2c, MockPin};
+    use kernel::hil::gpio::{Configure, Input, Interrupt, Output};
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct PinClient {
+        fired: Cell<usize>,
+    }
+
+    impl gpio::Client for PinClient {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+        }
+    }
+
+    type Device = Pca9555<'static, MockI2c, MockPin<'static>>;
+
+    #[test]
+    fn dispatches_changed_inputs_to_their_clients() {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let pca9555: &Device = Box::leak(Box::new(Pca9555::new(
+            i2c,
+            Box::leak(Box::new(MockPin::default())),
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        let pins = Box::leak(Box::new(Pca9555Pins::new(pca9555)));
+        let button = Box::leak(Box::new(PinClient::default()));
+        let switch = Box::leak(Box::new(PinClient::default()));
+
+        // An LED on IO1_0, a button on IO0_3 and a switch on IO1_7,
+        // configured before the driver starts.
+        let led = pins.io1_0();
+        led.make_output();
+        led.clear();
+        pins.io0_3().set_client(button);
+        pins.io0_3()
+            .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        pins.io1_7().set_client(switch);
+        pins.io1_7()
+            .enable_interrupts(gpio::InterruptEdge::EitherEdge);
+        assert!(!i2c.busy());
+
+        assert_eq!(pca9555.start(), Ok(()));
+        assert_eq!(i2c.complete(pca9555, &[]), [REG_OUTPUT0, 0xFF, 0xFE]);
+        assert_eq!(i2c.complete(pca9555, &[]), [REG_POLARITY0, 0x00, 0x00]);
+        assert_eq!(i2c.complete(pca9555, &[]), [REG_CONFIG0, 0xFF, 0xFE]);
+        assert_eq!(i2c.complete(pca9555, &[0xFF, 0x7E]), [REG_INPUT0]);
+        assert!(!i2c.busy());
+        assert!(pins.io0_3().read());
+        assert!(!pins.io1_7().read());
+        assert!(!led.read());
+
+        // Reading the inputs at start does not count as a change.
+        assert_eq!(button.fired.get(), 0);
+        assert_eq!(switch.fired.get(), 0);
+
+        // The button is pressed and the switch turned on at the same time.
+        gpio::Client::fired(pca9555);
+        assert_eq!(i2c.complete(pca9555, &[0xF7, 0xFE]), [REG_INPUT0]);
+        assert_eq!(button.fired.get(), 1);
+        assert_eq!(switch.fired.get(), 1);
+        assert!(!pins.io0_3().read());
+        assert!(pins.io1_7().read());
+
+        // The button is released, which its client did not ask for, and the
+        // LED is toggled, which is not an input.
+        assert!(led.toggle());
+        assert_eq!(i2c.complete(pca9555, &[]), [REG_OUTPUT0, 0xFF, 0xFF]);
+        gpio::Client::fired(pca9555);
+        i2c.complete(pca9555, &[0xFF, 0xFF]);
+        assert_eq!(button.fired.get(), 1);
+        assert_eq!(switch.fired.get(), 1);
+        assert!(led.read());
+    }
+}
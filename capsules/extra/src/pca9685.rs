@@ -0,0 +1,502 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the NXP PCA9685 16-channel, 12-bit PWM controller.
+//!
+//! <https://www.nxp.com/docs/en/data-sheet/PCA9685.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! Every channel counts from 0 to 4095 in each PWM period, turns on when the
+//! count reaches its `on` tick and off when it reaches its `off` tick. Bit
+//! 12 of either value turns the channel fully on or fully off. All channels
+//! share one frequency, set with [Pca9685::configure], which puts the chip
+//! to sleep to change its prescaler and restarts it.
+//!
+//! The driver keeps a copy of every channel's ticks and writes them to the
+//! chip in the background. Channels changed while a write is in progress
+//! are written together in one burst once it is done, and
+//! [Pca9685::set_all_channels] writes all 16 channels in a single 64-byte
+//! burst. Nothing is written before the first `configure`.
+//!
+//! Each channel is also a [Pca9685Pin], which implements
+//! `hil::pwm::PwmPin`. Starting a pin at a different frequency changes the
+//! frequency of every channel.
+//!
+//! [ServoController] drives hobby servos from pulse widths in microseconds.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let (pca9685, pins) = components::pca9685::Pca9685Component::new(
+//!     mux_i2c,
+//!     capsules_extra::pca9685::BASE_ADDR,
+//!     mux_alarm,
+//!     50,
+//! )
+//! .finalize(components::pca9685_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//!
+//! let servos = static_init!(
+//!     capsules_extra::pca9685::ServoController<'static, _, _>,
+//!     capsules_extra::pca9685::ServoController::new(pca9685, 500, 2500)
+//! );
+//! servos.set_angle(0, 90).unwrap();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::pwm;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default I2C address of the PCA9685, with A0 to A5 connected to ground.
+pub const BASE_ADDR: u8 = 0x40;
+
+/// Number of PWM channels.
+pub const NUM_CHANNELS: usize = 16;
+
+/// Size of the buffer the driver needs, for a write of every channel.
+pub const BUFFER_SIZE: usize = 1 + 4 * NUM_CHANNELS;
+
+/// Number of ticks in a PWM period.
+pub const TICKS_PER_PERIOD: u16 = 4096;
+
+/// Bit of the `on` and `off` ticks that turns a channel fully on or off.
+pub const FULL: u16 = 0x1000;
+/// Largest valid `on` or `off` tick.
+const MAX_TICK: u16 = FULL | (TICKS_PER_PERIOD - 1);
+
+/// Frequency of the internal oscillator.
+const OSCILLATOR_HZ: u32 = 25_000_000;
+/// Largest frequency the prescaler allows.
+const MAX_FREQUENCY_HZ: u16 = 1526;
+
+const REG_MODE1: u8 = 0x00;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_PRE_SCALE: u8 = 0xFE;
+
+const MODE1_RESTART: u8 = 0x80;
+const MODE1_AI: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+
+/// Time the oscillator takes to start after sleep.
+const OSCILLATOR_STARTUP_US: u32 = 500;
+
+pub trait Pca9685Client {
+    /// The PWM frequency was changed.
+    fn configure_done(&self, result: Result<(), ErrorCode>);
+
+    /// Channel ticks were written to the chip.
+    fn update_done(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Putting the chip to sleep, to change the prescaler.
+    Sleep,
+    Prescale,
+    Wake,
+    /// Waiting for the oscillator to start.
+    WaitOscillator,
+    Restart,
+    Update,
+}
+
+pub struct Pca9685<'a, I: I2CDevice, A: Alarm<'a>> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn Pca9685Client>,
+    state: Cell<State>,
+    /// Frequency of the last `configure`, or 0 before the first.
+    frequency_hz: Cell<u16>,
+    /// Prescaler waiting to be written.
+    prescale: OptionalCell<u8>,
+    /// Whether the chip runs with the configured prescaler.
+    configured: Cell<bool>,
+    /// The `on` and `off` ticks of each channel.
+    ticks: [Cell<(u16, u16)>; NUM_CHANNELS],
+    /// Channels whose ticks have not been written yet.
+    dirty: Cell<u16>,
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> Pca9685<'a, I, A> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Self {
+        Pca9685 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            frequency_hz: Cell::new(0),
+            prescale: OptionalCell::empty(),
+            configured: Cell::new(false),
+            ticks: Default::default(),
+            dirty: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Pca9685Client) {
+        self.client.set(client);
+    }
+
+    /// Set the PWM frequency of all channels, from 24 Hz to 1526 Hz.
+    pub fn configure(&self, frequency_hz: u16) -> Result<(), ErrorCode> {
+        let prescale = prescale(frequency_hz).ok_or(ErrorCode::INVAL)?;
+        self.frequency_hz.set(frequency_hz);
+        self.prescale.set(prescale);
+        self.run()
+    }
+
+    /// The frequency of the last `configure`, if any.
+    pub fn frequency_hz(&self) -> Option<u16> {
+        match self.frequency_hz.get() {
+            0 => None,
+            frequency_hz => Some(frequency_hz),
+        }
+    }
+
+    /// Turn `channel` on at `on_tick` and off at `off_tick` of each period.
+    pub fn set_channel_pwm(
+        &self,
+        channel: u8,
+        on_tick: u16,
+        off_tick: u16,
+    ) -> Result<(), ErrorCode> {
+        self.store(channel, on_tick, off_tick)?;
+        self.run()
+    }
+
+    /// Set the ticks of several channels, as `(channel, on_tick, off_tick)`.
+    /// Channels are checked before any is changed.
+    pub fn set_all_channels(&self, values: &[(u8, u16, u16)]) -> Result<(), ErrorCode> {
+        if values
+            .iter()
+            .any(|&(channel, on, off)| !valid(channel, on, off))
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        for &(channel, on, off) in values {
+            self.store(channel, on, off)?;
+        }
+        self.run()
+    }
+
+    fn store(&self, channel: u8, on_tick: u16, off_tick: u16) -> Result<(), ErrorCode> {
+        if !valid(channel, on_tick, off_tick) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.ticks[channel as usize].set((on_tick, off_tick));
+        self.dirty.set(self.dirty.get() | 1 << channel);
+        Ok(())
+    }
+
+    /// Start the next write, if the chip is not busy.
+    fn run(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Ok(());
+        }
+        if self.prescale.is_some() {
+            // The prescaler can only be written while the chip sleeps.
+            self.write(State::Sleep, &[REG_MODE1, MODE1_SLEEP | MODE1_AI])
+                .map(|()| self.configured.set(false))
+        } else if self.configured.get() && self.dirty.get() != 0 {
+            self.write_channels()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the dirty channels, and any channel between them, in one burst.
+    fn write_channels(&self) -> Result<(), ErrorCode> {
+        let dirty = self.dirty.get();
+        let first = dirty.trailing_zeros() as usize;
+        let last = 15 - dirty.leading_zeros() as usize;
+        let mut burst = [0; BUFFER_SIZE];
+        burst[0] = REG_LED0_ON_L + 4 * first as u8;
+        for (channel, chunk) in (first..=last).zip(burst[1..].chunks_mut(4)) {
+            let (on, off) = self.ticks[channel].get();
+            chunk[..2].copy_from_slice(&on.to_le_bytes());
+            chunk[2..].copy_from_slice(&off.to_le_bytes());
+        }
+        self.write(State::Update, &burst[..1 + 4 * (last + 1 - first)])?;
+        self.dirty.set(0);
+        Ok(())
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        match self.i2c.write(buffer, bytes.len()) {
+            Ok(()) => {
+                self.state.set(state);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.buffer.replace(buffer);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Move to the next step of a configuration, or report its failure.
+    fn configure_step(&self, result: Result<(), ErrorCode>) {
+        if let Err(e) = result {
+            self.state.set(State::Idle);
+            self.client.map(|client| client.configure_done(Err(e)));
+        }
+    }
+}
+
+/// Prescaler for `frequency_hz`, rounded to the nearest.
+fn prescale(frequency_hz: u16) -> Option<u8> {
+    if frequency_hz == 0 {
+        return None;
+    }
+    let period = TICKS_PER_PERIOD as u32 * frequency_hz as u32;
+    let prescale = (OSCILLATOR_HZ + period / 2) / period - 1;
+    // The chip does not allow prescalers below 3.
+    if (3..=0xFF).contains(&prescale) {
+        Some(prescale as u8)
+    } else {
+        None
+    }
+}
+
+fn valid(channel: u8, on_tick: u16, off_tick: u16) -> bool {
+    (channel as usize) < NUM_CHANNELS && on_tick <= MAX_TICK && off_tick <= MAX_TICK
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> I2CClient for Pca9685<'a, I, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        self.buffer.replace(buffer);
+        let status = status.map_err(|e| e.into());
+        match self.state.get() {
+            State::Sleep => {
+                let prescale = self.prescale.take().unwrap_or(0xFF);
+                self.configure_step(
+                    status.and_then(|()| self.write(State::Prescale, &[REG_PRE_SCALE, prescale])),
+                );
+            }
+            State::Prescale => {
+                self.configure_step(
+                    status.and_then(|()| self.write(State::Wake, &[REG_MODE1, MODE1_AI])),
+                );
+            }
+            State::Wake => {
+                self.configure_step(status.map(|()| {
+                    self.state.set(State::WaitOscillator);
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_us(OSCILLATOR_STARTUP_US),
+                    );
+                }));
+            }
+            State::Restart => {
+                self.state.set(State::Idle);
+                self.configured.set(status.is_ok());
+                self.client.map(|client| client.configure_done(status));
+            }
+            State::Update => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.update_done(status));
+            }
+            State::Idle | State::WaitOscillator => {}
+        }
+
+        if self.state.get() == State::Idle {
+            if let Err(e) = self.run() {
+                self.client.map(|client| client.update_done(Err(e)));
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> time::AlarmClient for Pca9685<'a, I, A> {
+    fn alarm(&self) {
+        if self.state.get() == State::WaitOscillator {
+            // Resume the channels that were running before the sleep.
+            let result = self.write(State::Restart, &[REG_MODE1, MODE1_RESTART | MODE1_AI]);
+            self.configure_step(result);
+        }
+    }
+}
+
+/// One channel of a PCA9685, as a PWM pin.
+pub struct Pca9685Pin<'a, I: I2CDevice, A: Alarm<'a>> {
+    pca9685: &'a Pca9685<'a, I, A>,
+    channel: u8,
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> Pca9685Pin<'a, I, A> {
+    pub fn new(pca9685: &'a Pca9685<'a, I, A>, channel: u8) -> Self {
+        Pca9685Pin { pca9685, channel }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> pwm::PwmPin for Pca9685Pin<'a, I, A> {
+    fn start(&self, frequency_hz: usize, duty_cycle: usize) -> Result<(), ErrorCode> {
+        let frequency_hz = u16::try_from(frequency_hz).map_err(|_| ErrorCode::INVAL)?;
+        if self.pca9685.frequency_hz() != Some(frequency_hz) {
+            self.pca9685.configure(frequency_hz)?;
+        }
+        match duty_cycle {
+            0 => self.pca9685.set_channel_pwm(self.channel, 0, FULL),
+            duty_cycle if duty_cycle < TICKS_PER_PERIOD as usize => {
+                self.pca9685
+                    .set_channel_pwm(self.channel, 0, duty_cycle as u16)
+            }
+            _ => self.pca9685.set_channel_pwm(self.channel, FULL, 0),
+        }
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        self.pca9685.set_channel_pwm(self.channel, 0, FULL)
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        MAX_FREQUENCY_HZ as usize
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        TICKS_PER_PERIOD as usize
+    }
+}
+
+/// Hobby servos on the channels of a PCA9685.
+///
+/// Servos are usually driven at 50 Hz, and move from one end to the other
+/// as the pulse width goes from about 1 ms to 2 ms, though many go further.
+pub struct ServoController<'a, I: I2CDevice, A: Alarm<'a>> {
+    pca9685: &'a Pca9685<'a, I, A>,
+    /// Pulse width at 0 degrees.
+    min_pulse_us: u16,
+    /// Pulse width at 180 degrees.
+    max_pulse_us: u16,
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> ServoController<'a, I, A> {
+    pub fn new(pca9685: &'a Pca9685<'a, I, A>, min_pulse_us: u16, max_pulse_us: u16) -> Self {
+        ServoController {
+            pca9685,
+            min_pulse_us,
+            max_pulse_us,
+        }
+    }
+
+    /// Send pulses of `pulse_us` microseconds on `channel`. The frequency
+    /// must have been configured.
+    pub fn set_pulse_width_us(&self, channel: u8, pulse_us: u16) -> Result<(), ErrorCode> {
+        let frequency_hz = self.pca9685.frequency_hz().ok_or(ErrorCode::OFF)?;
+        let ticks = pulse_us as u64 * frequency_hz as u64 * TICKS_PER_PERIOD as u64 / 1_000_000;
+        if ticks >= TICKS_PER_PERIOD as u64 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.pca9685.set_channel_pwm(channel, 0, ticks as u16)
+    }
+
+    /// Move the servo on `channel` to `degrees`, from 0 to 180.
+    pub fn set_angle(&self, channel: u8, degrees: u16) -> Result<(), ErrorCode> {
+        if degrees > 180 {
+            return Err(ErrorCode::INVAL);
+        }
+        let range = self.max_pulse_us as i32 - self.min_pulse_us as i32;
+        let pulse_us = self.min_pulse_us as i32 + range * degrees as i32 / 180;
+        self.set_pulse_width_us(channel, pulse_us as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type Device = Pca9685<'static, MockI2c, TestAlarm>;
+
+    fn setup() -> (&'static MockI2c, &'static TestAlarm, &'static Device) {
+        let i2c = Box::leak(Box::new(MockI2c::new()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let pca9685 = Box::leak(Box::new(Pca9685::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(pca9685);
+        (i2c, alarm, pca9685)
+    }
+
+    #[test]
+    fn configure_then_write_all_channels_in_one_burst() {
+        let (i2c, alarm, pca9685) = setup();
+        let servos = ServoController::new(pca9685, 1000, 2000);
+
+        assert_eq!(servos.set_angle(3, 90), Err(ErrorCode::OFF));
+        assert_eq!(pca9685.configure(50), Ok(()));
+        let values: Vec<(u8, u16, u16)> = (0..16)
+            .map(|channel| (channel, 0, channel as u16))
+            .collect();
+        assert_eq!(pca9685.set_all_channels(&values), Ok(()));
+        i2c.complete(pca9685, &[]);
+        i2c.complete(pca9685, &[]);
+        i2c.complete(pca9685, &[]);
+        assert_eq!(alarm.dt(), Some(OSCILLATOR_STARTUP_US));
+        alarm.fire();
+        i2c.complete(pca9685, &[]);
+        // 1.5 ms at 50 Hz is 307 ticks.
+        assert_eq!(servos.set_angle(3, 90), Ok(()));
+        i2c.complete(pca9685, &[]);
+
+        let writes = i2c.writes();
+        assert_eq!(writes[0], [REG_MODE1, 0x30]);
+        assert_eq!(writes[1], [REG_PRE_SCALE, 121]);
+        assert_eq!(writes[2], [REG_MODE1, 0x20]);
+        assert_eq!(writes[3], [REG_MODE1, 0xA0]);
+        assert_eq!(writes[4].len(), BUFFER_SIZE);
+        assert_eq!(writes[4][0], REG_LED0_ON_L);
+        assert_eq!(writes[4][1 + 4 * 5..][..4], [0, 0, 5, 0]);
+        assert_eq!(writes[4][1 + 4 * 3..][..4], [0, 0, 3, 0]);
+        assert_eq!(writes[5], [REG_LED0_ON_L + 12, 0, 0, 0x33, 0x01]);
+    }
+
+    #[test]
+    fn pwm_pins_use_the_full_on_and_off_bits() {
+        let (i2c, alarm, pca9685) = setup();
+        assert_eq!(pca9685.configure(50), Ok(()));
+        for _ in 0..3 {
+            i2c.complete(pca9685, &[]);
+        }
+        alarm.fire();
+        i2c.complete(pca9685, &[]);
+        i2c.take_writes();
+
+        let pin = Pca9685Pin::new(pca9685, 15);
+        let max = pwm::PwmPin::get_maximum_duty_cycle(&pin);
+        assert_eq!(pwm::PwmPin::start(&pin, 50, max), Ok(()));
+        i2c.complete(pca9685, &[]);
+        assert_eq!(pwm::PwmPin::start(&pin, 50, 1024), Ok(()));
+        i2c.complete(pca9685, &[]);
+        assert_eq!(pwm::PwmPin::stop(&pin), Ok(()));
+        i2c.complete(pca9685, &[]);
+
+        let writes = i2c.writes();
+        let led15 = REG_LED0_ON_L + 4 * 15;
+        assert_eq!(writes[0], [led15, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(writes[1], [led15, 0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(writes[2], [led15, 0x00, 0x00, 0x00, 0x10]);
+    }
+}
@@ -0,0 +1,234 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for passive infrared (PIR) motion sensors.
+//!
+//! A PIR sensor drives its output high when it detects motion. The output
+//! can toggle many times while someone moves in front of the sensor, so this
+//! driver debounces it with a hold time: the first rising edge reports
+//! motion, every rising edge restarts the hold timer, and motion is reported
+//! as stopped once the hold time passes with no new edge and the output is
+//! low.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pir = components::pir_motion::PirMotionComponent::new(
+//!     &nrf52840_peripherals.gpio_port[PIR_PIN],
+//!     mux_alarm,
+//!     capsules_extra::pir_motion::DEFAULT_HOLD_TIME_MS,
+//! )
+//! .finalize(components::pir_motion_component_static!(
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{MotionDetector, MotionDetectorClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Hold time used unless the board configures another one.
+pub const DEFAULT_HOLD_TIME_MS: u32 = 2000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Disabled,
+    Idle,
+    Motion,
+}
+
+pub struct PirMotion<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> {
+    pin: &'a G,
+    alarm: &'a A,
+    hold_time_ms: Cell<u32>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn MotionDetectorClient>,
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> PirMotion<'a, G, A> {
+    pub fn new(pin: &'a G, alarm: &'a A) -> PirMotion<'a, G, A> {
+        PirMotion {
+            pin,
+            alarm,
+            hold_time_ms: Cell::new(DEFAULT_HOLD_TIME_MS),
+            state: Cell::new(State::Disabled),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Set how long the sensor output must stay quiet before motion is
+    /// reported as stopped. Takes effect from the next rising edge.
+    pub fn set_hold_time_ms(&self, hold_time_ms: u32) {
+        self.hold_time_ms.set(hold_time_ms);
+    }
+
+    fn start_hold(&self) {
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(self.hold_time_ms.get()),
+        );
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> MotionDetector<'a> for PirMotion<'a, G, A> {
+    fn set_client(&self, client: &'a dyn MotionDetectorClient) {
+        self.client.set(client);
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.state.set(State::Idle);
+        self.pin.make_input();
+        self.pin.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.state.set(State::Disabled);
+        self.pin.disable_interrupts();
+        let _ = self.alarm.disarm();
+        Ok(())
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Client for PirMotion<'a, G, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Disabled => {}
+            State::Idle => {
+                self.state.set(State::Motion);
+                self.start_hold();
+                self.client.map(|client| client.motion_detected());
+            }
+            State::Motion => self.start_hold(),
+        }
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> time::AlarmClient for PirMotion<'a, G, A> {
+    fn alarm(&self) {
+        if self.state.get() != State::Motion {
+            return;
+        }
+        if self.pin.read() {
+            // The sensor is still reporting motion without having toggled.
+            self.start_hold();
+        } else {
+            self.state.set(State::Idle);
+            self.client.map(|client| client.motion_stopped());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin};
+    use kernel::hil::gpio::Interrupt;
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct Events {
+        detected: Cell<usize>,
+        stopped: Cell<usize>,
+    }
+
+    impl MotionDetectorClient for Events {
+        fn motion_detected(&self) {
+            self.detected.set(self.detected.get() + 1);
+        }
+        fn motion_stopped(&self) {
+            self.stopped.set(self.stopped.get() + 1);
+        }
+    }
+
+    type TestPir = PirMotion<'static, MockPin<'static>, MockAlarm<'static>>;
+
+    fn setup() -> (
+        &'static MockPin<'static>,
+        &'static MockAlarm<'static>,
+        &'static Events,
+        &'static TestPir,
+    ) {
+        let pin: &'static MockPin = Box::leak(Box::default());
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let events: &'static Events = Box::leak(Box::default());
+        let pir = Box::leak(Box::new(PirMotion::new(pin, alarm)));
+        pin.set_client(pir);
+        alarm.set_alarm_client(pir);
+        pir.set_client(events);
+        (pin, alarm, events, pir)
+    }
+
+    #[test]
+    fn edges_during_hold_extend_motion() {
+        let (pin, alarm, events, pir) = setup();
+        assert_eq!(pir.enable(), Ok(()));
+
+        pin.set_level(true);
+        assert_eq!(events.detected.get(), 1);
+        pin.set_level(false);
+
+        // A second edge 1.5 s in restarts the 2 s hold.
+        alarm.advance(1500);
+        pin.set_level(true);
+        pin.set_level(false);
+        alarm.advance(1500);
+        assert_eq!(events.stopped.get(), 0);
+
+        alarm.advance(500);
+        assert_eq!(events.stopped.get(), 1);
+        assert_eq!(events.detected.get(), 1);
+
+        // The next edge is a new detection.
+        pin.set_level(true);
+        assert_eq!(events.detected.get(), 2);
+    }
+
+    #[test]
+    fn output_held_high_keeps_motion() {
+        let (pin, alarm, events, pir) = setup();
+        pir.set_hold_time_ms(100);
+        assert_eq!(pir.enable(), Ok(()));
+
+        pin.set_level(true);
+        alarm.advance(100);
+        alarm.advance(100);
+        assert_eq!(events.stopped.get(), 0);
+
+        pin.set_level(false);
+        alarm.advance(100);
+        assert_eq!(events.stopped.get(), 1);
+    }
+
+    #[test]
+    fn disabled_ignores_edges() {
+        let (pin, alarm, events, pir) = setup();
+
+        pin.set_level(true);
+        assert_eq!(events.detected.get(), 0);
+
+        assert_eq!(pir.enable(), Ok(()));
+        assert_eq!(pir.enable(), Err(ErrorCode::ALREADY));
+        pin.set_level(false);
+        pin.set_level(true);
+        assert_eq!(pir.disable(), Ok(()));
+        assert!(!alarm.is_armed());
+        assert!(!pin.interrupts_enabled());
+        assert_eq!(events.detected.get(), 1);
+        assert_eq!(events.stopped.get(), 0);
+    }
+}
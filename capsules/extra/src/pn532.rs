@@ -0,0 +1,977 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the NXP PN532 NFC controller.
+//!
+//! <https://www.nxp.com/docs/en/user-guide/141520.pdf>
+//!
+//! The PN532 is connected over I2C or SPI, through the `I2CTransport` or
+//! `SpiTransport` adapters, and signals that a reply is ready on its IRQ pin.
+//! Every command is sent as a normal information frame:
+//!
+//! ```text
+//! 00 00 FF LEN LCS D4 <command> <data> DCS 00
+//! ```
+//!
+//! where `LCS` makes `LEN + LCS` zero and `DCS` makes the sum of `D4`, the
+//! command and the data zero. The PN532 first answers with an ACK frame
+//! (`00 00 FF 00 FF 00`), and then with a response frame in the same format
+//! starting with `D5` and the command code plus one. A command whose reply
+//! does not arrive in time is aborted by sending an ACK frame.
+//!
+//! The PN532 starts in low battery mode, and is woken up with the
+//! `SAMConfiguration` command before the first command. It
+//! can take a couple of milliseconds to wake up, so that command is retried
+//! if the bus reports an error.
+//!
+//! Kernel capsules, such as the [`desfire`](crate::desfire) layer, exchange
+//! APDUs with the last tag a process found through the
+//! [`Transceive`](crate::desfire::Transceive) interface.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pn532 = components::pn532::Pn532Component::new(
+//!     board_kernel,
+//!     capsules_extra::pn532::DRIVER_NUM,
+//!     pn532_transport,
+//!     &nrf52840_peripherals.gpio_port[PN532_IRQ_PIN],
+//!     mux_alarm,
+//! )
+//! .finalize(components::pn532_component_static!(
+//!     capsules_extra::pn532::I2CTransport<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_i2c::I2CDevice<'static, nrf52840::i2c::TWI>,
+//!     >,
+//!     nrf52840::gpio::GPIOPin,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! See [the syscall documentation](../../../doc/syscalls/80005_pn532.md).
+
+use core::cell::Cell;
+
+use crate::desfire::{Transceive, TransceiveClient};
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::spi;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Pn532 as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The APDU to send with command 2.
+    pub const APDU: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The UID of a tag, or the response to an APDU.
+    pub const RESPONSE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+pub const I2C_ADDRESS: u8 = 0x24;
+
+/// Bytes of a frame around the data: preamble, start code, LEN, LCS, TFI,
+/// DCS and postamble.
+const FRAME_OVERHEAD: usize = 8;
+/// Largest LEN of a normal information frame.
+const MAX_FRAME_DATA: usize = 255;
+/// Size of the buffer shared with the transport: one byte reserved for the
+/// transport followed by the largest normal information frame.
+pub const BUFFER_LEN: usize = 1 + FRAME_OVERHEAD + MAX_FRAME_DATA - 1;
+/// Largest APDU that fits in an `InDataExchange` frame, after the TFI, the
+/// command code and the target number.
+pub const MAX_APDU_LEN: usize = MAX_FRAME_DATA - 3;
+
+const ACK_FRAME: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+
+const TFI_HOST: u8 = 0xD4;
+const TFI_PN532: u8 = 0xD5;
+
+const CMD_SAM_CONFIGURATION: u8 = 0x14;
+const CMD_IN_DATA_EXCHANGE: u8 = 0x40;
+const CMD_IN_LIST_PASSIVE_TARGET: u8 = 0x4A;
+
+/// Baud rate argument of `InListPassiveTarget` for 106 kbps ISO14443A.
+const BRTY_ISO14443A: u8 = 0x00;
+
+/// How long to wait for the ACK frame.
+const ACK_TIMEOUT_MS: u32 = 10;
+/// How long to wait for a tag to enter the field when polling.
+pub const POLL_TIMEOUT_MS: u32 = 1000;
+/// How long to wait for the response to any other command.
+const RESPONSE_TIMEOUT_MS: u32 = 500;
+/// How long to wait before retrying the wake up command.
+const WAKEUP_DELAY_MS: u32 = 2;
+const WAKEUP_ATTEMPTS: u8 = 3;
+
+/// Bytes read for the response to `InListPassiveTarget`, which is enough
+/// for a 10 byte UID and a short ATS.
+const POLL_RESPONSE_LEN: usize = 64;
+
+/// A bus over which the PN532 is connected.
+///
+/// Byte 0 of the buffers passed to the transport is reserved for its own
+/// use, and the frame starts at byte 1. When reading, byte 0 holds whatever
+/// the bus returned before the frame, such as the I2C status byte.
+pub trait Transport<'a> {
+    fn set_client(&self, client: &'a dyn TransportClient);
+
+    /// Write the `len` bytes of frame starting at `buffer[1]`.
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Read `len` bytes of frame into `buffer`, starting at `buffer[1]`.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait TransportClient {
+    /// Called when a write or a read finishes.
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>);
+}
+
+/// PN532 connected over I2C. Reads start with the PN532 status byte, which
+/// lands in the reserved byte.
+pub struct I2CTransport<'a, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, I: i2c::I2CDevice> I2CTransport<'a, I> {
+    pub fn new(i2c: &'a I) -> I2CTransport<'a, I> {
+        I2CTransport {
+            i2c,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> Transport<'a> for I2CTransport<'a, I> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        // The I2C write has no header, so drop the reserved byte.
+        buffer.copy_within(1..len + 1, 0);
+        self.i2c.enable();
+        self.i2c.write(buffer, len).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.i2c.enable();
+        self.i2c.read(buffer, len + 1).map_err(|(e, buffer)| {
+            self.i2c.disable();
+            (e.into(), buffer)
+        })
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> i2c::I2CClient for I2CTransport<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        self.i2c.disable();
+        self.client
+            .map(|client| client.transfer_done(buffer, status.map_err(|e| e.into())));
+    }
+}
+
+/// SPI data write and data read operation bytes.
+const SPI_DATA_WRITE: u8 = 0x01;
+const SPI_DATA_READ: u8 = 0x03;
+
+/// PN532 connected over SPI. The reserved byte carries the SPI operation.
+///
+/// The PN532 sends and receives the least significant bit first, which the
+/// SPI HIL cannot configure, so the bits of every byte are reversed here.
+pub struct SpiTransport<'a, S: spi::SpiMasterDevice<'a>> {
+    spi: &'a S,
+    /// Write buffer used while reading.
+    tx_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: OptionalCell<&'a dyn TransportClient>,
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> SpiTransport<'a, S> {
+    pub fn new(spi: &'a S, tx_buffer: &'static mut [u8]) -> SpiTransport<'a, S> {
+        SpiTransport {
+            spi,
+            tx_buffer: TakeCell::new(tx_buffer),
+            len: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn configure(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            rate,
+        )
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> Transport<'a> for SpiTransport<'a, S> {
+    fn set_client(&self, client: &'a dyn TransportClient) {
+        self.client.set(client);
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len + 1 > buffer.len() {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        buffer[0] = SPI_DATA_WRITE;
+        buffer[..len + 1]
+            .iter_mut()
+            .for_each(|b| *b = b.reverse_bits());
+        self.len.set(0);
+        self.spi
+            .read_write_bytes(buffer, None, len + 1)
+            .map_err(|(e, buffer, _)| (e, buffer))
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let tx_buffer = match self.tx_buffer.take() {
+            Some(tx_buffer) => tx_buffer,
+            None => return Err((ErrorCode::BUSY, buffer)),
+        };
+        if len + 1 > buffer.len() || len + 1 > tx_buffer.len() {
+            self.tx_buffer.replace(tx_buffer);
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        tx_buffer[..len + 1].fill(0);
+        tx_buffer[0] = SPI_DATA_READ.reverse_bits();
+        self.len.set(len + 1);
+        self.spi
+            .read_write_bytes(tx_buffer, Some(buffer), len + 1)
+            .map_err(|(e, tx_buffer, buffer)| {
+                self.tx_buffer.replace(tx_buffer);
+                (e, buffer.unwrap())
+            })
+    }
+}
+
+impl<'a, S: spi::SpiMasterDevice<'a>> spi::SpiMasterClient for SpiTransport<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        let buffer = match read_buffer {
+            Some(read_buffer) => {
+                self.tx_buffer.replace(write_buffer);
+                read_buffer[..self.len.get()]
+                    .iter_mut()
+                    .for_each(|b| *b = b.reverse_bits());
+                read_buffer
+            }
+            None => write_buffer,
+        };
+        self.client
+            .map(|client| client.transfer_done(buffer, status));
+    }
+}
+
+/// Finishes the command frame whose data, starting with the command code,
+/// is already at `frame[6..6 + data_len]`. Returns the length of the frame.
+fn finish_frame(frame: &mut [u8], data_len: usize) -> usize {
+    let len = data_len + 1;
+    frame[..3].copy_from_slice(&[0x00, 0x00, 0xFF]);
+    frame[3] = len as u8;
+    frame[4] = (len as u8).wrapping_neg();
+    frame[5] = TFI_HOST;
+    let sum = frame[5..5 + len]
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b));
+    frame[5 + len] = sum.wrapping_neg();
+    frame[6 + len] = 0x00;
+    data_len + FRAME_OVERHEAD
+}
+
+/// Returns the index of the byte after the `00 FF` start code.
+fn start_of_frame(frame: &[u8]) -> Option<usize> {
+    frame
+        .windows(2)
+        .position(|w| w == [0x00, 0xFF])
+        .map(|i| i + 2)
+}
+
+fn is_ack(frame: &[u8]) -> bool {
+    start_of_frame(frame).map_or(false, |start| frame[start..].starts_with(&ACK_FRAME[3..5]))
+}
+
+/// Checks a response frame and returns its data, starting with the response
+/// code.
+fn parse_frame(frame: &[u8]) -> Result<&[u8], ErrorCode> {
+    let start = start_of_frame(frame).ok_or(ErrorCode::FAIL)?;
+    let (len, lcs) = match frame.get(start..start + 2) {
+        Some(&[len, lcs]) => (len, lcs),
+        _ => return Err(ErrorCode::SIZE),
+    };
+    if len.wrapping_add(lcs) != 0 || len == 0 {
+        return Err(ErrorCode::FAIL);
+    }
+    let body = frame
+        .get(start + 2..start + 3 + len as usize)
+        .ok_or(ErrorCode::SIZE)?;
+    if body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 || body[0] != TFI_PN532 {
+        return Err(ErrorCode::FAIL);
+    }
+    // Drop the TFI and the DCS.
+    Ok(&body[1..body.len() - 1])
+}
+
+/// A tag found by `InListPassiveTarget`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Target<'b> {
+    number: u8,
+    uid: &'b [u8],
+}
+
+/// Parses the response data of `InListPassiveTarget` for ISO14443A, and
+/// returns the first tag, if any.
+fn parse_passive_target(data: &[u8]) -> Result<Option<Target>, ErrorCode> {
+    match data {
+        [code, 0, ..] if *code == CMD_IN_LIST_PASSIVE_TARGET + 1 => Ok(None),
+        // Tg, SENS_RES (2 bytes), SEL_RES, NFCIDLength, NFCID1
+        [code, _, number, _, _, _, uid_len, rest @ ..]
+            if *code == CMD_IN_LIST_PASSIVE_TARGET + 1 =>
+        {
+            let uid = rest.get(..*uid_len as usize).ok_or(ErrorCode::SIZE)?;
+            Ok(Some(Target {
+                number: *number,
+                uid,
+            }))
+        }
+        _ => Err(ErrorCode::FAIL),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Wakeup,
+    Poll,
+    Exchange,
+}
+
+impl Operation {
+    fn response_timeout_ms(&self) -> u32 {
+        match self {
+            Operation::Poll => POLL_TIMEOUT_MS,
+            Operation::Wakeup | Operation::Exchange => RESPONSE_TIMEOUT_MS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Send,
+    WakeupDelay,
+    WaitAck,
+    ReadAck,
+    WaitResponse,
+    ReadResponse,
+    Abort,
+}
+
+/// Who an operation is for: a process, or the kernel client of the
+/// `desfire::Transceive` interface.
+#[derive(Clone, Copy)]
+enum Owner {
+    Process(ProcessId),
+    Kernel,
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct Pn532<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> {
+    transport: &'a T,
+    irq: &'a P,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    /// Operation requested by the process while the PN532 is woken up.
+    pending: OptionalCell<Operation>,
+    frame_len: Cell<usize>,
+    response_len: Cell<usize>,
+    /// Error to report once the abort frame is sent.
+    abort_error: OptionalCell<ErrorCode>,
+    awake: Cell<bool>,
+    wakeup_attempts: Cell<u8>,
+    /// Target number of the last tag found.
+    target: OptionalCell<u8>,
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    owner: OptionalCell<Owner>,
+    /// The APDU of a kernel exchange, which receives the response.
+    apdu: TakeCell<'static, [u8]>,
+    apdu_len: Cell<usize>,
+    transceive_client: OptionalCell<&'a dyn TransceiveClient>,
+}
+
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> Pn532<'a, T, P, A> {
+    pub fn new(
+        transport: &'a T,
+        irq: &'a P,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Pn532<'a, T, P, A> {
+        irq.make_input();
+        Pn532 {
+            transport,
+            irq,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Wakeup),
+            pending: OptionalCell::empty(),
+            frame_len: Cell::new(0),
+            response_len: Cell::new(0),
+            abort_error: OptionalCell::empty(),
+            awake: Cell::new(false),
+            wakeup_attempts: Cell::new(0),
+            target: OptionalCell::empty(),
+            apps: grant,
+            owner: OptionalCell::empty(),
+            apdu: TakeCell::empty(),
+            apdu_len: Cell::new(0),
+            transceive_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts `operation` for `owner`, waking the PN532 first if needed.
+    fn start(&self, operation: Operation, owner: Owner) -> Result<(), ErrorCode> {
+        if self.owner.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        // Check the arguments of the operation even if it has to wait.
+        self.build(operation, owner)?;
+        let result = if self.awake.get() {
+            self.send(operation)
+        } else {
+            self.pending.set(operation);
+            self.wakeup_attempts.set(0);
+            self.build(Operation::Wakeup, owner)
+                .and_then(|()| self.send(Operation::Wakeup))
+        };
+        match result {
+            Ok(()) => self.owner.set(owner),
+            Err(_) => self.pending.clear(),
+        }
+        result
+    }
+
+    /// Builds the command frame for `operation` in the buffer.
+    fn build(&self, operation: Operation, owner: Owner) -> Result<(), ErrorCode> {
+        self.buffer.map_or(Err(ErrorCode::BUSY), |buffer| {
+            let frame = &mut buffer[1..];
+            let data = &mut frame[6..];
+            let (data_len, response_len) = match operation {
+                Operation::Wakeup => {
+                    // Normal mode, 1 s timeout, use the IRQ pin.
+                    data[..4].copy_from_slice(&[CMD_SAM_CONFIGURATION, 0x01, 0x14, 0x01]);
+                    (4, FRAME_OVERHEAD + 1)
+                }
+                Operation::Poll => {
+                    data[..3].copy_from_slice(&[CMD_IN_LIST_PASSIVE_TARGET, 1, BRTY_ISO14443A]);
+                    (3, POLL_RESPONSE_LEN)
+                }
+                Operation::Exchange => {
+                    let target = self.target.extract().ok_or(ErrorCode::INVAL)?;
+                    let apdu_len = self.copy_apdu(owner, &mut data[2..])?;
+                    data[0] = CMD_IN_DATA_EXCHANGE;
+                    data[1] = target;
+                    (2 + apdu_len, BUFFER_LEN - 1)
+                }
+            };
+            self.frame_len.set(finish_frame(frame, data_len));
+            self.response_len.set(response_len);
+            Ok(())
+        })
+    }
+
+    /// Copies the APDU of `owner` into `dest`, returning its length.
+    fn copy_apdu(&self, owner: Owner, dest: &mut [u8]) -> Result<usize, ErrorCode> {
+        let processid = match owner {
+            Owner::Process(processid) => processid,
+            Owner::Kernel => {
+                let len = self.apdu_len.get();
+                return self.apdu.map_or(Err(ErrorCode::FAIL), |apdu| {
+                    if len == 0 || len > MAX_APDU_LEN || len > apdu.len() {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    dest[..len].copy_from_slice(&apdu[..len]);
+                    Ok(len)
+                });
+            }
+        };
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::APDU)
+                    .and_then(|apdu| {
+                        apdu.enter(|apdu| {
+                            if apdu.len() == 0 || apdu.len() > MAX_APDU_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            apdu.copy_to_slice(&mut dest[..apdu.len()]);
+                            Ok(apdu.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Rebuilds and sends the frame for `operation` for the current owner.
+    fn resend(&self, operation: Operation) -> Result<(), ErrorCode> {
+        let owner = self.owner.extract().ok_or(ErrorCode::FAIL)?;
+        self.build(operation, owner)
+            .and_then(|()| self.send(operation))
+    }
+
+    fn send(&self, operation: Operation) -> Result<(), ErrorCode> {
+        self.operation.set(operation);
+        self.write(self.frame_len.get())
+    }
+
+    fn write(&self, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            match self.transport.write(buffer, len) {
+                Ok(()) => {
+                    self.state.set(State::Send);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    fn read(&self, state: State, len: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            match self.transport.read(buffer, len) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Waits for the IRQ pin to go low, or for `timeout_ms` to pass.
+    fn wait(&self, state: State, timeout_ms: u32) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+        self.irq.enable_interrupts(gpio::InterruptEdge::FallingEdge);
+        if !self.irq.read() {
+            // The reply was ready before the interrupt was enabled.
+            gpio::Client::fired(self);
+        }
+    }
+
+    /// Aborts the current command by sending an ACK frame, and then reports
+    /// `error`.
+    fn abort(&self, error: ErrorCode) {
+        let written = self.buffer.map_or(false, |buffer| {
+            buffer[1..1 + ACK_FRAME.len()].copy_from_slice(&ACK_FRAME);
+            true
+        });
+        match written.then(|| self.write(ACK_FRAME.len())) {
+            Some(Ok(())) => {
+                self.state.set(State::Abort);
+                self.abort_error.set(error);
+            }
+            _ => self.finish(Err(error)),
+        }
+    }
+
+    fn finish(&self, result: Result<usize, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.pending.clear();
+        match self.owner.take() {
+            Some(Owner::Process(processid)) => {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    let (status, len) = match result {
+                        Ok(len) => (0, len),
+                        Err(e) => (into_statuscode(Err(e)), 0),
+                    };
+                    kernel_data.schedule_upcall(0, (status, len, 0)).ok();
+                });
+            }
+            Some(Owner::Kernel) => {
+                if let Some(apdu) = self.apdu.take() {
+                    self.transceive_client
+                        .map(|client| client.transceive_done(apdu, result));
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Handles the data of a response frame, returning the length reported
+    /// to the process.
+    fn response(&self, operation: Operation, data: &[u8]) -> Result<usize, ErrorCode> {
+        match operation {
+            Operation::Wakeup => match data {
+                [code, ..] if *code == CMD_SAM_CONFIGURATION + 1 => Ok(0),
+                _ => Err(ErrorCode::FAIL),
+            },
+            Operation::Poll => match parse_passive_target(data)? {
+                Some(target) => {
+                    self.target.set(target.number);
+                    self.copy_response(target.uid)
+                }
+                None => Err(ErrorCode::NODEVICE),
+            },
+            Operation::Exchange => match data {
+                [code, status, response @ ..] if *code == CMD_IN_DATA_EXCHANGE + 1 => {
+                    if status & 0x3F != 0 {
+                        Err(ErrorCode::FAIL)
+                    } else {
+                        self.copy_response(response)
+                    }
+                }
+                _ => Err(ErrorCode::FAIL),
+            },
+        }
+    }
+
+    /// Copies `data` into the response buffer of the owner, and returns the
+    /// length of `data`. A process gets as much as fits in its buffer, while
+    /// a response that does not fit in the kernel's buffer is an error.
+    fn copy_response(&self, data: &[u8]) -> Result<usize, ErrorCode> {
+        let processid = match self.owner.extract().ok_or(ErrorCode::FAIL)? {
+            Owner::Process(processid) => processid,
+            Owner::Kernel => {
+                return self.apdu.map_or(Err(ErrorCode::FAIL), |apdu| {
+                    let dest = apdu.get_mut(..data.len()).ok_or(ErrorCode::SIZE)?;
+                    dest.copy_from_slice(data);
+                    Ok(data.len())
+                });
+            }
+        };
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RESPONSE)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            let len = dest.len().min(data.len());
+                            dest[..len].copy_from_slice(&data[..len]);
+                        })
+                    })
+            })
+            .and_then(|result| result)
+            .map(|()| data.len())
+            .map_err(ErrorCode::from)
+    }
+}
+
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> TransportClient
+    for Pn532<'a, T, P, A>
+{
+    fn transfer_done(&self, buffer: &'static mut [u8], status: Result<(), ErrorCode>) {
+        let state = self.state.get();
+        let operation = self.operation.get();
+
+        if let Err(e) = status {
+            self.buffer.replace(buffer);
+            let attempts = self.wakeup_attempts.get() + 1;
+            if state == State::Send && operation == Operation::Wakeup && attempts < WAKEUP_ATTEMPTS
+            {
+                // The PN532 does not answer on the bus until it is awake.
+                self.wakeup_attempts.set(attempts);
+                self.state.set(State::WakeupDelay);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(WAKEUP_DELAY_MS));
+            } else {
+                self.finish(Err(e));
+            }
+            return;
+        }
+
+        match state {
+            State::Send => {
+                self.buffer.replace(buffer);
+                self.wait(State::WaitAck, ACK_TIMEOUT_MS);
+            }
+            State::ReadAck => {
+                let ack = is_ack(&buffer[1..1 + ACK_FRAME.len()]);
+                self.buffer.replace(buffer);
+                if ack {
+                    self.wait(State::WaitResponse, operation.response_timeout_ms());
+                } else {
+                    self.finish(Err(ErrorCode::NOACK));
+                }
+            }
+            State::ReadResponse => {
+                let len = self.response_len.get();
+                let result = parse_frame(&buffer[1..1 + len])
+                    .and_then(|data| self.response(operation, data));
+                self.buffer.replace(buffer);
+                match (operation, result) {
+                    (Operation::Wakeup, Ok(_)) => {
+                        self.awake.set(true);
+                        let started = self
+                            .pending
+                            .take()
+                            .map_or(Err(ErrorCode::FAIL), |next| self.resend(next));
+                        if let Err(e) = started {
+                            self.finish(Err(e));
+                        }
+                    }
+                    (_, result) => self.finish(result),
+                }
+            }
+            State::Abort => {
+                self.buffer.replace(buffer);
+                let error = self.abort_error.take().unwrap_or(ErrorCode::FAIL);
+                self.finish(Err(error));
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Client
+    for Pn532<'a, T, P, A>
+{
+    fn fired(&self) {
+        let result = match self.state.get() {
+            State::WaitAck => self.read(State::ReadAck, ACK_FRAME.len()),
+            State::WaitResponse => self.read(State::ReadResponse, self.response_len.get()),
+            _ => return,
+        };
+        self.irq.disable_interrupts();
+        let _ = self.alarm.disarm();
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> time::AlarmClient
+    for Pn532<'a, T, P, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::WakeupDelay => {
+                // The failed write may have changed the frame in the buffer.
+                if let Err(e) = self.resend(Operation::Wakeup) {
+                    self.finish(Err(e));
+                }
+            }
+            State::WaitAck => {
+                self.irq.disable_interrupts();
+                self.finish(Err(ErrorCode::NOACK));
+            }
+            State::WaitResponse => {
+                self.irq.disable_interrupts();
+                // No tag entering the field in time is not a failure of the
+                // PN532.
+                let error = match self.operation.get() {
+                    Operation::Poll => ErrorCode::NODEVICE,
+                    _ => ErrorCode::FAIL,
+                };
+                self.abort(error);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// APDUs from the kernel are exchanged with the last tag found by a process
+/// with command `1`, in between the commands of processes.
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> Transceive<'a>
+    for Pn532<'a, T, P, A>
+{
+    fn set_client(&self, client: &'a dyn TransceiveClient) {
+        self.transceive_client.set(client);
+    }
+
+    fn transceive(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.owner.is_some() {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        self.apdu.replace(buffer);
+        self.apdu_len.set(len);
+        self.start(Operation::Exchange, Owner::Kernel)
+            .map_err(|e| (e, self.apdu.take().unwrap()))
+    }
+}
+
+impl<'a, T: Transport<'a>, P: gpio::InterruptPin<'a>, A: Alarm<'a>> SyscallDriver
+    for Pn532<'a, T, P, A>
+{
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Poll for an ISO14443A tag.
+    /// - `2`: Send the APDU in read-only allow `0` to the last tag found.
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 => self.start(Operation::Poll, Owner::Process(processid)),
+            2 => self.start(Operation::Exchange, Owner::Process(processid)),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        result.into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `InListPassiveTarget` response for a MIFARE Classic tag with UID
+    /// DE AD BE EF.
+    const PASSIVE_TARGET_RESPONSE: [u8; 20] = [
+        0x00, 0x00, 0xFF, 0x0C, 0xF4, 0xD5, 0x4B, 0x01, 0x01, 0x00, 0x04, 0x08, 0x04, 0xDE, 0xAD,
+        0xBE, 0xEF, 0x96, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn command_frame() {
+        let mut frame = [0; 16];
+        frame[6..10].copy_from_slice(&[CMD_SAM_CONFIGURATION, 0x01, 0x14, 0x01]);
+        let len = finish_frame(&mut frame, 4);
+        assert_eq!(
+            frame[..len],
+            [0x00, 0x00, 0xFF, 0x05, 0xFB, 0xD4, 0x14, 0x01, 0x14, 0x01, 0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn ack_frame() {
+        assert!(is_ack(&ACK_FRAME));
+        // The NACK frame.
+        assert!(!is_ack(&[0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00]));
+        assert!(!is_ack(&PASSIVE_TARGET_RESPONSE[..6]));
+    }
+
+    #[test]
+    fn passive_target_uid() {
+        let data = parse_frame(&PASSIVE_TARGET_RESPONSE).unwrap();
+        assert_eq!(
+            parse_passive_target(data),
+            Ok(Some(Target {
+                number: 1,
+                uid: &[0xDE, 0xAD, 0xBE, 0xEF],
+            }))
+        );
+
+        // No tag in the field.
+        assert_eq!(parse_passive_target(&[0x4B, 0x00]), Ok(None));
+        // A UID longer than the response.
+        assert_eq!(
+            parse_passive_target(&data[..data.len() - 1]),
+            Err(ErrorCode::SIZE)
+        );
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        let mut frame = PASSIVE_TARGET_RESPONSE;
+        frame[4] ^= 1;
+        assert_eq!(parse_frame(&frame), Err(ErrorCode::FAIL));
+
+        let mut frame = PASSIVE_TARGET_RESPONSE;
+        frame[13] ^= 1;
+        assert_eq!(parse_frame(&frame), Err(ErrorCode::FAIL));
+
+        assert_eq!(
+            parse_frame(&PASSIVE_TARGET_RESPONSE[..10]),
+            Err(ErrorCode::SIZE)
+        );
+    }
+}
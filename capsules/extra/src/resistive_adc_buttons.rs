@@ -0,0 +1,408 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Buttons on a resistor ladder read through a single ADC channel.
+//!
+//! Each button connects a different resistor into a voltage divider, so the
+//! voltage on the ADC pin identifies which button is pressed. The board
+//! provides the voltage, in millivolts, expected for each button and how far
+//! a reading may be from it. Readings that match no button mean that no
+//! button is pressed. Only one button can be detected at a time.
+//!
+//! `ResistiveButtons` samples the ADC every `SAMPLE_INTERVAL_MS`, and a
+//! button is only reported pressed or released once `DEBOUNCE_SAMPLES`
+//! consecutive samples agree. It implements `hil::input::Buttons`, and
+//! `ButtonDriver` exposes it to userspace with the same system call
+//! interface as the GPIO button driver (`capsules_core::button`).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! const BUTTON_LEVELS_MV: [u16; 4] = [0, 600, 1300, 2000];
+//!
+//! let buttons = components::resistive_adc_buttons::ResistiveButtonsComponent::new(
+//!     board_kernel,
+//!     capsules_core::button::DRIVER_NUM,
+//!     adc_channel,
+//!     mux_alarm,
+//!     &BUTTON_LEVELS_MV,
+//!     150,
+//! )
+//! .finalize(components::resistive_adc_buttons_component_static!(
+//!     capsules_core::virtualizers::virtual_adc::AdcDevice<'static, nrf52840::adc::Adc>,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     4
+//! ));
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! See [the button driver](../../../doc/syscalls/00003_buttons.md). A
+//! process receives events for all buttons once it enables interrupts for
+//! any of them, so commands `1` and `2` apply to all buttons at once.
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::adc;
+use kernel::hil::input::{ButtonClient, Buttons};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Time between ADC samples.
+pub const SAMPLE_INTERVAL_MS: u32 = 10;
+
+/// Number of consecutive samples that must agree before a change is
+/// reported.
+pub const DEBOUNCE_SAMPLES: u8 = 3;
+
+/// Returns the button whose level is closest to `mv`, if it is within
+/// `tolerance_mv`.
+fn match_button(levels_mv: &[u16], tolerance_mv: u16, mv: u16) -> Option<u8> {
+    levels_mv
+        .iter()
+        .enumerate()
+        .map(|(id, level)| (id, level.abs_diff(mv)))
+        .filter(|(_, diff)| *diff <= tolerance_mv)
+        .min_by_key(|(_, diff)| *diff)
+        .map(|(id, _)| id as u8)
+}
+
+pub struct ResistiveButtons<'a, A: adc::AdcChannel<'a>, T: Alarm<'a>, const N: usize> {
+    adc: &'a A,
+    alarm: &'a T,
+    levels_mv: &'a [u16; N],
+    tolerance_mv: u16,
+    reference_mv: Cell<u32>,
+    enabled: Cell<bool>,
+    /// Button seen in the most recent samples, and how many samples in a row
+    /// it has been seen.
+    candidate: Cell<Option<u8>>,
+    candidate_samples: Cell<u8>,
+    pressed: Cell<Option<u8>>,
+    client: OptionalCell<&'a dyn ButtonClient>,
+}
+
+impl<'a, A: adc::AdcChannel<'a>, T: Alarm<'a>, const N: usize> ResistiveButtons<'a, A, T, N> {
+    pub fn new(
+        adc: &'a A,
+        alarm: &'a T,
+        levels_mv: &'a [u16; N],
+        tolerance_mv: u16,
+    ) -> ResistiveButtons<'a, A, T, N> {
+        ResistiveButtons {
+            adc,
+            alarm,
+            levels_mv,
+            tolerance_mv,
+            reference_mv: Cell::new(0),
+            enabled: Cell::new(false),
+            candidate: Cell::new(None),
+            candidate_samples: Cell::new(0),
+            pressed: Cell::new(None),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn schedule_sample(&self) {
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(SAMPLE_INTERVAL_MS),
+        );
+    }
+
+    /// Debounces the button detected in a sample, reporting a change once
+    /// it has been seen `DEBOUNCE_SAMPLES` times in a row.
+    fn detected(&self, button: Option<u8>) {
+        if button == self.candidate.get() {
+            self.candidate_samples
+                .set(self.candidate_samples.get().saturating_add(1));
+        } else {
+            self.candidate.set(button);
+            self.candidate_samples.set(1);
+        }
+
+        let pressed = self.pressed.get();
+        if self.candidate_samples.get() < DEBOUNCE_SAMPLES || button == pressed {
+            return;
+        }
+        self.pressed.set(button);
+        if let Some(id) = pressed {
+            self.client.map(|client| client.button_released(id));
+        }
+        if let Some(id) = button {
+            self.client.map(|client| client.button_pressed(id));
+        }
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>, T: Alarm<'a>, const N: usize> Buttons<'a>
+    for ResistiveButtons<'a, A, T, N>
+{
+    fn set_client(&self, client: &'a dyn ButtonClient) {
+        self.client.set(client);
+    }
+
+    fn count(&self) -> usize {
+        N
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        // The button levels are in millivolts, so the reference must be
+        // known to compare samples against them.
+        let reference_mv = self
+            .adc
+            .get_voltage_reference_mv()
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        self.reference_mv.set(reference_mv as u32);
+        self.enabled.set(true);
+        self.schedule_sample();
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        if !self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.enabled.set(false);
+        let _ = self.alarm.disarm();
+        let _ = self.adc.stop_sampling();
+        self.candidate.set(None);
+        self.candidate_samples.set(0);
+        self.pressed.set(None);
+        Ok(())
+    }
+
+    fn is_pressed(&self, id: u8) -> bool {
+        self.pressed.get() == Some(id)
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>, T: Alarm<'a>, const N: usize> time::AlarmClient
+    for ResistiveButtons<'a, A, T, N>
+{
+    fn alarm(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+        if self.adc.sample().is_err() {
+            // The ADC is busy; try again on the next tick.
+            self.schedule_sample();
+        }
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>, T: Alarm<'a>, const N: usize> adc::Client
+    for ResistiveButtons<'a, A, T, N>
+{
+    fn sample_ready(&self, sample: u16) {
+        if !self.enabled.get() {
+            return;
+        }
+        // Samples are left-justified to 16 bits.
+        let mv = ((sample as u32 * self.reference_mv.get()) >> 16) as u16;
+        self.detected(match_button(self.levels_mv, self.tolerance_mv, mv));
+        self.schedule_sample();
+    }
+}
+
+/// Upcall for button events, called with the index of the button and its
+/// pressed (1) or released (0) state.
+const UPCALL_NUM: usize = 0;
+
+#[derive(Default)]
+pub struct App {
+    subscribed: bool,
+}
+
+/// Exposes a set of `hil::input::Buttons` to userspace with the button
+/// driver system call interface.
+pub struct ButtonDriver<'a, B: Buttons<'a>> {
+    buttons: &'a B,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, B: Buttons<'a>> ButtonDriver<'a, B> {
+    pub fn new(
+        buttons: &'a B,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> ButtonDriver<'a, B> {
+        ButtonDriver {
+            buttons,
+            apps: grant,
+        }
+    }
+
+    fn notify(&self, id: u8, pressed: bool) {
+        self.apps.each(|_, app, upcalls| {
+            if app.subscribed {
+                let _ = upcalls.schedule_upcall(UPCALL_NUM, (id as usize, pressed as usize, 0));
+            }
+        });
+    }
+}
+
+impl<'a, B: Buttons<'a>> ButtonClient for ButtonDriver<'a, B> {
+    fn button_pressed(&self, id: u8) {
+        self.notify(id, true);
+    }
+
+    fn button_released(&self, id: u8) {
+        self.notify(id, false);
+    }
+}
+
+impl<'a, B: Buttons<'a>> SyscallDriver for ButtonDriver<'a, B> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check and get number of buttons.
+    /// - `1`: Enable events for button `data`.
+    /// - `2`: Disable events for button `data`.
+    /// - `3`: Read whether button `data` is pressed.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num != 0 && data >= self.buttons.count() {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        match command_num {
+            0 => CommandReturn::success_u32(self.buttons.count() as u32),
+
+            1 | 2 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.subscribed = command_num == 1;
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
+            3 => CommandReturn::success_u32(self.buttons.is_pressed(data as u8) as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use kernel::hil::adc::Client;
+
+    const LEVELS_MV: [u16; 4] = [0, 600, 1300, 2000];
+
+    struct MockAdc;
+
+    impl<'a> adc::AdcChannel<'a> for MockAdc {
+        fn sample(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn sample_continuous(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+        fn stop_sampling(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn get_resolution_bits(&self) -> usize {
+            12
+        }
+        fn get_voltage_reference_mv(&self) -> Option<usize> {
+            Some(3300)
+        }
+        fn set_client(&self, _client: &'a dyn adc::Client) {}
+    }
+
+    #[derive(Default)]
+    struct Events {
+        pressed: Cell<Option<u8>>,
+        released: Cell<Option<u8>>,
+        count: Cell<usize>,
+    }
+
+    impl ButtonClient for Events {
+        fn button_pressed(&self, id: u8) {
+            self.pressed.set(Some(id));
+            self.count.set(self.count.get() + 1);
+        }
+        fn button_released(&self, id: u8) {
+            self.released.set(Some(id));
+            self.count.set(self.count.get() + 1);
+        }
+    }
+
+    /// Converts millivolts to a left-justified sample for a 3.3 V reference.
+    fn sample(mv: u32) -> u16 {
+        ((mv << 16) / 3300) as u16
+    }
+
+    #[test]
+    fn levels_match_within_tolerance() {
+        assert_eq!(match_button(&LEVELS_MV, 100, 20), Some(0));
+        assert_eq!(match_button(&LEVELS_MV, 100, 690), Some(1));
+        assert_eq!(match_button(&LEVELS_MV, 100, 1201), Some(2));
+        assert_eq!(match_button(&LEVELS_MV, 100, 950), None);
+        assert_eq!(match_button(&LEVELS_MV, 100, 3300), None);
+        // Overlapping tolerances pick the closest level.
+        assert_eq!(match_button(&LEVELS_MV, 500, 900), Some(1));
+        assert_eq!(match_button(&LEVELS_MV, 500, 1000), Some(2));
+    }
+
+    #[test]
+    fn changes_need_consecutive_samples() {
+        let adc = MockAdc;
+        let alarm: MockAlarm = MockAlarm::new();
+        let events = Events::default();
+        let buttons = ResistiveButtons::new(&adc, &alarm, &LEVELS_MV, 100);
+        buttons.set_client(&events);
+
+        // Samples are ignored until enabled.
+        buttons.sample_ready(sample(600));
+        assert_eq!(buttons.enable(), Ok(()));
+        assert!(alarm.is_armed());
+
+        // A glitch to another level restarts the count.
+        buttons.sample_ready(sample(600));
+        buttons.sample_ready(sample(600));
+        buttons.sample_ready(sample(1300));
+        assert_eq!(events.count.get(), 0);
+        buttons.sample_ready(sample(1300));
+        buttons.sample_ready(sample(1300));
+        assert_eq!(events.pressed.get(), Some(2));
+        assert!(buttons.is_pressed(2));
+
+        // Moving straight to another button releases the first.
+        for _ in 0..DEBOUNCE_SAMPLES {
+            buttons.sample_ready(sample(2000));
+        }
+        assert_eq!(events.released.get(), Some(2));
+        assert_eq!(events.pressed.get(), Some(3));
+        assert_eq!(events.count.get(), 3);
+
+        // Readings between levels are no button.
+        for _ in 0..DEBOUNCE_SAMPLES {
+            buttons.sample_ready(sample(3000));
+        }
+        assert_eq!(events.released.get(), Some(3));
+        assert!(!buttons.is_pressed(3));
+        assert_eq!(events.count.get(), 4);
+
+        assert_eq!(buttons.disable(), Ok(()));
+        assert!(!alarm.is_armed());
+    }
+}
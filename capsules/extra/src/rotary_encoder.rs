@@ -0,0 +1,235 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for quadrature rotary encoders, with an optional push button.
+//!
+//! The A and B outputs of the encoder go through a cycle of four states for
+//! every detent: with both outputs high at rest, turning clockwise goes
+//! 11, 10, 00, 01 and back to 11, with A (the high bit) following B. Every
+//! change of either output is decoded with the full transition table, so a
+//! bouncing contact moves back and forth between two neighbouring states and
+//! cancels out. Changes of both outputs at once, which can only come from a
+//! missed interrupt, are ignored. A step is only reported when the encoder
+//! is back at rest after a whole cycle in one direction.
+//!
+//! The inputs are pulled up, and the button is pressed when low.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let encoder = components::rotary_encoder::RotaryEncoderComponent::new(
+//!     &nrf52840_peripherals.gpio_port[ENCODER_A],
+//!     &nrf52840_peripherals.gpio_port[ENCODER_B],
+//!     Some(&nrf52840_peripherals.gpio_port[ENCODER_BUTTON]),
+//! )
+//! .finalize(components::rotary_encoder_component_static!(
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! encoder.set_client(menu);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The state of both outputs when the encoder is at rest.
+const REST: u8 = 0b11;
+
+/// Number of transitions in a step.
+const TRANSITIONS_PER_STEP: i8 = 4;
+
+/// Change of position for each transition, indexed by the previous and the
+/// new state of the outputs (A in the high bit). Clockwise is positive.
+const TRANSITIONS: [i8; 16] = [
+    0, 1, -1, 0, // from 00
+    -1, 0, 0, 1, // from 01
+    1, 0, 0, -1, // from 10
+    0, -1, 1, 0, // from 11
+];
+
+pub trait RotaryEncoderClient {
+    /// The encoder was turned by `steps`, clockwise if positive.
+    fn rotated(&self, steps: i32);
+
+    /// The button was pressed or released.
+    fn button(&self, pressed: bool);
+}
+
+pub struct RotaryEncoder<'a, P: gpio::InterruptPin<'a>> {
+    pin_a: &'a P,
+    pin_b: &'a P,
+    button: Option<&'a P>,
+    client: OptionalCell<&'a dyn RotaryEncoderClient>,
+    enabled: Cell<bool>,
+    /// The last state of the outputs.
+    state: Cell<u8>,
+    /// Transitions since the encoder was last at rest.
+    transitions: Cell<i8>,
+    pressed: Cell<bool>,
+}
+
+impl<'a, P: gpio::InterruptPin<'a>> RotaryEncoder<'a, P> {
+    pub fn new(pin_a: &'a P, pin_b: &'a P, button: Option<&'a P>) -> RotaryEncoder<'a, P> {
+        RotaryEncoder {
+            pin_a,
+            pin_b,
+            button,
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            state: Cell::new(REST),
+            transitions: Cell::new(0),
+            pressed: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn RotaryEncoderClient) {
+        self.client.set(client);
+    }
+
+    pub fn enable(&self) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.enabled.set(true);
+        for pin in [Some(self.pin_a), Some(self.pin_b), self.button]
+            .into_iter()
+            .flatten()
+        {
+            pin.make_input();
+            pin.set_floating_state(gpio::FloatingState::PullUp);
+            pin.enable_interrupts(gpio::InterruptEdge::EitherEdge);
+        }
+        self.state.set(self.read_outputs());
+        self.transitions.set(0);
+        self.pressed.set(self.read_button());
+        Ok(())
+    }
+
+    pub fn disable(&self) -> Result<(), ErrorCode> {
+        if !self.enabled.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.enabled.set(false);
+        for pin in [Some(self.pin_a), Some(self.pin_b), self.button]
+            .into_iter()
+            .flatten()
+        {
+            pin.disable_interrupts();
+        }
+        Ok(())
+    }
+
+    fn read_outputs(&self) -> u8 {
+        (self.pin_a.read() as u8) << 1 | self.pin_b.read() as u8
+    }
+
+    fn read_button(&self) -> bool {
+        self.button.map_or(false, |button| !button.read())
+    }
+
+    /// Follow the outputs to `state`. Returns the step completed, if any.
+    fn decode(&self, state: u8) -> Option<i32> {
+        let previous = self.state.replace(state);
+        let transitions = self.transitions.get() + TRANSITIONS[(previous << 2 | state) as usize];
+        if state != REST {
+            self.transitions.set(transitions);
+            return None;
+        }
+        // Back at rest: a whole cycle is a step, anything else was bounce
+        // or a turn that was not completed.
+        self.transitions.set(0);
+        match transitions {
+            TRANSITIONS_PER_STEP => Some(1),
+            t if t == -TRANSITIONS_PER_STEP => Some(-1),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>> gpio::Client for RotaryEncoder<'a, P> {
+    fn fired(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+        if let Some(steps) = self.decode(self.read_outputs()) {
+            self.client.map(|client| client.rotated(steps));
+        }
+        let pressed = self.read_button();
+        if pressed != self.pressed.replace(pressed) {
+            self.client.map(|client| client.button(pressed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capsules_core::test::mocks::MockPin;
+    use kernel::hil::gpio::Client;
+
+    #[derive(Default)]
+    struct MockClient {
+        steps: Cell<i32>,
+        presses: Cell<usize>,
+    }
+
+    impl RotaryEncoderClient for MockClient {
+        fn rotated(&self, steps: i32) {
+            self.steps.set(self.steps.get() + steps);
+        }
+
+        fn button(&self, pressed: bool) {
+            if pressed {
+                self.presses.set(self.presses.get() + 1);
+            }
+        }
+    }
+
+    /// Set the outputs to each state in turn, with an interrupt for each.
+    fn feed<'a>(encoder: &RotaryEncoder<'a, MockPin<'a>>, a: &MockPin, b: &MockPin, states: &[u8]) {
+        for &state in states {
+            a.set_level(state & 0b10 != 0);
+            b.set_level(state & 0b01 != 0);
+            encoder.fired();
+        }
+    }
+
+    #[test]
+    fn bouncy_transitions_give_the_net_steps() {
+        let (a, b, button) = (MockPin::new(true), MockPin::new(true), MockPin::new(true));
+        let encoder = RotaryEncoder::new(&a, &b, Some(&button));
+        let client = MockClient::default();
+        encoder.set_client(&client);
+        assert_eq!(encoder.enable(), Ok(()));
+
+        // Two clockwise steps, with B bouncing at the start of the first and
+        // A bouncing at the end of the second.
+        feed(&encoder, &a, &b, &[0b10, 0b11, 0b10, 0b00, 0b01, 0b11]);
+        feed(
+            &encoder,
+            &a,
+            &b,
+            &[0b10, 0b00, 0b01, 0b00, 0b01, 0b11, 0b01, 0b11],
+        );
+        assert_eq!(client.steps.get(), 2);
+
+        // One counterclockwise step.
+        feed(&encoder, &a, &b, &[0b01, 0b00, 0b10, 0b00, 0b10, 0b11]);
+        assert_eq!(client.steps.get(), 1);
+
+        // Half a turn and back, then a glitch skipping a state.
+        feed(&encoder, &a, &b, &[0b10, 0b00, 0b10, 0b11]);
+        feed(&encoder, &a, &b, &[0b10, 0b01, 0b11]);
+        assert_eq!(client.steps.get(), 1);
+
+        button.set_level(false);
+        encoder.fired();
+        encoder.fired();
+        button.set_level(true);
+        encoder.fired();
+        assert_eq!(client.presses.get(), 1);
+    }
+}
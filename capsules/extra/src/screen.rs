@@ -13,6 +13,17 @@
 //! let screen =
 //!     components::screen::ScreenComponent::new(board_kernel, tft).finalize();
 //! ```
+//!
+//! Software Rotation
+//! -----------------
+//!
+//! For displays that are mounted sideways or upside down, the capsule can
+//! rotate (and optionally mirror) everything applications draw before it
+//! reaches the underlying screen. Applications see the rotated resolution and
+//! use rotated coordinates, so no application changes are needed. Each frame
+//! is then sent to the screen one row at a time, which is slower than an
+//! untransformed write. Software rotation is not supported for pixel formats
+//! that use less than one byte per pixel.
 
 use core::cell::Cell;
 use core::convert::From;
@@ -79,6 +90,63 @@ enum ScreenCommand {
     Fill,
 }
 
+/// Returns the resolution as seen by applications for a screen with the
+/// given physical resolution.
+fn rotated_resolution(rotation: ScreenRotation, resolution: (usize, usize)) -> (usize, usize) {
+    match rotation {
+        ScreenRotation::Rotated90 | ScreenRotation::Rotated270 => (resolution.1, resolution.0),
+        _ => resolution,
+    }
+}
+
+/// Maps the application pixel `(x, y)` to the physical pixel of a screen
+/// with the physical `resolution`. Mirroring is applied horizontally, before
+/// the (clockwise) rotation.
+fn transform_point(
+    rotation: ScreenRotation,
+    mirrored: bool,
+    resolution: (usize, usize),
+    (x, y): (usize, usize),
+) -> (usize, usize) {
+    let (width, height) = resolution;
+    let (logical_width, _) = rotated_resolution(rotation, resolution);
+    let x = if mirrored { logical_width - 1 - x } else { x };
+    match rotation {
+        ScreenRotation::Normal => (x, y),
+        ScreenRotation::Rotated90 => (width - 1 - y, x),
+        ScreenRotation::Rotated180 => (width - 1 - x, height - 1 - y),
+        ScreenRotation::Rotated270 => (y, height - 1 - x),
+    }
+}
+
+/// Maps an application frame to the physical frame that covers the same
+/// pixels. Returns `None` if the frame is empty or does not fit on the
+/// screen.
+fn transform_frame(
+    rotation: ScreenRotation,
+    mirrored: bool,
+    resolution: (usize, usize),
+    (x, y, width, height): (usize, usize, usize, usize),
+) -> Option<(usize, usize, usize, usize)> {
+    let (logical_width, logical_height) = rotated_resolution(rotation, resolution);
+    if width == 0 || height == 0 || x + width > logical_width || y + height > logical_height {
+        return None;
+    }
+    let first = transform_point(rotation, mirrored, resolution, (x, y));
+    let last = transform_point(
+        rotation,
+        mirrored,
+        resolution,
+        (x + width - 1, y + height - 1),
+    );
+    Some((
+        first.0.min(last.0),
+        first.1.min(last.1),
+        first.0.abs_diff(last.0) + 1,
+        first.1.abs_diff(last.1) + 1,
+    ))
+}
+
 fn pixels_in_bytes(pixels: usize, bits_per_pixel: usize) -> usize {
     let bytes = pixels * bits_per_pixel / 8;
     if pixels * bits_per_pixel % 8 != 0 {
@@ -93,6 +161,8 @@ pub struct App {
     write_position: usize,
     write_len: usize,
     command: ScreenCommand,
+    x: usize,
+    y: usize,
     width: usize,
     height: usize,
 }
@@ -102,6 +172,8 @@ impl Default for App {
         App {
             pending_command: false,
             command: ScreenCommand::Nop,
+            x: 0,
+            y: 0,
             width: 0,
             height: 0,
             write_len: 0,
@@ -117,6 +189,13 @@ pub struct Screen<'a> {
     current_process: OptionalCell<ProcessId>,
     pixel_format: Cell<ScreenPixelFormat>,
     buffer: TakeCell<'static, [u8]>,
+    /// Software rotation applied to application coordinates.
+    rotation: ScreenRotation,
+    /// Whether application coordinates are mirrored horizontally.
+    mirrored: bool,
+    /// Length of the transformed segment waiting in `buffer` for its write
+    /// frame to be set.
+    segment_len: OptionalCell<usize>,
 }
 
 impl<'a> Screen<'a> {
@@ -125,6 +204,8 @@ impl<'a> Screen<'a> {
         screen_setup: Option<&'a dyn hil::screen::ScreenSetup<'a>>,
         buffer: &'static mut [u8],
         grant: Grant<App, UpcallCount<1>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+        rotation: ScreenRotation,
+        mirrored: bool,
     ) -> Screen<'a> {
         Screen {
             screen: screen,
@@ -133,6 +214,56 @@ impl<'a> Screen<'a> {
             current_process: OptionalCell::empty(),
             pixel_format: Cell::new(screen.get_pixel_format()),
             buffer: TakeCell::new(buffer),
+            rotation: rotation,
+            mirrored: mirrored,
+            segment_len: OptionalCell::empty(),
+        }
+    }
+
+    fn is_transformed(&self) -> bool {
+        self.rotation != ScreenRotation::Normal || self.mirrored
+    }
+
+    /// Starts writing a buffer in the current frame, either directly or as
+    /// transformed segments if software rotation is enabled.
+    fn start_write(&self, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        if self.is_transformed() {
+            if self.pixel_format.get().get_bits_per_pixel() % 8 != 0 {
+                self.buffer.replace(buffer);
+                Err(ErrorCode::NOSUPPORT)
+            } else {
+                self.write_next_segment(buffer)
+            }
+        } else {
+            let len = self.fill_next_buffer_for_write(buffer);
+            if len > 0 {
+                self.screen.write(buffer, len)
+            } else {
+                self.buffer.replace(buffer);
+                self.run_next_command(kernel::errorcode::into_statuscode(Ok(())), 0, 0);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fills `buffer` with the next segment and sets its write frame. The
+    /// segment is written once the screen reports the frame is set.
+    fn write_next_segment(&self, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let segment = self.fill_next_segment(buffer);
+        self.buffer.replace(buffer);
+        match segment {
+            Some(((x, y, width, height), len)) => {
+                self.segment_len.set(len);
+                let r = self.screen.set_write_frame(x, y, width, height);
+                if r.is_err() {
+                    self.segment_len.clear();
+                }
+                r
+            }
+            None => {
+                self.run_next_command(kernel::errorcode::into_statuscode(Ok(())), 0, 0);
+                Ok(())
+            }
         }
     }
 
@@ -224,16 +355,10 @@ impl<'a> Screen<'a> {
                 .unwrap_or_else(|err| err.into())
             {
                 Err(e) => Err(e),
-                Ok(()) => self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
-                    let len = self.fill_next_buffer_for_write(buffer);
-                    if len > 0 {
-                        self.screen.write(buffer, len)
-                    } else {
-                        self.buffer.replace(buffer);
-                        self.run_next_command(kernel::errorcode::into_statuscode(Ok(())), 0, 0);
-                        Ok(())
-                    }
-                }),
+                Ok(()) => self
+                    .buffer
+                    .take()
+                    .map_or(Err(ErrorCode::NOMEM), |buffer| self.start_write(buffer)),
             },
 
             ScreenCommand::Write(data_len) => match self
@@ -256,16 +381,10 @@ impl<'a> Screen<'a> {
                 })
                 .unwrap_or_else(|err| err.into())
             {
-                Ok(()) => self.buffer.take().map_or(Err(ErrorCode::FAIL), |buffer| {
-                    let len = self.fill_next_buffer_for_write(buffer);
-                    if len > 0 {
-                        self.screen.write(buffer, len)
-                    } else {
-                        self.buffer.replace(buffer);
-                        self.run_next_command(kernel::errorcode::into_statuscode(Ok(())), 0, 0);
-                        Ok(())
-                    }
-                }),
+                Ok(()) => self
+                    .buffer
+                    .take()
+                    .map_or(Err(ErrorCode::FAIL), |buffer| self.start_write(buffer)),
                 Err(e) => Err(e),
             },
             ScreenCommand::SetWriteFrame {
@@ -277,10 +396,26 @@ impl<'a> Screen<'a> {
                 .apps
                 .enter(process_id, |app, _| {
                     app.write_position = 0;
+                    app.x = x;
+                    app.y = y;
                     app.width = width;
                     app.height = height;
 
-                    self.screen.set_write_frame(x, y, width, height)
+                    if self.is_transformed() {
+                        match transform_frame(
+                            self.rotation,
+                            self.mirrored,
+                            self.screen.get_resolution(),
+                            (x, y, width, height),
+                        ) {
+                            Some((x, y, width, height)) => {
+                                self.screen.set_write_frame(x, y, width, height)
+                            }
+                            None => Err(ErrorCode::INVAL),
+                        }
+                    } else {
+                        self.screen.set_write_frame(x, y, width, height)
+                    }
                 })
                 .unwrap_or_else(|err| err.into()),
             _ => Err(ErrorCode::NOSUPPORT),
@@ -328,6 +463,95 @@ impl<'a> Screen<'a> {
         }
     }
 
+    /// Copies the pixels of the next segment of the current frame into
+    /// `buffer`. A segment is the part of a single application row that fits
+    /// in `buffer`, so it is a single row or column of physical pixels. The
+    /// pixels are reversed if the physical row or column runs backwards.
+    ///
+    /// Returns the physical frame of the segment and its length in bytes, or
+    /// `None` if there is nothing left to write.
+    fn fill_next_segment(
+        &self,
+        buffer: &mut [u8],
+    ) -> Option<((usize, usize, usize, usize), usize)> {
+        let bytes_per_pixel = self.pixel_format.get().get_bits_per_pixel() / 8;
+        self.current_process.and_then(|process_id| {
+            self.apps
+                .enter(process_id, |app, kernel_data| {
+                    let frame_len = app.width * app.height * bytes_per_pixel;
+                    let end = match app.command {
+                        ScreenCommand::Write(_) => app.write_len.min(frame_len),
+                        ScreenCommand::Fill => frame_len,
+                        _ => 0,
+                    };
+                    if app.write_position >= end {
+                        return None;
+                    }
+
+                    let pixel = app.write_position / bytes_per_pixel;
+                    let column = pixel % app.width;
+                    let row = pixel / app.width;
+                    let count = (app.width - column)
+                        .min(buffer.len() / bytes_per_pixel)
+                        .min((end - app.write_position) / bytes_per_pixel);
+                    if count == 0 {
+                        return None;
+                    }
+
+                    let resolution = self.screen.get_resolution();
+                    let first = transform_point(
+                        self.rotation,
+                        self.mirrored,
+                        resolution,
+                        (app.x + column, app.y + row),
+                    );
+                    let last = transform_point(
+                        self.rotation,
+                        self.mirrored,
+                        resolution,
+                        (app.x + column + count - 1, app.y + row),
+                    );
+                    let reversed = first.0 > last.0 || first.1 > last.1;
+
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::SHARED)
+                        .and_then(|shared| {
+                            shared.enter(|data| {
+                                for i in 0..count {
+                                    let source = match app.command {
+                                        ScreenCommand::Write(_) => {
+                                            let index = if reversed { count - 1 - i } else { i };
+                                            app.write_position + index * bytes_per_pixel
+                                        }
+                                        // Every pixel has the same color.
+                                        _ => 0,
+                                    };
+                                    for j in 0..bytes_per_pixel {
+                                        buffer[i * bytes_per_pixel + j] = data
+                                            .get(source + j..source + j + 1)
+                                            .map_or(0, |byte| byte[0].get());
+                                    }
+                                }
+                            })
+                        })
+                        .ok()?;
+                    app.write_position += count * bytes_per_pixel;
+
+                    Some((
+                        (
+                            first.0.min(last.0),
+                            first.1.min(last.1),
+                            first.0.abs_diff(last.0) + 1,
+                            first.1.abs_diff(last.1) + 1,
+                        ),
+                        count * bytes_per_pixel,
+                    ))
+                })
+                .ok()
+                .flatten()
+        })
+    }
+
     fn fill_next_buffer_for_write(&self, buffer: &mut [u8]) -> usize {
         self.current_process.map_or_else(
             || 0,
@@ -419,10 +643,35 @@ impl<'a> Screen<'a> {
 
 impl<'a> hil::screen::ScreenClient for Screen<'a> {
     fn command_complete(&self, r: Result<(), ErrorCode>) {
-        self.run_next_command(kernel::errorcode::into_statuscode(r), 0, 0);
+        // The write frame of a transformed segment is set, write its pixels.
+        let segment = self
+            .segment_len
+            .take()
+            .filter(|_| r == Ok(()))
+            .and_then(|len| self.buffer.take().map(|buffer| (buffer, len)));
+        match segment {
+            Some((buffer, len)) => {
+                if let Err(err) = self.screen.write(buffer, len) {
+                    self.run_next_command(kernel::errorcode::into_statuscode(Err(err)), 0, 0);
+                }
+            }
+            None => self.run_next_command(kernel::errorcode::into_statuscode(r), 0, 0),
+        }
     }
 
     fn write_complete(&self, buffer: &'static mut [u8], r: Result<(), ErrorCode>) {
+        if self.is_transformed() {
+            if r == Ok(()) {
+                if let Err(err) = self.write_next_segment(buffer) {
+                    self.run_next_command(kernel::errorcode::into_statuscode(Err(err)), 0, 0);
+                }
+            } else {
+                self.buffer.replace(buffer);
+                self.run_next_command(kernel::errorcode::into_statuscode(r), 0, 0);
+            }
+            return;
+        }
+
         let len = self.fill_next_buffer_for_write(buffer);
 
         if r == Ok(()) && len > 0 {
@@ -482,7 +731,10 @@ impl<'a> SyscallDriver for Screen<'a> {
             // Get Resolution Mode Width and Height
             12 => {
                 if let Some(screen) = self.screen_setup {
-                    match screen.get_supported_resolution(data1) {
+                    match screen
+                        .get_supported_resolution(data1)
+                        .map(|resolution| rotated_resolution(self.rotation, resolution))
+                    {
                         Some((width, height)) if width > 0 && height > 0 => {
                             CommandReturn::success_u32_u32(width as u32, height as u32)
                         }
@@ -525,17 +777,15 @@ impl<'a> SyscallDriver for Screen<'a> {
 
             // Get Resolution
             23 => {
-                let (width, height) = self.screen.get_resolution();
+                let (width, height) =
+                    rotated_resolution(self.rotation, self.screen.get_resolution());
                 CommandReturn::success_u32_u32(width as u32, height as u32)
             }
             // Set Resolution
-            24 => self.enqueue_command(
-                ScreenCommand::SetResolution {
-                    width: data1,
-                    height: data2,
-                },
-                process_id,
-            ),
+            24 => {
+                let (width, height) = rotated_resolution(self.rotation, (data1, data2));
+                self.enqueue_command(ScreenCommand::SetResolution { width, height }, process_id)
+            }
 
             // Get pixel format
             25 => CommandReturn::success_u32(self.screen.get_pixel_format() as u32),
@@ -571,3 +821,91 @@ impl<'a> SyscallDriver for Screen<'a> {
         self.apps.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTION: (usize, usize) = (240, 320);
+
+    #[test]
+    fn origin_lands_on_rotated_corner() {
+        let expected = [
+            (ScreenRotation::Normal, false, (0, 0)),
+            (ScreenRotation::Rotated90, false, (239, 0)),
+            (ScreenRotation::Rotated180, false, (239, 319)),
+            (ScreenRotation::Rotated270, false, (0, 319)),
+            (ScreenRotation::Normal, true, (239, 0)),
+            (ScreenRotation::Rotated90, true, (239, 319)),
+            (ScreenRotation::Rotated180, true, (0, 319)),
+            (ScreenRotation::Rotated270, true, (0, 0)),
+        ];
+        for (rotation, mirrored, physical) in expected {
+            assert_eq!(
+                transform_point(rotation, mirrored, RESOLUTION, (0, 0)),
+                physical
+            );
+        }
+    }
+
+    #[test]
+    fn rotated_resolution_swaps_dimensions() {
+        assert_eq!(
+            rotated_resolution(ScreenRotation::Normal, RESOLUTION),
+            (240, 320)
+        );
+        assert_eq!(
+            rotated_resolution(ScreenRotation::Rotated90, RESOLUTION),
+            (320, 240)
+        );
+        assert_eq!(
+            rotated_resolution(ScreenRotation::Rotated180, RESOLUTION),
+            (240, 320)
+        );
+        assert_eq!(
+            rotated_resolution(ScreenRotation::Rotated270, RESOLUTION),
+            (320, 240)
+        );
+    }
+
+    #[test]
+    fn frame_bounds() {
+        // A 10x20 frame at (5, 7) in the rotated coordinates.
+        let frame = (5, 7, 10, 20);
+        assert_eq!(
+            transform_frame(ScreenRotation::Normal, false, RESOLUTION, frame),
+            Some((5, 7, 10, 20))
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated90, false, RESOLUTION, frame),
+            Some((213, 5, 20, 10))
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated180, false, RESOLUTION, frame),
+            Some((225, 293, 10, 20))
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated270, false, RESOLUTION, frame),
+            Some((7, 305, 20, 10))
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated90, true, RESOLUTION, frame),
+            Some((213, 305, 20, 10))
+        );
+
+        // The rotated screen is 320x240, so this fits only when rotated.
+        let wide = (300, 0, 20, 240);
+        assert_eq!(
+            transform_frame(ScreenRotation::Normal, false, RESOLUTION, wide),
+            None
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated90, false, RESOLUTION, wide),
+            Some((0, 300, 240, 20))
+        );
+        assert_eq!(
+            transform_frame(ScreenRotation::Rotated270, false, RESOLUTION, (0, 0, 0, 1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,246 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Reads a group of sensors together on a common period.
+//!
+//! Sensors that are each read on their own timer wake the bus and the CPU
+//! once for every reading, which leaves the chip little time in deep sleep.
+//! A [`SensorBatch`] starts a reading of every sensor in its list from the
+//! same alarm, one after the other. Sensors on a shared bus queue behind
+//! each other in the bus virtualizer, so the bus is busy in one burst each
+//! period and idle in between.
+//!
+//! The batch only starts the readings. Each sensor delivers its reading to
+//! its own client, as it would for a reading started by that client.
+//!
+//! A sensor that is still busy with an earlier reading refuses to start
+//! another with `BUSY`. The batch skips it for that period and counts it in
+//! [`SensorBatch::skipped`], which helps with choosing a period that the
+//! slowest sensor can keep up with.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sensors = static_init!(
+//!     [capsules_extra::sensor_batch::Sensor<'static>; 3],
+//!     [
+//!         capsules_extra::sensor_batch::Sensor::Temperature(sht3x),
+//!         capsules_extra::sensor_batch::Sensor::Humidity(sht3x),
+//!         capsules_extra::sensor_batch::Sensor::AmbientLight(isl29035),
+//!     ]
+//! );
+//! let batch = components::sensor_batch::SensorBatchComponent::new(mux_alarm, sensors, 10_000)
+//!     .finalize(components::sensor_batch_component_static!(
+//!         nrf52840::rtc::Rtc<'static>
+//!     ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::sensors::{
+    AirQualityDriver, AmbientLight, HumidityDriver, Magnetometer, MultiChannelPowerMonitor,
+    NineDof, ProximityDriver, TemperatureDriver, UvIndex,
+};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::ErrorCode;
+
+/// A reading that the batch starts each period.
+#[derive(Clone, Copy)]
+pub enum Sensor<'a> {
+    Temperature(&'a dyn TemperatureDriver<'a>),
+    Humidity(&'a dyn HumidityDriver<'a>),
+    Co2(&'a dyn AirQualityDriver<'a>),
+    AmbientLight(&'a dyn AmbientLight<'a>),
+    Proximity(&'a dyn ProximityDriver<'a>),
+    UvIndex(&'a dyn UvIndex<'a>),
+    Accelerometer(&'a dyn NineDof<'a>),
+    Gyroscope(&'a dyn NineDof<'a>),
+    Magnetometer(&'a dyn Magnetometer<'a>),
+    /// A channel of a power monitor.
+    PowerChannel(&'a dyn MultiChannelPowerMonitor<'a>, u8),
+}
+
+impl Sensor<'_> {
+    fn read(&self) -> Result<(), ErrorCode> {
+        match *self {
+            Sensor::Temperature(sensor) => sensor.read_temperature(),
+            Sensor::Humidity(sensor) => sensor.read_humidity(),
+            Sensor::Co2(sensor) => sensor.read_co2(),
+            Sensor::AmbientLight(sensor) => sensor.read_light_intensity(),
+            Sensor::Proximity(sensor) => sensor.read_proximity(),
+            Sensor::UvIndex(sensor) => sensor.read_uv_index(),
+            Sensor::Accelerometer(sensor) => sensor.read_accelerometer(),
+            Sensor::Gyroscope(sensor) => sensor.read_gyroscope(),
+            Sensor::Magnetometer(sensor) => sensor.read_field_nt(),
+            Sensor::PowerChannel(monitor, ch) => monitor.read_channel(ch),
+        }
+    }
+}
+
+pub struct SensorBatch<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    sensors: &'a [Sensor<'a>],
+    period_ms: u32,
+    /// Readings that failed to start since the batch was created.
+    skipped: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> SensorBatch<'a, A> {
+    pub fn new(alarm: &'a A, sensors: &'a [Sensor<'a>], period_ms: u32) -> Self {
+        SensorBatch {
+            alarm,
+            sensors,
+            period_ms,
+            skipped: Cell::new(0),
+        }
+    }
+
+    /// Read the sensors every period, starting one period from now.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.alarm.is_armed() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.period_ms));
+        Ok(())
+    }
+
+    /// Stop reading the sensors. Readings in progress still complete.
+    pub fn stop(&self) -> Result<(), ErrorCode> {
+        self.alarm.disarm()
+    }
+
+    /// The number of readings skipped because the sensor was busy or
+    /// failed to start.
+    pub fn skipped(&self) -> usize {
+        self.skipped.get()
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for SensorBatch<'a, A> {
+    fn alarm(&self) {
+        // Count the next period from when this one was due, so that a late
+        // alarm does not shift every later batch.
+        self.alarm.set_alarm(
+            self.alarm.get_alarm(),
+            self.alarm.ticks_from_ms(self.period_ms),
+        );
+
+        for sensor in self.sensors {
+            if sensor.read().is_err() {
+                self.skipped.set(self.skipped.get() + 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use core::cell::RefCell;
+    use kernel::hil::sensors::{HumidityClient, TemperatureClient};
+    use kernel::hil::time::{Ticks, Time};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Records the time each reading was started. A busy sensor refuses to
+    /// start readings.
+    struct MockSensor<'a> {
+        alarm: &'a MockAlarm<'a>,
+        busy: Cell<bool>,
+        started: RefCell<Vec<u32>>,
+    }
+
+    impl MockSensor<'_> {
+        fn read(&self) -> Result<(), ErrorCode> {
+            if self.busy.get() {
+                return Err(ErrorCode::BUSY);
+            }
+            self.started.borrow_mut().push(self.alarm.now().into_u32());
+            Ok(())
+        }
+    }
+
+    impl<'a> TemperatureDriver<'a> for MockSensor<'_> {
+        fn set_client(&self, _client: &'a dyn TemperatureClient) {}
+        fn read_temperature(&self) -> Result<(), ErrorCode> {
+            self.read()
+        }
+    }
+
+    impl<'a> HumidityDriver<'a> for MockSensor<'_> {
+        fn set_client(&self, _client: &'a dyn HumidityClient) {}
+        fn read_humidity(&self) -> Result<(), ErrorCode> {
+            self.read()
+        }
+    }
+
+    fn setup() -> (
+        &'static MockAlarm<'static>,
+        &'static [MockSensor<'static>; 5],
+        &'static SensorBatch<'static, MockAlarm<'static>>,
+    ) {
+        let alarm: &'static MockAlarm = Box::leak(Box::default());
+        let mocks: &'static [MockSensor; 5] =
+            Box::leak(Box::new(core::array::from_fn(|_| MockSensor {
+                alarm,
+                busy: Cell::new(false),
+                started: RefCell::new(Vec::new()),
+            })));
+        let sensors: &'static [Sensor; 5] = Box::leak(Box::new([
+            Sensor::Temperature(&mocks[0]),
+            Sensor::Humidity(&mocks[1]),
+            Sensor::Temperature(&mocks[2]),
+            Sensor::Humidity(&mocks[3]),
+            Sensor::Temperature(&mocks[4]),
+        ]));
+        let batch = Box::leak(Box::new(SensorBatch::new(alarm, sensors, 1000)));
+        alarm.set_alarm_client(batch);
+        (alarm, mocks, batch)
+    }
+
+    #[test]
+    fn reads_every_sensor_in_one_wakeup() {
+        let (alarm, mocks, batch) = setup();
+        alarm.set_now(250);
+        assert_eq!(batch.start(), Ok(()));
+        assert_eq!(batch.start(), Err(ErrorCode::ALREADY));
+        assert!(mocks.iter().all(|mock| mock.started.borrow().is_empty()));
+
+        alarm.fire();
+        alarm.fire();
+        // Every sensor is started in the same wakeup, and the next one is a
+        // whole period later.
+        for mock in mocks {
+            assert_eq!(*mock.started.borrow(), [1250, 2250]);
+        }
+        assert_eq!(alarm.get_alarm().into_u32(), 3250);
+        assert_eq!(batch.skipped(), 0);
+
+        assert_eq!(batch.stop(), Ok(()));
+        assert!(!alarm.is_armed());
+    }
+
+    #[test]
+    fn skips_busy_sensors() {
+        let (alarm, mocks, batch) = setup();
+        assert_eq!(batch.start(), Ok(()));
+
+        mocks[1].busy.set(true);
+        alarm.fire();
+        assert!(mocks[1].started.borrow().is_empty());
+        assert_eq!(*mocks[2].started.borrow(), [1000]);
+        assert_eq!(batch.skipped(), 1);
+
+        mocks[1].busy.set(false);
+        alarm.fire();
+        assert_eq!(*mocks[1].started.borrow(), [2000]);
+        assert_eq!(*mocks[4].started.borrow(), [1000, 2000]);
+        assert_eq!(batch.skipped(), 1);
+    }
+}
@@ -0,0 +1,732 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Sensirion SGP30 air quality sensor, reporting eCO2 and
+//! TVOC.
+//!
+//! <https://sensirion.com/media/documents/984E0DD5/61644B8B/Sensirion_Gas_Sensors_Datasheet_SGP30.pdf>
+//!
+//! After the air quality algorithm is initialized, the sensor has to be
+//! asked for a measurement every second, which this driver does for as long
+//! as it runs. Requests for eCO2 and TVOC are answered with the next
+//! measurement. Every word read from or written to the sensor is followed by
+//! a CRC8.
+//!
+//! The algorithm compensates for drift with a baseline, which takes 12 hours
+//! to find and is lost when the sensor is powered off. It can be read and
+//! written with [`Sgp30::get_baseline`] and [`Sgp30::set_baseline`]. If the
+//! driver is given nonvolatile storage for the baseline, it restores the
+//! baseline after initializing the algorithm, and saves it every hour once
+//! it has one. Baselines are stored in the format the sensor sends them,
+//! two words each followed by its CRC, so erased storage is never restored.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sgp30 = components::sgp30::Sgp30Component::new(
+//!     mux_i2c,
+//!     capsules_extra::sgp30::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(baseline_storage),
+//!     0,
+//! )
+//! .finalize(components::sgp30_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::sensors::{AirQualityClient, AirQualityDriver};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const BASE_ADDR: u8 = 0x58;
+
+/// Size of the buffer for the sensor: a command and two words with their
+/// CRCs.
+pub const BUFFER_SIZE: usize = 8;
+
+/// Size of a baseline in nonvolatile storage.
+pub const BASELINE_SIZE: usize = 6;
+
+const CMD_INIT_AIR_QUALITY: u16 = 0x2003;
+const CMD_MEASURE_AIR_QUALITY: u16 = 0x2008;
+const CMD_GET_BASELINE: u16 = 0x2015;
+const CMD_SET_BASELINE: u16 = 0x201E;
+
+/// The sensor has to be asked for a measurement every second.
+const MEASUREMENT_INTERVAL_MS: u32 = 1000;
+
+/// Measurements between saving the baseline, one hour.
+const BASELINE_SAVE_INTERVAL: u32 = 3600;
+
+/// Measurements before the first baseline can be saved when none was
+/// restored, twelve hours.
+const BASELINE_FIRST_SAVE: u32 = 12 * 3600;
+
+fn crc8(data: &[u8]) -> u8 {
+    let polynomial = 0x31;
+    let mut crc = 0xff;
+
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if (crc & 0x80) != 0 {
+                crc = crc << 1 ^ polynomial;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Write `word` and its CRC to the start of `buffer`.
+fn put_word(buffer: &mut [u8], word: u16) {
+    buffer[..2].copy_from_slice(&word.to_be_bytes());
+    buffer[2] = crc8(&buffer[..2]);
+}
+
+/// Read a word from the start of `buffer`, if its CRC is correct.
+fn get_word(buffer: &[u8]) -> Option<u16> {
+    if crc8(&buffer[..2]) == buffer[2] {
+        Some(u16::from_be_bytes([buffer[0], buffer[1]]))
+    } else {
+        None
+    }
+}
+
+/// The baseline of the air quality algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Baseline {
+    pub eco2: u16,
+    pub tvoc: u16,
+}
+
+pub trait Sgp30BaselineClient {
+    /// The baseline was read with [`Sgp30::get_baseline`].
+    fn baseline_read(&self, result: Result<Baseline, ErrorCode>);
+
+    /// The baseline was written with [`Sgp30::set_baseline`].
+    fn baseline_written(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    InitAirQuality,
+    MeasureAirQuality,
+    GetBaseline,
+    SetBaseline { baseline: Baseline, restore: bool },
+}
+
+impl Operation {
+    fn command(self) -> u16 {
+        match self {
+            Operation::InitAirQuality => CMD_INIT_AIR_QUALITY,
+            Operation::MeasureAirQuality => CMD_MEASURE_AIR_QUALITY,
+            Operation::GetBaseline => CMD_GET_BASELINE,
+            Operation::SetBaseline { .. } => CMD_SET_BASELINE,
+        }
+    }
+
+    /// The longest the sensor takes to process the command.
+    fn duration_ms(self) -> u32 {
+        match self {
+            Operation::MeasureAirQuality => 12,
+            _ => 10,
+        }
+    }
+
+    /// Whether the command is followed by reading two words.
+    fn has_answer(self) -> bool {
+        matches!(self, Operation::MeasureAirQuality | Operation::GetBaseline)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Off,
+    /// Waiting for the next measurement.
+    Idle,
+    Writing(Operation),
+    Processing(Operation),
+    Reading(Operation),
+}
+
+pub struct Sgp30<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn AirQualityClient>,
+    baseline_client: OptionalCell<&'a dyn Sgp30BaselineClient>,
+    /// When the last measurement was started.
+    measured_at: Cell<A::Ticks>,
+    co2_pending: Cell<bool>,
+    tvoc_pending: Cell<bool>,
+    get_baseline_pending: Cell<bool>,
+    set_baseline_pending: OptionalCell<Baseline>,
+
+    storage: OptionalCell<&'a dyn NonvolatileStorage<'a>>,
+    storage_address: Cell<usize>,
+    storage_buffer: TakeCell<'static, [u8]>,
+    /// Whether a baseline read from storage should be restored.
+    restoring: Cell<bool>,
+    restored_baseline: OptionalCell<Baseline>,
+    save_baseline_pending: Cell<bool>,
+    /// Measurements left until the baseline is saved.
+    measurements_until_save: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Sgp30<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Sgp30<'a, A, I> {
+        Sgp30 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Off),
+            client: OptionalCell::empty(),
+            baseline_client: OptionalCell::empty(),
+            measured_at: Cell::new(alarm.now()),
+            co2_pending: Cell::new(false),
+            tvoc_pending: Cell::new(false),
+            get_baseline_pending: Cell::new(false),
+            set_baseline_pending: OptionalCell::empty(),
+            storage: OptionalCell::empty(),
+            storage_address: Cell::new(0),
+            storage_buffer: TakeCell::empty(),
+            restoring: Cell::new(false),
+            restored_baseline: OptionalCell::empty(),
+            save_baseline_pending: Cell::new(false),
+            measurements_until_save: Cell::new(BASELINE_FIRST_SAVE),
+        }
+    }
+
+    pub fn set_baseline_client(&self, client: &'a dyn Sgp30BaselineClient) {
+        self.baseline_client.set(client);
+    }
+
+    /// Keep the baseline in `storage` at `address`, which needs
+    /// [`BASELINE_SIZE`] bytes. `buffer` must be at least that long.
+    pub fn set_baseline_storage(
+        &self,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        address: usize,
+        buffer: &'static mut [u8],
+    ) {
+        self.storage.set(storage);
+        self.storage_address.set(address);
+        self.storage_buffer.replace(buffer);
+    }
+
+    /// Initialize the air quality algorithm and start measuring, restoring
+    /// the baseline if it is kept in storage.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Off {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.storage.map(|storage| {
+            self.storage_buffer.take().map(|buffer| {
+                self.restoring.set(true);
+                if let Err(e) = storage.read(buffer, self.storage_address.get(), BASELINE_SIZE) {
+                    kernel::debug!("sgp30: failed to read the baseline: {:?}", e);
+                }
+            });
+        });
+        self.begin(Operation::InitAirQuality)
+    }
+
+    /// Read the baseline. It is only meaningful once the algorithm has run
+    /// for 12 hours, or a baseline was restored.
+    pub fn get_baseline(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.get_baseline_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.get_baseline_pending.set(true);
+        self.run_next();
+        Ok(())
+    }
+
+    /// Write a baseline that was read earlier.
+    pub fn set_baseline(&self, baseline: Baseline) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.set_baseline_pending.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        // The baseline in storage is older.
+        self.restoring.set(false);
+        self.restored_baseline.clear();
+        self.set_baseline_pending.set(baseline);
+        self.run_next();
+        Ok(())
+    }
+
+    /// Send the command for `operation`.
+    fn begin(&self, operation: Operation) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        // The alarm may be set for the next measurement.
+        let _ = self.alarm.disarm();
+        buffer[..2].copy_from_slice(&operation.command().to_be_bytes());
+        let len = match operation {
+            Operation::SetBaseline { baseline, .. } => {
+                // The baseline is written in the reverse order it is read.
+                put_word(&mut buffer[2..], baseline.tvoc);
+                put_word(&mut buffer[5..], baseline.eco2);
+                8
+            }
+            _ => 2,
+        };
+        self.state.set(State::Writing(operation));
+        self.i2c.write(buffer, len).map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            self.state.set(State::Idle);
+            e.into()
+        })
+    }
+
+    /// Start the next operation if the sensor is idle, or wait for the next
+    /// measurement.
+    fn run_next(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        let operation = if let Some(baseline) = self.set_baseline_pending.take() {
+            Operation::SetBaseline {
+                baseline,
+                restore: false,
+            }
+        } else if let Some(baseline) = self.restored_baseline.take() {
+            Operation::SetBaseline {
+                baseline,
+                restore: true,
+            }
+        } else if self.get_baseline_pending.get() || self.save_baseline_pending.get() {
+            Operation::GetBaseline
+        } else {
+            self.alarm.set_alarm(
+                self.measured_at.get(),
+                self.alarm.ticks_from_ms(MEASUREMENT_INTERVAL_MS),
+            );
+            return;
+        };
+        if let Err(e) = self.begin(operation) {
+            self.finish(operation, Err(e));
+        }
+    }
+
+    /// Complete `operation` with the words read, if any.
+    fn finish(&self, operation: Operation, result: Result<(u16, u16), ErrorCode>) {
+        self.state.set(State::Idle);
+        match operation {
+            Operation::InitAirQuality => {
+                if result.is_err() {
+                    self.state.set(State::Off);
+                    return;
+                }
+                self.measured_at.set(self.alarm.now());
+            }
+            Operation::MeasureAirQuality => {
+                if self.co2_pending.take() {
+                    self.client
+                        .map(|client| client.co2_data_available(result.map(|(co2, _)| co2 as u32)));
+                }
+                if self.tvoc_pending.take() {
+                    self.client.map(|client| {
+                        client.tvoc_data_available(result.map(|(_, tvoc)| tvoc as u32))
+                    });
+                }
+                if self.storage.is_some() {
+                    let left = self.measurements_until_save.get().saturating_sub(1);
+                    if left == 0 {
+                        self.save_baseline_pending.set(true);
+                        self.measurements_until_save.set(BASELINE_SAVE_INTERVAL);
+                    } else {
+                        self.measurements_until_save.set(left);
+                    }
+                }
+            }
+            Operation::GetBaseline => {
+                let baseline = result.map(|(eco2, tvoc)| Baseline { eco2, tvoc });
+                if self.save_baseline_pending.take() {
+                    if let Ok(baseline) = baseline {
+                        self.save_baseline(baseline);
+                    }
+                }
+                if self.get_baseline_pending.take() {
+                    self.baseline_client
+                        .map(|client| client.baseline_read(baseline));
+                }
+            }
+            Operation::SetBaseline { restore, .. } => {
+                if restore {
+                    if result.is_ok() {
+                        self.measurements_until_save.set(BASELINE_SAVE_INTERVAL);
+                    }
+                } else {
+                    self.baseline_client
+                        .map(|client| client.baseline_written(result.map(|_| ())));
+                }
+            }
+        }
+        self.run_next();
+    }
+
+    fn save_baseline(&self, baseline: Baseline) {
+        self.storage.map(|storage| {
+            self.storage_buffer.take().map(|buffer| {
+                put_word(buffer, baseline.eco2);
+                put_word(&mut buffer[3..], baseline.tvoc);
+                if let Err(e) = storage.write(buffer, self.storage_address.get(), BASELINE_SIZE) {
+                    kernel::debug!("sgp30: failed to save the baseline: {:?}", e);
+                }
+            });
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AirQualityDriver<'a> for Sgp30<'a, A, I> {
+    fn set_client(&self, client: &'a dyn AirQualityClient) {
+        self.client.set(client);
+    }
+
+    fn specify_environment(
+        &self,
+        _temp: Option<i32>,
+        _humidity: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        // The sensor compensates for absolute humidity, which would need a
+        // temperature and relative humidity conversion.
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn read_co2(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.co2_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
+    }
+
+    fn read_tvoc(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.tvoc_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AlarmClient for Sgp30<'a, A, I> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => {
+                self.measured_at.set(self.alarm.now());
+                if let Err(e) = self.begin(Operation::MeasureAirQuality) {
+                    self.finish(Operation::MeasureAirQuality, Err(e));
+                }
+            }
+            State::Processing(operation) if operation.has_answer() => {
+                self.buffer.take().map(|buffer| {
+                    self.state.set(State::Reading(operation));
+                    if let Err((e, buffer)) = self.i2c.read(buffer, 6) {
+                        self.buffer.replace(buffer);
+                        self.finish(operation, Err(e.into()));
+                    }
+                });
+            }
+            State::Processing(operation) => self.finish(operation, Ok((0, 0))),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Sgp30<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let (operation, words) = match (state, status) {
+            (State::Writing(operation), _) | (State::Reading(operation), Err(_)) => {
+                (operation, None)
+            }
+            (State::Reading(operation), Ok(())) => {
+                (operation, Some((get_word(buffer), get_word(&buffer[3..]))))
+            }
+            _ => {
+                self.buffer.replace(buffer);
+                return;
+            }
+        };
+        self.buffer.replace(buffer);
+
+        match (status, words) {
+            (Err(e), _) => self.finish(operation, Err(e.into())),
+            (Ok(()), None) => {
+                self.state.set(State::Processing(operation));
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(operation.duration_ms()),
+                );
+            }
+            (Ok(()), Some((Some(first), Some(second)))) => {
+                self.finish(operation, Ok((first, second)))
+            }
+            // A word was corrupted.
+            (Ok(()), Some(_)) => self.finish(operation, Err(ErrorCode::FAIL)),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> NonvolatileStorageClient for Sgp30<'a, A, I> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if length == BASELINE_SIZE {
+            if let (Some(eco2), Some(tvoc)) = (get_word(buffer), get_word(&buffer[3..])) {
+                if self.restoring.take() {
+                    self.restored_baseline.set(Baseline { eco2, tvoc });
+                    self.run_next();
+                }
+            }
+        }
+        self.storage_buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.storage_buffer.replace(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use core::cell::RefCell;
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct MockStorage {
+        reads: Cell<usize>,
+        writes: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl<'a> NonvolatileStorage<'a> for MockStorage {
+        fn set_client(&self, _client: &'a dyn NonvolatileStorageClient) {}
+        fn read(
+            &self,
+            buffer: &'static mut [u8],
+            _address: usize,
+            _length: usize,
+        ) -> Result<(), ErrorCode> {
+            self.reads.set(self.reads.get() + 1);
+            core::mem::forget(buffer);
+            Ok(())
+        }
+        fn write(
+            &self,
+            buffer: &'static mut [u8],
+            _address: usize,
+            length: usize,
+        ) -> Result<(), ErrorCode> {
+            self.writes.borrow_mut().push(buffer[..length].to_vec());
+            core::mem::forget(buffer);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        co2: Cell<Option<Result<u32, ErrorCode>>>,
+        tvoc: Cell<Option<Result<u32, ErrorCode>>>,
+        baseline: Cell<Option<Result<Baseline, ErrorCode>>>,
+        written: Cell<Option<Result<(), ErrorCode>>>,
+    }
+
+    impl AirQualityClient for MockClient {
+        fn environment_specified(&self, _result: Result<(), ErrorCode>) {}
+        fn co2_data_available(&self, value: Result<u32, ErrorCode>) {
+            self.co2.set(Some(value));
+        }
+        fn tvoc_data_available(&self, value: Result<u32, ErrorCode>) {
+            self.tvoc.set(Some(value));
+        }
+    }
+
+    impl Sgp30BaselineClient for MockClient {
+        fn baseline_read(&self, result: Result<Baseline, ErrorCode>) {
+            self.baseline.set(Some(result));
+        }
+        fn baseline_written(&self, result: Result<(), ErrorCode>) {
+            self.written.set(Some(result));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestSgp30 = Sgp30<'static, TestAlarm, MockI2c>;
+
+    fn setup() -> (
+        &'static TestSgp30,
+        &'static MockI2c,
+        &'static TestAlarm,
+        &'static MockClient,
+    ) {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let sgp30 = Box::leak(Box::new(Sgp30::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(sgp30);
+        sgp30.set_client(client);
+        sgp30.set_baseline_client(client);
+        (sgp30, i2c, alarm, client)
+    }
+
+    /// Two words with their CRCs.
+    fn words(first: u16, second: u16) -> [u8; 6] {
+        let mut data = [0; 6];
+        put_word(&mut data, first);
+        put_word(&mut data[3..], second);
+        data
+    }
+
+    /// Send a command and let the sensor process it, with the answer `data`.
+    fn run_command(i2c: &MockI2c, alarm: &TestAlarm, sgp30: &TestSgp30, data: &[u8]) {
+        i2c.complete(sgp30, &[]);
+        assert!(alarm.dt().unwrap() < 20_000);
+        alarm.fire();
+        if !data.is_empty() {
+            i2c.complete(sgp30, data);
+        }
+    }
+
+    /// The commands written, without the reads of their answers.
+    fn commands(i2c: &MockI2c) -> Vec<Vec<u8>> {
+        i2c.transfers()
+            .into_iter()
+            .filter(|transfer| transfer.read_len == 0)
+            .map(|transfer| transfer.write)
+            .collect()
+    }
+
+    #[test]
+    fn measures_every_second_and_checks_crcs() {
+        let (sgp30, i2c, alarm, client) = setup();
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+
+        assert_eq!(sgp30.read_co2(), Err(ErrorCode::OFF));
+        assert_eq!(sgp30.start(), Ok(()));
+        run_command(i2c, alarm, sgp30, &[]);
+        assert_eq!(alarm.dt(), Some(1_000_000));
+        assert_eq!(commands(i2c)[0], [0x20, 0x03]);
+
+        assert_eq!(sgp30.read_co2(), Ok(()));
+        assert_eq!(sgp30.read_tvoc(), Ok(()));
+        alarm.fire();
+        run_command(i2c, alarm, sgp30, &words(450, 12));
+        assert_eq!(commands(i2c)[1], [0x20, 0x08]);
+        assert_eq!(
+            i2c.transfers()
+                .iter()
+                .filter(|transfer| transfer.read_len > 0)
+                .count(),
+            1
+        );
+        assert_eq!(client.co2.take(), Some(Ok(450)));
+        assert_eq!(client.tvoc.take(), Some(Ok(12)));
+        assert_eq!(alarm.dt(), Some(1_000_000));
+
+        // Nothing is reported without a request, and a corrupted word fails
+        // the measurement.
+        alarm.fire();
+        run_command(i2c, alarm, sgp30, &words(460, 13));
+        assert_eq!(client.co2.take(), None);
+        assert_eq!(sgp30.read_co2(), Ok(()));
+        let mut data = words(470, 14);
+        data[4] ^= 0x01;
+        alarm.fire();
+        run_command(i2c, alarm, sgp30, &data);
+        assert_eq!(client.co2.take(), Some(Err(ErrorCode::FAIL)));
+        assert_eq!(alarm.dt(), Some(1_000_000));
+        assert_eq!(commands(i2c).len(), 4);
+    }
+
+    #[test]
+    fn baseline_round_trips_through_storage() {
+        let (sgp30, i2c, alarm, client) = setup();
+        let storage = Box::leak(Box::new(MockStorage::default()));
+        sgp30.set_baseline_storage(storage, 0, Box::leak(Box::new([0u8; BASELINE_SIZE])));
+        let stored = Baseline {
+            eco2: 0x8973,
+            tvoc: 0x8AAE,
+        };
+
+        // The baseline in storage is restored after initialization.
+        assert_eq!(sgp30.start(), Ok(()));
+        assert_eq!(storage.reads.get(), 1);
+        let data = words(stored.eco2, stored.tvoc);
+        sgp30.read_done(Box::leak(Box::new(data)), BASELINE_SIZE);
+        run_command(i2c, alarm, sgp30, &[]);
+        run_command(i2c, alarm, sgp30, &[]);
+        assert_eq!(
+            commands(i2c)[1],
+            [
+                0x20,
+                0x1E,
+                0x8A,
+                0xAE,
+                crc8(&[0x8A, 0xAE]),
+                0x89,
+                0x73,
+                crc8(&[0x89, 0x73])
+            ]
+        );
+        assert_eq!(client.written.get(), None);
+        assert_eq!(alarm.dt(), Some(1_000_000));
+
+        // It is read back as it was written.
+        assert_eq!(sgp30.get_baseline(), Ok(()));
+        run_command(i2c, alarm, sgp30, &data);
+        assert_eq!(commands(i2c)[2], [0x20, 0x15]);
+        assert_eq!(client.baseline.take(), Some(Ok(stored)));
+
+        let baseline = Baseline {
+            eco2: 0x8A00,
+            tvoc: 0x8B00,
+        };
+        assert_eq!(sgp30.set_baseline(baseline), Ok(()));
+        run_command(i2c, alarm, sgp30, &[]);
+        assert_eq!(client.written.take(), Some(Ok(())));
+
+        // After an hour of measurements, the baseline is saved.
+        for _ in 0..BASELINE_SAVE_INTERVAL {
+            assert!(storage.writes.borrow().is_empty());
+            alarm.fire();
+            run_command(i2c, alarm, sgp30, &words(400, 0));
+        }
+        run_command(i2c, alarm, sgp30, &words(baseline.eco2, baseline.tvoc));
+        assert_eq!(
+            storage.writes.borrow()[0],
+            words(baseline.eco2, baseline.tvoc)
+        );
+        assert_eq!(client.baseline.get(), None);
+        assert_eq!(alarm.dt(), Some(1_000_000));
+    }
+}
@@ -0,0 +1,572 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Sensirion's gas index algorithm, which turns the raw signals of the
+//! SGP41 into a VOC index and a NOx index.
+//!
+//! <https://github.com/Sensirion/gas-index-algorithm>
+//!
+//! The VOC index is 100 for the average air of the last 24 hours, and goes
+//! up to 500 with more VOCs and down to 1 with fewer. The NOx index is 1 for
+//! the average air, and goes up to 500 with more NOx. The algorithm learns
+//! the mean and the spread of the raw signal, quickly at first and then
+//! over 12 hours, and stops learning while the index is high, so that a long
+//! event is not taken as the new average.
+//!
+//! This is a port of version 3.2 of the algorithm, with one instance per
+//! signal, sampled every second, and without the tuning parameters. The
+//! reference uses `float`. This port uses Q16.16 fixed point, like the
+//! earlier versions of the reference, as `core` has no `exp` or `sqrt` and
+//! many of the chips Tock runs on have no FPU, so the indexes can differ
+//! slightly from the reference.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A Q16.16 fixed point number. The arithmetic saturates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Fix16(i32);
+
+/// The `Fix16` closest to a float, for constants.
+macro_rules! f16 {
+    ($x:expr) => {
+        Fix16(if $x >= 0.0 {
+            ($x * 65536.0 + 0.5) as i32
+        } else {
+            ($x * 65536.0 - 0.5) as i32
+        })
+    };
+}
+
+impl Fix16 {
+    const ZERO: Fix16 = Fix16(0);
+    const ONE: Fix16 = Fix16(1 << 16);
+    const HALF: Fix16 = Fix16(1 << 15);
+    const MAX: Fix16 = Fix16(i32::MAX);
+
+    const fn from_int(value: i32) -> Fix16 {
+        Fix16(value << 16)
+    }
+
+    fn saturate(value: i64) -> Fix16 {
+        Fix16(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+
+    fn to_int(self) -> i32 {
+        self.0 >> 16
+    }
+
+    fn abs(self) -> Fix16 {
+        if self < Fix16::ZERO {
+            -self
+        } else {
+            self
+        }
+    }
+
+    fn sqrt(self) -> Fix16 {
+        if self <= Fix16::ZERO {
+            return Fix16::ZERO;
+        }
+        // The square root of a Q32.32 number is Q16.16.
+        let value = (self.0 as u64) << 16;
+        let mut root = 0u64;
+        let mut bit = 1u64 << 62;
+        while bit > value {
+            bit >>= 2;
+        }
+        let mut rest = value;
+        while bit != 0 {
+            if rest >= root + bit {
+                rest -= root + bit;
+                root = (root >> 1) + bit;
+            } else {
+                root >>= 1;
+            }
+            bit >>= 2;
+        }
+        Fix16(root as i32)
+    }
+
+    /// `exp`, by multiplying the exponentials of ±1, ±1/8, ±1/64 and ±1/512,
+    /// as the fixed point versions of the reference do.
+    fn exp(self) -> Fix16 {
+        const POSITIVE: [Fix16; 4] = [
+            f16!(core::f64::consts::E),
+            f16!(1.1331485),
+            f16!(1.0157477),
+            f16!(1.0019550),
+        ];
+        const NEGATIVE: [Fix16; 4] = [
+            f16!(0.3678794),
+            f16!(0.8824969),
+            f16!(0.9844964),
+            f16!(0.9980488),
+        ];
+        // The largest and smallest exponents with a result in range.
+        const MAX_EXPONENT: Fix16 = f16!(10.3972);
+        const MIN_EXPONENT: Fix16 = f16!(-11.7835);
+        if self >= MAX_EXPONENT {
+            return Fix16::MAX;
+        }
+        if self <= MIN_EXPONENT {
+            return Fix16::ZERO;
+        }
+        let (mut x, factors) = if self < Fix16::ZERO {
+            (-self, &NEGATIVE)
+        } else {
+            (self, &POSITIVE)
+        };
+        let mut result = Fix16::ONE;
+        let mut step = Fix16::ONE;
+        for &factor in factors {
+            while x >= step {
+                result = result * factor;
+                x = x - step;
+            }
+            step = Fix16(step.0 >> 3);
+        }
+        result
+    }
+}
+
+impl Add for Fix16 {
+    type Output = Fix16;
+    fn add(self, other: Fix16) -> Fix16 {
+        Fix16(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Fix16 {
+    type Output = Fix16;
+    fn sub(self, other: Fix16) -> Fix16 {
+        Fix16(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Mul for Fix16 {
+    type Output = Fix16;
+    fn mul(self, other: Fix16) -> Fix16 {
+        Fix16::saturate((self.0 as i64 * other.0 as i64 + (1 << 15)) >> 16)
+    }
+}
+
+impl Div for Fix16 {
+    type Output = Fix16;
+    fn div(self, other: Fix16) -> Fix16 {
+        if other.0 == 0 {
+            return if self < Fix16::ZERO {
+                Fix16(i32::MIN)
+            } else {
+                Fix16::MAX
+            };
+        }
+        Fix16::saturate(((self.0 as i64) << 16) / other.0 as i64)
+    }
+}
+
+impl Neg for Fix16 {
+    type Output = Fix16;
+    fn neg(self) -> Fix16 {
+        Fix16(self.0.saturating_neg())
+    }
+}
+
+/// The sampling interval, in seconds.
+const SAMPLING_INTERVAL: f64 = 1.0;
+/// Seconds of samples ignored after the start.
+const INITIAL_BLACKOUT: Fix16 = f16!(45.0);
+const INDEX_GAIN: Fix16 = f16!(230.0);
+const SRAW_STD_INITIAL: Fix16 = f16!(50.0);
+const SRAW_STD_BONUS_VOC: Fix16 = f16!(220.0);
+const SRAW_STD_NOX: Fix16 = f16!(2000.0);
+const TAU_MEAN_HOURS: f64 = 12.0;
+const TAU_VARIANCE_HOURS: f64 = 12.0;
+const TAU_INITIAL_MEAN_VOC: f64 = 20.0;
+const TAU_INITIAL_MEAN_NOX: f64 = 1200.0;
+const TAU_INITIAL_VARIANCE: f64 = 2500.0;
+const INIT_TRANSITION_MEAN: Fix16 = f16!(0.01);
+const INIT_TRANSITION_VARIANCE: Fix16 = f16!(0.01);
+const GATING_THRESHOLD_INITIAL: Fix16 = f16!(510.0);
+const GATING_THRESHOLD_TRANSITION: Fix16 = f16!(0.09);
+const GATING_MAX_RATIO: Fix16 = f16!(0.3);
+const SIGMOID_L: Fix16 = f16!(500.0);
+const LP_TAU_FAST: f64 = 20.0;
+const LP_TAU_SLOW: f64 = 500.0;
+const LP_ALPHA: Fix16 = f16!(-0.2);
+/// The estimator divides the deviations from the mean by this, to keep the
+/// variance in range.
+const MVE_GAMMA_SCALING: f64 = 64.0;
+const MVE_ADDITIONAL_GAMMA_MEAN_SCALING: f64 = 8.0;
+/// The uptimes of the estimator stop at the largest `Fix16`.
+const MVE_UPTIME_LIMIT: Fix16 = f16!(32767.0 - SAMPLING_INTERVAL);
+
+/// Rates of the fast and slow low pass filters.
+const LP_A1: Fix16 = f16!(SAMPLING_INTERVAL / (LP_TAU_FAST + SAMPLING_INTERVAL));
+const LP_A2: Fix16 = f16!(SAMPLING_INTERVAL / (LP_TAU_SLOW + SAMPLING_INTERVAL));
+
+/// Learning rates of the estimator, once it has learnt the signal.
+const GAMMA_MEAN: Fix16 = f16!(
+    MVE_ADDITIONAL_GAMMA_MEAN_SCALING * MVE_GAMMA_SCALING * (SAMPLING_INTERVAL / 3600.0)
+        / (TAU_MEAN_HOURS + SAMPLING_INTERVAL / 3600.0)
+);
+const GAMMA_VARIANCE: Fix16 = f16!(
+    MVE_GAMMA_SCALING * (SAMPLING_INTERVAL / 3600.0)
+        / (TAU_VARIANCE_HOURS + SAMPLING_INTERVAL / 3600.0)
+);
+const GAMMA_INITIAL_VARIANCE: Fix16 =
+    f16!(MVE_GAMMA_SCALING * SAMPLING_INTERVAL / (TAU_INITIAL_VARIANCE + SAMPLING_INTERVAL));
+
+/// The signal an instance of the algorithm processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Voc,
+    Nox,
+}
+
+/// The constants that differ between the signals.
+struct Parameters {
+    /// Raw signals are taken relative to this.
+    sraw_minimum: i32,
+    index_offset: Fix16,
+    /// Seconds of fast learning.
+    init_duration_mean: Fix16,
+    init_duration_variance: Fix16,
+    gamma_initial_mean: Fix16,
+    /// Learning stops above this index.
+    gating_threshold: Fix16,
+    /// Learning restarts after this many minutes without.
+    gating_max_duration_minutes: Fix16,
+    sigmoid_x0: Fix16,
+    sigmoid_k: Fix16,
+}
+
+const VOC: Parameters = Parameters {
+    sraw_minimum: 20000,
+    index_offset: f16!(100.0),
+    init_duration_mean: f16!(3600.0 * 0.75),
+    init_duration_variance: f16!(3600.0 * 1.45),
+    gamma_initial_mean: f16!(
+        MVE_ADDITIONAL_GAMMA_MEAN_SCALING * MVE_GAMMA_SCALING * SAMPLING_INTERVAL
+            / (TAU_INITIAL_MEAN_VOC + SAMPLING_INTERVAL)
+    ),
+    gating_threshold: f16!(340.0),
+    gating_max_duration_minutes: f16!(60.0 * 3.0),
+    sigmoid_x0: f16!(213.0),
+    sigmoid_k: f16!(-0.0065),
+};
+
+const NOX: Parameters = Parameters {
+    sraw_minimum: 10000,
+    index_offset: f16!(1.0),
+    init_duration_mean: f16!(3600.0 * 4.75),
+    init_duration_variance: f16!(3600.0 * 5.70),
+    gamma_initial_mean: f16!(
+        MVE_ADDITIONAL_GAMMA_MEAN_SCALING * MVE_GAMMA_SCALING * SAMPLING_INTERVAL
+            / (TAU_INITIAL_MEAN_NOX + SAMPLING_INTERVAL)
+    ),
+    gating_threshold: f16!(30.0),
+    gating_max_duration_minutes: f16!(60.0 * 12.0),
+    sigmoid_x0: f16!(614.0),
+    sigmoid_k: f16!(-0.0101),
+};
+
+/// A sigmoid falling from 1 to 0 around `x0`, with slope `k`.
+fn sigmoid(sample: Fix16, x0: Fix16, k: Fix16) -> Fix16 {
+    const LIMIT: Fix16 = f16!(50.0);
+    let x = k * (sample - x0);
+    if x < -LIMIT {
+        Fix16::ONE
+    } else if x > LIMIT {
+        Fix16::ZERO
+    } else {
+        Fix16::ONE / (Fix16::ONE + x.exp())
+    }
+}
+
+/// Learns the mean and the standard deviation of the raw signal.
+#[derive(Clone, Copy)]
+struct MeanVarianceEstimator {
+    initialized: bool,
+    /// The mean is kept relative to an offset, for precision.
+    mean: Fix16,
+    sraw_offset: Fix16,
+    std: Fix16,
+    gamma_mean: Fix16,
+    gamma_variance: Fix16,
+    uptime_gamma: Fix16,
+    uptime_gating: Fix16,
+    gating_duration_minutes: Fix16,
+}
+
+impl MeanVarianceEstimator {
+    const fn new() -> MeanVarianceEstimator {
+        MeanVarianceEstimator {
+            initialized: false,
+            mean: Fix16::ZERO,
+            sraw_offset: Fix16::ZERO,
+            std: SRAW_STD_INITIAL,
+            gamma_mean: Fix16::ZERO,
+            gamma_variance: Fix16::ZERO,
+            uptime_gamma: Fix16::ZERO,
+            uptime_gating: Fix16::ZERO,
+            gating_duration_minutes: Fix16::ZERO,
+        }
+    }
+
+    fn mean(&self) -> Fix16 {
+        self.mean + self.sraw_offset
+    }
+
+    /// The learning rates: fast at first, and zero while the index is high.
+    fn calculate_gamma(&mut self, p: &Parameters, gas_index: Fix16) {
+        const SECONDS: Fix16 = f16!(SAMPLING_INTERVAL);
+        const MINUTES: Fix16 = f16!(SAMPLING_INTERVAL / 60.0);
+        if self.uptime_gamma < MVE_UPTIME_LIMIT {
+            self.uptime_gamma = self.uptime_gamma + SECONDS;
+        }
+        if self.uptime_gating < MVE_UPTIME_LIMIT {
+            self.uptime_gating = self.uptime_gating + SECONDS;
+        }
+
+        let sigmoid_gamma_mean = sigmoid(
+            self.uptime_gamma,
+            p.init_duration_mean,
+            INIT_TRANSITION_MEAN,
+        );
+        let gamma_mean = GAMMA_MEAN + (p.gamma_initial_mean - GAMMA_MEAN) * sigmoid_gamma_mean;
+        let gating_threshold_mean = p.gating_threshold
+            + (GATING_THRESHOLD_INITIAL - p.gating_threshold)
+                * sigmoid(
+                    self.uptime_gating,
+                    p.init_duration_mean,
+                    INIT_TRANSITION_MEAN,
+                );
+        let sigmoid_gating_mean = sigmoid(
+            gas_index,
+            gating_threshold_mean,
+            GATING_THRESHOLD_TRANSITION,
+        );
+        self.gamma_mean = sigmoid_gating_mean * gamma_mean;
+
+        let sigmoid_gamma_variance = sigmoid(
+            self.uptime_gamma,
+            p.init_duration_variance,
+            INIT_TRANSITION_VARIANCE,
+        );
+        let gamma_variance = GAMMA_VARIANCE
+            + (GAMMA_INITIAL_VARIANCE - GAMMA_VARIANCE)
+                * (sigmoid_gamma_variance - sigmoid_gamma_mean);
+        let gating_threshold_variance = p.gating_threshold
+            + (GATING_THRESHOLD_INITIAL - p.gating_threshold)
+                * sigmoid(
+                    self.uptime_gating,
+                    p.init_duration_variance,
+                    INIT_TRANSITION_VARIANCE,
+                );
+        let sigmoid_gating_variance = sigmoid(
+            gas_index,
+            gating_threshold_variance,
+            GATING_THRESHOLD_TRANSITION,
+        );
+        self.gamma_variance = sigmoid_gating_variance * gamma_variance;
+
+        self.gating_duration_minutes = self.gating_duration_minutes
+            + MINUTES
+                * ((Fix16::ONE - sigmoid_gating_mean) * (Fix16::ONE + GATING_MAX_RATIO)
+                    - GATING_MAX_RATIO);
+        if self.gating_duration_minutes < Fix16::ZERO {
+            self.gating_duration_minutes = Fix16::ZERO;
+        }
+        if self.gating_duration_minutes > p.gating_max_duration_minutes {
+            self.uptime_gating = Fix16::ZERO;
+        }
+    }
+
+    fn process(&mut self, p: &Parameters, sraw: Fix16, gas_index: Fix16) {
+        const GAMMA_SCALING: Fix16 = f16!(MVE_GAMMA_SCALING);
+        const ADDITIONAL_GAMMA_MEAN_SCALING: Fix16 = f16!(MVE_ADDITIONAL_GAMMA_MEAN_SCALING);
+        /// The mean is moved into the offset beyond this.
+        const MEAN_LIMIT: Fix16 = f16!(100.0);
+        /// The variance is scaled down above this deviation.
+        const MAX_DEVIATION: Fix16 = f16!(1440.0);
+        if !self.initialized {
+            self.initialized = true;
+            self.sraw_offset = sraw;
+            self.mean = Fix16::ZERO;
+            return;
+        }
+        if self.mean >= MEAN_LIMIT || self.mean <= -MEAN_LIMIT {
+            self.sraw_offset = self.sraw_offset + self.mean;
+            self.mean = Fix16::ZERO;
+        }
+        let sraw = sraw - self.sraw_offset;
+        self.calculate_gamma(p, gas_index);
+        let delta = (sraw - self.mean) / GAMMA_SCALING;
+        let c = self.std + delta.abs();
+        let additional_scaling = if c > MAX_DEVIATION {
+            let ratio = c / MAX_DEVIATION;
+            ratio * ratio
+        } else {
+            Fix16::ONE
+        };
+        self.std = (additional_scaling * (GAMMA_SCALING - self.gamma_variance)).sqrt()
+            * (self.std * (self.std / (GAMMA_SCALING * additional_scaling))
+                + self.gamma_variance * delta / additional_scaling * delta)
+                .sqrt();
+        self.mean = self.mean + self.gamma_mean * delta / ADDITIONAL_GAMMA_MEAN_SCALING;
+    }
+}
+
+/// A low pass filter that follows fast changes quickly.
+#[derive(Clone, Copy)]
+struct AdaptiveLowpass {
+    initialized: bool,
+    x1: Fix16,
+    x2: Fix16,
+    x3: Fix16,
+}
+
+impl AdaptiveLowpass {
+    fn process(&mut self, sample: Fix16) -> Fix16 {
+        const INTERVAL: Fix16 = f16!(SAMPLING_INTERVAL);
+        const TAU_FAST: Fix16 = f16!(LP_TAU_FAST);
+        const TAU_SLOW: Fix16 = f16!(LP_TAU_SLOW);
+        if !self.initialized {
+            self.x1 = sample;
+            self.x2 = sample;
+            self.x3 = sample;
+            self.initialized = true;
+        }
+        self.x1 = (Fix16::ONE - LP_A1) * self.x1 + LP_A1 * sample;
+        self.x2 = (Fix16::ONE - LP_A2) * self.x2 + LP_A2 * sample;
+        let abs_delta = (self.x1 - self.x2).abs();
+        let f1 = (LP_ALPHA * abs_delta).exp();
+        let tau_a = (TAU_SLOW - TAU_FAST) * f1 + TAU_FAST;
+        let a3 = INTERVAL / (INTERVAL + tau_a);
+        self.x3 = (Fix16::ONE - a3) * self.x3 + a3 * sample;
+        self.x3
+    }
+}
+
+/// One instance of the algorithm.
+#[derive(Clone, Copy)]
+pub struct GasIndex {
+    algorithm: Algorithm,
+    uptime: Fix16,
+    /// The last valid raw signal, relative to the minimum.
+    sraw: Fix16,
+    gas_index: Fix16,
+    estimator: MeanVarianceEstimator,
+    lowpass: AdaptiveLowpass,
+}
+
+impl GasIndex {
+    pub const fn new(algorithm: Algorithm) -> GasIndex {
+        GasIndex {
+            algorithm,
+            uptime: Fix16::ZERO,
+            sraw: Fix16::ZERO,
+            gas_index: Fix16::ZERO,
+            estimator: MeanVarianceEstimator::new(),
+            lowpass: AdaptiveLowpass {
+                initialized: false,
+                x1: Fix16::ZERO,
+                x2: Fix16::ZERO,
+                x3: Fix16::ZERO,
+            },
+        }
+    }
+
+    fn parameters(&self) -> &'static Parameters {
+        match self.algorithm {
+            Algorithm::Voc => &VOC,
+            Algorithm::Nox => &NOX,
+        }
+    }
+
+    /// The index of the mean and standard deviation learnt so far.
+    fn mox_model(&self) -> Fix16 {
+        let deviation = self.sraw - self.estimator.mean();
+        match self.algorithm {
+            Algorithm::Voc => deviation / -(self.estimator.std + SRAW_STD_BONUS_VOC) * INDEX_GAIN,
+            Algorithm::Nox => deviation / SRAW_STD_NOX * INDEX_GAIN,
+        }
+    }
+
+    /// Process the raw signal of a sample, which must be taken every second.
+    /// Returns the index, or 0 for the first 45 seconds.
+    pub fn process(&mut self, sraw: u16) -> u32 {
+        const INTERVAL: Fix16 = f16!(SAMPLING_INTERVAL);
+        let p = self.parameters();
+        if self.uptime <= INITIAL_BLACKOUT {
+            self.uptime = self.uptime + INTERVAL;
+        } else {
+            if sraw > 0 && sraw < 65000 {
+                let sraw = (sraw as i32).clamp(p.sraw_minimum + 1, p.sraw_minimum + 32767);
+                self.sraw = Fix16::from_int(sraw - p.sraw_minimum);
+            }
+            self.gas_index = if self.algorithm == Algorithm::Voc || self.estimator.initialized {
+                // Without tuning, the scaled sigmoid of the reference is not
+                // shifted.
+                SIGMOID_L * sigmoid(self.mox_model(), p.sigmoid_x0, p.sigmoid_k)
+            } else {
+                p.index_offset
+            };
+            self.gas_index = self.lowpass.process(self.gas_index);
+            if self.gas_index < Fix16::HALF {
+                self.gas_index = Fix16::HALF;
+            }
+            if self.sraw > Fix16::ZERO {
+                self.estimator.process(p, self.sraw, self.gas_index);
+            }
+        }
+        (self.gas_index + Fix16::HALF).to_int() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_point() {
+        assert_eq!(f16!(1.5) * f16!(-2.0), f16!(-3.0));
+        assert_eq!(f16!(1.0) / f16!(4.0), f16!(0.25));
+        assert_eq!(f16!(2.25).sqrt(), f16!(1.5));
+        assert_eq!(Fix16::from_int(30000) * Fix16::from_int(2), Fix16::MAX);
+        // Within 0.1% of the real values.
+        assert!((Fix16::ONE.exp() - f16!(core::f64::consts::E)).abs() < f16!(0.003));
+        assert!((f16!(-2.5).exp() - f16!(0.0820850)).abs() < f16!(0.0001));
+        assert!((f16!(6.2).exp() - f16!(492.749)).abs() < f16!(0.5));
+    }
+
+    #[test]
+    fn indexes_follow_the_signal() {
+        let mut voc = GasIndex::new(Algorithm::Voc);
+        let mut nox = GasIndex::new(Algorithm::Nox);
+        for _ in 0..45 {
+            assert_eq!(voc.process(30000), 0);
+            assert_eq!(nox.process(15000), 0);
+        }
+
+        // Steady air is average air.
+        for _ in 0..600 {
+            voc.process(30000);
+            nox.process(15000);
+        }
+        assert_eq!(voc.process(30000), 100);
+        assert_eq!(nox.process(15000), 1);
+
+        // VOCs lower the raw signal, and NOx raises it. The VOC index then
+        // falls back as the algorithm learns the new signal as average.
+        let (mut voc_peak, mut nox_index) = (0, 0);
+        for _ in 0..120 {
+            voc_peak = voc_peak.max(voc.process(29000));
+            nox_index = nox.process(17000);
+        }
+        assert!(voc_peak > 250, "{}", voc_peak);
+        assert!(voc.process(29000) < 200);
+        assert!(nox_index > 3, "{}", nox_index);
+    }
+}
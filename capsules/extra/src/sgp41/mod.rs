@@ -0,0 +1,769 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Sensirion SGP41 VOC and NOx sensor, reporting the VOC
+//! index and the NOx index.
+//!
+//! <https://sensirion.com/media/documents/5FE8673C/61E96F50/Sensirion_Gas_Sensors_Datasheet_SGP41.pdf>
+//!
+//! The sensor has two hotplates and returns a raw signal for each. The
+//! driver samples them every second and runs the [`gas_index`] algorithm
+//! on them, and requests for an index are answered with the next sample
+//! that has one. The algorithm ignores the first 45 seconds of samples.
+//! Every word read from or written to the sensor is followed by a CRC8.
+//!
+//! After it is powered on, the NOx hotplate has to be conditioned for 10
+//! seconds, and no longer, before it can measure. The driver conditions it
+//! for the seconds left, and then measures both signals. If the driver is
+//! given a key-value store, it keeps the seconds of conditioning done there,
+//! so that the sensor is not conditioned again after a reset of the
+//! processor alone. A board that can reset the processor without power
+//! cycling the sensor, or the other way around, should not give it one.
+//!
+//! The sensor compensates its signals for the temperature and humidity set
+//! with `specify_environment`, 25 °C and 50 %RH until then.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sgp41 = components::sgp41::Sgp41Component::new(
+//!     mux_i2c,
+//!     capsules_extra::sgp41::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(kv_store),
+//! )
+//! .finalize(components::sgp41_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//!     capsules_extra::tickv::TicKVStore<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52840::nvmc::Nvmc>,
+//!         capsules_extra::sip_hash::SipHasher24<'static>,
+//!         2048,
+//!     >,
+//!     [u8; 8]
+//! ));
+//! ```
+
+pub mod gas_index;
+
+use core::cell::Cell;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::kv_system::{self, KVSystem};
+use kernel::hil::sensors::{AirQualityClient, AirQualityDriver};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::kv_store::KVStore;
+use gas_index::{Algorithm, GasIndex};
+
+pub const BASE_ADDR: u8 = 0x59;
+
+/// Size of the buffer for the sensor: a command and two words with their
+/// CRCs.
+pub const BUFFER_SIZE: usize = 8;
+
+/// Key of the seconds of conditioning done in the key-value store.
+pub const CONDITIONING_KEY: &[u8] = b"sgp41-conditioning";
+
+/// Size of the value buffer for the key-value store, which holds the
+/// store's header as well.
+pub const STORAGE_BUFFER_SIZE: usize = 16;
+
+const CMD_EXECUTE_CONDITIONING: u16 = 0x2612;
+const CMD_MEASURE_RAW_SIGNALS: u16 = 0x2619;
+
+/// The algorithm needs a sample every second.
+const MEASUREMENT_INTERVAL_MS: u32 = 1000;
+
+/// The longest the sensor takes to process a command.
+const COMMAND_DURATION_MS: u32 = 50;
+
+/// Seconds the NOx hotplate is conditioned for.
+const CONDITIONING_S: u32 = 10;
+
+/// Compensation for 50 %RH and 25 °C.
+const DEFAULT_HUMIDITY_TICKS: u16 = 0x8000;
+const DEFAULT_TEMPERATURE_TICKS: u16 = 0x6666;
+
+fn crc8(data: &[u8]) -> u8 {
+    let polynomial = 0x31;
+    let mut crc = 0xff;
+
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if (crc & 0x80) != 0 {
+                crc = crc << 1 ^ polynomial;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Write `word` and its CRC to the start of `buffer`.
+fn put_word(buffer: &mut [u8], word: u16) {
+    buffer[..2].copy_from_slice(&word.to_be_bytes());
+    buffer[2] = crc8(&buffer[..2]);
+}
+
+/// Read a word from the start of `buffer`, if its CRC is correct.
+fn get_word(buffer: &[u8]) -> Option<u16> {
+    if crc8(&buffer[..2]) == buffer[2] {
+        Some(u16::from_be_bytes([buffer[0], buffer[1]]))
+    } else {
+        None
+    }
+}
+
+/// The compensation word for a relative humidity in %.
+fn humidity_ticks(humidity: u32) -> u16 {
+    (humidity.min(100) * 65535 / 100) as u16
+}
+
+/// The compensation word for a temperature in °C.
+fn temperature_ticks(temp: i32) -> u16 {
+    ((temp.clamp(-45, 130) + 45) as u32 * 65535 / 175) as u16
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Command {
+    ExecuteConditioning,
+    MeasureRawSignals,
+}
+
+impl Command {
+    fn command(self) -> u16 {
+        match self {
+            Command::ExecuteConditioning => CMD_EXECUTE_CONDITIONING,
+            Command::MeasureRawSignals => CMD_MEASURE_RAW_SIGNALS,
+        }
+    }
+
+    /// Bytes read after the command: the VOC signal, and for a measurement
+    /// the NOx signal, with their CRCs.
+    fn answer_len(self) -> usize {
+        match self {
+            Command::ExecuteConditioning => 3,
+            Command::MeasureRawSignals => 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Off,
+    /// Reading the seconds of conditioning done from the key-value store.
+    Loading,
+    /// Waiting for the next sample.
+    Idle,
+    Writing(Command),
+    Processing(Command),
+    Reading(Command),
+}
+
+pub struct Sgp41<
+    'a,
+    A: Alarm<'a>,
+    I: I2CDevice,
+    K: KVSystem<'a> + KVSystem<'a, K = T>,
+    T: 'static + kv_system::KeyType,
+> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn AirQualityClient>,
+    /// When the last sample was started.
+    measured_at: Cell<A::Ticks>,
+    conditioned_s: Cell<u32>,
+    humidity_ticks: Cell<u16>,
+    temperature_ticks: Cell<u16>,
+    environment_pending: Cell<bool>,
+    voc_pending: Cell<bool>,
+    nox_pending: Cell<bool>,
+    voc: Cell<GasIndex>,
+    nox: Cell<GasIndex>,
+
+    storage: OptionalCell<&'a KVStore<'a, K, T>>,
+    storage_key: TakeCell<'static, [u8]>,
+    storage_buffer: TakeCell<'static, [u8]>,
+    storage_permissions: OptionalCell<StoragePermissions>,
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        I: I2CDevice,
+        K: KVSystem<'a> + KVSystem<'a, K = T>,
+        T: 'static + kv_system::KeyType,
+    > Sgp41<'a, A, I, K, T>
+{
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Sgp41<'a, A, I, K, T> {
+        Sgp41 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Off),
+            client: OptionalCell::empty(),
+            measured_at: Cell::new(alarm.now()),
+            conditioned_s: Cell::new(0),
+            humidity_ticks: Cell::new(DEFAULT_HUMIDITY_TICKS),
+            temperature_ticks: Cell::new(DEFAULT_TEMPERATURE_TICKS),
+            environment_pending: Cell::new(false),
+            voc_pending: Cell::new(false),
+            nox_pending: Cell::new(false),
+            voc: Cell::new(GasIndex::new(Algorithm::Voc)),
+            nox: Cell::new(GasIndex::new(Algorithm::Nox)),
+            storage: OptionalCell::empty(),
+            storage_key: TakeCell::empty(),
+            storage_buffer: TakeCell::empty(),
+            storage_permissions: OptionalCell::empty(),
+        }
+    }
+
+    /// Keep the seconds of conditioning done in `storage`, under the key in
+    /// `key`. `buffer` must be at least [`STORAGE_BUFFER_SIZE`] long.
+    pub fn set_conditioning_storage(
+        &self,
+        storage: &'a KVStore<'a, K, T>,
+        key: &'static mut [u8],
+        buffer: &'static mut [u8],
+        permissions: StoragePermissions,
+    ) {
+        self.storage.set(storage);
+        self.storage_key.replace(key);
+        self.storage_buffer.replace(buffer);
+        self.storage_permissions.set(permissions);
+    }
+
+    /// Start sampling, after the conditioning left.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Off {
+            return Err(ErrorCode::ALREADY);
+        }
+        let loading = self.storage.map_or(false, |storage| {
+            match (
+                self.storage_key.take(),
+                self.storage_buffer.take(),
+                self.storage_permissions.extract(),
+            ) {
+                (Some(key), Some(buffer), Some(permissions)) => {
+                    match storage.get(key, buffer, permissions) {
+                        Ok(()) => true,
+                        Err((key, buffer, e)) => {
+                            kernel::debug!("sgp41: failed to read the conditioning: {:?}", e);
+                            self.storage_key.replace(key);
+                            self.storage_buffer.replace(buffer);
+                            false
+                        }
+                    }
+                }
+                (key, buffer, _) => {
+                    key.map(|key| self.storage_key.replace(key));
+                    buffer.map(|buffer| self.storage_buffer.replace(buffer));
+                    false
+                }
+            }
+        });
+        if loading {
+            self.state.set(State::Loading);
+        } else {
+            self.state.set(State::Idle);
+            self.sample();
+        }
+        Ok(())
+    }
+
+    /// Condition the NOx hotplate, or measure once it is conditioned.
+    fn sample(&self) {
+        self.measured_at.set(self.alarm.now());
+        let command = if self.conditioned_s.get() < CONDITIONING_S {
+            Command::ExecuteConditioning
+        } else {
+            Command::MeasureRawSignals
+        };
+        if let Err(e) = self.begin(command) {
+            self.finish(command, Err(e));
+        }
+    }
+
+    /// Send `command`, with the compensation.
+    fn begin(&self, command: Command) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..2].copy_from_slice(&command.command().to_be_bytes());
+        put_word(&mut buffer[2..], self.humidity_ticks.get());
+        put_word(&mut buffer[5..], self.temperature_ticks.get());
+        self.state.set(State::Writing(command));
+        self.i2c.write(buffer, 8).map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            e.into()
+        })
+    }
+
+    /// Complete `command` with the signals read, and wait for the next
+    /// sample.
+    fn finish(&self, command: Command, result: Result<(u16, u16), ErrorCode>) {
+        self.state.set(State::Idle);
+        if let Ok((sraw_voc, sraw_nox)) = result {
+            if command == Command::ExecuteConditioning {
+                self.conditioned_s.set(self.conditioned_s.get() + 1);
+                if self.conditioned_s.get() == CONDITIONING_S {
+                    self.save_conditioning();
+                }
+            }
+            // The algorithm runs on every sample. A NOx signal of 0 while
+            // conditioning only counts towards its start.
+            let mut voc = self.voc.get();
+            let voc_index = voc.process(sraw_voc);
+            self.voc.set(voc);
+            let mut nox = self.nox.get();
+            let nox_index = nox.process(sraw_nox);
+            self.nox.set(nox);
+
+            if voc_index > 0 && self.voc_pending.take() {
+                self.client
+                    .map(|client| client.voc_index_available(Ok(voc_index)));
+            }
+            if nox_index > 0 && self.nox_pending.take() {
+                self.client
+                    .map(|client| client.nox_index_available(Ok(nox_index)));
+            }
+        } else {
+            if self.voc_pending.take() {
+                self.client
+                    .map(|client| client.voc_index_available(result.map(|_| 0)));
+            }
+            if self.nox_pending.take() {
+                self.client
+                    .map(|client| client.nox_index_available(result.map(|_| 0)));
+            }
+        }
+        self.alarm.set_alarm(
+            self.measured_at.get(),
+            self.alarm.ticks_from_ms(MEASUREMENT_INTERVAL_MS),
+        );
+    }
+
+    fn save_conditioning(&self) {
+        self.storage.map(|storage| {
+            match (
+                self.storage_key.take(),
+                self.storage_buffer.take(),
+                self.storage_permissions.extract(),
+            ) {
+                (Some(key), Some(buffer), Some(permissions)) => {
+                    buffer[..4].copy_from_slice(&self.conditioned_s.get().to_le_bytes());
+                    if let Err((key, buffer, e)) = storage.set(key, buffer, 4, permissions) {
+                        kernel::debug!("sgp41: failed to save the conditioning: {:?}", e);
+                        self.storage_key.replace(key);
+                        self.storage_buffer.replace(buffer);
+                    }
+                }
+                (key, buffer, _) => {
+                    key.map(|key| self.storage_key.replace(key));
+                    buffer.map(|buffer| self.storage_buffer.replace(buffer));
+                }
+            }
+        });
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        I: I2CDevice,
+        K: KVSystem<'a> + KVSystem<'a, K = T>,
+        T: 'static + kv_system::KeyType,
+    > AirQualityDriver<'a> for Sgp41<'a, A, I, K, T>
+{
+    fn set_client(&self, client: &'a dyn AirQualityClient) {
+        self.client.set(client);
+    }
+
+    /// `temp` is in °C and `humidity` in %RH. The compensation applies from
+    /// the next sample, and `None` restores the default.
+    fn specify_environment(
+        &self,
+        temp: Option<i32>,
+        humidity: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.environment_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.humidity_ticks
+            .set(humidity.map_or(DEFAULT_HUMIDITY_TICKS, humidity_ticks));
+        self.temperature_ticks
+            .set(temp.map_or(DEFAULT_TEMPERATURE_TICKS, temperature_ticks));
+        Ok(())
+    }
+
+    fn read_co2(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn read_tvoc(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn read_voc_index(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.voc_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
+    }
+
+    fn read_nox_index(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Off {
+            return Err(ErrorCode::OFF);
+        }
+        if self.nox_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        I: I2CDevice,
+        K: KVSystem<'a> + KVSystem<'a, K = T>,
+        T: 'static + kv_system::KeyType,
+    > AlarmClient for Sgp41<'a, A, I, K, T>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => self.sample(),
+            State::Processing(command) => {
+                self.buffer.take().map(|buffer| {
+                    self.state.set(State::Reading(command));
+                    if let Err((e, buffer)) = self.i2c.read(buffer, command.answer_len()) {
+                        self.buffer.replace(buffer);
+                        self.finish(command, Err(e.into()));
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        I: I2CDevice,
+        K: KVSystem<'a> + KVSystem<'a, K = T>,
+        T: 'static + kv_system::KeyType,
+    > I2CClient for Sgp41<'a, A, I, K, T>
+{
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let signals = match (state, status) {
+            (State::Reading(Command::ExecuteConditioning), Ok(())) => {
+                get_word(buffer).map(|voc| (voc, 0))
+            }
+            (State::Reading(Command::MeasureRawSignals), Ok(())) => {
+                get_word(buffer).zip(get_word(&buffer[3..]))
+            }
+            _ => None,
+        };
+        self.buffer.replace(buffer);
+
+        match (state, status) {
+            (State::Writing(command), Ok(())) => {
+                if self.environment_pending.take() {
+                    self.client
+                        .map(|client| client.environment_specified(Ok(())));
+                }
+                self.state.set(State::Processing(command));
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(COMMAND_DURATION_MS),
+                );
+            }
+            (State::Writing(command), Err(e)) | (State::Reading(command), Err(e)) => {
+                self.finish(command, Err(e.into()))
+            }
+            // A word was corrupted.
+            (State::Reading(command), Ok(())) => {
+                self.finish(command, signals.ok_or(ErrorCode::FAIL))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        I: I2CDevice,
+        K: KVSystem<'a> + KVSystem<'a, K = T>,
+        T: 'static + kv_system::KeyType,
+    > kv_system::StoreClient<T> for Sgp41<'a, A, I, K, T>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        ret_buf: &'static mut [u8],
+    ) {
+        if result.is_ok() {
+            let done = u32::from_le_bytes([ret_buf[0], ret_buf[1], ret_buf[2], ret_buf[3]]);
+            self.conditioned_s.set(done.min(CONDITIONING_S));
+        }
+        self.storage_key.replace(key);
+        self.storage_buffer.replace(ret_buf);
+        if self.state.get() == State::Loading {
+            self.state.set(State::Idle);
+            self.sample();
+        }
+    }
+
+    fn set_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.storage_key.replace(key);
+        self.storage_buffer.replace(value);
+    }
+
+    fn delete_complete(&self, _result: Result<(), ErrorCode>, key: &'static mut [u8]) {
+        self.storage_key.replace(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::kv_system::StoreClient;
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// The key-value store is not used by the tests.
+    struct NoKv;
+
+    impl<'a> KVSystem<'a> for NoKv {
+        type K = [u8; 8];
+
+        fn set_client(&self, _client: &'a dyn kv_system::Client<[u8; 8]>) {}
+        fn generate_key(
+            &self,
+            unhashed_key: &'static mut [u8],
+            key_buf: &'static mut [u8; 8],
+        ) -> Result<
+            (),
+            (
+                &'static mut [u8],
+                &'static mut [u8; 8],
+                Result<(), ErrorCode>,
+            ),
+        > {
+            Err((unhashed_key, key_buf, Err(ErrorCode::NOSUPPORT)))
+        }
+        fn append_key(
+            &self,
+            key: &'static mut [u8; 8],
+            value: &'static mut [u8],
+        ) -> Result<
+            (),
+            (
+                &'static mut [u8; 8],
+                &'static mut [u8],
+                Result<(), ErrorCode>,
+            ),
+        > {
+            Err((key, value, Err(ErrorCode::NOSUPPORT)))
+        }
+        fn get_value(
+            &self,
+            key: &'static mut [u8; 8],
+            ret_buf: &'static mut [u8],
+        ) -> Result<
+            (),
+            (
+                &'static mut [u8; 8],
+                &'static mut [u8],
+                Result<(), ErrorCode>,
+            ),
+        > {
+            Err((key, ret_buf, Err(ErrorCode::NOSUPPORT)))
+        }
+        fn invalidate_key(
+            &self,
+            key: &'static mut [u8; 8],
+        ) -> Result<(), (&'static mut [u8; 8], Result<(), ErrorCode>)> {
+            Err((key, Err(ErrorCode::NOSUPPORT)))
+        }
+        fn garbage_collect(&self) -> Result<usize, Result<(), ErrorCode>> {
+            Err(Err(ErrorCode::NOSUPPORT))
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        voc: Cell<Option<Result<u32, ErrorCode>>>,
+        nox: Cell<Option<Result<u32, ErrorCode>>>,
+    }
+
+    impl AirQualityClient for MockClient {
+        fn environment_specified(&self, _result: Result<(), ErrorCode>) {}
+        fn co2_data_available(&self, _value: Result<u32, ErrorCode>) {}
+        fn tvoc_data_available(&self, _value: Result<u32, ErrorCode>) {}
+        fn voc_index_available(&self, value: Result<u32, ErrorCode>) {
+            self.voc.set(Some(value));
+        }
+        fn nox_index_available(&self, value: Result<u32, ErrorCode>) {
+            self.nox.set(Some(value));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestSgp41 = Sgp41<'static, TestAlarm, MockI2c, NoKv, [u8; 8]>;
+
+    fn setup() -> (
+        &'static TestSgp41,
+        &'static MockI2c,
+        &'static TestAlarm,
+        &'static MockClient,
+    ) {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let sgp41 = Box::leak(Box::new(Sgp41::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0u8; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(sgp41);
+        sgp41.set_client(client);
+        (sgp41, i2c, alarm, client)
+    }
+
+    /// The raw signals with their CRCs.
+    fn signals(voc: u16, nox: u16) -> [u8; 6] {
+        let mut data = [0; 6];
+        put_word(&mut data, voc);
+        put_word(&mut data[3..], nox);
+        data
+    }
+
+    /// Write the command of the next sample, and read `data` after the
+    /// sensor processed it.
+    fn run_sample(i2c: &MockI2c, alarm: &TestAlarm, sgp41: &TestSgp41, data: &[u8]) {
+        i2c.complete(sgp41, &[]);
+        assert_eq!(alarm.dt(), Some(50_000));
+        alarm.fire();
+        i2c.complete(sgp41, data);
+        assert_eq!(alarm.dt(), Some(1_000_000));
+        alarm.fire();
+    }
+
+    /// The commands written, without the reads of their answers.
+    fn commands(i2c: &MockI2c) -> Vec<Vec<u8>> {
+        i2c.transfers()
+            .into_iter()
+            .filter(|transfer| transfer.read_len == 0)
+            .map(|transfer| transfer.write)
+            .collect()
+    }
+
+    /// The length of the last read.
+    fn last_read_len(i2c: &MockI2c) -> Option<usize> {
+        i2c.transfers()
+            .iter()
+            .rev()
+            .map(|transfer| transfer.read_len)
+            .find(|&len| len > 0)
+    }
+
+    #[test]
+    fn conditions_then_measures_indexes() {
+        let (sgp41, i2c, alarm, client) = setup();
+        assert_eq!(sgp41.read_voc_index(), Err(ErrorCode::OFF));
+        assert_eq!(sgp41.read_co2(), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(sgp41.start(), Ok(()));
+
+        // Ten seconds of conditioning, with the default compensation.
+        for _ in 0..10 {
+            run_sample(i2c, alarm, sgp41, &signals(30000, 0)[..3]);
+            assert_eq!(last_read_len(i2c), Some(3));
+        }
+        assert_eq!(
+            commands(i2c)[0],
+            [0x26, 0x12, 0x80, 0x00, 0xA2, 0x66, 0x66, 0x93]
+        );
+
+        assert_eq!(sgp41.specify_environment(Some(25), Some(50)), Ok(()));
+        assert_eq!(sgp41.read_voc_index(), Ok(()));
+        assert_eq!(sgp41.read_nox_index(), Ok(()));
+        assert_eq!(sgp41.read_nox_index(), Err(ErrorCode::BUSY));
+        run_sample(i2c, alarm, sgp41, &signals(30000, 15000));
+        assert_eq!(last_read_len(i2c), Some(6));
+        assert_eq!(commands(i2c)[10][..2], [0x26, 0x19]);
+        assert_eq!(client.voc.get(), None);
+
+        // A corrupted word fails the reads.
+        i2c.complete(sgp41, &[]);
+        alarm.fire();
+        i2c.complete(sgp41, &[0, 0, 0, 0, 0, 0]);
+        assert_eq!(client.voc.get(), Some(Err(ErrorCode::FAIL)));
+        assert_eq!(client.nox.get(), Some(Err(ErrorCode::FAIL)));
+        alarm.fire();
+
+        // The indexes come after the 45 seconds of the algorithm's blackout,
+        // counted from the first sample.
+        client.voc.set(None);
+        client.nox.set(None);
+        assert_eq!(sgp41.read_voc_index(), Ok(()));
+        assert_eq!(sgp41.read_nox_index(), Ok(()));
+        for _ in 0..36 {
+            run_sample(i2c, alarm, sgp41, &signals(30000, 15000));
+        }
+        assert!(client.voc.get().is_some());
+        assert_eq!(client.nox.get(), Some(Ok(1)));
+
+        // The VOC index settles at 100 in steady air.
+        for _ in 0..600 {
+            run_sample(i2c, alarm, sgp41, &signals(30000, 15000));
+        }
+        assert_eq!(sgp41.read_voc_index(), Ok(()));
+        run_sample(i2c, alarm, sgp41, &signals(30000, 15000));
+        assert_eq!(client.voc.get(), Some(Ok(100)));
+    }
+
+    #[test]
+    fn resumes_stored_conditioning() {
+        let (sgp41, i2c, alarm, _client) = setup();
+        let buffer = Box::leak(Box::new([0u8; STORAGE_BUFFER_SIZE]));
+        buffer[..4].copy_from_slice(&8u32.to_le_bytes());
+        sgp41.get_complete(Ok(()), Box::leak(Box::new([0u8; 1])), buffer);
+
+        assert_eq!(sgp41.start(), Ok(()));
+        for _ in 0..2 {
+            run_sample(i2c, alarm, sgp41, &signals(30000, 0)[..3]);
+        }
+        i2c.complete(sgp41, &[]);
+        let writes = commands(i2c);
+        assert_eq!(writes[1][..2], [0x26, 0x12]);
+        assert_eq!(writes[2][..2], [0x26, 0x19]);
+    }
+}
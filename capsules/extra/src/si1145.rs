@@ -0,0 +1,733 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Silicon Labs Si1145 UV index, ambient light and proximity
+//! sensor.
+//!
+//! <https://www.silabs.com/documents/public/data-sheets/Si1145-46-47.pdf>
+//!
+//! > The Si1145/46/47 is a low-power, reflectance-based, infrared proximity,
+//! > ultraviolet (UV) index, and ambient light sensor with I2C digital
+//! > interface and programmable-event interrupt output.
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The Si1145 is controlled through a command register: the host writes a
+//! command (and for parameter writes, the value in `PARAM_WR`), then polls
+//! the `RESPONSE` register until the sensor acknowledges it. Every command is
+//! preceded by a `NOP` to clear the response counter, so any non-zero
+//! response means the command was processed, and responses with the top bit
+//! set are errors.
+//!
+//! The driver runs the sensor in forced measurement mode. A reading issues
+//! `ALS_FORCE` or `PS_FORCE` and polls `RESPONSE` and `IRQ_STATUS` together
+//! until the measurement completes. If an ADC saturates, the sensor reports
+//! an overflow error in `RESPONSE`; the driver then halves the integration
+//! time of that channel (its ADC gain) and measures again. Gains only go
+//! down after an overflow and are restored by [Si1145::configure].
+//!
+//! Ambient light is provided through [AmbientLight] and proximity through
+//! [ProximityDriver]. There is no HIL for UV index, so it is available to
+//! processes through this driver's own system call interface.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called with the status and the UV index in hundredths when a UV
+//!   reading completes.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: read the UV index
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let si1145 = components::si1145::Si1145Component::new(
+//!     mux_i2c,
+//!     capsules_extra::si1145::BASE_ADDR,
+//!     mux_alarm,
+//!     board_kernel,
+//!     capsules_extra::si1145::DRIVER_NUM,
+//! )
+//! .finalize(components::si1145_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI
+//! ));
+//! si1145.configure(3, capsules_extra::si1145::ProximityLed::Led1)?;
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{AmbientLight, AmbientLightClient, ProximityClient, ProximityDriver};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Si1145 as usize;
+
+/// Default I2C address of the Si1145.
+pub const BASE_ADDR: u8 = 0x60;
+
+/// Size of the buffer the driver needs.
+pub const BUFFER_SIZE: usize = 14;
+
+const REG_INT_CFG: u8 = 0x03;
+const REG_HW_KEY: u8 = 0x07;
+const REG_PS_LED21: u8 = 0x0F;
+const REG_UCOEF0: u8 = 0x13;
+const REG_PARAM_WR: u8 = 0x17;
+const REG_COMMAND: u8 = 0x18;
+const REG_RESPONSE: u8 = 0x20;
+const REG_IRQ_STATUS: u8 = 0x21;
+const REG_ALS_VIS_DATA0: u8 = 0x22;
+const REG_PS1_DATA0: u8 = 0x26;
+const REG_UVINDEX0: u8 = 0x2C;
+
+/// Value that must be written to `HW_KEY` for the sensor to operate.
+const HW_KEY: u8 = 0x17;
+
+/// Default UV index coefficients from the datasheet.
+const UCOEF: [u8; 4] = [0x7B, 0x6B, 0x01, 0x00];
+
+const CMD_NOP: u8 = 0x00;
+const CMD_PS_FORCE: u8 = 0x05;
+const CMD_ALS_FORCE: u8 = 0x06;
+const CMD_PARAM_SET: u8 = 0xA0;
+
+const PARAM_CHLIST: u8 = 0x01;
+const PARAM_PSLED12_SELECT: u8 = 0x02;
+const PARAM_PS1_ADCMUX: u8 = 0x07;
+const PARAM_PS_ADC_GAIN: u8 = 0x0B;
+const PARAM_ALS_VIS_ADC_GAIN: u8 = 0x11;
+const PARAM_ALS_IR_ADC_GAIN: u8 = 0x1E;
+
+/// Measure UV index, visible and IR ambient light, and proximity with LED 1.
+const CHLIST: u8 = 0b1011_0001;
+/// Use the large IR photodiode for proximity.
+const PS1_ADCMUX_LARGE_IR: u8 = 0x03;
+
+const IRQ_ALS: u8 = 1 << 0;
+const IRQ_PS1: u8 = 1 << 2;
+
+const RESPONSE_ERROR: u8 = 0x80;
+const RESPONSE_PS1_OVERFLOW: u8 = 0x88;
+const RESPONSE_ALS_VIS_OVERFLOW: u8 = 0x8C;
+const RESPONSE_ALS_IR_OVERFLOW: u8 = 0x8D;
+
+/// Dark offset of the ADC outputs.
+const ADC_OFFSET: u16 = 256;
+
+/// ADC gain (log2 of the integration time multiplier) measurements start
+/// with after `configure()`.
+const INITIAL_ADC_GAIN: u8 = 3;
+
+/// Time between polls of the response register.
+const POLL_INTERVAL_US: u32 = 1000;
+
+/// Number of polls after which a command is considered to have failed.
+const MAX_POLLS: usize = 50;
+
+/// LED used for proximity measurements.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProximityLed {
+    Led1,
+    Led2,
+    Led3,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Measurement {
+    Light,
+    Proximity,
+    Uv,
+}
+
+impl Measurement {
+    fn command(&self) -> u8 {
+        match self {
+            Measurement::Light | Measurement::Uv => CMD_ALS_FORCE,
+            Measurement::Proximity => CMD_PS_FORCE,
+        }
+    }
+
+    fn irq(&self) -> u8 {
+        match self {
+            Measurement::Light | Measurement::Uv => IRQ_ALS,
+            Measurement::Proximity => IRQ_PS1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Command {
+    SetParam(u8, u8),
+    Force(Measurement),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Idle,
+    Configure(usize),
+    Measure(Measurement),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    Write,
+    ClearIrq(Command),
+    ClearResponse(Command),
+    Issue(Command),
+    Poll(Command),
+    PollWait(Command),
+}
+
+/// ADC whose gain is reduced when it overflows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Adc {
+    Proximity,
+    Visible,
+    Infrared,
+}
+
+impl Adc {
+    fn gain_param(&self) -> u8 {
+        match self {
+            Adc::Proximity => PARAM_PS_ADC_GAIN,
+            Adc::Visible => PARAM_ALS_VIS_ADC_GAIN,
+            Adc::Infrared => PARAM_ALS_IR_ADC_GAIN,
+        }
+    }
+}
+
+/// Contents of the `RESPONSE` register after a command.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Response {
+    /// The command has not been processed yet.
+    Pending,
+    /// The command was processed.
+    Done,
+    /// An ADC saturated during a measurement.
+    Overflow(Option<Adc>),
+    /// The command was rejected.
+    Invalid,
+}
+
+fn parse_response(response: u8) -> Response {
+    match response {
+        0 => Response::Pending,
+        RESPONSE_PS1_OVERFLOW => Response::Overflow(Some(Adc::Proximity)),
+        RESPONSE_ALS_VIS_OVERFLOW => Response::Overflow(Some(Adc::Visible)),
+        RESPONSE_ALS_IR_OVERFLOW => Response::Overflow(Some(Adc::Infrared)),
+        // The other channels' overflows have no gain setting.
+        r if r > RESPONSE_ERROR => Response::Overflow(None),
+        RESPONSE_ERROR => Response::Invalid,
+        _ => Response::Done,
+    }
+}
+
+/// Read a 16-bit little-endian output from the polled registers. `reg` is
+/// the address of its low byte.
+fn output(buf: &[u8], reg: u8) -> u16 {
+    let i = (reg - REG_RESPONSE) as usize;
+    u16::from_le_bytes([buf[i], buf[i + 1]])
+}
+
+/// Convert `ALS_VIS_DATA` counts to lux.
+///
+/// At the lowest gain in direct sunlight one count is about 0.282 lux; each
+/// step of gain doubles the integration time and so the counts.
+fn visible_to_lux(counts: u16, gain: u8) -> usize {
+    (counts.saturating_sub(ADC_OFFSET) as usize * 282 / 1000) >> gain
+}
+
+/// Convert `PS1_DATA` counts to the 0 (far) to 255 (near) proximity scale.
+fn proximity_to_u8(counts: u16, gain: u8) -> u8 {
+    ((counts.saturating_sub(ADC_OFFSET) >> gain) >> 8) as u8
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: bool,
+}
+
+pub struct Si1145<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    polls: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    led_current: Cell<u8>,
+    led: Cell<ProximityLed>,
+    ps_gain: Cell<u8>,
+    vis_gain: Cell<u8>,
+    ir_gain: Cell<u8>,
+    ambient_light_client: OptionalCell<&'a dyn AmbientLightClient>,
+    proximity_client: OptionalCell<&'a dyn ProximityClient>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Si1145<'a, A, I> {
+    pub fn new(
+        i2c: &'a I,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Self {
+        Si1145 {
+            i2c,
+            alarm,
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Idle),
+            polls: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            led_current: Cell::new(0),
+            led: Cell::new(ProximityLed::Led1),
+            ps_gain: Cell::new(INITIAL_ADC_GAIN),
+            vis_gain: Cell::new(INITIAL_ADC_GAIN),
+            ir_gain: Cell::new(INITIAL_ADC_GAIN),
+            ambient_light_client: OptionalCell::empty(),
+            proximity_client: OptionalCell::empty(),
+            apps: grant,
+        }
+    }
+
+    /// Enable the sensor and set up its measurement channels.
+    ///
+    /// `led_current` is the drive current of the proximity LED, from 0 (off)
+    /// to 15 (about 359 mA) as encoded in the `PS_LED21` and `PS_LED3`
+    /// registers. `led` selects which LED output is pulsed for proximity
+    /// measurements. This also restores the ADC gains reduced after
+    /// overflows.
+    pub fn configure(&self, led_current: u8, led: ProximityLed) -> Result<(), ErrorCode> {
+        if led_current > 0xF {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.led_current.set(led_current);
+        self.led.set(led);
+        self.ps_gain.set(INITIAL_ADC_GAIN);
+        self.vis_gain.set(INITIAL_ADC_GAIN);
+        self.ir_gain.set(INITIAL_ADC_GAIN);
+
+        self.operation.set(Operation::Configure(0));
+        self.i2c.enable();
+        self.configure_step(0).map_err(|e| {
+            self.i2c.disable();
+            self.operation.set(Operation::Idle);
+            e
+        })
+    }
+
+    /// Issue step `step` of the configuration sequence.
+    fn configure_step(&self, step: usize) -> Result<(), ErrorCode> {
+        self.operation.set(Operation::Configure(step));
+        let current = self.led_current.get();
+        let (led21, led3, led_select) = match self.led.get() {
+            ProximityLed::Led1 => (current, 0, 0x01),
+            ProximityLed::Led2 => (current << 4, 0, 0x02),
+            ProximityLed::Led3 => (0, current, 0x04),
+        };
+        match step {
+            0 => self.write_registers(&[REG_HW_KEY, HW_KEY]),
+            1 => self.write_registers(&[REG_UCOEF0, UCOEF[0], UCOEF[1], UCOEF[2], UCOEF[3]]),
+            // Completion is polled, so only enable the interrupt status bits
+            // and leave the INT pin disabled.
+            2 => self.write_registers(&[REG_INT_CFG, 0, IRQ_ALS | IRQ_PS1]),
+            3 => self.write_registers(&[REG_PS_LED21, led21, led3]),
+            4 => self.start_command(Command::SetParam(PARAM_CHLIST, CHLIST)),
+            5 => self.start_command(Command::SetParam(PARAM_PSLED12_SELECT, led_select)),
+            6 => self.start_command(Command::SetParam(PARAM_PS1_ADCMUX, PS1_ADCMUX_LARGE_IR)),
+            7 => self.start_command(Command::SetParam(PARAM_PS_ADC_GAIN, self.ps_gain.get())),
+            8 => self.start_command(Command::SetParam(
+                PARAM_ALS_VIS_ADC_GAIN,
+                self.vis_gain.get(),
+            )),
+            9 => self.start_command(Command::SetParam(PARAM_ALS_IR_ADC_GAIN, self.ir_gain.get())),
+            _ => {
+                self.finish(Ok(0));
+                Ok(())
+            }
+        }
+    }
+
+    fn start_measurement(&self, measurement: Measurement) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(Operation::Measure(measurement));
+        self.i2c.enable();
+        self.start_command(Command::Force(measurement))
+            .map_err(|e| {
+                self.i2c.disable();
+                self.operation.set(Operation::Idle);
+                e
+            })
+    }
+
+    fn gain(&self, adc: Adc) -> &Cell<u8> {
+        match adc {
+            Adc::Proximity => &self.ps_gain,
+            Adc::Visible => &self.vis_gain,
+            Adc::Infrared => &self.ir_gain,
+        }
+    }
+
+    fn write_registers(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        self.write(State::Write, data)
+    }
+
+    fn write(&self, state: State, data: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[..data.len()].copy_from_slice(data);
+            match self.i2c.write(buffer, data.len()) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((error, buffer)) => {
+                    self.buffer.replace(buffer);
+                    self.state.set(State::Idle);
+                    Err(error.into())
+                }
+            }
+        })
+    }
+
+    /// Run `command`, starting by clearing the previous response.
+    fn start_command(&self, command: Command) -> Result<(), ErrorCode> {
+        self.polls.set(0);
+        match command {
+            // Clear the completion flags of the previous measurement.
+            Command::Force(_) => self.write(
+                State::ClearIrq(command),
+                &[REG_IRQ_STATUS, IRQ_ALS | IRQ_PS1],
+            ),
+            Command::SetParam(..) => {
+                self.write(State::ClearResponse(command), &[REG_COMMAND, CMD_NOP])
+            }
+        }
+    }
+
+    fn poll(&self, command: Command) -> Result<(), ErrorCode> {
+        // Measurements also read the outputs along with the response.
+        let len = match command {
+            Command::SetParam(..) => 1,
+            Command::Force(_) => BUFFER_SIZE,
+        };
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[0] = REG_RESPONSE;
+            match self.i2c.write_read(buffer, 1, len) {
+                Ok(()) => {
+                    self.state.set(State::Poll(command));
+                    Ok(())
+                }
+                Err((error, buffer)) => {
+                    self.buffer.replace(buffer);
+                    self.state.set(State::Idle);
+                    Err(error.into())
+                }
+            }
+        })
+    }
+
+    fn poll_later(&self, command: Command) -> Result<(), ErrorCode> {
+        let polls = self.polls.get() + 1;
+        if polls > MAX_POLLS {
+            return Err(ErrorCode::FAIL);
+        }
+        self.polls.set(polls);
+        self.state.set(State::PollWait(command));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(POLL_INTERVAL_US));
+        Ok(())
+    }
+
+    /// Handle a poll of the response register, returning the result of the
+    /// current operation if it is complete.
+    fn poll_done(&self, command: Command, buffer: &[u8]) -> Result<Option<usize>, ErrorCode> {
+        let operation = self.operation.get();
+        match parse_response(buffer[0]) {
+            Response::Invalid => Err(ErrorCode::INVAL),
+            Response::Overflow(adc) => {
+                let measurement = match operation {
+                    Operation::Measure(measurement) => measurement,
+                    _ => return Err(ErrorCode::FAIL),
+                };
+                match adc {
+                    Some(adc) if self.gain(adc).get() > 0 => {
+                        let gain = self.gain(adc).get() - 1;
+                        self.gain(adc).set(gain);
+                        self.start_command(Command::SetParam(adc.gain_param(), gain))?;
+                        Ok(None)
+                    }
+                    // The output is saturated even at the shortest
+                    // integration time, so report full scale.
+                    Some(_) if measurement != Measurement::Uv => Ok(Some(match measurement {
+                        Measurement::Proximity => 255,
+                        _ => visible_to_lux(u16::MAX, 0),
+                    })),
+                    _ => Err(ErrorCode::SIZE),
+                }
+            }
+            Response::Pending => self.poll_later(command).map(|()| None),
+            Response::Done => match (operation, command) {
+                (Operation::Configure(step), _) => self.configure_step(step + 1).map(|()| None),
+                // A gain was reduced after an overflow, measure again.
+                (Operation::Measure(measurement), Command::SetParam(..)) => self
+                    .start_command(Command::Force(measurement))
+                    .map(|()| None),
+                (Operation::Measure(measurement), Command::Force(_)) => {
+                    if buffer[1] & measurement.irq() == 0 {
+                        return self.poll_later(command).map(|()| None);
+                    }
+                    Ok(Some(match measurement {
+                        Measurement::Light => {
+                            visible_to_lux(output(buffer, REG_ALS_VIS_DATA0), self.vis_gain.get())
+                        }
+                        Measurement::Proximity => {
+                            proximity_to_u8(output(buffer, REG_PS1_DATA0), self.ps_gain.get())
+                                as usize
+                        }
+                        // UVINDEX holds 100 times the UV index.
+                        Measurement::Uv => output(buffer, REG_UVINDEX0) as usize,
+                    }))
+                }
+                (Operation::Idle, _) => Err(ErrorCode::FAIL),
+            },
+        }
+    }
+
+    fn finish(&self, result: Result<usize, ErrorCode>) {
+        let operation = self.operation.get();
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        self.operation.set(Operation::Idle);
+        match operation {
+            Operation::Idle | Operation::Configure(_) => {}
+            Operation::Measure(Measurement::Light) => {
+                self.ambient_light_client
+                    .map(|client| client.callback(result.unwrap_or(0)));
+            }
+            Operation::Measure(Measurement::Proximity) => {
+                self.proximity_client
+                    .map(|client| client.callback(result.unwrap_or(0) as u8));
+            }
+            Operation::Measure(Measurement::Uv) => {
+                let status = into_statuscode(result.map(|_| ()));
+                let uv = result.unwrap_or(0);
+                self.apps.each(|_, app, upcalls| {
+                    if app.pending {
+                        app.pending = false;
+                        upcalls.schedule_upcall(0, (status, uv, 0)).ok();
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Si1145<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        if let Err(i2c_err) = status {
+            self.buffer.replace(buffer);
+            self.finish(Err(i2c_err.into()));
+            return;
+        }
+
+        let result = match self.state.get() {
+            State::Idle | State::PollWait(_) => {
+                self.buffer.replace(buffer);
+                return;
+            }
+            State::Write => {
+                self.buffer.replace(buffer);
+                match self.operation.get() {
+                    Operation::Configure(step) => self.configure_step(step + 1),
+                    _ => Err(ErrorCode::FAIL),
+                }
+            }
+            State::ClearIrq(command) => {
+                self.buffer.replace(buffer);
+                self.write(State::ClearResponse(command), &[REG_COMMAND, CMD_NOP])
+            }
+            State::ClearResponse(command) => {
+                self.buffer.replace(buffer);
+                match command {
+                    Command::SetParam(param, value) => self.write(
+                        State::Issue(command),
+                        &[REG_PARAM_WR, value, CMD_PARAM_SET | param],
+                    ),
+                    Command::Force(measurement) => {
+                        self.write(State::Issue(command), &[REG_COMMAND, measurement.command()])
+                    }
+                }
+            }
+            State::Issue(command) => {
+                self.buffer.replace(buffer);
+                self.poll(command)
+            }
+            State::Poll(command) => {
+                let mut registers = [0; BUFFER_SIZE];
+                registers.copy_from_slice(&buffer[..BUFFER_SIZE]);
+                self.buffer.replace(buffer);
+                match self.poll_done(command, &registers) {
+                    Ok(Some(value)) => {
+                        self.finish(Ok(value));
+                        return;
+                    }
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> time::AlarmClient for Si1145<'a, A, I> {
+    fn alarm(&self) {
+        if let State::PollWait(command) = self.state.get() {
+            if let Err(e) = self.poll(command) {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AmbientLight<'a> for Si1145<'a, A, I> {
+    fn set_client(&self, client: &'a dyn AmbientLightClient) {
+        self.ambient_light_client.set(client);
+    }
+
+    fn read_light_intensity(&self) -> Result<(), ErrorCode> {
+        self.start_measurement(Measurement::Light)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> ProximityDriver<'a> for Si1145<'a, A, I> {
+    fn set_client(&self, client: &'a dyn ProximityClient) {
+        self.proximity_client.set(client);
+    }
+
+    fn read_proximity(&self) -> Result<(), ErrorCode> {
+        self.start_measurement(Measurement::Proximity)
+    }
+
+    fn read_proximity_on_interrupt(
+        &self,
+        _low_threshold: u8,
+        _high_threshold: u8,
+    ) -> Result<(), ErrorCode> {
+        // Thresholds need the autonomous measurement mode, which this driver
+        // does not use.
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> SyscallDriver for Si1145<'a, A, I> {
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Read the UV index.
+            1 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if app.pending {
+                            return Err(ErrorCode::BUSY);
+                        }
+                        // Join a UV reading that is already in progress.
+                        if self.operation.get() != Operation::Measure(Measurement::Uv) {
+                            self.start_measurement(Measurement::Uv)?;
+                        }
+                        app.pending = true;
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                result.into()
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        assert_eq!(parse_response(0x00), Response::Pending);
+        assert_eq!(parse_response(0x01), Response::Done);
+        assert_eq!(parse_response(0x0F), Response::Done);
+        assert_eq!(parse_response(0x80), Response::Invalid);
+        assert_eq!(
+            parse_response(0x88),
+            Response::Overflow(Some(Adc::Proximity))
+        );
+        assert_eq!(parse_response(0x8C), Response::Overflow(Some(Adc::Visible)));
+        assert_eq!(
+            parse_response(0x8D),
+            Response::Overflow(Some(Adc::Infrared))
+        );
+        // UV/auxiliary channel overflow.
+        assert_eq!(parse_response(0x8E), Response::Overflow(None));
+    }
+
+    #[test]
+    fn decode_outputs() {
+        let mut registers = [0u8; BUFFER_SIZE];
+        registers[2] = 0x34;
+        registers[3] = 0x12;
+        registers[12] = 0x2C;
+        registers[13] = 0x01;
+        assert_eq!(output(&registers, REG_ALS_VIS_DATA0), 0x1234);
+        assert_eq!(output(&registers, REG_UVINDEX0), 300);
+    }
+
+    #[test]
+    fn conversions_scale_with_gain() {
+        assert_eq!(visible_to_lux(ADC_OFFSET, 0), 0);
+        assert_eq!(visible_to_lux(ADC_OFFSET - 10, 0), 0);
+        assert_eq!(visible_to_lux(ADC_OFFSET + 1000, 0), 282);
+        // The same light gives 8 times the counts at gain 3.
+        assert_eq!(visible_to_lux(ADC_OFFSET + 8000, 3), 282);
+
+        assert_eq!(proximity_to_u8(ADC_OFFSET, 0), 0);
+        assert_eq!(proximity_to_u8(u16::MAX, 0), 254);
+        assert_eq!(proximity_to_u8(ADC_OFFSET + 0x1000, 0), 0x10);
+        assert_eq!(proximity_to_u8(ADC_OFFSET + 0x8000, 3), 0x10);
+    }
+}
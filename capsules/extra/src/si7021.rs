@@ -18,6 +18,20 @@
 //! > accuracy applications, while the Si7006 is targeted toward lower-accuracy
 //! > applications that traditionally have used discrete RH/T sensors.
 //!
+//! The TE HTU21D uses the same commands and conversions, and works with this
+//! driver too.
+//!
+//! Measurements use the no-hold master commands, so the bus is free during
+//! the conversion. The driver waits for the longest conversion time of
+//! either sensor at the configured resolution, then reads the result. A
+//! sensor still converting does not acknowledge the read, which is retried
+//! a few times. Every result is checked against the CRC the sensor sends
+//! with it, and a reading that fails is reported as an error: `FAIL` for
+//! temperature, and `usize::MAX` for humidity.
+//!
+//! The resolution and the on-chip heater, which can burn off condensation,
+//! are set in the user register before the next measurement.
+//!
 //! Usage
 //! -----
 //!
@@ -48,6 +62,9 @@ use kernel::hil::time::{self, ConvertTicks};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
+/// Size of the buffer the driver needs: a result and its CRC.
+pub const BUFFER_SIZE: usize = 3;
+
 #[allow(dead_code)]
 enum Registers {
     MeasRelativeHumidityHoldMode = 0xe5,
@@ -68,42 +85,114 @@ enum Registers {
     ReadFirmwareVersionB = 0xb8,
 }
 
-/// States of the I2C protocol with the LPS331AP.
-#[derive(Clone, Copy, PartialEq)]
-enum State {
-    Idle,
-    WaitTemp,
-    WaitRh,
-
-    /// States to read the internal ID
-    SelectElectronicId1,
-    ReadElectronicId1,
-    SelectElectronicId2,
-    ReadElectronicId2,
-
-    /// States to take the current measurement
-    TakeTempMeasurementInit,
-    TakeRhMeasurementInit,
-    ReadRhMeasurement,
-    ReadTempMeasurement,
-    GotTempMeasurement,
-    GotRhMeasurement,
+/// Bits of the user register setting the resolution.
+const USER_RESOLUTION_MASK: u8 = 0x81;
+/// Bit of the user register enabling the heater.
+const USER_HEATER: u8 = 0x04;
+
+/// Time between reads of a result that is not ready yet, and how many times
+/// to read it before giving up.
+const POLL_MS: u32 = 5;
+const MAX_POLLS: u8 = 4;
+
+/// Resolution of the humidity and temperature measurements, in bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resolution {
+    /// The default after reset.
+    Rh12Temp14 = 0x00,
+    Rh8Temp12 = 0x01,
+    Rh10Temp13 = 0x80,
+    Rh11Temp11 = 0x81,
 }
 
-#[derive(PartialEq, Eq, Copy, Clone)]
-enum OnDeck {
-    Nothing,
+impl Resolution {
+    /// The longest conversion time in ms of the Si7021 or the HTU21D. A
+    /// humidity measurement of the Si7021 includes a temperature
+    /// measurement.
+    fn conversion_ms(self, measurement: Measurement) -> u32 {
+        match (measurement, self) {
+            (Measurement::Temperature, Resolution::Rh12Temp14) => 50,
+            (Measurement::Temperature, Resolution::Rh10Temp13) => 25,
+            (Measurement::Temperature, Resolution::Rh8Temp12) => 13,
+            (Measurement::Temperature, Resolution::Rh11Temp11) => 7,
+            (Measurement::Humidity, Resolution::Rh12Temp14) => 23,
+            (Measurement::Humidity, Resolution::Rh11Temp11) => 10,
+            (Measurement::Humidity, Resolution::Rh10Temp13) => 11,
+            (Measurement::Humidity, Resolution::Rh8Temp12) => 7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Measurement {
     Temperature,
     Humidity,
 }
 
+/// States of the I2C protocol with the SI7021.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+
+    /// States to write the resolution and heater to the user register
+    ReadUserRegister,
+    WriteUserRegister,
+
+    /// States to take a measurement
+    StartMeasurement(Measurement),
+    WaitConversion(Measurement),
+    ReadMeasurement(Measurement),
+}
+
+/// The CRC-8 sent after each result, with polynomial x^8 + x^5 + x^4 + 1.
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 0x80 != 0 {
+                crc = crc << 1 ^ 0x31;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// The result of a measurement and its CRC, without the two status bits.
+fn check_result(data: &[u8]) -> Result<u32, ErrorCode> {
+    if crc8(&data[..2]) != data[2] {
+        return Err(ErrorCode::FAIL);
+    }
+    Ok(u16::from_be_bytes([data[0], data[1]]) as u32 & 0xfffc)
+}
+
+/// Temperature in hundredths of degrees centigrade.
+fn temperature_from_raw(raw: u32) -> i32 {
+    (raw * 17572 / 65536) as i32 - 4685
+}
+
+/// Humidity in hundredths of percent. The conversion gives a little below
+/// 0% and above 100%, which are clamped.
+fn humidity_from_raw(raw: u32) -> usize {
+    ((raw * 12500 / 65536) as i32 - 600).clamp(0, 10000) as usize
+}
+
 pub struct SI7021<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> {
     i2c: &'a I,
     alarm: &'a A,
     temp_callback: OptionalCell<&'a dyn kernel::hil::sensors::TemperatureClient>,
     humidity_callback: OptionalCell<&'a dyn kernel::hil::sensors::HumidityClient>,
     state: Cell<State>,
-    on_deck: Cell<OnDeck>,
+    read_temp: Cell<bool>,
+    read_hum: Cell<bool>,
+    polls: Cell<u8>,
+    resolution: Cell<Resolution>,
+    heater: Cell<bool>,
+    /// Whether the resolution or heater changed since the user register was
+    /// written.
+    config_pending: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
 }
 
@@ -111,135 +200,191 @@ impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> SI7021<'a, A, I> {
     pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> SI7021<'a, A, I> {
         // setup and return struct
         SI7021 {
-            i2c: i2c,
-            alarm: alarm,
+            i2c,
+            alarm,
             temp_callback: OptionalCell::empty(),
             humidity_callback: OptionalCell::empty(),
             state: Cell::new(State::Idle),
-            on_deck: Cell::new(OnDeck::Nothing),
+            read_temp: Cell::new(false),
+            read_hum: Cell::new(false),
+            polls: Cell::new(0),
+            resolution: Cell::new(Resolution::Rh12Temp14),
+            heater: Cell::new(false),
+            config_pending: Cell::new(false),
             buffer: TakeCell::new(buffer),
         }
     }
 
-    pub fn read_id(&self) {
-        self.buffer.take().map(|buffer| {
-            // turn on i2c to send commands
+    /// Set the resolution of the measurements that follow.
+    pub fn set_resolution(&self, resolution: Resolution) {
+        self.resolution.set(resolution);
+        self.configure();
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution.get()
+    }
+
+    /// Turn the heater on or off. The heater raises the temperature of the
+    /// sensor, so measurements taken while it is on are not those of the
+    /// air.
+    pub fn set_heater(&self, enabled: bool) {
+        self.heater.set(enabled);
+        self.configure();
+    }
+
+    pub fn heater(&self) -> bool {
+        self.heater.get()
+    }
+
+    fn configure(&self) {
+        self.config_pending.set(true);
+        if self.state.get() == State::Idle {
             self.i2c.enable();
+            self.next();
+        }
+    }
+
+    /// Start the next operation: the configuration, then any requested
+    /// measurement. Operations that fail to start are finished with the
+    /// error.
+    fn next(&self) {
+        while self.state.get() == State::Idle {
+            let (state, result) = if self.config_pending.take() {
+                let result = self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+                    buffer[0] = Registers::ReadRHTUserRegister1 as u8;
+                    self.i2c.write_read(buffer, 1, 1).map_err(|(e, buffer)| {
+                        self.buffer.replace(buffer);
+                        e.into()
+                    })
+                });
+                (State::ReadUserRegister, result)
+            } else if self.read_hum.get() {
+                let measurement = Measurement::Humidity;
+                let command = Registers::MeasRelativeHumidityNoHoldMode as u8;
+                (State::StartMeasurement(measurement), self.send(command))
+            } else if self.read_temp.get() {
+                let measurement = Measurement::Temperature;
+                let command = Registers::MeasTemperatureNoHoldMode as u8;
+                (State::StartMeasurement(measurement), self.send(command))
+            } else {
+                self.i2c.disable();
+                return;
+            };
 
-            buffer[0] = Registers::ReadElectronicIdByteOneA as u8;
-            buffer[1] = Registers::ReadElectronicIdByteOneB as u8;
-            // TODO verify errors
-            let _ = self.i2c.write(buffer, 2);
-            self.state.set(State::SelectElectronicId1);
-        });
+            match result {
+                Ok(()) => self.state.set(state),
+                Err(e) => self.finish(state, Err(e)),
+            }
+        }
     }
 
-    fn init_measurement(&self, buffer: &'static mut [u8]) {
-        let delay = self.alarm.ticks_from_ms(20);
-        self.alarm.set_alarm(self.alarm.now(), delay);
+    fn send(&self, command: u8) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = command;
+            self.i2c.write(buffer, 1).map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e.into()
+            })
+        })
+    }
 
-        // Now wait for timer to expire
-        self.buffer.replace(buffer);
-        self.i2c.disable();
+    fn wait(&self, measurement: Measurement, ms: u32) {
+        self.state.set(State::WaitConversion(measurement));
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
     }
 
-    fn set_idle(&self, buffer: &'static mut [u8]) {
-        self.buffer.replace(buffer);
-        self.i2c.disable();
+    /// Finish the operation of `state`, giving a measurement to its client.
+    /// The driver is idle afterwards, and does not start the next operation.
+    fn finish(&self, state: State, result: Result<u32, ErrorCode>) {
         self.state.set(State::Idle);
+        match state {
+            State::StartMeasurement(Measurement::Temperature)
+            | State::WaitConversion(Measurement::Temperature)
+            | State::ReadMeasurement(Measurement::Temperature) => {
+                self.read_temp.set(false);
+                self.temp_callback
+                    .map(|cb| cb.callback(result.map(temperature_from_raw)));
+            }
+            State::StartMeasurement(Measurement::Humidity)
+            | State::WaitConversion(Measurement::Humidity)
+            | State::ReadMeasurement(Measurement::Humidity) => {
+                self.read_hum.set(false);
+                let humidity = result.map_or(usize::MAX, humidity_from_raw);
+                self.humidity_callback.map(|cb| cb.callback(humidity));
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&self, measurement: Measurement) -> Result<(), ErrorCode> {
+        let requested = match measurement {
+            Measurement::Temperature => &self.read_temp,
+            Measurement::Humidity => &self.read_hum,
+        };
+        if requested.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        requested.set(true);
+        // This chip handles both humidity and temperature measurements, one
+        // at a time. A measurement requested while the chip is busy is taken
+        // after the current one.
+        if self.state.get() == State::Idle {
+            self.i2c.enable();
+            self.next();
+        }
+        Ok(())
     }
 }
 
 impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> i2c::I2CClient for SI7021<'a, A, I> {
-    fn command_complete(&self, buffer: &'static mut [u8], _status: Result<(), i2c::Error>) {
-        match self.state.get() {
-            State::SelectElectronicId1 => {
-                // TODO verify errors
-                let _ = self.i2c.read(buffer, 8);
-                self.state.set(State::ReadElectronicId1);
-            }
-            State::ReadElectronicId1 => {
-                buffer[6] = buffer[0];
-                buffer[7] = buffer[1];
-                buffer[8] = buffer[2];
-                buffer[9] = buffer[3];
-                buffer[10] = buffer[4];
-                buffer[11] = buffer[5];
-                buffer[12] = buffer[6];
-                buffer[13] = buffer[7];
-                buffer[0] = Registers::ReadElectronicIdByteTwoA as u8;
-                buffer[1] = Registers::ReadElectronicIdByteTwoB as u8;
-                // TODO verify errors
-                let _ = self.i2c.write(buffer, 2);
-                self.state.set(State::SelectElectronicId2);
-            }
-            State::SelectElectronicId2 => {
-                // TODO verify errors
-                let _ = self.i2c.read(buffer, 6);
-                self.state.set(State::ReadElectronicId2);
-            }
-            State::ReadElectronicId2 => {
-                self.set_idle(buffer);
-            }
-            State::TakeTempMeasurementInit => {
-                self.init_measurement(buffer);
-                self.state.set(State::WaitTemp);
-            }
-            State::TakeRhMeasurementInit => {
-                self.init_measurement(buffer);
-                self.state.set(State::WaitRh);
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let data = [buffer[0], buffer[1], buffer[2]];
+        self.buffer.replace(buffer);
+
+        match (state, status) {
+            (State::ReadUserRegister, Ok(())) => {
+                let user = data[0] & !(USER_RESOLUTION_MASK | USER_HEATER)
+                    | self.resolution.get() as u8
+                    | if self.heater.get() { USER_HEATER } else { 0 };
+                let result = self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+                    buffer[0] = Registers::WriteRHTUserRegister1 as u8;
+                    buffer[1] = user;
+                    self.i2c.write(buffer, 2).map_err(|(e, buffer)| {
+                        self.buffer.replace(buffer);
+                        e.into()
+                    })
+                });
+                match result {
+                    Ok(()) => self.state.set(State::WriteUserRegister),
+                    Err(e) => self.finish(state, Err(e)),
+                }
             }
-            State::ReadRhMeasurement => {
-                // TODO verify errors
-                let _ = self.i2c.read(buffer, 2);
-                self.state.set(State::GotRhMeasurement);
+            (State::StartMeasurement(measurement), Ok(())) => {
+                self.polls.set(0);
+                self.wait(
+                    measurement,
+                    self.resolution.get().conversion_ms(measurement),
+                );
             }
-            State::ReadTempMeasurement => {
-                // TODO verify errors
-                let _ = self.i2c.read(buffer, 2);
-                self.state.set(State::GotTempMeasurement);
+            (State::ReadMeasurement(_), Ok(())) => {
+                self.finish(state, check_result(&data));
             }
-            State::GotTempMeasurement => {
-                // Temperature in hundredths of degrees centigrade
-                let temp_raw = (((buffer[0] as u32) << 8) | (buffer[1] as u32)) as u32;
-                let temp = ((temp_raw * 17572) / 65536) as i32 - 4685;
-
-                self.temp_callback.map(|cb| cb.callback(Ok(temp)));
-
-                match self.on_deck.get() {
-                    OnDeck::Humidity => {
-                        self.on_deck.set(OnDeck::Nothing);
-                        buffer[0] = Registers::MeasRelativeHumidityNoHoldMode as u8;
-                        // TODO verify errors
-                        let _ = self.i2c.write(buffer, 1);
-                        self.state.set(State::TakeRhMeasurementInit);
-                    }
-                    _ => {
-                        self.set_idle(buffer);
-                    }
-                }
+            // The sensor does not acknowledge reads until the conversion is
+            // done.
+            (State::ReadMeasurement(measurement), Err(i2c::Error::AddressNak))
+                if self.polls.get() < MAX_POLLS =>
+            {
+                self.polls.set(self.polls.get() + 1);
+                self.wait(measurement, POLL_MS);
             }
-            State::GotRhMeasurement => {
-                // Humidity in hundredths of percent
-                let humidity_raw = (((buffer[0] as u32) << 8) | (buffer[1] as u32)) as u32;
-                let humidity = (((humidity_raw * 125 * 100) / 65536) - 600) as u16;
-
-                self.humidity_callback
-                    .map(|cb| cb.callback(humidity as usize));
-                match self.on_deck.get() {
-                    OnDeck::Temperature => {
-                        self.on_deck.set(OnDeck::Nothing);
-                        buffer[0] = Registers::MeasTemperatureNoHoldMode as u8;
-                        // TODO verify errors
-                        let _ = self.i2c.write(buffer, 1);
-                        self.state.set(State::TakeTempMeasurementInit);
-                    }
-                    _ => {
-                        self.set_idle(buffer);
-                    }
-                }
-            }
-            _ => {}
+            (_, status) => self.finish(state, status.map(|()| 0).map_err(|e| e.into())),
+        }
+
+        if self.state.get() == State::Idle {
+            self.next();
         }
     }
 }
@@ -248,30 +393,7 @@ impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> kernel::hil::sensors::Temperatur
     for SI7021<'a, A, I>
 {
     fn read_temperature(&self) -> Result<(), ErrorCode> {
-        // This chip handles both humidity and temperature measurements. We can
-        // only start a new measurement if the chip is idle. If it isn't then we
-        // can put this request "on deck" and it will happen after the
-        // temperature measurement has finished.
-        if self.state.get() == State::Idle {
-            self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
-                // turn on i2c to send commands
-                self.i2c.enable();
-
-                buffer[0] = Registers::MeasTemperatureNoHoldMode as u8;
-                // TODO verify errors
-                let _ = self.i2c.write(buffer, 1);
-                self.state.set(State::TakeTempMeasurementInit);
-                Ok(())
-            })
-        } else {
-            // Queue this request if nothing else queued.
-            if self.on_deck.get() == OnDeck::Nothing {
-                self.on_deck.set(OnDeck::Temperature);
-                Ok(())
-            } else {
-                Err(ErrorCode::BUSY)
-            }
-        }
+        self.read(Measurement::Temperature)
     }
 
     fn set_client(&self, client: &'a dyn kernel::hil::sensors::TemperatureClient) {
@@ -283,31 +405,7 @@ impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> kernel::hil::sensors::HumidityDr
     for SI7021<'a, A, I>
 {
     fn read_humidity(&self) -> Result<(), ErrorCode> {
-        // This chip handles both humidity and temperature measurements. We can
-        // only start a new measurement if the chip is idle. If it isn't then we
-        // can put this request "on deck" and it will happen after the
-        // temperature measurement has finished.
-        if self.state.get() == State::Idle {
-            self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
-                // turn on i2c to send commands
-                self.i2c.enable();
-
-                buffer[0] = Registers::MeasRelativeHumidityNoHoldMode as u8;
-                // TODO verify errors
-                let _ = self.i2c.write(buffer, 1);
-                self.state.set(State::TakeRhMeasurementInit);
-                Ok(())
-            })
-        } else {
-            // Not idle, so queue this request if nothing else is queued. If we have already
-            // queued a request return an error.
-            if self.on_deck.get() == OnDeck::Nothing {
-                self.on_deck.set(OnDeck::Humidity);
-                Ok(())
-            } else {
-                Err(ErrorCode::BUSY)
-            }
-        }
+        self.read(Measurement::Humidity)
     }
 
     fn set_client(&self, client: &'a dyn kernel::hil::sensors::HumidityClient) {
@@ -317,17 +415,140 @@ impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> kernel::hil::sensors::HumidityDr
 
 impl<'a, A: time::Alarm<'a>, I: i2c::I2CDevice> time::AlarmClient for SI7021<'a, A, I> {
     fn alarm(&self) {
-        self.buffer.take().map(|buffer| {
-            // turn on i2c to send commands
-            self.i2c.enable();
-
-            // TODO verify errors
-            let _ = self.i2c.read(buffer, 2);
-            match self.state.get() {
-                State::WaitRh => self.state.set(State::ReadRhMeasurement),
-                State::WaitTemp => self.state.set(State::ReadTempMeasurement),
-                _ => (),
+        if let State::WaitConversion(measurement) = self.state.get() {
+            let result = self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+                self.i2c.read(buffer, 3).map_err(|(e, buffer)| {
+                    self.buffer.replace(buffer);
+                    e.into()
+                })
+            });
+            match result {
+                Ok(()) => self.state.set(State::ReadMeasurement(measurement)),
+                Err(e) => {
+                    self.finish(self.state.get(), Err(e));
+                    self.next();
+                }
             }
-        });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::sensors::{
+        HumidityClient, HumidityDriver, TemperatureClient, TemperatureDriver,
+    };
+    use kernel::hil::time::Alarm;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Client {
+        temperature: Cell<Option<Result<i32, ErrorCode>>>,
+        humidity: Cell<Option<usize>>,
+    }
+
+    impl TemperatureClient for Client {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.temperature.set(Some(value));
+        }
+    }
+
+    impl HumidityClient for Client {
+        fn callback(&self, value: usize) {
+            self.humidity.set(Some(value));
+        }
+    }
+
+    type Device = SI7021<'static, MockAlarm<'static>, MockI2c>;
+
+    /// Complete the transfer in progress, reading `bytes`, and return what
+    /// the transfer wrote.
+    fn complete(
+        i2c: &MockI2c,
+        si7021: &Device,
+        bytes: &[u8],
+        status: Result<(), i2c::Error>,
+    ) -> Vec<u8> {
+        match status {
+            Ok(()) => i2c.complete(si7021, bytes),
+            Err(error) => i2c.fail(si7021, error),
+        }
+    }
+
+    #[test]
+    fn decodes_results_and_checks_crc() {
+        // The examples of the HTU21D datasheet.
+        assert_eq!(crc8(&[0xDC]), 0x79);
+        assert_eq!(check_result(&[0x68, 0x3A, 0x7C]), Ok(0x6838));
+        assert_eq!(check_result(&[0x4E, 0x85, 0x6B]), Ok(0x4E84));
+        assert_eq!(temperature_from_raw(0x6838), 2468);
+        assert_eq!(humidity_from_raw(0x4E84), 3233);
+
+        // A corrupted result is rejected.
+        assert_eq!(check_result(&[0x68, 0x3B, 0x7C]), Err(ErrorCode::FAIL));
+        assert_eq!(check_result(&[0x68, 0x3A, 0x7D]), Err(ErrorCode::FAIL));
+
+        // The ends of the range.
+        assert_eq!(temperature_from_raw(0), -4685);
+        assert_eq!(humidity_from_raw(0), 0);
+        assert_eq!(humidity_from_raw(0xFFFC), 10000);
+    }
+
+    #[test]
+    fn measures_with_heater_and_resolution() {
+        let i2c = Box::leak(Box::new(MockI2c::default()));
+        let alarm = Box::leak(Box::new(MockAlarm::new()));
+        let si7021: &Device = Box::leak(Box::new(SI7021::new(
+            i2c,
+            alarm,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        alarm.set_alarm_client(si7021);
+        let client = Box::leak(Box::new(Client::default()));
+        TemperatureDriver::set_client(si7021, client);
+        HumidityDriver::set_client(si7021, client);
+
+        // The heater is turned on, keeping the reserved bits of the user
+        // register.
+        si7021.set_heater(true);
+        assert_eq!(complete(i2c, si7021, &[0x3A], Ok(())), [0xE7]);
+        assert_eq!(complete(i2c, si7021, &[], Ok(())), [0xE6, 0x3E]);
+        assert!(!i2c.busy());
+
+        // A temperature and a humidity requested together are measured one
+        // after the other. The humidity is not ready at first.
+        si7021.set_resolution(Resolution::Rh11Temp11);
+        assert_eq!(si7021.read_temperature(), Ok(()));
+        assert_eq!(si7021.read_temperature(), Err(ErrorCode::BUSY));
+        assert_eq!(si7021.read_humidity(), Ok(()));
+        assert_eq!(complete(i2c, si7021, &[0x3E], Ok(())), [0xE7]);
+        assert_eq!(complete(i2c, si7021, &[], Ok(())), [0xE6, 0xBF]);
+        assert_eq!(complete(i2c, si7021, &[], Ok(())), [0xF5]);
+        assert_eq!(alarm.dt(), Some(10));
+        alarm.fire();
+        complete(i2c, si7021, &[], Err(i2c::Error::AddressNak));
+        assert_eq!(alarm.dt(), Some(POLL_MS));
+        alarm.fire();
+        complete(i2c, si7021, &[0x4E, 0x85, 0x6B], Ok(()));
+        assert_eq!(client.humidity.get(), Some(3233));
+
+        // The temperature fails its CRC.
+        assert_eq!(complete(i2c, si7021, &[], Ok(())), [0xF3]);
+        assert_eq!(alarm.dt(), Some(7));
+        alarm.fire();
+        complete(i2c, si7021, &[0x68, 0x3A, 0x00], Ok(()));
+        assert_eq!(client.temperature.get(), Some(Err(ErrorCode::FAIL)));
+        assert!(!i2c.busy());
+
+        assert_eq!(si7021.read_temperature(), Ok(()));
+        complete(i2c, si7021, &[], Ok(()));
+        alarm.fire();
+        complete(i2c, si7021, &[0x68, 0x3A, 0x7C], Ok(()));
+        assert_eq!(client.temperature.get(), Some(Ok(2468)));
     }
 }
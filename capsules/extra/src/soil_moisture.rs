@@ -0,0 +1,207 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Drivers for analog and frequency output soil moisture sensors.
+//!
+//! Resistive probes and most capacitive probes output a voltage that changes
+//! with the moisture of the soil around them. [CapacitiveSoilMoisture] reads
+//! that voltage with an ADC channel. Some capacitive probes instead output a
+//! square wave whose frequency changes with moisture. [FrequencySoilMoisture]
+//! counts the edges of that signal on a GPIO pin over a fixed window.
+//!
+//! Both drivers are calibrated with the sensor output measured in dry and in
+//! saturated soil, and report moisture through the [SoilMoisture] HIL as a
+//! linear interpolation between those two points. Readings beyond either
+//! point are clamped to 0% or 100%. The dry point may be above or below the
+//! wet point: capacitive probes usually read higher when dry.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let soil_moisture = components::soil_moisture::CapacitiveSoilMoistureComponent::new(
+//!     adc_mux,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput2),
+//!     2800, // dry_mv
+//!     1200, // wet_mv
+//! )
+//! .finalize(components::capacitive_soil_moisture_component_static!(
+//!     nrf52840::adc::Adc
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::adc;
+use kernel::hil::gpio;
+use kernel::hil::sensors::{SoilMoisture, SoilMoistureClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Map `value` onto 0-100% between the `dry` and `wet` calibration points.
+fn moisture_percent(value: u32, dry: u32, wet: u32) -> usize {
+    if dry == wet {
+        return 0;
+    }
+    let value = value as i64;
+    let dry = dry as i64;
+    let wet = wet as i64;
+    let percent = (value - dry) * 100 / (wet - dry);
+    percent.clamp(0, 100) as usize
+}
+
+/// Soil moisture sensor with an analog output read by an ADC.
+pub struct CapacitiveSoilMoisture<'a, A: adc::AdcChannel<'a>> {
+    adc: &'a A,
+    dry_mv: u32,
+    wet_mv: u32,
+    busy: Cell<bool>,
+    client: OptionalCell<&'a dyn SoilMoistureClient>,
+}
+
+impl<'a, A: adc::AdcChannel<'a>> CapacitiveSoilMoisture<'a, A> {
+    /// `dry_mv` and `wet_mv` are the sensor output in millivolts in dry and
+    /// in saturated soil.
+    pub fn new(adc: &'a A, dry_mv: u32, wet_mv: u32) -> CapacitiveSoilMoisture<'a, A> {
+        CapacitiveSoilMoisture {
+            adc,
+            dry_mv,
+            wet_mv,
+            busy: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>> SoilMoisture<'a> for CapacitiveSoilMoisture<'a, A> {
+    fn set_client(&self, client: &'a dyn SoilMoistureClient) {
+        self.client.set(client);
+    }
+
+    fn get_moisture_percent(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.adc.sample()?;
+        self.busy.set(true);
+        Ok(())
+    }
+}
+
+impl<'a, A: adc::AdcChannel<'a>> adc::Client for CapacitiveSoilMoisture<'a, A> {
+    fn sample_ready(&self, sample: u16) {
+        self.busy.set(false);
+        // Samples are left-justified in the u16, so full scale is 65536.
+        let result = self
+            .adc
+            .get_voltage_reference_mv()
+            .map(|reference_mv| {
+                let mv = (sample as u32 * reference_mv as u32) >> 16;
+                moisture_percent(mv, self.dry_mv, self.wet_mv)
+            })
+            .ok_or(ErrorCode::FAIL);
+        self.client.map(|client| client.callback(result));
+    }
+}
+
+/// Soil moisture sensor with a frequency output connected to a GPIO pin.
+pub struct FrequencySoilMoisture<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> {
+    pin: &'a G,
+    alarm: &'a A,
+    window_ms: u32,
+    dry_hz: u32,
+    wet_hz: u32,
+    pulses: Cell<u32>,
+    busy: Cell<bool>,
+    client: OptionalCell<&'a dyn SoilMoistureClient>,
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> FrequencySoilMoisture<'a, G, A> {
+    /// `dry_hz` and `wet_hz` are the sensor output frequency in dry and in
+    /// saturated soil. Each reading counts rising edges for `window_ms`
+    /// milliseconds.
+    pub fn new(
+        pin: &'a G,
+        alarm: &'a A,
+        window_ms: u32,
+        dry_hz: u32,
+        wet_hz: u32,
+    ) -> FrequencySoilMoisture<'a, G, A> {
+        FrequencySoilMoisture {
+            pin,
+            alarm,
+            window_ms,
+            dry_hz,
+            wet_hz,
+            pulses: Cell::new(0),
+            busy: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> SoilMoisture<'a>
+    for FrequencySoilMoisture<'a, G, A>
+{
+    fn set_client(&self, client: &'a dyn SoilMoistureClient) {
+        self.client.set(client);
+    }
+
+    fn get_moisture_percent(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.window_ms == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.busy.set(true);
+        self.pulses.set(0);
+        self.pin.make_input();
+        self.pin.enable_interrupts(gpio::InterruptEdge::RisingEdge);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.window_ms));
+        Ok(())
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> gpio::Client for FrequencySoilMoisture<'a, G, A> {
+    fn fired(&self) {
+        self.pulses.set(self.pulses.get().saturating_add(1));
+    }
+}
+
+impl<'a, G: gpio::InterruptPin<'a>, A: Alarm<'a>> time::AlarmClient
+    for FrequencySoilMoisture<'a, G, A>
+{
+    fn alarm(&self) {
+        self.pin.disable_interrupts();
+        self.busy.set(false);
+        let hz = (self.pulses.get() as u64 * 1000 / self.window_ms as u64) as u32;
+        let percent = moisture_percent(hz, self.dry_hz, self.wet_hz);
+        self.client.map(|client| client.callback(Ok(percent)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_above_wet() {
+        assert_eq!(moisture_percent(2800, 2800, 1200), 0);
+        assert_eq!(moisture_percent(2000, 2800, 1200), 50);
+        assert_eq!(moisture_percent(1200, 2800, 1200), 100);
+        assert_eq!(moisture_percent(3300, 2800, 1200), 0);
+        assert_eq!(moisture_percent(0, 2800, 1200), 100);
+    }
+
+    #[test]
+    fn dry_below_wet() {
+        assert_eq!(moisture_percent(100, 100, 600), 0);
+        assert_eq!(moisture_percent(225, 100, 600), 25);
+        assert_eq!(moisture_percent(50, 100, 600), 0);
+        assert_eq!(moisture_percent(900, 100, 600), 100);
+        assert_eq!(moisture_percent(500, 500, 500), 0);
+    }
+}
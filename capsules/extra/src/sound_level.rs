@@ -0,0 +1,382 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Sound pressure level from a microphone connected to an ADC.
+//!
+//! A reading samples a window of `WINDOW_SAMPLES` samples at a fixed rate,
+//! removes the DC offset of the microphone and computes the RMS amplitude of
+//! what is left. The level is reported in hundredths of a dB SPL relative to
+//! a calibration value given at construction time: the level of a signal
+//! whose RMS amplitude is 32768 (half of the left-justified ADC range).
+//!
+//! Samples are taken with the ADC's high-speed mode, which collects them
+//! into buffers in the background. ADCs that return `NOSUPPORT` for
+//! high-speed sampling are sampled one at a time instead, paced by an alarm;
+//! this is only accurate at low sample rates.
+//!
+//! Samples are processed as they arrive rather than stored, so peak
+//! detection reports a loud sample while the window is still being
+//! collected. Peaks are measured from the average of the previous window,
+//! and reported at most once per window.
+//!
+//! This capsule uses the ADC directly, and so needs an ADC that is not
+//! shared with other capsules.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let microphone_channel = static_init!(
+//!     nrf52840::adc::AdcChannelSetup,
+//!     nrf52840::adc::AdcChannelSetup::new(nrf52840::adc::AdcChannel::AnalogInput4)
+//! );
+//! let sound_level = components::sound_level::SoundLevelComponent::new(
+//!     &base_peripherals.adc,
+//!     microphone_channel,
+//!     mux_alarm,
+//!     8000,
+//!     12000,
+//! )
+//! .finalize(components::sound_level_component_static!(
+//!     nrf52840::adc::Adc<'static>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::adc;
+use kernel::hil::sensors::{self, SoundLevelClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::math;
+use kernel::ErrorCode;
+
+/// Number of samples in one reading.
+pub const WINDOW_SAMPLES: usize = 1024;
+/// Size of each of the two high-speed sampling buffers.
+pub const BUFFER_SAMPLES: usize = 128;
+
+/// Square of the RMS amplitude the calibration value refers to.
+const REFERENCE_POWER: f32 = 32768.0 * 32768.0;
+
+/// Running sums over the samples of a window.
+#[derive(Clone, Copy, Default)]
+struct Window {
+    count: usize,
+    sum: u64,
+    sum_of_squares: u64,
+    peak_reported: bool,
+}
+
+impl Window {
+    /// Adds `sample` to the window. Returns the deviation of the sample from
+    /// `average` if it is the first in the window to exceed `threshold`.
+    fn add(&mut self, sample: u16, average: u16, threshold: Option<u16>) -> Option<u16> {
+        self.count += 1;
+        self.sum += sample as u64;
+        self.sum_of_squares += sample as u64 * sample as u64;
+
+        let deviation = if sample > average {
+            sample - average
+        } else {
+            average - sample
+        };
+        match threshold {
+            Some(threshold) if !self.peak_reported && deviation > threshold => {
+                self.peak_reported = true;
+                Some(deviation)
+            }
+            _ => None,
+        }
+    }
+
+    fn average(&self) -> u16 {
+        (self.sum / self.count as u64) as u16
+    }
+
+    /// Mean square of the samples with their average removed.
+    fn power(&self) -> u64 {
+        let n = self.count as u64;
+        let mean_of_squares = self.sum_of_squares / n;
+        let mean = self.sum / n;
+        mean_of_squares.saturating_sub(mean * mean)
+    }
+}
+
+/// Sound level of a signal with mean square `power`, in hundredths of a dB.
+fn level_cdb(power: u64, calibration_cdb: u16) -> u16 {
+    if power == 0 {
+        return 0;
+    }
+    let relative_cdb = 1000.0 * math::log10(power as f32 / REFERENCE_POWER);
+    let level = calibration_cdb as f32 + relative_cdb;
+    if level <= 0.0 {
+        0
+    } else if level >= u16::MAX as f32 {
+        u16::MAX
+    } else {
+        level as u16
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    HighSpeed,
+    SingleShot,
+}
+
+pub struct SoundLevel<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> {
+    adc: &'a A,
+    channel: &'a A::Channel,
+    alarm: &'a T,
+    sample_rate_hz: u32,
+    calibration_cdb: u16,
+    client: OptionalCell<&'a dyn SoundLevelClient>,
+    state: Cell<State>,
+    window: Cell<Window>,
+    /// Number of single samples requested in the current window.
+    requested: Cell<usize>,
+    /// Average of the last window, which peaks are measured from.
+    average: Cell<u16>,
+    peak_threshold: OptionalCell<u16>,
+    /// Cleared once the ADC reports that it has no high-speed mode.
+    high_speed: Cell<bool>,
+    buffer1: TakeCell<'static, [u16]>,
+    buffer2: TakeCell<'static, [u16]>,
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> SoundLevel<'a, A, T> {
+    pub fn new(
+        adc: &'a A,
+        channel: &'a A::Channel,
+        alarm: &'a T,
+        sample_rate_hz: u32,
+        calibration_cdb: u16,
+        buffer1: &'static mut [u16],
+        buffer2: &'static mut [u16],
+    ) -> SoundLevel<'a, A, T> {
+        SoundLevel {
+            adc,
+            channel,
+            alarm,
+            sample_rate_hz,
+            calibration_cdb,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            window: Cell::new(Window::default()),
+            requested: Cell::new(0),
+            average: Cell::new(0x8000),
+            peak_threshold: OptionalCell::empty(),
+            high_speed: Cell::new(true),
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+        }
+    }
+
+    fn store_buffer(&self, buffer: &'static mut [u16]) {
+        if self.buffer1.is_none() {
+            self.buffer1.replace(buffer);
+        } else {
+            self.buffer2.replace(buffer);
+        }
+    }
+
+    fn start_high_speed(&self) -> Result<(), ErrorCode> {
+        let (buffer1, buffer2) = match (self.buffer1.take(), self.buffer2.take()) {
+            (Some(buffer1), Some(buffer2)) => (buffer1, buffer2),
+            (buffer1, buffer2) => {
+                buffer1.map(|buffer| self.buffer1.replace(buffer));
+                buffer2.map(|buffer| self.buffer2.replace(buffer));
+                return Err(ErrorCode::NOMEM);
+            }
+        };
+        let len1 = buffer1.len().min(BUFFER_SAMPLES);
+        let len2 = buffer2.len().min(BUFFER_SAMPLES);
+        match self.adc.sample_highspeed(
+            self.channel,
+            self.sample_rate_hz,
+            buffer1,
+            len1,
+            buffer2,
+            len2,
+        ) {
+            Ok(()) => {
+                self.state.set(State::HighSpeed);
+                Ok(())
+            }
+            Err((e, buffer1, buffer2)) => {
+                self.buffer1.replace(buffer1);
+                self.buffer2.replace(buffer2);
+                Err(e)
+            }
+        }
+    }
+
+    fn stop_high_speed(&self) {
+        let _ = self.adc.stop_sampling();
+        if let Ok((buffer1, buffer2)) = self.adc.retrieve_buffers() {
+            buffer1.map(|buffer| self.store_buffer(buffer));
+            buffer2.map(|buffer| self.store_buffer(buffer));
+        }
+    }
+
+    fn start_single_shot(&self) {
+        self.state.set(State::SingleShot);
+        self.requested.set(0);
+        self.alarm.set_alarm(self.alarm.now(), self.sample_period());
+    }
+
+    fn sample_period(&self) -> T::Ticks {
+        self.alarm
+            .ticks_from_us(1_000_000 / self.sample_rate_hz.max(1))
+    }
+
+    /// Adds samples to the window, reporting any peak. Returns whether the
+    /// window is complete.
+    fn add_samples(&self, samples: &[u16]) -> bool {
+        let mut window = self.window.get();
+        let mut peak = None;
+        for &sample in samples.iter().take(WINDOW_SAMPLES - window.count) {
+            if let Some(amplitude) =
+                window.add(sample, self.average.get(), self.peak_threshold.extract())
+            {
+                peak = Some(amplitude);
+            }
+        }
+        self.window.set(window);
+        peak.map(|amplitude| self.client.map(|client| client.peak(amplitude)));
+        window.count == WINDOW_SAMPLES
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        let level = result.map(|()| {
+            let window = self.window.get();
+            self.average.set(window.average());
+            level_cdb(window.power(), self.calibration_cdb)
+        });
+        self.client.map(|client| client.sound_level(level));
+    }
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> sensors::SoundLevel<'a> for SoundLevel<'a, A, T> {
+    fn set_client(&self, client: &'a dyn SoundLevelClient) {
+        self.client.set(client);
+    }
+
+    fn read_db(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.window.set(Window::default());
+        if self.high_speed.get() {
+            match self.start_high_speed() {
+                Err(ErrorCode::NOSUPPORT) => self.high_speed.set(false),
+                result => return result,
+            }
+        }
+        self.start_single_shot();
+        Ok(())
+    }
+
+    fn set_peak_threshold(&self, threshold: Option<u16>) {
+        self.peak_threshold.insert(threshold);
+    }
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> adc::HighSpeedClient for SoundLevel<'a, A, T> {
+    fn samples_ready(&self, buffer: &'static mut [u16], length: usize) {
+        if self.state.get() != State::HighSpeed {
+            self.store_buffer(buffer);
+            return;
+        }
+        if self.add_samples(&buffer[..length]) {
+            self.store_buffer(buffer);
+            self.stop_high_speed();
+            self.finish(Ok(()));
+        } else if let Err((e, buffer)) = self.adc.provide_buffer(buffer, BUFFER_SAMPLES) {
+            self.store_buffer(buffer);
+            self.stop_high_speed();
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> adc::Client for SoundLevel<'a, A, T> {
+    fn sample_ready(&self, sample: u16) {
+        if self.state.get() == State::SingleShot && self.add_samples(&[sample]) {
+            self.finish(Ok(()));
+        }
+    }
+}
+
+impl<'a, A: adc::AdcHighSpeed<'a>, T: Alarm<'a>> time::AlarmClient for SoundLevel<'a, A, T> {
+    fn alarm(&self) {
+        if self.state.get() != State::SingleShot {
+            return;
+        }
+        if let Err(e) = self.adc.sample(self.channel) {
+            self.finish(Err(e));
+            return;
+        }
+        // Schedule the next sample from this one's deadline rather than
+        // from now, so that ADC latency does not lower the sample rate.
+        self.requested.set(self.requested.get() + 1);
+        if self.requested.get() < WINDOW_SAMPLES {
+            self.alarm
+                .set_alarm(self.alarm.get_alarm(), self.sample_period());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square wave of `amplitude` around mid scale.
+    fn square_wave(amplitude: u16) -> Window {
+        let mut window = Window::default();
+        for i in 0..WINDOW_SAMPLES {
+            let sample = if i % 2 == 0 {
+                0x8000 + amplitude
+            } else {
+                0x8000 - amplitude
+            };
+            window.add(sample, 0x8000, None);
+        }
+        window
+    }
+
+    #[test]
+    fn level_follows_amplitude() {
+        let calibration = 12000;
+
+        let full = square_wave(0x7FFF);
+        assert_eq!(full.average(), 0x8000);
+        let level = level_cdb(full.power(), calibration);
+        assert!((11990..=12000).contains(&level), "{}", level);
+
+        // A tenth of the amplitude is 20 dB quieter.
+        let quiet = level_cdb(square_wave(0x0CCC).power(), calibration);
+        assert!((9980..=10020).contains(&quiet), "{}", quiet);
+
+        // Silence, and a level below 0 dB SPL.
+        assert_eq!(level_cdb(square_wave(0).power(), calibration), 0);
+        assert_eq!(level_cdb(square_wave(1).power(), 5000), 0);
+    }
+
+    #[test]
+    fn peak_reported_once_per_window() {
+        let mut window = Window::default();
+        assert_eq!(window.add(0x8100, 0x8000, Some(0x1000)), None);
+        assert_eq!(window.add(0x6000, 0x8000, Some(0x1000)), Some(0x2000));
+        assert_eq!(window.add(0xA000, 0x8000, Some(0x1000)), None);
+        assert_eq!(window.add(0xA000, 0x8000, None), None);
+
+        let mut window = Window::default();
+        assert_eq!(window.add(0xA000, 0x8000, None), None);
+        assert_eq!(window.add(0xA000, 0x8000, Some(0x1000)), Some(0x2000));
+    }
+}
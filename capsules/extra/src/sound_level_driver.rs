@@ -0,0 +1,185 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with sound pressure level readings and peak events.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a reading completes, with the status code and the
+//!   level in hundredths of a dB SPL.
+//! * `1`: called when a sample exceeds the peak threshold, with its
+//!   amplitude.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: read the sound level, averaged over the number of windows in
+//!   argument 1 (at least 1, at most `MAX_AVERAGED_WINDOWS`)
+//! * `2`: set the peak threshold to argument 1, or disable peak detection
+//!   if it is 0
+//!
+//! Only one reading can be in progress at a time. The peak threshold is
+//! shared by all processes, and peak events go to every process that has
+//! subscribed to them.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::SoundLevel` trait.
+//!
+//! ```rust
+//! let sound_level_driver = components::sound_level::SoundLevelDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::sound_level_driver::DRIVER_NUM,
+//!     sound_level,
+//! )
+//! .finalize(components::sound_level_driver_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SoundLevel as usize;
+
+/// Most windows a reading can be averaged over.
+pub const MAX_AVERAGED_WINDOWS: usize = 64;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const SOUND_LEVEL: usize = 0;
+    pub const PEAK: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct SoundLevelDriver<'a> {
+    sensor: &'a dyn hil::sensors::SoundLevel<'a>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process whose reading is in progress.
+    processid: OptionalCell<ProcessId>,
+    /// Windows still to be read for the current reading.
+    remaining: Cell<usize>,
+    windows: Cell<usize>,
+    total_cdb: Cell<u32>,
+}
+
+impl<'a> SoundLevelDriver<'a> {
+    pub fn new(
+        sensor: &'a dyn hil::sensors::SoundLevel<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> SoundLevelDriver<'a> {
+        SoundLevelDriver {
+            sensor,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            remaining: Cell::new(0),
+            windows: Cell::new(0),
+            total_cdb: Cell::new(0),
+        }
+    }
+
+    fn start_reading(&self, windows: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.processid.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let windows = windows.clamp(1, MAX_AVERAGED_WINDOWS);
+        self.sensor.read_db()?;
+        self.processid.set(processid);
+        self.windows.set(windows);
+        self.remaining.set(windows);
+        self.total_cdb.set(0);
+        Ok(())
+    }
+
+    fn finish(&self, result: Result<u16, ErrorCode>) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let (status, level) = match result {
+                    Ok(level) => (0, level as usize),
+                    Err(e) => (into_statuscode(Err(e)), 0),
+                };
+                kernel_data
+                    .schedule_upcall(upcall::SOUND_LEVEL, (status, level, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl SyscallDriver for SoundLevelDriver<'_> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Read the sound level, averaged over `arg1` windows.
+    /// - `2`: Set the peak threshold to `arg1`, or disable it with 0.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start_reading(arg1, processid).into(),
+            2 => {
+                let threshold = match arg1 {
+                    0 => None,
+                    threshold => Some(u16::try_from(threshold).unwrap_or(u16::MAX)),
+                };
+                self.sensor.set_peak_threshold(threshold);
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl hil::sensors::SoundLevelClient for SoundLevelDriver<'_> {
+    fn sound_level(&self, level: Result<u16, ErrorCode>) {
+        if self.processid.is_none() {
+            return;
+        }
+        let level = match level {
+            Ok(level) => level,
+            Err(e) => {
+                self.finish(Err(e));
+                return;
+            }
+        };
+        self.total_cdb.set(self.total_cdb.get() + level as u32);
+        self.remaining.set(self.remaining.get() - 1);
+        if self.remaining.get() == 0 {
+            let average = self.total_cdb.get() / self.windows.get() as u32;
+            self.finish(Ok(average as u16));
+        } else if let Err(e) = self.sensor.read_db() {
+            self.finish(Err(e));
+        }
+    }
+
+    fn peak(&self, amplitude: u16) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::PEAK, (amplitude as usize, 0, 0))
+                .ok();
+        });
+    }
+}
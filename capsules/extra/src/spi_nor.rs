@@ -0,0 +1,619 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for common SPI NOR flash chips, such as the Winbond W25Q and the
+//! Macronix MX25 series.
+//!
+//! The driver implements `hil::flash::Flash`, so that storage capsules such
+//! as TicKV or the nonvolatile storage driver can use external flash. A
+//! flash page is a 4 KiB sector, the smallest unit the sector erase (0x20)
+//! command erases. Writing a page erases its sector, and then programs it
+//! with page program (0x02) commands, which must not cross a program page
+//! of the chip (256 bytes on most chips). Reads use the read (0x03) or fast
+//! read (0x0B) command.
+//!
+//! Erases and programs set the write enable latch first, and the driver
+//! polls the busy bit of the status register until the chip is done before
+//! it issues the next command.
+//!
+//! The chips use 24-bit addresses, so up to 16 MiB is addressable.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let spi_nor_spi = components::spi::SpiComponent::new(mux_spi, nrf52840::gpio::Pin::P0_17)
+//!     .finalize(components::spi_component_static!(nrf52840::spi::SPIM));
+//! let spi_nor = components::spi_nor::SpiNorComponent::new(
+//!     spi_nor_spi,
+//!     mux_alarm,
+//!     capsules_extra::spi_nor::Geometry {
+//!         sectors: 2048,
+//!         program_size: 256,
+//!     },
+//!     capsules_extra::spi_nor::ReadCommand::FastRead,
+//! )
+//! .finalize(components::spi_nor_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::spi::{SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The size of a flash page, the sector erased by the sector erase command.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The largest page program of a chip.
+pub const MAX_PROGRAM_SIZE: usize = 256;
+
+/// Size of the SPI buffers: an opcode, an address, a dummy byte and a
+/// program page.
+pub const BUF_LEN: usize = MAX_PROGRAM_SIZE + 5;
+
+/// The interval at which the status register is read while the chip is
+/// busy, in µs.
+const POLL_INTERVAL_US: u32 = 500;
+
+/// The write in progress bit of the status register.
+const STATUS_BUSY: u8 = 0x01;
+
+#[derive(Clone, Copy)]
+enum Opcodes {
+    WriteEnable = 0x06,
+    ReadStatus = 0x05,
+    Read = 0x03,
+    FastRead = 0x0B,
+    PageProgram = 0x02,
+    SectorErase = 0x20,
+}
+
+/// A flash page for SPI NOR chips.
+///
+/// ```
+/// # use capsules_extra::spi_nor::SpiNorSector;
+///
+/// static mut PAGEBUFFER: SpiNorSector = SpiNorSector::new();
+/// ```
+pub struct SpiNorSector(pub [u8; SECTOR_SIZE]);
+
+impl SpiNorSector {
+    pub const fn new() -> Self {
+        Self([0; SECTOR_SIZE])
+    }
+}
+
+impl Default for SpiNorSector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsMut<[u8]> for SpiNorSector {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// The layout of a chip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Geometry {
+    /// The number of 4 KiB sectors.
+    pub sectors: usize,
+    /// The program page of the chip, a power of two up to
+    /// `MAX_PROGRAM_SIZE`.
+    pub program_size: usize,
+}
+
+/// The command pages are read with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadCommand {
+    /// Read (0x03), for lower clock rates.
+    Read,
+    /// Fast read (0x0B), with a dummy byte after the address.
+    FastRead,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+/// A step of an operation, with the offset in the sector.
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Read(usize),
+    Erase,
+    Program(usize),
+    Finish,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Reading the status register, before the command.
+    ReadStatus(Command),
+    /// Waiting to read the status register again.
+    PollDelay(Command),
+    WriteEnable(Command),
+    Transfer(Command),
+}
+
+pub struct SpiNor<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> {
+    spi: &'a S,
+    alarm: &'a A,
+    geometry: Geometry,
+    read_command: ReadCommand,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    sector_index: Cell<usize>,
+    txbuffer: TakeCell<'static, [u8]>,
+    rxbuffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn hil::flash::Client<SpiNor<'a, S, A>>>,
+    client_sector: TakeCell<'static, SpiNorSector>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> SpiNor<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        geometry: Geometry,
+        read_command: ReadCommand,
+        txbuffer: &'static mut [u8; BUF_LEN],
+        rxbuffer: &'static mut [u8; BUF_LEN],
+    ) -> SpiNor<'a, S, A> {
+        SpiNor {
+            spi,
+            alarm,
+            geometry: Geometry {
+                program_size: geometry.program_size.clamp(1, MAX_PROGRAM_SIZE),
+                ..geometry
+            },
+            read_command,
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Read),
+            sector_index: Cell::new(0),
+            txbuffer: TakeCell::new(txbuffer),
+            rxbuffer: TakeCell::new(rxbuffer),
+            client: OptionalCell::empty(),
+            client_sector: TakeCell::empty(),
+        }
+    }
+
+    /// Start `operation` on `sector_index`, once the chip is not busy.
+    fn start(
+        &self,
+        operation: Operation,
+        sector_index: usize,
+        first: Command,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if sector_index >= self.geometry.sectors {
+            return Err(ErrorCode::INVAL);
+        }
+        self.operation.set(operation);
+        self.sector_index.set(sector_index);
+        self.poll(first).map_err(|error| {
+            self.state.set(State::Idle);
+            error
+        })
+    }
+
+    /// Send the first `len` bytes of the transmit buffer, filled by `fill`,
+    /// and move to `state`.
+    fn transfer(
+        &self,
+        state: State,
+        len: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) -> Result<(), ErrorCode> {
+        let (txbuffer, rxbuffer) = match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(txbuffer), Some(rxbuffer)) => (txbuffer, rxbuffer),
+            (txbuffer, rxbuffer) => {
+                txbuffer.map(|buffer| self.txbuffer.replace(buffer));
+                rxbuffer.map(|buffer| self.rxbuffer.replace(buffer));
+                return Err(ErrorCode::RESERVE);
+            }
+        };
+        fill(txbuffer);
+        self.state.set(state);
+        self.spi
+            .read_write_bytes(txbuffer, Some(rxbuffer), len)
+            .map_err(|(error, txbuffer, rxbuffer)| {
+                self.txbuffer.replace(txbuffer);
+                rxbuffer.map(|buffer| self.rxbuffer.replace(buffer));
+                error
+            })
+    }
+
+    /// Read the status register, to issue `then` once the chip is not busy.
+    fn poll(&self, then: Command) -> Result<(), ErrorCode> {
+        self.transfer(State::ReadStatus(then), 2, |txbuffer| {
+            txbuffer[0] = Opcodes::ReadStatus as u8;
+        })
+    }
+
+    fn issue(&self, command: Command) -> Result<(), ErrorCode> {
+        match command {
+            Command::Finish => {
+                self.finish(hil::flash::Error::CommandComplete);
+                Ok(())
+            }
+            Command::Read(offset) => {
+                let opcode = match self.read_command {
+                    ReadCommand::Read => Opcodes::Read,
+                    ReadCommand::FastRead => Opcodes::FastRead,
+                };
+                self.transfer(
+                    State::Transfer(command),
+                    self.read_header() + MAX_PROGRAM_SIZE,
+                    |txbuffer| self.command(txbuffer, opcode, offset),
+                )
+            }
+            // The write enable latch is cleared by every erase and program.
+            Command::Erase | Command::Program(_) => {
+                self.transfer(State::WriteEnable(command), 1, |txbuffer| {
+                    txbuffer[0] = Opcodes::WriteEnable as u8;
+                })
+            }
+        }
+    }
+
+    /// Write `opcode` and the address of `offset` in the sector.
+    fn command(&self, txbuffer: &mut [u8], opcode: Opcodes, offset: usize) {
+        let address = self.sector_index.get() * SECTOR_SIZE + offset;
+        txbuffer[0] = opcode as u8;
+        txbuffer[1] = (address >> 16) as u8;
+        txbuffer[2] = (address >> 8) as u8;
+        txbuffer[3] = address as u8;
+    }
+
+    /// Send the command, once the write enable latch is set.
+    fn write_command(&self, command: Command) -> Result<(), ErrorCode> {
+        match command {
+            Command::Erase => self.transfer(State::Transfer(command), 4, |txbuffer| {
+                self.command(txbuffer, Opcodes::SectorErase, 0)
+            }),
+            Command::Program(offset) => {
+                let len = self.geometry.program_size;
+                self.transfer(State::Transfer(command), 4 + len, |txbuffer| {
+                    self.command(txbuffer, Opcodes::PageProgram, offset);
+                    self.client_sector.map(|sector| {
+                        txbuffer[4..4 + len].copy_from_slice(&sector.0[offset..offset + len])
+                    });
+                })
+            }
+            _ => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// The bytes sent before the data of a read.
+    fn read_header(&self) -> usize {
+        match self.read_command {
+            ReadCommand::Read => 4,
+            ReadCommand::FastRead => 5,
+        }
+    }
+
+    /// Continue once `command` was sent.
+    fn command_done(&self, command: Command) -> Result<(), ErrorCode> {
+        match command {
+            Command::Read(offset) => {
+                let offset = offset + MAX_PROGRAM_SIZE;
+                if offset < SECTOR_SIZE {
+                    self.issue(Command::Read(offset))
+                } else {
+                    self.issue(Command::Finish)
+                }
+            }
+            Command::Erase if self.operation.get() == Operation::Write => {
+                self.poll(Command::Program(0))
+            }
+            Command::Program(offset) if offset + self.geometry.program_size < SECTOR_SIZE => {
+                self.poll(Command::Program(offset + self.geometry.program_size))
+            }
+            _ => self.poll(Command::Finish),
+        }
+    }
+
+    fn finish(&self, error: hil::flash::Error) {
+        self.state.set(State::Idle);
+        match self.operation.get() {
+            Operation::Read => {
+                self.client_sector.take().map(|sector| {
+                    self.client
+                        .map(move |client| client.read_complete(sector, error))
+                });
+            }
+            Operation::Write => {
+                self.client_sector.take().map(|sector| {
+                    self.client
+                        .map(move |client| client.write_complete(sector, error))
+                });
+            }
+            Operation::Erase => {
+                self.client.map(|client| client.erase_complete(error));
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> SpiMasterClient for SpiNor<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.txbuffer.replace(write_buffer);
+        let result = status.and_then(|()| {
+            let rxbuffer = read_buffer.ok_or(ErrorCode::FAIL)?;
+            let result = match self.state.get() {
+                State::ReadStatus(then) if rxbuffer[1] & STATUS_BUSY != 0 => {
+                    self.state.set(State::PollDelay(then));
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(POLL_INTERVAL_US));
+                    Ok(())
+                }
+                State::ReadStatus(then) => {
+                    self.rxbuffer.replace(rxbuffer);
+                    return self.issue(then);
+                }
+                State::WriteEnable(command) => {
+                    self.rxbuffer.replace(rxbuffer);
+                    return self.write_command(command);
+                }
+                State::Transfer(command) => {
+                    if let Command::Read(offset) = command {
+                        let header = self.read_header();
+                        self.client_sector.map(|sector| {
+                            sector.0[offset..offset + MAX_PROGRAM_SIZE]
+                                .copy_from_slice(&rxbuffer[header..header + MAX_PROGRAM_SIZE])
+                        });
+                    }
+                    self.rxbuffer.replace(rxbuffer);
+                    return self.command_done(command);
+                }
+                State::Idle | State::PollDelay(_) => Ok(()),
+            };
+            self.rxbuffer.replace(rxbuffer);
+            result
+        });
+        if result.is_err() {
+            self.finish(hil::flash::Error::FlashError);
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> AlarmClient for SpiNor<'a, S, A> {
+    fn alarm(&self) {
+        if let State::PollDelay(then) = self.state.get() {
+            if self.poll(then).is_err() {
+                self.finish(hil::flash::Error::FlashError);
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>, C: hil::flash::Client<Self>>
+    hil::flash::HasClient<'a, C> for SpiNor<'a, S, A>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> hil::flash::Flash for SpiNor<'a, S, A> {
+    type Page = SpiNorSector;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        match self.start(Operation::Read, page_number, Command::Read(0)) {
+            Ok(()) => {
+                self.client_sector.replace(buf);
+                Ok(())
+            }
+            Err(error) => Err((error, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        match self.start(Operation::Write, page_number, Command::Erase) {
+            Ok(()) => {
+                self.client_sector.replace(buf);
+                Ok(())
+            }
+            Err(error) => Err((error, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        self.start(Operation::Erase, page_number, Command::Erase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockSpi};
+    use core::cell::RefCell;
+    use kernel::hil::flash::{Flash, HasClient};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A model of a SPI NOR flash chip, which answers the transfers of the
+    /// SPI bus when the test steps it.
+    struct MockFlash {
+        memory: RefCell<Vec<u8>>,
+        write_enabled: Cell<bool>,
+        /// The status reads left before an erase or program is done.
+        busy: Cell<u8>,
+        /// Commands other than a status read sent while busy.
+        ignored: Cell<usize>,
+        /// The length of the largest page program.
+        largest_program: Cell<usize>,
+    }
+
+    impl MockFlash {
+        fn new(sectors: usize) -> Self {
+            MockFlash {
+                memory: RefCell::new(std::vec![0; sectors * SECTOR_SIZE]),
+                write_enabled: Cell::new(false),
+                busy: Cell::new(0),
+                ignored: Cell::new(0),
+                largest_program: Cell::new(0),
+            }
+        }
+
+        /// Execute the command written in `tx`, answering in `rx`.
+        fn execute(&self, tx: &[u8], rx: &mut [u8]) {
+            let len = tx.len();
+            let address = match tx {
+                [_, a2, a1, a0, ..] => (*a2 as usize) << 16 | (*a1 as usize) << 8 | *a0 as usize,
+                _ => 0,
+            };
+            let mut memory = self.memory.borrow_mut();
+            match tx[0] {
+                0x05 => {
+                    rx[1] = (self.busy.get() > 0) as u8 | (self.write_enabled.get() as u8) << 1;
+                    self.busy.set(self.busy.get().saturating_sub(1));
+                }
+                _ if self.busy.get() > 0 => self.ignored.set(self.ignored.get() + 1),
+                0x06 => self.write_enabled.set(true),
+                0x20 if self.write_enabled.get() => {
+                    let start = address & !(SECTOR_SIZE - 1);
+                    memory[start..start + SECTOR_SIZE].fill(0xFF);
+                    self.write_enabled.set(false);
+                    self.busy.set(3);
+                }
+                0x02 if self.write_enabled.get() => {
+                    // Programs wrap around within the 256-byte page, and
+                    // can only clear bits.
+                    let data = &tx[4..len];
+                    for (i, byte) in data.iter().enumerate() {
+                        memory[address & !0xFF | (address + i) & 0xFF] &= byte;
+                    }
+                    self.largest_program
+                        .set(self.largest_program.get().max(data.len()));
+                    self.write_enabled.set(false);
+                    self.busy.set(1);
+                }
+                0x03 => rx[4..len].copy_from_slice(&memory[address..address + len - 4]),
+                0x0B => rx[5..len].copy_from_slice(&memory[address..address + len - 5]),
+                _ => {}
+            }
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type TestFlash = SpiNor<'static, MockSpi<'static>, TestAlarm>;
+
+    struct MockClient {
+        sector: TakeCell<'static, SpiNorSector>,
+        result: Cell<Option<hil::flash::Error>>,
+    }
+
+    impl hil::flash::Client<TestFlash> for MockClient {
+        fn read_complete(&self, sector: &'static mut SpiNorSector, error: hil::flash::Error) {
+            self.sector.replace(sector);
+            self.result.set(Some(error));
+        }
+        fn write_complete(&self, sector: &'static mut SpiNorSector, error: hil::flash::Error) {
+            self.sector.replace(sector);
+            self.result.set(Some(error));
+        }
+        fn erase_complete(&self, error: hil::flash::Error) {
+            self.result.set(Some(error));
+        }
+    }
+
+    /// Run the chip and the alarm until the operation in progress is done.
+    fn run(spi: &MockSpi, flash: &MockFlash, alarm: &TestAlarm) {
+        loop {
+            if spi.busy() {
+                spi.complete_with(|tx, rx| flash.execute(tx, rx));
+            } else if alarm.is_armed() {
+                alarm.fire();
+            } else {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn writes_across_program_pages_and_reads_back() {
+        let spi = Box::leak(Box::new(MockSpi::new()));
+        let flash = Box::leak(Box::new(MockFlash::new(2)));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let client = Box::leak(Box::new(MockClient {
+            sector: TakeCell::empty(),
+            result: Cell::new(None),
+        }));
+        let spi_nor = Box::leak(Box::new(SpiNor::new(
+            &*spi,
+            &*alarm,
+            Geometry {
+                sectors: 2,
+                program_size: 256,
+            },
+            ReadCommand::FastRead,
+            Box::leak(Box::new([0; BUF_LEN])),
+            Box::leak(Box::new([0; BUF_LEN])),
+        )));
+        spi.set_client(spi_nor);
+        alarm.set_alarm_client(spi_nor);
+        spi_nor.set_client(client);
+
+        let sector = Box::leak(Box::new(SpiNorSector::new()));
+        for (i, byte) in sector.0.iter_mut().enumerate() {
+            *byte = (i * 7 + i / 256) as u8;
+        }
+        let expected = sector.0;
+        assert!(spi_nor.write_page(1, sector).is_ok());
+        run(spi, flash, alarm);
+        assert_eq!(
+            client.result.take(),
+            Some(hil::flash::Error::CommandComplete)
+        );
+        // The sector was erased and programmed in 256-byte pages, without
+        // a command sent while the chip was busy.
+        assert_eq!(flash.ignored.get(), 0);
+        assert_eq!(flash.largest_program.get(), 256);
+        assert_eq!(flash.memory.borrow()[SECTOR_SIZE..], expected);
+        assert!(flash.memory.borrow()[..SECTOR_SIZE].iter().all(|&b| b == 0));
+
+        let sector = client.sector.take().unwrap();
+        sector.0.fill(0);
+        assert!(spi_nor.read_page(1, sector).is_ok());
+        run(spi, flash, alarm);
+        assert_eq!(
+            client.result.take(),
+            Some(hil::flash::Error::CommandComplete)
+        );
+        assert_eq!(client.sector.take().unwrap().0, expected);
+
+        // Pages beyond the chip are rejected.
+        assert_eq!(spi_nor.erase_page(2), Err(ErrorCode::INVAL));
+    }
+}
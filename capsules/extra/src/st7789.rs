@@ -0,0 +1,732 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for ST7789 and ILI9341 TFT screens on a 4-wire SPI interface.
+//!
+//! - <https://www.rhydolabz.com/documents/33/ST7789.pdf>
+//! - <https://cdn-shop.adafruit.com/datasheets/ILI9341.pdf>
+//!
+//! Both controllers take a command byte with the DC pin low, followed by its
+//! parameters with the DC pin high, and share the commands this driver uses
+//! once they are initialised. Pixels are RGB565, sent big-endian.
+//!
+//! Unlike [crate::st77xx], which goes through the generic `Bus` interface,
+//! this driver talks to the SPI device directly and streams pixel data in
+//! transfers as large as its transmit buffer, so a frame is sent in a handful
+//! of DMA transfers instead of one transfer per pixel word. The buffer a
+//! client writes is copied into the transmit buffer one chunk at a time.
+//!
+//! The window set by `set_write_frame` is in the controller's memory
+//! coordinates. Panels smaller than the controller's memory that are not
+//! mounted at its origin, and rotation, are not supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tft = components::st7789::St7789Component::new(
+//!     mux_spi,
+//!     &nrf52840::gpio::PORT[GPIO_D4],
+//!     &nrf52840::gpio::PORT[GPIO_D3],
+//!     Some(&nrf52840::gpio::PORT[GPIO_D2]),
+//!     mux_alarm,
+//!     &capsules_extra::st7789::ST7789,
+//!     (240, 320),
+//! )
+//! .finalize(components::st7789_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::gpio::GPIOPin<'static>,
+//! ));
+//! let _ = tft.init();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil::gpio::Pin;
+use kernel::hil::screen::{Screen, ScreenClient, ScreenPixelFormat, ScreenRotation};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the transmit buffer, and so of the largest pixel transfer.
+pub const BUFFER_SIZE: usize = 1024;
+
+const SWRESET: u8 = 0x01;
+const SLPOUT: u8 = 0x11;
+const NORON: u8 = 0x13;
+const INVOFF: u8 = 0x20;
+const INVON: u8 = 0x21;
+const DISPOFF: u8 = 0x28;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2A;
+const RASET: u8 = 0x2B;
+const RAMWR: u8 = 0x2C;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3A;
+
+/// 16 bits per pixel on the interface and in memory.
+const COLMOD_RGB565: u8 = 0x55;
+
+/// Time the reset pin is held low.
+const RESET_LOW_MS: u32 = 10;
+/// Time the controller needs after a hardware reset.
+const RESET_WAIT_MS: u32 = 120;
+
+/// A command sent while initialising the controller.
+pub struct InitCommand {
+    command: u8,
+    parameters: &'static [u8],
+    /// Time to wait after the command, in ms.
+    delay: u32,
+}
+
+const fn init_command(command: u8, parameters: &'static [u8], delay: u32) -> InitCommand {
+    InitCommand {
+        command,
+        parameters,
+        delay,
+    }
+}
+
+/// A controller supported by this driver.
+pub struct Panel {
+    init_sequence: &'static [InitCommand],
+    /// Whether the panel shows colours inverted when the controller does not
+    /// invert them, as most IPS panels on the ST7789 do.
+    inverted: bool,
+    /// SPI clock rate in Hz.
+    rate: u32,
+}
+
+pub const ST7789: Panel = Panel {
+    init_sequence: &[
+        init_command(SWRESET, &[], 150),
+        init_command(SLPOUT, &[], 120),
+        init_command(COLMOD, &[COLMOD_RGB565], 10),
+        init_command(MADCTL, &[0x00], 0),
+        init_command(INVON, &[], 0),
+        init_command(NORON, &[], 10),
+        init_command(DISPON, &[], 10),
+    ],
+    inverted: true,
+    rate: 32_000_000,
+};
+
+pub const ILI9341: Panel = Panel {
+    init_sequence: &[
+        init_command(SWRESET, &[], 150),
+        // Power control A and B, driver timing control A and B and power on
+        // sequence control, with the values recommended by the vendor.
+        init_command(0xCB, &[0x39, 0x2C, 0x00, 0x34, 0x02], 0),
+        init_command(0xCF, &[0x00, 0xC1, 0x30], 0),
+        init_command(0xE8, &[0x85, 0x00, 0x78], 0),
+        init_command(0xEA, &[0x00, 0x00], 0),
+        init_command(0xED, &[0x64, 0x03, 0x12, 0x81], 0),
+        init_command(0xF7, &[0x20], 0),
+        // Power control 1 and 2, VCOM control 1 and 2.
+        init_command(0xC0, &[0x23], 0),
+        init_command(0xC1, &[0x10], 0),
+        init_command(0xC5, &[0x3E, 0x28], 0),
+        init_command(0xC7, &[0x86], 0),
+        // Column address order reversed, BGR panel.
+        init_command(MADCTL, &[0x48], 0),
+        init_command(COLMOD, &[COLMOD_RGB565], 0),
+        // Frame rate control and display function control.
+        init_command(0xB1, &[0x00, 0x18], 0),
+        init_command(0xB6, &[0x08, 0x82, 0x27], 0),
+        // 3-gamma off, gamma curve 1, positive and negative gamma correction.
+        init_command(0xF2, &[0x00], 0),
+        init_command(0x26, &[0x01], 0),
+        init_command(
+            0xE0,
+            &[
+                0x0F, 0x31, 0x2B, 0x0C, 0x0E, 0x08, 0x4E, 0xF1, 0x37, 0x07, 0x10, 0x03, 0x0E, 0x09,
+                0x00,
+            ],
+            0,
+        ),
+        init_command(
+            0xE1,
+            &[
+                0x00, 0x0E, 0x14, 0x03, 0x11, 0x07, 0x31, 0xC1, 0x48, 0x08, 0x0F, 0x0C, 0x31, 0x36,
+                0x0F,
+            ],
+            0,
+        ),
+        init_command(SLPOUT, &[], 120),
+        init_command(DISPON, &[], 10),
+    ],
+    inverted: false,
+    rate: 10_000_000,
+};
+
+/// The part of a command in flight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Command,
+    Parameters,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Uninitialized,
+    Idle,
+    /// Holding the reset pin low.
+    Reset,
+    /// Waiting for the controller to come out of reset.
+    ResetWait,
+    /// Sending a command of the init sequence.
+    Init(usize, Step),
+    /// Waiting after a command of the init sequence.
+    InitDelay(usize),
+    /// Setting the window's columns.
+    Columns(Step),
+    /// Setting the window's rows.
+    Rows(Step),
+    /// Sending `RAMWR` before pixel data.
+    MemoryWrite,
+    /// Sending a chunk of pixel data.
+    Pixels,
+    /// Sending `DISPON` or `DISPOFF`.
+    Power,
+    /// Sending `INVON` or `INVOFF`.
+    Invert,
+}
+
+/// `CASET` or `RASET` parameters for the addresses `start` to `end`.
+fn address_range(start: usize, end: usize) -> [u8; 4] {
+    let [start_high, start_low] = (start as u16).to_be_bytes();
+    let [end_high, end_low] = (end as u16).to_be_bytes();
+    [start_high, start_low, end_high, end_low]
+}
+
+pub struct St7789<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin> {
+    spi: &'a S,
+    alarm: &'a A,
+    dc: &'a P,
+    reset: Option<&'a P>,
+    panel: &'static Panel,
+    width: usize,
+    height: usize,
+    state: Cell<State>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// Columns and rows of the write frame.
+    columns: Cell<[u8; 4]>,
+    rows: Cell<[u8; 4]>,
+    write_buffer: TakeCell<'static, [u8]>,
+    write_len: Cell<usize>,
+    write_position: Cell<usize>,
+    client: OptionalCell<&'a dyn ScreenClient>,
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin> St7789<'a, A, S, P> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        dc: &'a P,
+        reset: Option<&'a P>,
+        tx_buffer: &'static mut [u8],
+        panel: &'static Panel,
+        resolution: (usize, usize),
+    ) -> Self {
+        dc.make_output();
+        reset.map(|reset| {
+            reset.make_output();
+            reset.set();
+        });
+        St7789 {
+            spi,
+            alarm,
+            dc,
+            reset,
+            panel,
+            width: resolution.0,
+            height: resolution.1,
+            state: Cell::new(State::Uninitialized),
+            tx_buffer: TakeCell::new(tx_buffer),
+            columns: Cell::new([0; 4]),
+            rows: Cell::new([0; 4]),
+            write_buffer: TakeCell::empty(),
+            write_len: Cell::new(0),
+            write_position: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Reset and initialise the controller. The client's `screen_is_ready`
+    /// is called once the screen is on.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Uninitialized | State::Idle => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        self.spi.configure(
+            ClockPolarity::IdleLow,
+            ClockPhase::SampleLeading,
+            self.panel.rate,
+        )?;
+        match self.reset {
+            Some(reset) => {
+                reset.clear();
+                self.set_delay(RESET_LOW_MS, State::Reset);
+                Ok(())
+            }
+            None => self.init_command(0),
+        }
+    }
+
+    fn set_delay(&self, ms: u32, state: State) {
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Send `command` with the DC pin low.
+    fn write_command(&self, command: u8, state: State) -> Result<(), ErrorCode> {
+        self.dc.clear();
+        self.write_data(&[command], state)
+    }
+
+    /// Send `data` with the DC pin high.
+    fn write_parameters(&self, data: &[u8], state: State) -> Result<(), ErrorCode> {
+        self.dc.set();
+        self.write_data(data, state)
+    }
+
+    fn write_data(&self, data: &[u8], state: State) -> Result<(), ErrorCode> {
+        self.tx_buffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                buffer[..data.len()].copy_from_slice(data);
+                self.transfer(buffer, data.len(), state)
+            })
+    }
+
+    fn transfer(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+        state: State,
+    ) -> Result<(), ErrorCode> {
+        let previous = self.state.replace(state);
+        self.spi
+            .read_write_bytes(buffer, None, len)
+            .map_err(|(error, buffer, _)| {
+                self.tx_buffer.replace(buffer);
+                self.state.set(previous);
+                error
+            })
+    }
+
+    fn init_command(&self, index: usize) -> Result<(), ErrorCode> {
+        match self.panel.init_sequence.get(index) {
+            Some(command) => self.write_command(command.command, State::Init(index, Step::Command)),
+            None => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.screen_is_ready());
+                Ok(())
+            }
+        }
+    }
+
+    /// Send the next chunk of pixel data, or complete the write if it has
+    /// all been sent.
+    fn write_pixels(&self) -> Result<(), ErrorCode> {
+        let position = self.write_position.get();
+        let len = self.write_len.get();
+        if position == len {
+            self.state.set(State::Idle);
+            self.write_buffer.take().map(|buffer| {
+                self.client
+                    .map(move |client| client.write_complete(buffer, Ok(())));
+            });
+            return Ok(());
+        }
+        self.dc.set();
+        let tx_buffer = self.tx_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        let chunk = cmp::min(len - position, tx_buffer.len());
+        self.write_buffer.map(|pixels| {
+            tx_buffer[..chunk].copy_from_slice(&pixels[position..position + chunk]);
+        });
+        self.write_position.set(position + chunk);
+        self.transfer(tx_buffer, chunk, State::Pixels)
+    }
+
+    /// Move on from the transfer that completed in `state`.
+    fn next(&self, state: State) -> Result<(), ErrorCode> {
+        match state {
+            State::Init(index, Step::Command) => {
+                let parameters = self.panel.init_sequence[index].parameters;
+                if parameters.is_empty() {
+                    self.next(State::Init(index, Step::Parameters))
+                } else {
+                    self.write_parameters(parameters, State::Init(index, Step::Parameters))
+                }
+            }
+            State::Init(index, Step::Parameters) => {
+                let delay = self.panel.init_sequence[index].delay;
+                if delay > 0 {
+                    self.set_delay(delay, State::InitDelay(index));
+                    Ok(())
+                } else {
+                    self.init_command(index + 1)
+                }
+            }
+            State::Columns(Step::Command) => {
+                self.write_parameters(&self.columns.get(), State::Columns(Step::Parameters))
+            }
+            State::Columns(Step::Parameters) => {
+                self.write_command(RASET, State::Rows(Step::Command))
+            }
+            State::Rows(Step::Command) => {
+                self.write_parameters(&self.rows.get(), State::Rows(Step::Parameters))
+            }
+            State::Rows(Step::Parameters) => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.command_complete(Ok(())));
+                Ok(())
+            }
+            State::MemoryWrite | State::Pixels => self.write_pixels(),
+            State::Power => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.screen_is_ready());
+                Ok(())
+            }
+            State::Invert => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.command_complete(Ok(())));
+                Ok(())
+            }
+            State::Uninitialized
+            | State::Idle
+            | State::Reset
+            | State::ResetWait
+            | State::InitDelay(_) => Ok(()),
+        }
+    }
+
+    /// Report `error` for the operation that was in progress in `state`.
+    fn fail(&self, state: State, error: ErrorCode) {
+        match state {
+            State::Reset | State::ResetWait | State::Init(..) | State::InitDelay(_) => {
+                // The controller is in an unknown state until it is
+                // initialised again.
+                self.state.set(State::Uninitialized);
+                return;
+            }
+            _ => self.state.set(State::Idle),
+        }
+        match self.write_buffer.take() {
+            Some(buffer) => self
+                .client
+                .map(move |client| client.write_complete(buffer, Err(error))),
+            None => self
+                .client
+                .map(|client| client.command_complete(Err(error))),
+        };
+    }
+
+    fn start_write(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => {}
+            State::Uninitialized => return Err(ErrorCode::OFF),
+            _ => return Err(ErrorCode::BUSY),
+        }
+        // Whole RGB565 pixels only.
+        if len == 0 || len % 2 != 0 || len > buffer.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.write_buffer.replace(buffer);
+        self.write_len.set(len);
+        self.write_position.set(0);
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin> Screen<'a> for St7789<'a, A, S, P> {
+    fn get_resolution(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn get_pixel_format(&self) -> ScreenPixelFormat {
+        ScreenPixelFormat::RGB_565
+    }
+
+    fn get_rotation(&self) -> ScreenRotation {
+        ScreenRotation::Normal
+    }
+
+    fn set_write_frame(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => {}
+            State::Uninitialized => return Err(ErrorCode::OFF),
+            _ => return Err(ErrorCode::BUSY),
+        }
+        if width == 0 || height == 0 || x + width > self.width || y + height > self.height {
+            return Err(ErrorCode::INVAL);
+        }
+        self.columns.set(address_range(x, x + width - 1));
+        self.rows.set(address_range(y, y + height - 1));
+        self.write_command(CASET, State::Columns(Step::Command))
+    }
+
+    fn write(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        self.start_write(buffer, len)?;
+        self.write_command(RAMWR, State::MemoryWrite)
+    }
+
+    fn write_continue(&self, buffer: &'static mut [u8], len: usize) -> Result<(), ErrorCode> {
+        self.start_write(buffer, len)?;
+        self.write_pixels()
+    }
+
+    fn set_client(&self, client: Option<&'a dyn ScreenClient>) {
+        match client {
+            Some(client) => self.client.set(client),
+            None => self.client.clear(),
+        }
+    }
+
+    fn set_brightness(&self, _brightness: usize) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn set_power(&self, enabled: bool) -> Result<(), ErrorCode> {
+        match (self.state.get(), enabled) {
+            (State::Uninitialized, true) => self.init(),
+            (State::Uninitialized, false) => Err(ErrorCode::OFF),
+            (State::Idle, _) => {
+                self.write_command(if enabled { DISPON } else { DISPOFF }, State::Power)
+            }
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    fn set_invert(&self, enabled: bool) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => {}
+            State::Uninitialized => return Err(ErrorCode::OFF),
+            _ => return Err(ErrorCode::BUSY),
+        }
+        let command = if enabled != self.panel.inverted {
+            INVON
+        } else {
+            INVOFF
+        };
+        self.write_command(command, State::Invert)
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin> AlarmClient for St7789<'a, A, S, P> {
+    fn alarm(&self) {
+        let result = match self.state.get() {
+            State::Reset => {
+                self.reset.map(|reset| reset.set());
+                self.set_delay(RESET_WAIT_MS, State::ResetWait);
+                Ok(())
+            }
+            State::ResetWait => self.init_command(0),
+            State::InitDelay(index) => self.init_command(index + 1),
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            self.fail(self.state.get(), error);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, S: SpiMasterDevice<'a>, P: Pin> SpiMasterClient for St7789<'a, A, S, P> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        _read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(write_buffer);
+        let state = self.state.get();
+        if let Err(error) = status.and_then(|()| self.next(state)) {
+            self.fail(state, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockPin, MockSpi};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// The test's transmit buffer, small enough to see chunking.
+    const TX_LEN: usize = 16;
+
+    #[derive(Default)]
+    struct Client {
+        ready: Cell<usize>,
+        commands: Cell<usize>,
+        writes: RefCell<Vec<usize>>,
+    }
+
+    impl ScreenClient for Client {
+        fn command_complete(&self, r: Result<(), ErrorCode>) {
+            assert_eq!(r, Ok(()));
+            self.commands.set(self.commands.get() + 1);
+        }
+        fn write_complete(&self, buffer: &'static mut [u8], r: Result<(), ErrorCode>) {
+            assert_eq!(r, Ok(()));
+            self.writes.borrow_mut().push(buffer.len());
+        }
+        fn screen_is_ready(&self) {
+            self.ready.set(self.ready.get() + 1);
+        }
+    }
+
+    type Device = St7789<'static, MockAlarm<'static>, MockSpi<'static>, MockPin<'static>>;
+
+    struct Test {
+        spi: &'static MockSpi<'static>,
+        alarm: &'static MockAlarm<'static>,
+        tft: &'static Device,
+        client: &'static Client,
+    }
+
+    impl Test {
+        fn new(panel: &'static Panel, resolution: (usize, usize)) -> Test {
+            let dc: &'static MockPin = Box::leak(Box::default());
+            let reset: &'static MockPin = Box::leak(Box::default());
+            let spi: &'static MockSpi = Box::leak(Box::default());
+            spi.tag_with(dc);
+            let alarm: &'static MockAlarm = Box::leak(Box::default());
+            let client: &'static Client = Box::leak(Box::default());
+            let tft = Box::leak(Box::new(St7789::new(
+                spi,
+                alarm,
+                dc,
+                Some(reset),
+                Box::leak(Box::new([0; TX_LEN])),
+                panel,
+                resolution,
+            )));
+            spi.set_client(tft);
+            alarm.set_alarm_client(tft);
+            tft.set_client(Some(client));
+            Test {
+                spi,
+                alarm,
+                tft,
+                client,
+            }
+        }
+
+        /// Complete transfers and fire alarms until the driver is idle.
+        fn run(&self) {
+            loop {
+                if self.spi.busy() {
+                    self.spi.complete(&[]);
+                } else if self.alarm.is_armed() {
+                    self.alarm.fire();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        fn take_transfers(&self) -> Vec<(bool, Vec<u8>)> {
+            self.spi.take_tagged_transfers()
+        }
+    }
+
+    #[test]
+    fn init_sequence() {
+        let test = Test::new(&ST7789, (240, 240));
+        let pixels = Box::leak(Box::new([0u8; 4]));
+        assert_eq!(test.tft.set_write_frame(0, 0, 1, 1), Err(ErrorCode::OFF));
+        assert_eq!(test.tft.write(pixels, 4), Err(ErrorCode::OFF));
+
+        assert_eq!(test.tft.set_power(true), Ok(()));
+        test.run();
+        assert_eq!(test.client.ready.get(), 1);
+        let transfers = test.take_transfers();
+        // Commands with DC low, each followed by its parameters with DC high.
+        assert_eq!(transfers[0], (false, [SWRESET].to_vec()));
+        assert_eq!(transfers[2], (false, [COLMOD].to_vec()));
+        assert_eq!(transfers[3], (true, [COLMOD_RGB565].to_vec()));
+        assert_eq!(transfers.last(), Some(&(false, [DISPON].to_vec())));
+        assert_eq!(transfers.len(), 9);
+    }
+
+    #[test]
+    fn window_commands() {
+        let test = Test::new(&ILI9341, (240, 320));
+        assert_eq!(test.tft.init(), Ok(()));
+        test.run();
+        test.take_transfers();
+
+        assert_eq!(test.tft.set_write_frame(200, 300, 40, 20), Ok(()));
+        test.run();
+        assert_eq!(
+            test.take_transfers(),
+            [
+                (false, [CASET].to_vec()),
+                (true, [0x00, 200, 0x00, 239].to_vec()),
+                (false, [RASET].to_vec()),
+                (true, [0x01, 0x2C, 0x01, 0x3F].to_vec()),
+            ]
+        );
+        assert_eq!(test.client.commands.get(), 1);
+
+        assert_eq!(
+            test.tft.set_write_frame(200, 300, 41, 20),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(test.tft.set_write_frame(0, 0, 0, 20), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn pixel_data_is_chunked() {
+        let test = Test::new(&ST7789, (240, 240));
+        assert_eq!(test.tft.init(), Ok(()));
+        test.run();
+        test.take_transfers();
+
+        let pixels = Box::leak(Box::new([0u8; 48]));
+        for (i, byte) in pixels.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(test.tft.write(pixels, 40), Ok(()));
+        test.run();
+        let transfers = test.take_transfers();
+        assert_eq!(transfers[0], (false, [RAMWR].to_vec()));
+        assert_eq!(
+            transfers[1..],
+            [
+                (true, (0..16).collect::<Vec<u8>>()),
+                (true, (16..32).collect()),
+                (true, (32..40).collect()),
+            ]
+        );
+        assert_eq!(*test.client.writes.borrow(), [48]);
+
+        // Continuing a write sends no command.
+        let pixels = Box::leak(Box::new([0xAAu8; 10]));
+        assert_eq!(test.tft.write_continue(pixels, 10), Ok(()));
+        test.run();
+        assert_eq!(test.take_transfers(), [(true, [0xAA; 10].to_vec())]);
+
+        let pixels = Box::leak(Box::new([0u8; 10]));
+        assert_eq!(test.tft.write(pixels, 9), Err(ErrorCode::INVAL));
+    }
+}
@@ -0,0 +1,1396 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Semtech SX1276 LoRa transceiver, which is also used by
+//! the SX1277, SX1278, SX1279 and the RFM95 modules.
+//!
+//! <https://www.semtech.com/products/wireless-rf/lora-connect/sx1276>
+//!
+//! The driver runs the transceiver in LoRa mode, and sends or receives one
+//! packet at a time, with the settings given for it:
+//!
+//! - `transmit` sends a packet in TX mode.
+//! - `receive` listens in RXSINGLE mode for a preamble for a given number
+//!   of symbols, and gives up if none started.
+//! - `receive_for` listens in RXCONTINUOUS mode until a packet is received
+//!   or the alarm expires.
+//! - `channel_activity_detection` checks for a LoRa preamble in CAD mode.
+//! - `set_mode` puts the transceiver in SLEEP, STANDBY, FSTX or FSRX.
+//!
+//! Every operation first writes the registers it needs one by one, and the
+//! transceiver is put to sleep once it is done. DIO0 signals the end of a
+//! transmission or a reception, DIO1 the timeout of a single reception and
+//! DIO3 the end of a channel activity detection, so they must be connected
+//! to interrupt pins. DIO0 also signals the end of a channel activity
+//! detection, so DIO3 can be left unconnected.
+//!
+//! The transceiver also implements the radio HIL, with the settings given
+//! to `set_radio_config`. Frames start at `PSDU_OFFSET` in the buffers, as
+//! the HIL requires, and there are no addresses: the address and PAN ID are
+//! kept but not used, and there are no channels. While the radio is on and
+//! has a receive buffer it listens in RXCONTINUOUS mode, and stops to run
+//! any other operation. It stops listening once a frame is received, until
+//! the buffer is given back.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let radio = components::sx1276::Sx1276Component::new(
+//!     spi_mux,
+//!     nrf52840::gpio::Pin::P0_12,
+//!     &nrf52840_peripherals.gpio_port[DIO0_PIN],
+//!     &nrf52840_peripherals.gpio_port[DIO1_PIN],
+//!     Some(&nrf52840_peripherals.gpio_port[DIO3_PIN]),
+//!     mux_alarm,
+//! )
+//! .finalize(components::sx1276_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc,
+//! ));
+//! let _ = radio.init();
+//! ```
+//!
+//! The LoRaWAN MAC sets up its own transceiver, see
+//! `components::lorawan_mac::LorawanMacComponent`.
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::radio;
+use kernel::hil::spi::{SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The largest packet the driver sends or receives.
+pub const MAX_PACKET_SIZE: usize = 64;
+
+/// Size of the SPI buffers: an address, and a packet.
+pub const BUFFER_SIZE: usize = MAX_PACKET_SIZE + 1;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+enum Registers {
+    Fifo = 0x00,
+    OpMode = 0x01,
+    FrfMsb = 0x06,
+    FrfMid = 0x07,
+    FrfLsb = 0x08,
+    PaConfig = 0x09,
+    Lna = 0x0C,
+    FifoAddrPtr = 0x0D,
+    FifoTxBaseAddr = 0x0E,
+    FifoRxBaseAddr = 0x0F,
+    FifoRxCurrentAddr = 0x10,
+    IrqFlagsMask = 0x11,
+    IrqFlags = 0x12,
+    RxNbBytes = 0x13,
+    ModemConfig1 = 0x1D,
+    ModemConfig2 = 0x1E,
+    SymbTimeoutLsb = 0x1F,
+    PayloadLength = 0x22,
+    ModemConfig3 = 0x26,
+    InvertIq = 0x33,
+    SyncWord = 0x39,
+    InvertIq2 = 0x3B,
+    DioMapping1 = 0x40,
+    Version = 0x42,
+}
+
+/// Set in the address of a register write.
+const WRITE: u8 = 0x80;
+
+const VERSION: u8 = 0x12;
+
+/// LongRangeMode of RegOpMode.
+const LORA: u8 = 0x80;
+
+/// The operating modes of the transceiver, in RegOpMode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Mode {
+    Sleep = 0,
+    Standby = 1,
+    /// Frequency synthesis for transmission.
+    FsTx = 2,
+    Tx = 3,
+    /// Frequency synthesis for reception.
+    FsRx = 4,
+    RxContinuous = 5,
+    RxSingle = 6,
+    /// Channel activity detection.
+    Cad = 7,
+}
+
+/// RegOpMode for `mode`, in LoRa mode.
+fn op_mode(mode: Mode) -> u8 {
+    LORA | mode as u8
+}
+
+const IRQ_RX_TIMEOUT: u8 = 0x80;
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_CAD_DONE: u8 = 0x04;
+const IRQ_CAD_DETECTED: u8 = 0x01;
+
+/// DIO0 signals TxDone.
+const DIO_MAPPING_TX: u8 = 0x40;
+/// DIO0 signals RxDone, and DIO1 RxTimeout.
+const DIO_MAPPING_RX: u8 = 0x00;
+/// DIO0 and DIO3 signal CadDone, and DIO1 CadDetected.
+const DIO_MAPPING_CAD: u8 = 0xA0;
+
+/// The sync word of public LoRaWAN networks.
+const SYNC_WORD_LORAWAN: u8 = 0x34;
+/// PA_BOOST output, the only one on most modules.
+const PA_BOOST: u8 = 0x80;
+/// The transmit power range of the PA_BOOST output, in dBm.
+const MIN_TX_POWER: i8 = 2;
+const MAX_TX_POWER: i8 = 17;
+/// Maximum LNA gain, with the boost for the high frequency port.
+const LNA_MAX_GAIN: u8 = 0x23;
+
+const RX_PAYLOAD_CRC_ON: u8 = 0x04;
+const LOW_DATA_RATE_OPTIMIZE: u8 = 0x08;
+const AGC_AUTO_ON: u8 = 0x04;
+
+/// RegInvertIQ and RegInvertIQ2, for normal and inverted I and Q signals.
+const INVERT_IQ_OFF: (u8, u8) = (0x27, 0x1D);
+const INVERT_IQ_ON: (u8, u8) = (0x66, 0x19);
+
+const CRYSTAL_HZ: u64 = 32_000_000;
+
+/// Most register writes of an operation.
+const MAX_WRITES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bandwidth {
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    pub fn hz(self) -> u32 {
+        match self {
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+
+    /// The Bw field of RegModemConfig1.
+    fn field(self) -> u8 {
+        match self {
+            Bandwidth::Khz125 => 0x7,
+            Bandwidth::Khz250 => 0x8,
+            Bandwidth::Khz500 => 0x9,
+        }
+    }
+}
+
+/// The share of the bits sent that carry data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CodingRate {
+    Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
+}
+
+impl CodingRate {
+    /// The CodingRate field of RegModemConfig1.
+    fn field(self) -> u8 {
+        match self {
+            CodingRate::Cr4_5 => 0x1,
+            CodingRate::Cr4_6 => 0x2,
+            CodingRate::Cr4_7 => 0x3,
+            CodingRate::Cr4_8 => 0x4,
+        }
+    }
+}
+
+/// The settings of a packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoraConfig {
+    pub frequency_hz: u32,
+    /// From 7 to 12.
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Invert the I and Q signals, which LoRaWAN does for downlinks.
+    pub invert_iq: bool,
+}
+
+impl LoraConfig {
+    /// Duration of a symbol, in µs.
+    pub fn symbol_us(&self) -> u32 {
+        (1_000_000u64 << self.spreading_factor) as u32 / (self.bandwidth.hz() / 1000) / 1000
+    }
+
+    fn check(&self) -> Result<(), ErrorCode> {
+        if (7..=12).contains(&self.spreading_factor) {
+            Ok(())
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+}
+
+/// The settings of the radio HIL until `set_radio_config` is called: the
+/// first EU868 channel, at the fastest spreading factor.
+pub const DEFAULT_RADIO_CONFIG: LoraConfig = LoraConfig {
+    frequency_hz: 868_100_000,
+    spreading_factor: 7,
+    bandwidth: Bandwidth::Khz125,
+    coding_rate: CodingRate::Cr4_5,
+    invert_iq: false,
+};
+
+pub trait Sx1276Client {
+    /// Called when the transceiver is set up, or with `NODEVICE` if it
+    /// did not answer.
+    fn init_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when a packet was sent, with its buffer.
+    fn transmit_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// Called with the length of the packet received into `buffer`, `NOACK`
+    /// if no packet started before the timeout, `FAIL` if its CRC was
+    /// wrong, or `SIZE` if it was larger than the buffer.
+    fn receive_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+
+    /// Called when a channel activity detection is done, with whether a
+    /// LoRa preamble was detected.
+    fn cad_done(&self, _result: Result<bool, ErrorCode>) {}
+
+    /// Called when the mode given to `set_mode` is set.
+    fn mode_done(&self, _result: Result<(), ErrorCode>) {}
+}
+
+/// What to do once the register writes are done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Then {
+    Ready,
+    WriteFifo,
+    /// Wait for the interrupt of the transmission, reception or channel
+    /// activity detection.
+    Wait,
+    TransmitDone(Result<(), ErrorCode>),
+    ReadFifo(usize),
+    ReceiveDone(Result<usize, ErrorCode>),
+    CadDone(Result<bool, ErrorCode>),
+    ModeSet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Transmit,
+    /// A transmission of the radio HIL.
+    RadioTransmit,
+    Receive,
+    /// Listening for frames for the radio HIL.
+    Listen,
+    Cad,
+    SetMode,
+    /// Turning the radio HIL on or off.
+    Power,
+}
+
+impl Operation {
+    /// Where the packet starts in the buffer.
+    fn offset(self) -> usize {
+        match self {
+            Operation::RadioTransmit | Operation::Listen => radio::PSDU_OFFSET,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Off,
+    ReadVersion,
+    Idle,
+    Writing(Then),
+    WritingFifo,
+    Waiting,
+    ReadFlags,
+    ReadFifo,
+}
+
+pub struct Sx1276<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> {
+    spi: &'a S,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn Sx1276Client>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    /// The packet being sent or received.
+    packet: TakeCell<'static, [u8]>,
+    packet_len: Cell<usize>,
+    /// The register writes of the operation, and how many are done.
+    writes: Cell<[(Registers, u8); MAX_WRITES]>,
+    writes_len: Cell<usize>,
+    writes_done: Cell<usize>,
+    /// In dBm.
+    tx_power: Cell<i8>,
+
+    // The radio HIL.
+    radio_config: Cell<LoraConfig>,
+    /// Whether the radio HIL was started.
+    powered: Cell<bool>,
+    address: Cell<u16>,
+    address_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    tx_client: OptionalCell<&'a dyn radio::TxClient>,
+    rx_client: OptionalCell<&'a dyn radio::RxClient>,
+    power_client: OptionalCell<&'a dyn radio::PowerClient>,
+    config_client: OptionalCell<&'a dyn radio::ConfigClient>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> Sx1276<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+    ) -> Sx1276<'a, S, A> {
+        Sx1276 {
+            spi,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Off),
+            operation: Cell::new(Operation::Transmit),
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+            packet: TakeCell::empty(),
+            packet_len: Cell::new(0),
+            writes: Cell::new([(Registers::OpMode, 0); MAX_WRITES]),
+            writes_len: Cell::new(0),
+            writes_done: Cell::new(0),
+            tx_power: Cell::new(MAX_TX_POWER),
+            radio_config: Cell::new(DEFAULT_RADIO_CONFIG),
+            powered: Cell::new(false),
+            address: Cell::new(0),
+            address_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Sx1276Client) {
+        self.client.set(client);
+    }
+
+    /// Set the settings of the packets of the radio HIL, from the next
+    /// operation.
+    pub fn set_radio_config(&self, config: LoraConfig) -> Result<(), ErrorCode> {
+        config.check()?;
+        self.radio_config.set(config);
+        Ok(())
+    }
+
+    /// Check the version of the transceiver, and set it up in LoRa mode.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Off {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.state.set(State::ReadVersion);
+        self.read(Registers::Version, 1).map_err(|e| {
+            self.state.set(State::Off);
+            e
+        })
+    }
+
+    /// Send the first `len` bytes of `buffer`.
+    pub fn transmit(
+        &self,
+        config: LoraConfig,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.start_transmit(Operation::Transmit, config, buffer, len)
+    }
+
+    /// Receive a packet into `buffer`, if its preamble starts within
+    /// `timeout_symbols` (at most 1023).
+    pub fn receive(
+        &self,
+        config: LoraConfig,
+        timeout_symbols: u16,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.start_receive(
+            Operation::Receive,
+            config,
+            Mode::RxSingle,
+            timeout_symbols.min(0x3FF),
+            buffer,
+        )
+    }
+
+    /// Receive a packet into `buffer`, if it is received within
+    /// `timeout_ms`, or else fail with `NOACK`.
+    pub fn receive_for(
+        &self,
+        config: LoraConfig,
+        timeout_ms: u32,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.start_receive(Operation::Receive, config, Mode::RxContinuous, 0, buffer)?;
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+        Ok(())
+    }
+
+    /// Check whether a LoRa preamble is being sent with `config`.
+    pub fn channel_activity_detection(&self, config: LoraConfig) -> Result<(), ErrorCode> {
+        config.check()?;
+        self.claim()?;
+        let (frf_msb, frf_mid, frf_lsb) = frf(config.frequency_hz);
+        self.operation.set(Operation::Cad);
+        self.write_registers(
+            &[
+                (Registers::OpMode, op_mode(Mode::Standby)),
+                (Registers::FrfMsb, frf_msb),
+                (Registers::FrfMid, frf_mid),
+                (Registers::FrfLsb, frf_lsb),
+                (Registers::ModemConfig1, modem_config_1(&config)),
+                (Registers::ModemConfig2, modem_config_2(&config, false, 0)),
+                (Registers::ModemConfig3, modem_config_3(&config)),
+                (Registers::DioMapping1, DIO_MAPPING_CAD),
+                (Registers::IrqFlags, 0xFF),
+                (Registers::OpMode, op_mode(Mode::Cad)),
+            ],
+            Then::Wait,
+        )
+        .map_err(|e| self.abandon(e))
+    }
+
+    /// Put the transceiver in `mode`, which must be `Sleep`, `Standby`,
+    /// `FsTx` or `FsRx`: the others are set by the operations.
+    pub fn set_mode(&self, mode: Mode) -> Result<(), ErrorCode> {
+        match mode {
+            Mode::Sleep | Mode::Standby | Mode::FsTx | Mode::FsRx => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+        self.claim()?;
+        self.operation.set(Operation::SetMode);
+        self.write_registers(&[(Registers::OpMode, op_mode(mode))], Then::ModeSet)
+            .map_err(|e| self.abandon(e))
+    }
+
+    /// Make way for a new operation. Listening for the radio HIL is stopped
+    /// for it, and the others make it fail with `BUSY`.
+    fn claim(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Idle => Ok(()),
+            State::Waiting if self.operation.get() == Operation::Listen => {
+                self.packet
+                    .take()
+                    .map(|buffer| self.rx_buffer.replace(buffer));
+                self.state.set(State::Idle);
+                Ok(())
+            }
+            State::Off => Err(ErrorCode::OFF),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// An operation could not be started: go back to listening if it was
+    /// stopped for it.
+    fn abandon(&self, e: ErrorCode) -> ErrorCode {
+        self.state.set(State::Idle);
+        self.resume();
+        e
+    }
+
+    fn start_transmit(
+        &self,
+        operation: Operation,
+        config: LoraConfig,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = config.check().and_then(|()| self.claim()) {
+            return Err((e, buffer));
+        }
+        if len > MAX_PACKET_SIZE || operation.offset() + len > buffer.len() {
+            return Err((self.abandon(ErrorCode::SIZE), buffer));
+        }
+        let (invert_iq, invert_iq_2) = INVERT_IQ_OFF;
+        let (frf_msb, frf_mid, frf_lsb) = frf(config.frequency_hz);
+        self.packet_len.set(len);
+        self.start(
+            operation,
+            buffer,
+            &[
+                (Registers::OpMode, op_mode(Mode::Standby)),
+                (Registers::FrfMsb, frf_msb),
+                (Registers::FrfMid, frf_mid),
+                (Registers::FrfLsb, frf_lsb),
+                (Registers::PaConfig, pa_config(self.tx_power.get())),
+                (Registers::ModemConfig1, modem_config_1(&config)),
+                (Registers::ModemConfig2, modem_config_2(&config, true, 0)),
+                (Registers::ModemConfig3, modem_config_3(&config)),
+                (Registers::InvertIq, invert_iq),
+                (Registers::InvertIq2, invert_iq_2),
+                (Registers::PayloadLength, len as u8),
+                (Registers::FifoAddrPtr, 0),
+                (Registers::DioMapping1, DIO_MAPPING_TX),
+                (Registers::IrqFlags, 0xFF),
+            ],
+            Then::WriteFifo,
+        )
+    }
+
+    fn start_receive(
+        &self,
+        operation: Operation,
+        config: LoraConfig,
+        mode: Mode,
+        timeout_symbols: u16,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = config.check().and_then(|()| self.claim()) {
+            return Err((e, buffer));
+        }
+        let (invert_iq, invert_iq_2) = if config.invert_iq {
+            INVERT_IQ_ON
+        } else {
+            INVERT_IQ_OFF
+        };
+        let (frf_msb, frf_mid, frf_lsb) = frf(config.frequency_hz);
+        self.start(
+            operation,
+            buffer,
+            &[
+                (Registers::OpMode, op_mode(Mode::Standby)),
+                (Registers::FrfMsb, frf_msb),
+                (Registers::FrfMid, frf_mid),
+                (Registers::FrfLsb, frf_lsb),
+                (Registers::ModemConfig1, modem_config_1(&config)),
+                (
+                    Registers::ModemConfig2,
+                    modem_config_2(&config, false, timeout_symbols),
+                ),
+                (Registers::SymbTimeoutLsb, timeout_symbols as u8),
+                (Registers::ModemConfig3, modem_config_3(&config)),
+                (Registers::InvertIq, invert_iq),
+                (Registers::InvertIq2, invert_iq_2),
+                (Registers::FifoAddrPtr, 0),
+                (Registers::DioMapping1, DIO_MAPPING_RX),
+                (Registers::IrqFlags, 0xFF),
+                (Registers::OpMode, op_mode(mode)),
+            ],
+            Then::Wait,
+        )
+    }
+
+    /// Start `operation` on `packet`, with `writes`.
+    fn start(
+        &self,
+        operation: Operation,
+        packet: &'static mut [u8],
+        writes: &[(Registers, u8)],
+        then: Then,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.operation.set(operation);
+        match self.write_registers(writes, then) {
+            Ok(()) => {
+                self.packet.replace(packet);
+                Ok(())
+            }
+            Err(e) => {
+                // Listening is not resumed with the buffer it would need.
+                if operation == Operation::Listen {
+                    self.state.set(State::Idle);
+                    return Err((e, packet));
+                }
+                Err((self.abandon(e), packet))
+            }
+        }
+    }
+
+    /// Listen for the radio HIL, if it is on, idle and has a buffer.
+    fn resume(&self) {
+        if !self.powered.get() || self.state.get() != State::Idle || self.rx_client.is_none() {
+            return;
+        }
+        if let Some(buffer) = self.rx_buffer.take() {
+            let config = self.radio_config.get();
+            if let Err((_, buffer)) =
+                self.start_receive(Operation::Listen, config, Mode::RxContinuous, 0, buffer)
+            {
+                self.rx_buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_registers(&self, writes: &[(Registers, u8)], then: Then) -> Result<(), ErrorCode> {
+        let mut all = [(Registers::OpMode, 0); MAX_WRITES];
+        all[..writes.len()].copy_from_slice(writes);
+        self.writes.set(all);
+        self.writes_len.set(writes.len());
+        self.writes_done.set(0);
+        self.state.set(State::Writing(then));
+        self.write_next()
+    }
+
+    fn write_next(&self) -> Result<(), ErrorCode> {
+        let (register, value) = self.writes.get()[self.writes_done.get()];
+        self.writes_done.set(self.writes_done.get() + 1);
+        let buffer = self.write_buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = register as u8 | WRITE;
+        buffer[1] = value;
+        self.spi
+            .read_write_bytes(buffer, None, 2)
+            .map_err(|(e, buffer, _)| {
+                self.write_buffer.replace(buffer);
+                e
+            })
+    }
+
+    /// Read `len` registers from `register`.
+    fn read(&self, register: Registers, len: usize) -> Result<(), ErrorCode> {
+        let buffer = self.write_buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = register as u8;
+        buffer[1..=len].fill(0);
+        self.spi
+            .read_write_bytes(buffer, self.read_buffer.take(), len + 1)
+            .map_err(|(e, buffer, read_buffer)| {
+                self.write_buffer.replace(buffer);
+                read_buffer.map(|read_buffer| self.read_buffer.replace(read_buffer));
+                e
+            })
+    }
+
+    /// Continue once the register writes are done.
+    fn then(&self, then: Then) {
+        let result = match then {
+            Then::Ready => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.init_done(Ok(())));
+                if self.powered.get() {
+                    self.power_client.map(|client| client.changed(true));
+                }
+                self.resume();
+                Ok(())
+            }
+            Then::WriteFifo => self.write_fifo(),
+            Then::Wait => {
+                self.state.set(State::Waiting);
+                Ok(())
+            }
+            Then::TransmitDone(result) => {
+                self.state.set(State::Idle);
+                self.packet.take().map(|packet| match self.operation.get() {
+                    Operation::RadioTransmit => self
+                        .tx_client
+                        .map(move |client| client.send_done(packet, false, result)),
+                    _ => self
+                        .client
+                        .map(move |client| client.transmit_done(packet, result)),
+                });
+                self.resume();
+                Ok(())
+            }
+            Then::ReadFifo(len) => {
+                self.state.set(State::ReadFifo);
+                self.read(Registers::Fifo, len)
+            }
+            Then::ReceiveDone(result) => {
+                self.receive_done(result);
+                Ok(())
+            }
+            Then::CadDone(result) => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.cad_done(result));
+                self.resume();
+                Ok(())
+            }
+            Then::ModeSet => {
+                self.state.set(State::Idle);
+                match self.operation.get() {
+                    Operation::Power => {
+                        let on = self.powered.get();
+                        self.power_client.map(|client| client.changed(on));
+                    }
+                    _ => {
+                        self.client.map(|client| client.mode_done(Ok(())));
+                    }
+                }
+                self.resume();
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+
+    fn write_fifo(&self) -> Result<(), ErrorCode> {
+        let buffer = self.write_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let len = self.packet_len.get();
+        let offset = self.operation.get().offset();
+        buffer[0] = Registers::Fifo as u8 | WRITE;
+        self.packet
+            .map(|packet| buffer[1..=len].copy_from_slice(&packet[offset..offset + len]));
+        self.state.set(State::WritingFifo);
+        self.spi
+            .read_write_bytes(buffer, None, len + 1)
+            .map_err(|(e, buffer, _)| {
+                self.write_buffer.replace(buffer);
+                e
+            })
+    }
+
+    fn receive_done(&self, result: Result<usize, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.packet.take().map(|packet| match self.operation.get() {
+            Operation::Listen => self.rx_client.map(move |client| match result {
+                Ok(len) => client.receive(packet, len, true, Ok(())),
+                Err(e) => client.receive(packet, 0, false, Err(e)),
+            }),
+            _ => self
+                .client
+                .map(move |client| client.receive_done(packet, result)),
+        });
+        self.resume();
+    }
+
+    /// End the operation in progress with `e`.
+    fn fail(&self, e: ErrorCode) {
+        match (self.state.get(), self.operation.get()) {
+            (State::ReadVersion, _) | (State::Writing(Then::Ready), _) => {
+                self.state.set(State::Off);
+                self.client.map(|client| client.init_done(Err(e)));
+            }
+            (_, Operation::Transmit) | (_, Operation::RadioTransmit) => {
+                self.then(Then::TransmitDone(Err(e)))
+            }
+            (_, Operation::Receive) | (_, Operation::Listen) => self.receive_done(Err(e)),
+            (_, Operation::Cad) => self.then(Then::CadDone(Err(e))),
+            (_, Operation::SetMode) | (_, Operation::Power) => {
+                self.state.set(State::Idle);
+                self.client.map(|client| client.mode_done(Err(e)));
+            }
+        }
+    }
+
+    /// The flags read at the end of the operation: what to write, and what
+    /// to do next.
+    fn flags_read(&self, current_addr: u8, flags: u8, received: usize) -> Result<(), ErrorCode> {
+        let stop = [
+            (Registers::IrqFlags, 0xFF),
+            (Registers::OpMode, op_mode(Mode::Sleep)),
+        ];
+        match self.operation.get() {
+            Operation::Transmit | Operation::RadioTransmit => {
+                let result = if flags & IRQ_TX_DONE != 0 {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                };
+                self.write_registers(&stop, Then::TransmitDone(result))
+            }
+            Operation::Cad => {
+                let result = if flags & IRQ_CAD_DONE != 0 {
+                    Ok(flags & IRQ_CAD_DETECTED != 0)
+                } else {
+                    Err(ErrorCode::FAIL)
+                };
+                self.write_registers(&stop, Then::CadDone(result))
+            }
+            Operation::Receive | Operation::Listen => {
+                let offset = self.operation.get().offset();
+                let error = if flags & IRQ_RX_DONE == 0 || flags & IRQ_RX_TIMEOUT != 0 {
+                    Some(ErrorCode::NOACK)
+                } else if flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+                    Some(ErrorCode::FAIL)
+                } else if received > MAX_PACKET_SIZE
+                    || offset + received > self.packet.map_or(0, |packet| packet.len())
+                {
+                    Some(ErrorCode::SIZE)
+                } else {
+                    None
+                };
+                match error {
+                    // Keep listening past frames that were not received
+                    // whole.
+                    Some(_) if self.operation.get() == Operation::Listen => {
+                        self.write_registers(&[(Registers::IrqFlags, 0xFF)], Then::Wait)
+                    }
+                    Some(e) => self.write_registers(&stop, Then::ReceiveDone(Err(e))),
+                    // The transceiver stays in reception in RXCONTINUOUS
+                    // mode, and the FIFO is cleared in sleep, so the packet
+                    // is read first.
+                    None => {
+                        self.alarm.disarm()?;
+                        self.write_registers(
+                            &[
+                                (Registers::IrqFlags, 0xFF),
+                                (Registers::OpMode, op_mode(Mode::Standby)),
+                                (Registers::FifoAddrPtr, current_addr),
+                            ],
+                            Then::ReadFifo(received),
+                        )
+                    }
+                }
+            }
+            Operation::SetMode | Operation::Power => Ok(()),
+        }
+    }
+}
+
+/// The frequency registers for `frequency_hz`.
+fn frf(frequency_hz: u32) -> (u8, u8, u8) {
+    let frf = ((frequency_hz as u64) << 19) / CRYSTAL_HZ;
+    ((frf >> 16) as u8, (frf >> 8) as u8, frf as u8)
+}
+
+/// RegPaConfig for `power` dBm on the PA_BOOST output, which gives
+/// 17 - (15 - OutputPower) dBm.
+fn pa_config(power: i8) -> u8 {
+    PA_BOOST | (power - MIN_TX_POWER) as u8
+}
+
+/// With an explicit header.
+fn modem_config_1(config: &LoraConfig) -> u8 {
+    config.bandwidth.field() << 4 | config.coding_rate.field() << 1
+}
+
+fn modem_config_2(config: &LoraConfig, crc: bool, timeout_symbols: u16) -> u8 {
+    let crc = if crc { RX_PAYLOAD_CRC_ON } else { 0 };
+    config.spreading_factor << 4 | crc | (timeout_symbols >> 8) as u8 & 0x03
+}
+
+fn modem_config_3(config: &LoraConfig) -> u8 {
+    // Required when symbols are longer than 16 ms.
+    if config.symbol_us() > 16_000 {
+        AGC_AUTO_ON | LOW_DATA_RATE_OPTIMIZE
+    } else {
+        AGC_AUTO_ON
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> SpiMasterClient for Sx1276<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.write_buffer.replace(write_buffer);
+        let mut read = [0; 4];
+        let mut packet_len = 0;
+        if let Some(read_buffer) = read_buffer {
+            read.copy_from_slice(&read_buffer[1..5]);
+            if self.state.get() == State::ReadFifo {
+                packet_len = len - 1;
+                let offset = self.operation.get().offset();
+                self.packet.map(|packet| {
+                    packet[offset..offset + packet_len].copy_from_slice(&read_buffer[1..len])
+                });
+            }
+            self.read_buffer.replace(read_buffer);
+        }
+        if let Err(e) = status {
+            self.fail(e);
+            return;
+        }
+
+        let result = match self.state.get() {
+            State::ReadVersion if read[0] == VERSION => self.write_registers(
+                &[
+                    // The mode can only be changed to LoRa in sleep.
+                    (Registers::OpMode, Mode::Sleep as u8),
+                    (Registers::OpMode, op_mode(Mode::Sleep)),
+                    (Registers::Lna, LNA_MAX_GAIN),
+                    (Registers::SyncWord, SYNC_WORD_LORAWAN),
+                    (Registers::FifoTxBaseAddr, 0),
+                    (Registers::FifoRxBaseAddr, 0),
+                ],
+                Then::Ready,
+            ),
+            State::ReadVersion => Err(ErrorCode::NODEVICE),
+            State::Writing(then) => {
+                if self.writes_done.get() < self.writes_len.get() {
+                    self.write_next()
+                } else {
+                    self.then(then);
+                    Ok(())
+                }
+            }
+            State::WritingFifo => {
+                self.write_registers(&[(Registers::OpMode, op_mode(Mode::Tx))], Then::Wait)
+            }
+            // RegFifoRxCurrentAddr, RegIrqFlagsMask, RegIrqFlags and
+            // RegRxNbBytes.
+            State::ReadFlags => self.flags_read(read[0], read[2], read[3] as usize),
+            State::ReadFifo => self.write_registers(
+                &[(Registers::OpMode, op_mode(Mode::Sleep))],
+                Then::ReceiveDone(Ok(packet_len)),
+            ),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> gpio::Client for Sx1276<'a, S, A> {
+    fn fired(&self) {
+        if self.state.get() == State::Waiting {
+            self.state.set(State::ReadFlags);
+            if let Err(e) = self.read(Registers::FifoRxCurrentAddr, 4) {
+                self.fail(e);
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> AlarmClient for Sx1276<'a, S, A> {
+    /// The timeout of `receive_for`.
+    fn alarm(&self) {
+        if self.state.get() == State::Waiting && self.operation.get() == Operation::Receive {
+            if let Err(e) = self.write_registers(
+                &[
+                    (Registers::IrqFlags, 0xFF),
+                    (Registers::OpMode, op_mode(Mode::Sleep)),
+                ],
+                Then::ReceiveDone(Err(ErrorCode::NOACK)),
+            ) {
+                self.fail(e);
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> radio::RadioConfig<'a> for Sx1276<'a, S, A> {
+    /// The buffers of the transceiver are given to `new`, so those given
+    /// here are not used.
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Off => self.init(),
+            _ => Ok(()),
+        }
+    }
+
+    /// The transceiver can only be reset with its reset pin.
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        self.powered.set(true);
+        if self.state.get() == State::Off {
+            return self.init();
+        }
+        self.claim()?;
+        self.operation.set(Operation::Power);
+        self.write_registers(
+            &[(Registers::OpMode, op_mode(Mode::Standby))],
+            Then::ModeSet,
+        )
+        .map_err(|e| self.abandon(e))
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        self.powered.set(false);
+        self.claim()?;
+        self.operation.set(Operation::Power);
+        self.write_registers(&[(Registers::OpMode, op_mode(Mode::Sleep))], Then::ModeSet)
+            .map_err(|e| self.abandon(e))
+    }
+
+    fn is_on(&self) -> bool {
+        self.powered.get() && self.state.get() != State::Off
+    }
+
+    fn busy(&self) -> bool {
+        match self.state.get() {
+            State::Idle => false,
+            State::Waiting => self.operation.get() != Operation::Listen,
+            _ => true,
+        }
+    }
+
+    fn set_power_client(&self, client: &'a dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    /// The settings are used from the next operation, so there is nothing
+    /// to commit.
+    fn config_commit(&self) {
+        self.config_client.map(|client| client.config_done(Ok(())));
+    }
+
+    fn set_config_client(&self, client: &'a dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.address_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        0
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.address_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    /// From 2 to 17 dBm, for all later transmissions.
+    fn set_tx_power(&self, power: i8) -> Result<(), ErrorCode> {
+        if !(MIN_TX_POWER..=MAX_TX_POWER).contains(&power) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.tx_power.set(power);
+        Ok(())
+    }
+
+    /// LoRa has no channels: the frequency is set with `set_radio_config`.
+    fn set_channel(&self, _chan: u8) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>, A: Alarm<'a>> radio::RadioData<'a> for Sx1276<'a, S, A> {
+    fn set_transmit_client(&self, client: &'a dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'a dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.rx_client.set(client);
+        self.set_receive_buffer(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buffer.replace(receive_buffer);
+        self.resume();
+    }
+
+    /// Send the `frame_len` bytes of `spi_buf` after `PSDU_OFFSET`. The
+    /// frame is never acknowledged.
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.start_transmit(
+            Operation::RadioTransmit,
+            self.radio_config.get(),
+            spi_buf,
+            frame_len,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockSpi};
+    use core::cell::RefCell;
+    use kernel::hil::radio::{RadioConfig, RadioData};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// The registers and FIFO of a transceiver, which answer the SPI
+    /// transfers completed by `run`.
+    struct Transceiver {
+        registers: RefCell<[u8; 0x80]>,
+        fifo: RefCell<[u8; 256]>,
+    }
+
+    impl Transceiver {
+        fn register(&self, register: Registers) -> u8 {
+            self.registers.borrow()[register as usize]
+        }
+
+        fn set_register(&self, register: Registers, value: u8) {
+            self.registers.borrow_mut()[register as usize] = value;
+        }
+
+        /// The address of the next byte of the FIFO, in RegFifoAddrPtr.
+        fn fifo_address(&self) -> usize {
+            let mut registers = self.registers.borrow_mut();
+            let address = registers[Registers::FifoAddrPtr as usize];
+            registers[Registers::FifoAddrPtr as usize] = address.wrapping_add(1);
+            address as usize
+        }
+
+        /// Execute the access written in `write`, answering in `read`.
+        fn transfer(&self, write: &[u8], read: &mut [u8]) {
+            let address = write[0] & !WRITE;
+            if write[0] & WRITE != 0 {
+                if address == Registers::Fifo as u8 {
+                    for &byte in &write[1..] {
+                        self.fifo.borrow_mut()[self.fifo_address()] = byte;
+                    }
+                } else if address == Registers::IrqFlags as u8 {
+                    self.registers.borrow_mut()[address as usize] &= !write[1];
+                } else {
+                    self.registers.borrow_mut()[address as usize] = write[1];
+                }
+            } else {
+                for i in 1..read.len() {
+                    read[i] = if address == Registers::Fifo as u8 {
+                        self.fifo.borrow()[self.fifo_address()]
+                    } else {
+                        self.registers.borrow()[address as usize + i - 1]
+                    };
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Client {
+        init: RefCell<Vec<Result<(), ErrorCode>>>,
+        transmitted: RefCell<Vec<Result<(), ErrorCode>>>,
+        received: RefCell<Vec<Result<usize, ErrorCode>>>,
+        cad: RefCell<Vec<Result<bool, ErrorCode>>>,
+        sent: RefCell<Vec<Result<(), ErrorCode>>>,
+        frames: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl Sx1276Client for Client {
+        fn init_done(&self, result: Result<(), ErrorCode>) {
+            self.init.borrow_mut().push(result);
+        }
+        fn transmit_done(&self, _buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.transmitted.borrow_mut().push(result);
+        }
+        fn receive_done(&self, _buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+            self.received.borrow_mut().push(result);
+        }
+        fn cad_done(&self, result: Result<bool, ErrorCode>) {
+            self.cad.borrow_mut().push(result);
+        }
+    }
+
+    impl radio::TxClient for Client {
+        fn send_done(&self, _buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+            assert!(!acked);
+            self.sent.borrow_mut().push(result);
+        }
+    }
+
+    impl radio::RxClient for Client {
+        fn receive(
+            &self,
+            buf: &'static mut [u8],
+            frame_len: usize,
+            crc_valid: bool,
+            result: Result<(), ErrorCode>,
+        ) {
+            assert!(crc_valid);
+            assert_eq!(result, Ok(()));
+            self.frames
+                .borrow_mut()
+                .push(buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len].to_vec());
+        }
+    }
+
+    const CONFIG: LoraConfig = DEFAULT_RADIO_CONFIG;
+
+    struct Test {
+        spi: &'static MockSpi<'static>,
+        chip: &'static Transceiver,
+        alarm: &'static MockAlarm<'static>,
+        radio: &'static Sx1276<'static, MockSpi<'static>, MockAlarm<'static>>,
+        client: &'static Client,
+    }
+
+    impl Test {
+        /// A transceiver that has been set up.
+        fn new() -> Test {
+            let spi: &'static MockSpi = Box::leak(Box::default());
+            let chip = Box::leak(Box::new(Transceiver {
+                registers: RefCell::new([0; 0x80]),
+                fifo: RefCell::new([0; 256]),
+            }));
+            chip.set_register(Registers::Version, VERSION);
+            let alarm: &'static MockAlarm = Box::leak(Box::default());
+            let client: &'static Client = Box::leak(Box::default());
+            let radio = Box::leak(Box::new(Sx1276::new(
+                spi,
+                alarm,
+                Box::leak(Box::new([0; BUFFER_SIZE])),
+                Box::leak(Box::new([0; BUFFER_SIZE])),
+            )));
+            spi.set_client(radio);
+            alarm.set_alarm_client(radio);
+            radio.set_client(client);
+            let test = Test {
+                spi,
+                chip,
+                alarm,
+                radio,
+                client,
+            };
+            assert_eq!(radio.init(), Ok(()));
+            test.run();
+            assert_eq!(*client.init.borrow(), [Ok(())]);
+            test
+        }
+
+        /// Complete transfers until the driver waits.
+        fn run(&self) {
+            while self.spi.busy() {
+                self.spi
+                    .complete_with(|write, read| self.chip.transfer(write, read));
+            }
+        }
+
+        /// Raise `flags`, and signal them on a DIO pin.
+        fn interrupt(&self, flags: u8) {
+            self.chip.set_register(Registers::IrqFlags, flags);
+            gpio::Client::fired(self.radio);
+            self.run();
+        }
+
+        fn mode(&self) -> u8 {
+            self.chip.register(Registers::OpMode)
+        }
+    }
+
+    #[test]
+    fn transmit_with_power() {
+        let test = Test::new();
+        assert_eq!(test.radio.set_tx_power(18), Err(ErrorCode::INVAL));
+        assert_eq!(test.radio.set_tx_power(10), Ok(()));
+        let buffer = Box::leak(Box::new([1, 2, 3, 0]));
+        assert!(test.radio.transmit(CONFIG, buffer, 3).is_ok());
+        test.run();
+        assert_eq!(test.chip.register(Registers::PaConfig), PA_BOOST | 8);
+        assert_eq!(test.chip.register(Registers::PayloadLength), 3);
+        assert_eq!(test.chip.fifo.borrow()[..3], [1, 2, 3]);
+        assert_eq!(test.mode(), op_mode(Mode::Tx));
+
+        test.interrupt(IRQ_TX_DONE);
+        assert_eq!(*test.client.transmitted.borrow(), [Ok(())]);
+        assert_eq!(test.mode(), op_mode(Mode::Sleep));
+        assert_eq!(test.chip.register(Registers::IrqFlags), 0);
+    }
+
+    #[test]
+    fn channel_activity_detected() {
+        let test = Test::new();
+        let bad = LoraConfig {
+            spreading_factor: 6,
+            ..CONFIG
+        };
+        assert_eq!(
+            test.radio.channel_activity_detection(bad),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(test.radio.channel_activity_detection(CONFIG), Ok(()));
+        test.run();
+        assert_eq!(test.chip.register(Registers::DioMapping1), DIO_MAPPING_CAD);
+        assert_eq!(test.mode(), op_mode(Mode::Cad));
+        assert_eq!(
+            test.radio.channel_activity_detection(CONFIG),
+            Err(ErrorCode::BUSY)
+        );
+
+        test.interrupt(IRQ_CAD_DONE | IRQ_CAD_DETECTED);
+        assert_eq!(*test.client.cad.borrow(), [Ok(true)]);
+        assert_eq!(test.mode(), op_mode(Mode::Sleep));
+    }
+
+    #[test]
+    fn receive_times_out() {
+        let test = Test::new();
+        let buffer = Box::leak(Box::new([0; MAX_PACKET_SIZE]));
+        assert!(test.radio.receive_for(CONFIG, 100, buffer).is_ok());
+        test.run();
+        assert!(test.alarm.is_armed());
+        assert_eq!(test.mode(), op_mode(Mode::RxContinuous));
+
+        test.alarm.fire();
+        test.run();
+        assert_eq!(*test.client.received.borrow(), [Err(ErrorCode::NOACK)]);
+        assert_eq!(test.mode(), op_mode(Mode::Sleep));
+    }
+
+    #[test]
+    fn radio_listens_between_transmissions() {
+        let test = Test::new();
+        test.radio.set_transmit_client(test.client);
+        test.radio
+            .set_receive_client(test.client, Box::leak(Box::new([0; 16])));
+        assert_eq!(test.mode(), op_mode(Mode::Sleep));
+        assert_eq!(RadioConfig::start(test.radio), Ok(()));
+        test.run();
+        assert!(test.radio.is_on());
+        assert_eq!(test.mode(), op_mode(Mode::RxContinuous));
+        assert!(!test.radio.busy());
+
+        // Sending stops listening, which resumes once it is done.
+        let frame = Box::leak(Box::new([0xFF, 0xFF, 9, 8]));
+        assert!(RadioData::transmit(test.radio, frame, 2).is_ok());
+        test.run();
+        assert!(test.radio.busy());
+        assert_eq!(test.chip.fifo.borrow()[..2], [9, 8]);
+        test.interrupt(IRQ_TX_DONE);
+        assert_eq!(*test.client.sent.borrow(), [Ok(())]);
+        assert_eq!(test.mode(), op_mode(Mode::RxContinuous));
+
+        // A frame with a wrong CRC is skipped.
+        test.interrupt(IRQ_RX_DONE | IRQ_PAYLOAD_CRC_ERROR);
+        assert!(test.client.frames.borrow().is_empty());
+        assert_eq!(test.mode(), op_mode(Mode::RxContinuous));
+
+        test.chip.fifo.borrow_mut()[0x20..0x23].copy_from_slice(&[5, 6, 7]);
+        test.chip.set_register(Registers::FifoRxCurrentAddr, 0x20);
+        test.chip.set_register(Registers::RxNbBytes, 3);
+        test.interrupt(IRQ_RX_DONE);
+        assert_eq!(*test.client.frames.borrow(), [[5, 6, 7]]);
+        assert_eq!(test.mode(), op_mode(Mode::Sleep));
+    }
+}
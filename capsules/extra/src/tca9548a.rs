@@ -0,0 +1,326 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TI TCA9548A 8-channel I2C multiplexer.
+//!
+//! <https://www.ti.com/lit/ds/symlink/tca9548a.pdf>
+//!
+//! The TCA9548A connects any of eight downstream I2C buses to the upstream
+//! bus, which lets a board use several devices with the same address. Each
+//! downstream device is a [MuxedI2cDevice], which implements
+//! `hil::i2c::I2CDevice` and so can be handed to any I2C driver.
+//!
+//! Before every transaction of a downstream device, the multiplexer writes
+//! the device's channel to its control register. Transactions of different
+//! downstream devices are run one at a time, so the channel can not change
+//! while one is in progress. Devices on the upstream bus itself are not
+//! affected, and can still be used in between.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! // Eight identical sensors at 0x44, one on each channel.
+//! let channels = components::tca9548a::Tca9548aComponent::new(mux_i2c, 0x70, 0x44)
+//!     .finalize(components::tca9548a_component_static!(
+//!         nrf52840::i2c::TWI
+//!     ));
+//! ```
+
+use core::cell::Cell;
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::i2c::{self, Error, I2CClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Address of the multiplexer with A0, A1 and A2 low.
+pub const BASE_ADDR: u8 = 0x70;
+/// Number of downstream channels.
+pub const NUM_CHANNELS: usize = 8;
+/// Size of the buffer for the control register.
+pub const BUFFER_SIZE: usize = 1;
+
+pub struct Tca9548a<'a, I: i2c::I2CDevice> {
+    /// The multiplexer's own address on the upstream bus.
+    control: &'a I,
+    buffer: TakeCell<'static, [u8]>,
+    devices: List<'a, MuxedI2cDevice<'a, I>>,
+    inflight: OptionalCell<&'a MuxedI2cDevice<'a, I>>,
+    enabled: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, I: i2c::I2CDevice> Tca9548a<'a, I> {
+    pub fn new(control: &'a I, buffer: &'static mut [u8]) -> Self {
+        Tca9548a {
+            control,
+            buffer: TakeCell::new(buffer),
+            devices: List::new(),
+            inflight: OptionalCell::empty(),
+            enabled: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn enable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled + 1);
+        if enabled == 0 {
+            self.control.enable();
+        }
+    }
+
+    fn disable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled - 1);
+        if enabled == 1 {
+            self.control.disable();
+        }
+    }
+
+    /// Select the channel of the next waiting device, if nothing is in
+    /// progress. The device's transaction starts once that is done.
+    fn do_next_op(&self) {
+        if self.inflight.is_some() {
+            return;
+        }
+        let node = self
+            .devices
+            .iter()
+            .find(|node| node.operation.get() != Op::Idle);
+        node.map(|node| {
+            if let Op::CommandComplete(status) = node.operation.get() {
+                node.finish(status);
+                self.do_next_op();
+                return;
+            }
+            self.buffer.take().map(|buffer| {
+                buffer[0] = 1 << node.channel;
+                self.inflight.set(node);
+                if let Err((e, buffer)) = self.control.write(buffer, 1) {
+                    self.buffer.replace(buffer);
+                    self.inflight.clear();
+                    node.operation.set(Op::CommandComplete(Err(e)));
+                    self.do_next_op_async();
+                }
+            });
+        });
+    }
+
+    /// Run `do_next_op` after the current call returns, so that errors are
+    /// not reported from within the call that caused them.
+    fn do_next_op_async(&self) {
+        self.deferred_call.set();
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> I2CClient for Tca9548a<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        self.buffer.replace(buffer);
+        self.inflight.map(|node| match status {
+            Ok(()) => node.start(),
+            Err(e) => {
+                self.inflight.clear();
+                node.finish(Err(e));
+                self.do_next_op();
+            }
+        });
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> DeferredCallClient for Tca9548a<'a, I> {
+    fn handle_deferred_call(&self) {
+        self.do_next_op();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    Idle,
+    Write(usize),
+    Read(usize),
+    WriteRead(usize, usize),
+    CommandComplete(Result<(), Error>),
+}
+
+/// A device on one of the multiplexer's downstream channels.
+pub struct MuxedI2cDevice<'a, I: i2c::I2CDevice> {
+    mux: &'a Tca9548a<'a, I>,
+    channel: u8,
+    /// The device's address on the upstream bus.
+    device: &'a I,
+    enabled: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Op>,
+    next: ListLink<'a, MuxedI2cDevice<'a, I>>,
+    client: OptionalCell<&'a dyn I2CClient>,
+}
+
+impl<'a, I: i2c::I2CDevice> MuxedI2cDevice<'a, I> {
+    /// `channel` must be below [NUM_CHANNELS]. `device` must have this
+    /// device as its client.
+    pub fn new(mux: &'a Tca9548a<'a, I>, channel: u8, device: &'a I) -> Self {
+        MuxedI2cDevice {
+            mux,
+            channel,
+            device,
+            enabled: Cell::new(false),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&'a self, client: &'a dyn I2CClient) {
+        self.mux.devices.push_head(self);
+        self.client.set(client);
+    }
+
+    /// Start the transaction once the channel is selected.
+    fn start(&self) {
+        self.buffer.take().map(|buffer| {
+            let result = match self.operation.get() {
+                Op::Write(len) => self.device.write(buffer, len),
+                Op::Read(len) => self.device.read(buffer, len),
+                Op::WriteRead(write_len, read_len) => {
+                    self.device.write_read(buffer, write_len, read_len)
+                }
+                Op::Idle | Op::CommandComplete(_) => unreachable!(),
+            };
+            if let Err((e, buffer)) = result {
+                self.buffer.replace(buffer);
+                self.mux.inflight.clear();
+                self.operation.set(Op::CommandComplete(Err(e)));
+                self.mux.do_next_op_async();
+            }
+        });
+    }
+
+    fn finish(&self, status: Result<(), Error>) {
+        self.operation.set(Op::Idle);
+        self.buffer.take().map(|buffer| {
+            self.client
+                .map(move |client| client.command_complete(buffer, status));
+        });
+    }
+
+    fn queue(&self, buffer: &'static mut [u8], op: Op) -> Result<(), (Error, &'static mut [u8])> {
+        if self.operation.get() != Op::Idle {
+            return Err((Error::ArbitrationLost, buffer));
+        }
+        self.buffer.replace(buffer);
+        self.operation.set(op);
+        self.mux.do_next_op();
+        Ok(())
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> I2CClient for MuxedI2cDevice<'a, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+        self.mux.inflight.clear();
+        self.buffer.replace(buffer);
+        self.finish(status);
+        self.mux.do_next_op();
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> ListNode<'a, MuxedI2cDevice<'a, I>> for MuxedI2cDevice<'a, I> {
+    fn next(&'a self) -> &'a ListLink<'a, MuxedI2cDevice<'a, I>> {
+        &self.next
+    }
+}
+
+impl<'a, I: i2c::I2CDevice> i2c::I2CDevice for MuxedI2cDevice<'a, I> {
+    fn enable(&self) {
+        if !self.enabled.get() {
+            self.enabled.set(true);
+            self.mux.enable();
+            self.device.enable();
+        }
+    }
+
+    fn disable(&self) {
+        if self.enabled.get() {
+            self.enabled.set(false);
+            self.device.disable();
+            self.mux.disable();
+        }
+    }
+
+    fn write_read(
+        &self,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.queue(data, Op::WriteRead(write_len, read_len))
+    }
+
+    fn write(&self, data: &'static mut [u8], len: usize) -> Result<(), (Error, &'static mut [u8])> {
+        self.queue(data, Op::Write(len))
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.queue(buffer, Op::Read(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockI2c;
+    use std::boxed::Box;
+
+    struct Client {
+        completed: Cell<usize>,
+    }
+
+    impl I2CClient for Client {
+        fn command_complete(&self, _buffer: &'static mut [u8], status: Result<(), Error>) {
+            assert_eq!(status, Ok(()));
+            self.completed.set(self.completed.get() + 1);
+        }
+    }
+
+    #[test]
+    fn transactions_are_serialized_behind_channel_selects() {
+        let control: &'static MockI2c = Box::leak(Box::default());
+        let sensor: [&'static MockI2c; 2] = [Box::leak(Box::default()), Box::leak(Box::default())];
+        let mux = Box::leak(Box::new(Tca9548a::new(
+            control,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        let client = Box::leak(Box::new(Client {
+            completed: Cell::new(0),
+        }));
+        let channel2 = Box::leak(Box::new(MuxedI2cDevice::new(mux, 2, sensor[0])));
+        let channel5 = Box::leak(Box::new(MuxedI2cDevice::new(mux, 5, sensor[1])));
+        channel2.set_client(client);
+        channel5.set_client(client);
+
+        use i2c::I2CDevice;
+        assert!(channel2.write(Box::leak(Box::new([0xAA])), 1).is_ok());
+        assert!(channel5.write(Box::leak(Box::new([0xBB])), 1).is_ok());
+        // Only the first channel is selected until its write is done.
+        assert!(!sensor[0].busy() && !sensor[1].busy());
+        assert_eq!(control.complete(mux, &[]), [0x04]);
+        assert!(!control.busy());
+        assert_eq!(sensor[0].complete(channel2, &[]), [0xAA]);
+        assert_eq!(control.complete(mux, &[]), [0x20]);
+        assert_eq!(sensor[1].complete(channel5, &[]), [0xBB]);
+
+        assert_eq!(client.completed.get(), 2);
+    }
+}
@@ -0,0 +1,472 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TCS34725 RGBC color sensor.
+//!
+//! <https://ams.com/documents/20143/36005/TCS3472_DS000390_3-00.pdf>
+//!
+//! Each reading powers the sensor up, runs one RGBC integration cycle and
+//! powers it down again. The end of the cycle is signalled on the INT pin,
+//! which the sensor asserts after every cycle once the AIEN bit is set.
+//!
+//! The driver adjusts the gain automatically: after a measurement in which
+//! any channel reached full scale the gain is lowered, and after one in which
+//! every channel stayed below 10% of full scale it is raised. The new gain is
+//! used from the next measurement, and every reading reports the gain it was
+//! measured with.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcs34725 = components::tcs34725::Tcs34725Component::new(
+//!     sensors_i2c_bus,
+//!     capsules_extra::tcs34725::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[TCS34725_INT_PIN],
+//!     mux_alarm,
+//!     capsules_extra::tcs34725::DEFAULT_ATIME,
+//! )
+//! .finalize(components::tcs34725_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::gpio::GPIOPin
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::sensors::{Color, ColorClient, ColorReading};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const BASE_ADDR: u8 = 0x29;
+
+/// Integration time used unless the board configures another one: 10
+/// cycles of 2.4 ms.
+pub const DEFAULT_ATIME: u8 = 0xF6;
+
+pub const BUFFER_SIZE: usize = 8;
+
+const COMMAND: u8 = 0x80;
+const AUTO_INCREMENT: u8 = 0x20;
+/// Special function command that clears the RGBC interrupt.
+const CLEAR_INTERRUPT: u8 = 0xE6;
+
+#[allow(dead_code)]
+enum Registers {
+    Enable = 0x00,
+    Atime = 0x01,
+    Pers = 0x0C,
+    Control = 0x0F,
+    Id = 0x12,
+    Status = 0x13,
+    Cdatal = 0x14,
+}
+
+const ENABLE_PON: u8 = 0x01;
+const ENABLE_AEN: u8 = 0x02;
+const ENABLE_AIEN: u8 = 0x10;
+
+/// Time for the oscillator to start after setting PON.
+const POWER_ON_DELAY_MS: u32 = 3;
+/// Length of one integration cycle, in microseconds.
+const CYCLE_US: u32 = 2400;
+/// Extra time to wait for the INT pin after the integration time.
+const INTERRUPT_MARGIN_MS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gain {
+    X1 = 0,
+    X4 = 1,
+    X16 = 2,
+    X60 = 3,
+}
+
+impl Gain {
+    pub fn multiplier(&self) -> u16 {
+        match self {
+            Gain::X1 => 1,
+            Gain::X4 => 4,
+            Gain::X16 => 16,
+            Gain::X60 => 60,
+        }
+    }
+
+    fn lower(&self) -> Gain {
+        match self {
+            Gain::X1 | Gain::X4 => Gain::X1,
+            Gain::X16 => Gain::X4,
+            Gain::X60 => Gain::X16,
+        }
+    }
+
+    fn higher(&self) -> Gain {
+        match self {
+            Gain::X1 => Gain::X4,
+            Gain::X4 => Gain::X16,
+            Gain::X16 | Gain::X60 => Gain::X60,
+        }
+    }
+}
+
+/// Largest count a channel can reach with `atime`: 1024 per integration
+/// cycle, up to 16 bits.
+fn full_scale(atime: u8) -> u16 {
+    let cycles = 256 - atime as u32;
+    (cycles * 1024).min(u16::MAX as u32) as u16
+}
+
+/// Returns the gain for the measurement after `reading`.
+fn next_gain(gain: Gain, reading: &ColorReading, full_scale: u16) -> Gain {
+    let channels = [reading.red, reading.green, reading.blue, reading.clear];
+    if channels.iter().any(|&c| c >= full_scale) {
+        gain.lower()
+    } else if channels
+        .iter()
+        .all(|&c| (c as u32) * 10 < full_scale as u32)
+    {
+        gain.higher()
+    } else {
+        gain
+    }
+}
+
+/// Parses the CDATAL..BDATAH registers.
+fn parse_channels(data: &[u8], gain: Gain) -> ColorReading {
+    let channel = |i: usize| u16::from_le_bytes([data[2 * i], data[2 * i + 1]]);
+    ColorReading {
+        clear: channel(0),
+        red: channel(1),
+        green: channel(2),
+        blue: channel(3),
+        gain: gain.multiplier(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    SetAtime,
+    SetPers,
+    SetGain,
+    PowerOn,
+    PowerOnWait,
+    Enable,
+    Measuring,
+    ReadData,
+    ClearInterrupt,
+    PowerOff,
+}
+
+pub struct Tcs34725<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> {
+    i2c: &'a I,
+    alarm: &'a A,
+    interrupt_pin: &'a P,
+    client: OptionalCell<&'a dyn ColorClient>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    atime: u8,
+    gain: Cell<Gain>,
+    configured: Cell<bool>,
+    result: Cell<Result<ColorReading, ErrorCode>>,
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> Tcs34725<'a, A, I, P> {
+    pub fn new(
+        i2c: &'a I,
+        interrupt_pin: &'a P,
+        buffer: &'static mut [u8],
+        alarm: &'a A,
+        atime: u8,
+    ) -> Tcs34725<'a, A, I, P> {
+        // The INT pin is open drain and active low.
+        interrupt_pin.make_input();
+        interrupt_pin.set_floating_state(gpio::FloatingState::PullUp);
+
+        Tcs34725 {
+            i2c,
+            alarm,
+            interrupt_pin,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            atime,
+            gain: Cell::new(Gain::X1),
+            configured: Cell::new(false),
+            result: Cell::new(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    fn write(&self, state: State, data: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[..data.len()].copy_from_slice(data);
+            match self.i2c.write(buffer, data.len()) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn read_channels(&self) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = COMMAND | AUTO_INCREMENT | Registers::Cdatal as u8;
+            match self.i2c.write_read(buffer, 1, BUFFER_SIZE) {
+                Ok(()) => {
+                    self.state.set(State::ReadData);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn set_gain(&self) -> Result<(), ErrorCode> {
+        self.write(
+            State::SetGain,
+            &[COMMAND | Registers::Control as u8, self.gain.get() as u8],
+        )
+    }
+
+    /// Powers the sensor down, and then reports `result`.
+    fn power_off(&self, result: Result<ColorReading, ErrorCode>) {
+        self.result.set(result);
+        if self
+            .write(State::PowerOff, &[COMMAND | Registers::Enable as u8, 0])
+            .is_err()
+        {
+            self.finish();
+        }
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        let result = self.result.get();
+        if let Ok(reading) = result {
+            self.gain
+                .set(next_gain(self.gain.get(), &reading, full_scale(self.atime)));
+        }
+        self.client.map(|client| client.callback(result));
+    }
+
+    fn integration_time_ms(&self) -> u32 {
+        (256 - self.atime as u32) * CYCLE_US / 1000 + 1
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> Color<'a>
+    for Tcs34725<'a, A, I, P>
+{
+    fn set_client(&self, client: &'a dyn ColorClient) {
+        self.client.set(client);
+    }
+
+    fn read_color(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.i2c.enable();
+        let result = if self.configured.get() {
+            self.set_gain()
+        } else {
+            self.write(
+                State::SetAtime,
+                &[COMMAND | Registers::Atime as u8, self.atime],
+            )
+        };
+        if result.is_err() {
+            self.i2c.disable();
+        }
+        result
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> i2c::I2CClient
+    for Tcs34725<'a, A, I, P>
+{
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let reading = parse_channels(buffer, self.gain.get());
+        self.buffer.replace(buffer);
+
+        if let Err(e) = status {
+            if state == State::PowerOff {
+                self.finish();
+            } else {
+                self.power_off(Err(e.into()));
+            }
+            return;
+        }
+
+        let result = match state {
+            State::SetAtime => {
+                // Generate an interrupt after every integration cycle.
+                self.write(State::SetPers, &[COMMAND | Registers::Pers as u8, 0])
+            }
+            State::SetPers => {
+                self.configured.set(true);
+                self.set_gain()
+            }
+            State::SetGain => self.write(
+                State::PowerOn,
+                &[COMMAND | Registers::Enable as u8, ENABLE_PON],
+            ),
+            State::PowerOn => {
+                self.state.set(State::PowerOnWait);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(POWER_ON_DELAY_MS),
+                );
+                Ok(())
+            }
+            State::Enable => {
+                self.state.set(State::Measuring);
+                self.interrupt_pin
+                    .enable_interrupts(gpio::InterruptEdge::FallingEdge);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm
+                        .ticks_from_ms(self.integration_time_ms() + INTERRUPT_MARGIN_MS),
+                );
+                Ok(())
+            }
+            State::ReadData => {
+                self.result.set(Ok(reading));
+                self.write(State::ClearInterrupt, &[CLEAR_INTERRUPT])
+            }
+            State::ClearInterrupt => {
+                self.power_off(self.result.get());
+                Ok(())
+            }
+            State::PowerOff => {
+                self.finish();
+                Ok(())
+            }
+            State::Idle | State::PowerOnWait | State::Measuring => Ok(()),
+        };
+        if let Err(e) = result {
+            self.power_off(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> gpio::Client
+    for Tcs34725<'a, A, I, P>
+{
+    fn fired(&self) {
+        if self.state.get() != State::Measuring {
+            return;
+        }
+        self.interrupt_pin.disable_interrupts();
+        let _ = self.alarm.disarm();
+        if let Err(e) = self.read_channels() {
+            self.power_off(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice, P: gpio::InterruptPin<'a>> time::AlarmClient
+    for Tcs34725<'a, A, I, P>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::PowerOnWait => {
+                let enable = ENABLE_PON | ENABLE_AEN | ENABLE_AIEN;
+                if let Err(e) =
+                    self.write(State::Enable, &[COMMAND | Registers::Enable as u8, enable])
+                {
+                    self.power_off(Err(e));
+                }
+            }
+            State::Measuring => {
+                // The INT pin never asserted.
+                self.interrupt_pin.disable_interrupts();
+                self.power_off(Err(ErrorCode::FAIL));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(red: u16, green: u16, blue: u16, clear: u16) -> ColorReading {
+        ColorReading {
+            red,
+            green,
+            blue,
+            clear,
+            gain: 1,
+        }
+    }
+
+    #[test]
+    fn channel_order() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let reading = parse_channels(&data, Gain::X16);
+        assert_eq!(reading.clear, 0x0201);
+        assert_eq!(reading.red, 0x0403);
+        assert_eq!(reading.green, 0x0605);
+        assert_eq!(reading.blue, 0x0807);
+        assert_eq!(reading.gain, 16);
+    }
+
+    #[test]
+    fn full_scale_depends_on_integration_time() {
+        assert_eq!(full_scale(DEFAULT_ATIME), 10240);
+        assert_eq!(full_scale(0xC0), 0xFFFF);
+        assert_eq!(full_scale(0x00), 0xFFFF);
+    }
+
+    #[test]
+    fn gain_control() {
+        let full = 0xFFFF;
+
+        // A saturated channel lowers the gain.
+        assert_eq!(
+            next_gain(Gain::X60, &reading(100, 0xFFFF, 100, 0xFFFF), full),
+            Gain::X16
+        );
+        assert_eq!(
+            next_gain(Gain::X1, &reading(0, 0, 0, 0xFFFF), full),
+            Gain::X1
+        );
+
+        // Every channel below 10% raises it.
+        assert_eq!(
+            next_gain(Gain::X4, &reading(10, 20, 30, 6000), full),
+            Gain::X16
+        );
+        assert_eq!(
+            next_gain(Gain::X60, &reading(10, 20, 30, 40), full),
+            Gain::X60
+        );
+
+        // Otherwise it stays.
+        assert_eq!(
+            next_gain(Gain::X4, &reading(10, 20, 30, 7000), full),
+            Gain::X4
+        );
+
+        // Full scale follows the integration time.
+        let full = full_scale(DEFAULT_ATIME);
+        assert_eq!(
+            next_gain(Gain::X4, &reading(10, 20, 30, 10240), full),
+            Gain::X1
+        );
+    }
+}
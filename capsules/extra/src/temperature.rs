@@ -27,6 +27,8 @@
 //!
 //! * `0`: check whether the driver exist
 //! * `1`: read the temperature
+//! * `2`: get the resistance measured by the last reading, in ohms, for
+//!   resistive sensors such as thermistors
 //!
 //!
 //! The possible return from the 'command' system call indicates the following:
@@ -142,6 +144,12 @@ impl SyscallDriver for TemperatureSensor<'_> {
 
             // read temperature
             1 => self.enqueue_command(processid),
+
+            // resistance of the last reading, without an upcall
+            2 => match self.driver.resistance_ohms() {
+                Ok(ohms) => CommandReturn::success_u32(ohms),
+                Err(error) => CommandReturn::failure(error),
+            },
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,208 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Deterministic random number generator, for reproducible tests.
+//!
+//! **This is not a source of randomness. Never use it in a production
+//! build:** every value it returns follows from the seed, so anything
+//! derived from it (keys, nonces, tokens) is known to anyone who knows
+//! the seed.
+//!
+//! The generator is the 32-bit xorshift of Marsaglia, with shifts 13, 17
+//! and 5, starting from the seed (a zero seed, which xorshift cannot
+//! leave, is replaced with [DEFAULT_SEED]). The values are returned in the
+//! same order however they are split between calls to `get()`. With
+//! [DEFAULT_SEED] the sequence starts with:
+//!
+//! ```text
+//! 0x00042021, 0x04080601, 0x9DCCA8C5, 0x1255994F,
+//! 0x8EF917D1, 0x2C6F5BD0, 0x25B2331A, 0x19F91CB2, ...
+//! ```
+//!
+//! As with a hardware RNG, the values are passed to the client from a
+//! deferred call, at most [BATCH_SIZE] at a time, and more are passed for
+//! as long as the client returns `Continue::More`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let test_rng = static_init!(
+//!     capsules_extra::test_rng::TestRng<'static>,
+//!     capsules_extra::test_rng::TestRng::new(capsules_extra::test_rng::DEFAULT_SEED)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(test_rng);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::rng::{Client, Continue, Rng};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Seed of the documented sequence.
+pub const DEFAULT_SEED: u32 = 1;
+
+/// The most values passed to the client by one callback.
+pub const BATCH_SIZE: usize = 8;
+
+pub struct TestRng<'a> {
+    state: Cell<u32>,
+    requested: Cell<bool>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'a dyn Client>,
+}
+
+impl<'a> TestRng<'a> {
+    pub fn new(seed: u32) -> TestRng<'a> {
+        TestRng {
+            state: Cell::new(if seed == 0 { DEFAULT_SEED } else { seed }),
+            requested: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn next(&self) -> u32 {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state.set(x);
+        x
+    }
+}
+
+struct TestRngIter<'a, 'b> {
+    rng: &'a TestRng<'b>,
+    remaining: usize,
+}
+
+impl Iterator for TestRngIter<'_, '_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            None
+        } else {
+            self.remaining -= 1;
+            Some(self.rng.next())
+        }
+    }
+}
+
+impl<'a> Rng<'a> for TestRng<'a> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.requested.set(true);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+}
+
+impl DeferredCallClient for TestRng<'_> {
+    fn handle_deferred_call(&self) {
+        if !self.requested.replace(false) {
+            return;
+        }
+        let more = self.client.map_or(Continue::Done, |client| {
+            client.randomness_available(
+                &mut TestRngIter {
+                    rng: self,
+                    remaining: BATCH_SIZE,
+                },
+                Ok(()),
+            )
+        });
+        if let Continue::More = more {
+            self.requested.set(true);
+            self.deferred_call.set();
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Collects `wanted` values, then stops asking for more.
+    struct MockClient {
+        wanted: usize,
+        values: RefCell<Vec<u32>>,
+    }
+
+    impl Client for MockClient {
+        fn randomness_available(
+            &self,
+            randomness: &mut dyn Iterator<Item = u32>,
+            error: Result<(), ErrorCode>,
+        ) -> Continue {
+            assert_eq!(error, Ok(()));
+            let mut values = self.values.borrow_mut();
+            while values.len() < self.wanted {
+                match randomness.next() {
+                    Some(value) => values.push(value),
+                    None => return Continue::More,
+                }
+            }
+            Continue::Done
+        }
+    }
+
+    /// Read `wanted` values from a new generator seeded with `seed`.
+    fn sequence(seed: u32, wanted: usize) -> Vec<u32> {
+        let rng: &'static TestRng = Box::leak(Box::new(TestRng::new(seed)));
+        let client = Box::leak(Box::new(MockClient {
+            wanted,
+            values: RefCell::new(Vec::new()),
+        }));
+        rng.set_client(client);
+        assert_eq!(rng.get(), Ok(()));
+        // Run the deferred calls until the client is done.
+        while rng.requested.get() {
+            rng.handle_deferred_call();
+        }
+        client.values.take()
+    }
+
+    #[test]
+    fn default_seed_matches_documented_sequence() {
+        assert_eq!(
+            sequence(DEFAULT_SEED, 8),
+            [
+                0x00042021, 0x04080601, 0x9DCCA8C5, 0x1255994F, 0x8EF917D1, 0x2C6F5BD0, 0x25B2331A,
+                0x19F91CB2,
+            ]
+        );
+        assert_eq!(sequence(0, 8), sequence(DEFAULT_SEED, 8));
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        // More than one batch, so the client asks for more.
+        let first = sequence(0x1234_5678, 3 * BATCH_SIZE + 1);
+        assert_eq!(first, sequence(0x1234_5678, 3 * BATCH_SIZE + 1));
+        assert_ne!(first, sequence(0x1234_5679, 3 * BATCH_SIZE + 1));
+        // A shorter read is a prefix of the same sequence.
+        assert_eq!(sequence(0x1234_5678, 5)[..], first[..5]);
+    }
+}
@@ -0,0 +1,187 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Tags sensor readings with the time they were delivered.
+//!
+//! The sensor HILs only deliver the value of a reading. A
+//! [`TimestampedSensor`] is the client of a sensor, and delivers each
+//! reading to its own client along with the time of a `hil::time::Time`
+//! source, taken when the sensor delivered the reading.
+//!
+//! A reading is started with `read`, or through the sensor directly. The
+//! wrapper exists for the temperature, humidity and ambient light HILs.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timestamped = components::timestamped_sensor::TimestampedTemperatureComponent::new(
+//!     temperature_sensor,
+//!     rv_timer,
+//! )
+//! .finalize(components::timestamped_temperature_component_static!(
+//!     earlgrey::timer::RvTimer<'static>
+//! ));
+//! timestamped.set_client(logger);
+//! timestamped.read();
+//! ```
+
+use kernel::hil::sensors::{
+    AmbientLight, AmbientLightClient, HumidityClient, HumidityDriver, TemperatureClient,
+    TemperatureDriver,
+};
+use kernel::hil::time::Time;
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Client of a [`TimestampedSensor`].
+pub trait TimestampedClient<V, T> {
+    /// Called with a `value` of the sensor, and the `timestamp` at which the
+    /// sensor delivered it.
+    fn reading_available(&self, value: V, timestamp: T);
+}
+
+/// A wrapper around the sensor `S`, which delivers values of type `V`.
+pub struct TimestampedSensor<'a, T: Time, S: ?Sized, V> {
+    sensor: &'a S,
+    time: &'a T,
+    client: OptionalCell<&'a dyn TimestampedClient<V, T::Ticks>>,
+}
+
+/// Temperatures in hundredths of a degree Celsius.
+pub type TimestampedTemperature<'a, T> =
+    TimestampedSensor<'a, T, dyn TemperatureDriver<'a>, Result<i32, ErrorCode>>;
+
+/// Relative humidities in hundredths of a percent.
+pub type TimestampedHumidity<'a, T> = TimestampedSensor<'a, T, dyn HumidityDriver<'a>, usize>;
+
+/// Light intensities in lux.
+pub type TimestampedAmbientLight<'a, T> = TimestampedSensor<'a, T, dyn AmbientLight<'a>, usize>;
+
+impl<'a, T: Time, S: ?Sized, V> TimestampedSensor<'a, T, S, V> {
+    pub fn new(sensor: &'a S, time: &'a T) -> TimestampedSensor<'a, T, S, V> {
+        TimestampedSensor {
+            sensor,
+            time,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TimestampedClient<V, T::Ticks>) {
+        self.client.set(client);
+    }
+
+    fn deliver(&self, value: V) {
+        let timestamp = self.time.now();
+        self.client
+            .map(|client| client.reading_available(value, timestamp));
+    }
+}
+
+impl<'a, T: Time> TimestampedTemperature<'a, T> {
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        self.sensor.read_temperature()
+    }
+}
+
+impl<'a, T: Time> TemperatureClient for TimestampedTemperature<'a, T> {
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        self.deliver(value);
+    }
+}
+
+impl<'a, T: Time> TimestampedHumidity<'a, T> {
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        self.sensor.read_humidity()
+    }
+}
+
+impl<'a, T: Time> HumidityClient for TimestampedHumidity<'a, T> {
+    fn callback(&self, value: usize) {
+        self.deliver(value);
+    }
+}
+
+impl<'a, T: Time> TimestampedAmbientLight<'a, T> {
+    pub fn read(&self) -> Result<(), ErrorCode> {
+        self.sensor.read_light_intensity()
+    }
+}
+
+impl<'a, T: Time> AmbientLightClient for TimestampedAmbientLight<'a, T> {
+    fn callback(&self, lux: usize) {
+        self.deliver(lux);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::Cell;
+    use kernel::hil::time::{Freq1MHz, Ticks32};
+    use std::boxed::Box;
+
+    struct MockTime {
+        now: Cell<u32>,
+    }
+
+    impl Time for MockTime {
+        type Ticks = Ticks32;
+        type Frequency = Freq1MHz;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get().into()
+        }
+    }
+
+    #[derive(Default)]
+    struct MockSensor {
+        reads: Cell<usize>,
+    }
+
+    impl<'a> TemperatureDriver<'a> for MockSensor {
+        fn set_client(&self, _client: &'a dyn TemperatureClient) {}
+        fn read_temperature(&self) -> Result<(), ErrorCode> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        reading: Cell<Option<(Result<i32, ErrorCode>, Ticks32)>>,
+    }
+
+    impl TimestampedClient<Result<i32, ErrorCode>, Ticks32> for MockClient {
+        fn reading_available(&self, value: Result<i32, ErrorCode>, timestamp: Ticks32) {
+            self.reading.set(Some((value, timestamp)));
+        }
+    }
+
+    #[test]
+    fn readings_have_the_delivery_time() {
+        let time = Box::leak(Box::new(MockTime { now: Cell::new(5) }));
+        let sensor = Box::leak(Box::new(MockSensor::default()));
+        let client = Box::leak(Box::new(MockClient::default()));
+        let timestamped: &TimestampedTemperature<MockTime> = Box::leak(Box::new(
+            TimestampedSensor::new(sensor as &dyn TemperatureDriver, time),
+        ));
+        timestamped.set_client(client);
+
+        assert_eq!(timestamped.read(), Ok(()));
+        assert_eq!(sensor.reads.get(), 1);
+        time.now.set(1234);
+        timestamped.callback(Ok(2150));
+        assert_eq!(client.reading.get(), Some((Ok(2150), 1234.into())));
+
+        time.now.set(u32::MAX);
+        timestamped.callback(Err(ErrorCode::FAIL));
+        assert_eq!(
+            client.reading.get(),
+            Some((Err(ErrorCode::FAIL), u32::MAX.into()))
+        );
+    }
+}
@@ -0,0 +1,571 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the TPS65987D USB Type-C and Power Delivery controller.
+//!
+//! <https://www.ti.com/lit/ds/symlink/tps65987d.pdf>
+//!
+//! The controller negotiates the power contract on its own, from the
+//! configuration in its flash. The host reads and writes its registers over
+//! I2C: a write sends the register address, the number of bytes and the
+//! bytes, and a read returns the number of bytes before them. Registers are
+//! little-endian.
+//!
+//! Tasks are started by writing a four character code (4CC), such as
+//! `SWSk`, to the CMD1 register, with their input in DATA1. The controller
+//! clears CMD1 once the task is done, and puts the result in the first byte
+//! of DATA1, or replaces the code with `!CMD` if it does not know it. The
+//! driver polls CMD1 every 10 ms.
+//!
+//! - Reading the contract reads the Power Status register, and if a PD
+//!   contract is in place, the Active Contract PDO register. Without one,
+//!   the contract is 5 V at the Type-C current of the source.
+//! - Requesting power writes the Transmit Sink Capabilities register with
+//!   the vSafe5V PDO and a fixed supply PDO of the voltage requested, and
+//!   runs `ANeg` to renegotiate.
+//! - Becoming the sink runs `SWSk`, a power role swap.
+//!
+//! The INT pin is driven low while an unmasked event is set in INT_EVENT1.
+//! The driver unmasks plug and new contract events, and reads and clears
+//! them when the pin falls.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tps65987d = components::tps65987d::Tps65987dComponent::new(
+//!     i2c_mux,
+//!     capsules_extra::tps65987d::BASE_ADDR,
+//!     &nrf52840_peripherals.gpio_port[PD_INT_PIN],
+//!     mux_alarm,
+//! )
+//! .finalize(components::tps65987d_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::hil::usb_pd::{PowerContract, UsbPdClient, UsbPdController};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub const BASE_ADDR: u8 = 0x20;
+
+/// Large enough to write INT_CLEAR1.
+pub const BUFFER_SIZE: usize = 2 + INT_EVENT_LEN;
+
+#[allow(dead_code)]
+enum Registers {
+    Cmd1 = 0x08,
+    Data1 = 0x09,
+    IntEvent1 = 0x14,
+    IntMask1 = 0x16,
+    IntClear1 = 0x18,
+    TxSinkCapabilities = 0x33,
+    ActiveContractPdo = 0x34,
+    PowerStatus = 0x3F,
+}
+
+/// Length of INT_EVENT1, INT_MASK1 and INT_CLEAR1.
+const INT_EVENT_LEN: usize = 11;
+
+/// Events of INT_EVENT1 that change the contract.
+const PLUG_INSERT_OR_REMOVAL: u32 = 1 << 3;
+const NEW_CONTRACT_AS_PROVIDER: u32 = 1 << 12;
+const NEW_CONTRACT_AS_CONSUMER: u32 = 1 << 13;
+const CONTRACT_EVENTS: u32 =
+    PLUG_INSERT_OR_REMOVAL | NEW_CONTRACT_AS_PROVIDER | NEW_CONTRACT_AS_CONSUMER;
+
+/// PowerConnection bit of Power Status.
+const POWER_CONNECTION: u8 = 0x01;
+/// TypeCCurrent field of Power Status.
+const TYPEC_CURRENT_MASK: u8 = 0x0C;
+const TYPEC_CURRENT_SHIFT: u8 = 2;
+const TYPEC_CURRENT_PD_CONTRACT: u8 = 3;
+/// Current advertised by each other TypeCCurrent value, in mA.
+const TYPEC_CURRENTS_MA: [u32; 3] = [500, 1500, 3000];
+
+const CMD_SWAP_TO_SINK: [u8; 4] = *b"SWSk";
+const CMD_AUTO_NEGOTIATE_SINK: [u8; 4] = *b"ANeg";
+const CMD_UNRECOGNIZED: [u8; 4] = *b"!CMD";
+
+/// Task result field of the first byte of DATA1.
+const TASK_RESULT_MASK: u8 = 0x0F;
+
+const POLL_INTERVAL_MS: u32 = 10;
+/// Polls of CMD1 before a task is given up on. A power role swap takes a
+/// few hundred ms.
+const MAX_POLLS: u8 = 100;
+
+const VSAFE5V_MV: u32 = 5000;
+const MAX_VOLTAGE_MV: u32 = 20000;
+const MAX_CURRENT_MA: u32 = 5000;
+
+/// A fixed supply PDO of `voltage_mv` and `current_ma`.
+fn fixed_pdo(voltage_mv: u32, current_ma: u32) -> u32 {
+    (voltage_mv / 50) << 10 | current_ma / 10
+}
+
+/// The contract of a PDO from the source, `None` for battery supplies,
+/// which give a power rather than a current.
+fn decode_pdo(pdo: u32) -> Option<PowerContract> {
+    match pdo >> 30 {
+        // Fixed and variable supplies.
+        0 | 2 => Some(PowerContract {
+            voltage_mv: (pdo >> 10 & 0x3FF) * 50,
+            current_ma: (pdo & 0x3FF) * 10,
+        }),
+        // Programmable power supplies, by their maximum voltage.
+        3 => Some(PowerContract {
+            voltage_mv: (pdo >> 17 & 0xFF) * 100,
+            current_ma: (pdo & 0x7F) * 50,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    ReadPowerStatus,
+    ReadActivePdo,
+    WriteSinkCapabilities,
+    WriteCommand,
+    WaitCommand,
+    PollCommand,
+    ReadCommandResult,
+    WriteEventMask,
+    ReadEvents,
+    ClearEvents,
+}
+
+/// What the client is told when the current operation ends.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    ReadContract,
+    Command,
+    Events,
+}
+
+pub struct Tps65987d<'a, A: Alarm<'a>, I: i2c::I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn UsbPdClient>,
+    state: Cell<State>,
+    operation: OptionalCell<Operation>,
+    buffer: TakeCell<'static, [u8]>,
+    polls: Cell<u8>,
+    /// The events read include a contract change.
+    contract_changed: Cell<bool>,
+    /// The INT pin fired while an operation was in progress.
+    events_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> Tps65987d<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Tps65987d<'a, A, I> {
+        Tps65987d {
+            i2c,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            operation: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            polls: Cell::new(0),
+            contract_changed: Cell::new(false),
+            events_pending: Cell::new(false),
+        }
+    }
+
+    /// Unmask the contract events, and clear the events already set so
+    /// that the INT pin is released.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let mut mask = [0; INT_EVENT_LEN];
+        mask[..4].copy_from_slice(&CONTRACT_EVENTS.to_le_bytes());
+        self.begin(Operation::Events, || {
+            self.write_register(State::WriteEventMask, Registers::IntMask1, &mask)
+        })
+    }
+
+    fn begin(
+        &self,
+        operation: Operation,
+        first: impl FnOnce() -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.i2c.enable();
+        self.operation.set(operation);
+        let result = first();
+        if result.is_err() {
+            self.operation.clear();
+            self.i2c.disable();
+        }
+        result
+    }
+
+    fn read_register(
+        &self,
+        state: State,
+        register: Registers,
+        len: usize,
+    ) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register as u8;
+            // The byte count comes first.
+            match self.i2c.write_read(buffer, 1, len + 1) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn write_register(
+        &self,
+        state: State,
+        register: Registers,
+        data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = register as u8;
+            buffer[1] = data.len() as u8;
+            buffer[2..2 + data.len()].copy_from_slice(data);
+            match self.i2c.write(buffer, 2 + data.len()) {
+                Ok(()) => {
+                    self.state.set(state);
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn write_command(&self, command: [u8; 4]) -> Result<(), ErrorCode> {
+        self.polls.set(0);
+        self.write_register(State::WriteCommand, Registers::Cmd1, &command)
+    }
+
+    fn wait_command(&self) {
+        self.state.set(State::WaitCommand);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(POLL_INTERVAL_MS));
+    }
+
+    fn read_events(&self) {
+        self.events_pending.set(false);
+        self.contract_changed.set(false);
+        let _ = self.begin(Operation::Events, || {
+            self.read_register(State::ReadEvents, Registers::IntEvent1, INT_EVENT_LEN)
+        });
+    }
+
+    /// End the current operation, and then read the events if the INT pin
+    /// fired while it was in progress.
+    fn finish(&self, result: Result<Option<PowerContract>, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        match self.operation.take() {
+            Some(Operation::ReadContract) => {
+                self.client.map(|client| client.contract_read(result));
+            }
+            Some(Operation::Command) => {
+                self.client
+                    .map(|client| client.command_complete(result.map(|_| ())));
+            }
+            Some(Operation::Events) => {
+                if result.is_ok() && self.contract_changed.get() {
+                    self.client.map(|client| client.contract_changed());
+                }
+            }
+            None => {}
+        }
+        if self.events_pending.get() && self.state.get() == State::Idle {
+            self.read_events();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> UsbPdController<'a> for Tps65987d<'a, A, I> {
+    fn set_client(&self, client: &'a dyn UsbPdClient) {
+        self.client.set(client);
+    }
+
+    fn read_contract(&self) -> Result<(), ErrorCode> {
+        self.begin(Operation::ReadContract, || {
+            self.read_register(State::ReadPowerStatus, Registers::PowerStatus, 2)
+        })
+    }
+
+    fn request_power(&self, voltage_mv: u32, current_ma: u32) -> Result<(), ErrorCode> {
+        if !(VSAFE5V_MV..=MAX_VOLTAGE_MV).contains(&voltage_mv) || current_ma > MAX_CURRENT_MA {
+            return Err(ErrorCode::INVAL);
+        }
+        // The first sink PDO must be vSafe5V.
+        let mut capabilities = [0; 9];
+        capabilities[1..5].copy_from_slice(&fixed_pdo(VSAFE5V_MV, current_ma).to_le_bytes());
+        let len = if voltage_mv == VSAFE5V_MV {
+            capabilities[0] = 1;
+            5
+        } else {
+            capabilities[0] = 2;
+            capabilities[5..9].copy_from_slice(&fixed_pdo(voltage_mv, current_ma).to_le_bytes());
+            9
+        };
+        self.begin(Operation::Command, || {
+            self.write_register(
+                State::WriteSinkCapabilities,
+                Registers::TxSinkCapabilities,
+                &capabilities[..len],
+            )
+        })
+    }
+
+    fn set_sink(&self) -> Result<(), ErrorCode> {
+        self.begin(Operation::Command, || self.write_command(CMD_SWAP_TO_SINK))
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> i2c::I2CClient for Tps65987d<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let mut data = [0; INT_EVENT_LEN];
+        data.copy_from_slice(&buffer[1..1 + INT_EVENT_LEN]);
+        self.buffer.replace(buffer);
+
+        if let Err(e) = status {
+            self.finish(Err(e.into()));
+            return;
+        }
+
+        let result = match state {
+            State::ReadPowerStatus => {
+                let current = (data[0] & TYPEC_CURRENT_MASK) >> TYPEC_CURRENT_SHIFT;
+                if data[0] & POWER_CONNECTION == 0 {
+                    self.finish(Ok(None));
+                    Ok(())
+                } else if current == TYPEC_CURRENT_PD_CONTRACT {
+                    self.read_register(State::ReadActivePdo, Registers::ActiveContractPdo, 4)
+                } else {
+                    self.finish(Ok(Some(PowerContract {
+                        voltage_mv: VSAFE5V_MV,
+                        current_ma: TYPEC_CURRENTS_MA[current as usize],
+                    })));
+                    Ok(())
+                }
+            }
+            State::ReadActivePdo => {
+                let pdo = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                self.finish(decode_pdo(pdo).ok_or(ErrorCode::NOSUPPORT).map(Some));
+                Ok(())
+            }
+            State::WriteSinkCapabilities => self.write_command(CMD_AUTO_NEGOTIATE_SINK),
+            State::WriteCommand => {
+                self.wait_command();
+                Ok(())
+            }
+            State::PollCommand => {
+                let cmd = [data[0], data[1], data[2], data[3]];
+                if cmd == [0; 4] {
+                    self.read_register(State::ReadCommandResult, Registers::Data1, 1)
+                } else if cmd == CMD_UNRECOGNIZED {
+                    Err(ErrorCode::NOSUPPORT)
+                } else if self.polls.get() >= MAX_POLLS {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    self.wait_command();
+                    Ok(())
+                }
+            }
+            State::ReadCommandResult => {
+                if data[0] & TASK_RESULT_MASK == 0 {
+                    self.finish(Ok(None));
+                    Ok(())
+                } else {
+                    Err(ErrorCode::FAIL)
+                }
+            }
+            State::WriteEventMask => {
+                self.read_register(State::ReadEvents, Registers::IntEvent1, INT_EVENT_LEN)
+            }
+            State::ReadEvents => {
+                let events = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                self.contract_changed
+                    .set(self.contract_changed.get() || events & CONTRACT_EVENTS != 0);
+                self.write_register(State::ClearEvents, Registers::IntClear1, &data)
+            }
+            State::ClearEvents => {
+                self.finish(Ok(None));
+                Ok(())
+            }
+            State::Idle | State::WaitCommand => Ok(()),
+        };
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> time::AlarmClient for Tps65987d<'a, A, I> {
+    fn alarm(&self) {
+        if self.state.get() != State::WaitCommand {
+            return;
+        }
+        self.polls.set(self.polls.get() + 1);
+        if let Err(e) = self.read_register(State::PollCommand, Registers::Cmd1, 4) {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: i2c::I2CDevice> gpio::Client for Tps65987d<'a, A, I> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => self.read_events(),
+            // INT stays low until the events are cleared, so they are not
+            // lost by reading them once the current operation is done.
+            _ => self.events_pending.set(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+
+    /// Complete the transfer in progress, reading `data` after its length
+    /// byte.
+    fn complete(i2c: &MockI2c, tps: &Tps65987d<'static, TestAlarm, MockI2c>, data: &[u8]) {
+        if i2c.transfer().unwrap().read_len == 0 {
+            i2c.complete(tps, &[]);
+        } else {
+            let mut read = std::vec![data.len() as u8];
+            read.extend_from_slice(data);
+            i2c.complete(tps, &read);
+        }
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        contract: Cell<Option<Result<Option<PowerContract>, ErrorCode>>>,
+        command: Cell<Option<Result<(), ErrorCode>>>,
+        changes: Cell<usize>,
+    }
+
+    impl UsbPdClient for MockClient {
+        fn contract_read(&self, result: Result<Option<PowerContract>, ErrorCode>) {
+            self.contract.set(Some(result));
+        }
+        fn command_complete(&self, result: Result<(), ErrorCode>) {
+            self.command.set(Some(result));
+        }
+        fn contract_changed(&self) {
+            self.changes.set(self.changes.get() + 1);
+        }
+    }
+
+    #[test]
+    fn contract_and_power_request() {
+        let i2c = Box::leak(Box::new(MockI2c::new()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let buffer = Box::leak(Box::new([0; BUFFER_SIZE]));
+        let tps = Box::leak(Box::new(Tps65987d::new(i2c, alarm, buffer)));
+        let client = Box::leak(Box::new(MockClient::default()));
+        alarm.set_alarm_client(tps);
+        tps.set_client(client);
+
+        // Connected with a PD contract of 9 V, 3 A.
+        assert_eq!(tps.read_contract(), Ok(()));
+        complete(i2c, tps, &[0x0F, 0x00]);
+        complete(i2c, tps, &fixed_pdo(9000, 3000).to_le_bytes());
+        assert_eq!(
+            client.contract.take(),
+            Some(Ok(Some(PowerContract {
+                voltage_mv: 9000,
+                current_ma: 3000,
+            })))
+        );
+        assert_eq!(
+            i2c.take_writes(),
+            [
+                [Registers::PowerStatus as u8],
+                [Registers::ActiveContractPdo as u8]
+            ]
+        );
+
+        // Without a PD contract, 5 V at the Type-C current.
+        assert_eq!(tps.read_contract(), Ok(()));
+        complete(i2c, tps, &[0x05, 0x00]);
+        assert_eq!(
+            client.contract.take(),
+            Some(Ok(Some(PowerContract {
+                voltage_mv: 5000,
+                current_ma: 1500,
+            })))
+        );
+        i2c.take_writes();
+
+        assert_eq!(tps.request_power(21000, 1000), Err(ErrorCode::INVAL));
+        assert_eq!(tps.request_power(15000, 2000), Ok(()));
+        assert_eq!(tps.read_contract(), Err(ErrorCode::BUSY));
+        // The INT pin fires during the request.
+        gpio::Client::fired(tps);
+        complete(i2c, tps, &[]);
+        complete(i2c, tps, &[]);
+        // Still running, then done.
+        assert_eq!(alarm.dt(), Some(10_000));
+        alarm.fire();
+        complete(i2c, tps, b"ANeg");
+        assert_eq!(alarm.dt(), Some(10_000));
+        alarm.fire();
+        complete(i2c, tps, &[0; 4]);
+        complete(i2c, tps, &[0x00]);
+        assert_eq!(client.command.take(), Some(Ok(())));
+
+        // The new contract event is then read and cleared.
+        let mut events = [0; INT_EVENT_LEN];
+        events[1] = 0x20;
+        complete(i2c, tps, &events);
+        complete(i2c, tps, &[]);
+        assert_eq!(client.changes.get(), 1);
+        assert_eq!(tps.state.get(), State::Idle);
+
+        let mut sink_capabilities = std::vec![Registers::TxSinkCapabilities as u8, 9, 2];
+        sink_capabilities.extend_from_slice(&fixed_pdo(5000, 2000).to_le_bytes());
+        sink_capabilities.extend_from_slice(&fixed_pdo(15000, 2000).to_le_bytes());
+        let mut clear = std::vec![Registers::IntClear1 as u8, INT_EVENT_LEN as u8];
+        clear.extend_from_slice(&events);
+        assert_eq!(
+            i2c.take_writes(),
+            [
+                sink_capabilities,
+                std::vec![Registers::Cmd1 as u8, 4, b'A', b'N', b'e', b'g'],
+                std::vec![Registers::Cmd1 as u8],
+                std::vec![Registers::Cmd1 as u8],
+                std::vec![Registers::Data1 as u8],
+                std::vec![Registers::IntEvent1 as u8],
+                clear,
+            ]
+        );
+    }
+}
@@ -0,0 +1,613 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the ams TSL2591 high dynamic range light sensor, over I2C.
+//!
+//! <https://ams.com/documents/20143/36005/TSL2591_DS000338_6-00.pdf>
+//!
+//! Driver Semantics
+//! ----------------
+//!
+//! The sensor has two photodiodes: channel 0 sees visible and infrared
+//! light, channel 1 only infrared. Their counts scale with the gain, from
+//! 1x to 9876x, and the integration time, from 100 ms to 600 ms.
+//!
+//! A reading picks the gain and integration time automatically. The first
+//! reading starts at low gain and 100 ms. Whenever channel 0 is below 100
+//! counts the next, more sensitive, setting is tried, and whenever either
+//! channel saturates the previous one, until the reading is in range or
+//! there is no setting left to try. Later readings start from the setting of
+//! the last one. Lux are then computed with the formula of the ams
+//! application note, corrected for the gain and integration time. The raw
+//! counts of both channels of a reading are also available, for
+//! applications that look at the infrared content of the light.
+//!
+//! If the INT pin is connected, [Tsl2591::set_thresholds] keeps the sensor
+//! integrating and interrupts when channel 0 stays outside a window for a
+//! number of consecutive cycles, so the client does not need to poll for
+//! changes of the light level. While the thresholds are enabled the
+//! setting of the last reading is kept, so that the thresholds, in channel
+//! 0 counts, keep their meaning, and readings return the latest cycle.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tsl2591 = components::tsl2591::Tsl2591Component::new(
+//!     mux_i2c,
+//!     capsules_extra::tsl2591::BASE_ADDR,
+//!     mux_alarm,
+//!     Some(&nrf52840_peripherals.gpio_port[TSL2591_INT]),
+//! )
+//! .finalize(components::tsl2591_component_static!(
+//!     nrf52840::i2c::TWI,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let ambient_light = components::isl29035::AmbientLightComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ambient_light::DRIVER_NUM,
+//!     tsl2591,
+//! )
+//! .finalize(components::ambient_light_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{AmbientLight, AmbientLightClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the TSL2591.
+pub const BASE_ADDR: u8 = 0x29;
+
+/// Size of the buffer the driver needs, for a read of the status and both
+/// channels.
+pub const BUFFER_SIZE: usize = 5;
+
+/// Command bit, with normal register transactions.
+const COMMAND: u8 = 0xA0;
+/// Special function that clears both ALS interrupts.
+const SF_CLEAR_INTERRUPTS: u8 = 0xE7;
+
+const REG_ENABLE: u8 = 0x00;
+const REG_AILTL: u8 = 0x04;
+const REG_PERSIST: u8 = 0x0C;
+const REG_STATUS: u8 = 0x13;
+
+const ENABLE_PON: u8 = 0x01;
+const ENABLE_AEN: u8 = 0x02;
+const ENABLE_AIEN: u8 = 0x10;
+const STATUS_AVALID: u8 = 0x01;
+
+/// Channel 0 counts below which a reading is underrange.
+const UNDERRANGE_COUNTS: u16 = 100;
+
+/// Time between the end of an integration and the data being valid.
+const READ_MARGIN_MS: u32 = 10;
+
+/// Lux coefficient of the ams formula.
+const LUX_DF: u64 = 408;
+
+/// Consecutive cycles outside the thresholds for each `APERS` value. 0
+/// interrupts on every cycle.
+const PERSISTENCE_CYCLES: [u8; 16] = [0, 1, 2, 3, 5, 10, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60];
+
+/// Gain of the ADCs, as the `AGAIN` field of `CONTROL`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gain {
+    Low = 0x00,
+    Medium = 0x10,
+    High = 0x20,
+    Max = 0x30,
+}
+
+impl Gain {
+    fn multiplier(self) -> u64 {
+        match self {
+            Gain::Low => 1,
+            Gain::Medium => 25,
+            Gain::High => 428,
+            Gain::Max => 9876,
+        }
+    }
+}
+
+/// Settings tried by the automatic gain, from the least to the most
+/// sensitive, as a gain and an `ATIME` integration time of
+/// `100 * (ATIME + 1)` ms.
+const SETTINGS: [(Gain, u8); 6] = [
+    (Gain::Low, 0),
+    (Gain::Medium, 0),
+    (Gain::High, 0),
+    (Gain::Max, 0),
+    (Gain::Max, 2),
+    (Gain::Max, 5),
+];
+
+fn integration_ms(atime: u8) -> u32 {
+    100 * (atime as u32 + 1)
+}
+
+/// Counts at which the channels saturate.
+fn max_counts(atime: u8) -> u16 {
+    if atime == 0 {
+        37888
+    } else {
+        65535
+    }
+}
+
+/// Lux for the counts of both channels, at the setting `SETTINGS[setting]`.
+fn lux(ch0: u16, ch1: u16, setting: usize) -> usize {
+    let (gain, atime) = SETTINGS[setting];
+    if ch0 == 0 || ch1 >= ch0 {
+        return 0;
+    }
+    // lux = (ch0 - ch1) * (1 - ch1 / ch0) / cpl, with
+    // cpl = integration_ms * gain / LUX_DF.
+    let visible = (ch0 - ch1) as u64;
+    let counts_per_lux = integration_ms(atime) as u64 * gain.multiplier();
+    (visible * visible * LUX_DF / (ch0 as u64 * counts_per_lux)) as usize
+}
+
+/// The setting to try after a reading of `ch0` and `ch1` at `setting`, or
+/// `None` if the reading is in range or there is no better setting.
+fn next_setting(ch0: u16, ch1: u16, setting: usize) -> Option<usize> {
+    let max = max_counts(SETTINGS[setting].1);
+    if ch0 >= max || ch1 >= max {
+        setting.checked_sub(1)
+    } else if ch0 < UNDERRANGE_COUNTS && setting + 1 < SETTINGS.len() {
+        Some(setting + 1)
+    } else {
+        None
+    }
+}
+
+/// Receives configurations and threshold interrupts from the TSL2591.
+pub trait Tsl2591Client {
+    /// [Tsl2591::set_thresholds] or [Tsl2591::disable_thresholds]
+    /// completed.
+    fn configured(&self, result: Result<(), ErrorCode>);
+
+    /// Channel 0 stayed outside the thresholds, and the light is now `lux`.
+    fn threshold_interrupt(&self, lux: usize);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Powering the ADCs off, and writing the gain and integration time.
+    Configure,
+    Enable,
+    Integrating,
+    Read,
+    /// Powering the sensor off after a reading of both channels.
+    PowerOff(u16, u16),
+    WriteThresholds,
+    WritePersistence,
+    /// Writing `ENABLE`, with the interrupt enabled or not.
+    WriteInterruptEnable(bool),
+    ReadInterrupt,
+    ClearInterrupt(u16, u16),
+}
+
+pub struct Tsl2591<'a, I: I2CDevice, A: Alarm<'a>> {
+    i2c: &'a I,
+    alarm: &'a A,
+    interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Index in `SETTINGS` of the current setting.
+    setting: Cell<usize>,
+    /// Settings tried by the current reading.
+    attempts: Cell<usize>,
+    lux_requested: Cell<bool>,
+    raw_requested: Cell<bool>,
+    thresholds_enabled: Cell<bool>,
+    /// Cycles of the thresholds being written.
+    persistence_cycles: Cell<u8>,
+    /// The INT line fell while the driver was busy.
+    interrupt_pending: Cell<bool>,
+    client: OptionalCell<&'a dyn Tsl2591Client>,
+    light_client: OptionalCell<&'a dyn AmbientLightClient>,
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> Tsl2591<'a, I, A> {
+    pub fn new(
+        i2c: &'a I,
+        alarm: &'a A,
+        interrupt_pin: Option<&'a dyn gpio::InterruptPin<'a>>,
+        buffer: &'static mut [u8; BUFFER_SIZE],
+    ) -> Tsl2591<'a, I, A> {
+        Tsl2591 {
+            i2c,
+            alarm,
+            interrupt_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            setting: Cell::new(0),
+            attempts: Cell::new(0),
+            lux_requested: Cell::new(false),
+            raw_requested: Cell::new(false),
+            thresholds_enabled: Cell::new(false),
+            persistence_cycles: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            client: OptionalCell::empty(),
+            light_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Tsl2591Client) {
+        self.client.set(client);
+    }
+
+    /// Interrupt when channel 0 is below `low` or above `high` counts for
+    /// `cycles` consecutive integrations, rounded up to a number of cycles
+    /// the sensor supports, up to 60. With `cycles` 0, the sensor
+    /// interrupts after every integration.
+    pub fn set_thresholds(&self, low: u16, high: u16, cycles: u8) -> Result<(), ErrorCode> {
+        if self.interrupt_pin.is_none() {
+            return Err(ErrorCode::NODEVICE);
+        }
+        if PERSISTENCE_CYCLES.iter().all(|&n| n < cycles) || low > high {
+            return Err(ErrorCode::INVAL);
+        }
+        self.idle()?;
+        self.persistence_cycles.set(cycles);
+        let [low_low, low_high] = low.to_le_bytes();
+        let [high_low, high_high] = high.to_le_bytes();
+        self.write(
+            State::WriteThresholds,
+            &[COMMAND | REG_AILTL, low_low, low_high, high_low, high_high],
+        )
+    }
+
+    /// Stop interrupting on the thresholds, and power the sensor off.
+    pub fn disable_thresholds(&self) -> Result<(), ErrorCode> {
+        self.idle()?;
+        self.interrupt_pin.map(|pin| pin.disable_interrupts());
+        self.write(
+            State::WriteInterruptEnable(false),
+            &[COMMAND | REG_ENABLE, 0],
+        )
+    }
+
+    fn idle(&self) -> Result<(), ErrorCode> {
+        if self.state.get() == State::Idle {
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    fn write(&self, state: State, bytes: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write(buffer, bytes.len()) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    /// Read the status and the counts of both channels.
+    fn read_channels(&self, state: State) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0] = COMMAND | REG_STATUS;
+        self.i2c.enable();
+        if let Err((error, buffer)) = self.i2c.write_read(buffer, 1, BUFFER_SIZE) {
+            self.buffer.replace(buffer);
+            self.i2c.disable();
+            Err(error.into())
+        } else {
+            self.state.set(state);
+            Ok(())
+        }
+    }
+
+    /// Power the ADCs off and write the current setting.
+    fn configure(&self) -> Result<(), ErrorCode> {
+        let (gain, atime) = SETTINGS[self.setting.get()];
+        self.write(
+            State::Configure,
+            &[COMMAND | REG_ENABLE, ENABLE_PON, gain as u8 | atime],
+        )
+    }
+
+    fn wait(&self, ms: u32) {
+        self.state.set(State::Integrating);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Start the next operation, if the driver is idle.
+    fn run(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        if self.interrupt_pending.get() {
+            self.interrupt_pending.set(false);
+            let _ = self.read_channels(State::ReadInterrupt);
+        } else if self.lux_requested.get() || self.raw_requested.get() {
+            if self.start_reading().is_err() {
+                self.report(0, 0);
+            }
+        }
+    }
+
+    fn start_reading(&self) -> Result<(), ErrorCode> {
+        self.attempts.set(0);
+        // While the thresholds are enabled the sensor keeps integrating at
+        // the same setting, so the last cycle can be read directly.
+        if self.thresholds_enabled.get() {
+            self.read_channels(State::Read)
+        } else {
+            self.configure()
+        }
+    }
+
+    /// Ask for a reading, which starts now unless the driver is busy.
+    fn request(&self, requested: &Cell<bool>) -> Result<(), ErrorCode> {
+        requested.set(true);
+        if self.state.get() != State::Idle || self.interrupt_pending.get() {
+            return Ok(());
+        }
+        self.start_reading().map_err(|e| {
+            requested.set(false);
+            e
+        })
+    }
+
+    /// Return a reading of both channels to the clients that asked for it.
+    fn report(&self, ch0: u16, ch1: u16) {
+        self.state.set(State::Idle);
+        let lux = lux(ch0, ch1, self.setting.get());
+        if self.lux_requested.replace(false) {
+            self.light_client.map(|client| client.callback(lux));
+        }
+        if self.raw_requested.replace(false) {
+            self.light_client
+                .map(|client| client.raw_callback(ch0 as usize, ch1 as usize));
+        }
+    }
+
+    /// Continue a reading after the status and channels were read.
+    fn reading_done(&self, avalid: bool, ch0: u16, ch1: u16) -> Result<(), ErrorCode> {
+        if !avalid {
+            self.wait(READ_MARGIN_MS);
+            return Ok(());
+        }
+        if self.thresholds_enabled.get() {
+            self.report(ch0, ch1);
+            return Ok(());
+        }
+        match next_setting(ch0, ch1, self.setting.get()) {
+            Some(setting) if self.attempts.get() < SETTINGS.len() => {
+                self.setting.set(setting);
+                self.attempts.set(self.attempts.get() + 1);
+                self.configure()
+            }
+            _ => self.write(State::PowerOff(ch0, ch1), &[COMMAND | REG_ENABLE, 0]),
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> I2CClient for Tsl2591<'a, I, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let state = self.state.get();
+        let avalid = buffer[0] & STATUS_AVALID != 0;
+        let ch0 = u16::from_le_bytes([buffer[1], buffer[2]]);
+        let ch1 = u16::from_le_bytes([buffer[3], buffer[4]]);
+        self.buffer.replace(buffer);
+        self.i2c.disable();
+        self.state.set(State::Idle);
+
+        let status: Result<(), ErrorCode> = status.map_err(|e| e.into());
+        match state {
+            State::Configure | State::Enable | State::Read => {
+                let result = status.and_then(|()| match state {
+                    State::Configure => self.write(
+                        State::Enable,
+                        &[COMMAND | REG_ENABLE, ENABLE_PON | ENABLE_AEN],
+                    ),
+                    State::Enable => {
+                        let atime = SETTINGS[self.setting.get()].1;
+                        self.wait(integration_ms(atime) + READ_MARGIN_MS);
+                        Ok(())
+                    }
+                    _ => self.reading_done(avalid, ch0, ch1),
+                });
+                if result.is_err() {
+                    self.report(0, 0);
+                }
+            }
+            State::PowerOff(ch0, ch1) => self.report(ch0, ch1),
+            State::WriteThresholds => {
+                let cycles = self.persistence_cycles.get();
+                let apers = PERSISTENCE_CYCLES
+                    .iter()
+                    .position(|&n| n >= cycles)
+                    .unwrap_or(0) as u8;
+                if let Err(e) = status.and_then(|()| {
+                    self.write(State::WritePersistence, &[COMMAND | REG_PERSIST, apers])
+                }) {
+                    self.client.map(|client| client.configured(Err(e)));
+                }
+            }
+            State::WritePersistence => {
+                if let Err(e) = status.and_then(|()| {
+                    self.write(
+                        State::WriteInterruptEnable(true),
+                        &[COMMAND | REG_ENABLE, ENABLE_PON | ENABLE_AEN | ENABLE_AIEN],
+                    )
+                }) {
+                    self.client.map(|client| client.configured(Err(e)));
+                }
+            }
+            State::WriteInterruptEnable(enabled) => {
+                if status.is_ok() {
+                    self.thresholds_enabled.set(enabled);
+                    if enabled {
+                        self.interrupt_pin
+                            .map(|pin| pin.enable_interrupts(gpio::InterruptEdge::FallingEdge));
+                    }
+                }
+                self.client.map(|client| client.configured(status));
+            }
+            State::ReadInterrupt => {
+                let _ = status.and_then(|()| {
+                    self.write(State::ClearInterrupt(ch0, ch1), &[SF_CLEAR_INTERRUPTS])
+                });
+            }
+            State::ClearInterrupt(ch0, ch1) => {
+                let lux = lux(ch0, ch1, self.setting.get());
+                self.client.map(|client| client.threshold_interrupt(lux));
+            }
+            State::Idle | State::Integrating => {}
+        }
+        self.run();
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> time::AlarmClient for Tsl2591<'a, I, A> {
+    fn alarm(&self) {
+        if self.state.get() == State::Integrating {
+            if self.read_channels(State::Read).is_err() {
+                self.report(0, 0);
+                self.run();
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> gpio::Client for Tsl2591<'a, I, A> {
+    fn fired(&self) {
+        self.interrupt_pending.set(true);
+        self.run();
+    }
+}
+
+impl<'a, I: I2CDevice, A: Alarm<'a>> AmbientLight<'a> for Tsl2591<'a, I, A> {
+    fn set_client(&self, client: &'a dyn AmbientLightClient) {
+        self.light_client.set(client);
+    }
+
+    fn read_light_intensity(&self) -> Result<(), ErrorCode> {
+        self.request(&self.lux_requested)
+    }
+
+    fn read_raw_channels(&self) -> Result<(), ErrorCode> {
+        self.request(&self.raw_requested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::{MockAlarm, MockI2c};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+
+    struct MockClient {
+        lux: Cell<Option<usize>>,
+        raw: Cell<Option<(usize, usize)>>,
+    }
+
+    impl AmbientLightClient for MockClient {
+        fn callback(&self, lux: usize) {
+            self.lux.set(Some(lux));
+        }
+
+        fn raw_callback(&self, ch0: usize, ch1: usize) {
+            self.raw.set(Some((ch0, ch1)));
+        }
+    }
+
+    type TestAlarm = MockAlarm<'static, Freq1MHz>;
+    type Sensor = Tsl2591<'static, MockI2c, TestAlarm>;
+
+    /// Run one integration of a reading, which reads `ch0` and `ch1`.
+    fn integrate(i2c: &MockI2c, alarm: &TestAlarm, tsl2591: &Sensor, ch0: u16, ch1: u16) {
+        // Configure, then enable.
+        i2c.complete(tsl2591, &[]);
+        i2c.complete(tsl2591, &[]);
+        assert!(alarm.dt().is_some());
+        alarm.fire();
+        let [ch0_low, ch0_high] = ch0.to_le_bytes();
+        let [ch1_low, ch1_high] = ch1.to_le_bytes();
+        i2c.complete(
+            tsl2591,
+            &[STATUS_AVALID, ch0_low, ch0_high, ch1_low, ch1_high],
+        );
+    }
+
+    #[test]
+    fn lux_and_automatic_gain_steps() {
+        // 1000 visible counts at 25x and 100 ms.
+        assert_eq!(lux(1250, 250, 1), 130);
+        // The same light at 1x.
+        assert_eq!(lux(50, 10, 0), 130);
+        assert_eq!(lux(0, 0, 0), 0);
+        assert_eq!(lux(100, 200, 0), 0);
+
+        assert_eq!(next_setting(50, 10, 0), Some(1));
+        assert_eq!(next_setting(1250, 250, 1), None);
+        assert_eq!(next_setting(37888, 9000, 1), Some(0));
+        assert_eq!(next_setting(37888, 9000, 0), None);
+        // Above 100 ms, the channels saturate at 65535 counts.
+        assert_eq!(next_setting(40000, 9000, 4), None);
+        assert_eq!(next_setting(65535, 9000, 4), Some(3));
+        assert_eq!(next_setting(5, 1, 5), None);
+    }
+
+    #[test]
+    fn reading_increases_gain_until_in_range() {
+        let i2c = Box::leak(Box::new(MockI2c::new()));
+        let alarm = Box::leak(Box::new(TestAlarm::new()));
+        let tsl2591 = Box::leak(Box::new(Tsl2591::new(
+            i2c,
+            alarm,
+            None,
+            Box::leak(Box::new([0; BUFFER_SIZE])),
+        )));
+        let client = Box::leak(Box::new(MockClient {
+            lux: Cell::new(None),
+            raw: Cell::new(None),
+        }));
+        alarm.set_alarm_client(tsl2591);
+        AmbientLight::set_client(tsl2591, client);
+
+        assert_eq!(tsl2591.read_light_intensity(), Ok(()));
+        assert_eq!(tsl2591.read_raw_channels(), Ok(()));
+        // Underrange at low gain, in range at medium gain.
+        integrate(i2c, alarm, tsl2591, 50, 10);
+        integrate(i2c, alarm, tsl2591, 1250, 250);
+        assert_eq!(client.lux.get(), None);
+        // Power off.
+        i2c.complete(tsl2591, &[]);
+        assert_eq!(client.lux.get(), Some(130));
+        assert_eq!(client.raw.get(), Some((1250, 250)));
+        assert_eq!(
+            tsl2591.set_thresholds(100, 2000, 3),
+            Err(ErrorCode::NODEVICE)
+        );
+
+        let writes = i2c.writes();
+        assert_eq!(writes[0], [COMMAND | REG_ENABLE, ENABLE_PON, 0x00]);
+        assert_eq!(writes[1], [COMMAND | REG_ENABLE, ENABLE_PON | ENABLE_AEN]);
+        assert_eq!(writes[2], [COMMAND | REG_STATUS]);
+        assert_eq!(writes[3], [COMMAND | REG_ENABLE, ENABLE_PON, 0x10]);
+        assert_eq!(writes[6], [COMMAND | REG_ENABLE, 0]);
+        assert_eq!(writes.len(), 7);
+    }
+}
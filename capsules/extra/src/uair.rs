@@ -0,0 +1,602 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Universal air quality reporting: combines the readings of all the air
+//! quality sensors on a board into one report.
+//!
+//! Each source is a sensor providing `hil::sensors::AirQualityDriver` and
+//! the gas read from it. A sensor that measures several gases is listed
+//! once per gas. The hub polls the sources one after the other every
+//! interval, averages the readings of sensors measuring the same gas, and
+//! reports them together once all the sources have answered. Sources that
+//! fail to read are counted in the report. If a source has not answered by
+//! the end of the interval, it is counted as failed and the report is made
+//! from the other readings.
+//!
+//! The hub takes the client of every sensor, so the sensors cannot also be
+//! used through the `air_quality` driver.
+//!
+//! Userspace receives the reports as JSON, written into a buffer it has
+//! allowed, for example:
+//!
+//! ```json
+//! {"co2":{"ppm":412,"sensors":2},"tvoc":null,"failed":1}
+//! ```
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow_readwrite` System Call
+//!
+//! * `0`: the buffer the next report is written into.
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called with the next report, with a status and the length of the
+//!   JSON in the buffer. The status is `SIZE` if the report does not fit.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: request the next report
+//! * `2`: write the last report into the buffer now, returning its length
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sources = static_init!(
+//!     [capsules_extra::uair::AirQualitySource<'static>; 3],
+//!     [
+//!         AirQualitySource::new(Gas::Co2, ccs811),
+//!         AirQualitySource::new(Gas::Tvoc, ccs811),
+//!         AirQualitySource::new(Gas::Co2, scd30),
+//!     ]
+//! );
+//! let uair = components::uair::AirQualityHubComponent::new(
+//!     board_kernel,
+//!     capsules_extra::uair::DRIVER_NUM,
+//!     sources,
+//!     mux_alarm,
+//!     60_000,
+//! )
+//! .finalize(components::air_quality_hub_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::sensors::{AirQualityClient, AirQualityDriver};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::processbuffer::{WriteableProcessBuffer, WriteableProcessSlice};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Uair as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const REPORT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+const NUM_GASES: usize = 2;
+
+/// The gases that can be read through `AirQualityDriver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gas {
+    /// CO2 or equivalent CO2, in ppm.
+    Co2 = 0,
+    /// Total volatile organic compounds, in ppb.
+    Tvoc = 1,
+}
+
+/// A sensor and the gas read from it.
+pub struct AirQualitySource<'a> {
+    pub gas: Gas,
+    pub sensor: &'a dyn AirQualityDriver<'a>,
+}
+
+impl<'a> AirQualitySource<'a> {
+    pub fn new(gas: Gas, sensor: &'a dyn AirQualityDriver<'a>) -> AirQualitySource<'a> {
+        AirQualitySource { gas, sensor }
+    }
+}
+
+/// The average reading of the sensors measuring a gas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasReading {
+    pub value: u32,
+    /// The number of sensors that were averaged.
+    pub sensors: usize,
+}
+
+/// The readings of one poll of all the sources. A gas is `None` if no
+/// sensor read it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AirQualityReport {
+    /// CO2 in ppm.
+    pub co2: Option<GasReading>,
+    /// TVOC in ppb.
+    pub tvoc: Option<GasReading>,
+    /// The number of sources that failed to read.
+    pub failed: usize,
+}
+
+/// Formats the report as JSON.
+impl fmt::Display for AirQualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn gas(f: &mut fmt::Formatter, unit: &str, reading: Option<GasReading>) -> fmt::Result {
+            match reading {
+                Some(reading) => write!(
+                    f,
+                    "{{\"{}\":{},\"sensors\":{}}}",
+                    unit, reading.value, reading.sensors
+                ),
+                None => f.write_str("null"),
+            }
+        }
+
+        f.write_str("{\"co2\":")?;
+        gas(f, "ppm", self.co2)?;
+        f.write_str(",\"tvoc\":")?;
+        gas(f, "ppb", self.tvoc)?;
+        write!(f, ",\"failed\":{}}}", self.failed)
+    }
+}
+
+pub trait AirQualityHubClient {
+    /// Called when all the sources have been polled.
+    fn report_ready(&self, report: AirQualityReport);
+}
+
+#[derive(Clone, Copy, Default)]
+struct Accumulator {
+    sum: u64,
+    count: usize,
+}
+
+impl Accumulator {
+    fn average(&self) -> Option<GasReading> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = self.count as u64;
+        Some(GasReading {
+            value: ((self.sum + count / 2) / count) as u32,
+            sensors: self.count,
+        })
+    }
+}
+
+pub struct AirQualityHub<'a, A: Alarm<'a>> {
+    sources: &'a [AirQualitySource<'a>],
+    alarm: &'a A,
+    interval_ms: u32,
+    /// The source being read, while polling.
+    current: OptionalCell<usize>,
+    readings: Cell<[Accumulator; NUM_GASES]>,
+    failed: Cell<usize>,
+    report: OptionalCell<AirQualityReport>,
+    client: OptionalCell<&'a dyn AirQualityHubClient>,
+}
+
+impl<'a, A: Alarm<'a>> AirQualityHub<'a, A> {
+    pub fn new(
+        sources: &'a [AirQualitySource<'a>],
+        alarm: &'a A,
+        interval_ms: u32,
+    ) -> AirQualityHub<'a, A> {
+        AirQualityHub {
+            sources,
+            alarm,
+            interval_ms,
+            current: OptionalCell::empty(),
+            readings: Cell::new([Accumulator::default(); NUM_GASES]),
+            failed: Cell::new(0),
+            report: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn AirQualityHubClient) {
+        self.client.set(client);
+    }
+
+    /// Polls the sources now, and then every interval.
+    pub fn start(&self) {
+        self.poll();
+    }
+
+    /// The report of the last completed poll.
+    pub fn report(&self) -> Option<AirQualityReport> {
+        self.report.extract()
+    }
+
+    fn poll(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.interval_ms));
+        self.readings.set([Accumulator::default(); NUM_GASES]);
+        self.failed.set(0);
+        self.read_from(0);
+    }
+
+    /// Starts reading the first source from `index` that can be read.
+    fn read_from(&self, mut index: usize) {
+        while let Some(source) = self.sources.get(index) {
+            let result = match source.gas {
+                Gas::Co2 => source.sensor.read_co2(),
+                Gas::Tvoc => source.sensor.read_tvoc(),
+            };
+            match result {
+                Ok(()) => {
+                    self.current.set(index);
+                    return;
+                }
+                Err(_) => {
+                    self.failed.set(self.failed.get() + 1);
+                    index += 1;
+                }
+            }
+        }
+        self.finish();
+    }
+
+    fn data_available(&self, gas: Gas, value: Result<u32, ErrorCode>) {
+        let index = match self.current.extract() {
+            Some(index) if self.sources[index].gas == gas => index,
+            // Not a reading we asked for.
+            _ => return,
+        };
+        self.current.clear();
+
+        match value {
+            Ok(value) => {
+                let mut readings = self.readings.get();
+                readings[gas as usize].sum += value as u64;
+                readings[gas as usize].count += 1;
+                self.readings.set(readings);
+            }
+            Err(_) => self.failed.set(self.failed.get() + 1),
+        }
+        self.read_from(index + 1);
+    }
+
+    fn finish(&self) {
+        let readings = self.readings.get();
+        let report = AirQualityReport {
+            co2: readings[Gas::Co2 as usize].average(),
+            tvoc: readings[Gas::Tvoc as usize].average(),
+            failed: self.failed.get(),
+        };
+        self.report.set(report);
+        self.client.map(|client| client.report_ready(report));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AirQualityClient for AirQualityHub<'a, A> {
+    fn environment_specified(&self, _result: Result<(), ErrorCode>) {}
+
+    fn co2_data_available(&self, value: Result<u32, ErrorCode>) {
+        self.data_available(Gas::Co2, value);
+    }
+
+    fn tvoc_data_available(&self, value: Result<u32, ErrorCode>) {
+        self.data_available(Gas::Tvoc, value);
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for AirQualityHub<'a, A> {
+    fn alarm(&self) {
+        if self.current.take().is_some() {
+            // The source did not answer within the interval.
+            self.failed.set(self.failed.get() + 1);
+            self.finish();
+        }
+        self.poll();
+    }
+}
+
+/// Writes formatted text into a process buffer.
+struct ProcessSliceWriter<'b> {
+    slice: &'b WriteableProcessSlice,
+    len: usize,
+}
+
+impl Write for ProcessSliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let dest = self.slice.get(self.len..end).ok_or(fmt::Error)?;
+        dest.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Writes `report` into `slice` as JSON, and returns its length.
+fn write_report(report: &AirQualityReport, slice: &WriteableProcessSlice) -> Option<usize> {
+    let mut writer = ProcessSliceWriter { slice, len: 0 };
+    write!(writer, "{}", report).ok().map(|()| writer.len)
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: bool,
+}
+
+pub struct AirQualityHubDriver<'a, A: Alarm<'a>> {
+    hub: &'a AirQualityHub<'a, A>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+}
+
+impl<'a, A: Alarm<'a>> AirQualityHubDriver<'a, A> {
+    pub fn new(
+        hub: &'a AirQualityHub<'a, A>,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> AirQualityHubDriver<'a, A> {
+        AirQualityHubDriver { hub, apps: grant }
+    }
+
+    fn last_report(&self, processid: ProcessId) -> CommandReturn {
+        let report = match self.hub.report() {
+            Some(report) => report,
+            None => return CommandReturn::failure(ErrorCode::BUSY),
+        };
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::REPORT)
+                    .and_then(|buffer| buffer.mut_enter(|buffer| write_report(&report, buffer)))
+                    .unwrap_or(None)
+                    .map_or(CommandReturn::failure(ErrorCode::SIZE), |len| {
+                        CommandReturn::success_u32(len as u32)
+                    })
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+}
+
+impl<'a, A: Alarm<'a>> AirQualityHubClient for AirQualityHubDriver<'a, A> {
+    fn report_ready(&self, report: AirQualityReport) {
+        self.apps.each(|_, app, kernel_data| {
+            if !app.pending {
+                return;
+            }
+            app.pending = false;
+            let len = kernel_data
+                .get_readwrite_processbuffer(rw_allow::REPORT)
+                .and_then(|buffer| buffer.mut_enter(|buffer| write_report(&report, buffer)))
+                .unwrap_or(None);
+            let status = into_statuscode(len.map(|_| ()).ok_or(ErrorCode::SIZE));
+            kernel_data
+                .schedule_upcall(0, (status, len.unwrap_or(0), 0))
+                .ok();
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for AirQualityHubDriver<'a, A> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Request the next report.
+    /// - `2`: Write the last report into the buffer now.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self
+                .apps
+                .enter(processid, |app, _| {
+                    if app.pending {
+                        Err(ErrorCode::BUSY)
+                    } else {
+                        app.pending = true;
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+                .into(),
+            2 => self.last_report(processid),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockAlarm;
+    use std::boxed::Box;
+    use std::format;
+
+    /// A sensor whose reading completes when `complete` is called.
+    struct MockSensor {
+        value: Result<u32, ErrorCode>,
+        /// The gas being read.
+        reading: Cell<Option<Gas>>,
+        client: OptionalCell<&'static dyn AirQualityClient>,
+    }
+
+    impl MockSensor {
+        fn new(value: Result<u32, ErrorCode>) -> &'static MockSensor {
+            Box::leak(Box::new(MockSensor {
+                value,
+                reading: Cell::new(None),
+                client: OptionalCell::empty(),
+            }))
+        }
+
+        fn read(&self, gas: Gas) -> Result<(), ErrorCode> {
+            if self.reading.get().is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+            self.reading.set(Some(gas));
+            Ok(())
+        }
+
+        /// Completes the reading, returning whether one was in progress.
+        fn complete(&self) -> bool {
+            match self.reading.take() {
+                Some(gas) => {
+                    self.client.map(|client| match gas {
+                        Gas::Co2 => client.co2_data_available(self.value),
+                        Gas::Tvoc => client.tvoc_data_available(self.value),
+                    });
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    impl AirQualityDriver<'static> for MockSensor {
+        fn set_client(&self, client: &'static dyn AirQualityClient) {
+            self.client.set(client);
+        }
+        fn specify_environment(&self, _: Option<i32>, _: Option<u32>) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+        fn read_co2(&self) -> Result<(), ErrorCode> {
+            self.read(Gas::Co2)
+        }
+        fn read_tvoc(&self) -> Result<(), ErrorCode> {
+            self.read(Gas::Tvoc)
+        }
+    }
+
+    struct Client {
+        reports: Cell<usize>,
+    }
+
+    impl AirQualityHubClient for Client {
+        fn report_ready(&self, _report: AirQualityReport) {
+            self.reports.set(self.reports.get() + 1);
+        }
+    }
+
+    fn setup(
+        sources: std::vec::Vec<AirQualitySource<'static>>,
+    ) -> (
+        &'static AirQualityHub<'static, MockAlarm<'static>>,
+        &'static MockAlarm<'static>,
+        &'static Client,
+    ) {
+        let sources = Box::leak(sources.into_boxed_slice());
+        let alarm = Box::leak(Box::new(MockAlarm::new()));
+        let hub = Box::leak(Box::new(AirQualityHub::new(sources, alarm, 1000)));
+        let client = Box::leak(Box::new(Client {
+            reports: Cell::new(0),
+        }));
+        for source in sources.iter() {
+            source.sensor.set_client(hub);
+        }
+        alarm.set_alarm_client(hub);
+        hub.set_client(client);
+        (hub, alarm, client)
+    }
+
+    #[test]
+    fn averages_redundant_sensors() {
+        let ccs811 = MockSensor::new(Ok(400));
+        let scd30 = MockSensor::new(Ok(415));
+        let broken = MockSensor::new(Err(ErrorCode::FAIL));
+        let (hub, _alarm, client) = setup(std::vec![
+            AirQualitySource::new(Gas::Co2, ccs811),
+            AirQualitySource::new(Gas::Tvoc, ccs811),
+            AirQualitySource::new(Gas::Co2, scd30),
+            AirQualitySource::new(Gas::Co2, broken),
+        ]);
+        hub.start();
+
+        // The sources are read one at a time, and reported together.
+        for sensor in [ccs811, ccs811, scd30, broken] {
+            assert_eq!(client.reports.get(), 0);
+            assert!(sensor.complete());
+        }
+        assert_eq!(client.reports.get(), 1);
+        assert_eq!(
+            hub.report(),
+            Some(AirQualityReport {
+                co2: Some(GasReading {
+                    value: 408,
+                    sensors: 2
+                }),
+                tvoc: Some(GasReading {
+                    value: 400,
+                    sensors: 1
+                }),
+                failed: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn unanswered_source_fails_at_next_poll() {
+        let stuck = MockSensor::new(Ok(0));
+        let sgp30 = MockSensor::new(Ok(12));
+        let (hub, alarm, client) = setup(std::vec![
+            AirQualitySource::new(Gas::Tvoc, sgp30),
+            AirQualitySource::new(Gas::Co2, stuck),
+        ]);
+        hub.start();
+        assert!(sgp30.complete());
+        assert_eq!(client.reports.get(), 0);
+
+        alarm.fire();
+        assert_eq!(client.reports.get(), 1);
+        let report = hub.report().unwrap();
+        assert_eq!(report.co2, None);
+        assert_eq!(report.failed, 1);
+
+        // The next poll has started, and the stuck sensor is still busy.
+        assert!(sgp30.complete());
+        assert_eq!(client.reports.get(), 2);
+        assert_eq!(hub.report().unwrap().failed, 1);
+    }
+
+    #[test]
+    fn report_json() {
+        let report = AirQualityReport {
+            co2: Some(GasReading {
+                value: 412,
+                sensors: 2,
+            }),
+            tvoc: None,
+            failed: 1,
+        };
+        let json = "{\"co2\":{\"ppm\":412,\"sensors\":2},\"tvoc\":null,\"failed\":1}";
+        assert_eq!(format!("{}", report), json);
+
+        let mut buffer = [0; 64];
+        assert_eq!(
+            write_report(&report, (&mut buffer[..]).into()),
+            Some(json.len())
+        );
+        assert_eq!(&buffer[..json.len()], json.as_bytes());
+
+        let mut buffer = [0; 16];
+        assert_eq!(write_report(&report, (&mut buffer[..]).into()), None);
+    }
+}
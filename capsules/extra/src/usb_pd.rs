@@ -0,0 +1,162 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Provides userspace with the power contract of a USB Type-C port.
+//!
+//! Requesting a voltage the system cannot handle can damage it, so boards
+//! should only give this driver to a trusted process, with the board's
+//! `SyscallFilter`.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! * `0`: called when a command completes, with the status code, and for
+//!   command `1` the voltage in mV and the current in mA of the contract.
+//! * `1`: called when the contract changes.
+//!
+//! ### `command` System Call
+//!
+//! * `0`: check whether the driver exists
+//! * `1`: read the power contract
+//! * `2`: request a fixed supply of argument 1 mV, for a current of argument
+//!   2 mA
+//! * `3`: make the port the power sink
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::usb_pd::UsbPdController` trait.
+//!
+//! ```rust
+//! let usb_pd = components::usb_pd::UsbPdComponent::new(
+//!     board_kernel,
+//!     capsules_extra::usb_pd::DRIVER_NUM,
+//!     tps65987d,
+//! )
+//! .finalize(components::usb_pd_component_static!());
+//! ```
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::usb_pd::{PowerContract, UsbPdClient, UsbPdController};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UsbPd as usize;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    pub const COMMAND_COMPLETE: usize = 0;
+    pub const CONTRACT_CHANGED: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct UsbPdDriver<'a> {
+    controller: &'a dyn UsbPdController<'a>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The process whose command is in progress.
+    processid: OptionalCell<ProcessId>,
+}
+
+impl<'a> UsbPdDriver<'a> {
+    pub fn new(
+        controller: &'a dyn UsbPdController<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> UsbPdDriver<'a> {
+        UsbPdDriver {
+            controller,
+            apps: grant,
+            processid: OptionalCell::empty(),
+        }
+    }
+
+    fn complete(&self, result: Result<(), ErrorCode>, voltage_mv: u32, current_ma: u32) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcall::COMMAND_COMPLETE,
+                        (
+                            into_statuscode(result),
+                            voltage_mv as usize,
+                            current_ma as usize,
+                        ),
+                    )
+                    .ok();
+            });
+        });
+    }
+}
+
+impl SyscallDriver for UsbPdDriver<'_> {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Read the power contract.
+    /// - `2`: Request a fixed supply of `data1` mV for `data2` mA.
+    /// - `3`: Make the port the power sink.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if self.processid.is_some() {
+            return CommandReturn::failure(ErrorCode::BUSY);
+        }
+        let result = match command_num {
+            1 => self.controller.read_contract(),
+            2 => match (u32::try_from(data1), u32::try_from(data2)) {
+                (Ok(voltage_mv), Ok(current_ma)) => {
+                    self.controller.request_power(voltage_mv, current_ma)
+                }
+                _ => Err(ErrorCode::INVAL),
+            },
+            3 => self.controller.set_sink(),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        if result.is_ok() {
+            self.processid.set(processid);
+        }
+        result.into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl UsbPdClient for UsbPdDriver<'_> {
+    fn contract_read(&self, result: Result<Option<PowerContract>, ErrorCode>) {
+        match result {
+            Ok(Some(contract)) => self.complete(Ok(()), contract.voltage_mv, contract.current_ma),
+            Ok(None) => self.complete(Ok(()), 0, 0),
+            Err(e) => self.complete(Err(e), 0, 0),
+        }
+    }
+
+    fn command_complete(&self, result: Result<(), ErrorCode>) {
+        self.complete(result, 0, 0);
+    }
+
+    fn contract_changed(&self) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::CONTRACT_CHANGED, (0, 0, 0))
+                .ok();
+        });
+    }
+}
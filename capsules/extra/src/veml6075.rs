@@ -0,0 +1,357 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for the Vishay VEML6075 UV-A and UV-B light sensor.
+//!
+//! <https://www.vishay.com/docs/84304/veml6075.pdf>
+//!
+//! The sensor measures UV-A (365 nm) and UV-B (330 nm) light, and two
+//! compensation channels for the visible and infrared light the UV channels
+//! also see. The UV index is computed from the four counts as in Vishay's
+//! application note "Designing the VEML6075 into an Application"
+//! (<https://www.vishay.com/docs/84339/designingveml6075.pdf>), with the
+//! coefficients for an open sensor without a diffuser or cover:
+//!
+//! ```text
+//! UVAcalc = UVA - 2.22 * UVcomp1 - 1.33 * UVcomp2
+//! UVBcalc = UVB - 2.95 * UVcomp1 - 1.74 * UVcomp2
+//! UVI = (UVAcalc * 0.001461 + UVBcalc * 0.002591) / 2
+//! ```
+//!
+//! The responsivities are those at a 100 ms integration time in normal
+//! dynamic, and scale with the integration time and dynamic setting.
+//!
+//! In the default continuous mode the sensor measures all the time, and a
+//! reading returns the last measurement, after waiting for one integration
+//! time if the configuration just changed. In active force mode the sensor
+//! only measures when a reading is requested, and waits for the integration
+//! time each time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let veml6075 = components::veml6075::Veml6075Component::new(
+//!     mux_i2c,
+//!     capsules_extra::veml6075::BASE_ADDR,
+//!     mux_alarm,
+//! )
+//! .finalize(components::veml6075_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     nrf52840::i2c::TWI,
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::sensors::{UvIndex, UvIndexClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// I2C address of the VEML6075.
+pub const BASE_ADDR: u8 = 0x10;
+
+/// Size of the buffer the driver needs: a command code and a 16-bit value.
+pub const BUFFER_SIZE: usize = 3;
+
+const REG_UV_CONF: u8 = 0x00;
+/// The UV-A, UV-B, UV-comp1 and UV-comp2 counts, in the order they are read.
+const REG_COUNTS: [u8; 4] = [0x07, 0x09, 0x0A, 0x0B];
+
+const UV_CONF_AF: u8 = 1 << 1;
+const UV_CONF_TRIG: u8 = 1 << 2;
+const UV_CONF_HD: u8 = 1 << 3;
+const UV_CONF_IT_SHIFT: u8 = 4;
+
+/// Integration time of the measurements.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IntegrationTime {
+    Ms50 = 0,
+    Ms100 = 1,
+    Ms200 = 2,
+    Ms400 = 3,
+    Ms800 = 4,
+}
+
+impl IntegrationTime {
+    pub fn ms(self) -> u32 {
+        50 << self as u32
+    }
+}
+
+/// The counts of a measurement.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct UvCounts {
+    pub uva: u16,
+    pub uvb: u16,
+    pub uv_comp1: u16,
+    pub uv_comp2: u16,
+}
+
+/// The UV index times ten of `counts`, measured with `integration_time` in
+/// high dynamic mode or not.
+pub fn uv_index_tenths(
+    counts: &UvCounts,
+    integration_time: IntegrationTime,
+    high_dynamic: bool,
+) -> u16 {
+    // The counts compensated for visible and infrared light, times 100.
+    let comp1 = counts.uv_comp1 as i64;
+    let comp2 = counts.uv_comp2 as i64;
+    let uva = (counts.uva as i64 * 100 - 222 * comp1 - 133 * comp2).max(0);
+    let uvb = (counts.uvb as i64 * 100 - 295 * comp1 - 174 * comp2).max(0);
+
+    // The responsivities are per count in millionths at 100 ms. The sum is
+    // scaled by 100 for the counts, 10^6 for the responsivities, and 2 for
+    // the average of UV-A and UV-B. High dynamic halves the sensitivity.
+    let sum = uva * 1461 + uvb * 2591;
+    let numerator = sum * 10 * 100 * if high_dynamic { 2 } else { 1 };
+    let denominator = 100 * 1_000_000 * 2 * integration_time.ms() as i64;
+    ((numerator + denominator / 2) / denominator).min(u16::MAX as i64) as u16
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Writing `UV_CONF`, to then wait for a measurement.
+    Configuring,
+    /// Waiting for the sensor to integrate.
+    Integrating,
+    /// Reading count `n` of `REG_COUNTS`.
+    Reading(usize),
+}
+
+pub struct Veml6075<'a, A: Alarm<'a>, I: I2CDevice> {
+    i2c: &'a I,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    integration_time: Cell<IntegrationTime>,
+    active_force: Cell<bool>,
+    high_dynamic: Cell<bool>,
+    /// Whether the configuration changed since `UV_CONF` was written.
+    config_changed: Cell<bool>,
+    counts: Cell<UvCounts>,
+    /// The counts of the last measurement.
+    last_counts: OptionalCell<UvCounts>,
+    client: OptionalCell<&'a dyn UvIndexClient>,
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> Veml6075<'a, A, I> {
+    pub fn new(i2c: &'a I, alarm: &'a A, buffer: &'static mut [u8]) -> Veml6075<'a, A, I> {
+        Veml6075 {
+            i2c,
+            alarm,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            integration_time: Cell::new(IntegrationTime::Ms100),
+            active_force: Cell::new(false),
+            high_dynamic: Cell::new(false),
+            // The sensor is shut down after power on.
+            config_changed: Cell::new(true),
+            counts: Cell::new(UvCounts::default()),
+            last_counts: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Set the integration time of the measurements that follow.
+    pub fn set_integration_time(&self, integration_time: IntegrationTime) {
+        self.integration_time.set(integration_time);
+        self.config_changed.set(true);
+    }
+
+    /// Measure only when a reading is requested, instead of continuously.
+    pub fn set_active_force(&self, active_force: bool) {
+        self.active_force.set(active_force);
+        self.config_changed.set(true);
+    }
+
+    /// Use the high dynamic setting, which halves the sensitivity for
+    /// bright light.
+    pub fn set_high_dynamic(&self, high_dynamic: bool) {
+        self.high_dynamic.set(high_dynamic);
+        self.config_changed.set(true);
+    }
+
+    /// The counts of the last measurement, if there was one.
+    pub fn counts(&self) -> Option<UvCounts> {
+        self.last_counts.extract()
+    }
+
+    fn uv_conf(&self) -> u8 {
+        let mut conf = (self.integration_time.get() as u8) << UV_CONF_IT_SHIFT;
+        if self.active_force.get() {
+            conf |= UV_CONF_AF;
+        }
+        if self.high_dynamic.get() {
+            conf |= UV_CONF_HD;
+        }
+        conf
+    }
+
+    fn write_uv_conf(&self, conf: u8) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = REG_UV_CONF;
+            buffer[1] = conf;
+            buffer[2] = 0;
+            self.i2c.write(buffer, 3).map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e.into()
+            })
+        })
+    }
+
+    fn read_count(&self, index: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            buffer[0] = REG_COUNTS[index];
+            match self.i2c.write_read(buffer, 1, 2) {
+                Ok(()) => {
+                    self.state.set(State::Reading(index));
+                    Ok(())
+                }
+                Err((e, buffer)) => {
+                    self.buffer.replace(buffer);
+                    Err(e.into())
+                }
+            }
+        })
+    }
+
+    fn wait_integration(&self) {
+        // Leave a margin for the oscillator of the sensor.
+        let ms = self.integration_time.get().ms();
+        self.state.set(State::Integrating);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms + ms / 8));
+    }
+
+    fn finish(&self, result: Result<u16, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        self.client.map(|client| client.uv_index_tenths(result));
+    }
+
+    fn check(&self, result: Result<(), ErrorCode>) {
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> UvIndex<'a> for Veml6075<'a, A, I> {
+    fn set_client(&self, client: &'a dyn UvIndexClient) {
+        self.client.set(client);
+    }
+
+    fn read_uv_index(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.i2c.enable();
+        let result = if self.active_force.get() {
+            self.config_changed.set(false);
+            self.state.set(State::Configuring);
+            self.write_uv_conf(self.uv_conf() | UV_CONF_TRIG)
+        } else if self.config_changed.take() {
+            self.state.set(State::Configuring);
+            self.write_uv_conf(self.uv_conf())
+        } else {
+            self.read_count(0)
+        };
+        result.map_err(|e| {
+            self.state.set(State::Idle);
+            self.i2c.disable();
+            e
+        })
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> I2CClient for Veml6075<'a, A, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let value = u16::from_le_bytes([buffer[0], buffer[1]]);
+        self.buffer.replace(buffer);
+        if let Err(e) = status {
+            self.finish(Err(e.into()));
+            return;
+        }
+
+        match self.state.get() {
+            State::Configuring => self.wait_integration(),
+            State::Reading(index) => {
+                let mut counts = self.counts.get();
+                match index {
+                    0 => counts.uva = value,
+                    1 => counts.uvb = value,
+                    2 => counts.uv_comp1 = value,
+                    _ => counts.uv_comp2 = value,
+                }
+                self.counts.set(counts);
+
+                if index + 1 < REG_COUNTS.len() {
+                    self.check(self.read_count(index + 1));
+                } else {
+                    self.last_counts.set(counts);
+                    self.finish(Ok(uv_index_tenths(
+                        &counts,
+                        self.integration_time.get(),
+                        self.high_dynamic.get(),
+                    )));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, I: I2CDevice> AlarmClient for Veml6075<'a, A, I> {
+    fn alarm(&self) {
+        if self.state.get() == State::Integrating {
+            self.check(self.read_count(0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_uv_index() {
+        // UVAcalc = 1000 - 222 - 66.5 = 711.5, UVBcalc = 800 - 295 - 87 =
+        // 418, and UVI = (711.5 * 0.001461 + 418 * 0.002591) / 2 = 1.06.
+        let counts = UvCounts {
+            uva: 1000,
+            uvb: 800,
+            uv_comp1: 100,
+            uv_comp2: 50,
+        };
+        assert_eq!(uv_index_tenths(&counts, IntegrationTime::Ms100, false), 11);
+
+        // Four times the integration time gives four times the counts for
+        // the same light.
+        assert_eq!(uv_index_tenths(&counts, IntegrationTime::Ms400, false), 3);
+        assert_eq!(uv_index_tenths(&counts, IntegrationTime::Ms100, true), 21);
+
+        // Visible and infrared light alone is no UV.
+        let counts = UvCounts {
+            uva: 200,
+            uvb: 250,
+            uv_comp1: 100,
+            uv_comp2: 50,
+        };
+        assert_eq!(uv_index_tenths(&counts, IntegrationTime::Ms100, false), 0);
+
+        // Full scale at the shortest integration time in high dynamic.
+        let counts = UvCounts {
+            uva: u16::MAX,
+            uvb: u16::MAX,
+            uv_comp1: 0,
+            uv_comp2: 0,
+        };
+        assert_eq!(uv_index_tenths(&counts, IntegrationTime::Ms50, true), 5311);
+    }
+}
@@ -0,0 +1,570 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Animations for strips of addressable RGB LEDs, such as WS2812B strips.
+//!
+//! An animation is a pattern played over a sequence of frames. Each frame is
+//! a color and how long the step showing it lasts. The frames come either
+//! from a static array provided by the board (for example [`RAINBOW`]) or
+//! from a buffer shared by the application. The supported patterns are:
+//!
+//! - Rainbow cycle: LED `i` shows frame `i`, and the frames scroll along the
+//!   strip by one LED every step.
+//! - Color wipe: the LEDs change to the color of the current frame one at a
+//!   time, then the next frame starts.
+//! - Theater chase: every third LED shows the color of the current frame and
+//!   the lit LEDs move by one every step. Each frame lasts three steps.
+//!
+//! The capsule drives the LEDs through the [`LedStrip`] trait, which the
+//! driver for the strip implements. Only one process can run an animation at
+//! a time; it keeps the strip until it stops the animation or exits.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let animation = components::ws2812b_animation::LedAnimationComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ws2812b_animation::DRIVER_NUM,
+//!     strip,
+//!     mux_alarm,
+//!     &capsules_extra::ws2812b_animation::RAINBOW,
+//! )
+//! .finalize(components::led_animation_component_static!(
+//!     Ws2812b<'static>,
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::LedAnimation as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Animation frames, `FRAME_SIZE` bytes each.
+    pub const FRAMES: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Size of a frame shared by an application: red, green, blue and the delay
+/// in milliseconds as a little-endian `u16`.
+pub const FRAME_SIZE: usize = 5;
+
+/// Number of steps each frame of a theater chase lasts.
+const THEATER_CHASE_STEPS: usize = 3;
+
+/// Speed, in percent, that animations play at unless changed.
+const DEFAULT_SPEED: u32 = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RGB8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl RGB8 {
+    pub const OFF: RGB8 = RGB8::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> RGB8 {
+        RGB8 { r, g, b }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AnimationFrame {
+    pub rgb: RGB8,
+    pub delay_ms: u16,
+}
+
+impl AnimationFrame {
+    pub const fn new(rgb: RGB8, delay_ms: u16) -> AnimationFrame {
+        AnimationFrame { rgb, delay_ms }
+    }
+
+    fn from_bytes(bytes: [u8; FRAME_SIZE]) -> AnimationFrame {
+        AnimationFrame::new(
+            RGB8::new(bytes[0], bytes[1], bytes[2]),
+            u16::from_le_bytes([bytes[3], bytes[4]]),
+        )
+    }
+}
+
+/// Twelve hues around the color wheel, 50 ms per step.
+pub const RAINBOW: [AnimationFrame; 12] = [
+    AnimationFrame::new(RGB8::new(255, 0, 0), 50),
+    AnimationFrame::new(RGB8::new(255, 128, 0), 50),
+    AnimationFrame::new(RGB8::new(255, 255, 0), 50),
+    AnimationFrame::new(RGB8::new(128, 255, 0), 50),
+    AnimationFrame::new(RGB8::new(0, 255, 0), 50),
+    AnimationFrame::new(RGB8::new(0, 255, 128), 50),
+    AnimationFrame::new(RGB8::new(0, 255, 255), 50),
+    AnimationFrame::new(RGB8::new(0, 128, 255), 50),
+    AnimationFrame::new(RGB8::new(0, 0, 255), 50),
+    AnimationFrame::new(RGB8::new(128, 0, 255), 50),
+    AnimationFrame::new(RGB8::new(255, 0, 255), 50),
+    AnimationFrame::new(RGB8::new(255, 0, 128), 50),
+];
+
+/// A strip of individually addressable RGB LEDs.
+pub trait LedStrip<'a> {
+    fn set_client(&self, client: &'a dyn LedStripClient);
+
+    /// Returns the number of LEDs on the strip.
+    fn num_leds(&self) -> usize;
+
+    /// Sets the color of one LED. The color is shown after the next call
+    /// to `show`.
+    fn set_color(&self, index: usize, color: RGB8) -> Result<(), ErrorCode>;
+
+    /// Sends the colors to the strip. On success, `show_done` is called
+    /// once the strip is updated.
+    fn show(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait LedStripClient {
+    fn show_done(&self, result: Result<(), ErrorCode>);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Animation {
+    RainbowCycle,
+    ColorWipe,
+    TheaterChase,
+}
+
+impl Animation {
+    fn from_usize(animation: usize) -> Option<Animation> {
+        match animation {
+            0 => Some(Animation::RainbowCycle),
+            1 => Some(Animation::ColorWipe),
+            2 => Some(Animation::TheaterChase),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FrameSource {
+    Static,
+    Process,
+}
+
+/// Returns the index of the frame that sets the delay of `step`.
+fn frame_index(animation: Animation, step: usize, num_leds: usize) -> usize {
+    match animation {
+        Animation::RainbowCycle => step,
+        Animation::ColorWipe => step / num_leds,
+        Animation::TheaterChase => step / THEATER_CHASE_STEPS,
+    }
+}
+
+/// Returns the color of `led` at `step`. `frame` must accept any index.
+fn led_color(
+    animation: Animation,
+    step: usize,
+    led: usize,
+    num_leds: usize,
+    frame: &dyn Fn(usize) -> AnimationFrame,
+) -> RGB8 {
+    let index = frame_index(animation, step, num_leds);
+    match animation {
+        Animation::RainbowCycle => frame(index + led).rgb,
+        Animation::ColorWipe => {
+            if led <= step % num_leds {
+                frame(index).rgb
+            } else if index == 0 {
+                RGB8::OFF
+            } else {
+                frame(index - 1).rgb
+            }
+        }
+        Animation::TheaterChase => {
+            if led % THEATER_CHASE_STEPS == step % THEATER_CHASE_STEPS {
+                frame(index).rgb
+            } else {
+                RGB8::OFF
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct LedAnimation<'a, W: LedStrip<'a>, A: Alarm<'a>> {
+    strip: &'a W,
+    alarm: &'a A,
+    frames: &'static [AnimationFrame],
+    apps: Grant<App, UpcallCount<0>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+    owner: OptionalCell<ProcessId>,
+    animation: OptionalCell<Animation>,
+    source: Cell<FrameSource>,
+    step: Cell<usize>,
+    speed: Cell<u32>,
+    override_color: OptionalCell<(RGB8, u32)>,
+    next_delay_ms: OptionalCell<u32>,
+    showing: Cell<bool>,
+    refresh_pending: Cell<bool>,
+}
+
+impl<'a, W: LedStrip<'a>, A: Alarm<'a>> LedAnimation<'a, W, A> {
+    pub fn new(
+        strip: &'a W,
+        alarm: &'a A,
+        frames: &'static [AnimationFrame],
+        grant: Grant<App, UpcallCount<0>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+    ) -> LedAnimation<'a, W, A> {
+        LedAnimation {
+            strip,
+            alarm,
+            frames,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            animation: OptionalCell::empty(),
+            source: Cell::new(FrameSource::Static),
+            step: Cell::new(0),
+            speed: Cell::new(DEFAULT_SPEED),
+            override_color: OptionalCell::empty(),
+            next_delay_ms: OptionalCell::empty(),
+            showing: Cell::new(false),
+            refresh_pending: Cell::new(false),
+        }
+    }
+
+    /// Gives the strip to `process_id` unless another process that still
+    /// exists holds it.
+    fn claim(&self, process_id: ProcessId) -> Result<(), ErrorCode> {
+        let held_by_other = self.owner.map_or(false, |owner| {
+            *owner != process_id && self.apps.enter(*owner, |_, _| ()).is_ok()
+        });
+        if held_by_other {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.owner.set(process_id);
+            Ok(())
+        }
+    }
+
+    fn start(
+        &self,
+        animation: Animation,
+        source: FrameSource,
+        process_id: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        if self.strip.num_leds() == 0 {
+            return Err(ErrorCode::NODEVICE);
+        }
+        let has_frames = match source {
+            FrameSource::Static => !self.frames.is_empty(),
+            FrameSource::Process => self
+                .apps
+                .enter(process_id, |_, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::FRAMES)
+                        .map_or(false, |frames| frames.len() >= FRAME_SIZE)
+                })
+                .unwrap_or(false),
+        };
+        if !has_frames {
+            return Err(ErrorCode::INVAL);
+        }
+        self.claim(process_id)?;
+
+        self.animation.set(animation);
+        self.source.set(source);
+        self.step.set(0);
+        self.refresh();
+        Ok(())
+    }
+
+    fn stop(&self, process_id: ProcessId) -> Result<(), ErrorCode> {
+        self.claim(process_id)?;
+        self.owner.clear();
+        self.animation.clear();
+        self.override_color.clear();
+        self.refresh();
+        Ok(())
+    }
+
+    fn show_color(&self, color: RGB8, duration_ms: u32) {
+        self.override_color.set((color, duration_ms));
+        self.refresh();
+    }
+
+    fn scaled_delay_ms(&self, delay_ms: u16) -> u32 {
+        (delay_ms as u32 * DEFAULT_SPEED / self.speed.get()).max(1)
+    }
+
+    /// Shows the next step as soon as the strip is free.
+    fn refresh(&self) {
+        let _ = self.alarm.disarm();
+        if self.showing.get() {
+            self.refresh_pending.set(true);
+        } else {
+            self.show_next();
+        }
+    }
+
+    /// Sets the colors of every LED for `step` of `animation`, and returns
+    /// the delay of the step.
+    fn render(
+        &self,
+        animation: Animation,
+        step: usize,
+        num_frames: usize,
+        frame: &dyn Fn(usize) -> AnimationFrame,
+    ) -> u16 {
+        let num_leds = self.strip.num_leds();
+        let frame = |index: usize| frame(index % num_frames);
+        for led in 0..num_leds {
+            let _ = self
+                .strip
+                .set_color(led, led_color(animation, step, led, num_leds, &frame));
+        }
+        frame(frame_index(animation, step, num_leds)).delay_ms
+    }
+
+    /// Renders `step` of `animation` from the frames of its source. Returns
+    /// `None` if the frames are no longer available.
+    fn render_step(&self, animation: Animation, step: usize) -> Option<u16> {
+        match self.source.get() {
+            FrameSource::Static => {
+                Some(self.render(animation, step, self.frames.len(), &|i| self.frames[i]))
+            }
+            FrameSource::Process => self.owner.and_then(|owner| {
+                self.apps
+                    .enter(owner, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::FRAMES)
+                            .and_then(|frames| {
+                                frames.enter(|data| {
+                                    let num_frames = data.len() / FRAME_SIZE;
+                                    if num_frames == 0 {
+                                        None
+                                    } else {
+                                        Some(self.render(animation, step, num_frames, &|i| {
+                                            read_frame(data, i)
+                                        }))
+                                    }
+                                })
+                            })
+                            .ok()
+                            .flatten()
+                    })
+                    .ok()
+                    .flatten()
+            }),
+        }
+    }
+
+    fn show_next(&self) {
+        let num_leds = self.strip.num_leds();
+        let delay_ms = if let Some((color, duration_ms)) = self.override_color.take() {
+            for led in 0..num_leds {
+                let _ = self.strip.set_color(led, color);
+            }
+            Some(duration_ms)
+        } else if let Some(animation) = self.animation.extract() {
+            let step = self.step.get();
+            self.step.set(step.wrapping_add(1));
+            match self.render_step(animation, step) {
+                Some(delay_ms) => Some(self.scaled_delay_ms(delay_ms)),
+                None => {
+                    // The process exited or withdrew its frames.
+                    self.animation.clear();
+                    self.owner.clear();
+                    self.show_off();
+                    None
+                }
+            }
+        } else {
+            // Nothing is playing, release the strip.
+            self.owner.clear();
+            self.show_off();
+            None
+        };
+
+        self.next_delay_ms.insert(delay_ms);
+        self.showing.set(true);
+        if let Err(e) = self.strip.show() {
+            self.show_done(Err(e));
+        }
+    }
+
+    fn show_off(&self) {
+        for led in 0..self.strip.num_leds() {
+            let _ = self.strip.set_color(led, RGB8::OFF);
+        }
+    }
+}
+
+fn read_frame(data: &ReadableProcessSlice, index: usize) -> AnimationFrame {
+    let mut bytes = [0; FRAME_SIZE];
+    if let Some(frame) = data.get(index * FRAME_SIZE..(index + 1) * FRAME_SIZE) {
+        frame.copy_to_slice(&mut bytes);
+    }
+    AnimationFrame::from_bytes(bytes)
+}
+
+impl<'a, W: LedStrip<'a>, A: Alarm<'a>> LedStripClient for LedAnimation<'a, W, A> {
+    fn show_done(&self, _result: Result<(), ErrorCode>) {
+        self.showing.set(false);
+        if self.refresh_pending.take() {
+            self.show_next();
+        } else if let Some(delay_ms) = self.next_delay_ms.take() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay_ms));
+        }
+    }
+}
+
+impl<'a, W: LedStrip<'a>, A: Alarm<'a>> time::AlarmClient for LedAnimation<'a, W, A> {
+    fn alarm(&self) {
+        if !self.showing.get() {
+            self.show_next();
+        }
+    }
+}
+
+impl<'a, W: LedStrip<'a>, A: Alarm<'a>> SyscallDriver for LedAnimation<'a, W, A> {
+    /// Control the animation.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start the animation `data1` (0: rainbow cycle, 1: color wipe,
+    ///   2: theater chase). `data2` selects the frames: 0 for the frames
+    ///   built into the kernel, 1 for the frames shared with allow.
+    /// - `2`: Stop the animation and turn the LEDs off.
+    /// - `3`: Set the speed, in percent of the speed given by the frames.
+    /// - `4`: Show the color `data1` (`0xRRGGBB`) on every LED for `data2`
+    ///   milliseconds, then continue the animation.
+    /// - `5`: Return the number of LEDs.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        process_id: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                let source = match data2 {
+                    0 => FrameSource::Static,
+                    1 => FrameSource::Process,
+                    _ => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                match Animation::from_usize(data1) {
+                    Some(animation) => {
+                        CommandReturn::from(self.start(animation, source, process_id))
+                    }
+                    None => CommandReturn::failure(ErrorCode::INVAL),
+                }
+            }
+
+            2 => CommandReturn::from(self.stop(process_id)),
+
+            3 => {
+                if data1 == 0 || data1 > u16::MAX as usize {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                } else {
+                    self.speed.set(data1 as u32);
+                    CommandReturn::success()
+                }
+            }
+
+            4 => match self.claim(process_id) {
+                Ok(()) => {
+                    let color = RGB8::new((data1 >> 16) as u8, (data1 >> 8) as u8, data1 as u8);
+                    self.show_color(color, data2 as u32);
+                    CommandReturn::success()
+                }
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            5 => CommandReturn::success_u32(self.strip.num_leds() as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RGB8 = RGB8::new(255, 0, 0);
+    const GREEN: RGB8 = RGB8::new(0, 255, 0);
+    const BLUE: RGB8 = RGB8::new(0, 0, 255);
+    const FRAMES: [AnimationFrame; 3] = [
+        AnimationFrame::new(RED, 10),
+        AnimationFrame::new(GREEN, 20),
+        AnimationFrame::new(BLUE, 30),
+    ];
+
+    fn strip(animation: Animation, step: usize, num_leds: usize) -> [RGB8; 4] {
+        let frame = |i: usize| FRAMES[i % FRAMES.len()];
+        let mut leds = [RGB8::OFF; 4];
+        for (led, color) in leds.iter_mut().enumerate().take(num_leds) {
+            *color = led_color(animation, step, led, num_leds, &frame);
+        }
+        leds
+    }
+
+    #[test]
+    fn rainbow_cycle_scrolls() {
+        assert_eq!(
+            strip(Animation::RainbowCycle, 0, 4),
+            [RED, GREEN, BLUE, RED]
+        );
+        assert_eq!(
+            strip(Animation::RainbowCycle, 1, 4),
+            [GREEN, BLUE, RED, GREEN]
+        );
+        assert_eq!(frame_index(Animation::RainbowCycle, 2, 4), 2);
+    }
+
+    #[test]
+    fn color_wipe_fills_one_led_per_step() {
+        let off = RGB8::OFF;
+        assert_eq!(strip(Animation::ColorWipe, 0, 4), [RED, off, off, off]);
+        assert_eq!(strip(Animation::ColorWipe, 3, 4), [RED, RED, RED, RED]);
+        assert_eq!(strip(Animation::ColorWipe, 5, 4), [GREEN, GREEN, RED, RED]);
+        assert_eq!(frame_index(Animation::ColorWipe, 5, 4), 1);
+    }
+
+    #[test]
+    fn theater_chase_lights_every_third_led() {
+        let off = RGB8::OFF;
+        assert_eq!(strip(Animation::TheaterChase, 0, 4), [RED, off, off, RED]);
+        assert_eq!(strip(Animation::TheaterChase, 4, 4), [off, GREEN, off, off]);
+        assert_eq!(frame_index(Animation::TheaterChase, 4, 4), 1);
+    }
+
+    #[test]
+    fn frame_from_process_bytes() {
+        assert_eq!(
+            AnimationFrame::from_bytes([1, 2, 3, 0x34, 0x12]),
+            AnimationFrame::new(RGB8::new(1, 2, 3), 0x1234)
+        );
+    }
+}
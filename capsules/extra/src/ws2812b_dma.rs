@@ -0,0 +1,237 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Driver for WS2812B addressable RGB LED strips, using the MOSI line of a
+//! SPI bus to generate the data signal.
+//!
+//! Each bit sent to the strip is encoded as four SPI bits at 3.2 MHz:
+//! `1110` for a one and `1000` for a zero. The colors of the LEDs are
+//! encoded directly into a buffer holding the whole frame, followed by
+//! enough zeros to latch it, so that updating the strip is a single SPI
+//! transfer. `show` starts the transfer and returns immediately; the SPI
+//! controller's DMA sends the frame, and the processor is free until
+//! `show_done` reports that the buffer can be used for the next frame. On a
+//! 144 LED strip the transfer lasts about 4.6 ms.
+//!
+//! The original WS2812, and NeoPixel strips built with either part, use the
+//! same timing and only need a shorter latch, so this driver works for them
+//! too.
+//!
+//! While a frame is being sent, `set_color` and `show` return `BUSY`.
+//!
+//! The buffer is handed to the SPI controller for DMA, so it must be
+//! `'static` and, for most DMA controllers, 32-bit aligned. Allocate it with
+//! [`DmaBuffer`] and [`buffer_len`], as the component does.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let strip = components::ws2812b_dma::Ws2812bDmaComponent::new(
+//!     spi_mux,
+//!     &nrf52840_peripherals.gpio_port[LED_STRIP_CS],
+//! )
+//! .finalize(components::ws2812b_dma_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     144
+//! ));
+//! ```
+
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::ws2812b_animation::{LedStrip, LedStripClient, RGB8};
+
+/// SPI clock rate: four SPI bits per 1.25 us bit of the strip.
+pub const SPI_RATE: u32 = 3_200_000;
+
+/// Bytes of the buffer for each LED: 24 bits of color, four SPI bits each.
+const BYTES_PER_LED: usize = 12;
+
+/// Zeros sent after the colors to latch them, which must last at least
+/// 280 us.
+const RESET_LEN: usize = 112;
+
+/// Returns the length of the buffer needed for a strip of `num_leds` LEDs.
+pub const fn buffer_len(num_leds: usize) -> usize {
+    num_leds * BYTES_PER_LED + RESET_LEN
+}
+
+/// A buffer aligned for DMA.
+#[repr(C, align(4))]
+pub struct DmaBuffer<const N: usize>(pub [u8; N]);
+
+/// Encodes `byte` into four bytes of SPI data, most significant bit first.
+fn encode(byte: u8, out: &mut [u8]) {
+    for (i, out) in out.iter_mut().enumerate().take(4) {
+        let bits = byte >> (6 - 2 * i);
+        let high = if bits & 0b10 != 0 { 0xE0 } else { 0x80 };
+        let low = if bits & 0b01 != 0 { 0x0E } else { 0x08 };
+        *out = high | low;
+    }
+}
+
+pub struct Ws2812bDma<'a, S: SpiMasterDevice<'a>> {
+    spi: &'a S,
+    buffer: TakeCell<'static, [u8]>,
+    num_leds: usize,
+    client: OptionalCell<&'a dyn LedStripClient>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>> Ws2812bDma<'a, S> {
+    /// `buffer` holds the frame, and its length sets the number of LEDs.
+    pub fn new(spi: &'a S, buffer: &'static mut [u8]) -> Ws2812bDma<'a, S> {
+        let num_leds = buffer.len().saturating_sub(RESET_LEN) / BYTES_PER_LED;
+        let (leds, reset) = buffer.split_at_mut(num_leds * BYTES_PER_LED);
+        for byte in leds.chunks_mut(4) {
+            encode(0, byte);
+        }
+        reset.fill(0);
+
+        Ws2812bDma {
+            spi,
+            buffer: TakeCell::new(buffer),
+            num_leds,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn configure(&self) -> Result<(), ErrorCode> {
+        self.spi
+            .configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading, SPI_RATE)
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> LedStrip<'a> for Ws2812bDma<'a, S> {
+    fn set_client(&self, client: &'a dyn LedStripClient) {
+        self.client.set(client);
+    }
+
+    fn num_leds(&self) -> usize {
+        self.num_leds
+    }
+
+    fn set_color(&self, index: usize, color: RGB8) -> Result<(), ErrorCode> {
+        if index >= self.num_leds {
+            return Err(ErrorCode::INVAL);
+        }
+        self.buffer.map_or(Err(ErrorCode::BUSY), |buffer| {
+            // The strip expects green, red, then blue.
+            let led = &mut buffer[index * BYTES_PER_LED..(index + 1) * BYTES_PER_LED];
+            encode(color.g, &mut led[0..4]);
+            encode(color.r, &mut led[4..8]);
+            encode(color.b, &mut led[8..12]);
+            Ok(())
+        })
+    }
+
+    fn show(&self) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            let len = buffer.len();
+            self.spi
+                .read_write_bytes(buffer, None, len)
+                .map_err(|(e, buffer, _)| {
+                    self.buffer.replace(buffer);
+                    e
+                })
+        })
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> SpiMasterClient for Ws2812bDma<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        _read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(write_buffer);
+        self.client.map(|client| client.show_done(status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use capsules_core::test::mocks::MockSpi;
+    use core::cell::Cell;
+    use std::boxed::Box;
+
+    /// Counts the callbacks into the client, which is all the work done
+    /// by the processor while a frame is sent.
+    struct Client {
+        done: Cell<usize>,
+    }
+
+    impl LedStripClient for Client {
+        fn show_done(&self, result: Result<(), ErrorCode>) {
+            assert_eq!(result, Ok(()));
+            self.done.set(self.done.get() + 1);
+        }
+    }
+
+    const NUM_LEDS: usize = 144;
+
+    fn setup() -> (
+        &'static Ws2812bDma<'static, MockSpi<'static>>,
+        &'static MockSpi<'static>,
+        &'static Client,
+    ) {
+        let spi: &'static MockSpi = Box::leak(Box::default());
+        let buffer = Box::leak(Box::new(DmaBuffer([0xFF; buffer_len(NUM_LEDS)])));
+        let strip = Box::leak(Box::new(Ws2812bDma::new(spi, &mut buffer.0)));
+        let client = Box::leak(Box::new(Client { done: Cell::new(0) }));
+        spi.set_client(strip);
+        strip.set_client(client);
+        (strip, spi, client)
+    }
+
+    #[test]
+    fn encodes_green_red_blue() {
+        let (strip, spi, _) = setup();
+        assert_eq!(strip.num_leds(), NUM_LEDS);
+        assert_eq!(strip.set_color(1, RGB8::new(0xFF, 0x00, 0x81)), Ok(()));
+        assert_eq!(strip.set_color(NUM_LEDS, RGB8::OFF), Err(ErrorCode::INVAL));
+        assert_eq!(strip.show(), Ok(()));
+
+        let frame = spi.complete_with(|tx, _| assert_eq!(tx.as_ptr() as usize % 4, 0));
+        assert_eq!(&frame[..12], &[0x88; 12]);
+        assert_eq!(
+            &frame[12..24],
+            &[0x88, 0x88, 0x88, 0x88, 0xEE, 0xEE, 0xEE, 0xEE, 0xE8, 0x88, 0x88, 0x8E]
+        );
+        assert!(frame[NUM_LEDS * BYTES_PER_LED..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn show_returns_while_frame_is_sent() {
+        let (strip, spi, client) = setup();
+        for led in 0..NUM_LEDS {
+            assert_eq!(strip.set_color(led, RGB8::new(1, 2, 3)), Ok(()));
+        }
+
+        // The whole frame is handed to the controller in one transfer, and
+        // `show` returns before it is sent.
+        assert_eq!(strip.show(), Ok(()));
+        assert_eq!(spi.transfers().len(), 1);
+        assert_eq!(spi.transfers()[0].len(), buffer_len(NUM_LEDS));
+        assert_eq!(client.done.get(), 0);
+
+        // The buffer belongs to the controller until the transfer is done.
+        assert_eq!(strip.set_color(0, RGB8::OFF), Err(ErrorCode::BUSY));
+        assert_eq!(strip.show(), Err(ErrorCode::BUSY));
+        assert_eq!(spi.transfers().len(), 1);
+
+        // Sending the frame takes no further work until it completes.
+        spi.complete(&[]);
+        assert_eq!(client.done.get(), 1);
+        assert_eq!(strip.set_color(0, RGB8::OFF), Ok(()));
+        assert_eq!(strip.show(), Ok(()));
+        assert_eq!(spi.transfers().len(), 2);
+    }
+}
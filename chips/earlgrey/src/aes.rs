@@ -6,12 +6,13 @@
 //!
 //! <https://docs.opentitan.org/hw/ip/aes/doc/>
 
+use crate::clkmgr::{ClockGate, ClockManager, Peripheral};
 use core::cell::Cell;
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::hil::symmetric_encryption;
 use kernel::hil::symmetric_encryption::{AES128_BLOCK_SIZE, AES128_KEY_SIZE};
-use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
     register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
@@ -84,6 +85,7 @@ register_bitfields![u32,
             Key192 = 2,
             Key256 = 4,
         ],
+        SIDELOAD OFFSET(11) NUMBITS(1) [],
         MANUAL_OPERATION OFFSET(15) NUMBITS(1) [],
         FORCE_ZERO_MASKS OFFSET(16) NUMBITS(1) [],
     ],
@@ -123,22 +125,38 @@ pub struct Aes<'a> {
     source: TakeCell<'static, [u8]>,
     dest: TakeCell<'static, [u8]>,
     mode: Cell<Mode>,
+    /// Use the key loaded by the key manager rather than `set_key`.
+    sideload: Cell<bool>,
+
+    clkmgr: &'a ClockManager,
+    /// Held from `enable()` to `disable()`.
+    clock: MapCell<ClockGate<'a>>,
 
     deferred_call: DeferredCall,
 }
 
 impl<'a> Aes<'a> {
-    pub fn new() -> Aes<'a> {
+    pub fn new(clkmgr: &'a ClockManager) -> Aes<'a> {
         Aes {
             registers: AES_BASE,
             client: OptionalCell::empty(),
             source: TakeCell::empty(),
             dest: TakeCell::empty(),
             mode: Cell::new(Mode::IDLE),
+            sideload: Cell::new(false),
+            clkmgr,
+            clock: MapCell::empty(),
             deferred_call: DeferredCall::new(),
         }
     }
 
+    /// Use the key the key manager loaded into the sideload port, see
+    /// [KeyMgr](crate::keymgr::KeyMgr), instead of the one given to
+    /// `set_key`. This takes effect on the next `set_mode_*` call.
+    pub fn set_sideload_key(&self, sideload: bool) {
+        self.sideload.set(sideload);
+    }
+
     pub fn idle(&self) -> bool {
         self.registers.status.is_set(STATUS::IDLE)
     }
@@ -264,6 +282,40 @@ impl<'a> Aes<'a> {
         )
     }
 
+    /// Encrypts or decrypts a single block in place, with the mode, key and
+    /// IV already set. Busy-waits for the result.
+    pub(crate) fn crypt_block(&self, block: &mut [u8; AES128_BLOCK_SIZE]) -> Result<(), ErrorCode> {
+        self.wait_for_input_ready()?;
+        for (i, word) in block.chunks(4).enumerate() {
+            let v = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            match i {
+                0 => self.registers.data_in0.set(v),
+                1 => self.registers.data_in1.set(v),
+                2 => self.registers.data_in2.set(v),
+                3 => self.registers.data_in3.set(v),
+                _ => unreachable!(),
+            }
+        }
+
+        self.wait_for_output_valid()?;
+        for (i, word) in block.chunks_mut(4).enumerate() {
+            let v = match i {
+                0 => self.registers.data_out0.get(),
+                1 => self.registers.data_out1.get(),
+                2 => self.registers.data_out2.get(),
+                3 => self.registers.data_out3.get(),
+                _ => unreachable!(),
+            };
+            word.copy_from_slice(&v.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Returns true if a `crypt` call is waiting to report completion.
+    pub(crate) fn crypt_pending(&self) -> bool {
+        self.deferred_call.is_pending()
+    }
+
     fn do_crypt(
         &self,
         start_index: usize,
@@ -288,6 +340,9 @@ impl<'a> Aes<'a> {
 
 impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
     fn enable(&self) {
+        if self.clock.is_none() {
+            self.clock.put(self.clkmgr.acquire(Peripheral::Aes));
+        }
         self.registers.trigger.write(
             TRIGGER::KEY_IV_DATA_IN_CLEAR::SET
                 + TRIGGER::DATA_OUT_CLEAR::SET
@@ -301,6 +356,9 @@ impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
 
         self.registers.ctrl.write(CTRL::MANUAL_OPERATION::CLEAR);
         self.registers.ctrl.write(CTRL::MANUAL_OPERATION::CLEAR);
+
+        // The clock manager turns the clock off once the block is idle.
+        self.clock.take();
     }
 
     fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
@@ -451,6 +509,7 @@ impl kernel::hil::symmetric_encryption::AES128Ctr for Aes<'_> {
         ctrl += CTRL::MODE::AES_CTR;
         // Tock only supports 128-bit keys
         ctrl += CTRL::KEY_LEN::Key128;
+        ctrl += CTRL::SIDELOAD.val(self.sideload.get() as u32);
         ctrl += CTRL::MANUAL_OPERATION::CLEAR;
 
         // We need to set the control register twice as it's shadowed
@@ -474,6 +533,7 @@ impl kernel::hil::symmetric_encryption::AES128ECB for Aes<'_> {
         ctrl += CTRL::MODE::AES_ECB;
         // Tock only supports 128-bit keys
         ctrl += CTRL::KEY_LEN::Key128;
+        ctrl += CTRL::SIDELOAD.val(self.sideload.get() as u32);
         ctrl += CTRL::MANUAL_OPERATION::CLEAR;
 
         // We need to set the control register twice as it's shadowed
@@ -497,6 +557,7 @@ impl kernel::hil::symmetric_encryption::AES128CBC for Aes<'_> {
         ctrl += CTRL::MODE::AES_CBC;
         // Tock only supports 128-bit keys
         ctrl += CTRL::KEY_LEN::Key128;
+        ctrl += CTRL::SIDELOAD.val(self.sideload.get() as u32);
         ctrl += CTRL::MANUAL_OPERATION::CLEAR;
 
         // We need to set the control register twice as it's shadowed
@@ -0,0 +1,363 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! AES-CCM* on top of the AES hardware block.
+//!
+//! The CCM* formatting (the B_0 block, the encoding of the additional data
+//! and the counter blocks) is done in software, while every block cipher
+//! call of the CBC-MAC and CTR passes runs on the AES block. The message is
+//! processed in place, so unlike the generic `virtual_aes_ccm` capsule this
+//! needs no staging buffer.
+//!
+//! IEEE 802.15.4-2015: Appendix B.4.1, CCM* transformations. The length
+//! field is two bytes, so nonces are `CCM_NONCE_LENGTH` (13) bytes long and
+//! messages can be up to 65535 bytes. If confidentiality is not requested,
+//! the message is authenticated as additional data and left unencrypted.
+//!
+//! When decrypting, a message whose tag does not match is left encrypted
+//! and reported with `tag_is_valid` set to `false`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let aes_ccm = static_init!(
+//!     earlgrey::aes_ccm::AesCcm<'static>,
+//!     earlgrey::aes_ccm::AesCcm::new(&peripherals.aes)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(aes_ccm);
+//! ```
+
+use core::cell::Cell;
+
+use crate::aes::Aes;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::symmetric_encryption::{
+    CCMClient, AES128, AES128CCM, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+type Block = [u8; AES128_BLOCK_SIZE];
+
+/// Returns true if `mic_len` is a valid CCM* tag length.
+fn valid_mic_len(mic_len: usize) -> bool {
+    matches!(mic_len, 0 | 4 | 6 | 8 | 10 | 12 | 14 | 16)
+}
+
+/// Returns the counter block A_i.
+fn counter_block(nonce: &[u8; CCM_NONCE_LENGTH], i: usize) -> Block {
+    let mut block = [0; AES128_BLOCK_SIZE];
+    // flags = reserved | reserved | 0 | (L - 1), with L = 2
+    block[0] = 1;
+    block[1..1 + CCM_NONCE_LENGTH].copy_from_slice(nonce);
+    block[14..].copy_from_slice(&(i as u16).to_be_bytes());
+    block
+}
+
+/// XORs `data` into the CBC-MAC state `x` and encrypts it.
+fn mac_block(
+    encrypt_block: &dyn Fn(&mut Block) -> Result<(), ErrorCode>,
+    x: &mut Block,
+    data: &[u8],
+) -> Result<(), ErrorCode> {
+    x.iter_mut().zip(data).for_each(|(x, d)| *x ^= *d);
+    encrypt_block(x)
+}
+
+/// Computes the unencrypted tag T over the additional data `a` and the
+/// plaintext `m`.
+fn cbc_mac(
+    encrypt_block: &dyn Fn(&mut Block) -> Result<(), ErrorCode>,
+    nonce: &[u8; CCM_NONCE_LENGTH],
+    mic_len: usize,
+    a: &[u8],
+    m: &[u8],
+) -> Result<Block, ErrorCode> {
+    // B_0 = flags | nonce | l(m), with
+    // flags = reserved | Adata | (M - 2) / 2 | (L - 1)
+    let mut x = [0; AES128_BLOCK_SIZE];
+    if !a.is_empty() {
+        x[0] |= 1 << 6;
+    }
+    if mic_len != 0 {
+        x[0] |= (((mic_len - 2) / 2) as u8) << 3;
+    }
+    x[0] |= 1;
+    x[1..1 + CCM_NONCE_LENGTH].copy_from_slice(nonce);
+    x[14..].copy_from_slice(&(m.len() as u16).to_be_bytes());
+    encrypt_block(&mut x)?;
+
+    // The additional data is prefixed with its 2 byte length and padded
+    // with zeros to a whole number of blocks.
+    if !a.is_empty() {
+        let prefix = (a.len() as u16).to_be_bytes();
+        let mut bytes = prefix.iter().chain(a.iter()).peekable();
+        while bytes.peek().is_some() {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            block
+                .iter_mut()
+                .zip(&mut bytes)
+                .for_each(|(b, byte)| *b = *byte);
+            mac_block(encrypt_block, &mut x, &block)?;
+        }
+    }
+
+    for chunk in m.chunks(AES128_BLOCK_SIZE) {
+        mac_block(encrypt_block, &mut x, chunk)?;
+    }
+    Ok(x)
+}
+
+/// Encrypts or decrypts `data` in place with the key stream from A_1.
+fn ctr(
+    encrypt_block: &dyn Fn(&mut Block) -> Result<(), ErrorCode>,
+    nonce: &[u8; CCM_NONCE_LENGTH],
+    data: &mut [u8],
+) -> Result<(), ErrorCode> {
+    for (i, chunk) in data.chunks_mut(AES128_BLOCK_SIZE).enumerate() {
+        let mut s = counter_block(nonce, i + 1);
+        encrypt_block(&mut s)?;
+        chunk.iter_mut().zip(s.iter()).for_each(|(d, s)| *d ^= *s);
+    }
+    Ok(())
+}
+
+/// Runs the CCM* transformation over `buf` in place, using `encrypt_block`
+/// as the block cipher. The arguments are those of `AES128CCM::crypt` and
+/// must already be validated. Returns whether the tag is valid.
+fn ccm_crypt(
+    encrypt_block: &dyn Fn(&mut Block) -> Result<(), ErrorCode>,
+    nonce: &[u8; CCM_NONCE_LENGTH],
+    buf: &mut [u8],
+    (a_off, m_off, m_len, mic_len): (usize, usize, usize, usize),
+    confidential: bool,
+    encrypting: bool,
+) -> Result<bool, ErrorCode> {
+    // Without confidentiality the message is only authenticated.
+    let (m_off, m_len) = if confidential {
+        (m_off, m_len)
+    } else {
+        (m_off + m_len, 0)
+    };
+    let m_end = m_off + m_len;
+
+    if !encrypting {
+        ctr(encrypt_block, nonce, &mut buf[m_off..m_end])?;
+    }
+
+    let mut tag = cbc_mac(
+        encrypt_block,
+        nonce,
+        mic_len,
+        &buf[a_off..m_off],
+        &buf[m_off..m_end],
+    )?;
+    let mut s0 = counter_block(nonce, 0);
+    encrypt_block(&mut s0)?;
+    tag.iter_mut().zip(s0.iter()).for_each(|(t, s)| *t ^= *s);
+
+    if encrypting {
+        ctr(encrypt_block, nonce, &mut buf[m_off..m_end])?;
+        buf[m_end..m_end + mic_len].copy_from_slice(&tag[..mic_len]);
+        Ok(true)
+    } else {
+        let diff = buf[m_end..m_end + mic_len]
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        if diff != 0 {
+            // Don't hand out plaintext that failed authentication.
+            ctr(encrypt_block, nonce, &mut buf[m_off..m_end])?;
+        }
+        Ok(diff == 0)
+    }
+}
+
+pub struct AesCcm<'a> {
+    aes: &'a Aes<'a>,
+    client: OptionalCell<&'a dyn CCMClient>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    buf: TakeCell<'static, [u8]>,
+    tag_is_valid: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> AesCcm<'a> {
+    pub fn new(aes: &'a Aes<'a>) -> AesCcm<'a> {
+        AesCcm {
+            aes,
+            client: OptionalCell::empty(),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            buf: TakeCell::empty(),
+            tag_is_valid: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn validate(
+        buf: &[u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+    ) -> Result<(), ErrorCode> {
+        if !valid_mic_len(mic_len) || a_off > m_off {
+            return Err(ErrorCode::INVAL);
+        }
+        match m_off
+            .checked_add(m_len)
+            .and_then(|m_end| m_end.checked_add(mic_len))
+        {
+            Some(end) if end <= buf.len() => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+        // Both lengths are encoded in 2 bytes, and the additional data must
+        // stay below the 0xFF00 escape value.
+        if m_off + m_len - a_off >= 0xFF00 || m_len > u16::MAX as usize {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> AES128CCM<'a> for AesCcm<'a> {
+    fn set_client(&'a self, client: &'a dyn CCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let key: [u8; AES128_KEY_SIZE] = key.try_into().or(Err(ErrorCode::INVAL))?;
+        self.key.set(key);
+        Ok(())
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        let nonce: [u8; CCM_NONCE_LENGTH] = nonce.try_into().or(Err(ErrorCode::INVAL))?;
+        self.nonce.set(nonce);
+        Ok(())
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.buf.is_some() || self.aes.crypt_pending() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        if let Err(e) = Self::validate(buf, a_off, m_off, m_len, mic_len) {
+            return Err((e, buf));
+        }
+
+        // Both passes only ever need the forward block cipher.
+        let res = self
+            .aes
+            .set_mode_aes128ecb(true)
+            .and_then(|()| self.aes.set_key(&self.key.get()))
+            .and_then(|()| {
+                ccm_crypt(
+                    &|block| self.aes.crypt_block(block),
+                    &self.nonce.get(),
+                    buf,
+                    (a_off, m_off, m_len, mic_len),
+                    confidential,
+                    encrypting,
+                )
+            });
+
+        match res {
+            Ok(tag_is_valid) => {
+                self.tag_is_valid.set(tag_is_valid);
+                self.buf.replace(buf);
+                self.deferred_call.set();
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+}
+
+impl DeferredCallClient for AesCcm<'_> {
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        self.buf.take().map(|buf| {
+            self.client.map(|client| {
+                client.crypt_done(buf, Ok(()), self.tag_is_valid.get());
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capsules_core::test::mocks::aes128_encrypt;
+
+    /// RFC 3610, Packet Vector #1: 8 bytes of additional data, 23 bytes of
+    /// message and an 8 byte tag.
+    const KEY: [u8; AES128_KEY_SIZE] = [
+        0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD, 0xCE,
+        0xCF,
+    ];
+    const NONCE: [u8; CCM_NONCE_LENGTH] = [
+        0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, 0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5,
+    ];
+    const CIPHERTEXT: [u8; 39] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x58, 0x8C, 0x97, 0x9A, 0x61, 0xC6, 0x63,
+        0xD2, 0xF0, 0x66, 0xD0, 0xC2, 0xC0, 0xF9, 0x89, 0x80, 0x6D, 0x5F, 0x6B, 0x61, 0xDA, 0xC3,
+        0x84, 0x17, 0xE8, 0xD1, 0x2C, 0xFD, 0xF9, 0x26, 0xE0,
+    ];
+    const OFFSETS: (usize, usize, usize, usize) = (0, 8, 23, 8);
+
+    fn plaintext() -> [u8; 39] {
+        let mut packet = [0; 39];
+        for (i, byte) in packet[..31].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        packet
+    }
+
+    fn crypt(buf: &mut [u8], encrypting: bool) -> Result<bool, ErrorCode> {
+        let encrypt_block = |block: &mut Block| {
+            aes128_encrypt(&KEY, block);
+            Ok(())
+        };
+        ccm_crypt(&encrypt_block, &NONCE, buf, OFFSETS, true, encrypting)
+    }
+
+    #[test]
+    fn rfc3610_packet_vector_1() {
+        let mut buf = plaintext();
+        assert_eq!(crypt(&mut buf, true), Ok(true));
+        assert_eq!(buf, CIPHERTEXT);
+
+        assert_eq!(crypt(&mut buf, false), Ok(true));
+        assert_eq!(buf[..31], plaintext()[..31]);
+    }
+
+    #[test]
+    fn flipped_tag_bit_fails() {
+        let mut buf = CIPHERTEXT;
+        buf[38] ^= 0x01;
+        assert_eq!(crypt(&mut buf, false), Ok(false));
+        // The message is left encrypted.
+        assert_eq!(buf[..38], CIPHERTEXT[..38]);
+
+        // So does a corrupted message.
+        let mut buf = CIPHERTEXT;
+        buf[12] ^= 0x80;
+        assert_eq!(crypt(&mut buf, false), Ok(false));
+    }
+}
@@ -13,6 +13,7 @@ use rv32i::epmp::PMP;
 use rv32i::syscall::SysCall;
 
 use crate::chip_config::CONFIG;
+use crate::clkmgr::{ClockGate, ClockManager, Peripheral, PeripheralClock};
 use crate::interrupts;
 use crate::plic::Plic;
 use crate::plic::PLIC;
@@ -32,24 +33,36 @@ pub struct EarlGreyDefaultPeripherals<'a> {
     pub usb: lowrisc::usbdev::Usb<'a>,
     pub uart0: lowrisc::uart::Uart<'a>,
     pub otbn: lowrisc::otbn::Otbn<'a>,
+    pub keymgr: crate::keymgr::KeyMgr<'a>,
+    pub kmac: crate::kmac::Kmac<'a>,
     pub gpio_port: crate::gpio::Port<'a>,
+    pub pinmux: crate::pinmux::Pinmux,
     pub i2c0: lowrisc::i2c::I2c<'a>,
     pub spi_host0: lowrisc::spi_host::SpiHost<'a>,
     pub spi_host1: lowrisc::spi_host::SpiHost<'a>,
     pub flash_ctrl: lowrisc::flash_ctrl::FlashCtrl<'a>,
     pub rng: lowrisc::csrng::CsRng<'a>,
     pub watchdog: lowrisc::aon_timer::AonTimer,
+    pub retention_ram: crate::retention_ram::RetentionRam,
+    pub spi_host0_clock: PeripheralClock<'a>,
+    pub spi_host1_clock: PeripheralClock<'a>,
+    /// The UART, GPIO and I2C blocks share a clock, which the console needs
+    /// at all times.
+    _console_clock: ClockGate<'a>,
 }
 
 impl<'a> EarlGreyDefaultPeripherals<'a> {
-    pub fn new() -> Self {
+    pub fn new(clkmgr: &'a ClockManager) -> Self {
         Self {
-            aes: crate::aes::Aes::new(),
+            aes: crate::aes::Aes::new(clkmgr),
             hmac: lowrisc::hmac::Hmac::new(crate::hmac::HMAC0_BASE),
             usb: lowrisc::usbdev::Usb::new(crate::usbdev::USB0_BASE),
             uart0: lowrisc::uart::Uart::new(crate::uart::UART0_BASE, CONFIG.peripheral_freq),
             otbn: lowrisc::otbn::Otbn::new(crate::otbn::OTBN_BASE),
+            keymgr: crate::keymgr::KeyMgr::new(crate::keymgr::KEYMGR_BASE),
+            kmac: crate::kmac::Kmac::new(crate::kmac::KMAC_BASE, clkmgr),
             gpio_port: crate::gpio::Port::new(),
+            pinmux: crate::pinmux::Pinmux::new(crate::pinmux::PINMUX_BASE),
             i2c0: lowrisc::i2c::I2c::new(
                 crate::i2c::I2C0_BASE,
                 (1 / CONFIG.cpu_freq) * 1000 * 1000,
@@ -72,11 +85,31 @@ impl<'a> EarlGreyDefaultPeripherals<'a> {
                 crate::aon_timer::AON_TIMER_BASE,
                 CONFIG.cpu_freq,
             ),
+            retention_ram: crate::retention_ram::RetentionRam::new(
+                crate::retention_ram::SRAM_CTRL_RET_BASE,
+                crate::retention_ram::RETENTION_REGION_BASE,
+            ),
+            spi_host0_clock: PeripheralClock::new(clkmgr, Peripheral::SpiHost0),
+            spi_host1_clock: PeripheralClock::new(clkmgr, Peripheral::SpiHost1),
+            _console_clock: clkmgr.acquire(Peripheral::Uart),
         }
     }
 
     pub fn init(&'static self) {
+        self.retention_ram
+            .init(crate::retention_ram::ResetReason::read(
+                crate::retention_ram::RSTMGR_BASE,
+            ));
+        self.spi_host0.set_clock(&self.spi_host0_clock);
+        self.spi_host1.set_clock(&self.spi_host1_clock);
+        self.i2c0.set_fifo_depth(crate::i2c::I2C_FIFO_DEPTH);
+
         kernel::deferred_call::DeferredCallClient::register(&self.aes);
+        kernel::deferred_call::DeferredCallClient::register(&self.kmac);
+        kernel::deferred_call::DeferredCallClient::register(&self.uart0);
+        kernel::deferred_call::DeferredCallClient::register(&self.i2c0);
+        kernel::deferred_call::DeferredCallClient::register(&self.spi_host0);
+        kernel::deferred_call::DeferredCallClient::register(&self.spi_host1);
     }
 }
 
@@ -103,6 +136,8 @@ impl<'a> InterruptService for EarlGreyDefaultPeripherals<'a> {
                 self.i2c0.handle_interrupt()
             }
             interrupts::OTBN_DONE => self.otbn.handle_interrupt(),
+            interrupts::KEYMGR_OP_DONE => self.keymgr.handle_interrupt(),
+            interrupts::KMAC_KMACDONE..=interrupts::KMAC_KMACERR => self.kmac.handle_interrupt(),
             interrupts::CSRNG_CSCMDREQDONE..=interrupts::CSRNG_CSFATALERR => {
                 self.rng.handle_interrupt()
             }
@@ -289,6 +324,9 @@ fn handle_exception(exception: mcause::Exception) {
     match exception {
         mcause::Exception::UserEnvCall | mcause::Exception::SupervisorEnvCall => (),
 
+        // Watchpoints are handled and return to the access.
+        mcause::Exception::Breakpoint if rv32i::triggers::handle_breakpoint() => (),
+
         // Breakpoints occur from the tests running on hardware
         mcause::Exception::Breakpoint => loop {
             unsafe { rv32i::support::wfi() }
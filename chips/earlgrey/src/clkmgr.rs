@@ -0,0 +1,257 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Clock manager (CLKMGR) for EarlGrey
+//!
+//! <https://opentitan.org/book/hw/ip/clkmgr/>
+//!
+//! The clock manager can turn off the clocks of peripherals that are idle.
+//! Most peripherals share a clock with others, for example every UART, I2C
+//! and GPIO block runs from the same `io_div4` peripheral clock, so a clock
+//! is only turned off when no driver on it needs it.
+//!
+//! A driver asks for its clock with [ClockManager::acquire], which returns
+//! a [ClockGate]. The clock stays on until every gate for it is dropped.
+//! Drivers that use [kernel::platform::chip::ClockInterface] are given a
+//! [PeripheralClock] instead, which holds a gate while enabled.
+//!
+//! The transactional blocks (AES, HMAC, KMAC and OTBN) are controlled with
+//! hints: once their clock is released the clock manager turns it off when
+//! the block finishes its current operation.
+
+use core::cell::Cell;
+use kernel::platform::chip::ClockInterface;
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, Field, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::utilities::StaticRef;
+
+register_structs! {
+    pub ClkMgrRegisters {
+        (0x00 => alert_test: WriteOnly<u32>),
+        (0x04 => extclk_ctrl_regwen: ReadWrite<u32>),
+        (0x08 => extclk_ctrl: ReadWrite<u32>),
+        (0x0C => extclk_status: ReadOnly<u32>),
+        (0x10 => jitter_regwen: ReadWrite<u32>),
+        (0x14 => jitter_enable: ReadWrite<u32>),
+        (0x18 => clk_enables: ReadWrite<u32, CLK_ENABLES::Register>),
+        (0x1C => clk_hints: ReadWrite<u32, CLK_HINTS::Register>),
+        (0x20 => clk_hints_status: ReadOnly<u32, CLK_HINTS::Register>),
+        (0x24 => @END),
+    }
+}
+
+register_bitfields![u32,
+    CLK_ENABLES [
+        CLK_IO_DIV4_PERI_EN OFFSET(0) NUMBITS(1) [],
+        CLK_IO_DIV2_PERI_EN OFFSET(1) NUMBITS(1) [],
+        CLK_IO_PERI_EN OFFSET(2) NUMBITS(1) [],
+        CLK_USB_PERI_EN OFFSET(3) NUMBITS(1) [],
+    ],
+    CLK_HINTS [
+        CLK_MAIN_AES_HINT OFFSET(0) NUMBITS(1) [],
+        CLK_MAIN_HMAC_HINT OFFSET(1) NUMBITS(1) [],
+        CLK_MAIN_KMAC_HINT OFFSET(2) NUMBITS(1) [],
+        CLK_MAIN_OTBN_HINT OFFSET(3) NUMBITS(1) [],
+    ],
+];
+
+pub const CLKMGR_BASE: StaticRef<ClkMgrRegisters> =
+    unsafe { StaticRef::new(0x4042_0000 as *const ClkMgrRegisters) };
+
+/// Number of clocks in `CLK_ENABLES`, the hinted clocks follow them.
+const NUM_GATED_CLOCKS: usize = 4;
+const NUM_CLOCKS: usize = NUM_GATED_CLOCKS + 4;
+
+/// The peripherals whose clock can be turned off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peripheral {
+    Uart,
+    Gpio,
+    I2c,
+    SpiHost0,
+    SpiHost1,
+    Usb,
+    Aes,
+    Hmac,
+    Kmac,
+    Otbn,
+}
+
+/// How the clock of a peripheral is turned off.
+enum Clock {
+    /// Turned off by software.
+    Gated(Field<u32, CLK_ENABLES::Register>),
+    /// Turned off by the clock manager once the block is idle.
+    Hinted(Field<u32, CLK_HINTS::Register>),
+}
+
+impl Peripheral {
+    fn clock(self) -> Clock {
+        match self {
+            Peripheral::Uart | Peripheral::Gpio | Peripheral::I2c => {
+                Clock::Gated(CLK_ENABLES::CLK_IO_DIV4_PERI_EN)
+            }
+            Peripheral::SpiHost0 => Clock::Gated(CLK_ENABLES::CLK_IO_PERI_EN),
+            Peripheral::SpiHost1 => Clock::Gated(CLK_ENABLES::CLK_IO_DIV2_PERI_EN),
+            Peripheral::Usb => Clock::Gated(CLK_ENABLES::CLK_USB_PERI_EN),
+            Peripheral::Aes => Clock::Hinted(CLK_HINTS::CLK_MAIN_AES_HINT),
+            Peripheral::Hmac => Clock::Hinted(CLK_HINTS::CLK_MAIN_HMAC_HINT),
+            Peripheral::Kmac => Clock::Hinted(CLK_HINTS::CLK_MAIN_KMAC_HINT),
+            Peripheral::Otbn => Clock::Hinted(CLK_HINTS::CLK_MAIN_OTBN_HINT),
+        }
+    }
+
+    /// Index of the peripheral's clock in the reference counts.
+    fn clock_index(self) -> usize {
+        match self.clock() {
+            Clock::Gated(field) => field.shift,
+            Clock::Hinted(field) => NUM_GATED_CLOCKS + field.shift,
+        }
+    }
+}
+
+pub struct ClockManager {
+    registers: StaticRef<ClkMgrRegisters>,
+    /// The number of [ClockGate]s held on each clock.
+    references: [Cell<usize>; NUM_CLOCKS],
+}
+
+impl ClockManager {
+    pub fn new(base: StaticRef<ClkMgrRegisters>) -> ClockManager {
+        ClockManager {
+            registers: base,
+            references: Default::default(),
+        }
+    }
+
+    /// Turn on the clock of `periph`, and of every peripheral sharing it.
+    pub fn enable_peripheral_clock(&self, periph: Peripheral) {
+        match periph.clock() {
+            Clock::Gated(field) => self.registers.clk_enables.modify(field.val(1)),
+            Clock::Hinted(field) => self.registers.clk_hints.modify(field.val(1)),
+        }
+    }
+
+    /// Turn off the clock of `periph`, and of every peripheral sharing it.
+    ///
+    /// This ignores the [ClockGate]s held on the clock, drivers should drop
+    /// their gate instead.
+    pub fn disable_peripheral_clock(&self, periph: Peripheral) {
+        match periph.clock() {
+            Clock::Gated(field) => self.registers.clk_enables.modify(field.val(0)),
+            Clock::Hinted(field) => self.registers.clk_hints.modify(field.val(0)),
+        }
+    }
+
+    /// Whether the clock of `periph` is running.
+    pub fn is_peripheral_clock_enabled(&self, periph: Peripheral) -> bool {
+        match periph.clock() {
+            Clock::Gated(field) => self.registers.clk_enables.is_set(field),
+            Clock::Hinted(field) => self.registers.clk_hints_status.is_set(field),
+        }
+    }
+
+    /// Keep the clock of `periph` on until the returned gate is dropped.
+    #[must_use]
+    pub fn acquire(&self, periph: Peripheral) -> ClockGate<'_> {
+        let references = &self.references[periph.clock_index()];
+        if references.get() == 0 {
+            self.enable_peripheral_clock(periph);
+        }
+        references.set(references.get() + 1);
+        ClockGate {
+            clkmgr: self,
+            periph,
+        }
+    }
+
+    fn release(&self, periph: Peripheral) {
+        let references = &self.references[periph.clock_index()];
+        references.set(references.get() - 1);
+        if references.get() == 0 {
+            self.disable_peripheral_clock(periph);
+        }
+    }
+}
+
+/// Keeps the clock of a peripheral on while it is held.
+pub struct ClockGate<'a> {
+    clkmgr: &'a ClockManager,
+    periph: Peripheral,
+}
+
+impl Drop for ClockGate<'_> {
+    fn drop(&mut self) {
+        self.clkmgr.release(self.periph);
+    }
+}
+
+/// The clock of one peripheral driver, holding a [ClockGate] while it is
+/// enabled.
+pub struct PeripheralClock<'a> {
+    clkmgr: &'a ClockManager,
+    periph: Peripheral,
+    gate: MapCell<ClockGate<'a>>,
+}
+
+impl<'a> PeripheralClock<'a> {
+    pub fn new(clkmgr: &'a ClockManager, periph: Peripheral) -> PeripheralClock<'a> {
+        PeripheralClock {
+            clkmgr,
+            periph,
+            gate: MapCell::empty(),
+        }
+    }
+}
+
+impl ClockInterface for PeripheralClock<'_> {
+    fn is_enabled(&self) -> bool {
+        self.clkmgr.is_peripheral_clock_enabled(self.periph)
+    }
+
+    fn enable(&self) {
+        if self.gate.is_none() {
+            self.gate.put(self.clkmgr.acquire(self.periph));
+        }
+    }
+
+    fn disable(&self) {
+        self.gate.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_ON: u32 = 0xF;
+
+    #[test]
+    fn clock_stays_on_while_a_gate_is_held() {
+        let memory: [Cell<u32>; 9] = Default::default();
+        memory[6].set(ALL_ON);
+        let clkmgr =
+            ClockManager::new(unsafe { StaticRef::new(memory.as_ptr() as *const ClkMgrRegisters) });
+
+        let uart = clkmgr.acquire(Peripheral::Uart);
+        let i2c = clkmgr.acquire(Peripheral::I2c);
+        drop(uart);
+        assert!(clkmgr.is_peripheral_clock_enabled(Peripheral::Uart));
+        drop(i2c);
+        assert!(!clkmgr.is_peripheral_clock_enabled(Peripheral::Gpio));
+        // The other clocks are untouched.
+        assert_eq!(memory[6].get(), ALL_ON & !1);
+
+        let spi = PeripheralClock::new(&clkmgr, Peripheral::SpiHost0);
+        spi.enable();
+        spi.enable();
+        spi.disable();
+        assert!(!spi.is_enabled());
+        let _gpio = clkmgr.acquire(Peripheral::Gpio);
+        assert_eq!(memory[6].get(), 0b1011);
+    }
+}
@@ -7,8 +7,9 @@
 use core::ops::{Index, IndexMut};
 
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 use lowrisc::gpio::GpioRegisters;
-pub use lowrisc::gpio::{pins, GpioPin};
+pub use lowrisc::gpio::{pins, GpioPin, PinGroup};
 use lowrisc::padctrl::PadCtrlRegisters;
 
 pub const PADCTRL_BASE: StaticRef<PadCtrlRegisters> =
@@ -60,6 +61,15 @@ impl<'a> Port<'a> {
             ],
         }
     }
+
+    /// Group the pins with indices `pins`, to read and write them in a
+    /// single register access.
+    pub fn group(&self, pins: &[usize]) -> Result<PinGroup, ErrorCode> {
+        if pins.iter().any(|&index| index >= self.pins.len()) {
+            return Err(ErrorCode::INVAL);
+        }
+        PinGroup::new(pins.iter().map(|&index| &self.pins[index]))
+    }
 }
 
 impl<'a> Index<usize> for Port<'a> {
@@ -7,3 +7,6 @@ use lowrisc::i2c::I2cRegisters;
 
 pub const I2C0_BASE: StaticRef<I2cRegisters> =
     unsafe { StaticRef::new(0x4008_0000 as *const I2cRegisters) };
+
+/// Depth of the I2C FMT and RX FIFOs.
+pub const I2C_FIFO_DEPTH: usize = 32;
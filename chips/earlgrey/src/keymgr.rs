@@ -0,0 +1,394 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Key manager (KEYMGR) for EarlGrey
+//!
+//! <https://opentitan.org/book/hw/ip/keymgr/>
+//!
+//! The key manager derives keys from secrets in OTP and flash, and can load
+//! them straight into the AES, KMAC and OTBN blocks through their sideload
+//! ports, so that software never sees the key. HMAC has no sideload port.
+//!
+//! Keys are derived from the current state of the key manager, which only
+//! moves forward: reset, initialized, creator root key, owner intermediate
+//! key and owner key. [KeyMgr::advance] moves to the next state, and
+//! [KeyMgr::generate_sideload_key] derives a key for a destination, bound
+//! to a key version and a salt. Keys can only be derived in the last three
+//! states, and operations out of order are rejected with `OFF`.
+//!
+//! To use a sideloaded key with AES, call
+//! [Aes::set_sideload_key](crate::aes::Aes::set_sideload_key). OTBN uses
+//! the sideloaded key when its program asks for it.
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+register_structs! {
+    pub KeyMgrRegisters {
+        (0x00 => intr_state: ReadWrite<u32, INTR::Register>),
+        (0x04 => intr_enable: ReadWrite<u32, INTR::Register>),
+        (0x08 => intr_test: WriteOnly<u32, INTR::Register>),
+        (0x0C => alert_test: WriteOnly<u32>),
+        (0x10 => cfg_regwen: ReadOnly<u32>),
+        (0x14 => start: ReadWrite<u32, START::Register>),
+        (0x18 => control_shadowed: ReadWrite<u32, CONTROL::Register>),
+        (0x1C => sideload_clear: ReadWrite<u32>),
+        (0x20 => reseed_interval_regwen: ReadWrite<u32>),
+        (0x24 => reseed_interval_shadowed: ReadWrite<u32>),
+        (0x28 => sw_binding_regwen: ReadWrite<u32>),
+        (0x2C => sealing_sw_binding: [ReadWrite<u32>; 8]),
+        (0x4C => attest_sw_binding: [ReadWrite<u32>; 8]),
+        (0x6C => salt: [ReadWrite<u32>; 8]),
+        (0x8C => key_version: ReadWrite<u32>),
+        (0x90 => max_creator_key_ver_regwen: ReadWrite<u32>),
+        (0x94 => max_creator_key_ver_shadowed: ReadWrite<u32>),
+        (0x98 => max_owner_int_key_ver_regwen: ReadWrite<u32>),
+        (0x9C => max_owner_int_key_ver_shadowed: ReadWrite<u32>),
+        (0xA0 => max_owner_key_ver_regwen: ReadWrite<u32>),
+        (0xA4 => max_owner_key_ver_shadowed: ReadWrite<u32>),
+        (0xA8 => sw_share0_output: [ReadOnly<u32>; 8]),
+        (0xC8 => sw_share1_output: [ReadOnly<u32>; 8]),
+        (0xE8 => working_state: ReadOnly<u32, WORKING_STATE::Register>),
+        (0xEC => op_status: ReadWrite<u32, OP_STATUS::Register>),
+        (0xF0 => err_code: ReadWrite<u32>),
+        (0xF4 => fault_status: ReadOnly<u32>),
+        (0xF8 => debug: ReadWrite<u32>),
+        (0xFC => @END),
+    }
+}
+
+register_bitfields![u32,
+    INTR [
+        OP_DONE OFFSET(0) NUMBITS(1) [],
+    ],
+    START [
+        EN OFFSET(0) NUMBITS(1) [],
+    ],
+    CONTROL [
+        OPERATION OFFSET(4) NUMBITS(3) [
+            Advance = 0,
+            GenerateId = 1,
+            GenerateSwOutput = 2,
+            GenerateHwOutput = 3,
+            Disable = 4,
+        ],
+        CDI_SEL OFFSET(7) NUMBITS(1) [
+            Sealing = 0,
+            Attestation = 1,
+        ],
+        DEST_SEL OFFSET(12) NUMBITS(3) [
+            None = 0,
+            Aes = 1,
+            Kmac = 2,
+            Otbn = 3,
+        ],
+    ],
+    WORKING_STATE [
+        STATE OFFSET(0) NUMBITS(3) [
+            Reset = 0,
+            Init = 1,
+            CreatorRootKey = 2,
+            OwnerIntermediateKey = 3,
+            OwnerKey = 4,
+            Disabled = 5,
+            Invalid = 6,
+        ],
+    ],
+    OP_STATUS [
+        STATUS OFFSET(0) NUMBITS(2) [
+            Idle = 0,
+            Wip = 1,
+            DoneSuccess = 2,
+            DoneError = 3,
+        ],
+    ],
+];
+
+pub const KEYMGR_BASE: StaticRef<KeyMgrRegisters> =
+    unsafe { StaticRef::new(0x4114_0000 as *const KeyMgrRegisters) };
+
+/// The state of the key manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyMgrState {
+    Reset,
+    Init,
+    CreatorRootKey,
+    OwnerIntermediateKey,
+    OwnerKey,
+    Disabled,
+    Invalid,
+}
+
+impl KeyMgrState {
+    /// Whether keys can be derived in this state.
+    fn has_key(self) -> bool {
+        matches!(
+            self,
+            KeyMgrState::CreatorRootKey | KeyMgrState::OwnerIntermediateKey | KeyMgrState::OwnerKey
+        )
+    }
+}
+
+/// The block a derived key is loaded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Aes,
+    Kmac,
+    Otbn,
+}
+
+/// Identifies a key loaded into a sideload port. The key itself is never
+/// visible to software.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SideloadKey {
+    pub destination: Destination,
+    /// The state the key was derived in.
+    pub state: KeyMgrState,
+    pub version: u32,
+}
+
+pub trait KeyMgrClient {
+    /// The key manager advanced, and is now in the returned state.
+    fn advance_done(&self, result: Result<KeyMgrState, ErrorCode>);
+
+    /// A key was derived and loaded into its destination.
+    fn key_generated(&self, result: Result<SideloadKey, ErrorCode>);
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Advance,
+    Generate(SideloadKey),
+}
+
+pub struct KeyMgr<'a> {
+    registers: StaticRef<KeyMgrRegisters>,
+    client: OptionalCell<&'a dyn KeyMgrClient>,
+    operation: OptionalCell<Operation>,
+}
+
+impl<'a> KeyMgr<'a> {
+    pub fn new(base: StaticRef<KeyMgrRegisters>) -> KeyMgr<'a> {
+        KeyMgr {
+            registers: base,
+            client: OptionalCell::empty(),
+            operation: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn KeyMgrClient) {
+        self.client.set(client);
+    }
+
+    pub fn state(&self) -> KeyMgrState {
+        match self
+            .registers
+            .working_state
+            .read_as_enum(WORKING_STATE::STATE)
+        {
+            Some(WORKING_STATE::STATE::Value::Reset) => KeyMgrState::Reset,
+            Some(WORKING_STATE::STATE::Value::Init) => KeyMgrState::Init,
+            Some(WORKING_STATE::STATE::Value::CreatorRootKey) => KeyMgrState::CreatorRootKey,
+            Some(WORKING_STATE::STATE::Value::OwnerIntermediateKey) => {
+                KeyMgrState::OwnerIntermediateKey
+            }
+            Some(WORKING_STATE::STATE::Value::OwnerKey) => KeyMgrState::OwnerKey,
+            Some(WORKING_STATE::STATE::Value::Disabled) => KeyMgrState::Disabled,
+            _ => KeyMgrState::Invalid,
+        }
+    }
+
+    /// Move to the next state, up to the owner key.
+    pub fn advance(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        match self.state() {
+            KeyMgrState::Reset
+            | KeyMgrState::Init
+            | KeyMgrState::CreatorRootKey
+            | KeyMgrState::OwnerIntermediateKey => {}
+            KeyMgrState::OwnerKey | KeyMgrState::Disabled | KeyMgrState::Invalid => {
+                return Err(ErrorCode::OFF)
+            }
+        }
+
+        self.start(CONTROL::OPERATION::Advance + CONTROL::DEST_SEL::None);
+        self.operation.set(Operation::Advance);
+        Ok(())
+    }
+
+    /// Derive a key of `version` from the current state and `salt`, and
+    /// load it into `destination`.
+    ///
+    /// The version must not be above the maximum the boot ROM set for the
+    /// current state, or the derivation fails.
+    pub fn generate_sideload_key(
+        &self,
+        destination: Destination,
+        version: u32,
+        salt: &[u32; 8],
+    ) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let state = self.state();
+        if !state.has_key() {
+            return Err(ErrorCode::OFF);
+        }
+
+        let regs = self.registers;
+        for (register, &word) in regs.salt.iter().zip(salt.iter()) {
+            register.set(word);
+        }
+        regs.key_version.set(version);
+        let dest = match destination {
+            Destination::Aes => CONTROL::DEST_SEL::Aes,
+            Destination::Kmac => CONTROL::DEST_SEL::Kmac,
+            Destination::Otbn => CONTROL::DEST_SEL::Otbn,
+        };
+        self.start(CONTROL::OPERATION::GenerateHwOutput + CONTROL::CDI_SEL::Sealing + dest);
+        self.operation.set(Operation::Generate(SideloadKey {
+            destination,
+            state,
+            version,
+        }));
+        Ok(())
+    }
+
+    fn start(&self, control: FieldValue<u32, CONTROL::Register>) {
+        let regs = self.registers;
+        // The control register is shadowed, so it is written twice.
+        regs.control_shadowed.write(control);
+        regs.control_shadowed.write(control);
+        regs.intr_enable.write(INTR::OP_DONE::SET);
+        regs.start.write(START::EN::SET);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = self.registers;
+        regs.intr_state.write(INTR::OP_DONE::SET);
+
+        let result = match regs.op_status.read_as_enum(OP_STATUS::STATUS) {
+            Some(OP_STATUS::STATUS::Value::DoneSuccess) => Ok(()),
+            Some(OP_STATUS::STATUS::Value::DoneError) => {
+                // Clear the errors, which are write-1-to-clear.
+                regs.err_code.set(regs.err_code.get());
+                Err(ErrorCode::FAIL)
+            }
+            _ => return,
+        };
+        // Back to idle, the status is write-1-to-clear.
+        regs.op_status.set(regs.op_status.get());
+
+        match self.operation.take() {
+            Some(Operation::Advance) => {
+                let state = self.state();
+                self.client
+                    .map(|client| client.advance_done(result.map(|()| state)));
+            }
+            Some(Operation::Generate(key)) => {
+                self.client
+                    .map(|client| client.key_generated(result.map(|()| key)));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    const START: usize = 0x14 / 4;
+    const CONTROL: usize = 0x18 / 4;
+    const KEY_VERSION: usize = 0x8C / 4;
+    const WORKING_STATE: usize = 0xE8 / 4;
+    const OP_STATUS: usize = 0xEC / 4;
+
+    #[derive(Default)]
+    struct Client {
+        advanced: Cell<Option<Result<KeyMgrState, ErrorCode>>>,
+        generated: Cell<Option<Result<SideloadKey, ErrorCode>>>,
+    }
+
+    impl KeyMgrClient for Client {
+        fn advance_done(&self, result: Result<KeyMgrState, ErrorCode>) {
+            self.advanced.set(Some(result));
+        }
+
+        fn key_generated(&self, result: Result<SideloadKey, ErrorCode>) {
+            self.generated.set(Some(result));
+        }
+    }
+
+    /// Complete the operation the key manager started, ending in `state`.
+    fn complete(memory: &[Cell<u32>], keymgr: &KeyMgr, state: u32) {
+        assert_eq!(memory[START].replace(0), 1);
+        memory[WORKING_STATE].set(state);
+        memory[OP_STATUS].set(2);
+        keymgr.handle_interrupt();
+    }
+
+    #[test]
+    fn keys_are_only_derived_after_advancing() {
+        let memory: [Cell<u32>; 63] = core::array::from_fn(|_| Cell::new(0));
+        let keymgr =
+            KeyMgr::new(unsafe { StaticRef::new(memory.as_ptr() as *const KeyMgrRegisters) });
+        let client = Client::default();
+        keymgr.set_client(&client);
+        let salt = [0; 8];
+
+        // Reset and initialized have no key.
+        assert_eq!(
+            keymgr.generate_sideload_key(Destination::Aes, 0, &salt),
+            Err(ErrorCode::OFF)
+        );
+        assert_eq!(keymgr.advance(), Ok(()));
+        assert_eq!(keymgr.advance(), Err(ErrorCode::BUSY));
+        complete(&memory, &keymgr, 1);
+        assert_eq!(client.advanced.take(), Some(Ok(KeyMgrState::Init)));
+        assert_eq!(
+            keymgr.generate_sideload_key(Destination::Aes, 0, &salt),
+            Err(ErrorCode::OFF)
+        );
+
+        // Creator root key.
+        assert_eq!(keymgr.advance(), Ok(()));
+        complete(&memory, &keymgr, 2);
+        assert_eq!(
+            keymgr.generate_sideload_key(Destination::Otbn, 3, &salt),
+            Ok(())
+        );
+        assert_eq!(memory[KEY_VERSION].get(), 3);
+        assert_eq!(memory[CONTROL].get(), 3 << 12 | 3 << 4);
+        complete(&memory, &keymgr, 2);
+        assert_eq!(
+            client.generated.take(),
+            Some(Ok(SideloadKey {
+                destination: Destination::Otbn,
+                state: KeyMgrState::CreatorRootKey,
+                version: 3,
+            }))
+        );
+
+        // Owner intermediate and owner keys, then no further.
+        assert_eq!(keymgr.advance(), Ok(()));
+        complete(&memory, &keymgr, 3);
+        assert_eq!(keymgr.advance(), Ok(()));
+        complete(&memory, &keymgr, 4);
+        assert_eq!(client.advanced.take(), Some(Ok(KeyMgrState::OwnerKey)));
+        assert_eq!(keymgr.advance(), Err(ErrorCode::OFF));
+        assert_eq!(
+            keymgr.generate_sideload_key(Destination::Aes, 0, &salt),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,709 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! KMAC/SHA-3 hardware block for EarlGrey
+//!
+//! <https://opentitan.org/book/hw/ip/kmac/>
+//!
+//! The block computes SHA3-256, SHA3-512, SHAKE128, SHAKE256, KMAC128 and
+//! KMAC256. The message is written to a FIFO, which is refilled when the
+//! `fifo_empty` interrupt fires. Once the message is absorbed the block
+//! runs the Keccak permutation and exposes one rate-sized block of output
+//! at a time in its state window, as two shares which are XORed together.
+//! Asking for more output than one block runs the permutation again.
+//!
+//! KMAC keys of 128, 192, 256, 384 and 512 bits are supported, and the
+//! customization string is limited by the 44 bytes of prefix registers,
+//! which hold `encode_string("KMAC") || encode_string(S)`.
+
+use crate::clkmgr::{ClockGate, ClockManager, Peripheral};
+use core::cell::Cell;
+use core::cmp;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::hash::{Sha3, Sha3Mode, SpongeClient};
+use kernel::hil::mac::{Kmac as KmacHil, KmacMode};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+register_structs! {
+    pub KmacRegisters {
+        (0x000 => intr_state: ReadWrite<u32, INTR::Register>),
+        (0x004 => intr_enable: ReadWrite<u32, INTR::Register>),
+        (0x008 => intr_test: WriteOnly<u32, INTR::Register>),
+        (0x00C => alert_test: WriteOnly<u32>),
+        (0x010 => cfg_regwen: ReadOnly<u32>),
+        (0x014 => cfg_shadowed: ReadWrite<u32, CFG::Register>),
+        (0x018 => cmd: WriteOnly<u32, CMD::Register>),
+        (0x01C => status: ReadOnly<u32, STATUS::Register>),
+        (0x020 => entropy_period: ReadWrite<u32>),
+        (0x024 => entropy_refresh_hash_cnt: ReadOnly<u32>),
+        (0x028 => entropy_refresh_threshold_shadowed: ReadWrite<u32>),
+        (0x02C => entropy_seed: [WriteOnly<u32>; 5]),
+        (0x040 => key_share0: [WriteOnly<u32>; 16]),
+        (0x080 => key_share1: [WriteOnly<u32>; 16]),
+        (0x0C0 => key_len: WriteOnly<u32, KEY_LEN::Register>),
+        (0x0C4 => prefix: [ReadWrite<u32>; 11]),
+        (0x0F0 => err_code: ReadOnly<u32>),
+        (0x0F4 => _reserved0),
+        (0x400 => state_share0: [ReadOnly<u32>; 50]),
+        (0x4C8 => _reserved1),
+        (0x500 => state_share1: [ReadOnly<u32>; 50]),
+        (0x5C8 => _reserved2),
+        (0x800 => msg_fifo: WriteOnly<u32>),
+        // Every address of the FIFO window writes to the FIFO; this one is
+        // used for the bytes that do not fill a word.
+        (0x804 => msg_fifo_byte: WriteOnly<u8>),
+        (0x805 => _reserved3),
+        (0x1000 => @END),
+    }
+}
+
+register_bitfields![u32,
+    INTR [
+        KMAC_DONE OFFSET(0) NUMBITS(1) [],
+        FIFO_EMPTY OFFSET(1) NUMBITS(1) [],
+        KMAC_ERR OFFSET(2) NUMBITS(1) [],
+    ],
+    CFG [
+        KMAC_EN OFFSET(0) NUMBITS(1) [],
+        KSTRENGTH OFFSET(1) NUMBITS(3) [
+            L128 = 0,
+            L224 = 1,
+            L256 = 2,
+            L384 = 3,
+            L512 = 4,
+        ],
+        MODE OFFSET(4) NUMBITS(2) [
+            Sha3 = 0,
+            Shake = 2,
+            CShake = 3,
+        ],
+        MSG_ENDIANNESS OFFSET(8) NUMBITS(1) [],
+        STATE_ENDIANNESS OFFSET(9) NUMBITS(1) [],
+        SIDELOAD OFFSET(12) NUMBITS(1) [],
+        ENTROPY_MODE OFFSET(16) NUMBITS(2) [
+            Idle = 0,
+            Edn = 1,
+            Software = 2,
+        ],
+        ENTROPY_FAST_PROCESS OFFSET(19) NUMBITS(1) [],
+        MSG_MASK OFFSET(20) NUMBITS(1) [],
+        ENTROPY_READY OFFSET(24) NUMBITS(1) [],
+        ERR_PROCESSED OFFSET(25) NUMBITS(1) [],
+    ],
+    CMD [
+        CMD OFFSET(0) NUMBITS(6) [
+            Start = 0x1D,
+            Process = 0x2E,
+            Run = 0x31,
+            Done = 0x16,
+        ],
+        ENTROPY_REQ OFFSET(8) NUMBITS(1) [],
+        HASH_CNT_CLR OFFSET(9) NUMBITS(1) [],
+    ],
+    STATUS [
+        SHA3_IDLE OFFSET(0) NUMBITS(1) [],
+        SHA3_ABSORB OFFSET(1) NUMBITS(1) [],
+        SHA3_SQUEEZE OFFSET(2) NUMBITS(1) [],
+        FIFO_DEPTH OFFSET(8) NUMBITS(5) [],
+        FIFO_EMPTY OFFSET(14) NUMBITS(1) [],
+        FIFO_FULL OFFSET(15) NUMBITS(1) [],
+    ],
+    KEY_LEN [
+        LEN OFFSET(0) NUMBITS(3) [
+            Key128 = 0,
+            Key192 = 1,
+            Key256 = 2,
+            Key384 = 3,
+            Key512 = 4,
+        ],
+    ],
+];
+
+// https://opentitan.org/book/hw/top_earlgrey/doc/
+pub const KMAC_BASE: StaticRef<KmacRegisters> =
+    unsafe { StaticRef::new(0x4112_0000 as *const KmacRegisters) };
+
+/// The longest KMAC key the block accepts, in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// The size of the prefix registers, in bytes.
+const PREFIX_LEN: usize = 44;
+
+/// `encode_string("KMAC")`, the start of the prefix for KMAC.
+const KMAC_FUNCTION_NAME: [u8; 6] = [0x01, 0x20, b'K', b'M', b'A', b'C'];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    /// No operation is in progress.
+    Idle,
+    /// The message is being absorbed.
+    Absorbing,
+    /// The permutation is running, waiting for `kmac_done`.
+    Processing,
+    /// A block of output is available in the state window.
+    Squeezing,
+}
+
+/// The big-endian bytes of `value`, and how many of the last ones are
+/// needed to represent it (at least one), as used by `left_encode` and
+/// `right_encode` in NIST SP 800-185.
+fn encode_value(value: usize) -> ([u8; 8], usize) {
+    let bytes = (value as u64).to_be_bytes();
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+    (bytes, cmp::max(1, 8 - zeros))
+}
+
+/// `encode_string("KMAC") || encode_string(customization)`, as loaded into
+/// the prefix registers.
+fn encode_prefix(customization: &[u8]) -> Result<[u8; PREFIX_LEN], ErrorCode> {
+    let (bits, n) = encode_value(customization.len() * 8);
+    let start = KMAC_FUNCTION_NAME.len() + 1 + n;
+    if start + customization.len() > PREFIX_LEN {
+        return Err(ErrorCode::SIZE);
+    }
+
+    let mut prefix = [0; PREFIX_LEN];
+    prefix[..KMAC_FUNCTION_NAME.len()].copy_from_slice(&KMAC_FUNCTION_NAME);
+    prefix[KMAC_FUNCTION_NAME.len()] = n as u8;
+    prefix[KMAC_FUNCTION_NAME.len() + 1..start].copy_from_slice(&bits[8 - n..]);
+    prefix[start..start + customization.len()].copy_from_slice(customization);
+    Ok(prefix)
+}
+
+pub struct Kmac<'a> {
+    registers: StaticRef<KmacRegisters>,
+
+    client: OptionalCell<&'a dyn SpongeClient>,
+    state: Cell<State>,
+    /// The number of bytes of output per permutation.
+    rate: Cell<usize>,
+    /// The most output the function produces, `None` for an XOF.
+    output_limit: Cell<Option<usize>>,
+    /// For KMAC, the output length in bits appended to the message.
+    kmac_output_bits: OptionalCell<usize>,
+    /// Output squeezed so far in this operation.
+    squeezed: Cell<usize>,
+    /// Position of the next output byte in the state window.
+    state_offset: Cell<usize>,
+
+    data: TakeCell<'static, [u8]>,
+    data_len: Cell<usize>,
+    data_index: Cell<usize>,
+    output: TakeCell<'static, [u8]>,
+    output_len: Cell<usize>,
+    output_index: Cell<usize>,
+    /// The result reported by the deferred call.
+    result: Cell<Result<(), ErrorCode>>,
+
+    clkmgr: &'a ClockManager,
+    /// Held from the start of an operation to `finish()`.
+    clock: MapCell<ClockGate<'a>>,
+
+    deferred_call: DeferredCall,
+}
+
+impl<'a> Kmac<'a> {
+    pub fn new(base: StaticRef<KmacRegisters>, clkmgr: &'a ClockManager) -> Kmac<'a> {
+        Kmac {
+            registers: base,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            rate: Cell::new(0),
+            output_limit: Cell::new(None),
+            kmac_output_bits: OptionalCell::empty(),
+            squeezed: Cell::new(0),
+            state_offset: Cell::new(0),
+            data: TakeCell::empty(),
+            data_len: Cell::new(0),
+            data_index: Cell::new(0),
+            output: TakeCell::empty(),
+            output_len: Cell::new(0),
+            output_index: Cell::new(0),
+            result: Cell::new(Ok(())),
+            clkmgr,
+            clock: MapCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Wait for the block to finish the previous operation.
+    fn wait_on_idle(&self) -> Result<(), ErrorCode> {
+        for _i in 0..10000 {
+            if self.registers.status.is_set(STATUS::SHA3_IDLE) {
+                return Ok(());
+            }
+        }
+        Err(ErrorCode::BUSY)
+    }
+
+    /// Configure the block and start absorbing a message.
+    ///
+    /// `rate` is the number of bytes of output per permutation for the
+    /// security strength in `cfg`.
+    fn start(&self, cfg: FieldValue<u32, CFG::Register>, rate: usize, output_limit: Option<usize>) {
+        self.rate.set(rate);
+        self.output_limit.set(output_limit);
+        self.squeezed.set(0);
+        self.state_offset.set(0);
+
+        // The configuration register is shadowed, so it is written twice.
+        let cfg = cfg + CFG::ENTROPY_MODE::Edn + CFG::ENTROPY_READY::SET;
+        self.registers.cfg_shadowed.write(cfg);
+        self.registers.cfg_shadowed.write(cfg);
+
+        self.registers
+            .intr_state
+            .write(INTR::KMAC_DONE::SET + INTR::FIFO_EMPTY::SET + INTR::KMAC_ERR::SET);
+        self.registers
+            .intr_enable
+            .write(INTR::KMAC_DONE::SET + INTR::KMAC_ERR::SET);
+        self.registers.cmd.write(CMD::CMD::Start);
+        self.state.set(State::Absorbing);
+    }
+
+    /// Load `key` into the key registers.
+    fn write_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let key_len = match key.len() {
+            16 => KEY_LEN::LEN::Key128,
+            24 => KEY_LEN::LEN::Key192,
+            32 => KEY_LEN::LEN::Key256,
+            48 => KEY_LEN::LEN::Key384,
+            64 => KEY_LEN::LEN::Key512,
+            _ => return Err(ErrorCode::NOSUPPORT),
+        };
+        self.registers.key_len.write(key_len);
+
+        // The key is given in the clear, so the second share is zero.
+        for (i, word) in key.chunks(4).enumerate() {
+            let v = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            self.registers.key_share0[i].set(v);
+            self.registers.key_share1[i].set(0);
+        }
+        Ok(())
+    }
+
+    /// Write the message to the FIFO until it is all written or the FIFO is
+    /// full. Returns true once all of it is written.
+    fn fill_fifo(&self) -> bool {
+        self.data.map_or(true, |data| {
+            let len = self.data_len.get();
+            let mut i = self.data_index.get();
+            while i < len && !self.registers.status.is_set(STATUS::FIFO_FULL) {
+                if len - i >= 4 {
+                    self.registers.msg_fifo.set(u32::from_le_bytes([
+                        data[i],
+                        data[i + 1],
+                        data[i + 2],
+                        data[i + 3],
+                    ]));
+                    i += 4;
+                } else {
+                    self.registers.msg_fifo_byte.set(data[i]);
+                    i += 1;
+                }
+            }
+            self.data_index.set(i);
+            i == len
+        })
+    }
+
+    /// Write `bytes` to the FIFO, waiting for space if needed.
+    fn write_fifo_bytes(&self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        for byte in bytes {
+            let mut j = 0;
+            while self.registers.status.is_set(STATUS::FIFO_FULL) {
+                j += 1;
+                if j > 10000 {
+                    return Err(ErrorCode::FAIL);
+                }
+            }
+            self.registers.msg_fifo_byte.set(*byte);
+        }
+        Ok(())
+    }
+
+    /// Copy output from the state window. If the block of output runs out
+    /// before `output` is full the permutation is run again. Returns true
+    /// once `output` is full.
+    fn read_state(&self) -> bool {
+        self.output.map_or(true, |output| {
+            let len = self.output_len.get();
+            let rate = self.rate.get();
+            let mut i = self.output_index.get();
+            let mut offset = self.state_offset.get();
+            while i < len && offset < rate {
+                let word = self.registers.state_share0[offset / 4].get()
+                    ^ self.registers.state_share1[offset / 4].get();
+                output[i] = word.to_le_bytes()[offset % 4];
+                i += 1;
+                offset += 1;
+            }
+            self.squeezed
+                .set(self.squeezed.get() + i - self.output_index.get());
+            self.output_index.set(i);
+            self.state_offset.set(offset);
+
+            if i < len {
+                self.run();
+                false
+            } else {
+                true
+            }
+        })
+    }
+
+    /// Run the permutation for the next block of output.
+    fn run(&self) {
+        self.state_offset.set(0);
+        self.state.set(State::Processing);
+        self.registers.cmd.write(CMD::CMD::Run);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let intrs = self.registers.intr_state.extract();
+        self.registers.intr_state.set(intrs.get());
+
+        if intrs.is_set(INTR::KMAC_ERR) {
+            self.registers.intr_enable.modify(INTR::FIFO_EMPTY::CLEAR);
+            self.client.map(|client| {
+                if let Some(data) = self.data.take() {
+                    client.absorb_done(Err(ErrorCode::FAIL), data);
+                }
+                if let Some(output) = self.output.take() {
+                    client.squeeze_done(Err(ErrorCode::FAIL), output);
+                }
+            });
+            return;
+        }
+
+        if intrs.is_set(INTR::FIFO_EMPTY)
+            && self.state.get() == State::Absorbing
+            && self.data.is_some()
+            && self.fill_fifo()
+        {
+            self.registers.intr_enable.modify(INTR::FIFO_EMPTY::CLEAR);
+            self.data.take().map(|data| {
+                self.client.map(|client| client.absorb_done(Ok(()), data));
+            });
+        }
+
+        if intrs.is_set(INTR::KMAC_DONE) && self.state.get() == State::Processing {
+            self.state.set(State::Squeezing);
+            if self.read_state() {
+                self.output.take().map(|output| {
+                    self.client
+                        .map(|client| client.squeeze_done(Ok(()), output));
+                });
+            }
+        }
+    }
+}
+
+impl<'a> Sha3<'a> for Kmac<'a> {
+    fn set_client(&self, client: &'a dyn SpongeClient) {
+        self.client.set(client);
+    }
+
+    fn start_sha3(&self, mode: Sha3Mode) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.clock.is_none() {
+            self.clock.put(self.clkmgr.acquire(Peripheral::Kmac));
+        }
+        self.wait_on_idle()?;
+
+        let (cfg, rate) = match mode {
+            Sha3Mode::Sha3_256 => (CFG::KSTRENGTH::L256 + CFG::MODE::Sha3, 136),
+            Sha3Mode::Sha3_512 => (CFG::KSTRENGTH::L512 + CFG::MODE::Sha3, 72),
+            Sha3Mode::Shake128 => (CFG::KSTRENGTH::L128 + CFG::MODE::Shake, 168),
+            Sha3Mode::Shake256 => (CFG::KSTRENGTH::L256 + CFG::MODE::Shake, 136),
+        };
+        self.kmac_output_bits.clear();
+        self.start(cfg, rate, mode.digest_len());
+        Ok(())
+    }
+
+    fn absorb(
+        &self,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.data.is_some() || self.output.is_some() || self.deferred_call.is_pending() {
+            return Err((ErrorCode::BUSY, data));
+        }
+        match self.state.get() {
+            State::Idle => return Err((ErrorCode::OFF, data)),
+            State::Absorbing => {}
+            State::Processing | State::Squeezing => return Err((ErrorCode::INVAL, data)),
+        }
+        if len > data.len() {
+            return Err((ErrorCode::SIZE, data));
+        }
+
+        self.data.replace(data);
+        self.data_len.set(len);
+        self.data_index.set(0);
+        if self.fill_fifo() {
+            self.result.set(Ok(()));
+            self.deferred_call.set();
+        } else {
+            self.registers.intr_state.write(INTR::FIFO_EMPTY::SET);
+            self.registers.intr_enable.modify(INTR::FIFO_EMPTY::SET);
+        }
+        Ok(())
+    }
+
+    fn squeeze(
+        &self,
+        output: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.data.is_some() || self.output.is_some() || self.deferred_call.is_pending() {
+            return Err((ErrorCode::BUSY, output));
+        }
+        if self.state.get() == State::Idle {
+            return Err((ErrorCode::OFF, output));
+        }
+        let limit = self.output_limit.get().unwrap_or(usize::MAX);
+        if len > output.len() || self.squeezed.get() + len > limit {
+            return Err((ErrorCode::SIZE, output));
+        }
+
+        self.output.replace(output);
+        self.output_len.set(len);
+        self.output_index.set(0);
+
+        if self.state.get() == State::Absorbing {
+            // KMAC ends the message with `right_encode(L)`.
+            if let Some(bits) = self.kmac_output_bits.take() {
+                let (bytes, n) = encode_value(bits);
+                if let Err(e) = self
+                    .write_fifo_bytes(&bytes[8 - n..])
+                    .and_then(|()| self.write_fifo_bytes(&[n as u8]))
+                {
+                    return Err((e, self.output.take().unwrap()));
+                }
+            }
+            self.state.set(State::Processing);
+            self.registers.cmd.write(CMD::CMD::Process);
+        } else if self.read_state() {
+            self.result.set(Ok(()));
+            self.deferred_call.set();
+        }
+        Ok(())
+    }
+
+    fn finish(&self) {
+        self.registers.intr_enable.set(0);
+
+        match self.state.get() {
+            State::Idle => {}
+            State::Absorbing => {
+                // The block can only leave the absorbing phase by
+                // processing the message.
+                self.registers.cmd.write(CMD::CMD::Process);
+                for _i in 0..10000 {
+                    if self.registers.status.is_set(STATUS::SHA3_SQUEEZE) {
+                        break;
+                    }
+                }
+                self.registers.cmd.write(CMD::CMD::Done);
+            }
+            State::Processing | State::Squeezing => {
+                self.registers.cmd.write(CMD::CMD::Done);
+            }
+        }
+        self.state.set(State::Idle);
+        self.kmac_output_bits.clear();
+
+        if self.data.is_some() || self.output.is_some() {
+            self.result.set(Err(ErrorCode::CANCEL));
+            self.deferred_call.set();
+        }
+
+        // The clock manager turns the clock off once the block is idle.
+        self.clock.take();
+    }
+}
+
+impl<'a> KmacHil<'a> for Kmac<'a> {
+    fn start_kmac(
+        &self,
+        mode: KmacMode,
+        key: &[u8],
+        customization: &[u8],
+        output_len: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let prefix = encode_prefix(customization)?;
+        if self.clock.is_none() {
+            self.clock.put(self.clkmgr.acquire(Peripheral::Kmac));
+        }
+        self.wait_on_idle()?;
+
+        self.write_key(key)?;
+        for (i, word) in prefix.chunks(4).enumerate() {
+            self.registers.prefix[i].set(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+
+        let (strength, rate) = match mode {
+            KmacMode::Kmac128 => (CFG::KSTRENGTH::L128, 168),
+            KmacMode::Kmac256 => (CFG::KSTRENGTH::L256, 136),
+        };
+        self.kmac_output_bits.set(output_len * 8);
+        let output_limit = if output_len == 0 {
+            None
+        } else {
+            Some(output_len)
+        };
+        self.start(
+            strength + CFG::MODE::CShake + CFG::KMAC_EN::SET,
+            rate,
+            output_limit,
+        );
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for Kmac<'_> {
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        let result = self.result.replace(Ok(()));
+        self.client.map(|client| {
+            if let Some(data) = self.data.take() {
+                client.absorb_done(result, data);
+            }
+            if let Some(output) = self.output.take() {
+                client.squeeze_done(result, output);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::clkmgr::ClkMgrRegisters;
+    use std::boxed::Box;
+
+    const CFG: usize = 0x14 / 4;
+    const CMD: usize = 0x18 / 4;
+    const STATUS: usize = 0x1C / 4;
+    const KEY_SHARE0: usize = 0x40 / 4;
+    const KEY_LEN: usize = 0xC0 / 4;
+    const PREFIX: usize = 0xC4 / 4;
+    const STATE_SHARE0: usize = 0x400 / 4;
+    const STATE_SHARE1: usize = 0x500 / 4;
+    const MSG_FIFO_BYTE: usize = 0x804 / 4;
+
+    struct Client {
+        squeezed: Cell<Option<Result<(), ErrorCode>>>,
+        output: TakeCell<'static, [u8]>,
+    }
+
+    impl SpongeClient for Client {
+        fn absorb_done(&self, _result: Result<(), ErrorCode>, _data: &'static mut [u8]) {}
+
+        fn squeeze_done(&self, result: Result<(), ErrorCode>, output: &'static mut [u8]) {
+            self.squeezed.set(Some(result));
+            self.output.replace(output);
+        }
+    }
+
+    fn with_kmac(test: impl FnOnce(&[Cell<u32>], &Kmac)) {
+        let clkmgr_memory: [Cell<u32>; 9] = Default::default();
+        let clkmgr = ClockManager::new(unsafe {
+            StaticRef::new(clkmgr_memory.as_ptr() as *const ClkMgrRegisters)
+        });
+        let memory: [Cell<u32>; 0x1000 / 4] = core::array::from_fn(|_| Cell::new(0));
+        memory[STATUS].set(1);
+        let kmac = Kmac::new(
+            unsafe { StaticRef::new(memory.as_ptr() as *const KmacRegisters) },
+            &clkmgr,
+        );
+        test(&memory, &kmac);
+    }
+
+    #[test]
+    fn encodings() {
+        let (bytes, n) = encode_value(256);
+        assert_eq!(&bytes[8 - n..], &[0x01, 0x00]);
+        let (bytes, n) = encode_value(0);
+        assert_eq!(&bytes[8 - n..], &[0x00]);
+
+        // NIST SP 800-185 KMAC sample #2.
+        let prefix = encode_prefix(b"My Tagged Application").unwrap();
+        assert_eq!(
+            prefix[..8],
+            [0x01, 0x20, b'K', b'M', b'A', b'C', 0x01, 0xA8]
+        );
+        assert_eq!(&prefix[8..29], b"My Tagged Application");
+        assert_eq!(encode_prefix(&[0; 36]), Err(ErrorCode::SIZE));
+    }
+
+    #[test]
+    fn sha3_output_is_limited_to_the_digest() {
+        with_kmac(|memory, kmac| {
+            kmac.start_sha3(Sha3Mode::Sha3_256).unwrap();
+            let cfg = memory[CFG].get();
+            assert_eq!(cfg & 0x3F, 2 << 1);
+            assert_eq!(memory[CMD].get(), 0x1D);
+
+            let output: &'static mut [u8] = Box::leak(Box::new([0; 64]));
+            let (error, _) = kmac.squeeze(output, 33).unwrap_err();
+            assert_eq!(error, ErrorCode::SIZE);
+            kmac.finish();
+            assert_eq!(memory[CMD].get(), 0x16);
+        });
+    }
+
+    #[test]
+    fn kmac_appends_output_length_and_reads_both_shares() {
+        with_kmac(|memory, kmac| {
+            let client: &'static Client = Box::leak(Box::new(Client {
+                squeezed: Cell::new(None),
+                output: TakeCell::empty(),
+            }));
+            kmac.set_client(client);
+
+            // NIST SP 800-185 KMAC sample #2 key and customization string.
+            let key: [u8; 32] = core::array::from_fn(|i| 0x40 + i as u8);
+            kmac.start_kmac(KmacMode::Kmac128, &key, b"My Tagged Application", 32)
+                .unwrap();
+            assert_eq!(memory[CFG].get() & 0x3F, 0b11_000_1);
+            assert_eq!(memory[KEY_LEN].get(), 2);
+            assert_eq!(memory[KEY_SHARE0].get(), 0x4342_4140);
+            assert_eq!(memory[PREFIX].get(), 0x4D4B_2001);
+            assert_eq!(memory[PREFIX + 1].get(), 0xA801_4341);
+
+            // `right_encode(256)` ends with the byte count.
+            let output: &'static mut [u8] = Box::leak(Box::new([0; 32]));
+            kmac.squeeze(output, 32).unwrap();
+            assert_eq!(memory[MSG_FIFO_BYTE].get() & 0xFF, 0x02);
+            assert_eq!(memory[CMD].get(), 0x2E);
+
+            memory[STATE_SHARE0].set(0x1234_5678);
+            memory[STATE_SHARE1].set(0x1111_1111);
+            memory[0].set(1);
+            kmac.handle_interrupt();
+            assert_eq!(client.squeezed.get(), Some(Ok(())));
+            let output = client.output.take().unwrap();
+            assert_eq!(output[..4], [0x69, 0x47, 0x25, 0x03]);
+        });
+    }
+}
@@ -13,16 +13,22 @@ pub mod chip_config;
 mod interrupts;
 
 pub mod aes;
+pub mod aes_ccm;
 pub mod aon_timer;
 pub mod chip;
+pub mod clkmgr;
 pub mod csrng;
 pub mod flash_ctrl;
 pub mod gpio;
 pub mod hmac;
 pub mod i2c;
+pub mod keymgr;
+pub mod kmac;
 pub mod otbn;
+pub mod pinmux;
 pub mod plic;
 pub mod pwrmgr;
+pub mod retention_ram;
 pub mod spi_host;
 pub mod timer;
 pub mod uart;
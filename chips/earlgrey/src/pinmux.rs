@@ -0,0 +1,306 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Pin multiplexer (PINMUX) for EarlGrey.
+//!
+//! The pin multiplexer connects the multiplexed IO (MIO) pads of the chip to
+//! peripheral inputs and outputs. Each peripheral input selects the pad it
+//! reads from, and each pad selects the peripheral output that drives it.
+//!
+//! Connecting the wrong pads can disconnect the console UART, so the
+//! configuration functions require a [PinmuxCapability].
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let pinmux_cap = create_capability!(earlgrey::pinmux::PinmuxCapability);
+//! peripherals
+//!     .pinmux
+//!     .configure_input(MioPad::Ioc3, PeripheralInput::Uart0Rx, &pinmux_cap)?;
+//! peripherals
+//!     .pinmux
+//!     .configure_output(PeripheralOutput::Uart0Tx, MioPad::Ioc4, &pinmux_cap)?;
+//! ```
+
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+pub const PINMUX_BASE: StaticRef<PinmuxRegisters> =
+    unsafe { StaticRef::new(0x4046_0000 as *const PinmuxRegisters) };
+
+/// Number of peripheral inputs connected to the pin multiplexer.
+const NUM_PERIPHERAL_INPUTS: usize = 57;
+
+/// Number of MIO pads.
+const NUM_MIO_PADS: usize = 47;
+
+/// `MIO_PERIPH_INSEL` value of the first MIO pad. 0 and 1 select a constant
+/// zero and one.
+const INSEL_FIRST_PAD: u32 = 2;
+
+/// `MIO_OUTSEL` value of the first peripheral output. 0, 1 and 2 select a
+/// constant zero, a constant one and high-Z.
+const OUTSEL_FIRST_PERIPHERAL: u32 = 3;
+
+register_structs! {
+    pub PinmuxRegisters {
+        (0x000 => alert_test: WriteOnly<u32>),
+        (0x004 => mio_periph_insel_regwen: [ReadWrite<u32, REGWEN::Register>; NUM_PERIPHERAL_INPUTS]),
+        (0x0E8 => mio_periph_insel: [ReadWrite<u32>; NUM_PERIPHERAL_INPUTS]),
+        (0x1CC => mio_outsel_regwen: [ReadWrite<u32, REGWEN::Register>; NUM_MIO_PADS]),
+        (0x288 => mio_outsel: [ReadWrite<u32>; NUM_MIO_PADS]),
+        (0x344 => @END),
+    }
+}
+
+register_bitfields![u32,
+    REGWEN [
+        EN OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+/// Capability required to change the pin multiplexer configuration.
+pub unsafe trait PinmuxCapability {}
+
+/// Multiplexed IO pads.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MioPad {
+    Ioa0 = 0,
+    Ioa1 = 1,
+    Ioa2 = 2,
+    Ioa3 = 3,
+    Ioa4 = 4,
+    Ioa5 = 5,
+    Ioa6 = 6,
+    Ioa7 = 7,
+    Ioa8 = 8,
+    Iob0 = 9,
+    Iob1 = 10,
+    Iob2 = 11,
+    Iob3 = 12,
+    Iob4 = 13,
+    Iob5 = 14,
+    Iob6 = 15,
+    Iob7 = 16,
+    Iob8 = 17,
+    Iob9 = 18,
+    Iob10 = 19,
+    Iob11 = 20,
+    Iob12 = 21,
+    Ioc0 = 22,
+    Ioc1 = 23,
+    Ioc2 = 24,
+    Ioc3 = 25,
+    Ioc4 = 26,
+    Ioc5 = 27,
+    Ioc6 = 28,
+    Ioc7 = 29,
+    Ioc8 = 30,
+    Ioc9 = 31,
+    Ioc10 = 32,
+    Ioc11 = 33,
+    Ioc12 = 34,
+    Ior0 = 35,
+    Ior1 = 36,
+    Ior2 = 37,
+    Ior3 = 38,
+    Ior4 = 39,
+    Ior5 = 40,
+    Ior6 = 41,
+    Ior7 = 42,
+    Ior10 = 43,
+    Ior11 = 44,
+    Ior12 = 45,
+    Ior13 = 46,
+}
+
+/// Peripheral inputs that can be connected to a pad.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PeripheralInput {
+    GpioGpio0 = 0,
+    GpioGpio1 = 1,
+    GpioGpio2 = 2,
+    GpioGpio3 = 3,
+    GpioGpio4 = 4,
+    GpioGpio5 = 5,
+    GpioGpio6 = 6,
+    GpioGpio7 = 7,
+    GpioGpio8 = 8,
+    GpioGpio9 = 9,
+    GpioGpio10 = 10,
+    GpioGpio11 = 11,
+    GpioGpio12 = 12,
+    GpioGpio13 = 13,
+    GpioGpio14 = 14,
+    GpioGpio15 = 15,
+    GpioGpio16 = 16,
+    GpioGpio17 = 17,
+    GpioGpio18 = 18,
+    GpioGpio19 = 19,
+    GpioGpio20 = 20,
+    GpioGpio21 = 21,
+    GpioGpio22 = 22,
+    GpioGpio23 = 23,
+    GpioGpio24 = 24,
+    GpioGpio25 = 25,
+    GpioGpio26 = 26,
+    GpioGpio27 = 27,
+    GpioGpio28 = 28,
+    GpioGpio29 = 29,
+    GpioGpio30 = 30,
+    GpioGpio31 = 31,
+    I2c0Sda = 32,
+    I2c0Scl = 33,
+    I2c1Sda = 34,
+    I2c1Scl = 35,
+    I2c2Sda = 36,
+    I2c2Scl = 37,
+    SpiHost1Sd0 = 38,
+    SpiHost1Sd1 = 39,
+    SpiHost1Sd2 = 40,
+    SpiHost1Sd3 = 41,
+    Uart0Rx = 42,
+    Uart1Rx = 43,
+    Uart2Rx = 44,
+    Uart3Rx = 45,
+    SpiDeviceTpmCsb = 46,
+    FlashCtrlTck = 47,
+    FlashCtrlTms = 48,
+    FlashCtrlTdi = 49,
+    SysrstCtrlAonAcPresent = 50,
+    SysrstCtrlAonKey0In = 51,
+    SysrstCtrlAonKey1In = 52,
+    SysrstCtrlAonKey2In = 53,
+    SysrstCtrlAonPwrbIn = 54,
+    SysrstCtrlAonLidOpen = 55,
+    UsbdevSense = 56,
+}
+
+/// Peripheral outputs that can drive a pad.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PeripheralOutput {
+    GpioGpio0 = 0,
+    GpioGpio1 = 1,
+    GpioGpio2 = 2,
+    GpioGpio3 = 3,
+    GpioGpio4 = 4,
+    GpioGpio5 = 5,
+    GpioGpio6 = 6,
+    GpioGpio7 = 7,
+    GpioGpio8 = 8,
+    GpioGpio9 = 9,
+    GpioGpio10 = 10,
+    GpioGpio11 = 11,
+    GpioGpio12 = 12,
+    GpioGpio13 = 13,
+    GpioGpio14 = 14,
+    GpioGpio15 = 15,
+    GpioGpio16 = 16,
+    GpioGpio17 = 17,
+    GpioGpio18 = 18,
+    GpioGpio19 = 19,
+    GpioGpio20 = 20,
+    GpioGpio21 = 21,
+    GpioGpio22 = 22,
+    GpioGpio23 = 23,
+    GpioGpio24 = 24,
+    GpioGpio25 = 25,
+    GpioGpio26 = 26,
+    GpioGpio27 = 27,
+    GpioGpio28 = 28,
+    GpioGpio29 = 29,
+    GpioGpio30 = 30,
+    GpioGpio31 = 31,
+    I2c0Sda = 32,
+    I2c0Scl = 33,
+    I2c1Sda = 34,
+    I2c1Scl = 35,
+    I2c2Sda = 36,
+    I2c2Scl = 37,
+    SpiHost1Sd0 = 38,
+    SpiHost1Sd1 = 39,
+    SpiHost1Sd2 = 40,
+    SpiHost1Sd3 = 41,
+    Uart0Tx = 42,
+    Uart1Tx = 43,
+    Uart2Tx = 44,
+    Uart3Tx = 45,
+    PattgenPda0Tx = 46,
+    PattgenPcl0Tx = 47,
+    PattgenPda1Tx = 48,
+    PattgenPcl1Tx = 49,
+    SpiHost1Sck = 50,
+    SpiHost1Csb = 51,
+    FlashCtrlTdo = 52,
+    SensorCtrlAstDebugOut0 = 53,
+    SensorCtrlAstDebugOut1 = 54,
+    SensorCtrlAstDebugOut2 = 55,
+    SensorCtrlAstDebugOut3 = 56,
+    SensorCtrlAstDebugOut4 = 57,
+    SensorCtrlAstDebugOut5 = 58,
+    SensorCtrlAstDebugOut6 = 59,
+    SensorCtrlAstDebugOut7 = 60,
+    SensorCtrlAstDebugOut8 = 61,
+    PwmAonPwm0 = 62,
+    PwmAonPwm1 = 63,
+    PwmAonPwm2 = 64,
+    PwmAonPwm3 = 65,
+    PwmAonPwm4 = 66,
+    PwmAonPwm5 = 67,
+    OtpCtrlTest0 = 68,
+    SysrstCtrlAonBatDisable = 69,
+    SysrstCtrlAonKey0Out = 70,
+    SysrstCtrlAonKey1Out = 71,
+    SysrstCtrlAonKey2Out = 72,
+    SysrstCtrlAonPwrbOut = 73,
+    SysrstCtrlAonZ3Wakeup = 74,
+}
+
+pub struct Pinmux {
+    registers: StaticRef<PinmuxRegisters>,
+}
+
+impl Pinmux {
+    pub const fn new(base: StaticRef<PinmuxRegisters>) -> Pinmux {
+        Pinmux { registers: base }
+    }
+
+    /// Connect `peripheral_input` to `mio_pad`.
+    ///
+    /// Returns `NOSUPPORT` if the selection for `peripheral_input` has been
+    /// locked.
+    pub fn configure_input(
+        &self,
+        mio_pad: MioPad,
+        peripheral_input: PeripheralInput,
+        _cap: &dyn PinmuxCapability,
+    ) -> Result<(), ErrorCode> {
+        let input = peripheral_input as usize;
+        if !self.registers.mio_periph_insel_regwen[input].is_set(REGWEN::EN) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.registers.mio_periph_insel[input].set(INSEL_FIRST_PAD + mio_pad as u32);
+        Ok(())
+    }
+
+    /// Drive `mio_pad` with `peripheral_output`.
+    ///
+    /// Returns `NOSUPPORT` if the selection for `mio_pad` has been locked.
+    pub fn configure_output(
+        &self,
+        peripheral_output: PeripheralOutput,
+        mio_pad: MioPad,
+        _cap: &dyn PinmuxCapability,
+    ) -> Result<(), ErrorCode> {
+        let pad = mio_pad as usize;
+        if !self.registers.mio_outsel_regwen[pad].is_set(REGWEN::EN) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.registers.mio_outsel[pad].set(OUTSEL_FIRST_PERIPHERAL + peripheral_output as u32);
+        Ok(())
+    }
+}
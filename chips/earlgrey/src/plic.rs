@@ -3,6 +3,11 @@
 // Copyright Tock Contributors 2022.
 
 //! Platform Level Interrupt Control peripheral driver.
+//!
+//! The priorities of interrupts and the priority threshold of the hart can
+//! be changed at runtime. An interrupt is only taken if its priority is
+//! above the threshold, so a wrong priority can silence a peripheral: the
+//! functions that change them require a [PlicConfigCapability].
 
 use kernel::utilities::cells::VolatileCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
@@ -17,6 +22,39 @@ pub static mut PLIC: Plic = Plic::new(PLIC_BASE);
 
 pub const PLIC_REGS: usize = 6;
 
+/// Number of interrupt sources, including the reserved source 0.
+const NUM_SOURCES: u32 = 181;
+
+/// Number of harts with an interrupt context.
+const NUM_HARTS: usize = 1;
+
+/// Highest priority of an interrupt.
+pub const MAX_PRIORITY: u8 = 7;
+
+/// Capability required to change interrupt priorities and thresholds.
+pub unsafe trait PlicConfigCapability {}
+
+/// An interrupt priority or threshold, from 0 to [MAX_PRIORITY].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PlicPriority(u8);
+
+impl PlicPriority {
+    /// Panics in debug builds if `priority` is above [MAX_PRIORITY], and
+    /// clamps it otherwise.
+    pub const fn new(priority: u8) -> PlicPriority {
+        debug_assert!(priority <= MAX_PRIORITY);
+        if priority > MAX_PRIORITY {
+            PlicPriority(MAX_PRIORITY)
+        } else {
+            PlicPriority(priority)
+        }
+    }
+
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+}
+
 register_structs! {
     pub PlicRegisters {
         /// Interrupt Priority Registers
@@ -88,6 +126,36 @@ impl Plic {
         self.registers.threshold.write(priority::Priority.val(1));
     }
 
+    /// Set the priority of interrupt `interrupt_id`. Priority 0 never
+    /// interrupts.
+    pub fn set_priority(
+        &self,
+        interrupt_id: u32,
+        priority: PlicPriority,
+        _cap: &dyn PlicConfigCapability,
+    ) {
+        if interrupt_id >= NUM_SOURCES {
+            panic!("Invalid IRQ: {}", interrupt_id);
+        }
+        self.registers.priority[interrupt_id as usize]
+            .write(priority::Priority.val(priority.value() as u32));
+    }
+
+    /// Only take interrupts with a priority above `threshold` on `hart`.
+    pub fn set_threshold(
+        &self,
+        hart: usize,
+        threshold: PlicPriority,
+        _cap: &dyn PlicConfigCapability,
+    ) {
+        if hart >= NUM_HARTS {
+            panic!("Invalid hart: {}", hart);
+        }
+        self.registers
+            .threshold
+            .write(priority::Priority.val(threshold.value() as u32));
+    }
+
     /// Disable specific interrupt.
     pub fn disable(&self, index: u32) {
         let offset = if index < 32 {
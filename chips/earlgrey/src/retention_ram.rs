@@ -0,0 +1,424 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Retention SRAM for EarlGrey
+//!
+//! <https://opentitan.org/book/hw/ip/sram_ctrl/>
+//!
+//! The 4 KiB retention SRAM is in the always-on domain, so it keeps its
+//! contents through deep sleep, and through resets that leave its SRAM
+//! controller alone, such as a software reset. The kernel gets a small
+//! region at the end of it, leaving the start to the ROM and ROM_EXT, to
+//! keep state such as a boot counter or the context of a wakeup.
+//!
+//! The SRAM is scrambled with a key held by its controller. A power-on
+//! reset resets the controller and loses the key, after which the old
+//! contents cannot be read back: they would fail their integrity check and
+//! fault the bus. [RetentionRam::init] is called once after every reset,
+//! with the reason for the reset. It keeps the contents if the reset does
+//! not lose them and the controller still has a valid key, and otherwise
+//! asks the controller for a new key and to initialize the SRAM, so that it
+//! can be read.
+//!
+//! Values are read and written with [RetentionRam::read] and
+//! [RetentionRam::write], for any type that implements [Retained]. They are
+//! stored with a magic number, their size and a checksum, so a region that
+//! was never written or was only partly written when power went away reads
+//! as invalid.
+
+use core::cell::Cell;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::{
+    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+register_structs! {
+    pub SramCtrlRegisters {
+        (0x00 => alert_test: WriteOnly<u32>),
+        (0x04 => status: ReadOnly<u32, STATUS::Register>),
+        (0x08 => exec_regwen: ReadWrite<u32>),
+        (0x0C => exec: ReadWrite<u32>),
+        (0x10 => ctrl_regwen: ReadWrite<u32>),
+        (0x14 => ctrl: WriteOnly<u32, CTRL::Register>),
+        (0x18 => @END),
+    }
+}
+
+register_structs! {
+    pub RstMgrRegisters {
+        (0x00 => alert_test: WriteOnly<u32>),
+        (0x04 => reset_req: ReadWrite<u32>),
+        (0x08 => reset_info: ReadWrite<u32, RESET_INFO::Register>),
+        (0x0C => @END),
+    }
+}
+
+register_structs! {
+    /// The kernel's region of the retention SRAM.
+    pub RetentionRegion {
+        (0x00 => magic: ReadWrite<u32>),
+        (0x04 => len: ReadWrite<u32>),
+        (0x08 => checksum: ReadWrite<u32>),
+        (0x0C => data: [ReadWrite<u32>; REGION_WORDS]),
+        (0x100 => @END),
+    }
+}
+
+register_bitfields![u32,
+    STATUS [
+        BUS_INTEG_ERROR OFFSET(0) NUMBITS(1) [],
+        INIT_ERROR OFFSET(1) NUMBITS(1) [],
+        ESCALATED OFFSET(2) NUMBITS(1) [],
+        SCR_KEY_VALID OFFSET(3) NUMBITS(1) [],
+        SCR_KEY_SEED_VALID OFFSET(4) NUMBITS(1) [],
+        INIT_DONE OFFSET(5) NUMBITS(1) [],
+    ],
+    CTRL [
+        RENEW_SCR_KEY OFFSET(0) NUMBITS(1) [],
+        INIT OFFSET(1) NUMBITS(1) [],
+    ],
+    RESET_INFO [
+        POR OFFSET(0) NUMBITS(1) [],
+        LOW_POWER_EXIT OFFSET(1) NUMBITS(1) [],
+        NDM_RESET OFFSET(2) NUMBITS(1) [],
+        SW_RESET OFFSET(3) NUMBITS(1) [],
+        HW_REQ OFFSET(4) NUMBITS(4) [],
+    ],
+];
+
+pub const SRAM_CTRL_RET_BASE: StaticRef<SramCtrlRegisters> =
+    unsafe { StaticRef::new(0x4050_0000 as *const SramCtrlRegisters) };
+
+pub const RSTMGR_BASE: StaticRef<RstMgrRegisters> =
+    unsafe { StaticRef::new(0x4041_0000 as *const RstMgrRegisters) };
+
+/// The last 256 bytes of the retention SRAM, at 0x4060_0000.
+pub const RETENTION_REGION_BASE: StaticRef<RetentionRegion> =
+    unsafe { StaticRef::new(0x4060_0F00 as *const RetentionRegion) };
+
+/// Words of data the region can hold.
+pub const REGION_WORDS: usize = 61;
+
+/// Marks a region holding a value, "TRET".
+const MAGIC: u32 = 0x5445_5254;
+
+/// Why the chip last reset.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResetReason {
+    /// Power was applied, or the power-on reset was asserted.
+    PowerOn,
+    /// Woke from deep sleep.
+    LowPowerExit,
+    /// Software asked for a reset.
+    Software,
+    /// The debugger or a peripheral, such as the watchdog, asked for a
+    /// reset.
+    Other,
+}
+
+impl ResetReason {
+    /// The reason recorded by the reset manager. If several are recorded,
+    /// the one that reset the most wins.
+    pub fn read(rstmgr: StaticRef<RstMgrRegisters>) -> ResetReason {
+        let info = rstmgr.reset_info.extract();
+        if info.is_set(RESET_INFO::POR) {
+            ResetReason::PowerOn
+        } else if info.read(RESET_INFO::NDM_RESET) != 0 || info.read(RESET_INFO::HW_REQ) != 0 {
+            ResetReason::Other
+        } else if info.is_set(RESET_INFO::SW_RESET) {
+            ResetReason::Software
+        } else {
+            ResetReason::LowPowerExit
+        }
+    }
+
+    /// Whether the retention SRAM can keep its contents through the reset.
+    /// Peripheral and debugger resets can reset its controller, or lose
+    /// power on the way, so they are not trusted.
+    pub fn retains(&self) -> bool {
+        match self {
+            ResetReason::LowPowerExit | ResetReason::Software => true,
+            ResetReason::PowerOn | ResetReason::Other => false,
+        }
+    }
+}
+
+/// A value that can be kept in the retention SRAM.
+pub trait Retained: Sized {
+    /// Words the value takes, at most [REGION_WORDS].
+    const WORDS: usize;
+
+    /// Store the value in `words`, which has `WORDS` words.
+    fn store(&self, words: &mut [u32]);
+
+    /// Load a value from `words`, which has `WORDS` words.
+    fn load(words: &[u32]) -> Self;
+}
+
+impl Retained for u32 {
+    const WORDS: usize = 1;
+
+    fn store(&self, words: &mut [u32]) {
+        words[0] = *self;
+    }
+
+    fn load(words: &[u32]) -> Self {
+        words[0]
+    }
+}
+
+impl Retained for u64 {
+    const WORDS: usize = 2;
+
+    fn store(&self, words: &mut [u32]) {
+        words[0] = *self as u32;
+        words[1] = (*self >> 32) as u32;
+    }
+
+    fn load(words: &[u32]) -> Self {
+        words[0] as u64 | (words[1] as u64) << 32
+    }
+}
+
+impl<const N: usize> Retained for [u32; N] {
+    const WORDS: usize = N;
+
+    fn store(&self, words: &mut [u32]) {
+        words.copy_from_slice(self);
+    }
+
+    fn load(words: &[u32]) -> Self {
+        let mut value = [0; N];
+        value.copy_from_slice(words);
+        value
+    }
+}
+
+/// A checksum of the stored words, which also covers their number so a
+/// value of one type does not read as another.
+fn checksum(words: &[u32]) -> u32 {
+    words
+        .iter()
+        .fold(0x811C_9DC5 ^ words.len() as u32, |hash, &word| {
+            (hash ^ word).wrapping_mul(0x0100_0193).rotate_left(5)
+        })
+}
+
+pub struct RetentionRam {
+    registers: StaticRef<SramCtrlRegisters>,
+    region: StaticRef<RetentionRegion>,
+    /// Whether the SRAM can be read since the reset.
+    ready: Cell<bool>,
+    /// Whether the contents were kept through the reset.
+    retained: Cell<bool>,
+}
+
+impl RetentionRam {
+    pub const fn new(
+        registers: StaticRef<SramCtrlRegisters>,
+        region: StaticRef<RetentionRegion>,
+    ) -> RetentionRam {
+        RetentionRam {
+            registers,
+            region,
+            ready: Cell::new(false),
+            retained: Cell::new(false),
+        }
+    }
+
+    /// Set up the retention SRAM after a reset for `reason`. Returns whether
+    /// its contents were kept, otherwise it is initialized and holds no
+    /// value.
+    pub fn init(&self, reason: ResetReason) -> bool {
+        let regs = self.registers;
+        let retained = reason.retains() && regs.status.is_set(STATUS::SCR_KEY_VALID);
+        if !retained {
+            regs.ctrl.write(CTRL::RENEW_SCR_KEY::SET + CTRL::INIT::SET);
+            while !regs.status.is_set(STATUS::INIT_DONE) {
+                if regs.status.is_set(STATUS::INIT_ERROR) {
+                    return false;
+                }
+            }
+            self.region.magic.set(0);
+        }
+        self.ready.set(true);
+        self.retained.set(retained);
+        retained
+    }
+
+    /// Whether the contents were kept through the last reset.
+    pub fn retained(&self) -> bool {
+        self.retained.get()
+    }
+
+    /// Whether the region holds a complete value.
+    pub fn is_valid(&self) -> bool {
+        if !self.ready.get() || self.region.magic.get() != MAGIC {
+            return false;
+        }
+        let len = self.region.len.get() as usize;
+        len <= REGION_WORDS && self.region.checksum.get() == self.checksum(len)
+    }
+
+    fn checksum(&self, len: usize) -> u32 {
+        let mut words = [0; REGION_WORDS];
+        for (word, register) in words.iter_mut().zip(self.region.data.iter()).take(len) {
+            *word = register.get();
+        }
+        checksum(&words[..len])
+    }
+
+    /// Read the value in the region.
+    ///
+    /// Fails with `OFF` before [RetentionRam::init], `INVAL` if the region
+    /// holds no complete value, and `SIZE` if it holds a value of another
+    /// size.
+    pub fn read<T: Retained>(&self) -> Result<T, ErrorCode> {
+        if !self.ready.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if !self.is_valid() {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.region.len.get() as usize != T::WORDS {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut words = [0; REGION_WORDS];
+        for (word, register) in words.iter_mut().zip(self.region.data.iter()) {
+            *word = register.get();
+        }
+        Ok(T::load(&words[..T::WORDS]))
+    }
+
+    /// Write `value` to the region.
+    ///
+    /// Fails with `OFF` before [RetentionRam::init] and `SIZE` if the value
+    /// does not fit.
+    pub fn write<T: Retained>(&self, value: &T) -> Result<(), ErrorCode> {
+        if !self.ready.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if T::WORDS > REGION_WORDS {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut words = [0; REGION_WORDS];
+        value.store(&mut words[..T::WORDS]);
+
+        // Invalidate the region while it is written, so a reset part way
+        // through leaves no value rather than a torn one.
+        let region = self.region;
+        region.magic.set(0);
+        for (register, &word) in region.data.iter().zip(words[..T::WORDS].iter()) {
+            register.set(word);
+        }
+        region.len.set(T::WORDS as u32);
+        region.checksum.set(checksum(&words[..T::WORDS]));
+        region.magic.set(MAGIC);
+        Ok(())
+    }
+
+    /// Remove the value from the region.
+    pub fn clear(&self) {
+        if self.ready.get() {
+            self.region.magic.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: usize = 0x04 / 4;
+    const CTRL: usize = 0x14 / 4;
+    const RESET_INFO: usize = 0x08 / 4;
+
+    const SCR_KEY_VALID: u32 = 1 << 3;
+    const INIT_DONE: u32 = 1 << 5;
+
+    fn retention_ram(sram_ctrl: &[Cell<u32>; 6], region: &[Cell<u32>; 0x100 / 4]) -> RetentionRam {
+        RetentionRam::new(
+            unsafe { StaticRef::new(sram_ctrl.as_ptr() as *const SramCtrlRegisters) },
+            unsafe { StaticRef::new(region.as_ptr() as *const RetentionRegion) },
+        )
+    }
+
+    #[test]
+    fn value_survives_deep_sleep() {
+        let sram_ctrl: [Cell<u32>; 6] = Default::default();
+        let region: [Cell<u32>; 0x100 / 4] = core::array::from_fn(|i| Cell::new(i as u32));
+
+        // Power on: the SRAM is scrambled with a new key and initialized
+        // before anything reads it.
+        sram_ctrl[STATUS].set(SCR_KEY_VALID | INIT_DONE);
+        let ram = retention_ram(&sram_ctrl, &region);
+        assert_eq!(ram.read::<u32>(), Err(ErrorCode::OFF));
+        assert!(!ram.init(ResetReason::PowerOn));
+        assert_eq!(sram_ctrl[CTRL].get(), 0b11);
+        assert!(!ram.is_valid());
+        assert_eq!(ram.read::<u32>(), Err(ErrorCode::INVAL));
+
+        ram.write(&0xB007_0001u32).unwrap();
+        assert_eq!(ram.read::<u32>(), Ok(0xB007_0001));
+
+        // Wake from deep sleep: the controller kept its key, and the kernel
+        // starts again from scratch.
+        sram_ctrl[CTRL].set(0);
+        let ram = retention_ram(&sram_ctrl, &region);
+        assert!(ram.init(ResetReason::LowPowerExit));
+        assert_eq!(sram_ctrl[CTRL].get(), 0);
+        assert!(ram.retained());
+        assert_eq!(ram.read::<u32>(), Ok(0xB007_0001));
+        assert_eq!(ram.read::<u64>(), Err(ErrorCode::SIZE));
+
+        // A larger value replaces it, and a corrupted one is not read.
+        ram.write(&[1u32, 2, 3]).unwrap();
+        assert_eq!(ram.read::<[u32; 3]>(), Ok([1, 2, 3]));
+        region[4].set(7);
+        assert_eq!(ram.read::<[u32; 3]>(), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn contents_are_lost_without_the_key() {
+        let sram_ctrl: [Cell<u32>; 6] = Default::default();
+        let region: [Cell<u32>; 0x100 / 4] = core::array::from_fn(|_| Cell::new(0));
+        sram_ctrl[STATUS].set(SCR_KEY_VALID | INIT_DONE);
+        let ram = retention_ram(&sram_ctrl, &region);
+        ram.init(ResetReason::PowerOn);
+        ram.write(&42u64).unwrap();
+
+        // A software reset that also reset the controller cannot read the
+        // old contents back.
+        sram_ctrl[STATUS].set(INIT_DONE);
+        let ram = retention_ram(&sram_ctrl, &region);
+        assert!(!ram.init(ResetReason::Software));
+        assert_eq!(sram_ctrl[CTRL].get(), 0b11);
+        assert_eq!(ram.read::<u64>(), Err(ErrorCode::INVAL));
+
+        // Nor does a watchdog reset, even with the key.
+        ram.write(&42u64).unwrap();
+        sram_ctrl[STATUS].set(SCR_KEY_VALID | INIT_DONE);
+        let ram = retention_ram(&sram_ctrl, &region);
+        assert!(!ram.init(ResetReason::Other));
+        assert!(!ram.is_valid());
+    }
+
+    #[test]
+    fn reset_reasons() {
+        let rstmgr: [Cell<u32>; 3] = Default::default();
+        let rstmgr_ref = unsafe { StaticRef::new(rstmgr.as_ptr() as *const RstMgrRegisters) };
+        for (info, reason) in [
+            (0b0000_0011, ResetReason::PowerOn),
+            (0b0000_0010, ResetReason::LowPowerExit),
+            (0b0000_1010, ResetReason::Software),
+            (0b0001_1000, ResetReason::Other),
+            (0b0000_0100, ResetReason::Other),
+        ] {
+            rstmgr[RESET_INFO].set(info);
+            assert_eq!(ResetReason::read(rstmgr_ref), reason);
+        }
+    }
+}
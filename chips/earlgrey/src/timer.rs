@@ -11,6 +11,7 @@ use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
+use rv32i::csr::CSR;
 use rv32i::machine_timer::MachineTimer;
 
 const PRESCALE: u16 = ((CONFIG.cpu_freq / 10_000) - 1) as u16; // 10Khz
@@ -162,3 +163,57 @@ impl<'a> time::Alarm<'a> for RvTimer<'a> {
 
 const TIMER_BASE: StaticRef<TimerRegisters> =
     unsafe { StaticRef::new(0x4010_0000 as *const TimerRegisters) };
+
+/// Busy-wait for at least `us` microseconds.
+///
+/// For short, precise waits during bring-up, e.g. reset pulses or bit-banged
+/// timing. This spins on the `mcycle` CSR and converts with the configured
+/// CPU clock, so it is accurate to a cycle and never relies on interrupts.
+/// It blocks the whole kernel: use an alarm for anything longer.
+pub fn delay_us(us: u32) {
+    delay_cycles(us_to_cycles(us, CONFIG.cpu_freq));
+}
+
+/// Busy-wait for at least `cycles` CPU clock cycles.
+pub fn delay_cycles(cycles: u64) {
+    spin_for(|| CSR.read_cycle_counter(), cycles);
+}
+
+fn us_to_cycles(us: u32, cpu_freq: u32) -> u64 {
+    (us as u64 * cpu_freq as u64 + 999_999) / 1_000_000
+}
+
+/// Spin until `counter` has advanced by `ticks`.
+fn spin_for(counter: impl Fn() -> u64, ticks: u64) {
+    let start = counter();
+    while counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn delay_us_spans_the_expected_mtime() {
+        // Simulate a core that spends a few cycles per loop iteration, and
+        // the rv_timer counting with the prescaler `setup` programs.
+        let mcycle = Cell::new(123_456u64);
+        let mtime = |cycles: u64| cycles / (PRESCALE as u64 + 1);
+        let counter = || {
+            mcycle.set(mcycle.get() + 7);
+            mcycle.get()
+        };
+
+        let start = mtime(mcycle.get());
+        spin_for(counter, us_to_cycles(1000, CONFIG.cpu_freq));
+        let elapsed = mtime(mcycle.get()) - start;
+
+        // 1 ms is 10 ticks of the 10 kHz timer, give or take the tick the
+        // delay started or ended in.
+        assert!((9..=11).contains(&elapsed), "elapsed {} ticks", elapsed);
+        assert_eq!(us_to_cycles(1, 500_000), 1);
+    }
+}
@@ -3,8 +3,8 @@
 // Copyright Tock Contributors 2022.
 
 use kernel::utilities::StaticRef;
-pub use lowrisc::uart::Uart;
 use lowrisc::uart::UartRegisters;
+pub use lowrisc::uart::{FlushClient, PanicWriter, TxWatermark, Uart};
 
 use crate::chip_config::CONFIG;
 
@@ -12,3 +12,12 @@ pub const UART0_BAUDRATE: u32 = CONFIG.uart_baudrate;
 
 pub const UART0_BASE: StaticRef<UartRegisters> =
     unsafe { StaticRef::new(0x4000_0000 as *const UartRegisters) };
+
+pub const UART1_BASE: StaticRef<UartRegisters> =
+    unsafe { StaticRef::new(0x4001_0000 as *const UartRegisters) };
+
+pub const UART2_BASE: StaticRef<UartRegisters> =
+    unsafe { StaticRef::new(0x4002_0000 as *const UartRegisters) };
+
+pub const UART3_BASE: StaticRef<UartRegisters> =
+    unsafe { StaticRef::new(0x4003_0000 as *const UartRegisters) };
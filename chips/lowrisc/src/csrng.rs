@@ -5,7 +5,15 @@
 //! Support for the CSRNG hardware block on OpenTitan
 //!
 //! <https://docs.opentitan.org/hw/ip/csrng/doc>
-
+//!
+//! By default the DRBG is instantiated on the first request for entropy. A
+//! board can instead instantiate it with a personalization string, such as
+//! one derived from the device ID, with
+//! [`CsRng::instantiate_with_personalization`]. The seed is then the output
+//! of the entropy source XORed with the personalization string, so different
+//! strings give separate streams that still depend on hardware entropy.
+
+use core::cell::Cell;
 use kernel::hil::entropy::{Client32, Continue, Entropy32};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
@@ -88,10 +96,41 @@ register_bitfields![u32,
 
 pub const TWO_UNITS_OF_128BIT_ENTROPY: u32 = 0x02;
 
+/// Maximum length of a personalization string, in 32-bit words.
+pub const MAX_PERSONALIZATION_WORDS: usize = 12;
+
+/// Replaces any existing instantiation with one seeded from the entropy
+/// source XORed with `personalization`, writing each command word with
+/// `write_cmd`.
+fn issue_instantiate(
+    personalization: &[u32],
+    mut wait_for_cmd_ready: impl FnMut() -> Result<(), ErrorCode>,
+    mut write_cmd: impl FnMut(u32),
+) -> Result<(), ErrorCode> {
+    wait_for_cmd_ready()?;
+    write_cmd(COMMAND::ACMD::UNINSTANTIATE.into());
+
+    wait_for_cmd_ready()?;
+    write_cmd(
+        (COMMAND::ACMD::INSTANTIATE
+            + COMMAND::FLAGS::INSTANTIATE_SOURCE_XOR_SEED
+            + COMMAND::CLEN.val(personalization.len() as u32)
+            + COMMAND::GLEN.val(0x00))
+        .into(),
+    );
+    // The additional data follows the command header.
+    for &word in personalization {
+        write_cmd(word);
+    }
+
+    wait_for_cmd_ready()
+}
+
 pub struct CsRng<'a> {
     registers: StaticRef<CsRngRegisters>,
 
     client: OptionalCell<&'a dyn Client32>,
+    instantiated: Cell<bool>,
 }
 
 struct CsRngIter<'a, 'b: 'a>(&'a CsRng<'b>);
@@ -113,7 +152,45 @@ impl<'a> CsRng<'a> {
         CsRng {
             registers: base,
             client: OptionalCell::empty(),
+            instantiated: Cell::new(false),
+        }
+    }
+
+    /// Instantiates the DRBG with a seed from the entropy source XORed with
+    /// `personalization`, which is at most `MAX_PERSONALIZATION_WORDS`
+    /// long. Any existing instantiation is uninstantiated first. Later
+    /// requests for entropy generate from this instantiation.
+    pub fn instantiate_with_personalization(
+        &self,
+        personalization: &[u32],
+    ) -> Result<(), ErrorCode> {
+        if personalization.len() > MAX_PERSONALIZATION_WORDS {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.disable_interrupts();
+        self.enable()?;
+
+        self.instantiated.set(false);
+        issue_instantiate(
+            personalization,
+            || self.wait_for_cmd_ready(),
+            |cmd| self.registers.cmd_req.set(cmd),
+        )?;
+        self.instantiated.set(true);
+        Ok(())
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if !self.registers.regwen.is_set(REGWEN::REGWEN) {
+            // Registers are read only
+            return Err(ErrorCode::FAIL);
         }
+
+        self.registers.ctrl.write(
+            CTRL::ENABLE::ENABLE + CTRL::READ_INT_STATE::ENABLE + CTRL::SW_APP_ENABLE::ENABLE,
+        );
+        Ok(())
     }
 
     fn enable_interrupts(&self) {
@@ -197,30 +274,25 @@ impl<'a> Entropy32<'a> for CsRng<'a> {
 
     fn get(&self) -> Result<(), ErrorCode> {
         self.disable_interrupts();
+        self.enable()?;
 
-        if !self.registers.regwen.is_set(REGWEN::REGWEN) {
-            // Registers are read only
-            return Err(ErrorCode::FAIL);
-        }
-
-        self.registers.ctrl.write(
-            CTRL::ENABLE::ENABLE + CTRL::READ_INT_STATE::ENABLE + CTRL::SW_APP_ENABLE::ENABLE,
-        );
+        if !self.instantiated.get() {
+            // Check if IP ready for new command
+            match self.wait_for_cmd_ready() {
+                Ok(()) => {}
+                Err(e) => return Err(e),
+            }
 
-        // Check if IP ready for new command
-        match self.wait_for_cmd_ready() {
-            Ok(()) => {}
-            Err(e) => return Err(e),
+            // Init IP
+            self.registers.cmd_req.write(
+                COMMAND::ACMD::INSTANTIATE
+                    + COMMAND::FLAGS::INSTANTIATE_ZERO_ADDITIONAL_SEED
+                    + COMMAND::CLEN.val(0x00)
+                    + COMMAND::GLEN.val(0x00),
+            );
+            self.instantiated.set(true);
         }
 
-        // Init IP
-        self.registers.cmd_req.write(
-            COMMAND::ACMD::INSTANTIATE
-                + COMMAND::FLAGS::INSTANTIATE_ZERO_ADDITIONAL_SEED
-                + COMMAND::CLEN.val(0x00)
-                + COMMAND::GLEN.val(0x00),
-        );
-
         // Check if IP ready for new command
         match self.wait_for_cmd_ready() {
             Ok(()) => {}
@@ -245,7 +317,71 @@ impl<'a> Entropy32<'a> for CsRng<'a> {
         self.disable_interrupts();
 
         self.registers.cmd_req.write(COMMAND::ACMD::UNINSTANTIATE);
+        self.instantiated.set(false);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instantiate_with_personalization_commands() {
+        let personalization = [0x0123_4567, 0x89AB_CDEF, 0xDEAD_BEEF];
+        let mut written = [0u32; 8];
+        let mut len = 0;
+        let mut ready_checks = 0;
+
+        let result = issue_instantiate(
+            &personalization,
+            || {
+                ready_checks += 1;
+                Ok(())
+            },
+            |cmd| {
+                written[len] = cmd;
+                len += 1;
+            },
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(ready_checks, 3);
+        assert_eq!(
+            &written[..len],
+            &[
+                // Uninstantiate.
+                0x0000_0005,
+                // Instantiate with the entropy source XORed with 3 words of
+                // additional data.
+                0x0000_0931,
+                0x0123_4567,
+                0x89AB_CDEF,
+                0xDEAD_BEEF,
+            ]
+        );
+    }
+
+    #[test]
+    fn instantiate_stops_if_not_ready() {
+        let mut len = 0;
+        let mut ready = [true, false].into_iter();
+
+        let result = issue_instantiate(
+            &[1, 2],
+            || {
+                if ready.next().unwrap() {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::BUSY)
+                }
+            },
+            |_| len += 1,
+        );
+
+        assert_eq!(result, Err(ErrorCode::BUSY));
+        // Only the uninstantiate command was written.
+        assert_eq!(len, 1);
+    }
+}
@@ -288,6 +288,23 @@ pub const FLASH_PROG_WINDOW_MASK: u32 = 0xFFFFFFF0;
 
 pub struct LowRiscPage(pub [u8; PAGE_SIZE as usize]);
 
+/// Convert a page aligned region of `size` bytes at absolute address `base`
+/// into its first page number and number of pages.
+fn mp_region_pages(base: usize, size: usize) -> Result<(usize, usize), ErrorCode> {
+    if size == 0 || base % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(ErrorCode::INVAL);
+    }
+    let offset = base
+        .checked_sub(FLASH_ADDR_OFFSET)
+        .ok_or(ErrorCode::NOSUPPORT)?;
+    let page_number = offset / PAGE_SIZE;
+    let num_pages = size / PAGE_SIZE;
+    if page_number >= FLASH_MAX_PAGES || num_pages > FLASH_MAX_PAGES - page_number {
+        return Err(ErrorCode::NOSUPPORT);
+    }
+    Ok((page_number, num_pages))
+}
+
 /// Defines region permissions for flash memory protection.
 /// To be used when requesting the flash controller to set
 /// specific permissions for a regions, or when reading
@@ -763,13 +780,80 @@ impl<'a> FlashCtrl<'a> {
             return Err(ErrorCode::NOSUPPORT);
         }
 
-        let regs = self.registers;
-
-        if !regs.region_cfg_regwen[region_num].is_set(REGION_CFG_REGWEN::REGION) {
+        if !self.registers.region_cfg_regwen[region_num].is_set(REGION_CFG_REGWEN::REGION) {
             // Region locked, cannot modify until next reset
             return Err(ErrorCode::NOSUPPORT);
         }
 
+        self.mp_write_region(region_num, page_number, num_pages, mp_perms);
+
+        Ok(())
+    }
+
+    /// Setup flash memory protection for `size` bytes starting at `base`,
+    /// using the next configuration region that is not already in use.
+    ///
+    /// A region is in use if it is enabled, locked, or is the region this
+    /// driver uses for the data partition.
+    ///
+    /// Returns `Ok(region_num)` with the configuration region that was used,
+    ///     which can then be passed to `mp_lock_region_cfg()`
+    /// Returns `[`INVAL`](ErrorCode::INVAL)` if `base` or `size` is not a
+    ///     multiple of `PAGE_SIZE`, or `size` is zero
+    /// Returns `[`NOSUPPORT`](ErrorCode::NOSUPPORT)` if the range does not
+    ///     fall within flash
+    /// Returns `[`NOMEM`](ErrorCode::NOMEM)` if all configuration regions are
+    ///     in use
+    ///
+    /// # Arguments
+    ///
+    /// * `base`      - Starting address of the region.
+    ///                  Note: This is the absolute address, i.e `FLASH_ADDR_OFFSET` and onwards
+    /// * `size`      - Size of the region in bytes
+    /// * `mp_perms`  - Specifies the permissions to set
+    ///
+    /// # Examples
+    ///
+    /// Usage:
+    ///
+    /// ```ignore
+    /// let region = peripherals
+    ///     .flash_ctrl
+    ///     .mp_add_region(code_start, code_size, &read_only)?;
+    /// peripherals.flash_ctrl.mp_lock_region_cfg(region)?;
+    /// ```
+    pub fn mp_add_region(
+        &self,
+        base: usize,
+        size: usize,
+        mp_perms: &FlashMPConfig,
+    ) -> Result<usize, ErrorCode> {
+        let (page_number, num_pages) = mp_region_pages(base, size)?;
+
+        let regs = self.registers;
+        let region_num = (0..FLASH_MP_MAX_CFGS)
+            .find(|&region_num| {
+                region_num != self.region_num as usize
+                    && regs.region_cfg_regwen[region_num].is_set(REGION_CFG_REGWEN::REGION)
+                    && !regs.mp_region_cfg[region_num].is_set(MP_REGION_CFG::EN)
+            })
+            .ok_or(ErrorCode::NOMEM)?;
+
+        self.mp_write_region(region_num, page_number, num_pages, mp_perms);
+
+        Ok(region_num)
+    }
+
+    /// Write and enable the configuration of an unlocked region.
+    fn mp_write_region(
+        &self,
+        region_num: usize,
+        page_number: usize,
+        num_pages: usize,
+        mp_perms: &FlashMPConfig,
+    ) {
+        let regs = self.registers;
+
         // Clear any existing permissions (reset state)
         self.registers.mp_region_cfg[region_num].write(
             MP_REGION_CFG::EN::Clear
@@ -814,8 +898,6 @@ impl<'a> FlashCtrl<'a> {
 
         // Activate protection region with specified permissions
         self.registers.mp_region_cfg[region_num].modify(MP_REGION_CFG::EN::Set);
-
-        Ok(())
     }
 
     /// Read the flash memory protection configuration bounded by the specified region
@@ -1142,3 +1224,44 @@ impl hil::flash::Flash for FlashCtrl<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mp_region_pages_validation() {
+        let flash_end = FLASH_ADDR_OFFSET + FLASH_MAX_PAGES * PAGE_SIZE;
+
+        assert_eq!(
+            mp_region_pages(FLASH_ADDR_OFFSET + 4 * PAGE_SIZE, 3 * PAGE_SIZE),
+            Ok((4, 3))
+        );
+        assert_eq!(
+            mp_region_pages(flash_end - PAGE_SIZE, PAGE_SIZE),
+            Ok((FLASH_MAX_PAGES - 1, 1))
+        );
+
+        // Unaligned base or size, or empty.
+        assert_eq!(
+            mp_region_pages(FLASH_ADDR_OFFSET + 4, PAGE_SIZE),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            mp_region_pages(FLASH_ADDR_OFFSET, PAGE_SIZE + 4),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(mp_region_pages(FLASH_ADDR_OFFSET, 0), Err(ErrorCode::INVAL));
+
+        // Outside of flash.
+        assert_eq!(mp_region_pages(0, PAGE_SIZE), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(
+            mp_region_pages(flash_end - PAGE_SIZE, 2 * PAGE_SIZE),
+            Err(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            mp_region_pages(flash_end, PAGE_SIZE),
+            Err(ErrorCode::NOSUPPORT)
+        );
+    }
+}
@@ -12,6 +12,7 @@ use kernel::utilities::registers::{
     register_bitfields, register_structs, Field, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 register_structs! {
     pub GpioRegisters {
@@ -128,6 +129,72 @@ impl<'a> GpioPin<'a> {
     }
 }
 
+/// Pins of the same GPIO block that are read and written together.
+///
+/// A read samples all the pins in one access. A write changes all the pins
+/// at once: with one masked write if they are in the same half of the block,
+/// or else with one write of the whole output register after reading it.
+pub struct PinGroup {
+    gpio_registers: StaticRef<GpioRegisters>,
+    mask: u32,
+}
+
+impl PinGroup {
+    /// Group `pins`, which must all be in the same GPIO block.
+    pub fn new<'b, 'a: 'b>(
+        pins: impl IntoIterator<Item = &'b GpioPin<'a>>,
+    ) -> Result<PinGroup, ErrorCode> {
+        let mut pins = pins.into_iter();
+        let first = pins.next().ok_or(ErrorCode::INVAL)?;
+        let mut mask = first.pin.mask << first.pin.shift;
+        for pin in pins {
+            if !core::ptr::eq(&*pin.gpio_registers, &*first.gpio_registers) {
+                return Err(ErrorCode::INVAL);
+            }
+            mask |= pin.pin.mask << pin.pin.shift;
+        }
+        Ok(PinGroup {
+            gpio_registers: first.gpio_registers,
+            mask,
+        })
+    }
+
+    /// The pins of the group, by their bits in the block.
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Read the levels of the pins in `mask`, which must be in the group.
+    pub fn read_group(&self, mask: u32) -> Result<u32, ErrorCode> {
+        if mask & !self.mask != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(self.gpio_registers.data_in.get() & mask)
+    }
+
+    /// Drive the pins in `mask`, which must be in the group, to their bits in
+    /// `values`.
+    pub fn write_group(&self, mask: u32, values: u32) -> Result<(), ErrorCode> {
+        if mask & !self.mask != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        let values = values & mask;
+        if mask >> 16 == 0 {
+            self.gpio_registers
+                .masked_out_lower
+                .write(mask_half::data.val(values) + mask_half::mask.val(mask));
+        } else if mask & 0xFFFF == 0 {
+            self.gpio_registers
+                .masked_out_upper
+                .write(mask_half::data.val(values >> 16) + mask_half::mask.val(mask >> 16));
+        } else {
+            let out = self.gpio_registers.direct_out.get();
+            self.gpio_registers.direct_out.set(out & !mask | values);
+        }
+        Ok(())
+    }
+}
+
 impl gpio::Configure for GpioPin<'_> {
     fn configuration(&self) -> gpio::Configuration {
         match self.gpio_registers.direct_oe.is_set(self.pin) {
@@ -296,3 +363,63 @@ impl<'a> gpio::Interrupt<'a> for GpioPin<'a> {
         self.gpio_registers.intr_state.is_set(self.pin)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Index of `direct_out` in the register block.
+    const DIRECT_OUT: usize = 5;
+    const MASKED_OUT_LOWER: usize = 6;
+    const DATA_IN: usize = 4;
+
+    fn pin(memory: &[Cell<u32>; 16], pin: Field<u32, pins::Register>) -> GpioPin<'static> {
+        GpioPin::new(
+            unsafe { StaticRef::new(memory.as_ptr() as *const GpioRegisters) },
+            unsafe { StaticRef::new(memory.as_ptr() as *const padctrl::PadCtrlRegisters) },
+            pin,
+        )
+    }
+
+    #[test]
+    fn write_and_read_pattern() {
+        let memory: [Cell<u32>; 16] = Default::default();
+        memory[DIRECT_OUT].set(0x8000_0001);
+        let pins = [
+            pin(&memory, pins::pin3),
+            pin(&memory, pins::pin4),
+            pin(&memory, pins::pin15),
+            pin(&memory, pins::pin16),
+        ];
+        let group = PinGroup::new(&pins).unwrap();
+        assert_eq!(group.mask(), 0x0001_8018);
+
+        // Across both halves, in one write of the output register that
+        // leaves the other pins alone.
+        assert_eq!(group.write_group(0x0001_8018, 0xFFFF_0010), Ok(()));
+        assert_eq!(memory[DIRECT_OUT].get(), 0x8001_0011);
+        // The pads loop the outputs back.
+        memory[DATA_IN].set(memory[DIRECT_OUT].get());
+        assert_eq!(group.read_group(0x0001_8018), Ok(0x0001_0010));
+        assert_eq!(group.read_group(0x0000_0018), Ok(0x0000_0010));
+
+        // Within the lower half, in one masked write.
+        assert_eq!(group.write_group(0x0000_8008, 0x0000_8000), Ok(()));
+        assert_eq!(memory[MASKED_OUT_LOWER].get(), 0x8008_8000);
+
+        // Only pins of the group.
+        assert_eq!(group.read_group(0x0000_0001), Err(ErrorCode::INVAL));
+        assert_eq!(group.write_group(0x8000_0000, 0), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn pins_must_share_a_block() {
+        let first: [Cell<u32>; 16] = Default::default();
+        let second: [Cell<u32>; 16] = Default::default();
+        let pins = [pin(&first, pins::pin0), pin(&second, pins::pin1)];
+        assert!(PinGroup::new(&pins).is_err());
+        assert!(PinGroup::new(&pins[..1]).is_ok());
+        assert!(PinGroup::new(&pins[..0]).is_err());
+    }
+}
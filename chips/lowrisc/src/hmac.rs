@@ -83,6 +83,8 @@ pub struct Hmac<'a> {
     digest: Cell<Option<&'static mut [u8; 32]>>,
     cancelled: Cell<bool>,
     busy: Cell<bool>,
+    /// Whether the key is cleared once a digest has been computed.
+    scrub_key: Cell<bool>,
 }
 
 impl Hmac<'_> {
@@ -95,6 +97,29 @@ impl Hmac<'_> {
             digest: Cell::new(None),
             cancelled: Cell::new(false),
             busy: Cell::new(false),
+            scrub_key: Cell::new(false),
+        }
+    }
+
+    /// Clear the key once each digest has been computed, before the client
+    /// is called, so that the next user of the engine cannot use or read it.
+    /// The key then has to be set again before the next HMAC. Off by default,
+    /// so that a key can be used for several HMACs.
+    ///
+    /// The message length registers are read-only; they keep the length of
+    /// the last message until the next one is started.
+    pub fn set_scrub_key(&self, scrub: bool) {
+        self.scrub_key.set(scrub);
+    }
+
+    /// Clear the key if that was asked for, once the digest has been read.
+    fn scrub(&self) {
+        if self.scrub_key.get() {
+            let regs = self.registers;
+            for key in regs.key.iter() {
+                key.set(0);
+            }
+            regs.wipe_secret.set(1 as u32);
         }
     }
 
@@ -184,6 +209,7 @@ impl Hmac<'_> {
                             equal = false;
                         }
                     }
+                    self.scrub();
 
                     if self.cancelled.get() {
                         self.clear_data();
@@ -205,6 +231,7 @@ impl Hmac<'_> {
                         digest[idx + 2] = d[2];
                         digest[idx + 3] = d[3];
                     }
+                    self.scrub();
                     if self.cancelled.get() {
                         self.clear_data();
                         self.cancelled.set(false);
@@ -242,6 +269,7 @@ impl Hmac<'_> {
             }
         } else if intrs.is_set(INTR_STATE::HMAC_ERR) {
             regs.intr_state.modify(INTR_STATE::HMAC_ERR::SET);
+            self.scrub();
 
             self.client.map(|client| {
                 let errval = if self.cancelled.get() {
@@ -464,3 +492,97 @@ impl hil::digest::Sha512 for Hmac<'_> {
         Err(ErrorCode::NOSUPPORT)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::hil::digest::{ClientData, ClientHash, ClientVerify, Digest, HmacSha256};
+    use std::boxed::Box;
+
+    /// Word index of the first key register.
+    const KEY: usize = 0x24 / 4;
+    /// Word index of the first digest register.
+    const DIGEST: usize = 0x44 / 4;
+
+    #[derive(Default)]
+    struct MockClient {
+        digest: Cell<Option<[u8; 32]>>,
+    }
+
+    impl ClientData<32> for MockClient {
+        fn add_data_done(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _data: LeasableBuffer<'static, u8>,
+        ) {
+        }
+        fn add_mut_data_done(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _data: LeasableMutableBuffer<'static, u8>,
+        ) {
+        }
+    }
+
+    impl ClientHash<32> for MockClient {
+        fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+            assert_eq!(result, Ok(()));
+            self.digest.set(Some(*digest));
+        }
+    }
+
+    impl ClientVerify<32> for MockClient {
+        fn verification_done(
+            &self,
+            _result: Result<bool, ErrorCode>,
+            _compare: &'static mut [u8; 32],
+        ) {
+        }
+    }
+
+    /// Run an HMAC with a known key on a mock register block, returning the
+    /// digest passed to the client and the key registers afterwards.
+    fn hmac(scrub: bool) -> ([u8; 32], [u32; 8]) {
+        let memory: &'static [Cell<u32>; 0x808 / 4] =
+            Box::leak(Box::new([(); 0x808 / 4].map(|()| Cell::new(0))));
+        let hmac: &'static Hmac = Box::leak(Box::new(Hmac::new(unsafe {
+            StaticRef::new(memory.as_ptr() as *const HmacRegisters)
+        })));
+        let client: &'static MockClient = Box::leak(Box::default());
+        hmac.set_client(client);
+        hmac.set_scrub_key(scrub);
+
+        assert_eq!(hmac.set_mode_hmacsha256(&[0x5A; 32]), Ok(()));
+        assert!(memory[KEY..KEY + 8].iter().all(|k| k.get() == 0x5A5A5A5A));
+        assert!(hmac.run(Box::leak(Box::new([0; 32]))).is_ok());
+
+        // The engine finishes.
+        for (i, word) in memory[DIGEST..DIGEST + 8].iter().enumerate() {
+            word.set(u32::from_ne_bytes([i as u8; 4]));
+        }
+        memory[0].set(1);
+        hmac.handle_interrupt();
+
+        let mut key = [0; 8];
+        for (k, word) in key.iter_mut().zip(&memory[KEY..KEY + 8]) {
+            *k = word.get();
+        }
+        (client.digest.take().unwrap(), key)
+    }
+
+    #[test]
+    fn scrub_key_after_digest() {
+        let (digest, key) = hmac(true);
+        // The digest was read before the key was cleared.
+        assert_eq!(digest[..4], [0; 4]);
+        assert_eq!(digest[28..], [7; 4]);
+        assert_eq!(key, [0; 8]);
+
+        // The key is kept unless scrubbing was asked for.
+        let (digest, key) = hmac(false);
+        assert_eq!(digest[28..], [7; 4]);
+        assert_eq!(key, [0x5A5A5A5A; 8]);
+    }
+}
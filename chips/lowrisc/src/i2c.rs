@@ -3,15 +3,28 @@
 // Copyright Tock Contributors 2022.
 
 //! I2C Master Driver
+//!
+//! For short transfers, such as reading a register, the interrupts cost
+//! more than the transfer itself. With [I2c::set_polled_threshold], a
+//! transfer of fewer bytes than the threshold is polled to completion, and
+//! the client is called back from a deferred call.
+//!
+//! Longer transfers are split to fit the FIFOs: the FMT FIFO is refilled
+//! from its watermark interrupt, and reads are requested at most a FIFO's
+//! depth at a time, so the RX FIFO cannot overflow. The chunks are joined
+//! on the bus, with no STOP until the end of the transfer, and the client is
+//! called back once the whole transfer is done.
 
 use core::cell::Cell;
+use core::cmp;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::hil::i2c;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
-    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+    register_bitfields, register_structs, FieldValue, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
 
@@ -135,17 +148,14 @@ pub struct I2c<'a> {
 
     master_client: OptionalCell<&'a dyn hil::i2c::I2CHwMasterClient>,
 
-    // Set when calling the write_read operation
-    // This specifies the address of the read operation
-    // after the write operation. Set to 0 for single read/write operations.
-    slave_read_address: Cell<u8>,
-
     buffer: TakeCell<'static, [u8]>,
-    write_len: Cell<usize>,
-    write_index: Cell<usize>,
+    transfer: Cell<Transfer>,
+    fifo_depth: Cell<usize>,
 
-    read_len: Cell<usize>,
-    read_index: Cell<usize>,
+    polled_threshold: Cell<usize>,
+    /// The result of a polled transfer, until the client is called back.
+    polled_result: OptionalCell<Result<(), i2c::Error>>,
+    deferred_call: DeferredCall,
 }
 
 impl<'a> I2c<'_> {
@@ -154,15 +164,30 @@ impl<'a> I2c<'_> {
             registers: base,
             clock_period_nanos,
             master_client: OptionalCell::empty(),
-            slave_read_address: Cell::new(0),
             buffer: TakeCell::empty(),
-            write_len: Cell::new(0),
-            write_index: Cell::new(0),
-            read_len: Cell::new(0),
-            read_index: Cell::new(0),
+            transfer: Cell::new(Transfer::default()),
+            fifo_depth: Cell::new(DEFAULT_FIFO_DEPTH),
+            polled_threshold: Cell::new(0),
+            polled_result: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
         }
     }
 
+    /// Poll transfers of fewer than `bytes` bytes, written and read, to
+    /// completion.
+    ///
+    /// The client is still called back, from a deferred call. A threshold
+    /// of 0, the default, always uses interrupts.
+    pub fn set_polled_threshold(&self, bytes: usize) {
+        self.polled_threshold.set(bytes);
+    }
+
+    /// Set the depth of the FMT and RX FIFOs, which bounds how many bytes
+    /// are read between two refills of the FMT FIFO.
+    pub fn set_fifo_depth(&self, bytes: usize) {
+        self.fifo_depth.set(cmp::max(bytes, 1));
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let irqs = regs.intr_state.extract();
@@ -180,18 +205,18 @@ impl<'a> I2c<'_> {
                 + INTR::SDA_UNSTABLE::SET,
         );
 
-        if irqs.is_set(INTR::FMT_WATERMARK) {
-            // FMT Watermark
-            if self.slave_read_address.get() != 0 {
-                self.write_read_data();
-            } else {
-                self.write_data();
-            }
+        if self.polled_result.is_some() {
+            // The FIFOs were used by a polled transfer.
+            return;
         }
 
-        if irqs.is_set(INTR::RX_WATERMARK) {
-            // RX Watermark
-            self.read_data();
+        if irqs.is_set(INTR::NAK) {
+            // The controller does not say which byte was not acknowledged,
+            // most often it is the address.
+            self.fifo_reset();
+            self.finish(Err(i2c::Error::AddressNak));
+        } else if irqs.is_set(INTR::FMT_WATERMARK) || irqs.is_set(INTR::RX_WATERMARK) {
+            self.service(&*self.registers);
         }
     }
 
@@ -217,148 +242,326 @@ impl<'a> I2c<'_> {
         );
     }
 
-    fn fifo_reset(&self) {
-        let regs = self.registers;
+    /// Runs the transfer by polling if it is short enough, otherwise
+    /// returns the buffer.
+    fn polled_transfer(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), &'static mut [u8]> {
+        if write_len + read_len >= self.polled_threshold.get() || read_len > u8::MAX as usize {
+            return Err(data);
+        }
 
-        regs.fifo_ctrl
-            .modify(FIFO_CTRL::RXRST::SET + FIFO_CTRL::FMTRST::SET);
+        let write_len = write_len.min(data.len());
+        let read_len = read_len.min(data.len());
+        let result = self.transfer_polled(addr, data, write_len, read_len);
+        self.buffer.replace(data);
+        self.polled_result.set(result);
+        self.deferred_call.set();
+        Ok(())
     }
 
-    fn read_data(&self) {
+    /// Writes `write_len` bytes from `buf`, then reads `read_len` bytes into
+    /// it, waiting on the FIFOs.
+    fn transfer_polled(
+        &self,
+        addr: u8,
+        buf: &mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), i2c::Error> {
         let regs = self.registers;
-        let mut data_popped = self.read_index.get();
-        let len = self.read_len.get();
-
-        self.buffer.map(|buf| {
-            for i in self.read_index.get()..len {
-                if regs.status.is_set(STATUS::RXEMPTY) {
-                    // The RX buffer is empty
-                    data_popped = i;
-                    break;
-                }
-                // Read the data
-                buf[i as usize] = regs.rdata.read(RDATA::RDATA) as u8;
-                data_popped = i;
+        let push = |fdata: FieldValue<u32, FDATA::Register>| -> Result<(), i2c::Error> {
+            while regs.status.is_set(STATUS::FMTFULL) {
+                self.check_nak()?;
             }
+            regs.fdata.write(fdata);
+            Ok(())
+        };
 
-            if data_popped == len {
-                // Finished call the callback
-                self.master_client.map(|client| {
-                    client.command_complete(self.buffer.take().unwrap(), Ok(()));
-                });
-            } else {
-                self.read_index.set(data_popped as usize + 1);
+        self.fifo_reset();
 
-                // Update the FIFO depth
-                if len - data_popped > 8 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL8);
-                } else if len - data_popped > 4 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL4);
-                } else {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL1);
-                }
+        if write_len > 0 {
+            // Zero out the LSB to signal a write
+            push(FDATA::START::SET + FDATA::FBYTE.val((addr & !1) as u32))?;
+            for (i, byte) in buf[..write_len].iter().enumerate() {
+                let last = i == write_len - 1 && read_len == 0;
+                push(FDATA::FBYTE.val(*byte as u32) + FDATA::STOP.val(last as u32))?;
             }
-        });
-    }
+        }
 
-    fn write_data(&self) {
-        let regs = self.registers;
-        let mut data_pushed = self.write_index.get();
-        let len = self.write_len.get();
-
-        self.buffer.map(|buf| {
-            for i in self.write_index.get()..(len - 1) {
-                if regs.status.read(STATUS::FMTFULL) != 0 {
-                    // The FMT buffer is full
-                    data_pushed = i;
-                    break;
+        if read_len > 0 {
+            // Set the LSB to signal a read
+            push(FDATA::START::SET + FDATA::FBYTE.val((addr | 1) as u32))?;
+            push(FDATA::READ::SET + FDATA::STOP::SET + FDATA::FBYTE.val(read_len as u32))?;
+            for byte in buf[..read_len].iter_mut() {
+                while regs.status.is_set(STATUS::RXEMPTY) {
+                    self.check_nak()?;
                 }
-                // Send the data
-                regs.fdata
-                    .write(FDATA::FBYTE.val(*buf.get(i).unwrap_or(&0) as u32));
-                data_pushed = i;
+                *byte = regs.rdata.read(RDATA::RDATA) as u8;
             }
+        }
 
-            // Check if we can send the last byte
-            if regs.status.read(STATUS::FMTFULL) == 0 && data_pushed == (len - 1) {
-                // Send the last byte with the stop signal
-                regs.fdata
-                    .write(FDATA::FBYTE.val(*buf.get(len).unwrap_or(&0) as u32) + FDATA::STOP::SET);
+        // Wait for the stop condition.
+        while !regs.status.is_set(STATUS::FMTEMPTY) || !regs.status.is_set(STATUS::HOSTIDLE) {
+            self.check_nak()?;
+        }
+        self.check_nak()
+    }
 
-                data_pushed = len;
-            }
+    /// Ends a polled transfer if the target did not acknowledge a byte.
+    fn check_nak(&self) -> Result<(), i2c::Error> {
+        let regs = self.registers;
+        if regs.intr_state.is_set(INTR::NAK) {
+            regs.intr_state.write(INTR::NAK::SET);
+            self.fifo_reset();
+            // The controller does not say which byte was not acknowledged,
+            // most often it is the address.
+            return Err(i2c::Error::AddressNak);
+        }
+        Ok(())
+    }
 
-            if data_pushed == len {
-                // Finished call the callback
-                self.master_client.map(|client| {
-                    client.command_complete(self.buffer.take().unwrap(), Ok(()));
-                });
-            } else {
-                self.write_index.set(data_pushed as usize + 1);
+    fn fifo_reset(&self) {
+        let regs = self.registers;
 
-                // Update the FIFO depth
-                if len - data_pushed > 8 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL8);
-                } else if len - data_pushed > 4 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL4);
-                } else {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL1);
-                }
-            }
+        regs.fifo_ctrl
+            .modify(FIFO_CTRL::RXRST::SET + FIFO_CTRL::FMTRST::SET);
+    }
+
+    /// Starts an interrupt driven transfer.
+    fn start(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+        fifos: &impl Fifos,
+    ) {
+        self.fifo_reset();
+        self.transfer.set(Transfer {
+            addr,
+            write_len: cmp::min(write_len, data.len()),
+            read_len: cmp::min(read_len, data.len()),
+            ..Transfer::default()
         });
+        self.buffer.replace(data);
+        self.service(fifos);
     }
 
-    fn write_read_data(&self) {
+    /// Moves the transfer on as far as the FIFOs allow, then waits for the
+    /// interrupt that lets it go further.
+    fn service(&self, fifos: &impl Fifos) {
         let regs = self.registers;
-        let mut data_pushed = self.write_index.get();
-        let len = self.write_len.get();
-
-        self.buffer.map(|buf| {
-            let start_index = data_pushed;
-            for i in start_index..(len - 1) {
-                if regs.status.read(STATUS::FMTFULL) != 0 {
-                    // The FMT buffer is full
-                    data_pushed = i;
-                    break;
-                }
-                // Send the data
-                regs.fdata
-                    .write(FDATA::FBYTE.val(*buf.get(i).unwrap_or(&0) as u32));
-                data_pushed = i;
+        let mut transfer = self.transfer.get();
+        let done = self
+            .buffer
+            .map(|buf| transfer.advance(buf, self.fifo_depth.get(), fifos));
+        self.transfer.set(transfer);
+
+        match done {
+            None => {}
+            Some(true) => self.finish(Ok(())),
+            Some(false) => {
+                // While reads are outstanding nothing can be queued, so only
+                // wait for the RX FIFO. Otherwise wait for room in the FMT
+                // FIFO, or for it to drain at the end of a write.
+                let outstanding = transfer.outstanding();
+                let rx_level = match outstanding {
+                    0..=3 => FIFO_CTRL::RXILVL::RXLVL1,
+                    4..=7 => FIFO_CTRL::RXILVL::RXLVL4,
+                    8..=15 => FIFO_CTRL::RXILVL::RXLVL8,
+                    16..=29 => FIFO_CTRL::RXILVL::RXLVL16,
+                    _ => FIFO_CTRL::RXILVL::RXLVL30,
+                };
+                let fmt_level = if transfer.is_queued() {
+                    FIFO_CTRL::FMTILVL::FMTLVL1
+                } else {
+                    FIFO_CTRL::FMTILVL::FMTLVL4
+                };
+                regs.fifo_ctrl.modify(rx_level + fmt_level);
+                regs.intr_enable.modify(
+                    INTR::FMT_WATERMARK.val((outstanding == 0) as u32)
+                        + INTR::RX_WATERMARK.val((outstanding > 0) as u32),
+                );
             }
+        }
+    }
 
-            // Check if we can send the last byte
-            if regs.status.read(STATUS::FMTFULL) == 0 && data_pushed == (len - 1) {
-                // Send the last byte with the stop signal
-                regs.fdata
-                    .write(FDATA::FBYTE.val(*buf.get(len).unwrap_or(&0) as u32) + FDATA::STOP::SET);
+    /// Ends the interrupt driven transfer, if there is one.
+    fn finish(&self, result: Result<(), i2c::Error>) {
+        self.registers
+            .intr_enable
+            .modify(INTR::FMT_WATERMARK::CLEAR + INTR::RX_WATERMARK::CLEAR);
+        if let Some(buffer) = self.buffer.take() {
+            self.master_client
+                .map(|client| client.command_complete(buffer, result));
+        }
+    }
+}
 
-                data_pushed = len;
-            }
+/// Default depth of the FMT and RX FIFOs, see [I2c::set_fifo_depth].
+pub const DEFAULT_FIFO_DEPTH: usize = 32;
+
+/// The FMT and RX FIFOs, as seen by an interrupt driven transfer.
+trait Fifos {
+    fn fmt_full(&self) -> bool;
+    fn fmt_empty(&self) -> bool;
+    fn push(&self, entry: FieldValue<u32, FDATA::Register>);
+    fn pop(&self) -> Option<u8>;
+}
 
-            if data_pushed == len {
-                // Finished writing. Read the data as well.
-                // Set the LSB to signal a read
-                let read_addr = self.slave_read_address.get() | 1;
+impl Fifos for I2cRegisters {
+    fn fmt_full(&self) -> bool {
+        self.status.is_set(STATUS::FMTFULL)
+    }
 
-                // Set the start condition and the address
-                regs.fdata
-                    .write(FDATA::START::SET + FDATA::FBYTE.val(read_addr as u32));
+    fn fmt_empty(&self) -> bool {
+        self.status.is_set(STATUS::FMTEMPTY)
+    }
 
-                self.read_data();
-            } else {
-                self.write_index.set(data_pushed as usize + 1);
+    fn push(&self, entry: FieldValue<u32, FDATA::Register>) {
+        self.fdata.write(entry);
+    }
 
-                // Update the FIFO depth
-                if len - data_pushed > 8 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL8);
-                } else if len - data_pushed > 4 {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL4);
-                } else {
-                    regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL1);
+    fn pop(&self) -> Option<u8> {
+        if self.status.is_set(STATUS::RXEMPTY) {
+            None
+        } else {
+            Some(self.rdata.read(RDATA::RDATA) as u8)
+        }
+    }
+}
+
+/// An interrupt driven transfer: `write_len` bytes written from the buffer,
+/// then `read_len` bytes read into it.
+///
+/// The write ends with a STOP, unless a read follows with a repeated START.
+/// Reads are requested in chunks no larger than the RX FIFO, each chunk once
+/// the previous one has been received. All but the last chunk set RCONT so
+/// the controller acknowledges their last byte and carries on reading, and
+/// the last ends with a STOP.
+#[derive(Clone, Copy, Default)]
+struct Transfer {
+    addr: u8,
+    write_len: usize,
+    read_len: usize,
+    /// Format entries queued for the write: the address, then the bytes.
+    written: usize,
+    /// Whether the read address has been queued.
+    read_addressed: bool,
+    /// Bytes requested by read entries.
+    requested: usize,
+    /// Bytes received.
+    received: usize,
+}
+
+impl Transfer {
+    /// Format entries of the write, including the address. A transfer
+    /// with nothing to read or write still sends the address.
+    fn write_entries(&self) -> usize {
+        if self.write_len > 0 || self.read_len == 0 {
+            self.write_len + 1
+        } else {
+            0
+        }
+    }
+
+    /// Bytes requested but not yet received.
+    fn outstanding(&self) -> usize {
+        self.requested - self.received
+    }
+
+    /// Whether every format entry has been queued.
+    fn is_queued(&self) -> bool {
+        self.written == self.write_entries() && self.requested == self.read_len
+    }
+
+    /// Receives what the RX FIFO holds and queues what the FMT FIFO has
+    /// room for. Returns whether the transfer is done.
+    fn advance(&mut self, buf: &mut [u8], fifo_depth: usize, fifos: &impl Fifos) -> bool {
+        while self.received < self.requested {
+            match fifos.pop() {
+                Some(byte) => {
+                    buf[self.received] = byte;
+                    self.received += 1;
                 }
+                None => break,
             }
-        });
+        }
+
+        while !fifos.fmt_full() {
+            match self.next_entry(buf, fifo_depth) {
+                Some(entry) => fifos.push(entry),
+                None => break,
+            }
+        }
+
+        self.is_queued() && self.received == self.read_len && fifos.fmt_empty()
+    }
+
+    /// The next format entry, if it can be queued yet.
+    fn next_entry(
+        &mut self,
+        buf: &[u8],
+        fifo_depth: usize,
+    ) -> Option<FieldValue<u32, FDATA::Register>> {
+        let write_entries = self.write_entries();
+        if self.written < write_entries {
+            let entry = if self.written == 0 {
+                // Zero out the LSB to signal a write
+                FDATA::START::SET + FDATA::FBYTE.val((self.addr & !1) as u32)
+            } else {
+                FDATA::FBYTE.val(buf[self.written - 1] as u32)
+            };
+            self.written += 1;
+            let stop = self.written == write_entries && self.read_len == 0;
+            return Some(entry + FDATA::STOP.val(stop as u32));
+        }
+
+        if self.requested == self.read_len {
+            return None;
+        }
+        if !self.read_addressed {
+            self.read_addressed = true;
+            // Set the LSB to signal a read
+            return Some(FDATA::START::SET + FDATA::FBYTE.val((self.addr | 1) as u32));
+        }
+        if self.outstanding() > 0 {
+            // Wait for the RX FIFO to drain.
+            return None;
+        }
+
+        let chunk = cmp::min(
+            cmp::min(self.read_len - self.requested, fifo_depth),
+            u8::MAX as usize,
+        );
+        self.requested += chunk;
+        let end = if self.requested == self.read_len {
+            FDATA::STOP::SET
+        } else {
+            FDATA::RCONT::SET
+        };
+        Some(FDATA::READ::SET + FDATA::FBYTE.val(chunk as u32) + end)
+    }
+}
+
+impl DeferredCallClient for I2c<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some(result) = self.polled_result.take() {
+            self.master_client.map(|client| {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.command_complete(buffer, result));
+            });
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
     }
 }
 
@@ -373,11 +576,10 @@ impl<'a> hil::i2c::I2CMaster<'a> for I2c<'a> {
         self.timing_parameter_init(self.clock_period_nanos);
         self.fifo_reset();
 
-        // Enable all interrupts
+        // Enable the error interrupts, the watermark interrupts are enabled
+        // while a transfer needs them.
         regs.intr_enable.modify(
-            INTR::FMT_WATERMARK::SET
-                + INTR::RX_WATERMARK::SET
-                + INTR::FMT_OVERFLOW::SET
+            INTR::FMT_OVERFLOW::SET
                 + INTR::RX_OVERFLOW::SET
                 + INTR::NAK::SET
                 + INTR::SCL_INTERFERENCE::SET
@@ -403,44 +605,11 @@ impl<'a> hil::i2c::I2CMaster<'a> for I2c<'a> {
         write_len: usize,
         read_len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
-        let regs = self.registers;
-
-        // Set the FIFO depth and reset the FIFO
-        if write_len > 8 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL8);
-        } else if write_len > 4 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL4);
-        } else {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL1);
-        }
-
-        if read_len > 8 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL8);
-        } else if read_len > 4 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL4);
-        } else {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL1);
-        }
-
-        self.fifo_reset();
-
-        // Zero out the LSB to signal a write
-        let write_addr = addr & !1;
-
-        // Set the start condition and the address
-        regs.fdata
-            .write(FDATA::START::SET + FDATA::FBYTE.val(write_addr as u32));
-
-        // Save all the data and offsets we still need to send and receive
-        self.slave_read_address.set(addr);
-        self.buffer.replace(data);
-        self.write_len.set(write_len as usize);
-        self.read_len.set(read_len as usize);
-        self.write_index.set(0);
-        self.read_index.set(0);
-
-        self.write_read_data();
-
+        let data = match self.polled_transfer(addr, data, write_len, read_len) {
+            Ok(()) => return Ok(()),
+            Err(data) => data,
+        };
+        self.start(addr, data, write_len, read_len, &*self.registers);
         Ok(())
     }
 
@@ -450,34 +619,11 @@ impl<'a> hil::i2c::I2CMaster<'a> for I2c<'a> {
         data: &'static mut [u8],
         len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
-        let regs = self.registers;
-
-        // Set the FIFO depth and reset the FIFO
-        if len > 8 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL8);
-        } else if len > 4 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL4);
-        } else {
-            regs.fifo_ctrl.modify(FIFO_CTRL::FMTILVL::FMTLVL1);
-        }
-
-        self.fifo_reset();
-
-        // Zero out the LSB to signal a write
-        let write_addr = addr & !1;
-
-        // Set the start condition and the address
-        regs.fdata
-            .write(FDATA::START::SET + FDATA::FBYTE.val(write_addr as u32));
-
-        // Save all the data and offsets we still need to send
-        self.slave_read_address.set(0);
-        self.buffer.replace(data);
-        self.write_len.set(len as usize);
-        self.write_index.set(0);
-
-        self.write_data();
-
+        let data = match self.polled_transfer(addr, data, len, 0) {
+            Ok(()) => return Ok(()),
+            Err(data) => data,
+        };
+        self.start(addr, data, len, 0, &*self.registers);
         Ok(())
     }
 
@@ -487,34 +633,179 @@ impl<'a> hil::i2c::I2CMaster<'a> for I2c<'a> {
         buffer: &'static mut [u8],
         len: usize,
     ) -> Result<(), (hil::i2c::Error, &'static mut [u8])> {
-        let regs = self.registers;
+        let buffer = match self.polled_transfer(addr, buffer, 0, len) {
+            Ok(()) => return Ok(()),
+            Err(buffer) => buffer,
+        };
+        self.start(addr, buffer, 0, len, &*self.registers);
+        Ok(())
+    }
+}
 
-        // Set the FIFO depth and reset the FIFO
-        if len > 8 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL8);
-        } else if len > 4 {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL4);
-        } else {
-            regs.fifo_ctrl.modify(FIFO_CTRL::RXILVL::RXLVL1);
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    const FIFO_DEPTH: usize = 8;
+
+    const START: u32 = 1 << 8;
+    const STOP: u32 = 1 << 9;
+    const READ: u32 = 1 << 10;
+    const RCONT: u32 = 1 << 11;
+
+    /// A controller that runs the queued format entries whenever the CPU
+    /// lets it, against a target whose register `n` reads as `n * 3`.
+    struct Controller {
+        fmt: RefCell<VecDeque<u32>>,
+        rx: RefCell<VecDeque<u8>>,
+        /// The format entries run, in order.
+        entries: RefCell<Vec<u32>>,
+        /// The bytes written to the target.
+        written: RefCell<Vec<u8>>,
+        next_read: Cell<u8>,
+    }
+
+    impl Controller {
+        fn new() -> Self {
+            Controller {
+                fmt: RefCell::new(VecDeque::new()),
+                rx: RefCell::new(VecDeque::new()),
+                entries: RefCell::new(Vec::new()),
+                written: RefCell::new(Vec::new()),
+                next_read: Cell::new(0),
+            }
         }
 
-        self.fifo_reset();
+        /// Runs the queued entries, as far as there is room for reads.
+        fn run(&self) {
+            let mut fmt = self.fmt.borrow_mut();
+            let mut rx = self.rx.borrow_mut();
+            while let Some(&entry) = fmt.front() {
+                let byte = entry & 0xFF;
+                if entry & READ != 0 {
+                    assert!(rx.len() + byte as usize <= FIFO_DEPTH, "RX FIFO overflow");
+                    for _ in 0..byte {
+                        rx.push_back(self.next_read.get().wrapping_mul(3));
+                        self.next_read.set(self.next_read.get().wrapping_add(1));
+                    }
+                } else if entry & START == 0 {
+                    self.written.borrow_mut().push(byte as u8);
+                }
+                self.entries.borrow_mut().push(entry);
+                fmt.pop_front();
+            }
+        }
+    }
+
+    impl Fifos for Controller {
+        fn fmt_full(&self) -> bool {
+            self.fmt.borrow().len() == FIFO_DEPTH
+        }
 
-        // Set the LSB to signal a read
-        let read_addr = addr | 1;
+        fn fmt_empty(&self) -> bool {
+            self.fmt.borrow().is_empty()
+        }
 
-        // Set the start condition and the address
-        regs.fdata
-            .write(FDATA::START::SET + FDATA::FBYTE.val(read_addr as u32));
+        fn push(&self, entry: FieldValue<u32, FDATA::Register>) {
+            assert!(!self.fmt_full(), "FMT FIFO overflow");
+            self.fmt.borrow_mut().push_back(entry.into());
+        }
 
-        // Save all the data and offsets we still need to read
-        self.slave_read_address.set(0);
-        self.buffer.replace(buffer);
-        self.read_len.set(len as usize);
-        self.read_index.set(0);
+        fn pop(&self) -> Option<u8> {
+            self.rx.borrow_mut().pop_front()
+        }
+    }
 
-        self.read_data();
+    struct Client {
+        completions: Cell<usize>,
+        buffer: TakeCell<'static, [u8]>,
+    }
 
-        Ok(())
+    impl i2c::I2CHwMasterClient for Client {
+        fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+            assert_eq!(status, Ok(()));
+            self.completions.set(self.completions.get() + 1);
+            self.buffer.replace(buffer);
+        }
+    }
+
+    /// Runs a transfer to the end, returning the client and the controller.
+    fn transfer(
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> (&'static Client, Controller) {
+        let memory: &'static [Cell<u32>; 0x44 / 4] =
+            Box::leak(Box::new(core::array::from_fn(|_| Cell::new(0))));
+        let i2c = I2c::new(
+            unsafe { StaticRef::new(memory.as_ptr() as *const I2cRegisters) },
+            10,
+        );
+        i2c.set_fifo_depth(FIFO_DEPTH);
+        let client: &'static Client = Box::leak(Box::new(Client {
+            completions: Cell::new(0),
+            buffer: TakeCell::empty(),
+        }));
+        i2c::I2CMaster::set_master_client(&i2c, client);
+        let controller = Controller::new();
+
+        i2c.start(0x50 << 1, data, write_len, read_len, &controller);
+        for _ in 0..100 {
+            if client.completions.get() > 0 {
+                break;
+            }
+            controller.run();
+            i2c.service(&controller);
+        }
+        // Interrupts after the end have nothing to do.
+        i2c.service(&controller);
+
+        (client, controller)
+    }
+
+    #[test]
+    fn long_write_is_refilled_with_one_stop() {
+        let data: &'static mut [u8; 50] = Box::leak(Box::new(core::array::from_fn(|i| i as u8)));
+        let (client, controller) = transfer(data, 50, 0);
+
+        assert_eq!(client.completions.get(), 1);
+        let written = controller.written.borrow();
+        assert_eq!(written.len(), 50);
+        assert!(written.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+        let entries = controller.entries.borrow();
+        assert_eq!(entries.len(), 51);
+        assert_eq!(entries[0], START | 0xA0);
+        assert!(entries[1..].iter().all(|e| e & START == 0));
+        assert!(entries[..50].iter().all(|e| e & STOP == 0));
+        assert_eq!(entries[50], STOP | 49);
+    }
+
+    #[test]
+    fn long_read_is_chunked_after_repeated_start() {
+        let data: &'static mut [u8; 40] = Box::leak(Box::new([0; 40]));
+        data[0] = 0x10;
+        let (client, controller) = transfer(data, 1, 40);
+
+        assert_eq!(client.completions.get(), 1);
+        let buffer = client.buffer.take().unwrap();
+        assert!(buffer.iter().enumerate().all(|(i, &b)| b == (i as u8) * 3));
+        assert_eq!(&controller.written.borrow()[..], &[0x10]);
+
+        // The write, a repeated START, then five chunks of eight bytes with
+        // only the last one stopping.
+        let entries = controller.entries.borrow();
+        assert_eq!(&entries[..3], &[START | 0xA0, 0x10, START | 0xA1]);
+        assert_eq!(entries.len(), 8);
+        for chunk in &entries[3..7] {
+            assert_eq!(*chunk, READ | RCONT | FIFO_DEPTH as u32);
+        }
+        assert_eq!(entries[7], READ | STOP | FIFO_DEPTH as u32);
     }
 }
@@ -3,11 +3,22 @@
 // Copyright Tock Contributors 2022.
 
 //! Serial Peripheral Interface (SPI) Host Driver
+//!
+//! For short transfers the interrupts cost more than the transfer itself.
+//! With [SpiHost::set_polled_threshold], a transfer shorter than the
+//! threshold that fits in the TX FIFO is polled to completion, and the
+//! client is called back from a deferred call.
+//!
+//! If given a clock with [SpiHost::set_clock], the driver enables it
+//! while it uses the registers, and disables it when no transfer is
+//! running.
 use core::cell::Cell;
 use core::cmp;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::spi::{ClockPhase, ClockPolarity};
+use kernel::platform::chip::ClockInterface;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
@@ -152,6 +163,11 @@ pub struct SpiHost<'a> {
     rx_len: Cell<usize>,
     tx_offset: Cell<usize>,
     rx_offset: Cell<usize>,
+    polled_threshold: Cell<usize>,
+    /// The result of a polled transfer, until the client is called back.
+    polled_result: OptionalCell<Result<(), ErrorCode>>,
+    deferred_call: DeferredCall,
+    clock: OptionalCell<&'a dyn ClockInterface>,
 }
 // SPI Host Command Direction: Bidirectional
 const SPI_HOST_CMD_BIDIRECTIONAL: u32 = 3;
@@ -173,9 +189,42 @@ impl<'a> SpiHost<'a> {
             rx_len: Cell::new(0),
             tx_offset: Cell::new(0),
             rx_offset: Cell::new(0),
+            polled_threshold: Cell::new(0),
+            polled_result: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            clock: OptionalCell::empty(),
         }
     }
 
+    /// Turn `clock` off while the SPI host is idle.
+    pub fn set_clock(&self, clock: &'a dyn ClockInterface) {
+        self.clock.set(clock);
+    }
+
+    /// Run `f` with the clock on, and turn it off afterwards unless a
+    /// transfer is running.
+    fn with_clock<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.clock.map(|clock| clock.enable());
+        let result = f();
+        self.release_clock();
+        result
+    }
+
+    fn release_clock(&self) {
+        if !self.busy.get() {
+            self.clock.map(|clock| clock.disable());
+        }
+    }
+
+    /// Poll transfers shorter than `bytes` to completion.
+    ///
+    /// The transfer must also fit in the TX FIFO. The client is still
+    /// called back, from a deferred call. A threshold of 0, the default,
+    /// always uses interrupts.
+    pub fn set_polled_threshold(&self, bytes: usize) {
+        self.polled_threshold.set(bytes);
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = self.registers;
         let irq = regs.intr_state.extract();
@@ -356,9 +405,31 @@ impl<'a> SpiHost<'a> {
         }
     }
 
+    /// Issue a command to start SPI transaction, and interrupt when it
+    /// completes.
+    fn start_transceive(&self) {
+        self.issue_command();
+        self.enable_interrupts();
+        self.enable_tx_interrupt();
+    }
+
+    /// Run a transfer that is entirely in the TX FIFO to completion.
+    fn transceive_polled(&self) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+        self.disable_interrupts();
+        self.issue_command();
+        while !regs.status.is_set(status::TXEMPTY) || regs.status.is_set(status::ACTIVE) {}
+        self.clear_event_interrupt();
+
+        match self.continue_transfer()? {
+            SpiHostStatus::SpiTransferCmplt => Ok(()),
+            SpiHostStatus::SpiTransferInprog => Err(ErrorCode::FAIL),
+        }
+    }
+
     /// Issue a command to start SPI transaction
     /// Currently only Bi-Directional transactions are supported
-    fn start_transceive(&self) {
+    fn issue_command(&self) {
         let regs = self.registers;
         //8-bits that describe command transfer len (cannot exceed 255)
         let num_transfer_bytes: u32;
@@ -388,8 +459,6 @@ impl<'a> SpiHost<'a> {
                     + command::SPEED.val(SPI_HOST_CMD_STANDARD_SPI),
             );
         }
-        self.enable_interrupts();
-        self.enable_tx_interrupt();
     }
 
     /// Reset the soft internal state, should be called once
@@ -526,6 +595,7 @@ impl<'a> SpiHost<'a> {
 
     fn clear_spi_busy(&self) {
         self.busy.set(false);
+        self.release_clock();
     }
 
     /// Divide a/b and return a value always rounded
@@ -552,24 +622,50 @@ impl<'a> SpiHost<'a> {
     }
 }
 
+impl DeferredCallClient for SpiHost<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some(result) = self.polled_result.take() {
+            let len = match result {
+                Ok(()) => self.tx_len.get(),
+                Err(_) => {
+                    self.reset_spi_ip();
+                    self.tx_offset.get()
+                }
+            };
+            let tx_buf = self.tx_buf.take();
+            let rx_buf = self.rx_buf.take();
+            self.reset_internal_state();
+            self.client.map(|client| {
+                tx_buf.map(|tx_buf| client.read_write_done(tx_buf, rx_buf, len, result));
+            });
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
 impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
     type ChipSelect = u32;
 
     fn init(&self) -> Result<(), ErrorCode> {
         let regs = self.registers;
-        self.event_enable();
-        self.err_enable();
+        self.with_clock(|| {
+            self.event_enable();
+            self.err_enable();
 
-        self.enable_interrupts();
+            self.enable_interrupts();
 
-        self.enable_spi_host();
+            self.enable_spi_host();
 
-        //TODO: I think this is bug in OT, where the `first` word written
-        // (while TXEMPTY) to TX_DATA is dropped/ignored and not added to TX_FIFO (TXQD = 0).
-        // The following write (0x00), works around this `bug`.
-        // Could be Verilator specific
-        regs.tx_data.write(tx_data::DATA.val(0x00));
-        assert_eq!(regs.status.read(status::TXQD), 0);
+            //TODO: I think this is bug in OT, where the `first` word written
+            // (while TXEMPTY) to TX_DATA is dropped/ignored and not added to TX_FIFO (TXQD = 0).
+            // The following write (0x00), works around this `bug`.
+            // Could be Verilator specific
+            regs.tx_data.write(tx_data::DATA.val(0x00));
+            assert_eq!(regs.status.read(status::TXQD), 0);
+        });
         Ok(())
     }
 
@@ -592,11 +688,15 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
         debug_assert!(self.rx_buf.is_none());
         let regs = self.registers;
 
+        self.clock.map(|clock| clock.enable());
+
         if self.is_busy() || regs.status.is_set(status::TXFULL) {
+            self.release_clock();
             return Err((ErrorCode::BUSY, tx_buf, rx_buf));
         }
 
         if rx_buf.is_none() {
+            self.release_clock();
             return Err((ErrorCode::NOMEM, tx_buf, rx_buf));
         }
 
@@ -636,6 +736,14 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
             self.rx_buf.replace(rx_buf_t);
         });
 
+        if self.tx_len.get() < self.polled_threshold.get()
+            && self.tx_offset.get() >= self.tx_len.get()
+        {
+            self.polled_result.set(self.transceive_polled());
+            self.deferred_call.set();
+            return Ok(());
+        }
+
         //Set command register to init transfer
         self.start_transceive();
 
@@ -661,7 +769,7 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
         let regs = self.registers;
 
         //CSID will index the CONFIGOPTS multi-register
-        regs.csid.write(csid_ctrl::CSID.val(cs));
+        self.with_clock(|| regs.csid.write(csid_ctrl::CSID.val(cs)));
         self.chip_select.set(cs);
 
         Ok(())
@@ -672,8 +780,10 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
 
         match self.calculate_tsck_scaler(rate) {
             Ok(scaler) => {
-                regs.config_opts
-                    .modify(conf_opts::CLKDIV_0.val(scaler as u32));
+                self.with_clock(|| {
+                    regs.config_opts
+                        .modify(conf_opts::CLKDIV_0.val(scaler as u32))
+                });
                 self.tsclk.set(rate);
                 Ok(rate)
             }
@@ -687,17 +797,17 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
 
     fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
         let regs = self.registers;
-        match polarity {
+        self.with_clock(|| match polarity {
             ClockPolarity::IdleLow => regs.config_opts.modify(conf_opts::CPOL_0::CLEAR),
             ClockPolarity::IdleHigh => regs.config_opts.modify(conf_opts::CPOL_0::SET),
-        };
+        });
         Ok(())
     }
 
     fn get_polarity(&self) -> ClockPolarity {
         let regs = self.registers;
 
-        match regs.config_opts.read(conf_opts::CPOL_0) {
+        match self.with_clock(|| regs.config_opts.read(conf_opts::CPOL_0)) {
             0 => ClockPolarity::IdleLow,
             1 => ClockPolarity::IdleHigh,
             _ => unreachable!(),
@@ -706,17 +816,17 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
 
     fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
         let regs = self.registers;
-        match phase {
+        self.with_clock(|| match phase {
             ClockPhase::SampleLeading => regs.config_opts.modify(conf_opts::CPHA_0::CLEAR),
             ClockPhase::SampleTrailing => regs.config_opts.modify(conf_opts::CPHA_0::SET),
-        };
+        });
         Ok(())
     }
 
     fn get_phase(&self) -> ClockPhase {
         let regs = self.registers;
 
-        match regs.config_opts.read(conf_opts::CPHA_0) {
+        match self.with_clock(|| regs.config_opts.read(conf_opts::CPHA_0)) {
             1 => ClockPhase::SampleTrailing,
             0 => ClockPhase::SampleLeading,
             _ => unreachable!(),
@@ -735,3 +845,74 @@ impl<'a> hil::spi::SpiMaster<'a> for SpiHost<'a> {
         unimplemented!("spi_host: does not support release low");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    struct Client {
+        done: Cell<Option<(u8, usize)>>,
+    }
+
+    impl hil::spi::SpiMasterClient for Client {
+        fn read_write_done(
+            &self,
+            _write_buffer: &'static mut [u8],
+            read_buffer: Option<&'static mut [u8]>,
+            len: usize,
+            status: Result<(), ErrorCode>,
+        ) {
+            assert_eq!(status, Ok(()));
+            self.done.set(Some((read_buffer.unwrap()[0], len)));
+        }
+    }
+
+    #[test]
+    fn short_transfer_is_polled() {
+        // Registers backed by memory. The controller is idle with an empty
+        // TX FIFO, and RXDATA holds the bytes clocked in.
+        let memory: [Cell<u32>; 14] = Default::default();
+        let registers = unsafe { StaticRef::new(memory.as_ptr() as *const SpiHostRegisters) };
+        memory[5].set(1 << 28);
+        memory[9].set(0x4433_22A5);
+
+        let spi = SpiHost::new(registers, 1_000_000);
+        let client = Client {
+            done: Cell::new(None),
+        };
+        spi.set_client(&client);
+        spi.set_polled_threshold(4);
+
+        // A one byte transfer completes without interrupts, and the client
+        // is called back from the deferred call.
+        let result = spi.read_write_bytes(
+            Box::leak(Box::new([0x9F])),
+            Some(Box::leak(Box::new([0]))),
+            1,
+        );
+        assert!(result.is_ok());
+        assert!(!registers.intr_enable.is_set(intr::SPI_EVENT));
+        assert!(spi.is_busy());
+        assert_eq!(client.done.get(), None);
+        spi.handle_deferred_call();
+        assert_eq!(client.done.take(), Some((0xA5, 1)));
+        assert!(!spi.is_busy());
+
+        // A longer transfer completes from the interrupt.
+        let result = spi.read_write_bytes(
+            Box::leak(Box::new([0; 16])),
+            Some(Box::leak(Box::new([0; 16]))),
+            16,
+        );
+        assert!(result.is_ok());
+        assert!(registers.intr_enable.is_set(intr::SPI_EVENT));
+        spi.handle_deferred_call();
+        assert_eq!(client.done.get(), None);
+        registers.intr_state.set(intr::SPI_EVENT::SET.value);
+        spi.handle_interrupt();
+        assert_eq!(client.done.take(), Some((0xA5, 16)));
+    }
+}
@@ -3,14 +3,71 @@
 // Copyright Tock Contributors 2022.
 
 //! UART driver.
+//!
+//! Continuous receive
+//! ------------------
+//!
+//! By default a receive only enables the RX interrupt while a buffer from
+//! the client is outstanding, so bytes that arrive between two calls to
+//! `receive_buffer()` can overflow the 32 byte hardware FIFO and be lost.
+//! For high-rate input a board can call [Uart::enable_receive_ring] with a
+//! ring buffer. The RX interrupt then stays enabled permanently, the FIFO is
+//! drained into the ring on every watermark or timeout interrupt, and bytes
+//! are copied out of the ring into the client's buffers as they are
+//! provided. The client can process one chunk while the ring keeps filling.
+//!
+//! The UART on LowRISC/OpenTitan chips is not connected to a DMA engine, so
+//! the ring is filled from the FIFO by the interrupt handler rather than by
+//! DMA.
+//!
+//! Polled transmit
+//! ---------------
+//!
+//! For short transmissions the TX interrupt costs more than sending the
+//! bytes. With [Uart::set_polled_threshold], a buffer shorter than the
+//! threshold that fits in the TX FIFO is written to the FIFO directly and
+//! the client is called back from a deferred call, without enabling the TX
+//! interrupt. Receive always uses interrupts, as it waits for the remote
+//! end.
+//!
+//! Batched transmit
+//! ----------------
+//!
+//! By default a transmit waits for the TX FIFO to empty before refilling it
+//! and before calling the client back, so every transmit costs at least one
+//! interrupt and the line is idle while the next one is set up. With
+//! [Uart::set_tx_watermark], transmits are written into whatever space the
+//! FIFO has left, and the TX watermark interrupt is only enabled when a
+//! buffer does not fit, to refill the FIFO once it drains below the
+//! watermark. The client is called back from a deferred call as soon as
+//! the whole buffer has been handed to the FIFO, so short writes in quick
+//! succession are batched into the FIFO without any interrupt.
+//!
+//! Flush
+//! -----
+//!
+//! A completed transmit only means the bytes are in the FIFO. For
+//! half-duplex lines, [Uart::flush] calls the [FlushClient] back once all
+//! transmits have completed and the last byte has left the shift register.
+//!
+//! Panic output
+//! ------------
+//!
+//! [PanicWriter] writes to a UART by polling its status register. It does
+//! not use interrupts, clients or any of the state of [Uart], so a panic
+//! handler can use it on any UART instance even when the kernel's own
+//! driver for that instance was in the middle of a transfer.
 
 use core::cell::Cell;
 use kernel::ErrorCode;
 
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::hil::uart;
 use kernel::utilities::cells::OptionalCell;
-use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::{MapCell, TakeCell};
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{
     register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
@@ -88,8 +145,8 @@ register_bitfields![u32,
         txilvl OFFSET(5) NUMBITS(2) []
     ],
     fifo_status [
-        txlvl OFFSET(0) NUMBITS(5) [],
-        rxlvl OFFSET(16) NUMBITS(5) []
+        txlvl OFFSET(0) NUMBITS(6) [],
+        rxlvl OFFSET(16) NUMBITS(6) []
     ],
     ovrd [
         txen OFFSET(0) NUMBITS(1) [],
@@ -104,11 +161,90 @@ register_bitfields![u32,
     ]
 ];
 
+/// Idle time, in bit periods, after which the RX timeout interrupt fires in
+/// continuous receive mode.
+const RX_TIMEOUT_BIT_TIMES: u32 = 40;
+
+/// Depth of the TX FIFO.
+const TX_FIFO_DEPTH: usize = 32;
+
+/// Level of the TX FIFO below which the TX watermark interrupt fires.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TxWatermark {
+    Bytes1 = 0,
+    Bytes4 = 1,
+    Bytes8 = 2,
+    Bytes16 = 3,
+}
+
+impl TxWatermark {
+    pub fn bytes(self) -> usize {
+        match self {
+            TxWatermark::Bytes1 => 1,
+            TxWatermark::Bytes4 => 4,
+            TxWatermark::Bytes8 => 8,
+            TxWatermark::Bytes16 => 16,
+        }
+    }
+}
+
+/// Client for [Uart::flush].
+pub trait FlushClient {
+    /// All bytes have been sent and the transmitter is idle.
+    fn flushed(&self);
+}
+
+/// Move bytes from the hardware into `ring` until `next` returns `None`.
+///
+/// Returns the number of bytes that had to be dropped because the ring was
+/// full.
+fn drain_into_ring(ring: &mut RingBuffer<u8>, mut next: impl FnMut() -> Option<u8>) -> usize {
+    let mut dropped = 0;
+    while let Some(byte) = next() {
+        if !ring.enqueue(byte) {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+/// Write `bytes` to the hardware with `put`, waiting for `tx_full` to
+/// clear before each byte.
+///
+/// Returns the number of bytes written.
+fn transmit_polled(
+    bytes: &[u8],
+    mut tx_full: impl FnMut() -> bool,
+    mut put: impl FnMut(u8),
+) -> usize {
+    for b in bytes.iter() {
+        while tx_full() {}
+        put(*b);
+    }
+    bytes.len()
+}
+
+/// Move as many bytes as are available from `ring` into `buf`.
+///
+/// Returns the number of bytes copied.
+fn fill_from_ring(ring: &mut RingBuffer<u8>, buf: &mut [u8]) -> usize {
+    let mut copied = 0;
+    for slot in buf.iter_mut() {
+        match ring.dequeue() {
+            Some(byte) => *slot = byte,
+            None => break,
+        }
+        copied += 1;
+    }
+    copied
+}
+
 pub struct Uart<'a> {
     registers: StaticRef<UartRegisters>,
     clock_frequency: u32,
     tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
     rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    flush_client: OptionalCell<&'a dyn FlushClient>,
 
     tx_buffer: TakeCell<'static, [u8]>,
     tx_len: Cell<usize>,
@@ -116,6 +252,18 @@ pub struct Uart<'a> {
 
     rx_buffer: TakeCell<'static, [u8]>,
     rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+
+    rx_ring: MapCell<RingBuffer<'static, u8>>,
+    rx_overrun: Cell<bool>,
+
+    polled_threshold: Cell<usize>,
+    tx_watermark: Cell<Option<TxWatermark>>,
+    /// Set while the callback for a transmit that was handed to the FIFO
+    /// without waiting for an interrupt is pending.
+    tx_deferred: Cell<bool>,
+    flush_pending: Cell<bool>,
+    deferred_call: DeferredCall,
 }
 
 #[derive(Copy, Clone)]
@@ -130,12 +278,73 @@ impl<'a> Uart<'a> {
             clock_frequency: clock_frequency,
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
+            flush_client: OptionalCell::empty(),
             tx_buffer: TakeCell::empty(),
             tx_len: Cell::new(0),
             tx_index: Cell::new(0),
             rx_buffer: TakeCell::empty(),
             rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_ring: MapCell::empty(),
+            rx_overrun: Cell::new(false),
+            polled_threshold: Cell::new(0),
+            tx_watermark: Cell::new(None),
+            tx_deferred: Cell::new(false),
+            flush_pending: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Transmit buffers shorter than `bytes` by polling.
+    ///
+    /// The buffer must also fit in the free space of the TX FIFO. The
+    /// client is still called back, from a deferred call. A threshold of 0,
+    /// the default, always uses the TX interrupt.
+    pub fn set_polled_threshold(&self, bytes: usize) {
+        self.polled_threshold.set(bytes);
+    }
+
+    /// Batch transmits into the TX FIFO, refilling it when it drains below
+    /// `watermark`.
+    ///
+    /// `None`, the default, waits for the FIFO to empty instead.
+    pub fn set_tx_watermark(&self, watermark: Option<TxWatermark>) {
+        self.tx_watermark.set(watermark);
+    }
+
+    pub fn set_flush_client(&self, client: &'a dyn FlushClient) {
+        self.flush_client.set(client);
+    }
+
+    /// Call the flush client back once the outstanding transmit, if any,
+    /// has completed and the transmitter is idle.
+    pub fn flush(&self) -> Result<(), ErrorCode> {
+        if self.flush_pending.replace(true) {
+            return Err(ErrorCode::BUSY);
         }
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    /// Switch receive to continuous mode, buffering incoming bytes in `ring`.
+    ///
+    /// Once enabled, received bytes are kept in `ring` until the client
+    /// provides a buffer with `receive_buffer()`, instead of being left in
+    /// the hardware FIFO. If the ring fills up, further bytes are dropped
+    /// and the next completed receive reports `uart::Error::OverrunError`.
+    pub fn enable_receive_ring(&self, ring: &'static mut [u8]) {
+        let regs = self.registers;
+
+        self.rx_ring.replace(RingBuffer::new(ring));
+        self.rx_overrun.set(false);
+
+        // Interrupt once the FIFO holds 8 bytes, or when the line has been
+        // idle for 4 character times with fewer bytes than that in the FIFO.
+        regs.fifo_ctrl.modify(fifo_ctrl::rxilvl.val(2));
+        regs.timeout_ctrl
+            .write(timeout_ctrl::en::SET + timeout_ctrl::val.val(RX_TIMEOUT_BIT_TIMES));
+        regs.intr_enable
+            .modify(intr::rx_watermark::SET + intr::rx_timeout::SET + intr::rx_overflow::SET);
     }
 
     fn set_baud_rate(&self, baud_rate: u32) {
@@ -159,9 +368,11 @@ impl<'a> Uart<'a> {
     fn disable_tx_interrupt(&self) {
         let regs = self.registers;
 
-        regs.intr_enable.modify(intr::tx_empty::CLEAR);
-        // Clear the interrupt bit (by writing 1), if it happens to be set
-        regs.intr_state.write(intr::tx_empty::SET);
+        regs.intr_enable
+            .modify(intr::tx_empty::CLEAR + intr::tx_watermark::CLEAR);
+        // Clear the interrupt bits (by writing 1), if they happen to be set
+        regs.intr_state
+            .write(intr::tx_empty::SET + intr::tx_watermark::SET);
     }
 
     fn enable_rx_interrupt(&self) {
@@ -169,7 +380,7 @@ impl<'a> Uart<'a> {
 
         // Generate an interrupt if we get any value in the RX buffer
         regs.intr_enable.modify(intr::rx_watermark::SET);
-        regs.fifo_ctrl.write(fifo_ctrl::rxilvl.val(0 as u32));
+        regs.fifo_ctrl.modify(fifo_ctrl::rxilvl.val(0 as u32));
     }
 
     fn disable_rx_interrupt(&self) {
@@ -188,27 +399,72 @@ impl<'a> Uart<'a> {
         let len = self.tx_len.get();
 
         if idx < len {
-            // If we are going to transmit anything, we first need to enable the
-            // TX interrupt. This ensures that we will get an interrupt, where
-            // we can either call the callback from, or continue transmitting
-            // bytes.
-            self.enable_tx_interrupt();
+            // Without a watermark, we first need to enable the TX interrupt.
+            // This ensures that we will get an interrupt, where we can either
+            // call the callback from, or continue transmitting bytes.
+            if self.tx_watermark.get().is_none() {
+                self.enable_tx_interrupt();
+            }
 
             // Read from the transmit buffer and send bytes to the UART hardware
             // until either the buffer is empty or the UART hardware is full.
+            let fifo_free =
+                TX_FIFO_DEPTH.saturating_sub(regs.fifo_status.read(fifo_status::txlvl) as usize);
+            let end = core::cmp::min(len, idx + fifo_free);
             self.tx_buffer.map(|tx_buf| {
-                let tx_len = len - idx;
-
-                for i in 0..tx_len {
-                    if regs.status.is_set(status::txfull) {
-                        break;
-                    }
-                    let tx_idx = idx + i;
-                    let data: u32 = *tx_buf.get(tx_idx).unwrap_or(&0) as u32;
-                    regs.wdata.write(wdata::data.val(data));
-                    self.tx_index.set(tx_idx + 1)
+                for b in tx_buf[idx..end].iter() {
+                    regs.wdata.write(wdata::data.val(*b as u32));
                 }
             });
+            self.tx_index.set(end);
+        }
+
+        if let Some(watermark) = self.tx_watermark.get() {
+            if self.tx_index.get() == len {
+                // Everything is in the FIFO, there is nothing to wait for.
+                self.tx_deferred.set(true);
+                self.deferred_call.set();
+            } else {
+                // The FIFO is full, so the watermark interrupt fires once it
+                // has drained.
+                regs.fifo_ctrl
+                    .modify(fifo_ctrl::txilvl.val(watermark as u32));
+                regs.intr_enable.modify(intr::tx_watermark::SET);
+            }
+        }
+    }
+
+    /// Return the transmit buffer to the client, and continue a pending
+    /// flush if the client did not start another transmit.
+    fn tx_done(&self) {
+        self.tx_client.map(|client| {
+            self.tx_buffer.take().map(|tx_buf| {
+                client.transmitted_buffer(tx_buf, self.tx_len.get(), Ok(()));
+            });
+        });
+        self.flush_progress();
+    }
+
+    fn flush_progress(&self) {
+        if !self.flush_pending.get() || self.tx_buffer.is_some() {
+            return;
+        }
+
+        let regs = self.registers;
+        let line = regs.status.extract();
+        if line.is_set(status::txidle) {
+            self.flush_pending.set(false);
+            self.flush_client.map(|client| client.flushed());
+        } else if line.is_set(status::txempty) {
+            // The last byte is still in the shift register, which has no
+            // interrupt. It is sent within one character time, so poll it.
+            self.deferred_call.set();
+        } else {
+            self.enable_tx_interrupt();
+            // Do not miss the FIFO emptying before the interrupt was enabled.
+            if regs.status.is_set(status::txempty) {
+                self.deferred_call.set();
+            }
         }
     }
 
@@ -216,22 +472,26 @@ impl<'a> Uart<'a> {
         let regs = self.registers;
         let intrs = regs.intr_state.extract();
 
-        if intrs.is_set(intr::tx_empty) {
+        // A polled transmit can leave tx_empty set while the interrupt is
+        // disabled, and tx_watermark is set whenever the FIFO is nearly empty.
+        let enabled = regs.intr_enable.extract();
+        if (intrs.is_set(intr::tx_empty) && enabled.is_set(intr::tx_empty))
+            || (intrs.is_set(intr::tx_watermark) && enabled.is_set(intr::tx_watermark))
+        {
             self.disable_tx_interrupt();
 
-            if self.tx_index.get() == self.tx_len.get() {
+            if self.tx_buffer.is_none() {
+                // Only a flush was waiting for the FIFO to empty.
+                self.flush_progress();
+            } else if self.tx_index.get() == self.tx_len.get() {
                 // We sent everything to the UART hardware, now from an
                 // interrupt callback we can issue the callback.
-                self.tx_client.map(|client| {
-                    self.tx_buffer.take().map(|tx_buf| {
-                        client.transmitted_buffer(tx_buf, self.tx_len.get(), Ok(()));
-                    });
-                });
+                self.tx_done();
             } else {
                 // We have more to transmit, so continue in tx_progress().
                 self.tx_progress();
             }
-        } else if intrs.is_set(intr::rx_watermark) {
+        } else if intrs.is_set(intr::rx_watermark) && self.rx_ring.is_none() {
             self.disable_rx_interrupt();
 
             self.rx_client.map(|client| {
@@ -254,15 +514,149 @@ impl<'a> Uart<'a> {
                 });
             });
         }
+
+        if self.rx_ring.is_some()
+            && (intrs.is_set(intr::rx_watermark)
+                || intrs.is_set(intr::rx_timeout)
+                || intrs.is_set(intr::rx_overflow))
+        {
+            if intrs.is_set(intr::rx_overflow) {
+                self.rx_overrun.set(true);
+            }
+            regs.intr_state
+                .write(intr::rx_watermark::SET + intr::rx_timeout::SET + intr::rx_overflow::SET);
+            self.rx_ring_progress();
+        }
+    }
+
+    /// Drain the FIFO into the receive ring and, if the client has provided
+    /// a buffer, copy as much of the ring into it as fits. The buffer is
+    /// returned to the client once it has been filled to the requested
+    /// length.
+    fn rx_ring_progress(&self) {
+        let regs = self.registers;
+
+        let completed = self.rx_ring.map(|ring| {
+            let dropped = drain_into_ring(ring, || {
+                if regs.status.is_set(status::rxempty) {
+                    None
+                } else {
+                    Some(regs.rdata.get() as u8)
+                }
+            });
+            if dropped > 0 {
+                self.rx_overrun.set(true);
+            }
+
+            self.rx_buffer.take().and_then(|rx_buf| {
+                let len = self.rx_len.get();
+                let index = self.rx_index.get();
+                let index = index + fill_from_ring(ring, &mut rx_buf[index..len]);
+                self.rx_index.set(index);
+                if index == len {
+                    Some(rx_buf)
+                } else {
+                    self.rx_buffer.replace(rx_buf);
+                    None
+                }
+            })
+        });
+
+        if let Some(rx_buf) = completed.flatten() {
+            let (return_code, error) = if self.rx_overrun.replace(false) {
+                (Err(ErrorCode::FAIL), uart::Error::OverrunError)
+            } else {
+                (Ok(()), uart::Error::None)
+            };
+            self.rx_client.map(|client| {
+                client.received_buffer(rx_buf, self.rx_len.get(), return_code, error);
+            });
+        }
     }
 
     pub fn transmit_sync(&self, bytes: &[u8]) {
         let regs = self.registers;
-        for b in bytes.iter() {
-            while regs.status.is_set(status::txfull) {}
-            regs.wdata.write(wdata::data.val(*b as u32));
+        transmit_polled(
+            bytes,
+            || regs.status.is_set(status::txfull),
+            |b| regs.wdata.write(wdata::data.val(b as u32)),
+        );
+    }
+}
+
+/// Blocking, polled UART writer for panic output.
+///
+/// The first write sets the baud rate, enables the transmitter and masks
+/// the UART's interrupts. The TX FIFO is not reset, so bytes the kernel
+/// had already queued still go out ahead of the panic dump. Every write
+/// returns only once the transmitter is idle.
+pub struct PanicWriter {
+    registers: StaticRef<UartRegisters>,
+    clock_frequency: u32,
+    baud_rate: u32,
+    configured: bool,
+}
+
+impl PanicWriter {
+    pub const fn new(
+        base: StaticRef<UartRegisters>,
+        clock_frequency: u32,
+        baud_rate: u32,
+    ) -> PanicWriter {
+        PanicWriter {
+            registers: base,
+            clock_frequency,
+            baud_rate,
+            configured: false,
+        }
+    }
+
+    fn configure(&mut self) {
+        let regs = self.registers;
+        let uart_ctrl_nco = ((self.baud_rate as u64) << 20) / self.clock_frequency as u64;
+
+        regs.intr_enable.set(0);
+        regs.ctrl
+            .modify(ctrl::nco.val((uart_ctrl_nco & 0xffff) as u32) + ctrl::tx::SET);
+        self.configured = true;
+    }
+}
+
+impl kernel::debug::IoWrite for PanicWriter {
+    fn write(&mut self, buf: &[u8]) -> usize {
+        if !self.configured {
+            self.configure();
+        }
+        let regs = self.registers;
+        let written = transmit_polled(
+            buf,
+            || regs.status.is_set(status::txfull),
+            |b| regs.wdata.write(wdata::data.val(b as u32)),
+        );
+        while !regs.status.is_set(status::txidle) {}
+        written
+    }
+}
+
+impl core::fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        kernel::debug::IoWrite::write(self, s.as_bytes());
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for Uart<'_> {
+    fn handle_deferred_call(&self) {
+        if self.tx_deferred.replace(false) {
+            self.tx_done();
+        } else {
+            self.flush_progress();
         }
     }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
 }
 
 impl hil::uart::Configure for Uart<'_> {
@@ -296,12 +690,31 @@ impl<'a> hil::uart::Transmit<'a> for Uart<'a> {
         } else if self.tx_buffer.is_some() {
             Err((ErrorCode::BUSY, tx_data))
         } else {
+            let regs = self.registers;
+            let fifo_free =
+                TX_FIFO_DEPTH.saturating_sub(regs.fifo_status.read(fifo_status::txlvl) as usize);
+            let polled = tx_len < self.polled_threshold.get() && tx_len <= fifo_free;
+
+            if polled {
+                transmit_polled(
+                    &tx_data[..tx_len],
+                    || regs.status.is_set(status::txfull),
+                    |b| regs.wdata.write(wdata::data.val(b as u32)),
+                );
+            }
+
             // Save the buffer so we can keep sending it.
             self.tx_buffer.replace(tx_data);
             self.tx_len.set(tx_len);
-            self.tx_index.set(0);
 
-            self.tx_progress();
+            if polled {
+                self.tx_index.set(tx_len);
+                self.tx_deferred.set(true);
+                self.deferred_call.set();
+            } else {
+                self.tx_index.set(0);
+                self.tx_progress();
+            }
             Ok(())
         }
     }
@@ -329,11 +742,22 @@ impl<'a> hil::uart::Receive<'a> for Uart<'a> {
         if rx_len == 0 || rx_len > rx_buffer.len() {
             return Err((ErrorCode::SIZE, rx_buffer));
         }
-
-        self.enable_rx_interrupt();
+        if self.rx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
 
         self.rx_buffer.replace(rx_buffer);
         self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+
+        if self.rx_ring.is_some() {
+            // Bytes may already be waiting in the ring. Raise the RX timeout
+            // interrupt so they are copied out from the interrupt handler
+            // rather than calling the client back from within this call.
+            self.registers.intr_test.write(intr::rx_timeout::SET);
+        } else {
+            self.enable_rx_interrupt();
+        }
 
         Ok(())
     }
@@ -346,3 +770,340 @@ impl<'a> hil::uart::Receive<'a> for Uart<'a> {
         Err(ErrorCode::FAIL)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    /// Depth of the hardware RX FIFO.
+    const FIFO_DEPTH: usize = 32;
+
+    #[test]
+    fn burst_through_ring_is_lossless() {
+        let mut ring_storage = [0u8; 48];
+        let mut ring = RingBuffer::new(&mut ring_storage);
+
+        let mut burst = [0u8; 500];
+        for (i, byte) in burst.iter_mut().enumerate() {
+            *byte = (i * 7 + 3) as u8;
+        }
+
+        let mut received = [0u8; 500];
+        let mut received_len = 0;
+        let mut client_buf = [0u8; 20];
+        let mut client_index = 0;
+        let mut sent = 0;
+        let mut dropped = 0;
+
+        while received_len < burst.len() {
+            // Each interrupt finds a full FIFO, as it would with the line
+            // running flat out.
+            let end = core::cmp::min(sent + FIFO_DEPTH, burst.len());
+            let mut fifo = burst[sent..end].iter().copied();
+            dropped += drain_into_ring(&mut ring, || fifo.next());
+            sent = end;
+
+            // The client re-arms with a new buffer as soon as one is
+            // returned, so keep copying until the ring is empty.
+            loop {
+                client_index += fill_from_ring(&mut ring, &mut client_buf[client_index..]);
+                let last = sent == burst.len() && !ring.has_elements();
+                if client_index < client_buf.len() && !last {
+                    break;
+                }
+                received[received_len..received_len + client_index]
+                    .copy_from_slice(&client_buf[..client_index]);
+                received_len += client_index;
+                client_index = 0;
+                if last {
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(dropped, 0);
+        assert_eq!(received, burst);
+    }
+
+    #[test]
+    fn full_ring_reports_dropped_bytes() {
+        let mut ring_storage = [0u8; 8];
+        let mut ring = RingBuffer::new(&mut ring_storage);
+
+        let mut fifo = 0u8..20;
+        // One slot of the ring is always kept free to tell full from empty.
+        assert_eq!(drain_into_ring(&mut ring, || fifo.next()), 13);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(fill_from_ring(&mut ring, &mut buf), 7);
+        assert_eq!(&buf[..7], &[0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn polled_transmit_waits_for_fifo_space() {
+        let message = b"panicked at kernel/src/main.rs";
+
+        // A small FIFO that drains one byte for every three polls of the
+        // status register, with nothing else ever running.
+        const TX_DEPTH: usize = 4;
+        let fifo_level = Cell::new(0usize);
+        let polls = Cell::new(0usize);
+        let mut sent = [0u8; 30];
+        let mut sent_len = 0;
+
+        let written = transmit_polled(
+            message,
+            || {
+                polls.set(polls.get() + 1);
+                if polls.get() % 3 == 0 && fifo_level.get() > 0 {
+                    fifo_level.set(fifo_level.get() - 1);
+                }
+                fifo_level.get() == TX_DEPTH
+            },
+            |b| {
+                assert!(fifo_level.get() < TX_DEPTH);
+                fifo_level.set(fifo_level.get() + 1);
+                sent[sent_len] = b;
+                sent_len += 1;
+            },
+        );
+
+        assert_eq!(written, message.len());
+        assert_eq!(&sent[..sent_len], message);
+        // The writer had to wait for the FIFO to drain.
+        assert!(polls.get() > message.len());
+    }
+
+    struct Client {
+        transmitted: Cell<Option<usize>>,
+    }
+
+    impl hil::uart::TransmitClient for Client {
+        fn transmitted_buffer(
+            &self,
+            _tx_buffer: &'static mut [u8],
+            tx_len: usize,
+            rval: Result<(), ErrorCode>,
+        ) {
+            assert_eq!(rval, Ok(()));
+            self.transmitted.set(Some(tx_len));
+        }
+    }
+
+    #[test]
+    fn short_transmit_is_polled() {
+        use hil::uart::Transmit;
+
+        // Registers backed by memory: the FIFO is never full, and writes to
+        // the interrupt enable register stick.
+        let memory: [Cell<u32>; 13] = Default::default();
+        let registers = unsafe { StaticRef::new(memory.as_ptr() as *const UartRegisters) };
+        let uart = Uart::new(registers, 1_000_000);
+        let client = Client {
+            transmitted: Cell::new(None),
+        };
+        uart.set_transmit_client(&client);
+        uart.set_polled_threshold(4);
+
+        // A one byte transfer goes straight to the FIFO, without the TX
+        // interrupt, and the client is called back from the deferred call.
+        assert!(uart.transmit_buffer(Box::leak(Box::new([0x5A])), 1).is_ok());
+        assert!(!registers.intr_enable.is_set(intr::tx_empty));
+        // The last byte written to WDATA.
+        assert_eq!(memory[7].get(), 0x5A);
+        assert_eq!(client.transmitted.get(), None);
+        uart.handle_deferred_call();
+        assert_eq!(client.transmitted.take(), Some(1));
+
+        // A longer transfer waits for the TX interrupt.
+        assert!(uart
+            .transmit_buffer(Box::leak(Box::new([0; 16])), 16)
+            .is_ok());
+        assert!(registers.intr_enable.is_set(intr::tx_empty));
+        uart.handle_deferred_call();
+        assert_eq!(client.transmitted.get(), None);
+        registers.intr_state.modify(intr::tx_empty::SET);
+        uart.handle_interrupt();
+        assert_eq!(client.transmitted.take(), Some(16));
+    }
+
+    #[derive(Default)]
+    struct LineClient {
+        transmits: Cell<usize>,
+        transmitted_bytes: Cell<usize>,
+        flushed: Cell<bool>,
+    }
+
+    impl hil::uart::TransmitClient for LineClient {
+        fn transmitted_buffer(
+            &self,
+            _tx_buffer: &'static mut [u8],
+            tx_len: usize,
+            rval: Result<(), ErrorCode>,
+        ) {
+            assert_eq!(rval, Ok(()));
+            self.transmits.set(self.transmits.get() + 1);
+            self.transmitted_bytes
+                .set(self.transmitted_bytes.get() + tx_len);
+        }
+    }
+
+    impl FlushClient for LineClient {
+        fn flushed(&self) {
+            self.flushed.set(true);
+        }
+    }
+
+    /// The TX side of the hardware, on registers backed by `memory`. Every
+    /// step is one character time: the shift register sends a byte and
+    /// takes the next one from the FIFO.
+    struct TxLine<'a> {
+        memory: &'a [Cell<u32>; 13],
+        registers: StaticRef<UartRegisters>,
+        /// Bytes the driver has written to the FIFO.
+        written: usize,
+        level: usize,
+        shifting: bool,
+        interrupts: usize,
+    }
+
+    impl<'a> TxLine<'a> {
+        fn new(memory: &'a [Cell<u32>; 13]) -> TxLine<'a> {
+            TxLine {
+                memory,
+                registers: unsafe { StaticRef::new(memory.as_ptr() as *const UartRegisters) },
+                written: 0,
+                level: 0,
+                shifting: false,
+                interrupts: 0,
+            }
+        }
+
+        /// Account for the bytes the driver has written since the last
+        /// call, as WDATA only holds the last one.
+        fn sync(&mut self, uart: &Uart, client: &LineClient) {
+            let in_progress = uart.tx_buffer.map_or(0, |_| uart.tx_index.get());
+            let written = client.transmitted_bytes.get() + in_progress;
+            self.level += written - self.written;
+            self.written = written;
+            assert!(self.level <= TX_FIFO_DEPTH);
+            self.update_registers();
+        }
+
+        fn update_registers(&self) {
+            let regs = self.registers;
+            regs.fifo_status
+                .write(fifo_status::txlvl.val(self.level as u32));
+            let empty = self.level == 0;
+            // STATUS is read-only to the driver.
+            self.memory[5].set(
+                (status::txempty.val(empty as u32)
+                    + status::txidle.val((empty && !self.shifting) as u32))
+                .value,
+            );
+        }
+
+        fn step(&mut self, uart: &Uart, client: &LineClient) {
+            self.shifting = self.level > 0;
+            self.level = self.level.saturating_sub(1);
+            self.update_registers();
+
+            // Both TX interrupts reflect the current level of the FIFO.
+            let regs = self.registers;
+            let txilvl = regs.fifo_ctrl.read(fifo_ctrl::txilvl);
+            let watermark = [1, 4, 8, 16][txilvl as usize];
+            self.memory[0].set(
+                (intr::tx_empty.val((self.level == 0) as u32)
+                    + intr::tx_watermark.val((self.level < watermark) as u32))
+                .value,
+            );
+            if regs.intr_state.get() & regs.intr_enable.get() != 0 {
+                self.interrupts += 1;
+                uart.handle_interrupt();
+                self.sync(uart, client);
+            }
+        }
+    }
+
+    /// Send 40 messages of 8 bytes as fast as the driver takes them, and
+    /// return the number of interrupts.
+    fn send_messages(watermark: Option<TxWatermark>) -> usize {
+        use hil::uart::Transmit;
+
+        let memory: [Cell<u32>; 13] = Default::default();
+        let mut line = TxLine::new(&memory);
+        let uart = Uart::new(line.registers, 1_000_000);
+        let client = LineClient::default();
+        uart.set_transmit_client(&client);
+        uart.set_tx_watermark(watermark);
+
+        for message in 0..40 {
+            assert!(uart.transmit_buffer(Box::leak(Box::new([0; 8])), 8).is_ok());
+            line.sync(&uart, &client);
+            while client.transmits.get() == message {
+                uart.handle_deferred_call();
+                line.sync(&uart, &client);
+                if client.transmits.get() == message {
+                    line.step(&uart, &client);
+                }
+            }
+            // The buffer is only returned once all of it is in the FIFO.
+            assert_eq!(client.transmits.get(), message + 1);
+            assert!(uart.tx_buffer.is_none());
+        }
+        assert_eq!(client.transmitted_bytes.get(), 320);
+        line.interrupts
+    }
+
+    #[test]
+    fn watermark_batches_transmits() {
+        // Without a watermark every transmit waits for the FIFO to empty.
+        assert_eq!(send_messages(None), 40);
+        // With one, short transmits are batched into the FIFO and it is
+        // only refilled once per watermark interrupt.
+        assert_eq!(send_messages(Some(TxWatermark::Bytes8)), 12);
+        assert_eq!(send_messages(Some(TxWatermark::Bytes16)), 17);
+    }
+
+    #[test]
+    fn flush_waits_for_shift_register() {
+        use hil::uart::Transmit;
+
+        let memory: [Cell<u32>; 13] = Default::default();
+        let mut line = TxLine::new(&memory);
+        let uart = Uart::new(line.registers, 1_000_000);
+        let client = LineClient::default();
+        uart.set_transmit_client(&client);
+        uart.set_flush_client(&client);
+
+        assert!(uart.transmit_buffer(Box::leak(Box::new([0; 8])), 8).is_ok());
+        line.sync(&uart, &client);
+        assert_eq!(uart.flush(), Ok(()));
+        assert_eq!(uart.flush(), Err(ErrorCode::BUSY));
+
+        let mut steps = 0;
+        while !client.flushed.get() {
+            assert!(steps < 100);
+            uart.handle_deferred_call();
+            line.sync(&uart, &client);
+            if !client.flushed.get() {
+                line.step(&uart, &client);
+                steps += 1;
+            }
+        }
+        // All 8 bytes have gone through the shift register, which took one
+        // step more than emptying the FIFO.
+        assert_eq!(client.transmits.get(), 1);
+        assert!(line.level == 0 && !line.shifting);
+        assert_eq!(steps, 9);
+
+        // With nothing to send, a flush completes from the deferred call.
+        assert_eq!(uart.flush(), Ok(()));
+        client.flushed.set(false);
+        uart.handle_deferred_call();
+        assert!(client.flushed.get());
+    }
+}
@@ -342,6 +342,12 @@ pub struct Endpoint<'a> {
     slice_in: OptionalCell<&'a [VolatileCell<u8>]>,
     slice_out: OptionalCell<&'a [VolatileCell<u8>]>,
     state: Cell<EndpointState>,
+    /// Bulk transfers on this endpoint use two packet buffers, see
+    /// `Usb::endpoint_set_double_buffered`.
+    double_buffered: Cell<bool>,
+    /// The next IN packet, copied to a packet buffer while the previous one
+    /// is being sent: the buffer ID and the packet size.
+    staged_in: Cell<Option<(usize, usize)>>,
 
     _reserved: u32,
 }
@@ -352,6 +358,8 @@ impl Endpoint<'_> {
             slice_in: OptionalCell::empty(),
             slice_out: OptionalCell::empty(),
             state: Cell::new(EndpointState::Disabled),
+            double_buffered: Cell::new(false),
+            staged_in: Cell::new(None),
             _reserved: 0,
         }
     }
@@ -497,6 +505,42 @@ impl<'a> Usb<'a> {
         );
     }
 
+    /// Double-buffer bulk transfers on `endpoint`.
+    ///
+    /// The controller has a single IN buffer slot per endpoint, so while an
+    /// IN packet is being sent the driver asks the client for the next one
+    /// and copies it into a second packet buffer. When the first packet is
+    /// acknowledged the second is handed to the controller straight away,
+    /// before the client is told the first was transmitted, so the host is
+    /// not NAKed while software refills the endpoint. The client must accept
+    /// `packet_in` being called again before `packet_transmitted` for the
+    /// previous packet; `packet_transmitted` is still called once per packet,
+    /// in order. For OUT, every packet the controller has already received
+    /// into its free buffers is delivered in the same interrupt.
+    ///
+    /// Call this while setting up the endpoint, before it is enabled.
+    pub fn endpoint_set_double_buffered(&self, endpoint: usize, double_buffered: bool) {
+        self.descriptors[endpoint]
+            .double_buffered
+            .set(double_buffered);
+    }
+
+    fn is_double_buffered_bulk(&self, ep: usize) -> bool {
+        self.descriptors[ep].double_buffered.get()
+            && matches!(self.descriptors[ep].state.get(), EndpointState::Bulk(_, _))
+    }
+
+    /// Claim a free packet buffer for an IN packet.
+    fn take_free_buffer(&self) -> Option<usize> {
+        let mut bufs = self.bufs.get();
+        let buf_id = bufs.iter_mut().find(|buf| buf.free).map(|buf| {
+            buf.free = false;
+            buf.id
+        });
+        self.bufs.set(bufs);
+        buf_id
+    }
+
     fn free_buffer(&self, buf_id: usize) {
         let mut bufs = self.bufs.get();
 
@@ -517,6 +561,20 @@ impl<'a> Usb<'a> {
     }
 
     fn copy_slice_out_to_hw(&self, ep: usize, buf_id: usize, size: usize) {
+        self.copy_slice_out_to_buffer(ep, buf_id, size);
+        self.arm_in(ep, buf_id, size);
+    }
+
+    /// Hand a packet buffer to the controller to be sent on `ep`.
+    fn arm_in(&self, ep: usize, buf_id: usize, size: usize) {
+        self.registers.configin[ep].write(
+            CONFIGIN::BUFFER.val(buf_id as u32)
+                + CONFIGIN::SIZE.val(size as u32)
+                + CONFIGIN::RDY::SET,
+        );
+    }
+
+    fn copy_slice_out_to_buffer(&self, ep: usize, buf_id: usize, size: usize) {
         // Get the slice
         let slice = self.descriptors[ep].slice_out.unwrap_or_panic(); // Unwrap fail = No OUT slice set for this descriptor
 
@@ -547,12 +605,41 @@ impl<'a> Usb<'a> {
             // Write the data
             self.registers.buffer[(buf_id * 8) + (slice_start / 8)].set(to_write);
         }
+    }
 
-        self.registers.configin[ep].write(
-            CONFIGIN::BUFFER.val(buf_id as u32)
-                + CONFIGIN::SIZE.val(size as u32)
-                + CONFIGIN::RDY::SET,
+    /// Ask the client for the next IN packet of a double-buffered endpoint
+    /// and copy it to a packet buffer, to be sent once the packet in flight
+    /// is acknowledged.
+    fn stage_in(&self, ep: usize) {
+        let in_flight = matches!(
+            self.descriptors[ep].state.get(),
+            EndpointState::Bulk(Some(BulkInState::In(_)), _)
         );
+        if !self.is_double_buffered_bulk(ep)
+            || !in_flight
+            || self.descriptors[ep].staged_in.get().is_some()
+        {
+            return;
+        }
+        // Claim the buffer first, so a packet is never taken from the client
+        // without somewhere to put it.
+        let buf_id = match self.take_free_buffer() {
+            Some(buf_id) => buf_id,
+            None => return,
+        };
+        let result = self.client.map_or(hil::usb::InResult::Delay, |client| {
+            client.packet_in(TransferType::Bulk, ep)
+        });
+        match result {
+            hil::usb::InResult::Packet(size) => {
+                self.copy_slice_out_to_buffer(ep, buf_id, size);
+                self.descriptors[ep].staged_in.set(Some((buf_id, size)));
+            }
+            // The client calls `endpoint_resume_in` when it has more data.
+            // An error is reported again, and the endpoint stalled, once the
+            // packet in flight has been sent.
+            hil::usb::InResult::Delay | hil::usb::InResult::Error => self.free_buffer(buf_id),
+        }
     }
 
     fn copy_from_hw(&self, ep: usize, buf_id: usize, size: usize) {
@@ -828,9 +915,30 @@ impl<'a> Usb<'a> {
 
                 self.free_buffer(buf as usize);
 
+                // Send the staged packet before anything else, so the host
+                // finds it ready on its next IN token.
+                let staged = self.descriptors[ep as usize].staged_in.take();
+                if let Some((staged_buf, size)) = staged {
+                    self.arm_in(ep as usize, staged_buf, size);
+                }
+                if let EndpointState::Bulk(Some(_), out_state) =
+                    self.descriptors[ep as usize].state.get()
+                {
+                    let in_state = match staged {
+                        Some((_, size)) => BulkInState::In(size),
+                        None => BulkInState::Init,
+                    };
+                    self.descriptors[ep as usize]
+                        .state
+                        .set(EndpointState::Bulk(Some(in_state), out_state));
+                }
+
                 self.client.map(|client| {
                     client.packet_transmitted(ep as usize);
                 });
+                if staged.is_some() {
+                    self.stage_in(ep as usize);
+                }
 
                 match self.descriptors[ep as usize].state.get() {
                     EndpointState::Disabled => unimplemented!(),
@@ -908,7 +1016,15 @@ impl<'a> Usb<'a> {
                         };
                         self.ep_receive(ep as usize, buf as usize, receive_size, setup);
                         self.free_buffer(buf as usize);
-                        break;
+                        // Keep delivering the packets queued behind this
+                        // one, unless the client asked to wait.
+                        let delayed = matches!(
+                            self.descriptors[ep as usize].state.get(),
+                            EndpointState::Bulk(_, Some(BulkOutState::OutDelay))
+                        );
+                        if !self.is_double_buffered_bulk(ep as usize) || delayed {
+                            break;
+                        }
                     }
                     8 => unimplemented!("isochronous endpoint"),
                     _ => unimplemented!(),
@@ -928,26 +1044,23 @@ impl<'a> Usb<'a> {
     }
 
     fn transmit_in(&self, ep: usize) {
+        if self.is_double_buffered_bulk(ep)
+            && matches!(
+                self.descriptors[ep].state.get(),
+                EndpointState::Bulk(Some(BulkInState::In(_)), _)
+            )
+        {
+            // A packet is in flight, prepare the next one behind it.
+            self.stage_in(ep);
+            return;
+        }
+
         self.client.map(|client| {
             let result = client.packet_in(self.get_transfer_type(ep), ep);
 
             let new_in_state = match result {
                 hil::usb::InResult::Packet(size) => {
-                    let mut buf_id = None;
-                    let mut bufs = self.bufs.get();
-
-                    for buf in bufs.iter_mut() {
-                        if !buf.free {
-                            continue;
-                        }
-
-                        self.registers.avbuffer.set(buf.id as u32);
-                        buf.free = false;
-                        buf_id = Some(buf.id);
-                        break;
-                    }
-
-                    self.bufs.set(bufs);
+                    let buf_id = self.take_free_buffer();
                     match self.descriptors[ep as usize].state.get() {
                         EndpointState::Disabled => unreachable!(),
                         EndpointState::Ctrl(_state) => unreachable!(),
@@ -999,6 +1112,7 @@ impl<'a> Usb<'a> {
 
             self.descriptors[ep].state.set(new_in_state);
         });
+        self.stage_in(ep);
     }
 
     /// Provide a buffer for transfers in and out of the given endpoint
@@ -1175,3 +1289,135 @@ impl<'a> hil::usb::UsbController<'a> for Usb<'a> {
             .set(1 << endpoint | self.registers.rxenable_out.get());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    /// Supplies `remaining` IN packets of 8 bytes, the first filled with 1,
+    /// the second with 2 and so on.
+    struct Client {
+        registers: StaticRef<UsbRegisters>,
+        slice: &'static [VolatileCell<u8>],
+        remaining: Cell<u8>,
+        sent: Cell<u8>,
+        /// `CONFIGIN` of endpoint 1 each time a packet was asked for.
+        configin_at_packet_in: RefCell<Vec<u32>>,
+        transmitted: Cell<usize>,
+    }
+
+    impl<'a> hil::usb::Client<'a> for Client {
+        fn enable(&'a self) {}
+        fn attach(&'a self) {}
+        fn bus_reset(&'a self) {}
+        fn ctrl_setup(&'a self, _endpoint: usize) -> hil::usb::CtrlSetupResult {
+            hil::usb::CtrlSetupResult::ErrGeneric
+        }
+        fn ctrl_in(&'a self, _endpoint: usize) -> hil::usb::CtrlInResult {
+            hil::usb::CtrlInResult::Error
+        }
+        fn ctrl_out(&'a self, _endpoint: usize, _packet_bytes: u32) -> hil::usb::CtrlOutResult {
+            hil::usb::CtrlOutResult::Halted
+        }
+        fn ctrl_status(&'a self, _endpoint: usize) {}
+        fn ctrl_status_complete(&'a self, _endpoint: usize) {}
+        fn packet_in(
+            &'a self,
+            _transfer_type: TransferType,
+            endpoint: usize,
+        ) -> hil::usb::InResult {
+            assert_eq!(endpoint, 1);
+            self.configin_at_packet_in
+                .borrow_mut()
+                .push(self.registers.configin[1].get());
+            if self.remaining.get() == 0 {
+                return hil::usb::InResult::Delay;
+            }
+            self.remaining.set(self.remaining.get() - 1);
+            self.sent.set(self.sent.get() + 1);
+            for byte in &self.slice[..8] {
+                byte.set(self.sent.get());
+            }
+            hil::usb::InResult::Packet(8)
+        }
+        fn packet_out(
+            &'a self,
+            _transfer_type: TransferType,
+            _endpoint: usize,
+            _packet_bytes: u32,
+        ) -> hil::usb::OutResult {
+            hil::usb::OutResult::Error
+        }
+        fn packet_transmitted(&'a self, endpoint: usize) {
+            assert_eq!(endpoint, 1);
+            self.transmitted.set(self.transmitted.get() + 1);
+        }
+    }
+
+    #[test]
+    fn double_buffered_in_packets_are_sent_back_to_back() {
+        // Registers backed by memory. The available buffer FIFO is full, so
+        // the interrupt handler does not hand out any packet buffers.
+        let memory: &'static [Cell<u64>] = Vec::leak((0..512).map(|_| Cell::new(0)).collect());
+        let registers = unsafe { StaticRef::new(memory.as_ptr() as *const UsbRegisters) };
+        memory[3].set((USBSTAT::AV_FULL::SET.value as u64) << 32);
+        // The controller reports a packet sent on endpoint 1.
+        let sent = || {
+            memory[6].set((1 << 1) << 32);
+            memory[0].set(INTR::PKT_SENT::SET.value as u64);
+        };
+
+        let slice: &'static [VolatileCell<u8>] =
+            Vec::leak((0..64).map(|_| VolatileCell::new(0)).collect());
+        let client: &'static Client = std::boxed::Box::leak(std::boxed::Box::new(Client {
+            registers,
+            slice,
+            remaining: Cell::new(2),
+            sent: Cell::new(0),
+            configin_at_packet_in: RefCell::new(Vec::new()),
+            transmitted: Cell::new(0),
+        }));
+        let usb: &'static Usb = std::boxed::Box::leak(std::boxed::Box::new(Usb::new(registers)));
+        hil::usb::UsbController::set_client(usb, client);
+        hil::usb::UsbController::endpoint_set_in_buffer(usb, 1, slice);
+        usb.endpoint_set_double_buffered(1, true);
+        hil::usb::UsbController::endpoint_in_enable(usb, TransferType::Bulk, 1);
+
+        // The first packet is handed to the controller in buffer 0 and the
+        // second is copied to buffer 1 behind it.
+        hil::usb::UsbController::endpoint_resume_in(usb, 1);
+        let configin = registers.configin[1].extract();
+        assert_eq!(configin.read(CONFIGIN::BUFFER), 0);
+        assert_eq!(configin.read(CONFIGIN::SIZE), 8);
+        assert!(configin.is_set(CONFIGIN::RDY));
+        assert_eq!(registers.buffer[0].get(), 0x0101_0101_0101_0101);
+        assert_eq!(registers.buffer[8].get(), 0x0202_0202_0202_0202);
+        assert_eq!(client.sent.get(), 2);
+
+        // Once the first is acknowledged the second is sent without asking
+        // the client for anything first.
+        sent();
+        usb.handle_interrupt();
+        let configin = registers.configin[1].extract();
+        assert_eq!(configin.read(CONFIGIN::BUFFER), 1);
+        assert_eq!(configin.read(CONFIGIN::SIZE), 8);
+        assert!(configin.is_set(CONFIGIN::RDY));
+        assert_eq!(client.transmitted.get(), 1);
+        let at_packet_in = client.configin_at_packet_in.borrow();
+        assert_eq!(at_packet_in.len(), 3);
+        assert_eq!(at_packet_in[2], configin.get());
+        drop(at_packet_in);
+
+        // The client had nothing more, so nothing new is armed after the
+        // second packet.
+        sent();
+        usb.handle_interrupt();
+        assert_eq!(client.transmitted.get(), 2);
+        assert_eq!(registers.configin[1].read(CONFIGIN::BUFFER), 1);
+        assert_eq!(client.configin_at_packet_in.borrow().len(), 3);
+    }
+}
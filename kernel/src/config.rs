@@ -31,6 +31,8 @@
 //! experiments on generated Tock code have confirmed this zero cost in
 //! practice.
 
+use crate::debug::LogLevel;
+
 /// Data structure holding compile-time configuration options.
 ///
 /// To change the configuration, modify the relevant values in the `CONFIG`
@@ -80,6 +82,22 @@ pub(crate) struct Config {
     // credentials checking, e.g., whether elf2tab and tockloader are generating
     // properly formatted footers.
     pub(crate) debug_process_credentials: bool,
+
+    /// Whether the kernel should count the system calls each process makes by
+    /// system call class.
+    ///
+    /// If enabled, the counts are available through
+    /// `Process::debug_syscall_counters()`, for example to profile which
+    /// system calls an application makes most from the process console.
+    pub(crate) syscall_counters: bool,
+
+    /// The least severe level retained by the kernel log, or `None` if the
+    /// kernel log is compiled out.
+    ///
+    /// Set by the `kernel_log_*` features. If several are enabled the most
+    /// verbose one wins. Messages below this level are discarded at compile
+    /// time by `klog!`, so their format arguments are never evaluated.
+    pub(crate) kernel_log_level: Option<LogLevel>,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are
@@ -92,4 +110,16 @@ pub(crate) const CONFIG: Config = Config {
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
     debug_process_credentials: cfg!(feature = "debug_process_credentials"),
+    syscall_counters: cfg!(feature = "syscall_counters"),
+    kernel_log_level: if cfg!(feature = "kernel_log_debug") {
+        Some(LogLevel::Debug)
+    } else if cfg!(feature = "kernel_log_info") {
+        Some(LogLevel::Info)
+    } else if cfg!(feature = "kernel_log_warn") {
+        Some(LogLevel::Warn)
+    } else if cfg!(feature = "kernel_log_error") {
+        Some(LogLevel::Error)
+    } else {
+        None
+    },
 };
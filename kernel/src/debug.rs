@@ -28,6 +28,15 @@
 //!     .finalize(components::debug_queue_component_static!());
 //! ```
 //!
+//! The kernel log is also optional. It keeps the most recent `klog!` messages
+//! at or above the level selected by the `kernel_log_*` kernel features so
+//! they can be dumped later from the process console:
+//!
+//! ```ignore
+//! components::kernel_log::KernelLogComponent::new()
+//!     .finalize(components::kernel_log_component_static!(32));
+//! ```
+//!
 //! Example
 //! -------
 //!
@@ -53,13 +62,17 @@
 //! TOCK_DEBUG(0): /tock/capsules/src/sensys.rs:24: got here
 //! ```
 
-use core::cell::Cell;
+use core::cell::{Cell, UnsafeCell};
 use core::fmt::{write, Arguments, Result, Write};
+use core::ops::Range;
 use core::panic::PanicInfo;
+use core::ptr;
 use core::str;
+use core::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
 
 use crate::collections::queue::Queue;
 use crate::collections::ring_buffer::RingBuffer;
+use crate::config;
 use crate::hil;
 use crate::platform::chip::Chip;
 use crate::process::Process;
@@ -240,6 +253,57 @@ pub fn panic_blink_forever<L: hil::led::Led>(leds: &mut [&L]) -> ! {
 // panic! support routines
 ///////////////////////////////////////////////////////////////////
 
+///////////////////////////////////////////////////////////////////
+// Semihosting support
+
+/// Semihosting operation writing a NUL-terminated string to the console.
+const SEMIHOSTING_SYS_WRITE0: usize = 0x04;
+
+/// Synchronous writer to the console of a debugger or simulator, using
+/// semihosting.
+///
+/// This can be passed to `panic` and `panic_print` on simulators with no
+/// UART where the board expects one. The semihosting call comes from the
+/// architecture crate, `rv32i::semihost_command` or
+/// `cortexm::semihost_command`. A semihosting call traps into the debugger,
+/// so without a debugger or a simulator with semihosting enabled it faults.
+pub struct SemihostingDebugWriter {
+    semihost_command: unsafe fn(usize, usize, usize) -> usize,
+}
+
+impl SemihostingDebugWriter {
+    pub const fn new(semihost_command: unsafe fn(usize, usize, usize) -> usize) -> Self {
+        SemihostingDebugWriter { semihost_command }
+    }
+}
+
+impl IoWrite for SemihostingDebugWriter {
+    fn write(&mut self, buf: &[u8]) -> usize {
+        // Strings are written in chunks, each copied with a NUL terminator.
+        let mut chunk = [0; 33];
+        for part in buf.split(|&byte| byte == 0) {
+            for bytes in part.chunks(chunk.len() - 1) {
+                chunk[..bytes.len()].copy_from_slice(bytes);
+                chunk[bytes.len()] = 0;
+                unsafe {
+                    (self.semihost_command)(SEMIHOSTING_SYS_WRITE0, chunk.as_ptr() as usize, 0);
+                }
+            }
+        }
+        buf.len()
+    }
+}
+
+impl Write for SemihostingDebugWriter {
+    fn write_str(&mut self, s: &str) -> Result {
+        IoWrite::write(self, s.as_bytes());
+        Ok(())
+    }
+}
+
+// Semihosting support
+///////////////////////////////////////////////////////////////////
+
 ///////////////////////////////////////////////////////////////////
 // debug_gpio! support
 
@@ -365,6 +429,241 @@ macro_rules! debug_flush_queue {
     }};
 }
 
+///////////////////////////////////////////////////////////////////
+// klog! support
+
+/// Severity of a kernel log message, from most to least severe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    /// Fixed-width name used when dumping the log.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN ",
+            LogLevel::Info => "INFO ",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+}
+
+/// Maximum length of a single kernel log message in bytes. Longer messages
+/// are truncated.
+pub const LOG_MESSAGE_LEN: usize = 56;
+
+/// A copy of one message taken out of the kernel log.
+#[derive(Copy, Clone)]
+pub struct LogRecord {
+    level: LogLevel,
+    len: usize,
+    message: [u8; LOG_MESSAGE_LEN],
+}
+
+impl LogRecord {
+    const fn empty() -> Self {
+        LogRecord {
+            level: LogLevel::Error,
+            len: 0,
+            message: [0; LOG_MESSAGE_LEN],
+        }
+    }
+
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    pub fn message(&self) -> &str {
+        // The writer only ever truncates on a character boundary.
+        str::from_utf8(&self.message[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for LogRecord {
+    fn write_str(&mut self, s: &str) -> Result {
+        let mut n = core::cmp::min(s.len(), LOG_MESSAGE_LEN - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.message[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// One slot of the kernel log ring buffer.
+///
+/// `sequence` holds the sequence number of the stored message plus one, or
+/// zero while the slot is empty or being rewritten.
+pub struct LogEntry {
+    sequence: AtomicUsize,
+    record: UnsafeCell<LogRecord>,
+}
+
+impl LogEntry {
+    pub const fn new() -> Self {
+        LogEntry {
+            sequence: AtomicUsize::new(0),
+            record: UnsafeCell::new(LogRecord::empty()),
+        }
+    }
+}
+
+// Writes to `record` are published through `sequence`, see `KernelLog`.
+unsafe impl Sync for LogEntry {}
+
+/// Fixed-size ring buffer of recent kernel log messages.
+///
+/// Messages are numbered by a sequence counter and stored in slot `sequence %
+/// entries.len()`, overwriting the oldest message once the buffer is full.
+/// Writing only uses atomic loads and stores, so it is safe from interrupt
+/// context and on cores without compare-and-swap. A slot is marked invalid
+/// while its message is copied in and a reader rejects any copy whose slot
+/// changed underneath it. If an interrupt logs between another writer reading
+/// and bumping the counter, both use the same slot and one message is lost.
+pub struct KernelLog {
+    entries: &'static [LogEntry],
+    threshold: Option<LogLevel>,
+    next: AtomicUsize,
+    start: AtomicUsize,
+}
+
+impl KernelLog {
+    /// Create a kernel log retaining messages at or above the level selected
+    /// by the `kernel_log_*` features.
+    pub fn new(entries: &'static [LogEntry]) -> Self {
+        Self::with_threshold(entries, config::CONFIG.kernel_log_level)
+    }
+
+    pub(crate) fn with_threshold(
+        entries: &'static [LogEntry],
+        threshold: Option<LogLevel>,
+    ) -> Self {
+        KernelLog {
+            entries,
+            threshold,
+            next: AtomicUsize::new(0),
+            start: AtomicUsize::new(0),
+        }
+    }
+
+    /// Append a message, dropping it if `level` is below the threshold.
+    pub fn log(&self, level: LogLevel, args: Arguments) {
+        if self.entries.is_empty() || !log_level_retained(level, self.threshold) {
+            return;
+        }
+
+        let sequence = self.next.load(Ordering::Relaxed);
+        self.next.store(sequence.wrapping_add(1), Ordering::Relaxed);
+
+        let mut record = LogRecord::empty();
+        record.level = level;
+        let _ = write(&mut record, args);
+
+        let entry = &self.entries[sequence % self.entries.len()];
+        entry.sequence.store(0, Ordering::Relaxed);
+        compiler_fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(entry.record.get(), record) };
+        compiler_fence(Ordering::SeqCst);
+        entry
+            .sequence
+            .store(sequence.wrapping_add(1), Ordering::Relaxed);
+    }
+
+    /// The sequence numbers of the messages currently retained, oldest
+    /// first.
+    pub fn sequences(&self) -> Range<usize> {
+        let end = self.next.load(Ordering::Relaxed);
+        let oldest = end.saturating_sub(self.entries.len());
+        core::cmp::max(oldest, self.start.load(Ordering::Relaxed))..end
+    }
+
+    /// Copy out the message with the given sequence number, or `None` if it
+    /// has been overwritten or is still being written.
+    pub fn record(&self, sequence: usize) -> Option<LogRecord> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let entry = &self.entries[sequence % self.entries.len()];
+        let tag = sequence.wrapping_add(1);
+        if entry.sequence.load(Ordering::Relaxed) != tag {
+            return None;
+        }
+        compiler_fence(Ordering::SeqCst);
+        let record = unsafe { ptr::read_volatile(entry.record.get()) };
+        compiler_fence(Ordering::SeqCst);
+        if entry.sequence.load(Ordering::Relaxed) != tag {
+            return None;
+        }
+        Some(record)
+    }
+
+    /// Discard all messages logged so far.
+    pub fn clear(&self) {
+        self.start
+            .store(self.next.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// Whether a message at `level` is kept by a log whose least severe retained
+/// level is `threshold`.
+pub const fn log_level_retained(level: LogLevel, threshold: Option<LogLevel>) -> bool {
+    match threshold {
+        Some(threshold) => level as usize <= threshold as usize,
+        None => false,
+    }
+}
+
+/// Whether messages at `level` are compiled into this kernel. Used by `klog!`
+/// so that disabled levels cost nothing.
+#[inline(always)]
+pub const fn kernel_log_enabled(level: LogLevel) -> bool {
+    log_level_retained(level, config::CONFIG.kernel_log_level)
+}
+
+static mut KERNEL_LOG: Option<&'static KernelLog> = None;
+
+/// Function used by board main.rs to set a reference to the kernel log.
+pub unsafe fn set_kernel_log(log: &'static KernelLog) {
+    KERNEL_LOG = Some(log);
+}
+
+/// The kernel log set by the board, if any.
+pub fn kernel_log() -> Option<&'static KernelLog> {
+    unsafe { KERNEL_LOG }
+}
+
+pub fn kernel_log_fmt(level: LogLevel, args: Arguments) {
+    kernel_log().map(|log| log.log(level, args));
+}
+
+/// This macro appends a message to the kernel log, which can later be dumped
+/// from the process console. The first argument is a `LogLevel`; messages
+/// below the level selected by the `kernel_log_*` features of the kernel crate
+/// are compiled out.
+///
+/// ```ignore
+/// kernel::klog!(kernel::debug::LogLevel::Warn, "spi: timeout after {}ms", ms);
+/// ```
+#[macro_export]
+macro_rules! klog {
+    ($level:expr, $msg:expr $(,)?) => ({
+        if $crate::debug::kernel_log_enabled($level) {
+            $crate::debug::kernel_log_fmt($level, format_args!($msg))
+        }
+    });
+    ($level:expr, $fmt:expr, $($arg:tt)+) => ({
+        if $crate::debug::kernel_log_enabled($level) {
+            $crate::debug::kernel_log_fmt($level, format_args!($fmt, $($arg)+))
+        }
+    });
+}
+
 ///////////////////////////////////////////////////////////////////
 // debug! and debug_verbose! support
 
@@ -745,3 +1044,63 @@ pub unsafe fn flush<W: Write + IoWrite>(writer: &mut W) {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::cell::RefCell;
+    use std::string::String;
+
+    std::thread_local! {
+        static CONSOLE: RefCell<String> = RefCell::new(String::new());
+    }
+
+    unsafe fn semihost_command(command: usize, arg0: usize, _arg1: usize) -> usize {
+        assert_eq!(command, SEMIHOSTING_SYS_WRITE0);
+        let string = core::ffi::CStr::from_ptr(arg0 as *const core::ffi::c_char);
+        CONSOLE.with(|console| console.borrow_mut().push_str(string.to_str().unwrap()));
+        0
+    }
+
+    #[test]
+    fn semihosting_writes_whole_strings() {
+        let mut writer = SemihostingDebugWriter::new(semihost_command);
+        let message = "---| Semihosting output longer than one chunk |---";
+        let _ = write!(writer, "{}\r\n", message);
+        CONSOLE.with(|console| assert_eq!(*console.borrow(), std::format!("{}\r\n", message)));
+    }
+
+    #[test]
+    fn kernel_log_retains_levels_above_threshold() {
+        let entries: &'static [LogEntry] = std::boxed::Box::leak(
+            std::vec![LogEntry::new(), LogEntry::new(), LogEntry::new()].into_boxed_slice(),
+        );
+        let log = KernelLog::with_threshold(entries, Some(LogLevel::Warn));
+
+        log.log(LogLevel::Debug, format_args!("dropped {}", 1));
+        log.log(LogLevel::Error, format_args!("radio lost sync"));
+        log.log(LogLevel::Info, format_args!("dropped {}", 2));
+        log.log(LogLevel::Warn, format_args!("retry {} of {}", 2, 3));
+
+        let dump: std::vec::Vec<_> = log
+            .sequences()
+            .filter_map(|sequence| log.record(sequence))
+            .map(|record| std::format!("{} {}", record.level().as_str(), record.message()))
+            .collect();
+        assert_eq!(dump, ["ERROR radio lost sync", "WARN  retry 2 of 3"]);
+
+        log.clear();
+        assert!(log.sequences().is_empty());
+        log.log(
+            LogLevel::Error,
+            format_args!("{}", "x".repeat(2 * LOG_MESSAGE_LEN)),
+        );
+        let sequence = log.sequences().start;
+        assert_eq!(
+            log.record(sequence).unwrap().message().len(),
+            LOG_MESSAGE_LEN
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for battery charger ICs.
+//!
+//! The setters are split-phase: each returns `Ok(())` if the request was
+//! started, and `command_complete()` is called once the charger has been
+//! updated. Only one request can be outstanding at a time.
+
+use crate::ErrorCode;
+
+/// Why charging stopped, as reported by the charger.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChargerFault {
+    /// The battery finished charging.
+    NormalTermination,
+    /// The input supply is over voltage or too weak.
+    InputFault,
+    /// The charger is too hot.
+    ThermalShutdown,
+    /// Charging took longer than the charger's safety timer.
+    SafetyTimer,
+    /// The battery is over voltage.
+    BatteryFault,
+}
+
+pub trait BatteryCharger<'a> {
+    /// Set the client for completions and faults.
+    fn set_client(&self, client: &'a dyn BatteryChargerClient);
+
+    /// Limit the current drawn from the input supply, in mA. Chargers
+    /// round down to the closest limit they support, and return `INVAL` if
+    /// `ma` is below the lowest.
+    fn set_input_current_limit_ma(&self, ma: u16) -> Result<(), ErrorCode>;
+
+    /// Set the fast charge current, in mA. Chargers round down to the
+    /// closest current they support, and return `INVAL` if `ma` is out of
+    /// range.
+    fn set_charge_current_ma(&self, ma: u16) -> Result<(), ErrorCode>;
+
+    /// Start charging the battery.
+    fn enable_charging(&self) -> Result<(), ErrorCode>;
+
+    /// Stop charging the battery.
+    fn disable_charging(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait BatteryChargerClient {
+    /// Called when a request to the charger has completed.
+    fn command_complete(&self, result: Result<(), ErrorCode>);
+
+    /// Called when the charger reports that charging stopped, either
+    /// normally or because of `fault`.
+    fn fault(&self, fault: ChargerFault);
+}
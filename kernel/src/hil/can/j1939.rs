@@ -0,0 +1,79 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! SAE J1939 frames.
+//!
+//! J1939 packs the priority, the Parameter Group Number (PGN) and the source
+//! and destination addresses of a message in the 29-bit extended identifier
+//! of a CAN frame:
+//!
+//! ```text
+//! 28   26 25  24  23      16 15      8 7       0
+//! +------+---+----+----------+---------+---------+
+//! | prio | R | DP |    PF    |   PS    |   SA    |
+//! +------+---+----+----------+---------+---------+
+//! ```
+//!
+//! When the PDU format (PF) is below 240 the message is addressed (PDU1) and
+//! the PDU specific field (PS) is the destination address. Otherwise it is
+//! broadcast (PDU2) and PS is part of the PGN.
+
+/// The address of every node, for broadcast messages.
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+
+/// The source address of a node that has not claimed an address.
+pub const NULL_ADDRESS: u8 = 0xFE;
+
+/// The longest message the transport protocol can carry: 255 packets of 7
+/// bytes.
+pub const MAX_MESSAGE_LEN: usize = 1785;
+
+/// The fields of a J1939 frame identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct J1939Frame {
+    /// 0 is the highest priority, 7 the lowest.
+    pub priority: u8,
+    /// 18-bit Parameter Group Number. For PDU1 messages its low byte is 0.
+    pub pgn: u32,
+    pub source: u8,
+    /// The destination of a PDU1 message, `GLOBAL_ADDRESS` for PDU2.
+    pub destination: u8,
+}
+
+impl J1939Frame {
+    pub fn new(priority: u8, pgn: u32, source: u8, destination: u8) -> J1939Frame {
+        let pgn = pgn & 0x3FFFF;
+        J1939Frame {
+            priority: priority & 0x7,
+            pgn: if is_pdu1(pgn) { pgn & 0x3FF00 } else { pgn },
+            source,
+            destination: if is_pdu1(pgn) {
+                destination
+            } else {
+                GLOBAL_ADDRESS
+            },
+        }
+    }
+
+    /// Decode a 29-bit extended identifier.
+    pub fn from_id(id: u32) -> J1939Frame {
+        let pgn = (id >> 8) & 0x3FFFF;
+        J1939Frame::new((id >> 26) as u8, pgn, id as u8, pgn as u8)
+    }
+
+    /// The 29-bit extended identifier of the frame.
+    pub fn id(&self) -> u32 {
+        let pgn = if is_pdu1(self.pgn) {
+            (self.pgn & 0x3FF00) | self.destination as u32
+        } else {
+            self.pgn & 0x3FFFF
+        };
+        ((self.priority as u32 & 0x7) << 26) | (pgn << 8) | self.source as u32
+    }
+}
+
+/// Whether messages of `pgn` are addressed to a single node.
+pub fn is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xFF < 240
+}
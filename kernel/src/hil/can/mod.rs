@@ -37,10 +37,15 @@
 //! when a message was received, when the receiving process was aborted
 //! and anytime an error occurs.
 //!
+//! The `j1939` module defines the frame format of the SAE J1939 protocol,
+//! which is carried in extended CAN identifiers.
+//!
 
 use crate::ErrorCode;
 use core::cmp;
 
+pub mod j1939;
+
 pub const STANDARD_CAN_PACKET_SIZE: usize = 8;
 pub const FD_CAN_PACKET_SIZE: usize = 64;
 
@@ -10,4 +10,12 @@ use crate::ErrorCode;
 pub trait DacChannel {
     /// Set the DAC output value.
     fn set_value(&self, value: usize) -> Result<(), ErrorCode>;
+
+    /// Set the DAC output value, and store it in non-volatile memory as the
+    /// output value at power up.
+    ///
+    /// Returns `NOSUPPORT` if the DAC has no non-volatile memory.
+    fn set_power_on_value(&self, _value: usize) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for sponge-based hash functions such as SHA-3.
+//!
+//! A sponge first absorbs the message, in as many `absorb` calls as needed,
+//! and the output is then squeezed out of it, in as many `squeeze` calls as
+//! needed. The fixed-length functions (SHA3-256 and SHA3-512) produce at most
+//! their digest size of output; the extendable-output functions (SHAKE128 and
+//! SHAKE256) produce as much as is asked for. Once squeezing has started,
+//! no more data can be absorbed.
+
+use crate::ErrorCode;
+
+/// The SHA-3 family functions from FIPS 202.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sha3Mode {
+    Sha3_256,
+    Sha3_512,
+    Shake128,
+    Shake256,
+}
+
+impl Sha3Mode {
+    /// The digest size in bytes, or `None` for an extendable-output function.
+    pub fn digest_len(&self) -> Option<usize> {
+        match self {
+            Sha3Mode::Sha3_256 => Some(32),
+            Sha3Mode::Sha3_512 => Some(64),
+            Sha3Mode::Shake128 | Sha3Mode::Shake256 => None,
+        }
+    }
+}
+
+/// Implement this trait and use `set_client()` in order to receive callbacks
+/// from a sponge.
+pub trait SpongeClient {
+    /// Called when the data passed to `absorb` has been absorbed. `data` is
+    /// the buffer passed to `absorb`. Valid `ErrorCode` values are:
+    ///  - CANCEL: the operation was cancelled by a call to `finish`.
+    ///  - FAIL: an internal failure.
+    fn absorb_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]);
+
+    /// Called when the output asked for by `squeeze` is available in
+    /// `output`, the buffer passed to `squeeze`. If `result` is `Err`, the
+    /// contents of `output` are undefined. Valid `ErrorCode` values are:
+    ///  - CANCEL: the operation was cancelled by a call to `finish`.
+    ///  - FAIL: an internal failure.
+    fn squeeze_done(&self, result: Result<(), ErrorCode>, output: &'static mut [u8]);
+}
+
+/// A SHA-3 engine.
+///
+/// An operation runs from `start_sha3` to `finish`; only one operation can
+/// be in progress at a time.
+pub trait Sha3<'a> {
+    /// Set the client to receive `absorb_done` and `squeeze_done` callbacks.
+    fn set_client(&self, client: &'a dyn SpongeClient);
+
+    /// Start hashing a new message with `mode`. Returns `BUSY` if an
+    /// operation is already in progress.
+    fn start_sha3(&self, mode: Sha3Mode) -> Result<(), ErrorCode>;
+
+    /// Absorb the first `len` bytes of `data` into the sponge. On success
+    /// `absorb_done` is called. Valid `ErrorCode` values are:
+    ///  - OFF: no operation is in progress.
+    ///  - BUSY: an `absorb` or `squeeze` is outstanding.
+    ///  - INVAL: squeezing has already started.
+    ///  - SIZE: `len` is larger than `data`.
+    fn absorb(
+        &self,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Squeeze the next `len` bytes of output into `output`. The first call
+    /// ends the absorbing phase. On success `squeeze_done` is called. Valid
+    /// `ErrorCode` values are:
+    ///  - OFF: no operation is in progress.
+    ///  - BUSY: an `absorb` or `squeeze` is outstanding.
+    ///  - SIZE: `len` is larger than `output`, or would take the output past
+    ///  the digest size of a fixed-length function.
+    fn squeeze(
+        &self,
+        output: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// End the operation and clear the state of the sponge. An outstanding
+    /// `absorb` or `squeeze` completes with `CANCEL`.
+    fn finish(&self);
+}
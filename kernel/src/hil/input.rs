@@ -0,0 +1,39 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for button input devices.
+//!
+//! This is for sets of buttons that are not individual GPIO pins, such as
+//! buttons read through a resistor ladder on an ADC or a keypad controller.
+//! Buttons are identified by their index, from 0 to `count() - 1`.
+
+use crate::ErrorCode;
+
+pub trait Buttons<'a> {
+    /// Set the client for button events.
+    fn set_client(&self, client: &'a dyn ButtonClient);
+
+    /// Number of buttons.
+    fn count(&self) -> usize;
+
+    /// Start reporting button events. Returns `ALREADY` if already
+    /// enabled.
+    fn enable(&self) -> Result<(), ErrorCode>;
+
+    /// Stop reporting button events. Returns `ALREADY` if already
+    /// disabled.
+    fn disable(&self) -> Result<(), ErrorCode>;
+
+    /// Whether button `id` is currently pressed. Buttons are only tracked
+    /// while enabled, and are reported as released otherwise.
+    fn is_pressed(&self, id: u8) -> bool;
+}
+
+pub trait ButtonClient {
+    /// Called when button `id` is pressed.
+    fn button_pressed(&self, id: u8);
+
+    /// Called when button `id` is released.
+    fn button_released(&self, id: u8);
+}
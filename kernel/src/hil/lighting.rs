@@ -0,0 +1,141 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interfaces for lighting control buses.
+//!
+//! DALI (IEC 62386) connects a bus master to up to 64 control gear, such as
+//! LED drivers and ballasts. The master sends forward frames of an address
+//! and a command, and control gear answers queries with a backward frame of
+//! one byte. A query that no control gear answers means "no".
+//!
+//! Every operation is split-phase and completes with a call to the
+//! [DaliClient]. Only one operation can be outstanding at a time.
+
+use crate::ErrorCode;
+
+/// The control gear a forward frame is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DaliAddress {
+    /// A single control gear, by its short address, 0 to 63.
+    Short(u8),
+    /// The members of a group, 0 to 15.
+    Group(u8),
+    /// All control gear on the bus.
+    Broadcast,
+}
+
+impl DaliAddress {
+    /// The address byte of a forward frame. The selector bit, the lowest,
+    /// is clear for direct arc power control and set for commands.
+    ///
+    /// Returns `None` if the short address or group is out of range.
+    pub fn address_byte(self, command: bool) -> Option<u8> {
+        let address = match self {
+            DaliAddress::Short(address) if address < 64 => address << 1,
+            DaliAddress::Group(group) if group < 16 => 0x80 | group << 1,
+            DaliAddress::Broadcast => 0xFE,
+            _ => return None,
+        };
+        Some(address | command as u8)
+    }
+}
+
+/// Commands to control gear, which have no answer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DaliCommand {
+    /// Direct arc power control: fade to a level, 0 (off) to 254. 255 stops
+    /// a running fade.
+    Dapc(u8),
+    /// Switch off without fading.
+    Off,
+    /// Fade up for 200 ms at the fade rate.
+    Up,
+    /// Fade down for 200 ms at the fade rate.
+    Down,
+    /// Go to the maximum level without fading.
+    RecallMaxLevel,
+    /// Go to the minimum level without fading.
+    RecallMinLevel,
+}
+
+impl DaliCommand {
+    /// The second byte of the forward frame, and whether it is a command
+    /// rather than an arc power level.
+    pub fn opcode(self) -> (u8, bool) {
+        match self {
+            DaliCommand::Dapc(level) => (level, false),
+            DaliCommand::Off => (0x00, true),
+            DaliCommand::Up => (0x01, true),
+            DaliCommand::Down => (0x02, true),
+            DaliCommand::RecallMaxLevel => (0x05, true),
+            DaliCommand::RecallMinLevel => (0x06, true),
+        }
+    }
+}
+
+/// Queries to control gear, answered with a backward frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DaliQuery {
+    /// The status byte.
+    Status,
+    /// Whether control gear is present. Control gear answers 0xFF.
+    ControlGearPresent,
+    /// Whether a lamp has failed. Control gear answers 0xFF.
+    LampFailure,
+    /// The current arc power level.
+    ActualLevel,
+    /// The maximum level.
+    MaxLevel,
+    /// The minimum level.
+    MinLevel,
+}
+
+impl DaliQuery {
+    /// The second byte of the forward frame.
+    pub fn opcode(self) -> u8 {
+        match self {
+            DaliQuery::Status => 0x90,
+            DaliQuery::ControlGearPresent => 0x91,
+            DaliQuery::LampFailure => 0x92,
+            DaliQuery::ActualLevel => 0xA0,
+            DaliQuery::MaxLevel => 0xA1,
+            DaliQuery::MinLevel => 0xA2,
+        }
+    }
+
+    /// The query with opcode `opcode`, if it is supported.
+    pub fn from_opcode(opcode: u8) -> Option<DaliQuery> {
+        match opcode {
+            0x90 => Some(DaliQuery::Status),
+            0x91 => Some(DaliQuery::ControlGearPresent),
+            0x92 => Some(DaliQuery::LampFailure),
+            0xA0 => Some(DaliQuery::ActualLevel),
+            0xA1 => Some(DaliQuery::MaxLevel),
+            0xA2 => Some(DaliQuery::MinLevel),
+            _ => None,
+        }
+    }
+}
+
+pub trait DaliMaster<'a> {
+    fn set_client(&self, client: &'a dyn DaliClient);
+
+    /// Send a command. Returns `INVAL` if the address is out of range and
+    /// `BUSY` if an operation is in progress.
+    fn send_command(&self, address: DaliAddress, command: DaliCommand) -> Result<(), ErrorCode>;
+
+    /// Send a query and wait for the answer. Returns `INVAL` if the address
+    /// is out of range and `BUSY` if an operation is in progress.
+    fn query(&self, address: DaliAddress, query: DaliQuery) -> Result<(), ErrorCode>;
+}
+
+pub trait DaliClient {
+    /// A command was sent, and the bus is ready for the next frame.
+    fn command_done(&self, result: Result<(), ErrorCode>);
+
+    /// A query completed with the answer, or `None` if no control gear
+    /// answered. A garbled answer, for example from several control gear
+    /// answering at once, is reported as `FAIL`.
+    fn query_done(&self, result: Result<Option<u8>, ErrorCode>);
+}
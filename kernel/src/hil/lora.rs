@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for LoRaWAN end devices.
+//!
+//! A LoRaWAN end device first joins a network, which gives it an address
+//! and the session keys. It then sends uplinks on an application port, and
+//! after each one listens for a downlink in two receive windows (Class A).
+//!
+//! The operations are split-phase, and only one of `join` and `send` can be
+//! outstanding at a time.
+
+use crate::ErrorCode;
+
+pub trait LoraWan<'a> {
+    fn set_client(&self, client: &'a dyn LoraWanClient);
+
+    /// Join the network, with over-the-air activation. `join_done` is
+    /// called once the network accepted the join, or did not answer.
+    ///
+    /// Returns `OFF` if the radio is not set up, and `BUSY` if a join or a
+    /// send is in progress.
+    fn join(&self) -> Result<(), ErrorCode>;
+
+    /// Send the first `len` bytes of `payload` as an unconfirmed uplink, on
+    /// application `port` (1 to 223). `send_done` is called after the
+    /// receive windows of the uplink closed.
+    ///
+    /// Returns `OFF` if the device has not joined a network, `BUSY` if a
+    /// join or a send is in progress, `INVAL` for an invalid port and
+    /// `SIZE` if the payload does not fit in a frame.
+    fn send(
+        &self,
+        port: u8,
+        payload: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Provide the buffer for the application payload of the next downlink,
+    /// which is returned through `received`.
+    ///
+    /// Returns `ALREADY` if a buffer was already provided.
+    fn receive(&self, buffer: &'static mut [u8]) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait LoraWanClient {
+    /// Called when a join completes. `NOACK` means no network accepted it.
+    fn join_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when a send completes, with the payload buffer.
+    fn send_done(&self, payload: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// Called with the first `len` bytes of `buffer` set to the application
+    /// payload of a downlink received on `port`.
+    fn received(&self, port: u8, buffer: &'static mut [u8], len: usize);
+}
@@ -0,0 +1,41 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for message authentication codes built on a sponge.
+//!
+//! KMAC (NIST SP 800-185) is keyed cSHAKE. Once started with `start_kmac`,
+//! the message is absorbed and the tag squeezed out with the
+//! [Sha3](crate::hil::hash::Sha3) operations.
+
+use crate::hil::hash::Sha3;
+use crate::ErrorCode;
+
+/// The KMAC variants from NIST SP 800-185.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KmacMode {
+    Kmac128,
+    Kmac256,
+}
+
+/// A KMAC engine.
+pub trait Kmac<'a>: Sha3<'a> {
+    /// Start computing the KMAC of a new message with `key` and the
+    /// customization string `customization`.
+    ///
+    /// `output_len` is the length in bytes of the tag that will be squeezed
+    /// out, which KMAC binds into the result. An `output_len` of zero
+    /// selects KMACXOF, which produces as much output as is asked for.
+    ///
+    /// Valid `ErrorCode` values are:
+    ///  - BUSY: an operation is already in progress.
+    ///  - NOSUPPORT: the key length is not supported by the engine.
+    ///  - SIZE: `customization` is too long for the engine.
+    fn start_kmac(
+        &self,
+        mode: KmacMode,
+        key: &[u8],
+        customization: &[u8],
+        output_len: usize,
+    ) -> Result<(), ErrorCode>;
+}
@@ -6,6 +6,7 @@
 
 pub mod adc;
 pub mod analog_comparator;
+pub mod battery_charger;
 pub mod ble_advertising;
 pub mod bus8080;
 pub mod buzzer;
@@ -18,12 +19,18 @@ pub mod entropy;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
+pub mod hash;
 pub mod hasher;
 pub mod i2c;
+pub mod input;
 pub mod kv_system;
 pub mod led;
+pub mod lighting;
 pub mod log;
+pub mod lora;
+pub mod mac;
 pub mod nonvolatile_storage;
+pub mod onewire;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod radio;
@@ -38,6 +45,7 @@ pub mod touch;
 pub mod uart;
 pub mod usb;
 pub mod usb_hid;
+pub mod usb_pd;
 
 /// Shared interface for configuring components.
 pub trait Controller {
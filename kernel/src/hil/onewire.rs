@@ -0,0 +1,64 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for 1-Wire bus masters.
+//!
+//! A transaction starts with a reset pulse, to which every device on the bus
+//! answers with a presence pulse, followed by a ROM command selecting the
+//! devices and the command for them. Bytes are sent least significant bit
+//! first.
+//!
+//! Every operation is split-phase and completes with a call to the
+//! [Client]. Only one operation can be outstanding at a time.
+
+use crate::ErrorCode;
+
+/// The result of one step of the ROM search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Triplet {
+    /// The bit read from the devices still taking part in the search.
+    pub id_bit: bool,
+    /// The complement of the bit, read from the same devices.
+    pub complement_bit: bool,
+    /// The bit written, which the devices that do not have it leave the
+    /// search on.
+    pub direction: bool,
+}
+
+pub trait OneWire<'a> {
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Send a reset pulse and listen for a presence pulse.
+    fn reset(&self) -> Result<(), ErrorCode>;
+
+    /// Write the first `len` bytes of `buffer`.
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Read `len` bytes into `buffer`.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Run one step of the ROM search: read a bit and its complement, then
+    /// write the bit the devices agree on. If they disagree, the bit written
+    /// is `direction`.
+    fn triplet(&self, direction: bool) -> Result<(), ErrorCode>;
+}
+
+pub trait Client {
+    /// A reset completed, and a device answered if `presence` is true.
+    fn reset_done(&self, result: Result<bool, ErrorCode>);
+
+    fn write_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    fn read_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    fn triplet_done(&self, result: Result<Triplet, ErrorCode>);
+}
@@ -10,6 +10,13 @@ use crate::errorcode::ErrorCode;
 pub trait TemperatureDriver<'a> {
     fn set_client(&self, client: &'a dyn TemperatureClient);
     fn read_temperature(&self) -> Result<(), ErrorCode>;
+
+    /// The resistance of a resistive sensor, such as a thermistor, measured
+    /// by the last reading, in ohms. This is a diagnostic for the wiring and
+    /// calibration of the sensor.
+    fn resistance_ohms(&self) -> Result<u32, ErrorCode> {
+        Err(ErrorCode::NODEVICE)
+    }
 }
 
 /// Client for receiving temperature readings.
@@ -78,6 +85,42 @@ pub trait AirQualityDriver<'a> {
     ///           operation or initialisation/calibration.
     /// - `NOSUPPORT`: Indicates that this data type isn't supported.
     fn read_tvoc(&self) -> Result<(), ErrorCode>;
+
+    /// Read the VOC index from the sensor.
+    /// This will trigger the `AirQualityClient` `voc_index_available()`
+    /// callback when the data is ready.
+    ///
+    /// This function might return the following errors:
+    /// - `BUSY`: Indicates that the hardware is busy with an existing
+    ///           operation or initialisation/calibration.
+    /// - `NOSUPPORT`: Indicates that this data type isn't supported.
+    fn read_voc_index(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Read the NOx index from the sensor.
+    /// This will trigger the `AirQualityClient` `nox_index_available()`
+    /// callback when the data is ready.
+    ///
+    /// This function might return the following errors:
+    /// - `BUSY`: Indicates that the hardware is busy with an existing
+    ///           operation or initialisation/calibration.
+    /// - `NOSUPPORT`: Indicates that this data type isn't supported.
+    fn read_nox_index(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Read the air quality index (AQI) from the sensor.
+    /// This will trigger the `AirQualityClient` `aqi_available()`
+    /// callback when the data is ready.
+    ///
+    /// This function might return the following errors:
+    /// - `BUSY`: Indicates that the hardware is busy with an existing
+    ///           operation or initialisation/calibration.
+    /// - `NOSUPPORT`: Indicates that this data type isn't supported.
+    fn read_aqi(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Client for receiving Air Quality readings
@@ -96,6 +139,24 @@ pub trait AirQualityClient {
     /// - `value`: will contain the latest TVOC reading in ppb. An example value
     ///            might be `0`.
     fn tvoc_data_available(&self, value: Result<u32, ErrorCode>);
+
+    /// Called when a VOC index reading has completed.
+    ///
+    /// - `value`: will contain the VOC index, from 1 to 500. 100 is the
+    ///            average air of the last day, more VOCs give a higher index.
+    fn voc_index_available(&self, _value: Result<u32, ErrorCode>) {}
+
+    /// Called when a NOx index reading has completed.
+    ///
+    /// - `value`: will contain the NOx index, from 1 to 500. 1 is the
+    ///            average air of the last day, more NOx give a higher index.
+    fn nox_index_available(&self, _value: Result<u32, ErrorCode>) {}
+
+    /// Called when an air quality index (AQI) reading has completed.
+    ///
+    /// - `value`: will contain the AQI of the German Federal Environmental
+    ///            Agency (UBA), from 1 (excellent) to 5 (unhealthy).
+    fn aqi_available(&self, _value: Result<u32, ErrorCode>) {}
 }
 
 /// A basic interface for a proximity sensor
@@ -135,6 +196,25 @@ pub trait AmbientLight<'a> {
     fn read_light_intensity(&self) -> Result<(), ErrorCode> {
         Err(ErrorCode::NODEVICE)
     }
+
+    /// Get a single reading of the raw counts of the photodiodes of a
+    /// sensor with a broadband and an infrared channel.
+    fn read_raw_channels(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Get a single reading of the ambient light intensity in millilux, for
+    /// sensors that resolve less than one lux.
+    fn read_light_intensity_millilux(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Select continuous conversions, where the sensor keeps converting and
+    /// readings return the latest conversion, or a single conversion for
+    /// each reading, and the integration time of a conversion.
+    fn set_conversion(&self, _continuous: bool, _integration_ms: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Client for receiving light intensity readings.
@@ -143,6 +223,17 @@ pub trait AmbientLightClient {
     ///
     /// - `lux`: the most recently read ambient light reading in lux (lx).
     fn callback(&self, lux: usize);
+
+    /// Called when a raw reading has completed.
+    ///
+    /// - `broadband`: the counts of the visible and infrared channel.
+    /// - `infrared`: the counts of the infrared channel.
+    fn raw_callback(&self, _broadband: usize, _infrared: usize) {}
+
+    /// Called when a reading in millilux has completed.
+    ///
+    /// - `millilux`: the ambient light reading in thousandths of a lux.
+    fn millilux_callback(&self, _millilux: u32) {}
 }
 
 /// A basic interface for a 9-DOF compatible chip.
@@ -170,11 +261,50 @@ pub trait NineDof<'a> {
         Err(ErrorCode::NODEVICE)
     }
 
+    /// Measure the magnetometer's hard-iron offset while the device is
+    /// turned through every orientation, and subtract it from later
+    /// magnetometer readings.
+    ///
+    /// On completion the client callback is called with the offset on each
+    /// axis, in the same units as magnetometer readings.
+    fn calibrate_magnetometer(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NODEVICE)
+    }
+
     /// Get a single instantaneous reading from the gyroscope of the rotation
     /// around all three axes.
     fn read_gyroscope(&self) -> Result<(), ErrorCode> {
         Err(ErrorCode::NODEVICE)
     }
+
+    /// Run the gyroscope's built-in self-test.
+    ///
+    /// On completion the client callback is called with `1` as the first
+    /// argument if the self-test passed and `0` if it failed. Sensors without
+    /// a self-test return `NODEVICE`.
+    fn run_gyroscope_self_test(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NODEVICE)
+    }
+
+    /// Measure the gyroscope's zero-rate offset by averaging `samples`
+    /// readings taken at rest. The device must be kept still until the
+    /// calibration completes.
+    ///
+    /// The offset is subtracted from all subsequent gyroscope readings. On
+    /// completion the client callback is called with the measured offset
+    /// for the X, Y and Z axes, in the same units as gyroscope readings.
+    /// Sensors that do not support calibration return `NODEVICE`.
+    fn calibrate_gyroscope(&self, _samples: usize) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NODEVICE)
+    }
+
+    /// Report gyroscope readings below `threshold` on an axis as zero on
+    /// that axis, in the same units as gyroscope readings. This hides the
+    /// noise of the zero-rate level when the device is at rest. A
+    /// `threshold` of 0 disables the dead band.
+    fn set_gyroscope_dead_band(&self, _threshold: usize) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NODEVICE)
+    }
 }
 
 /// Client for receiving done events from the chip.
@@ -184,6 +314,66 @@ pub trait NineDofClient {
     fn callback(&self, arg1: usize, arg2: usize, arg3: usize);
 }
 
+/// A basic interface for a 3-axis magnetometer.
+pub trait Magnetometer<'a> {
+    /// Set the client to be notified when a reading has completed.
+    fn set_client(&self, client: &'a dyn MagnetometerClient);
+
+    /// Get a single instantaneous reading of the magnetic field along the
+    /// X, Y and Z axes.
+    fn read_field_nt(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client for receiving magnetometer readings.
+pub trait MagnetometerClient {
+    /// Called when a magnetic field reading has completed.
+    ///
+    /// - `value`: the field strength along the X, Y and Z axes in nanotesla
+    /// (nT), or Err on failure.
+    fn callback(&self, value: Result<(i32, i32, i32), ErrorCode>);
+}
+
+/// A basic interface for a soil moisture sensor.
+pub trait SoilMoisture<'a> {
+    /// Set the client to be notified when a reading has completed.
+    fn set_client(&self, client: &'a dyn SoilMoistureClient);
+
+    /// Get a single reading of the soil moisture.
+    fn get_moisture_percent(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client for receiving soil moisture readings.
+pub trait SoilMoistureClient {
+    /// Called when a soil moisture reading has completed.
+    ///
+    /// - `value`: the moisture in percent, from 0 (the sensor's dry
+    /// calibration point) to 100 (its wet calibration point), or Err on
+    /// failure.
+    fn callback(&self, value: Result<usize, ErrorCode>);
+}
+
+/// A basic interface for a motion detector, such as a passive infrared (PIR)
+/// sensor.
+pub trait MotionDetector<'a> {
+    /// Set the client to be notified when motion starts or stops.
+    fn set_client(&self, client: &'a dyn MotionDetectorClient);
+
+    /// Start reporting motion events to the client.
+    fn enable(&self) -> Result<(), ErrorCode>;
+
+    /// Stop reporting motion events to the client.
+    fn disable(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client for receiving motion events.
+pub trait MotionDetectorClient {
+    /// Called when motion is detected after a period without motion.
+    fn motion_detected(&self);
+
+    /// Called when no motion has been detected for the detector's hold time.
+    fn motion_stopped(&self);
+}
+
 /// Basic Interface for Sound Pressure
 pub trait SoundPressure<'a> {
     /// Read the sound pressure level
@@ -211,3 +401,120 @@ pub trait SoundPressureClient {
     /// Signals the sound pressure in dB
     fn callback(&self, ret: Result<(), ErrorCode>, sound_pressure: u8);
 }
+
+/// A measurement from an RGBC color sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorReading {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub clear: u16,
+    /// Analog gain the channels were measured with, as a multiplier.
+    pub gain: u16,
+}
+
+/// Basic interface for RGBC color sensors
+pub trait Color<'a> {
+    /// Set the client to be notified when a measurement completes.
+    fn set_client(&self, client: &'a dyn ColorClient);
+
+    /// Start a measurement of the red, green, blue and clear channels.
+    fn read_color(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait ColorClient {
+    /// Called with the result of a measurement started by `read_color`.
+    fn callback(&self, reading: Result<ColorReading, ErrorCode>);
+}
+
+/// Basic interface for UV index sensors
+pub trait UvIndex<'a> {
+    /// Set the client to be notified when a measurement completes.
+    fn set_client(&self, client: &'a dyn UvIndexClient);
+
+    /// Start a measurement of the UV index.
+    fn read_uv_index(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait UvIndexClient {
+    /// Called with the result of a measurement started by `read_uv_index`,
+    /// as the UV index times ten.
+    fn uv_index_tenths(&self, value: Result<u16, ErrorCode>);
+}
+
+/// Interface for measuring sound pressure level from a microphone
+pub trait SoundLevel<'a> {
+    /// Set the client for sound level readings and peaks.
+    fn set_client(&self, client: &'a dyn SoundLevelClient);
+
+    /// Measure the sound level over one window of samples.
+    fn read_db(&self) -> Result<(), ErrorCode>;
+
+    /// Report peaks while sampling: `peak()` is called as soon as a sample
+    /// deviates from the signal's average by more than `threshold`, in the
+    /// same left-justified units as ADC samples. `None` disables peak
+    /// detection.
+    fn set_peak_threshold(&self, threshold: Option<u16>);
+}
+
+pub trait SoundLevelClient {
+    /// Called with the sound pressure level of a window, in hundredths of
+    /// a dB SPL.
+    fn sound_level(&self, level: Result<u16, ErrorCode>);
+
+    /// Called when a sample exceeds the peak threshold, with its
+    /// deviation from the average.
+    fn peak(&self, amplitude: u16);
+}
+
+/// Interface for estimating indoor air quality from a heated gas sensor
+pub trait IndoorAirQuality<'a> {
+    /// Set the client for air quality estimates.
+    fn set_client(&self, client: &'a dyn IndoorAirQualityClient);
+
+    /// Estimate the indoor air quality from the next measurement.
+    fn read_iaq_score(&self) -> Result<(), ErrorCode>;
+
+    /// Heat the sensor to `temperature_c` °C for `duration_ms` at set point
+    /// `step` of its heater profile. Changing the profile restarts the
+    /// estimate.
+    fn set_heater_step(
+        &self,
+        _step: usize,
+        _temperature_c: u16,
+        _duration_ms: u16,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Use the first `steps` set points of the heater profile, one for each
+    /// measurement in turn.
+    fn set_heater_steps(&self, _steps: usize) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+pub trait IndoorAirQualityClient {
+    /// Called with the indoor air quality index, from 0 for clean air to
+    /// 500 for heavily polluted air.
+    fn iaq_score(&self, score: Result<u16, ErrorCode>);
+}
+
+/// Interface for power monitors that measure several rails
+pub trait MultiChannelPowerMonitor<'a> {
+    /// Set the client for channel readings.
+    fn set_client(&self, client: &'a dyn MultiChannelPowerMonitorClient);
+
+    /// The number of channels, which are numbered from 0.
+    fn channels(&self) -> u8;
+
+    /// Measure the bus voltage and current of channel `ch`.
+    fn read_channel(&self, ch: u8) -> Result<(), ErrorCode>;
+}
+
+pub trait MultiChannelPowerMonitorClient {
+    /// Called with the result of a measurement started by `read_channel`,
+    /// as the bus voltage in mV and the current in µA. The current is
+    /// negative when it flows backwards through the shunt.
+    fn channel_read(&self, ch: u8, reading: Result<(u32, i32), ErrorCode>);
+}
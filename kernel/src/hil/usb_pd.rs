@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Interface for USB Type-C Power Delivery controllers.
+//!
+//! A PD controller negotiates a power contract with the port partner: the
+//! source advertises the voltages and currents it can supply as power data
+//! objects (PDOs), and the sink requests one of them. Without a PD contract
+//! the sink gets 5 V at the current advertised on the CC lines.
+//!
+//! The operations are split-phase, and only one can be outstanding at a
+//! time. The client is also told whenever the contract changes, for example
+//! because a cable was plugged in or the source renegotiated.
+
+use crate::ErrorCode;
+
+/// The power a port gets from, or supplies to, its partner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PowerContract {
+    /// The voltage of the contract, in mV.
+    pub voltage_mv: u32,
+    /// The maximum current of the contract, in mA.
+    pub current_ma: u32,
+}
+
+pub trait UsbPdController<'a> {
+    /// Set the client for completions and contract changes.
+    fn set_client(&self, client: &'a dyn UsbPdClient);
+
+    /// Read the current power contract.
+    fn read_contract(&self) -> Result<(), ErrorCode>;
+
+    /// Request a fixed supply PDO of `voltage_mv` that can supply at least
+    /// `current_ma`. Returns `INVAL` if the controller cannot sink that
+    /// voltage or current.
+    fn request_power(&self, voltage_mv: u32, current_ma: u32) -> Result<(), ErrorCode>;
+
+    /// Make the port a power sink, swapping power roles with the partner if
+    /// the port is currently the source.
+    fn set_sink(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait UsbPdClient {
+    /// The contract was read, `None` if nothing is attached to the port.
+    fn contract_read(&self, result: Result<Option<PowerContract>, ErrorCode>);
+
+    /// A request for power, or to become the sink, completed. Returns
+    /// `FAIL` if the port partner rejected it.
+    fn command_complete(&self, result: Result<(), ErrorCode>);
+
+    /// The power contract changed, or the port partner was attached or
+    /// detached.
+    fn contract_changed(&self);
+}
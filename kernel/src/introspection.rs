@@ -95,6 +95,27 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_syscall_count())
     }
 
+    /// Returns the number of syscalls of each class the app has called, or
+    /// `None` if the kernel was built without the `syscall_counters` feature.
+    pub fn app_syscall_counters(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<process::SyscallCounters> {
+        self.kernel
+            .process_map_or(None, app, |process| process.debug_syscall_counters())
+    }
+
+    /// Set the per-class syscall counters of the app back to zero.
+    pub fn reset_app_syscall_counters(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) {
+        self.kernel
+            .process_map_or((), app, |process| process.debug_reset_syscall_counters())
+    }
+
     /// Returns the number of dropped upcalls the app has experience.
     /// Upcalls can be dropped if the queue for the app is full when a capsule
     /// tries to schedule a upcall.
@@ -26,6 +26,7 @@ use crate::platform::mpu::MPU;
 use crate::platform::platform::ContextSwitchCallback;
 use crate::platform::platform::KernelResources;
 use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter};
+use crate::platform::platform::{YieldSpinAction, YieldSpinPolicy};
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::process::{self, Process, ProcessId, ShortID, Task};
@@ -67,6 +68,10 @@ pub struct Kernel {
     /// established.
     grants_finalized: Cell<bool>,
 
+    /// How many times in a row the scheduler picked a process that the yield
+    /// spin policy kept from running.
+    suspended_picks: Cell<usize>,
+
     init_cap: KernelProcessInitCapability,
 
     checker: ProcessCheckerMachine,
@@ -95,6 +100,10 @@ pub enum StoppedExecutingReason {
     /// interrupt), or because the scheduler no longer wants to execute that
     /// process.
     KernelPreemption,
+
+    /// The process kept calling yield-no-wait with no upcalls pending, and the
+    /// board's `YieldSpinPolicy` ended its turn or kept it from running.
+    YieldSpin,
 }
 
 /// Represents the different outcomes when trying to allocate a grant region
@@ -130,6 +139,7 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            suspended_picks: Cell::new(0),
             init_cap: KernelProcessInitCapability {},
             checker: ProcessCheckerMachine {
                 process: Cell::new(0),
@@ -429,6 +439,32 @@ impl Kernel {
                     // No kernel work ready, so ask scheduler for a process.
                     match scheduler.next() {
                         SchedulingDecision::RunProcess((processid, timeslice_us)) => {
+                            if resources.yield_spin_policy().suspended(processid) {
+                                // The process is sitting out for spinning on
+                                // yield, let the scheduler pick another one.
+                                scheduler.result(
+                                    StoppedExecutingReason::YieldSpin,
+                                    timeslice_us.map(|_| 0),
+                                );
+                                // Once every ready process has sat out, there
+                                // is nothing to run: sleep instead of spinning
+                                // through the penalties.
+                                let picks = self.suspended_picks.get() + 1;
+                                let mut ready = 0;
+                                self.process_each(|process| {
+                                    if process.ready() {
+                                        ready += 1;
+                                    }
+                                });
+                                if picks < ready {
+                                    self.suspended_picks.set(picks);
+                                    return;
+                                }
+                                self.suspended_picks.set(0);
+                                self.try_sleep(resources, chip, no_sleep);
+                                return;
+                            }
+                            self.suspended_picks.set(0);
                             self.process_map_or((), processid, |process| {
                                 let (reason, time_executed) =
                                     self.do_process(resources, chip, process, ipc, timeslice_us);
@@ -436,29 +472,7 @@ impl Kernel {
                             });
                         }
                         SchedulingDecision::TrySleep => {
-                            // For testing, it may be helpful to
-                            // disable sleeping the chip in case
-                            // the running test does not generate
-                            // any interrupts.
-                            if !no_sleep {
-                                chip.atomic(|| {
-                                    // Cannot sleep if interrupts are pending,
-                                    // as on most platforms unhandled interrupts
-                                    // will wake the device. Also, if the only
-                                    // pending interrupt occurred after the
-                                    // scheduler decided to put the chip to
-                                    // sleep, but before this atomic section
-                                    // starts, the interrupt will not be
-                                    // serviced and the chip will never wake
-                                    // from sleep.
-                                    if !chip.has_pending_interrupts() && !DeferredCall::has_tasks()
-                                    {
-                                        resources.watchdog().suspend();
-                                        chip.sleep();
-                                        resources.watchdog().resume();
-                                    }
-                                });
-                            }
+                            self.try_sleep(resources, chip, no_sleep);
                         }
                     }
                 }
@@ -466,6 +480,33 @@ impl Kernel {
         }
     }
 
+    /// Put the chip to sleep, unless `no_sleep` is set or there is kernel
+    /// work to do.
+    unsafe fn try_sleep<KR: KernelResources<C>, C: Chip>(
+        &self,
+        resources: &KR,
+        chip: &C,
+        no_sleep: bool,
+    ) {
+        // For testing, it may be helpful to disable sleeping the chip in case
+        // the running test does not generate any interrupts.
+        if !no_sleep {
+            chip.atomic(|| {
+                // Cannot sleep if interrupts are pending, as on most platforms
+                // unhandled interrupts will wake the device. Also, if the only
+                // pending interrupt occurred after the scheduler decided to put
+                // the chip to sleep, but before this atomic section starts, the
+                // interrupt will not be serviced and the chip will never wake
+                // from sleep.
+                if !chip.has_pending_interrupts() && !DeferredCall::has_tasks() {
+                    resources.watchdog().suspend();
+                    chip.sleep();
+                    resources.watchdog().resume();
+                }
+            });
+        }
+    }
+
     /// Main loop of the OS.
     ///
     /// Most of the behavior of this loop is controlled by the `Scheduler`
@@ -615,6 +656,20 @@ impl Kernel {
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
                             self.handle_syscall(resources, process, syscall);
+                            if let Syscall::Yield { which, .. } = syscall {
+                                let policy = resources.yield_spin_policy();
+                                // A yield-no-wait only leaves the process
+                                // running if it had no upcalls to run.
+                                if process.get_state() != process::State::Running {
+                                    policy.yield_progressed(process.processid());
+                                } else if which == YieldCall::NoWait as usize
+                                    && policy.yield_spun(process.processid())
+                                        == YieldSpinAction::EndTurn
+                                {
+                                    return_reason = StoppedExecutingReason::YieldSpin;
+                                    break;
+                                }
+                            }
                         }
                         Some(ContextSwitchReason::Interrupted) => {
                             if scheduler_timer.get_remaining_us().is_none() {
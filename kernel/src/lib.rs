@@ -114,6 +114,8 @@ pub mod processbuffer;
 pub mod scheduler;
 pub mod storage_permissions;
 pub mod syscall;
+#[cfg(any(test, feature = "test_mocks"))]
+pub mod test;
 pub mod upcall;
 pub mod utilities;
 
@@ -19,3 +19,5 @@ pub use self::platform::ProcessFault;
 pub use self::platform::SyscallDriverLookup;
 pub use self::platform::SyscallFilter;
 pub use self::platform::TbfHeaderFilterDefaultAllow;
+pub use self::platform::YieldSpinAction;
+pub use self::platform::YieldSpinPolicy;
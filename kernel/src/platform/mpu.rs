@@ -272,6 +272,43 @@ pub trait MPU {
         }
     }
 
+    /// Returns the size of the guard region placed below each process's
+    /// stack, or 0 if the implementation does not use stack guard regions.
+    ///
+    /// The kernel reserves this many bytes directly below process memory,
+    /// where the stack starts, for `allocate_stack_guard_region`.
+    fn stack_guard_size(&self) -> usize {
+        0
+    }
+
+    /// Allocates a guard region directly below a process's stack.
+    ///
+    /// An implementation must allocate an MPU region of `stack_guard_size()`
+    /// bytes that ends at `stack_bottom`, is inaccessible in user mode, and
+    /// takes precedence over any other region covering the same memory. It
+    /// must store the region in `config`. A process whose stack overflows
+    /// then faults in the guard region, which the kernel reports as a stack
+    /// overflow.
+    ///
+    /// # Arguments
+    ///
+    /// - `stack_bottom`: lowest address of the process's stack, which is the
+    ///                   start of process memory
+    /// - `config`:       MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the allocated guard region. If stack guard regions are not
+    /// used or it is infeasible to allocate the region, returns None.
+    #[allow(unused_variables)]
+    fn allocate_stack_guard_region(
+        &self,
+        stack_bottom: *const u8,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
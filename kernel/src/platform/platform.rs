@@ -40,6 +40,10 @@ pub trait KernelResources<C: Chip> {
     /// the kernel will use.
     type ContextSwitchCallback: ContextSwitchCallback;
 
+    /// The implementation of the policy for processes that spin on
+    /// yield-no-wait the kernel will use.
+    type YieldSpinPolicy: YieldSpinPolicy;
+
     /// The implementation of the scheduling algorithm the kernel will use.
     type Scheduler: Scheduler<C>;
 
@@ -71,6 +75,10 @@ pub trait KernelResources<C: Chip> {
     /// for this platform.
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback;
 
+    /// Returns a reference to the implementation of the YieldSpinPolicy this
+    /// platform wants the kernel to use.
+    fn yield_spin_policy(&self) -> &Self::YieldSpinPolicy;
+
     /// Returns a reference to the implementation of the Scheduler this platform
     /// wants the kernel to use.
     fn scheduler(&self) -> &Self::Scheduler;
@@ -293,3 +301,45 @@ pub trait ContextSwitchCallback {
 impl ContextSwitchCallback for () {
     fn context_switch_hook(&self, _process: &dyn process::Process) {}
 }
+
+/// What the kernel does with a process that called yield-no-wait with no
+/// pending upcalls.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum YieldSpinAction {
+    /// Let the process keep running.
+    Continue,
+    /// End the process's turn, so the scheduler picks a process to run next.
+    EndTurn,
+}
+
+/// Trait for implementing a policy for processes that busy-yield.
+///
+/// A process that calls yield-no-wait in a loop while it has no upcalls
+/// pending keeps running until its timeslice expires, or forever with a
+/// cooperative scheduler. The kernel reports every such yield, and every
+/// yield that found or waited for an upcall, so an implementation can detect
+/// a spinning process and end its turn or keep it from running for a while.
+///
+/// The default implementation, used by most boards, never intervenes.
+pub trait YieldSpinPolicy {
+    /// Called when `process` called yield-no-wait and, having no upcalls
+    /// pending, is about to continue running.
+    fn yield_spun(&self, _process: process::ProcessId) -> YieldSpinAction {
+        YieldSpinAction::Continue
+    }
+
+    /// Called when `process` yielded with upcalls pending or yield-waited.
+    fn yield_progressed(&self, _process: process::ProcessId) {}
+
+    /// Called every time the scheduler picks `process` to run. If this
+    /// returns `true` the kernel does not run the process, and tells the
+    /// scheduler it stopped with `StoppedExecutingReason::YieldSpin`. When
+    /// every ready process has been kept from running in a row, the kernel
+    /// sleeps until the next interrupt.
+    fn suspended(&self, _process: process::ProcessId) -> bool {
+        false
+    }
+}
+
+/// Implement default YieldSpinPolicy trait for unit.
+impl YieldSpinPolicy for () {}
@@ -23,9 +23,14 @@ use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials};
 // Export all process related types via `kernel::process::`.
 pub use crate::process_loading::ProcessLoadError;
 pub use crate::process_loading::{load_and_check_processes, load_processes};
+pub use crate::process_loading::{
+    load_deferred_processes, load_processes_with_priorities, AppPriority, DEFAULT_PRIORITY,
+    MAX_PRIORITY,
+};
 pub use crate::process_policies::{
-    PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy, StopFaultPolicy,
-    StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy, ThresholdRestartThenPanicFaultPolicy,
+    PanicFaultPolicy, PerProcessFaultPolicy, ProcessFaultPolicy, ProcessMatch, RestartBackoff,
+    RestartFaultPolicy, StopFaultPolicy, StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy,
+    ThresholdRestartThenPanicFaultPolicy, ThresholdYieldSpinPolicy,
 };
 pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext, ProcessPrinterText};
 pub use crate::process_standard::ProcessStandard;
@@ -363,6 +368,11 @@ pub trait Process {
     /// process.
     fn set_fault_state(&self);
 
+    /// Classify the fault this process last stopped on. This is only
+    /// meaningful while the fault is being handled, or for a process left in
+    /// the `Faulted` state.
+    fn fault_cause(&self) -> FaultCause;
+
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
@@ -740,6 +750,13 @@ pub trait Process {
     /// Return the last syscall the process called. Returns `None` if the
     /// process has not called any syscalls or the information is unknown.
     fn debug_syscall_last(&self) -> Option<Syscall>;
+
+    /// Returns how many syscalls of each class this process has called, or
+    /// `None` if the kernel was built without the `syscall_counters` feature.
+    fn debug_syscall_counters(&self) -> Option<SyscallCounters>;
+
+    /// Set the per-class syscall counters of this process back to zero.
+    fn debug_reset_syscall_counters(&self);
 }
 
 /// Opaque identifier for custom grants allocated dynamically from a process's
@@ -906,7 +923,7 @@ pub enum State {
 ///
 /// The actions are separate from the policy on deciding which action to take. A
 /// separate process-specific policy should determine which action to take.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum FaultAction {
     /// Generate a `panic!()` call and crash the entire system. This is useful
     /// for debugging applications as the error is displayed immediately after
@@ -922,6 +939,37 @@ pub enum FaultAction {
     Stop,
 }
 
+/// The cause of a process fault, as far as the kernel can tell.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultCause {
+    /// The process accessed the guard region below its stack, that is its
+    /// stack overflowed.
+    StackOverflow,
+
+    /// Any other fault, including memory accesses outside of the stack guard
+    /// region.
+    Other,
+}
+
+impl FaultCause {
+    /// Classify a fault of a process with the given stack guard region, from
+    /// the address it faulted on if the architecture reported one.
+    pub(crate) fn classify(
+        fault_address: Option<*const u8>,
+        stack_guard: Option<mpu::Region>,
+    ) -> FaultCause {
+        match (fault_address, stack_guard) {
+            (Some(address), Some(guard))
+                if address >= guard.start_address()
+                    && (address as usize) < guard.start_address() as usize + guard.size() =>
+            {
+                FaultCause::StackOverflow
+            }
+            _ => FaultCause::Other,
+        }
+    }
+}
+
 /// Tasks that can be enqueued for a process.
 ///
 /// This is public for external implementations of `Process`.
@@ -1035,3 +1083,129 @@ pub struct ProcessSizes {
     /// `ProcessX` struct).
     pub process_control_block: usize,
 }
+
+/// Number of system calls a process has made, by system call class.
+///
+/// These are only collected if the kernel is built with the
+/// `syscall_counters` feature.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SyscallCounters {
+    /// Number of Command system calls.
+    pub command: usize,
+    /// Number of ReadWriteAllow, UserspaceReadableAllow and ReadOnlyAllow
+    /// system calls.
+    pub allow: usize,
+    /// Number of Subscribe system calls.
+    pub subscribe: usize,
+    /// Number of Yield system calls.
+    pub yield_: usize,
+    /// Number of Memop system calls.
+    pub memop: usize,
+    /// Number of Exit system calls.
+    pub exit: usize,
+}
+
+impl SyscallCounters {
+    /// Count one call of `syscall`.
+    pub(crate) fn record(&mut self, syscall: &Syscall) {
+        let counter = match syscall {
+            Syscall::Command { .. } => &mut self.command,
+            Syscall::ReadWriteAllow { .. }
+            | Syscall::UserspaceReadableAllow { .. }
+            | Syscall::ReadOnlyAllow { .. } => &mut self.allow,
+            Syscall::Subscribe { .. } => &mut self.subscribe,
+            Syscall::Yield { .. } => &mut self.yield_,
+            Syscall::Memop { .. } => &mut self.memop,
+            Syscall::Exit { .. } => &mut self.exit,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Set all counters back to zero.
+    pub(crate) fn reset(&mut self) {
+        *self = SyscallCounters::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    // `ProcessStandard` only records system calls with the `syscall_counters`
+    // feature, so test the counters themselves without it.
+    #[test]
+    fn syscall_counters_record_and_reset() {
+        let mut counters = SyscallCounters::default();
+        let calls = [
+            Syscall::Command {
+                driver_number: 1,
+                subdriver_number: 1,
+                arg0: 0,
+                arg1: 0,
+            },
+            Syscall::ReadOnlyAllow {
+                driver_number: 1,
+                subdriver_number: 1,
+                allow_address: ptr::null(),
+                allow_size: 0,
+            },
+            Syscall::ReadWriteAllow {
+                driver_number: 1,
+                subdriver_number: 0,
+                allow_address: ptr::null_mut(),
+                allow_size: 0,
+            },
+            Syscall::UserspaceReadableAllow {
+                driver_number: 1,
+                subdriver_number: 0,
+                allow_address: ptr::null_mut(),
+                allow_size: 0,
+            },
+            Syscall::Subscribe {
+                driver_number: 1,
+                subdriver_number: 0,
+                upcall_ptr: ptr::null_mut(),
+                appdata: 0,
+            },
+            Syscall::Yield {
+                which: 0,
+                address: ptr::null_mut(),
+            },
+            Syscall::Yield {
+                which: 1,
+                address: ptr::null_mut(),
+            },
+            Syscall::Memop {
+                operand: 1,
+                arg0: 0,
+            },
+            Syscall::Exit {
+                which: 0,
+                completion_code: 0,
+            },
+        ];
+        for call in calls.iter() {
+            counters.record(call);
+        }
+        assert_eq!(
+            counters,
+            SyscallCounters {
+                command: 1,
+                allow: 3,
+                subscribe: 1,
+                yield_: 2,
+                memop: 1,
+                exit: 1,
+            }
+        );
+
+        // Counters stop at their maximum instead of wrapping.
+        counters.command = usize::MAX;
+        counters.record(&calls[0]);
+        assert_eq!(counters.command, usize::MAX);
+
+        counters.reset();
+        assert_eq!(counters, SyscallCounters::default());
+    }
+}
@@ -18,10 +18,11 @@ use crate::debug;
 use crate::kernel::{Kernel, ProcessCheckerMachine};
 use crate::platform::chip::Chip;
 use crate::platform::platform::KernelResources;
-use crate::process::{Process, ShortID};
+use crate::process::{Process, ShortID, State};
 use crate::process_checker::AppCredentialsChecker;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandard;
+use tock_tbf::types::TbfHeader;
 
 /// Errors that can occur when trying to load and create processes.
 pub enum ProcessLoadError {
@@ -166,6 +167,66 @@ impl fmt::Debug for ProcessLoadError {
     }
 }
 
+/// The highest priority of a process. Higher priorities are clamped to it.
+pub const MAX_PRIORITY: u8 = 7;
+
+/// The priority of processes without an entry in the priority table.
+pub const DEFAULT_PRIORITY: u8 = 4;
+
+/// An entry of the priority table of a board, for the process with the
+/// package name `name`.
+///
+/// Processes with a higher priority are loaded first, so they get lower
+/// indices in the `procs` array: the priority scheduler runs them first, and
+/// the other schedulers start them first. Processes with the same priority
+/// are loaded in flash order. Deferred processes are not loaded with the
+/// others, but when the board calls `load_deferred_processes`.
+///
+/// Processes with a fixed RAM address in their TBF header ignore the table:
+/// they expect their RAM where the processes before them in flash leave it,
+/// so they are loaded first, in flash order, before any other process, and
+/// are never deferred.
+#[derive(Clone, Copy, Debug)]
+pub struct AppPriority {
+    pub name: &'static str,
+    pub priority: u8,
+    pub deferred: bool,
+}
+
+/// The loading pass of the processes with a fixed RAM address, before the
+/// highest priority.
+const FIXED_ADDRESS_PASS: u8 = MAX_PRIORITY + 1;
+
+impl AppPriority {
+    /// The loading pass of the process with the TBF header `header` in
+    /// `priorities`, and whether it is deferred.
+    fn lookup(header: Option<&TbfHeader>, priorities: &[AppPriority]) -> (u8, bool) {
+        if !priorities.is_empty()
+            && header.map_or(false, |header| header.get_fixed_address_ram().is_some())
+        {
+            return (FIXED_ADDRESS_PASS, false);
+        }
+        header
+            .and_then(|header| header.get_package_name())
+            .and_then(|name| priorities.iter().find(|entry| entry.name == name))
+            .map_or((DEFAULT_PRIORITY, false), |entry| {
+                (entry.priority.min(MAX_PRIORITY), entry.deferred)
+            })
+    }
+
+    /// The loading passes of the processes with `priorities`, first to last.
+    /// Without a table, there is a single pass in flash order.
+    fn levels(priorities: &[AppPriority]) -> impl Iterator<Item = u8> + '_ {
+        (0..=FIXED_ADDRESS_PASS).rev().filter(move |&level| {
+            level == DEFAULT_PRIORITY
+                || (level == FIXED_ADDRESS_PASS && !priorities.is_empty())
+                || priorities
+                    .iter()
+                    .any(|entry| entry.priority.min(MAX_PRIORITY) == level)
+        })
+    }
+}
+
 /// Load processes (stored as TBF objects in flash) into runnable
 /// process structures stored in the `procs` array. If the kernel is
 /// configured with an `AppCredentialsChecker`, this method scans the
@@ -193,6 +254,8 @@ where
         app_memory,
         &mut procs,
         fault_policy,
+        &[],
+        false,
         capability_management,
     )?;
     let _res = check_processes(kernel_resources, kernel.get_checker());
@@ -224,15 +287,90 @@ pub fn load_processes<C: Chip>(
         app_memory,
         &mut procs,
         fault_policy,
+        &[],
+        false,
         capability_management,
     )?;
 
+    approve_processes(procs)
+}
+
+/// Load processes like `load_processes`, in the order of their priority in
+/// `priorities`. Processes that are deferred in `priorities` are not loaded.
+/// Processes with a fixed RAM address are loaded before all others, in flash
+/// order, whatever their entry in `priorities`.
+///
+/// Returns the memory left for the deferred processes, which the board
+/// passes to `load_deferred_processes`.
+#[inline(always)]
+pub fn load_processes_with_priorities<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    mut procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    priorities: &[AppPriority],
+    capability_management: &dyn ProcessManagementCapability,
+) -> Result<&'static mut [u8], ProcessLoadError> {
+    let remaining_memory = load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        &mut procs,
+        fault_policy,
+        priorities,
+        false,
+        capability_management,
+    )?;
+    approve_processes(procs)?;
+    Ok(remaining_memory)
+}
+
+/// Load the processes that are deferred in `priorities` and not loaded yet
+/// into the free slots of `procs`, with the memory returned by
+/// `load_processes_with_priorities`, and mark them runnable. The board
+/// calls this on the trigger of the deferred processes.
+///
+/// Returns the memory left.
+pub fn load_deferred_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    mut procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    priorities: &[AppPriority],
+    capability_management: &dyn ProcessManagementCapability,
+) -> Result<&'static mut [u8], ProcessLoadError> {
+    let remaining_memory = load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        &mut procs,
+        fault_policy,
+        priorities,
+        true,
+        capability_management,
+    )?;
+    approve_processes(procs)?;
+    Ok(remaining_memory)
+}
+
+/// Mark the processes in `procs` that were not checked yet as runnable,
+/// without checking their credentials.
+fn approve_processes(procs: &[Option<&'static dyn Process>]) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_process_credentials {
         debug!("Checking: no checking, load and run all processes");
     }
     let capability = create_capability!(ProcessApprovalCapability);
     for proc in procs.iter() {
         let res = proc.map(|p| {
+            if p.get_state() != State::CredentialsUnchecked {
+                return Ok(());
+            }
             p.mark_credentials_pass(None, ShortID::LocallyUnique, &capability)
                 .or(Err(ProcessLoadError::InternalError))?;
             if config::CONFIG.debug_process_credentials {
@@ -258,15 +396,21 @@ pub fn load_processes<C: Chip>(
 /// ensuring that this code cannot hold onto the slice past the end of this function
 /// (instead, processes store a pointer and length), which necessary for later
 /// creation of `ProcessBuffer`s in this memory region to be sound.
-/// A reference to each process is stored in the provided `procs` array.
-/// How process faults are handled by the
+/// A reference to each process is stored in the first free slot of the
+/// provided `procs` array. How process faults are handled by the
 /// kernel must be provided and is assigned to every created process.
 ///
+/// Processes are loaded in the order of their priority in `priorities`, by
+/// walking flash once for each priority, after a first walk for the processes
+/// with a fixed RAM address. If `deferred` is set, only the
+/// deferred processes that are not loaded yet are loaded, otherwise only the
+/// others.
+///
 /// This function is made `pub` so that board files can use it, but loading
 /// processes from slices of flash an memory is fundamentally unsafe. Therefore,
 /// we require the `ProcessManagementCapability` to call this function.
 ///
-/// Returns `Ok(())` if process discovery went as expected. Returns a
+/// Returns the memory left if process discovery went as expected. Returns a
 /// `ProcessLoadError` if something goes wrong during TBF parsing or process
 /// creation.
 #[inline(always)]
@@ -277,8 +421,10 @@ fn load_processes_from_flash<C: Chip>(
     app_memory: &'static mut [u8],
     procs: &mut &'static mut [Option<&'static dyn Process>],
     fault_policy: &'static dyn ProcessFaultPolicy,
+    priorities: &[AppPriority],
+    deferred: bool,
     capability: &dyn ProcessManagementCapability,
-) -> Result<(), ProcessLoadError> {
+) -> Result<&'static mut [u8], ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
             "Loading processes from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X}",
@@ -289,47 +435,58 @@ fn load_processes_from_flash<C: Chip>(
         );
     }
 
-    let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
-    // Try to discover up to `procs.len()` processes in flash.
-    let mut index = 0;
-    let num_procs = procs.len();
-    while index < num_procs {
-        let load_result = load_process(
-            kernel,
-            chip,
-            remaining_flash,
-            remaining_memory,
-            index,
-            fault_policy,
-            capability,
-        );
-        match load_result {
-            Ok((new_flash, new_mem, proc)) => {
-                remaining_flash = new_flash;
-                remaining_memory = new_mem;
-                if proc.is_some() {
-                    if config::CONFIG.debug_load_processes {
-                        proc.map(|p| debug!("Loaded process {}", p.get_process_name()));
+    for level in AppPriority::levels(priorities) {
+        let mut remaining_flash = app_flash;
+        // Try to discover processes in flash until `procs` is full.
+        while let Some(index) = procs.iter().position(|proc| proc.is_none()) {
+            let loaded: &[Option<&'static dyn Process>] = procs;
+            let select = |header: Option<&TbfHeader>| {
+                let name = header.and_then(|header| header.get_package_name());
+                AppPriority::lookup(header, priorities) == (level, deferred)
+                    && !(deferred
+                        && loaded
+                            .iter()
+                            .flatten()
+                            .any(|proc| Some(proc.get_process_name()) == name))
+            };
+            let load_result = load_process(
+                kernel,
+                chip,
+                remaining_flash,
+                remaining_memory,
+                index,
+                fault_policy,
+                &select,
+                capability,
+            );
+            match load_result {
+                Ok((new_flash, new_mem, proc)) => {
+                    remaining_flash = new_flash;
+                    remaining_memory = new_mem;
+                    if proc.is_some() {
+                        if config::CONFIG.debug_load_processes {
+                            proc.map(|p| debug!("Loaded process {}", p.get_process_name()));
+                        }
+                        procs[index] = proc;
+                    } else {
+                        if config::CONFIG.debug_load_processes {
+                            debug!("No process loaded.");
+                        }
                     }
-                    procs[index] = proc;
-                    index = index + 1;
-                } else {
+                }
+                Err((_new_flash, new_mem, err)) => {
+                    remaining_memory = new_mem;
                     if config::CONFIG.debug_load_processes {
-                        debug!("No process loaded.");
+                        debug!("No more processes to load: {:?}.", err);
                     }
+                    // No more processes to load with this priority.
+                    break;
                 }
             }
-            Err((_new_flash, _new_mem, err)) => {
-                if config::CONFIG.debug_load_processes {
-                    debug!("No more processes to load: {:?}.", err);
-                }
-                // No more processes to load.
-                break;
-            }
         }
     }
-    Ok(())
+    Ok(remaining_memory)
 }
 
 /// Use `checker` to transition `procs` from the
@@ -356,7 +513,8 @@ fn check_processes<'a, KR: KernelResources<C>, C: Chip>(
 /// Returns `Ok` if there are possibly more processes and `load_process` should
 /// be called again, `Err` if it should not be. May return `Ok` with `None` if
 /// a process was not found (e..g, there was padding) but there may be more
-/// processes.
+/// processes. Processes whose header `select` rejects are skipped like
+/// padding.
 fn load_process<C: Chip>(
     kernel: &'static Kernel,
     chip: &'static C,
@@ -364,6 +522,7 @@ fn load_process<C: Chip>(
     app_memory: &'static mut [u8],
     index: usize,
     fault_policy: &'static dyn ProcessFaultPolicy,
+    select: &dyn Fn(Option<&TbfHeader>) -> bool,
     _capability: &dyn ProcessManagementCapability,
 ) -> Result<
     (
@@ -436,7 +595,17 @@ fn load_process<C: Chip>(
 
     // Need to reassign remaining_memory in every iteration so the compiler
     // knows it will not be re-borrowed.
-    let (process_option, remaining_memory) = if header_length > 0 {
+    // A header that does not parse is selected, so that creating the
+    // process reports the error.
+    let selected = header_length > 0
+        && select(
+            entry_flash
+                .get(0..header_length as usize)
+                .and_then(|header| tock_tbf::parse::parse_tbf_header(header, version).ok())
+                .as_ref(),
+        );
+
+    let (process_option, remaining_memory) = if selected {
         // If we found an actual app header, try to create a `Process`
         // object. We also need to shrink the amount of remaining memory
         // based on whatever is assigned to the new process if one is
@@ -479,9 +648,178 @@ fn load_process<C: Chip>(
         });
         (process_option, unused_memory)
     } else {
-        // We are just skipping over this region of flash, so we have the
-        // same amount of process memory to allocate from.
+        // We are just skipping over this region of flash, or a process that
+        // is not selected, so we have the same amount of process memory to
+        // allocate from.
         (None, app_memory)
     };
     Ok((remaining_flash, remaining_memory, process_option))
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::scheduler::priority::PrioritySched;
+    use crate::scheduler::{Scheduler, SchedulingDecision};
+    use crate::test::mocks::{self, MockChip};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const PRIORITIES: [AppPriority; 5] = [
+        AppPriority {
+            name: "radio",
+            priority: 7,
+            deferred: false,
+        },
+        AppPriority {
+            name: "sensor",
+            priority: 200,
+            deferred: false,
+        },
+        AppPriority {
+            name: "logger",
+            priority: 1,
+            deferred: false,
+        },
+        AppPriority {
+            name: "ui",
+            priority: 6,
+            deferred: true,
+        },
+        AppPriority {
+            name: "clock",
+            priority: 0,
+            deferred: true,
+        },
+    ];
+
+    fn header(name: &str, fixed_ram: Option<usize>) -> TbfHeader {
+        let flash = mocks::app_flash(&[mocks::tbf(name, fixed_ram)]);
+        let header_length = u16::from_le_bytes([flash[2], flash[3]]) as usize;
+        tock_tbf::parse::parse_tbf_header(&flash[..header_length], 2).unwrap()
+    }
+
+    /// The process the priority scheduler runs next.
+    fn next(kernel: &Kernel, scheduler: &PrioritySched) -> Option<&'static str> {
+        match Scheduler::<MockChip>::next(scheduler) {
+            SchedulingDecision::RunProcess((processid, _)) => {
+                kernel.process_map_or(None, processid, |process| Some(process.get_process_name()))
+            }
+            SchedulingDecision::TrySleep => None,
+        }
+    }
+
+    #[test]
+    fn priorities_are_clamped_and_default_to_equal() {
+        let lookup =
+            |name, fixed_ram| AppPriority::lookup(Some(&header(name, fixed_ram)), &PRIORITIES);
+        assert_eq!(lookup("radio", None), (7, false));
+        assert_eq!(lookup("sensor", None), (MAX_PRIORITY, false));
+        assert_eq!(lookup("ui", None), (6, true));
+        assert_eq!(lookup("blink", None), (DEFAULT_PRIORITY, false));
+        assert_eq!(
+            AppPriority::lookup(None, &PRIORITIES),
+            (DEFAULT_PRIORITY, false)
+        );
+        assert_eq!(
+            AppPriority::levels(&PRIORITIES).collect::<Vec<_>>(),
+            [FIXED_ADDRESS_PASS, 7, 6, 4, 1, 0]
+        );
+
+        // A fixed RAM address overrides the table, but not its absence.
+        assert_eq!(
+            lookup("clock", Some(0x2000_0000)),
+            (FIXED_ADDRESS_PASS, false)
+        );
+        assert_eq!(
+            AppPriority::lookup(Some(&header("clock", Some(0x2000_0000))), &[]),
+            (DEFAULT_PRIORITY, false)
+        );
+        assert_eq!(
+            AppPriority::levels(&[]).collect::<Vec<_>>(),
+            [DEFAULT_PRIORITY]
+        );
+    }
+
+    #[test]
+    fn scheduler_follows_load_priority() {
+        let chip = MockChip::new();
+        let (kernel, procs) = mocks::kernel(6);
+        let memory = mocks::app_memory(6);
+        // The clock is linked for the start of app memory, where it is
+        // loaded even though it comes after processes loaded before it.
+        let clock_ram = memory.as_ptr() as usize;
+        let flash = mocks::app_flash(&[
+            mocks::tbf("blink", None),
+            mocks::tbf("logger", None),
+            mocks::tbf("radio", None),
+            mocks::tbf("ui", None),
+            mocks::tbf("clock", Some(clock_ram)),
+            mocks::tbf("sensor", None),
+        ]);
+        let capability = create_capability!(ProcessManagementCapability);
+        let procs: &'static [Option<&'static dyn Process>] = procs;
+        // Both loads fill the free slots of the same array.
+        let procs_mut =
+            || unsafe { core::slice::from_raw_parts_mut(procs.as_ptr() as *mut _, procs.len()) };
+        let fault_policy = &crate::process_policies::StopFaultPolicy {};
+
+        let memory = load_processes_with_priorities(
+            kernel,
+            chip,
+            flash,
+            memory,
+            procs_mut(),
+            fault_policy,
+            &PRIORITIES,
+            &capability,
+        )
+        .unwrap();
+        let names = || {
+            procs
+                .iter()
+                .map(|proc| proc.map(|proc| proc.get_process_name()))
+                .collect::<Vec<_>>()
+        };
+        // Higher priorities are loaded first, in flash order within a
+        // priority, and deferred processes are left out.
+        assert_eq!(
+            names(),
+            [
+                Some("clock"),
+                Some("radio"),
+                Some("sensor"),
+                Some("blink"),
+                Some("logger"),
+                None
+            ]
+        );
+        assert_eq!(procs[0].unwrap().get_addresses().sram_start, clock_ram);
+
+        load_deferred_processes(
+            kernel,
+            chip,
+            flash,
+            memory,
+            procs_mut(),
+            fault_policy,
+            &PRIORITIES,
+            &capability,
+        )
+        .unwrap();
+        assert_eq!(names()[5], Some("ui"));
+
+        let scheduler: &'static PrioritySched = Box::leak(Box::new(PrioritySched::new(kernel)));
+        for expected in ["clock", "radio", "sensor", "blink", "logger", "ui"] {
+            assert_eq!(next(kernel, scheduler), Some(expected));
+            let running = procs
+                .iter()
+                .flatten()
+                .find(|proc| proc.get_process_name() == expected);
+            running.unwrap().terminate(None);
+        }
+        assert_eq!(next(kernel, scheduler), None);
+    }
+}
@@ -8,8 +8,13 @@
 //! kernel can use when managing processes. For example, these policies control
 //! decisions such as whether a specific process should be restarted.
 
+use crate::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use crate::platform::platform::{YieldSpinAction, YieldSpinPolicy};
 use crate::process;
 use crate::process::Process;
+use crate::process::ProcessId;
+use crate::process::ShortID;
+use core::cell::Cell;
 
 /// Generic trait for implementing a policy on what to do when a process faults.
 ///
@@ -46,10 +51,16 @@ pub struct StopWithDebugFaultPolicy {}
 
 impl ProcessFaultPolicy for StopWithDebugFaultPolicy {
     fn action(&self, process: &dyn Process) -> process::FaultAction {
-        crate::debug!(
-            "Process {} faulted and was stopped.",
-            process.get_process_name()
-        );
+        match process.fault_cause() {
+            process::FaultCause::StackOverflow => crate::debug!(
+                "Process {} overflowed its stack and was stopped.",
+                process.get_process_name()
+            ),
+            process::FaultCause::Other => crate::debug!(
+                "Process {} faulted and was stopped.",
+                process.get_process_name()
+            ),
+        }
         process::FaultAction::Stop
     }
 }
@@ -109,3 +120,594 @@ impl ProcessFaultPolicy for ThresholdRestartThenPanicFaultPolicy {
         }
     }
 }
+
+/// Identifies the processes a rule of a `PerProcessFaultPolicy` applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessMatch {
+    /// The process with this package name in its TBF header.
+    Name(&'static str),
+    /// The process with this fixed `ShortID`, from its TBF header or
+    /// credentials.
+    ShortId(u32),
+}
+
+impl ProcessMatch {
+    fn matches(&self, process: &dyn Process) -> bool {
+        match *self {
+            ProcessMatch::Name(name) => process.get_process_name() == name,
+            ProcessMatch::ShortId(id) => match process.short_app_id() {
+                ShortID::Fixed(short_id) => short_id.get() == id,
+                ShortID::LocallyUnique => false,
+            },
+        }
+    }
+}
+
+/// How `PerProcessFaultPolicy` delays restarting a process.
+#[derive(Clone, Copy, Debug)]
+pub struct RestartBackoff {
+    /// Restarts after which a process that faults is stopped instead.
+    pub max_restarts: usize,
+    /// Delay before the first restart, doubled for every restart after it.
+    pub initial_ms: u32,
+    /// Longest delay before a restart.
+    pub max_ms: u32,
+}
+
+/// Implementation of `ProcessFaultPolicy` that takes a different action for
+/// each process. The first rule that matches a process that faults decides
+/// the action, or `default` if none does.
+///
+/// With `FaultAction::Restart`, the process is stopped and restarted once a
+/// delay has passed, which `backoff` doubles with every restart. After
+/// `backoff.max_restarts` restarts the process is stopped for good.
+/// `NUM_PROCS` should be the size of the board's process array; processes
+/// beyond it are stopped instead of restarted.
+pub struct PerProcessFaultPolicy<'a, A: Alarm<'a>, const NUM_PROCS: usize> {
+    alarm: &'a A,
+    rules: &'a [(ProcessMatch, process::FaultAction)],
+    default: process::FaultAction,
+    backoff: RestartBackoff,
+    /// Processes waiting to be restarted, with when their delay started and
+    /// its length.
+    restarts: [Cell<Option<(ProcessId, A::Ticks, A::Ticks)>>; NUM_PROCS],
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> PerProcessFaultPolicy<'a, A, NUM_PROCS> {
+    pub fn new(
+        alarm: &'a A,
+        rules: &'a [(ProcessMatch, process::FaultAction)],
+        default: process::FaultAction,
+        backoff: RestartBackoff,
+    ) -> PerProcessFaultPolicy<'a, A, NUM_PROCS> {
+        PerProcessFaultPolicy {
+            alarm,
+            rules,
+            default,
+            backoff,
+            restarts: [(); NUM_PROCS].map(|()| Cell::new(None)),
+        }
+    }
+
+    /// Whether `process` faulted and is waiting to be restarted.
+    pub fn restart_pending(&self, process: ProcessId) -> bool {
+        self.restarts
+            .get(process.index)
+            .map_or(false, |restart| restart.get().is_some())
+    }
+
+    /// Stop `process` now, and restart it after the backoff delay.
+    fn restart_later(&self, process: &dyn Process) -> process::FaultAction {
+        let processid = process.processid();
+        let restarts = process.get_restart_count();
+        match self.restarts.get(processid.index) {
+            Some(restart) if restarts < self.backoff.max_restarts => {
+                let factor = 1u32.checked_shl(restarts as u32).unwrap_or(u32::MAX);
+                let delay = self
+                    .backoff
+                    .initial_ms
+                    .saturating_mul(factor)
+                    .min(self.backoff.max_ms);
+                restart.set(Some((
+                    processid,
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(delay),
+                )));
+                self.arm();
+            }
+            // Give up on the process.
+            _ => {}
+        }
+        process::FaultAction::Stop
+    }
+
+    /// Set the alarm for the next restart, if there is one.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .restarts
+            .iter()
+            .filter_map(Cell::get)
+            .map(|(_, reference, dt)| {
+                let end = reference.wrapping_add(dt);
+                if now.within_range(reference, end) {
+                    end.wrapping_sub(now)
+                } else {
+                    A::Ticks::from(0)
+                }
+            })
+            .min_by_key(|remaining| remaining.into_u32());
+        match next {
+            Some(remaining) => self.alarm.set_alarm(now, remaining),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> ProcessFaultPolicy
+    for PerProcessFaultPolicy<'a, A, NUM_PROCS>
+{
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let action = self
+            .rules
+            .iter()
+            .find(|(rule, _)| rule.matches(process))
+            .map_or(self.default, |(_, action)| *action);
+        match action {
+            process::FaultAction::Restart => self.restart_later(process),
+            _ => action,
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> AlarmClient
+    for PerProcessFaultPolicy<'a, A, NUM_PROCS>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for restart in self.restarts.iter() {
+            if let Some((processid, reference, dt)) = restart.get() {
+                if !now.within_range(reference, reference.wrapping_add(dt)) {
+                    restart.set(None);
+                    processid
+                        .kernel
+                        .process_map_or((), processid, |process| process.try_restart(None));
+                }
+            }
+        }
+        self.arm();
+    }
+}
+
+/// Implementation of `YieldSpinPolicy` that ends the turn of a process once it
+/// has called yield-no-wait `threshold` times in a row with no upcalls
+/// pending, and then skips the process the next `penalty` times the scheduler
+/// picks it. A `penalty` of 0 only ends the turn, so the process goes to the
+/// back of the queue.
+///
+/// Yield-wait, or a yield that runs an upcall, resets the count, so processes
+/// that wait for upcalls are never affected. `NUM_PROCS` should be the size of
+/// the board's process array; processes beyond it are ignored.
+pub struct ThresholdYieldSpinPolicy<const NUM_PROCS: usize> {
+    threshold: usize,
+    penalty: usize,
+    /// Spinning yields each process has made in a row.
+    spins: [Cell<usize>; NUM_PROCS],
+    /// Times each process will still be skipped.
+    skips: [Cell<usize>; NUM_PROCS],
+    /// Times each process's turn was ended for spinning.
+    caught: [Cell<usize>; NUM_PROCS],
+}
+
+impl<const NUM_PROCS: usize> ThresholdYieldSpinPolicy<NUM_PROCS> {
+    pub const fn new(threshold: usize, penalty: usize) -> ThresholdYieldSpinPolicy<NUM_PROCS> {
+        const ZERO: Cell<usize> = Cell::new(0);
+        ThresholdYieldSpinPolicy {
+            threshold,
+            penalty,
+            spins: [ZERO; NUM_PROCS],
+            skips: [ZERO; NUM_PROCS],
+            caught: [ZERO; NUM_PROCS],
+        }
+    }
+
+    /// How many times the turn of `process` was ended for spinning on yield.
+    pub fn times_caught(&self, process: ProcessId) -> usize {
+        self.caught.get(process.index).map_or(0, Cell::get)
+    }
+}
+
+impl<const NUM_PROCS: usize> YieldSpinPolicy for ThresholdYieldSpinPolicy<NUM_PROCS> {
+    fn yield_spun(&self, process: ProcessId) -> YieldSpinAction {
+        let index = process.index;
+        if index >= NUM_PROCS {
+            return YieldSpinAction::Continue;
+        }
+        let spins = self.spins[index].get() + 1;
+        if spins < self.threshold {
+            self.spins[index].set(spins);
+            return YieldSpinAction::Continue;
+        }
+        self.spins[index].set(0);
+        self.skips[index].set(self.penalty);
+        self.caught[index].set(self.caught[index].get() + 1);
+        YieldSpinAction::EndTurn
+    }
+
+    fn yield_progressed(&self, process: ProcessId) {
+        if let Some(spins) = self.spins.get(process.index) {
+            spins.set(0);
+        }
+    }
+
+    fn suspended(&self, process: ProcessId) -> bool {
+        match self.skips.get(process.index) {
+            Some(skips) if skips.get() > 0 => {
+                skips.set(skips.get() - 1);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::errorcode::ErrorCode;
+    use crate::hil::time::{Freq1KHz, Ticks32, Time};
+    use crate::kernel::Kernel;
+    use crate::process::{FaultAction, FunctionCall, FunctionCallSource, Task};
+    use crate::scheduler::round_robin::{RoundRobinProcessNode, RoundRobinSched};
+    use crate::syscall::{Syscall, YieldCall};
+    use crate::test::mocks::{self, MockChip, MockResources, NoDrivers};
+    use core::cell::RefCell;
+    use core::num::NonZeroU32;
+    use core::ptr;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// What the kernel told a `Recorder` about a process, by index.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Event {
+        /// The scheduler picked the process.
+        Picked(usize),
+        /// The policy kept the process from running.
+        Skipped(usize),
+        Spun(usize),
+        Progressed(usize),
+    }
+
+    /// Records what the kernel tells `policy`.
+    struct Recorder<P> {
+        policy: P,
+        events: RefCell<Vec<Event>>,
+    }
+
+    impl<P: YieldSpinPolicy> YieldSpinPolicy for Recorder<P> {
+        fn yield_spun(&self, process: ProcessId) -> YieldSpinAction {
+            self.events.borrow_mut().push(Event::Spun(process.index));
+            self.policy.yield_spun(process)
+        }
+
+        fn yield_progressed(&self, process: ProcessId) {
+            self.events
+                .borrow_mut()
+                .push(Event::Progressed(process.index));
+            self.policy.yield_progressed(process);
+        }
+
+        fn suspended(&self, process: ProcessId) -> bool {
+            self.events.borrow_mut().push(Event::Picked(process.index));
+            let suspended = self.policy.suspended(process);
+            if suspended {
+                self.events.borrow_mut().push(Event::Skipped(process.index));
+            }
+            suspended
+        }
+    }
+
+    /// Processes run by the kernel loop with a round robin scheduler and
+    /// `policy`.
+    struct Board<P: 'static> {
+        chip: &'static MockChip,
+        kernel: &'static Kernel,
+        processes: Vec<&'static dyn Process>,
+        resources: MockResources<RoundRobinSched<'static>, Recorder<P>>,
+    }
+
+    impl<P: YieldSpinPolicy> Board<P> {
+        fn new(names: &[&str], policy: P) -> Board<P> {
+            let chip = MockChip::new();
+            let apps: Vec<_> = names
+                .iter()
+                .map(|name| (*name, ShortID::LocallyUnique))
+                .collect();
+            let (kernel, processes) = mocks::processes(chip, &apps);
+            let scheduler = RoundRobinSched::new();
+            for process in processes.iter() {
+                let slot: &'static Option<&'static dyn Process> =
+                    Box::leak(Box::new(Some(*process)));
+                scheduler
+                    .processes
+                    .push_tail(Box::leak(Box::new(RoundRobinProcessNode::new(slot))));
+            }
+            Board {
+                chip,
+                kernel,
+                processes,
+                resources: MockResources {
+                    scheduler,
+                    yield_spin_policy: Recorder {
+                        policy,
+                        events: RefCell::new(Vec::new()),
+                    },
+                    drivers: NoDrivers,
+                },
+            }
+        }
+
+        fn policy(&self) -> &P {
+            &self.resources.yield_spin_policy.policy
+        }
+
+        fn processid(&self, index: usize) -> ProcessId {
+            self.processes[index].processid()
+        }
+
+        /// Queue an upcall for the process at `index`.
+        fn upcall(&self, index: usize) {
+            let upcall = FunctionCall {
+                source: FunctionCallSource::Kernel,
+                argument0: 0,
+                argument1: 0,
+                argument2: 0,
+                argument3: 0,
+                pc: 0,
+            };
+            assert_eq!(
+                self.processes[index].enqueue_task(Task::FunctionCall(upcall)),
+                Ok(())
+            );
+        }
+
+        /// Run one iteration of the kernel loop, in which the process that
+        /// runs makes `syscalls`, and return what the policy was told.
+        fn turn(&self, syscalls: &[Syscall]) -> Vec<Event> {
+            for syscall in syscalls {
+                self.chip.syscall(*syscall);
+            }
+            self.resources.run_once(self.kernel, self.chip);
+            assert_eq!(self.chip.switches_left(), 0);
+            self.resources.yield_spin_policy.events.take()
+        }
+    }
+
+    fn yield_(which: YieldCall) -> Syscall {
+        Syscall::Yield {
+            which: which as usize,
+            address: ptr::null_mut(),
+        }
+    }
+
+    const SPIN: [Event; 4] = [
+        Event::Picked(0),
+        Event::Spun(0),
+        Event::Spun(0),
+        Event::Spun(0),
+    ];
+    const WAIT: [Event; 2] = [Event::Picked(1), Event::Progressed(1)];
+
+    #[test]
+    fn spinning_process_yields_the_kernel_loop() {
+        // Without a policy the spinner keeps the kernel loop until it waits.
+        let board = Board::new(&["spinner", "waiter"], ());
+        let mut syscalls = std::vec![yield_(YieldCall::NoWait); 50];
+        syscalls.push(yield_(YieldCall::Wait));
+        let events = board.turn(&syscalls);
+        assert_eq!(events.len(), 52);
+        assert_eq!(events[0], Event::Picked(0));
+        assert_eq!(events[51], Event::Progressed(0));
+
+        // With a policy its turn ends after `threshold` spins, so the waiter
+        // runs between them.
+        let board = Board::new(
+            &["spinner", "waiter"],
+            ThresholdYieldSpinPolicy::<2>::new(3, 0),
+        );
+        for _ in 0..3 {
+            assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), SPIN);
+            assert_eq!(board.turn(&[yield_(YieldCall::Wait)]), WAIT);
+            board.upcall(1);
+        }
+        assert_eq!(board.policy().times_caught(board.processid(0)), 3);
+        assert_eq!(board.policy().times_caught(board.processid(1)), 0);
+
+        // With a penalty the spinner sits out its next two turns, without
+        // running.
+        let board = Board::new(
+            &["spinner", "waiter"],
+            ThresholdYieldSpinPolicy::<2>::new(3, 2),
+        );
+        assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), SPIN);
+        for _ in 0..2 {
+            assert_eq!(board.turn(&[yield_(YieldCall::Wait)]), WAIT);
+            board.upcall(1);
+            assert_eq!(board.turn(&[]), [Event::Picked(0), Event::Skipped(0)]);
+        }
+        assert_eq!(board.turn(&[yield_(YieldCall::Wait)]), WAIT);
+        assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), SPIN);
+        // The waiter was ready to run while the spinner sat out.
+        assert_eq!(board.chip.sleeps(), 0);
+    }
+
+    #[test]
+    fn kernel_sleeps_when_every_ready_process_sits_out() {
+        let board = Board::new(
+            &["spinner", "other spinner"],
+            ThresholdYieldSpinPolicy::<2>::new(3, 2),
+        );
+        let spin = |index| {
+            [
+                Event::Picked(index),
+                Event::Spun(index),
+                Event::Spun(index),
+                Event::Spun(index),
+            ]
+        };
+        assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), spin(0));
+        assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), spin(1));
+
+        // Both are ready, and sit out their next two turns. Once both have
+        // sat out the kernel sleeps, rather than going around again.
+        for _ in 0..2 {
+            assert_eq!(board.turn(&[]), [Event::Picked(0), Event::Skipped(0)]);
+            let sleeps = board.chip.sleeps();
+            assert_eq!(board.turn(&[]), [Event::Picked(1), Event::Skipped(1)]);
+            assert_eq!(board.chip.sleeps(), sleeps + 1);
+        }
+        assert_eq!(board.chip.sleeps(), 2);
+        assert_eq!(board.turn(&[yield_(YieldCall::NoWait); 3]), spin(0));
+    }
+
+    #[test]
+    fn waiting_resets_the_spin_count() {
+        let board = Board::new(
+            &["spinner", "other"],
+            ThresholdYieldSpinPolicy::<1>::new(3, 1),
+        );
+        assert_eq!(
+            board.turn(&[
+                yield_(YieldCall::NoWait),
+                yield_(YieldCall::NoWait),
+                yield_(YieldCall::Wait)
+            ]),
+            [
+                Event::Picked(0),
+                Event::Spun(0),
+                Event::Spun(0),
+                Event::Progressed(0)
+            ]
+        );
+
+        // Processes the policy has no room for are left alone.
+        let mut syscalls = std::vec![yield_(YieldCall::NoWait); 10];
+        syscalls.push(yield_(YieldCall::Wait));
+        let events = board.turn(&syscalls);
+        assert_eq!(events.len(), 12);
+        assert_eq!(events[11], Event::Progressed(1));
+
+        // Yield-no-wait with an upcall pending is progress too: the spinner
+        // runs the upcall, and then spins `threshold` more times.
+        board.upcall(0);
+        board.upcall(0);
+        let events = board.turn(&[yield_(YieldCall::NoWait); 4]);
+        assert_eq!(events[..2], [Event::Picked(0), Event::Progressed(0)]);
+        assert_eq!(events[2..], SPIN[1..]);
+        assert_eq!(board.policy().times_caught(board.processid(0)), 1);
+
+        // The penalty keeps it from running once.
+        assert_eq!(board.turn(&[]), [Event::Picked(0), Event::Skipped(0)]);
+    }
+
+    struct MockAlarm {
+        now: Cell<u32>,
+        dt: Cell<Option<u32>>,
+    }
+
+    impl Time for MockAlarm {
+        type Ticks = Ticks32;
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get().into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for MockAlarm {
+        fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.dt.set(Some(reference.into_u32() + dt.into_u32()));
+        }
+        fn get_alarm(&self) -> Ticks32 {
+            self.dt.get().unwrap_or(0).into()
+        }
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            self.dt.set(None);
+            Ok(())
+        }
+        fn is_armed(&self) -> bool {
+            self.dt.get().is_some()
+        }
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    #[test]
+    fn each_process_gets_its_fault_policy() {
+        let chip = MockChip::new();
+        let (_, processes) = mocks::processes(
+            chip,
+            &[
+                ("daemon", ShortID::LocallyUnique),
+                ("sensor", ShortID::Fixed(NonZeroU32::new(0x1234).unwrap())),
+                ("untrusted", ShortID::LocallyUnique),
+            ],
+        );
+        let (daemon, sensor, untrusted) = (processes[0], processes[1], processes[2]);
+
+        let alarm = MockAlarm {
+            now: Cell::new(0),
+            dt: Cell::new(None),
+        };
+        let rules = [
+            (ProcessMatch::Name("daemon"), FaultAction::Panic),
+            (ProcessMatch::ShortId(0x1234), FaultAction::Restart),
+        ];
+        let policy = PerProcessFaultPolicy::<_, 3>::new(
+            &alarm,
+            &rules,
+            FaultAction::Stop,
+            RestartBackoff {
+                max_restarts: 2,
+                initial_ms: 100,
+                max_ms: 150,
+            },
+        );
+
+        assert_eq!(policy.action(daemon), FaultAction::Panic);
+        assert_eq!(policy.action(untrusted), FaultAction::Stop);
+        assert!(!policy.restart_pending(untrusted.processid()));
+        assert_eq!(alarm.dt.get(), None);
+
+        // The sensor is stopped, and restarted once the backoff has passed.
+        assert_eq!(policy.action(sensor), FaultAction::Stop);
+        assert!(policy.restart_pending(sensor.processid()));
+        assert_eq!(alarm.dt.get(), Some(100));
+        alarm.now.set(100);
+        policy.alarm();
+        assert_eq!(sensor.get_restart_count(), 1);
+        assert!(!policy.restart_pending(sensor.processid()));
+        assert_eq!(alarm.dt.get(), None);
+
+        // The backoff doubles, up to its maximum.
+        assert_eq!(policy.action(sensor), FaultAction::Stop);
+        assert_eq!(alarm.dt.get(), Some(250));
+        alarm.now.set(200);
+        policy.alarm();
+        assert_eq!(sensor.get_restart_count(), 1);
+        alarm.now.set(250);
+        policy.alarm();
+        assert_eq!(sensor.get_restart_count(), 2);
+
+        // After the last restart, the policy gives up on the sensor.
+        assert_eq!(policy.action(sensor), FaultAction::Stop);
+        assert!(!policy.restart_pending(sensor.processid()));
+        assert_eq!(alarm.dt.get(), None);
+    }
+}
@@ -23,8 +23,8 @@ use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
-use crate::process::{FaultAction, ProcessCustomGrantIdentifier, ProcessId};
-use crate::process::{ProcessAddresses, ProcessSizes, ShortID};
+use crate::process::{FaultAction, FaultCause, ProcessCustomGrantIdentifier, ProcessId};
+use crate::process::{ProcessAddresses, ProcessSizes, ShortID, SyscallCounters};
 use crate::process_loading::ProcessLoadError;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
@@ -67,6 +67,11 @@ struct ProcessStandardDebug {
     /// What was the most recent syscall.
     last_syscall: Option<Syscall>,
 
+    /// How many syscalls of each class have occurred since the process
+    /// started or the counters were last reset. Only updated if the
+    /// `syscall_counters` feature is enabled.
+    syscall_counters: SyscallCounters,
+
     /// How many upcalls were dropped because the queue was insufficiently
     /// long.
     dropped_upcall_count: usize,
@@ -211,6 +216,10 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// MPU regions are saved as a pointer-size pair.
     mpu_regions: [Cell<Option<mpu::Region>>; 6],
 
+    /// The guard region below the process's stack, if the MPU uses them and
+    /// there was room for one when the process was created.
+    stack_guard: Option<mpu::Region>,
+
     /// Essentially a list of upcalls that want to call functions in the
     /// process.
     tasks: MapCell<RingBuffer<'a, Task>>,
@@ -408,6 +417,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         // Use the per-process fault policy to determine what action the kernel
         // should take since the process faulted.
         let action = self.fault_policy.action(self);
+        let cause = self.fault_cause();
         let state = self.state.get();
         // Accidentally calling faulted on an unchecked or failed process should
         // not make it eventually runnable.
@@ -418,7 +428,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             FaultAction::Panic => {
                 // process faulted. Panic and print status
                 self.state.set(State::Faulted);
-                panic!("Process {} had a fault", self.process_name);
+                match cause {
+                    FaultCause::StackOverflow => {
+                        panic!("Process {} overflowed its stack", self.process_name)
+                    }
+                    FaultCause::Other => panic!("Process {} had a fault", self.process_name),
+                }
             }
             FaultAction::Restart => {
                 self.try_restart(None);
@@ -435,6 +450,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         }
     }
 
+    fn fault_cause(&self) -> FaultCause {
+        let fault_address = self.stored_state.map_or(None, |stored_state| {
+            self.chip
+                .userspace_kernel_boundary()
+                .fault_address(stored_state)
+        });
+        FaultCause::classify(fault_address, self.stack_guard)
+    }
+
     fn try_restart(&self, completion_code: Option<u32>) {
         let current_state = self.state.get();
         if current_state == State::CredentialsFailed || current_state == State::CredentialsUnchecked
@@ -482,9 +506,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             tasks.empty();
         });
 
-        // Clear any grant regions this app has setup with any capsules.
+        // Clear any grant regions this app has setup with any capsules, and
+        // wipe their contents so secrets capsules keep there, such as keys,
+        // do not outlive the process.
         unsafe {
             self.grant_ptrs_reset();
+            self.grant_region_zero();
         }
 
         // Save the completion code.
@@ -1189,6 +1216,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
             debug.last_syscall = Some(last_syscall);
+            if config::CONFIG.syscall_counters {
+                debug.syscall_counters.record(&last_syscall);
+            }
         });
     }
 
@@ -1196,6 +1226,18 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.debug.map_or(None, |debug| debug.last_syscall)
     }
 
+    fn debug_syscall_counters(&self) -> Option<SyscallCounters> {
+        if config::CONFIG.syscall_counters {
+            self.debug.map(|debug| debug.syscall_counters)
+        } else {
+            None
+        }
+    }
+
+    fn debug_reset_syscall_counters(&self) {
+        self.debug.map(|debug| debug.syscall_counters.reset());
+    }
+
     fn get_addresses(&self) -> ProcessAddresses {
         ProcessAddresses {
             flash_start: self.flash_start() as usize,
@@ -1247,6 +1289,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             }
         });
 
+        if let (FaultCause::StackOverflow, Some(guard)) = (self.fault_cause(), self.stack_guard) {
+            let _ = writer.write_fmt(format_args!(
+                "\
+                 \r\n Stack overflow: faulted in the stack guard region {:#010X}-{:#010X}\r\n",
+                guard.start_address() as usize,
+                guard.start_address() as usize + guard.size() - 1
+            ));
+        }
+
         // Display grant information.
         let number_grants = self.kernel.get_grant_count_and_finalize();
         let _ = writer.write_fmt(format_args!(
@@ -1535,6 +1586,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // Minimum memory size for the process.
         let min_total_memory_size = min_process_ram_size + initial_kernel_memory_size;
 
+        // The stack starts at the beginning of process memory, so the stack
+        // guard region goes in the memory directly below it.
+        let stack_guard_size = chip.mpu().stack_guard_size();
+        let unallocated_memory_start = remaining_memory.as_ptr() as usize;
+
         // Check if this process requires a fixed memory start address. If so,
         // try to adjust the memory region to work for this process.
         //
@@ -1586,11 +1642,19 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             remaining_memory
         };
 
+        // Leave room for the stack guard region. A process with a fixed memory
+        // address only gets one if enough memory was skipped to reach it.
+        let stack_guard_offset = if tbf_header.get_fixed_address_ram().is_none() {
+            cmp::min(stack_guard_size, remaining_memory.len())
+        } else {
+            0
+        };
+
         // Determine where process memory will go and allocate MPU region for
         // app-owned memory.
         let (app_memory_start, app_memory_size) = match chip.mpu().allocate_app_memory_region(
-            remaining_memory.as_ptr() as *const u8,
-            remaining_memory.len(),
+            remaining_memory.as_ptr().add(stack_guard_offset),
+            remaining_memory.len() - stack_guard_offset,
             min_total_memory_size,
             min_process_memory_size,
             initial_kernel_memory_size,
@@ -1613,6 +1677,25 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
+        // Allocate the stack guard region if there is room for it. The process
+        // can run without one, so failing to allocate it is not an error.
+        let stack_guard = if stack_guard_size > 0
+            && app_memory_start as usize - unallocated_memory_start >= stack_guard_size
+        {
+            chip.mpu()
+                .allocate_stack_guard_region(app_memory_start, &mut mpu_config)
+        } else {
+            None
+        };
+        if config::CONFIG.debug_load_processes && stack_guard_size > 0 && stack_guard.is_none() {
+            debug!(
+                "[!] flash={:#010X}-{:#010X} process={:?} - no stack guard region",
+                app_flash.as_ptr() as usize,
+                app_flash.as_ptr() as usize + app_flash.len() - 1,
+                process_name
+            );
+        }
+
         // Get a slice for the memory dedicated to the process. This can fail if
         // the MPU returns a region of memory that is not inside of the
         // `remaining_memory` slice passed to `create()` to allocate the
@@ -1755,6 +1838,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             Cell::new(None),
             Cell::new(None),
         ];
+        process.stack_guard = stack_guard;
         process.tasks = MapCell::new(tasks);
         process.process_name = process_name.unwrap_or("");
 
@@ -1766,6 +1850,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             app_stack_min_pointer: None,
             syscall_count: 0,
             last_syscall: None,
+            syscall_counters: SyscallCounters::default(),
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
         });
@@ -1829,6 +1914,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.debug.map(|debug| {
             debug.syscall_count = 0;
             debug.last_syscall = None;
+            debug.syscall_counters.reset();
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
         });
@@ -1894,6 +1980,17 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
+        // Restore the stack guard region, if the process had one.
+        if self.stack_guard.is_some()
+            && self
+                .chip
+                .mpu()
+                .allocate_stack_guard_region(app_mpu_mem_start, &mut mpu_config)
+                .is_none()
+        {
+            return Err(ErrorCode::FAIL);
+        }
+
         // Reset memory pointers now that we know the layout of the process
         // memory and know that we can configure the MPU.
 
@@ -1982,6 +2079,16 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         });
     }
 
+    /// Overwrite the memory allocated in the grant region with zeros.
+    ///
+    /// Grants are allocated downwards from the process struct, which sits at
+    /// the top of the initial kernel memory break, to `kernel_memory_break`.
+    unsafe fn grant_region_zero(&self) {
+        let start = self.kernel_memory_break.get() as *mut u8;
+        let end = self as *const Self as *const u8;
+        ptr::write_bytes(start, 0, end as usize - start as usize);
+    }
+
     /// Allocate memory in a process's grant region.
     ///
     /// Ensures that the allocation is of `size` bytes and aligned to `align`
@@ -2110,3 +2217,147 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.app_break.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::priority::PrioritySched;
+    use crate::syscall::YieldCall;
+    use crate::test::mocks::{self, MockChip, MockResources, NoDrivers};
+
+    extern crate std;
+    use std::string::String;
+    use std::vec::Vec;
+
+    #[test]
+    fn syscall_counters_by_class() {
+        let chip = MockChip::new();
+        let (kernel, processes) = mocks::processes(chip, &[("counted", ShortID::LocallyUnique)]);
+        let process = processes[0];
+        let resources = MockResources {
+            scheduler: PrioritySched::new(kernel),
+            yield_spin_policy: (),
+            drivers: NoDrivers,
+        };
+
+        chip.syscall(Syscall::Command {
+            driver_number: 1,
+            subdriver_number: 1,
+            arg0: 0,
+            arg1: 0,
+        });
+        chip.syscall(Syscall::ReadOnlyAllow {
+            driver_number: 1,
+            subdriver_number: 1,
+            allow_address: ptr::null(),
+            allow_size: 0,
+        });
+        chip.syscall(Syscall::ReadWriteAllow {
+            driver_number: 1,
+            subdriver_number: 0,
+            allow_address: ptr::null_mut(),
+            allow_size: 0,
+        });
+        chip.syscall(Syscall::Subscribe {
+            driver_number: 1,
+            subdriver_number: 0,
+            upcall_ptr: ptr::null_mut(),
+            appdata: 0,
+        });
+        chip.syscall(Syscall::Memop {
+            operand: 2,
+            arg0: 0,
+        });
+        // Without upcalls to run, yield-no-wait returns to the process and
+        // yield-wait ends its turn.
+        chip.syscall(Syscall::Yield {
+            which: YieldCall::NoWait as usize,
+            address: ptr::null_mut(),
+        });
+        chip.syscall(Syscall::Yield {
+            which: YieldCall::Wait as usize,
+            address: ptr::null_mut(),
+        });
+        resources.run_once(kernel, chip);
+        assert_eq!(chip.switches_left(), 0);
+        assert_eq!(process.get_state(), State::Yielded);
+
+        let expected = SyscallCounters {
+            command: 1,
+            allow: 2,
+            subscribe: 1,
+            yield_: 2,
+            memop: 1,
+            exit: 0,
+        };
+        let enabled = config::CONFIG.syscall_counters;
+        assert_eq!(
+            process.debug_syscall_counters(),
+            enabled.then_some(expected)
+        );
+
+        process.debug_reset_syscall_counters();
+        assert_eq!(
+            process.debug_syscall_counters(),
+            enabled.then(SyscallCounters::default)
+        );
+    }
+
+    #[test]
+    fn fault_in_stack_guard_is_a_stack_overflow() {
+        let chip = MockChip::with_stack_guard(64);
+        let (kernel, processes) = mocks::processes(
+            chip,
+            &[
+                ("overflow", ShortID::LocallyUnique),
+                ("wild", ShortID::LocallyUnique),
+                ("unknown", ShortID::LocallyUnique),
+            ],
+        );
+        let resources = MockResources {
+            scheduler: PrioritySched::new(kernel),
+            yield_spin_policy: (),
+            drivers: NoDrivers,
+        };
+
+        // The stack starts at the beginning of process memory, with the
+        // guard region directly below it.
+        let stack_bottom = |process: &dyn Process| process.get_addresses().sram_start as *const u8;
+        // The stack grew past its bottom, into the guard region.
+        chip.fault(Some(stack_bottom(processes[0]).wrapping_sub(4)));
+        // An access just above the guard region, to process memory.
+        chip.fault(Some(stack_bottom(processes[1])));
+        // The architecture did not report where the process faulted.
+        chip.fault(None);
+        for _ in &processes {
+            resources.run_once(kernel, chip);
+        }
+        assert_eq!(chip.switches_left(), 0);
+
+        let causes: Vec<_> = processes
+            .iter()
+            .map(|process| {
+                assert_eq!(process.get_state(), State::Faulted);
+                process.fault_cause()
+            })
+            .collect();
+        assert_eq!(
+            causes,
+            [
+                FaultCause::StackOverflow,
+                FaultCause::Other,
+                FaultCause::Other
+            ]
+        );
+
+        let mut report = String::new();
+        processes[0].print_full_process(&mut report);
+        assert_eq!(
+            report.contains("Stack overflow"),
+            config::CONFIG.debug_panics
+        );
+        let mut report = String::new();
+        processes[1].print_full_process(&mut report);
+        assert!(!report.contains("Stack overflow"));
+    }
+}
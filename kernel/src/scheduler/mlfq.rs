@@ -172,7 +172,11 @@ impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for MLFQSched<
             .us_used_this_queue
             .set(self.last_timeslice.get() - execution_time_us);
 
-        let punish = result == StoppedExecutingReason::TimesliceExpired;
+        // A process spinning on yield would have used up its timeslice.
+        let punish = matches!(
+            result,
+            StoppedExecutingReason::TimesliceExpired | StoppedExecutingReason::YieldSpin
+        );
         if punish {
             node_ref.state.us_used_this_queue.set(0);
             let next_queue = if queue_idx == Self::NUM_QUEUES - 1 {
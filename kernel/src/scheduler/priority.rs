@@ -7,7 +7,9 @@
 //! This scheduler assigns priority to processes based on their order in the
 //! `PROCESSES` array, and runs the highest priority process available at any
 //! point in time. Kernel tasks (bottom half interrupt handling / deferred call
-//! handling) always take priority over userspace processes.
+//! handling) always take priority over userspace processes. Boards can load
+//! processes with `process::load_processes_with_priorities` to order the
+//! `PROCESSES` array by a priority table, rather than by flash order.
 //!
 //! Notably, there is no need to enforce timeslices, as it is impossible for a
 //! process running to not be the highest priority process at any point while it
@@ -656,4 +656,14 @@ pub trait UserspaceKernelBoundary {
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Returns the address the process was accessing if it last stopped on a
+    /// memory access fault, and the architecture records the address.
+    ///
+    /// This is only used to classify process faults, so returning `None` is
+    /// always correct.
+    #[allow(unused_variables)]
+    fn fault_address(&self, state: &Self::StoredState) -> Option<*const u8> {
+        None
+    }
 }
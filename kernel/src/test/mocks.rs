@@ -0,0 +1,446 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A chip and real processes for host tests of the kernel.
+//!
+//! [`MockChip`] runs no process code: every switch to a process returns the
+//! next context switch reason the test queued, so a test scripts the
+//! syscalls and faults of its processes. Processes are `ProcessStandard`s,
+//! created from TBF headers built by [`tbf`], so the kernel loop, the
+//! process loader and the schedulers run unchanged.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::ptr;
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use crate::capabilities;
+use crate::errorcode::ErrorCode;
+use crate::kernel::Kernel;
+use crate::platform::chip::Chip;
+use crate::platform::mpu::{self, MpuConfigDefault, MPU};
+use crate::platform::platform::{KernelResources, SyscallDriverLookup, YieldSpinPolicy};
+use crate::process::{FunctionCall, Process, ShortID};
+use crate::process_policies::StopFaultPolicy;
+use crate::process_standard::ProcessStandard;
+use crate::scheduler::Scheduler;
+use crate::syscall::{ContextSwitchReason, Syscall, SyscallReturn, UserspaceKernelBoundary};
+use crate::syscall_driver::SyscallDriver;
+
+/// RAM each process asks for in its TBF header.
+const MINIMUM_RAM_SIZE: u32 = 1024;
+/// Size of the binary after the TBF header.
+const BINARY_SIZE: usize = 16;
+/// Memory each process can access when it starts, at the beginning of its
+/// RAM.
+const INITIAL_APP_BRK_SIZE: usize = 256;
+
+/// Returns the reasons queued with [`MockChip::switch`], in order.
+pub struct MockBoundary {
+    switches: RefCell<VecDeque<(ContextSwitchReason, Option<*const u8>)>>,
+    returns: RefCell<Vec<SyscallReturn>>,
+}
+
+impl UserspaceKernelBoundary for MockBoundary {
+    /// The address the process last faulted on.
+    type StoredState = Option<*const u8>;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        INITIAL_APP_BRK_SIZE
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Option<*const u8>,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Option<*const u8>,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        self.returns.borrow_mut().push(return_value);
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Option<*const u8>,
+        _upcall: FunctionCall,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Option<*const u8>,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        let (reason, fault_address) = self
+            .switches
+            .borrow_mut()
+            .pop_front()
+            .expect("switched to a process with nothing queued");
+        *state = fault_address;
+        (reason, None)
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &Option<*const u8>,
+        _writer: &mut dyn Write,
+    ) {
+    }
+
+    fn store_context(
+        &self,
+        _state: &Option<*const u8>,
+        _out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        Ok(0)
+    }
+
+    fn fault_address(&self, state: &Option<*const u8>) -> Option<*const u8> {
+        *state
+    }
+}
+
+/// Protects nothing, but places a stack guard region of `stack_guard_size`
+/// bytes below each process.
+pub struct MockMpu {
+    stack_guard_size: usize,
+}
+
+impl MPU for MockMpu {
+    type MpuConfig = MpuConfigDefault;
+
+    fn stack_guard_size(&self) -> usize {
+        self.stack_guard_size
+    }
+
+    fn allocate_stack_guard_region(
+        &self,
+        stack_bottom: *const u8,
+        _config: &mut MpuConfigDefault,
+    ) -> Option<mpu::Region> {
+        (self.stack_guard_size > 0).then(|| {
+            mpu::Region::new(
+                stack_bottom.wrapping_sub(self.stack_guard_size),
+                self.stack_guard_size,
+            )
+        })
+    }
+}
+
+/// A chip without interrupts, whose processes do what the test queues.
+pub struct MockChip {
+    mpu: MockMpu,
+    boundary: MockBoundary,
+    sleeps: Cell<usize>,
+}
+
+impl MockChip {
+    pub fn new() -> &'static MockChip {
+        MockChip::with_stack_guard(0)
+    }
+
+    /// A chip that places a stack guard region of `size` bytes below the
+    /// processes it loads.
+    pub fn with_stack_guard(size: usize) -> &'static MockChip {
+        Box::leak(Box::new(MockChip {
+            mpu: MockMpu {
+                stack_guard_size: size,
+            },
+            boundary: MockBoundary {
+                switches: RefCell::new(VecDeque::new()),
+                returns: RefCell::new(Vec::new()),
+            },
+            sleeps: Cell::new(0),
+        }))
+    }
+
+    /// Queue what the next process switched to does.
+    pub fn switch(&self, reason: ContextSwitchReason) {
+        self.boundary
+            .switches
+            .borrow_mut()
+            .push_back((reason, None));
+    }
+
+    /// Queue a fault of the next process switched to, on `address` if the
+    /// architecture reports it.
+    pub fn fault(&self, address: Option<*const u8>) {
+        self.boundary
+            .switches
+            .borrow_mut()
+            .push_back((ContextSwitchReason::Fault, address));
+    }
+
+    /// Queue a syscall of the next process switched to.
+    pub fn syscall(&self, syscall: Syscall) {
+        self.switch(ContextSwitchReason::SyscallFired { syscall });
+    }
+
+    /// The values returned to processes from system calls since the last
+    /// call.
+    pub fn take_syscall_returns(&self) -> Vec<SyscallReturn> {
+        self.boundary.returns.take()
+    }
+
+    /// How many times the kernel put the chip to sleep.
+    pub fn sleeps(&self) -> usize {
+        self.sleeps.get()
+    }
+
+    /// How many queued switches have not happened yet.
+    pub fn switches_left(&self) -> usize {
+        self.boundary.switches.borrow().len()
+    }
+}
+
+impl Chip for MockChip {
+    type MPU = MockMpu;
+    type UserspaceKernelBoundary = MockBoundary;
+
+    fn service_pending_interrupts(&self) {}
+
+    fn has_pending_interrupts(&self) -> bool {
+        false
+    }
+
+    fn mpu(&self) -> &MockMpu {
+        &self.mpu
+    }
+
+    fn userspace_kernel_boundary(&self) -> &MockBoundary {
+        &self.boundary
+    }
+
+    fn sleep(&self) {
+        self.sleeps.set(self.sleeps.get() + 1);
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    unsafe fn print_state(&self, _writer: &mut dyn Write) {}
+}
+
+/// A board with `drivers`, running `scheduler` and `yield_spin_policy`.
+pub struct MockResources<S, P, D = NoDrivers> {
+    pub scheduler: S,
+    pub yield_spin_policy: P,
+    pub drivers: D,
+}
+
+/// Every driver lookup fails.
+pub struct NoDrivers;
+
+impl SyscallDriverLookup for NoDrivers {
+    fn with_driver<F, R>(&self, _driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn SyscallDriver>) -> R,
+    {
+        f(None)
+    }
+}
+
+impl<S: Scheduler<MockChip>, P: YieldSpinPolicy, D: SyscallDriverLookup> KernelResources<MockChip>
+    for MockResources<S, P, D>
+{
+    type SyscallDriverLookup = D;
+    type SyscallFilter = ();
+    type ProcessFault = ();
+    type CredentialsCheckingPolicy = ();
+    type ContextSwitchCallback = ();
+    type YieldSpinPolicy = P;
+    type Scheduler = S;
+    type SchedulerTimer = ();
+    type WatchDog = ();
+
+    fn syscall_driver_lookup(&self) -> &D {
+        &self.drivers
+    }
+
+    fn syscall_filter(&self) -> &() {
+        &()
+    }
+
+    fn process_fault(&self) -> &() {
+        &()
+    }
+
+    fn credentials_checking_policy(&self) -> &'static () {
+        &()
+    }
+
+    fn context_switch_callback(&self) -> &() {
+        &()
+    }
+
+    fn yield_spin_policy(&self) -> &P {
+        &self.yield_spin_policy
+    }
+
+    fn scheduler(&self) -> &S {
+        &self.scheduler
+    }
+
+    fn scheduler_timer(&self) -> &() {
+        &()
+    }
+
+    fn watchdog(&self) -> &() {
+        &()
+    }
+}
+
+impl<S, P, D> MockResources<S, P, D> {
+    /// Run one iteration of the kernel loop on `chip`. Sleeping returns
+    /// immediately.
+    pub fn run_once(&self, kernel: &Kernel, chip: &MockChip)
+    where
+        Self: KernelResources<MockChip>,
+    {
+        let capability = crate::create_capability!(capabilities::MainLoopCapability);
+        kernel.kernel_loop_operation::<_, _, 0>(self, chip, None, false, &capability);
+    }
+}
+
+fn tlv(header: &mut Vec<u8>, tipe: u16, value: &[u8]) {
+    header.extend(tipe.to_le_bytes());
+    header.extend((value.len() as u16).to_le_bytes());
+    header.extend(value);
+    header.resize((header.len() + 3) & !3, 0);
+}
+
+/// An enabled TBF v2 app named `name`, with its RAM at `fixed_ram` if set.
+pub fn tbf(name: &str, fixed_ram: Option<usize>) -> Vec<u8> {
+    let mut header = std::vec![0; 16];
+    // Main: init_fn_offset, protected_trailer_size, minimum_ram_size.
+    let main: Vec<u8> = [0, 0, MINIMUM_RAM_SIZE]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    tlv(&mut header, 1, &main);
+    tlv(&mut header, 3, name.as_bytes());
+    let mut version = crate::KERNEL_MAJOR_VERSION.to_le_bytes().to_vec();
+    version.extend(crate::KERNEL_MINOR_VERSION.to_le_bytes());
+    tlv(&mut header, 8, &version);
+    if let Some(ram) = fixed_ram {
+        // Only host addresses below 4 GiB can be fixed, as in the header.
+        let mut addresses = (ram as u32).to_le_bytes().to_vec();
+        addresses.extend(u32::MAX.to_le_bytes());
+        tlv(&mut header, 5, &addresses);
+    }
+
+    let header_size = header.len();
+    header[0..2].copy_from_slice(&2u16.to_le_bytes());
+    header[2..4].copy_from_slice(&(header_size as u16).to_le_bytes());
+    header[4..8].copy_from_slice(&((header_size + BINARY_SIZE) as u32).to_le_bytes());
+    // Enabled.
+    header[8..12].copy_from_slice(&1u32.to_le_bytes());
+    let checksum = header
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .fold(0, |checksum, word| checksum ^ word);
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    header.resize(header_size + BINARY_SIZE, 0);
+    header
+}
+
+/// Flash holding `apps` back to back, and the end of the app list.
+pub fn app_flash(apps: &[Vec<u8>]) -> &'static [u8] {
+    let mut flash: Vec<u8> = apps.concat();
+    flash.extend([0; 8]);
+    Box::leak(flash.into_boxed_slice())
+}
+
+/// Word aligned memory for processes, enough for `processes` of them.
+pub fn app_memory(processes: usize) -> &'static mut [u8] {
+    let words = processes * 8 * MINIMUM_RAM_SIZE as usize / 8;
+    let memory: &'static mut [u64] = Box::leak(std::vec![0; words].into_boxed_slice());
+    // Safety: the memory is leaked, so it is never freed nor used as `u64`s
+    // again.
+    unsafe { core::slice::from_raw_parts_mut(memory.as_mut_ptr().cast(), words * 8) }
+}
+
+/// A kernel with room for `len` processes, and its process array for the
+/// loader to fill in.
+pub fn kernel(len: usize) -> (&'static Kernel, &'static mut [Option<&'static dyn Process>]) {
+    let procs: &'static mut [Option<&'static dyn Process>] =
+        Box::leak(std::vec![None; len].into_boxed_slice());
+    // Like the `PROCESSES` array of a board, the kernel reads the array the
+    // loader writes.
+    let shared = unsafe { &*ptr::addr_of!(*procs) };
+    (Box::leak(Box::new(Kernel::new(shared))), procs)
+}
+
+/// A kernel with a process for each of `apps`, with their short IDs, ready
+/// to be started.
+pub fn processes(
+    chip: &'static MockChip,
+    apps: &[(&str, ShortID)],
+) -> (&'static Kernel, Vec<&'static dyn Process>) {
+    let (kernel, procs) = kernel(apps.len());
+    (kernel, create_processes(chip, kernel, procs, apps))
+}
+
+/// Fill in `procs` of `kernel` with a process for each of `apps`, with their
+/// short IDs, ready to be started. Grants must be created before.
+pub fn create_processes(
+    chip: &'static MockChip,
+    kernel: &'static Kernel,
+    procs: &'static mut [Option<&'static dyn Process>],
+    apps: &[(&str, ShortID)],
+) -> Vec<&'static dyn Process> {
+    let mut memory = app_memory(apps.len());
+    let capability = crate::create_capability!(capabilities::ProcessApprovalCapability);
+    for (index, (name, short_id)) in apps.iter().enumerate() {
+        let flash = app_flash(&[tbf(name, None)]);
+        let header_length = u16::from_le_bytes([flash[2], flash[3]]) as usize;
+        let (process, remaining) = unsafe {
+            ProcessStandard::create(
+                kernel,
+                chip,
+                &flash[..flash.len() - 8],
+                header_length,
+                2,
+                memory,
+                &StopFaultPolicy {},
+                true,
+                index,
+            )
+        }
+        .map_err(|(err, _)| err)
+        .unwrap();
+        let process = process.unwrap();
+        process
+            .mark_credentials_pass(None, *short_id, &capability)
+            .unwrap();
+        procs[index] = Some(process);
+        memory = remaining;
+    }
+    procs.iter().flatten().copied().collect()
+}
@@ -25,6 +25,9 @@ pub const MCAUSE: usize = 0x342;
 pub const MTVAL: usize = 0x343;
 pub const MIP: usize = 0x344;
 pub const MSECCFG: usize = 0x747;
+pub const TSELECT: usize = 0x7A0;
+pub const TDATA1: usize = 0x7A1;
+pub const TDATA2: usize = 0x7A2;
 pub const MSECCFGH: usize = 0x757;
 pub const PMPCFG0: usize = 0x3A0;
 pub const PMPCFG1: usize = 0x3A1;